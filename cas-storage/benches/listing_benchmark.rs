@@ -0,0 +1,96 @@
+//! Benchmarks listing a large bucket through `iter_all` and `range_filter`.
+//!
+//! The amount of keys defaults to 1M and can be changed with the
+//! `LISTING_BENCH_KEYS` environment variable.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use cas_storage::{FjallStore, FjallStoreNotx, MetaTreeExt, Object, ObjectData, Store};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use tempfile::TempDir;
+
+const BUCKET: &str = "listing-bench";
+
+fn key_count() -> usize {
+    std::env::var("LISTING_BENCH_KEYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1_000_000)
+}
+
+fn fill(store: &impl Store, keys: usize) -> Arc<dyn MetaTreeExt + Send + Sync> {
+    let tree = store.tree_ext_open(BUCKET).unwrap();
+    let obj = Object::new(
+        1024,
+        [1; 16],
+        ObjectData::SinglePart {
+            blocks: vec![[1; 16]],
+        },
+    )
+    .to_vec();
+    for i in 0..keys {
+        tree.insert(format!("dir-{:04}/object-{:08}", i % 1000, i).as_bytes(), obj.clone())
+            .unwrap();
+    }
+    tree
+}
+
+fn bench_tree(
+    c: &mut Criterion,
+    name: &str,
+    tree: Arc<dyn MetaTreeExt + Send + Sync>,
+    keys: usize,
+) {
+    let mut group = c.benchmark_group("list_bucket");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(30));
+
+    group.bench_function(BenchmarkId::new(name, format!("iter_all/{keys}")), |b| {
+        b.iter(|| black_box(tree.iter_all().count()))
+    });
+
+    group.bench_function(BenchmarkId::new(name, format!("range_filter/{keys}")), |b| {
+        b.iter(|| black_box(tree.range_filter(None, None, None).count()))
+    });
+
+    // a full listing paginated the way ListObjectsV2 does it, 1000 keys per page
+    group.bench_function(BenchmarkId::new(name, format!("paginated/{keys}")), |b| {
+        b.iter(|| {
+            let mut token = None;
+            let mut listed = 0;
+            loop {
+                let page: Vec<_> = tree.range_filter(None, None, token).take(1000).collect();
+                listed += page.len();
+                match page.last() {
+                    Some((key, _)) if page.len() == 1000 => token = Some(key.clone()),
+                    _ => break,
+                }
+            }
+            black_box(listed)
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_listing(c: &mut Criterion) {
+    let keys = key_count();
+
+    {
+        let dir = TempDir::new().unwrap();
+        let store = FjallStore::new(dir.path().to_path_buf(), Some(1), None);
+        let tree = fill(&store, keys);
+        bench_tree(c, "FjallStore", tree, keys);
+    }
+
+    {
+        let dir = TempDir::new().unwrap();
+        let store = FjallStoreNotx::new(dir.path().to_path_buf(), Some(1));
+        let tree = fill(&store, keys);
+        bench_tree(c, "FjallStoreNotx", tree, keys);
+    }
+}
+
+criterion_group!(benches, bench_listing);
+criterion_main!(benches);
@@ -0,0 +1,33 @@
+//! Decodes arbitrary bytes as object, block and bucket metadata. Decoding must
+//! fail with an error instead of panicking, and whatever decodes must encode to
+//! a representation which decodes to the same value.
+#![no_main]
+
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+use cas_storage::{Block, BucketMeta, Object};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(object) = Object::try_from(data) {
+        object.last_modified();
+        object.format_ctime();
+        object.format_e_tag();
+        let raw = object.to_vec();
+        let decoded = Object::try_from(raw.as_slice()).expect("encoded object decodes");
+        assert_eq!(decoded.to_vec(), raw);
+    }
+
+    if let Ok(block) = Block::try_from(data) {
+        block.disk_path(PathBuf::from("/blocks"));
+        assert_eq!(block.to_vec(), data);
+    }
+
+    if let Ok(meta) = BucketMeta::try_from(data) {
+        meta.ctime();
+        let raw = meta.to_vec();
+        let decoded = BucketMeta::try_from(raw.as_slice()).expect("encoded bucket decodes");
+        assert_eq!(decoded.to_vec(), raw);
+    }
+});
@@ -1,9 +1,71 @@
+pub mod block_pins;
 pub mod block_stream;
+pub mod bucket_activity;
+pub mod builder;
+pub mod byte_ranges;
+pub mod content_hash;
+pub mod corrupt_blocks;
+pub mod degraded_objects;
+pub mod delete_queue;
+pub mod events;
+pub mod file_ids;
+pub mod group_sync;
+pub mod hash_pool;
+pub mod idempotency;
+pub mod jobs;
+pub mod list_snapshots;
+pub mod manifest;
+pub mod meta_cache;
+pub mod meta_executor;
+pub mod meta_size;
+pub mod metadata_engine;
 pub mod multipart;
+pub mod object_locks;
+pub mod placement;
 pub mod range_request;
+pub mod request_timings;
+pub mod resumable;
 pub mod shared_block_store;
-pub use fs::CasFS;
+pub mod store_lock;
+pub mod usage;
+pub mod write_limiter;
+pub use byte_ranges::{ByteRanges, ByteRangesStream};
+pub use bucket_activity::{
+    Access, ActivityCounts, ActivityTracker, BucketActivity, ACTIVITY_WINDOW_MINUTES,
+    BUCKET_ACTIVITY_TREE,
+};
+pub use builder::{BuildError, CasFSBuilder, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+pub use content_hash::{ContentHash, ContentHasher};
+pub use corrupt_blocks::{CorruptBlock, CorruptBlocks, CORRUPT_BLOCKS_TREE};
+pub use degraded_objects::{DegradedObject, DegradedObjects, DEGRADED_OBJECTS_TREE};
+pub use delete_queue::{DeleteQueue, DeleteQueueStats, QueuedBlock, DELETE_QUEUE_TREE};
+pub use events::ObjectEventHandler;
+pub use file_ids::{FileId, FileIdCache, FILE_IDS_TREE};
+pub use group_sync::{GroupSync, GroupSyncConfig};
+pub use hash_pool::{HashPool, StreamHasher};
+pub use idempotency::{
+    IdempotencyKeys, IdempotentResult, IDEMPOTENCY_KEYS_TREE, IDEMPOTENCY_KEY_LIFETIME,
+    MAX_IDEMPOTENCY_KEY_LEN,
+};
+pub use jobs::{JobRecord, JobStatus, JobStore, JOBS_TREE};
+pub use fs::{CasFS, STAGED_BLOCKS_PREFIX};
 pub use fs::StorageEngine;
+pub use list_snapshots::{
+    ListSnapshots, DEFAULT_LIST_SNAPSHOT_LIFETIME, DEFAULT_MAX_LIST_SNAPSHOTS,
+};
+pub use manifest::{ManifestBlock, ManifestEntry};
+pub use meta_cache::MetaCache;
+pub use meta_executor::{MetaExecutor, DEFAULT_META_THREADS};
+pub use meta_size::{MetaSizeHistory, MetadataSize, TreeSize, TreeSizeSample, META_SIZE_HISTORY_TREE};
+pub use metadata_engine::{migrate_store, recorded_engine, MigrationStats, ENGINE_FILE_NAME};
+pub use placement::{Placement, MAX_LOCATION_NAME};
+pub use request_timings::PhaseTimings;
+pub use resumable::{ResumableUpload, ResumableUploads, RESUMABLE_UPLOADS_TREE};
+pub use fs::DEFAULT_WRITE_CONCURRENCY;
 pub use shared_block_store::SharedBlockStore;
+pub use store_lock::{StoreLock, StoreLockError};
+pub use usage::{BucketUsage, StoreStats, UsageHistory, UsageSample, STATS_HISTORY_TREE};
+pub use write_limiter::{AdaptiveWriteLimiter, WriteLimiterConfig};
 mod buffered_byte_stream;
+mod refcount_batch;
 pub mod fs;
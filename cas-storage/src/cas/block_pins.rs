@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use faster_hex::hex_string;
+
+use super::delete_queue::{DeleteQueue, QueuedBlock};
+use crate::metastore::BaseMetaTree;
+
+/// A block file whose physical deletion was postponed because it was being read.
+#[derive(Debug)]
+struct DeferredDelete {
+    // entry of the block in the delete queue, which removes it after a restart
+    queued: QueuedBlock,
+}
+
+#[derive(Debug, Default)]
+struct PinState {
+    readers: HashMap<PathBuf, usize>,
+    deferred: HashMap<PathBuf, DeferredDelete>,
+}
+
+/// Keeps track of block files which are being streamed to clients.
+///
+/// A delete which drops the last reference to a block removes the block metadata
+/// right away, but when the block file is pinned by a reader, removing the file
+/// (and unlinking its path in the path map) is deferred until the last reader
+/// releases its pin. Until then the path stays allocated, so new blocks can't
+/// end up at the same location on disk.
+///
+/// A deferred removal is recorded in the delete queue as well, so the file is
+/// still removed if the process stops before the last reader is done. The last
+/// reader takes the block off the queue once the file is removed.
+pub struct BlockPins {
+    state: Mutex<PinState>,
+    path_tree: Arc<dyn BaseMetaTree>,
+    delete_queue: DeleteQueue,
+}
+
+/// Pins a set of block files for as long as it is alive.
+pub struct BlockPinGuard {
+    pins: Arc<BlockPins>,
+    paths: Vec<PathBuf>,
+}
+
+impl BlockPins {
+    pub fn new(path_tree: Arc<dyn BaseMetaTree>, delete_queue: DeleteQueue) -> Self {
+        Self {
+            state: Mutex::new(PinState::default()),
+            path_tree,
+            delete_queue,
+        }
+    }
+
+    /// Pin the given block files until the returned guard is dropped.
+    pub fn pin(self: &Arc<Self>, paths: &[PathBuf]) -> BlockPinGuard {
+        let mut state = self.state.lock().expect("pin lock is not poisoned");
+        for path in paths {
+            *state.readers.entry(path.clone()).or_insert(0) += 1;
+        }
+        BlockPinGuard {
+            pins: Arc::clone(self),
+            paths: paths.to_vec(),
+        }
+    }
+
+    /// Returns true if the block file is currently pinned by a reader.
+    pub fn is_pinned(&self, disk_path: &Path) -> bool {
+        let state = self.state.lock().expect("pin lock is not poisoned");
+        state.readers.contains_key(disk_path)
+    }
+
+    /// Defer the removal of a block file if it is pinned, `queued` is its entry in
+    /// the delete queue. Returns None if the removal was deferred, in which case
+    /// the caller must not remove the file. Otherwise the entry is returned, and
+    /// the caller removes the file and then the entry.
+    pub fn defer_if_pinned(&self, disk_path: &Path, queued: QueuedBlock) -> Option<QueuedBlock> {
+        let mut state = self.state.lock().expect("pin lock is not poisoned");
+        if !state.readers.contains_key(disk_path) {
+            return Some(queued);
+        }
+        tracing::debug!(
+            path = %disk_path.display(),
+            "Block is being read, deferring deletion"
+        );
+        state
+            .deferred
+            .insert(disk_path.to_path_buf(), DeferredDelete { queued });
+        None
+    }
+
+    fn unpin(&self, paths: &[PathBuf]) {
+        let mut ready = Vec::new();
+        {
+            let mut state = self.state.lock().expect("pin lock is not poisoned");
+            for path in paths {
+                let remaining = match state.readers.get_mut(path) {
+                    Some(count) => {
+                        *count -= 1;
+                        *count
+                    }
+                    None => continue,
+                };
+                if remaining == 0 {
+                    state.readers.remove(path);
+                    if let Some(deferred) = state.deferred.remove(path) {
+                        ready.push((path.clone(), deferred));
+                    }
+                }
+            }
+        }
+
+        // Perform the deferred deletions outside of the lock
+        for (disk_path, deferred) in ready {
+            let map_path = deferred.queued.block.path();
+            match std::fs::remove_file(&disk_path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    // the delete queue retries it
+                    tracing::error!(path = %disk_path.display(), error = %e, "Could not remove deferred block");
+                    continue;
+                }
+            }
+            if let Err(e) = self.path_tree.remove(map_path) {
+                tracing::error!(
+                    path = %hex_string(map_path),
+                    error = %e,
+                    "Could not unlink path from path map"
+                );
+                continue;
+            }
+            if let Err(e) = self.delete_queue.remove(&deferred.queued) {
+                tracing::error!(
+                    path = %hex_string(map_path),
+                    error = %e,
+                    "Could not take deferred block off the delete queue"
+                );
+            }
+        }
+    }
+}
+
+impl Drop for BlockPinGuard {
+    fn drop(&mut self) {
+        self.pins.unpin(&self.paths);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::metrics::SharedMetrics;
 
+use super::block_pins::BlockPinGuard;
 use super::range_request::RangeRequest;
 use bytes::Bytes;
 use futures::{ready, AsyncRead, AsyncSeek, Future, Stream};
@@ -21,6 +22,8 @@ pub struct BlockStream {
     range: RangeRequest,
     file: Option<async_fs::File>, // current file to read
     open_fut: Option<Pin<Box<dyn Future<Output = io::Result<async_fs::File>> + Send + Sync>>>,
+    // keeps the block files from being deleted while they are streamed
+    _pin: Option<BlockPinGuard>,
 }
 
 impl BlockStream {
@@ -40,8 +43,15 @@ impl BlockStream {
             processed: 0,
             open_fut: None,
             range,
+            _pin: None,
         }
     }
+
+    /// Keep the block files pinned until the stream is dropped
+    pub fn with_pin(mut self, pin: BlockPinGuard) -> Self {
+        self._pin = Some(pin);
+        self
+    }
 }
 unsafe impl Sync for BlockStream {}
 
@@ -0,0 +1,367 @@
+//! Request and transfer counts of the buckets over the last hour, to find hot
+//! buckets and noisy tenants.
+//!
+//! Counts are kept in memory in a ring buffer of one minute slots per bucket, so
+//! recording a request takes no metadata write. The rings are flushed to a tree
+//! of the metadata store now and then, so a restart keeps the last hour.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::metastore::{MetaError, MetaStore};
+
+/// Tree in the user metadata store holding the activity of the buckets
+pub const BUCKET_ACTIVITY_TREE: &str = "_BUCKET_ACTIVITY";
+
+/// Minutes of activity kept per bucket, the longest window which can be queried
+pub const ACTIVITY_WINDOW_MINUTES: u64 = 60;
+
+/// Whether a request reads or modifies a bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl Access {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Write => "write",
+        }
+    }
+}
+
+/// Requests and bytes transferred over some time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityCounts {
+    pub reads: u64,
+    pub writes: u64,
+    /// Bytes of object data sent to clients
+    pub bytes_read: u64,
+    /// Bytes of object data received from clients
+    pub bytes_written: u64,
+    /// Bytes of object data sent from a requester pays bucket, charged to the
+    /// requesters instead of the owner and not part of `bytes_read`
+    #[serde(default)]
+    pub bytes_read_by_requesters: u64,
+}
+
+impl ActivityCounts {
+    pub fn requests(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes_read + self.bytes_written + self.bytes_read_by_requesters
+    }
+
+    fn add(&mut self, other: &ActivityCounts) {
+        self.reads += other.reads;
+        self.writes += other.writes;
+        self.bytes_read += other.bytes_read;
+        self.bytes_written += other.bytes_written;
+        self.bytes_read_by_requesters += other.bytes_read_by_requesters;
+    }
+}
+
+/// Activity of a bucket over a window
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BucketActivity {
+    pub bucket: String,
+    #[serde(flatten)]
+    pub counts: ActivityCounts,
+}
+
+/// The activity of one minute, in minutes since the UNIX epoch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Slot {
+    minute: u64,
+    #[serde(flatten)]
+    counts: ActivityCounts,
+}
+
+/// The slots of a bucket, the slot of a minute is at `minute % ACTIVITY_WINDOW_MINUTES`
+#[derive(Debug, Clone, Default)]
+struct Ring {
+    slots: Vec<Slot>,
+    /// Changed since the last flush
+    dirty: bool,
+}
+
+impl Ring {
+    fn slot(&mut self, minute: u64) -> &mut Slot {
+        if self.slots.is_empty() {
+            self.slots = vec![Slot::default(); ACTIVITY_WINDOW_MINUTES as usize];
+        }
+        let slot = &mut self.slots[(minute % ACTIVITY_WINDOW_MINUTES) as usize];
+        if slot.minute != minute {
+            *slot = Slot {
+                minute,
+                counts: ActivityCounts::default(),
+            };
+        }
+        slot
+    }
+
+    fn record(&mut self, minute: u64, access: Access, bytes: u64) {
+        let counts = &mut self.slot(minute).counts;
+        match access {
+            Access::Read => {
+                counts.reads += 1;
+                counts.bytes_read += bytes;
+            }
+            Access::Write => {
+                counts.writes += 1;
+                counts.bytes_written += bytes;
+            }
+        }
+        self.dirty = true;
+    }
+
+    fn record_requester_paid(&mut self, minute: u64, bytes: u64) {
+        let counts = &mut self.slot(minute).counts;
+        counts.reads += 1;
+        counts.bytes_read_by_requesters += bytes;
+        self.dirty = true;
+    }
+
+    /// The sum of the last `minutes` slots up to `now`
+    fn sum(&self, now: u64, minutes: u64) -> ActivityCounts {
+        let mut sum = ActivityCounts::default();
+        for slot in &self.slots {
+            if slot.minute <= now && slot.minute + minutes > now {
+                sum.add(&slot.counts);
+            }
+        }
+        sum
+    }
+
+    fn to_vec(&self) -> Result<Vec<u8>, MetaError> {
+        let slots: Vec<&Slot> = self.slots.iter().filter(|slot| slot.minute > 0).collect();
+        serde_json::to_vec(&slots).map_err(|e| MetaError::OtherDBError(e.to_string()))
+    }
+
+    fn from_slice(data: &[u8]) -> Result<Ring, MetaError> {
+        let slots: Vec<Slot> = serde_json::from_slice(data)
+            .map_err(|e| MetaError::OtherDBError(format!("invalid bucket activity: {}", e)))?;
+        let mut ring = Ring::default();
+        for slot in slots {
+            *ring.slot(slot.minute) = slot;
+        }
+        Ok(ring)
+    }
+}
+
+fn now_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 60)
+        .unwrap_or_default()
+}
+
+/// The activity of the buckets of a store in the last hour
+#[derive(Debug, Default)]
+pub struct ActivityTracker {
+    rings: Mutex<HashMap<String, Ring>>,
+}
+
+impl ActivityTracker {
+    /// A tracker starting with the activity stored in `meta_store`
+    pub fn load(meta_store: &MetaStore) -> Result<Self, MetaError> {
+        let tree = meta_store.get_bucket_ext(BUCKET_ACTIVITY_TREE)?;
+        let mut rings = HashMap::new();
+        for item in tree.iter_all() {
+            let (key, value) = item?;
+            let bucket = String::from_utf8_lossy(&key).into_owned();
+            rings.insert(bucket, Ring::from_slice(&value)?);
+        }
+        Ok(Self {
+            rings: Mutex::new(rings),
+        })
+    }
+
+    /// Count a request to `bucket`, transferring `bytes` of object data
+    pub fn record(&self, bucket: &str, access: Access, bytes: u64) {
+        self.record_at(now_minute(), bucket, access, bytes);
+    }
+
+    /// Count a download of `bytes` from a requester pays bucket, charged to the
+    /// requester
+    pub fn record_requester_paid(&self, bucket: &str, bytes: u64) {
+        self.update(bucket, |ring| {
+            ring.record_requester_paid(now_minute(), bytes)
+        });
+    }
+
+    fn record_at(&self, minute: u64, bucket: &str, access: Access, bytes: u64) {
+        self.update(bucket, |ring| ring.record(minute, access, bytes));
+    }
+
+    fn update(&self, bucket: &str, record: impl FnOnce(&mut Ring)) {
+        let mut rings = self.rings.lock().unwrap();
+        match rings.get_mut(bucket) {
+            Some(ring) => record(ring),
+            None => {
+                let mut ring = Ring::default();
+                record(&mut ring);
+                rings.insert(bucket.to_string(), ring);
+            }
+        }
+    }
+
+    /// The buckets with requests in the last `minutes`, at most an hour, with the
+    /// most requests first
+    pub fn busiest(&self, minutes: u64) -> Vec<BucketActivity> {
+        self.busiest_at(now_minute(), minutes)
+    }
+
+    fn busiest_at(&self, now: u64, minutes: u64) -> Vec<BucketActivity> {
+        let minutes = minutes.clamp(1, ACTIVITY_WINDOW_MINUTES);
+        let mut busiest: Vec<BucketActivity> = self
+            .rings
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(bucket, ring)| BucketActivity {
+                bucket: bucket.clone(),
+                counts: ring.sum(now, minutes),
+            })
+            .filter(|activity| activity.counts.requests() > 0)
+            .collect();
+        busiest.sort_by(|a, b| {
+            (b.counts.requests(), b.counts.bytes())
+                .cmp(&(a.counts.requests(), a.counts.bytes()))
+                .then_with(|| a.bucket.cmp(&b.bucket))
+        });
+        busiest
+    }
+
+    /// Store the activity of the buckets with requests since the last flush.
+    /// Returns the amount of buckets stored.
+    pub fn flush(&self, meta_store: &MetaStore) -> Result<usize, MetaError> {
+        let changed: Vec<(String, Vec<u8>)> = {
+            let mut rings = self.rings.lock().unwrap();
+            let mut changed = Vec::new();
+            for (bucket, ring) in rings.iter_mut().filter(|(_, ring)| ring.dirty) {
+                changed.push((bucket.clone(), ring.to_vec()?));
+                ring.dirty = false;
+            }
+            changed
+        };
+        if changed.is_empty() {
+            return Ok(0);
+        }
+
+        let tree = meta_store.get_tree(BUCKET_ACTIVITY_TREE)?;
+        for (i, (bucket, value)) in changed.iter().enumerate() {
+            if let Err(e) = tree.insert(bucket.as_bytes(), value.clone()) {
+                // the next flush stores the ones which weren't
+                let mut rings = self.rings.lock().unwrap();
+                for (bucket, _) in &changed[i..] {
+                    if let Some(ring) = rings.get_mut(bucket) {
+                        ring.dirty = true;
+                    }
+                }
+                return Err(e);
+            }
+        }
+        Ok(changed.len())
+    }
+
+    /// Forget the activity of a deleted bucket
+    pub fn remove(&self, meta_store: &MetaStore, bucket: &str) -> Result<(), MetaError> {
+        self.rings.lock().unwrap().remove(bucket);
+        meta_store
+            .get_tree(BUCKET_ACTIVITY_TREE)?
+            .remove(bucket.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::FjallStore;
+
+    #[test]
+    fn test_rolling_window() {
+        let tracker = ActivityTracker::default();
+        tracker.record_at(1000, "cold", Access::Read, 10);
+        tracker.record_at(1050, "hot", Access::Write, 100);
+        tracker.record_at(1059, "hot", Access::Read, 5);
+        tracker.record_at(1059, "hot", Access::Read, 5);
+
+        let busiest = tracker.busiest_at(1059, 60);
+        assert_eq!(busiest.len(), 2);
+        assert_eq!(busiest[0].bucket, "hot");
+        assert_eq!(
+            busiest[0].counts,
+            ActivityCounts {
+                reads: 2,
+                writes: 1,
+                bytes_read: 10,
+                bytes_written: 100,
+                bytes_read_by_requesters: 0,
+            }
+        );
+        assert_eq!(busiest[1].counts.bytes_read, 10);
+
+        // the last minute only
+        let busiest = tracker.busiest_at(1059, 1);
+        assert_eq!(busiest.len(), 1);
+        assert_eq!(busiest[0].counts.requests(), 2);
+
+        // an hour later the slot of minute 1000 is reused
+        tracker.record_at(1060, "cold", Access::Read, 1);
+        let busiest = tracker.busiest_at(1060, 60);
+        let cold = busiest.iter().find(|a| a.bucket == "cold").unwrap();
+        assert_eq!(cold.counts.reads, 1);
+        assert_eq!(cold.counts.bytes_read, 1);
+        assert!(tracker.busiest_at(1200, 60).is_empty());
+    }
+
+    #[test]
+    fn test_flush_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FjallStore::new(dir.path().to_path_buf(), Some(1), None);
+        let meta_store = MetaStore::new(store, Some(1));
+
+        let tracker = ActivityTracker::default();
+        let now = now_minute();
+        tracker.record_at(now, "bucket", Access::Write, 42);
+        tracker.record_at(now, "other", Access::Read, 1);
+        assert_eq!(tracker.flush(&meta_store).unwrap(), 2);
+        // nothing changed since
+        assert_eq!(tracker.flush(&meta_store).unwrap(), 0);
+        tracker.remove(&meta_store, "other").unwrap();
+
+        let loaded = ActivityTracker::load(&meta_store).unwrap();
+        let busiest = loaded.busiest_at(now, 60);
+        assert_eq!(busiest.len(), 1);
+        assert_eq!(busiest[0].bucket, "bucket");
+        assert_eq!(busiest[0].counts.bytes_written, 42);
+    }
+
+    #[test]
+    fn test_requester_paid_reads() {
+        let tracker = ActivityTracker::default();
+        tracker.record("dataset", Access::Read, 10);
+        tracker.record_requester_paid("dataset", 100);
+
+        let busiest = tracker.busiest(1);
+        assert_eq!(busiest[0].counts.reads, 2);
+        assert_eq!(busiest[0].counts.bytes_read, 10);
+        assert_eq!(busiest[0].counts.bytes_read_by_requesters, 100);
+        assert_eq!(busiest[0].counts.bytes(), 110);
+
+        // slots stored before the counter existed still load
+        let ring = Ring::from_slice(
+            br#"[{"minute":5,"reads":1,"writes":0,"bytes_read":3,"bytes_written":0}]"#,
+        )
+        .unwrap();
+        assert_eq!(ring.sum(5, 1).bytes_read, 3);
+    }
+}
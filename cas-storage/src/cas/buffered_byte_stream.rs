@@ -1,4 +1,3 @@
-use super::fs::BLOCK_SIZE;
 use futures::{ready, Stream};
 use rusoto_core::ByteStream;
 use std::{
@@ -15,14 +14,16 @@ pub struct BufferedByteStream {
     // TODO: benchmark both approaches
     bs: ByteStream,
     buffer: Vec<u8>,
+    block_size: usize,
     finished: bool,
 }
 
 impl BufferedByteStream {
-    pub fn new(bs: ByteStream) -> Self {
+    pub fn new(bs: ByteStream, block_size: usize) -> Self {
         Self {
             bs,
-            buffer: Vec::with_capacity(BLOCK_SIZE),
+            buffer: Vec::with_capacity(block_size),
+            block_size,
             finished: false,
         }
     }
@@ -36,6 +37,7 @@ impl Stream for BufferedByteStream {
             return Poll::Ready(None);
         }
 
+        let block_size = self.block_size;
         loop {
             match ready!(Pin::new(&mut self.bs).poll_next(cx)) {
                 None => {
@@ -59,7 +61,7 @@ impl Stream for BufferedByteStream {
                         self.buffer.extend_from_slice(&bytes);
                         return Poll::Ready(Some(Ok(vec![mem::replace(
                             &mut self.buffer,
-                            Vec::with_capacity(BLOCK_SIZE),
+                            Vec::with_capacity(block_size),
                         )])));
                     } else {
                         let mut out = Vec::with_capacity(
@@ -68,12 +70,12 @@ impl Stream for BufferedByteStream {
                         self.buffer.extend_from_slice(&bytes[..buf_remainder]);
                         out.push(mem::replace(
                             &mut self.buffer,
-                            Vec::with_capacity(BLOCK_SIZE),
+                            Vec::with_capacity(block_size),
                         ));
                         // repurpose buf_remainder as pointer to start of data
-                        while bytes[buf_remainder..].len() > BLOCK_SIZE {
-                            out.push(Vec::from(&bytes[buf_remainder..buf_remainder + BLOCK_SIZE]));
-                            buf_remainder += BLOCK_SIZE;
+                        while bytes[buf_remainder..].len() > block_size {
+                            out.push(Vec::from(&bytes[buf_remainder..buf_remainder + block_size]));
+                            buf_remainder += block_size;
                         }
                         // place the remainder in our buf
                         self.buffer.extend_from_slice(&bytes[buf_remainder..]);
@@ -0,0 +1,605 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::metastore::{
+    BaseMetaTree, BlockTree, Durability, FjallStore, FjallStoreNotx, KvSeparation, MetaError,
+    MetaStore,
+};
+use crate::metrics::SharedMetrics;
+
+use super::{
+    content_hash::ContentHash,
+    events::ObjectEventHandler,
+    fs::{CasFS, StorageEngine, BLOCK_SIZE, DEFAULT_WRITE_CONCURRENCY},
+    group_sync::GroupSync,
+    hash_pool::HashPool,
+    meta_executor::{MetaExecutor, DEFAULT_META_THREADS},
+    metadata_engine::check_engine,
+    multipart::MultiPartTree,
+    placement::{Placement, MAX_LOCATION_NAME},
+    shared_block_store::SharedBlockStore,
+    write_limiter::AdaptiveWriteLimiter,
+};
+
+/// Smallest supported block size
+pub const MIN_BLOCK_SIZE: usize = 4 << 10;
+/// Largest supported block size, every block is held in memory while it is written
+pub const MAX_BLOCK_SIZE: usize = 64 << 20;
+
+/// Errors returned by [`CasFSBuilder::build`]
+#[derive(Debug)]
+pub enum BuildError {
+    /// The block size is outside of `MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE`
+    InvalidBlockSize(usize),
+    /// A storage directory could not be created
+    CreateDir { path: PathBuf, source: io::Error },
+    /// The metadata store could not be opened
+    OpenMetaStore { path: PathBuf, source: MetaError },
+    /// A storage location has an invalid name, or a placement rule refers to a
+    /// location which doesn't exist
+    InvalidPlacement(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::InvalidBlockSize(size) => write!(
+                f,
+                "Invalid block size {}, must be between {} and {} bytes",
+                size, MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+            ),
+            BuildError::CreateDir { path, source } => {
+                write!(
+                    f,
+                    "Could not create directory {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            BuildError::OpenMetaStore { path, source } => write!(
+                f,
+                "Could not open metadata store at {}: {}",
+                path.display(),
+                source
+            ),
+            BuildError::InvalidPlacement(reason) => write!(f, "Invalid block placement: {reason}"),
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BuildError::InvalidBlockSize(_) => None,
+            BuildError::CreateDir { source, .. } => Some(source),
+            BuildError::OpenMetaStore { source, .. } => Some(source),
+            BuildError::InvalidPlacement(_) => None,
+        }
+    }
+}
+
+/// Builder for [`CasFS`] instances.
+///
+/// Blocks are stored in `<fs_root>/blocks` and metadata in `<meta_root>/db`.
+///
+/// ```no_run
+/// use cas_storage::{CasFSBuilder, Durability};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let casfs = CasFSBuilder::new("./data", "./data/meta")
+///     .durability(Durability::Fsync)
+///     .inlined_metadata_size(4096)
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct CasFSBuilder {
+    fs_root: PathBuf,
+    meta_root: PathBuf,
+    metrics: SharedMetrics,
+    storage_engine: StorageEngine,
+    inlined_metadata_size: Option<usize>,
+    durability: Option<Durability>,
+    kv_separation: Option<KvSeparation>,
+    block_size: usize,
+    content_hash: ContentHash,
+    shared_block_store: Option<Arc<SharedBlockStore>>,
+    write_concurrency: usize,
+    write_limiter: Option<Arc<AdaptiveWriteLimiter>>,
+    group_sync: Option<Arc<GroupSync>>,
+    list_snapshots: Option<(Duration, usize)>,
+    meta_cache_entries: usize,
+    meta_threads: usize,
+    meta_executor: Option<Arc<MetaExecutor>>,
+    hash_pool: Option<Arc<HashPool>>,
+    block_refs: bool,
+    bucket_durability: HashMap<String, Durability>,
+    event_handlers: Vec<Arc<dyn ObjectEventHandler>>,
+    storage_locations: HashMap<String, PathBuf>,
+    placement_rules: Vec<(String, String, String)>,
+    delete_grace: Duration,
+}
+
+impl CasFSBuilder {
+    pub fn new(fs_root: impl Into<PathBuf>, meta_root: impl Into<PathBuf>) -> Self {
+        Self {
+            fs_root: fs_root.into(),
+            meta_root: meta_root.into(),
+            metrics: SharedMetrics::default(),
+            storage_engine: StorageEngine::Fjall,
+            inlined_metadata_size: None,
+            durability: None,
+            kv_separation: None,
+            block_size: BLOCK_SIZE,
+            content_hash: ContentHash::default(),
+            shared_block_store: None,
+            write_concurrency: DEFAULT_WRITE_CONCURRENCY,
+            write_limiter: None,
+            group_sync: None,
+            list_snapshots: None,
+            meta_cache_entries: 0,
+            meta_threads: DEFAULT_META_THREADS,
+            meta_executor: None,
+            hash_pool: None,
+            block_refs: false,
+            bucket_durability: HashMap::new(),
+            event_handlers: Vec::new(),
+            storage_locations: HashMap::new(),
+            placement_rules: Vec::new(),
+            delete_grace: Duration::ZERO,
+        }
+    }
+
+    pub fn metrics(mut self, metrics: SharedMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Storage engine of the metadata store, defaults to `StorageEngine::Fjall`.
+    pub fn storage_engine(mut self, storage_engine: StorageEngine) -> Self {
+        self.storage_engine = storage_engine;
+        self
+    }
+
+    /// Maximum size of objects which are stored inlined in the metadata.
+    pub fn inlined_metadata_size(mut self, size: usize) -> Self {
+        self.inlined_metadata_size = Some(size);
+        self
+    }
+
+    /// Durability of metadata transactions. A write is acknowledged once its
+    /// commit is persisted with it, with both storage engines.
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = Some(durability);
+        self
+    }
+
+    /// Store the large values of new bucket partitions, the objects with inlined
+    /// data, in blob files instead of the LSM tree.
+    pub fn kv_separation(mut self, kv_separation: KvSeparation) -> Self {
+        self.kv_separation = Some(kv_separation);
+        self
+    }
+
+    /// Durability of the metadata commits writing to `bucket`, instead of the
+    /// `durability` of the store. See [`Durability`] for the crash consistency of
+    /// buckets with different durabilities.
+    ///
+    /// ```no_run
+    /// use cas_storage::{CasFSBuilder, Durability};
+    ///
+    /// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// // objects in `scratch` can be recreated, they don't need an fsync per write
+    /// let casfs = CasFSBuilder::new("./data", "./data/meta")
+    ///     .durability(Durability::Fdatasync)
+    ///     .bucket_durability("scratch", Durability::Buffer)
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn bucket_durability(mut self, bucket: impl Into<String>, durability: Durability) -> Self {
+        self.bucket_durability.insert(bucket.into(), durability);
+        self
+    }
+
+    /// Size of the blocks new objects are split into, defaults to 1 MiB.
+    ///
+    /// Blocks are deduplicated by their hash, so objects stored with different
+    /// block sizes don't share blocks.
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Hash identifying blocks and the content of objects, defaults to
+    /// `ContentHash::Md5`. See [`CasFS::with_content_hash`].
+    pub fn content_hash(mut self, content_hash: ContentHash) -> Self {
+        self.content_hash = content_hash;
+        self
+    }
+
+    /// Build a multi-user instance: blocks, paths and multipart uploads are kept
+    /// in the shared store, and only the bucket and object metadata of the user
+    /// in `meta_root`.
+    pub fn shared_block_store(mut self, shared_block_store: Arc<SharedBlockStore>) -> Self {
+        self.shared_block_store = Some(shared_block_store);
+        self
+    }
+
+    /// See [`CasFS::with_write_concurrency`].
+    pub fn write_concurrency(mut self, write_concurrency: usize) -> Self {
+        self.write_concurrency = write_concurrency;
+        self
+    }
+
+    /// See [`CasFS::with_write_limiter`].
+    pub fn write_limiter(mut self, limiter: Arc<AdaptiveWriteLimiter>) -> Self {
+        self.write_limiter = Some(limiter);
+        self
+    }
+
+    /// See [`CasFS::with_group_sync`].
+    pub fn group_sync(mut self, group_sync: Arc<GroupSync>) -> Self {
+        self.group_sync = Some(group_sync);
+        self
+    }
+
+    /// See [`CasFS::with_list_snapshots`].
+    pub fn list_snapshots(mut self, max_lifetime: Duration, max_count: usize) -> Self {
+        self.list_snapshots = Some((max_lifetime, max_count));
+        self
+    }
+
+    /// See [`CasFS::with_delete_grace`].
+    pub fn delete_grace(mut self, grace: Duration) -> Self {
+        self.delete_grace = grace;
+        self
+    }
+
+    /// See [`CasFS::with_meta_cache`], 0 disables the cache.
+    pub fn meta_cache(mut self, entries: usize) -> Self {
+        self.meta_cache_entries = entries;
+        self
+    }
+
+    /// Maximum amount of metadata operations running on blocking threads, see
+    /// [`MetaExecutor`]. Ignored if an executor is set with `meta_executor`.
+    pub fn meta_threads(mut self, threads: usize) -> Self {
+        self.meta_threads = threads;
+        self
+    }
+
+    /// Share a metadata executor with other CasFS instances.
+    pub fn meta_executor(mut self, executor: Arc<MetaExecutor>) -> Self {
+        self.meta_executor = Some(executor);
+        self
+    }
+
+    /// See [`CasFS::with_hash_pool`], the pool can be shared with other CasFS
+    /// instances.
+    pub fn hash_pool(mut self, pool: Arc<HashPool>) -> Self {
+        self.hash_pool = Some(pool);
+        self
+    }
+
+    /// Maintain the block reference index, see `MetaStore::set_block_refs`
+    pub fn block_refs(mut self, enabled: bool) -> Self {
+        self.block_refs = enabled;
+        self
+    }
+
+    /// See [`CasFS::with_event_handler`], can be called multiple times.
+    pub fn event_handler(mut self, handler: Arc<dyn ObjectEventHandler>) -> Self {
+        self.event_handlers.push(handler);
+        self
+    }
+
+    /// Add a storage location named `name`, for blocks stored in `<path>/blocks`,
+    /// e.g. on another disk. The name is recorded with every block placed there,
+    /// so a location can be moved by changing its path, but not renamed.
+    pub fn storage_location(mut self, name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.storage_locations.insert(name.into(), path.into());
+        self
+    }
+
+    /// Place the new blocks of objects in `bucket` whose key starts with `prefix` in
+    /// the storage location `location`, the longest matching prefix wins. See
+    /// [`Placement`].
+    ///
+    /// ```no_run
+    /// use cas_storage::CasFSBuilder;
+    ///
+    /// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let casfs = CasFSBuilder::new("./data", "./data/meta")
+    ///     .storage_location("hdd", "/mnt/hdd/s3-cas")
+    ///     .storage_location("ssd", "/mnt/ssd/s3-cas")
+    ///     .prefix_placement("media", "videos/", "hdd")
+    ///     .prefix_placement("media", "thumbs/", "ssd")
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn prefix_placement(
+        mut self,
+        bucket: impl Into<String>,
+        prefix: impl Into<String>,
+        location: impl Into<String>,
+    ) -> Self {
+        self.placement_rules
+            .push((bucket.into(), prefix.into(), location.into()));
+        self
+    }
+
+    /// Create the storage directories and open the metadata store.
+    pub fn build(self) -> Result<CasFS, BuildError> {
+        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&self.block_size) {
+            return Err(BuildError::InvalidBlockSize(self.block_size));
+        }
+
+        let root = prepare_dir(self.fs_root.join("blocks"))?;
+        let mut placement = Placement::default();
+        for (name, path) in self.storage_locations {
+            if name.is_empty() || name.len() > MAX_LOCATION_NAME {
+                return Err(BuildError::InvalidPlacement(format!(
+                    "storage location names must have 1 to {MAX_LOCATION_NAME} bytes, got '{name}'"
+                )));
+            }
+            placement.add_location(name, prepare_dir(path.join("blocks"))?);
+        }
+        for (bucket, prefix, location) in self.placement_rules {
+            placement.add_rule(bucket, prefix, location);
+        }
+        if let Some(name) = placement.unknown_locations().first() {
+            return Err(BuildError::InvalidPlacement(format!(
+                "unknown storage location '{name}'"
+            )));
+        }
+        let meta_path = prepare_dir(self.meta_root.join("db"))?;
+        let open_error = |source| BuildError::OpenMetaStore {
+            path: meta_path.clone(),
+            source,
+        };
+
+        let mut meta_store = open_meta_store(
+            meta_path.clone(),
+            self.storage_engine,
+            self.inlined_metadata_size,
+            self.durability,
+            self.kv_separation,
+        )
+        .map_err(open_error)?;
+        meta_store
+            .set_block_refs(self.block_refs)
+            .map_err(open_error)?;
+        meta_store.set_bucket_durability(self.bucket_durability);
+        meta_store.set_metrics(self.metrics.clone());
+        let shared = self.shared_block_store.as_deref().map(SharedTrees::from);
+        let meta_executor = match self.meta_executor {
+            Some(executor) => executor,
+            None => Arc::new(MetaExecutor::new(self.meta_threads, self.metrics.clone())),
+        };
+        let casfs = CasFS::from_parts(
+            root,
+            meta_store,
+            shared,
+            self.metrics,
+            meta_executor,
+            self.block_size,
+        )
+        .map_err(open_error)?
+        .with_write_concurrency(self.write_concurrency)
+        .with_content_hash(self.content_hash)
+        .with_placement(placement);
+
+        let casfs = match self.write_limiter {
+            Some(limiter) => casfs.with_write_limiter(limiter),
+            None => casfs,
+        };
+        let casfs = match self.group_sync {
+            Some(group_sync) => casfs.with_group_sync(group_sync),
+            None => casfs,
+        };
+        let casfs = match self.list_snapshots {
+            Some((lifetime, max_count)) => casfs.with_list_snapshots(lifetime, max_count),
+            None => casfs,
+        };
+        let casfs = match self.hash_pool {
+            Some(pool) => casfs.with_hash_pool(pool),
+            None => casfs,
+        };
+        let casfs = match self.meta_cache_entries {
+            0 => casfs,
+            entries => casfs.with_meta_cache(entries),
+        };
+        let casfs = casfs.with_delete_grace(self.delete_grace);
+        let casfs = self
+            .event_handlers
+            .into_iter()
+            .fold(casfs, CasFS::with_event_handler);
+        Ok(casfs)
+    }
+}
+
+/// The trees of a `SharedBlockStore` used by a multi-user CasFS
+pub(super) struct SharedTrees {
+    pub block_tree: Arc<BlockTree>,
+    pub path_tree: Arc<dyn BaseMetaTree>,
+    pub multipart_tree: Arc<MultiPartTree>,
+    pub meta_store: Arc<MetaStore>,
+}
+
+impl From<&SharedBlockStore> for SharedTrees {
+    fn from(store: &SharedBlockStore) -> Self {
+        Self {
+            block_tree: store.block_tree(),
+            path_tree: store.path_tree(),
+            multipart_tree: store.multipart_tree(),
+            meta_store: store.meta_store(),
+        }
+    }
+}
+
+/// Create a directory and canonicalize its path.
+// Canonical paths eliminate getcwd() syscalls in async operations, this is critical
+// for performance as it avoids repeated getcwd() on every file op
+pub(super) fn prepare_dir(path: PathBuf) -> Result<PathBuf, BuildError> {
+    if let Err(source) = std::fs::create_dir_all(&path) {
+        return Err(BuildError::CreateDir { path, source });
+    }
+    Ok(path.canonicalize().unwrap_or(path))
+}
+
+pub(super) fn open_meta_store(
+    path: PathBuf,
+    storage_engine: StorageEngine,
+    inlined_metadata_size: Option<usize>,
+    durability: Option<Durability>,
+    kv_separation: Option<KvSeparation>,
+) -> Result<MetaStore, MetaError> {
+    check_engine(&path, storage_engine)?;
+    let meta_store = match storage_engine {
+        StorageEngine::Fjall => {
+            let store = FjallStore::try_new(path, inlined_metadata_size, durability)?
+                .with_kv_separation(kv_separation);
+            MetaStore::new(store, inlined_metadata_size)
+        }
+        StorageEngine::FjallNotx => {
+            let store = FjallStoreNotx::try_new(path, inlined_metadata_size)?
+                .with_durability(durability)
+                .with_kv_separation(kv_separation);
+            MetaStore::new(store, inlined_metadata_size)
+        }
+    };
+    meta_store.ensure_counters()?;
+    Ok(meta_store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::stream;
+    use rusoto_core::ByteStream;
+
+    #[test]
+    fn test_invalid_block_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let res = CasFSBuilder::new(dir.path(), dir.path().join("meta"))
+            .block_size(MIN_BLOCK_SIZE - 1)
+            .build();
+        assert!(matches!(res, Err(BuildError::InvalidBlockSize(_))));
+    }
+
+    #[test]
+    fn test_create_dir_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"not a directory").unwrap();
+
+        let res = CasFSBuilder::new(&file, dir.path().join("meta")).build();
+        assert!(matches!(res, Err(BuildError::CreateDir { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_build_with_block_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let casfs = CasFSBuilder::new(dir.path(), dir.path().join("meta"))
+            .durability(Durability::Buffer)
+            .inlined_metadata_size(1)
+            .block_size(MIN_BLOCK_SIZE)
+            .build()
+            .unwrap();
+        assert_eq!(casfs.block_size(), MIN_BLOCK_SIZE);
+
+        casfs.create_bucket("bucket").unwrap();
+        let data: Vec<u8> = (0..MIN_BLOCK_SIZE * 2 + 1)
+            .map(|i| (i / MIN_BLOCK_SIZE) as u8)
+            .collect();
+        let len = data.len();
+        let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+        let obj = casfs
+            .store_single_object_and_meta("bucket", "key", stream, len)
+            .await
+            .unwrap();
+
+        // two full blocks and a partial one
+        assert_eq!(obj.blocks().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_bucket_durability() {
+        let dir = tempfile::tempdir().unwrap();
+        let casfs = CasFSBuilder::new(dir.path(), dir.path().join("meta"))
+            .durability(Durability::Fdatasync)
+            .bucket_durability("scratch", Durability::Buffer)
+            .inlined_metadata_size(1)
+            .build()
+            .unwrap();
+        assert_eq!(casfs.bucket_durability("scratch"), Some(Durability::Buffer));
+        assert_eq!(casfs.bucket_durability("other"), None);
+
+        // writes and deletions commit with either durability
+        for bucket in ["scratch", "other"] {
+            casfs.create_bucket(bucket).unwrap();
+            let stream = ByteStream::new(stream::once(async { Ok(Bytes::from_static(b"data")) }));
+            casfs
+                .store_single_object_and_meta(bucket, "key", stream, 4)
+                .await
+                .unwrap();
+            assert!(casfs.get_object_meta(bucket, "key").unwrap().is_some());
+        }
+        casfs.delete_object("scratch", "key").await.unwrap();
+        assert!(casfs.get_object_meta("scratch", "key").unwrap().is_none());
+        let (_, paths) = casfs.get_object_paths("other", "key").unwrap().unwrap();
+        assert!(paths[0].0.exists());
+    }
+
+    #[tokio::test]
+    async fn test_prefix_placement() {
+        let dir = tempfile::tempdir().unwrap();
+        let fast = dir.path().join("fast");
+        let casfs = CasFSBuilder::new(dir.path(), dir.path().join("meta"))
+            .durability(Durability::Buffer)
+            .inlined_metadata_size(1)
+            .storage_location("fast", &fast)
+            .prefix_placement("bucket", "thumbs/", "fast")
+            .build()
+            .unwrap();
+        let fast = fast.join("blocks").canonicalize().unwrap();
+
+        casfs.create_bucket("bucket").unwrap();
+        async fn store(casfs: &CasFS, key: &str, data: &'static [u8]) -> PathBuf {
+            let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+            casfs
+                .store_single_object_and_meta("bucket", key, stream, data.len())
+                .await
+                .unwrap();
+            let (_, paths) = casfs.get_object_paths("bucket", key).unwrap().unwrap();
+            paths[0].0.clone()
+        }
+        let thumb = store(&casfs, "thumbs/a.jpg", b"thumbnail").await;
+        assert!(thumb.starts_with(&fast));
+        assert!(thumb.exists());
+        let video = store(&casfs, "videos/a.mp4", b"video").await;
+        assert!(!video.starts_with(&fast));
+        assert!(video.exists());
+
+        // a deduplicated block stays where it was placed first
+        let copy = store(&casfs, "videos/b.jpg", b"thumbnail").await;
+        assert_eq!(copy, thumb);
+
+        casfs.delete_object("bucket", "thumbs/a.jpg").await.unwrap();
+        casfs.delete_object("bucket", "videos/b.jpg").await.unwrap();
+        assert!(!thumb.exists());
+
+        let res = CasFSBuilder::new(dir.path(), dir.path().join("meta2"))
+            .prefix_placement("bucket", "thumbs/", "missing")
+            .build();
+        assert!(matches!(res, Err(BuildError::InvalidPlacement(_))));
+    }
+}
@@ -0,0 +1,221 @@
+//! `multipart/byteranges` responses to requests for multiple ranges of an object.
+
+use std::collections::VecDeque;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::Stream;
+
+use super::block_pins::BlockPinGuard;
+use super::block_stream::BlockStream;
+use super::range_request::RangeRequest;
+use crate::metrics::SharedMetrics;
+
+/// The body of a `multipart/byteranges` response: every range is a part with its
+/// own `Content-Type` and `Content-Range` headers.
+#[derive(Debug)]
+pub struct ByteRanges {
+    boundary: String,
+    part_content_type: String,
+    size: u64,
+    // first and last byte of every range
+    ranges: Vec<(u64, u64)>,
+}
+
+impl ByteRanges {
+    /// The satisfiable `ranges` of an object of `size` bytes, with parts of
+    /// `part_content_type`. Ranges beyond the object are left out, `None` if none
+    /// remain and the request can't be satisfied.
+    pub fn new(ranges: &[RangeRequest], size: u64, part_content_type: &str) -> Option<Self> {
+        let ranges: Vec<_> = ranges
+            .iter()
+            .filter_map(|range| range.bounds(size))
+            .collect();
+        if ranges.is_empty() {
+            return None;
+        }
+        Some(Self {
+            boundary: uuid::Uuid::new_v4().simple().to_string(),
+            part_content_type: part_content_type.to_string(),
+            size,
+            ranges,
+        })
+    }
+
+    /// The `Content-Type` header of the response.
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    /// The `Content-Length` of the response.
+    pub fn content_length(&self) -> u64 {
+        let data: u64 = self.ranges.iter().map(|(start, end)| end - start + 1).sum();
+        let headers: u64 = self
+            .ranges
+            .iter()
+            .map(|&(start, end)| self.part_header(start, end).len() as u64 + 2)
+            .sum();
+        data + headers + self.closing().len() as u64
+    }
+
+    fn part_header(&self, start: u64, end: u64) -> String {
+        format!(
+            "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {start}-{end}/{}\r\n\r\n",
+            self.boundary, self.part_content_type, self.size
+        )
+    }
+
+    fn closing(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+
+    /// The body for an object whose data is held in memory.
+    pub fn body(&self, data: &[u8]) -> Bytes {
+        let mut body = Vec::with_capacity(self.content_length() as usize);
+        for &(start, end) in &self.ranges {
+            body.extend_from_slice(self.part_header(start, end).as_bytes());
+            body.extend_from_slice(&data[start as usize..=end as usize]);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(self.closing().as_bytes());
+        body.into()
+    }
+
+    /// The body for an object stored in the block files at `paths`, every range is
+    /// streamed from the blocks it covers.
+    pub fn stream(&self, paths: Vec<(PathBuf, usize)>, metrics: SharedMetrics) -> ByteRangesStream {
+        let mut parts = VecDeque::with_capacity(self.ranges.len() * 3 + 1);
+        for &(start, end) in &self.ranges {
+            parts.push_back(Part::Bytes(self.part_header(start, end).into()));
+            let blocks = BlockStream::new(
+                paths.clone(),
+                self.size as usize,
+                RangeRequest::FromBytes(start),
+                metrics.clone(),
+            );
+            parts.push_back(Part::Blocks(blocks, end - start + 1));
+            parts.push_back(Part::Bytes(Bytes::from_static(b"\r\n")));
+        }
+        parts.push_back(Part::Bytes(self.closing().into()));
+        ByteRangesStream { parts, _pin: None }
+    }
+}
+
+enum Part {
+    Bytes(Bytes),
+    // the blocks from the start of a range, and the amount of bytes left in it
+    Blocks(BlockStream, u64),
+}
+
+/// Stream of a `multipart/byteranges` body, see [`ByteRanges::stream`].
+pub struct ByteRangesStream {
+    parts: VecDeque<Part>,
+    // keeps the block files from being deleted while they are streamed
+    _pin: Option<BlockPinGuard>,
+}
+
+impl ByteRangesStream {
+    /// Keep the block files pinned until the stream is dropped
+    pub fn with_pin(mut self, pin: BlockPinGuard) -> Self {
+        self._pin = Some(pin);
+        self
+    }
+}
+
+impl Stream for ByteRangesStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.parts.front_mut() {
+                None => return Poll::Ready(None),
+                Some(Part::Bytes(_)) => {
+                    let Some(Part::Bytes(bytes)) = self.parts.pop_front() else {
+                        unreachable!()
+                    };
+                    return Poll::Ready(Some(Ok(bytes)));
+                }
+                Some(Part::Blocks(_, 0)) => {
+                    self.parts.pop_front();
+                }
+                Some(Part::Blocks(blocks, remaining)) => {
+                    // the block stream reads on from the start of the range, the
+                    // range ends where the last chunk is cut off
+                    match futures::ready!(Pin::new(blocks).poll_next(cx)) {
+                        Some(Ok(mut chunk)) => {
+                            if chunk.len() as u64 > *remaining {
+                                chunk.truncate(*remaining as usize);
+                            }
+                            *remaining -= chunk.len() as u64;
+                            return Poll::Ready(Some(Ok(chunk)));
+                        }
+                        Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                        None => {
+                            return Poll::Ready(Some(Err(io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                "block files end before the range",
+                            ))))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_byte_ranges() {
+        let data: Vec<u8> = (0..=255).collect();
+        let dir = tempdir().unwrap();
+        let mut paths = Vec::new();
+        for (i, block) in data.chunks(100).enumerate() {
+            let path = dir.path().join(i.to_string());
+            std::fs::write(&path, block).unwrap();
+            paths.push((path, block.len()));
+        }
+
+        let ranges = [
+            RangeRequest::Range(10, 19),
+            RangeRequest::Range(95, 205),
+            RangeRequest::FromBytes(250),
+            RangeRequest::FromBytes(300),
+        ];
+        let byte_ranges = ByteRanges::new(&ranges, 256, "text/plain").unwrap();
+        let body = byte_ranges.body(&data);
+        assert_eq!(body.len() as u64, byte_ranges.content_length());
+
+        let mut streamed = Vec::new();
+        let mut stream = byte_ranges.stream(paths, SharedMetrics::default());
+        while let Some(chunk) = stream.next().await {
+            streamed.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(streamed, body);
+
+        let boundary = &byte_ranges.boundary;
+        let mut expected = Vec::new();
+        for (start, end) in [(10, 19), (95, 205), (250, 255)] {
+            expected.extend_from_slice(
+                format!(
+                    "--{boundary}\r\nContent-Type: text/plain\r\n\
+                     Content-Range: bytes {start}-{end}/256\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            expected.extend_from_slice(&data[start..=end]);
+            expected.extend_from_slice(b"\r\n");
+        }
+        expected.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+        assert_eq!(body, expected);
+
+        assert!(ByteRanges::new(&[RangeRequest::FromBytes(256)], 256, "text/plain").is_none());
+    }
+}
@@ -0,0 +1,132 @@
+//! The hash identifying blocks and the content of objects.
+//!
+//! The content hash is a storage concern: blocks are deduplicated by it and an
+//! object records the hash of its content. The ETag returned to S3 clients is
+//! separate from it and always computed per S3 semantics, see `Object::e_tag`,
+//! so the content hash can change without clients noticing.
+//!
+//! Only MD5 is supported for now. A hash with a longer digest, like BLAKE3,
+//! also needs a larger `BlockID`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use md5::{Digest, Md5};
+
+use crate::metastore::{BlockID, ETag};
+
+/// Hash function identifying blocks and the content of objects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContentHash {
+    /// MD5, the content hash of objects is also their ETag
+    #[default]
+    Md5,
+}
+
+impl ContentHash {
+    /// Returns a hasher computing this hash incrementally.
+    pub fn hasher(self) -> ContentHasher {
+        match self {
+            ContentHash::Md5 => ContentHasher::Md5(Md5::new()),
+        }
+    }
+
+    /// Computes the hash of `data`.
+    pub fn digest(self, data: &[u8]) -> BlockID {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Returns `true` if the content hash of an object is also its ETag, which
+    /// then doesn't need to be computed separately.
+    pub fn is_e_tag(self) -> bool {
+        matches!(self, ContentHash::Md5)
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentHash::Md5 => f.write_str("md5"),
+        }
+    }
+}
+
+impl FromStr for ContentHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "md5" => Ok(ContentHash::Md5),
+            _ => Err(format!("Unknown content hash: {s}")),
+        }
+    }
+}
+
+/// Incremental [`ContentHash`] computation
+pub enum ContentHasher {
+    Md5(Md5),
+}
+
+impl ContentHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ContentHasher::Md5(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> BlockID {
+        match self {
+            ContentHasher::Md5(hasher) => hasher.finalize().into(),
+        }
+    }
+}
+
+/// Computes the ETag of an object uploaded in one part: the MD5 of its data.
+pub fn e_tag(data: &[u8]) -> ETag {
+    Md5::digest(data).into()
+}
+
+/// Computes the ETag of an object uploaded in parts from the ETags of its parts,
+/// the MD5 of the concatenated binary ETags. The number of parts is only added
+/// when the ETag is formatted.
+pub fn multipart_e_tag<'a>(part_e_tags: impl IntoIterator<Item = &'a ETag>) -> ETag {
+    let mut hasher = Md5::new();
+    for part_e_tag in part_e_tags {
+        hasher.update(part_e_tag);
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_e_tags() {
+        // the ETags of S3 for the empty object and for an object of two parts
+        assert_eq!(
+            faster_hex::hex_string(&e_tag(b"")),
+            "d41d8cd98f00b204e9800998ecf8427e"
+        );
+        let parts = [e_tag(b"part 1"), e_tag(b"part 2")];
+        let mut concatenated = Vec::new();
+        concatenated.extend_from_slice(&parts[0]);
+        concatenated.extend_from_slice(&parts[1]);
+        assert_eq!(multipart_e_tag(&parts), e_tag(&concatenated));
+    }
+
+    #[test]
+    fn test_content_hash() {
+        assert_eq!("MD5".parse::<ContentHash>(), Ok(ContentHash::Md5));
+        assert!("sha1".parse::<ContentHash>().is_err());
+        assert_eq!(ContentHash::Md5.to_string(), "md5");
+
+        let mut hasher = ContentHash::Md5.hasher();
+        hasher.update(b"some ");
+        hasher.update(b"data");
+        assert_eq!(hasher.finalize(), ContentHash::Md5.digest(b"some data"));
+        assert_eq!(ContentHash::Md5.digest(b"some data"), e_tag(b"some data"));
+    }
+}
@@ -0,0 +1,160 @@
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::metastore::{BlockID, MetaError, MetaStore};
+
+/// Tree in the block metadata store holding the blocks marked as corrupt
+pub const CORRUPT_BLOCKS_TREE: &str = "_CORRUPT_BLOCKS";
+
+const RECORD_LEN: usize = 16;
+
+/// Remediation status of a block marked as corrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CorruptBlock {
+    /// When the block was marked as corrupt, in seconds since the UNIX epoch
+    pub marked_at: u64,
+    /// When the block file was rewritten from an upload of the same data
+    pub healed_at: Option<u64>,
+}
+
+impl CorruptBlock {
+    pub fn is_healed(&self) -> bool {
+        self.healed_at.is_some()
+    }
+
+    fn to_vec(self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(RECORD_LEN);
+        data.extend_from_slice(&self.marked_at.to_le_bytes());
+        // 0 is never a healing time, it stands for not healed
+        data.extend_from_slice(&self.healed_at.unwrap_or(0).to_le_bytes());
+        data
+    }
+
+    fn from_slice(data: &[u8]) -> Result<CorruptBlock, MetaError> {
+        if data.len() != RECORD_LEN {
+            return Err(MetaError::OtherDBError(format!(
+                "invalid corrupt block record length {}",
+                data.len()
+            )));
+        }
+        let field = |i: usize| u64::from_le_bytes(data[i * 8..(i + 1) * 8].try_into().unwrap());
+        Ok(CorruptBlock {
+            marked_at: field(0),
+            healed_at: Some(field(1)).filter(|healed_at| *healed_at != 0),
+        })
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+        .max(1)
+}
+
+/// The blocks marked as corrupt, kept in the store of the block metadata, which is
+/// shared by all users in multi-user mode.
+///
+/// Reads of objects using a corrupt block fail until the block is healed, by
+/// uploading data containing the block again. Healed blocks are kept for
+/// reference until they are cleared.
+pub struct CorruptBlocks {
+    meta_store: MetaStore,
+}
+
+impl CorruptBlocks {
+    pub fn new(meta_store: MetaStore) -> Self {
+        Self { meta_store }
+    }
+
+    /// Mark `block` as corrupt. Marking a healed block again resets its status.
+    pub fn mark(&self, block: &BlockID) -> Result<CorruptBlock, MetaError> {
+        let record = CorruptBlock {
+            marked_at: now_secs(),
+            healed_at: None,
+        };
+        let tree = self.meta_store.get_tree(CORRUPT_BLOCKS_TREE)?;
+        tree.insert(block, record.to_vec())?;
+        Ok(record)
+    }
+
+    /// The status of `block`, `None` if it was never marked as corrupt.
+    pub fn get(&self, block: &BlockID) -> Result<Option<CorruptBlock>, MetaError> {
+        let tree = self.meta_store.get_tree(CORRUPT_BLOCKS_TREE)?;
+        match tree.get(block)? {
+            Some(data) => Ok(Some(CorruptBlock::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns `true` if `block` is marked as corrupt and not healed.
+    pub fn is_corrupt(&self, block: &BlockID) -> Result<bool, MetaError> {
+        Ok(self.get(block)?.is_some_and(|record| !record.is_healed()))
+    }
+
+    /// Mark `block` as healed, if it is marked as corrupt.
+    pub fn mark_healed(&self, block: &BlockID) -> Result<(), MetaError> {
+        let Some(mut record) = self.get(block)? else {
+            return Ok(());
+        };
+        record.healed_at = Some(now_secs());
+        let tree = self.meta_store.get_tree(CORRUPT_BLOCKS_TREE)?;
+        tree.insert(block, record.to_vec())
+    }
+
+    /// Forget the status of `block`.
+    pub fn clear(&self, block: &BlockID) -> Result<(), MetaError> {
+        let tree = self.meta_store.get_tree(CORRUPT_BLOCKS_TREE)?;
+        tree.remove(block)
+    }
+
+    /// All blocks marked as corrupt, healed or not.
+    pub fn list(&self) -> Result<Vec<(BlockID, CorruptBlock)>, MetaError> {
+        let tree = self.meta_store.get_bucket_ext(CORRUPT_BLOCKS_TREE)?;
+        let mut blocks = Vec::new();
+        for item in tree.iter_all() {
+            let (key, value) = item?;
+            let block: BlockID = key[..].try_into().map_err(|_| {
+                MetaError::OtherDBError(format!("invalid corrupt block key length {}", key.len()))
+            })?;
+            blocks.push((block, CorruptBlock::from_slice(&value)?));
+        }
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::{FjallStore, BLOCKID_SIZE};
+
+    #[test]
+    fn test_corrupt_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FjallStore::new(dir.path().to_path_buf(), Some(1), None);
+        let corrupt_blocks = CorruptBlocks::new(MetaStore::new(store, Some(1)));
+
+        let block = [1; BLOCKID_SIZE];
+        assert_eq!(corrupt_blocks.get(&block).unwrap(), None);
+        assert!(!corrupt_blocks.is_corrupt(&block).unwrap());
+        // healing an unmarked block does nothing
+        corrupt_blocks.mark_healed(&block).unwrap();
+        assert_eq!(corrupt_blocks.get(&block).unwrap(), None);
+
+        let record = corrupt_blocks.mark(&block).unwrap();
+        assert!(corrupt_blocks.is_corrupt(&block).unwrap());
+        assert_eq!(corrupt_blocks.list().unwrap(), vec![(block, record)]);
+
+        corrupt_blocks.mark_healed(&block).unwrap();
+        assert!(!corrupt_blocks.is_corrupt(&block).unwrap());
+        let healed = corrupt_blocks.get(&block).unwrap().unwrap();
+        assert_eq!(healed.marked_at, record.marked_at);
+        assert!(healed.is_healed());
+
+        corrupt_blocks.clear(&block).unwrap();
+        assert!(corrupt_blocks.list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,220 @@
+//! Objects which may be unreadable, because a check or a scrub found corrupt
+//! blocks they use.
+//!
+//! Corrupt blocks are marked in the block metadata, but which objects use them is
+//! only known from the block reference index or by scanning all objects, and a
+//! deduplicated block can be used by many of them. The objects using the blocks a
+//! check marked are recorded here, so they can be listed and shown without a read
+//! failing first. An object stays degraded until its corrupt blocks are healed,
+//! or it is replaced or deleted. Entries which no longer apply are dropped when
+//! the objects are listed.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::corrupt_blocks::CorruptBlocks;
+use crate::metastore::{BlockID, MetaError, MetaStore, Object};
+
+/// Tree in the user metadata store holding the degraded objects
+pub const DEGRADED_OBJECTS_TREE: &str = "_DEGRADED_OBJECTS";
+
+/// An object using blocks marked as corrupt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DegradedObject {
+    pub bucket: String,
+    pub key: String,
+    /// Content hash of the object when it was marked, an object uploaded to the
+    /// key since isn't degraded
+    pub hash: BlockID,
+    /// The corrupt blocks of the object, which are not healed
+    pub blocks: Vec<BlockID>,
+    /// When the object was marked, in seconds since the UNIX epoch
+    pub marked_at: u64,
+}
+
+impl DegradedObject {
+    fn from_slice(data: &[u8]) -> Result<DegradedObject, MetaError> {
+        serde_json::from_slice(data)
+            .map_err(|e| MetaError::OtherDBError(format!("invalid degraded object: {}", e)))
+    }
+}
+
+// entries are keyed by the bucket and the key separated by a 0 byte, which
+// can't appear in bucket names, so the objects of a bucket share a prefix
+fn entry_key(bucket: &str, key: &str) -> Vec<u8> {
+    let mut entry_key = bucket_prefix(bucket);
+    entry_key.extend_from_slice(key.as_bytes());
+    entry_key
+}
+
+fn bucket_prefix(bucket: &str) -> Vec<u8> {
+    let mut prefix = bucket.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+/// The degraded objects of a user, by bucket and key.
+pub struct DegradedObjects<'a> {
+    meta_store: &'a MetaStore,
+}
+
+impl<'a> DegradedObjects<'a> {
+    pub fn new(meta_store: &'a MetaStore) -> Self {
+        Self { meta_store }
+    }
+
+    /// Record that `obj`, stored at `key` in `bucket`, uses the corrupt `blocks`.
+    /// Marking an object again replaces its blocks.
+    pub fn mark(
+        &self,
+        bucket: &str,
+        key: &str,
+        obj: &Object,
+        blocks: Vec<BlockID>,
+    ) -> Result<DegradedObject, MetaError> {
+        let degraded = DegradedObject {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            hash: *obj.hash(),
+            blocks,
+            marked_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        };
+        let value =
+            serde_json::to_vec(&degraded).map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        self.meta_store
+            .get_tree(DEGRADED_OBJECTS_TREE)?
+            .insert(&entry_key(bucket, key), value)?;
+        Ok(degraded)
+    }
+
+    /// The record of the object at `key` in `bucket`, if it was marked. It may no
+    /// longer apply, see [`DegradedObjects::list`].
+    pub fn get(&self, bucket: &str, key: &str) -> Result<Option<DegradedObject>, MetaError> {
+        let tree = self.meta_store.get_tree(DEGRADED_OBJECTS_TREE)?;
+        match tree.get(&entry_key(bucket, key))? {
+            Some(data) => Ok(Some(DegradedObject::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Forget the object at `key` in `bucket`.
+    pub fn remove(&self, bucket: &str, key: &str) -> Result<(), MetaError> {
+        self.meta_store
+            .get_tree(DEGRADED_OBJECTS_TREE)?
+            .remove(&entry_key(bucket, key))
+    }
+
+    /// Move the record of the object at `key` in `bucket` to `new_key`, after the
+    /// object was renamed.
+    pub fn rename(&self, bucket: &str, key: &str, new_key: &str) -> Result<(), MetaError> {
+        let Some(mut degraded) = self.get(bucket, key)? else {
+            return Ok(());
+        };
+        degraded.key = new_key.to_string();
+        let value =
+            serde_json::to_vec(&degraded).map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        let tree = self.meta_store.get_tree(DEGRADED_OBJECTS_TREE)?;
+        tree.insert(&entry_key(bucket, new_key), value)?;
+        tree.remove(&entry_key(bucket, key))
+    }
+
+    /// The objects of `bucket`, or of all buckets, which are still degraded,
+    /// sorted by bucket and key, with the blocks of `corrupt_blocks` which are
+    /// not healed yet. The objects which were replaced or deleted, or whose
+    /// blocks were all healed since they were marked, are removed.
+    pub fn list(
+        &self,
+        bucket: Option<&str>,
+        corrupt_blocks: &CorruptBlocks,
+    ) -> Result<Vec<DegradedObject>, MetaError> {
+        let tree = self.meta_store.get_bucket_ext(DEGRADED_OBJECTS_TREE)?;
+        let entries = match bucket {
+            Some(bucket) => tree.iter_prefix(&bucket_prefix(bucket)),
+            None => tree.iter_all(),
+        };
+        let mut records = Vec::new();
+        for item in entries {
+            let (_, value) = item?;
+            records.push(DegradedObject::from_slice(&value)?);
+        }
+
+        let mut degraded = Vec::with_capacity(records.len());
+        for mut record in records {
+            let current = self.meta_store.bucket_exists(&record.bucket)?
+                && self
+                    .meta_store
+                    .get_meta(&record.bucket, &record.key)?
+                    .is_some_and(|obj| *obj.hash() == record.hash);
+            if current {
+                let mut blocks = Vec::with_capacity(record.blocks.len());
+                for block in record.blocks {
+                    if corrupt_blocks.is_corrupt(&block)? {
+                        blocks.push(block);
+                    }
+                }
+                record.blocks = blocks;
+            }
+            if !current || record.blocks.is_empty() {
+                self.remove(&record.bucket, &record.key)?;
+                continue;
+            }
+            degraded.push(record);
+        }
+        Ok(degraded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::{FjallStore, ObjectData, BLOCKID_SIZE};
+
+    #[test]
+    fn test_degraded_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MetaStore::new(
+            FjallStore::new(dir.path().to_path_buf(), Some(1), None),
+            Some(1),
+        );
+        let corrupt_blocks = CorruptBlocks::new(store.clone());
+        let degraded = DegradedObjects::new(&store);
+        store.insert_bucket("bucket", Vec::new()).unwrap();
+
+        let (bad, good) = ([1; BLOCKID_SIZE], [2; BLOCKID_SIZE]);
+        let object = |hash: u8| {
+            let blocks = vec![bad, good];
+            Object::new(10, [hash; BLOCKID_SIZE], ObjectData::SinglePart { blocks })
+        };
+        for key in ["a", "b", "c"] {
+            store
+                .insert_meta("bucket", key, object(7).to_vec())
+                .unwrap();
+            degraded.mark("bucket", key, &object(7), vec![bad]).unwrap();
+        }
+        corrupt_blocks.mark(&bad).unwrap();
+        let keys = |bucket| {
+            let list = degraded.list(bucket, &corrupt_blocks).unwrap();
+            list.into_iter().map(|d| d.key).collect::<Vec<_>>()
+        };
+        assert_eq!(keys(Some("bucket")), ["a", "b", "c"]);
+        assert_eq!(keys(Some("other")), Vec::<String>::new());
+        assert_eq!(degraded.get("bucket", "a").unwrap().unwrap().blocks, [bad]);
+
+        // replaced and deleted objects are no longer degraded
+        store
+            .insert_meta("bucket", "a", object(8).to_vec())
+            .unwrap();
+        store.get_tree("bucket").unwrap().remove(b"b").unwrap();
+        assert_eq!(keys(None), ["c"]);
+        assert_eq!(degraded.get("bucket", "a").unwrap(), None);
+
+        // neither are objects whose blocks are healed
+        corrupt_blocks.mark_healed(&bad).unwrap();
+        assert!(keys(None).is_empty());
+        assert_eq!(degraded.get("bucket", "c").unwrap(), None);
+    }
+}
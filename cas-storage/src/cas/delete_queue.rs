@@ -0,0 +1,177 @@
+//! Queue of block files waiting for their physical removal, so the files of
+//! deleted objects are kept for a grace window after their metadata is gone.
+
+use std::convert::{TryFrom, TryInto};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::metastore::{Block, MetaError, MetaStore};
+
+/// Tree in the block metadata store holding the blocks waiting for removal
+pub const DELETE_QUEUE_TREE: &str = "_DELETE_QUEUE";
+
+/// Delay before a block file which couldn't be removed is tried again
+pub(crate) const DELETE_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Blocks waiting in the delete queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DeleteQueueStats {
+    /// Amount of queued blocks
+    pub blocks: u64,
+    /// Sum of the sizes of the queued blocks
+    pub bytes: u64,
+    /// When the oldest queued block is removed, in seconds since the UNIX epoch
+    pub next_due: Option<u64>,
+}
+
+/// A queued block, removed once `due` has passed
+#[derive(Debug)]
+pub struct QueuedBlock {
+    /// When the block file can be removed, in seconds since the UNIX epoch
+    pub due: u64,
+    pub block: Block,
+    key: Vec<u8>,
+}
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The blocks whose last reference was released, kept in the store of the block
+/// metadata, which is shared by all users in multi-user mode.
+///
+/// The block metadata is removed when the block is queued, but its path stays
+/// allocated in the path map so no other block is written to the file. Entries
+/// are ordered by the time they are due, keyed by that time and the block path.
+pub struct DeleteQueue {
+    meta_store: MetaStore,
+}
+
+impl DeleteQueue {
+    pub fn new(meta_store: MetaStore) -> Self {
+        Self { meta_store }
+    }
+
+    /// Queue `blocks` for removal at `due`.
+    pub fn push(&self, blocks: &[Block], due: u64) -> Result<(), MetaError> {
+        let tree = self.meta_store.get_tree(DELETE_QUEUE_TREE)?;
+        for block in blocks {
+            tree.insert(&Self::key(due, block), block.to_vec())?;
+        }
+        Ok(())
+    }
+
+    /// Queue `block` for removal at `due`, and return its entry.
+    pub fn push_block(&self, block: &Block, due: u64) -> Result<QueuedBlock, MetaError> {
+        let key = Self::key(due, block);
+        self.meta_store
+            .get_tree(DELETE_QUEUE_TREE)?
+            .insert(&key, block.to_vec())?;
+        Ok(QueuedBlock {
+            due,
+            block: block.clone(),
+            key,
+        })
+    }
+
+    /// Up to `limit` blocks due at `now`, the oldest first.
+    pub fn due(&self, now: u64, limit: usize) -> Result<Vec<QueuedBlock>, MetaError> {
+        let queue = self.meta_store.get_bucket_ext(DELETE_QUEUE_TREE)?;
+        let mut blocks = Vec::new();
+        for item in queue.iter_prefix(&[]) {
+            let (key, value) = item?;
+            let queued = Self::parse(&key, &value)?;
+            if queued.due > now || blocks.len() == limit {
+                break;
+            }
+            blocks.push(queued);
+        }
+        Ok(blocks)
+    }
+
+    /// Remove a block from the queue, once its file is removed.
+    pub fn remove(&self, queued: &QueuedBlock) -> Result<(), MetaError> {
+        self.meta_store
+            .get_tree(DELETE_QUEUE_TREE)?
+            .remove(&queued.key)
+    }
+
+    /// The amount and size of the queued blocks.
+    pub fn stats(&self) -> Result<DeleteQueueStats, MetaError> {
+        let queue = self.meta_store.get_bucket_ext(DELETE_QUEUE_TREE)?;
+        let mut stats = DeleteQueueStats::default();
+        for item in queue.iter_prefix(&[]) {
+            let (key, value) = item?;
+            let queued = Self::parse(&key, &value)?;
+            stats.blocks += 1;
+            stats.bytes += queued.block.size() as u64;
+            stats.next_due.get_or_insert(queued.due);
+        }
+        Ok(stats)
+    }
+
+    fn key(due: u64, block: &Block) -> Vec<u8> {
+        let mut key = due.to_be_bytes().to_vec();
+        key.extend_from_slice(block.path());
+        key
+    }
+
+    fn parse(key: &[u8], value: &[u8]) -> Result<QueuedBlock, MetaError> {
+        let malformed = || MetaError::OtherDBError("malformed delete queue entry".to_string());
+        let due = key
+            .get(..8)
+            .and_then(|due| due.try_into().ok())
+            .map(u64::from_be_bytes)
+            .ok_or_else(malformed)?;
+        let block = Block::try_from(value).map_err(|_| malformed())?;
+        Ok(QueuedBlock {
+            due,
+            block,
+            key: key.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::FjallStore;
+
+    #[test]
+    fn test_delete_queue() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FjallStore::new(dir.path().to_path_buf(), Some(1), None);
+        let queue = DeleteQueue::new(MetaStore::new(store, Some(1)));
+
+        queue.push(&[Block::new(10, vec![2])], 200).unwrap();
+        queue
+            .push(&[Block::new(20, vec![1]), Block::new(30, vec![3, 4])], 100)
+            .unwrap();
+        assert_eq!(
+            queue.stats().unwrap(),
+            DeleteQueueStats {
+                blocks: 3,
+                bytes: 60,
+                next_due: Some(100),
+            }
+        );
+
+        assert!(queue.due(99, 10).unwrap().is_empty());
+        let due = queue.due(150, 10).unwrap();
+        let paths: Vec<&[u8]> = due.iter().map(|queued| queued.block.path()).collect();
+        assert_eq!(paths, vec![&[1][..], &[3, 4][..]]);
+        assert_eq!(queue.due(300, 1).unwrap().len(), 1);
+
+        for queued in &due {
+            queue.remove(queued).unwrap();
+        }
+        let due = queue.due(300, 10).unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].due, 200);
+        assert_eq!(due[0].block.size(), 10);
+    }
+}
@@ -0,0 +1,50 @@
+//! In-process notifications of object mutations, for embedders which index or
+//! cache objects and don't want to poll the store.
+
+use std::sync::Arc;
+
+use crate::metastore::Object;
+
+/// Receives the mutations of objects made through a [`CasFS`](super::CasFS).
+///
+/// The methods are called after the metadata transaction of the mutation is
+/// committed, on the thread which made it, which can be a thread of the metadata
+/// executor. They should return quickly, e.g. by sending the event over a channel.
+/// Mutations made by other processes writing to the same store are not reported.
+///
+/// All methods do nothing by default.
+pub trait ObjectEventHandler: Send + Sync {
+    /// `object` was stored at `key`, replacing the object which was there.
+    fn on_put(&self, _bucket: &str, _key: &str, _object: &Object) {}
+
+    /// The object at `key` was deleted. Not called if there was no object.
+    fn on_delete(&self, _bucket: &str, _key: &str) {}
+
+    /// A multipart upload was completed into `object` at `key`, replacing the
+    /// object which was there.
+    fn on_multipart_complete(&self, _bucket: &str, _key: &str, _object: &Object) {}
+
+    /// `bucket` was deleted with all its objects, `on_delete` is not called for them.
+    fn on_bucket_delete(&self, _bucket: &str) {}
+}
+
+/// The event handlers registered on a CasFS.
+#[derive(Clone, Default)]
+pub(crate) struct EventHandlers(Vec<Arc<dyn ObjectEventHandler>>);
+
+impl EventHandlers {
+    pub fn push(&mut self, handler: Arc<dyn ObjectEventHandler>) {
+        self.0.push(handler);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Calls `f` with every handler, in the order they were registered.
+    pub fn emit(&self, f: impl Fn(&dyn ObjectEventHandler)) {
+        for handler in &self.0 {
+            f(handler.as_ref());
+        }
+    }
+}
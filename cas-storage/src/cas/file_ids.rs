@@ -0,0 +1,143 @@
+//! Cache of the objects stored from local files, so files which didn't change since
+//! they were last ingested, or which are hard links of an ingested file, are stored
+//! again without reading and hashing their data.
+
+use std::fs::Metadata;
+
+use crate::cas::manifest::ManifestEntry;
+use crate::metastore::{MetaError, MetaStore};
+
+/// Tree in the user metadata store mapping file identities to the manifest entry of
+/// the object last stored from the file
+pub const FILE_IDS_TREE: &str = "_FILE_IDS";
+
+/// Identity of the contents of a local file: its inode, which hard links share,
+/// with the size and modification time it had when it was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId {
+    pub dev: u64,
+    pub ino: u64,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+}
+
+impl FileId {
+    /// The identity of a file from its metadata.
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        Some(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        })
+    }
+
+    /// Files have no stable identity on this platform, so they are always hashed.
+    #[cfg(not(unix))]
+    pub fn from_metadata(_metadata: &Metadata) -> Option<Self> {
+        None
+    }
+
+    fn to_key(self) -> [u8; 40] {
+        let mut key = [0; 40];
+        key[0..8].copy_from_slice(&self.dev.to_be_bytes());
+        key[8..16].copy_from_slice(&self.ino.to_be_bytes());
+        key[16..24].copy_from_slice(&self.size.to_be_bytes());
+        key[24..32].copy_from_slice(&self.mtime.to_be_bytes());
+        key[32..40].copy_from_slice(&self.mtime_nsec.to_be_bytes());
+        key
+    }
+}
+
+/// The manifest entries of the objects stored from local files, by file identity.
+///
+/// An entry is only a hint: the blocks it lists may have been removed since, in
+/// which case importing it fails and the file has to be read again.
+pub struct FileIdCache<'a> {
+    meta_store: &'a MetaStore,
+}
+
+impl<'a> FileIdCache<'a> {
+    pub fn new(meta_store: &'a MetaStore) -> Self {
+        Self { meta_store }
+    }
+
+    /// The entry of the object last stored from the file with identity `id`.
+    pub fn get(&self, id: FileId) -> Result<Option<ManifestEntry>, MetaError> {
+        let tree = self.meta_store.get_tree(FILE_IDS_TREE)?;
+        let Some(value) = tree.get(&id.to_key())? else {
+            return Ok(None);
+        };
+        // entries of an older format are ignored, the file is ingested again
+        Ok(serde_json::from_slice(&value).ok())
+    }
+
+    /// Remember that the object of `entry` was stored from the file with identity `id`.
+    pub fn insert(&self, id: FileId, entry: &ManifestEntry) -> Result<(), MetaError> {
+        let value =
+            serde_json::to_vec(entry).map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        self.meta_store
+            .get_tree(FILE_IDS_TREE)?
+            .insert(&id.to_key(), value)
+    }
+
+    /// Remove all entries.
+    pub fn clear(&self) -> Result<(), MetaError> {
+        let cache = self.meta_store.get_bucket_ext(FILE_IDS_TREE)?;
+        for item in cache.iter_prefix(&[]) {
+            let (key, _) = item?;
+            cache.remove(&key)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::{FjallStore, Object, ObjectData};
+
+    #[test]
+    fn test_file_id_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FjallStore::new(dir.path().to_path_buf(), Some(1), None);
+        let meta_store = MetaStore::new(store, Some(1));
+        let cache = FileIdCache::new(&meta_store);
+
+        let path = dir.path().join("file");
+        std::fs::write(&path, b"data").unwrap();
+        let Some(id) = FileId::from_metadata(&std::fs::metadata(&path).unwrap()) else {
+            return;
+        };
+        // hard links share the identity of the file
+        let link = dir.path().join("link");
+        std::fs::hard_link(&path, &link).unwrap();
+        assert_eq!(
+            FileId::from_metadata(&std::fs::metadata(&link).unwrap()),
+            Some(id)
+        );
+
+        let obj = Object::new(
+            4,
+            [1; 16],
+            ObjectData::Inline {
+                data: b"data".to_vec(),
+            },
+        );
+        let entry = ManifestEntry::new("file", &obj, &[]);
+        assert_eq!(cache.get(id).unwrap(), None);
+        cache.insert(id, &entry).unwrap();
+        assert_eq!(cache.get(id).unwrap(), Some(entry));
+
+        let changed = FileId { size: 5, ..id };
+        assert_eq!(cache.get(changed).unwrap(), None);
+
+        cache.clear().unwrap();
+        assert_eq!(cache.get(id).unwrap(), None);
+    }
+}
@@ -1,18 +1,50 @@
+use std::convert::TryFrom;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{io, path::PathBuf};
 
 use super::{
+    block_pins::{BlockPinGuard, BlockPins},
+    bucket_activity::{Access, ActivityTracker, BucketActivity, BUCKET_ACTIVITY_TREE},
+    corrupt_blocks::{CorruptBlocks, CORRUPT_BLOCKS_TREE},
+    degraded_objects::{DegradedObject, DegradedObjects, DEGRADED_OBJECTS_TREE},
+    delete_queue::{
+        now_secs, DeleteQueue, DeleteQueueStats, DELETE_QUEUE_TREE, DELETE_RETRY_DELAY,
+    },
     buffered_byte_stream::BufferedByteStream,
-    multipart::{MultiPart, MultiPartTree},
+    builder::{open_meta_store, prepare_dir, CasFSBuilder, SharedTrees},
+    content_hash::{self, ContentHash},
+    events::{EventHandlers, ObjectEventHandler},
+    file_ids::{FileIdCache, FILE_IDS_TREE},
+    group_sync::GroupSync,
+    hash_pool::{HashPool, StreamHashing},
+    idempotency::{IdempotencyKeys, IDEMPOTENCY_KEYS_TREE},
+    jobs::{JobStore, JOBS_TREE},
+    list_snapshots::ListSnapshots,
+    manifest::ManifestEntry,
+    meta_cache::MetaCache,
+    meta_executor::{MetaExecutor, DEFAULT_META_THREADS},
+    meta_size::{MetaSizeHistory, MetadataSize, TreeSize, META_SIZE_HISTORY_TREE},
+    multipart::{legacy_part_key, part_key, MultiPart, MultiPartTree, MULTIPART_TREE},
+    object_locks::ObjectLocks,
+    placement::Placement,
+    refcount_batch::{PendingRefs, RefcountBatch, REFCOUNT_BATCH_MAX_BYTES},
+    request_timings,
+    resumable::{ResumableUpload, ResumableUploads, RESUMABLE_UPLOADS_TREE},
+    usage::{BucketUsage, StoreStats, UsageHistory, STATS_HISTORY_TREE},
+    write_limiter::AdaptiveWriteLimiter,
 };
 use crate::metrics::SharedMetrics;
 
 use crate::metastore::{
-    BaseMetaTree, BlockID, BlockTree, BucketMeta, Durability, FjallStore, FjallStoreNotx,
-    MetaError, MetaStore, MetaTreeExt, Object, ObjectData,
+    BaseMetaTree, BlobStats, Block, BlockID, BlockRef, BlockTree, BucketCounters, BucketLifecycle,
+    BucketLimits, BucketLogging, BucketMeta, CannedAcl, Durability, ETag, LimitExceeded, MetaError,
+    MetaStore, MetaTreeExt, Object, ObjectAttributes, ObjectData, ObjectExpiration, ObjectTags,
+    PrefixCount, TagFilter, Transaction,
 };
 
+use bytes::Bytes;
 use faster_hex::hex_string;
 use futures::{
     channel::mpsc::unbounded,
@@ -23,8 +55,12 @@ use futures::{
 use md5::{Digest, Md5};
 use rusoto_core::ByteStream;
 
+/// Default size of the blocks objects are split into
 pub const BLOCK_SIZE: usize = 1 << 20; // Supposedly 1 MiB
 
+/// Default amount of blocks of a single object which are processed concurrently
+pub const DEFAULT_WRITE_CONCURRENCY: usize = 1;
+
 struct PendingMarker {
     metrics: SharedMetrics,
     in_flight: u64,
@@ -64,6 +100,24 @@ impl Drop for PendingMarker {
     }
 }
 
+/// Tracks a block for the duration it spends in the write pipeline.
+struct InFlightMarker {
+    metrics: SharedMetrics,
+}
+
+impl InFlightMarker {
+    pub fn new(metrics: SharedMetrics) -> Self {
+        metrics.block_write_started();
+        Self { metrics }
+    }
+}
+
+impl Drop for InFlightMarker {
+    fn drop(&mut self) {
+        self.metrics.block_write_finished();
+    }
+}
+
 use async_trait::async_trait;
 
 #[async_trait]
@@ -95,9 +149,28 @@ pub struct CasFS {
     block_tree: Arc<BlockTree>,
     shared_path_tree: Option<Arc<dyn BaseMetaTree>>,
     shared_meta_store: Option<Arc<MetaStore>>,
+    write_concurrency: usize,
+    write_limiter: Option<Arc<AdaptiveWriteLimiter>>,
+    group_sync: Option<Arc<GroupSync>>,
+    object_locks: ObjectLocks,
+    // serialize the requests of a resumable upload, apart from the object locks
+    // they take while storing its blocks and object
+    upload_locks: ObjectLocks,
+    idempotency_locks: ObjectLocks,
+    block_pins: Arc<BlockPins>,
+    list_snapshots: Option<ListSnapshots>,
+    meta_cache: Option<MetaCache>,
+    meta_executor: Arc<MetaExecutor>,
+    hash_pool: Option<Arc<HashPool>>,
+    block_size: usize,
+    content_hash: ContentHash,
+    event_handlers: EventHandlers,
+    placement: Placement,
+    delete_grace: Option<Duration>,
+    activity: ActivityTracker,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StorageEngine {
     // fjall with transactions support
     Fjall,
@@ -119,60 +192,58 @@ impl FromStr for StorageEngine {
     }
 }
 
+impl StorageEngine {
+    /// Name of the engine, as accepted by `from_str`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageEngine::Fjall => "fjall",
+            StorageEngine::FjallNotx => "fjall_notx",
+        }
+    }
+}
+
 pub type ObjectPaths = (Object, Vec<(PathBuf, usize)>);
+pub type PinnedObjectPaths = (Object, Vec<(PathBuf, usize)>, BlockPinGuard);
+
+// amount of times get_object_paths_pinned retries when blocks disappear under it
+const PIN_RETRIES: usize = 3;
+
+/// Prefix of the objects holding the blocks uploaded with [`CasFS::stage_block`]
+pub const STAGED_BLOCKS_PREFIX: &str = ".blocks/";
+
+fn staged_block_key(id: &BlockID) -> String {
+    format!("{STAGED_BLOCKS_PREFIX}{}", hex_string(id))
+}
 
 impl CasFS {
+    /// Create a single-user CasFS.
+    ///
+    /// Panics if the storage directories or the metadata store can't be opened.
+    #[deprecated(note = "use CasFSBuilder, which returns an error instead of panicking")]
     pub fn new(
-        mut root: PathBuf,
-        mut meta_path: PathBuf,
+        root: PathBuf,
+        meta_path: PathBuf,
         metrics: SharedMetrics,
         storage_engine: StorageEngine,
         inlined_metadata_size: Option<usize>,
         durability: Option<Durability>,
     ) -> Self {
-        meta_path.push("db");
-        root.push("blocks");
-
-        // Canonicalize both paths to eliminate getcwd() syscalls in async operations
-        // This is critical for performance as it avoids repeated getcwd() on every file op
-        std::fs::create_dir_all(&root).ok();
-        root = root.canonicalize().unwrap_or(root);
-
-        std::fs::create_dir_all(&meta_path).ok();
-        meta_path = meta_path.canonicalize().unwrap_or(meta_path);
-
-        let meta_store = match storage_engine {
-            StorageEngine::Fjall => {
-                let store = FjallStore::new(meta_path, inlined_metadata_size, durability);
-                MetaStore::new(store, inlined_metadata_size)
-            }
-            StorageEngine::FjallNotx => {
-                let store = FjallStoreNotx::new(meta_path, inlined_metadata_size);
-                MetaStore::new(store, inlined_metadata_size)
-            }
-        };
-        //let meta_store = MetaStore::new(store, inlined_metadata_size);
-
-        // Get the current amount of buckets
-        //metrics.set_bucket_count(db.open_tree(BUCKET_META_TREE).unwrap().len());
-
-        let tree = meta_store.get_tree("_MULTIPART_PARTS").unwrap();
-        let multipart_tree = MultiPartTree::new(tree);
-        let block_tree = meta_store.get_block_tree().expect("Can open block tree");
-        Self {
-            async_fs: Box::new(RealAsyncFs),
-            user_meta_store: meta_store,
-            root,
-            metrics,
-            multipart_tree: Arc::new(multipart_tree),
-            block_tree: Arc::new(block_tree),
-            shared_path_tree: None, // Single-user mode
-            shared_meta_store: None, // Single-user mode
+        let mut builder = CasFSBuilder::new(root, meta_path)
+            .metrics(metrics)
+            .storage_engine(storage_engine);
+        if let Some(size) = inlined_metadata_size {
+            builder = builder.inlined_metadata_size(size);
+        }
+        if let Some(durability) = durability {
+            builder = builder.durability(durability);
         }
+        builder.build().expect("Can open CasFS")
     }
 
     /// Create a new CasFS instance for multi-user mode
     ///
+    /// Panics if the storage directories or the user metadata store can't be opened.
+    ///
     /// # Arguments
     /// * `root` - Root directory for block storage (shared across all users)
     /// * `user_meta_path` - Path to user-specific metadata DB
@@ -184,9 +255,11 @@ impl CasFS {
     /// * `storage_engine` - Storage engine for user metadata
     /// * `inlined_metadata_size` - Maximum size for inlined metadata
     /// * `durability` - Durability level for user metadata transactions
+    #[deprecated(note = "use CasFSBuilder with shared_block_store, which returns an error instead")]
+    #[allow(clippy::too_many_arguments)]
     pub fn new_multi_user(
-        mut root: PathBuf,
-        mut user_meta_path: PathBuf,
+        root: PathBuf,
+        user_meta_path: PathBuf,
         shared_block_tree: Arc<BlockTree>,
         shared_path_tree: Arc<dyn BaseMetaTree>,
         shared_multipart_tree: Arc<MultiPartTree>,
@@ -196,38 +269,423 @@ impl CasFS {
         inlined_metadata_size: Option<usize>,
         durability: Option<Durability>,
     ) -> Self {
-        user_meta_path.push("db");
-        root.push("blocks");
-
-        // Canonicalize both paths to eliminate getcwd() syscalls in async operations
-        // This is critical for performance as it avoids repeated getcwd() on every file op
-        std::fs::create_dir_all(&root).ok();
-        root = root.canonicalize().unwrap_or(root);
-
-        std::fs::create_dir_all(&user_meta_path).ok();
-        user_meta_path = user_meta_path.canonicalize().unwrap_or(user_meta_path);
-
-        let user_meta_store = match storage_engine {
-            StorageEngine::Fjall => {
-                let store = FjallStore::new(user_meta_path, inlined_metadata_size, durability);
-                MetaStore::new(store, inlined_metadata_size)
-            }
-            StorageEngine::FjallNotx => {
-                let store = FjallStoreNotx::new(user_meta_path, inlined_metadata_size);
-                MetaStore::new(store, inlined_metadata_size)
-            }
+        let root = prepare_dir(root.join("blocks")).expect("Can create block directory");
+        let user_meta_path =
+            prepare_dir(user_meta_path.join("db")).expect("Can create metadata directory");
+        let user_meta_store = open_meta_store(
+            user_meta_path,
+            storage_engine,
+            inlined_metadata_size,
+            durability,
+            None,
+        )
+        .expect("Can open user metadata store");
+        let shared = SharedTrees {
+            block_tree: shared_block_tree,
+            path_tree: shared_path_tree,
+            multipart_tree: shared_multipart_tree,
+            meta_store: shared_meta_store,
         };
+        let meta_executor = Arc::new(MetaExecutor::new(DEFAULT_META_THREADS, metrics.clone()));
+        Self::from_parts(
+            root,
+            user_meta_store,
+            Some(shared),
+            metrics,
+            meta_executor,
+            BLOCK_SIZE,
+        )
+        .expect("Can open CasFS")
+    }
 
-        Self {
+    /// Assemble a CasFS from an opened metadata store. In multi-user mode the
+    /// block, path and multipart trees are the shared ones, otherwise they are
+    /// opened from `user_meta_store`.
+    pub(super) fn from_parts(
+        root: PathBuf,
+        user_meta_store: MetaStore,
+        shared: Option<SharedTrees>,
+        metrics: SharedMetrics,
+        meta_executor: Arc<MetaExecutor>,
+        block_size: usize,
+    ) -> Result<Self, MetaError> {
+        let (multipart_tree, block_tree, path_tree, shared_path_tree, shared_meta_store) =
+            match shared {
+                Some(shared) => (
+                    shared.multipart_tree,
+                    shared.block_tree,
+                    Arc::clone(&shared.path_tree),
+                    Some(shared.path_tree),
+                    Some(shared.meta_store),
+                ),
+                None => {
+                    let tree = user_meta_store.get_tree(MULTIPART_TREE)?;
+                    (
+                        Arc::new(MultiPartTree::new(tree)),
+                        Arc::new(user_meta_store.get_block_tree()?),
+                        user_meta_store.get_path_tree()?,
+                        None,
+                        None,
+                    )
+                }
+            };
+        let activity = ActivityTracker::load(&user_meta_store)?;
+        let delete_queue = DeleteQueue::new(match &shared_meta_store {
+            Some(shared_store) => MetaStore::clone(shared_store),
+            None => user_meta_store.clone(),
+        });
+
+        Ok(Self {
             async_fs: Box::new(RealAsyncFs),
             user_meta_store,
             root,
             metrics,
-            multipart_tree: shared_multipart_tree,
-            block_tree: shared_block_tree,
-            shared_path_tree: Some(shared_path_tree),
-            shared_meta_store: Some(shared_meta_store),
+            multipart_tree,
+            block_tree,
+            shared_path_tree,
+            shared_meta_store,
+            write_concurrency: DEFAULT_WRITE_CONCURRENCY,
+            write_limiter: None,
+            group_sync: None,
+            object_locks: ObjectLocks::default(),
+            upload_locks: ObjectLocks::default(),
+            idempotency_locks: ObjectLocks::default(),
+            block_pins: Arc::new(BlockPins::new(path_tree, delete_queue)),
+            list_snapshots: None,
+            meta_cache: None,
+            meta_executor,
+            hash_pool: None,
+            block_size,
+            content_hash: ContentHash::default(),
+            event_handlers: EventHandlers::default(),
+            placement: Placement::default(),
+            delete_grace: None,
+            activity,
+        })
+    }
+
+    /// The size of the blocks new objects are split into.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Identify blocks and the content of objects with `content_hash`. It must not
+    /// change for an existing store, blocks are only deduplicated with blocks
+    /// hashed the same way.
+    pub fn with_content_hash(mut self, content_hash: ContentHash) -> Self {
+        self.content_hash = content_hash;
+        self
+    }
+
+    /// The hash identifying blocks and the content of objects.
+    pub fn content_hash(&self) -> ContentHash {
+        self.content_hash
+    }
+
+    /// Place new blocks in storage locations by the key prefix of their object, see
+    /// [`Placement`]. Blocks already placed in a location which is left out can't be
+    /// read anymore.
+    pub fn with_placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /// The storage locations and the rules placing blocks in them.
+    pub fn placement(&self) -> &Placement {
+        &self.placement
+    }
+
+    /// The path of the file of `block`, in the storage location it was placed in.
+    pub fn block_disk_path(&self, block: &Block) -> Result<PathBuf, MetaError> {
+        Ok(block.disk_path(self.block_root(block)?.to_path_buf()))
+    }
+
+    // the directory of the storage location of `block`
+    fn block_root(&self, block: &Block) -> Result<&std::path::Path, MetaError> {
+        match block.location() {
+            None => Ok(&self.root),
+            Some(name) => self.placement.location_dir(name).ok_or_else(|| {
+                MetaError::OtherDBError(format!(
+                    "block stored in unknown storage location '{name}'"
+                ))
+            }),
+        }
+    }
+
+    /// Keep the files of blocks no longer referenced for `grace` before removing
+    /// them, in a delete queue purged with [`CasFS::purge_delete_queue`]. Objects
+    /// deleted by mistake can be restored meanwhile by importing their manifest
+    /// entries. A zero grace removes the files right away.
+    pub fn with_delete_grace(mut self, grace: Duration) -> Self {
+        self.delete_grace = Some(grace).filter(|grace| !grace.is_zero());
+        self
+    }
+
+    /// The blocks waiting for the removal of their files.
+    pub fn delete_queue(&self) -> DeleteQueue {
+        DeleteQueue::new(self.block_meta_store())
+    }
+
+    /// The amount and size of the blocks waiting for the removal of their files.
+    pub fn delete_queue_stats(&self) -> Result<DeleteQueueStats, MetaError> {
+        self.delete_queue().stats()
+    }
+
+    /// Set the amount of blocks of a single object which are hashed and written
+    /// concurrently by `store_object`. Values below 1 are treated as 1.
+    pub fn with_write_concurrency(mut self, write_concurrency: usize) -> Self {
+        self.write_concurrency = write_concurrency.max(1);
+        self
+    }
+
+    pub fn write_concurrency(&self) -> usize {
+        self.write_concurrency
+    }
+
+    /// Use an adaptive limiter for block writes instead of a fixed concurrency.
+    /// The limiter can be shared between CasFS instances writing to the same storage.
+    pub fn with_write_limiter(mut self, limiter: Arc<AdaptiveWriteLimiter>) -> Self {
+        self.write_limiter = Some(limiter);
+        self
+    }
+
+    /// Sync the block files and the metadata of this instance with `group_sync`
+    /// instead of on every write, see [`GroupSync`]. The metadata stores should
+    /// be opened with `Durability::Buffer`, the group sync persists them.
+    pub fn with_group_sync(mut self, group_sync: Arc<GroupSync>) -> Self {
+        if let Some(shared_store) = &self.shared_meta_store {
+            group_sync.register_store(&shared_store.get_underlying_store());
+        }
+        group_sync.register_store(&self.user_meta_store.get_underlying_store());
+        self.group_sync = Some(group_sync);
+        self
+    }
+
+    /// Serve paginated listings from a snapshot of the bucket taken at the first
+    /// page, so keys don't appear or disappear between pages. Snapshots are
+    /// released after the last page, or after `max_lifetime` at the latest. At
+    /// most `max_count` snapshots are kept, the oldest is released first.
+    pub fn with_list_snapshots(mut self, max_lifetime: Duration, max_count: usize) -> Self {
+        self.list_snapshots = Some(ListSnapshots::new(max_lifetime, max_count));
+        self
+    }
+
+    /// The listing snapshots, if snapshot-consistent listings are enabled.
+    pub fn list_snapshots(&self) -> Option<&ListSnapshots> {
+        self.list_snapshots.as_ref()
+    }
+
+    /// Cache the deserialized metadata of up to `capacity` objects. The cache is
+    /// invalidated by writes through this instance only, so it must not be used
+    /// when other processes write to the same metadata store.
+    pub fn with_meta_cache(mut self, capacity: usize) -> Self {
+        self.meta_cache = Some(MetaCache::new(capacity, self.metrics.clone()));
+        self
+    }
+
+    /// Run blocking metadata operations on the given executor. The executor can be
+    /// shared between CasFS instances, to bound the blocking threads of all of them.
+    pub fn with_meta_executor(mut self, executor: Arc<MetaExecutor>) -> Self {
+        self.meta_executor = executor;
+        self
+    }
+
+    pub fn meta_executor(&self) -> &Arc<MetaExecutor> {
+        &self.meta_executor
+    }
+
+    /// Hash the blocks and content of uploads on the given pool instead of the
+    /// request task. The blocks of an upload are hashed in parallel, while the
+    /// blocks before them are written.
+    pub fn with_hash_pool(mut self, pool: Arc<HashPool>) -> Self {
+        self.hash_pool = Some(pool);
+        self
+    }
+
+    /// The id of the block with `data`, computed on the hash pool if there is one.
+    async fn block_hash(&self, data: &Bytes) -> BlockID {
+        match &self.hash_pool {
+            Some(pool) => pool.digest(self.content_hash, data.clone()).await,
+            None => self.content_hash.digest(data),
+        }
+    }
+
+    /// Notify `handler` of the objects stored and deleted through this instance.
+    /// Handlers are called in the order they were added.
+    pub fn with_event_handler(mut self, handler: Arc<dyn ObjectEventHandler>) -> Self {
+        self.event_handlers.push(handler);
+        self
+    }
+
+    /// Run a blocking operation on this CasFS on the metadata executor, for use from
+    /// async code: `casfs.run_blocking(move |fs| fs.create_bucket(&name)).await`.
+    pub async fn run_blocking<T, F>(self: &Arc<Self>, f: F) -> Result<T, MetaError>
+    where
+        F: FnOnce(&CasFS) -> Result<T, MetaError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let casfs = Arc::clone(self);
+        self.meta_executor.run(move || f(&casfs)).await
+    }
+
+    /// Acquire the write lock of an object.
+    ///
+    /// `store_single_object_and_meta` and `delete_object` take this lock themselves.
+    /// Callers which write object metadata directly (inlined objects, completed
+    /// multipart uploads) must hold it while doing so. The lock is not reentrant.
+    pub async fn lock_object(&self, bucket: &str, key: &str) -> tokio::sync::MutexGuard<'_, ()> {
+        self.object_locks.lock(bucket, key).await
+    }
+
+    // the store holding the block metadata, the shared one in multi-user mode
+    fn block_meta_store(&self) -> MetaStore {
+        match &self.shared_meta_store {
+            Some(shared_store) => MetaStore::clone(shared_store),
+            None => self.user_meta_store.clone(),
+        }
+    }
+
+    /// Persist the metadata committed so far with `durability`, in the store of
+    /// the blocks and the one of the objects. The commits of a store are journaled
+    /// in order, so the writes committed before with a weaker durability are then
+    /// as durable as if they had used `durability`. With a group sync, the block
+    /// files waiting for it are synced first.
+    pub fn persist_metadata(&self, durability: Durability) -> Result<(), MetaError> {
+        let start = Instant::now();
+        if let Some(group_sync) = &self.group_sync {
+            group_sync
+                .sync()
+                .map_err(|e| MetaError::PersistError(e.to_string()))?;
+        }
+        if let Some(shared_store) = &self.shared_meta_store {
+            shared_store.get_underlying_store().persist(durability)?;
+        }
+        self.user_meta_store
+            .get_underlying_store()
+            .persist(durability)?;
+        request_timings::record_meta(start);
+        Ok(())
+    }
+
+    /// The durability override of `bucket`, `None` if its writes use the durability
+    /// of the store.
+    pub fn bucket_durability(&self, bucket: &str) -> Option<Durability> {
+        self.user_meta_store.bucket_durability(bucket)
+    }
+
+    /// The blocks marked as corrupt.
+    pub fn corrupt_blocks(&self) -> CorruptBlocks {
+        CorruptBlocks::new(self.block_meta_store())
+    }
+
+    /// The records of the maintenance jobs, shared by all users in multi-user mode.
+    pub fn jobs(&self) -> JobStore {
+        JobStore::new(self.block_meta_store())
+    }
+
+    /// The blocks of `obj` which are marked as corrupt and not healed yet. Reads of
+    /// the object fail until they are healed.
+    pub fn unhealed_blocks(&self, obj: &Object) -> Result<Vec<BlockID>, MetaError> {
+        let corrupt_blocks = self.corrupt_blocks();
+        let mut blocks = Vec::new();
+        for block in obj.blocks() {
+            if !blocks.contains(block) && corrupt_blocks.is_corrupt(block)? {
+                blocks.push(*block);
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// The objects of this store recorded as using corrupt blocks, see
+    /// [`CasFS::mark_degraded_objects`].
+    pub fn degraded_objects(&self) -> DegradedObjects<'_> {
+        DegradedObjects::new(&self.user_meta_store)
+    }
+
+    /// The objects of `bucket`, or of all buckets, which are degraded: they use
+    /// blocks marked as corrupt which are not healed yet.
+    pub fn list_degraded_objects(
+        &self,
+        bucket: Option<&str>,
+    ) -> Result<Vec<DegradedObject>, MetaError> {
+        self.degraded_objects().list(bucket, &self.corrupt_blocks())
+    }
+
+    /// Mark the objects of this store using any of `blocks` as degraded, with all
+    /// their blocks which are marked as corrupt and not healed. The objects are
+    /// found with the block reference index, or else by scanning all objects.
+    /// Returns the objects marked.
+    pub fn mark_degraded_objects(
+        &self,
+        blocks: &[BlockID],
+    ) -> Result<Vec<DegradedObject>, MetaError> {
+        let mut refs = Vec::new();
+        if self.has_block_refs()? {
+            for block in blocks {
+                refs.extend(self.user_meta_store.block_refs(block)?);
+            }
+        } else {
+            for bucket in self.list_buckets()? {
+                let tree = self.get_bucket(bucket.name())?;
+                for (key, obj) in tree.range_filter(None, None, None) {
+                    if blocks.iter().any(|block| obj.has_block(block)) {
+                        refs.push(BlockRef {
+                            bucket: bucket.name().to_string(),
+                            key,
+                        });
+                    }
+                }
+            }
         }
+        refs.sort();
+        refs.dedup();
+
+        let degraded_objects = self.degraded_objects();
+        let mut marked = Vec::with_capacity(refs.len());
+        for BlockRef { bucket, key } in refs {
+            let Some(obj) = self.get_object_meta(&bucket, &key)? else {
+                continue;
+            };
+            let corrupt = self.unhealed_blocks(&obj)?;
+            if !corrupt.is_empty() {
+                marked.push(degraded_objects.mark(&bucket, &key, &obj, corrupt)?);
+            }
+        }
+        Ok(marked)
+    }
+
+    /// Describe the corrupt `blocks` of an object for a read error: the blocks, and
+    /// the objects using them if the block reference index is enabled. Uploading
+    /// any of these objects again heals the block.
+    pub fn describe_corrupt_blocks(&self, blocks: &[BlockID]) -> String {
+        const MAX_LISTED_OBJECTS: usize = 10;
+
+        let has_refs = self.user_meta_store.has_block_refs().unwrap_or(false);
+        let mut description = format!("{} corrupt block(s):", blocks.len());
+        for block in blocks {
+            description.push_str(&format!(" {}", hex_string(block)));
+            if !has_refs {
+                continue;
+            }
+            match self.user_meta_store.block_refs(block) {
+                Ok(refs) => {
+                    let mut objects: Vec<String> = refs
+                        .iter()
+                        .take(MAX_LISTED_OBJECTS)
+                        .map(|block_ref| format!("{}/{}", block_ref.bucket, block_ref.key))
+                        .collect();
+                    if refs.len() > MAX_LISTED_OBJECTS {
+                        objects.push(format!("{} more", refs.len() - MAX_LISTED_OBJECTS));
+                    }
+                    description.push_str(&format!(" (used by {})", objects.join(", ")));
+                }
+                Err(e) => {
+                    tracing::warn!(block = %hex_string(block), error = %e, "Could not read block references")
+                }
+            }
+        }
+        description.push_str("; upload an object containing the data again to repair it");
+        description
     }
 
     fn path_tree(&self) -> Result<Arc<dyn BaseMetaTree>, MetaError> {
@@ -262,28 +720,136 @@ impl CasFS {
         self.user_meta_store.bucket_exists(bucket_name)
     }
 
-    // create a meta object and insert it into the database
+    // create a meta object and insert it into the database, with its attributes
+    #[allow(clippy::too_many_arguments)]
     pub fn create_object_meta(
         &self,
         bucket_name: &str,
         key: &str,
         size: u64,
         hash: BlockID,
+        e_tag: ETag,
         object_data: ObjectData,
+        attributes: &ObjectAttributes,
     ) -> Result<Object, MetaError> {
-        let obj_meta = Object::new(size, hash, object_data);
-        self.user_meta_store
-            .insert_meta(bucket_name, key, obj_meta.to_vec())?;
+        let obj_meta = Object::new(size, hash, object_data).with_e_tag(e_tag);
+        self.user_meta_store.insert_meta_with_attributes(
+            bucket_name,
+            key,
+            obj_meta.to_vec(),
+            attributes,
+        )?;
+        if let Some(cache) = &self.meta_cache {
+            cache.invalidate(bucket_name, key);
+        }
+        // only completed multipart uploads create multipart objects this way
+        if matches!(obj_meta.data(), ObjectData::MultiPart { .. }) {
+            self.event_handlers.emit(|h| h.on_multipart_complete(bucket_name, key, &obj_meta));
+        } else {
+            self.event_handlers.emit(|h| h.on_put(bucket_name, key, &obj_meta));
+        }
         Ok(obj_meta)
     }
 
+    // like create_object_meta, but the insert runs on the metadata executor
+    async fn insert_object_meta(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        obj_meta: &Object,
+        attributes: ObjectAttributes,
+    ) -> Result<(), MetaError> {
+        let store = self.user_meta_store.clone();
+        let (bucket, object_key, raw_obj) =
+            (bucket_name.to_string(), key.to_string(), obj_meta.to_vec());
+        self.meta_executor
+            .run(move || {
+                store.insert_meta_with_attributes(&bucket, &object_key, raw_obj, &attributes)
+            })
+            .await?;
+        if let Some(cache) = &self.meta_cache {
+            cache.invalidate(bucket_name, key);
+        }
+        self.event_handlers.emit(|h| h.on_put(bucket_name, key, obj_meta));
+        Ok(())
+    }
+
+    // Replace the object at `key` with `obj`, whose references to its blocks
+    // `add_refs` adds, and return the blocks of the replaced object which are no
+    // longer referenced. The caller holds the object lock.
+    async fn replace_object_meta(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        obj: &Object,
+        add_refs: impl FnOnce(&mut Transaction) -> Result<(), MetaError> + Send + 'static,
+    ) -> Result<Vec<Block>, MetaError> {
+        let store = self.user_meta_store.clone();
+        let shared_store = self.shared_meta_store.clone();
+        let (bucket, object_key, raw_obj) =
+            (bucket_name.to_string(), key.to_string(), obj.to_vec());
+        let durability = self.user_meta_store.bucket_durability(bucket_name);
+        let blocks_to_delete = self
+            .meta_executor
+            .run(move || {
+                let Some(block_store) = shared_store else {
+                    return store.replace_meta(&bucket, &object_key, raw_obj, add_refs);
+                };
+                // the shared store of the blocks can't take part in a transaction of
+                // the store of the objects. The references are added before the object
+                // is replaced and the old ones released after, so a failure in between
+                // leaks blocks instead of losing the object.
+                let mut store_tx = block_store.begin_transaction();
+                if let Some(durability) = durability {
+                    store_tx.set_durability(durability);
+                }
+                add_refs(&mut store_tx)?;
+                store_tx.commit()?;
+
+                let Some(old) = store.swap_meta(&bucket, &object_key, raw_obj)? else {
+                    return Ok(Vec::new());
+                };
+                let mut store_tx = block_store.begin_transaction();
+                if let Some(durability) = durability {
+                    store_tx.set_durability(durability);
+                }
+                let mut released = Vec::new();
+                for block_id in old.blocks() {
+                    if let Some(block) = store_tx.release_block(block_id)? {
+                        released.push(block);
+                    }
+                }
+                store_tx.commit()?;
+                Ok(released)
+            })
+            .await?;
+        if let Some(cache) = &self.meta_cache {
+            cache.invalidate(bucket_name, key);
+        }
+        self.event_handlers
+            .emit(|h| h.on_put(bucket_name, key, obj));
+        Ok(blocks_to_delete)
+    }
+
     // get meta object from the DB
     pub fn get_object_meta(
         &self,
         bucket_name: &str,
         key: &str,
     ) -> Result<Option<Object>, MetaError> {
-        self.user_meta_store.get_meta(bucket_name, key)
+        let Some(cache) = &self.meta_cache else {
+            return self.user_meta_store.get_meta(bucket_name, key);
+        };
+        if let Some(obj) = cache.get(bucket_name, key) {
+            return Ok(Some(obj));
+        }
+
+        let generation = cache.generation();
+        let obj = self.user_meta_store.get_meta(bucket_name, key)?;
+        if let Some(obj) = &obj {
+            cache.insert(bucket_name, key, obj, generation);
+        }
+        Ok(obj)
     }
 
     pub fn get_object_paths(
@@ -299,114 +865,520 @@ impl CasFS {
         if obj_meta.is_inlined() {
             Ok(Some((obj_meta, vec![])))
         } else {
-            let blocks = obj_meta.blocks();
-            let block_map = self.block_tree()?;
-            let mut paths = Vec::with_capacity(blocks.len());
-            for block in blocks {
-                let block_meta = block_map
-                    .get_block(block)?
-                    .ok_or(MetaError::BlockNotFound)?;
-                paths.push((
-                    block_meta.disk_path(self.fs_root().clone()),
-                    block_meta.size(),
-                ));
-            }
+            let paths = self.block_paths(obj_meta.blocks())?;
             Ok(Some((obj_meta, paths)))
         }
     }
 
+    /// The paths of the files of `blocks` with their sizes, in order, e.g. to
+    /// read the data of a multipart upload before it is completed.
+    pub fn block_paths(&self, blocks: &[BlockID]) -> Result<Vec<(PathBuf, usize)>, MetaError> {
+        let block_map = self.block_tree()?;
+        let mut paths = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            let block_meta = block_map
+                .get_block(block)?
+                .ok_or(MetaError::BlockNotFound)?;
+            paths.push((self.block_disk_path(&block_meta)?, block_meta.size()));
+        }
+        Ok(paths)
+    }
+
+    /// Like [`CasFS::get_object_paths`], but also pins the block files so they are
+    /// not removed from disk by a concurrent delete until the returned guard is dropped.
+    pub fn get_object_paths_pinned(
+        &self,
+        bucket_name: &str,
+        key: &str,
+    ) -> Result<Option<PinnedObjectPaths>, MetaError> {
+        for _ in 0..PIN_RETRIES {
+            let Some((obj_meta, paths)) = self.get_object_paths(bucket_name, key)? else {
+                return Ok(None);
+            };
+            let disk_paths: Vec<PathBuf> = paths.iter().map(|(path, _)| path.clone()).collect();
+            let pin = self.block_pins.pin(&disk_paths);
+
+            // A delete might have removed blocks between looking up the paths and pinning
+            // them. From here on deletes are deferred, so if all files are still there
+            // they will stay there.
+            if disk_paths.iter().all(|path| path.exists()) {
+                return Ok(Some((obj_meta, paths, pin)));
+            }
+            drop(pin);
+        }
+        Err(MetaError::BlockNotFound)
+    }
+
+    /// Returns `true` if the block reference index of this store is complete, so
+    /// objects and blocks can be looked up by hash.
+    pub fn has_block_refs(&self) -> Result<bool, MetaError> {
+        self.user_meta_store.has_block_refs()
+    }
+
+    /// The objects of this store with the content hash `hash`, sorted by bucket
+    /// and key. Needs the block reference index.
+    pub fn objects_by_hash(&self, hash: &BlockID) -> Result<Vec<BlockRef>, MetaError> {
+        self.user_meta_store.objects_by_hash(hash)
+    }
+
+    /// The path and size of `block` if an object of this store uses it, with the
+    /// file pinned like [`CasFS::get_object_paths_pinned`]. Blocks only used by
+    /// other users of a shared block store are not returned. Needs the block
+    /// reference index.
+    pub fn referenced_block_pinned(
+        &self,
+        block: &BlockID,
+    ) -> Result<Option<(PathBuf, usize, BlockPinGuard)>, MetaError> {
+        if self.user_meta_store.block_refs(block)?.is_empty() {
+            return Ok(None);
+        }
+        for _ in 0..PIN_RETRIES {
+            let Some(block_meta) = self.block_tree()?.get_block(block)? else {
+                return Ok(None);
+            };
+            let path = self.block_disk_path(&block_meta)?;
+            let pin = self.block_pins.pin(std::slice::from_ref(&path));
+            if path.exists() {
+                return Ok(Some((path, block_meta.size(), pin)));
+            }
+            drop(pin);
+        }
+        Err(MetaError::BlockNotFound)
+    }
+
     // create and insert a new  bucket
     pub fn create_bucket(&self, bucket_name: &str) -> Result<(), MetaError> {
         let bm = BucketMeta::new(bucket_name.to_string());
         self.user_meta_store.insert_bucket(bucket_name, bm.to_vec())
     }
 
-    /// Remove a bucket and its associated metadata.
-    // TODO: this is very much not optimal
-    #[tracing::instrument(skip(self), fields(bucket = %bucket_name, objects_deleted))]
-    pub async fn bucket_delete(&self, bucket_name: &str) -> Result<(), MetaError> {
-        // remove from the bucket list tree/partition
-        let bmt = self.user_meta_store.get_allbuckets_tree()?;
-        bmt.remove(bucket_name.as_bytes())?;
-
-        // removes all objects in the bucket
-        let bucket = self.user_meta_store.get_bucket_ext(bucket_name)?;
-        let mut object_count = 0;
-        for key_val in bucket.iter_all() {
-            let (key, _) = key_val?;
-            self.delete_object(
-                bucket_name,
-                std::str::from_utf8(&key).expect("keys are valid utf-8"),
-            )
-            .await?;
-            object_count += 1;
-        }
-
-        tracing::Span::current().record("objects_deleted", object_count);
+    /// Get the ACL of a bucket, which is also the default ACL of its objects.
+    pub fn bucket_acl(&self, bucket_name: &str) -> Result<CannedAcl, MetaError> {
+        Ok(self
+            .user_meta_store
+            .get_acl(bucket_name, None)?
+            .unwrap_or_default())
+    }
 
-        // remove the bucket tree/partition itself
-        self.user_meta_store.drop_bucket(bucket_name)?;
-        Ok(())
+    pub fn set_bucket_acl(&self, bucket_name: &str, acl: CannedAcl) -> Result<(), MetaError> {
+        self.user_meta_store.set_acl(bucket_name, None, acl)
     }
 
-    fn part_key(&self, bucket: &str, key: &str, upload_id: &str, part_number: i64) -> String {
-        format!("{bucket}-{key}-{upload_id}-{part_number}")
+    /// Get the default server-side encryption algorithm of a bucket, applied to
+    /// every object written to it.
+    pub fn bucket_encryption(&self, bucket_name: &str) -> Result<Option<String>, MetaError> {
+        self.user_meta_store.get_bucket_encryption(bucket_name)
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub fn insert_multipart_part(
+    /// Set the default server-side encryption algorithm of a bucket, `None` removes it.
+    pub fn set_bucket_encryption(
         &self,
-        bucket: String,
-        key: String,
-        size: usize,
-        part_number: i64,
-        upload_id: String,
-        hash: BlockID,
-        blocks: Vec<BlockID>,
+        bucket_name: &str,
+        algorithm: Option<&str>,
     ) -> Result<(), MetaError> {
-        let mp_map = self.multipart_tree.clone();
+        self.user_meta_store.set_bucket_encryption(bucket_name, algorithm)
+    }
 
-        let storage_key = self.part_key(&bucket, &key, &upload_id, part_number);
+    /// Get the limits of the contents of a bucket.
+    pub fn bucket_limits(&self, bucket_name: &str) -> Result<BucketLimits, MetaError> {
+        Ok(self
+            .user_meta_store
+            .get_bucket_meta(bucket_name)?
+            .ok_or(MetaError::BucketNotFound)?
+            .limits())
+    }
 
-        tracing::debug!(
-            "CasFS: insert_multipart_part storage_key={}, size={}, blocks={}",
-            storage_key,
-            size,
-            blocks.len()
-        );
+    /// Get the tags of a bucket.
+    pub fn bucket_tags(&self, bucket_name: &str) -> Result<ObjectTags, MetaError> {
+        Ok(self
+            .user_meta_store
+            .get_bucket_meta(bucket_name)?
+            .ok_or(MetaError::BucketNotFound)?
+            .tags()
+            .clone())
+    }
 
-        let mp = MultiPart::new(size, part_number, bucket, key, upload_id, hash, blocks);
+    /// Replace the tags of a bucket, an empty tag set removes them.
+    pub fn set_bucket_tags(&self, bucket_name: &str, tags: ObjectTags) -> Result<(), MetaError> {
+        self.user_meta_store.set_bucket_tags(bucket_name, tags)
+    }
 
-        mp_map.insert(storage_key.as_bytes(), mp)?;
-        Ok(())
+    /// Number of objects and logical bytes stored in a bucket.
+    pub fn bucket_counters(&self, bucket_name: &str) -> Result<BucketCounters, MetaError> {
+        if !self.bucket_exists(bucket_name)? {
+            return Err(MetaError::BucketNotFound);
+        }
+        self.user_meta_store.bucket_counters(bucket_name)
     }
 
-    pub fn get_multipart_part(
+    /// Set the limits of the contents of a bucket. Objects already in the bucket
+    /// are kept when they exceed the new limits.
+    pub fn set_bucket_limits(
         &self,
-        bucket: &str,
-        key: &str,
-        upload_id: &str,
-        part_number: i64,
-    ) -> Result<Option<MultiPart>, MetaError> {
-        let mp_map = self.multipart_tree.clone();
-        let part_key = self.part_key(bucket, key, upload_id, part_number);
+        bucket_name: &str,
+        limits: BucketLimits,
+    ) -> Result<(), MetaError> {
+        self.user_meta_store.set_bucket_limits(bucket_name, limits)
+    }
+
+    /// Checks whether an object of `size` bytes can be stored under `key`
+    /// without exceeding the limits of the bucket. The limits are checked before
+    /// the object is written, concurrent uploads can exceed them together.
+    ///
+    /// Returns the exceeded limit, `None` if the object can be stored.
+    pub fn check_bucket_limits(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        size: u64,
+    ) -> Result<Option<LimitExceeded>, MetaError> {
+        let Some(meta) = self.user_meta_store.get_bucket_meta(bucket_name)? else {
+            return Ok(None);
+        };
+        let limits = meta.limits();
+        if limits.is_empty() {
+            return Ok(None);
+        }
+        let counters = self.user_meta_store.bucket_counters(bucket_name)?;
+        let replaced = self.get_object_meta(bucket_name, key)?.map(|obj| obj.size());
+        Ok(limits.check(&counters, replaced, size))
+    }
+
+    /// Get the effective ACL of an object: its own ACL if it has one, the ACL of
+    /// the bucket otherwise.
+    pub fn object_acl(&self, bucket_name: &str, key: &str) -> Result<CannedAcl, MetaError> {
+        match self.user_meta_store.get_acl(bucket_name, Some(key))? {
+            Some(acl) => Ok(acl),
+            None => self.bucket_acl(bucket_name),
+        }
+    }
+
+    /// Set the ACL of an object. With `None` the object inherits the ACL of the bucket.
+    pub fn set_object_acl(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        acl: Option<CannedAcl>,
+    ) -> Result<(), MetaError> {
+        match acl {
+            Some(acl) => self.user_meta_store.set_acl(bucket_name, Some(key), acl),
+            None => self.user_meta_store.remove_acl(bucket_name, Some(key)),
+        }
+    }
+
+    /// Get the tags of an object, empty if none are set.
+    pub fn object_tags(&self, bucket_name: &str, key: &str) -> Result<ObjectTags, MetaError> {
+        self.user_meta_store.get_tags(bucket_name, key)
+    }
+
+    /// Replace the tags of an object, an empty set removes them.
+    pub fn set_object_tags(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        tags: &ObjectTags,
+    ) -> Result<(), MetaError> {
+        self.user_meta_store.set_tags(bucket_name, key, tags)
+    }
+
+    /// Keys of the objects in a bucket which have the tag of `filter`, in key
+    /// order. This uses the tag index instead of scanning the bucket.
+    pub fn tagged_keys(
+        &self,
+        bucket_name: &str,
+        filter: &TagFilter,
+    ) -> Result<Vec<String>, MetaError> {
+        self.user_meta_store.tagged_keys(bucket_name, filter)
+    }
+
+    /// Get the lifecycle configuration of a bucket, `None` if it has none.
+    pub fn bucket_lifecycle(
+        &self,
+        bucket_name: &str,
+    ) -> Result<Option<BucketLifecycle>, MetaError> {
+        self.user_meta_store.get_bucket_lifecycle(bucket_name)
+    }
+
+    /// Set the lifecycle configuration of a bucket, `None` removes it.
+    pub fn set_bucket_lifecycle(
+        &self,
+        bucket_name: &str,
+        lifecycle: Option<&BucketLifecycle>,
+    ) -> Result<(), MetaError> {
+        self.user_meta_store
+            .set_bucket_lifecycle(bucket_name, lifecycle)
+    }
+
+    /// Get the server access logging configuration of a bucket, `None` if logging
+    /// is disabled.
+    pub fn bucket_logging(&self, bucket_name: &str) -> Result<Option<BucketLogging>, MetaError> {
+        self.user_meta_store.get_bucket_logging(bucket_name)
+    }
+
+    /// Set the server access logging configuration of a bucket, `None` disables it.
+    pub fn set_bucket_logging(
+        &self,
+        bucket_name: &str,
+        logging: Option<&BucketLogging>,
+    ) -> Result<(), MetaError> {
+        self.user_meta_store
+            .set_bucket_logging(bucket_name, logging)
+    }
+
+    /// Whether the requesters pay for the downloads from a bucket.
+    pub fn requester_pays(&self, bucket_name: &str) -> Result<bool, MetaError> {
+        self.user_meta_store.get_requester_pays(bucket_name)
+    }
+
+    /// Let the requesters pay for the downloads from a bucket, or its owner again.
+    pub fn set_requester_pays(&self, bucket_name: &str, enabled: bool) -> Result<(), MetaError> {
+        self.user_meta_store
+            .set_requester_pays(bucket_name, enabled)
+    }
+
+    /// The expiration of the object `key`, last written at `mtime`, by the
+    /// lifecycle rules of its bucket. `None` if no rule applies to it.
+    pub fn object_expiration(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        mtime: SystemTime,
+    ) -> Result<Option<ObjectExpiration>, MetaError> {
+        let Some(lifecycle) = self.bucket_lifecycle(bucket_name)? else {
+            return Ok(None);
+        };
+        let tags = if lifecycle.has_tag_filters() {
+            self.object_tags(bucket_name, key)?
+        } else {
+            ObjectTags::default()
+        };
+        let mtime = mtime
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        Ok(lifecycle.expiration(key, &tags, mtime))
+    }
+
+    /// Remove a bucket and its associated metadata.
+    ///
+    /// The objects are deleted in batched transactions which release their block
+    /// references, and the blocks no longer referenced are removed from disk.
+    #[tracing::instrument(skip(self), fields(bucket = %bucket_name, blocks_deleted))]
+    pub async fn bucket_delete(&self, bucket_name: &str) -> Result<(), MetaError> {
+        // remove from the bucket list tree/partition
+        let bmt = self.user_meta_store.get_allbuckets_tree()?;
+        bmt.remove(bucket_name.as_bytes())?;
+
+        // removes all objects in the bucket, and the bucket tree/partition itself
+        let store = self.user_meta_store.clone();
+        let name = bucket_name.to_string();
+        let blocks_to_delete = self
+            .meta_executor
+            .run(move || store.drop_bucket(&name))
+            .await?;
+        tracing::Span::current().record("blocks_deleted", blocks_to_delete.len());
+        self.remove_blocks(blocks_to_delete).await?;
+
+        self.user_meta_store.remove_acl(bucket_name, None)?;
+        self.user_meta_store.set_bucket_encryption(bucket_name, None)?;
+        self.user_meta_store
+            .set_bucket_lifecycle(bucket_name, None)?;
+        self.user_meta_store.set_bucket_logging(bucket_name, None)?;
+        self.user_meta_store
+            .set_requester_pays(bucket_name, false)?;
+        self.usage_history().remove(bucket_name)?;
+        self.meta_size_history().remove(bucket_name)?;
+        self.activity.remove(&self.user_meta_store, bucket_name)?;
+        if let Some(cache) = &self.meta_cache {
+            cache.invalidate_bucket(bucket_name);
+        }
+        self.event_handlers.emit(|h| h.on_bucket_delete(bucket_name));
+        Ok(())
+    }
+
+    /// Move a batch of the objects of `bucket` whose key starts with `from` to
+    /// the key starting with `to` instead, without touching their blocks. Returns
+    /// the amount of objects moved, 0 once the prefix is empty.
+    ///
+    /// A renamed object is reported as deleted at its old key and put at its new
+    /// one. See [`MetaStore::rename_objects`] for the conflicts refused.
+    pub async fn rename_prefix_batch(
+        &self,
+        bucket: &str,
+        from: &str,
+        to: &str,
+    ) -> Result<usize, MetaError> {
+        let (renamed, _guards) = loop {
+            let store = self.user_meta_store.clone();
+            let (bucket_name, from_prefix, to_prefix) =
+                (bucket.to_string(), from.to_string(), to.to_string());
+            let renames = self
+                .meta_executor
+                .run(move || store.prefix_renames(&bucket_name, &from_prefix, &to_prefix))
+                .await?;
+            if renames.is_empty() {
+                return Ok(0);
+            }
+
+            // a concurrent write of an old or new key would otherwise update the
+            // block references of the object while they are moved, and leak or
+            // free its blocks
+            let keys: Vec<&str> = renames
+                .iter()
+                .flat_map(|(key, new_key)| [key.as_str(), new_key.as_str()])
+                .collect();
+            let guards = self.object_locks.lock_all(bucket, &keys).await;
+            let store = self.user_meta_store.clone();
+            let bucket_name = bucket.to_string();
+            let renamed = self
+                .meta_executor
+                .run(move || store.rename_objects(&bucket_name, &renames))
+                .await?;
+            // every object of the batch was deleted while waiting for the locks
+            if !renamed.is_empty() {
+                break (renamed, guards);
+            }
+        };
+
+        let degraded_objects = self.degraded_objects();
+        for (key, new_key) in &renamed {
+            if let Some(cache) = &self.meta_cache {
+                cache.invalidate(bucket, key);
+                cache.invalidate(bucket, new_key);
+            }
+            degraded_objects.rename(bucket, key, new_key)?;
+            if !self.event_handlers.is_empty() {
+                if let Some(obj) = self.user_meta_store.get_meta(bucket, new_key)? {
+                    self.event_handlers.emit(|h| h.on_delete(bucket, key));
+                    self.event_handlers
+                        .emit(|h| h.on_put(bucket, new_key, &obj));
+                }
+            }
+        }
+        Ok(renamed.len())
+    }
+
+    /// The amount of objects of `bucket` whose key starts with `prefix`.
+    pub fn count_prefix(&self, bucket: &str, prefix: &str) -> Result<u64, MetaError> {
+        let mut count = 0;
+        for item in self.get_bucket(bucket)?.iter_prefix(prefix.as_bytes()) {
+            item?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Store a part of a multipart upload. A part uploaded before with the same
+    /// part number is replaced, and its blocks released.
+    ///
+    /// The part is swapped in a transaction of the store holding the blocks, so of
+    /// concurrent uploads of the same part the last one wins, and the blocks of the
+    /// others are released.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_multipart_part(
+        &self,
+        bucket: String,
+        key: String,
+        size: usize,
+        part_number: i64,
+        upload_id: String,
+        e_tag: ETag,
+        blocks: Vec<BlockID>,
+    ) -> Result<(), MetaError> {
+        let storage_key = part_key(&upload_id, part_number);
+        let legacy_key = legacy_part_key(&bucket, &key, &upload_id, part_number);
 
         tracing::debug!(
-            "CasFS: get_multipart_part storage_key={}",
-            part_key
+            "CasFS: insert_multipart_part upload_id={}, part_number={}, size={}, blocks={}",
+            upload_id,
+            part_number,
+            size,
+            blocks.len()
         );
 
-        let result = mp_map.get_multipart_part(part_key.as_bytes());
-
-        if let Ok(Some(ref mp)) = result {
+        let mp = MultiPart::new(size, part_number, bucket, key, upload_id, e_tag, blocks);
+        let raw_mp = mp.to_vec();
+        let store = self.block_meta_store();
+        let released = self
+            .meta_executor
+            .run(move || {
+                let mut tx = store.begin_transaction();
+                let previous = tx.replace(MULTIPART_TREE, &storage_key, raw_mp)?;
+                // a part stored by an older version under its legacy key
+                let legacy = tx.take(MULTIPART_TREE, legacy_key.as_bytes())?;
+                let mut released = Vec::new();
+                for raw_part in previous.iter().chain(legacy.iter()) {
+                    let part = MultiPart::try_from(raw_part.as_ref())
+                        .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+                    if part.bucket() != mp.bucket() || part.key() != mp.key() {
+                        tx.rollback();
+                        return Err(MetaError::OtherDBError(format!(
+                            "Upload {} belongs to another object",
+                            mp.upload_id()
+                        )));
+                    }
+                    for block_id in part.blocks() {
+                        if let Some(block) = tx.release_block(block_id)? {
+                            released.push(block);
+                        }
+                    }
+                }
+                tx.commit()?;
+                Ok(released)
+            })
+            .await?;
+        if !released.is_empty() {
             tracing::debug!(
-                "CasFS: get_multipart_part found storage_key={}, blocks={}",
-                part_key,
-                mp.blocks().len()
+                part_number,
+                blocks_deleted = released.len(),
+                "Replaced multipart upload part"
+            );
+        }
+        self.remove_blocks(released).await
+    }
+
+    /// Get a part of a multipart upload, `None` if it wasn't uploaded or belongs
+    /// to an upload of another object.
+    pub fn get_multipart_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: i64,
+    ) -> Result<Option<MultiPart>, MetaError> {
+        let mp_map = self.multipart_tree.clone();
+
+        tracing::debug!(
+            "CasFS: get_multipart_part upload_id={}, part_number={}",
+            upload_id,
+            part_number
+        );
+
+        let mp = match mp_map.get_multipart_part(&part_key(upload_id, part_number))? {
+            Some(mp) => mp,
+            None => {
+                let legacy_key = legacy_part_key(bucket, key, upload_id, part_number);
+                match mp_map.get_multipart_part(legacy_key.as_bytes())? {
+                    Some(mp) => mp,
+                    None => return Ok(None),
+                }
+            }
+        };
+        if mp.bucket() != bucket || mp.key() != key {
+            tracing::warn!(
+                upload_id,
+                part_number,
+                "Multipart upload part belongs to another object"
             );
+            return Ok(None);
         }
 
-        result
+        tracing::debug!(
+            "CasFS: get_multipart_part found upload_id={}, part_number={}, blocks={}",
+            upload_id,
+            part_number,
+            mp.blocks().len()
+        );
+        Ok(Some(mp))
     }
 
     pub fn remove_multipart_part(
@@ -417,14 +1389,15 @@ impl CasFS {
         part_number: i64,
     ) -> Result<(), MetaError> {
         let mp_map = self.multipart_tree.clone();
-        let part_key = self.part_key(bucket, key, upload_id, part_number);
 
         tracing::debug!(
-            "CasFS: remove_multipart_part storage_key={}",
-            part_key
+            "CasFS: remove_multipart_part upload_id={}, part_number={}",
+            upload_id,
+            part_number
         );
 
-        mp_map.remove(part_key.as_bytes())
+        mp_map.remove(&part_key(upload_id, part_number))?;
+        mp_map.remove(legacy_part_key(bucket, key, upload_id, part_number).as_bytes())
     }
 
     pub fn key_exists(&self, bucket: &str, key: &str) -> Result<bool, MetaError> {
@@ -437,762 +1410,3698 @@ impl CasFS {
         self.user_meta_store.list_buckets()
     }
 
+    /// The directories at the top level of a bucket and the amount of objects
+    /// under them, in key order. The counts are maintained by the writes, so
+    /// this doesn't scan the objects.
+    pub fn prefix_counts(&self, bucket_name: &str) -> Result<Vec<PrefixCount>, MetaError> {
+        self.user_meta_store.prefix_counts(bucket_name)
+    }
+
+    /// Compute the current usage of a bucket, this scans all objects in it.
+    pub fn bucket_usage(&self, bucket_name: &str) -> Result<BucketUsage, MetaError> {
+        let bucket = self.user_meta_store.get_bucket_ext(bucket_name)?;
+        BucketUsage::compute(bucket.as_ref(), &self.block_tree)
+    }
+
+    /// Statistics of the store, read from counters maintained by every write, so
+    /// unlike `bucket_usage` this doesn't scan the objects.
+    pub fn stats(&self) -> Result<StoreStats, MetaError> {
+        let buckets = self.list_buckets()?.len() as u64;
+        let objects = self.user_meta_store.counters()?;
+        let blocks = match &self.shared_meta_store {
+            Some(shared_store) => shared_store.counters()?,
+            None => objects,
+        };
+        Ok(StoreStats::new(buckets, &objects, &blocks))
+    }
+
+    /// Disk space used by the metadata trees, including the shared block and path
+    /// trees in multi-user mode, and the size of the data they describe.
+    pub fn metadata_size(&self) -> Result<MetadataSize, MetaError> {
+        let tree_sizes = |store: &MetaStore, extra: &[&str], shared| {
+            let mut sizes = store.tree_sizes()?;
+            for name in extra {
+                let bytes = store.tree_disk_space(name)?;
+                if bytes > 0 {
+                    sizes.push((name.to_string(), bytes));
+                }
+            }
+            Ok::<_, MetaError>(sizes.into_iter().map(move |(name, bytes)| TreeSize {
+                name,
+                shared,
+                bytes,
+            }))
+        };
+
+        // the corrupt blocks, the delete queue, multipart parts and jobs are kept with the blocks
+        let block_trees = [CORRUPT_BLOCKS_TREE, DELETE_QUEUE_TREE, MULTIPART_TREE, JOBS_TREE];
+        let mut trees: Vec<TreeSize> = match &self.shared_meta_store {
+            Some(shared_store) => {
+                let user_trees = [
+                    STATS_HISTORY_TREE,
+                    META_SIZE_HISTORY_TREE,
+                    FILE_IDS_TREE,
+                    BUCKET_ACTIVITY_TREE,
+                    RESUMABLE_UPLOADS_TREE,
+                    IDEMPOTENCY_KEYS_TREE,
+                    DEGRADED_OBJECTS_TREE,
+                ];
+                tree_sizes(&self.user_meta_store, &user_trees, false)?
+                    .chain(tree_sizes(shared_store, &block_trees, true)?)
+                    .collect()
+            }
+            None => {
+                let user_trees = [
+                    STATS_HISTORY_TREE,
+                    META_SIZE_HISTORY_TREE,
+                    FILE_IDS_TREE,
+                    BUCKET_ACTIVITY_TREE,
+                    RESUMABLE_UPLOADS_TREE,
+                    IDEMPOTENCY_KEYS_TREE,
+                    DEGRADED_OBJECTS_TREE,
+                ];
+                let trees = [&block_trees[..], &user_trees[..]].concat();
+                tree_sizes(&self.user_meta_store, &trees, false)?.collect()
+            }
+        };
+        trees.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(MetadataSize {
+            trees,
+            block_bytes: self.block_meta_store().counters()?.block_bytes,
+            inline_bytes: self.user_meta_store.counters()?.inline_bytes,
+        })
+    }
+
+    /// The daily size samples of the metadata trees.
+    pub fn meta_size_history(&self) -> MetaSizeHistory<'_> {
+        MetaSizeHistory::new(&self.user_meta_store)
+    }
+
+    /// The results of the requests made with an idempotency key.
+    pub fn idempotency_keys(&self) -> IdempotencyKeys<'_> {
+        IdempotencyKeys::new(&self.user_meta_store)
+    }
+
+    /// Acquire the lock of an idempotency key, held while its request is served
+    /// so a retry waits for the result. These locks are apart from the object
+    /// locks, the request takes those while it writes.
+    pub async fn lock_idempotency_key(&self, key: &str) -> tokio::sync::MutexGuard<'_, ()> {
+        self.idempotency_locks
+            .lock(IDEMPOTENCY_KEYS_TREE, key)
+            .await
+    }
+
+    /// The objects stored from local files, by file identity.
+    pub fn file_id_cache(&self) -> FileIdCache<'_> {
+        FileIdCache::new(&self.user_meta_store)
+    }
+
+    /// Store the size of every metadata tree of this store in the history for
+    /// `day` (`YYYY-MM-DD`), and return it.
+    pub fn record_metadata_size(&self, day: &str) -> Result<MetadataSize, MetaError> {
+        let size = self.metadata_size()?;
+        self.meta_size_history().record(day, &size)?;
+        Ok(size)
+    }
+
+    /// Blob file utilization of the bucket partitions created with key-value
+    /// separation.
+    pub fn blob_stats(&self) -> Result<Vec<BlobStats>, MetaError> {
+        self.user_meta_store.blob_stats()
+    }
+
+    /// Rewrite the blob files of the bucket partitions above the garbage collection
+    /// targets, returns the number of bytes freed.
+    pub fn blob_gc(&self) -> Result<u64, MetaError> {
+        self.user_meta_store.blob_gc()
+    }
+
+    /// The daily usage samples of the buckets.
+    pub fn usage_history(&self) -> UsageHistory<'_> {
+        UsageHistory::new(&self.user_meta_store)
+    }
+
+    /// Sample the usage of every bucket and store it in the usage history for `day`
+    /// (`YYYY-MM-DD`). Returns the amount of buckets sampled.
+    pub fn record_usage(&self, day: &str) -> Result<usize, MetaError> {
+        let history = self.usage_history();
+        let buckets = self.list_buckets()?;
+        for bucket in &buckets {
+            history.record(bucket.name(), day, self.bucket_usage(bucket.name())?)?;
+        }
+        Ok(buckets.len())
+    }
+
+    /// Count a request to `bucket` in its activity, transferring `bytes` of object
+    /// data.
+    pub fn record_access(&self, bucket: &str, access: Access, bytes: u64) {
+        self.activity.record(bucket, access, bytes);
+    }
+
+    /// Count a download of `bytes` of object data from a requester pays bucket,
+    /// charged to the requester instead of the owner of the bucket.
+    pub fn record_requester_paid_read(&self, bucket: &str, bytes: u64) {
+        self.activity.record_requester_paid(bucket, bytes);
+    }
+
+    /// The buckets with requests in the last `minutes`, busiest first.
+    pub fn bucket_activity(&self, minutes: u64) -> Vec<BucketActivity> {
+        self.activity.busiest(minutes)
+    }
+
+    /// Store the bucket activity recorded since the last flush, so it survives a
+    /// restart. Returns the amount of buckets stored.
+    pub fn flush_activity(&self) -> Result<usize, MetaError> {
+        self.activity.flush(&self.user_meta_store)
+    }
+
     /// Delete an object from a bucket.
     /// it also delete keys under it's tree
     #[tracing::instrument(skip(self), fields(bucket = %bucket, key = %key, blocks_deleted))]
     pub async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), MetaError> {
-        let path_map = self.path_tree()?;
+        let _guard = self.lock_object(bucket, key).await;
 
         // get blocks that safe to delete
-        let blocks_to_delete = self.user_meta_store.delete_object(bucket, key)?;
+        let store = self.user_meta_store.clone();
+        let (bucket_name, object_key) = (bucket.to_string(), key.to_string());
+        // the delete doesn't tell if there was an object, only look it up if
+        // someone listens, the lock keeps it from changing in between
+        let check_existed = !self.event_handlers.is_empty();
+        let (existed, blocks_to_delete) = self
+            .meta_executor
+            .run(move || {
+                let existed =
+                    check_existed && store.get_meta(&bucket_name, &object_key)?.is_some();
+                let blocks = store.delete_object(&bucket_name, &object_key)?;
+                store.remove_acl(&bucket_name, Some(&object_key))?;
+                store.remove_tags(&bucket_name, &object_key)?;
+                Ok((existed, blocks))
+            })
+            .await?;
+        if let Some(cache) = &self.meta_cache {
+            cache.invalidate(bucket, key);
+        }
+        if existed {
+            self.event_handlers.emit(|h| h.on_delete(bucket, key));
+        }
 
         tracing::Span::current().record("blocks_deleted", blocks_to_delete.len());
 
-        // Now
-        // - delete all the blocks from disk
-        // - and unlink them in the path map.
-        for block in blocks_to_delete {
-            async_fs::remove_file(block.disk_path(self.root.clone()))
-                .await
-                .expect("Could not delete file");
-            // Now that the path is free it can be removed from the path map
-            if let Err(e) = path_map.remove(block.path()) {
-                // Only print error, we might be able to remove the other ones. If we exist
-                // here, those will be left dangling.
+        self.remove_blocks(blocks_to_delete).await
+    }
+
+    /// Remove blocks which are no longer referenced from disk and unlink them in
+    /// the path map, or queue them for removal with a delete grace.
+    async fn remove_blocks(&self, blocks: Vec<Block>) -> Result<(), MetaError> {
+        if let Some(grace) = self.delete_grace {
+            if blocks.is_empty() {
+                return Ok(());
+            }
+            let queue = self.delete_queue();
+            let due = now_secs() + grace.as_secs();
+            return self
+                .meta_executor
+                .run(move || queue.push(&blocks, due))
+                .await;
+        }
+
+        let path_map = self.path_tree()?;
+        let mut failed = Vec::new();
+        for block in blocks {
+            if self.remove_block_file(&block, &path_map).await.is_err() {
+                failed.push(block);
+            }
+        }
+        // the delete queue retries the removals which failed
+        if failed.is_empty() {
+            return Ok(());
+        }
+        let queue = self.delete_queue();
+        let due = now_secs() + DELETE_RETRY_DELAY.as_secs();
+        self.meta_executor
+            .run(move || queue.push(&failed, due))
+            .await
+    }
+
+    // Remove the file of a block and unlink its path. A file which is gone already,
+    // e.g. removed before a restart, counts as removed. Other errors are logged and
+    // returned, the path then stays allocated.
+    async fn remove_block_file(
+        &self,
+        block: &Block,
+        path_map: &Arc<dyn BaseMetaTree>,
+    ) -> io::Result<()> {
+        let disk_path = match self.block_disk_path(block) {
+            Ok(disk_path) => disk_path,
+            Err(e) => {
+                // the metadata is gone already, the file is leaked
+                tracing::error!(path = %hex_string(block.path()), error = %e, "Could not remove block");
+                return Ok(());
+            }
+        };
+        // the block is still being streamed, the last reader removes it. The removal
+        // is queued as well, so it isn't lost if the process stops before.
+        let mut queued = None;
+        if self.block_pins.is_pinned(&disk_path) {
+            let entry = self
+                .delete_queue()
+                .push_block(block, now_secs() + DELETE_RETRY_DELAY.as_secs())
+                .map_err(io::Error::other)?;
+            match self.block_pins.defer_if_pinned(&disk_path, entry) {
+                None => return Ok(()),
+                // the last reader was done meanwhile
+                Some(entry) => queued = Some(entry),
+            }
+        }
+        match async_fs::remove_file(&disk_path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                tracing::debug!(path = %disk_path.display(), "Block file was removed already");
+            }
+            Err(e) => {
+                tracing::error!(path = %disk_path.display(), error = %e, "Could not remove block file");
+                return Err(e);
+            }
+        }
+        // Now that the path is free it can be removed from the path map
+        if let Err(e) = path_map.remove(block.path()) {
+            // Only print error, we might be able to remove the other ones. If we exist
+            // here, those will be left dangling.
+            tracing::error!(
+                path = %hex_string(block.path()),
+                error = %e,
+                "Could not unlink path from path map"
+            );
+            // a queued removal stays queued, and is retried
+            return Ok(());
+        };
+        if let Some(queued) = queued {
+            if let Err(e) = self.delete_queue().remove(&queued) {
                 tracing::error!(
                     path = %hex_string(block.path()),
                     error = %e,
-                    "Could not unlink path from path map"
+                    "Could not take block off the delete queue"
                 );
-            };
+            }
         }
-
         Ok(())
     }
 
-    // convenient function to store an object to disk and then store it's metada
-    pub async fn store_single_object_and_meta(
+    /// Remove the files of up to `limit` blocks of the delete queue whose grace
+    /// has passed, the oldest first. Returns the amount of blocks taken off the
+    /// queue.
+    ///
+    /// Blocks which were attached again meanwhile, by importing the manifest entry
+    /// of an object using them, are taken off the queue without removing their file.
+    /// Blocks which are still streamed are left to their readers, and the removals
+    /// which fail are tried again later.
+    pub async fn purge_delete_queue(&self, limit: usize) -> Result<usize, MetaError> {
+        self.purge_delete_queue_at(now_secs(), limit).await
+    }
+
+    async fn purge_delete_queue_at(&self, now: u64, limit: usize) -> Result<usize, MetaError> {
+        let queue = self.delete_queue();
+        let due = self
+            .meta_executor
+            .run(move || queue.due(now, limit))
+            .await?;
+
+        let (queue, path_map) = (self.delete_queue(), self.path_tree()?);
+        let mut purged = 0;
+        for queued in &due {
+            // a reader still streams the block, it removes the file once done
+            let pinned = self
+                .block_disk_path(&queued.block)
+                .is_ok_and(|path| self.block_pins.is_pinned(&path));
+            if pinned {
+                continue;
+            }
+            if !self.block_restored(&queued.block, &path_map)?
+                && self
+                    .remove_block_file(&queued.block, &path_map)
+                    .await
+                    .is_err()
+            {
+                // kept in the queue, and retried later so it doesn't hold up the
+                // blocks behind it
+                queue.push(
+                    std::slice::from_ref(&queued.block),
+                    now.saturating_add(DELETE_RETRY_DELAY.as_secs()),
+                )?;
+            } else {
+                purged += 1;
+            }
+            queue.remove(queued)?;
+        }
+        Ok(purged)
+    }
+
+    // a queued block is restored if the block owning its path points to its file again
+    fn block_restored(
         &self,
-        bucket_name: &str,
-        key: &str,
-        data: ByteStream,
-        len: usize,
-    ) -> io::Result<Object> {
-        let (blocks, content_hash, size) = if len > 0 {
-            self.store_object(bucket_name, key, data).await?
-        } else {
-            tracing::warn!(%key, "Skipping store for empty blob");
-            (Vec::new(), [0; 16], 0)
+        block: &Block,
+        path_map: &Arc<dyn BaseMetaTree>,
+    ) -> Result<bool, MetaError> {
+        let Some(owner) = path_map.get(block.path())? else {
+            return Ok(false);
         };
-        let obj = self
-            .create_object_meta(
-                bucket_name,
-                key,
-                size,
-                content_hash,
-                ObjectData::SinglePart { blocks },
-            )
-            .unwrap();
-        Ok(obj)
+        let Ok(id) = BlockID::try_from(&owner[..]) else {
+            return Ok(false);
+        };
+        Ok(self
+            .block_tree
+            .get_block(&id)?
+            .is_some_and(|current| current.path() == block.path()))
     }
 
-    /// Save the stream of bytes to disk.
-    ///
-    /// old_obj_meta is an optional Object that is Some if the key already exists in the metadata.
+    /// Create `key` as the concatenation of the `sources` objects of the same bucket,
+    /// in order, without copying any data.
     ///
-    /// The data is streamed in chunks, and each chunk is hashed and stored on disk.
-    /// The hash of each chunk is used as a key to store the data in the database.
-    ///
-    /// A list of block ID's used as keys for the data blocks is
-    /// returned, along with the hash of the full byte stream, and the length of the stream.
-    #[tracing::instrument(skip(self, data), fields(bucket = %bucket_name, key = %key, size, blocks))]
-    pub async fn store_object(
+    /// The new object references the blocks of the sources, their refcounts are
+    /// incremented once per reference. Like a completed multipart upload the object
+    /// is a composite with one part per source, its hash is computed over the block
+    /// ids and not over the content. An existing object at `key` is replaced, and
+    /// `key` may be one of the sources. Inlined sources have no blocks to reference
+    /// and are rejected, like an object exceeding the limits of the bucket.
+    #[tracing::instrument(
+        skip(self, sources),
+        fields(bucket = %bucket, key = %key, sources = sources.len())
+    )]
+    pub async fn concat_objects(
         &self,
-        bucket_name: &str,
+        bucket: &str,
         key: &str,
-        data: ByteStream,
-    ) -> io::Result<(Vec<BlockID>, BlockID, u64)> {
-        let old_obj_meta = match self.get_object_meta(bucket_name, key) {
-            Ok(Some(obj_meta)) => Some(obj_meta),
-            _ => None,
-        };
-        let old_obj_meta = Arc::new(old_obj_meta);
+        sources: &[String],
+    ) -> Result<Object, MetaError> {
+        if sources.is_empty() {
+            return Err(MetaError::InvalidArgument(
+                "at least one source object is required".to_string(),
+            ));
+        }
+        if !self.bucket_exists(bucket)? {
+            return Err(MetaError::BucketNotFound);
+        }
 
-        let (tx, rx) = unbounded();
-        let mut content_hash = Md5::new();
-        let data = BufferedByteStream::new(data);
+        let _guard = self.lock_object(bucket, key).await;
+
+        let mut blocks = Vec::new();
+        let mut e_tags = Vec::with_capacity(sources.len());
         let mut size = 0;
-        data.map(|res| match res {
-            Ok(buffers) => buffers.into_iter().map(Ok).collect(),
-            Err(e) => vec![Err(e)],
-        })
-        .map(stream::iter)
-        .flatten()
-        .inspect(|maybe_bytes| {
-            if let Ok(bytes) = maybe_bytes {
-                content_hash.update(bytes);
-                size += bytes.len() as u64;
-                self.metrics.bytes_received(bytes.len());
+        for source in sources {
+            let obj = self
+                .get_object_meta(bucket, source)?
+                .ok_or(MetaError::KeyNotFound)?;
+            if obj.inlined().is_some_and(|data| !data.is_empty()) {
+                return Err(MetaError::InvalidArgument(format!(
+                    "inlined object {source} can't be concatenated"
+                )));
             }
-        })
-        .zip(stream::repeat((tx, old_obj_meta)))
-        .enumerate()
-        .for_each(
-            // 1,
-            |(idx, (maybe_chunk, (mut tx, old_obj_meta)))| async move {
-                if let Err(e) = maybe_chunk {
-                    if let Err(e) = tx
-                        .send(Err(std::io::Error::new(e.kind(), e.to_string())))
-                        .await
-                    {
-                        tracing::error!(error = %e, "Could not convey result");
-                    }
-                    return;
-                }
-                // unwrap is safe as we checked that there is no error above
-                let bytes: Vec<u8> = maybe_chunk.unwrap();
-                let mut hasher = Md5::new();
-                hasher.update(&bytes);
-                let block_hash: BlockID = hasher.finalize().into();
-                let data_len = bytes.len();
+            blocks.extend_from_slice(obj.blocks());
+            e_tags.push(*obj.e_tag());
+            size += obj.size();
+        }
+        if let Some(exceeded) = self.check_bucket_limits(bucket, key, size)? {
+            return Err(MetaError::InvalidArgument(exceeded.to_string()));
+        }
 
-                // check if this key already has this block
-                let key_has_block = if let Some(obj) = old_obj_meta.as_ref() {
-                    obj.has_block(&block_hash)
-                } else {
-                    false
-                };
+        // the sources are the parts of the new object
+        let mut hasher = self.content_hash.hasher();
+        for block in &blocks {
+            hasher.update(block);
+        }
+        let obj = Object::new(
+            size,
+            hasher.finalize(),
+            ObjectData::MultiPart {
+                blocks,
+                parts: sources.len(),
+            },
+        )
+        .with_e_tag(content_hash::multipart_e_tag(&e_tags));
+
+        // a source deleted meanwhile fails here, before the object is replaced
+        let block_ids = obj.blocks().to_vec();
+        let blocks_to_delete = self
+            .replace_object_meta(bucket, key, &obj, move |tx| {
+                for block_id in &block_ids {
+                    tx.add_block_reference(block_id)?;
+                }
+                Ok(())
+            })
+            .await?;
 
-                // begin the transaction
-                // there are two main things we need to do here:
-                // 1. write the meta to the database
-                //      - if the block already exists, we don't need to write it to the storage
-                //      - if the block does not exist, we need to write it to the storage
-                // 2. write the actual block to disk
-                //
-                // we commit the meta database transaction BEFORE writing the block to disk
-                // to avoid holding the lock during slow I/O operations.
-                //
-                // IMPORTANT: In multi-user mode, use shared MetaStore for block transactions
-                // to ensure blocks are written to the shared _BLOCKS tree, not user-specific tree
-                let mut store_tx = match &self.shared_meta_store {
-                    Some(shared_store) => shared_store.begin_transaction(),
-                    None => self.user_meta_store.begin_transaction(),
-                };
-                let write_meta_result = store_tx.write_block(block_hash, data_len, key_has_block);
+        self.remove_blocks(blocks_to_delete).await?;
+        Ok(obj)
+    }
 
-                let mut pm = PendingMarker::new(self.metrics.clone());
+    /// Returns for each of `blocks` whether an object of `bucket` can be assembled
+    /// from it with [`CasFS::assemble_object`], so a client only uploads the others.
+    ///
+    /// With a shared block store only the blocks of this user count, the blocks of
+    /// their objects if the block reference index is complete and the staged blocks
+    /// of `bucket`, so the data of other users can't be probed or claimed by hash.
+    pub fn has_blocks(&self, bucket: &str, blocks: &[BlockID]) -> Result<Vec<bool>, MetaError> {
+        let own_refs = self.shared_meta_store.is_some() && self.has_block_refs()?;
+        blocks
+            .iter()
+            .map(|id| {
+                if !self.is_known_block(id)? {
+                    return Ok(false);
+                }
+                if self.shared_meta_store.is_none()
+                    || (own_refs && !self.user_meta_store.block_refs(id)?.is_empty())
+                {
+                    return Ok(true);
+                }
+                Ok(self
+                    .get_object_meta(bucket, &staged_block_key(id))?
+                    .is_some_and(|obj| obj.blocks() == [*id]))
+            })
+            .collect()
+    }
 
-                let block = match write_meta_result {
-                    Err(e) => {
-                        if let Err(e) = tx.unbounded_send(Err(e.into())) {
-                            tracing::error!(error = %e, "Could not send transaction error");
-                        }
-                        return;
-                    }
-                    Ok((false, _)) => {
-                        // the block already exists, no need to write it to the storage
-                        pm.block_ignored();
+    /// Store `data` as a single block of `bucket`, to assemble objects from with
+    /// [`CasFS::assemble_object`].
+    ///
+    /// `data` must hash to `id` and fit in a block. Until an assembled object uses
+    /// it, the block is held by an object below [`STAGED_BLOCKS_PREFIX`], so an
+    /// abandoned upload shows up in the bucket and counts against its limits.
+    #[tracing::instrument(skip(self, data), fields(bucket = %bucket, block = %hex_string(id)))]
+    pub async fn stage_block(
+        &self,
+        bucket: &str,
+        id: &BlockID,
+        data: Bytes,
+    ) -> Result<(), MetaError> {
+        if data.is_empty() || data.len() > self.block_size {
+            return Err(MetaError::InvalidArgument(format!(
+                "a block holds 1 to {} bytes",
+                self.block_size
+            )));
+        }
+        if self.content_hash.digest(&data) != *id {
+            return Err(MetaError::InvalidArgument(
+                "the data doesn't match the block hash".to_string(),
+            ));
+        }
+        self.store_staged_block(bucket, id, data).await
+    }
 
-                        tracing::debug!(target: "cas_storage::locks", "Committing metadata transaction (block exists)");
-                        Box::new(store_tx).commit().unwrap();
+    // stores `data`, which hashes to `id`, as the staged block object of `id`
+    async fn store_staged_block(
+        &self,
+        bucket: &str,
+        id: &BlockID,
+        data: Bytes,
+    ) -> Result<(), MetaError> {
+        if !self.bucket_exists(bucket)? {
+            return Err(MetaError::BucketNotFound);
+        }
+        let key = staged_block_key(id);
+        if let Some(exceeded) = self.check_bucket_limits(bucket, &key, data.len() as u64)? {
+            return Err(MetaError::InvalidArgument(exceeded.to_string()));
+        }
 
-                        if let Err(e) = tx.unbounded_send(Ok((idx, block_hash))) {
-                            tracing::error!(error = %e, "Could not send block id");
-                        }
-                        return;
-                    }
-                    Ok((true, block)) => {
-                        // the block does not exist, we need to write it to the storage
-                        pm.block_pending();
-                        
-                        // COMMIT IMMEDIATELY to release lock
-                        tracing::debug!(target: "cas_storage::locks", "Committing metadata transaction (new block)");
-                        Box::new(store_tx).commit().unwrap();
-                        
-                        block
-                    }
-                };
+        let len = data.len();
+        let stream = ByteStream::new(stream::once(async move { Ok(data) }));
+        self.store_single_object_and_meta(bucket, &key, stream, len)
+            .await
+            .map_err(|e| MetaError::OtherDBError(format!("storing block: {e}")))?;
+        Ok(())
+    }
 
-                // write the actual block to disk
-                // if the disk operation fails, we must manually rollback (compensating transaction)
-                let block_path = block.disk_path(self.root.clone());
-                
-                // Helper to cleanup on failure
-                let cleanup_on_failure = || {
-                    // We need to delete the block we just added.
-                    // Since we just added it with rc=1, we can just delete it.
-                    // We accept potential data leakage here if this cleanup fails,
-                    // as per the design principles (leakage is better than data loss).
-                    
-                    // We need to access the block tree to remove the block.
-                    // In multi-user mode, this is in the shared store.
-                    let block_tree = match &self.shared_meta_store {
-                        Some(shared_store) => shared_store.get_block_tree(),
-                        None => self.user_meta_store.get_block_tree(),
-                    };
-                    
-                    if let Ok(tree) = block_tree {
-                        // We can try to remove it directly from the tree.
-                        // This bypasses the transaction for deletion, but since we know 
-                        // we are the only ones who just added it (rc=1), and we are failing, 
-                        // it should be safe to remove.
-                         if let Err(e) = tree.remove(&block_hash) {
-                             tracing::warn!(block = %hex_string(&block_hash), error = %e, "Failed to cleanup orphan block metadata");
-                         } else {
-                             tracing::debug!(block = %hex_string(&block_hash), "Cleaned up orphan block metadata");
-                         }
-                    }
-                };
+    /// Create `key` from `blocks` this store already has, in order, without the
+    /// data being uploaded again, see [`CasFS::has_blocks`].
+    ///
+    /// The object is the same as if its content had been uploaded in a single
+    /// part: the blocks are read to compute its hash and ETag, and checked against
+    /// their ids. The staged blocks of `bucket` it uses are released once it is
+    /// stored. An existing object at `key` is replaced.
+    #[tracing::instrument(skip(self, blocks), fields(bucket = %bucket, key = %key, blocks = blocks.len()))]
+    pub async fn assemble_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        blocks: &[BlockID],
+    ) -> Result<Object, MetaError> {
+        if blocks.is_empty() {
+            return Err(MetaError::InvalidArgument(
+                "at least one block is required".to_string(),
+            ));
+        }
+        if !self.bucket_exists(bucket)? {
+            return Err(MetaError::BucketNotFound);
+        }
+        if self.has_blocks(bucket, blocks)?.contains(&false) {
+            return Err(MetaError::BlockNotFound);
+        }
 
-                if let Err(e) = self.async_fs.create_dir_all(block_path.parent().unwrap()) {
-                    cleanup_on_failure();
+        let mut hashing = StreamHashing::new(self.content_hash, self.hash_pool.as_deref());
+        let mut size = 0;
+        for id in blocks {
+            let block = self
+                .block_tree
+                .get_block(id)?
+                .ok_or(MetaError::BlockNotFound)?;
+            let path = self.block_disk_path(&block)?;
+            let data = Bytes::from(tokio::fs::read(&path).await.map_err(|e| {
+                MetaError::OtherDBError(format!("reading block file {}: {e}", path.display()))
+            })?);
+            if self.block_hash(&data).await != *id {
+                return Err(MetaError::OtherDBError(format!(
+                    "block file {} doesn't match its id",
+                    path.display()
+                )));
+            }
+            size += data.len() as u64;
+            hashing.update(&data);
+        }
+        let (content_hash, e_tag) = hashing.finish().await;
+        if let Some(exceeded) = self.check_bucket_limits(bucket, key, size)? {
+            return Err(MetaError::InvalidArgument(exceeded.to_string()));
+        }
+        let obj = Object::new(
+            size,
+            content_hash,
+            ObjectData::SinglePart {
+                blocks: blocks.to_vec(),
+            },
+        )
+        .with_e_tag(e_tag);
 
-                    if let Err(e) = tx.unbounded_send(Err(e)) {
-                        pm.block_write_error();
-                        tracing::error!(error = %e, "Could not send path create error");
-                        return;
-                    }
-                }
-                if let Err(e) = self.async_fs.write(&block_path, &bytes) {
-                    cleanup_on_failure();
+        let guard = self.lock_object(bucket, key).await;
 
-                    if let Err(e) = tx.unbounded_send(Err(e)) {
-                        pm.block_write_error();
-                        tracing::error!(error = %e, "Could not send block write error");
-                        return;
-                    }
+        // a block removed meanwhile fails here, before the object is replaced
+        let block_ids = blocks.to_vec();
+        let blocks_to_delete = self
+            .replace_object_meta(bucket, key, &obj, move |tx| {
+                for block_id in &block_ids {
+                    tx.add_block_reference(block_id)?;
                 }
+                Ok(())
+            })
+            .await?;
+        // deleting the staged blocks takes their object locks, which may be the
+        // stripe of this one
+        drop(guard);
+        self.remove_blocks(blocks_to_delete).await?;
+
+        // the object holds its own references to the blocks now, which are
+        // committed, so the staged objects can go
+        let mut staged = blocks.to_vec();
+        staged.sort_unstable();
+        staged.dedup();
+        for id in &staged {
+            let staged_key = staged_block_key(id);
+            if staged_key != key && self.get_object_meta(bucket, &staged_key)?.is_some() {
+                self.delete_object(bucket, &staged_key).await?;
+            }
+        }
+        Ok(obj)
+    }
 
-                pm.block_written(bytes.len());
-
-                if let Err(e) = tx.unbounded_send(Ok((idx, block_hash))) {
-                    tracing::error!(error = %e, "Could not send block id");
-                }
-            },
-        )
-        .await;
+    /// The state of the resumable uploads, see [`CasFS::create_resumable_upload`].
+    pub fn resumable_uploads(&self) -> ResumableUploads<'_> {
+        ResumableUploads::new(&self.user_meta_store)
+    }
 
-        let mut ids = rx.try_collect::<Vec<(usize, BlockID)>>().await?;
-        // Make sure the chunks are in the proper order
-        ids.sort_by_key(|a| a.0);
+    /// Start a resumable upload of `key` in `bucket`, of `size` bytes if known.
+    ///
+    /// The data is appended a block at a time with
+    /// [`CasFS::append_resumable_block`], so an upload which is interrupted
+    /// continues from the last block stored, and the object is created by
+    /// [`CasFS::finish_resumable_upload`].
+    pub fn create_resumable_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        size: Option<u64>,
+    ) -> Result<ResumableUpload, MetaError> {
+        if key.is_empty() {
+            return Err(MetaError::InvalidArgument("missing object key".to_string()));
+        }
+        if !self.bucket_exists(bucket)? {
+            return Err(MetaError::BucketNotFound);
+        }
+        if let Some(size) = size {
+            if let Some(exceeded) = self.check_bucket_limits(bucket, key, size)? {
+                return Err(MetaError::InvalidArgument(exceeded.to_string()));
+            }
+        }
+        self.resumable_uploads().create(bucket, key, size)
+    }
 
-        let blocks: Vec<BlockID> = ids.into_iter().map(|(_, id)| id).collect();
+    /// The resumable upload of `bucket` with `token`, if it is known.
+    pub fn resumable_upload(
+        &self,
+        bucket: &str,
+        token: &str,
+    ) -> Result<Option<ResumableUpload>, MetaError> {
+        Ok(self
+            .resumable_uploads()
+            .get(token)?
+            .filter(|upload| upload.bucket == bucket))
+    }
 
-        tracing::Span::current().record("size", size);
-        tracing::Span::current().record("blocks", blocks.len());
+    // the upload with `token`, if the data sent at `offset` continues it
+    fn continued_upload(
+        &self,
+        bucket: &str,
+        token: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<ResumableUpload, MetaError> {
+        let upload = self
+            .resumable_upload(bucket, token)?
+            .ok_or(MetaError::KeyNotFound)?;
+        if offset != upload.offset {
+            return Err(MetaError::InvalidArgument(format!(
+                "the upload continues at offset {}, not {offset}",
+                upload.offset
+            )));
+        }
+        if upload.size.is_some_and(|size| offset + len > size) {
+            return Err(MetaError::InvalidArgument(
+                "the data exceeds the size of the upload".to_string(),
+            ));
+        }
+        Ok(upload)
+    }
 
-        Ok((
-            blocks,
-            content_hash.finalize().into(),
-            size,
-        ))
+    /// Store a full block of a resumable upload, which continues the upload at
+    /// `offset`. Returns the state of the upload with the block.
+    ///
+    /// Fails with [`MetaError::KeyNotFound`] if the upload is unknown. Only whole
+    /// blocks are stored, so the object is split into the same blocks as when it
+    /// is uploaded at once.
+    #[tracing::instrument(skip(self, data), fields(bucket = %bucket, token = %token, len = data.len()))]
+    pub async fn append_resumable_block(
+        &self,
+        bucket: &str,
+        token: &str,
+        offset: u64,
+        data: Bytes,
+    ) -> Result<ResumableUpload, MetaError> {
+        if data.len() != self.block_size {
+            return Err(MetaError::InvalidArgument(format!(
+                "a block holds {} bytes",
+                self.block_size
+            )));
+        }
+        // appends to the same upload are serialized, the second one sees the
+        // offset moved
+        let _guard = self.upload_locks.lock(bucket, token).await;
+        let mut upload = self.continued_upload(bucket, token, offset, data.len() as u64)?;
+
+        let id = self.block_hash(&data).await;
+        self.store_staged_block(bucket, &id, data).await?;
+        self.resumable_uploads()
+            .append(&mut upload, &id, self.block_size as u64)?;
+        Ok(upload)
     }
 
-    // Store an object inlined in the metadata.
-    pub fn store_inlined_object(
+    /// Create the object of a resumable upload from its stored blocks followed by
+    /// `tail`, the data of the upload from `offset` on, less than a block.
+    ///
+    /// The object is the same as if it had been uploaded at once, see
+    /// [`CasFS::assemble_object`], and the upload is removed.
+    #[tracing::instrument(skip(self, tail), fields(bucket = %bucket, token = %token, len = tail.len()))]
+    pub async fn finish_resumable_upload(
         &self,
-        bucket_name: &str,
-        key: &str,
-        data: Vec<u8>,
+        bucket: &str,
+        token: &str,
+        offset: u64,
+        tail: Bytes,
     ) -> Result<Object, MetaError> {
-        let content_hash = Md5::digest(&data).into();
-        let size = data.len() as u64;
-        let obj = self.create_object_meta(
-            bucket_name,
-            key,
-            size,
-            content_hash,
-            ObjectData::Inline { data },
-        )?;
+        if tail.len() >= self.block_size {
+            return Err(MetaError::InvalidArgument(format!(
+                "the end of an upload holds less than {} bytes",
+                self.block_size
+            )));
+        }
+        let _guard = self.upload_locks.lock(bucket, token).await;
+        let upload = self.continued_upload(bucket, token, offset, tail.len() as u64)?;
+        let size = upload.offset + tail.len() as u64;
+        if upload.size.is_some_and(|expected| size != expected) {
+            return Err(MetaError::InvalidArgument(format!(
+                "the upload ends at {size}, not at its size"
+            )));
+        }
+
+        let mut blocks = self.resumable_uploads().blocks(&upload)?;
+        let obj = if blocks.is_empty() && tail.is_empty() {
+            let stream = ByteStream::new(stream::empty::<io::Result<Bytes>>());
+            self.store_single_object_and_meta(bucket, &upload.key, stream, 0)
+                .await
+                .map_err(|e| MetaError::OtherDBError(format!("storing object: {e}")))?
+        } else {
+            if !tail.is_empty() {
+                let id = self.block_hash(&tail).await;
+                self.store_staged_block(bucket, &id, tail).await?;
+                blocks.push(id);
+            }
+            self.assemble_object(bucket, &upload.key, &blocks).await?
+        };
+        self.resumable_uploads().remove(token)?;
         Ok(obj)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use bytes::Bytes;
-    use futures::stream;
-    use once_cell::sync::Lazy;
-    use rusoto_core::ByteStream;
-    use tempfile::tempdir;
+    /// Cancel the resumable upload of `bucket` with `token` and release the blocks
+    /// it stored. Returns whether the upload was known.
+    pub async fn abort_resumable_upload(
+        &self,
+        bucket: &str,
+        token: &str,
+    ) -> Result<bool, MetaError> {
+        let _guard = self.upload_locks.lock(bucket, token).await;
+        let Some(upload) = self.resumable_upload(bucket, token)? else {
+            return Ok(false);
+        };
+        let mut blocks = self.resumable_uploads().blocks(&upload)?;
+        self.resumable_uploads().remove(token)?;
+        blocks.sort_unstable();
+        blocks.dedup();
+        for id in &blocks {
+            let staged_key = staged_block_key(id);
+            if self.get_object_meta(bucket, &staged_key)?.is_some() {
+                self.delete_object(bucket, &staged_key).await?;
+            }
+        }
+        Ok(true)
+    }
 
-    const TEST_ENGINES: [StorageEngine; 2] = [StorageEngine::Fjall, StorageEngine::FjallNotx];
+    /// The manifest entry of the object at `key`, listing the blocks it references
+    /// and the paths of their files, see [`CasFS::import_manifest_entry`].
+    pub fn manifest_entry(&self, key: &str, obj: &Object) -> Result<ManifestEntry, MetaError> {
+        let mut blocks: Vec<(BlockID, Block)> = Vec::new();
+        for id in obj.blocks() {
+            if blocks.iter().any(|(known, _)| known == id) {
+                continue;
+            }
+            let block = self
+                .block_tree
+                .get_block(id)?
+                .ok_or(MetaError::BlockNotFound)?;
+            blocks.push((*id, block));
+        }
+        Ok(ManifestEntry::new(key, obj, &blocks))
+    }
 
-    static METRICS: Lazy<SharedMetrics> = Lazy::new(|| SharedMetrics::default());
+    /// Store the object of a manifest entry exported from another store at its key
+    /// in `bucket`, without copying any data.
+    ///
+    /// Blocks this store has already get a reference added. The files of the other
+    /// blocks must have been copied to the same paths in the storage locations of
+    /// this store, e.g. with rsync of the fs root, and are checked to exist with the
+    /// right size, and with `verify` also to match their id. An existing object at
+    /// the key is replaced.
+    #[tracing::instrument(skip(self, entry), fields(bucket = %bucket, key = %entry.key))]
+    pub async fn import_manifest_entry(
+        &self,
+        bucket: &str,
+        entry: &ManifestEntry,
+        verify: bool,
+    ) -> Result<Object, MetaError> {
+        let obj = entry.object()?;
+        let blocks = entry.blocks()?;
+        if !self.bucket_exists(bucket)? {
+            return Err(MetaError::BucketNotFound);
+        }
 
-    fn setup_test_fs(storage_engine: StorageEngine) -> (CasFS, tempfile::TempDir) {
-        let dir = tempdir().unwrap();
-        let meta_path = dir.path().join("meta");
-        let metrics = METRICS.clone();
+        for (id, block) in &blocks {
+            if self.block_tree.get_block(id)?.is_some() {
+                continue;
+            }
+            let path = self.block_disk_path(block)?;
+            let missing = |e: io::Error| {
+                MetaError::OtherDBError(format!(
+                    "block file {} of {} is not readable: {e}",
+                    path.display(),
+                    entry.key
+                ))
+            };
+            let len = tokio::fs::metadata(&path).await.map_err(missing)?.len();
+            if len != block.size() as u64 {
+                return Err(MetaError::OtherDBError(format!(
+                    "block file {} of {} has {len} bytes instead of {}",
+                    path.display(),
+                    entry.key,
+                    block.size()
+                )));
+            }
+            if verify {
+                let data = tokio::fs::read(&path).await.map_err(missing)?;
+                if self.content_hash.digest(&data) != *id {
+                    return Err(MetaError::OtherDBError(format!(
+                        "block file {} of {} doesn't match its id",
+                        path.display(),
+                        entry.key
+                    )));
+                }
+            }
+        }
 
-        let fs = CasFS::new(
-            dir.path().to_path_buf(),
-            meta_path,
-            metrics,
-            storage_engine,
-            Some(1),
-            Some(Durability::Buffer),
-        );
-        (fs, dir)
+        let key = entry.key.as_str();
+        let _guard = self.lock_object(bucket, key).await;
+
+        let block_ids = obj.blocks().to_vec();
+        let blocks_to_delete = self
+            .replace_object_meta(bucket, key, &obj, move |tx| {
+                for block_id in &block_ids {
+                    let (_, block) = blocks
+                        .iter()
+                        .find(|(id, _)| id == block_id)
+                        .expect("blocks of the object are listed");
+                    tx.attach_block(block_id, block.size(), block.path(), block.location())?;
+                }
+                Ok(())
+            })
+            .await?;
+
+        self.remove_blocks(blocks_to_delete).await?;
+        Ok(obj)
     }
 
-    #[derive(Debug)]
-    struct MockFs {
-        should_fail_write: bool,
+    /// Move the file of a block to the storage location `location`, `None` for the
+    /// default one, for rebalancing blocks onto new disks.
+    ///
+    /// The file is copied and the copy checked to match the block id before the
+    /// block metadata points to it, then the old file is removed. Returns the
+    /// moved bytes, 0 if the block already is in the location. Readers streaming
+    /// the old file are not tracked, so blocks must only be moved while the store
+    /// isn't serving requests.
+    #[tracing::instrument(skip(self), fields(block = %hex_string(id)))]
+    pub async fn relocate_block(
+        &self,
+        id: &BlockID,
+        location: Option<&str>,
+    ) -> Result<usize, MetaError> {
+        let block = self
+            .block_tree
+            .get_block(id)?
+            .ok_or(MetaError::BlockNotFound)?;
+        if block.location() == location {
+            return Ok(0);
+        }
+        let size = block.size();
+        let from = self.block_disk_path(&block)?;
+        let to = self.block_disk_path(&block.with_location(location.map(str::to_string)))?;
+
+        let io_error = |e: io::Error| MetaError::OtherDBError(format!("moving block: {e}"));
+        let data = tokio::fs::read(&from).await.map_err(io_error)?;
+        if self.content_hash.digest(&data) != *id {
+            return Err(MetaError::OtherDBError(format!(
+                "block file {} doesn't match its id, not moving it",
+                from.display()
+            )));
+        }
+        if let Some(dir) = to.parent() {
+            tokio::fs::create_dir_all(dir).await.map_err(io_error)?;
+        }
+        let tmp = to.with_extension("relocating");
+        let copy = async {
+            let mut file = tokio::fs::File::create(&tmp).await?;
+            tokio::io::AsyncWriteExt::write_all(&mut file, &data).await?;
+            file.sync_all().await?;
+            // read the copy back, so a bad disk is noticed before the original is gone
+            let copied = tokio::fs::read(&tmp).await?;
+            if self.content_hash.digest(&copied) != *id {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the copy doesn't match the block id",
+                ));
+            }
+            tokio::fs::rename(&tmp, &to).await
+        };
+        if let Err(e) = copy.await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(io_error(e));
+        }
+
+        let block_store = self.block_meta_store();
+        let (block_id, new_location) = (*id, location.map(str::to_string));
+        let moved = self
+            .meta_executor
+            .run(move || {
+                let mut tx = block_store.begin_transaction();
+                tx.set_block_location(&block_id, new_location.as_deref())?;
+                tx.commit()
+            })
+            .await;
+        if let Err(e) = moved {
+            let _ = tokio::fs::remove_file(&to).await;
+            return Err(e);
+        }
+
+        tokio::fs::remove_file(&from).await.map_err(io_error)?;
+        Ok(size)
+    }
+
+    // convenient function to store an object to disk and then store it's metada
+    pub async fn store_single_object_and_meta(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        data: ByteStream,
+        len: usize,
+    ) -> io::Result<Object> {
+        self.store_single_object_and_meta_with_attributes(
+            bucket_name,
+            key,
+            data,
+            len,
+            ObjectAttributes::default(),
+        )
+        .await
+    }
+
+    /// Like [`CasFS::store_single_object_and_meta`], but the `attributes` of the
+    /// object are written in the same transaction as its metadata.
+    pub async fn store_single_object_and_meta_with_attributes(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        data: ByteStream,
+        len: usize,
+        attributes: ObjectAttributes,
+    ) -> io::Result<Object> {
+        // Serialize writers of the same key, otherwise both see the same old object
+        // and the refcounts of the blocks they share are incremented twice.
+        let _guard = self.lock_object(bucket_name, key).await;
+        let (blocks, content_hash, e_tag, size) = if len > 0 {
+            self.store_object(bucket_name, key, data).await?
+        } else {
+            tracing::warn!(%key, "Skipping store for empty blob");
+            (Vec::new(), [0; 16], content_hash::e_tag(&[]), 0)
+        };
+        let obj =
+            Object::new(size, content_hash, ObjectData::SinglePart { blocks }).with_e_tag(e_tag);
+        self.insert_object_meta(bucket_name, key, &obj, attributes)
+            .await?;
+        Ok(obj)
+    }
+
+    /// Like [`CasFS::store_single_object_and_meta`], but the object is only created
+    /// if `check` succeeds once its data is stored, e.g. a scan of the data which
+    /// was streamed to a scanner alongside, with the attributes `check` returns.
+    /// Otherwise the references the data added to its blocks are released, the
+    /// object at `key` is left as it was, and the error of `check` is returned.
+    pub async fn store_single_object_and_meta_checked<E>(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        data: ByteStream,
+        len: usize,
+        check: impl std::future::Future<Output = Result<ObjectAttributes, E>>,
+    ) -> io::Result<Result<Object, E>> {
+        let _guard = self.lock_object(bucket_name, key).await;
+        let old_obj_meta = self.get_object_meta(bucket_name, key).ok().flatten();
+        let (blocks, content_hash, e_tag, size) = if len > 0 {
+            self.store_blocks(bucket_name, key, data, old_obj_meta.clone())
+                .await?
+        } else {
+            // the check may wait for the end of the data
+            drop(data);
+            (Vec::new(), [0; 16], content_hash::e_tag(&[]), 0)
+        };
+        let attributes = match check.await {
+            Ok(attributes) => attributes,
+            Err(e) => {
+                // the blocks the old object has didn't get another reference
+                let added = match &old_obj_meta {
+                    Some(old) => blocks
+                        .into_iter()
+                        .filter(|block| !old.has_block(block))
+                        .collect(),
+                    None => blocks,
+                };
+                self.release_blocks(added).await?;
+                return Ok(Err(e));
+            }
+        };
+        let obj =
+            Object::new(size, content_hash, ObjectData::SinglePart { blocks }).with_e_tag(e_tag);
+        self.insert_object_meta(bucket_name, key, &obj, attributes)
+            .await?;
+        Ok(Ok(obj))
+    }
+
+    // release a reference of every entry of `blocks`, and remove the blocks which
+    // are no longer referenced
+    async fn release_blocks(&self, blocks: Vec<BlockID>) -> Result<(), MetaError> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        let store = self.block_meta_store();
+        let released = self
+            .meta_executor
+            .run(move || {
+                let mut tx = store.begin_transaction();
+                let mut released = Vec::new();
+                for block_id in &blocks {
+                    if let Some(block) = tx.release_block(block_id)? {
+                        released.push(block);
+                    }
+                }
+                tx.commit()?;
+                Ok(released)
+            })
+            .await?;
+        self.remove_blocks(released).await
+    }
+
+    /// Write the data of a block whose metadata was committed to its file.
+    ///
+    /// If the write fails, the metadata of a `created` block is removed again. A
+    /// block which existed is rewritten because it was marked as corrupt, and is
+    /// marked as healed once written.
+    async fn write_block_file(
+        &self,
+        block_hash: &BlockID,
+        block: &Block,
+        bytes: &[u8],
+        created: bool,
+        ready_at: Instant,
+        pm: &mut PendingMarker,
+    ) -> io::Result<()> {
+        // if the disk operation fails, we must manually rollback (compensating transaction)
+        let block_path = self.block_disk_path(block).map_err(io::Error::other)?;
+        // wait for the adaptive limiter (if any) before touching the disk
+        let permit = match &self.write_limiter {
+            Some(limiter) => Some(limiter.acquire().await),
+            None => None,
+        };
+        self.metrics.block_queue_wait(ready_at.elapsed());
+        let write_start = Instant::now();
+
+        // Helper to cleanup on failure
+        let cleanup_on_failure = || {
+            // We need to release the reference to the block we just added.
+            // We accept potential data leakage here if this cleanup fails,
+            // as per the design principles (leakage is better than data loss).
+            //
+            // The reference is released in a transaction of the store holding the
+            // blocks, the shared one in multi-user mode, so the block counters stay
+            // right. A concurrent upload of the same data may have added a reference
+            // meanwhile, the block is then kept.
+            let mut tx = self.block_meta_store().begin_transaction();
+            match tx.release_block(block_hash).and_then(|_| tx.commit()) {
+                Ok(()) => {
+                    tracing::debug!(block = %hex_string(block_hash), "Cleaned up orphan block metadata")
+                }
+                Err(e) => {
+                    tracing::warn!(block = %hex_string(block_hash), error = %e, "Failed to cleanup orphan block metadata")
+                }
+            }
+        };
+
+        let write_result = self
+            .async_fs
+            .create_dir_all(block_path.parent().unwrap())
+            .and_then(|_| self.async_fs.write(&block_path, bytes));
+        let write_latency = write_start.elapsed();
+        self.metrics.block_write_latency(write_latency);
+        request_timings::record_disk(ready_at.elapsed());
+        if let Some(permit) = permit {
+            permit.complete(write_latency, write_result.is_ok());
+        }
+        if let Some(limiter) = &self.write_limiter {
+            self.metrics.write_concurrency_limit(limiter.limit());
+        }
+
+        if let Err(e) = write_result {
+            // the metadata of a block being healed stays, it is still referenced
+            if created {
+                cleanup_on_failure();
+            }
+            pm.block_write_error();
+            return Err(e);
+        }
+
+        pm.block_written(bytes.len());
+        if let Some(group_sync) = &self.group_sync {
+            let root = self.block_root(block).map_err(io::Error::other)?;
+            group_sync.add_file(root, block_path, bytes.len() as u64);
+        }
+
+        if !created {
+            match self.corrupt_blocks().mark_healed(block_hash) {
+                Ok(()) => tracing::info!(block = %hex_string(block_hash), "Healed corrupt block"),
+                Err(e) => tracing::warn!(block = %hex_string(block_hash), error = %e, "Could not mark block as healed"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if `block` exists and is not corrupt, so storing it again only
+    /// needs its refcount incremented.
+    fn is_known_block(&self, block: &BlockID) -> Result<bool, MetaError> {
+        Ok(self.block_tree.get_block(block)?.is_some() && !self.corrupt_blocks().is_corrupt(block)?)
+    }
+
+    /// Apply the batched refcount increments of existing blocks in one transaction.
+    ///
+    /// A block removed by a concurrent delete since it was batched is created
+    /// again, and its file written from the batched data.
+    async fn apply_refcount_batch(
+        &self,
+        pending: Vec<PendingRefs>,
+        durability: Option<Durability>,
+        location: Option<&str>,
+    ) -> io::Result<()> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let block_store = self.block_meta_store();
+        let location = location.map(str::to_string);
+        let counts: Vec<(BlockID, usize, usize)> = pending
+            .iter()
+            .map(|refs| (refs.block, refs.count, refs.data.len()))
+            .collect();
+        let recreated = self
+            .meta_executor
+            .run(move || {
+                let mut store_tx = block_store.begin_transaction();
+                if let Some(durability) = durability {
+                    store_tx.set_durability(durability);
+                }
+                let mut recreated = Vec::new();
+                for (block_hash, count, data_len) in counts {
+                    if store_tx.add_block_references(&block_hash, count)?.is_some() {
+                        continue;
+                    }
+                    let (_, block) =
+                        store_tx.write_block(block_hash, data_len, false, location.as_deref())?;
+                    if count > 1 {
+                        store_tx.add_block_references(&block_hash, count - 1)?;
+                    }
+                    recreated.push((block_hash, block));
+                }
+                tracing::debug!(target: "cas_storage::locks", "Committing refcount batch transaction");
+                store_tx.commit()?;
+                Ok(recreated)
+            })
+            .await?;
+
+        for (block_hash, block) in recreated {
+            tracing::debug!(block = %hex_string(&block_hash), "Block removed while batched, writing it again");
+            let refs = pending
+                .iter()
+                .find(|refs| refs.block == block_hash)
+                .expect("recreated blocks are batched");
+            let mut pm = PendingMarker::new(self.metrics.clone());
+            pm.block_pending();
+            self.write_block_file(&block_hash, &block, &refs.data, true, Instant::now(), &mut pm)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Save the stream of bytes to disk.
+    ///
+    /// old_obj_meta is an optional Object that is Some if the key already exists in the metadata.
+    ///
+    /// The data is streamed in chunks, and each chunk is hashed and stored on disk.
+    /// The hash of each chunk is used as a key to store the data in the database.
+    ///
+    /// A list of block ID's used as keys for the data blocks is returned, along with
+    /// the content hash and the ETag of the full byte stream, and the length of the
+    /// stream. The ETag is only computed separately if the content hash isn't one.
+    #[tracing::instrument(skip(self, data), fields(bucket = %bucket_name, key = %key, size, blocks))]
+    pub async fn store_object(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        data: ByteStream,
+    ) -> io::Result<(Vec<BlockID>, BlockID, ETag, u64)> {
+        let old_obj_meta = match self.get_object_meta(bucket_name, key) {
+            Ok(Some(obj_meta)) => Some(obj_meta),
+            _ => None,
+        };
+        self.store_blocks(bucket_name, key, data, old_obj_meta).await
+    }
+
+    /// Save the stream of bytes of a part of a multipart upload to disk, like
+    /// [`CasFS::store_object`].
+    ///
+    /// Every block of a part holds its own reference, also the blocks the object
+    /// at the key already has, as the part is released on its own when it is
+    /// uploaded again.
+    #[tracing::instrument(skip(self, data), fields(bucket = %bucket_name, key = %key, size, blocks))]
+    pub async fn store_part(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        data: ByteStream,
+    ) -> io::Result<(Vec<BlockID>, BlockID, ETag, u64)> {
+        self.store_blocks(bucket_name, key, data, None).await
+    }
+
+    async fn store_blocks(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        data: ByteStream,
+        old_obj_meta: Option<Object>,
+    ) -> io::Result<(Vec<BlockID>, BlockID, ETag, u64)> {
+        let old_obj_meta = Arc::new(old_obj_meta);
+
+        // with an adaptive limiter the limiter decides how many writes actually run
+        let concurrency = match &self.write_limiter {
+            Some(limiter) => limiter.max_concurrency(),
+            None => self.write_concurrency,
+        };
+
+        let durability = self.user_meta_store.bucket_durability(bucket_name);
+        let location = self.placement.location_of(bucket_name, key);
+        // refcount increments of blocks which already exist are applied in batches
+        let refcount_batch = Mutex::new(RefcountBatch::new(REFCOUNT_BATCH_MAX_BYTES));
+        let refcount_batch = &refcount_batch;
+
+        let (tx, rx) = unbounded();
+        let mut hashing = StreamHashing::new(self.content_hash, self.hash_pool.as_deref());
+        let data = BufferedByteStream::new(data, self.block_size);
+        let mut size = 0;
+        data.map(|res| match res {
+            Ok(buffers) => buffers.into_iter().map(|b| Ok(Bytes::from(b))).collect(),
+            Err(e) => vec![Err(e)],
+        })
+        .map(stream::iter)
+        .flatten()
+        .inspect(|maybe_bytes| {
+            if let Ok(bytes) = maybe_bytes {
+                hashing.update(bytes);
+                size += bytes.len() as u64;
+                self.metrics.bytes_received(bytes.len());
+            }
+        })
+        .map(|maybe_bytes| (maybe_bytes, Instant::now()))
+        .zip(stream::repeat((tx, old_obj_meta)))
+        .enumerate()
+        .for_each_concurrent(
+            concurrency,
+            |(idx, ((maybe_chunk, ready_at), (mut tx, old_obj_meta)))| async move {
+                let _in_flight = InFlightMarker::new(self.metrics.clone());
+                if let Err(e) = maybe_chunk {
+                    if let Err(e) = tx
+                        .send(Err(std::io::Error::new(e.kind(), e.to_string())))
+                        .await
+                    {
+                        tracing::error!(error = %e, "Could not convey result");
+                    }
+                    return;
+                }
+                // unwrap is safe as we checked that there is no error above
+                let bytes: Bytes = maybe_chunk.unwrap();
+                let block_hash = self.block_hash(&bytes).await;
+                let data_len = bytes.len();
+
+                // check if this key already has this block
+                let key_has_block = if let Some(obj) = old_obj_meta.as_ref() {
+                    obj.has_block(&block_hash)
+                } else {
+                    false
+                };
+
+                // a known block only needs its refcount incremented, which is batched
+                if !key_has_block {
+                    match self.is_known_block(&block_hash) {
+                        Ok(true) => {
+                            PendingMarker::new(self.metrics.clone()).block_ignored();
+                            let full = refcount_batch.lock().unwrap().add(block_hash, bytes);
+                            let mut result = Ok((idx, block_hash));
+                            if full {
+                                let pending = refcount_batch.lock().unwrap().take();
+                                if let Err(e) =
+                                    self.apply_refcount_batch(pending, durability, location).await
+                                {
+                                    result = Err(e);
+                                }
+                            }
+                            if let Err(e) = tx.unbounded_send(result) {
+                                tracing::error!(error = %e, "Could not send block id");
+                            }
+                            return;
+                        }
+                        Ok(false) => {}
+                        Err(e) => {
+                            if let Err(e) = tx.unbounded_send(Err(e.into())) {
+                                tracing::error!(error = %e, "Could not send transaction error");
+                            }
+                            return;
+                        }
+                    }
+                }
+
+                // begin the transaction
+                // there are two main things we need to do here:
+                // 1. write the meta to the database
+                //      - if the block already exists, we don't need to write it to the storage
+                //      - if the block does not exist, we need to write it to the storage
+                // 2. write the actual block to disk
+                //
+                // we commit the meta database transaction BEFORE writing the block to disk
+                // to avoid holding the lock during slow I/O operations.
+                //
+                // IMPORTANT: In multi-user mode, use shared MetaStore for block transactions
+                // to ensure blocks are written to the shared _BLOCKS tree, not user-specific tree
+                //
+                // The transaction (and its fsync) runs on the metadata executor, so it
+                // doesn't block the runtime.
+                let block_store = match &self.shared_meta_store {
+                    Some(shared_store) => MetaStore::clone(shared_store),
+                    None => self.user_meta_store.clone(),
+                };
+                let location = location.map(str::to_string);
+                let write_meta_result = self
+                    .meta_executor
+                    .run(move || {
+                        let mut store_tx = block_store.begin_transaction();
+                        if let Some(durability) = durability {
+                            store_tx.set_durability(durability);
+                        }
+                        let (created, block) = store_tx.write_block(
+                            block_hash,
+                            data_len,
+                            key_has_block,
+                            location.as_deref(),
+                        )?;
+                        // COMMIT IMMEDIATELY to release lock
+                        tracing::debug!(target: "cas_storage::locks", created, "Committing metadata transaction");
+                        store_tx.commit()?;
+                        // an existing block marked as corrupt is healed with this data,
+                        // its hash is the block id
+                        let heal = !created && CorruptBlocks::new(block_store).is_corrupt(&block_hash)?;
+                        Ok((created, block, heal))
+                    })
+                    .await;
+
+                let mut pm = PendingMarker::new(self.metrics.clone());
+
+                let (created, block) = match write_meta_result {
+                    Err(e) => {
+                        if let Err(e) = tx.unbounded_send(Err(e.into())) {
+                            tracing::error!(error = %e, "Could not send transaction error");
+                        }
+                        return;
+                    }
+                    Ok((false, _, false)) => {
+                        // the block already exists, no need to write it to the storage
+                        pm.block_ignored();
+
+                        if let Err(e) = tx.unbounded_send(Ok((idx, block_hash))) {
+                            tracing::error!(error = %e, "Could not send block id");
+                        }
+                        return;
+                    }
+                    Ok((created, block, _)) => {
+                        // the block does not exist, or is corrupt, we need to write it to the storage
+                        pm.block_pending();
+                        (created, block)
+                    }
+                };
+
+                if let Err(e) = self
+                    .write_block_file(&block_hash, &block, &bytes, created, ready_at, &mut pm)
+                    .await
+                {
+                    if let Err(e) = tx.unbounded_send(Err(e)) {
+                        tracing::error!(error = %e, "Could not send block write error");
+                    }
+                    return;
+                }
+
+                if let Err(e) = tx.unbounded_send(Ok((idx, block_hash))) {
+                    tracing::error!(error = %e, "Could not send block id");
+                }
+            },
+        )
+        .await;
+
+        let pending = refcount_batch.lock().unwrap().take();
+        self.apply_refcount_batch(pending, durability, location).await?;
+
+        let mut ids = rx.try_collect::<Vec<(usize, BlockID)>>().await?;
+        // Make sure the chunks are in the proper order
+        ids.sort_by_key(|a| a.0);
+
+        let blocks: Vec<BlockID> = ids.into_iter().map(|(_, id)| id).collect();
+
+        tracing::Span::current().record("size", size);
+        tracing::Span::current().record("blocks", blocks.len());
+
+        let (content_hash, e_tag) = hashing.finish().await;
+        Ok((blocks, content_hash, e_tag, size))
+    }
+
+    // Store an object inlined in the metadata.
+    pub fn store_inlined_object(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        data: Vec<u8>,
+    ) -> Result<Object, MetaError> {
+        self.store_inlined_object_with_attributes(
+            bucket_name,
+            key,
+            data,
+            &ObjectAttributes::default(),
+        )
+    }
+
+    // Like store_inlined_object, with the attributes of the object
+    pub fn store_inlined_object_with_attributes(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        data: Vec<u8>,
+        attributes: &ObjectAttributes,
+    ) -> Result<Object, MetaError> {
+        let content_hash = self.content_hash.digest(&data);
+        let e_tag = if self.content_hash.is_e_tag() {
+            content_hash
+        } else {
+            content_hash::e_tag(&data)
+        };
+        let size = data.len() as u64;
+        let obj = self.create_object_meta(
+            bucket_name,
+            key,
+            size,
+            content_hash,
+            e_tag,
+            ObjectData::Inline { data },
+            attributes,
+        )?;
+        Ok(obj)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::{Expiration, LifecycleRule, COUNTERS_TREE};
+    use bytes::Bytes;
+    use futures::stream;
+    use once_cell::sync::Lazy;
+    use rusoto_core::ByteStream;
+    use tempfile::tempdir;
+
+    const TEST_ENGINES: [StorageEngine; 2] = [StorageEngine::Fjall, StorageEngine::FjallNotx];
+
+    static METRICS: Lazy<SharedMetrics> = Lazy::new(|| SharedMetrics::default());
+
+    fn setup_test_fs(storage_engine: StorageEngine) -> (CasFS, tempfile::TempDir) {
+        let dir = tempdir().unwrap();
+        let meta_path = dir.path().join("meta");
+        let metrics = METRICS.clone();
+
+        let fs = CasFSBuilder::new(dir.path(), meta_path)
+            .metrics(metrics)
+            .storage_engine(storage_engine)
+            .inlined_metadata_size(1)
+            .durability(Durability::Buffer)
+            .build()
+            .unwrap();
+        (fs, dir)
+    }
+
+    #[derive(Debug)]
+    struct MockFs {
+        should_fail_write: bool,
+    }
+
+    impl MockFs {
+        fn new() -> Self {
+            Self {
+                should_fail_write: false,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl AsyncFileSystem for MockFs {
+        fn create_dir_all(&self, _path: &std::path::Path) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn write(&self, _path: &std::path::Path, _contents: &[u8]) -> std::io::Result<()> {
+            if !self.should_fail_write {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "Mock write failure",
+                ))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl CasFS {
+        #[cfg(test)]
+        fn with_mock_fs(mut self) -> (Self, MockFs) {
+            // Changed return type
+            let mock_fs = MockFs::new();
+            self.async_fs = Box::new(mock_fs.clone()); // Implement Clone for MockFs
+            (self, mock_fs)
+        }
+    }
+
+    // Add Clone implementation for MockFs
+    impl Clone for MockFs {
+        fn clone(&self) -> Self {
+            Self {
+                should_fail_write: self.should_fail_write,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_object_write_failure() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine).0.with_mock_fs();
+            do_test_store_object_write_failure(fs).await;
+        }
+    }
+
+    async fn do_test_store_object_write_failure(fs: CasFS) {
+        let bucket_name = "test_bucket";
+        let key = "test_key";
+        fs.create_bucket(bucket_name).unwrap();
+
+        let test_data = b"test data".repeat(100);
+        let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data)) }));
+
+        let result = fs.store_object(bucket_name, key, stream).await;
+        assert!(result.is_err());
+
+        // Verify the error
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Other);
+        assert_eq!(err.to_string(), "Mock write failure");
+
+        // Verify no blocks were stored in metadata
+        // the block must be rolled back
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        assert_eq!(block_tree.len().unwrap(), 0);
+
+        // Verify object metadata was not created
+        assert!(!fs.key_exists(bucket_name, key).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_store_object() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_store_object(fs).await;
+        }
+    }
+
+    async fn do_test_store_object(fs: CasFS) {
+        const BUCKET_NAME: &str = "test_bucket";
+        const KEY1: &str = "test_key1";
+        const KEY2: &str = "test_key2";
+        fs.create_bucket(BUCKET_NAME).unwrap();
+
+        // Create ByteStream from test data
+        let test_data = b"long test data".repeat(100).to_vec();
+        let test_data_2 = test_data.clone();
+        let test_data_len = test_data.len();
+        let stream = ByteStream::new(stream::once(
+            async move { Ok(Bytes::from(test_data.clone())) },
+        ));
+
+        // Store object
+        let obj = fs
+            .store_single_object_and_meta(BUCKET_NAME, KEY1, stream, test_data_len)
+            .await
+            .unwrap();
+
+        // Verify results
+        assert_eq!(obj.size(), test_data_len as u64);
+        assert_eq!(obj.blocks().len(), 1);
+
+        // Verify block & path was stored
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        assert!(block_tree.len().unwrap() > 0);
+        let stored_block = block_tree.get_block(&obj.blocks()[0]).unwrap().unwrap();
+        assert_eq!(stored_block.size(), test_data_len);
+        assert_eq!(stored_block.rc(), 1);
+        assert_eq!(
+            fs.path_tree()
+                .unwrap()
+                .contains_key(stored_block.path())
+                .unwrap(),
+            true
+        );
+
+        // Store the same data again with different key
+        // - The same block should be returned
+        // - The refcount should be increased
+
+        let stream = ByteStream::new(stream::once(
+            async move { Ok(Bytes::from(test_data_2.clone())) },
+        ));
+
+        let new_obj = fs
+            .store_single_object_and_meta(BUCKET_NAME, KEY2, stream, test_data_len)
+            .await
+            .unwrap();
+
+        assert_eq!(new_obj.blocks(), obj.blocks());
+
+        let stored_block = block_tree.get_block(&new_obj.blocks()[0]).unwrap().unwrap();
+        assert_eq!(stored_block.rc(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_store_object_concurrent_blocks_keep_order() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_store_object_concurrent_blocks_keep_order(fs.with_write_concurrency(4)).await;
+        }
+    }
+
+    async fn do_test_store_object_concurrent_blocks_keep_order(fs: CasFS) {
+        const BUCKET_NAME: &str = "test_bucket";
+        const KEY: &str = "test_key";
+        fs.create_bucket(BUCKET_NAME).unwrap();
+        assert_eq!(fs.write_concurrency(), 4);
+
+        // 4 distinct blocks, the last one partially filled
+        let test_data: Vec<u8> = (0..BLOCK_SIZE * 3 + 10).map(|i| (i / BLOCK_SIZE) as u8).collect();
+        let expected: Vec<BlockID> = test_data
+            .chunks(BLOCK_SIZE)
+            .map(|chunk| Md5::digest(chunk).into())
+            .collect();
+        let test_data_len = test_data.len();
+        let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data)) }));
+
+        let obj = fs
+            .store_single_object_and_meta(BUCKET_NAME, KEY, stream, test_data_len)
+            .await
+            .unwrap();
+
+        assert_eq!(obj.size(), test_data_len as u64);
+        assert_eq!(obj.blocks(), expected.as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_store_inlined_object() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_store_inlined_object(fs).await;
+        }
+    }
+
+    async fn do_test_store_inlined_object(fs: CasFS) {
+        let bucket_name = "test_bucket";
+        let key = "test_key1";
+        fs.create_bucket(bucket_name).unwrap();
+
+        let small_data = b"small test data".to_vec();
+        let obj_meta = fs
+            .store_inlined_object(bucket_name, key, small_data.clone())
+            .unwrap();
+
+        // Verify inlined data
+        assert_eq!(obj_meta.size(), small_data.len() as u64);
+        assert_eq!(obj_meta.inlined().unwrap(), &small_data);
+    }
+
+    #[tokio::test]
+    async fn test_store_object_refcount() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_store_object_refcount(fs).await;
+        }
+    }
+
+    async fn do_test_store_object_refcount(fs: CasFS) {
+        let bucket_name = "test_bucket";
+        let key1 = "test_key1";
+        let key2 = "test_key2";
+        fs.create_bucket(bucket_name).unwrap();
+
+        // Create ByteStream from test data
+        let test_data = b"long test data".repeat(100).to_vec();
+        let test_data_len = test_data.len();
+        let test_data_2 = test_data.clone();
+        let test_data_3 = test_data.clone();
+        let stream = ByteStream::new(stream::once(
+            async move { Ok(Bytes::from(test_data.clone())) },
+        ));
+
+        // Store object
+        let obj = fs
+            .store_single_object_and_meta(bucket_name, key1, stream, test_data_len)
+            .await
+            .unwrap();
+
+        // Initial refcount must be 1
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        for id in obj.blocks() {
+            let block = block_tree.get_block(id).unwrap().unwrap();
+            assert_eq!(block.rc(), 1);
+        }
+
+        {
+            // Test using  the same key
+            // Refcount must not be increased
+
+            let stream =
+                ByteStream::new(stream::once(
+                    async move { Ok(Bytes::from(test_data_2.clone())) },
+                ));
+
+            let new_obj = fs
+                .store_single_object_and_meta(bucket_name, key1, stream, test_data_len)
+                .await
+                .unwrap();
+
+            assert_eq!(new_obj.blocks(), obj.blocks());
+
+            let stored_block = block_tree.get_block(&new_obj.blocks()[0]).unwrap().unwrap();
+            assert_eq!(stored_block.rc(), 1);
+        }
+        {
+            // Test  using a new key
+            // Refcount must be increased
+            let stream =
+                ByteStream::new(stream::once(
+                    async move { Ok(Bytes::from(test_data_3.clone())) },
+                ));
+
+            let new_obj = fs
+                .store_single_object_and_meta(bucket_name, key2, stream, test_data_len)
+                .await
+                .unwrap();
+
+            assert_eq!(new_obj.blocks(), obj.blocks());
+
+            let stored_block = block_tree.get_block(&new_obj.blocks()[0]).unwrap().unwrap();
+            assert_eq!(stored_block.rc(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_and_delete_object() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_store_and_delete_object(fs).await;
+        }
+    }
+
+    // test store and delete object
+    // - store an object
+    // - delete the object
+    async fn do_test_store_and_delete_object(fs: CasFS) {
+        let bucket_name = "test-bucket";
+        let key = "test/key";
+
+        // Create bucket
+        fs.create_bucket(bucket_name).unwrap();
+
+        // Create test data and stream
+        let test_data = b"test data".to_vec();
+        let test_data_len = test_data.len();
+        let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data)) }));
+
+        // Store object
+        let obj = fs
+            .store_single_object_and_meta(bucket_name, key, stream, test_data_len)
+            .await
+            .unwrap();
+
+        // Verify object exists
+        let exists = fs.key_exists(bucket_name, key).unwrap();
+        assert_eq!(exists, true);
+
+        // verify blocks and path exist
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        let mut stored_paths = Vec::new();
+        for id in obj.blocks() {
+            let block = block_tree.get_block(id).unwrap().unwrap();
+            assert_eq!(
+                fs.path_tree().unwrap().contains_key(block.path()).unwrap(),
+                true
+            );
+            stored_paths.push(block.path().to_vec());
+        }
+
+        // Delete object
+        fs.delete_object(bucket_name, key).await.unwrap();
+
+        // Verify object no longer exists
+        let exists = fs.key_exists(bucket_name, key).unwrap();
+        assert_eq!(exists, false);
+
+        // Verify blocks were cleaned up
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        for id in obj.blocks() {
+            assert!(block_tree.get_block(id).unwrap().is_none());
+        }
+        // Verify paths were cleaned up
+        for path in stored_paths {
+            assert_eq!(fs.path_tree().unwrap().contains_key(&path).unwrap(), false);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_and_delete_object_with_refcount_same_blocks_diffkey() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_store_and_delete_object_with_refcount_same_blocks_diffkey(fs).await;
+        }
+    }
+
+    // Test storing and deleting an object with refcount
+    // - store object
+    //       refcount == 1
+    // - store object again with differrent key
+    //      refcount == 2
+    // - delete the first object
+    // - check block/disk/whatever is still there
+    // - delete the second object
+    // - check block/disk/whatever should be gone
+    async fn do_test_store_and_delete_object_with_refcount_same_blocks_diffkey(fs: CasFS) {
+        let bucket = "test-bucket";
+        let key1 = "test/key1";
+        let key2 = "test/key2";
+
+        // Create bucket
+        fs.create_bucket(bucket).unwrap();
+
+        // Create test data
+        let test_data = b"test data".to_vec();
+        let test_data_len = test_data.len();
+        let test_data2 = test_data.clone();
+        let stream1 = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data)) }));
+
+        // Store first object
+        let obj1 = fs
+            .store_single_object_and_meta(bucket, key1, stream1, test_data_len)
+            .await
+            .unwrap();
+        // Verify blocks  exist with rc=1
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        for id in obj1.blocks() {
+            let block = block_tree.get_block(id).unwrap().unwrap();
+            assert_eq!(block.rc(), 1);
+        }
+
+        // Store same data with different key
+
+        let stream2 = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data2)) }));
+
+        let obj2 = fs
+            .store_single_object_and_meta(bucket, key2, stream2, test_data_len)
+            .await
+            .unwrap();
+
+        // Verify both objects share same blocks
+        assert_eq!(obj1.blocks(), obj2.blocks());
+        assert_eq!(obj1.hash(), obj2.hash());
+        // Verify blocks  exist with rc=2
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        for id in obj2.blocks() {
+            let block = block_tree.get_block(id).unwrap().unwrap();
+            assert_eq!(block.rc(), 2);
+        }
+
+        // Delete first object
+        fs.delete_object(bucket, key1).await.unwrap();
+
+        // Verify blocks still exist
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        for id in obj1.blocks() {
+            let block = block_tree.get_block(id).unwrap().unwrap();
+            assert_eq!(block.rc(), 1);
+        }
+
+        // Delete second object
+        fs.delete_object(bucket, key2).await.unwrap();
+
+        // Verify blocks are gone
+        for id in obj1.blocks() {
+            assert!(block_tree.get_block(id).unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_and_delete_object_with_refcount_same_blocks_samekey() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_store_and_delete_object_with_refcount_same_blocks_samekey(fs).await;
+        }
+    }
+
+    // Test storing and deleting an object with refcount
+    // - store object
+    //       refcount == 1
+    // - store object again with differrent key
+    //      refcount == 1
+    // - delete the object
+    // - check block/disk/whatever should be gone
+    async fn do_test_store_and_delete_object_with_refcount_same_blocks_samekey(fs: CasFS) {
+        let bucket = "test-bucket";
+        let key1 = "test/key1";
+
+        // Create bucket
+        fs.create_bucket(bucket).unwrap();
+
+        // Create test data
+        let test_data = b"test data".to_vec();
+        let test_data_len = test_data.len();
+        let test_data2 = test_data.clone();
+        let stream1 = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data)) }));
+
+        // Store first object
+        let obj1 = fs
+            .store_single_object_and_meta(bucket, key1, stream1, test_data_len)
+            .await
+            .unwrap();
+        // Verify blocks  exist with rc=1
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        for id in obj1.blocks() {
+            let block = block_tree.get_block(id).unwrap().unwrap();
+            assert_eq!(block.rc(), 1);
+        }
+
+        // Store same data with same key
+
+        let stream2 = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data2)) }));
+
+        let obj2 = fs
+            .store_single_object_and_meta(bucket, key1, stream2, test_data_len)
+            .await
+            .unwrap();
+
+        // Verify both objects share same blocks
+        assert_eq!(obj1.blocks(), obj2.blocks());
+        assert_eq!(obj1.hash(), obj2.hash());
+        // Verify blocks  exist with rc=1
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        for id in obj2.blocks() {
+            let block = block_tree.get_block(id).unwrap().unwrap();
+            assert_eq!(block.rc(), 1);
+        }
+
+        // Delete object
+        fs.delete_object(bucket, key1).await.unwrap();
+
+        // Verify blocks are gone
+        for id in obj1.blocks() {
+            assert!(block_tree.get_block(id).unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_object_checked() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_store_object_checked(fs).await;
+        }
+    }
+
+    async fn do_test_store_object_checked(fs: CasFS) {
+        let bucket = "test-bucket";
+        fs.create_bucket(bucket).unwrap();
+        let data = |data: &'static [u8]| {
+            ByteStream::new(stream::once(async move { Ok(Bytes::from_static(data)) }))
+        };
+        let old = fs
+            .store_single_object_and_meta(bucket, "a", data(b"old data"), 8)
+            .await
+            .unwrap();
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        let rc = |id: &BlockID| block_tree.get_block(id).unwrap().map(|block| block.rc());
+        let hash = |key| {
+            fs.get_object_meta(bucket, key)
+                .unwrap()
+                .map(|obj| *obj.hash())
+        };
+
+        // a rejected object is not stored, and the object it replaced stays
+        let rejected = fs
+            .store_single_object_and_meta_checked(bucket, "a", data(b"new data"), 8, async {
+                Err::<ObjectAttributes, _>("infected")
+            })
+            .await
+            .unwrap();
+        assert_eq!(rejected.unwrap_err(), "infected");
+        assert_eq!(hash("a"), Some(*old.hash()));
+        assert_eq!(rc(&old.blocks()[0]), Some(1));
+
+        // the blocks it shares with other objects keep their references
+        let rejected = fs
+            .store_single_object_and_meta_checked(bucket, "b", data(b"old data"), 8, async {
+                Err::<ObjectAttributes, _>("infected")
+            })
+            .await
+            .unwrap();
+        assert!(rejected.is_err());
+        assert_eq!(hash("b"), None);
+        assert_eq!(rc(&old.blocks()[0]), Some(1));
+
+        // the attributes the check returns are written with the object
+        let tags = ObjectTags::new(vec![("scan".to_string(), "clean".to_string())]).unwrap();
+        let attributes = ObjectAttributes::default()
+            .with_acl(Some(CannedAcl::PublicRead))
+            .with_tags(tags.clone());
+        let new = fs
+            .store_single_object_and_meta_checked(bucket, "a", data(b"new data"), 8, async {
+                Ok::<_, ()>(attributes)
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            fs.user_meta_store.get_acl(bucket, Some("a")).unwrap(),
+            Some(CannedAcl::PublicRead)
+        );
+        assert_eq!(fs.object_tags(bucket, "a").unwrap(), tags);
+        assert_eq!(hash("a"), Some(*new.hash()));
+        assert_eq!(rc(&new.blocks()[0]), Some(1));
+        assert_eq!(fs.block_paths(new.blocks()).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concat_objects() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_concat_objects(fs).await;
+        }
+    }
+
+    async fn do_test_concat_objects(fs: CasFS) {
+        let bucket_name = "test-bucket";
+        fs.create_bucket(bucket_name).unwrap();
+
+        let mut sources = Vec::new();
+        for (key, data) in [("a", &b"first part"[..]), ("b", &b"second part"[..])] {
+            let data = Bytes::from(data.to_vec());
+            let len = data.len();
+            let stream = ByteStream::new(stream::once(async move { Ok(data) }));
+            let obj = fs
+                .store_single_object_and_meta(bucket_name, key, stream, len)
+                .await
+                .unwrap();
+            sources.push((key.to_string(), obj));
+        }
+        let keys: Vec<String> = sources.iter().map(|(key, _)| key.clone()).collect();
+
+        let obj = fs.concat_objects(bucket_name, "ab", &keys).await.unwrap();
+        let expected_blocks: Vec<BlockID> = sources
+            .iter()
+            .flat_map(|(_, obj)| obj.blocks().to_vec())
+            .collect();
+        assert_eq!(obj.blocks(), &expected_blocks[..]);
+        assert_eq!(obj.size(), 21);
+        assert!(obj.format_e_tag().ends_with("-2\""));
+
+        // the ETag is computed from the ETags of the sources, like for a multipart upload
+        let e_tags: Vec<ETag> = sources.iter().map(|(_, obj)| *obj.e_tag()).collect();
+        assert_eq!(obj.e_tag(), &content_hash::multipart_e_tag(&e_tags));
+        assert_ne!(obj.e_tag(), obj.hash());
+        let stored = fs.get_object_meta(bucket_name, "ab").unwrap().unwrap();
+        assert_eq!(stored.e_tag(), obj.e_tag());
+        assert_eq!(stored.hash(), obj.hash());
+
+        let block_tree = fs.block_tree().unwrap();
+        for id in obj.blocks() {
+            assert_eq!(block_tree.get_block(id).unwrap().unwrap().rc(), 2);
+        }
+
+        // the concatenation keeps the blocks alive after the sources are deleted
+        for key in &keys {
+            fs.delete_object(bucket_name, key).await.unwrap();
+        }
+        for id in obj.blocks() {
+            assert_eq!(block_tree.get_block(id).unwrap().unwrap().rc(), 1);
+        }
+        let (_, paths) = fs.get_object_paths(bucket_name, "ab").unwrap().unwrap();
+        assert!(paths.iter().all(|(path, _)| path.exists()));
+
+        // replacing an object with a concatenation of itself
+        let obj = fs
+            .concat_objects(bucket_name, "ab", &["ab".to_string(), "ab".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(obj.blocks().len(), 4);
+        for id in obj.blocks() {
+            assert_eq!(block_tree.get_block(id).unwrap().unwrap().rc(), 2);
+        }
+
+        // a replacement whose references can't be added leaves the object as it was
+        let replacement = Object::new(0, [0; 16], ObjectData::Inline { data: Vec::new() });
+        assert!(matches!(
+            fs.user_meta_store
+                .replace_meta(bucket_name, "ab", replacement.to_vec(), |_| {
+                    Err(MetaError::BlockNotFound)
+                }),
+            Err(MetaError::BlockNotFound)
+        ));
+        let stored = fs.get_object_meta(bucket_name, "ab").unwrap().unwrap();
+        assert_eq!(stored.blocks(), obj.blocks());
+        for id in obj.blocks() {
+            assert_eq!(block_tree.get_block(id).unwrap().unwrap().rc(), 2);
+        }
+
+        assert!(matches!(
+            fs.concat_objects(bucket_name, "c", &["missing".to_string()]).await,
+            Err(MetaError::KeyNotFound)
+        ));
+        assert!(matches!(
+            fs.concat_objects(bucket_name, "c", &[]).await,
+            Err(MetaError::InvalidArgument(_))
+        ));
+
+        fs.delete_object(bucket_name, "ab").await.unwrap();
+        for id in &expected_blocks {
+            assert!(block_tree.get_block(id).unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_assemble_object() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_assemble_object(fs).await;
+        }
+    }
+
+    async fn do_test_assemble_object(fs: CasFS) {
+        let bucket_name = "test-bucket";
+        fs.create_bucket(bucket_name).unwrap();
+        let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from("first part")) }));
+        let first = fs
+            .store_single_object_and_meta(bucket_name, "a", stream, 10)
+            .await
+            .unwrap();
+
+        let existing = first.blocks()[0];
+        let new = fs.content_hash().digest(b" and more");
+        assert_eq!(
+            fs.has_blocks(bucket_name, &[existing, new]).unwrap(),
+            vec![true, false]
+        );
+        assert!(matches!(
+            fs.assemble_object(bucket_name, "c", &[existing, new]).await,
+            Err(MetaError::BlockNotFound)
+        ));
+
+        // only data matching the hash is staged
+        assert!(matches!(
+            fs.stage_block(bucket_name, &new, Bytes::from("other data"))
+                .await,
+            Err(MetaError::InvalidArgument(_))
+        ));
+        fs.stage_block(bucket_name, &new, Bytes::from(" and more"))
+            .await
+            .unwrap();
+        let staged_key = format!("{STAGED_BLOCKS_PREFIX}{}", hex_string(&new));
+        assert!(fs
+            .get_object_meta(bucket_name, &staged_key)
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            fs.has_blocks(bucket_name, &[existing, new]).unwrap(),
+            vec![true, true]
+        );
+
+        // the object looks like a single part upload of the content
+        let obj = fs
+            .assemble_object(bucket_name, "c", &[existing, new])
+            .await
+            .unwrap();
+        assert_eq!(obj.blocks(), &[existing, new][..]);
+        assert_eq!(obj.size(), 19);
+        assert_eq!(
+            obj.hash(),
+            &fs.content_hash().digest(b"first part and more")
+        );
+        assert_eq!(obj.e_tag(), obj.hash());
+
+        // the staged block is released, the object holds the references
+        assert!(fs
+            .get_object_meta(bucket_name, &staged_key)
+            .unwrap()
+            .is_none());
+        let block_tree = fs.block_tree().unwrap();
+        assert_eq!(block_tree.get_block(&existing).unwrap().unwrap().rc(), 2);
+        assert_eq!(block_tree.get_block(&new).unwrap().unwrap().rc(), 1);
+
+        fs.delete_object(bucket_name, "a").await.unwrap();
+        fs.delete_object(bucket_name, "c").await.unwrap();
+        assert!(block_tree.get_block(&existing).unwrap().is_none());
+        assert!(block_tree.get_block(&new).unwrap().is_none());
+    }
+
+    // Copy the files of a store, as a crash of the process holding it open would
+    // leave them on disk
+    fn copy_dir(from: &std::path::Path, to: &std::path::Path) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            let target = to.join(entry.file_name());
+            if entry.file_type().unwrap().is_dir() {
+                copy_dir(&entry.path(), &target);
+            } else {
+                std::fs::copy(entry.path(), target).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_acknowledged_writes_survive_crash() {
+        for engine in TEST_ENGINES {
+            for durability in [Durability::Buffer, Durability::Fdatasync] {
+                let dir = tempdir().unwrap();
+                let open = |root: std::path::PathBuf| {
+                    CasFSBuilder::new(&root, root.join("meta"))
+                        .metrics(METRICS.clone())
+                        .storage_engine(engine)
+                        .inlined_metadata_size(1)
+                        .durability(durability)
+                        .build()
+                        .unwrap()
+                };
+                let fs = open(dir.path().join("live"));
+                fs.create_bucket("bucket").unwrap();
+                for i in 0..10u8 {
+                    let data = vec![i; 1000];
+                    let stream =
+                        ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+                    fs.store_single_object_and_meta("bucket", &format!("key-{i}"), stream, 1000)
+                        .await
+                        .unwrap();
+                }
+
+                // every write acknowledged before the crash is found after it
+                copy_dir(&dir.path().join("live"), &dir.path().join("crashed"));
+                let recovered = open(dir.path().join("crashed"));
+                for i in 0..10u8 {
+                    let key = format!("key-{i}");
+                    let obj = recovered.get_object_meta("bucket", &key).unwrap();
+                    assert!(obj.is_some(), "{engine:?} {durability:?}: {key} is lost");
+                    let (_, paths) = recovered.get_object_paths("bucket", &key).unwrap().unwrap();
+                    for (path, size) in paths {
+                        assert_eq!(std::fs::read(path).unwrap(), vec![i; size]);
+                    }
+                }
+                drop(fs);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_group_sync() {
+        let dir = tempdir().unwrap();
+        let config = crate::cas::GroupSyncConfig {
+            max_delay: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let group_sync = Arc::new(GroupSync::new(config, METRICS.clone()));
+        let open = |root: std::path::PathBuf| {
+            CasFSBuilder::new(&root, root.join("meta"))
+                .metrics(METRICS.clone())
+                .inlined_metadata_size(1)
+                .durability(Durability::Buffer)
+                .group_sync(group_sync.clone())
+                .build()
+                .unwrap()
+        };
+        let fs = open(dir.path().join("live"));
+        fs.create_bucket("bucket").unwrap();
+        for i in 0..10u8 {
+            let data = vec![i; 1000];
+            let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+            fs.store_single_object_and_meta("bucket", &format!("key-{i}"), stream, 1000)
+                .await
+                .unwrap();
+        }
+        assert_eq!(group_sync.pending_bytes(), 10 * 1000);
+
+        // the writes are durable once synced
+        group_sync.sync().unwrap();
+        assert_eq!(group_sync.pending_bytes(), 0);
+        copy_dir(&dir.path().join("live"), &dir.path().join("crashed"));
+        let recovered = open(dir.path().join("crashed"));
+        for i in 0..10u8 {
+            let (_, paths) = recovered
+                .get_object_paths("bucket", &format!("key-{i}"))
+                .unwrap()
+                .unwrap();
+            for (path, size) in paths {
+                assert_eq!(std::fs::read(path).unwrap(), vec![i; size]);
+            }
+        }
+
+        // a persist requested by a client syncs the block files as well
+        let stream = ByteStream::new(stream::once(async { Ok(Bytes::from(vec![42; 1000])) }));
+        fs.store_single_object_and_meta("bucket", "key", stream, 1000)
+            .await
+            .unwrap();
+        assert_eq!(group_sync.pending_bytes(), 1000);
+        fs.persist_metadata(Durability::Fdatasync).unwrap();
+        assert_eq!(group_sync.pending_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        for engine in TEST_ENGINES {
+            let dir = tempdir().unwrap();
+            let fs = CasFSBuilder::new(dir.path(), dir.path().join("meta"))
+                .metrics(METRICS.clone())
+                .storage_engine(engine)
+                .inlined_metadata_size(1)
+                .block_size(crate::cas::builder::MIN_BLOCK_SIZE)
+                .durability(Durability::Buffer)
+                .build()
+                .unwrap();
+            do_test_resumable_upload(fs).await;
+        }
+    }
+
+    async fn do_test_resumable_upload(fs: CasFS) {
+        let bucket_name = "test-bucket";
+        fs.create_bucket(bucket_name).unwrap();
+        let block_size = fs.block_size();
+        let data: Vec<u8> = (0..2 * block_size + 10).map(|i| i as u8).collect();
+        let block = |i: usize| Bytes::copy_from_slice(&data[i * block_size..(i + 1) * block_size]);
+
+        let upload = fs
+            .create_resumable_upload(bucket_name, "big", Some(data.len() as u64))
+            .unwrap();
+        let token = upload.token.clone();
+        assert!(fs.resumable_upload("other", &token).unwrap().is_none());
+
+        // only whole blocks at the offset reached are stored
+        let upload = fs
+            .append_resumable_block(bucket_name, &token, 0, block(0))
+            .await
+            .unwrap();
+        assert_eq!(upload.offset, block_size as u64);
+        assert!(matches!(
+            fs.append_resumable_block(bucket_name, &token, 0, block(0))
+                .await,
+            Err(MetaError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            fs.append_resumable_block(bucket_name, &token, upload.offset, Bytes::from("short"))
+                .await,
+            Err(MetaError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            fs.append_resumable_block(bucket_name, "unknown", 0, block(0))
+                .await,
+            Err(MetaError::KeyNotFound)
+        ));
+
+        // the upload continues from its stored state
+        let offset = fs
+            .resumable_upload(bucket_name, &token)
+            .unwrap()
+            .unwrap()
+            .offset;
+        fs.append_resumable_block(bucket_name, &token, offset, block(1))
+            .await
+            .unwrap();
+        let tail = Bytes::copy_from_slice(&data[2 * block_size..]);
+        assert!(matches!(
+            fs.finish_resumable_upload(bucket_name, &token, 2 * block_size as u64, tail.slice(1..))
+                .await,
+            Err(MetaError::InvalidArgument(_))
+        ));
+        let obj = fs
+            .finish_resumable_upload(bucket_name, &token, 2 * block_size as u64, tail)
+            .await
+            .unwrap();
+
+        // the object is the same as when uploaded at once
+        let len = data.len();
+        let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+        let direct = fs
+            .store_single_object_and_meta(bucket_name, "direct", stream, len)
+            .await
+            .unwrap();
+        assert_eq!(obj.blocks(), direct.blocks());
+        assert_eq!(obj.hash(), direct.hash());
+        assert_eq!(obj.e_tag(), direct.e_tag());
+        assert!(fs.resumable_upload(bucket_name, &token).unwrap().is_none());
+        for id in obj.blocks() {
+            let staged_key = format!("{STAGED_BLOCKS_PREFIX}{}", hex_string(id));
+            assert!(fs
+                .get_object_meta(bucket_name, &staged_key)
+                .unwrap()
+                .is_none());
+        }
+
+        // an aborted upload releases its blocks
+        let upload = fs
+            .create_resumable_upload(bucket_name, "gone", None)
+            .unwrap();
+        let block_data = Bytes::from(vec![7; block_size]);
+        fs.append_resumable_block(bucket_name, &upload.token, 0, block_data.clone())
+            .await
+            .unwrap();
+        let id = fs.content_hash().digest(&block_data);
+        assert_eq!(fs.has_blocks(bucket_name, &[id]).unwrap(), vec![true]);
+        assert!(fs
+            .abort_resumable_upload(bucket_name, &upload.token)
+            .await
+            .unwrap());
+        assert!(!fs
+            .abort_resumable_upload(bucket_name, &upload.token)
+            .await
+            .unwrap());
+        assert!(fs.block_tree().unwrap().get_block(&id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_overwrite_malformed_object() {
+        for engine in TEST_ENGINES {
+            for block_refs in [false, true] {
+                let (mut fs, _dir) = setup_test_fs(engine);
+                fs.user_meta_store.set_block_refs(block_refs).unwrap();
+                fs.create_bucket("bucket").unwrap();
+                fs.get_bucket("bucket")
+                    .unwrap()
+                    .insert(b"key", vec![0xff; 3])
+                    .unwrap();
+
+                // an error instead of a panic
+                let result = fs.store_inlined_object("bucket", "key", b"data".to_vec());
+                assert!(matches!(result, Err(MetaError::OtherDBError(_))));
+                let result = fs.user_meta_store.delete_object("bucket", "key");
+                assert!(matches!(result, Err(MetaError::OtherDBError(_))));
+                let result = fs.user_meta_store.drop_bucket("bucket");
+                assert!(matches!(result, Err(MetaError::OtherDBError(_))));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_block_refs() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_block_refs(fs).await;
+        }
+    }
+
+    async fn do_test_block_refs(mut fs: CasFS) {
+        async fn store(fs: &CasFS, key: &str, data: &'static [u8]) -> Object {
+            let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+            fs.store_single_object_and_meta("bucket", key, stream, data.len())
+                .await
+                .unwrap()
+        }
+        let refs = |fs: &CasFS, block: &BlockID| -> Vec<String> {
+            fs.user_meta_store
+                .block_refs(block)
+                .unwrap()
+                .into_iter()
+                .map(|block_ref| format!("{}/{}", block_ref.bucket, block_ref.key))
+                .collect()
+        };
+
+        // objects stored without the index are added when it is built
+        fs.create_bucket("bucket").unwrap();
+        let a = store(&fs, "a", b"shared data").await;
+        assert!(!fs.user_meta_store.has_block_refs().unwrap());
+        fs.user_meta_store.set_block_refs(true).unwrap();
+        assert!(fs.user_meta_store.has_block_refs().unwrap());
+        let shared = a.blocks()[0];
+        assert_eq!(refs(&fs, &shared), vec!["bucket/a"]);
+        let by_hash = |fs: &CasFS, hash: &BlockID| -> Vec<String> {
+            fs.objects_by_hash(hash)
+                .unwrap()
+                .into_iter()
+                .map(|block_ref| block_ref.key)
+                .collect()
+        };
+        assert_eq!(by_hash(&fs, a.hash()), vec!["a"]);
+
+        let b = store(&fs, "b", b"shared data").await;
+        assert_eq!(b.blocks(), a.blocks());
+        assert_eq!(refs(&fs, &shared), vec!["bucket/a", "bucket/b"]);
+        assert_eq!(by_hash(&fs, a.hash()), vec!["a", "b"]);
+
+        // overwriting moves the reference to the new block
+        let b = store(&fs, "b", b"other data").await;
+        assert_eq!(refs(&fs, &shared), vec!["bucket/a"]);
+        assert_eq!(refs(&fs, &b.blocks()[0]), vec!["bucket/b"]);
+        assert_eq!(by_hash(&fs, a.hash()), vec!["a"]);
+        assert_eq!(by_hash(&fs, b.hash()), vec!["b"]);
+
+        let (path, size, pin) = fs.referenced_block_pinned(&shared).unwrap().unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"shared data");
+        assert_eq!(size, b"shared data".len());
+        drop(pin);
+
+        fs.delete_object("bucket", "a").await.unwrap();
+        assert!(refs(&fs, &shared).is_empty());
+        assert!(by_hash(&fs, a.hash()).is_empty());
+        assert!(fs.referenced_block_pinned(&shared).unwrap().is_none());
+
+        // the index is not maintained while disabled, so it is no longer complete
+        fs.user_meta_store.set_block_refs(false).unwrap();
+        assert!(!fs.user_meta_store.has_block_refs().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_heal_corrupt_block() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_heal_corrupt_block(fs).await;
+        }
+    }
+
+    async fn do_test_heal_corrupt_block(fs: CasFS) {
+        async fn store(fs: &CasFS, key: &str, data: &'static [u8]) -> Object {
+            let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+            fs.store_single_object_and_meta("bucket", key, stream, data.len())
+                .await
+                .unwrap()
+        }
+
+        fs.create_bucket("bucket").unwrap();
+        let obj = store(&fs, "a", b"precious data").await;
+        assert!(fs.unhealed_blocks(&obj).unwrap().is_empty());
+
+        // damage the block file and mark it as corrupt
+        let block = obj.blocks()[0];
+        let (_, paths) = fs.get_object_paths("bucket", "a").unwrap().unwrap();
+        std::fs::write(&paths[0].0, b"garbage").unwrap();
+        fs.corrupt_blocks().mark(&block).unwrap();
+        assert_eq!(fs.unhealed_blocks(&obj).unwrap(), vec![block]);
+        let marked = fs.mark_degraded_objects(&[block]).unwrap();
+        assert_eq!(marked.len(), 1);
+        assert_eq!(
+            (marked[0].key.as_str(), &marked[0].blocks[..]),
+            ("a", &[block][..])
+        );
+        assert_eq!(fs.list_degraded_objects(Some("bucket")).unwrap(), marked);
+
+        // uploading the same data under another key rewrites the block file
+        let copy = store(&fs, "b", b"precious data").await;
+        assert_eq!(copy.blocks(), obj.blocks());
+        assert!(fs.unhealed_blocks(&obj).unwrap().is_empty());
+        assert!(fs.list_degraded_objects(None).unwrap().is_empty());
+        assert!(fs.corrupt_blocks().get(&block).unwrap().unwrap().is_healed());
+        assert_eq!(std::fs::read(&paths[0].0).unwrap(), b"precious data");
+
+        // healing doesn't change the reference count
+        let block_tree = fs.block_tree().unwrap();
+        assert_eq!(block_tree.get_block(&block).unwrap().unwrap().rc(), 2);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "without references"))]
+    async fn test_release_unreferenced_block() {
+        let (fs, _dir) = setup_test_fs(StorageEngine::Fjall);
+        fs.create_bucket("bucket").unwrap();
+        let data = Bytes::from_static(b"data");
+        let stream = ByteStream::new(stream::once(async move { Ok(data) }));
+        let obj = fs
+            .store_single_object_and_meta("bucket", "a", stream, 4)
+            .await
+            .unwrap();
+        let block_id = obj.blocks()[0];
+
+        // corrupt the metadata, the block has no references left
+        let block_tree = fs.block_tree().unwrap();
+        let mut block = block_tree.get_block(&block_id).unwrap().unwrap();
+        assert!(block.decrement_refcount());
+        fs.block_meta_store()
+            .get_tree("_BLOCKS")
+            .unwrap()
+            .insert(&block_id, block.to_vec())
+            .unwrap();
+
+        // without debug assertions the count saturates, and the block is kept
+        let mut tx = fs.block_meta_store().begin_transaction();
+        assert!(tx.release_block(&block_id).unwrap().is_none());
+        tx.commit().unwrap();
+        assert_eq!(block_tree.get_block(&block_id).unwrap().unwrap().rc(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_refcount_batch() {
+        for engine in TEST_ENGINES {
+            let dir = tempdir().unwrap();
+            let fs = CasFSBuilder::new(dir.path(), dir.path().join("meta"))
+                .metrics(METRICS.clone())
+                .storage_engine(engine)
+                .inlined_metadata_size(1)
+                .block_size(crate::cas::builder::MIN_BLOCK_SIZE)
+                .durability(Durability::Buffer)
+                .build()
+                .unwrap();
+            do_test_refcount_batch(fs).await;
+        }
     }
 
-    impl MockFs {
-        fn new() -> Self {
-            Self {
-                should_fail_write: false,
-            }
+    async fn do_test_refcount_batch(fs: CasFS) {
+        async fn store(fs: &CasFS, key: &str, data: Vec<u8>) -> Object {
+            let len = data.len();
+            let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+            fs.store_single_object_and_meta("bucket", key, stream, len)
+                .await
+                .unwrap()
         }
-    }
 
-    #[async_trait]
-    impl AsyncFileSystem for MockFs {
-        fn create_dir_all(&self, _path: &std::path::Path) -> std::io::Result<()> {
-            Ok(())
+        fs.create_bucket("bucket").unwrap();
+        let block_size = fs.block_size();
+        let data = b"a".repeat(4 * block_size);
+
+        // every occurrence of a block is a reference to it
+        let obj = store(&fs, "a", data.clone()).await;
+        assert_eq!(obj.blocks().len(), 4);
+        let block = obj.blocks()[0];
+        assert!(obj.blocks().iter().all(|b| *b == block));
+        let block_tree = fs.block_tree().unwrap();
+        assert_eq!(block_tree.get_block(&block).unwrap().unwrap().rc(), 4);
+
+        let copy = store(&fs, "b", data.clone()).await;
+        assert_eq!(copy.blocks(), obj.blocks());
+        assert_eq!(block_tree.get_block(&block).unwrap().unwrap().rc(), 8);
+
+        let (_, paths) = fs.get_object_paths("bucket", "b").unwrap().unwrap();
+        let mut read = Vec::new();
+        for (path, _) in paths {
+            read.extend(std::fs::read(path).unwrap());
         }
+        assert_eq!(read, data);
+
+        // a batched block which was removed meanwhile is written again
+        let data = b"b".repeat(block_size);
+        let missing: BlockID = Md5::digest(&data).into();
+        let pending = vec![PendingRefs {
+            block: missing,
+            count: 3,
+            data: Bytes::from(data.clone()),
+        }];
+        fs.apply_refcount_batch(pending, None, None).await.unwrap();
+        let recreated = block_tree.get_block(&missing).unwrap().unwrap();
+        assert_eq!(recreated.rc(), 3);
+        assert_eq!(recreated.size(), block_size);
+        let path = recreated.disk_path(fs.root.clone());
+        assert_eq!(std::fs::read(path).unwrap(), data);
+    }
 
-        fn write(&self, _path: &std::path::Path, _contents: &[u8]) -> std::io::Result<()> {
-            if !self.should_fail_write {
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Mock write failure",
-                ))
-            } else {
-                Ok(())
+    #[tokio::test]
+    async fn test_hash_pool() {
+        let pool = Arc::new(HashPool::new(4).unwrap());
+        let data: Vec<u8> = (0..10 * crate::cas::builder::MIN_BLOCK_SIZE + 7)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut objects = Vec::new();
+        for pool in [None, Some(pool)] {
+            let dir = tempdir().unwrap();
+            let mut builder = CasFSBuilder::new(dir.path(), dir.path().join("meta"))
+                .metrics(METRICS.clone())
+                .inlined_metadata_size(1)
+                .block_size(crate::cas::builder::MIN_BLOCK_SIZE)
+                .durability(Durability::Buffer);
+            if let Some(pool) = pool {
+                builder = builder.hash_pool(pool);
             }
+            let fs = builder.build().unwrap();
+            fs.create_bucket("bucket").unwrap();
+
+            // the data arrives in chunks which don't line up with the blocks
+            let chunks: Vec<_> = data
+                .chunks(3000)
+                .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                .collect();
+            let stream = ByteStream::new(stream::iter(chunks));
+            let obj = fs
+                .store_single_object_and_meta("bucket", "key", stream, data.len())
+                .await
+                .unwrap();
+            let assembled = fs
+                .assemble_object("bucket", "copy", obj.blocks())
+                .await
+                .unwrap();
+            assert_eq!(assembled.hash(), obj.hash());
+            objects.push(obj);
         }
+
+        let (inline, pooled) = (&objects[0], &objects[1]);
+        assert_eq!(pooled.blocks().len(), 11);
+        assert_eq!(pooled.blocks(), inline.blocks());
+        let hash: BlockID = Md5::digest(&data).into();
+        assert_eq!(*pooled.hash(), hash);
+        assert_eq!(pooled.hash(), inline.hash());
+        assert_eq!(pooled.e_tag(), inline.e_tag());
     }
 
-    impl CasFS {
-        #[cfg(test)]
-        fn with_mock_fs(mut self) -> (Self, MockFs) {
-            // Changed return type
-            let mock_fs = MockFs::new();
-            self.async_fs = Box::new(mock_fs.clone()); // Implement Clone for MockFs
-            (self, mock_fs)
+    #[tokio::test]
+    async fn test_bucket_limits() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_bucket_limits(fs).await;
         }
     }
 
-    // Add Clone implementation for MockFs
-    impl Clone for MockFs {
-        fn clone(&self) -> Self {
-            Self {
-                should_fail_write: self.should_fail_write,
-            }
+    async fn do_test_bucket_limits(fs: CasFS) {
+        async fn store(fs: &CasFS, key: &str, data: &'static [u8]) {
+            let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+            fs.store_single_object_and_meta("bucket", key, stream, data.len())
+                .await
+                .unwrap();
         }
+
+        fs.create_bucket("bucket").unwrap();
+        assert_eq!(fs.bucket_limits("bucket").unwrap(), BucketLimits::default());
+        assert!(matches!(
+            fs.set_bucket_limits("missing", BucketLimits::default()),
+            Err(MetaError::BucketNotFound)
+        ));
+        let limits = BucketLimits {
+            max_objects: Some(2),
+            max_bytes: Some(30),
+        };
+        fs.set_bucket_limits("bucket", limits).unwrap();
+        assert_eq!(fs.bucket_limits("bucket").unwrap(), limits);
+        assert_eq!(fs.list_buckets().unwrap()[0].limits(), limits);
+
+        // tags are kept next to the limits
+        let tags = ObjectTags::for_bucket(vec![("team".to_string(), "ops".to_string())]).unwrap();
+        fs.set_bucket_tags("bucket", tags.clone()).unwrap();
+        assert_eq!(fs.bucket_tags("bucket").unwrap(), tags);
+        assert_eq!(fs.bucket_limits("bucket").unwrap(), limits);
+        assert!(matches!(
+            fs.bucket_tags("missing"),
+            Err(MetaError::BucketNotFound)
+        ));
+
+        store(&fs, "a", b"ten bytes!").await;
+        store(&fs, "b", b"ten bytes!").await;
+        assert_eq!(
+            fs.check_bucket_limits("bucket", "c", 1).unwrap(),
+            Some(LimitExceeded::Objects(2))
+        );
+        // replacing an object frees its size
+        assert_eq!(fs.check_bucket_limits("bucket", "a", 20).unwrap(), None);
+        assert_eq!(
+            fs.check_bucket_limits("bucket", "a", 21).unwrap(),
+            Some(LimitExceeded::Bytes(30))
+        );
+        let sources = ["a".to_string(), "b".to_string()];
+        assert!(matches!(
+            fs.concat_objects("bucket", "c", &sources).await,
+            Err(MetaError::InvalidArgument(_))
+        ));
+
+        fs.delete_object("bucket", "b").await.unwrap();
+        assert_eq!(fs.check_bucket_limits("bucket", "c", 20).unwrap(), None);
+
+        fs.set_bucket_limits("bucket", BucketLimits::default()).unwrap();
+        assert_eq!(fs.check_bucket_limits("bucket", "c", 100).unwrap(), None);
     }
 
     #[tokio::test]
-    async fn test_store_object_write_failure() {
+    async fn test_prefix_counts() {
         for engine in TEST_ENGINES {
-            let (fs, _dir) = setup_test_fs(engine).0.with_mock_fs();
-            do_test_store_object_write_failure(fs).await;
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_prefix_counts(fs).await;
         }
     }
 
-    async fn do_test_store_object_write_failure(fs: CasFS) {
-        let bucket_name = "test_bucket";
-        let key = "test_key";
-        fs.create_bucket(bucket_name).unwrap();
-
-        let test_data = b"test data".repeat(100);
-        let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data)) }));
-
-        let result = fs.store_object(bucket_name, key, stream).await;
-        assert!(result.is_err());
+    async fn do_test_prefix_counts(fs: CasFS) {
+        let counts = |fs: &CasFS| -> Vec<(String, u64)> {
+            fs.prefix_counts("bucket")
+                .unwrap()
+                .into_iter()
+                .map(|count| (count.prefix, count.objects))
+                .collect()
+        };
+        fs.create_bucket("bucket").unwrap();
+        for key in ["top", "logs/a", "logs/b", "logs/2024/c", "photos/x"] {
+            fs.store_inlined_object("bucket", key, b"data".to_vec())
+                .unwrap();
+        }
+        // overwriting an object doesn't count it twice
+        fs.store_inlined_object("bucket", "logs/a", b"new".to_vec())
+            .unwrap();
+        assert_eq!(
+            counts(&fs),
+            vec![("logs/".to_string(), 3), ("photos/".to_string(), 1)]
+        );
 
-        // Verify the error
-        let err = result.unwrap_err();
-        assert_eq!(err.kind(), std::io::ErrorKind::Other);
-        assert_eq!(err.to_string(), "Mock write failure");
+        // a directory without objects is gone
+        fs.delete_object("bucket", "photos/x").await.unwrap();
+        assert_eq!(counts(&fs), vec![("logs/".to_string(), 3)]);
 
-        // Verify no blocks were stored in metadata
-        // the block must be rolled back
-        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
-        assert_eq!(block_tree.len().unwrap(), 0);
+        // the counts of a store written before they were maintained are rebuilt
+        let tree = fs.user_meta_store.get_tree(COUNTERS_TREE).unwrap();
+        tree.remove(b"complete_v3").unwrap();
+        tree.remove(b"prefix/bucket/logs/").unwrap();
+        fs.user_meta_store.ensure_counters().unwrap();
+        assert_eq!(counts(&fs), vec![("logs/".to_string(), 3)]);
 
-        // Verify object metadata was not created
-        assert!(!fs.key_exists(bucket_name, key).unwrap());
+        fs.bucket_delete("bucket").await.unwrap();
+        fs.create_bucket("bucket").unwrap();
+        assert!(counts(&fs).is_empty());
     }
 
     #[tokio::test]
-    async fn test_store_object() {
+    async fn test_stats() {
         for engine in TEST_ENGINES {
             let (fs, _dir) = setup_test_fs(engine);
-            do_test_store_object(fs).await;
+            do_test_stats(fs).await;
         }
     }
 
-    async fn do_test_store_object(fs: CasFS) {
-        const BUCKET_NAME: &str = "test_bucket";
-        const KEY1: &str = "test_key1";
-        const KEY2: &str = "test_key2";
-        fs.create_bucket(BUCKET_NAME).unwrap();
+    async fn do_test_stats(fs: CasFS) {
+        async fn store(fs: &CasFS, key: &str, data: &'static [u8]) {
+            let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+            fs.store_single_object_and_meta("bucket", key, stream, data.len())
+                .await
+                .unwrap();
+        }
 
-        // Create ByteStream from test data
-        let test_data = b"long test data".repeat(100).to_vec();
-        let test_data_2 = test_data.clone();
-        let test_data_len = test_data.len();
-        let stream = ByteStream::new(stream::once(
-            async move { Ok(Bytes::from(test_data.clone())) },
-        ));
+        let stats = fs.stats().unwrap();
+        assert_eq!(
+            stats,
+            StoreStats {
+                dedup_ratio: 1.0,
+                ..Default::default()
+            }
+        );
 
-        // Store object
-        let obj = fs
-            .store_single_object_and_meta(BUCKET_NAME, KEY1, stream, test_data_len)
-            .await
+        fs.create_bucket("bucket").unwrap();
+        store(&fs, "a", b"shared data").await;
+        store(&fs, "b", b"shared data").await;
+        fs.store_inlined_object("bucket", "c", b"tiny".to_vec())
             .unwrap();
-
-        // Verify results
-        assert_eq!(obj.size(), test_data_len as u64);
-        assert_eq!(obj.blocks().len(), 1);
-
-        // Verify block & path was stored
-        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
-        assert!(block_tree.len().unwrap() > 0);
-        let stored_block = block_tree.get_block(&obj.blocks()[0]).unwrap().unwrap();
-        assert_eq!(stored_block.size(), test_data_len);
-        assert_eq!(stored_block.rc(), 1);
+        let stats = fs.stats().unwrap();
+        assert_eq!(stats.buckets, 1);
+        assert_eq!(stats.objects, 3);
+        assert_eq!(stats.logical_bytes, 26);
+        assert_eq!(stats.blocks, 1);
+        assert_eq!(stats.physical_bytes, 15);
+        assert_eq!(stats.inline_objects, 1);
+        assert_eq!(stats.dedup_ratio, 26.0 / 15.0);
+
+        let bucket_counters = fs.user_meta_store.bucket_counters("bucket").unwrap();
+        assert_eq!(bucket_counters.objects, 3);
+        assert_eq!(bucket_counters.logical_bytes, 26);
+
+        // the counters of a store written before they were maintained are rebuilt
+        let counters = fs.user_meta_store.counters().unwrap();
+        let tree = fs.user_meta_store.get_tree(COUNTERS_TREE).unwrap();
+        tree.remove(b"complete_v3").unwrap();
+        tree.insert(b"objects", 7u64.to_le_bytes().to_vec()).unwrap();
+        tree.insert(b"bucket/bucket/objects", 7u64.to_le_bytes().to_vec())
+            .unwrap();
+        fs.user_meta_store.ensure_counters().unwrap();
+        assert_eq!(fs.user_meta_store.counters().unwrap(), counters);
         assert_eq!(
-            fs.path_tree()
-                .unwrap()
-                .contains_key(stored_block.path())
-                .unwrap(),
-            true
+            fs.user_meta_store.bucket_counters("bucket").unwrap(),
+            bucket_counters
         );
 
-        // Store the same data again with different key
-        // - The same block should be returned
-        // - The refcount should be increased
+        fs.delete_object("bucket", "a").await.unwrap();
+        let stats = fs.stats().unwrap();
+        assert_eq!(stats.objects, 2);
+        assert_eq!(stats.logical_bytes, 15);
+        assert_eq!(stats.blocks, 1);
+
+        fs.delete_object("bucket", "b").await.unwrap();
+        let stats = fs.stats().unwrap();
+        assert_eq!(stats.objects, 1);
+        assert_eq!(stats.blocks, 0);
+        assert_eq!(stats.physical_bytes, 4);
+
+        fs.bucket_delete("bucket").await.unwrap();
+        let stats = fs.stats().unwrap();
+        assert_eq!(stats.buckets, 0);
+        assert_eq!(stats.objects, 0);
+        assert_eq!(stats.inline_objects, 0);
+        assert_eq!(stats.logical_bytes, 0);
+    }
 
-        let stream = ByteStream::new(stream::once(
-            async move { Ok(Bytes::from(test_data_2.clone())) },
-        ));
+    #[derive(Default)]
+    struct RecordingHandler {
+        events: Mutex<Vec<String>>,
+    }
 
-        let new_obj = fs
-            .store_single_object_and_meta(BUCKET_NAME, KEY2, stream, test_data_len)
+    impl ObjectEventHandler for RecordingHandler {
+        fn on_put(&self, bucket: &str, key: &str, object: &Object) {
+            let event = format!("put {bucket}/{key} {}", object.size());
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_delete(&self, bucket: &str, key: &str) {
+            self.events.lock().unwrap().push(format!("delete {bucket}/{key}"));
+        }
+
+        fn on_multipart_complete(&self, bucket: &str, key: &str, object: &Object) {
+            let event = format!("multipart {bucket}/{key} {}", object.size());
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_bucket_delete(&self, bucket: &str) {
+            self.events.lock().unwrap().push(format!("drop {bucket}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_manifest_import() {
+        for engine in TEST_ENGINES {
+            let (source, _source_dir) = setup_test_fs(engine);
+            let (target, _target_dir) = setup_test_fs(engine);
+            source.create_bucket("bucket").unwrap();
+            target.create_bucket("bucket").unwrap();
+
+            let data = Bytes::from(vec![7u8; 3 * BLOCK_SIZE / 2]);
+            let len = data.len();
+            let stream = ByteStream::new(stream::once(async move { Ok(data) }));
+            let obj = source
+                .store_single_object_and_meta("bucket", "key", stream, len)
+                .await
+                .unwrap();
+            let entry = source.manifest_entry("key", &obj).unwrap();
+            assert_eq!(entry.blocks.len(), 2);
+
+            // the block files weren't copied yet
+            assert!(target.import_manifest_entry("bucket", &entry, true).await.is_err());
+            assert!(target.get_object_meta("bucket", "key").unwrap().is_none());
+
+            for (_, block) in entry.blocks().unwrap() {
+                let from = source.block_disk_path(&block).unwrap();
+                let to = target.block_disk_path(&block).unwrap();
+                std::fs::create_dir_all(to.parent().unwrap()).unwrap();
+                std::fs::copy(from, to).unwrap();
+            }
+            let imported = target.import_manifest_entry("bucket", &entry, true).await.unwrap();
+            assert_eq!(imported.hash(), obj.hash());
+            let (_, paths) = target.get_object_paths("bucket", "key").unwrap().unwrap();
+            let mut read = Vec::new();
+            for (path, _) in paths {
+                read.extend(std::fs::read(path).unwrap());
+            }
+            assert_eq!(read.len(), len);
+
+            // a second copy references the same blocks
+            let mut copy = entry.clone();
+            copy.key = "copy".to_string();
+            target.import_manifest_entry("bucket", &copy, false).await.unwrap();
+            let block_tree = target.block_tree().unwrap();
+            for id in obj.blocks() {
+                assert_eq!(block_tree.get_block(id).unwrap().unwrap().rc(), 2);
+            }
+            target.delete_object("bucket", "key").await.unwrap();
+            target.delete_object("bucket", "copy").await.unwrap();
+            assert!(block_tree.get_block(&obj.blocks()[0]).unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_event_handlers() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            let handler = Arc::new(RecordingHandler::default());
+            do_test_event_handlers(fs.with_event_handler(handler.clone())).await;
+            assert_eq!(
+                *handler.events.lock().unwrap(),
+                vec![
+                    "put bucket/a 11",
+                    "put bucket/b 4",
+                    "multipart bucket/c 0",
+                    "put bucket/d 11",
+                    "delete bucket/a",
+                    "drop bucket",
+                ]
+            );
+        }
+    }
+
+    async fn do_test_event_handlers(fs: CasFS) {
+        fs.create_bucket("bucket").unwrap();
+        let stream = ByteStream::new(stream::once(async { Ok(Bytes::from("shared data")) }));
+        let a = fs
+            .store_single_object_and_meta("bucket", "a", stream, 11)
+            .await
+            .unwrap();
+        fs.store_inlined_object("bucket", "b", b"tiny".to_vec())
+            .unwrap();
+        // an upload of one empty part, it has no blocks to reference
+        let data = ObjectData::MultiPart {
+            blocks: Vec::new(),
+            parts: 1,
+        };
+        fs.create_object_meta(
+            "bucket",
+            "c",
+            0,
+            [0; 16],
+            *a.e_tag(),
+            data,
+            &ObjectAttributes::default(),
+        )
+        .unwrap();
+        fs.concat_objects("bucket", "d", &["a".to_string(), "c".to_string()])
             .await
             .unwrap();
 
-        assert_eq!(new_obj.blocks(), obj.blocks());
-
-        let stored_block = block_tree.get_block(&new_obj.blocks()[0]).unwrap().unwrap();
-        assert_eq!(stored_block.rc(), 2);
+        fs.delete_object("bucket", "a").await.unwrap();
+        // nothing to delete, no event
+        fs.delete_object("bucket", "a").await.unwrap();
+        fs.bucket_delete("bucket").await.unwrap();
     }
 
     #[tokio::test]
-    async fn test_store_inlined_object() {
+    async fn test_meta_cache_invalidated_on_write() {
         for engine in TEST_ENGINES {
             let (fs, _dir) = setup_test_fs(engine);
-            do_test_store_inlined_object(fs).await;
+            do_test_meta_cache_invalidated_on_write(fs.with_meta_cache(16)).await;
         }
     }
 
-    async fn do_test_store_inlined_object(fs: CasFS) {
-        let bucket_name = "test_bucket";
-        let key = "test_key1";
-        fs.create_bucket(bucket_name).unwrap();
+    async fn do_test_meta_cache_invalidated_on_write(fs: CasFS) {
+        let bucket = "test-bucket";
+        let key = "cached";
+        fs.create_bucket(bucket).unwrap();
 
-        let small_data = b"small test data".to_vec();
-        let obj_meta = fs
-            .store_inlined_object(bucket_name, key, small_data.clone())
-            .unwrap();
+        fs.store_inlined_object(bucket, key, b"first".to_vec()).unwrap();
+        assert_eq!(fs.get_object_meta(bucket, key).unwrap().unwrap().size(), 5);
+        // served from the cache
+        assert_eq!(fs.get_object_meta(bucket, key).unwrap().unwrap().size(), 5);
+        assert_eq!(fs.meta_cache.as_ref().unwrap().len(), 1);
 
-        // Verify inlined data
-        assert_eq!(obj_meta.size(), small_data.len() as u64);
-        assert_eq!(obj_meta.inlined().unwrap(), &small_data);
+        fs.store_inlined_object(bucket, key, b"second".to_vec()).unwrap();
+        assert_eq!(fs.get_object_meta(bucket, key).unwrap().unwrap().size(), 6);
+
+        fs.delete_object(bucket, key).await.unwrap();
+        assert!(fs.get_object_meta(bucket, key).unwrap().is_none());
     }
 
     #[tokio::test]
-    async fn test_store_object_refcount() {
+    async fn test_bucket_delete_releases_blocks() {
         for engine in TEST_ENGINES {
             let (fs, _dir) = setup_test_fs(engine);
-            do_test_store_object_refcount(fs).await;
+            do_test_bucket_delete_releases_blocks(fs).await;
         }
     }
 
-    async fn do_test_store_object_refcount(fs: CasFS) {
-        let bucket_name = "test_bucket";
-        let key1 = "test_key1";
-        let key2 = "test_key2";
-        fs.create_bucket(bucket_name).unwrap();
+    async fn do_test_bucket_delete_releases_blocks(fs: CasFS) {
+        async fn store(fs: &CasFS, bucket: &str, key: &str, data: &'static [u8]) -> Object {
+            let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+            fs.store_single_object_and_meta(bucket, key, stream, data.len())
+                .await
+                .unwrap()
+        }
 
-        // Create ByteStream from test data
-        let test_data = b"long test data".repeat(100).to_vec();
-        let test_data_len = test_data.len();
-        let test_data_2 = test_data.clone();
-        let test_data_3 = test_data.clone();
-        let stream = ByteStream::new(stream::once(
-            async move { Ok(Bytes::from(test_data.clone())) },
-        ));
+        fs.create_bucket("dropped").unwrap();
+        fs.create_bucket("kept").unwrap();
 
-        // Store object
-        let obj = fs
-            .store_single_object_and_meta(bucket_name, key1, stream, test_data_len)
-            .await
+        // more objects than fit in a single batch, all sharing one block
+        let shared = store(&fs, "kept", "shared", b"shared data").await.blocks()[0];
+        for i in 0..1005 {
+            store(&fs, "dropped", &format!("obj-{i:04}"), b"shared data").await;
+        }
+        let unique = store(&fs, "dropped", "unique", b"unique data").await;
+        let unique = unique.blocks()[0];
+        fs.set_object_acl("dropped", "unique", Some(CannedAcl::PublicRead))
             .unwrap();
 
-        // Initial refcount must be 1
-        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
-        for id in obj.blocks() {
-            let block = block_tree.get_block(id).unwrap().unwrap();
-            assert_eq!(block.rc(), 1);
+        let block_tree = fs.block_tree().unwrap();
+        assert_eq!(block_tree.get_block(&shared).unwrap().unwrap().rc(), 1006);
+        let unique_path = block_tree.get_block(&unique).unwrap().unwrap().disk_path(fs.root.clone());
+        assert!(unique_path.exists());
+
+        fs.bucket_delete("dropped").await.unwrap();
+
+        // only the references of the dropped bucket are released
+        assert_eq!(block_tree.get_block(&shared).unwrap().unwrap().rc(), 1);
+        assert!(block_tree.get_block(&unique).unwrap().is_none());
+        assert!(!unique_path.exists());
+        let (_, paths) = fs.get_object_paths("kept", "shared").unwrap().unwrap();
+        assert!(paths[0].0.exists());
+
+        // a new bucket with the same name starts empty
+        fs.create_bucket("dropped").unwrap();
+        assert!(fs.get_object_meta("dropped", "unique").unwrap().is_none());
+        assert_eq!(fs.object_acl("dropped", "unique").unwrap(), CannedAcl::Private);
+    }
+
+    #[tokio::test]
+    async fn test_acl_inheritance() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_acl_inheritance(fs).await;
         }
+    }
 
-        {
-            // Test using  the same key
-            // Refcount must not be increased
+    async fn do_test_acl_inheritance(fs: CasFS) {
+        let bucket = "test-bucket";
+        fs.create_bucket(bucket).unwrap();
+        fs.store_inlined_object(bucket, "a", b"a".to_vec()).unwrap();
+        fs.store_inlined_object(bucket, "b", b"b".to_vec()).unwrap();
 
-            let stream =
-                ByteStream::new(stream::once(
-                    async move { Ok(Bytes::from(test_data_2.clone())) },
-                ));
+        // everything is private by default
+        assert_eq!(fs.bucket_acl(bucket).unwrap(), CannedAcl::Private);
+        assert_eq!(fs.object_acl(bucket, "a").unwrap(), CannedAcl::Private);
 
-            let new_obj = fs
-                .store_single_object_and_meta(bucket_name, key1, stream, test_data_len)
-                .await
-                .unwrap();
+        // objects without an ACL follow the bucket
+        fs.set_bucket_acl(bucket, CannedAcl::PublicRead).unwrap();
+        fs.set_object_acl(bucket, "b", Some(CannedAcl::Private)).unwrap();
+        assert_eq!(fs.object_acl(bucket, "a").unwrap(), CannedAcl::PublicRead);
+        assert_eq!(fs.object_acl(bucket, "b").unwrap(), CannedAcl::Private);
 
-            assert_eq!(new_obj.blocks(), obj.blocks());
+        // a deleted object doesn't leave its ACL behind
+        fs.delete_object(bucket, "b").await.unwrap();
+        assert_eq!(fs.object_acl(bucket, "b").unwrap(), CannedAcl::PublicRead);
 
-            let stored_block = block_tree.get_block(&new_obj.blocks()[0]).unwrap().unwrap();
-            assert_eq!(stored_block.rc(), 1);
-        }
-        {
-            // Test  using a new key
-            // Refcount must be increased
-            let stream =
-                ByteStream::new(stream::once(
-                    async move { Ok(Bytes::from(test_data_3.clone())) },
-                ));
+        fs.bucket_delete(bucket).await.unwrap();
+        fs.create_bucket(bucket).unwrap();
+        assert_eq!(fs.bucket_acl(bucket).unwrap(), CannedAcl::Private);
+    }
 
-            let new_obj = fs
-                .store_single_object_and_meta(bucket_name, key2, stream, test_data_len)
+    #[tokio::test]
+    async fn test_delete_grace() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            let fs = fs.with_delete_grace(Duration::from_secs(3600));
+            fs.create_bucket("bucket").unwrap();
+
+            let mut files = Vec::new();
+            for (key, data) in [("a", &b"kept data"[..]), ("b", &b"purged data"[..])] {
+                let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+                let obj = fs
+                    .store_single_object_and_meta("bucket", key, stream, data.len())
+                    .await
+                    .unwrap();
+                let block = fs.block_tree.get_block(&obj.blocks()[0]).unwrap().unwrap();
+                files.push((fs.block_disk_path(&block).unwrap(), block.path().to_vec()));
+            }
+            let obj = fs.get_object_meta("bucket", "a").unwrap().unwrap();
+            let entry = fs.manifest_entry("a", &obj).unwrap();
+
+            fs.delete_object("bucket", "a").await.unwrap();
+            fs.delete_object("bucket", "b").await.unwrap();
+            assert!(files.iter().all(|(path, _)| path.exists()));
+            let stats = fs.delete_queue_stats().unwrap();
+            assert_eq!((stats.blocks, stats.bytes), (2, 20));
+            assert_eq!(fs.purge_delete_queue(10).await.unwrap(), 0);
+
+            // restoring an object within the grace keeps its block file
+            fs.import_manifest_entry("bucket", &entry, true)
                 .await
                 .unwrap();
+            assert_eq!(fs.purge_delete_queue_at(u64::MAX, 10).await.unwrap(), 2);
+            assert!(files[0].0.exists());
+            assert!(!files[1].0.exists());
+            assert!(!fs.path_tree().unwrap().contains_key(&files[1].1).unwrap());
+            assert_eq!(fs.delete_queue_stats().unwrap().blocks, 0);
+            assert!(fs.get_object_paths("bucket", "a").unwrap().is_some());
+
+            // a block file which is gone already, e.g. removed before a restart,
+            // is taken off the queue
+            let data: &'static [u8] = b"removed data";
+            let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+            let obj = fs
+                .store_single_object_and_meta("bucket", "c", stream, data.len())
+                .await
+                .unwrap();
+            let block = fs.block_tree.get_block(&obj.blocks()[0]).unwrap().unwrap();
+            fs.delete_object("bucket", "c").await.unwrap();
+            std::fs::remove_file(fs.block_disk_path(&block).unwrap()).unwrap();
+            assert_eq!(fs.purge_delete_queue_at(u64::MAX, 10).await.unwrap(), 1);
+            assert_eq!(fs.delete_queue_stats().unwrap().blocks, 0);
+            assert!(!fs.path_tree().unwrap().contains_key(block.path()).unwrap());
+        }
+    }
 
-            assert_eq!(new_obj.blocks(), obj.blocks());
+    #[tokio::test]
+    async fn test_relocate_block() {
+        for engine in TEST_ENGINES {
+            let (fs, dir) = setup_test_fs(engine);
+            let mut placement = Placement::default();
+            placement.add_location("new", dir.path().join("new"));
+            let fs = fs.with_placement(placement);
+
+            fs.create_bucket("bucket").unwrap();
+            let data: &'static [u8] = b"relocated data";
+            let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+            let obj = fs
+                .store_single_object_and_meta("bucket", "a", stream, data.len())
+                .await
+                .unwrap();
+            let id = obj.blocks()[0];
+            let block = fs.block_tree.get_block(&id).unwrap().unwrap();
+            let old_path = fs.block_disk_path(&block).unwrap();
 
-            let stored_block = block_tree.get_block(&new_obj.blocks()[0]).unwrap().unwrap();
-            assert_eq!(stored_block.rc(), 2);
+            assert_eq!(fs.relocate_block(&id, Some("new")).await.unwrap(), data.len());
+            let block = fs.block_tree.get_block(&id).unwrap().unwrap();
+            assert_eq!(block.location(), Some("new"));
+            assert_eq!(block.rc(), 1);
+            let new_path = fs.block_disk_path(&block).unwrap();
+            assert!(new_path.starts_with(dir.path().join("new")));
+            assert_eq!(std::fs::read(&new_path).unwrap(), data);
+            assert!(!old_path.exists());
+
+            // a block already in the location is left alone
+            assert_eq!(fs.relocate_block(&id, Some("new")).await.unwrap(), 0);
+            assert_eq!(fs.relocate_block(&id, None).await.unwrap(), data.len());
+            assert_eq!(std::fs::read(&old_path).unwrap(), data);
+            assert!(!new_path.exists());
         }
     }
 
     #[tokio::test]
-    async fn test_store_and_delete_object() {
+    async fn test_bucket_encryption() {
         for engine in TEST_ENGINES {
             let (fs, _dir) = setup_test_fs(engine);
-            do_test_store_and_delete_object(fs).await;
+            let bucket = "test-bucket";
+            fs.create_bucket(bucket).unwrap();
+            assert_eq!(fs.bucket_encryption(bucket).unwrap(), None);
+
+            fs.set_bucket_encryption(bucket, Some("AES256")).unwrap();
+            assert_eq!(fs.bucket_encryption(bucket).unwrap().as_deref(), Some("AES256"));
+            fs.set_bucket_encryption(bucket, None).unwrap();
+            assert_eq!(fs.bucket_encryption(bucket).unwrap(), None);
+
+            // a recreated bucket doesn't inherit the configuration
+            fs.set_bucket_encryption(bucket, Some("AES256")).unwrap();
+            fs.bucket_delete(bucket).await.unwrap();
+            fs.create_bucket(bucket).unwrap();
+            assert_eq!(fs.bucket_encryption(bucket).unwrap(), None);
         }
     }
 
-    // test store and delete object
-    // - store an object
-    // - delete the object
-    async fn do_test_store_and_delete_object(fs: CasFS) {
-        let bucket_name = "test-bucket";
-        let key = "test/key";
-
-        // Create bucket
-        fs.create_bucket(bucket_name).unwrap();
+    #[tokio::test]
+    async fn test_bucket_lifecycle() {
+        let (fs, _dir) = setup_test_fs(StorageEngine::Fjall);
+        let bucket = "test-bucket";
+        fs.create_bucket(bucket).unwrap();
+        fs.store_inlined_object(bucket, "tmp/a", b"a".to_vec())
+            .unwrap();
+        let mtime = UNIX_EPOCH;
+        assert_eq!(fs.object_expiration(bucket, "tmp/a", mtime).unwrap(), None);
+
+        let rule = |id: &str, tags: Vec<TagFilter>| LifecycleRule {
+            id: id.to_string(),
+            enabled: true,
+            prefix: "tmp/".to_string(),
+            tags,
+            expiration: Expiration::Days(1),
+        };
+        let lifecycle =
+            BucketLifecycle::new(vec![rule("tagged", vec![TagFilter::new("keep", "false")])])
+                .unwrap();
+        fs.set_bucket_lifecycle(bucket, Some(&lifecycle)).unwrap();
+        assert_eq!(fs.bucket_lifecycle(bucket).unwrap(), Some(lifecycle));
+        // the rule only applies to objects with the tag
+        assert_eq!(fs.object_expiration(bucket, "tmp/a", mtime).unwrap(), None);
+        let tags = ObjectTags::new(vec![("keep".to_string(), "false".to_string())]).unwrap();
+        fs.set_object_tags(bucket, "tmp/a", &tags).unwrap();
+        let expiration = fs
+            .object_expiration(bucket, "tmp/a", mtime)
+            .unwrap()
+            .unwrap();
+        assert_eq!(expiration.rule_id, "tagged");
+        assert_eq!(expiration.expiry_date, 2 * 24 * 60 * 60);
 
-        // Create test data and stream
-        let test_data = b"test data".to_vec();
-        let test_data_len = test_data.len();
-        let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data)) }));
+        let logging = BucketLogging {
+            target_bucket: "logs".to_string(),
+            target_prefix: "test-bucket/".to_string(),
+        };
+        fs.set_bucket_logging(bucket, Some(&logging)).unwrap();
+        assert_eq!(fs.bucket_logging(bucket).unwrap(), Some(logging));
+        fs.set_requester_pays(bucket, true).unwrap();
+        assert!(fs.requester_pays(bucket).unwrap());
 
-        // Store object
-        let obj = fs
-            .store_single_object_and_meta(bucket_name, key, stream, test_data_len)
-            .await
-            .unwrap();
+        // a recreated bucket doesn't inherit the configuration
+        fs.bucket_delete(bucket).await.unwrap();
+        fs.create_bucket(bucket).unwrap();
+        assert_eq!(fs.bucket_lifecycle(bucket).unwrap(), None);
+        assert_eq!(fs.bucket_logging(bucket).unwrap(), None);
+        assert!(!fs.requester_pays(bucket).unwrap());
+    }
 
-        // Verify object exists
-        let exists = fs.key_exists(bucket_name, key).unwrap();
-        assert_eq!(exists, true);
+    #[tokio::test]
+    async fn test_object_tags() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_object_tags(fs).await;
+        }
+    }
 
-        // verify blocks and path exist
-        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
-        let mut stored_paths = Vec::new();
-        for id in obj.blocks() {
-            let block = block_tree.get_block(id).unwrap().unwrap();
-            assert_eq!(
-                fs.path_tree().unwrap().contains_key(block.path()).unwrap(),
-                true
-            );
-            stored_paths.push(block.path().to_vec());
+    async fn do_test_object_tags(fs: CasFS) {
+        let bucket = "test-bucket";
+        fs.create_bucket(bucket).unwrap();
+        for key in ["a", "b", "c"] {
+            fs.store_inlined_object(bucket, key, key.as_bytes().to_vec())
+                .unwrap();
         }
+        let tags = |tags: &[(&str, &str)]| {
+            ObjectTags::new(
+                tags.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            )
+            .unwrap()
+        };
+        let tmp = TagFilter::new("tmp", "true");
 
-        // Delete object
-        fs.delete_object(bucket_name, key).await.unwrap();
+        fs.set_object_tags(bucket, "a", &tags(&[("tmp", "true")]))
+            .unwrap();
+        fs.set_object_tags(bucket, "b", &tags(&[("tmp", "true"), ("team", "x")]))
+            .unwrap();
+        fs.set_object_tags(bucket, "c", &tags(&[("tmp", "false")]))
+            .unwrap();
+        assert_eq!(fs.tagged_keys(bucket, &tmp).unwrap(), vec!["a", "b"]);
+        assert_eq!(fs.object_tags(bucket, "b").unwrap().get("team"), Some("x"));
 
-        // Verify object no longer exists
-        let exists = fs.key_exists(bucket_name, key).unwrap();
-        assert_eq!(exists, false);
+        // replacing the tags updates the index
+        fs.set_object_tags(bucket, "a", &tags(&[("tmp", "false")]))
+            .unwrap();
+        assert_eq!(fs.tagged_keys(bucket, &tmp).unwrap(), vec!["b"]);
 
-        // Verify blocks were cleaned up
-        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
-        for id in obj.blocks() {
-            assert!(block_tree.get_block(id).unwrap().is_none());
-        }
-        // Verify paths were cleaned up
-        for path in stored_paths {
-            assert_eq!(fs.path_tree().unwrap().contains_key(&path).unwrap(), false);
-        }
+        // a deleted object doesn't leave its tags behind
+        fs.delete_object(bucket, "b").await.unwrap();
+        assert!(fs.tagged_keys(bucket, &tmp).unwrap().is_empty());
+        assert!(fs.object_tags(bucket, "b").unwrap().is_empty());
+        assert_eq!(
+            fs.tagged_keys(bucket, &TagFilter::new("tmp", "false"))
+                .unwrap(),
+            vec!["a", "c"]
+        );
     }
 
     #[tokio::test]
-    async fn test_store_and_delete_object_with_refcount_same_blocks_diffkey() {
+    async fn test_rename_prefix() {
         for engine in TEST_ENGINES {
             let (fs, _dir) = setup_test_fs(engine);
-            do_test_store_and_delete_object_with_refcount_same_blocks_diffkey(fs).await;
+            do_test_rename_prefix(fs).await;
         }
     }
 
-    // Test storing and deleting an object with refcount
-    // - store object
-    //       refcount == 1
-    // - store object again with differrent key
-    //      refcount == 2
-    // - delete the first object
-    // - check block/disk/whatever is still there
-    // - delete the second object
-    // - check block/disk/whatever should be gone
-    async fn do_test_store_and_delete_object_with_refcount_same_blocks_diffkey(fs: CasFS) {
+    async fn do_test_rename_prefix(fs: CasFS) {
         let bucket = "test-bucket";
-        let key1 = "test/key1";
-        let key2 = "test/key2";
-
-        // Create bucket
         fs.create_bucket(bucket).unwrap();
+        for key in ["logs/2023/a", "logs/2023/b", "logs/2024/a", "other"] {
+            fs.store_inlined_object(bucket, key, key.as_bytes().to_vec())
+                .unwrap();
+        }
+        fs.set_object_acl(bucket, "logs/2023/a", Some(CannedAcl::PublicRead))
+            .unwrap();
+        let tags = ObjectTags::new(vec![("tmp".to_string(), "true".to_string())]).unwrap();
+        fs.set_object_tags(bucket, "logs/2023/b", &tags).unwrap();
 
-        // Create test data
-        let test_data = b"test data".to_vec();
-        let test_data_len = test_data.len();
-        let test_data2 = test_data.clone();
-        let stream1 = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data)) }));
+        // overlapping prefixes are refused
+        assert!(matches!(
+            fs.rename_prefix_batch(bucket, "logs/", "logs/old/").await,
+            Err(MetaError::InvalidArgument(_))
+        ));
 
-        // Store first object
-        let obj1 = fs
-            .store_single_object_and_meta(bucket, key1, stream1, test_data_len)
+        assert_eq!(fs.count_prefix(bucket, "logs/2023/").unwrap(), 2);
+        let renamed = fs
+            .rename_prefix_batch(bucket, "logs/2023/", "archive/2023/")
             .await
             .unwrap();
-        // Verify blocks  exist with rc=1
-        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
-        for id in obj1.blocks() {
-            let block = block_tree.get_block(id).unwrap().unwrap();
-            assert_eq!(block.rc(), 1);
-        }
+        assert_eq!(renamed, 2);
+        assert_eq!(
+            fs.rename_prefix_batch(bucket, "logs/2023/", "archive/2023/")
+                .await
+                .unwrap(),
+            0
+        );
 
-        // Store same data with different key
+        assert!(!fs.key_exists(bucket, "logs/2023/a").unwrap());
+        let obj = fs
+            .get_object_meta(bucket, "archive/2023/a")
+            .unwrap()
+            .unwrap();
+        assert_eq!(obj.inlined().unwrap(), b"logs/2023/a");
+        assert_eq!(
+            fs.object_acl(bucket, "archive/2023/a").unwrap(),
+            CannedAcl::PublicRead
+        );
+        assert_eq!(
+            fs.object_acl(bucket, "logs/2023/a").unwrap(),
+            CannedAcl::Private
+        );
+        assert_eq!(
+            fs.tagged_keys(bucket, &TagFilter::new("tmp", "true"))
+                .unwrap(),
+            vec!["archive/2023/b"]
+        );
+        assert!(fs.object_tags(bucket, "logs/2023/b").unwrap().is_empty());
+
+        // the objects are counted under their new directory
+        let counts: Vec<(String, u64)> = fs
+            .prefix_counts(bucket)
+            .unwrap()
+            .into_iter()
+            .map(|count| (count.prefix, count.objects))
+            .collect();
+        assert_eq!(
+            counts,
+            [("archive/".to_string(), 2), ("logs/".to_string(), 1)]
+        );
+        assert_eq!(fs.bucket_counters(bucket).unwrap().objects, 4);
 
-        let stream2 = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data2)) }));
+        // a taken key fails the batch before anything is moved
+        fs.store_inlined_object(bucket, "archive/2024/a", b"taken".to_vec())
+            .unwrap();
+        assert!(matches!(
+            fs.rename_prefix_batch(bucket, "logs/2024/", "archive/2024/")
+                .await,
+            Err(MetaError::InvalidArgument(_))
+        ));
+        assert!(fs.key_exists(bucket, "logs/2024/a").unwrap());
 
-        let obj2 = fs
-            .store_single_object_and_meta(bucket, key2, stream2, test_data_len)
-            .await
+        // objects deleted since their batch was listed are skipped
+        let renames = fs
+            .user_meta_store
+            .prefix_renames(bucket, "logs/2024/", "moved/2024/")
             .unwrap();
+        fs.delete_object(bucket, "logs/2024/a").await.unwrap();
+        assert!(fs
+            .user_meta_store
+            .rename_objects(bucket, &renames)
+            .unwrap()
+            .is_empty());
+        assert!(!fs.key_exists(bucket, "moved/2024/a").unwrap());
+    }
 
-        // Verify both objects share same blocks
-        assert_eq!(obj1.blocks(), obj2.blocks());
-        assert_eq!(obj1.hash(), obj2.hash());
-        // Verify blocks  exist with rc=2
-        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
-        for id in obj2.blocks() {
-            let block = block_tree.get_block(id).unwrap().unwrap();
-            assert_eq!(block.rc(), 2);
+    #[tokio::test]
+    async fn test_concurrent_overwrite_same_key() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_concurrent_overwrite_same_key(fs).await;
         }
+    }
 
-        // Delete first object
-        fs.delete_object(bucket, key1).await.unwrap();
+    // Concurrent PUTs of the same data to the same key must not increment the
+    // refcount once per writer, otherwise the block leaks after deletion.
+    async fn do_test_concurrent_overwrite_same_key(fs: CasFS) {
+        let bucket = "test-bucket";
+        let key = "race/key";
+        fs.create_bucket(bucket).unwrap();
+
+        let test_data = b"racing data".repeat(10);
+        let test_data_len = test_data.len();
+        let puts = (0..8).map(|_| {
+            let data = test_data.clone();
+            let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+            fs.store_single_object_and_meta(bucket, key, stream, test_data_len)
+        });
+        let objects = futures::future::join_all(puts).await;
+        let obj = objects.into_iter().last().unwrap().unwrap();
 
-        // Verify blocks still exist
         let block_tree = fs.user_meta_store.get_block_tree().unwrap();
-        for id in obj1.blocks() {
-            let block = block_tree.get_block(id).unwrap().unwrap();
-            assert_eq!(block.rc(), 1);
+        for id in obj.blocks() {
+            assert_eq!(block_tree.get_block(id).unwrap().unwrap().rc(), 1);
         }
 
-        // Delete second object
-        fs.delete_object(bucket, key2).await.unwrap();
-
-        // Verify blocks are gone
-        for id in obj1.blocks() {
+        fs.delete_object(bucket, key).await.unwrap();
+        for id in obj.blocks() {
             assert!(block_tree.get_block(id).unwrap().is_none());
         }
     }
 
     #[tokio::test]
-    async fn test_store_and_delete_object_with_refcount_same_blocks_samekey() {
+    async fn test_concurrent_put_and_delete_same_key() {
         for engine in TEST_ENGINES {
             let (fs, _dir) = setup_test_fs(engine);
-            do_test_store_and_delete_object_with_refcount_same_blocks_samekey(fs).await;
+            do_test_concurrent_put_and_delete_same_key(fs).await;
         }
     }
 
-    // Test storing and deleting an object with refcount
-    // - store object
-    //       refcount == 1
-    // - store object again with differrent key
-    //      refcount == 1
-    // - delete the object
-    // - check block/disk/whatever should be gone
-    async fn do_test_store_and_delete_object_with_refcount_same_blocks_samekey(fs: CasFS) {
+    // Whatever order a PUT and a DELETE of the same key end up in, the block
+    // refcount must match the object metadata.
+    async fn do_test_concurrent_put_and_delete_same_key(fs: CasFS) {
         let bucket = "test-bucket";
-        let key1 = "test/key1";
-
-        // Create bucket
+        let key = "race/key";
         fs.create_bucket(bucket).unwrap();
 
-        // Create test data
-        let test_data = b"test data".to_vec();
+        let test_data = b"racing data".repeat(10);
         let test_data_len = test_data.len();
-        let test_data2 = test_data.clone();
-        let stream1 = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data)) }));
+        let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data)) }));
+
+        let (put, delete) = futures::join!(
+            fs.store_single_object_and_meta(bucket, key, stream, test_data_len),
+            fs.delete_object(bucket, key)
+        );
+        let obj = put.unwrap();
+        delete.unwrap();
 
-        // Store first object
-        let obj1 = fs
-            .store_single_object_and_meta(bucket, key1, stream1, test_data_len)
-            .await
-            .unwrap();
-        // Verify blocks  exist with rc=1
         let block_tree = fs.user_meta_store.get_block_tree().unwrap();
-        for id in obj1.blocks() {
-            let block = block_tree.get_block(id).unwrap().unwrap();
-            assert_eq!(block.rc(), 1);
+        let exists = fs.key_exists(bucket, key).unwrap();
+        for id in obj.blocks() {
+            match block_tree.get_block(id).unwrap() {
+                Some(block) => {
+                    assert!(exists);
+                    assert_eq!(block.rc(), 1);
+                }
+                None => assert!(!exists),
+            }
         }
+    }
 
-        // Store same data with same key
+    #[tokio::test]
+    async fn test_delete_while_reading_defers_block_removal() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_delete_while_reading_defers_block_removal(fs).await;
+        }
+    }
 
-        let stream2 = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data2)) }));
+    async fn do_test_delete_while_reading_defers_block_removal(fs: CasFS) {
+        let bucket = "test-bucket";
+        let key = "pinned/key";
+        fs.create_bucket(bucket).unwrap();
 
-        let obj2 = fs
-            .store_single_object_and_meta(bucket, key1, stream2, test_data_len)
+        let test_data = b"pinned data".repeat(10);
+        let test_data_len = test_data.len();
+        let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(test_data)) }));
+        fs.store_single_object_and_meta(bucket, key, stream, test_data_len)
             .await
             .unwrap();
 
-        // Verify both objects share same blocks
-        assert_eq!(obj1.blocks(), obj2.blocks());
-        assert_eq!(obj1.hash(), obj2.hash());
-        // Verify blocks  exist with rc=1
-        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
-        for id in obj2.blocks() {
-            let block = block_tree.get_block(id).unwrap().unwrap();
-            assert_eq!(block.rc(), 1);
+        let (_, paths, pin) = fs.get_object_paths_pinned(bucket, key).unwrap().unwrap();
+        let (disk_path, _) = paths[0].clone();
+
+        // The object is gone, but the block file must survive while it is pinned
+        fs.delete_object(bucket, key).await.unwrap();
+        assert!(!fs.key_exists(bucket, key).unwrap());
+        assert!(disk_path.exists());
+        // the removal is queued, so it isn't lost on a restart, and the queue
+        // leaves the file of a pinned block alone
+        assert_eq!(fs.delete_queue_stats().unwrap().blocks, 1);
+        assert_eq!(fs.purge_delete_queue_at(u64::MAX, 10).await.unwrap(), 0);
+        assert!(disk_path.exists());
+
+        // Releasing the last pin removes the file, and takes it off the queue
+        drop(pin);
+        assert!(!disk_path.exists());
+        assert_eq!(fs.delete_queue_stats().unwrap().blocks, 0);
+        assert!(fs.get_object_paths_pinned(bucket, key).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_multipart_parts() {
+        for engine in TEST_ENGINES {
+            let (fs, _dir) = setup_test_fs(engine);
+            do_test_multipart_parts(fs).await;
         }
+    }
 
-        // Delete object
-        fs.delete_object(bucket, key1).await.unwrap();
+    async fn do_test_multipart_parts(fs: CasFS) {
+        const BUCKET_NAME: &str = "test_bucket";
+        const KEY: &str = "test_key";
+        const UPLOAD_ID: &str = "upload";
+        fs.create_bucket(BUCKET_NAME).unwrap();
 
-        // Verify blocks are gone
-        for id in obj1.blocks() {
-            assert!(block_tree.get_block(id).unwrap().is_none());
+        let upload_part = |part_number: i64, data: Vec<u8>| {
+            let fs = &fs;
+            async move {
+                let stream = ByteStream::new(stream::once(async move { Ok(Bytes::from(data)) }));
+                let (blocks, _, e_tag, size) =
+                    fs.store_part(BUCKET_NAME, KEY, stream).await.unwrap();
+                fs.insert_multipart_part(
+                    BUCKET_NAME.to_string(),
+                    KEY.to_string(),
+                    size as usize,
+                    part_number,
+                    UPLOAD_ID.to_string(),
+                    e_tag,
+                    blocks.clone(),
+                )
+                .await
+                .unwrap();
+                blocks
+            }
+        };
+
+        // concurrent uploads of the parts of an upload don't lose any part
+        let uploads =
+            (1..=8).map(|n| upload_part(n, format!("part {}", n).repeat(100).into_bytes()));
+        futures::future::join_all(uploads).await;
+        for n in 1..=8 {
+            let part = fs
+                .get_multipart_part(BUCKET_NAME, KEY, UPLOAD_ID, n)
+                .unwrap()
+                .unwrap();
+            assert_eq!(part.part_number(), n);
+            assert_eq!(part.size(), format!("part {}", n).len() * 100);
         }
+        // parts are only found for their own object
+        assert!(fs
+            .get_multipart_part(BUCKET_NAME, "other", UPLOAD_ID, 1)
+            .unwrap()
+            .is_none());
+
+        // uploading a part again replaces it and releases its blocks
+        let old_blocks = fs
+            .get_multipart_part(BUCKET_NAME, KEY, UPLOAD_ID, 1)
+            .unwrap()
+            .unwrap()
+            .blocks()
+            .to_vec();
+        let new_blocks = upload_part(1, b"new part 1".repeat(100)).await;
+        let part = fs
+            .get_multipart_part(BUCKET_NAME, KEY, UPLOAD_ID, 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(part.blocks(), new_blocks.as_slice());
+        assert!(!fs.is_known_block(&old_blocks[0]).unwrap());
+        assert!(fs.is_known_block(&new_blocks[0]).unwrap());
+
+        // the same data again keeps the blocks, with one reference
+        upload_part(1, b"new part 1".repeat(100)).await;
+        let block_tree = fs.user_meta_store.get_block_tree().unwrap();
+        assert_eq!(block_tree.get_block(&new_blocks[0]).unwrap().unwrap().rc(), 1);
+
+        // another object can't take over the parts of the upload
+        let stream = ByteStream::new(stream::once(async { Ok(Bytes::from_static(b"other")) }));
+        let (blocks, _, e_tag, size) = fs.store_part(BUCKET_NAME, "other", stream).await.unwrap();
+        assert!(fs
+            .insert_multipart_part(
+                BUCKET_NAME.to_string(),
+                "other".to_string(),
+                size as usize,
+                1,
+                UPLOAD_ID.to_string(),
+                e_tag,
+                blocks,
+            )
+            .await
+            .is_err());
+
+        fs.remove_multipart_part(BUCKET_NAME, KEY, UPLOAD_ID, 1).unwrap();
+        assert!(fs
+            .get_multipart_part(BUCKET_NAME, KEY, UPLOAD_ID, 1)
+            .unwrap()
+            .is_none());
     }
 }
@@ -0,0 +1,267 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+use crate::metastore::{Durability, Store};
+use crate::metrics::SharedMetrics;
+
+/// Configuration of a [`GroupSync`]
+#[derive(Debug, Clone)]
+pub struct GroupSyncConfig {
+    /// Size of the written block files at which they are synced without waiting
+    /// for `max_delay`
+    pub max_bytes: u64,
+    /// Longest time between two syncs, which bounds the writes lost on a crash
+    pub max_delay: Duration,
+    /// Durability of the syncs, `Buffer` is taken as `Fdatasync`
+    pub durability: Durability,
+}
+
+impl Default for GroupSyncConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 8 << 20,
+            max_delay: Duration::from_millis(10),
+            durability: Durability::Fdatasync,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct PendingFiles {
+    files: Vec<PathBuf>,
+    // the directories holding the files, up to the storage root, so new
+    // directories are synced as well
+    dirs: BTreeSet<PathBuf>,
+    bytes: u64,
+}
+
+/// Syncs the block files and the metadata stores written by many requests
+/// together (group commit), instead of once per write.
+///
+/// Writes are acknowledged before they are synced. The files written meanwhile
+/// and the journals of the registered stores, opened with `Durability::Buffer`,
+/// are synced every `max_delay`, or as soon as `max_bytes` of block files are
+/// waiting. A crash then loses at most the writes of the last `max_delay`, while
+/// many small writes share a single fsync.
+///
+/// The block files are synced before the stores, so the metadata of a synced
+/// object never refers to a block file which isn't. The stores are persisted in
+/// the order they were registered, the shared store of the blocks first.
+pub struct GroupSync {
+    config: GroupSyncConfig,
+    pending: Mutex<PendingFiles>,
+    stores: Mutex<Vec<Weak<dyn Store>>>,
+    // one sync at a time, so the end of a sync means that all writes before its
+    // start are durable
+    syncing: Mutex<()>,
+    wakeup: Notify,
+    metrics: SharedMetrics,
+}
+
+impl GroupSync {
+    pub fn new(mut config: GroupSyncConfig, metrics: SharedMetrics) -> Self {
+        if !config.durability.is_synced() {
+            config.durability = Durability::Fdatasync;
+        }
+        config.max_bytes = config.max_bytes.max(1);
+        Self {
+            config,
+            pending: Mutex::default(),
+            stores: Mutex::default(),
+            syncing: Mutex::new(()),
+            wakeup: Notify::new(),
+            metrics,
+        }
+    }
+
+    pub fn config(&self) -> &GroupSyncConfig {
+        &self.config
+    }
+
+    /// Persist `store` with every sync. The store is held weakly, a closed store
+    /// is forgotten.
+    pub fn register_store(&self, store: &Arc<dyn Store>) {
+        let mut stores = self.stores.lock().expect("store lock is not poisoned");
+        stores.retain(|registered| registered.strong_count() > 0);
+        let known = stores
+            .iter()
+            .any(|registered| std::ptr::addr_eq(registered.as_ptr(), Arc::as_ptr(store)));
+        if !known {
+            stores.push(Arc::downgrade(store));
+        }
+    }
+
+    /// Sync the block file `path` of `len` bytes, written below the storage
+    /// directory `root`, with the next group.
+    pub fn add_file(&self, root: &Path, path: PathBuf, len: u64) {
+        let mut pending = self.pending.lock().expect("pending lock is not poisoned");
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(root) || !pending.dirs.insert(dir.to_path_buf()) {
+                break;
+            }
+        }
+        pending.files.push(path);
+        pending.bytes += len;
+        if pending.bytes >= self.config.max_bytes {
+            self.wakeup.notify_one();
+        }
+    }
+
+    /// Size of the block files waiting for the next sync
+    pub fn pending_bytes(&self) -> u64 {
+        self.pending
+            .lock()
+            .expect("pending lock is not poisoned")
+            .bytes
+    }
+
+    /// Sync the block files added so far and persist the registered stores. This
+    /// blocks, run it on a blocking thread.
+    ///
+    /// A failed fsync may have dropped the written data from the page cache, and
+    /// a retry could then succeed without the data being on disk. The files of a
+    /// failed sync are therefore not synced again, the error is returned.
+    pub fn sync(&self) -> io::Result<()> {
+        let _syncing = self.syncing.lock().expect("sync lock is not poisoned");
+        let start = Instant::now();
+        let pending =
+            std::mem::take(&mut *self.pending.lock().expect("pending lock is not poisoned"));
+        let stores: Vec<Arc<dyn Store>> = {
+            let mut stores = self.stores.lock().expect("store lock is not poisoned");
+            stores.retain(|store| store.strong_count() > 0);
+            stores.iter().filter_map(Weak::upgrade).collect()
+        };
+
+        for path in &pending.files {
+            match File::open(path) {
+                Ok(file) => self.sync_file(&file)?,
+                // removed since it was written
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        // deepest first, a directory is only synced after the new ones in it
+        for dir in pending.dirs.iter().rev() {
+            match File::open(dir) {
+                Ok(dir) => dir.sync_all()?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        for store in &stores {
+            store
+                .persist(self.config.durability)
+                .map_err(io::Error::other)?;
+        }
+
+        self.metrics.group_sync(start.elapsed(), pending.bytes);
+        Ok(())
+    }
+
+    fn sync_file(&self, file: &File) -> io::Result<()> {
+        match self.config.durability {
+            Durability::Fsync => file.sync_all(),
+            _ => file.sync_data(),
+        }
+    }
+
+    /// Sync every `max_delay`, or earlier once `max_bytes` are waiting. Runs
+    /// until the task is dropped, call [`GroupSync::sync`] once more on shutdown.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            tokio::select! {
+                () = tokio::time::sleep(self.config.max_delay) => {}
+                () = self.wakeup.notified() => {}
+            }
+            let group_sync = self.clone();
+            match tokio::task::spawn_blocking(move || group_sync.sync()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    tracing::error!(error = %e, "Group sync failed, recent writes may not be durable")
+                }
+                Err(e) => tracing::error!(error = %e, "Group sync task failed"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::FjallStore;
+
+    #[test]
+    fn test_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("blocks");
+        let config = GroupSyncConfig {
+            max_bytes: 10,
+            ..Default::default()
+        };
+        let group_sync = GroupSync::new(config, SharedMetrics::default());
+
+        let store: Arc<dyn Store> = Arc::new(FjallStore::new(
+            dir.path().join("db"),
+            None,
+            Some(Durability::Buffer),
+        ));
+        group_sync.register_store(&store);
+        group_sync.register_store(&store);
+        assert_eq!(group_sync.stores.lock().unwrap().len(), 1);
+
+        let path = root.join("ab").join("_cd");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"block").unwrap();
+        group_sync.add_file(&root, path.clone(), 5);
+        // a removed file is skipped
+        group_sync.add_file(&root, root.join("ab").join("_ef"), 5);
+        assert_eq!(group_sync.pending_bytes(), 10);
+        {
+            let pending = group_sync.pending.lock().unwrap();
+            let dirs: Vec<_> = pending.dirs.iter().cloned().collect();
+            assert_eq!(dirs, vec![root.clone(), root.join("ab")]);
+        }
+
+        group_sync.sync().unwrap();
+        assert_eq!(group_sync.pending_bytes(), 0);
+
+        // closed stores are forgotten
+        drop(store);
+        group_sync.sync().unwrap();
+        assert!(group_sync.stores.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = GroupSyncConfig {
+            max_bytes: 100,
+            max_delay: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let group_sync = Arc::new(GroupSync::new(config, SharedMetrics::default()));
+        tokio::spawn(group_sync.clone().run());
+
+        let path = dir.path().join("_00");
+        std::fs::write(&path, b"block").unwrap();
+        group_sync.add_file(dir.path(), path.clone(), 5);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // below the size threshold, waits for the delay
+        assert_eq!(group_sync.pending_bytes(), 5);
+
+        group_sync.add_file(dir.path(), path, 100);
+        for _ in 0..500 {
+            if group_sync.pending_bytes() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(group_sync.pending_bytes(), 0);
+    }
+}
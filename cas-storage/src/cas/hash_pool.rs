@@ -0,0 +1,229 @@
+//! Hashing of stored data on a dedicated thread pool.
+//!
+//! Every block of an upload is hashed twice: once for its block id and once as
+//! part of the content hash (and ETag) of the object. Done on the request task,
+//! MD5 alone uses most of a core at a few hundred MiB/s, which caps the
+//! throughput of an upload and stalls the other tasks of the runtime thread.
+//!
+//! With a [`HashPool`] the block ids are computed on the pool, the blocks of an
+//! upload in parallel while earlier blocks are written to disk. The hash of the
+//! whole object can't be split, MD5 is sequential, but it is computed on the pool
+//! as well, by a [`StreamHasher`] which is fed the blocks in order while the
+//! upload carries on.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use futures::channel::oneshot;
+use md5::{Digest, Md5};
+
+use super::content_hash::{ContentHash, ContentHasher};
+use crate::metastore::{BlockID, ETag};
+
+/// Threads hashing the data of uploads, shared by the stores of all users.
+pub struct HashPool {
+    pool: Arc<rayon::ThreadPool>,
+}
+
+impl HashPool {
+    /// Values of `threads` below 1 are treated as 1.
+    pub fn new(threads: usize) -> io::Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.max(1))
+            .thread_name(|i| format!("cas-hash-{i}"))
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(Self {
+            pool: Arc::new(pool),
+        })
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Computes the `hash` of `data` on the pool.
+    pub async fn digest(&self, hash: ContentHash, data: Bytes) -> BlockID {
+        let (tx, rx) = oneshot::channel();
+        self.pool.spawn(move || {
+            // the receiver is gone if the upload was dropped
+            let _ = tx.send(hash.digest(&data));
+        });
+        rx.await.expect("the hash pool runs all its tasks")
+    }
+
+    /// Returns a hasher computing the content hash of a stream on the pool, and
+    /// its MD5 ETag if the content hash isn't also the ETag.
+    pub fn stream_hasher(&self, hash: ContentHash) -> StreamHasher {
+        StreamHasher {
+            pool: self.pool.clone(),
+            state: Arc::new(Mutex::new(StreamState {
+                queue: VecDeque::new(),
+                hashers: Some(StreamHashers::new(hash)),
+                finished: None,
+            })),
+        }
+    }
+}
+
+/// The hashers of a stream: its content hash and, if that isn't one, its ETag.
+pub(crate) struct StreamHashers {
+    content_hash: ContentHasher,
+    e_tag: Option<Md5>,
+}
+
+impl StreamHashers {
+    pub fn new(hash: ContentHash) -> Self {
+        Self {
+            content_hash: hash.hasher(),
+            e_tag: (!hash.is_e_tag()).then(Md5::new),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.content_hash.update(data);
+        if let Some(e_tag) = &mut self.e_tag {
+            e_tag.update(data);
+        }
+    }
+
+    /// Returns the content hash and the ETag of the stream.
+    pub fn finalize(self) -> (BlockID, ETag) {
+        let content_hash = self.content_hash.finalize();
+        let e_tag = match self.e_tag {
+            Some(e_tag) => e_tag.finalize().into(),
+            None => content_hash,
+        };
+        (content_hash, e_tag)
+    }
+}
+
+struct StreamState {
+    /// Chunks waiting to be hashed, in stream order
+    queue: VecDeque<Bytes>,
+    /// `None` while a task of the pool hashes the queued chunks with them
+    hashers: Option<StreamHashers>,
+    /// Receives the hashes once the queue is drained after `finish`
+    finished: Option<oneshot::Sender<(BlockID, ETag)>>,
+}
+
+/// Hashes a stream on a [`HashPool`], without blocking the caller.
+///
+/// At most one task of the pool hashes the chunks of a stream at a time, it
+/// takes the chunks from the queue until it is empty. So a stream never holds
+/// a thread of the pool while it waits for data.
+pub struct StreamHasher {
+    pool: Arc<rayon::ThreadPool>,
+    state: Arc<Mutex<StreamState>>,
+}
+
+impl StreamHasher {
+    /// Queues the next chunk of the stream.
+    pub fn update(&self, chunk: Bytes) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.push_back(chunk);
+        if let Some(hashers) = state.hashers.take() {
+            drop(state);
+            self.drain(hashers);
+        }
+    }
+
+    /// Returns the content hash and the ETag of the stream, once all its chunks
+    /// are hashed.
+    pub async fn finish(self) -> (BlockID, ETag) {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            match state.hashers.take() {
+                // nothing is queued while no task holds the hashers
+                Some(hashers) => return hashers.finalize(),
+                None => state.finished = Some(tx),
+            }
+        }
+        rx.await.expect("the hash pool runs all its tasks")
+    }
+
+    fn drain(&self, mut hashers: StreamHashers) {
+        let state = self.state.clone();
+        self.pool.spawn(move || loop {
+            let mut guard = state.lock().unwrap();
+            match guard.queue.pop_front() {
+                Some(chunk) => {
+                    drop(guard);
+                    hashers.update(&chunk);
+                }
+                None => {
+                    match guard.finished.take() {
+                        Some(tx) => {
+                            let _ = tx.send(hashers.finalize());
+                        }
+                        None => guard.hashers = Some(hashers),
+                    }
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// Hashes a stream, on a [`HashPool`] if there is one and else inline.
+pub(crate) enum StreamHashing {
+    Inline(StreamHashers),
+    Pool(StreamHasher),
+}
+
+impl StreamHashing {
+    pub fn new(hash: ContentHash, pool: Option<&HashPool>) -> Self {
+        match pool {
+            Some(pool) => StreamHashing::Pool(pool.stream_hasher(hash)),
+            None => StreamHashing::Inline(StreamHashers::new(hash)),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &Bytes) {
+        match self {
+            StreamHashing::Inline(hashers) => hashers.update(chunk),
+            StreamHashing::Pool(hasher) => hasher.update(chunk.clone()),
+        }
+    }
+
+    pub async fn finish(self) -> (BlockID, ETag) {
+        match self {
+            StreamHashing::Inline(hashers) => hashers.finalize(),
+            StreamHashing::Pool(hasher) => hasher.finish().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cas::content_hash::e_tag;
+
+    #[tokio::test]
+    async fn test_hash_pool() {
+        let pool = HashPool::new(2).unwrap();
+        let data = Bytes::from_static(b"some data");
+        assert_eq!(
+            pool.digest(ContentHash::Md5, data.clone()).await,
+            ContentHash::Md5.digest(&data)
+        );
+
+        let chunks: Vec<Bytes> = (0..64u8).map(|i| Bytes::from(vec![i; 4096])).collect();
+        let mut inline = StreamHashing::new(ContentHash::Md5, None);
+        let mut pooled = StreamHashing::new(ContentHash::Md5, Some(&pool));
+        for chunk in &chunks {
+            inline.update(chunk);
+            pooled.update(chunk);
+        }
+        let expected = inline.finish().await;
+        assert_eq!(pooled.finish().await, expected);
+        assert_eq!(expected.0, e_tag(&chunks.concat()));
+
+        // an empty stream
+        let hasher = pool.stream_hasher(ContentHash::Md5);
+        assert_eq!(hasher.finish().await.1, e_tag(b""));
+    }
+}
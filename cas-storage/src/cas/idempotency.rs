@@ -0,0 +1,173 @@
+//! Results of requests made with an idempotency key, so a request which is
+//! retried after a network failure returns the result of the first one instead
+//! of doing the same thing again.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::metastore::{MetaError, MetaStore};
+
+/// Tree in the user metadata store holding the results by idempotency key
+pub const IDEMPOTENCY_KEYS_TREE: &str = "_IDEMPOTENCY_KEYS";
+
+/// How long the result of a request is kept for its retries
+pub const IDEMPOTENCY_KEY_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Longest accepted idempotency key
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+// results are keyed by idempotency key, and indexed by the time they were stored
+// to remove the expired ones without scanning the others
+const RESULT_PREFIX: &[u8] = b"r/";
+const EXPIRY_PREFIX: &[u8] = b"t/";
+
+/// The result of a request made with an idempotency key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdempotentResult {
+    /// Identifies the request, a retry with the same key must match it
+    pub fingerprint: String,
+    /// HTTP status of the response
+    pub status: u16,
+    pub body: String,
+    /// In seconds since the UNIX epoch
+    pub created_at: u64,
+}
+
+impl IdempotentResult {
+    fn from_slice(data: &[u8]) -> Result<IdempotentResult, MetaError> {
+        serde_json::from_slice(data)
+            .map_err(|e| MetaError::OtherDBError(format!("invalid idempotent result: {}", e)))
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn result_key(key: &str) -> Vec<u8> {
+    [RESULT_PREFIX, key.as_bytes()].concat()
+}
+
+fn expiry_key(created_at: u64, key: &str) -> Vec<u8> {
+    [EXPIRY_PREFIX, &created_at.to_be_bytes()[..], key.as_bytes()].concat()
+}
+
+/// The results of the requests of a user made with an idempotency key, kept for
+/// [`IDEMPOTENCY_KEY_LIFETIME`].
+pub struct IdempotencyKeys<'a> {
+    meta_store: &'a MetaStore,
+}
+
+impl<'a> IdempotencyKeys<'a> {
+    pub fn new(meta_store: &'a MetaStore) -> Self {
+        Self { meta_store }
+    }
+
+    /// The result of the request made with `key`, if it didn't expire yet.
+    pub fn get(&self, key: &str) -> Result<Option<IdempotentResult>, MetaError> {
+        let tree = self.meta_store.get_tree(IDEMPOTENCY_KEYS_TREE)?;
+        let Some(value) = tree.get(&result_key(key))? else {
+            return Ok(None);
+        };
+        let result = IdempotentResult::from_slice(&value)?;
+        let expires_at = result.created_at + IDEMPOTENCY_KEY_LIFETIME.as_secs();
+        Ok((expires_at > now_secs()).then_some(result))
+    }
+
+    /// Store the result of the request made with `key`, replacing an expired one.
+    /// The results which expired are removed.
+    pub fn insert(&self, key: &str, result: &IdempotentResult) -> Result<(), MetaError> {
+        self.prune(now_secs())?;
+        let tree = self.meta_store.get_tree(IDEMPOTENCY_KEYS_TREE)?;
+        let value =
+            serde_json::to_vec(result).map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        tree.insert(&expiry_key(result.created_at, key), Vec::new())?;
+        tree.insert(&result_key(key), value)
+    }
+
+    /// Remove the results which expired at `now`, in seconds since the UNIX
+    /// epoch. Returns the amount of results removed.
+    pub fn prune(&self, now: u64) -> Result<usize, MetaError> {
+        let cutoff = now.saturating_sub(IDEMPOTENCY_KEY_LIFETIME.as_secs());
+        let mut expired = Vec::new();
+        for item in self
+            .meta_store
+            .get_bucket_ext(IDEMPOTENCY_KEYS_TREE)?
+            .iter_prefix(EXPIRY_PREFIX)
+        {
+            let (index_key, _) = item?;
+            let Some(entry) = index_key.get(EXPIRY_PREFIX.len()..) else {
+                continue;
+            };
+            let Some((created_at, key)) = entry.split_first_chunk::<8>() else {
+                continue;
+            };
+            if u64::from_be_bytes(*created_at) > cutoff {
+                break;
+            }
+            expired.push((index_key.clone(), key.to_vec()));
+        }
+
+        let tree = self.meta_store.get_tree(IDEMPOTENCY_KEYS_TREE)?;
+        for (index_key, key) in &expired {
+            let key = [RESULT_PREFIX, key].concat();
+            // the key may have been used again since, with a later index entry
+            let reused = match tree.get(&key)? {
+                Some(value) => IdempotentResult::from_slice(&value)?.created_at > cutoff,
+                None => false,
+            };
+            if !reused {
+                tree.remove(&key)?;
+            }
+            tree.remove(index_key)?;
+        }
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::FjallStore;
+
+    #[test]
+    fn test_idempotency_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MetaStore::new(
+            FjallStore::new(dir.path().to_path_buf(), Some(1), None),
+            Some(1),
+        );
+        let keys = IdempotencyKeys::new(&store);
+        let now = now_secs();
+        let lifetime = IDEMPOTENCY_KEY_LIFETIME.as_secs();
+        let result = |created_at| IdempotentResult {
+            fingerprint: "f".to_string(),
+            status: 200,
+            body: "{}".to_string(),
+            created_at,
+        };
+
+        keys.insert("old", &result(now - lifetime - 1)).unwrap();
+        keys.insert("new", &result(now)).unwrap();
+        assert_eq!(keys.get("new").unwrap(), Some(result(now)));
+        assert_eq!(keys.get("unknown").unwrap(), None);
+        // an expired result is ignored, and removed when another one is stored
+        assert_eq!(keys.get("old").unwrap(), None);
+        keys.insert("other", &result(now)).unwrap();
+        let tree = store.get_bucket_ext(IDEMPOTENCY_KEYS_TREE).unwrap();
+        assert_eq!(tree.iter_all().count(), 4);
+
+        // a key used again keeps its new result
+        keys.insert("again", &result(now - lifetime + 10)).unwrap();
+        keys.insert("again", &result(now)).unwrap();
+        assert_eq!(keys.prune(now + 10).unwrap(), 1);
+        assert!(keys.get("again").unwrap().is_some());
+
+        assert_eq!(keys.prune(now + lifetime).unwrap(), 3);
+        assert_eq!(tree.iter_all().count(), 0);
+    }
+}
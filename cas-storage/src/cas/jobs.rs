@@ -0,0 +1,206 @@
+//! Records of long-running maintenance jobs, like garbage collection or scrubbing,
+//! so their progress and outcome can be followed and survive restarts.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::metastore::{MetaError, MetaStore};
+
+/// Tree in the block metadata store holding the job records
+pub const JOBS_TREE: &str = "_JOBS";
+
+/// State of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    /// Stopped on request before it finished
+    Cancelled,
+    /// The server stopped while the job was running
+    Interrupted,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        self != JobStatus::Running
+    }
+}
+
+/// A job and its progress, `done` out of `total` units of work. What a unit is
+/// depends on the kind of job, e.g. blocks for a scrub.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: u64,
+    /// Kind of the job, e.g. `blob_gc`
+    pub kind: String,
+    /// What the job works on, e.g. a bucket, if not the whole store
+    pub target: Option<String>,
+    pub status: JobStatus,
+    pub done: u64,
+    /// `0` while the amount of work is not known yet
+    pub total: u64,
+    /// In seconds since the UNIX epoch
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    /// Why the job failed
+    pub error: Option<String>,
+}
+
+impl JobRecord {
+    /// Progress in percent, `None` while the amount of work is not known.
+    pub fn percent(&self) -> Option<u8> {
+        if self.status == JobStatus::Completed {
+            return Some(100);
+        }
+        if self.total == 0 {
+            return None;
+        }
+        Some((self.done.min(self.total) * 100 / self.total) as u8)
+    }
+
+    fn from_slice(data: &[u8]) -> Result<JobRecord, MetaError> {
+        serde_json::from_slice(data)
+            .map_err(|e| MetaError::OtherDBError(format!("invalid job record: {}", e)))
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The job records, kept in the store of the block metadata, which is shared by
+/// all users in multi-user mode. Records are keyed by id, which increases, so they
+/// are listed in the order the jobs started.
+pub struct JobStore {
+    meta_store: MetaStore,
+}
+
+impl JobStore {
+    pub fn new(meta_store: MetaStore) -> Self {
+        Self { meta_store }
+    }
+
+    /// Record a new running job of `kind`, with an id higher than all earlier jobs.
+    pub fn create(&self, kind: &str, target: Option<String>) -> Result<JobRecord, MetaError> {
+        let id = self.list()?.last().map_or(1, |job| job.id + 1);
+        let record = JobRecord {
+            id,
+            kind: kind.to_string(),
+            target,
+            status: JobStatus::Running,
+            done: 0,
+            total: 0,
+            started_at: now_secs(),
+            finished_at: None,
+            error: None,
+        };
+        self.update(&record)?;
+        Ok(record)
+    }
+
+    /// Store the current state of a job.
+    pub fn update(&self, record: &JobRecord) -> Result<(), MetaError> {
+        let value =
+            serde_json::to_vec(record).map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        self.meta_store
+            .get_tree(JOBS_TREE)?
+            .insert(&record.id.to_be_bytes(), value)
+    }
+
+    /// The job with `id`, if it is known.
+    pub fn get(&self, id: u64) -> Result<Option<JobRecord>, MetaError> {
+        let tree = self.meta_store.get_tree(JOBS_TREE)?;
+        match tree.get(&id.to_be_bytes())? {
+            Some(data) => Ok(Some(JobRecord::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// All jobs, oldest first.
+    pub fn list(&self) -> Result<Vec<JobRecord>, MetaError> {
+        let tree = self.meta_store.get_bucket_ext(JOBS_TREE)?;
+        let mut jobs = Vec::new();
+        for item in tree.iter_all() {
+            let (_, value) = item?;
+            jobs.push(JobRecord::from_slice(&value)?);
+        }
+        Ok(jobs)
+    }
+
+    /// Mark the jobs still recorded as running as interrupted. Called on startup,
+    /// when no job can be running yet. Returns the amount of jobs marked.
+    pub fn mark_interrupted(&self) -> Result<usize, MetaError> {
+        let mut marked = 0;
+        for mut record in self.list()? {
+            if record.status == JobStatus::Running {
+                record.status = JobStatus::Interrupted;
+                record.finished_at = Some(now_secs());
+                self.update(&record)?;
+                marked += 1;
+            }
+        }
+        Ok(marked)
+    }
+
+    /// Remove the oldest finished jobs, keeping the last `keep` ones, at least one
+    /// so the ids of new jobs keep increasing. Returns the amount of jobs removed.
+    pub fn prune(&self, keep: usize) -> Result<usize, MetaError> {
+        let finished: Vec<u64> = self
+            .list()?
+            .into_iter()
+            .filter(|job| job.status.is_finished())
+            .map(|job| job.id)
+            .collect();
+        let remove = finished.len().saturating_sub(keep.max(1));
+        let tree = self.meta_store.get_tree(JOBS_TREE)?;
+        for id in &finished[..remove] {
+            tree.remove(&id.to_be_bytes())?;
+        }
+        Ok(remove)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::FjallStore;
+
+    #[test]
+    fn test_job_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FjallStore::new(dir.path().to_path_buf(), Some(1), None);
+        let jobs = JobStore::new(MetaStore::new(store, Some(1)));
+
+        let mut gc = jobs.create("blob_gc", None).unwrap();
+        let scrub = jobs.create("scrub", None).unwrap();
+        assert_eq!((gc.id, scrub.id), (1, 2));
+        assert_eq!(gc.percent(), None);
+
+        gc.total = 4;
+        gc.done = 1;
+        jobs.update(&gc).unwrap();
+        assert_eq!(jobs.get(1).unwrap().unwrap().percent(), Some(25));
+        assert_eq!(jobs.get(3).unwrap(), None);
+
+        gc.status = JobStatus::Completed;
+        jobs.update(&gc).unwrap();
+        assert_eq!(jobs.mark_interrupted().unwrap(), 1);
+        let listed = jobs.list().unwrap();
+        assert_eq!(listed[0].status, JobStatus::Completed);
+        assert_eq!(listed[0].percent(), Some(100));
+        assert_eq!(listed[1].status, JobStatus::Interrupted);
+
+        assert_eq!(jobs.prune(1).unwrap(), 1);
+        let listed = jobs.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, 2);
+        // ids keep increasing
+        assert_eq!(jobs.create("scrub", None).unwrap().id, 3);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::metastore::MetaTreeSnapshot;
+
+/// Default maximum lifetime of a listing snapshot
+pub const DEFAULT_LIST_SNAPSHOT_LIFETIME: Duration = Duration::from_secs(300);
+
+/// Default maximum amount of live listing snapshots
+pub const DEFAULT_MAX_LIST_SNAPSHOTS: usize = 1024;
+
+struct ListSnapshot {
+    bucket: String,
+    created: Instant,
+    snapshot: Arc<dyn MetaTreeSnapshot>,
+}
+
+/// Keeps the bucket snapshots of paginated listings alive between requests.
+///
+/// The first page of a listing registers a snapshot of the bucket, later pages
+/// look it up by id, so all pages see the bucket as it was when the listing
+/// started. Snapshots keep old versions of the metadata alive in the store, so
+/// they expire after a maximum lifetime, counted from the first page, and at
+/// most `max_count` are kept: the oldest snapshot is released to make room for a
+/// new one, and its listing has to be restarted.
+pub struct ListSnapshots {
+    max_lifetime: Duration,
+    max_count: usize,
+    next_id: AtomicU64,
+    snapshots: Mutex<HashMap<u64, ListSnapshot>>,
+}
+
+impl ListSnapshots {
+    pub fn new(max_lifetime: Duration, max_count: usize) -> Self {
+        Self {
+            max_lifetime,
+            max_count: max_count.max(1),
+            // start at the current time, so tokens handed out before a restart
+            // don't match snapshots of the new process
+            next_id: AtomicU64::new(
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_micros() as u64)
+                    .unwrap_or_default(),
+            ),
+            snapshots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn max_lifetime(&self) -> Duration {
+        self.max_lifetime
+    }
+
+    pub fn max_count(&self) -> usize {
+        self.max_count
+    }
+
+    /// Register a snapshot of a bucket and return its id.
+    pub fn insert(&self, bucket: &str, snapshot: Arc<dyn MetaTreeSnapshot>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut snapshots = self.snapshots.lock().expect("snapshot lock is not poisoned");
+        self.prune(&mut snapshots);
+        // make room by releasing the oldest snapshots
+        while snapshots.len() >= self.max_count {
+            let Some(oldest) = snapshots
+                .iter()
+                .min_by_key(|(_, s)| s.created)
+                .map(|(id, _)| *id)
+            else {
+                break;
+            };
+            snapshots.remove(&oldest);
+        }
+        snapshots.insert(
+            id,
+            ListSnapshot {
+                bucket: bucket.to_string(),
+                created: Instant::now(),
+                snapshot,
+            },
+        );
+        id
+    }
+
+    /// Look up a snapshot of a bucket. Returns None if the snapshot does not exist,
+    /// belongs to another bucket or has expired.
+    pub fn get(&self, bucket: &str, id: u64) -> Option<Arc<dyn MetaTreeSnapshot>> {
+        let mut snapshots = self.snapshots.lock().expect("snapshot lock is not poisoned");
+        self.prune(&mut snapshots);
+        snapshots
+            .get(&id)
+            .filter(|s| s.bucket == bucket)
+            .map(|s| Arc::clone(&s.snapshot))
+    }
+
+    /// Release a snapshot, typically after the last page of a listing.
+    pub fn remove(&self, id: u64) {
+        self.snapshots
+            .lock()
+            .expect("snapshot lock is not poisoned")
+            .remove(&id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots
+            .lock()
+            .expect("snapshot lock is not poisoned")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn prune(&self, snapshots: &mut HashMap<u64, ListSnapshot>) {
+        let max_lifetime = self.max_lifetime;
+        snapshots.retain(|_, s| s.created.elapsed() < max_lifetime);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::Object;
+
+    struct EmptySnapshot;
+
+    impl MetaTreeSnapshot for EmptySnapshot {
+        fn range_filter<'a>(
+            &'a self,
+            _start_after: Option<String>,
+            _prefix: Option<String>,
+            _continuation_token: Option<String>,
+        ) -> Box<dyn Iterator<Item = (String, Object)> + 'a> {
+            Box::new(std::iter::empty())
+        }
+    }
+
+    #[test]
+    fn test_lookup_and_remove() {
+        let snapshots = ListSnapshots::new(Duration::from_secs(60), 10);
+        let id = snapshots.insert("bucket", Arc::new(EmptySnapshot));
+
+        assert!(snapshots.get("bucket", id).is_some());
+        // snapshots are bound to their bucket
+        assert!(snapshots.get("other", id).is_none());
+
+        snapshots.remove(id);
+        assert!(snapshots.get("bucket", id).is_none());
+        assert!(snapshots.is_empty());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let snapshots = ListSnapshots::new(Duration::from_millis(10), 10);
+        let id = snapshots.insert("bucket", Arc::new(EmptySnapshot));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(snapshots.get("bucket", id).is_none());
+        assert!(snapshots.is_empty());
+    }
+
+    #[test]
+    fn test_max_count() {
+        let snapshots = ListSnapshots::new(Duration::from_secs(60), 2);
+        let first = snapshots.insert("bucket", Arc::new(EmptySnapshot));
+        std::thread::sleep(Duration::from_millis(1));
+        let second = snapshots.insert("bucket", Arc::new(EmptySnapshot));
+        std::thread::sleep(Duration::from_millis(1));
+        let third = snapshots.insert("bucket", Arc::new(EmptySnapshot));
+
+        // the oldest snapshot made room for the new one
+        assert_eq!(snapshots.len(), 2);
+        assert!(snapshots.get("bucket", first).is_none());
+        assert!(snapshots.get("bucket", second).is_some());
+        assert!(snapshots.get("bucket", third).is_some());
+    }
+}
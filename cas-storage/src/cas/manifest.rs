@@ -0,0 +1,104 @@
+//! Manifests of the object metadata of a bucket, to move a bucket to another store
+//! which has a copy of its block files without copying any data.
+
+use std::convert::{TryFrom, TryInto};
+
+use serde::{Deserialize, Serialize};
+
+use crate::metastore::{Block, BlockID, MetaError, Object};
+
+/// An object of an exported bucket, with the blocks it references. Manifests are
+/// written as one JSON entry per line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub key: String,
+    pub size: u64,
+    /// Content hash of the object, hex encoded
+    pub hash: String,
+    /// The encoded object metadata, hex encoded
+    pub meta: String,
+    /// The distinct blocks of the object
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocks: Vec<ManifestBlock>,
+}
+
+/// A block of an exported object, and where its file is stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestBlock {
+    /// Block id, hex encoded
+    pub id: String,
+    pub size: usize,
+    /// Path of the block file below the storage location, hex encoded
+    pub path: String,
+    /// Storage location of the block file, absent for the default one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+impl ManifestEntry {
+    /// The entry of `obj` at `key`, whose distinct blocks are `blocks`.
+    pub fn new(key: &str, obj: &Object, blocks: &[(BlockID, Block)]) -> Self {
+        Self {
+            key: key.to_string(),
+            size: obj.size(),
+            hash: hex::encode(obj.hash()),
+            meta: hex::encode(obj.to_vec()),
+            blocks: blocks
+                .iter()
+                .map(|(id, block)| ManifestBlock {
+                    id: hex::encode(id),
+                    size: block.size(),
+                    path: hex::encode(block.path()),
+                    location: block.location().map(str::to_string),
+                })
+                .collect(),
+        }
+    }
+
+    /// Decodes the object metadata, checking that all its blocks are listed.
+    pub fn object(&self) -> Result<Object, MetaError> {
+        let raw = decode_hex(&self.meta, "object metadata")?;
+        let obj = Object::try_from(raw.as_slice()).map_err(|e| {
+            MetaError::InvalidArgument(format!("invalid object metadata of {}: {e}", self.key))
+        })?;
+        let blocks = self.block_ids()?;
+        if let Some(missing) = obj.blocks().iter().find(|id| !blocks.contains(id)) {
+            return Err(MetaError::InvalidArgument(format!(
+                "block {} of {} is not listed",
+                hex::encode(missing),
+                self.key
+            )));
+        }
+        Ok(obj)
+    }
+
+    /// The listed blocks, with the metadata they are attached with.
+    pub fn blocks(&self) -> Result<Vec<(BlockID, Block)>, MetaError> {
+        self.blocks
+            .iter()
+            .map(|block| {
+                let id = block_id(&block.id)?;
+                let path = decode_hex(&block.path, "block path")?;
+                let meta = Block::new(block.size, path).with_location(block.location.clone());
+                Ok((id, meta))
+            })
+            .collect()
+    }
+
+    fn block_ids(&self) -> Result<Vec<BlockID>, MetaError> {
+        self.blocks
+            .iter()
+            .map(|block| block_id(&block.id))
+            .collect()
+    }
+}
+
+fn block_id(hex: &str) -> Result<BlockID, MetaError> {
+    decode_hex(hex, "block id")?
+        .try_into()
+        .map_err(|_| MetaError::InvalidArgument(format!("invalid block id {hex}")))
+}
+
+fn decode_hex(hex: &str, what: &str) -> Result<Vec<u8>, MetaError> {
+    hex::decode(hex).map_err(|e| MetaError::InvalidArgument(format!("invalid {what}: {e}")))
+}
@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use crate::metastore::Object;
+use crate::metrics::SharedMetrics;
+
+struct CacheEntry {
+    object: Object,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    // bucket -> key -> entry, nested so lookups don't need an owned key
+    entries: HashMap<String, HashMap<String, CacheEntry>>,
+    // last_used tick -> (bucket, key), oldest first
+    lru: BTreeMap<u64, (String, String)>,
+    len: usize,
+    tick: u64,
+    // bumped on every invalidation
+    generation: u64,
+}
+
+/// Bounded LRU cache of deserialized object metadata, keyed by bucket and key.
+///
+/// Entries are invalidated by the writes going through the owning `CasFS`. The
+/// cache is local to the process: it must not be enabled when other processes
+/// write to the same metadata store.
+///
+/// To avoid caching a value which was read just before a concurrent write, a
+/// reader takes the [`MetaCache::generation`] before reading from the store and
+/// passes it to [`MetaCache::insert`], which drops the value if an invalidation
+/// happened in the meantime.
+pub struct MetaCache {
+    capacity: usize,
+    state: Mutex<CacheState>,
+    metrics: SharedMetrics,
+}
+
+impl MetaCache {
+    pub fn new(capacity: usize, metrics: SharedMetrics) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(CacheState::default()),
+            metrics,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().expect("cache lock is not poisoned").len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Look up the metadata of an object, marking it as recently used.
+    pub fn get(&self, bucket: &str, key: &str) -> Option<Object> {
+        let mut state = self.state.lock().expect("cache lock is not poisoned");
+        state.tick += 1;
+        let tick = state.tick;
+
+        let CacheState { entries, lru, .. } = &mut *state;
+        let entry = entries.get_mut(bucket).and_then(|keys| keys.get_mut(key));
+        match entry {
+            Some(entry) => {
+                if let Some(lru_key) = lru.remove(&entry.last_used) {
+                    lru.insert(tick, lru_key);
+                }
+                entry.last_used = tick;
+                self.metrics.meta_cache_hit();
+                Some(entry.object.clone())
+            }
+            None => {
+                self.metrics.meta_cache_miss();
+                None
+            }
+        }
+    }
+
+    /// The current generation, to be passed to [`MetaCache::insert`].
+    pub fn generation(&self) -> u64 {
+        self.state
+            .lock()
+            .expect("cache lock is not poisoned")
+            .generation
+    }
+
+    /// Cache the metadata of an object read from the store, unless an invalidation
+    /// happened since `generation` was taken.
+    pub fn insert(&self, bucket: &str, key: &str, object: &Object, generation: u64) {
+        let mut state = self.state.lock().expect("cache lock is not poisoned");
+        if state.generation != generation {
+            return;
+        }
+        state.tick += 1;
+        let tick = state.tick;
+
+        let CacheState {
+            entries, lru, len, ..
+        } = &mut *state;
+        let keys = entries.entry(bucket.to_string()).or_default();
+        match keys.get_mut(key) {
+            Some(entry) => {
+                lru.remove(&entry.last_used);
+                entry.object = object.clone();
+                entry.last_used = tick;
+            }
+            None => {
+                keys.insert(
+                    key.to_string(),
+                    CacheEntry {
+                        object: object.clone(),
+                        last_used: tick,
+                    },
+                );
+                *len += 1;
+            }
+        }
+        lru.insert(tick, (bucket.to_string(), key.to_string()));
+
+        while state.len > self.capacity {
+            Self::evict_oldest(&mut state);
+        }
+    }
+
+    /// Drop the cached metadata of an object. Must be called after the new
+    /// metadata has been written to the store.
+    pub fn invalidate(&self, bucket: &str, key: &str) {
+        let mut state = self.state.lock().expect("cache lock is not poisoned");
+        state.generation += 1;
+
+        let CacheState {
+            entries, lru, len, ..
+        } = &mut *state;
+        if let Some(keys) = entries.get_mut(bucket) {
+            if let Some(entry) = keys.remove(key) {
+                lru.remove(&entry.last_used);
+                *len -= 1;
+            }
+            if keys.is_empty() {
+                entries.remove(bucket);
+            }
+        }
+    }
+
+    /// Drop the cached metadata of all objects in a bucket.
+    pub fn invalidate_bucket(&self, bucket: &str) {
+        let mut state = self.state.lock().expect("cache lock is not poisoned");
+        state.generation += 1;
+
+        let CacheState {
+            entries, lru, len, ..
+        } = &mut *state;
+        if let Some(keys) = entries.remove(bucket) {
+            for entry in keys.values() {
+                lru.remove(&entry.last_used);
+            }
+            *len -= keys.len();
+        }
+    }
+
+    fn evict_oldest(state: &mut CacheState) {
+        let Some((_, (bucket, key))) = state.lru.pop_first() else {
+            return;
+        };
+        if let Some(keys) = state.entries.get_mut(&bucket) {
+            if keys.remove(&key).is_some() {
+                state.len -= 1;
+            }
+            if keys.is_empty() {
+                state.entries.remove(&bucket);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::ObjectData;
+
+    fn object(size: u64) -> Object {
+        Object::new(size, [0; 16], ObjectData::Inline { data: vec![0; 4] })
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = MetaCache::new(2, SharedMetrics::default());
+        cache.insert("b", "k1", &object(1), cache.generation());
+        cache.insert("b", "k2", &object(2), cache.generation());
+
+        // k1 becomes the most recently used entry, so k2 is evicted
+        assert_eq!(cache.get("b", "k1").unwrap().size(), 1);
+        cache.insert("b", "k3", &object(3), cache.generation());
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("b", "k2").is_none());
+        assert!(cache.get("b", "k1").is_some());
+        assert!(cache.get("b", "k3").is_some());
+    }
+
+    #[test]
+    fn test_invalidation() {
+        let cache = MetaCache::new(10, SharedMetrics::default());
+        cache.insert("b1", "k", &object(1), cache.generation());
+        cache.insert("b2", "k", &object(1), cache.generation());
+
+        cache.invalidate("b1", "k");
+        assert!(cache.get("b1", "k").is_none());
+        assert!(cache.get("b2", "k").is_some());
+
+        cache.invalidate_bucket("b2");
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_stale_insert_is_dropped() {
+        let cache = MetaCache::new(10, SharedMetrics::default());
+        let generation = cache.generation();
+        // a write happens between reading the store and filling the cache
+        cache.invalidate("b", "k");
+        cache.insert("b", "k", &object(1), generation);
+        assert!(cache.get("b", "k").is_none());
+    }
+}
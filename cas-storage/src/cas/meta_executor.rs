@@ -0,0 +1,168 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio::sync::Semaphore;
+
+use super::request_timings;
+use crate::metastore::MetaError;
+use crate::metrics::SharedMetrics;
+
+/// Default amount of metadata operations which run concurrently on blocking threads
+pub const DEFAULT_META_THREADS: usize = 16;
+
+/// Runs blocking metadata operations (transaction commits, which fsync depending on
+/// the durability) on tokio's blocking thread pool, so they don't stall the async
+/// runtime and with it unrelated requests.
+///
+/// At most `max_threads` operations run at the same time, the others wait for a
+/// free slot. The amount of waiting and running operations, and the time spent
+/// waiting, are reported to the metrics collector, a pool which is saturated all
+/// the time means the metadata store can't keep up.
+pub struct MetaExecutor {
+    semaphore: Arc<Semaphore>,
+    max_threads: usize,
+    queued: AtomicUsize,
+    metrics: SharedMetrics,
+}
+
+impl MetaExecutor {
+    /// Values of `max_threads` below 1 are treated as 1.
+    pub fn new(max_threads: usize, metrics: SharedMetrics) -> Self {
+        let max_threads = max_threads.max(1);
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_threads)),
+            max_threads,
+            queued: AtomicUsize::new(0),
+            metrics,
+        }
+    }
+
+    pub fn max_threads(&self) -> usize {
+        self.max_threads
+    }
+
+    /// Amount of operations currently running.
+    pub fn active(&self) -> usize {
+        self.max_threads - self.semaphore.available_permits()
+    }
+
+    /// Amount of operations waiting for a free slot.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Run `f` on a blocking thread once a slot is free.
+    ///
+    /// A panic in `f` is resumed in the caller. If the returned future is dropped
+    /// after `f` started, `f` still runs to completion.
+    pub async fn run<T, F>(&self, f: F) -> Result<T, MetaError>
+    where
+        F: FnOnce() -> Result<T, MetaError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let queued_at = Instant::now();
+        let permit = {
+            let _queued = QueuedMarker::new(self);
+            Arc::clone(&self.semaphore)
+                .acquire_owned()
+                .await
+                .expect("meta executor semaphore is never closed")
+        };
+        self.metrics.meta_pool_wait(queued_at.elapsed());
+        self.metrics.meta_pool_active(self.active());
+
+        let result = tokio::task::spawn_blocking(move || {
+            let result = f();
+            drop(permit);
+            result
+        })
+        .await;
+        self.metrics.meta_pool_active(self.active());
+        request_timings::record_meta(queued_at);
+
+        match result {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(MetaError::OtherDBError(format!(
+                "metadata operation was cancelled: {}",
+                e
+            ))),
+        }
+    }
+}
+
+// Keeps the queued count correct when the future is dropped while waiting.
+struct QueuedMarker<'a> {
+    executor: &'a MetaExecutor,
+}
+
+impl<'a> QueuedMarker<'a> {
+    fn new(executor: &'a MetaExecutor) -> Self {
+        let queued = executor.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        executor.metrics.meta_pool_queued(queued);
+        Self { executor }
+    }
+}
+
+impl Drop for QueuedMarker<'_> {
+    fn drop(&mut self) {
+        let queued = self.executor.queued.fetch_sub(1, Ordering::Relaxed) - 1;
+        self.executor.metrics.meta_pool_queued(queued);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_run_returns_result() {
+        let executor = MetaExecutor::new(2, SharedMetrics::default());
+        assert_eq!(executor.run(|| Ok(42)).await.unwrap(), 42);
+        assert!(matches!(
+            executor.run(|| Err::<(), _>(MetaError::KeyNotFound)).await,
+            Err(MetaError::KeyNotFound)
+        ));
+        assert_eq!(executor.active(), 0);
+        assert_eq!(executor.queued(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_run_is_bounded() {
+        let executor = Arc::new(MetaExecutor::new(1, SharedMetrics::default()));
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+
+        let first = tokio::spawn({
+            let executor = Arc::clone(&executor);
+            async move {
+                executor
+                    .run(move || {
+                        started_tx.send(()).unwrap();
+                        release_rx.recv().unwrap();
+                        Ok(())
+                    })
+                    .await
+            }
+        });
+        tokio::task::spawn_blocking(move || started_rx.recv().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(executor.active(), 1);
+
+        let second = tokio::spawn({
+            let executor = Arc::clone(&executor);
+            async move { executor.run(|| Ok(())).await }
+        });
+        while executor.queued() == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        release_tx.send(()).unwrap();
+        first.await.unwrap().unwrap();
+        second.await.unwrap().unwrap();
+        assert_eq!(executor.queued(), 0);
+    }
+}
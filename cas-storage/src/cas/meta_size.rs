@@ -0,0 +1,225 @@
+//! Accounting of the disk space used by the metadata trees, compared to the data
+//! they describe.
+
+use std::convert::TryInto;
+
+use serde::Serialize;
+
+use crate::metastore::{MetaError, MetaStore};
+
+/// Tree in the user metadata store holding the daily size samples of the trees
+pub const META_SIZE_HISTORY_TREE: &str = "_META_SIZE_HISTORY";
+
+/// Disk space used by a metadata tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TreeSize {
+    pub name: String,
+    /// Whether the tree is in the block store shared by all users
+    pub shared: bool,
+    pub bytes: u64,
+}
+
+/// Disk space of the metadata trees of a store, see `CasFS::metadata_size`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MetadataSize {
+    pub trees: Vec<TreeSize>,
+    /// Size of the stored blocks, of all users in multi-user mode
+    pub block_bytes: u64,
+    /// Size of the data inlined in the metadata
+    pub inline_bytes: u64,
+}
+
+impl MetadataSize {
+    /// Combine the sizes of the stores of several users. The shared trees and
+    /// blocks are counted once.
+    pub fn combine(sizes: &[MetadataSize]) -> MetadataSize {
+        let mut combined = MetadataSize::default();
+        for (i, size) in sizes.iter().enumerate() {
+            let shared = size.trees.iter().any(|tree| tree.shared);
+            combined.trees.extend(
+                size.trees
+                    .iter()
+                    .filter(|tree| i == 0 || !tree.shared)
+                    .cloned(),
+            );
+            if i == 0 || !shared {
+                combined.block_bytes += size.block_bytes;
+            }
+            combined.inline_bytes += size.inline_bytes;
+        }
+        combined
+    }
+
+    /// Size of the data described by the metadata, as `physical_bytes` of
+    /// `StoreStats`.
+    pub fn data_bytes(&self) -> u64 {
+        self.block_bytes + self.inline_bytes
+    }
+
+    /// Disk space used by all trees.
+    pub fn metadata_bytes(&self) -> u64 {
+        self.trees.iter().map(|tree| tree.bytes).sum()
+    }
+
+    /// Metadata bytes per data byte, 0 for a store without data.
+    pub fn ratio(&self) -> f64 {
+        if self.data_bytes() == 0 {
+            return 0.0;
+        }
+        self.metadata_bytes() as f64 / self.data_bytes() as f64
+    }
+
+    /// The trees ordered by decreasing size.
+    pub fn largest_first(&self) -> Vec<&TreeSize> {
+        let mut trees: Vec<&TreeSize> = self.trees.iter().collect();
+        trees.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+        trees
+    }
+}
+
+/// Size of a tree sampled on `day`, formatted as `YYYY-MM-DD`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TreeSizeSample {
+    pub day: String,
+    pub bytes: u64,
+}
+
+/// Daily size samples of the metadata trees.
+///
+/// Samples are keyed by `<tree>\0<day>` like the `UsageHistory`, so a second
+/// sample on the same day replaces the first one.
+pub struct MetaSizeHistory<'a> {
+    meta_store: &'a MetaStore,
+}
+
+impl<'a> MetaSizeHistory<'a> {
+    pub fn new(meta_store: &'a MetaStore) -> Self {
+        Self { meta_store }
+    }
+
+    fn key(tree: &str, day: &str) -> Vec<u8> {
+        let mut key = Vec::with_capacity(tree.len() + day.len() + 1);
+        key.extend_from_slice(tree.as_bytes());
+        key.push(0);
+        key.extend_from_slice(day.as_bytes());
+        key
+    }
+
+    /// Store the sizes of the trees in `size` on `day`.
+    pub fn record(&self, day: &str, size: &MetadataSize) -> Result<(), MetaError> {
+        let tree = self.meta_store.get_tree(META_SIZE_HISTORY_TREE)?;
+        for sample in &size.trees {
+            tree.insert(
+                &Self::key(&sample.name, day),
+                sample.bytes.to_le_bytes().to_vec(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// All samples of `tree`, oldest first.
+    pub fn samples(&self, tree: &str) -> Result<Vec<TreeSizeSample>, MetaError> {
+        let history = self.meta_store.get_bucket_ext(META_SIZE_HISTORY_TREE)?;
+        let prefix = Self::key(tree, "");
+
+        let mut samples = Vec::new();
+        for item in history.iter_prefix(&prefix) {
+            let (key, value) = item?;
+            let bytes: [u8; 8] = value.as_ref().try_into().map_err(|_| {
+                MetaError::OtherDBError(format!("invalid size sample length {}", value.len()))
+            })?;
+            samples.push(TreeSizeSample {
+                day: String::from_utf8_lossy(&key[prefix.len()..]).into_owned(),
+                bytes: u64::from_le_bytes(bytes),
+            });
+        }
+        Ok(samples)
+    }
+
+    /// Remove all samples of `tree`.
+    pub fn remove(&self, tree: &str) -> Result<(), MetaError> {
+        let history = self.meta_store.get_bucket_ext(META_SIZE_HISTORY_TREE)?;
+        let prefix = Self::key(tree, "");
+        for item in history.iter_prefix(&prefix) {
+            let (key, _) = item?;
+            history.remove(&key)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::FjallStore;
+
+    fn tree(name: &str, bytes: u64) -> TreeSize {
+        TreeSize {
+            name: name.to_string(),
+            shared: false,
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_metadata_size() {
+        let size = MetadataSize {
+            trees: vec![tree("_BLOCKS", 10), tree("bucket", 30)],
+            block_bytes: 300,
+            inline_bytes: 100,
+        };
+        assert_eq!(size.metadata_bytes(), 40);
+        assert_eq!(size.ratio(), 0.1);
+        assert_eq!(size.largest_first()[0].name, "bucket");
+        assert_eq!(MetadataSize::default().ratio(), 0.0);
+
+        let user = |bucket_bytes| MetadataSize {
+            trees: vec![
+                tree("bucket", bucket_bytes),
+                TreeSize {
+                    shared: true,
+                    ..tree("_BLOCKS", 10)
+                },
+            ],
+            block_bytes: 300,
+            inline_bytes: 50,
+        };
+        let combined = MetadataSize::combine(&[user(20), user(30)]);
+        assert_eq!(combined.metadata_bytes(), 60);
+        assert_eq!(combined.data_bytes(), 400);
+    }
+
+    #[test]
+    fn test_meta_size_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FjallStore::new(dir.path().to_path_buf(), Some(1), None);
+        let meta_store = MetaStore::new(store, Some(1));
+        let history = MetaSizeHistory::new(&meta_store);
+
+        let size = |bytes| MetadataSize {
+            trees: vec![tree("bucket", bytes)],
+            ..Default::default()
+        };
+        history.record("2026-01-02", &size(20)).unwrap();
+        history.record("2026-01-01", &size(10)).unwrap();
+        history.record("2026-01-02", &size(25)).unwrap();
+
+        let samples = history.samples("bucket").unwrap();
+        assert_eq!(
+            samples,
+            vec![
+                TreeSizeSample {
+                    day: "2026-01-01".to_string(),
+                    bytes: 10
+                },
+                TreeSizeSample {
+                    day: "2026-01-02".to_string(),
+                    bytes: 25
+                },
+            ]
+        );
+
+        history.remove("bucket").unwrap();
+        assert!(history.samples("bucket").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,255 @@
+//! The metadata engine a store was created with, and the migration of a store
+//! to the other engine.
+//!
+//! Both engines keep the same trees, but `fjall_notx` doesn't make the changes
+//! of a transaction atomic, a store it wrote can hold half done changes which
+//! the transactional engine never expects. The engine is recorded next to the
+//! `db` directory of a store when it's first opened, and the store is refused
+//! with the other one. [`migrate_store`] copies every tree into a new store
+//! with the other engine, and verifies the copy.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::fs::StorageEngine;
+use crate::metastore::{
+    Durability, FjallStore, FjallStoreNotx, MetaError, Store, KV_SEPARATED_TREE,
+};
+
+/// Name of the file recording the engine, next to the `db` directory of a store
+pub const ENGINE_FILE_NAME: &str = "s3-cas-engine";
+
+/// Amounts copied by [`migrate_store`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationStats {
+    pub trees: usize,
+    pub keys: u64,
+    /// Size of the keys and values
+    pub bytes: u64,
+}
+
+fn engine_file(db_path: &Path) -> PathBuf {
+    db_path.with_file_name(ENGINE_FILE_NAME)
+}
+
+/// The engine recorded for the store in `db_path`, `None` for a new store or
+/// one created before the engine was recorded.
+pub fn recorded_engine(db_path: &Path) -> Result<Option<StorageEngine>, MetaError> {
+    let path = engine_file(db_path);
+    match fs::read_to_string(&path) {
+        Ok(content) => content
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e: String| MetaError::OtherDBError(format!("{}: {e}", path.display()))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(MetaError::OtherDBError(format!(
+            "Can't read {}: {e}",
+            path.display()
+        ))),
+    }
+}
+
+fn record_engine(db_path: &Path, engine: StorageEngine) -> Result<(), MetaError> {
+    let path = engine_file(db_path);
+    fs::write(&path, format!("{}\n", engine.as_str()))
+        .map_err(|e| MetaError::OtherDBError(format!("Can't write {}: {e}", path.display())))
+}
+
+/// Check the store in `db_path` is opened with the engine it was created with.
+/// A store without a recorded engine is assumed to use `engine`, which is then
+/// recorded.
+pub(crate) fn check_engine(db_path: &Path, engine: StorageEngine) -> Result<(), MetaError> {
+    match recorded_engine(db_path)? {
+        Some(recorded) if recorded != engine => Err(MetaError::InvalidArgument(format!(
+            "the metadata store in {} uses {}, not {}. Convert it with `s3-cas migrate-metadata` \
+             instead of changing --metadata-db",
+            db_path.display(),
+            recorded.as_str(),
+            engine.as_str()
+        ))),
+        Some(_) => Ok(()),
+        None => {
+            let existing = fs::read_dir(db_path).is_ok_and(|mut entries| entries.next().is_some());
+            if existing {
+                tracing::info!(
+                    "Recording {} as the engine of the metadata store in {}",
+                    engine.as_str(),
+                    db_path.display()
+                );
+            }
+            record_engine(db_path, engine)
+        }
+    }
+}
+
+fn open_store(db_path: &Path, engine: StorageEngine) -> Result<Arc<dyn Store>, MetaError> {
+    let path = db_path.to_path_buf();
+    Ok(match engine {
+        // the copy is synced once it's complete
+        StorageEngine::Fjall => {
+            Arc::new(FjallStore::try_new(path, None, Some(Durability::Buffer))?)
+        }
+        StorageEngine::FjallNotx => Arc::new(FjallStoreNotx::try_new(path, None)?),
+    })
+}
+
+/// Copy the store in `from`, which uses `from_engine`, to a new store in `to`
+/// using `to_engine`, and check both have the same trees, keys and values. The
+/// engine of the new store is recorded once the copy is verified.
+///
+/// The source store must not be in use, and `to` must not exist or be empty.
+/// Bucket trees created with key-value separation are copied into regular
+/// trees.
+pub fn migrate_store(
+    from: &Path,
+    from_engine: StorageEngine,
+    to: &Path,
+    to_engine: StorageEngine,
+) -> Result<MigrationStats, MetaError> {
+    if let Some(recorded) = recorded_engine(from)? {
+        if recorded != from_engine {
+            return Err(MetaError::InvalidArgument(format!(
+                "the metadata store in {} uses {}, not {}",
+                from.display(),
+                recorded.as_str(),
+                from_engine.as_str()
+            )));
+        }
+    }
+    let not_empty = fs::read_dir(to)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+    if not_empty || engine_file(to).exists() {
+        return Err(MetaError::InvalidArgument(format!(
+            "{} already holds a metadata store",
+            to.display()
+        )));
+    }
+
+    let source = open_store(from, from_engine)?;
+    let target = open_store(to, to_engine)?;
+    let stats = copy_trees(source.as_ref(), target.as_ref())?;
+    target.persist(Durability::Fsync)?;
+    verify_trees(source.as_ref(), target.as_ref())?;
+    record_engine(to, to_engine)?;
+    Ok(stats)
+}
+
+// the marker of the key-value separated trees would claim the copies are
+// separated too
+fn copied_trees(store: &dyn Store) -> Result<Vec<String>, MetaError> {
+    let mut names = store.tree_names()?;
+    names.retain(|name| name != KV_SEPARATED_TREE);
+    names.sort();
+    Ok(names)
+}
+
+fn copy_trees(source: &dyn Store, target: &dyn Store) -> Result<MigrationStats, MetaError> {
+    let mut stats = MigrationStats::default();
+    for name in copied_trees(source)? {
+        let tree = target.tree_open(&name)?;
+        for item in source.tree_ext_open(&name)?.iter_all() {
+            let (key, value) = item?;
+            stats.keys += 1;
+            stats.bytes += (key.len() + value.len()) as u64;
+            tree.insert(&key, value.to_vec())?;
+        }
+        stats.trees += 1;
+        tracing::debug!(tree = %name, keys = stats.keys, "Copied metadata tree");
+    }
+    Ok(stats)
+}
+
+fn verify_trees(source: &dyn Store, target: &dyn Store) -> Result<(), MetaError> {
+    let names = copied_trees(source)?;
+    if copied_trees(target)? != names {
+        return Err(MetaError::OtherDBError(
+            "the copy doesn't have the trees of the source".to_string(),
+        ));
+    }
+    for name in names {
+        let mut copied = target.tree_ext_open(&name)?.iter_all();
+        for item in source.tree_ext_open(&name)?.iter_all() {
+            let (key, value) = item?;
+            match copied.next().transpose()? {
+                Some(entry) if entry == (key.clone(), value) => {}
+                _ => {
+                    return Err(MetaError::OtherDBError(format!(
+                        "tree {name} of the copy differs at key {}",
+                        String::from_utf8_lossy(&key)
+                    )))
+                }
+            }
+        }
+        if copied.next().is_some() {
+            return Err(MetaError::OtherDBError(format!(
+                "tree {name} of the copy has more keys than the source"
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StorageEngine::{Fjall, FjallNotx};
+    use super::*;
+
+    #[test]
+    fn test_check_engine() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("db");
+        assert_eq!(recorded_engine(&db).unwrap(), None);
+        check_engine(&db, FjallNotx).unwrap();
+        assert_eq!(recorded_engine(&db).unwrap(), Some(FjallNotx));
+        assert!(dir.path().join(ENGINE_FILE_NAME).exists());
+        check_engine(&db, FjallNotx).unwrap();
+        assert!(matches!(
+            check_engine(&db, Fjall),
+            Err(MetaError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_migrate_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("from/db"), dir.path().join("to/db"));
+        {
+            let store = FjallStore::try_new(from.clone(), None, None).unwrap();
+            for tree in ["_BUCKETS", "bucket"] {
+                let tree = store.tree_open(tree).unwrap();
+                tree.insert(b"a", b"1".to_vec()).unwrap();
+                tree.insert(b"b", b"22".to_vec()).unwrap();
+            }
+            store.tree_open(KV_SEPARATED_TREE).unwrap();
+        }
+        check_engine(&from, Fjall).unwrap();
+
+        assert!(matches!(
+            migrate_store(&from, FjallNotx, &to, FjallNotx),
+            Err(MetaError::InvalidArgument(_))
+        ));
+        let stats = migrate_store(&from, Fjall, &to, FjallNotx).unwrap();
+        assert_eq!(
+            stats,
+            MigrationStats {
+                trees: 2,
+                keys: 4,
+                bytes: 10,
+            }
+        );
+        assert_eq!(recorded_engine(&to).unwrap(), Some(FjallNotx));
+
+        let copy = FjallStoreNotx::try_new(to.clone(), None).unwrap();
+        let value = copy.tree_open("bucket").unwrap().get(b"b").unwrap();
+        assert_eq!(value.as_deref(), Some(&b"22"[..]));
+        assert!(!copy.tree_exists(KV_SEPARATED_TREE).unwrap());
+        drop(copy);
+
+        // a store is never copied over another one
+        assert!(migrate_store(&from, Fjall, &to, FjallNotx).is_err());
+    }
+}
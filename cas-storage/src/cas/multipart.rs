@@ -3,7 +3,35 @@ use std::{
     sync::Arc,
 };
 
-use crate::metastore::{BaseMetaTree, BlockID, FsError, MetaError, BLOCKID_SIZE, PTR_SIZE};
+use crate::metastore::{
+    BaseMetaTree, BlockID, ETag, FsError, MetaError, BLOCKID_SIZE, ETAG_SIZE, PTR_SIZE,
+};
+
+/// Tree holding the uploaded parts of multipart uploads, in the store holding the
+/// blocks
+pub const MULTIPART_TREE: &str = "_MULTIPART_PARTS";
+
+/// Key of a part in the multipart tree: `<upload_id>\0<part_number>`, with the part
+/// number in big endian, so the parts of an upload are stored next to each other in
+/// part number order. Every part has its own key, so concurrent uploads of parts
+/// of the same upload don't touch the same record.
+pub fn part_key(upload_id: &str, part_number: i64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(upload_id.len() + 9);
+    key.extend_from_slice(upload_id.as_bytes());
+    key.push(0);
+    key.extend_from_slice(&part_number.to_be_bytes());
+    key
+}
+
+/// Key of a part stored before parts were keyed by [`part_key`]
+pub(crate) fn legacy_part_key(
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    part_number: i64,
+) -> String {
+    format!("{bucket}-{key}-{upload_id}-{part_number}")
+}
 
 #[derive(Debug)]
 pub struct MultiPart {
@@ -12,7 +40,8 @@ pub struct MultiPart {
     bucket: String,
     key: String,
     upload_id: String,
-    hash: BlockID,
+    /// The ETag of the part, the MD5 of its data
+    e_tag: ETag,
     blocks: Vec<BlockID>,
 }
 
@@ -23,7 +52,7 @@ impl MultiPart {
         bucket: String,
         key: String,
         upload_id: String,
-        hash: BlockID,
+        e_tag: ETag,
         blocks: Vec<BlockID>,
     ) -> Self {
         Self {
@@ -32,15 +61,39 @@ impl MultiPart {
             bucket,
             key,
             upload_id,
-            hash,
+            e_tag,
             blocks,
         }
     }
 
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn part_number(&self) -> i64 {
+        self.part_number
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn upload_id(&self) -> &str {
+        &self.upload_id
+    }
+
     pub fn blocks(&self) -> &[BlockID] {
         &self.blocks
     }
 
+    pub fn e_tag(&self) -> &ETag {
+        &self.e_tag
+    }
+
     pub fn to_vec(&self) -> Vec<u8> {
         self.into()
     }
@@ -54,7 +107,8 @@ impl From<&MultiPart> for Vec<u8> {
                 + mp.bucket.len()
                 + mp.key.len()
                 + mp.upload_id.len()
-                + (1 + mp.blocks.len()) * BLOCKID_SIZE,
+                + ETAG_SIZE
+                + mp.blocks.len() * BLOCKID_SIZE,
         );
 
         out.extend_from_slice(&mp.size.to_le_bytes());
@@ -65,7 +119,7 @@ impl From<&MultiPart> for Vec<u8> {
         out.extend_from_slice(mp.key.as_bytes());
         out.extend_from_slice(&mp.upload_id.len().to_le_bytes());
         out.extend_from_slice(mp.upload_id.as_bytes());
-        out.extend_from_slice(&mp.hash);
+        out.extend_from_slice(&mp.e_tag);
         out.extend_from_slice(&mp.blocks.len().to_le_bytes());
         for block in &mp.blocks {
             out.extend_from_slice(block);
@@ -79,7 +133,7 @@ impl TryFrom<&[u8]> for MultiPart {
     type Error = FsError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        if value.len() < 5 * PTR_SIZE + 8 + BLOCKID_SIZE {
+        if value.len() < 5 * PTR_SIZE + 8 + ETAG_SIZE {
             return Err(FsError::MalformedObject);
         }
 
@@ -116,7 +170,7 @@ impl TryFrom<&[u8]> for MultiPart {
                 .try_into()
                 .unwrap(),
         );
-        if value.len() < 8 + 5 * PTR_SIZE + bucket_len + key_len + upload_id_len + BLOCKID_SIZE {
+        if value.len() < 8 + 5 * PTR_SIZE + bucket_len + key_len + upload_id_len + ETAG_SIZE {
             return Err(FsError::MalformedObject);
         }
         // SAFETY: Safe as we only insert valid strings
@@ -129,8 +183,8 @@ impl TryFrom<&[u8]> for MultiPart {
         };
 
         let block_len = usize::from_le_bytes(
-            value[8 + 4 * PTR_SIZE + bucket_len + key_len + upload_id_len + BLOCKID_SIZE
-                ..8 + 5 * PTR_SIZE + bucket_len + key_len + upload_id_len + BLOCKID_SIZE]
+            value[8 + 4 * PTR_SIZE + bucket_len + key_len + upload_id_len + ETAG_SIZE
+                ..8 + 5 * PTR_SIZE + bucket_len + key_len + upload_id_len + ETAG_SIZE]
                 .try_into()
                 .unwrap(),
         );
@@ -139,12 +193,13 @@ impl TryFrom<&[u8]> for MultiPart {
                 + bucket_len
                 + key_len
                 + upload_id_len
-                + (1 + block_len) * BLOCKID_SIZE
+                + ETAG_SIZE
+                + block_len * BLOCKID_SIZE
         {
             return Err(FsError::MalformedObject);
         }
         let mut blocks = Vec::with_capacity(block_len);
-        for chunk in value[8 + 5 * PTR_SIZE + bucket_len + key_len + upload_id_len + BLOCKID_SIZE..]
+        for chunk in value[8 + 5 * PTR_SIZE + bucket_len + key_len + upload_id_len + ETAG_SIZE..]
             .chunks_exact(BLOCKID_SIZE)
         {
             blocks.push(chunk.try_into().unwrap());
@@ -156,8 +211,8 @@ impl TryFrom<&[u8]> for MultiPart {
             bucket,
             key,
             upload_id,
-            hash: value[8 + 4 * PTR_SIZE + bucket_len + key_len + upload_id_len
-                ..8 + 4 * PTR_SIZE + bucket_len + key_len + upload_id_len + BLOCKID_SIZE]
+            e_tag: value[8 + 4 * PTR_SIZE + bucket_len + key_len + upload_id_len
+                ..8 + 4 * PTR_SIZE + bucket_len + key_len + upload_id_len + ETAG_SIZE]
                 .try_into()
                 .unwrap(),
             blocks,
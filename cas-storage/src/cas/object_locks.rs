@@ -0,0 +1,88 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use tokio::sync::{Mutex, MutexGuard};
+
+/// Default amount of lock stripes per CasFS instance
+pub const DEFAULT_OBJECT_LOCK_STRIPES: usize = 256;
+
+/// Striped async locks used to serialize writes (PUT, overwrite, delete) to the
+/// same object key.
+///
+/// Every (bucket, key) pair maps onto one of a fixed amount of stripes, so the
+/// memory used is bounded regardless of the amount of keys. Unrelated keys which
+/// hash to the same stripe are serialized as well, which is harmless apart from
+/// some lost concurrency.
+#[derive(Debug)]
+pub struct ObjectLocks {
+    stripes: Vec<Mutex<()>>,
+}
+
+impl ObjectLocks {
+    pub fn new(stripes: usize) -> Self {
+        Self {
+            stripes: (0..stripes.max(1)).map(|_| Mutex::new(())).collect(),
+        }
+    }
+
+    fn stripe(&self, bucket: &str, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        bucket.hash(&mut hasher);
+        key.hash(&mut hasher);
+        (hasher.finish() % self.stripes.len() as u64) as usize
+    }
+
+    /// Acquire the write lock for an object. The lock is released when the guard is dropped.
+    pub async fn lock(&self, bucket: &str, key: &str) -> MutexGuard<'_, ()> {
+        self.stripes[self.stripe(bucket, key)].lock().await
+    }
+
+    /// Acquire the write locks for many objects of a bucket, released when the
+    /// guards are dropped. The stripes are locked once each, in ascending order,
+    /// so keys sharing a stripe don't wait on each other, and two callers locking
+    /// overlapping keys can't deadlock.
+    pub async fn lock_all(&self, bucket: &str, keys: &[&str]) -> Vec<MutexGuard<'_, ()>> {
+        let mut stripes: Vec<usize> = keys.iter().map(|key| self.stripe(bucket, key)).collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        let mut guards = Vec::with_capacity(stripes.len());
+        for stripe in stripes {
+            guards.push(self.stripes[stripe].lock().await);
+        }
+        guards
+    }
+}
+
+impl Default for ObjectLocks {
+    fn default() -> Self {
+        Self::new(DEFAULT_OBJECT_LOCK_STRIPES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_same_key_is_serialized() {
+        let locks = ObjectLocks::new(8);
+        let guard = locks.lock("bucket", "key").await;
+        assert!(locks.stripes[locks.stripe("bucket", "key")]
+            .try_lock()
+            .is_err());
+        drop(guard);
+        assert!(locks.stripes[locks.stripe("bucket", "key")]
+            .try_lock()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_lock_all_shared_stripe() {
+        let locks = ObjectLocks::new(1);
+        let guards = locks.lock_all("bucket", &["a", "b", "a"]).await;
+        assert_eq!(guards.len(), 1);
+        assert!(locks.stripes[0].try_lock().is_err());
+        drop(guards);
+        assert!(locks.stripes[0].try_lock().is_ok());
+    }
+}
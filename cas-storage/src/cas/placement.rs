@@ -0,0 +1,105 @@
+//! Placement of new blocks in storage locations chosen by the key prefix of the
+//! object they are written for.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Maximum length of the name of a storage location, it is stored with every
+/// block placed there.
+pub const MAX_LOCATION_NAME: usize = 64;
+
+/// Named storage locations, and the rules placing the blocks of objects in them.
+///
+/// A block is placed when it is first written, in the location of the longest
+/// prefix rule of the bucket matching the key of the object. Its location is
+/// recorded in the block metadata, and doesn't change when objects of other
+/// prefixes deduplicate against it. Blocks matching no rule are stored in the
+/// default `<fs_root>/blocks`.
+#[derive(Debug, Clone, Default)]
+pub struct Placement {
+    // location name -> directory holding its blocks
+    locations: HashMap<String, PathBuf>,
+    // bucket -> (prefix, location name), longest prefix first
+    rules: HashMap<String, Vec<(String, String)>>,
+}
+
+impl Placement {
+    /// Store blocks placed in `name` in `dir`.
+    pub fn add_location(&mut self, name: impl Into<String>, dir: PathBuf) {
+        self.locations.insert(name.into(), dir);
+    }
+
+    /// Place the blocks of objects in `bucket` whose key starts with `prefix` in
+    /// the location `name`.
+    pub fn add_rule(
+        &mut self,
+        bucket: impl Into<String>,
+        prefix: impl Into<String>,
+        name: impl Into<String>,
+    ) {
+        let rules = self.rules.entry(bucket.into()).or_default();
+        rules.push((prefix.into(), name.into()));
+        rules.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+    }
+
+    /// The location new blocks of `key` in `bucket` are placed in, `None` for the
+    /// default one.
+    pub fn location_of(&self, bucket: &str, key: &str) -> Option<&str> {
+        self.rules
+            .get(bucket)?
+            .iter()
+            .find(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// The directory holding the blocks of location `name`.
+    pub fn location_dir(&self, name: &str) -> Option<&Path> {
+        self.locations.get(name).map(PathBuf::as_path)
+    }
+
+    /// The names of the locations the rules refer to which don't exist.
+    pub fn unknown_locations(&self) -> Vec<&str> {
+        let mut unknown: Vec<&str> = self
+            .rules
+            .values()
+            .flatten()
+            .map(|(_, name)| name.as_str())
+            .filter(|name| !self.locations.contains_key(*name))
+            .collect();
+        unknown.sort_unstable();
+        unknown.dedup();
+        unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location_of() {
+        let mut placement = Placement::default();
+        placement.add_location("hdd", PathBuf::from("/mnt/hdd/blocks"));
+        placement.add_location("ssd", PathBuf::from("/mnt/ssd/blocks"));
+        placement.add_rule("media", "videos/", "hdd");
+        placement.add_rule("media", "videos/previews/", "ssd");
+        placement.add_rule("media", "thumbs/", "ssd");
+
+        assert_eq!(placement.location_of("media", "videos/a.mp4"), Some("hdd"));
+        assert_eq!(
+            placement.location_of("media", "videos/previews/a.jpg"),
+            Some("ssd")
+        );
+        assert_eq!(placement.location_of("media", "thumbs/a.jpg"), Some("ssd"));
+        assert_eq!(placement.location_of("media", "docs/a.pdf"), None);
+        assert_eq!(placement.location_of("other", "videos/a.mp4"), None);
+        assert_eq!(
+            placement.location_dir("ssd"),
+            Some(Path::new("/mnt/ssd/blocks"))
+        );
+        assert!(placement.unknown_locations().is_empty());
+
+        placement.add_rule("media", "audio/", "tape");
+        assert_eq!(placement.unknown_locations(), vec!["tape"]);
+    }
+}
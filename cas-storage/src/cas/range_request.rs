@@ -26,6 +26,54 @@ impl RangeRequest {
         };
         end - start + 1
     }
+
+    /// The first and last byte of the range in a file of `file_size` bytes, the
+    /// end is clamped to the file. `None` if the range lies beyond the file.
+    pub fn bounds(&self, file_size: u64) -> Option<(u64, u64)> {
+        let (start, end) = match *self {
+            RangeRequest::All => (0, u64::MAX),
+            RangeRequest::ToBytes(end) => (0, end),
+            RangeRequest::FromBytes(start) => (start, u64::MAX),
+            RangeRequest::Range(start, end) => (start, end),
+        };
+        if start >= file_size {
+            return None;
+        }
+        Some((start, end.min(file_size - 1)))
+    }
+}
+
+/// Maximum amount of ranges accepted in one `Range` header, every range reads
+/// the blocks it covers again.
+pub const MAX_RANGES: usize = 64;
+
+/// Parse a `Range` header holding one or more comma separated ranges, e.g.
+/// `bytes=0-99, 200-`. Returns `None` if the header is malformed or has more than
+/// [`MAX_RANGES`] ranges.
+pub fn parse_multi_range_request(input: &str) -> Option<Vec<RangeRequest>> {
+    let specs = input.strip_prefix("bytes=")?;
+    let ranges = specs
+        .split(',')
+        .map(|spec| parse_range_spec(spec.trim()))
+        .collect::<Option<Vec<_>>>()?;
+    if ranges.len() > MAX_RANGES {
+        return None;
+    }
+    Some(ranges)
+}
+
+fn parse_range_spec(spec: &str) -> Option<RangeRequest> {
+    let (first, second) = spec.split_once('-')?;
+    match (first.is_empty(), second.is_empty()) {
+        (true, true) => None,
+        (true, false) => second.parse().ok().map(RangeRequest::ToBytes),
+        (false, true) => first.parse().ok().map(RangeRequest::FromBytes),
+        (false, false) => {
+            let start = first.parse().ok()?;
+            let end = second.parse().ok()?;
+            (start <= end).then_some(RangeRequest::Range(start, end))
+        }
+    }
 }
 
 // TODO: replace with a parse impl on RangeRequest
@@ -103,3 +151,24 @@ pub fn parse_range_request(input: &Option<String>) -> RangeRequest {
         RangeRequest::All
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multi_range_request() {
+        let ranges = parse_multi_range_request("bytes=0-9, 20-,-5").unwrap();
+        let bounds: Vec<_> = ranges.iter().map(|range| range.bounds(100)).collect();
+        assert_eq!(bounds, vec![Some((0, 9)), Some((20, 99)), Some((0, 5))]);
+
+        assert_eq!(RangeRequest::Range(90, 200).bounds(100), Some((90, 99)));
+        assert_eq!(RangeRequest::FromBytes(100).bounds(100), None);
+
+        assert!(parse_multi_range_request("bytes=0-9,").is_none());
+        assert!(parse_multi_range_request("bytes=9-0").is_none());
+        assert!(parse_multi_range_request("0-9,20-29").is_none());
+        let too_many = vec!["0-1"; MAX_RANGES + 1].join(",");
+        assert!(parse_multi_range_request(&format!("bytes={too_many}")).is_none());
+    }
+}
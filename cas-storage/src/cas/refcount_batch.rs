@@ -0,0 +1,105 @@
+//! Batching of the refcount increments of blocks which already exist.
+//!
+//! Objects which are mostly made of known blocks would otherwise commit a
+//! transaction per block, only to increment its refcount. Instead the
+//! increments are merged per block and applied together in one transaction.
+//!
+//! fjall has no merge operator, so applying an increment still reads and
+//! rewrites the block record, but once per block and batch instead of once per
+//! occurrence. With merge operands the deltas could be written without reading
+//! the records, the batch would then only need to keep the ids and counts.
+//!
+//! Between the check that a block exists and the commit of its increment, a
+//! concurrent delete can drop the last reference to the block and remove it.
+//! The batch therefore keeps the data of its blocks, so a removed block is
+//! written again, and bounds the amount of data it holds with `max_bytes`.
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use crate::metastore::BlockID;
+
+/// Data buffered by a batch before it must be applied
+pub(crate) const REFCOUNT_BATCH_MAX_BYTES: usize = 32 * 1024 * 1024;
+
+/// The pending increments of a block
+#[derive(Debug)]
+pub(crate) struct PendingRefs {
+    pub block: BlockID,
+    pub count: usize,
+    pub data: Bytes,
+}
+
+/// Refcount increments of existing blocks, merged per block.
+#[derive(Debug)]
+pub(crate) struct RefcountBatch {
+    pending: HashMap<BlockID, PendingRefs>,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl RefcountBatch {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            pending: HashMap::new(),
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    /// Add a reference to `block`, whose data is `data`. Returns `true` if the
+    /// batch is full and must be applied.
+    pub fn add(&mut self, block: BlockID, data: Bytes) -> bool {
+        match self.pending.get_mut(&block) {
+            Some(pending) => pending.count += 1,
+            None => {
+                self.bytes += data.len();
+                self.pending.insert(
+                    block,
+                    PendingRefs {
+                        block,
+                        count: 1,
+                        data,
+                    },
+                );
+            }
+        }
+        self.bytes >= self.max_bytes
+    }
+
+    /// Take the pending increments, leaving the batch empty.
+    pub fn take(&mut self) -> Vec<PendingRefs> {
+        self.bytes = 0;
+        self.pending.drain().map(|(_, pending)| pending).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::BLOCKID_SIZE;
+
+    #[test]
+    fn test_refcount_batch() {
+        let mut batch = RefcountBatch::new(8);
+        let (a, b) = ([1; BLOCKID_SIZE], [2; BLOCKID_SIZE]);
+
+        assert!(!batch.add(a, Bytes::from(vec![0; 4])));
+        // the data of a block is only counted once
+        assert!(!batch.add(a, Bytes::from(vec![0; 4])));
+        assert!(!batch.add(a, Bytes::from(vec![0; 4])));
+        assert!(batch.add(b, Bytes::from(vec![0; 4])));
+
+        let mut pending = batch.take();
+        pending.sort_by_key(|pending| pending.block);
+        let counts: Vec<_> = pending.iter().map(|p| (p.block, p.count)).collect();
+        assert_eq!(counts, vec![(a, 3), (b, 1)]);
+        assert!(batch.is_empty());
+        assert!(!batch.add(b, Bytes::from(vec![0; 4])));
+    }
+}
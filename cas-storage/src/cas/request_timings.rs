@@ -0,0 +1,89 @@
+//! Time a request spends in the metadata store and on disk, to tell where the
+//! time of a slow request went.
+//!
+//! The times are collected in a task local, so only the work done in the task
+//! of the request counts: the metadata operations run through the
+//! [`MetaExecutor`](super::MetaExecutor), the object metadata lookups and the
+//! block writes. Blocks written concurrently add up, the disk time of an upload
+//! can be longer than the request. The data of a GET is read while the response
+//! body is sent, after the request returned, and isn't counted.
+
+use std::cell::Cell;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+tokio::task_local! {
+    static TIMINGS: Recorder;
+}
+
+#[derive(Default)]
+struct Recorder {
+    meta: Cell<Duration>,
+    disk: Cell<Duration>,
+}
+
+/// Time spent by a request in each phase
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseTimings {
+    /// Metadata store operations, including the wait for a free metadata thread
+    pub meta: Duration,
+    /// Block writes, including the wait for the write limiter
+    pub disk: Duration,
+}
+
+/// Run `fut`, returning its output and the time it spent in each phase.
+pub async fn timed<F: Future>(fut: F) -> (F::Output, PhaseTimings) {
+    TIMINGS
+        .scope(Recorder::default(), async move {
+            let output = fut.await;
+            let timings = TIMINGS.with(|recorder| PhaseTimings {
+                meta: recorder.meta.get(),
+                disk: recorder.disk.get(),
+            });
+            (output, timings)
+        })
+        .await
+}
+
+fn add(phase: fn(&Recorder) -> &Cell<Duration>, duration: Duration) {
+    // outside of `timed` nothing is recorded
+    let _ = TIMINGS.try_with(|recorder| {
+        let cell = phase(recorder);
+        cell.set(cell.get() + duration);
+    });
+}
+
+/// Count the time since `start` as metadata store time of the current request.
+pub(crate) fn record_meta(start: Instant) {
+    add(|r| &r.meta, start.elapsed());
+}
+
+/// Count `duration` as disk time of the current request.
+pub(crate) fn record_disk(duration: Duration) {
+    add(|r| &r.disk, duration);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_timed() {
+        let start = Instant::now() - Duration::from_millis(5);
+        let (output, timings) = timed(async {
+            record_meta(start);
+            record_disk(Duration::from_millis(3));
+            record_disk(Duration::from_millis(4));
+            42
+        })
+        .await;
+        assert_eq!(output, 42);
+        assert!(timings.meta >= Duration::from_millis(5));
+        assert_eq!(timings.disk, Duration::from_millis(7));
+
+        // outside of a timed request the times are dropped
+        record_disk(Duration::from_millis(1));
+        let (_, timings) = timed(async {}).await;
+        assert_eq!(timings, PhaseTimings::default());
+    }
+}
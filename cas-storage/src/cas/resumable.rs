@@ -0,0 +1,210 @@
+//! State of resumable uploads: objects uploaded as a sequence of requests, which
+//! can be continued where the last one stopped after a connection is lost.
+//!
+//! Every full block received is staged like a block of a client-side
+//! deduplicated upload and recorded under the token of the upload, so it survives
+//! restarts. The object is assembled from the recorded blocks once the last data
+//! is received.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::metastore::{BlockID, MetaError, MetaStore};
+
+/// Tree in the user metadata store holding the state of the resumable uploads
+pub const RESUMABLE_UPLOADS_TREE: &str = "_RESUMABLE_UPLOADS";
+
+/// A resumable upload and how much of it is stored
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResumableUpload {
+    pub token: String,
+    pub bucket: String,
+    /// Key of the object created once the upload is finished
+    pub key: String,
+    /// Size of the object, if the client announced it
+    pub size: Option<u64>,
+    /// Amount of bytes stored, the upload continues from here
+    pub offset: u64,
+    /// Amount of blocks stored
+    pub blocks: u64,
+    /// In seconds since the UNIX epoch
+    pub created_at: u64,
+}
+
+impl ResumableUpload {
+    fn from_slice(data: &[u8]) -> Result<ResumableUpload, MetaError> {
+        serde_json::from_slice(data)
+            .map_err(|e| MetaError::OtherDBError(format!("invalid resumable upload: {}", e)))
+    }
+}
+
+// the blocks of an upload are keyed by token and sequence number, a block is
+// recorded with a single insert and listed in order
+fn block_key(token: &str, seq: u64) -> Vec<u8> {
+    let mut key = block_prefix(token);
+    key.extend_from_slice(&seq.to_be_bytes());
+    key
+}
+
+fn block_prefix(token: &str) -> Vec<u8> {
+    let mut key = token.as_bytes().to_vec();
+    key.push(b'/');
+    key
+}
+
+/// The resumable uploads of a user, by token.
+pub struct ResumableUploads<'a> {
+    meta_store: &'a MetaStore,
+}
+
+impl<'a> ResumableUploads<'a> {
+    pub fn new(meta_store: &'a MetaStore) -> Self {
+        Self { meta_store }
+    }
+
+    /// Start an upload of `key` in `bucket`, with a new random token.
+    pub fn create(
+        &self,
+        bucket: &str,
+        key: &str,
+        size: Option<u64>,
+    ) -> Result<ResumableUpload, MetaError> {
+        let upload = ResumableUpload {
+            token: uuid::Uuid::new_v4().simple().to_string(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            size,
+            offset: 0,
+            blocks: 0,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        };
+        self.update(&upload)?;
+        Ok(upload)
+    }
+
+    fn update(&self, upload: &ResumableUpload) -> Result<(), MetaError> {
+        let value =
+            serde_json::to_vec(upload).map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        self.meta_store
+            .get_tree(RESUMABLE_UPLOADS_TREE)?
+            .insert(upload.token.as_bytes(), value)
+    }
+
+    /// The upload with `token`, if it is known.
+    pub fn get(&self, token: &str) -> Result<Option<ResumableUpload>, MetaError> {
+        // a token never contains the separator of the block keys
+        if token.is_empty() || token.contains('/') {
+            return Ok(None);
+        }
+        let tree = self.meta_store.get_tree(RESUMABLE_UPLOADS_TREE)?;
+        match tree.get(token.as_bytes())? {
+            Some(data) => Ok(Some(ResumableUpload::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Record that `block`, of `len` bytes, follows the data stored of `upload`.
+    ///
+    /// The block is written before the state referring to it, a block written by
+    /// an append which didn't complete is overwritten by the next one.
+    pub fn append(
+        &self,
+        upload: &mut ResumableUpload,
+        block: &BlockID,
+        len: u64,
+    ) -> Result<(), MetaError> {
+        let tree = self.meta_store.get_tree(RESUMABLE_UPLOADS_TREE)?;
+        tree.insert(&block_key(&upload.token, upload.blocks), block.to_vec())?;
+        upload.blocks += 1;
+        upload.offset += len;
+        self.update(upload)
+    }
+
+    /// The blocks stored of `upload`, in order.
+    pub fn blocks(&self, upload: &ResumableUpload) -> Result<Vec<BlockID>, MetaError> {
+        let tree = self.meta_store.get_bucket_ext(RESUMABLE_UPLOADS_TREE)?;
+        let mut blocks = Vec::with_capacity(upload.blocks as usize);
+        for item in tree.iter_prefix(&block_prefix(&upload.token)) {
+            if blocks.len() as u64 == upload.blocks {
+                break;
+            }
+            let (_, value) = item?;
+            let id: BlockID = value
+                .as_ref()
+                .try_into()
+                .map_err(|_| MetaError::OtherDBError("invalid resumable block".to_string()))?;
+            blocks.push(id);
+        }
+        if blocks.len() as u64 != upload.blocks {
+            return Err(MetaError::OtherDBError(format!(
+                "resumable upload {} lost blocks",
+                upload.token
+            )));
+        }
+        Ok(blocks)
+    }
+
+    /// Forget the upload with `token` and its blocks.
+    pub fn remove(&self, token: &str) -> Result<(), MetaError> {
+        let tree = self.meta_store.get_tree(RESUMABLE_UPLOADS_TREE)?;
+        let keys: Vec<_> = self
+            .meta_store
+            .get_bucket_ext(RESUMABLE_UPLOADS_TREE)?
+            .iter_prefix(&block_prefix(token))
+            .map(|item| item.map(|(key, _)| key))
+            .collect::<Result<_, _>>()?;
+        for key in keys {
+            tree.remove(&key[..])?;
+        }
+        tree.remove(token.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::{FjallStore, BLOCKID_SIZE};
+
+    #[test]
+    fn test_resumable_uploads() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MetaStore::new(
+            FjallStore::new(dir.path().to_path_buf(), Some(1), None),
+            Some(1),
+        );
+        let uploads = ResumableUploads::new(&store);
+
+        let mut upload = uploads.create("bucket", "key", Some(10)).unwrap();
+        let other = uploads.create("bucket", "key", None).unwrap();
+        assert_ne!(upload.token, other.token);
+        assert_eq!(uploads.get(&upload.token).unwrap(), Some(upload.clone()));
+        assert_eq!(uploads.get("unknown").unwrap(), None);
+
+        uploads.append(&mut upload, &[1; BLOCKID_SIZE], 4).unwrap();
+        uploads.append(&mut upload, &[2; BLOCKID_SIZE], 4).unwrap();
+        assert_eq!((upload.offset, upload.blocks), (8, 2));
+        let stored = uploads.get(&upload.token).unwrap().unwrap();
+        assert_eq!(stored, upload);
+        assert_eq!(
+            uploads.blocks(&stored).unwrap(),
+            vec![[1; BLOCKID_SIZE], [2; BLOCKID_SIZE]]
+        );
+        assert!(uploads.blocks(&other).unwrap().is_empty());
+
+        // a block recorded without the state is not part of the upload
+        let tree = store.get_tree(RESUMABLE_UPLOADS_TREE).unwrap();
+        tree.insert(&block_key(&upload.token, 2), vec![3; BLOCKID_SIZE])
+            .unwrap();
+        assert_eq!(uploads.blocks(&stored).unwrap().len(), 2);
+
+        uploads.remove(&upload.token).unwrap();
+        assert_eq!(uploads.get(&upload.token).unwrap(), None);
+        assert!(uploads.get(&other.token).unwrap().is_some());
+        let tree = store.get_bucket_ext(RESUMABLE_UPLOADS_TREE).unwrap();
+        assert_eq!(tree.iter_all().count(), 1);
+    }
+}
@@ -4,8 +4,13 @@ use std::sync::Arc;
 use crate::metastore::{
     BaseMetaTree, BlockTree, Durability, FjallStore, FjallStoreNotx, MetaError, MetaStore,
 };
+use crate::metrics::SharedMetrics;
 
-use super::{multipart::MultiPartTree, StorageEngine};
+use super::{
+    metadata_engine::check_engine,
+    multipart::{MultiPartTree, MULTIPART_TREE},
+    StorageEngine,
+};
 
 /// SharedBlockStore manages the shared block metadata (_BLOCKS, _PATHS, and _MULTIPART_PARTS trees)
 /// that is accessed by all users for block refcounting, path allocation, and multipart uploads.
@@ -38,10 +43,11 @@ impl SharedBlockStore {
         // This is critical for performance as it avoids repeated getcwd() on every file op
         std::fs::create_dir_all(&path).ok();
         path = path.canonicalize().unwrap_or(path);
+        check_engine(&path, storage_engine)?;
 
         let meta_store = match storage_engine {
             StorageEngine::Fjall => {
-                let store = FjallStore::new(path, inlined_metadata_size, durability);
+                let store = FjallStore::try_new(path, inlined_metadata_size, durability)?;
                 MetaStore::new(store, inlined_metadata_size)
             }
             StorageEngine::FjallNotx => {
@@ -49,18 +55,19 @@ impl SharedBlockStore {
                     "Using fjall_notx for shared block metadata in multi-user mode. \
                      Note: fjall_notx has weaker consistency guarantees: \
                      (1) transactions are not atomic - blocks visible before commit, \
-                     (2) rollback uses best-effort cleanup, not true rollback, \
-                     (3) durability parameter is ignored. \
+                     (2) rollback uses best-effort cleanup, not true rollback. \
                      For production multi-user deployments, consider using 'fjall' instead."
                 );
-                let store = FjallStoreNotx::new(path, inlined_metadata_size);
+                let store = FjallStoreNotx::try_new(path, inlined_metadata_size)?
+                    .with_durability(durability);
                 MetaStore::new(store, inlined_metadata_size)
             }
         };
 
+        meta_store.ensure_counters()?;
         let block_tree = meta_store.get_block_tree()?;
         let path_tree = meta_store.get_path_tree()?;
-        let multipart_tree_base = meta_store.get_tree("_MULTIPART_PARTS")?;
+        let multipart_tree_base = meta_store.get_tree(MULTIPART_TREE)?;
         let multipart_tree = MultiPartTree::new(multipart_tree_base);
 
         Ok(Self {
@@ -71,6 +78,14 @@ impl SharedBlockStore {
         })
     }
 
+    /// Report the anomalies of the block reference counts to `metrics`
+    pub fn with_metrics(mut self, metrics: SharedMetrics) -> Self {
+        let mut meta_store = MetaStore::clone(&self.meta_store);
+        meta_store.set_metrics(metrics);
+        self.meta_store = Arc::new(meta_store);
+        self
+    }
+
     /// Get a reference to the shared block tree
     pub fn block_tree(&self) -> Arc<BlockTree> {
         Arc::clone(&self.block_tree)
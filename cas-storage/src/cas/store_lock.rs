@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the lock file in the metadata root
+pub const LOCK_FILE_NAME: &str = "s3-cas.lock";
+
+/// Errors returned by [`StoreLock::acquire`]
+#[derive(Debug)]
+pub enum StoreLockError {
+    /// Another process holds the lock, `holder` is what it wrote in the lock file
+    Locked {
+        path: PathBuf,
+        holder: String,
+    },
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for StoreLockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreLockError::Locked { path, holder } => write!(
+                f,
+                "Store is already in use by another process ({}), lock file: {}",
+                if holder.is_empty() { "unknown" } else { holder },
+                path.display()
+            ),
+            StoreLockError::Io { path, source } => {
+                write!(f, "Could not lock {}: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl Error for StoreLockError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StoreLockError::Locked { .. } => None,
+            StoreLockError::Io { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Exclusive lock on a metadata root, so only one process opens the store.
+///
+/// The metadata stores don't support concurrent access from several processes,
+/// two servers on the same `meta_root` corrupt it. The lock is an advisory lock on
+/// `<meta_root>/s3-cas.lock`, held until the `StoreLock` is dropped. The OS releases
+/// it when the process exits, so a crashed server doesn't leave a stale lock.
+/// Advisory locks are not reliable on all network filesystems.
+#[derive(Debug)]
+pub struct StoreLock {
+    _file: File,
+    path: PathBuf,
+}
+
+impl StoreLock {
+    /// Lock the store in `meta_root`. `holder` describes the process in the lock
+    /// file, it is shown to other processes trying to take the lock.
+    pub fn acquire(meta_root: &Path, holder: &str) -> Result<Self, StoreLockError> {
+        let path = meta_root.join(LOCK_FILE_NAME);
+        let io_error = |source| StoreLockError::Io {
+            path: path.clone(),
+            source,
+        };
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(io_error)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut current = String::new();
+                file.read_to_string(&mut current).ok();
+                return Err(StoreLockError::Locked {
+                    path,
+                    holder: current.trim().to_string(),
+                });
+            }
+            Err(TryLockError::Error(e)) => return Err(io_error(e)),
+        }
+
+        file.set_len(0).map_err(io_error)?;
+        writeln!(file, "pid {} ({})", std::process::id(), holder).map_err(io_error)?;
+
+        Ok(Self { _file: file, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let lock = StoreLock::acquire(dir.path(), "server").unwrap();
+        match StoreLock::acquire(dir.path(), "other") {
+            Err(StoreLockError::Locked { holder, .. }) => {
+                assert_eq!(holder, format!("pid {} (server)", std::process::id()));
+            }
+            other => panic!("expected the store to be locked, got {:?}", other),
+        }
+
+        drop(lock);
+        StoreLock::acquire(dir.path(), "other").unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashSet;
+use std::convert::TryInto;
+
+use serde::Serialize;
+
+use crate::metastore::{BlockTree, MetaError, MetaStore, MetaTreeExt, StoreCounters};
+
+/// Tree in the user metadata store holding the daily usage samples of the buckets
+pub const STATS_HISTORY_TREE: &str = "_STATS_HISTORY";
+
+const SAMPLE_LEN: usize = 24;
+
+/// Usage of a single bucket at a point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct BucketUsage {
+    /// Amount of objects in the bucket
+    pub objects: u64,
+    /// Sum of the object sizes
+    pub logical_bytes: u64,
+    /// Size of the distinct blocks referenced by the objects, plus inlined data.
+    /// Blocks shared with other buckets are counted in every bucket using them.
+    pub physical_bytes: u64,
+}
+
+impl BucketUsage {
+    /// Compute the usage of the objects in `bucket`.
+    pub fn compute(
+        bucket: &dyn MetaTreeExt,
+        block_tree: &BlockTree,
+    ) -> Result<BucketUsage, MetaError> {
+        let mut usage = BucketUsage::default();
+        let mut blocks = HashSet::new();
+
+        for (_, obj) in bucket.range_filter(None, None, None) {
+            usage.objects += 1;
+            usage.logical_bytes += obj.size();
+            if let Some(data) = obj.inlined() {
+                usage.physical_bytes += data.len() as u64;
+            }
+            for block_id in obj.blocks() {
+                if !blocks.insert(*block_id) {
+                    continue;
+                }
+                // a missing block is reported by `check`, it takes no space here
+                if let Some(block) = block_tree.get_block(block_id)? {
+                    usage.physical_bytes += block.size() as u64;
+                }
+            }
+        }
+
+        Ok(usage)
+    }
+
+    fn to_vec(self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(SAMPLE_LEN);
+        data.extend_from_slice(&self.objects.to_le_bytes());
+        data.extend_from_slice(&self.logical_bytes.to_le_bytes());
+        data.extend_from_slice(&self.physical_bytes.to_le_bytes());
+        data
+    }
+
+    fn from_slice(data: &[u8]) -> Result<BucketUsage, MetaError> {
+        if data.len() != SAMPLE_LEN {
+            return Err(MetaError::OtherDBError(format!(
+                "invalid usage sample length {}",
+                data.len()
+            )));
+        }
+        let field = |i: usize| u64::from_le_bytes(data[i * 8..(i + 1) * 8].try_into().unwrap());
+        Ok(BucketUsage {
+            objects: field(0),
+            logical_bytes: field(1),
+            physical_bytes: field(2),
+        })
+    }
+}
+
+/// Statistics of a whole store, see `CasFS::stats`.
+///
+/// In multi-user mode blocks are shared by all users: `blocks`, `physical_bytes`
+/// and `dedup_ratio` cover the blocks of all users, the other figures only the
+/// buckets and objects of one user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct StoreStats {
+    /// Amount of buckets
+    pub buckets: u64,
+    /// Amount of objects
+    pub objects: u64,
+    /// Sum of the object sizes
+    pub logical_bytes: u64,
+    /// Size of the stored blocks, plus inlined data
+    pub physical_bytes: u64,
+    /// Amount of stored blocks
+    pub blocks: u64,
+    /// Amount of objects inlined in the metadata
+    pub inline_objects: u64,
+    /// `logical_bytes` divided by `physical_bytes`, 1 for an empty store
+    pub dedup_ratio: f64,
+}
+
+impl StoreStats {
+    /// Combine the counters of the store holding the objects and of the one
+    /// holding the blocks, which are the same in single-user mode.
+    pub fn new(buckets: u64, objects: &StoreCounters, blocks: &StoreCounters) -> Self {
+        let physical_bytes = blocks.block_bytes + objects.inline_bytes;
+        let dedup_ratio = if physical_bytes == 0 {
+            1.0
+        } else {
+            objects.logical_bytes as f64 / physical_bytes as f64
+        };
+        Self {
+            buckets,
+            objects: objects.objects,
+            logical_bytes: objects.logical_bytes,
+            physical_bytes,
+            blocks: blocks.blocks,
+            inline_objects: objects.inline_objects,
+            dedup_ratio,
+        }
+    }
+}
+
+/// Usage of a bucket sampled on `day`, formatted as `YYYY-MM-DD`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UsageSample {
+    pub day: String,
+    #[serde(flatten)]
+    pub usage: BucketUsage,
+}
+
+/// Daily usage samples of the buckets in a metadata store.
+///
+/// Samples are keyed by `<bucket>\0<day>`, so the samples of a bucket are sorted
+/// by day and a second sample on the same day replaces the first one.
+pub struct UsageHistory<'a> {
+    meta_store: &'a MetaStore,
+}
+
+impl<'a> UsageHistory<'a> {
+    pub fn new(meta_store: &'a MetaStore) -> Self {
+        Self { meta_store }
+    }
+
+    fn key(bucket: &str, day: &str) -> Vec<u8> {
+        let mut key = Vec::with_capacity(bucket.len() + day.len() + 1);
+        key.extend_from_slice(bucket.as_bytes());
+        key.push(0);
+        key.extend_from_slice(day.as_bytes());
+        key
+    }
+
+    /// Store the usage of `bucket` on `day`.
+    pub fn record(&self, bucket: &str, day: &str, usage: BucketUsage) -> Result<(), MetaError> {
+        let tree = self.meta_store.get_tree(STATS_HISTORY_TREE)?;
+        tree.insert(&Self::key(bucket, day), usage.to_vec())
+    }
+
+    /// All samples of `bucket`, oldest first.
+    pub fn samples(&self, bucket: &str) -> Result<Vec<UsageSample>, MetaError> {
+        let tree = self.meta_store.get_bucket_ext(STATS_HISTORY_TREE)?;
+        let prefix = Self::key(bucket, "");
+
+        let mut samples = Vec::new();
+        for item in tree.iter_prefix(&prefix) {
+            let (key, value) = item?;
+            samples.push(UsageSample {
+                day: String::from_utf8_lossy(&key[prefix.len()..]).into_owned(),
+                usage: BucketUsage::from_slice(&value)?,
+            });
+        }
+        Ok(samples)
+    }
+
+    /// Remove all samples of `bucket`.
+    pub fn remove(&self, bucket: &str) -> Result<(), MetaError> {
+        let tree = self.meta_store.get_bucket_ext(STATS_HISTORY_TREE)?;
+        let prefix = Self::key(bucket, "");
+        for item in tree.iter_prefix(&prefix) {
+            let (key, _) = item?;
+            tree.remove(&key)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::FjallStore;
+
+    #[test]
+    fn test_usage_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FjallStore::new(dir.path().to_path_buf(), Some(1), None);
+        let meta_store = MetaStore::new(store, Some(1));
+        let history = UsageHistory::new(&meta_store);
+
+        let usage = |objects| BucketUsage {
+            objects,
+            logical_bytes: objects * 10,
+            physical_bytes: objects * 5,
+        };
+        history.record("bucket", "2026-01-02", usage(2)).unwrap();
+        history.record("bucket", "2026-01-01", usage(1)).unwrap();
+        history.record("bucket", "2026-01-02", usage(3)).unwrap();
+        history.record("bucket2", "2026-01-01", usage(7)).unwrap();
+
+        let samples = history.samples("bucket").unwrap();
+        assert_eq!(
+            samples,
+            vec![
+                UsageSample {
+                    day: "2026-01-01".to_string(),
+                    usage: usage(1)
+                },
+                UsageSample {
+                    day: "2026-01-02".to_string(),
+                    usage: usage(3)
+                },
+            ]
+        );
+
+        history.remove("bucket").unwrap();
+        assert!(history.samples("bucket").unwrap().is_empty());
+        assert_eq!(history.samples("bucket2").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,261 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Configuration of an [`AdaptiveWriteLimiter`]
+#[derive(Debug, Clone)]
+pub struct WriteLimiterConfig {
+    /// Lower bound for the amount of concurrent block writes
+    pub min_concurrency: usize,
+    /// Upper bound for the amount of concurrent block writes
+    pub max_concurrency: usize,
+    /// Amount of concurrent block writes to start with
+    pub initial_concurrency: usize,
+    /// Average write latency above which the limit is decreased
+    pub target_latency: Duration,
+    /// Factor applied to the limit on overload (multiplicative decrease)
+    pub backoff: f64,
+    /// Amount of completed writes between two adjustments of the limit
+    pub window: usize,
+}
+
+impl Default for WriteLimiterConfig {
+    fn default() -> Self {
+        Self {
+            min_concurrency: 1,
+            max_concurrency: 32,
+            initial_concurrency: 4,
+            target_latency: Duration::from_millis(50),
+            backoff: 0.5,
+            window: 16,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    limit: usize,
+    // permits which must be forgotten when they are released, to shrink the limit
+    pending_shrink: usize,
+    samples: usize,
+    latency_sum: Duration,
+    errors: usize,
+}
+
+/// Limits the amount of concurrent block writes to a storage backend, adjusting
+/// the limit based on observed write latency and errors (AIMD).
+///
+/// After every `window` completed writes the average latency is compared to the
+/// target: if it is below the target and no errors occurred, the limit grows by
+/// one; otherwise it is multiplied by `backoff`. This way fast storage (NVMe)
+/// ramps up to many concurrent writes while slow storage (spinning disks) settles
+/// on a few.
+#[derive(Debug)]
+pub struct AdaptiveWriteLimiter {
+    config: WriteLimiterConfig,
+    semaphore: Semaphore,
+    state: Mutex<LimiterState>,
+}
+
+/// A permit to write a single block. Report the outcome with [`WritePermit::complete`].
+pub struct WritePermit<'a> {
+    limiter: &'a AdaptiveWriteLimiter,
+    permit: Option<SemaphorePermit<'a>>,
+}
+
+impl AdaptiveWriteLimiter {
+    pub fn new(mut config: WriteLimiterConfig) -> Self {
+        config.min_concurrency = config.min_concurrency.max(1);
+        config.max_concurrency = config.max_concurrency.max(config.min_concurrency);
+        config.initial_concurrency = config
+            .initial_concurrency
+            .clamp(config.min_concurrency, config.max_concurrency);
+        config.window = config.window.max(1);
+
+        Self {
+            semaphore: Semaphore::new(config.initial_concurrency),
+            state: Mutex::new(LimiterState {
+                limit: config.initial_concurrency,
+                pending_shrink: 0,
+                samples: 0,
+                latency_sum: Duration::ZERO,
+                errors: 0,
+            }),
+            config,
+        }
+    }
+
+    /// Current concurrency limit
+    pub fn limit(&self) -> usize {
+        self.state.lock().expect("limiter lock is not poisoned").limit
+    }
+
+    /// Upper bound of the concurrency limit
+    pub fn max_concurrency(&self) -> usize {
+        self.config.max_concurrency
+    }
+
+    /// Wait until a block write is allowed to start
+    pub async fn acquire(&self) -> WritePermit<'_> {
+        let permit = self
+            .semaphore
+            .acquire()
+            .await
+            .expect("write limiter semaphore is never closed");
+        WritePermit {
+            limiter: self,
+            permit: Some(permit),
+        }
+    }
+
+    fn record(&self, latency: Duration, success: bool) {
+        let mut state = self.state.lock().expect("limiter lock is not poisoned");
+        state.samples += 1;
+        state.latency_sum += latency;
+        if !success {
+            state.errors += 1;
+        }
+        if state.samples < self.config.window {
+            return;
+        }
+
+        let avg = state.latency_sum / state.samples as u32;
+        let overloaded = state.errors > 0 || avg > self.config.target_latency;
+        state.samples = 0;
+        state.latency_sum = Duration::ZERO;
+        state.errors = 0;
+
+        let new_limit = if overloaded {
+            ((state.limit as f64 * self.config.backoff) as usize).max(self.config.min_concurrency)
+        } else {
+            (state.limit + 1).min(self.config.max_concurrency)
+        };
+
+        if new_limit > state.limit {
+            let mut grow = new_limit - state.limit;
+            // first cancel outstanding shrinks before handing out new permits
+            let cancelled = grow.min(state.pending_shrink);
+            state.pending_shrink -= cancelled;
+            grow -= cancelled;
+            self.semaphore.add_permits(grow);
+        } else if new_limit < state.limit {
+            state.pending_shrink += state.limit - new_limit;
+            // reclaim idle permits right away, the others are forgotten on release
+            while state.pending_shrink > 0 {
+                match self.semaphore.try_acquire() {
+                    Ok(permit) => {
+                        permit.forget();
+                        state.pending_shrink -= 1;
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+
+        if new_limit != state.limit {
+            tracing::debug!(
+                old_limit = state.limit,
+                new_limit,
+                avg_latency_ms = avg.as_millis() as u64,
+                "Adjusted block write concurrency"
+            );
+            state.limit = new_limit;
+        }
+    }
+
+    fn release(&self, permit: SemaphorePermit<'_>) {
+        let mut state = self.state.lock().expect("limiter lock is not poisoned");
+        if state.pending_shrink > 0 {
+            state.pending_shrink -= 1;
+            permit.forget();
+        }
+    }
+}
+
+impl WritePermit<'_> {
+    /// Report the latency and outcome of the write and release the permit.
+    pub fn complete(mut self, latency: Duration, success: bool) {
+        self.limiter.record(latency, success);
+        if let Some(permit) = self.permit.take() {
+            self.limiter.release(permit);
+        }
+    }
+}
+
+impl Drop for WritePermit<'_> {
+    fn drop(&mut self) {
+        // Permit dropped without an outcome (e.g. the upload was aborted)
+        if let Some(permit) = self.permit.take() {
+            self.limiter.release(permit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> WriteLimiterConfig {
+        WriteLimiterConfig {
+            min_concurrency: 1,
+            max_concurrency: 8,
+            initial_concurrency: 4,
+            target_latency: Duration::from_millis(10),
+            backoff: 0.5,
+            window: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_increase_on_low_latency() {
+        let limiter = AdaptiveWriteLimiter::new(config());
+        for _ in 0..2 {
+            limiter
+                .acquire()
+                .await
+                .complete(Duration::from_millis(1), true);
+        }
+        assert_eq!(limiter.limit(), 5);
+        assert_eq!(limiter.semaphore.available_permits(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_decrease_on_high_latency_and_errors() {
+        let limiter = AdaptiveWriteLimiter::new(config());
+        for _ in 0..2 {
+            limiter
+                .acquire()
+                .await
+                .complete(Duration::from_millis(100), true);
+        }
+        assert_eq!(limiter.limit(), 2);
+        assert_eq!(limiter.semaphore.available_permits(), 2);
+
+        limiter.acquire().await.complete(Duration::from_millis(1), false);
+        limiter.acquire().await.complete(Duration::from_millis(1), true);
+        assert_eq!(limiter.limit(), 1);
+        assert_eq!(limiter.semaphore.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_limit_stays_within_bounds() {
+        let limiter = AdaptiveWriteLimiter::new(config());
+        for _ in 0..100 {
+            limiter
+                .acquire()
+                .await
+                .complete(Duration::from_millis(1), true);
+        }
+        assert_eq!(limiter.limit(), 8);
+
+        for _ in 0..100 {
+            limiter
+                .acquire()
+                .await
+                .complete(Duration::from_secs(1), true);
+        }
+        assert_eq!(limiter.limit(), 1);
+        assert_eq!(limiter.semaphore.available_permits(), 1);
+    }
+}
@@ -15,19 +15,14 @@
 //! ## Example: Single-User Storage
 //!
 //! ```no_run
-//! use cas_storage::{CasFS, StorageEngine, Durability};
-//! use std::path::PathBuf;
+//! use cas_storage::{CasFSBuilder, StorageEngine, Durability};
 //!
 //! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
-//! // Create storage instance
-//! let casfs = CasFS::new(
-//!     PathBuf::from("./data/blocks"),
-//!     PathBuf::from("./data/meta"),
-//!     Default::default(),  // metrics
-//!     StorageEngine::Fjall,
-//!     None,  // inline_metadata_size
-//!     Some(Durability::Fsync),
-//! );
+//! // Create storage instance, blocks are stored in ./data/blocks
+//! let casfs = CasFSBuilder::new("./data", "./data/meta")
+//!     .storage_engine(StorageEngine::Fjall)
+//!     .durability(Durability::Fsync)
+//!     .build()?;
 //!
 //! // Create bucket
 //! casfs.create_bucket("my-bucket")?;
@@ -46,7 +41,7 @@
 //! ## Example: Multi-User Storage
 //!
 //! ```no_run
-//! use cas_storage::{SharedBlockStore, CasFS, StorageEngine, Durability};
+//! use cas_storage::{SharedBlockStore, CasFSBuilder, StorageEngine, Durability};
 //! use std::path::PathBuf;
 //! use std::sync::Arc;
 //!
@@ -60,18 +55,10 @@
 //! )?);
 //!
 //! // Create per-user CasFS instances
-//! let user1_casfs = CasFS::new_multi_user(
-//!     PathBuf::from("./data/blocks"),
-//!     PathBuf::from("./data/meta/user_alice"),
-//!     shared.block_tree(),
-//!     shared.path_tree(),
-//!     shared.multipart_tree(),
-//!     shared.meta_store(),
-//!     Default::default(),
-//!     StorageEngine::Fjall,
-//!     None,
-//!     Some(Durability::Fsync),
-//! );
+//! let user1_casfs = CasFSBuilder::new("./data", "./data/meta/user_alice")
+//!     .shared_block_store(Arc::clone(&shared))
+//!     .durability(Durability::Fsync)
+//!     .build()?;
 //! # Ok(())
 //! # }
 //! ```
@@ -83,22 +70,84 @@ pub mod metrics;
 // Re-export main types from metastore
 pub use metastore::{
     // Metadata structures
-    Block, BlockID, BucketMeta, Object, ObjectData, ObjectType,
+    Block, BlockID, BucketCounters, BucketLimits, BucketMeta, CannedAcl, ETag, LimitExceeded,
+    Object, ObjectAttributes, ObjectData, ObjectTags, ObjectType, PrefixCount, StoreCounters,
+    TagFilter,
     // Storage abstractions
-    BaseMetaTree, BlockTree, MetaError, MetaStore, MetaTreeExt, Store, Transaction,
+    BaseMetaTree, BlockTree, MetaError, MetaStore, MetaTreeExt, MetaTreeSnapshot, Store,
+    Transaction,
     // Storage backends
     Durability, FjallStore, FjallStoreNotx,
+    // Key-value separation of bucket partitions
+    BlobStats, KvSeparation,
+    // Lifecycle rules of buckets
+    BucketLifecycle, Expiration, LifecycleRule, ObjectExpiration,
+    // Server access logging of buckets
+    BucketLogging,
 };
 
 // Re-export main types from cas
 pub use cas::{
     // Core storage
-    CasFS, SharedBlockStore, StorageEngine,
+    CasFS, SharedBlockStore, StorageEngine, DEFAULT_WRITE_CONCURRENCY,
+    // Construction
+    BuildError, CasFSBuilder, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE,
     // Multipart support
     multipart::{MultiPart, MultiPartTree},
     // Streaming and utilities
     block_stream::BlockStream,
-    range_request::{RangeRequest, parse_range_request},
+    range_request::{RangeRequest, parse_range_request, parse_multi_range_request, MAX_RANGES},
+    ByteRanges, ByteRangesStream,
+    // Write throttling
+    AdaptiveWriteLimiter, WriteLimiterConfig,
+    // Syncs of the writes of many requests together
+    GroupSync, GroupSyncConfig,
+    // Snapshot-consistent listings
+    ListSnapshots, DEFAULT_LIST_SNAPSHOT_LIFETIME, DEFAULT_MAX_LIST_SNAPSHOTS,
+    // Metadata caching
+    MetaCache,
+    // Blocking metadata operations
+    MetaExecutor, DEFAULT_META_THREADS,
+    // Time spent by a request in the metadata store and on disk
+    PhaseTimings,
+    // Hashing of uploads on a thread pool
+    HashPool, StreamHasher,
+    // Single process access to a store
+    StoreLock, StoreLockError,
+    // Bucket usage reports and store statistics
+    BucketUsage, StoreStats, UsageHistory, UsageSample, STATS_HISTORY_TREE,
+    // Request rates of the buckets to find hot spots
+    Access, ActivityCounts, ActivityTracker, BucketActivity, ACTIVITY_WINDOW_MINUTES,
+    BUCKET_ACTIVITY_TREE,
+    // Metadata size accounting
+    MetaSizeHistory, MetadataSize, TreeSize, TreeSizeSample, META_SIZE_HISTORY_TREE,
+    // Engine of the metadata stores and the migration to the other one
+    migrate_store, recorded_engine, MigrationStats, ENGINE_FILE_NAME,
+    // Ingest of unchanged and hard linked local files without hashing
+    FileId, FileIdCache, FILE_IDS_TREE,
+    // Corrupted block remediation
+    CorruptBlock, CorruptBlocks, CORRUPT_BLOCKS_TREE,
+    // Objects using corrupt blocks
+    DegradedObject, DegradedObjects, DEGRADED_OBJECTS_TREE,
+    // Delayed removal of the files of deleted blocks
+    DeleteQueue, DeleteQueueStats, QueuedBlock, DELETE_QUEUE_TREE,
+    // Progress of long-running maintenance jobs
+    JobRecord, JobStatus, JobStore, JOBS_TREE,
+    // Block identity and ETags
+    ContentHash, ContentHasher,
+    // Notifications of object mutations
+    ObjectEventHandler,
+    // Block placement by key prefix
+    Placement, MAX_LOCATION_NAME,
+    // Metadata-only bucket migration
+    ManifestBlock, ManifestEntry,
+    // Objects assembled from blocks uploaded by clients
+    STAGED_BLOCKS_PREFIX,
+    // Uploads continued after an interruption
+    ResumableUpload, ResumableUploads, RESUMABLE_UPLOADS_TREE,
+    // Results of retried requests
+    IdempotencyKeys, IdempotentResult, IDEMPOTENCY_KEYS_TREE, IDEMPOTENCY_KEY_LIFETIME,
+    MAX_IDEMPOTENCY_KEY_LEN,
 };
 
 // Re-export metrics types
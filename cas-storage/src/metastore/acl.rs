@@ -0,0 +1,88 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use super::FsError;
+
+/// `CannedAcl` is the access control list of a bucket or an object.
+///
+/// Only the canned ACLs which can be enforced without a grant model are supported:
+/// - `private`: only the owner has access
+/// - `public-read`: the owner has full control, anyone can read
+///
+/// The ACL of a bucket is the default for the objects in it which don't have their
+/// own ACL.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CannedAcl {
+    #[default]
+    Private,
+    PublicRead,
+}
+
+impl CannedAcl {
+    /// Returns the canned ACL name as used in the `x-amz-acl` header.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CannedAcl::Private => "private",
+            CannedAcl::PublicRead => "public-read",
+        }
+    }
+
+    /// Returns `true` if anonymous users are allowed to read.
+    pub fn is_public_read(&self) -> bool {
+        matches!(self, CannedAcl::PublicRead)
+    }
+
+    /// Serializes the ACL to a byte vector.
+    pub fn to_vec(&self) -> Vec<u8> {
+        match self {
+            CannedAcl::Private => vec![0],
+            CannedAcl::PublicRead => vec![1],
+        }
+    }
+}
+
+impl fmt::Display for CannedAcl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CannedAcl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "private" => Ok(CannedAcl::Private),
+            "public-read" => Ok(CannedAcl::PublicRead),
+            _ => Err(format!("Unsupported canned ACL: {s}")),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for CannedAcl {
+    type Error = FsError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value {
+            [0] => Ok(CannedAcl::Private),
+            [1] => Ok(CannedAcl::PublicRead),
+            _ => Err(FsError::MalformedObject),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        for acl in [CannedAcl::Private, CannedAcl::PublicRead] {
+            assert_eq!(CannedAcl::try_from(acl.to_vec().as_slice()).unwrap(), acl);
+            assert_eq!(acl.as_str().parse::<CannedAcl>().unwrap(), acl);
+        }
+        assert!("public-read-write".parse::<CannedAcl>().is_err());
+        assert!(CannedAcl::try_from(&[2u8][..]).is_err());
+    }
+}
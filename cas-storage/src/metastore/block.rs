@@ -4,7 +4,7 @@ use std::{
     path::PathBuf,
 };
 
-use super::{FsError, PTR_SIZE};
+use super::{FsError, MetaError, PTR_SIZE};
 
 /// Size of a block identifier in bytes (16 bytes, equivalent to an MD5 hash)
 pub const BLOCKID_SIZE: usize = 16;
@@ -14,23 +14,32 @@ pub const BLOCKID_SIZE: usize = 16;
 /// BlockID is used throughout the system to uniquely identify data blocks
 pub type BlockID = [u8; BLOCKID_SIZE];
 
+/// Size of the serialized reference count, the same as a pointer on 64 bit
+/// platforms, where blocks were serialized with a `usize` reference count
+const RC_SIZE: usize = std::mem::size_of::<u64>();
+
 /// `Block` represents metadata about a stored data block in the content-addressable storage system.
 ///
 /// Each Block contains:
 /// - The size of the actual data
 /// - A path to locate the block in the storage hierarchy
 /// - A reference count (rc) tracking how many objects reference this block
+/// - The storage location holding the block file, if it isn't the default one
 ///
 /// The path is stored as a variable-length byte array, which could be optimized in the future.
+/// The storage location is only serialized if it is set, so blocks written before
+/// locations existed stay readable.
 // TODO: this can be optimized by making path a `[u8;BLOCKID_SIZE]` and keeping track of a len u8
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Block {
     /// Size of the block data in bytes
     size: usize,
     /// Path to the block in the storage hierarchy
     path: Vec<u8>,
     /// Reference count - how many objects reference this block
-    rc: usize,
+    rc: u64,
+    /// Name of the storage location holding the block file, `None` for the default one
+    location: Option<String>,
 }
 
 /// Implements serialization of a Block to a byte vector
@@ -44,6 +53,10 @@ impl From<&Block> for Vec<u8> {
         out.extend_from_slice(&(b.path.len() as u8).to_le_bytes());
         out.extend_from_slice(&b.path);
         out.extend_from_slice(&b.rc.to_le_bytes());
+        if let Some(location) = &b.location {
+            out.extend_from_slice(&(location.len() as u8).to_le_bytes());
+            out.extend_from_slice(location.as_bytes());
+        }
         out
     }
 }
@@ -60,19 +73,31 @@ impl TryFrom<&[u8]> for Block {
 
         let vec_size =
             u8::from_le_bytes(value[PTR_SIZE..PTR_SIZE + 1].try_into().unwrap()) as usize;
-        if value.len() < PTR_SIZE + 1 + vec_size {
+        // the path has at least one byte, `disk_path` relies on it
+        if vec_size == 0 || value.len() < PTR_SIZE + 1 + vec_size {
             return Err(FsError::MalformedObject);
         }
         let path = value[PTR_SIZE + 1..PTR_SIZE + 1 + vec_size].to_vec();
 
-        if value.len() != PTR_SIZE * 2 + 1 + vec_size {
+        let rc_end = PTR_SIZE + 1 + vec_size + RC_SIZE;
+        if value.len() < rc_end {
             return Err(FsError::MalformedObject);
         }
+        let rc = u64::from_le_bytes(value[PTR_SIZE + 1 + vec_size..rc_end].try_into().unwrap());
+
+        let location = match &value[rc_end..] {
+            [] => None,
+            [len, name @ ..] if name.len() == *len as usize => Some(
+                String::from_utf8(name.to_vec()).map_err(|_| FsError::MalformedObject)?,
+            ),
+            _ => return Err(FsError::MalformedObject),
+        };
 
         Ok(Block {
             size,
             path,
-            rc: usize::from_le_bytes(value[PTR_SIZE + 1 + vec_size..].try_into().unwrap()),
+            rc,
+            location,
         })
     }
 }
@@ -87,7 +112,26 @@ impl Block {
     /// # Returns
     /// A new Block instance with reference count set to 1
     pub fn new(size: usize, path: Vec<u8>) -> Self {
-        Self { size, path, rc: 1 }
+        Self {
+            size,
+            path,
+            rc: 1,
+            location: None,
+        }
+    }
+
+    /// Stores the block file in the storage location named `location` instead of
+    /// the default one. Names are at most 255 bytes long.
+    pub fn with_location(mut self, location: Option<String>) -> Self {
+        debug_assert!(location.as_ref().map_or(true, |name| name.len() <= u8::MAX as usize));
+        self.location = location;
+        self
+    }
+
+    /// Returns the name of the storage location holding the block file, `None` for
+    /// the default one
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
     }
 
     /// Returns the size of the block data in bytes
@@ -124,22 +168,49 @@ impl Block {
     }
 
     /// Returns the current reference count of the block
-    pub fn rc(&self) -> usize {
+    pub fn rc(&self) -> u64 {
         self.rc
     }
 
     /// Increments the reference count of the block
     ///
     /// This is called when a new object references this block
-    pub fn increment_refcount(&mut self) {
-        self.rc += 1
+    ///
+    /// # Errors
+    /// `MetaError::RefcountOverflow` if the count can't grow, it is left unchanged
+    pub fn increment_refcount(&mut self) -> Result<(), MetaError> {
+        self.add_refcount(1)
     }
 
-    /// Decrements the reference count of the block
+    /// Adds `count` references to the block at once
+    ///
+    /// # Errors
+    /// `MetaError::RefcountOverflow` if the count would wrap around, it is left
+    /// unchanged
+    pub fn add_refcount(&mut self, count: u64) -> Result<(), MetaError> {
+        self.rc = self
+            .rc
+            .checked_add(count)
+            .ok_or(MetaError::RefcountOverflow)?;
+        Ok(())
+    }
+
+    /// Decrements the reference count of the block, saturating at 0
     ///
     /// This is called when an object that referenced this block is deleted
-    pub fn decrement_refcount(&mut self) {
-        self.rc -= 1
+    ///
+    /// # Returns
+    /// `false` if the count was already 0, so more references were released
+    /// than counted
+    #[must_use]
+    pub fn decrement_refcount(&mut self) -> bool {
+        match self.rc.checked_sub(1) {
+            Some(rc) => {
+                self.rc = rc;
+                true
+            }
+            None => false,
+        }
     }
 
     /// Serializes the block to a byte vector
@@ -150,3 +221,97 @@ impl Block {
         self.into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_location_serialization() {
+        let block = Block::new(10, vec![1, 2]);
+        let decoded = Block::try_from(&*block.to_vec()).unwrap();
+        assert_eq!(decoded.location(), None);
+        assert_eq!(decoded.rc(), 1);
+
+        let mut block = Block::new(10, vec![1, 2]).with_location(Some("ssd".to_string()));
+        block.increment_refcount().unwrap();
+        let raw = block.to_vec();
+        let decoded = Block::try_from(&*raw).unwrap();
+        assert_eq!(decoded.location(), Some("ssd"));
+        assert_eq!(decoded.rc(), 2);
+        assert_eq!(decoded.path(), &[1, 2]);
+
+        assert!(Block::try_from(&raw[..raw.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_refcount_bounds() {
+        let mut block = Block::new(10, vec![1]);
+        block.rc = u64::MAX - 1;
+        block.increment_refcount().unwrap();
+        assert_eq!(block.rc(), u64::MAX);
+        assert!(matches!(
+            block.increment_refcount(),
+            Err(MetaError::RefcountOverflow)
+        ));
+        assert!(matches!(
+            block.add_refcount(2),
+            Err(MetaError::RefcountOverflow)
+        ));
+        assert_eq!(block.rc(), u64::MAX);
+
+        block.rc = 1;
+        assert!(block.decrement_refcount());
+        assert_eq!(block.rc(), 0);
+        assert!(!block.decrement_refcount());
+        assert_eq!(block.rc(), 0);
+    }
+
+    #[test]
+    fn test_block_empty_path() {
+        let mut raw = 10usize.to_le_bytes().to_vec();
+        raw.push(0);
+        raw.extend_from_slice(&1usize.to_le_bytes());
+        assert!(Block::try_from(&*raw).is_err());
+    }
+
+    mod proptests {
+        use super::*;
+        use proptest::collection::vec;
+        use proptest::prelude::*;
+
+        prop_compose! {
+            fn block()(
+                size in any::<usize>(),
+                path in vec(any::<u8>(), 1..=BLOCKID_SIZE),
+                rc in any::<u64>(),
+                location in proptest::option::of("[a-z0-9_-]{0,32}"),
+            ) -> Block {
+                let mut block = Block::new(size, path).with_location(location);
+                block.rc = rc;
+                block
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn roundtrip(block in block()) {
+                let raw = block.to_vec();
+                let decoded = Block::try_from(&*raw).unwrap();
+                prop_assert_eq!(decoded.size(), block.size());
+                prop_assert_eq!(decoded.path(), block.path());
+                prop_assert_eq!(decoded.rc(), block.rc());
+                prop_assert_eq!(decoded.location(), block.location());
+                prop_assert_eq!(decoded.to_vec(), raw);
+            }
+
+            #[test]
+            fn arbitrary_bytes(raw in vec(any::<u8>(), 0..64)) {
+                if let Ok(decoded) = Block::try_from(&*raw) {
+                    decoded.disk_path(PathBuf::from("/blocks"));
+                    prop_assert_eq!(decoded.to_vec(), raw);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+
+use super::{BlockID, BLOCKID_SIZE};
+
+/// Tree holding the optional reverse index from blocks to the objects using them
+pub const BLOCK_REFS_TREE: &str = "_BLOCK_REFS";
+
+/// Tree holding the objects by their content hash, maintained with the block
+/// reference index. Its entries have the same layout, keyed by the content hash
+/// instead of a block id.
+pub const OBJECT_HASHES_TREE: &str = "_OBJECT_HASHES";
+
+/// Key of the entry marking the index as complete, in both trees. It is shorter
+/// than a block id, so it never matches the prefix of a block.
+pub(crate) const BLOCK_REFS_COMPLETE_KEY: &[u8] = b"complete";
+
+/// An object referencing a block
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct BlockRef {
+    pub bucket: String,
+    pub key: String,
+}
+
+/// Index entries are keyed by `<block id><bucket>\0<key>`, with an empty value. An
+/// object using a block several times has a single entry for it.
+pub(crate) fn block_ref_key(block: &BlockID, bucket: &str, key: &str) -> Vec<u8> {
+    let mut ref_key = Vec::with_capacity(BLOCKID_SIZE + bucket.len() + key.len() + 1);
+    ref_key.extend_from_slice(block);
+    ref_key.extend_from_slice(bucket.as_bytes());
+    ref_key.push(0);
+    ref_key.extend_from_slice(key.as_bytes());
+    ref_key
+}
+
+/// The object of an index entry of `block_ref_key`
+pub(crate) fn parse_block_ref_key(ref_key: &[u8]) -> Option<BlockRef> {
+    let object = ref_key.get(BLOCKID_SIZE..)?;
+    let separator = object.iter().position(|b| *b == 0)?;
+    Some(BlockRef {
+        bucket: String::from_utf8_lossy(&object[..separator]).into_owned(),
+        key: String::from_utf8_lossy(&object[separator + 1..]).into_owned(),
+    })
+}
+
+/// The distinct blocks of an object
+pub(crate) fn distinct_blocks(blocks: &[BlockID]) -> BTreeSet<&BlockID> {
+    blocks.iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_ref_key() {
+        let block = [7; BLOCKID_SIZE];
+        let ref_key = block_ref_key(&block, "bucket", "dir/with\0zero");
+        assert!(ref_key.starts_with(&block));
+        assert_eq!(
+            parse_block_ref_key(&ref_key),
+            Some(BlockRef {
+                bucket: "bucket".to_string(),
+                key: "dir/with\0zero".to_string(),
+            })
+        );
+        assert_eq!(parse_block_ref_key(BLOCK_REFS_COMPLETE_KEY), None);
+    }
+}
@@ -1,17 +1,86 @@
 use std::{
     convert::{TryFrom, TryInto},
+    fmt,
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use chrono::Utc;
+use serde::{Deserialize, Serialize};
 
-use super::{FsError, PTR_SIZE};
+use super::{BucketCounters, FsError, ObjectTags, PTR_SIZE};
+
+/// Length of the serialized limits: a flags byte and both limits
+const LIMITS_SIZE: usize = 1 + 8 + 8;
+const HAS_MAX_OBJECTS: u8 = 1;
+const HAS_MAX_BYTES: u8 = 2;
+
+/// Caps on the contents of a bucket, set by its owner.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BucketLimits {
+    /// Maximum amount of objects
+    pub max_objects: Option<u64>,
+    /// Maximum sum of the object sizes
+    pub max_bytes: Option<u64>,
+}
+
+/// The limit of a bucket an object can't be stored because of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Objects(u64),
+    Bytes(u64),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitExceeded::Objects(max) => {
+                write!(f, "Bucket object limit of {max} objects reached")
+            }
+            LimitExceeded::Bytes(max) => write!(f, "Bucket size limit of {max} bytes exceeded"),
+        }
+    }
+}
+
+impl BucketLimits {
+    /// Returns `true` if no limit is set
+    pub fn is_empty(&self) -> bool {
+        self.max_objects.is_none() && self.max_bytes.is_none()
+    }
+
+    /// Checks storing an object of `size` bytes in a bucket with `counters`.
+    /// `replaced` is the size of the object with the same key, which doesn't
+    /// count anymore once it's overwritten.
+    ///
+    /// # Returns
+    /// The exceeded limit, `None` if the object can be stored
+    pub fn check(
+        &self,
+        counters: &BucketCounters,
+        replaced: Option<u64>,
+        size: u64,
+    ) -> Option<LimitExceeded> {
+        if let Some(max) = self.max_objects {
+            if replaced.is_none() && counters.objects >= max {
+                return Some(LimitExceeded::Objects(max));
+            }
+        }
+        if let Some(max) = self.max_bytes {
+            let bytes = counters.logical_bytes.saturating_sub(replaced.unwrap_or(0));
+            if bytes.saturating_add(size) > max {
+                return Some(LimitExceeded::Bytes(max));
+            }
+        }
+        None
+    }
+}
 
 /// `BucketMeta` represents metadata for a storage bucket.
 ///
 /// This struct stores essential information about a bucket, including:
 /// - Creation time (ctime) as a Unix timestamp
 /// - The bucket name as a string
+/// - The limits of its contents
+/// - Its tags, e.g. the project or team it is billed to
 ///
 /// BucketMeta is used to track and manage buckets in the storage system.
 #[derive(Debug)]
@@ -20,6 +89,10 @@ pub struct BucketMeta {
     ctime: i64,
     /// Name of the bucket
     name: String,
+    /// Limits of the contents of the bucket
+    limits: BucketLimits,
+    /// Tags of the bucket, as set with `PutBucketTagging`
+    tags: ObjectTags,
 }
 
 impl BucketMeta {
@@ -34,6 +107,8 @@ impl BucketMeta {
         Self {
             ctime: Utc::now().timestamp(),
             name,
+            limits: BucketLimits::default(),
+            tags: ObjectTags::default(),
         }
     }
 
@@ -42,7 +117,7 @@ impl BucketMeta {
     /// # Returns
     /// The creation time as a SystemTime instance
     pub fn ctime(&self) -> SystemTime {
-        UNIX_EPOCH + std::time::Duration::from_secs(self.ctime as u64)
+        UNIX_EPOCH + std::time::Duration::from_secs(self.ctime.max(0) as u64)
     }
 
     /// Returns the name of the bucket.
@@ -53,6 +128,26 @@ impl BucketMeta {
         &self.name
     }
 
+    /// Returns the limits of the contents of the bucket.
+    pub fn limits(&self) -> BucketLimits {
+        self.limits
+    }
+
+    /// Replaces the limits of the contents of the bucket.
+    pub fn set_limits(&mut self, limits: BucketLimits) {
+        self.limits = limits;
+    }
+
+    /// Returns the tags of the bucket.
+    pub fn tags(&self) -> &ObjectTags {
+        &self.tags
+    }
+
+    /// Replaces the tags of the bucket.
+    pub fn set_tags(&mut self, tags: ObjectTags) {
+        self.tags = tags;
+    }
+
     /// Serializes the bucket metadata to a byte vector.
     ///
     /// # Returns
@@ -68,19 +163,38 @@ impl BucketMeta {
 /// - 8 bytes for the creation time (i64)
 /// - PTR_SIZE bytes for the length of the name
 /// - The name bytes
+/// - If limits or tags are set, a flags byte telling which limits are set,
+///   followed by 8 bytes for the maximum amount of objects and 8 bytes for the
+///   maximum size (u64)
+/// - If tags are set, the tags as serialized by [`ObjectTags::to_vec`]
 impl From<&BucketMeta> for Vec<u8> {
     fn from(b: &BucketMeta) -> Self {
-        let mut out = Vec::with_capacity(8 + PTR_SIZE + b.name.len());
+        let tags = b.tags.to_vec();
+        let mut out = Vec::with_capacity(8 + PTR_SIZE + b.name.len() + LIMITS_SIZE + tags.len());
         out.extend_from_slice(&b.ctime.to_le_bytes());
         out.extend_from_slice(&b.name.len().to_le_bytes());
         out.extend_from_slice(b.name.as_bytes());
+        if !b.limits.is_empty() || !tags.is_empty() {
+            let mut flags = 0;
+            if b.limits.max_objects.is_some() {
+                flags |= HAS_MAX_OBJECTS;
+            }
+            if b.limits.max_bytes.is_some() {
+                flags |= HAS_MAX_BYTES;
+            }
+            out.push(flags);
+            out.extend_from_slice(&b.limits.max_objects.unwrap_or(0).to_le_bytes());
+            out.extend_from_slice(&b.limits.max_bytes.unwrap_or(0).to_le_bytes());
+        }
+        out.extend_from_slice(&tags);
         out
     }
 }
 
 /// Implements deserialization of BucketMeta from a byte slice.
 ///
-/// This implementation validates the input format and extracts the creation time and name.
+/// This implementation validates the input format and extracts the creation time, name,
+/// limits and tags. Buckets written before limits or tags existed have none.
 impl TryFrom<&[u8]> for BucketMeta {
     type Error = FsError;
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
@@ -88,13 +202,195 @@ impl TryFrom<&[u8]> for BucketMeta {
             return Err(FsError::MalformedObject);
         }
         let name_len = usize::from_le_bytes(value[8..8 + PTR_SIZE].try_into().unwrap());
-        if value.len() != 8 + PTR_SIZE + name_len {
-            return Err(FsError::MalformedObject);
-        }
+        let name_end = name_len
+            .checked_add(8 + PTR_SIZE)
+            .ok_or(FsError::MalformedObject)?;
+        let (limits, tags) = match value.len().checked_sub(name_end) {
+            Some(0) => (BucketLimits::default(), ObjectTags::default()),
+            Some(len) if len >= LIMITS_SIZE => {
+                let flags = value[name_end];
+                let limit = |offset: usize, flag: u8| {
+                    let start = name_end + 1 + offset;
+                    (flags & flag != 0)
+                        .then(|| u64::from_le_bytes(value[start..start + 8].try_into().unwrap()))
+                };
+                let limits = BucketLimits {
+                    max_objects: limit(0, HAS_MAX_OBJECTS),
+                    max_bytes: limit(8, HAS_MAX_BYTES),
+                };
+                let tags = ObjectTags::try_from(&value[name_end + LIMITS_SIZE..])?;
+                (limits, tags)
+            }
+            _ => return Err(FsError::MalformedObject),
+        };
         Ok(BucketMeta {
             ctime: i64::from_le_bytes(value[..8].try_into().unwrap()),
-            // SAFETY: this is safe because we only store valid strings in the first place.
-            name: unsafe { String::from_utf8_unchecked(value[8 + PTR_SIZE..].to_vec()) },
+            name: String::from_utf8(value[8 + PTR_SIZE..name_end].to_vec())
+                .map_err(|_| FsError::MalformedObject)?,
+            limits,
+            tags,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_roundtrip() {
+        let mut meta = BucketMeta::new("scratch".to_string());
+        // without limits the format is unchanged
+        assert_eq!(meta.to_vec().len(), 8 + PTR_SIZE + 7);
+        assert_eq!(
+            BucketMeta::try_from(&*meta.to_vec()).unwrap().limits(),
+            BucketLimits::default()
+        );
+
+        for limits in [
+            BucketLimits {
+                max_objects: Some(10),
+                max_bytes: None,
+            },
+            BucketLimits {
+                max_objects: None,
+                max_bytes: Some(0),
+            },
+            BucketLimits {
+                max_objects: Some(3),
+                max_bytes: Some(1 << 40),
+            },
+        ] {
+            meta.set_limits(limits);
+            let decoded = BucketMeta::try_from(&*meta.to_vec()).unwrap();
+            assert_eq!(decoded.name(), "scratch");
+            assert_eq!(decoded.limits(), limits);
+        }
+
+        let mut data = meta.to_vec();
+        data.pop();
+        assert!(BucketMeta::try_from(&*data).is_err());
+    }
+
+    #[test]
+    fn test_tags_roundtrip() {
+        let mut meta = BucketMeta::new("billing".to_string());
+        let tags = ObjectTags::for_bucket(vec![
+            ("team".to_string(), "ops".to_string()),
+            ("project".to_string(), "".to_string()),
+        ])
+        .unwrap();
+        meta.set_tags(tags.clone());
+        let decoded = BucketMeta::try_from(&*meta.to_vec()).unwrap();
+        assert_eq!(decoded.tags(), &tags);
+        assert_eq!(decoded.limits(), BucketLimits::default());
+
+        let limits = BucketLimits {
+            max_objects: Some(5),
+            max_bytes: None,
+        };
+        meta.set_limits(limits);
+        let decoded = BucketMeta::try_from(&*meta.to_vec()).unwrap();
+        assert_eq!(decoded.tags(), &tags);
+        assert_eq!(decoded.limits(), limits);
+
+        // without tags the format is the one of the limits
+        meta.set_tags(ObjectTags::default());
+        assert_eq!(meta.to_vec().len(), 8 + PTR_SIZE + 7 + LIMITS_SIZE);
+
+        let mut data = BucketMeta::try_from(&*meta.to_vec()).unwrap().to_vec();
+        data.extend_from_slice(&[1, 0]);
+        assert!(BucketMeta::try_from(&*data).is_err());
+    }
+
+    #[test]
+    fn test_limits_check() {
+        let limits = BucketLimits {
+            max_objects: Some(2),
+            max_bytes: Some(100),
+        };
+        let counters = BucketCounters {
+            objects: 2,
+            logical_bytes: 90,
+        };
+        assert_eq!(
+            limits.check(&counters, None, 1),
+            Some(LimitExceeded::Objects(2))
+        );
+        // overwriting an object doesn't add one
+        assert_eq!(limits.check(&counters, Some(40), 50), None);
+        assert_eq!(
+            limits.check(&counters, Some(40), 51),
+            Some(LimitExceeded::Bytes(100))
+        );
+
+        let counters = BucketCounters {
+            objects: 1,
+            logical_bytes: 90,
+        };
+        assert_eq!(limits.check(&counters, None, 10), None);
+        assert_eq!(
+            limits.check(&counters, None, 11),
+            Some(LimitExceeded::Bytes(100))
+        );
+        assert_eq!(
+            BucketLimits::default().check(&counters, None, u64::MAX),
+            None
+        );
+    }
+
+    #[test]
+    fn test_malformed_name() {
+        let mut raw = 0i64.to_le_bytes().to_vec();
+        raw.extend_from_slice(&usize::MAX.to_le_bytes());
+        assert!(BucketMeta::try_from(&*raw).is_err());
+
+        let mut raw = 0i64.to_le_bytes().to_vec();
+        raw.extend_from_slice(&2usize.to_le_bytes());
+        raw.extend_from_slice(&[0xff, 0xfe]);
+        assert!(BucketMeta::try_from(&*raw).is_err());
+    }
+
+    mod proptests {
+        use super::*;
+        use proptest::collection::vec;
+        use proptest::prelude::*;
+
+        prop_compose! {
+            fn bucket_meta()(
+                ctime in any::<i64>(),
+                name in any::<String>(),
+                max_objects in any::<Option<u64>>(),
+                max_bytes in any::<Option<u64>>(),
+            ) -> BucketMeta {
+                let mut meta = BucketMeta::new(name);
+                meta.ctime = ctime;
+                meta.set_limits(BucketLimits { max_objects, max_bytes });
+                meta
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn roundtrip(meta in bucket_meta()) {
+                let raw = meta.to_vec();
+                let decoded = BucketMeta::try_from(&*raw).unwrap();
+                prop_assert_eq!(decoded.ctime, meta.ctime);
+                prop_assert_eq!(decoded.name(), meta.name());
+                prop_assert_eq!(decoded.limits(), meta.limits());
+                prop_assert_eq!(decoded.to_vec(), raw);
+                decoded.ctime();
+            }
+
+            #[test]
+            fn arbitrary_bytes(raw in vec(any::<u8>(), 0..64)) {
+                if let Ok(decoded) = BucketMeta::try_from(&*raw) {
+                    decoded.ctime();
+                    let canonical = decoded.to_vec();
+                    let again = BucketMeta::try_from(&*canonical).unwrap();
+                    prop_assert_eq!(again.to_vec(), canonical);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use serde::Serialize;
+
+use super::{Block, Object};
+
+/// Tree holding the counters of a metadata store
+pub const COUNTERS_TREE: &str = "_COUNTERS";
+
+/// Key of the entry marking the counters as complete, they are rebuilt from all
+/// objects and blocks if it is missing. Counters completed before the bucket
+/// counters or the prefix counts were added are marked with `complete` or
+/// `complete_v2`, and are rebuilt as well.
+pub(crate) const COUNTERS_COMPLETE_KEY: &[u8] = b"complete_v3";
+
+/// Fields of the counters of a bucket
+pub(crate) const BUCKET_FIELDS: [&[u8]; 2] = [b"objects", b"logical_bytes"];
+
+/// Counters of the objects and blocks in a metadata store, maintained by the
+/// transactions writing them.
+///
+/// In multi-user mode the blocks are counted in the shared store, and the objects
+/// in the store of every user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StoreCounters {
+    /// Amount of objects
+    pub objects: u64,
+    /// Sum of the object sizes
+    pub logical_bytes: u64,
+    /// Amount of objects stored inlined in the metadata
+    pub inline_objects: u64,
+    /// Sum of the sizes of the inlined objects
+    pub inline_bytes: u64,
+    /// Amount of blocks
+    pub blocks: u64,
+    /// Sum of the block sizes
+    pub block_bytes: u64,
+}
+
+/// Counters of the objects in a bucket, maintained like the [`StoreCounters`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct BucketCounters {
+    /// Amount of objects
+    pub objects: u64,
+    /// Sum of the object sizes
+    pub logical_bytes: u64,
+}
+
+impl BucketCounters {
+    /// The counter stored under `field`, see [`BUCKET_FIELDS`].
+    pub(crate) fn field_mut(&mut self, field: &[u8]) -> Option<&mut u64> {
+        Some(match field {
+            b"objects" => &mut self.objects,
+            b"logical_bytes" => &mut self.logical_bytes,
+            _ => return None,
+        })
+    }
+}
+
+/// Key of the counter `field` of `bucket`. Bucket names don't contain a `/`.
+pub(crate) fn bucket_counter_key(bucket: &str, field: &[u8]) -> Vec<u8> {
+    [&b"bucket/"[..], bucket.as_bytes(), b"/", field].concat()
+}
+
+/// Amount of objects in a directory at the top level of a bucket: the keys
+/// starting with `prefix`, their first path segment up to and including its `/`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PrefixCount {
+    pub prefix: String,
+    pub objects: u64,
+}
+
+/// Key of the count of the objects of `bucket` under the top level directory
+/// `prefix`. With an empty `prefix`, the start of the counts of the bucket.
+pub(crate) fn prefix_counter_key(bucket: &str, prefix: &[u8]) -> Vec<u8> {
+    [&b"prefix/"[..], bucket.as_bytes(), b"/", prefix].concat()
+}
+
+/// The top level directory of `key`, `None` for keys without a `/`.
+fn top_level_prefix(key: &[u8]) -> Option<&[u8]> {
+    let slash = key.iter().position(|b| *b == b'/')?;
+    Some(&key[..=slash])
+}
+
+impl StoreCounters {
+    /// The counter stored under `field`, None for other keys of the tree.
+    pub(crate) fn field_mut(&mut self, field: &[u8]) -> Option<&mut u64> {
+        Some(match field {
+            b"objects" => &mut self.objects,
+            b"logical_bytes" => &mut self.logical_bytes,
+            b"inline_objects" => &mut self.inline_objects,
+            b"inline_bytes" => &mut self.inline_bytes,
+            b"blocks" => &mut self.blocks,
+            b"block_bytes" => &mut self.block_bytes,
+            _ => return None,
+        })
+    }
+}
+
+/// Changes of the counters made by a transaction, applied when it is committed.
+#[derive(Debug, Default)]
+pub(crate) struct CounterDeltas {
+    objects: i64,
+    logical_bytes: i64,
+    inline_objects: i64,
+    inline_bytes: i64,
+    blocks: i64,
+    block_bytes: i64,
+    // objects and logical bytes per bucket
+    buckets: HashMap<String, (i64, i64)>,
+    // objects per top level directory, by the key of their count
+    prefixes: HashMap<Vec<u8>, i64>,
+}
+
+impl CounterDeltas {
+    /// Count the object `key` of `bucket` as added (`sign` 1) or removed (`sign` -1).
+    pub fn object(&mut self, bucket: &str, key: &[u8], obj: &Object, sign: i64) {
+        if let Some(prefix) = top_level_prefix(key) {
+            *self
+                .prefixes
+                .entry(prefix_counter_key(bucket, prefix))
+                .or_default() += sign;
+        }
+        self.objects += sign;
+        self.logical_bytes += sign * obj.size() as i64;
+        let (objects, bytes) = match self.buckets.get_mut(bucket) {
+            Some(deltas) => deltas,
+            None => self.buckets.entry(bucket.to_string()).or_default(),
+        };
+        *objects += sign;
+        *bytes += sign * obj.size() as i64;
+        if let Some(data) = obj.inlined() {
+            self.inline_objects += sign;
+            self.inline_bytes += sign * data.len() as i64;
+        }
+    }
+
+    /// Count a block as added (`sign` 1) or removed (`sign` -1).
+    pub fn block(&mut self, block: &Block, sign: i64) {
+        self.blocks += sign;
+        self.block_bytes += sign * block.size() as i64;
+    }
+
+    /// The changed counters and their deltas.
+    pub fn fields(&self) -> impl Iterator<Item = (&'static [u8], i64)> {
+        IntoIterator::into_iter([
+            (&b"objects"[..], self.objects),
+            (&b"logical_bytes"[..], self.logical_bytes),
+            (&b"inline_objects"[..], self.inline_objects),
+            (&b"inline_bytes"[..], self.inline_bytes),
+            (&b"blocks"[..], self.blocks),
+            (&b"block_bytes"[..], self.block_bytes),
+        ])
+        .filter(|(_, delta)| *delta != 0)
+    }
+
+    /// The keys of the changed bucket counters and their deltas.
+    pub fn bucket_fields(&self) -> impl Iterator<Item = (Vec<u8>, i64)> + '_ {
+        self.buckets
+            .iter()
+            .flat_map(|(bucket, (objects, bytes))| {
+                [
+                    (bucket_counter_key(bucket, BUCKET_FIELDS[0]), *objects),
+                    (bucket_counter_key(bucket, BUCKET_FIELDS[1]), *bytes),
+                ]
+            })
+            .filter(|(_, delta)| *delta != 0)
+    }
+
+    /// The keys of the changed prefix counts and their deltas.
+    pub fn prefix_fields(&self) -> impl Iterator<Item = (&[u8], i64)> + '_ {
+        self.prefixes
+            .iter()
+            .map(|(key, delta)| (key.as_slice(), *delta))
+            .filter(|(_, delta)| *delta != 0)
+    }
+}
+
+/// Adds `delta` to a stored counter value, a missing value is 0.
+pub(crate) fn apply_delta(value: Option<&[u8]>, delta: i64) -> Vec<u8> {
+    let current = value
+        .and_then(|value| value.try_into().ok())
+        .map(u64::from_le_bytes)
+        .unwrap_or(0);
+    current.saturating_add_signed(delta).to_le_bytes().to_vec()
+}
+
+/// Reads a stored counter value.
+pub(crate) fn decode(value: &[u8]) -> u64 {
+    value.try_into().map(u64::from_le_bytes).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::{ObjectData, BLOCKID_SIZE};
+
+    #[test]
+    fn test_counter_deltas() {
+        let inline = Object::new(
+            3,
+            [0; BLOCKID_SIZE],
+            ObjectData::Inline { data: vec![1; 3] },
+        );
+        let blocks = Object::new(
+            10,
+            [1; BLOCKID_SIZE],
+            ObjectData::SinglePart {
+                blocks: vec![[2; BLOCKID_SIZE]],
+            },
+        );
+        let block = Block::new(10, vec![2]);
+
+        let mut deltas = CounterDeltas::default();
+        deltas.object("a", b"x", &inline, 1);
+        deltas.object("b", b"dir/x", &blocks, 1);
+        deltas.block(&block, 1);
+        let mut counters = StoreCounters::default();
+        for (field, delta) in deltas.fields() {
+            *counters.field_mut(field).unwrap() = delta as u64;
+        }
+        assert_eq!(
+            counters,
+            StoreCounters {
+                objects: 2,
+                logical_bytes: 13,
+                inline_objects: 1,
+                inline_bytes: 3,
+                blocks: 1,
+                block_bytes: 10,
+            }
+        );
+
+        let mut bucket_fields: Vec<_> = deltas.bucket_fields().collect();
+        bucket_fields.sort();
+        assert_eq!(
+            bucket_fields,
+            vec![
+                (b"bucket/a/logical_bytes".to_vec(), 3),
+                (b"bucket/a/objects".to_vec(), 1),
+                (b"bucket/b/logical_bytes".to_vec(), 10),
+                (b"bucket/b/objects".to_vec(), 1),
+            ]
+        );
+
+        let prefix_fields: Vec<_> = deltas.prefix_fields().collect();
+        assert_eq!(prefix_fields, vec![(&b"prefix/b/dir/"[..], 1)]);
+
+        // replacing an object leaves the count of its directory
+        let mut deltas = CounterDeltas::default();
+        deltas.object("a", b"dir/x", &blocks, 1);
+        deltas.object("a", b"dir/x", &inline, -1);
+        assert_eq!(deltas.prefix_fields().count(), 0);
+        let fields: Vec<_> = deltas.fields().collect();
+        assert_eq!(
+            fields,
+            vec![
+                (&b"logical_bytes"[..], 7),
+                (&b"inline_objects"[..], -1),
+                (&b"inline_bytes"[..], -3),
+            ]
+        );
+        let bucket_fields: Vec<_> = deltas.bucket_fields().collect();
+        assert_eq!(bucket_fields, vec![(b"bucket/a/logical_bytes".to_vec(), 7)]);
+
+        let value = apply_delta(None, 5);
+        assert_eq!(decode(&apply_delta(Some(&value), -2)), 3);
+        // counters don't go below 0
+        assert_eq!(decode(&apply_delta(Some(&value), -7)), 0);
+    }
+}
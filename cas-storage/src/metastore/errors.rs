@@ -41,6 +41,9 @@ pub enum MetaError {
     TransactionError(String),
     PersistError(String),
     BlockNotFound,
+    /// Adding references to a block would overflow its reference count
+    RefcountOverflow,
+    InvalidArgument(String),
     OtherDBError(String),
 }
 
@@ -61,6 +64,8 @@ impl fmt::Display for MetaError {
             MetaError::TransactionError(ref s) => write!(f, "Transaction error: {s}"),
             MetaError::PersistError(ref s) => write!(f, "Persist error: {s}"),
             MetaError::BlockNotFound => write!(f, "Block not found"),
+            MetaError::RefcountOverflow => write!(f, "Block reference count overflow"),
+            MetaError::InvalidArgument(ref s) => write!(f, "Invalid argument: {s}"),
             MetaError::OtherDBError(ref s) => write!(f, "Other DB error: {s}"),
         }
     }
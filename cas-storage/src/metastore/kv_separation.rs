@@ -0,0 +1,89 @@
+//! Key-value separation of the bucket partitions, which moves large values (objects
+//! with inlined data) out of the LSM tree into blob files.
+
+use serde::Serialize;
+
+/// Tree recording the partitions created with key-value separation. fjall only
+/// applies the partition options when a partition is created, so a store keeps
+/// track of which partitions have blob files to collect.
+pub const KV_SEPARATED_TREE: &str = "_KV_SEPARATED";
+
+/// Key-value separation options of the bucket partitions created by a store.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KvSeparation {
+    /// Values of at least this size are written to blob files
+    pub threshold: u32,
+    /// Target size of the blob files
+    pub file_target_size: u64,
+    /// Rewrite blob files until the blob space amplification is below this factor
+    pub gc_space_amp_target: f32,
+    /// Rewrite blob files with at least this ratio of stale bytes
+    pub gc_staleness_threshold: f32,
+}
+
+impl Default for KvSeparation {
+    fn default() -> Self {
+        Self {
+            threshold: 4096,
+            file_target_size: 64 * 1024 * 1024,
+            gc_space_amp_target: 2.0,
+            gc_staleness_threshold: 0.5,
+        }
+    }
+}
+
+/// Blob file utilization of a key-value separated partition.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BlobStats {
+    pub tree: String,
+    pub blob_files: usize,
+    pub stale_blob_files: usize,
+    pub total_bytes: u64,
+    pub stale_bytes: u64,
+}
+
+impl BlobStats {
+    /// Ratio of the blob bytes which are no longer referenced.
+    pub fn stale_ratio(&self) -> f32 {
+        if self.total_bytes == 0 {
+            return 0.0;
+        }
+        self.stale_bytes as f32 / self.total_bytes as f32
+    }
+
+    /// Blob bytes on disk per live blob byte.
+    pub fn space_amp(&self) -> f32 {
+        let live = self.total_bytes - self.stale_bytes;
+        if live == 0 {
+            return 0.0;
+        }
+        self.total_bytes as f32 / live as f32
+    }
+}
+
+/// Bucket partitions are named after their bucket, internal trees start with `_`.
+pub fn is_bucket_tree(name: &str) -> bool {
+    !name.starts_with('_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_stats_ratios() {
+        let stats = BlobStats {
+            tree: "bucket".to_string(),
+            blob_files: 4,
+            stale_blob_files: 1,
+            total_bytes: 1000,
+            stale_bytes: 500,
+        };
+        assert_eq!(stats.stale_ratio(), 0.5);
+        assert_eq!(stats.space_amp(), 2.0);
+        assert_eq!(BlobStats::default().space_amp(), 0.0);
+
+        assert!(is_bucket_tree("photos"));
+        assert!(!is_bucket_tree(KV_SEPARATED_TREE));
+    }
+}
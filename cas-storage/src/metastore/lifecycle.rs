@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+
+use super::{MetaError, ObjectTags, TagFilter};
+
+/// Maximum amount of rules in the lifecycle configuration of a bucket
+pub const MAX_LIFECYCLE_RULES: usize = 1000;
+/// Maximum length of the id of a lifecycle rule, in characters
+pub const MAX_RULE_ID_LENGTH: usize = 255;
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// When the objects of a rule expire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Expiration {
+    /// Days after the object was written, rounded up to the next midnight UTC
+    Days(u32),
+    /// At a midnight UTC, in seconds since the UNIX epoch
+    Date(i64),
+}
+
+/// An expiration rule of a bucket. The rule applies to the objects with a key
+/// starting with `prefix` which have all the `tags`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LifecycleRule {
+    pub id: String,
+    /// Disabled rules are kept, but don't apply to any object
+    pub enabled: bool,
+    pub prefix: String,
+    pub tags: Vec<TagFilter>,
+    pub expiration: Expiration,
+}
+
+impl LifecycleRule {
+    /// Returns `true` if the rule applies to the object `key` with `tags`.
+    pub fn matches(&self, key: &str, tags: &ObjectTags) -> bool {
+        self.enabled
+            && key.starts_with(&self.prefix)
+            && self.tags.iter().all(|filter| filter.matches(tags))
+    }
+
+    /// Returns when an object written at `mtime`, in seconds since the UNIX
+    /// epoch, expires by this rule.
+    pub fn expiry_date(&self, mtime: i64) -> i64 {
+        match self.expiration {
+            Expiration::Days(days) => {
+                let expires = mtime + i64::from(days) * SECS_PER_DAY;
+                (expires.div_euclid(SECS_PER_DAY) + 1) * SECS_PER_DAY
+            }
+            Expiration::Date(date) => date,
+        }
+    }
+}
+
+/// The expiration of an object by the lifecycle rules of its bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectExpiration {
+    /// The rule expiring the object first
+    pub rule_id: String,
+    /// Seconds since the UNIX epoch
+    pub expiry_date: i64,
+}
+
+/// `BucketLifecycle` holds the expiration rules of a bucket, as set with
+/// `PutBucketLifecycleConfiguration`.
+///
+/// The rules are reported to clients, in the `x-amz-expiration` header of the
+/// objects they apply to, and to the HTTP UI.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BucketLifecycle {
+    rules: Vec<LifecycleRule>,
+}
+
+impl BucketLifecycle {
+    /// Creates a lifecycle configuration, checking its rules.
+    ///
+    /// # Arguments
+    /// * `rules` - The expiration rules
+    ///
+    /// # Returns
+    /// The configuration, or a description of the invalid rule
+    pub fn new(rules: Vec<LifecycleRule>) -> Result<Self, String> {
+        if rules.is_empty() || rules.len() > MAX_LIFECYCLE_RULES {
+            return Err(format!(
+                "A lifecycle configuration needs 1 to {MAX_LIFECYCLE_RULES} rules"
+            ));
+        }
+        for (i, rule) in rules.iter().enumerate() {
+            if rule.id.is_empty() || rule.id.chars().count() > MAX_RULE_ID_LENGTH {
+                return Err(format!("Invalid rule id: '{}'", rule.id));
+            }
+            if rules[..i].iter().any(|other| other.id == rule.id) {
+                return Err(format!("Rule id '{}' is used twice", rule.id));
+            }
+            match rule.expiration {
+                Expiration::Days(0) => {
+                    return Err(format!(
+                        "Rule '{}': Days must be a positive integer",
+                        rule.id
+                    ))
+                }
+                Expiration::Date(date) if date.rem_euclid(SECS_PER_DAY) != 0 => {
+                    return Err(format!("Rule '{}': Date must be at midnight UTC", rule.id))
+                }
+                _ => {}
+            }
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn rules(&self) -> &[LifecycleRule] {
+        &self.rules
+    }
+
+    /// Returns `true` if a rule filters on tags, so the tags of an object are
+    /// needed to find its expiration.
+    pub fn has_tag_filters(&self) -> bool {
+        self.rules.iter().any(|rule| !rule.tags.is_empty())
+    }
+
+    /// Returns the first expiration of the object `key` with `tags`, written at
+    /// `mtime` in seconds since the UNIX epoch, `None` if no rule applies to it.
+    pub fn expiration(&self, key: &str, tags: &ObjectTags, mtime: i64) -> Option<ObjectExpiration> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(key, tags))
+            .map(|rule| ObjectExpiration {
+                rule_id: rule.id.clone(),
+                expiry_date: rule.expiry_date(mtime),
+            })
+            .min_by_key(|expiration| expiration.expiry_date)
+    }
+
+    /// Serializes the configuration as JSON.
+    pub fn to_vec(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("a lifecycle configuration serializes")
+    }
+
+    /// Deserializes a configuration stored by [`BucketLifecycle::to_vec`].
+    pub fn from_slice(data: &[u8]) -> Result<Self, MetaError> {
+        serde_json::from_slice(data)
+            .map_err(|e| MetaError::OtherDBError(format!("Invalid lifecycle configuration: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(id: &str, prefix: &str, expiration: Expiration) -> LifecycleRule {
+        LifecycleRule {
+            id: id.to_string(),
+            enabled: true,
+            prefix: prefix.to_string(),
+            tags: Vec::new(),
+            expiration,
+        }
+    }
+
+    #[test]
+    fn test_expiry_date() {
+        // 2014-01-15 10:30:00 UTC
+        let mtime = 1_389_781_800;
+        // 2014-01-19 00:00:00 UTC
+        assert_eq!(
+            rule("r", "", Expiration::Days(3)).expiry_date(mtime),
+            1_390_089_600
+        );
+        assert_eq!(
+            rule("r", "", Expiration::Date(1_390_089_600)).expiry_date(mtime),
+            1_390_089_600
+        );
+    }
+
+    #[test]
+    fn test_expiration() {
+        let mut tmp = rule("tmp", "", Expiration::Days(1));
+        tmp.tags = vec![TagFilter::new("tmp", "true")];
+        let mut disabled = rule("disabled", "", Expiration::Days(1));
+        disabled.enabled = false;
+        let lifecycle = BucketLifecycle::new(vec![
+            rule("logs", "logs/", Expiration::Days(30)),
+            tmp,
+            disabled,
+        ])
+        .unwrap();
+        assert!(lifecycle.has_tag_filters());
+
+        let none = ObjectTags::default();
+        let tagged = ObjectTags::new(vec![("tmp".to_string(), "true".to_string())]).unwrap();
+        let expiration = lifecycle.expiration("logs/a", &none, 0).unwrap();
+        assert_eq!(expiration.rule_id, "logs");
+        assert_eq!(expiration.expiry_date, 31 * SECS_PER_DAY);
+        // the rule expiring the object first wins
+        let expiration = lifecycle.expiration("logs/a", &tagged, 0).unwrap();
+        assert_eq!(expiration.rule_id, "tmp");
+        assert_eq!(lifecycle.expiration("data/a", &none, 0), None);
+
+        let decoded = BucketLifecycle::from_slice(&lifecycle.to_vec()).unwrap();
+        assert_eq!(decoded, lifecycle);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(BucketLifecycle::new(Vec::new()).is_err());
+        assert!(BucketLifecycle::new(vec![rule("", "", Expiration::Days(1))]).is_err());
+        assert!(BucketLifecycle::new(vec![rule("r", "", Expiration::Days(0))]).is_err());
+        assert!(BucketLifecycle::new(vec![rule("r", "", Expiration::Date(100))]).is_err());
+        assert!(BucketLifecycle::new(vec![
+            rule("r", "a/", Expiration::Days(1)),
+            rule("r", "b/", Expiration::Days(2)),
+        ])
+        .is_err());
+    }
+}
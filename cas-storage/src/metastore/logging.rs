@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+use super::MetaError;
+
+/// `BucketLogging` is the server access logging configuration of a bucket, as
+/// set with `PutBucketLogging`: the records of the requests to the bucket are
+/// written as objects with keys starting with `target_prefix` to `target_bucket`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BucketLogging {
+    pub target_bucket: String,
+    pub target_prefix: String,
+}
+
+impl BucketLogging {
+    /// Serializes the configuration as JSON.
+    pub fn to_vec(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("a logging configuration serializes")
+    }
+
+    /// Deserializes a configuration stored by [`BucketLogging::to_vec`].
+    pub fn from_slice(data: &[u8]) -> Result<Self, MetaError> {
+        serde_json::from_slice(data)
+            .map_err(|e| MetaError::OtherDBError(format!("Invalid logging configuration: {e}")))
+    }
+}
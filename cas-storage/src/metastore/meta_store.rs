@@ -1,9 +1,26 @@
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::time::Instant;
+
+use bytes::Bytes;
+
+use super::block_refs::{
+    block_ref_key, distinct_blocks, parse_block_ref_key, BlockRef, BLOCK_REFS_COMPLETE_KEY,
+    BLOCK_REFS_TREE, OBJECT_HASHES_TREE,
+};
+use super::counters::{
+    self, bucket_counter_key, prefix_counter_key, BucketCounters, CounterDeltas, PrefixCount,
+    StoreCounters, BUCKET_FIELDS, COUNTERS_COMPLETE_KEY, COUNTERS_TREE,
+};
+use crate::cas::request_timings;
+use crate::metrics::SharedMetrics;
 
 use super::{
-    BaseMetaTree, Block, BlockID, BucketMeta, MetaError, MetaTreeExt, Object, Store, BLOCKID_SIZE,
+    BaseMetaTree, BlobStats, Block, BlockID, BucketLifecycle, BucketLimits, BucketLogging,
+    BucketMeta, CannedAcl, Durability, MetaError, MetaTreeExt, Object, ObjectTags, Store,
+    TagFilter, BLOCKID_SIZE, KV_SEPARATED_TREE,
 };
 
 /// `MetaStore` is a struct that provides methods to interact with the metadata store.
@@ -15,6 +32,9 @@ use super::{
 pub struct MetaStore {
     store: Arc<dyn Store>,
     inlined_metadata_size: usize,
+    block_refs: bool,
+    bucket_durability: Arc<HashMap<String, Durability>>,
+    metrics: SharedMetrics,
 }
 
 /// Default tree names used by the MetaStore
@@ -22,6 +42,47 @@ pub struct MetaStore {
 const DEFAULT_BUCKET_TREE: &str = "_BUCKETS";
 const DEFAULT_BLOCK_TREE: &str = "_BLOCKS";
 const DEFAULT_PATH_TREE: &str = "_PATHS";
+const DEFAULT_ACL_TREE: &str = "_ACLS";
+const DEFAULT_TAGS_TREE: &str = "_TAGS";
+const BUCKET_ENCRYPTION_TREE: &str = "_BUCKET_ENCRYPTION";
+const BUCKET_LIFECYCLE_TREE: &str = "_BUCKET_LIFECYCLE";
+const BUCKET_LOGGING_TREE: &str = "_BUCKET_LOGGING";
+const BUCKET_REQUEST_PAYMENT_TREE: &str = "_BUCKET_REQUEST_PAYMENT";
+
+/// Number of objects deleted per transaction when a bucket is dropped
+const DROP_BUCKET_BATCH_SIZE: usize = 1000;
+
+/// Number of objects moved per transaction when a key prefix is renamed
+const RENAME_PREFIX_BATCH_SIZE: usize = 1000;
+
+// Keys in the tags tree: the tag set of an object is stored under
+// `o<bucket>\0<key>`, and every tag has an index entry
+// `i<bucket>\0<tag key>\0<tag value>\0<key>` with an empty value.
+const TAGS_OBJECT_PREFIX: u8 = b'o';
+const TAGS_INDEX_PREFIX: u8 = b'i';
+
+/// `ObjectAttributes` are the attributes of an object which are written in the
+/// same transaction as its metadata, so a new object is never visible with those
+/// of the object it replaces. Attributes which aren't set are kept.
+#[derive(Debug, Default, Clone)]
+pub struct ObjectAttributes {
+    acl: Option<Option<CannedAcl>>,
+    tags: Option<ObjectTags>,
+}
+
+impl ObjectAttributes {
+    /// Replaces the ACL of the object. Without an ACL the object follows its bucket.
+    pub fn with_acl(mut self, acl: Option<CannedAcl>) -> Self {
+        self.acl = Some(acl);
+        self
+    }
+
+    /// Replaces the tags of the object, an empty set removes them.
+    pub fn with_tags(mut self, tags: ObjectTags) -> Self {
+        self.tags = Some(tags);
+        self
+    }
+}
 
 impl MetaStore {
     /// Creates a new MetaStore instance with the given store implementation.
@@ -38,9 +99,251 @@ impl MetaStore {
         Self {
             store: Arc::new(store),
             inlined_metadata_size: inlined_metadata_size.unwrap_or(DEFAULT_INLINED_METADATA_SIZE),
+            block_refs: false,
+            bucket_durability: Arc::default(),
+            metrics: SharedMetrics::default(),
         }
     }
 
+    /// Reports the anomalies of block reference counts found by transactions to
+    /// `metrics`.
+    ///
+    /// # Arguments
+    /// * `metrics` - The metrics collector
+    pub fn set_metrics(&mut self, metrics: SharedMetrics) {
+        self.metrics = metrics;
+    }
+
+    /// Enables or disables the block reference index, which maps every block to
+    /// the objects using it, and the content hash of every object to the objects
+    /// with it.
+    ///
+    /// Enabling the index on a store where it is not complete rebuilds it from all
+    /// objects. Disabling it marks the index as incomplete, since later writes
+    /// don't update it, so it is rebuilt when enabled again.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether object writes and deletions maintain the index
+    ///
+    /// # Returns
+    /// Success or an error if the index can't be rebuilt or updated
+    pub fn set_block_refs(&mut self, enabled: bool) -> Result<(), MetaError> {
+        let complete = self.has_block_refs()?;
+        if enabled && !complete {
+            tracing::info!("Building the block reference index");
+            self.rebuild_block_refs()?;
+        } else if !enabled && complete {
+            for tree in [BLOCK_REFS_TREE, OBJECT_HASHES_TREE] {
+                self.store
+                    .tree_open(tree)?
+                    .remove(BLOCK_REFS_COMPLETE_KEY)?;
+            }
+        }
+        self.block_refs = enabled;
+        Ok(())
+    }
+
+    /// Returns `true` if the block reference index is complete, so it can be
+    /// queried with `block_refs` and `objects_by_hash`.
+    pub fn has_block_refs(&self) -> Result<bool, MetaError> {
+        // stores indexed before objects were indexed by hash have no complete
+        // object hashes, and are rebuilt
+        for tree in [BLOCK_REFS_TREE, OBJECT_HASHES_TREE] {
+            if !self.store.tree_exists(tree)?
+                || !self
+                    .store
+                    .tree_open(tree)?
+                    .contains_key(BLOCK_REFS_COMPLETE_KEY)?
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // replaces the index with the references of all objects
+    fn rebuild_block_refs(&self) -> Result<(), MetaError> {
+        let tree = self.store.tree_ext_open(BLOCK_REFS_TREE)?;
+        let hashes = self.store.tree_ext_open(OBJECT_HASHES_TREE)?;
+        for index in [&tree, &hashes] {
+            for item in index.iter_all() {
+                let (ref_key, _) = item?;
+                index.remove(&ref_key)?;
+            }
+        }
+
+        let mut entries = 0;
+        for bucket in self.list_buckets()? {
+            let bucket_tree = self.get_bucket_ext(bucket.name())?;
+            for (key, obj) in bucket_tree.range_filter(None, None, None) {
+                for block in distinct_blocks(obj.blocks()) {
+                    tree.insert(&block_ref_key(block, bucket.name(), &key), Vec::new())?;
+                    entries += 1;
+                }
+                hashes.insert(&block_ref_key(obj.hash(), bucket.name(), &key), Vec::new())?;
+            }
+        }
+        tracing::info!(entries, "Built the block reference index");
+        hashes.insert(BLOCK_REFS_COMPLETE_KEY, Vec::new())?;
+        tree.insert(BLOCK_REFS_COMPLETE_KEY, Vec::new())
+    }
+
+    /// Lists the objects referencing a block, using the block reference index.
+    ///
+    /// # Arguments
+    /// * `block` - The id of the block
+    ///
+    /// # Returns
+    /// The objects using the block, sorted by bucket and key, or an error
+    pub fn block_refs(&self, block: &BlockID) -> Result<Vec<BlockRef>, MetaError> {
+        Self::index_refs(&*self.store.tree_ext_open(BLOCK_REFS_TREE)?, block)
+    }
+
+    /// Lists the objects with the content hash `hash`, using the block reference
+    /// index.
+    ///
+    /// # Arguments
+    /// * `hash` - The content hash of the objects, see `Object::hash`
+    ///
+    /// # Returns
+    /// The objects with the hash, sorted by bucket and key, or an error
+    pub fn objects_by_hash(&self, hash: &BlockID) -> Result<Vec<BlockRef>, MetaError> {
+        Self::index_refs(&*self.store.tree_ext_open(OBJECT_HASHES_TREE)?, hash)
+    }
+
+    // the objects of the entries of `tree` under `id`
+    fn index_refs(tree: &dyn MetaTreeExt, id: &BlockID) -> Result<Vec<BlockRef>, MetaError> {
+        let mut refs = Vec::new();
+        for item in tree.iter_prefix(id) {
+            let (ref_key, _) = item?;
+            if let Some(block_ref) = parse_block_ref_key(&ref_key) {
+                refs.push(block_ref);
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Rebuilds the counters from all objects and blocks if they are not complete,
+    /// like in a store written before they were maintained.
+    ///
+    /// # Returns
+    /// Success or an error if the counters can't be rebuilt
+    pub fn ensure_counters(&self) -> Result<(), MetaError> {
+        if self.store.tree_exists(COUNTERS_TREE)?
+            && self
+                .store
+                .tree_open(COUNTERS_TREE)?
+                .contains_key(COUNTERS_COMPLETE_KEY)?
+        {
+            return Ok(());
+        }
+        tracing::info!("Building the store counters");
+        self.rebuild_counters()
+    }
+
+    // replaces the counters with the ones of all objects and blocks
+    fn rebuild_counters(&self) -> Result<(), MetaError> {
+        let tree = self.store.tree_ext_open(COUNTERS_TREE)?;
+        for item in tree.iter_all() {
+            let (key, _) = item?;
+            tree.remove(&key)?;
+        }
+
+        let mut deltas = CounterDeltas::default();
+        for bucket in self.list_buckets()? {
+            let bucket_tree = self.get_bucket_ext(bucket.name())?;
+            for (key, obj) in bucket_tree.range_filter(None, None, None) {
+                deltas.object(bucket.name(), key.as_bytes(), &obj, 1);
+            }
+        }
+        for item in self.get_block_tree()?.iter_all() {
+            let (_, block) = item?;
+            deltas.block(&block, 1);
+        }
+        for (field, count) in deltas.fields() {
+            tree.insert(field, (count as u64).to_le_bytes().to_vec())?;
+        }
+        for (key, count) in deltas.bucket_fields() {
+            tree.insert(&key, (count as u64).to_le_bytes().to_vec())?;
+        }
+        for (key, count) in deltas.prefix_fields() {
+            tree.insert(key, (count as u64).to_le_bytes().to_vec())?;
+        }
+        tracing::info!(?deltas, "Built the store counters");
+        tree.insert(COUNTERS_COMPLETE_KEY, Vec::new())
+    }
+
+    /// Returns the counters of the objects and blocks in the store, see
+    /// [`StoreCounters`]. They are only complete after `ensure_counters`.
+    ///
+    /// # Returns
+    /// The counters or an error
+    pub fn counters(&self) -> Result<StoreCounters, MetaError> {
+        let mut result = StoreCounters::default();
+        if !self.store.tree_exists(COUNTERS_TREE)? {
+            return Ok(result);
+        }
+        let tree = self.store.tree_ext_open(COUNTERS_TREE)?;
+        for item in tree.iter_all() {
+            let (key, value) = item?;
+            if let Some(field) = result.field_mut(&key) {
+                *field = counters::decode(&value);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the counters of the objects in a bucket, see [`BucketCounters`].
+    /// They are only complete after `ensure_counters`.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    ///
+    /// # Returns
+    /// The counters or an error
+    pub fn bucket_counters(&self, bucket: &str) -> Result<BucketCounters, MetaError> {
+        let mut result = BucketCounters::default();
+        if !self.store.tree_exists(COUNTERS_TREE)? {
+            return Ok(result);
+        }
+        let tree = self.store.tree_open(COUNTERS_TREE)?;
+        for field in BUCKET_FIELDS {
+            if let Some(value) = tree.get(&bucket_counter_key(bucket, field))? {
+                *result.field_mut(field).expect("bucket counter field") = counters::decode(&value);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the directories at the top level of a bucket with the amount of
+    /// objects under them, in key order. Like the other counters they are
+    /// maintained by the writes, so listings can find the directories without
+    /// scanning their keys. They are only complete after `ensure_counters`.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    ///
+    /// # Returns
+    /// The directories and their object counts, or an error
+    pub fn prefix_counts(&self, bucket: &str) -> Result<Vec<PrefixCount>, MetaError> {
+        if !self.store.tree_exists(COUNTERS_TREE)? {
+            return Ok(Vec::new());
+        }
+        let start = prefix_counter_key(bucket, b"");
+        let mut result = Vec::new();
+        for item in self.store.tree_ext_open(COUNTERS_TREE)?.iter_prefix(&start) {
+            let (key, value) = item?;
+            let objects = counters::decode(&value);
+            if objects > 0 {
+                result.push(PrefixCount {
+                    prefix: String::from_utf8_lossy(&key[start.len()..]).into_owned(),
+                    objects,
+                });
+            }
+        }
+        Ok(result)
+    }
+
     /// Returns the maximum length of the data that can be inlined in the metadata object.
     ///
     /// Inlining small data directly in metadata can improve performance by reducing the number
@@ -105,154 +408,933 @@ impl MetaStore {
         Ok(BlockTree { tree })
     }
 
-    /// Returns a tree with the given name.
-    ///
-    /// This is typically used when the application needs to store custom metadata
-    /// for a specific purpose outside the standard bucket/object model.
+    /// Returns a tree with the given name.
+    ///
+    /// This is typically used when the application needs to store custom metadata
+    /// for a specific purpose outside the standard bucket/object model.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the tree to open
+    ///
+    /// # Returns
+    /// A tree instance or an error
+    pub fn get_tree(&self, name: &str) -> Result<Arc<dyn BaseMetaTree>, MetaError> {
+        self.store.tree_open(name)
+    }
+
+    /// Returns the path metadata tree.
+    ///
+    /// This tree is used to store file path metadata and path-related information.
+    ///
+    /// # Returns
+    /// A tree instance or an error
+    pub fn get_path_tree(&self) -> Result<Arc<dyn BaseMetaTree>, MetaError> {
+        self.store.tree_open(DEFAULT_PATH_TREE)
+    }
+
+    /// Checks if a bucket with the given name exists.
+    ///
+    /// # Arguments
+    /// * `bucket_name` - The name of the bucket to check
+    ///
+    /// # Returns
+    /// `true` if the bucket exists, `false` otherwise, or an error
+    pub fn bucket_exists(&self, bucket_name: &str) -> Result<bool, MetaError> {
+        self.store.tree_exists(bucket_name)
+    }
+
+    /// Deletes the bucket with the given name, and all objects in it.
+    ///
+    /// Like `delete_object` the block references of the objects are released,
+    /// so blocks only used by the bucket are freed. The objects are deleted in
+    /// batches of `DROP_BUCKET_BATCH_SIZE`, each batch in a single transaction,
+    /// before the bucket tree itself is dropped. An interrupted drop leaves a
+    /// consistent partial bucket, which can be dropped again.
+    ///
+    /// If the bucket doesn't exist, this operation is a no-op and returns success.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the bucket to delete
+    ///
+    /// # Returns
+    /// The blocks which are no longer referenced and must be removed from disk,
+    /// or an error if the deletion fails
+    pub fn drop_bucket(&self, name: &str) -> Result<Vec<Block>, MetaError> {
+        if !self.bucket_exists(name)? {
+            return Ok(vec![]);
+        }
+
+        let bucket_tree = self.get_bucket_ext(name)?;
+        let mut to_delete = Vec::new();
+        let mut object_count = 0;
+        loop {
+            // the deleted objects are gone, so every batch starts at the beginning
+            let batch = bucket_tree
+                .iter_all()
+                .take(DROP_BUCKET_BATCH_SIZE)
+                .collect::<Result<Vec<_>, _>>()?;
+            if batch.is_empty() {
+                break;
+            }
+
+            let mut tx = self.begin_bucket_transaction(name);
+            for (key, raw_object) in &batch {
+                let obj = Object::try_from(&**raw_object)
+                    .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+                tx.backend.remove(name, key)?;
+                tx.counters.object(name, key, &obj, -1);
+                if self.block_refs {
+                    let key = String::from_utf8_lossy(key);
+                    for block_id in distinct_blocks(obj.blocks()) {
+                        tx.backend
+                            .remove(BLOCK_REFS_TREE, &block_ref_key(block_id, name, &key))?;
+                    }
+                    tx.backend
+                        .remove(OBJECT_HASHES_TREE, &block_ref_key(obj.hash(), name, &key))?;
+                }
+                for block_id in obj.blocks() {
+                    if let Some(block) = tx.release_block(block_id)? {
+                        to_delete.push(block);
+                    }
+                }
+            }
+            tx.commit()?;
+
+            for (key, _) in &batch {
+                let key = String::from_utf8_lossy(key);
+                self.remove_acl(name, Some(&key))?;
+                self.remove_tags(name, &key)?;
+            }
+            object_count += batch.len();
+            tracing::debug!(
+                bucket = name,
+                objects = object_count,
+                "Deleted batch of bucket objects"
+            );
+        }
+
+        self.store.tree_delete(name)?;
+        // the counters of the bucket are 0 now
+        let counters = self.store.tree_open(COUNTERS_TREE)?;
+        for field in BUCKET_FIELDS {
+            counters.remove(&bucket_counter_key(name, field))?;
+        }
+        let prefix_keys = self
+            .store
+            .tree_ext_open(COUNTERS_TREE)?
+            .iter_prefix(&prefix_counter_key(name, b""))
+            .map(|item| item.map(|(key, _)| key))
+            .collect::<Result<Vec<_>, _>>()?;
+        for key in prefix_keys {
+            counters.remove(&key)?;
+        }
+        tracing::debug!(
+            bucket = name,
+            objects = object_count,
+            blocks_to_delete = to_delete.len(),
+            "Dropped bucket"
+        );
+        Ok(to_delete)
+    }
+
+    /// Lists the next batch of a prefix rename: at most `RENAME_PREFIX_BATCH_SIZE`
+    /// keys of a bucket starting with `from`, each with the same key with `to`
+    /// instead of `from`. The prefixes must not overlap. Moving every batch with
+    /// [`MetaStore::rename_objects`] until none is left renames the whole prefix;
+    /// an interrupted rename is continued the same way.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    /// * `from` - The prefix of the keys to move
+    /// * `to` - The prefix replacing it
+    ///
+    /// # Returns
+    /// The old and new keys of the batch, empty once no key starts with `from`,
+    /// or an error
+    pub fn prefix_renames(
+        &self,
+        bucket: &str,
+        from: &str,
+        to: &str,
+    ) -> Result<Vec<(String, String)>, MetaError> {
+        if from.is_empty() || from.starts_with(to) || to.starts_with(from) {
+            return Err(MetaError::InvalidArgument(format!(
+                "can't rename prefix '{from}' to '{to}', the prefixes overlap"
+            )));
+        }
+
+        // the moved objects leave the prefix, so every batch starts at the beginning
+        let batch = self
+            .get_bucket_ext(bucket)?
+            .iter_prefix(from.as_bytes())
+            .take(RENAME_PREFIX_BATCH_SIZE)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(batch
+            .iter()
+            .map(|(key, _)| {
+                let key = String::from_utf8_lossy(key).into_owned();
+                let new_key = format!("{to}{}", &key[from.len()..]);
+                (key, new_key)
+            })
+            .collect())
+    }
+
+    /// Moves objects of a bucket to new keys in a single transaction, committed
+    /// with the durability of the bucket. The caller holds the object locks of
+    /// the old and new keys.
+    ///
+    /// Only the metadata is moved: the objects keep their blocks, and their block
+    /// references, counters, ACLs and tags move with them. A batch with an object
+    /// whose new key is taken is refused before anything is moved. Old keys
+    /// without an object, deleted since they were listed, are skipped.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    /// * `renames` - The old and new key of every object to move
+    ///
+    /// # Returns
+    /// The old and new keys of the moved objects, or an error
+    pub fn rename_objects(
+        &self,
+        bucket: &str,
+        renames: &[(String, String)],
+    ) -> Result<Vec<(String, String)>, MetaError> {
+        let mut tx = self.begin_bucket_transaction(bucket);
+        for (_, new_key) in renames {
+            if tx.backend.get(bucket, new_key.as_bytes())?.is_some() {
+                tx.rollback();
+                return Err(MetaError::InvalidArgument(format!(
+                    "can't rename to '{new_key}', an object exists with that key"
+                )));
+            }
+        }
+
+        let mut renamed = Vec::with_capacity(renames.len());
+        for (key, new_key) in renames {
+            let Some(raw_object) = tx.backend.get(bucket, key.as_bytes())? else {
+                continue;
+            };
+            let obj = Object::try_from(&*raw_object)
+                .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+            tx.backend.remove(bucket, key.as_bytes())?;
+            tx.backend
+                .insert(bucket, new_key.as_bytes(), raw_object.to_vec())?;
+            tx.counters.object(bucket, key.as_bytes(), &obj, -1);
+            tx.counters.object(bucket, new_key.as_bytes(), &obj, 1);
+            if self.block_refs {
+                for block_id in distinct_blocks(obj.blocks()) {
+                    tx.backend
+                        .remove(BLOCK_REFS_TREE, &block_ref_key(block_id, bucket, key))?;
+                    tx.backend.insert(
+                        BLOCK_REFS_TREE,
+                        &block_ref_key(block_id, bucket, new_key),
+                        Vec::new(),
+                    )?;
+                }
+                tx.backend
+                    .remove(OBJECT_HASHES_TREE, &block_ref_key(obj.hash(), bucket, key))?;
+                tx.backend.insert(
+                    OBJECT_HASHES_TREE,
+                    &block_ref_key(obj.hash(), bucket, new_key),
+                    Vec::new(),
+                )?;
+            }
+            Self::rename_acl_and_tags(&mut tx, bucket, key, new_key)?;
+            renamed.push((key.clone(), new_key.clone()));
+        }
+        tx.commit()?;
+
+        tracing::debug!(bucket, objects = renamed.len(), "Renamed batch of objects");
+        Ok(renamed)
+    }
+
+    // move the ACL and the tags of an object, with their index entries, in `tx`
+    fn rename_acl_and_tags(
+        tx: &mut Transaction,
+        bucket: &str,
+        key: &str,
+        new_key: &str,
+    ) -> Result<(), MetaError> {
+        let acl_key = Self::acl_key(bucket, Some(key));
+        if let Some(acl) = tx.backend.get(DEFAULT_ACL_TREE, &acl_key)? {
+            tx.backend.remove(DEFAULT_ACL_TREE, &acl_key)?;
+            tx.backend.insert(
+                DEFAULT_ACL_TREE,
+                &Self::acl_key(bucket, Some(new_key)),
+                acl.to_vec(),
+            )?;
+        }
+
+        let tags_key = Self::tags_key(bucket, key);
+        let Some(raw_tags) = tx.backend.get(DEFAULT_TAGS_TREE, &tags_key)? else {
+            return Ok(());
+        };
+        let tags =
+            ObjectTags::try_from(&*raw_tags).map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        for (tag_key, tag_value) in tags.iter() {
+            let mut index_key = Self::tag_index_prefix(bucket, tag_key, tag_value);
+            let mut new_index_key = index_key.clone();
+            index_key.extend_from_slice(key.as_bytes());
+            new_index_key.extend_from_slice(new_key.as_bytes());
+            tx.backend.remove(DEFAULT_TAGS_TREE, &index_key)?;
+            tx.backend
+                .insert(DEFAULT_TAGS_TREE, &new_index_key, Vec::new())?;
+        }
+        tx.backend.remove(DEFAULT_TAGS_TREE, &tags_key)?;
+        tx.backend.insert(
+            DEFAULT_TAGS_TREE,
+            &Self::tags_key(bucket, new_key),
+            raw_tags.to_vec(),
+        )
+    }
+
+    /// Inserts a raw representation of a bucket into the meta store.
+    ///
+    /// This method both adds the bucket metadata to the buckets tree and
+    /// creates the bucket's own tree if it doesn't already exist.
+    ///
+    /// # Arguments
+    /// * `bucket_name` - The name of the bucket
+    /// * `raw_bucket` - The serialized bucket metadata
+    ///
+    /// # Returns
+    /// Success or an error if the insertion fails
+    pub fn insert_bucket(&self, bucket_name: &str, raw_bucket: Vec<u8>) -> Result<(), MetaError> {
+        // Insert the bucket metadata into the buckets tree
+        let buckets = self.store.tree_open(DEFAULT_BUCKET_TREE)?;
+        buckets.insert(bucket_name.as_bytes(), raw_bucket)?;
+
+        // Create the bucket tree if it doesn't exist
+        self.store.tree_open(bucket_name)?;
+
+        Ok(())
+    }
+
+    /// Retrieves the metadata of a bucket.
+    ///
+    /// # Arguments
+    /// * `bucket_name` - The name of the bucket
+    ///
+    /// # Returns
+    /// The BucketMeta, None if the bucket doesn't exist, or an error
+    pub fn get_bucket_meta(&self, bucket_name: &str) -> Result<Option<BucketMeta>, MetaError> {
+        let buckets = self.store.tree_open(DEFAULT_BUCKET_TREE)?;
+        match buckets.get(bucket_name.as_bytes())? {
+            Some(data) => BucketMeta::try_from(&*data)
+                .map(Some)
+                .map_err(|e| MetaError::OtherDBError(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the limits of the contents of a bucket.
+    ///
+    /// # Arguments
+    /// * `bucket_name` - The name of the bucket
+    /// * `limits` - The new limits, without limits the bucket is unlimited
+    ///
+    /// # Returns
+    /// Success, `MetaError::BucketNotFound` if the bucket doesn't exist, or an error
+    pub fn set_bucket_limits(
+        &self,
+        bucket_name: &str,
+        limits: BucketLimits,
+    ) -> Result<(), MetaError> {
+        let mut meta = self
+            .get_bucket_meta(bucket_name)?
+            .ok_or(MetaError::BucketNotFound)?;
+        meta.set_limits(limits);
+        let buckets = self.store.tree_open(DEFAULT_BUCKET_TREE)?;
+        buckets.insert(bucket_name.as_bytes(), meta.to_vec())
+    }
+
+    /// Replaces the tags of a bucket.
+    ///
+    /// # Arguments
+    /// * `bucket_name` - The name of the bucket
+    /// * `tags` - The new tags, an empty tag set removes them
+    ///
+    /// # Returns
+    /// Success, `MetaError::BucketNotFound` if the bucket doesn't exist, or an error
+    pub fn set_bucket_tags(&self, bucket_name: &str, tags: ObjectTags) -> Result<(), MetaError> {
+        let mut meta = self
+            .get_bucket_meta(bucket_name)?
+            .ok_or(MetaError::BucketNotFound)?;
+        meta.set_tags(tags);
+        let buckets = self.store.tree_open(DEFAULT_BUCKET_TREE)?;
+        buckets.insert(bucket_name.as_bytes(), meta.to_vec())
+    }
+
+    /// Returns a list of all buckets in the system.
+    ///
+    /// # Returns
+    /// A vector of BucketMeta objects or an error
+    ///
+    /// # Note
+    /// This method currently loads all buckets into memory at once.
+    /// TODO: This should be paginated and return a stream for better scalability.
+    pub fn list_buckets(&self) -> Result<Vec<BucketMeta>, MetaError> {
+        let bucket = self.get_allbuckets_tree()?;
+        let buckets = bucket
+            .iter_all()
+            .filter_map(|result| {
+                let (_, value) = match result {
+                    Ok(kv) => kv,
+                    Err(_) => return None,
+                };
+
+                let bucket_meta = BucketMeta::try_from(&*value).ok()?;
+                Some(bucket_meta) // Just return the BucketMeta without the key
+            })
+            .collect();
+        Ok(buckets)
+    }
+
+    /// Inserts a metadata Object into the specified bucket.
+    ///
+    /// # Arguments
+    /// * `bucket_name` - The name of the bucket
+    /// * `key` - The key to associate with the object
+    /// * `raw_obj` - The serialized object metadata
+    ///
+    /// # Returns
+    /// Success or an error if the insertion fails
+    pub fn insert_meta(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        raw_obj: Vec<u8>,
+    ) -> Result<(), MetaError> {
+        self.insert_meta_with_attributes(bucket_name, key, raw_obj, &ObjectAttributes::default())
+    }
+
+    /// Like [`MetaStore::insert_meta`], but also writes the `attributes` of the
+    /// object in the same transaction.
+    ///
+    /// # Arguments
+    /// * `bucket_name` - The name of the bucket
+    /// * `key` - The key to associate with the object
+    /// * `raw_obj` - The serialized object metadata
+    /// * `attributes` - The attributes to write with the object
+    ///
+    /// # Returns
+    /// Success or an error if the insertion fails
+    pub fn insert_meta_with_attributes(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        raw_obj: Vec<u8>,
+        attributes: &ObjectAttributes,
+    ) -> Result<(), MetaError> {
+        let obj = Object::try_from(&*raw_obj).map_err(|e| MetaError::InsertError(e.to_string()))?;
+
+        let mut tx = self.begin_bucket_transaction(bucket_name);
+        self.write_meta(&mut tx, bucket_name, key, &obj, raw_obj)?;
+        Self::write_attributes(&mut tx, bucket_name, key, attributes)?;
+        tx.commit()
+    }
+
+    /// Like [`MetaStore::insert_meta`], but returns the replaced object, e.g. to
+    /// release its blocks in another store.
+    ///
+    /// # Arguments
+    /// * `bucket_name` - The name of the bucket
+    /// * `key` - The key to associate with the object
+    /// * `raw_obj` - The serialized object metadata
+    ///
+    /// # Returns
+    /// The replaced object, None if there was none, or an error
+    pub fn swap_meta(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        raw_obj: Vec<u8>,
+    ) -> Result<Option<Object>, MetaError> {
+        let obj = Object::try_from(&*raw_obj).map_err(|e| MetaError::InsertError(e.to_string()))?;
+
+        let mut tx = self.begin_bucket_transaction(bucket_name);
+        let old = self.write_meta(&mut tx, bucket_name, key, &obj, raw_obj)?;
+        tx.commit()?;
+        Ok(old)
+    }
+
+    /// Replaces the object at a key with a new one whose blocks are in this store,
+    /// in a single transaction committed with the durability of the bucket:
+    /// `add_refs` adds the references of the new object to its blocks, the
+    /// metadata is written, and the references of the replaced object are
+    /// released.
+    ///
+    /// The references are added before the old ones are released, so blocks both
+    /// objects use are kept. In a transactional store, a crash or an error leaves
+    /// the old object and the refcounts as they were.
+    ///
+    /// # Arguments
+    /// * `bucket_name` - The name of the bucket
+    /// * `key` - The key of the object
+    /// * `raw_obj` - The serialized metadata of the new object
+    /// * `add_refs` - Adds the references of the new object in the transaction
+    ///
+    /// # Returns
+    /// The blocks of the replaced object which are no longer referenced and must
+    /// be removed from disk, or an error
+    pub fn replace_meta(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        raw_obj: Vec<u8>,
+        add_refs: impl FnOnce(&mut Transaction) -> Result<(), MetaError>,
+    ) -> Result<Vec<Block>, MetaError> {
+        let obj = Object::try_from(&*raw_obj).map_err(|e| MetaError::InsertError(e.to_string()))?;
+
+        let mut tx = self.begin_bucket_transaction(bucket_name);
+        add_refs(&mut tx)?;
+        let old = self.write_meta(&mut tx, bucket_name, key, &obj, raw_obj)?;
+        let mut to_delete = Vec::new();
+        for block_id in old.iter().flat_map(|old| old.blocks()) {
+            if let Some(block) = tx.release_block(block_id)? {
+                to_delete.push(block);
+            }
+        }
+        tx.commit()?;
+        Ok(to_delete)
+    }
+
+    // write the metadata of an object in `tx`, replacing the block references of
+    // the old object with the ones of the new object, and return the old object
+    fn write_meta(
+        &self,
+        tx: &mut Transaction,
+        bucket_name: &str,
+        key: &str,
+        obj: &Object,
+        raw_obj: Vec<u8>,
+    ) -> Result<Option<Object>, MetaError> {
+        let old = match tx.backend.get(bucket_name, key.as_bytes())? {
+            Some(old_raw) => Some(
+                Object::try_from(&*old_raw).map_err(|e| MetaError::OtherDBError(e.to_string()))?,
+            ),
+            None => None,
+        };
+        if let Some(old) = &old {
+            tx.counters.object(bucket_name, key.as_bytes(), old, -1);
+        }
+        tx.counters.object(bucket_name, key.as_bytes(), obj, 1);
+        if self.block_refs {
+            if let Some(old) = &old {
+                for block in distinct_blocks(old.blocks()) {
+                    if !obj.has_block(block) {
+                        tx.backend
+                            .remove(BLOCK_REFS_TREE, &block_ref_key(block, bucket_name, key))?;
+                    }
+                }
+                if old.hash() != obj.hash() {
+                    tx.backend.remove(
+                        OBJECT_HASHES_TREE,
+                        &block_ref_key(old.hash(), bucket_name, key),
+                    )?;
+                }
+            }
+            for block in distinct_blocks(obj.blocks()) {
+                tx.backend.insert(
+                    BLOCK_REFS_TREE,
+                    &block_ref_key(block, bucket_name, key),
+                    Vec::new(),
+                )?;
+            }
+            tx.backend.insert(
+                OBJECT_HASHES_TREE,
+                &block_ref_key(obj.hash(), bucket_name, key),
+                Vec::new(),
+            )?;
+        }
+        tx.backend.insert(bucket_name, key.as_bytes(), raw_obj)?;
+        Ok(old)
+    }
+
+    // write the attributes of an object which are set in `tx`
+    fn write_attributes(
+        tx: &mut Transaction,
+        bucket: &str,
+        key: &str,
+        attributes: &ObjectAttributes,
+    ) -> Result<(), MetaError> {
+        if let Some(acl) = attributes.acl {
+            let acl_key = Self::acl_key(bucket, Some(key));
+            match acl {
+                Some(acl) => tx
+                    .backend
+                    .insert(DEFAULT_ACL_TREE, &acl_key, acl.to_vec())?,
+                None => tx.backend.remove(DEFAULT_ACL_TREE, &acl_key)?,
+            }
+        }
+        if let Some(tags) = &attributes.tags {
+            Self::write_tags(tx, bucket, key, tags)?;
+        }
+        Ok(())
+    }
+
+    /// Retrieves the Object metadata for the given bucket and key.
+    ///
+    /// This method returns the deserialized Object struct instead of raw bytes
+    /// for better performance and convenience.
+    ///
+    /// # Arguments
+    /// * `bucket_name` - The name of the bucket
+    /// * `key` - The key to look up
+    ///
+    /// # Returns
+    /// The Object if found, None if the key doesn't exist, or an error
+    pub fn get_meta(&self, bucket_name: &str, key: &str) -> Result<Option<Object>, MetaError> {
+        let start = Instant::now();
+        let bucket = self.get_bucket_ext(bucket_name)?;
+        let data = bucket.get(key.as_bytes());
+        request_timings::record_meta(start);
+        match data? {
+            Some(data) => {
+                let obj = Object::try_from(&*data).expect("Malformed object");
+                Ok(Some(obj))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // ACLs of buckets are keyed by the bucket name, ACLs of objects by the bucket
+    // name and the key separated by a 0 byte, which can't appear in bucket names.
+    fn acl_key(bucket: &str, key: Option<&str>) -> Vec<u8> {
+        let mut acl_key = bucket.as_bytes().to_vec();
+        if let Some(key) = key {
+            acl_key.push(0);
+            acl_key.extend_from_slice(key.as_bytes());
+        }
+        acl_key
+    }
+
+    /// Retrieves the ACL of a bucket, or of an object if `key` is set.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    /// * `key` - The key of the object, or None for the bucket itself
+    ///
+    /// # Returns
+    /// The ACL if one was set, None otherwise, or an error
+    pub fn get_acl(&self, bucket: &str, key: Option<&str>) -> Result<Option<CannedAcl>, MetaError> {
+        let acls = self.store.tree_open(DEFAULT_ACL_TREE)?;
+        match acls.get(&Self::acl_key(bucket, key))? {
+            Some(data) => CannedAcl::try_from(&*data)
+                .map(Some)
+                .map_err(|e| MetaError::OtherDBError(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets the ACL of a bucket, or of an object if `key` is set.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    /// * `key` - The key of the object, or None for the bucket itself
+    /// * `acl` - The ACL to set
+    ///
+    /// # Returns
+    /// Success or an error if the insertion fails
+    pub fn set_acl(
+        &self,
+        bucket: &str,
+        key: Option<&str>,
+        acl: CannedAcl,
+    ) -> Result<(), MetaError> {
+        let acls = self.store.tree_open(DEFAULT_ACL_TREE)?;
+        acls.insert(&Self::acl_key(bucket, key), acl.to_vec())
+    }
+
+    /// Removes the ACL of a bucket, or of an object if `key` is set.
+    ///
+    /// Removing the ACL of an object makes it inherit the ACL of its bucket again.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    /// * `key` - The key of the object, or None for the bucket itself
+    ///
+    /// # Returns
+    /// Success or an error if the removal fails
+    pub fn remove_acl(&self, bucket: &str, key: Option<&str>) -> Result<(), MetaError> {
+        let acls = self.store.tree_open(DEFAULT_ACL_TREE)?;
+        acls.remove(&Self::acl_key(bucket, key))
+    }
+
+    /// Retrieves the default server-side encryption algorithm of a bucket.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    ///
+    /// # Returns
+    /// The algorithm if one was set, None otherwise, or an error
+    pub fn get_bucket_encryption(&self, bucket: &str) -> Result<Option<String>, MetaError> {
+        let encryption = self.store.tree_open(BUCKET_ENCRYPTION_TREE)?;
+        Ok(encryption
+            .get(bucket.as_bytes())?
+            .map(|data| String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    /// Sets the default server-side encryption algorithm of a bucket, `None`
+    /// removes it.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    /// * `algorithm` - The algorithm applied to the objects written to the bucket
+    ///
+    /// # Returns
+    /// Success or an error if the update fails
+    pub fn set_bucket_encryption(
+        &self,
+        bucket: &str,
+        algorithm: Option<&str>,
+    ) -> Result<(), MetaError> {
+        let encryption = self.store.tree_open(BUCKET_ENCRYPTION_TREE)?;
+        match algorithm {
+            Some(algorithm) => encryption.insert(bucket.as_bytes(), algorithm.as_bytes().to_vec()),
+            None => encryption.remove(bucket.as_bytes()),
+        }
+    }
+
+    /// Retrieves the lifecycle configuration of a bucket.
     ///
     /// # Arguments
-    /// * `name` - The name of the tree to open
+    /// * `bucket` - The name of the bucket
     ///
     /// # Returns
-    /// A tree instance or an error
-    pub fn get_tree(&self, name: &str) -> Result<Arc<dyn BaseMetaTree>, MetaError> {
-        self.store.tree_open(name)
+    /// The configuration if one was set, None otherwise, or an error
+    pub fn get_bucket_lifecycle(&self, bucket: &str) -> Result<Option<BucketLifecycle>, MetaError> {
+        let lifecycles = self.store.tree_open(BUCKET_LIFECYCLE_TREE)?;
+        lifecycles
+            .get(bucket.as_bytes())?
+            .map(|data| BucketLifecycle::from_slice(&data))
+            .transpose()
     }
 
-    /// Returns the path metadata tree.
+    /// Sets the lifecycle configuration of a bucket, `None` removes it.
     ///
-    /// This tree is used to store file path metadata and path-related information.
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    /// * `lifecycle` - The expiration rules of the objects of the bucket
     ///
     /// # Returns
-    /// A tree instance or an error
-    pub fn get_path_tree(&self) -> Result<Arc<dyn BaseMetaTree>, MetaError> {
-        self.store.tree_open(DEFAULT_PATH_TREE)
+    /// Success or an error if the update fails
+    pub fn set_bucket_lifecycle(
+        &self,
+        bucket: &str,
+        lifecycle: Option<&BucketLifecycle>,
+    ) -> Result<(), MetaError> {
+        let lifecycles = self.store.tree_open(BUCKET_LIFECYCLE_TREE)?;
+        match lifecycle {
+            Some(lifecycle) => lifecycles.insert(bucket.as_bytes(), lifecycle.to_vec()),
+            None => lifecycles.remove(bucket.as_bytes()),
+        }
     }
 
-    /// Checks if a bucket with the given name exists.
+    /// Retrieves the server access logging configuration of a bucket.
     ///
     /// # Arguments
-    /// * `bucket_name` - The name of the bucket to check
+    /// * `bucket` - The name of the bucket
     ///
     /// # Returns
-    /// `true` if the bucket exists, `false` otherwise, or an error
-    pub fn bucket_exists(&self, bucket_name: &str) -> Result<bool, MetaError> {
-        self.store.tree_exists(bucket_name)
+    /// The configuration if one was set, None otherwise, or an error
+    pub fn get_bucket_logging(&self, bucket: &str) -> Result<Option<BucketLogging>, MetaError> {
+        let logging = self.store.tree_open(BUCKET_LOGGING_TREE)?;
+        logging
+            .get(bucket.as_bytes())?
+            .map(|data| BucketLogging::from_slice(&data))
+            .transpose()
     }
 
-    /// Deletes the bucket with the given name.
-    ///
-    /// If the bucket doesn't exist, this operation is a no-op and returns success.
+    /// Sets the server access logging configuration of a bucket, `None`
+    /// disables logging.
     ///
     /// # Arguments
-    /// * `name` - The name of the bucket to delete
+    /// * `bucket` - The name of the bucket
+    /// * `logging` - Where the records of the requests to the bucket are written
     ///
     /// # Returns
-    /// Success or an error if the deletion fails
-    pub fn drop_bucket(&self, name: &str) -> Result<(), MetaError> {
-        if self.bucket_exists(name)? {
-            self.store.tree_delete(name)
-        } else {
-            Ok(())
+    /// Success or an error if the update fails
+    pub fn set_bucket_logging(
+        &self,
+        bucket: &str,
+        logging: Option<&BucketLogging>,
+    ) -> Result<(), MetaError> {
+        let tree = self.store.tree_open(BUCKET_LOGGING_TREE)?;
+        match logging {
+            Some(logging) => tree.insert(bucket.as_bytes(), logging.to_vec()),
+            None => tree.remove(bucket.as_bytes()),
         }
     }
 
-    /// Inserts a raw representation of a bucket into the meta store.
+    /// Checks whether the requesters pay for the downloads from a bucket,
+    /// instead of its owner.
     ///
-    /// This method both adds the bucket metadata to the buckets tree and
-    /// creates the bucket's own tree if it doesn't already exist.
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    ///
+    /// # Returns
+    /// True if requester pays is enabled, or an error
+    pub fn get_requester_pays(&self, bucket: &str) -> Result<bool, MetaError> {
+        let tree = self.store.tree_open(BUCKET_REQUEST_PAYMENT_TREE)?;
+        tree.contains_key(bucket.as_bytes())
+    }
+
+    /// Enables or disables requester pays on a bucket.
     ///
     /// # Arguments
-    /// * `bucket_name` - The name of the bucket
-    /// * `raw_bucket` - The serialized bucket metadata
+    /// * `bucket` - The name of the bucket
+    /// * `enabled` - Whether the requesters pay for the downloads
     ///
     /// # Returns
-    /// Success or an error if the insertion fails
-    pub fn insert_bucket(&self, bucket_name: &str, raw_bucket: Vec<u8>) -> Result<(), MetaError> {
-        // Insert the bucket metadata into the buckets tree
-        let buckets = self.store.tree_open(DEFAULT_BUCKET_TREE)?;
-        buckets.insert(bucket_name.as_bytes(), raw_bucket)?;
+    /// Success or an error if the update fails
+    pub fn set_requester_pays(&self, bucket: &str, enabled: bool) -> Result<(), MetaError> {
+        let tree = self.store.tree_open(BUCKET_REQUEST_PAYMENT_TREE)?;
+        if enabled {
+            tree.insert(bucket.as_bytes(), Vec::new())
+        } else {
+            tree.remove(bucket.as_bytes())
+        }
+    }
 
-        // Create the bucket tree if it doesn't exist
-        self.store.tree_open(bucket_name)?;
+    fn tags_key(bucket: &str, key: &str) -> Vec<u8> {
+        let mut tags_key = vec![TAGS_OBJECT_PREFIX];
+        tags_key.extend_from_slice(bucket.as_bytes());
+        tags_key.push(0);
+        tags_key.extend_from_slice(key.as_bytes());
+        tags_key
+    }
 
-        Ok(())
+    fn tag_index_prefix(bucket: &str, tag_key: &str, tag_value: &str) -> Vec<u8> {
+        let mut prefix = vec![TAGS_INDEX_PREFIX];
+        for part in [bucket, tag_key, tag_value] {
+            prefix.extend_from_slice(part.as_bytes());
+            prefix.push(0);
+        }
+        prefix
     }
 
-    /// Returns a list of all buckets in the system.
+    /// Retrieves the tags of an object.
     ///
-    /// # Returns
-    /// A vector of BucketMeta objects or an error
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    /// * `key` - The key of the object
     ///
-    /// # Note
-    /// This method currently loads all buckets into memory at once.
-    /// TODO: This should be paginated and return a stream for better scalability.
-    pub fn list_buckets(&self) -> Result<Vec<BucketMeta>, MetaError> {
-        let bucket = self.get_allbuckets_tree()?;
-        let buckets = bucket
-            .iter_all()
-            .filter_map(|result| {
-                let (_, value) = match result {
-                    Ok(kv) => kv,
-                    Err(_) => return None,
-                };
-
-                let bucket_meta = BucketMeta::try_from(&*value).ok()?;
-                Some(bucket_meta) // Just return the BucketMeta without the key
-            })
-            .collect();
-        Ok(buckets)
+    /// # Returns
+    /// The tags of the object, empty if none are set, or an error
+    pub fn get_tags(&self, bucket: &str, key: &str) -> Result<ObjectTags, MetaError> {
+        let tags = self.store.tree_open(DEFAULT_TAGS_TREE)?;
+        match tags.get(&Self::tags_key(bucket, key))? {
+            Some(data) => ObjectTags::try_from(&*data)
+                .map_err(|e| MetaError::OtherDBError(e.to_string())),
+            None => Ok(ObjectTags::default()),
+        }
     }
 
-    /// Inserts a metadata Object into the specified bucket.
+    /// Replaces the tags of an object, and updates the tag index.
     ///
     /// # Arguments
-    /// * `bucket_name` - The name of the bucket
-    /// * `key` - The key to associate with the object
-    /// * `raw_obj` - The serialized object metadata
+    /// * `bucket` - The name of the bucket
+    /// * `key` - The key of the object
+    /// * `tags` - The new tags, an empty set removes the tags
     ///
     /// # Returns
-    /// Success or an error if the insertion fails
-    pub fn insert_meta(
-        &self,
-        bucket_name: &str,
+    /// Success or an error if the update fails
+    pub fn set_tags(&self, bucket: &str, key: &str, tags: &ObjectTags) -> Result<(), MetaError> {
+        let mut tx = self.begin_bucket_transaction(bucket);
+        Self::write_tags(&mut tx, bucket, key, tags)?;
+        tx.commit()
+    }
+
+    // replace the tags of an object and their index entries in `tx`
+    fn write_tags(
+        tx: &mut Transaction,
+        bucket: &str,
         key: &str,
-        raw_obj: Vec<u8>,
+        tags: &ObjectTags,
     ) -> Result<(), MetaError> {
-        let bucket = self.get_bucket_ext(bucket_name)?;
-        bucket.insert(key.as_bytes(), raw_obj)
+        let index_key = |tag_key: &str, tag_value: &str| {
+            let mut index_key = Self::tag_index_prefix(bucket, tag_key, tag_value);
+            index_key.extend_from_slice(key.as_bytes());
+            index_key
+        };
+
+        let tags_key = Self::tags_key(bucket, key);
+        if let Some(raw_tags) = tx.backend.get(DEFAULT_TAGS_TREE, &tags_key)? {
+            let old_tags = ObjectTags::try_from(&*raw_tags)
+                .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+            for (tag_key, tag_value) in old_tags.iter() {
+                tx.backend
+                    .remove(DEFAULT_TAGS_TREE, &index_key(tag_key, tag_value))?;
+            }
+        }
+        if tags.is_empty() {
+            return tx.backend.remove(DEFAULT_TAGS_TREE, &tags_key);
+        }
+        // the index entries are written first, a failure of a store without
+        // transactions leaves stale entries which are filtered out on lookup, but
+        // never misses a tagged object
+        for (tag_key, tag_value) in tags.iter() {
+            tx.backend.insert(
+                DEFAULT_TAGS_TREE,
+                &index_key(tag_key, tag_value),
+                Vec::new(),
+            )?;
+        }
+        tx.backend
+            .insert(DEFAULT_TAGS_TREE, &tags_key, tags.to_vec())
     }
 
-    /// Retrieves the Object metadata for the given bucket and key.
+    /// Removes the tags of an object.
     ///
-    /// This method returns the deserialized Object struct instead of raw bytes
-    /// for better performance and convenience.
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    /// * `key` - The key of the object
+    ///
+    /// # Returns
+    /// Success or an error if the removal fails
+    pub fn remove_tags(&self, bucket: &str, key: &str) -> Result<(), MetaError> {
+        self.set_tags(bucket, key, &ObjectTags::default())
+    }
+
+    /// Lists the keys of the objects in a bucket which have the tag of `filter`,
+    /// using the tag index.
     ///
     /// # Arguments
-    /// * `bucket_name` - The name of the bucket
-    /// * `key` - The key to look up
+    /// * `bucket` - The name of the bucket
+    /// * `filter` - The tag the objects must have
     ///
     /// # Returns
-    /// The Object if found, None if the key doesn't exist, or an error
-    pub fn get_meta(&self, bucket_name: &str, key: &str) -> Result<Option<Object>, MetaError> {
-        let bucket = self.get_bucket_ext(bucket_name)?;
-        match bucket.get(key.as_bytes())? {
-            Some(data) => {
-                let obj = Object::try_from(&*data).expect("Malformed object");
-                Ok(Some(obj))
+    /// The matching object keys in key order, or an error
+    pub fn tagged_keys(
+        &self,
+        bucket: &str,
+        filter: &TagFilter,
+    ) -> Result<Vec<String>, MetaError> {
+        let tree = self.store.tree_ext_open(DEFAULT_TAGS_TREE)?;
+        let prefix = Self::tag_index_prefix(bucket, &filter.key, &filter.value);
+
+        let mut keys = Vec::new();
+        for item in tree.iter_prefix(&prefix) {
+            let (index_key, _) = item?;
+            let key = String::from_utf8_lossy(&index_key[prefix.len()..]).into_owned();
+            // skip entries left behind by an interrupted update
+            if filter.matches(&self.get_tags(bucket, &key)?) {
+                keys.push(key);
             }
-            None => Ok(None),
         }
+        Ok(keys)
     }
 
     /// Deletes an object from a bucket and manages its associated blocks.
     ///
-    /// This method performs the following operations:
+    /// This method performs the following operations in a single transaction,
+    /// committed with the durability of the bucket:
     /// 1. Retrieves the object metadata from the bucket
-    /// 2. Removes the object from the bucket
+    /// 2. Removes the object from the bucket, and its block references
     /// 3. For each block in the object:
     ///    - Decrements its reference count
-    ///    - If the reference count reaches zero, marks the block for deletion
+    ///    - If the reference count reaches zero, removes the block
     /// 4. Returns the list of blocks that should be physically deleted from storage
     ///
     /// # Arguments
@@ -261,21 +1343,20 @@ impl MetaStore {
     ///
     /// # Returns
     /// A vector of Block objects that should be physically deleted, or an error
-    ///
-    /// # Note
-    /// This method currently handles reference counting and block management directly.
-    /// In the future, these operations should be abstracted into a transaction system.
     pub fn delete_object(&self, bucket: &str, key: &str) -> Result<Vec<Block>, MetaError> {
-        let bucket_tree = self.get_bucket_ext(bucket)?;
-        let block_tree = self.get_block_tree()?;
+        let mut tx = self.begin_bucket_transaction(bucket);
 
         // Get the object metadata
-        let raw_object = match bucket_tree.get(key.as_bytes())? {
+        let raw_object = match tx.backend.get(bucket, key.as_bytes())? {
             Some(o) => o,
-            None => return Ok(vec![]),
+            None => {
+                tx.rollback();
+                return Ok(vec![]);
+            }
         };
 
-        let obj = Object::try_from(&*raw_object).expect("Malformed object");
+        let obj =
+            Object::try_from(&*raw_object).map_err(|e| MetaError::OtherDBError(e.to_string()))?;
         let mut to_delete: Vec<Block> = Vec::with_capacity(obj.blocks().len());
 
         tracing::debug!(
@@ -285,47 +1366,29 @@ impl MetaStore {
             "Deleting object"
         );
 
-        // Delete the object from the bucket
-        bucket_tree.remove(key.as_bytes())?;
+        // Delete the object from the bucket, and its block references with it
+        tx.backend.remove(bucket, key.as_bytes())?;
+        tx.counters.object(bucket, key.as_bytes(), &obj, -1);
+        if self.block_refs {
+            for block_id in distinct_blocks(obj.blocks()) {
+                tx.backend
+                    .remove(BLOCK_REFS_TREE, &block_ref_key(block_id, bucket, key))?;
+            }
+            tx.backend
+                .remove(OBJECT_HASHES_TREE, &block_ref_key(obj.hash(), bucket, key))?;
+        }
 
-        // Process all blocks in the object
+        // Release the reference of every occurrence of a block
         for block_id in obj.blocks() {
-            match block_tree.get(block_id)? {
-                Some(block_data) => {
-                    let mut block = Block::try_from(&*block_data).expect("Corrupted block data");
-
-                    // If this is the last reference to the block, delete it
-                    if block.rc() == 1 {
-                        tracing::debug!(
-                            block_hash = %hex::encode(block_id),
-                            rc = block.rc(),
-                            "Block rc==1: deleting block and marking for file deletion"
-                        );
-                        block_tree.remove(block_id)?;
-                        to_delete.push(block);
-                    } else {
-                        // Otherwise decrement the reference count
-                        let old_rc = block.rc();
-                        block.decrement_refcount();
-                        let new_rc = block.rc();
-                        tracing::debug!(
-                            block_hash = %hex::encode(block_id),
-                            old_rc = old_rc,
-                            new_rc = new_rc,
-                            "Block rc>1: decrementing refcount"
-                        );
-                        block_tree.insert(block_id, block.to_vec())?;
-                    }
-                }
-                None => {
-                    tracing::warn!(
-                        block_hash = %hex::encode(block_id),
-                        "Block not found in tree during deletion"
-                    );
-                    continue; // Block not found, skip it
-                }
+            if let Some(block) = tx.release_block(block_id)? {
+                tracing::debug!(
+                    block_hash = %hex::encode(block_id),
+                    "Last block reference released: marking for file deletion"
+                );
+                to_delete.push(block);
             }
         }
+        tx.commit()?;
 
         tracing::debug!(
             blocks_to_delete = to_delete.len(),
@@ -340,7 +1403,46 @@ impl MetaStore {
     /// # Returns
     /// A new Transaction object
     pub fn begin_transaction(&self) -> Transaction {
-        self.store.begin_transaction()
+        self.store
+            .begin_transaction()
+            .with_metrics(self.metrics.clone())
+    }
+
+    /// Begins a new transaction writing to `bucket`, committed with the durability
+    /// override of the bucket if it has one.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    ///
+    /// # Returns
+    /// A new Transaction object
+    pub fn begin_bucket_transaction(&self, bucket: &str) -> Transaction {
+        let mut tx = self.begin_transaction();
+        if let Some(durability) = self.bucket_durability(bucket) {
+            tx.set_durability(durability);
+        }
+        tx
+    }
+
+    /// Sets the durability overrides of buckets, replacing the previous ones.
+    /// Commits writing to other buckets use the durability of the store.
+    ///
+    /// # Arguments
+    /// * `overrides` - The durability of every overridden bucket
+    pub fn set_bucket_durability(&mut self, overrides: HashMap<String, Durability>) {
+        self.bucket_durability = Arc::new(overrides);
+    }
+
+    /// Returns the durability override of a bucket.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket
+    ///
+    /// # Returns
+    /// The durability of commits writing to the bucket, or None if it uses the
+    /// durability of the store
+    pub fn bucket_durability(&self, bucket: &str) -> Option<Durability> {
+        self.bucket_durability.get(bucket).copied()
     }
 
     /// Returns the total number of keys in the bucket tree.
@@ -360,6 +1462,61 @@ impl MetaStore {
     pub fn disk_space(&self) -> u64 {
         self.store.disk_space()
     }
+
+    /// Returns the disk space used by the tree `name`, 0 if it doesn't exist.
+    pub fn tree_disk_space(&self, name: &str) -> Result<u64, MetaError> {
+        self.store.tree_disk_space(name)
+    }
+
+    /// Returns the disk space used by the internal trees of the store and by the
+    /// tree of every bucket, skipping trees which don't exist.
+    ///
+    /// # Returns
+    /// (tree name, bytes) pairs, internal trees first
+    pub fn tree_sizes(&self) -> Result<Vec<(String, u64)>, MetaError> {
+        let internal = [
+            DEFAULT_BUCKET_TREE,
+            DEFAULT_BLOCK_TREE,
+            DEFAULT_PATH_TREE,
+            DEFAULT_ACL_TREE,
+            DEFAULT_TAGS_TREE,
+            BUCKET_ENCRYPTION_TREE,
+            BUCKET_LIFECYCLE_TREE,
+            BUCKET_LOGGING_TREE,
+            BUCKET_REQUEST_PAYMENT_TREE,
+            BLOCK_REFS_TREE,
+            OBJECT_HASHES_TREE,
+            COUNTERS_TREE,
+            KV_SEPARATED_TREE,
+        ];
+        let buckets = self.list_buckets()?;
+        let names = internal
+            .iter()
+            .map(|name| name.to_string())
+            .chain(buckets.iter().map(|bucket| bucket.name().to_string()));
+
+        let mut sizes = Vec::new();
+        for name in names {
+            if self.store.tree_exists(&name)? {
+                let bytes = self.store.tree_disk_space(&name)?;
+                sizes.push((name, bytes));
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// Returns the blob file utilization of the key-value separated bucket trees.
+    pub fn blob_stats(&self) -> Result<Vec<BlobStats>, MetaError> {
+        self.store.blob_stats()
+    }
+
+    /// Garbage collects the blob files of the key-value separated bucket trees.
+    ///
+    /// # Returns
+    /// The number of bytes freed
+    pub fn blob_gc(&self) -> Result<u64, MetaError> {
+        self.store.blob_gc()
+    }
 }
 
 impl Debug for MetaStore {
@@ -450,7 +1607,7 @@ impl BlockTree {
     ///
     /// # Returns
     /// The raw block data if found, None if the key doesn't exist, or an error
-    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, MetaError> {
+    fn get(&self, key: &[u8]) -> Result<Option<Bytes>, MetaError> {
         self.tree.get(key)
     }
 
@@ -491,6 +1648,10 @@ impl BlockTree {
 pub struct Transaction {
     // The backend storage implementation
     backend: Box<dyn TransactionBackend>,
+    // Changes of the store counters, applied on commit
+    counters: CounterDeltas,
+    // Receives the anomalies of block reference counts
+    metrics: SharedMetrics,
 }
 
 impl Transaction {
@@ -502,17 +1663,73 @@ impl Transaction {
     /// # Returns
     /// A new Transaction instance
     pub(crate) fn new(backend: Box<dyn TransactionBackend>) -> Self {
-        Self { backend }
+        Self {
+            backend,
+            counters: CounterDeltas::default(),
+            metrics: SharedMetrics::default(),
+        }
+    }
+
+    /// Reports the anomalies of block reference counts to `metrics`.
+    pub(crate) fn with_metrics(mut self, metrics: SharedMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Logs and counts an attempt to add `count` references to a block whose
+    /// reference count `rc` can't grow that much.
+    fn refcount_overflow(&self, block_hash: &BlockID, rc: u64, count: u64) -> MetaError {
+        tracing::error!(
+            block_hash = %hex::encode(block_hash),
+            rc,
+            count,
+            "Block reference count overflow"
+        );
+        self.metrics.refcount_anomaly("overflow");
+        MetaError::RefcountOverflow
     }
 
     /// Commits the transaction, making all changes permanent.
     ///
+    /// The changes of the store counters are written first, so they are committed
+    /// with the writes they count.
+    ///
     /// # Returns
     /// Success or an error if the commit fails
     pub fn commit(mut self) -> Result<(), MetaError> {
+        for (field, delta) in self.counters.fields() {
+            let value = self.backend.get(COUNTERS_TREE, field)?;
+            let value = counters::apply_delta(value.as_deref(), delta);
+            self.backend.insert(COUNTERS_TREE, field, value)?;
+        }
+        for (key, delta) in self.counters.bucket_fields() {
+            let value = self.backend.get(COUNTERS_TREE, &key)?;
+            let value = counters::apply_delta(value.as_deref(), delta);
+            self.backend.insert(COUNTERS_TREE, &key, value)?;
+        }
+        // the counts of directories without objects are removed, a bucket has
+        // no more counts than directories
+        for (key, delta) in self.counters.prefix_fields() {
+            let value = self.backend.get(COUNTERS_TREE, key)?;
+            let value = counters::apply_delta(value.as_deref(), delta);
+            if counters::decode(&value) == 0 {
+                self.backend.remove(COUNTERS_TREE, key)?;
+            } else {
+                self.backend.insert(COUNTERS_TREE, key, value)?;
+            }
+        }
         self.backend.commit()
     }
 
+    /// Commits the transaction with `durability` instead of the durability of
+    /// the store.
+    ///
+    /// # Arguments
+    /// * `durability` - How the commit is persisted
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.backend.set_durability(durability);
+    }
+
     /// Rolls back the transaction, discarding all changes.
     ///
     /// This method is called when the transaction should be aborted.
@@ -530,6 +1747,8 @@ impl Transaction {
     /// * `block_hash` - The hash of the block to write
     /// * `data_len` - The length of the block data
     /// * `key_has_block` - Whether the key already has this block
+    /// * `location` - The storage location of a new block, `None` for the default one.
+    ///   An existing block keeps its location.
     ///
     /// # Returns
     /// A tuple containing:
@@ -540,6 +1759,7 @@ impl Transaction {
         block_hash: BlockID,
         data_len: usize,
         key_has_block: bool,
+        location: Option<&str>,
     ) -> Result<(bool, Block), MetaError> {
         // Check if the block already exists
         match self.backend.get(DEFAULT_BLOCK_TREE, &block_hash)? {
@@ -551,7 +1771,9 @@ impl Transaction {
                 // If the key doesn't have this block, increment the reference count
                 if !key_has_block {
                     let old_rc = block.rc();
-                    block.increment_refcount();
+                    if block.increment_refcount().is_err() {
+                        return Err(self.refcount_overflow(&block_hash, old_rc, 1));
+                    }
                     let new_rc = block.rc();
                     tracing::debug!(
                         block_hash = %hex::encode(&block_hash),
@@ -592,7 +1814,8 @@ impl Transaction {
                     .insert(DEFAULT_PATH_TREE, &block_hash[..idx], block_hash.to_vec())?;
 
                 // insert this new block
-                let block = Block::new(data_len, block_hash[..idx].to_vec());
+                let block = Block::new(data_len, block_hash[..idx].to_vec())
+                    .with_location(location.map(str::to_string));
 
                 tracing::debug!(
                     block_hash = %hex::encode(&block_hash),
@@ -604,11 +1827,224 @@ impl Transaction {
 
                 self.backend
                     .insert(DEFAULT_BLOCK_TREE, &block_hash, block.to_vec())?;
+                self.counters.block(&block, 1);
 
                 Ok((true, block))
             }
         }
     }
+
+    /// Adds a reference to a block whose file was copied from another store,
+    /// creating the block at the `path` it has there if it doesn't exist.
+    ///
+    /// # Arguments
+    /// * `block_hash` - The hash of the block
+    /// * `data_len` - The length of the block data
+    /// * `path` - The path of the block in the other store, a prefix of its hash
+    /// * `location` - The storage location of the block in the other store
+    ///
+    /// # Returns
+    /// Whether the block was created, or an error if `path` is taken by another block
+    pub fn attach_block(
+        &mut self,
+        block_hash: &BlockID,
+        data_len: usize,
+        path: &[u8],
+        location: Option<&str>,
+    ) -> Result<bool, MetaError> {
+        if self.add_block_references(block_hash, 1)?.is_some() {
+            return Ok(false);
+        }
+        if path.is_empty() || !block_hash.starts_with(path) {
+            return Err(MetaError::InvalidArgument(format!(
+                "path {} is not a prefix of block {}",
+                hex::encode(path),
+                hex::encode(block_hash)
+            )));
+        }
+
+        match self.backend.get(DEFAULT_PATH_TREE, path)? {
+            Some(owner) if owner.as_ref() != block_hash.as_slice() => {
+                return Err(MetaError::OtherDBError(format!(
+                    "path {} of block {} is used by block {}",
+                    hex::encode(path),
+                    hex::encode(block_hash),
+                    hex::encode(&owner)
+                )));
+            }
+            Some(_) => {}
+            None => self
+                .backend
+                .insert(DEFAULT_PATH_TREE, path, block_hash.to_vec())?,
+        }
+
+        let block =
+            Block::new(data_len, path.to_vec()).with_location(location.map(str::to_string));
+        tracing::debug!(
+            block_hash = %hex::encode(block_hash),
+            data_len = data_len,
+            "Attaching copied block with rc=1"
+        );
+        self.backend
+            .insert(DEFAULT_BLOCK_TREE, block_hash, block.to_vec())?;
+        self.counters.block(&block, 1);
+        Ok(true)
+    }
+
+    /// Records that the file of a block moved to another storage location.
+    ///
+    /// # Arguments
+    /// * `block_hash` - The hash of the block
+    /// * `location` - The storage location now holding the block file, `None` for
+    ///   the default one
+    ///
+    /// # Returns
+    /// The previous location of the block, or `MetaError::BlockNotFound` if the
+    /// block doesn't exist
+    pub fn set_block_location(
+        &mut self,
+        block_hash: &BlockID,
+        location: Option<&str>,
+    ) -> Result<Option<String>, MetaError> {
+        let data = self
+            .backend
+            .get(DEFAULT_BLOCK_TREE, block_hash)?
+            .ok_or(MetaError::BlockNotFound)?;
+        let block = Block::try_from(&*data as &[u8])
+            .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        let previous = block.location().map(str::to_string);
+        let block = block.with_location(location.map(str::to_string));
+        self.backend
+            .insert(DEFAULT_BLOCK_TREE, block_hash, block.to_vec())?;
+        Ok(previous)
+    }
+
+    /// Adds a reference to an existing block, for objects reusing the blocks of
+    /// other objects.
+    ///
+    /// # Arguments
+    /// * `block_hash` - The hash of the block
+    ///
+    /// # Returns
+    /// The updated Block, or `MetaError::BlockNotFound` if the block doesn't exist
+    pub fn add_block_reference(&mut self, block_hash: &BlockID) -> Result<Block, MetaError> {
+        self.add_block_references(block_hash, 1)?
+            .ok_or(MetaError::BlockNotFound)
+    }
+
+    /// Adds `count` references to an existing block with a single update of its
+    /// record.
+    ///
+    /// # Arguments
+    /// * `block_hash` - The hash of the block
+    /// * `count` - The number of references to add
+    ///
+    /// # Returns
+    /// The updated Block, or None if the block doesn't exist
+    pub fn add_block_references(
+        &mut self,
+        block_hash: &BlockID,
+        count: usize,
+    ) -> Result<Option<Block>, MetaError> {
+        let Some(block_data) = self.backend.get(DEFAULT_BLOCK_TREE, block_hash)? else {
+            return Ok(None);
+        };
+        let mut block = Block::try_from(&*block_data as &[u8])
+            .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        if block.add_refcount(count as u64).is_err() {
+            return Err(self.refcount_overflow(block_hash, block.rc(), count as u64));
+        }
+        tracing::debug!(
+            block_hash = %hex::encode(block_hash),
+            count,
+            rc = block.rc(),
+            "Added block references"
+        );
+        self.backend
+            .insert(DEFAULT_BLOCK_TREE, block_hash, block.to_vec())?;
+        Ok(Some(block))
+    }
+
+    /// Stores `value` at `key` in a tree, returning the value it replaces.
+    ///
+    /// # Arguments
+    /// * `tree_name` - The name of the tree
+    /// * `key` - The key to store the value at
+    /// * `value` - The new value
+    ///
+    /// # Returns
+    /// The previous value, `None` if the key didn't exist
+    pub(crate) fn replace(
+        &mut self,
+        tree_name: &str,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<Option<Bytes>, MetaError> {
+        let previous = self.backend.get(tree_name, key)?;
+        self.backend.insert(tree_name, key, value)?;
+        Ok(previous)
+    }
+
+    /// Removes `key` from a tree, returning its value.
+    ///
+    /// # Arguments
+    /// * `tree_name` - The name of the tree
+    /// * `key` - The key to remove
+    ///
+    /// # Returns
+    /// The removed value, `None` if the key didn't exist
+    pub(crate) fn take(&mut self, tree_name: &str, key: &[u8]) -> Result<Option<Bytes>, MetaError> {
+        let previous = self.backend.get(tree_name, key)?;
+        if previous.is_some() {
+            self.backend.remove(tree_name, key)?;
+        }
+        Ok(previous)
+    }
+
+    /// Releases a reference to a block, the block is removed when this was the
+    /// last reference.
+    ///
+    /// # Arguments
+    /// * `block_hash` - The hash of the block
+    ///
+    /// # Returns
+    /// The removed Block, whose file must be deleted, or `None` if the block is
+    /// still referenced or doesn't exist
+    pub fn release_block(&mut self, block_hash: &BlockID) -> Result<Option<Block>, MetaError> {
+        let Some(block_data) = self.backend.get(DEFAULT_BLOCK_TREE, block_hash)? else {
+            tracing::warn!(
+                block_hash = %hex::encode(block_hash),
+                "Block not found in tree during release"
+            );
+            return Ok(None);
+        };
+        let mut block = Block::try_from(&*block_data as &[u8])
+            .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        if block.rc() == 1 {
+            self.backend.remove(DEFAULT_BLOCK_TREE, block_hash)?;
+            self.counters.block(&block, -1);
+            return Ok(Some(block));
+        }
+        // a block without references is removed with its last one, so a count of 0
+        // comes from corrupted metadata or a release without a reference
+        debug_assert!(
+            block.rc() > 0,
+            "released block {} without references",
+            hex::encode(block_hash)
+        );
+        if !block.decrement_refcount() {
+            // other objects may still use the block, so its file is kept
+            tracing::error!(
+                block_hash = %hex::encode(block_hash),
+                "Released a reference of a block without references"
+            );
+            self.metrics.refcount_anomaly("underflow");
+            return Ok(None);
+        }
+        self.backend
+            .insert(DEFAULT_BLOCK_TREE, block_hash, block.to_vec())?;
+        Ok(None)
+    }
 }
 
 /// Abstracts the storage backend operations needed by Transaction.
@@ -622,6 +2058,12 @@ pub(crate) trait TransactionBackend: Send + Sync {
     /// Success or an error if the commit fails
     fn commit(&mut self) -> Result<(), MetaError>;
 
+    /// Sets the durability of the commit, instead of the one of the store.
+    ///
+    /// # Arguments
+    /// * `durability` - How the commit is persisted
+    fn set_durability(&mut self, durability: Durability);
+
     /// Rolls back the transaction, discarding all changes.
     fn rollback(&mut self);
 
@@ -633,7 +2075,7 @@ pub(crate) trait TransactionBackend: Send + Sync {
     ///
     /// # Returns
     /// The value if found, None if the key doesn't exist, or an error
-    fn get(&mut self, tree_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>, MetaError>;
+    fn get(&mut self, tree_name: &str, key: &[u8]) -> Result<Option<Bytes>, MetaError>;
 
     /// Inserts a value into the specified tree.
     ///
@@ -645,4 +2087,14 @@ pub(crate) trait TransactionBackend: Send + Sync {
     /// # Returns
     /// Success or an error if the insertion fails
     fn insert(&mut self, tree_name: &str, key: &[u8], data: Vec<u8>) -> Result<(), MetaError>;
+
+    /// Removes a key from the specified tree.
+    ///
+    /// # Arguments
+    /// * `tree_name` - The name of the tree
+    /// * `key` - The key to remove
+    ///
+    /// # Returns
+    /// Success or an error if the removal fails
+    fn remove(&mut self, tree_name: &str, key: &[u8]) -> Result<(), MetaError>;
 }
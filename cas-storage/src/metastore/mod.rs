@@ -1,17 +1,37 @@
+mod acl;
 mod block;
+mod block_refs;
 mod bucket_meta;
 mod constants;
+mod counters;
 mod errors;
+mod kv_separation;
+mod lifecycle;
+mod logging;
 mod meta_store;
 mod object;
 mod stores;
+mod tags;
 mod traits;
 
+pub use acl::CannedAcl;
 pub use block::{Block, BlockID, BLOCKID_SIZE};
-pub use bucket_meta::BucketMeta;
+pub use block_refs::{BlockRef, BLOCK_REFS_TREE, OBJECT_HASHES_TREE};
+pub use bucket_meta::{BucketLimits, BucketMeta, LimitExceeded};
 pub use constants::*;
+pub use counters::{BucketCounters, PrefixCount, StoreCounters, COUNTERS_TREE};
 pub use errors::{FsError, MetaError};
+pub use kv_separation::{is_bucket_tree, BlobStats, KvSeparation, KV_SEPARATED_TREE};
+pub use lifecycle::{
+    BucketLifecycle, Expiration, LifecycleRule, ObjectExpiration, MAX_LIFECYCLE_RULES,
+    MAX_RULE_ID_LENGTH,
+};
+pub use logging::BucketLogging;
 pub use meta_store::*;
-pub use object::{Object, ObjectData, ObjectType};
+pub use object::{ETag, Object, ObjectData, ObjectType, ETAG_SIZE};
 pub use stores::{FjallStore, FjallStoreNotx};
+pub use tags::{
+    ObjectTags, TagFilter, MAX_BUCKET_TAGS, MAX_OBJECT_TAGS, MAX_TAG_KEY_LENGTH,
+    MAX_TAG_VALUE_LENGTH,
+};
 pub use traits::*;
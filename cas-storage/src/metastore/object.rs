@@ -9,6 +9,17 @@ use faster_hex::hex_string;
 
 use super::{BlockID, FsError, BLOCKID_SIZE, PTR_SIZE};
 
+/// Size of an ETag, an MD5 digest
+pub const ETAG_SIZE: usize = 16;
+
+/// The binary ETag of an object, see [`Object::e_tag`]
+pub type ETag = [u8; ETAG_SIZE];
+
+/// Set in the type byte of a serialized object if its ETag follows its hash.
+/// Without it the ETag is the hash, like for objects written before the two
+/// were separated.
+const SEPARATE_E_TAG: u8 = 0x80;
+
 /// Represents an object in the storage system with its metadata and content (for Inline objects).
 ///
 /// An Object is the primary entity stored in the system and can be one of three types:
@@ -16,9 +27,10 @@ use super::{BlockID, FsError, BLOCKID_SIZE, PTR_SIZE};
 /// - Multipart: An object composed of multiple parts uploaded separately
 /// - Inline: A small object with its data stored directly in the metadata
 ///
-/// Each object contains metadata such as size, creation time, and a unique hash,
-/// along with either references to data blocks or the inline data itself.
-#[derive(Debug)]
+/// Each object contains metadata such as size, creation time, the hash of its
+/// content and its ETag, along with either references to data blocks or the inline
+/// data itself.
+#[derive(Debug, Clone)]
 pub struct Object {
     /// The type of the object (Single, Multipart, or Inline)
     object_type: ObjectType,
@@ -26,8 +38,10 @@ pub struct Object {
     size: u64,
     /// Creation time as a Unix timestamp (seconds since epoch)
     ctime: i64,
-    /// Unique hash identifier for the object
+    /// Hash of the content, computed with the content hash of the store
     hash: BlockID,
+    /// The ETag returned to clients, without the part count of multipart objects
+    e_tag: ETag,
     /// The actual data or references to data blocks
     data: ObjectData,
 }
@@ -36,7 +50,7 @@ pub struct Object {
 ///
 /// This enum allows the system to handle different storage strategies
 /// based on object size and upload method.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ObjectData {
     /// The object is stored inline in the metadata.
     ///
@@ -100,10 +114,11 @@ impl Object {
     /// Creates a new Object with the specified properties.
     ///
     /// The object_type is automatically determined based on the provided object_data.
+    /// The ETag is the content hash, set it with `with_e_tag` if they differ.
     ///
     /// # Arguments
     /// * `size` - Total size of the object in bytes
-    /// * `hash` - Unique hash identifier for the object
+    /// * `hash` - Hash of the object content
     /// * `object_data` - The data storage strategy and content/references
     ///
     /// # Returns
@@ -119,10 +134,24 @@ impl Object {
             size,
             ctime: Utc::now().timestamp(),
             hash,
+            e_tag: hash,
             data: object_data,
         }
     }
 
+    /// Sets the ETag of the object.
+    ///
+    /// # Arguments
+    /// * `e_tag` - The MD5 of the data, or the MD5 of the ETags of the parts for
+    ///   multipart objects
+    ///
+    /// # Returns
+    /// The object with the ETag set
+    pub fn with_e_tag(mut self, e_tag: ETag) -> Self {
+        self.e_tag = e_tag;
+        self
+    }
+
     /// Returns the minimum size needed for inline metadata storage.
     ///
     /// This is used to determine if an object can be stored inline.
@@ -149,14 +178,16 @@ impl Object {
     /// A formatted ETag string
     pub fn format_e_tag(&self) -> String {
         if let ObjectData::MultiPart { parts, .. } = &self.data {
-            format!("\"{}-{}\"", hex_string(&self.hash), parts)
+            format!("\"{}-{}\"", hex_string(&self.e_tag), parts)
         } else {
-            // Handle error case or provide default
-            format!("\"{}\"", hex_string(&self.hash))
+            format!("\"{}\"", hex_string(&self.e_tag))
         }
     }
 
-    /// Returns the unique hash identifier of the object.
+    /// Returns the hash of the object content.
+    ///
+    /// This is computed with the content hash blocks are identified by, it is not
+    /// necessarily the ETag.
     ///
     /// # Returns
     /// A reference to the object's BlockID (hash)
@@ -164,6 +195,19 @@ impl Object {
         &self.hash
     }
 
+    /// Returns the ETag of the object, without the part count of multipart objects.
+    ///
+    /// # Returns
+    /// A reference to the binary ETag
+    pub fn e_tag(&self) -> &ETag {
+        &self.e_tag
+    }
+
+    /// Returns `true` if the ETag differs from the hash and is serialized separately.
+    fn has_separate_e_tag(&self) -> bool {
+        self.e_tag[..] != self.hash[..]
+    }
+
     /// Updates the object's creation time to the current time.
     ///
     /// This is typically used when an object is modified.
@@ -213,7 +257,7 @@ impl Object {
     /// # Returns
     /// The last modification time
     pub fn last_modified(&self) -> SystemTime {
-        UNIX_EPOCH + std::time::Duration::from_secs(self.ctime as u64)
+        UNIX_EPOCH + std::time::Duration::from_secs(self.ctime.max(0) as u64)
     }
 
     /// Formats the creation time as an RFC3339 string.
@@ -222,7 +266,8 @@ impl Object {
     /// A formatted timestamp string
     pub fn format_ctime(&self) -> String {
         Utc.timestamp_opt(self.ctime, 0)
-            .unwrap()
+            .single()
+            .unwrap_or_default()
             .to_rfc3339_opts(SecondsFormat::Secs, true)
     }
 
@@ -233,7 +278,10 @@ impl Object {
     /// # Returns
     /// The number of bytes needed for serialization
     fn num_bytes(&self) -> usize {
-        let mandatory_fields_size = 17 + BLOCKID_SIZE;
+        let mut mandatory_fields_size = 17 + BLOCKID_SIZE;
+        if self.has_separate_e_tag() {
+            mandatory_fields_size += ETAG_SIZE;
+        }
         match &self.data {
             ObjectData::SinglePart { blocks } => {
                 mandatory_fields_size + PTR_SIZE + (blocks.len() * BLOCKID_SIZE)
@@ -284,20 +332,29 @@ impl Object {
 /// Implements serialization of an Object to a byte vector.
 ///
 /// The serialization format includes:
-/// - 1 byte for object type
+/// - 1 byte for object type, with `SEPARATE_E_TAG` set if the ETag is not the hash
 /// - 8 bytes for size
 /// - 8 bytes for creation time
 /// - BLOCKID_SIZE bytes for hash
+/// - ETAG_SIZE bytes for the ETag, only with `SEPARATE_E_TAG`
 /// - Variant-specific data based on the object type
 impl From<&Object> for Vec<u8> {
     fn from(o: &Object) -> Self {
         let mut raw_data = Vec::with_capacity(o.num_bytes());
 
         // Write header fields
-        raw_data.extend_from_slice(&o.object_type.as_u8().to_le_bytes());
+        let separate_e_tag = o.has_separate_e_tag();
+        let mut object_type = o.object_type.as_u8();
+        if separate_e_tag {
+            object_type |= SEPARATE_E_TAG;
+        }
+        raw_data.extend_from_slice(&object_type.to_le_bytes());
         raw_data.extend_from_slice(&o.size.to_le_bytes());
         raw_data.extend_from_slice(&o.ctime.to_le_bytes());
         raw_data.extend_from_slice(&o.hash);
+        if separate_e_tag {
+            raw_data.extend_from_slice(&o.e_tag);
+        }
 
         // Write variant-specific data
         match &o.data {
@@ -348,7 +405,8 @@ impl TryFrom<&[u8]> for Object {
         let mut pos = 0;
 
         let object_type = u8::from_le_bytes(value[pos..pos + 1].try_into().unwrap());
-        let object_type = match object_type {
+        let separate_e_tag = object_type & SEPARATE_E_TAG != 0;
+        let object_type = match object_type & !SEPARATE_E_TAG {
             0 => ObjectType::Single,
             1 => ObjectType::Multipart,
             2 => ObjectType::Inline,
@@ -364,10 +422,22 @@ impl TryFrom<&[u8]> for Object {
         let ctime = i64::from_le_bytes(value[pos..pos + 8].try_into().unwrap());
         pos += 8;
 
-        // etag: BLOCKID_SIZE bytes
-        let e_tag = value[pos..pos + BLOCKID_SIZE].try_into().unwrap();
+        // hash: BLOCKID_SIZE bytes
+        let hash: BlockID = value[pos..pos + BLOCKID_SIZE].try_into().unwrap();
         pos += BLOCKID_SIZE;
 
+        // etag: ETAG_SIZE bytes, if it isn't the hash
+        let e_tag = if separate_e_tag {
+            if value.len() < minimum_raw_object_size() + ETAG_SIZE {
+                return Err(FsError::MalformedObject);
+            }
+            let e_tag = value[pos..pos + ETAG_SIZE].try_into().unwrap();
+            pos += ETAG_SIZE;
+            e_tag
+        } else {
+            hash
+        };
+
         let data = match object_type {
             ObjectType::Single | ObjectType::Multipart => {
                 // block_len : PTR_SIZE bytes
@@ -375,12 +445,14 @@ impl TryFrom<&[u8]> for Object {
                     usize::from_le_bytes(value[pos..pos + PTR_SIZE].try_into().unwrap());
                 pos += PTR_SIZE;
 
-                // check the expected length
-                let mut expected_len = pos + block_len * BLOCKID_SIZE;
+                // check the expected length, a corrupt block count must not overflow it
+                let mut expected_len = block_len
+                    .checked_mul(BLOCKID_SIZE)
+                    .and_then(|len| len.checked_add(pos));
                 if object_type == ObjectType::Multipart {
-                    expected_len += PTR_SIZE;
+                    expected_len = expected_len.and_then(|len| len.checked_add(PTR_SIZE));
                 }
-                if value.len() != expected_len {
+                if expected_len != Some(value.len()) {
                     return Err(FsError::MalformedObject);
                 }
 
@@ -407,13 +479,15 @@ impl TryFrom<&[u8]> for Object {
                 pos += PTR_SIZE;
 
                 // check the expected length
-                let expected_len = pos + data_len as usize;
-                if value.len() != expected_len {
+                let expected_len = usize::try_from(data_len)
+                    .ok()
+                    .and_then(|len| len.checked_add(pos));
+                if expected_len != Some(value.len()) {
                     return Err(FsError::MalformedObject);
                 }
 
                 // data: data_len bytes
-                let data = value[pos..pos + data_len as usize].to_vec();
+                let data = value[pos..].to_vec();
                 ObjectData::Inline { data }
             }
         };
@@ -421,7 +495,8 @@ impl TryFrom<&[u8]> for Object {
             object_type,
             size,
             ctime,
-            hash: e_tag,
+            hash,
+            e_tag,
             data,
         })
     }
@@ -486,6 +561,7 @@ mod tests {
             assert_eq!(deserialized.size, obj.size);
             assert_eq!(deserialized.ctime, obj.ctime);
             assert_eq!(deserialized.hash, obj.hash);
+            assert_eq!(deserialized.e_tag, obj.e_tag);
 
             match (obj.data, deserialized.data) {
                 (ObjectData::SinglePart { blocks: b1 }, ObjectData::SinglePart { blocks: b2 }) => {
@@ -537,6 +613,27 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_separate_e_tag() {
+        for (_, obj) in create_test_objects() {
+            let same: Vec<u8> = (&obj).into();
+            assert_eq!(same[0] & SEPARATE_E_TAG, 0);
+
+            let obj = obj.with_e_tag([9; ETAG_SIZE]);
+            let serialized: Vec<u8> = (&obj).into();
+            assert_eq!(serialized.len(), same.len() + ETAG_SIZE);
+            assert_eq!(serialized.len(), obj.num_bytes());
+
+            let deserialized = Object::try_from(serialized.as_slice()).unwrap();
+            assert_eq!(deserialized.object_type, obj.object_type);
+            assert_eq!(deserialized.hash(), obj.hash());
+            assert_eq!(deserialized.e_tag(), &[9; ETAG_SIZE]);
+            assert_eq!(deserialized.blocks(), obj.blocks());
+            assert_eq!(deserialized.inlined(), obj.inlined());
+            assert!(deserialized.format_e_tag().contains(&"09".repeat(ETAG_SIZE)));
+        }
+    }
+
     #[test]
     fn test_size_calculation() {
         for (_, obj) in create_test_objects() {
@@ -551,4 +648,101 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_malformed_lengths() {
+        // a block count or data length which overflows the expected length
+        for object_type in [0u8, 1, 2] {
+            let mut raw = vec![object_type];
+            raw.extend_from_slice(&[0; 16 + BLOCKID_SIZE]);
+            raw.extend_from_slice(&usize::MAX.to_le_bytes());
+            raw.extend_from_slice(&[0; BLOCKID_SIZE]);
+            assert!(matches!(
+                Object::try_from(raw.as_slice()),
+                Err(FsError::MalformedObject)
+            ));
+        }
+
+        // timestamps out of range are formatted, not panicking
+        let mut obj = create_test_objects().remove(0).1;
+        for ctime in [i64::MIN, -1, i64::MAX] {
+            obj.ctime = ctime;
+            obj.last_modified();
+            obj.format_ctime();
+        }
+    }
+
+    mod proptests {
+        use super::*;
+        use proptest::collection::vec;
+        use proptest::prelude::*;
+        use proptest::sample::Index;
+
+        fn object_data() -> impl Strategy<Value = ObjectData> {
+            prop_oneof![
+                vec(any::<u8>(), 0..256).prop_map(|data| ObjectData::Inline { data }),
+                vec(any::<BlockID>(), 0..32).prop_map(|blocks| ObjectData::SinglePart { blocks }),
+                (vec(any::<BlockID>(), 0..32), any::<usize>())
+                    .prop_map(|(blocks, parts)| ObjectData::MultiPart { blocks, parts }),
+            ]
+        }
+
+        prop_compose! {
+            fn object()(
+                size in any::<u64>(),
+                ctime in any::<i64>(),
+                hash in any::<BlockID>(),
+                e_tag in any::<Option<ETag>>(),
+                data in object_data(),
+            ) -> Object {
+                let mut obj = Object::new(size, hash, data).with_e_tag(e_tag.unwrap_or(hash));
+                obj.ctime = ctime;
+                obj
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn roundtrip(obj in object()) {
+                let raw = obj.to_vec();
+                prop_assert_eq!(raw.len(), obj.num_bytes());
+
+                let decoded = Object::try_from(raw.as_slice()).unwrap();
+                prop_assert_eq!(decoded.object_type, obj.object_type);
+                prop_assert_eq!(decoded.size, obj.size);
+                prop_assert_eq!(decoded.ctime, obj.ctime);
+                prop_assert_eq!(decoded.hash, obj.hash);
+                prop_assert_eq!(decoded.e_tag, obj.e_tag);
+                prop_assert_eq!(decoded.to_vec(), raw);
+            }
+
+            #[test]
+            fn truncated(obj in object(), cut in any::<Index>()) {
+                let raw = obj.to_vec();
+                let cut = cut.index(raw.len());
+                prop_assert!(Object::try_from(&raw[..cut]).is_err());
+            }
+
+            #[test]
+            fn corrupted(obj in object(), at in any::<Index>(), byte in any::<u8>()) {
+                let mut raw = obj.to_vec();
+                let at = at.index(raw.len());
+                raw[at] = byte;
+                if let Ok(decoded) = Object::try_from(raw.as_slice()) {
+                    decoded.last_modified();
+                    decoded.format_ctime();
+                    prop_assert_eq!(decoded.to_vec().len(), decoded.num_bytes());
+                }
+            }
+
+            #[test]
+            fn arbitrary_bytes(raw in vec(any::<u8>(), 0..512)) {
+                if let Ok(decoded) = Object::try_from(raw.as_slice()) {
+                    let canonical = decoded.to_vec();
+                    let again = Object::try_from(canonical.as_slice()).unwrap();
+                    prop_assert_eq!(again.to_vec(), canonical);
+                }
+            }
+        }
+    }
 }
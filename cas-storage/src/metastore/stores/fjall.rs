@@ -1,14 +1,19 @@
 use std::collections::HashMap;
-use std::ops::Deref;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::{convert::TryFrom, sync::Mutex};
+use std::sync::Mutex;
 
+use bytes::Bytes;
 use fjall::{self, TxPartitionHandle};
 
+use super::{
+    blob_gc_of, blob_stats_of, chunked_iter, partition_options, persist_mode, range_before_with,
+    range_filter_with, slice_to_bytes,
+};
 use crate::metastore::{
-    BaseMetaTree, Durability, KeyValuePairs, MetaError, MetaTreeExt, Object, Store, Transaction,
-    TransactionBackend,
+    is_bucket_tree, BaseMetaTree, BlobStats, Durability, KeyValuePairs, KvSeparation, MetaError,
+    MetaTreeExt, MetaTreeSnapshot, Object, Store, Transaction, TransactionBackend,
+    KV_SEPARATED_TREE,
 };
 
 #[derive(Clone)]
@@ -17,6 +22,7 @@ pub struct FjallStore {
     inlined_metadata_size: usize,
     durability: fjall::PersistMode,
     partition_cache: Arc<Mutex<HashMap<String, TxPartitionHandle>>>,
+    kv_separation: Option<KvSeparation>,
 }
 
 impl std::fmt::Debug for FjallStore {
@@ -35,50 +41,96 @@ impl FjallStore {
         inlined_metadata_size: Option<usize>,
         durability: Option<Durability>,
     ) -> Self {
+        Self::try_new(path, inlined_metadata_size, durability).expect("Can open fjall store")
+    }
+
+    /// Like `new`, but returns an error if the keyspace can't be opened.
+    pub fn try_new(
+        path: PathBuf,
+        inlined_metadata_size: Option<usize>,
+        durability: Option<Durability>,
+    ) -> Result<Self, MetaError> {
         tracing::debug!("Opening fjall store at {:?}", path);
 
-        let tx_keyspace = fjall::Config::new(path).open_transactional().unwrap();
+        let tx_keyspace = fjall::Config::new(path)
+            .open_transactional()
+            .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
         let inlined_metadata_size = inlined_metadata_size.unwrap_or(DEFAULT_INLINED_METADATA_SIZE);
 
-        let durability = durability.unwrap_or(Durability::Fdatasync);
-        let durability = match durability {
-            Durability::Buffer => fjall::PersistMode::Buffer,
-            Durability::Fsync => fjall::PersistMode::SyncData,
-            Durability::Fdatasync => fjall::PersistMode::SyncAll,
-        };
+        let durability = persist_mode(durability.unwrap_or(Durability::Fdatasync));
 
-        Self {
+        Ok(Self {
             keyspace: Arc::new(tx_keyspace),
             inlined_metadata_size,
             durability,
             partition_cache: Arc::new(Mutex::new(HashMap::new())),
-        }
+            kv_separation: None,
+        })
+    }
+
+    /// Create new bucket partitions with key-value separation, existing partitions
+    /// keep the options they were created with.
+    pub fn with_kv_separation(mut self, kv_separation: Option<KvSeparation>) -> Self {
+        self.kv_separation = kv_separation;
+        self
     }
 
     fn get_partition(&self, name: &str) -> Result<fjall::TxPartitionHandle, MetaError> {
+        if let Some(partition) = self
+            .partition_cache
+            .lock()
+            .expect("Can lock partition cache")
+            .get(name)
+        {
+            return Ok(partition.clone());
+        }
+
+        let kv_separation = self
+            .kv_separation
+            .filter(|_| is_bucket_tree(name) && !self.keyspace.partition_exists(name));
+        let partition = self
+            .keyspace
+            .open_partition(name, partition_options(kv_separation.as_ref()))
+            .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        if kv_separation.is_some() {
+            self.tree_open(KV_SEPARATED_TREE)?
+                .insert(name.as_bytes(), Vec::new())?;
+        }
+
         Ok(self
             .partition_cache
             .lock()
             .expect("Can lock partition cache")
             .entry(name.to_string())
-            .or_insert(
-                // match self.keyspace.open_partition(name, Default::default()) {
-                //     Ok(partition) => Ok(partition),
-                //     Err(e) => Err(MetaError::OtherDBError(e.to_string())),
-                // },
-                self.keyspace
-                    .open_partition(name, Default::default())
-                    .expect("Can open parition"),
-            )
+            .or_insert(partition)
             .clone())
     }
 
-    fn commit_persist(&self, tx: fjall::WriteTransaction) -> Result<(), MetaError> {
+    // The names of the partitions created with key-value separation
+    fn kv_separated_trees(&self) -> Result<Vec<String>, MetaError> {
+        if !self.keyspace.partition_exists(KV_SEPARATED_TREE) {
+            return Ok(Vec::new());
+        }
+        self.tree_ext_open(KV_SEPARATED_TREE)?
+            .iter_all()
+            .map(|res| res.map(|(name, _)| String::from_utf8_lossy(&name).into_owned()))
+            .filter(|res| {
+                res.as_ref()
+                    .map_or(true, |name| self.keyspace.partition_exists(name))
+            })
+            .collect()
+    }
+
+    fn commit_persist(
+        &self,
+        tx: fjall::WriteTransaction,
+        durability: fjall::PersistMode,
+    ) -> Result<(), MetaError> {
         tx.commit()
             .map_err(|e| MetaError::TransactionError(e.to_string()))?;
 
         self.keyspace
-            .persist(self.durability)
+            .persist(durability)
             .map_err(|e| MetaError::PersistError(e.to_string()))?;
         Ok(())
     }
@@ -112,10 +164,28 @@ impl Store for FjallStore {
 
     fn tree_delete(&self, name: &str) -> Result<(), MetaError> {
         let partition = self.get_partition(name)?;
-        match self.keyspace.delete_partition(partition) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(MetaError::OtherDBError(e.to_string())),
+        self.partition_cache
+            .lock()
+            .expect("Can lock partition cache")
+            .remove(name);
+        if let Err(e) = self.keyspace.delete_partition(partition) {
+            return Err(MetaError::OtherDBError(e.to_string()));
+        }
+        if is_bucket_tree(name) && self.keyspace.partition_exists(KV_SEPARATED_TREE) {
+            self.tree_open(KV_SEPARATED_TREE)?.remove(name.as_bytes())?;
         }
+        Ok(())
+    }
+
+    fn tree_names(&self) -> Result<Vec<String>, MetaError> {
+        let names = self.keyspace.list_partitions();
+        Ok(names.iter().map(|name| String::from(&**name)).collect())
+    }
+
+    fn persist(&self, durability: Durability) -> Result<(), MetaError> {
+        self.keyspace
+            .persist(persist_mode(durability))
+            .map_err(|e| MetaError::PersistError(e.to_string()))
     }
 
     fn begin_transaction(&self) -> Transaction {
@@ -139,17 +209,42 @@ impl Store for FjallStore {
     fn disk_space(&self) -> u64 {
         self.keyspace.disk_space()
     }
+
+    fn tree_disk_space(&self, tree_name: &str) -> Result<u64, MetaError> {
+        if !self.keyspace.partition_exists(tree_name) {
+            return Ok(0);
+        }
+        Ok(self.get_partition(tree_name)?.inner().disk_space())
+    }
+
+    fn blob_stats(&self) -> Result<Vec<BlobStats>, MetaError> {
+        self.kv_separated_trees()?
+            .iter()
+            .map(|name| blob_stats_of(name, &self.get_partition(name)?))
+            .collect()
+    }
+
+    fn blob_gc(&self) -> Result<u64, MetaError> {
+        let kv_separation = self.kv_separation.unwrap_or_default();
+        let mut freed = 0;
+        for name in self.kv_separated_trees()? {
+            freed += blob_gc_of(&self.get_partition(&name)?, &kv_separation)?;
+        }
+        Ok(freed)
+    }
 }
 
 pub struct FjallTransaction {
     tx: Option<fjall::WriteTransaction<'static>>,
     store: Arc<FjallStore>,
+    durability: fjall::PersistMode,
 }
 
 impl FjallTransaction {
     pub fn new(tx: fjall::WriteTransaction<'static>, store: Arc<FjallStore>) -> Self {
         Self {
             tx: Some(tx),
+            durability: store.durability,
             store,
         }
     }
@@ -162,7 +257,7 @@ impl TransactionBackend for FjallTransaction {
     fn commit(&mut self) -> Result<(), MetaError> {
         if let Some(tx) = self.tx.take() {
             tracing::debug!(target: "cas_storage::locks", "Transaction commit started");
-            let res = self.store.commit_persist(tx);
+            let res = self.store.commit_persist(tx, self.durability);
             tracing::debug!(target: "cas_storage::locks", "Transaction commit finished");
             res
         } else {
@@ -172,6 +267,10 @@ impl TransactionBackend for FjallTransaction {
         }
     }
 
+    fn set_durability(&mut self, durability: Durability) {
+        self.durability = persist_mode(durability);
+    }
+
     fn rollback(&mut self) {
         if let Some(tx) = self.tx.take() {
             tracing::debug!(target: "cas_storage::locks", "Transaction rollback");
@@ -179,11 +278,11 @@ impl TransactionBackend for FjallTransaction {
         }
     }
 
-    fn get(&mut self, tree_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>, MetaError> {
+    fn get(&mut self, tree_name: &str, key: &[u8]) -> Result<Option<Bytes>, MetaError> {
         let partition = self.store.get_partition(tree_name)?;
         if let Some(ref mut tx) = self.tx {
             match tx.get(&partition, key) {
-                Ok(Some(data)) => Ok(Some(data.to_vec())),
+                Ok(Some(data)) => Ok(Some(slice_to_bytes(data))),
                 Ok(None) => Ok(None),
                 Err(e) => Err(MetaError::OtherDBError(e.to_string())),
             }
@@ -205,6 +304,18 @@ impl TransactionBackend for FjallTransaction {
             ))
         }
     }
+
+    fn remove(&mut self, tree_name: &str, key: &[u8]) -> Result<(), MetaError> {
+        let partition = self.store.get_partition(tree_name)?;
+        if let Some(ref mut tx) = self.tx {
+            tx.remove(&partition, key);
+            Ok(())
+        } else {
+            Err(MetaError::TransactionError(
+                "Transaction already rolled back".to_string(),
+            ))
+        }
+    }
 }
 
 pub struct FjallTree {
@@ -251,9 +362,9 @@ impl BaseMetaTree for FjallTree {
         }
     }
 
-    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, MetaError> {
+    fn get(&self, key: &[u8]) -> Result<Option<Bytes>, MetaError> {
         match self.get(key) {
-            Ok(Some(v)) => Ok(Some(v.to_vec())),
+            Ok(Some(v)) => Ok(Some(slice_to_bytes(v))),
             Ok(None) => Ok(None),
             Err(e) => Err(e),
         }
@@ -271,96 +382,92 @@ impl BaseMetaTree for FjallTree {
 
 impl MetaTreeExt for FjallTree {
     fn iter_all(&self) -> KeyValuePairs {
+        let read_tx = self.keyspace.read_tx();
+        let partition = self.partition.clone();
+
+        chunked_iter(move |from, limit| {
+            read_tx
+                .range(&partition, from..)
+                .take(limit)
+                .map(|res| res.map(|(k, v)| (slice_to_bytes(k), slice_to_bytes(v))))
+                .collect()
+        })
+    }
+
+    fn iter_prefix(&self, prefix: &[u8]) -> KeyValuePairs {
+        let read_tx = self.keyspace.read_tx();
         let partition = self.partition.clone();
-        let keyspace = self.keyspace.clone();
-        let mut last_key: Option<Vec<u8>> = None;
-
-        Box::new(std::iter::from_fn(move || {
-            let read_tx = keyspace.read_tx();
-            let range = match &last_key {
-                Some(k) => {
-                    let mut next = k.clone();
-                    next.push(0);
-                    next..
-                }
-                None => Vec::new()..,
-            };
+        let prefix = prefix.to_vec();
 
+        chunked_iter(move |from, limit| {
+            let from = from.max(prefix.clone());
             read_tx
-                .range::<Vec<u8>, _>(&partition, range)
-                .next()
-                .map(|res| match res {
-                    Ok((k, v)) => {
-                        last_key = Some(k.to_vec());
-                        Ok((k.to_vec(), v.to_vec()))
-                    }
-                    Err(e) => {
-                        tracing::error!("Error reading key: {}", e);
-                        Err(MetaError::OtherDBError(e.to_string()))
-                    }
+                .range(&partition, from..)
+                .take_while(|res| match res {
+                    Ok((k, _)) => k.starts_with(&prefix),
+                    Err(_) => true,
                 })
-        }))
+                .take(limit)
+                .map(|res| res.map(|(k, v)| (slice_to_bytes(k), slice_to_bytes(v))))
+                .collect()
+        })
     }
 
-    // rules:
-    // 1. continuation_token and start_after exists: use the one with the highest lexicographical order
-    //    -> call it: ctsa
-    // 2. if prefix exists
-    //    -> ctsa > the prefix && doesn't have prefix: return zero results
-    //    -> ctsa < prefix: ignore it
-    //    -> ctsa has the prefix: use it as start_after
-    //          In kv store like fjall & Sled: we process it in the Rust code
     fn range_filter<'a>(
         &'a self,
         start_after: Option<String>,
         prefix: Option<String>,
         continuation_token: Option<String>,
     ) -> Box<dyn Iterator<Item = (String, Object)> + 'a> {
-        let mut ctsa = match (continuation_token, start_after) {
-            (Some(token), Some(start)) => Some(std::cmp::max(token, start)),
-            (Some(token), None) => Some(token),
-            (None, start) => start,
-        };
-
         let read_tx = self.keyspace.read_tx();
+        range_filter_with(
+            start_after,
+            prefix,
+            continuation_token,
+            |prefix| Box::new(read_tx.prefix(&self.partition, prefix)),
+            |from| Box::new(read_tx.range(&self.partition, from..)),
+        )
+    }
 
-        let base_iter: Box<
-            dyn Iterator<Item = Result<(fjall::Slice, fjall::Slice), fjall::Error>>,
-        > = match (prefix.as_ref(), ctsa.as_ref()) {
-            (Some(prefix), Some(ctsa)) if (ctsa > prefix && !ctsa.starts_with(prefix)) => {
-                //Return empty iterator if ctsa is after prefix
-                Box::new(std::iter::empty())
-            }
-            (Some(prefix), Some(ctsa_local)) if ctsa_local < prefix => {
-                // If ctsa is before prefix, ignore ctsa
-                ctsa = None;
-                Box::new(read_tx.prefix(&self.partition, prefix.as_bytes()))
-            }
-            (Some(prefix), _) => Box::new(read_tx.prefix(&self.partition, prefix.as_bytes())),
-            (None, Some(ctsa)) => {
-                let mut next_key = ctsa.as_bytes().to_vec();
-                next_key.push(0);
-                Box::new(read_tx.range(&self.partition, next_key..))
-            }
-            (None, None) => Box::new(read_tx.range::<Vec<u8>, _>(&self.partition, ..)),
-        };
+    fn range_before<'a>(
+        &'a self,
+        end_before: String,
+        prefix: Option<String>,
+    ) -> Box<dyn Iterator<Item = (String, Object)> + 'a> {
+        let read_tx = self.keyspace.read_tx();
+        range_before_with(end_before, prefix, |from, to| {
+            Box::new(read_tx.range(&self.partition, from..to).rev())
+        })
+    }
 
-        let filtered = base_iter.filter_map(|res| res.ok());
+    fn snapshot(&self) -> Arc<dyn MetaTreeSnapshot> {
+        Arc::new(FjallTreeSnapshot {
+            read_tx: self.keyspace.read_tx(),
+            partition: self.partition.clone(),
+        })
+    }
+}
 
-        let skip_filtered = if prefix.is_some() && ctsa.is_some() {
-            let ctsa_bytes = ctsa.unwrap().into_bytes();
-            Box::new(
-                filtered.skip_while(move |(raw_key, _)| raw_key.deref() <= ctsa_bytes.as_slice()),
-            ) as Box<dyn Iterator<Item = _>>
-        } else {
-            Box::new(filtered)
-        };
+/// Snapshot of a [`FjallTree`], backed by a read transaction.
+struct FjallTreeSnapshot {
+    read_tx: fjall::ReadTransaction,
+    partition: TxPartitionHandle,
+}
 
-        Box::new(skip_filtered.map(|(raw_key, raw_value)| {
-            let key = unsafe { String::from_utf8_unchecked(raw_key.to_vec()) };
-            let obj = Object::try_from(&*raw_value).unwrap();
-            (key, obj)
-        }))
+impl MetaTreeSnapshot for FjallTreeSnapshot {
+    fn range_filter<'a>(
+        &'a self,
+        start_after: Option<String>,
+        prefix: Option<String>,
+        continuation_token: Option<String>,
+    ) -> Box<dyn Iterator<Item = (String, Object)> + 'a> {
+        range_filter_with(
+            start_after,
+            prefix,
+            continuation_token,
+            |prefix| Box::new(self.read_tx.prefix(&self.partition, prefix)),
+            |from| Box::new(self.read_tx.range(&self.partition, from..)),
+        )
     }
 }
 
@@ -400,4 +507,49 @@ mod tests {
         let (store, _dir) = setup_store();
         test_utils::test_range_filter(&store);
     }
+
+    #[test]
+    fn test_range_before() {
+        let (store, _dir) = setup_store();
+        test_utils::test_range_before(&store);
+    }
+
+    #[test]
+    fn test_snapshot_range_filter() {
+        let (store, _dir) = setup_store();
+        test_utils::test_snapshot_range_filter(&store);
+    }
+
+    #[test]
+    fn test_iter_all_chunks() {
+        let (store, _dir) = setup_store();
+        test_utils::test_iter_all_chunks(&store);
+    }
+
+    #[test]
+    fn test_iter_prefix() {
+        let (store, _dir) = setup_store();
+        test_utils::test_iter_prefix(&store);
+    }
+
+    #[test]
+    fn test_kv_separation() {
+        let (store, _dir) = setup_store();
+        let store = store.with_kv_separation(Some(KvSeparation {
+            threshold: 16,
+            ..Default::default()
+        }));
+
+        let bucket = store.tree_open("bucket").unwrap();
+        bucket.insert(b"key", vec![7; 1024]).unwrap();
+        store.tree_open("_BLOCKS").unwrap();
+        let stats = store.blob_stats().unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].tree, "bucket");
+        assert_eq!(bucket.get(b"key").unwrap().unwrap().len(), 1024);
+
+        store.blob_gc().unwrap();
+        store.tree_delete("bucket").unwrap();
+        assert!(store.blob_stats().unwrap().is_empty());
+    }
 }
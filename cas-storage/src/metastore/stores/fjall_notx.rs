@@ -1,19 +1,25 @@
-use std::convert::TryFrom;
-use std::ops::Deref;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use bytes::Bytes;
 use fjall;
 
+use super::{
+    blob_gc_of, blob_stats_of, chunked_iter, partition_options, persist_mode, range_before_with,
+    range_filter_with, slice_to_bytes,
+};
 use crate::metastore::{
-    BaseMetaTree, KeyValuePairs, MetaError, MetaTreeExt, Object, Store, Transaction,
-    TransactionBackend,
+    is_bucket_tree, BaseMetaTree, BlobStats, Durability, KeyValuePairs, KvSeparation, MetaError,
+    MetaTreeExt, MetaTreeSnapshot, Object, Store, Transaction, TransactionBackend,
+    KV_SEPARATED_TREE,
 };
 
 #[derive(Clone)]
 pub struct FjallStoreNotx {
     keyspace: Arc<fjall::Keyspace>,
     inlined_metadata_size: usize,
+    durability: fjall::PersistMode,
+    kv_separation: Option<KvSeparation>,
 }
 
 impl std::fmt::Debug for FjallStoreNotx {
@@ -26,23 +32,74 @@ impl std::fmt::Debug for FjallStoreNotx {
 
 impl FjallStoreNotx {
     pub fn new(path: PathBuf, inlined_metadata_size: Option<usize>) -> Self {
+        Self::try_new(path, inlined_metadata_size).expect("Can open fjall store")
+    }
+
+    /// Like `new`, but returns an error if the keyspace can't be opened.
+    pub fn try_new(path: PathBuf, inlined_metadata_size: Option<usize>) -> Result<Self, MetaError> {
         tracing::debug!("Opening fjall store at {:?}", path);
 
-        let keyspace = fjall::Config::new(path).open().unwrap();
+        let keyspace = fjall::Config::new(path)
+            .open()
+            .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
         // setting very low will practically disable it by default
         let inlined_metadata_size = inlined_metadata_size.unwrap_or(1);
 
-        Self {
+        Ok(Self {
             keyspace: Arc::new(keyspace),
             inlined_metadata_size,
+            durability: persist_mode(Durability::Fdatasync),
+            kv_separation: None,
+        })
+    }
+
+    /// Persist the journal with `durability` when a transaction commits, by
+    /// default with `Durability::Fdatasync` like the transactional store. The
+    /// writes of a transaction are applied as they are made, the commit only
+    /// waits for them to be persisted, so a write is never acknowledged before
+    /// it would survive a crash.
+    pub fn with_durability(mut self, durability: Option<Durability>) -> Self {
+        if let Some(durability) = durability {
+            self.durability = persist_mode(durability);
         }
+        self
+    }
+
+    /// Create new bucket partitions with key-value separation, existing partitions
+    /// keep the options they were created with.
+    pub fn with_kv_separation(mut self, kv_separation: Option<KvSeparation>) -> Self {
+        self.kv_separation = kv_separation;
+        self
     }
 
     fn get_partition(&self, name: &str) -> Result<fjall::PartitionHandle, MetaError> {
-        match self.keyspace.open_partition(name, Default::default()) {
-            Ok(partition) => Ok(partition),
-            Err(e) => Err(MetaError::OtherDBError(e.to_string())),
+        let kv_separation = self
+            .kv_separation
+            .filter(|_| is_bucket_tree(name) && !self.keyspace.partition_exists(name));
+        let partition = self
+            .keyspace
+            .open_partition(name, partition_options(kv_separation.as_ref()))
+            .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+        if kv_separation.is_some() {
+            self.tree_open(KV_SEPARATED_TREE)?
+                .insert(name.as_bytes(), Vec::new())?;
         }
+        Ok(partition)
+    }
+
+    // The names of the partitions created with key-value separation
+    fn kv_separated_trees(&self) -> Result<Vec<String>, MetaError> {
+        if !self.keyspace.partition_exists(KV_SEPARATED_TREE) {
+            return Ok(Vec::new());
+        }
+        self.tree_ext_open(KV_SEPARATED_TREE)?
+            .iter_all()
+            .map(|res| res.map(|(name, _)| String::from_utf8_lossy(&name).into_owned()))
+            .filter(|res| {
+                res.as_ref()
+                    .map_or(true, |name| self.keyspace.partition_exists(name))
+            })
+            .collect()
     }
 
     pub fn get_inlined_metadata_size(&self) -> usize {
@@ -68,10 +125,24 @@ impl Store for FjallStoreNotx {
 
     fn tree_delete(&self, name: &str) -> Result<(), MetaError> {
         let partition = self.get_partition(name)?;
-        match self.keyspace.delete_partition(partition) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(MetaError::OtherDBError(e.to_string())),
+        if let Err(e) = self.keyspace.delete_partition(partition) {
+            return Err(MetaError::OtherDBError(e.to_string()));
+        }
+        if is_bucket_tree(name) && self.keyspace.partition_exists(KV_SEPARATED_TREE) {
+            self.tree_open(KV_SEPARATED_TREE)?.remove(name.as_bytes())?;
         }
+        Ok(())
+    }
+
+    fn tree_names(&self) -> Result<Vec<String>, MetaError> {
+        let names = self.keyspace.list_partitions();
+        Ok(names.iter().map(|name| String::from(&**name)).collect())
+    }
+
+    fn persist(&self, durability: Durability) -> Result<(), MetaError> {
+        self.keyspace
+            .persist(persist_mode(durability))
+            .map_err(|e| MetaError::PersistError(e.to_string()))
     }
 
     fn begin_transaction(&self) -> Transaction {
@@ -86,10 +157,34 @@ impl Store for FjallStoreNotx {
     fn disk_space(&self) -> u64 {
         self.keyspace.disk_space()
     }
+
+    fn tree_disk_space(&self, tree_name: &str) -> Result<u64, MetaError> {
+        if !self.keyspace.partition_exists(tree_name) {
+            return Ok(0);
+        }
+        Ok(self.get_partition(tree_name)?.disk_space())
+    }
+
+    fn blob_stats(&self) -> Result<Vec<BlobStats>, MetaError> {
+        self.kv_separated_trees()?
+            .iter()
+            .map(|name| blob_stats_of(name, &self.get_partition(name)?))
+            .collect()
+    }
+
+    fn blob_gc(&self) -> Result<u64, MetaError> {
+        let kv_separation = self.kv_separation.unwrap_or_default();
+        let mut freed = 0;
+        for name in self.kv_separated_trees()? {
+            freed += blob_gc_of(&self.get_partition(&name)?, &kv_separation)?;
+        }
+        Ok(freed)
+    }
 }
 
 pub struct FjallNoTransaction {
     store: Arc<FjallStoreNotx>,
+    durability: fjall::PersistMode,
 
     inserted_keys: Vec<(String, Vec<u8>)>, // tupple of tree name and key
 }
@@ -97,6 +192,7 @@ pub struct FjallNoTransaction {
 impl FjallNoTransaction {
     pub fn new(store: Arc<FjallStoreNotx>) -> Self {
         Self {
+            durability: store.durability,
             store,
             inserted_keys: Vec::new(),
         }
@@ -107,8 +203,17 @@ unsafe impl Send for FjallNoTransaction {}
 unsafe impl Sync for FjallNoTransaction {}
 
 impl TransactionBackend for FjallNoTransaction {
+    // the writes are already applied, the commit is the barrier persisting them
+    // before the caller acknowledges them
     fn commit(&mut self) -> Result<(), MetaError> {
-        Ok(())
+        self.store
+            .keyspace
+            .persist(self.durability)
+            .map_err(|e| MetaError::PersistError(e.to_string()))
+    }
+
+    fn set_durability(&mut self, durability: Durability) {
+        self.durability = persist_mode(durability);
     }
 
     fn rollback(&mut self) {
@@ -118,10 +223,10 @@ impl TransactionBackend for FjallNoTransaction {
         }
     }
 
-    fn get(&mut self, tree_name: &str, key: &[u8]) -> Result<Option<Vec<u8>>, MetaError> {
+    fn get(&mut self, tree_name: &str, key: &[u8]) -> Result<Option<Bytes>, MetaError> {
         let partition = self.store.get_partition(tree_name)?;
         match partition.get(key) {
-            Ok(Some(data)) => Ok(Some(data.to_vec())),
+            Ok(Some(data)) => Ok(Some(slice_to_bytes(data))),
             Ok(None) => Ok(None),
             Err(e) => Err(MetaError::OtherDBError(e.to_string())),
         }
@@ -138,6 +243,15 @@ impl TransactionBackend for FjallNoTransaction {
             Err(e) => Err(MetaError::InsertError(e.to_string())),
         }
     }
+
+    // removals are not undone on rollback, like the rest of this store they are
+    // not transactional
+    fn remove(&mut self, tree_name: &str, key: &[u8]) -> Result<(), MetaError> {
+        let partition = self.store.get_partition(tree_name)?;
+        partition
+            .remove(key)
+            .map_err(|e| MetaError::RemoveError(e.to_string()))
+    }
 }
 
 pub struct FjallTreeNotx {
@@ -180,9 +294,9 @@ impl BaseMetaTree for FjallTreeNotx {
         }
     }
 
-    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, MetaError> {
+    fn get(&self, key: &[u8]) -> Result<Option<Bytes>, MetaError> {
         match self.get(key) {
-            Ok(Some(v)) => Ok(Some(v.to_vec())),
+            Ok(Some(v)) => Ok(Some(slice_to_bytes(v))),
             Ok(None) => Ok(None),
             Err(e) => Err(e),
         }
@@ -200,94 +314,88 @@ impl BaseMetaTree for FjallTreeNotx {
 
 impl MetaTreeExt for FjallTreeNotx {
     fn iter_all(&self) -> KeyValuePairs {
-        let partition = self.partition.clone();
-        let mut last_key: Option<Vec<u8>> = None;
-
-        Box::new(std::iter::from_fn(move || {
-            let range = match &last_key {
-                Some(k) => {
-                    let mut next = k.clone();
-                    next.push(0);
-                    next..
-                }
-                None => Vec::new()..,
-            };
-
-            partition
-                .range::<Vec<u8>, _>(range)
-                .next()
-                .map(|res| match res {
-                    Ok((k, v)) => {
-                        last_key = Some(k.to_vec());
-                        Ok((k.to_vec(), v.to_vec()))
-                    }
-                    Err(e) => {
-                        tracing::error!("Error reading key: {}", e);
-                        Err(MetaError::OtherDBError(e.to_string()))
-                    }
+        let snapshot = self.partition.snapshot();
+
+        chunked_iter(move |from, limit| {
+            snapshot
+                .range(from..)
+                .take(limit)
+                .map(|res| res.map(|(k, v)| (slice_to_bytes(k), slice_to_bytes(v))))
+                .collect()
+        })
+    }
+
+    fn iter_prefix(&self, prefix: &[u8]) -> KeyValuePairs {
+        let snapshot = self.partition.snapshot();
+        let prefix = prefix.to_vec();
+
+        chunked_iter(move |from, limit| {
+            let from = from.max(prefix.clone());
+            snapshot
+                .range(from..)
+                .take_while(|res| match res {
+                    Ok((k, _)) => k.starts_with(&prefix),
+                    Err(_) => true,
                 })
-        }))
+                .take(limit)
+                .map(|res| res.map(|(k, v)| (slice_to_bytes(k), slice_to_bytes(v))))
+                .collect()
+        })
     }
 
-    // rules:
-    // 1. continuation_token and start_after exists: use the one with the highest lexicographical order
-    //    -> call it: ctsa
-    // 2. if prefix exists
-    //    -> ctsa > the prefix && doesn't have prefix: return zero results
-    //    -> ctsa < prefix: ignore it
-    //    -> ctsa has the prefix: use it as start_after
-    //          In kv store like fjall & Sled: we process it in the Rust code
     fn range_filter<'a>(
         &'a self,
         start_after: Option<String>,
         prefix: Option<String>,
         continuation_token: Option<String>,
     ) -> Box<dyn Iterator<Item = (String, Object)> + 'a> {
-        let mut ctsa = match (continuation_token, start_after) {
-            (Some(token), Some(start)) => Some(std::cmp::max(token, start)),
-            (Some(token), None) => Some(token),
-            (None, start) => start,
-        };
-
-        let partition = self.partition.clone();
-
-        let base_iter: Box<
-            dyn Iterator<Item = Result<(fjall::Slice, fjall::Slice), fjall::Error>>,
-        > = match (prefix.as_ref(), ctsa.as_ref()) {
-            (Some(prefix), Some(ctsa)) if (ctsa > prefix && !ctsa.starts_with(prefix)) => {
-                //Return empty iterator if ctsa is after prefix
-                Box::new(std::iter::empty())
-            }
-            (Some(prefix), Some(ctsa_local)) if ctsa_local < prefix => {
-                // If ctsa is before prefix, ignore ctsa
-                ctsa = None;
-                Box::new(partition.prefix(prefix.as_bytes()))
-            }
-            (Some(prefix), _) => Box::new(partition.prefix(prefix.as_bytes())),
-            (None, Some(ctsa)) => {
-                let mut next_key = ctsa.as_bytes().to_vec();
-                next_key.push(0);
-                Box::new(partition.range(next_key..))
-            }
-            (None, None) => Box::new(partition.range::<Vec<u8>, _>(..)),
-        };
-
-        let filtered = base_iter.filter_map(|res| res.ok());
-
-        let skip_filtered = if prefix.is_some() && ctsa.is_some() {
-            let ctsa_bytes = ctsa.unwrap().into_bytes();
-            Box::new(
-                filtered.skip_while(move |(raw_key, _)| raw_key.deref() <= ctsa_bytes.as_slice()),
-            ) as Box<dyn Iterator<Item = _>>
-        } else {
-            Box::new(filtered)
-        };
-
-        Box::new(skip_filtered.map(|(raw_key, raw_value)| {
-            let key = unsafe { String::from_utf8_unchecked(raw_key.to_vec()) };
-            let obj = Object::try_from(&*raw_value).unwrap();
-            (key, obj)
-        }))
+        let partition = &self.partition;
+        range_filter_with(
+            start_after,
+            prefix,
+            continuation_token,
+            |prefix| Box::new(partition.prefix(prefix)),
+            |from| Box::new(partition.range(from..)),
+        )
+    }
+
+    fn range_before<'a>(
+        &'a self,
+        end_before: String,
+        prefix: Option<String>,
+    ) -> Box<dyn Iterator<Item = (String, Object)> + 'a> {
+        let partition = &self.partition;
+        range_before_with(end_before, prefix, |from, to| {
+            Box::new(partition.range(from..to).rev())
+        })
+    }
+
+    fn snapshot(&self) -> Arc<dyn MetaTreeSnapshot> {
+        Arc::new(FjallTreeNotxSnapshot {
+            snapshot: self.partition.snapshot(),
+        })
+    }
+}
+
+/// Snapshot of a [`FjallTreeNotx`], backed by a partition snapshot.
+struct FjallTreeNotxSnapshot {
+    snapshot: fjall::Snapshot,
+}
+
+impl MetaTreeSnapshot for FjallTreeNotxSnapshot {
+    fn range_filter<'a>(
+        &'a self,
+        start_after: Option<String>,
+        prefix: Option<String>,
+        continuation_token: Option<String>,
+    ) -> Box<dyn Iterator<Item = (String, Object)> + 'a> {
+        range_filter_with(
+            start_after,
+            prefix,
+            continuation_token,
+            |prefix| Box::new(self.snapshot.prefix(prefix)),
+            |from| Box::new(self.snapshot.range(from..)),
+        )
     }
 }
 
@@ -327,4 +435,28 @@ mod tests {
         let (store, _dir) = setup_store();
         test_utils::test_range_filter(&store);
     }
+
+    #[test]
+    fn test_range_before() {
+        let (store, _dir) = setup_store();
+        test_utils::test_range_before(&store);
+    }
+
+    #[test]
+    fn test_snapshot_range_filter() {
+        let (store, _dir) = setup_store();
+        test_utils::test_snapshot_range_filter(&store);
+    }
+
+    #[test]
+    fn test_iter_all_chunks() {
+        let (store, _dir) = setup_store();
+        test_utils::test_iter_all_chunks(&store);
+    }
+
+    #[test]
+    fn test_iter_prefix() {
+        let (store, _dir) = setup_store();
+        test_utils::test_iter_prefix(&store);
+    }
 }
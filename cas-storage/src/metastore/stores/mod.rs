@@ -1,3 +1,10 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+use bytes::Bytes;
+
+use crate::metastore::{BlobStats, Durability, KeyValuePairs, KvSeparation, MetaError, Object};
+
 mod fjall;
 mod fjall_notx;
 
@@ -5,4 +12,227 @@ pub use fjall::FjallStore;
 pub use fjall_notx::FjallStoreNotx;
 
 #[cfg(test)]
-mod test_utils;
\ No newline at end of file
+mod test_utils;
+
+type RawKvIter<'a> =
+    Box<dyn Iterator<Item = Result<(::fjall::Slice, ::fjall::Slice), ::fjall::Error>> + 'a>;
+
+// Shared implementation of `range_filter` for the stores and their snapshots.
+// `scan_prefix` iterates over all keys with the given prefix, `scan_from` over all
+// keys starting at (and including) the given key.
+//
+// rules:
+// 1. continuation_token and start_after exists: use the one with the highest lexicographical order
+//    -> call it: ctsa
+// 2. if prefix exists
+//    -> ctsa > the prefix && doesn't have prefix: return zero results
+//    -> ctsa < prefix: ignore it
+//    -> ctsa has the prefix: use it as start_after
+//          In kv store like fjall & Sled: we seek to it and stop at the end of the prefix
+fn range_filter_with<'a>(
+    start_after: Option<String>,
+    prefix: Option<String>,
+    continuation_token: Option<String>,
+    scan_prefix: impl FnOnce(&[u8]) -> RawKvIter<'a>,
+    scan_from: impl FnOnce(Vec<u8>) -> RawKvIter<'a>,
+) -> Box<dyn Iterator<Item = (String, Object)> + 'a> {
+    let ctsa = match (continuation_token, start_after) {
+        (Some(token), Some(start)) => Some(std::cmp::max(token, start)),
+        (Some(token), None) => Some(token),
+        (None, start) => start,
+    };
+
+    let base_iter: RawKvIter<'a> = match (prefix, ctsa) {
+        (Some(prefix), Some(ctsa)) if (ctsa > prefix && !ctsa.starts_with(&prefix)) => {
+            //Return empty iterator if ctsa is after prefix
+            Box::new(std::iter::empty())
+        }
+        // If ctsa is before prefix, ignore ctsa
+        (Some(prefix), Some(ctsa)) if ctsa < prefix => scan_prefix(prefix.as_bytes()),
+        (Some(prefix), Some(ctsa)) => {
+            // seek to the key after ctsa rather than skipping the keys before it,
+            // which are all read on the last pages of a large prefix
+            let mut next_key = ctsa.into_bytes();
+            next_key.push(0);
+            let prefix = prefix.into_bytes();
+            Box::new(scan_from(next_key).take_while(move |res| match res {
+                Ok((k, _)) => k.starts_with(&prefix),
+                Err(_) => true,
+            }))
+        }
+        (Some(prefix), None) => scan_prefix(prefix.as_bytes()),
+        (None, Some(ctsa)) => {
+            let mut next_key = ctsa.into_bytes();
+            next_key.push(0);
+            scan_from(next_key)
+        }
+        // the empty key sorts before every other key
+        (None, None) => scan_from(Vec::new()),
+    };
+
+    Box::new(base_iter.filter_map(|res| res.ok()).map(to_key_object))
+}
+
+// Shared implementation of `range_before` for the stores. `scan_before` iterates
+// in reverse order over the keys from the first key (included) to the second key
+// (excluded).
+fn range_before_with<'a>(
+    end_before: String,
+    prefix: Option<String>,
+    scan_before: impl FnOnce(Vec<u8>, Vec<u8>) -> RawKvIter<'a>,
+) -> Box<dyn Iterator<Item = (String, Object)> + 'a> {
+    let prefix = prefix.unwrap_or_default();
+    if end_before <= prefix {
+        return Box::new(std::iter::empty());
+    }
+
+    // the keys between the prefix and `end_before` which don't have the prefix
+    // all sort after the ones which have it
+    let prefix = prefix.into_bytes();
+    let outside = prefix.clone();
+    let iter = scan_before(prefix.clone(), end_before.into_bytes())
+        .filter_map(|res| res.ok())
+        .skip_while(move |(k, _)| !k.starts_with(&outside))
+        .take_while(move |(k, _)| k.starts_with(&prefix));
+    Box::new(iter.map(to_key_object))
+}
+
+fn to_key_object((raw_key, raw_value): (::fjall::Slice, ::fjall::Slice)) -> (String, Object) {
+    let key = unsafe { String::from_utf8_unchecked(raw_key.to_vec()) };
+    let obj = Object::try_from(&*raw_value).unwrap();
+    (key, obj)
+}
+
+/// Amount of entries `iter_all` reads per range scan.
+const ITER_CHUNK_SIZE: usize = 1024;
+
+type RawKvChunk = Result<Vec<(Bytes, Bytes)>, ::fjall::Error>;
+
+/// Wraps a value of the store without copying it.
+// Creation options of a partition, with key-value separation if it's set.
+fn partition_options(kv_separation: Option<&KvSeparation>) -> ::fjall::PartitionCreateOptions {
+    let options = ::fjall::PartitionCreateOptions::default();
+    match kv_separation {
+        Some(kv) => options.with_kv_separation(
+            ::fjall::KvSeparationOptions::default()
+                .separation_threshold(kv.threshold)
+                .file_target_size(kv.file_target_size),
+        ),
+        None => options,
+    }
+}
+
+// Blob file utilization of a key-value separated partition. The garbage collection
+// functions panic on partitions without key-value separation.
+fn blob_stats_of(
+    tree: &str,
+    partition: &impl ::fjall::GarbageCollection,
+) -> Result<BlobStats, MetaError> {
+    let report = partition
+        .gc_scan()
+        .map_err(|e| MetaError::OtherDBError(e.to_string()))?;
+    Ok(BlobStats {
+        tree: tree.to_string(),
+        blob_files: report.blob_file_count,
+        stale_blob_files: report.stale_blob_file_count,
+        total_bytes: report.total_bytes,
+        stale_bytes: report.stale_bytes,
+    })
+}
+
+// Rewrites the blob files of a key-value separated partition above the targets,
+// and drops the fully stale ones. Returns the number of bytes freed.
+fn blob_gc_of(
+    partition: &impl ::fjall::GarbageCollection,
+    kv_separation: &KvSeparation,
+) -> Result<u64, MetaError> {
+    let db_err = |e: ::fjall::Error| MetaError::OtherDBError(e.to_string());
+    // the targets work on the staleness computed by the scan
+    partition.gc_scan().map_err(db_err)?;
+    let mut freed = partition
+        .gc_with_space_amp_target(kv_separation.gc_space_amp_target)
+        .map_err(db_err)?;
+    freed += partition
+        .gc_with_staleness_threshold(kv_separation.gc_staleness_threshold)
+        .map_err(db_err)?;
+    freed += partition.gc_drop_stale_segments().map_err(db_err)?;
+    Ok(freed)
+}
+
+fn slice_to_bytes(slice: ::fjall::Slice) -> Bytes {
+    Bytes::from_owner(slice)
+}
+
+// fsync persists the file metadata as well, fdatasync only what is needed to
+// read the data back
+fn persist_mode(durability: Durability) -> ::fjall::PersistMode {
+    match durability {
+        Durability::Buffer => ::fjall::PersistMode::Buffer,
+        Durability::Fsync => ::fjall::PersistMode::SyncAll,
+        Durability::Fdatasync => ::fjall::PersistMode::SyncData,
+    }
+}
+
+// Iterator over a whole tree which reads ITER_CHUNK_SIZE entries at a time.
+// fjall iterators can't be sent between threads, so instead of holding on to one,
+// every chunk is a new range scan starting after the last key of the previous
+// chunk. `fetch` is expected to read from a snapshot, so all chunks see the same
+// version of the tree.
+struct ChunkedIter<F> {
+    fetch: F,
+    buffer: VecDeque<(Bytes, Bytes)>,
+    last_key: Option<Bytes>,
+    done: bool,
+}
+
+impl<F> Iterator for ChunkedIter<F>
+where
+    F: FnMut(Vec<u8>, usize) -> RawKvChunk,
+{
+    type Item = Result<(Bytes, Bytes), MetaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(kv) = self.buffer.pop_front() {
+            return Some(Ok(kv));
+        }
+        if self.done {
+            return None;
+        }
+
+        let from = match self.last_key.take() {
+            Some(key) => {
+                let mut from = key.to_vec();
+                from.push(0);
+                from
+            }
+            None => Vec::new(),
+        };
+        match (self.fetch)(from, ITER_CHUNK_SIZE) {
+            Ok(chunk) => {
+                self.done = chunk.len() < ITER_CHUNK_SIZE;
+                self.last_key = chunk.last().map(|(k, _)| k.clone());
+                self.buffer.extend(chunk);
+                self.buffer.pop_front().map(Ok)
+            }
+            Err(e) => {
+                tracing::error!("Error reading key: {}", e);
+                self.done = true;
+                Some(Err(MetaError::OtherDBError(e.to_string())))
+            }
+        }
+    }
+}
+
+// `fetch(from, limit)` must return at most `limit` entries, starting at (and
+// including) `from`, in key order.
+fn chunked_iter<F>(fetch: F) -> KeyValuePairs
+where
+    F: FnMut(Vec<u8>, usize) -> RawKvChunk + Send + 'static,
+{
+    Box::new(ChunkedIter {
+        fetch,
+        buffer: VecDeque::new(),
+        last_key: None,
+        done: false,
+    })
+}
@@ -1,5 +1,8 @@
 use std::sync::Arc;
 
+use bytes::Bytes;
+
+use super::ITER_CHUNK_SIZE;
 use crate::metastore::{BaseMetaTree, BlockID, MetaError, MetaTreeExt, Object, ObjectData};
 
 pub trait TestStore {
@@ -31,7 +34,7 @@ pub fn test_get_bucket_keys(store: &impl TestStore) {
     let retrieved_keys: Vec<String> = bucket
         .iter_all()
         .into_iter()
-        .map(|kv| String::from_utf8(kv.unwrap().0).unwrap())
+        .map(|kv| String::from_utf8(kv.unwrap().0.to_vec()).unwrap())
         .collect();
 
     // Verify all keys present
@@ -163,3 +166,134 @@ pub fn test_range_filter(store: &impl TestStore) {
         assert_eq!(results[0], "b/2");
     }
 }
+
+pub fn test_range_before(store: &impl TestStore) {
+    let bucket_name = "range-before-bucket";
+    let bucket = store.tree_open(bucket_name).unwrap();
+
+    let obj = Object::new(
+        4,
+        BlockID::from([1; 16]),
+        ObjectData::SinglePart {
+            blocks: vec![BlockID::from([1; 16])],
+        },
+    );
+    for key in ["a", "b/1", "b/2", "b/3", "b0", "c"] {
+        bucket.insert(key.as_bytes(), obj.to_vec()).unwrap();
+    }
+
+    let bucket = store.get_bucket_ext(bucket_name).unwrap();
+    let before = |end_before: &str, prefix: Option<&str>| -> Vec<String> {
+        bucket
+            .range_before(end_before.to_string(), prefix.map(str::to_string))
+            .map(|(k, _)| k)
+            .collect()
+    };
+
+    // the keys before the given one, the last one first
+    assert_eq!(before("b/3", None), vec!["b/2", "b/1", "a"]);
+    assert_eq!(before("b/2\0", None), vec!["b/2", "b/1", "a"]);
+    assert_eq!(before("a", None), Vec::<String>::new());
+
+    // only the keys with the prefix
+    assert_eq!(before("b/3", Some("b/")), vec!["b/2", "b/1"]);
+    assert_eq!(before("z", Some("b/")), vec!["b/3", "b/2", "b/1"]);
+    assert_eq!(before("b/", Some("b/")), Vec::<String>::new());
+    assert_eq!(before("a", Some("b/")), Vec::<String>::new());
+}
+
+pub fn test_snapshot_range_filter(store: &impl TestStore) {
+    let bucket_name = "snapshot-bucket";
+    let bucket = store.tree_open(bucket_name).unwrap();
+
+    let obj = Object::new(
+        4,
+        BlockID::from([1; 16]),
+        ObjectData::SinglePart {
+            blocks: vec![BlockID::from([1; 16])],
+        },
+    );
+    for key in ["a", "b", "c"] {
+        bucket.insert(key.as_bytes(), obj.to_vec()).unwrap();
+    }
+
+    let snapshot = store.get_bucket_ext(bucket_name).unwrap().snapshot();
+
+    // Changes after the snapshot was taken are not visible in it
+    bucket.insert(b"ab", obj.to_vec()).unwrap();
+    bucket.remove(b"c").unwrap();
+
+    let keys: Vec<_> = snapshot
+        .range_filter(None, None, None)
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(keys, vec!["a", "b", "c"]);
+
+    // Pagination over the snapshot follows the regular range_filter rules
+    let keys: Vec<_> = snapshot
+        .range_filter(None, None, Some("a".to_string()))
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(keys, vec!["b", "c"]);
+
+    // The live tree does see the changes
+    let keys: Vec<_> = store
+        .get_bucket_ext(bucket_name)
+        .unwrap()
+        .range_filter(None, None, None)
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(keys, vec!["a", "ab", "b"]);
+}
+
+pub fn test_iter_all_chunks(store: &impl TestStore) {
+    let bucket_name = "chunked-bucket";
+    let bucket = store.tree_open(bucket_name).unwrap();
+
+    // spans multiple chunks, with a partial last chunk
+    let amount = ITER_CHUNK_SIZE * 2 + 5;
+    for i in 0..amount {
+        bucket.insert(format!("key-{:06}", i).as_bytes(), vec![1]).unwrap();
+    }
+
+    let iter = store.get_bucket_ext(bucket_name).unwrap().iter_all();
+
+    // the iterator reads from a snapshot, later writes are not visible
+    bucket.insert(b"key-999999", vec![1]).unwrap();
+    bucket.remove(b"key-000000").unwrap();
+
+    let keys: Vec<Bytes> = iter.map(|kv| kv.unwrap().0).collect();
+    assert_eq!(keys.len(), amount);
+    assert_eq!(keys[0], b"key-000000".to_vec());
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+    // exactly a multiple of the chunk size
+    let bucket_name = "chunked-bucket-exact";
+    let bucket = store.tree_open(bucket_name).unwrap();
+    for i in 0..ITER_CHUNK_SIZE {
+        bucket.insert(format!("key-{:06}", i).as_bytes(), vec![1]).unwrap();
+    }
+    let ext = store.get_bucket_ext(bucket_name).unwrap();
+    assert_eq!(ext.iter_all().count(), ITER_CHUNK_SIZE);
+}
+
+pub fn test_iter_prefix(store: &impl TestStore) {
+    let bucket_name = "prefix-bucket";
+    let bucket = store.tree_open(bucket_name).unwrap();
+
+    for i in 0..ITER_CHUNK_SIZE + 5 {
+        bucket.insert(format!("b/{:06}", i).as_bytes(), vec![1]).unwrap();
+    }
+    bucket.insert(b"a", vec![1]).unwrap();
+    bucket.insert(b"b", vec![1]).unwrap();
+    bucket.insert(b"c/1", vec![1]).unwrap();
+
+    let ext = store.get_bucket_ext(bucket_name).unwrap();
+    let keys: Vec<Bytes> = ext.iter_prefix(b"b/").map(|kv| kv.unwrap().0).collect();
+    assert_eq!(keys.len(), ITER_CHUNK_SIZE + 5);
+    assert_eq!(keys[0], b"b/000000".to_vec());
+    assert!(keys.iter().all(|k| k.starts_with(b"b/")));
+
+    assert_eq!(ext.iter_prefix(b"c").count(), 1);
+    assert_eq!(ext.iter_prefix(b"d").count(), 0);
+}
@@ -0,0 +1,231 @@
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::FsError;
+
+/// Maximum amount of tags on an object
+pub const MAX_OBJECT_TAGS: usize = 10;
+/// Maximum amount of tags on a bucket
+pub const MAX_BUCKET_TAGS: usize = 50;
+/// Maximum length of a tag key, in characters
+pub const MAX_TAG_KEY_LENGTH: usize = 128;
+/// Maximum length of a tag value, in characters
+pub const MAX_TAG_VALUE_LENGTH: usize = 256;
+
+/// `ObjectTags` is the tag set of an object, as set with `PutObjectTagging`.
+///
+/// Tags follow the S3 limits: at most 10 tags per object, keys of at most 128 and
+/// values of at most 256 characters without control characters, and every key
+/// only once. Tags are kept in the order they were set.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ObjectTags {
+    tags: Vec<(String, String)>,
+}
+
+impl ObjectTags {
+    /// Creates a tag set, checking the S3 limits.
+    ///
+    /// # Arguments
+    /// * `tags` - The `(key, value)` pairs of the tag set
+    ///
+    /// # Returns
+    /// The tag set, or a description of the violated limit
+    pub fn new(tags: Vec<(String, String)>) -> Result<Self, String> {
+        if tags.len() > MAX_OBJECT_TAGS {
+            return Err(format!(
+                "Object tags cannot be greater than {MAX_OBJECT_TAGS}"
+            ));
+        }
+        Self::checked(tags)
+    }
+
+    /// Creates the tag set of a bucket, as set with `PutBucketTagging`. The
+    /// limits of object tags apply, but a bucket can have up to 50 tags.
+    pub fn for_bucket(tags: Vec<(String, String)>) -> Result<Self, String> {
+        if tags.len() > MAX_BUCKET_TAGS {
+            return Err(format!(
+                "Bucket tags cannot be greater than {MAX_BUCKET_TAGS}"
+            ));
+        }
+        Self::checked(tags)
+    }
+
+    fn checked(tags: Vec<(String, String)>) -> Result<Self, String> {
+        for (i, (key, value)) in tags.iter().enumerate() {
+            // control characters would break the keys of the tag index
+            if key.is_empty()
+                || key.chars().count() > MAX_TAG_KEY_LENGTH
+                || key.chars().any(char::is_control)
+            {
+                return Err(format!("The TagKey you have provided is invalid: {key}"));
+            }
+            if value.chars().count() > MAX_TAG_VALUE_LENGTH
+                || value.chars().any(char::is_control)
+            {
+                return Err(format!(
+                    "The TagValue you have provided is invalid: {value}"
+                ));
+            }
+            if tags[..i].iter().any(|(other, _)| other == key) {
+                return Err(format!(
+                    "Cannot provide multiple Tags with the same key: {key}"
+                ));
+            }
+        }
+        Ok(Self { tags })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Returns the `(key, value)` pairs of the tag set.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns the value of the tag with the given key, if it is set.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// Serializes the tag set to a byte vector.
+    ///
+    /// Every key and value is stored as a little endian u32 length followed by
+    /// its UTF-8 bytes.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (key, value) in &self.tags {
+            for s in [key, value] {
+                data.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                data.extend_from_slice(s.as_bytes());
+            }
+        }
+        data
+    }
+}
+
+impl TryFrom<&[u8]> for ObjectTags {
+    type Error = FsError;
+
+    fn try_from(mut value: &[u8]) -> Result<Self, Self::Error> {
+        fn take_string(data: &mut &[u8]) -> Result<String, FsError> {
+            if data.len() < 4 {
+                return Err(FsError::MalformedObject);
+            }
+            let len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+            if data.len() < 4 + len {
+                return Err(FsError::MalformedObject);
+            }
+            let s = String::from_utf8(data[4..4 + len].to_vec())
+                .map_err(|_| FsError::MalformedObject)?;
+            *data = &data[4 + len..];
+            Ok(s)
+        }
+
+        let mut tags = Vec::new();
+        while !value.is_empty() {
+            let key = take_string(&mut value)?;
+            let val = take_string(&mut value)?;
+            tags.push((key, val));
+        }
+        Ok(Self { tags })
+    }
+}
+
+/// A `key=value` filter matching objects which have the tag `key` set to `value`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagFilter {
+    pub key: String,
+    pub value: String,
+}
+
+impl TagFilter {
+    pub fn new(key: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+
+    /// Returns `true` if `tags` contains the tag of the filter.
+    pub fn matches(&self, tags: &ObjectTags) -> bool {
+        tags.get(&self.key) == Some(self.value.as_str())
+    }
+}
+
+impl fmt::Display for TagFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+impl FromStr for TagFilter {
+    type Err = String;
+
+    /// Parses `key=value`, the value may be empty but the key may not.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(TagFilter::new(key, value)),
+            _ => Err(format!("Invalid tag filter, expected key=value: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let tags =
+            ObjectTags::new(vec![tag("tmp", "true"), tag("team", ""), tag("ü", "€")]).unwrap();
+        let decoded = ObjectTags::try_from(tags.to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, tags);
+        assert_eq!(decoded.get("tmp"), Some("true"));
+        assert_eq!(decoded.get("other"), None);
+
+        assert!(ObjectTags::try_from(&[5u8, 0, 0, 0, b'a'][..]).is_err());
+        assert!(
+            ObjectTags::try_from(ObjectTags::default().to_vec().as_slice())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        assert!(ObjectTags::new(vec![tag("a", "1"), tag("a", "2")]).is_err());
+        assert!(ObjectTags::new(vec![tag("", "1")]).is_err());
+        assert!(ObjectTags::new(vec![tag("a\0b", "1")]).is_err());
+        assert!(ObjectTags::new(vec![tag(&"k".repeat(MAX_TAG_KEY_LENGTH + 1), "1")]).is_err());
+        assert!(ObjectTags::new(vec![tag("k", &"v".repeat(MAX_TAG_VALUE_LENGTH + 1))]).is_err());
+        let many = (0..=MAX_OBJECT_TAGS)
+            .map(|i| tag(&i.to_string(), ""))
+            .collect();
+        assert!(ObjectTags::new(many).is_err());
+    }
+
+    #[test]
+    fn test_tag_filter() {
+        let filter: TagFilter = "tmp=true".parse().unwrap();
+        assert_eq!(filter, TagFilter::new("tmp", "true"));
+        assert_eq!(
+            "a=b=c".parse::<TagFilter>().unwrap(),
+            TagFilter::new("a", "b=c")
+        );
+        assert!("=true".parse::<TagFilter>().is_err());
+        assert!("tmp".parse::<TagFilter>().is_err());
+
+        let tags = ObjectTags::new(vec![tag("tmp", "true")]).unwrap();
+        assert!(filter.matches(&tags));
+        assert!(!TagFilter::new("tmp", "false").matches(&tags));
+    }
+}
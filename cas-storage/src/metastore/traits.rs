@@ -1,7 +1,9 @@
 use std::str::FromStr;
+
+use bytes::Bytes;
 use std::{fmt::Debug, sync::Arc};
 
-use super::{object::Object, MetaError, Transaction};
+use super::{object::Object, BlobStats, MetaError, Transaction};
 
 /// `BaseMetaTree` defines the core operations for a metadata tree storage.
 ///
@@ -38,12 +40,15 @@ pub trait BaseMetaTree: Send + Sync {
 
     /// Retrieves a value for the given key.
     ///
+    /// The returned buffer shares the memory of the store's value, so no copy is
+    /// made.
+    ///
     /// # Arguments
     /// * `key` - The key to look up as a byte slice
     ///
     /// # Returns
-    /// * `Result<Option<Vec<u8>>, MetaError>` - The value if found, None if the key doesn't exist, or an error
-    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, MetaError>;
+    /// * `Result<Option<Bytes>, MetaError>` - The value if found, None if the key doesn't exist, or an error
+    fn get(&self, key: &[u8]) -> Result<Option<Bytes>, MetaError>;
 
     /// Returns the number of key-value pairs in the tree.
     ///
@@ -55,8 +60,9 @@ pub trait BaseMetaTree: Send + Sync {
     fn len(&self) -> Result<usize, MetaError>;
 }
 
-/// Type alias for a boxed iterator over key-value pairs.
-pub type KeyValuePairs = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), MetaError>> + Send>;
+/// Type alias for a boxed iterator over key-value pairs. Keys and values share the
+/// memory of the store, so no copies are made.
+pub type KeyValuePairs = Box<dyn Iterator<Item = Result<(Bytes, Bytes), MetaError>> + Send>;
 
 /// `MetaTreeExt` extends the `BaseMetaTree` with additional operations.
 ///
@@ -65,10 +71,26 @@ pub type KeyValuePairs = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), Meta
 pub trait MetaTreeExt: BaseMetaTree {
     /// Iterates over all key-value pairs in the tree.
     ///
+    /// The iterator reads from a snapshot taken when it is created, so writes made
+    /// while iterating are not visible. Entries are fetched in chunks rather than
+    /// with one scan per key.
+    ///
     /// # Returns
     /// * `KeyValuePairs` - A boxed iterator over all key-value pairs
     fn iter_all(&self) -> KeyValuePairs;
 
+    /// Iterates over the key-value pairs whose key starts with `prefix`, in key order.
+    ///
+    /// Like [`iter_all`](Self::iter_all) the iterator reads from a snapshot, but
+    /// only the keys below the prefix are scanned.
+    ///
+    /// # Arguments
+    /// * `prefix` - Prefix of the keys to return
+    ///
+    /// # Returns
+    /// * `KeyValuePairs` - A boxed iterator over the matching key-value pairs
+    fn iter_prefix(&self, prefix: &[u8]) -> KeyValuePairs;
+
     /// Filters and iterates over a range of keys with optional filtering parameters.
     ///
     /// # Arguments
@@ -84,6 +106,54 @@ pub trait MetaTreeExt: BaseMetaTree {
         prefix: Option<String>,
         continuation_token: Option<String>,
     ) -> Box<dyn Iterator<Item = (String, Object)> + 'a>;
+
+    /// Iterates backwards over the keys before `end_before`, the last key first.
+    ///
+    /// Used to page back through a listing, `end_before` is the first key of the
+    /// current page.
+    ///
+    /// # Arguments
+    /// * `end_before` - The keys returned sort before this key
+    /// * `prefix` - Optional prefix to filter keys
+    ///
+    /// # Returns
+    /// * A boxed iterator yielding key-value pairs as (String, Object) tuples, in
+    ///   reverse key order
+    fn range_before<'a>(
+        &'a self,
+        end_before: String,
+        prefix: Option<String>,
+    ) -> Box<dyn Iterator<Item = (String, Object)> + 'a>;
+
+    /// Takes a point-in-time snapshot of the tree.
+    ///
+    /// Writes made after the snapshot was taken are not visible through it. Note
+    /// that a snapshot keeps old versions of the data alive in the store, so it
+    /// should not be held on to longer than needed.
+    ///
+    /// # Returns
+    /// * `Arc<dyn MetaTreeSnapshot>` - A read-only view of the tree
+    fn snapshot(&self) -> Arc<dyn MetaTreeSnapshot>;
+}
+
+/// `MetaTreeSnapshot` is a read-only, point-in-time view of a metadata tree.
+pub trait MetaTreeSnapshot: Send + Sync {
+    /// Filters and iterates over a range of keys of the snapshot, with the same
+    /// semantics as [`MetaTreeExt::range_filter`].
+    ///
+    /// # Arguments
+    /// * `start_after` - Optional string to start iteration after
+    /// * `prefix` - Optional prefix to filter keys
+    /// * `continuation_token` - Optional token for pagination
+    ///
+    /// # Returns
+    /// * A boxed iterator yielding key-value pairs as (String, Object) tuples
+    fn range_filter<'a>(
+        &'a self,
+        start_after: Option<String>,
+        prefix: Option<String>,
+        continuation_token: Option<String>,
+    ) -> Box<dyn Iterator<Item = (String, Object)> + 'a>;
 }
 
 /// `Store` represents a storage backend for metadata trees.
@@ -127,6 +197,22 @@ pub trait Store: Send + Sync + Debug + 'static {
     /// * `Result<(), MetaError>` - Success or an error if the deletion fails
     fn tree_delete(&self, name: &str) -> Result<(), MetaError>;
 
+    /// Returns the names of all trees of the store.
+    ///
+    /// # Returns
+    /// * `Result<Vec<String>, MetaError>` - The tree names or an error
+    fn tree_names(&self) -> Result<Vec<String>, MetaError>;
+
+    /// Writes all changes made so far, in transactions or outside of them, to disk
+    /// with `durability`.
+    ///
+    /// # Arguments
+    /// * `durability` - How the changes are persisted
+    ///
+    /// # Returns
+    /// * `Result<(), MetaError>` - Success or an error if the sync fails
+    fn persist(&self, durability: Durability) -> Result<(), MetaError>;
+
     /// Begins a new transaction.
     ///
     /// # Returns
@@ -147,13 +233,63 @@ pub trait Store: Send + Sync + Debug + 'static {
     /// # Returns
     /// * `u64` - The disk space usage in bytes
     fn disk_space(&self) -> u64;
+
+    /// Returns the disk space used by the specified tree.
+    ///
+    /// # Arguments
+    /// * `tree_name` - The name of the tree to measure
+    ///
+    /// # Returns
+    /// * `Result<u64, MetaError>` - The disk space usage in bytes, 0 if the tree doesn't exist
+    fn tree_disk_space(&self, tree_name: &str) -> Result<u64, MetaError>;
+
+    /// Returns the blob file utilization of the key-value separated trees.
+    ///
+    /// # Returns
+    /// * `Result<Vec<BlobStats>, MetaError>` - One entry per separated tree, empty if the store doesn't separate values
+    fn blob_stats(&self) -> Result<Vec<BlobStats>, MetaError> {
+        Ok(Vec::new())
+    }
+
+    /// Rewrites and drops the stale blob files of the key-value separated trees.
+    ///
+    /// # Returns
+    /// * `Result<u64, MetaError>` - The number of bytes freed or an error
+    fn blob_gc(&self) -> Result<u64, MetaError> {
+        Ok(0)
+    }
 }
 
 /// `Durability` defines the durability guarantees for storage operations.
 ///
 /// This enum represents different levels of durability that can be used
-/// when configuring storage operations.
-#[derive(Debug, Clone, Copy)]
+/// when configuring storage operations. Every metadata commit persists the
+/// journal of its store with the durability of the store, or the override of
+/// the bucket it writes to.
+///
+/// # Crash consistency
+///
+/// All trees of a store share one journal, written in commit order. A commit
+/// with `Fsync` or `Fdatasync` also persists every buffered commit before it,
+/// so a crash only loses a suffix of `Buffer` commits, never a synced one.
+///
+/// In multi-user mode the block refcounts are in the shared store, and the
+/// objects in the store of their user. After a crash a `Buffer` bucket can
+/// keep an object whose refcount increments were lost, if a later synced
+/// commit of the same user persisted the object. Its blocks may then be
+/// removed while it still uses them, so only data which can be recreated
+/// belongs in `Buffer` buckets.
+///
+/// ```
+/// use cas_storage::Durability;
+///
+/// let durability: Durability = "buffer".parse().unwrap();
+/// assert_eq!(durability, Durability::Buffer);
+/// assert!(!durability.is_synced());
+/// assert!("fdatasync".parse::<Durability>().unwrap().is_synced());
+/// assert!("sometimes".parse::<Durability>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Durability {
     /// Data is buffered in memory and will be written to disk later.
     /// This provides the highest performance but lowest durability.
@@ -168,6 +304,14 @@ pub enum Durability {
     Fdatasync,
 }
 
+impl Durability {
+    /// Returns `true` if commits are on disk when they return, and survive a
+    /// crash of the machine.
+    pub fn is_synced(&self) -> bool {
+        !matches!(self, Durability::Buffer)
+    }
+}
+
 impl FromStr for Durability {
     type Err = String;
 
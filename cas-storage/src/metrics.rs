@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Shared metrics collector interface
 ///
@@ -12,6 +13,35 @@ pub trait MetricsCollector: Send + Sync {
     fn blocks_dropped(&self, amount: u64);
     fn bytes_sent(&self, amount: usize);
     fn bytes_received(&self, amount: usize);
+
+    /// A block entered the write pipeline of `CasFS::store_object`
+    fn block_write_started(&self) {}
+    /// A block left the write pipeline, regardless of the outcome
+    fn block_write_finished(&self) {}
+    /// Time a block spent between being read from the client and the start of its disk write.
+    /// This includes waiting for a free slot in the pipeline and for the metadata transaction.
+    fn block_queue_wait(&self, _wait: Duration) {}
+    /// Time spent writing a block to disk
+    fn block_write_latency(&self, _duration: Duration) {}
+    /// Current limit of the adaptive block write limiter
+    fn write_concurrency_limit(&self, _limit: usize) {}
+    /// Object metadata was served from the metadata cache
+    fn meta_cache_hit(&self) {}
+    /// Object metadata was not in the metadata cache and had to be read from the store
+    fn meta_cache_miss(&self) {}
+    /// Amount of metadata operations waiting for a blocking thread
+    fn meta_pool_queued(&self, _queued: usize) {}
+    /// Amount of metadata operations running on blocking threads
+    fn meta_pool_active(&self, _active: usize) {}
+    /// Time a metadata operation waited for a blocking thread
+    fn meta_pool_wait(&self, _wait: Duration) {}
+    /// Amount and size of the blocks waiting in the delete queue for the removal of their files
+    fn delete_queue(&self, _blocks: u64, _bytes: u64) {}
+    /// A block reference count would have wrapped around: `overflow` when adding
+    /// references, `underflow` when releasing a reference of a block without any
+    fn refcount_anomaly(&self, _kind: &str) {}
+    /// Time spent by a group sync, and the size of the block files it synced
+    fn group_sync(&self, _duration: Duration, _bytes: u64) {}
 }
 
 /// No-op metrics collector (default)
@@ -64,6 +94,58 @@ impl SharedMetrics {
     pub fn bytes_received(&self, amount: usize) {
         self.0.bytes_received(amount);
     }
+
+    pub fn block_write_started(&self) {
+        self.0.block_write_started();
+    }
+
+    pub fn block_write_finished(&self) {
+        self.0.block_write_finished();
+    }
+
+    pub fn block_queue_wait(&self, wait: Duration) {
+        self.0.block_queue_wait(wait);
+    }
+
+    pub fn block_write_latency(&self, duration: Duration) {
+        self.0.block_write_latency(duration);
+    }
+
+    pub fn write_concurrency_limit(&self, limit: usize) {
+        self.0.write_concurrency_limit(limit);
+    }
+
+    pub fn meta_cache_hit(&self) {
+        self.0.meta_cache_hit();
+    }
+
+    pub fn meta_cache_miss(&self) {
+        self.0.meta_cache_miss();
+    }
+
+    pub fn meta_pool_queued(&self, queued: usize) {
+        self.0.meta_pool_queued(queued);
+    }
+
+    pub fn meta_pool_active(&self, active: usize) {
+        self.0.meta_pool_active(active);
+    }
+
+    pub fn meta_pool_wait(&self, wait: Duration) {
+        self.0.meta_pool_wait(wait);
+    }
+
+    pub fn delete_queue(&self, blocks: u64, bytes: u64) {
+        self.0.delete_queue(blocks, bytes);
+    }
+
+    pub fn refcount_anomaly(&self, kind: &str) {
+        self.0.refcount_anomaly(kind);
+    }
+
+    pub fn group_sync(&self, duration: Duration, bytes: u64) {
+        self.0.group_sync(duration, bytes);
+    }
 }
 
 impl Default for SharedMetrics {
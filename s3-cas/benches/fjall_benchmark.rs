@@ -294,7 +294,7 @@ fn bench_transaction(c: &mut Criterion) {
             b.iter(|| {
                 let mut tx = store.begin_transaction();
                 let (block_id, _) = create_test_block(rand::thread_rng().gen::<u8>(), 1024);
-                black_box(tx.write_block(block_id, 1024, false)).unwrap();
+                black_box(tx.write_block(block_id, 1024, false, None)).unwrap();
                 black_box(tx.commit()).unwrap();
             });
         });
@@ -311,7 +311,7 @@ fn bench_transaction(c: &mut Criterion) {
             b.iter(|| {
                 let mut tx = store.begin_transaction();
                 let (block_id, _) = create_test_block(rand::thread_rng().gen::<u8>(), 1024);
-                black_box(tx.write_block(block_id, 1024, false)).unwrap();
+                black_box(tx.write_block(block_id, 1024, false, None)).unwrap();
                 black_box(tx.commit()).unwrap();
             });
         });
@@ -355,7 +355,7 @@ fn bench_mixed_workload(c: &mut Criterion) {
                 // Use a transaction
                 let mut tx = store.begin_transaction();
                 let (block_id, _) = create_test_block(rand::thread_rng().gen::<u8>(), 1024);
-                black_box(tx.write_block(block_id, 1024, false)).unwrap();
+                black_box(tx.write_block(block_id, 1024, false, None)).unwrap();
                 black_box(tx.commit()).unwrap();
             });
         });
@@ -391,7 +391,7 @@ fn bench_mixed_workload(c: &mut Criterion) {
                 // Use a transaction
                 let mut tx = store.begin_transaction();
                 let (block_id, _) = create_test_block(rand::thread_rng().gen::<u8>(), 1024);
-                black_box(tx.write_block(block_id, 1024, false)).unwrap();
+                black_box(tx.write_block(block_id, 1024, false, None)).unwrap();
                 black_box(tx.commit()).unwrap();
             });
         });
@@ -0,0 +1,336 @@
+//! Access log for S3 requests.
+//!
+//! Produces one line per request, either in common log format or as JSON,
+//! independently of the tracing log level. Lines are written to stdout or to a
+//! file, which can be rotated by size. Successful requests can optionally be
+//! sampled; failed requests are always logged.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Output format of the access log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// NCSA common log format
+    Common,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "common" | "clf" => Ok(AccessLogFormat::Common),
+            "json" => Ok(AccessLogFormat::Json),
+            _ => Err(format!(
+                "Invalid access log format: {}, valid values are: common, json",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessLogConfig {
+    /// File to write to, `None` writes to stdout
+    pub path: Option<PathBuf>,
+    pub format: AccessLogFormat,
+    /// Rotate the file once it grows beyond this size in bytes, 0 disables rotation
+    pub max_size: u64,
+    /// Amount of rotated files to keep
+    pub max_files: usize,
+    /// Fraction (0.0 - 1.0) of successful requests to log
+    pub sample_rate: f64,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            format: AccessLogFormat::Common,
+            max_size: 0,
+            max_files: 5,
+            sample_rate: 1.0,
+        }
+    }
+}
+
+/// A single access log record
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogEntry {
+    #[serde(serialize_with = "serialize_time")]
+    pub time: DateTime<Utc>,
+    /// Access key of the requester, if the request was signed
+    pub user: Option<String>,
+    pub method: String,
+    pub uri: String,
+    /// S3 operation name, e.g. `put_object`
+    pub operation: &'static str,
+    pub status: u16,
+    /// Size of the response body, if known
+    pub bytes: Option<u64>,
+    pub duration_ms: f64,
+}
+
+impl AccessLogEntry {
+    pub fn new(
+        operation: &'static str,
+        method: &str,
+        uri: &str,
+        user: Option<&str>,
+        status: u16,
+        bytes: Option<u64>,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            time: Utc::now(),
+            user: user.map(|u| u.to_string()),
+            method: method.to_string(),
+            uri: uri.to_string(),
+            operation,
+            status,
+            bytes,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+        }
+    }
+
+    /// Format as common log format. The remote host is not known at the S3 layer
+    /// and is always logged as `-`.
+    pub fn to_common(&self) -> String {
+        format!(
+            "- - {} [{}] \"{} {}\" {} {}",
+            self.user.as_deref().unwrap_or("-"),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            self.uri,
+            self.status,
+            self.bytes
+                .map(|b| b.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+fn serialize_time<S: serde::Serializer>(time: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&time.to_rfc3339())
+}
+
+/// Writes access log entries according to an [`AccessLogConfig`]
+pub struct AccessLogger {
+    config: AccessLogConfig,
+    file: Option<RotatingFile>,
+}
+
+impl AccessLogger {
+    pub fn new(config: AccessLogConfig) -> io::Result<Self> {
+        let file = match &config.path {
+            Some(path) => Some(RotatingFile::open(
+                path.clone(),
+                config.max_size,
+                config.max_files,
+            )?),
+            None => None,
+        };
+        Ok(Self { config, file })
+    }
+
+    pub fn config(&self) -> &AccessLogConfig {
+        &self.config
+    }
+
+    /// Log an entry. Errors writing the log are reported through tracing and
+    /// otherwise ignored, they must never fail the request itself.
+    pub fn log(&self, entry: &AccessLogEntry) {
+        if !self.sampled(entry) {
+            return;
+        }
+
+        let mut line = match self.config.format {
+            AccessLogFormat::Common => entry.to_common(),
+            AccessLogFormat::Json => entry.to_json(),
+        };
+        line.push('\n');
+
+        let written = match &self.file {
+            Some(file) => file.write_line(&line),
+            None => io::stdout().lock().write_all(line.as_bytes()),
+        };
+        if let Err(e) = written {
+            tracing::warn!(error = %e, "failed to write access log");
+        }
+    }
+
+    fn sampled(&self, entry: &AccessLogEntry) -> bool {
+        if entry.status >= 400 || self.config.sample_rate >= 1.0 {
+            return true;
+        }
+        rand::random::<f64>() < self.config.sample_rate
+    }
+}
+
+struct LogFile {
+    file: File,
+    size: u64,
+}
+
+/// A log file which is rotated once it grows beyond a size, written a line at
+/// a time. Lines are written to stdout when the file couldn't be opened again
+/// after a rotation.
+pub(crate) struct RotatingFile {
+    path: PathBuf,
+    /// 0 disables rotation
+    max_size: u64,
+    max_files: usize,
+    file: Mutex<Option<LogFile>>,
+}
+
+impl RotatingFile {
+    pub(crate) fn open(path: PathBuf, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = open_log_file(&path)?;
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            file: Mutex::new(Some(file)),
+        })
+    }
+
+    pub(crate) fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut guard = self.file.lock().expect("log file lock is not poisoned");
+        if guard.is_none() {
+            return io::stdout().lock().write_all(line.as_bytes());
+        }
+
+        if self.max_size > 0
+            && guard.as_ref().map(|f| f.size).unwrap_or(0) + line.len() as u64 > self.max_size
+        {
+            // Close the current file before renaming it
+            *guard = None;
+            rotate(&self.path, self.max_files)?;
+            *guard = Some(open_log_file(&self.path)?);
+        }
+
+        let log_file = guard.as_mut().expect("log file is open");
+        log_file.file.write_all(line.as_bytes())?;
+        log_file.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+fn open_log_file(path: &Path) -> io::Result<LogFile> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok(LogFile { file, size })
+}
+
+pub(crate) fn rotated_path(path: &Path, idx: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", idx));
+    PathBuf::from(name)
+}
+
+/// Shift `path.N-1` to `path.N`, ..., `path` to `path.1`, dropping the oldest file.
+fn rotate(path: &Path, max_files: usize) -> io::Result<()> {
+    if max_files == 0 {
+        return fs::remove_file(path);
+    }
+    for idx in (1..max_files).rev() {
+        let from = rotated_path(path, idx);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, idx + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(status: u16) -> AccessLogEntry {
+        AccessLogEntry::new(
+            "get_object",
+            "GET",
+            "/bucket/key",
+            Some("AKIA"),
+            status,
+            Some(42),
+            Duration::from_millis(3),
+        )
+    }
+
+    #[test]
+    fn test_common_format() {
+        let line = entry(200).to_common();
+        assert!(line.starts_with("- - AKIA ["));
+        assert!(line.ends_with("] \"GET /bucket/key\" 200 42"));
+    }
+
+    #[test]
+    fn test_json_format() {
+        let value: serde_json::Value = serde_json::from_str(&entry(404).to_json()).unwrap();
+        assert_eq!(value["operation"], "get_object");
+        assert_eq!(value["status"], 404);
+        assert_eq!(value["bytes"], 42);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let logger = AccessLogger::new(AccessLogConfig {
+            path: Some(path.clone()),
+            max_size: 100,
+            max_files: 2,
+            ..Default::default()
+        })
+        .unwrap();
+
+        for _ in 0..10 {
+            logger.log(&entry(200));
+        }
+
+        assert!(path.exists());
+        assert!(rotated_path(&path, 1).exists());
+        assert!(rotated_path(&path, 2).exists());
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn test_errors_bypass_sampling() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let logger = AccessLogger::new(AccessLogConfig {
+            path: Some(path.clone()),
+            sample_rate: 0.0,
+            ..Default::default()
+        })
+        .unwrap();
+
+        logger.log(&entry(200));
+        logger.log(&entry(500));
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert!(contents.contains("\" 500 "));
+    }
+}
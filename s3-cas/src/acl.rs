@@ -0,0 +1,134 @@
+//! Canned ACL support: conversion from and to the S3 types, and the access check
+//! which lets anonymous users read public objects.
+
+use std::sync::Arc;
+
+use s3s::access::{S3Access, S3AccessContext};
+use s3s::dto::{Grant, Grantee, Owner, Permission, Type};
+use s3s::path::S3Path;
+use s3s::{s3_error, S3Result};
+
+use cas_storage::{CannedAcl, CasFS};
+
+/// URI of the group of all users, including anonymous ones
+const ALL_USERS_URI: &str = "http://acs.amazonaws.com/groups/global/AllUsers";
+
+/// Id of the owner reported in ACL and listing responses of a single-user server,
+/// all buckets are owned by the user of the CasFS. In multi-user mode the owner is
+/// the id of the user.
+pub const DEFAULT_OWNER_ID: &str = "s3-cas";
+
+/// Parse a canned ACL from a request, rejecting the ones we can't enforce.
+pub fn parse_canned_acl(acl: Option<&str>) -> S3Result<Option<CannedAcl>> {
+    match acl {
+        None => Ok(None),
+        Some(acl) => acl
+            .parse()
+            .map(Some)
+            .map_err(|e: String| s3_error!(NotImplemented, "{}", e)),
+    }
+}
+
+/// The owner with id `owner_id` reported in ACL and listing responses.
+pub fn acl_owner(owner_id: &str) -> Owner {
+    Owner {
+        display_name: Some(owner_id.to_string()),
+        id: Some(owner_id.to_string()),
+    }
+}
+
+/// The grants equivalent to a canned ACL, of a bucket or object owned by `owner_id`.
+pub fn acl_grants(acl: CannedAcl, owner_id: &str) -> Vec<Grant> {
+    let mut grants = vec![Grant {
+        grantee: Some(Grantee {
+            display_name: Some(owner_id.to_string()),
+            email_address: None,
+            id: Some(owner_id.to_string()),
+            type_: Type::from_static(Type::CANONICAL_USER),
+            uri: None,
+        }),
+        permission: Some(Permission::from_static(Permission::FULL_CONTROL)),
+    }];
+    if acl.is_public_read() {
+        grants.push(Grant {
+            grantee: Some(Grantee {
+                display_name: None,
+                email_address: None,
+                id: None,
+                type_: Type::from_static(Type::GROUP),
+                uri: Some(ALL_USERS_URI.to_string()),
+            }),
+            permission: Some(Permission::from_static(Permission::READ)),
+        });
+    }
+    grants
+}
+
+/// Access check for a single-user server with authentication enabled.
+///
+/// Authenticated requests are always allowed. Anonymous requests are only allowed
+/// to read (GET and HEAD) objects with a `public-read` ACL, either set on the
+/// object itself or inherited from the bucket.
+pub struct AclAccess {
+    casfs: Arc<CasFS>,
+}
+
+impl AclAccess {
+    pub fn new(casfs: Arc<CasFS>) -> Self {
+        Self { casfs }
+    }
+
+    fn is_public_read(&self, bucket: &str, key: &str) -> bool {
+        match self.casfs.object_acl(bucket, key) {
+            Ok(acl) => acl.is_public_read(),
+            Err(e) => {
+                tracing::error!(bucket = %bucket, key = %key, error = %e, "Could not get object ACL");
+                false
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl S3Access for AclAccess {
+    async fn check(&self, cx: &mut S3AccessContext<'_>) -> S3Result<()> {
+        if cx.credentials().is_some() {
+            return Ok(());
+        }
+
+        let is_read = matches!(cx.s3_op().name(), "GetObject" | "HeadObject");
+        if let S3Path::Object { bucket, key } = cx.s3_path() {
+            if is_read && self.is_public_read(bucket, key) {
+                return Ok(());
+            }
+        }
+
+        Err(s3_error!(AccessDenied, "Signature is required"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_canned_acl() {
+        assert_eq!(parse_canned_acl(None).unwrap(), None);
+        assert_eq!(
+            parse_canned_acl(Some("public-read")).unwrap(),
+            Some(CannedAcl::PublicRead)
+        );
+        assert!(parse_canned_acl(Some("authenticated-read")).is_err());
+    }
+
+    #[test]
+    fn test_acl_grants() {
+        assert_eq!(acl_grants(CannedAcl::Private, DEFAULT_OWNER_ID).len(), 1);
+
+        let grants = acl_grants(CannedAcl::PublicRead, "alice");
+        assert_eq!(grants.len(), 2);
+        assert_eq!(grants[0].grantee.as_ref().unwrap().id.as_deref(), Some("alice"));
+        let public = grants[1].grantee.as_ref().unwrap();
+        assert_eq!(public.uri.as_deref(), Some(ALL_USERS_URI));
+    }
+}
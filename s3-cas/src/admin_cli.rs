@@ -0,0 +1,406 @@
+//! `s3-cas admin`: remote management through the JSON admin API of a multi-user server.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use clap::{Parser, Subcommand};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::{header, Method, Request, Response};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+#[derive(Parser, Debug)]
+pub struct AdminConfig {
+    #[arg(
+        long,
+        default_value = "http://localhost:8080",
+        help = "URL of the HTTP UI of the server"
+    )]
+    pub endpoint: String,
+
+    #[arg(
+        long,
+        env = "S3CAS_ADMIN_TOKEN",
+        hide_env_values = true,
+        help = "Admin API token, as configured with --admin-token on the server"
+    )]
+    pub token: String,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "PEM CA certificates to verify an https endpoint with, instead of the system roots"
+    )]
+    pub ca_cert: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "client_key",
+        help = "PEM client certificate for an admin listener requiring mutual TLS"
+    )]
+    pub client_cert: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "client_cert",
+        help = "PEM private key of --client-cert"
+    )]
+    pub client_key: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: AdminCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AdminCommand {
+    /// Manage users
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Manage storage quotas
+    Quota {
+        #[command(subcommand)]
+        command: QuotaCommand,
+    },
+    /// Inspect the buckets of a user
+    Bucket {
+        #[command(subcommand)]
+        command: BucketCommand,
+    },
+    /// Rotate and revoke S3 access keys
+    Key {
+        #[command(subcommand)]
+        command: KeyCommand,
+    },
+    /// Show the storage usage and quota of a user
+    Usage {
+        /// User ID
+        user_id: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UserCommand {
+    /// Create a user, generated credentials are printed once
+    Create {
+        /// User ID
+        user_id: String,
+        /// HTTP UI login (defaults to the user ID)
+        #[arg(long)]
+        ui_login: Option<String>,
+        /// HTTP UI password (generated if not set)
+        #[arg(long)]
+        ui_password: Option<String>,
+        /// Give the user admin rights
+        #[arg(long)]
+        admin: bool,
+    },
+    /// List all users
+    List,
+    /// Delete a user
+    Delete {
+        /// User ID
+        user_id: String,
+        /// What to do with the buckets of the user: refuse to delete a user owning
+        /// buckets, delete them, or archive and then delete them. Buckets are
+        /// deleted by a job, the user once it completes
+        #[arg(long, default_value = "deny", value_parser = ["deny", "cascade", "archive"])]
+        policy: String,
+    },
+    /// Allow a user to sign S3 requests with Signature Version 2, if the server
+    /// runs with --allow-sig-v2
+    EnableSigV2 {
+        /// User ID
+        user_id: String,
+    },
+    /// Forbid a user to sign S3 requests with Signature Version 2
+    DisableSigV2 {
+        /// User ID
+        user_id: String,
+    },
+    /// Start a job exporting the buckets, usage history and audit entries of a
+    /// user to a tar archive, downloaded with `takeout-download` once completed
+    Takeout {
+        /// User ID
+        user_id: String,
+        /// Delete the buckets and the user once the archive is complete
+        #[arg(long)]
+        delete_after: bool,
+    },
+    /// Download the archive of a completed takeout job
+    TakeoutDownload {
+        /// User ID
+        user_id: String,
+        /// ID of the takeout job
+        job_id: u64,
+        /// File to write the archive to
+        #[arg(long, short, value_name = "FILE")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum QuotaCommand {
+    /// Limit the amount of bytes a user can store
+    Set {
+        /// User ID
+        user_id: String,
+        /// Maximum amount of stored bytes
+        max_bytes: u64,
+    },
+    /// Remove the quota of a user
+    Clear {
+        /// User ID
+        user_id: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BucketCommand {
+    /// List the buckets of a user
+    List {
+        /// User ID
+        user_id: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeyCommand {
+    /// List the active access keys of a user and when they expire
+    List {
+        /// User ID
+        user_id: String,
+    },
+    /// Generate a new key pair, the new secret key is printed once
+    Rotate {
+        /// User ID
+        user_id: String,
+        /// Seconds the old key stays valid, 0 revokes it immediately
+        #[arg(long, default_value_t = crate::auth::DEFAULT_KEY_GRACE_SECS)]
+        grace_secs: u64,
+    },
+    /// Revoke an old access key before its grace period ends
+    Revoke {
+        /// User ID
+        user_id: String,
+        /// The access key to revoke
+        access_key: String,
+    },
+}
+
+struct AdminClient {
+    endpoint: String,
+    token: String,
+    client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+}
+
+impl AdminClient {
+    fn new(config: &AdminConfig) -> Result<Self> {
+        let tls = if config.endpoint.starts_with("https://") {
+            let identity = config
+                .client_cert
+                .as_deref()
+                .zip(config.client_key.as_deref());
+            crate::tls::client_config(config.ca_cert.as_deref(), identity)?
+        } else {
+            // plain HTTP needs no root certificates, which may not be installed
+            ClientConfig::builder()
+                .with_root_certificates(RootCertStore::empty())
+                .with_no_client_auth()
+        };
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls)
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Self {
+            endpoint: config.endpoint.trim_end_matches('/').to_string(),
+            token: config.token.clone(),
+            client: Client::builder(TokioExecutor::new()).build(connector),
+        })
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> Result<Response<Incoming>> {
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))?;
+
+        self.client
+            .request(req)
+            .await
+            .with_context(|| format!("Request to {} failed", uri))
+    }
+
+    async fn request(&self, method: Method, path: &str, body: Option<Value>) -> Result<Value> {
+        let uri = format!("{}/api/admin/{}", self.endpoint, path);
+        let resp = self.send(method, &uri, body).await?;
+        let status = resp.status();
+        let body = resp.into_body().collect().await?.to_bytes();
+        let value: Value = if body.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&body)
+                .with_context(|| format!("Invalid response from {} ({})", uri, status))?
+        };
+
+        if !status.is_success() {
+            let message = value["error"].as_str().unwrap_or("unknown error");
+            bail!("{} ({})", message, status);
+        }
+        Ok(value)
+    }
+
+    /// Write the file served at `path` to `output`, returning its size
+    async fn download(&self, path: &str, output: &Path) -> Result<u64> {
+        let uri = format!("{}/api/admin/{}", self.endpoint, path);
+        let resp = self.send(Method::GET, &uri, None).await?;
+        let status = resp.status();
+        let mut body = resp.into_body();
+        if !status.is_success() {
+            let body = body.collect().await?.to_bytes();
+            let value: Value = serde_json::from_slice(&body).unwrap_or_default();
+            let message = value["error"].as_str().unwrap_or("unknown error");
+            bail!("{} ({})", message, status);
+        }
+
+        let mut file = tokio::fs::File::create(output)
+            .await
+            .with_context(|| format!("Can't create {}", output.display()))?;
+        let mut written = 0;
+        while let Some(frame) = body.frame().await {
+            if let Ok(data) = frame?.into_data() {
+                file.write_all(&data).await?;
+                written += data.len() as u64;
+            }
+        }
+        file.flush().await?;
+        Ok(written)
+    }
+}
+
+fn user_path(user_id: &str) -> String {
+    format!("users/{}", urlencoding::encode(user_id))
+}
+
+/// Run an admin command and print the JSON response
+#[tokio::main]
+pub async fn admin(config: AdminConfig) -> Result<()> {
+    let client = AdminClient::new(&config)?;
+
+    let result = match config.command {
+        AdminCommand::User { command } => match command {
+            UserCommand::Create {
+                user_id,
+                ui_login,
+                ui_password,
+                admin,
+            } => {
+                let body = json!({
+                    "user_id": user_id,
+                    "ui_login": ui_login,
+                    "ui_password": ui_password,
+                    "is_admin": admin,
+                });
+                client.request(Method::POST, "users", Some(body)).await?
+            }
+            UserCommand::List => client.request(Method::GET, "users", None).await?,
+            UserCommand::Delete { user_id, policy } => {
+                let path = format!("{}?policy={}", user_path(&user_id), policy);
+                client.request(Method::DELETE, &path, None).await?
+            }
+            UserCommand::EnableSigV2 { user_id } => {
+                let path = format!("{}/sigv2", user_path(&user_id));
+                let body = json!({ "enabled": true });
+                client.request(Method::PUT, &path, Some(body)).await?
+            }
+            UserCommand::DisableSigV2 { user_id } => {
+                let path = format!("{}/sigv2", user_path(&user_id));
+                let body = json!({ "enabled": false });
+                client.request(Method::PUT, &path, Some(body)).await?
+            }
+            UserCommand::Takeout {
+                user_id,
+                delete_after,
+            } => {
+                let path = format!("{}/takeout", user_path(&user_id));
+                let body = json!({ "delete_after": delete_after });
+                client.request(Method::POST, &path, Some(body)).await?
+            }
+            UserCommand::TakeoutDownload {
+                user_id,
+                job_id,
+                output,
+            } => {
+                let path = format!("{}/takeout/{}", user_path(&user_id), job_id);
+                let bytes = client.download(&path, &output).await?;
+                json!({ "output": output, "bytes": bytes })
+            }
+        },
+        AdminCommand::Quota { command } => {
+            let (user_id, max_bytes) = match command {
+                QuotaCommand::Set { user_id, max_bytes } => (user_id, Some(max_bytes)),
+                QuotaCommand::Clear { user_id } => (user_id, None),
+            };
+            let path = format!("{}/quota", user_path(&user_id));
+            let body = json!({ "max_bytes": max_bytes });
+            client.request(Method::PUT, &path, Some(body)).await?
+        }
+        AdminCommand::Bucket {
+            command: BucketCommand::List { user_id },
+        } => {
+            let path = format!("{}/buckets", user_path(&user_id));
+            client.request(Method::GET, &path, None).await?
+        }
+        AdminCommand::Key { command } => match command {
+            KeyCommand::List { user_id } => {
+                let path = format!("{}/keys", user_path(&user_id));
+                client.request(Method::GET, &path, None).await?
+            }
+            KeyCommand::Rotate {
+                user_id,
+                grace_secs,
+            } => {
+                let path = format!("{}/keys", user_path(&user_id));
+                let body = json!({ "grace_secs": grace_secs });
+                client.request(Method::POST, &path, Some(body)).await?
+            }
+            KeyCommand::Revoke {
+                user_id,
+                access_key,
+            } => {
+                let path = format!(
+                    "{}/keys/{}",
+                    user_path(&user_id),
+                    urlencoding::encode(&access_key)
+                );
+                client.request(Method::DELETE, &path, None).await?
+            }
+        },
+        AdminCommand::Usage { user_id } => {
+            let path = format!("{}/usage", user_path(&user_id));
+            client.request(Method::GET, &path, None).await?
+        }
+    };
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
@@ -0,0 +1,592 @@
+//! Alerts about operational events, posted to webhooks.
+//!
+//! Alerts are routed to the webhooks subscribed to their kind, either as a
+//! generic JSON document or as a Slack (compatible) incoming webhook message.
+//! Each webhook receives at most one alert of the same kind and severity within
+//! its rate limit interval; alerts dropped meanwhile are counted in the next
+//! one. Delivery happens in a background task, raising an alert never blocks.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{header, Method, Request, Uri};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Alerts waiting for delivery, further alerts are dropped
+const QUEUE_SIZE: usize = 256;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Kind of operational event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// A data or metadata directory is filled beyond the disk watermark
+    DiskWatermark,
+    /// The `check` command found corrupt blocks
+    Corruption,
+    /// Many failed logins to the HTTP UI in a short time
+    LoginStorm,
+    /// The metadata grew beyond the alert ratio of the data
+    MetadataRatio,
+}
+
+impl AlertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertKind::DiskWatermark => "disk_watermark",
+            AlertKind::Corruption => "corruption",
+            AlertKind::LoginStorm => "login_storm",
+            AlertKind::MetadataRatio => "metadata_ratio",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Critical,
+    /// The condition of an earlier alert is over
+    Resolved,
+}
+
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub severity: Severity,
+    pub summary: String,
+    pub details: Vec<(&'static str, String)>,
+}
+
+impl Alert {
+    pub fn new(kind: AlertKind, severity: Severity, summary: impl Into<String>) -> Self {
+        Self {
+            kind,
+            severity,
+            summary: summary.into(),
+            details: Vec::new(),
+        }
+    }
+
+    pub fn with_detail(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.details.push((name, value.to_string()));
+        self
+    }
+}
+
+/// Payload format of a webhook
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    #[default]
+    Json,
+    /// Slack incoming webhook message, also understood by Mattermost and Rocket.Chat
+    Slack,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// http or https URL the alerts are posted to
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// Kinds of alerts sent to the webhook, all kinds if empty
+    #[serde(default)]
+    pub events: Vec<AlertKind>,
+    /// Minimum time between two alerts of the same kind and severity
+    #[serde(default = "default_min_interval_secs")]
+    pub min_interval_secs: u64,
+}
+
+impl WebhookConfig {
+    fn accepts(&self, kind: AlertKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+/// Alerting configuration, loaded from a TOML file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertConfig {
+    /// Name of the instance in the alerts
+    #[serde(default = "default_instance")]
+    pub instance: String,
+    #[serde(default, rename = "webhook")]
+    pub webhooks: Vec<WebhookConfig>,
+    /// Fraction (0.0 - 1.0) of used space of the filesystem of a data or metadata
+    /// directory to alert at, `None` disables the disk check
+    pub disk_watermark: Option<f64>,
+    #[serde(default = "default_disk_check_interval_secs")]
+    pub disk_check_interval_secs: u64,
+    /// Failed logins within `login_storm_window_secs` to alert at, 0 disables it
+    #[serde(default = "default_login_storm_failures")]
+    pub login_storm_failures: usize,
+    #[serde(default = "default_login_storm_window_secs")]
+    pub login_storm_window_secs: u64,
+}
+
+fn default_instance() -> String {
+    "s3-cas".to_string()
+}
+
+fn default_min_interval_secs() -> u64 {
+    300
+}
+
+fn default_disk_check_interval_secs() -> u64 {
+    60
+}
+
+fn default_login_storm_failures() -> usize {
+    20
+}
+
+fn default_login_storm_window_secs() -> u64 {
+    60
+}
+
+impl AlertConfig {
+    /// Load and validate a configuration from a TOML file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read alert config {}", path.display()))?;
+        let config: Self = toml::from_str(&data)
+            .with_context(|| format!("Invalid alert config {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid alert config {}", path.display()))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for webhook in &self.webhooks {
+            let uri: Uri = webhook
+                .url
+                .parse()
+                .with_context(|| format!("invalid webhook url {}", webhook.url))?;
+            if !matches!(uri.scheme_str(), Some("http" | "https")) {
+                anyhow::bail!("webhook url {} is not an http or https url", webhook.url);
+            }
+        }
+        if let Some(watermark) = self.disk_watermark {
+            if !(watermark > 0.0 && watermark <= 1.0) {
+                anyhow::bail!("disk_watermark must be between 0.0 and 1.0, got {watermark}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Limits the alerts of a webhook to one per kind and severity per interval
+struct RateLimit {
+    min_interval: Duration,
+    /// Time of the last alert sent, and the alerts dropped since
+    sent: HashMap<(AlertKind, Severity), (Instant, u64)>,
+}
+
+impl RateLimit {
+    fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            sent: HashMap::new(),
+        }
+    }
+
+    /// Returns the amount of alerts dropped since the last one if an alert can
+    /// be sent at `now`, `None` if it has to be dropped.
+    fn admit(&mut self, alert: &Alert, now: Instant) -> Option<u64> {
+        match self.sent.get_mut(&(alert.kind, alert.severity)) {
+            Some((last, dropped)) if now.duration_since(*last) < self.min_interval => {
+                *dropped += 1;
+                None
+            }
+            Some((last, dropped)) => {
+                *last = now;
+                Some(std::mem::take(dropped))
+            }
+            None => {
+                self.sent.insert((alert.kind, alert.severity), (now, 0));
+                Some(0)
+            }
+        }
+    }
+}
+
+/// Counts failed logins in a sliding window
+struct LoginStorm {
+    threshold: usize,
+    window: Duration,
+    failures: VecDeque<Instant>,
+}
+
+impl LoginStorm {
+    /// Record a failed login at `now`, returns the failures in the window once
+    /// they reach the threshold. The count starts over after that.
+    fn failed(&mut self, now: Instant) -> Option<usize> {
+        while let Some(first) = self.failures.front() {
+            if now.duration_since(*first) < self.window {
+                break;
+            }
+            self.failures.pop_front();
+        }
+        self.failures.push_back(now);
+        if self.failures.len() < self.threshold {
+            return None;
+        }
+        let failures = self.failures.len();
+        self.failures.clear();
+        Some(failures)
+    }
+}
+
+struct Inner {
+    config: AlertConfig,
+    sender: Mutex<Option<mpsc::Sender<Alert>>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+    login_storm: Option<Mutex<LoginStorm>>,
+}
+
+/// Raises alerts. The default alerter is disabled and drops all alerts.
+#[derive(Clone, Default)]
+pub struct Alerter {
+    inner: Option<Arc<Inner>>,
+}
+
+impl Alerter {
+    /// Start delivering alerts to the webhooks of `config`. Must be called from
+    /// within a tokio runtime.
+    pub fn start(config: AlertConfig) -> anyhow::Result<Self> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .context("Failed to load the root certificates for webhooks")?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let client = Client::builder(TokioExecutor::new()).build(connector);
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let task = tokio::spawn(deliver(
+            receiver,
+            client,
+            config.instance.clone(),
+            config.webhooks.clone(),
+        ));
+        let login_storm = (config.login_storm_failures > 0).then(|| {
+            Mutex::new(LoginStorm {
+                threshold: config.login_storm_failures,
+                window: Duration::from_secs(config.login_storm_window_secs),
+                failures: VecDeque::new(),
+            })
+        });
+        Ok(Self {
+            inner: Some(Arc::new(Inner {
+                config,
+                sender: Mutex::new(Some(sender)),
+                task: Mutex::new(Some(task)),
+                login_storm,
+            })),
+        })
+    }
+
+    /// The configuration of an enabled alerter
+    pub fn config(&self) -> Option<&AlertConfig> {
+        self.inner.as_ref().map(|inner| &inner.config)
+    }
+
+    /// Queue `alert` for delivery to the webhooks. Alerts aren't logged, callers
+    /// log their events whether alerting is enabled or not.
+    pub fn send(&self, alert: Alert) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let sender = inner.sender.lock().unwrap();
+        let Some(sender) = sender.as_ref() else {
+            return;
+        };
+        if let Err(e) = sender.try_send(alert) {
+            tracing::warn!(error = %e, "Dropped alert, the delivery queue is full");
+        }
+    }
+
+    /// Record a failed login, alerts when the failures within the login storm
+    /// window reach the threshold.
+    pub fn login_failed(&self) {
+        let Some(login_storm) = self
+            .inner
+            .as_ref()
+            .and_then(|inner| inner.login_storm.as_ref())
+        else {
+            return;
+        };
+        let failures = login_storm.lock().unwrap().failed(Instant::now());
+        if let Some(failures) = failures {
+            let window = self
+                .config()
+                .map_or(0, |config| config.login_storm_window_secs);
+            self.send(
+                Alert::new(
+                    AlertKind::LoginStorm,
+                    Severity::Warning,
+                    format!("{failures} failed logins within {window} seconds"),
+                )
+                .with_detail("failures", failures)
+                .with_detail("window_secs", window),
+            );
+        }
+    }
+
+    /// Stop accepting alerts and wait until the queued ones are delivered
+    pub async fn close(&self) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        inner.sender.lock().unwrap().take();
+        let task = inner.task.lock().unwrap().take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
+    }
+}
+
+type WebhookClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+async fn deliver(
+    mut receiver: mpsc::Receiver<Alert>,
+    client: WebhookClient,
+    instance: String,
+    webhooks: Vec<WebhookConfig>,
+) {
+    let mut limits: Vec<RateLimit> = webhooks
+        .iter()
+        .map(|webhook| RateLimit::new(Duration::from_secs(webhook.min_interval_secs)))
+        .collect();
+    while let Some(alert) = receiver.recv().await {
+        for (webhook, limit) in webhooks.iter().zip(&mut limits) {
+            if !webhook.accepts(alert.kind) {
+                continue;
+            }
+            let Some(dropped) = limit.admit(&alert, Instant::now()) else {
+                tracing::debug!(kind = alert.kind.as_str(), url = %webhook.url, "Alert rate limited");
+                continue;
+            };
+            let body = payload(webhook.format, &instance, &alert, dropped);
+            if let Err(e) = post(&client, &webhook.url, body).await {
+                tracing::warn!(kind = alert.kind.as_str(), url = %webhook.url, error = %e, "Failed to deliver alert");
+            }
+        }
+    }
+}
+
+async fn post(client: &WebhookClient, url: &str, body: Value) -> anyhow::Result<()> {
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))?;
+    let resp = tokio::time::timeout(DELIVERY_TIMEOUT, client.request(req))
+        .await
+        .context("timed out")??;
+    if !resp.status().is_success() {
+        anyhow::bail!("webhook responded with {}", resp.status());
+    }
+    Ok(())
+}
+
+/// The body posted to a webhook for `alert`, `dropped` alerts of the same kind
+/// were rate limited since the previous one.
+fn payload(format: WebhookFormat, instance: &str, alert: &Alert, dropped: u64) -> Value {
+    match format {
+        WebhookFormat::Json => {
+            let details: Map<String, Value> = alert
+                .details
+                .iter()
+                .map(|(name, value)| (name.to_string(), Value::String(value.clone())))
+                .collect();
+            json!({
+                "instance": instance,
+                "kind": alert.kind,
+                "severity": alert.severity,
+                "summary": alert.summary,
+                "details": details,
+                "dropped": dropped,
+                "time": chrono::Utc::now().to_rfc3339(),
+            })
+        }
+        WebhookFormat::Slack => {
+            let icon = match alert.severity {
+                Severity::Warning => ":warning:",
+                Severity::Critical => ":rotating_light:",
+                Severity::Resolved => ":white_check_mark:",
+            };
+            let mut text = format!("{icon} *[{instance}] {}*", alert.summary);
+            for (name, value) in &alert.details {
+                text.push_str(&format!("\n• {name}: `{value}`"));
+            }
+            if dropped > 0 {
+                text.push_str(&format!("\n_{dropped} similar alert(s) suppressed_"));
+            }
+            json!({ "text": text })
+        }
+    }
+}
+
+/// Fraction of the space of the filesystem holding `path` which is in use, as
+/// reported by `df`: space reserved for root counts as unavailable.
+#[cfg(unix)]
+pub fn disk_usage(path: &Path) -> io::Result<f64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: statvfs only writes to the zeroed struct, path is NUL terminated
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let used = (stat.f_blocks - stat.f_bfree) as f64;
+    let available = stat.f_bavail as f64;
+    if used + available == 0.0 {
+        return Ok(0.0);
+    }
+    Ok(used / (used + available))
+}
+
+#[cfg(not(unix))]
+pub fn disk_usage(_path: &Path) -> io::Result<f64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "disk usage is only available on unix",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config: AlertConfig = toml::from_str(
+            r#"
+            instance = "eu-1"
+            disk_watermark = 0.9
+
+            [[webhook]]
+            url = "https://hooks.slack.com/services/T0/B0/x"
+            format = "slack"
+            events = ["corruption", "disk_watermark"]
+
+            [[webhook]]
+            url = "http://alerts.internal/s3"
+            min_interval_secs = 60
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.instance, "eu-1");
+        assert_eq!(config.webhooks[0].format, WebhookFormat::Slack);
+        assert!(config.webhooks[0].accepts(AlertKind::Corruption));
+        assert!(!config.webhooks[0].accepts(AlertKind::LoginStorm));
+        assert_eq!(config.webhooks[1].format, WebhookFormat::Json);
+        assert!(config.webhooks[1].accepts(AlertKind::LoginStorm));
+        assert_eq!(config.login_storm_failures, 20);
+
+        let config: AlertConfig =
+            toml::from_str("[[webhook]]\nurl = \"ftp://example.com\"").unwrap();
+        assert!(config.validate().is_err());
+        let config: AlertConfig = toml::from_str("disk_watermark = 90").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut limit = RateLimit::new(Duration::from_secs(60));
+        let warning = Alert::new(AlertKind::DiskWatermark, Severity::Warning, "disk full");
+        let resolved = Alert::new(AlertKind::DiskWatermark, Severity::Resolved, "disk ok");
+        let corruption = Alert::new(AlertKind::Corruption, Severity::Critical, "corrupt");
+        let start = Instant::now();
+
+        assert_eq!(limit.admit(&warning, start), Some(0));
+        assert_eq!(limit.admit(&warning, start + Duration::from_secs(10)), None);
+        assert_eq!(limit.admit(&warning, start + Duration::from_secs(20)), None);
+        // other kinds and severities have their own limit
+        assert_eq!(
+            limit.admit(&resolved, start + Duration::from_secs(20)),
+            Some(0)
+        );
+        assert_eq!(
+            limit.admit(&corruption, start + Duration::from_secs(20)),
+            Some(0)
+        );
+        assert_eq!(
+            limit.admit(&warning, start + Duration::from_secs(60)),
+            Some(2)
+        );
+        assert_eq!(limit.admit(&warning, start + Duration::from_secs(61)), None);
+    }
+
+    #[test]
+    fn test_login_storm() {
+        let mut storm = LoginStorm {
+            threshold: 3,
+            window: Duration::from_secs(10),
+            failures: VecDeque::new(),
+        };
+        let start = Instant::now();
+        assert_eq!(storm.failed(start), None);
+        assert_eq!(storm.failed(start + Duration::from_secs(5)), None);
+        // the first failure left the window
+        assert_eq!(storm.failed(start + Duration::from_secs(12)), None);
+        assert_eq!(storm.failed(start + Duration::from_secs(13)), Some(3));
+        assert_eq!(storm.failed(start + Duration::from_secs(14)), None);
+    }
+
+    #[test]
+    fn test_payload() {
+        let alert = Alert::new(
+            AlertKind::Corruption,
+            Severity::Critical,
+            "2 corrupt blocks",
+        )
+        .with_detail("object", "bucket/key");
+
+        let body = payload(WebhookFormat::Json, "eu-1", &alert, 3);
+        assert_eq!(body["instance"], "eu-1");
+        assert_eq!(body["kind"], "corruption");
+        assert_eq!(body["severity"], "critical");
+        assert_eq!(body["summary"], "2 corrupt blocks");
+        assert_eq!(body["details"]["object"], "bucket/key");
+        assert_eq!(body["dropped"], 3);
+
+        let body = payload(WebhookFormat::Slack, "eu-1", &alert, 0);
+        assert_eq!(
+            body["text"],
+            ":rotating_light: *[eu-1] 2 corrupt blocks*\n• object: `bucket/key`"
+        );
+        let body = payload(WebhookFormat::Slack, "eu-1", &alert, 3);
+        assert!(body["text"]
+            .as_str()
+            .unwrap()
+            .ends_with("_3 similar alert(s) suppressed_"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_disk_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let usage = disk_usage(dir.path()).unwrap();
+        assert!((0.0..=1.0).contains(&usage));
+        assert!(disk_usage(&dir.path().join("missing")).is_err());
+    }
+}
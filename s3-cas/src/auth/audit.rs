@@ -0,0 +1,341 @@
+//! Tamper-evident log of the actions of admins: users created and deleted,
+//! passwords reset, admin rights, quotas, keys and signups.
+//!
+//! Entries are appended to the `_ADMIN_AUDIT` tree of the user store under their
+//! sequence number, and are never changed or removed. Every entry holds the hash
+//! of the entry before it, and its own hash covers that link, so changing,
+//! removing or inserting an entry breaks the chain from there on. [`AuditLog::verify`]
+//! walks the chain, `s3-cas inspect audit-log` runs it.
+//!
+//! The chain can't tell if its newest entries were dropped, or if entries were
+//! appended by someone else than the server. Keep the head hash reported by a
+//! verification outside of the server, later verifications must still pass it.
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cas_storage::{MetaError, Store};
+
+const AUDIT_TREE: &str = "_ADMIN_AUDIT";
+
+/// Size of the hashes chaining the entries
+pub const AUDIT_HASH_SIZE: usize = 32;
+
+/// The link of the first entry
+const GENESIS_HASH: [u8; AUDIT_HASH_SIZE] = [0; AUDIT_HASH_SIZE];
+
+/// Actor of the actions done through the admin API, which knows no admin users
+pub const ADMIN_API_ACTOR: &str = "admin-api";
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// An admin action
+#[derive(Debug, Clone, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub struct AuditEntry {
+    /// Position in the log, starting at 1
+    pub seq: u64,
+    /// Seconds since the UNIX epoch
+    pub time: u64,
+    /// The admin who acted, [`ADMIN_API_ACTOR`] for the admin API
+    pub actor: String,
+    /// What was done, e.g. `user_create`, named like the admin operation metrics
+    pub action: String,
+    /// The user acted on
+    pub target: String,
+    /// Parameters of the action, never secrets
+    pub details: String,
+    /// Hash of the entry before, zeros for the first one
+    pub prev_hash: [u8; AUDIT_HASH_SIZE],
+    pub hash: [u8; AUDIT_HASH_SIZE],
+}
+
+impl AuditEntry {
+    /// The hash of the entry, over all its fields but `hash`. Strings are length
+    /// prefixed, so no two entries hash the same bytes.
+    pub fn compute_hash(&self) -> [u8; AUDIT_HASH_SIZE] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.seq.to_be_bytes());
+        hasher.update(self.time.to_be_bytes());
+        for field in [&self.actor, &self.action, &self.target, &self.details] {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field.as_bytes());
+        }
+        hasher.update(self.prev_hash);
+        hasher.finalize().into()
+    }
+
+    fn to_vec(&self) -> Result<Vec<u8>, MetaError> {
+        bincode::encode_to_vec(self, bincode::config::standard())
+            .map_err(|e| MetaError::OtherDBError(format!("Failed to serialize AuditEntry: {}", e)))
+    }
+
+    fn from_slice(data: &[u8]) -> Result<Self, MetaError> {
+        bincode::decode_from_slice(data, bincode::config::standard())
+            .map(|(entry, _)| entry)
+            .map_err(|e| {
+                MetaError::OtherDBError(format!("Failed to deserialize AuditEntry: {}", e))
+            })
+    }
+}
+
+fn seq_from_key(key: &[u8]) -> Result<u64, MetaError> {
+    key.try_into()
+        .map(u64::from_be_bytes)
+        .map_err(|_| MetaError::OtherDBError(format!("Invalid audit key {}", hex::encode(key))))
+}
+
+/// What is wrong with an entry of the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditProblem {
+    /// The entry can't be read
+    Unreadable,
+    /// The entries from `from` up to the entry were removed
+    Missing { from: u64 },
+    /// The entry doesn't link to the hash of the entry before it
+    BrokenLink,
+    /// The fields of the entry don't match its hash or its position
+    Modified,
+}
+
+impl fmt::Display for AuditProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditProblem::Unreadable => write!(f, "unreadable"),
+            AuditProblem::Missing { from } => write!(f, "entries from {} missing before", from),
+            AuditProblem::BrokenLink => write!(f, "doesn't link to the entry before"),
+            AuditProblem::Modified => write!(f, "modified"),
+        }
+    }
+}
+
+/// Result of walking the chain of the log
+#[derive(Debug, Default)]
+pub struct AuditVerification {
+    pub entries: u64,
+    /// Sequence number and hash of the last entry
+    pub head: Option<(u64, [u8; AUDIT_HASH_SIZE])>,
+    /// The entries which are tampered with, by sequence number
+    pub problems: Vec<(u64, AuditProblem)>,
+}
+
+impl AuditVerification {
+    pub fn is_intact(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// The audit log of the admin actions, shared by the HTTP UI and the admin API
+pub struct AuditLog {
+    store: Arc<dyn Store>,
+    /// Sequence number and hash of the last entry, loaded by the first append.
+    /// Held while appending, so entries get consecutive numbers.
+    head: Mutex<Option<(u64, [u8; AUDIT_HASH_SIZE])>>,
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLog").finish_non_exhaustive()
+    }
+}
+
+impl AuditLog {
+    pub fn new(store: Arc<dyn Store>) -> Self {
+        Self {
+            store,
+            head: Mutex::new(None),
+        }
+    }
+
+    /// Appends an action of `actor` on the user `target`
+    pub fn record(
+        &self,
+        actor: &str,
+        action: &str,
+        target: &str,
+        details: &str,
+    ) -> Result<AuditEntry, MetaError> {
+        let mut head = self.head.lock().unwrap();
+        let (last_seq, prev_hash) = match *head {
+            Some(head) => head,
+            None => self.load_head()?,
+        };
+        let mut entry = AuditEntry {
+            seq: last_seq + 1,
+            time: now_secs(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+            details: details.to_string(),
+            prev_hash,
+            hash: GENESIS_HASH,
+        };
+        entry.hash = entry.compute_hash();
+        self.store
+            .tree_open(AUDIT_TREE)?
+            .insert(&entry.seq.to_be_bytes(), entry.to_vec()?)?;
+        *head = Some((entry.seq, entry.hash));
+        Ok(entry)
+    }
+
+    /// The last entry as it is stored, appends refuse to extend an unreadable log
+    fn load_head(&self) -> Result<(u64, [u8; AUDIT_HASH_SIZE]), MetaError> {
+        let tree = self.store.tree_ext_open(AUDIT_TREE)?;
+        match tree.iter_all().last().transpose()? {
+            Some((key, value)) => {
+                let entry = AuditEntry::from_slice(&value)?;
+                Ok((seq_from_key(&key)?, entry.hash))
+            }
+            None => Ok((0, GENESIS_HASH)),
+        }
+    }
+
+    /// The entries of the log, oldest first
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, MetaError> {
+        let tree = self.store.tree_ext_open(AUDIT_TREE)?;
+        tree.iter_all()
+            .map(|item| AuditEntry::from_slice(&item?.1))
+            .collect()
+    }
+
+    /// Walks the chain of the log and reports the entries which were changed,
+    /// removed or inserted
+    pub fn verify(&self) -> Result<AuditVerification, MetaError> {
+        let tree = self.store.tree_ext_open(AUDIT_TREE)?;
+        let mut report = AuditVerification::default();
+        // the last entry, None after an unreadable one
+        let mut prev = Some((0, GENESIS_HASH));
+        let mut last_seq = 0;
+        for item in tree.iter_all() {
+            let (key, value) = item?;
+            let seq = seq_from_key(&key)?;
+            report.entries += 1;
+            if seq != last_seq + 1 {
+                report
+                    .problems
+                    .push((seq, AuditProblem::Missing { from: last_seq + 1 }));
+                prev = None;
+            }
+            last_seq = seq;
+
+            let entry = match AuditEntry::from_slice(&value) {
+                Ok(entry) => entry,
+                Err(_) => {
+                    report.problems.push((seq, AuditProblem::Unreadable));
+                    report.head = None;
+                    prev = None;
+                    continue;
+                }
+            };
+            if entry.seq != seq || entry.compute_hash() != entry.hash {
+                report.problems.push((seq, AuditProblem::Modified));
+            } else if prev.is_some_and(|(_, hash)| entry.prev_hash != hash) {
+                report.problems.push((seq, AuditProblem::BrokenLink));
+            }
+            prev = Some((seq, entry.hash));
+            report.head = prev;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audit_log() -> (tempfile::TempDir, Arc<dyn Store>, AuditLog) {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn Store> = Arc::new(cas_storage::FjallStore::new(
+            dir.path().to_path_buf(),
+            None,
+            None,
+        ));
+        (dir, store.clone(), AuditLog::new(store))
+    }
+
+    fn record_actions(log: &AuditLog) {
+        log.record("root", "user_create", "alice", "is_admin=false")
+            .unwrap();
+        log.record("root", "password_reset", "alice", "").unwrap();
+        log.record(ADMIN_API_ACTOR, "quota_set", "alice", "max_bytes=1000")
+            .unwrap();
+        log.record("root", "user_delete", "alice", "policy=deny")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_chain() {
+        let (_dir, store, log) = audit_log();
+        assert!(log.verify().unwrap().head.is_none());
+        record_actions(&log);
+
+        let entries = log.entries().unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].prev_hash, GENESIS_HASH);
+        for (i, pair) in entries.windows(2).enumerate() {
+            assert_eq!(pair[0].seq, i as u64 + 1);
+            assert_eq!(pair[1].prev_hash, pair[0].hash);
+        }
+        let report = log.verify().unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.entries, 4);
+        assert_eq!(report.head, Some((4, entries[3].hash)));
+
+        // a new log on the same store continues the chain
+        let log = AuditLog::new(store);
+        let entry = log.record("root", "user_create", "bob", "").unwrap();
+        assert_eq!(entry.seq, 5);
+        assert_eq!(entry.prev_hash, entries[3].hash);
+        assert!(log.verify().unwrap().is_intact());
+    }
+
+    #[test]
+    fn test_tampering() {
+        let (_dir, store, log) = audit_log();
+        record_actions(&log);
+        let tree = store.tree_open(AUDIT_TREE).unwrap();
+        let entries = log.entries().unwrap();
+
+        // changing an entry
+        let mut forged = entries[1].clone();
+        forged.actor = "someone".to_string();
+        tree.insert(&2u64.to_be_bytes(), forged.to_vec().unwrap())
+            .unwrap();
+        assert_eq!(
+            log.verify().unwrap().problems,
+            [(2, AuditProblem::Modified)]
+        );
+
+        // rehashing it breaks the link of the next entry
+        forged.hash = forged.compute_hash();
+        tree.insert(&2u64.to_be_bytes(), forged.to_vec().unwrap())
+            .unwrap();
+        assert_eq!(
+            log.verify().unwrap().problems,
+            [(3, AuditProblem::BrokenLink)]
+        );
+        tree.insert(&2u64.to_be_bytes(), entries[1].to_vec().unwrap())
+            .unwrap();
+        assert!(log.verify().unwrap().is_intact());
+
+        // removing an entry
+        tree.remove(&3u64.to_be_bytes()).unwrap();
+        let report = log.verify().unwrap();
+        assert_eq!(report.problems, [(4, AuditProblem::Missing { from: 3 })]);
+        assert_eq!(report.entries, 3);
+
+        // an entry which can't be read
+        tree.insert(&3u64.to_be_bytes(), b"garbage".to_vec())
+            .unwrap();
+        assert_eq!(
+            log.verify().unwrap().problems,
+            [(3, AuditProblem::Unreadable)]
+        );
+    }
+}
@@ -1,7 +1,24 @@
+pub mod audit;
+pub mod quota;
 pub mod router;
+pub mod secrets;
 pub mod session;
+pub mod signup;
+pub mod takeout;
+pub mod template;
+pub mod user_delete;
 pub mod user_store;
 
+pub use audit::{AuditEntry, AuditLog, AuditProblem, AuditVerification, ADMIN_API_ACTOR};
+pub use quota::{compute_usage, QuotaEnforcer, UserUsage};
 pub use router::{RouterError, UserRouter};
+pub use secrets::{EnvelopeCipher, MasterKey, PlaintextSecrets, SecretCipher};
 pub use session::{SessionData, SessionStore};
-pub use user_store::{UserRecord, UserStore};
+pub use signup::{SignupError, SignupRequest, SignupStatus, SignupStore};
+pub use takeout::takeout_path;
+pub use template::{BucketTemplate, UserTemplate};
+pub use user_delete::{DeleteError, DeletePolicy, Deletion, UserDeleter};
+pub use user_store::{
+    S3KeyInfo, Theme, UserRecord, UserStore, DEFAULT_KEY_GRACE_SECS, DEFAULT_TEMPORARY_KEY_SECS,
+    MAX_TEMPORARY_KEY_SECS,
+};
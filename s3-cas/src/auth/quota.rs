@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use cas_storage::{CasFS, MetaError};
+
+/// How long a computed usage is reused before the user's buckets are scanned again
+pub const DEFAULT_USAGE_REFRESH: Duration = Duration::from_secs(60);
+
+/// Storage usage of a user
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct UserUsage {
+    pub buckets: usize,
+    pub objects: usize,
+    pub bytes: u64,
+}
+
+/// Compute the usage of a user by scanning all their buckets.
+pub fn compute_usage(casfs: &CasFS) -> Result<UserUsage, MetaError> {
+    let mut usage = UserUsage::default();
+    for bucket in casfs.list_buckets()? {
+        usage.buckets += 1;
+        let tree = casfs.get_bucket(bucket.name())?;
+        for (_, obj) in tree.range_filter(None, None, None) {
+            usage.objects += 1;
+            usage.bytes += obj.size();
+        }
+    }
+    Ok(usage)
+}
+
+/// Enforces per-user storage quotas.
+///
+/// Computing the usage of a user requires a scan of all their objects, so the
+/// result is reused for `refresh` and quotas are soft: a user can exceed the quota
+/// by what they upload before the usage is recomputed.
+pub struct QuotaEnforcer {
+    refresh: Duration,
+    usage: Mutex<HashMap<String, (Instant, UserUsage)>>,
+}
+
+impl QuotaEnforcer {
+    pub fn new(refresh: Duration) -> Self {
+        Self {
+            refresh,
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `true` if a user with the given quota may store `incoming` more bytes.
+    pub fn allows(
+        &self,
+        user_id: &str,
+        casfs: &CasFS,
+        max_bytes: u64,
+        incoming: u64,
+    ) -> Result<bool, MetaError> {
+        let usage = self.usage(user_id, casfs)?;
+        Ok(usage.bytes.saturating_add(incoming) <= max_bytes)
+    }
+
+    fn usage(&self, user_id: &str, casfs: &CasFS) -> Result<UserUsage, MetaError> {
+        {
+            let cache = self.usage.lock().expect("usage lock is not poisoned");
+            if let Some((computed, usage)) = cache.get(user_id) {
+                if computed.elapsed() < self.refresh {
+                    return Ok(*usage);
+                }
+            }
+        }
+
+        // computed without holding the lock, concurrent requests might scan twice
+        let usage = compute_usage(casfs)?;
+        self.usage
+            .lock()
+            .expect("usage lock is not poisoned")
+            .insert(user_id.to_string(), (Instant::now(), usage));
+        Ok(usage)
+    }
+}
+
+impl Default for QuotaEnforcer {
+    fn default() -> Self {
+        Self::new(DEFAULT_USAGE_REFRESH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cas_storage::{CasFSBuilder, Durability};
+
+    #[test]
+    fn test_quota_uses_cached_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let casfs = CasFSBuilder::new(dir.path(), dir.path().join("meta"))
+            .inlined_metadata_size(1024)
+            .durability(Durability::Buffer)
+            .build()
+            .unwrap();
+        casfs.create_bucket("bucket").unwrap();
+        casfs
+            .store_inlined_object("bucket", "a", vec![0; 100])
+            .unwrap();
+
+        let usage = compute_usage(&casfs).unwrap();
+        assert_eq!((usage.buckets, usage.objects, usage.bytes), (1, 1, 100));
+
+        let quotas = QuotaEnforcer::new(Duration::from_secs(60));
+        assert!(quotas.allows("user", &casfs, 150, 50).unwrap());
+        assert!(!quotas.allows("user", &casfs, 150, 51).unwrap());
+
+        // the usage is not recomputed within the refresh interval
+        casfs
+            .store_inlined_object("bucket", "b", vec![0; 100])
+            .unwrap();
+        assert!(quotas.allows("user", &casfs, 150, 50).unwrap());
+    }
+}
@@ -1,11 +1,19 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
-use tracing::debug;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
 
-use cas_storage::{CasFS, SharedBlockStore, StorageEngine};
+use s3s::{s3_error, S3Error};
+
+use cas_storage::{
+    AdaptiveWriteLimiter, CasFS, CasFSBuilder, GroupSync, KvSeparation, MetaExecutor,
+    SharedBlockStore, StorageEngine,
+};
 use cas_storage::Durability;
 use crate::metrics::SharedMetrics;
+use crate::notifications::Notifier;
 
 /// Error types for user routing
 #[derive(Debug)]
@@ -13,6 +21,7 @@ pub enum RouterError {
     UnknownUser(String),
     AuthenticationFailed,
     CreationFailed(String),
+    RemovalFailed(String),
 }
 
 impl std::fmt::Display for RouterError {
@@ -21,22 +30,73 @@ impl std::fmt::Display for RouterError {
             RouterError::UnknownUser(key) => write!(f, "Unknown user with access key: {}", key),
             RouterError::AuthenticationFailed => write!(f, "Authentication failed"),
             RouterError::CreationFailed(msg) => write!(f, "Failed to create CasFS: {}", msg),
+            RouterError::RemovalFailed(msg) => write!(f, "Failed to remove CasFS: {}", msg),
         }
     }
 }
 
 impl std::error::Error for RouterError {}
 
+impl From<RouterError> for S3Error {
+    fn from(e: RouterError) -> Self {
+        match e {
+            RouterError::UnknownUser(_) => s3_error!(InvalidAccessKeyId, "Invalid access key"),
+            RouterError::AuthenticationFailed => s3_error!(AccessDenied, "Authentication failed"),
+            // usually a lack of file handles or memory, clients should retry later
+            RouterError::CreationFailed(_) | RouterError::RemovalFailed(_) => {
+                s3_error!(ServiceUnavailable, "Storage of the user is temporarily unavailable")
+            }
+        }
+    }
+}
+
+/// An open CasFS of a user, with the time it was last used
+struct CachedCasFS {
+    casfs: Arc<CasFS>,
+    /// Milliseconds since the start of the router
+    last_used: AtomicU64,
+}
+
+impl CachedCasFS {
+    /// Whether the router holds the only reference, so dropping it closes the store
+    fn is_idle(&self) -> bool {
+        Arc::strong_count(&self.casfs) == 1
+    }
+}
+
 /// UserRouter manages per-user CasFS instances with lazy initialization
+///
+/// Open instances are closed again when they are idle for longer than the idle
+/// timeout, or when more than `max_open_users` are open, least recently used
+/// first. An instance is only closed while no request uses it, so the limit
+/// can be exceeded while all open instances are busy.
 pub struct UserRouter {
     shared_block_store: Arc<SharedBlockStore>,
-    casfs_cache: Arc<RwLock<HashMap<String, Arc<CasFS>>>>,
+    casfs_cache: Arc<RwLock<HashMap<String, CachedCasFS>>>,
+    started: Instant,
+    max_open_users: Option<usize>,
+    idle_timeout: Option<Duration>,
     fs_root: PathBuf,
     meta_root: PathBuf,
     metrics: SharedMetrics,
     storage_engine: StorageEngine,
     inlined_metadata_size: Option<usize>,
     durability: Option<Durability>,
+    write_concurrency: usize,
+    write_limiter: Option<Arc<AdaptiveWriteLimiter>>,
+    group_sync: Option<Arc<GroupSync>>,
+    list_snapshots: Option<(Duration, usize)>,
+    meta_cache_entries: Option<usize>,
+    meta_executor: Option<Arc<MetaExecutor>>,
+    hash_pool: Option<Arc<HashPool>>,
+    block_refs: bool,
+    bucket_durability: HashMap<String, Durability>,
+    storage_locations: Vec<(String, PathBuf)>,
+    placement_rules: Vec<(String, String, String)>,
+    kv_separation: Option<KvSeparation>,
+    delete_grace: Duration,
+    flush_activity: bool,
+    notifier: Notifier,
 }
 
 impl UserRouter {
@@ -62,35 +122,216 @@ impl UserRouter {
         Self {
             shared_block_store,
             casfs_cache: Arc::new(RwLock::new(HashMap::new())),
+            started: Instant::now(),
+            max_open_users: None,
+            idle_timeout: None,
             fs_root,
             meta_root,
             metrics,
             storage_engine,
             inlined_metadata_size,
             durability,
+            write_concurrency: cas_storage::DEFAULT_WRITE_CONCURRENCY,
+            write_limiter: None,
+            group_sync: None,
+            list_snapshots: None,
+            meta_cache_entries: None,
+            meta_executor: None,
+            hash_pool: None,
+            block_refs: false,
+            bucket_durability: HashMap::new(),
+            storage_locations: Vec::new(),
+            placement_rules: Vec::new(),
+            kv_separation: None,
+            delete_grace: Duration::ZERO,
+            flush_activity: false,
+            notifier: Notifier::default(),
         }
     }
 
+    /// Set the block write concurrency of the CasFS instances created by this router
+    pub fn with_write_concurrency(mut self, write_concurrency: usize) -> Self {
+        self.write_concurrency = write_concurrency;
+        self
+    }
+
+    /// Share an adaptive block write limiter between all CasFS instances, they
+    /// all write to the same fs_root
+    pub fn with_write_limiter(mut self, limiter: Arc<AdaptiveWriteLimiter>) -> Self {
+        self.write_limiter = Some(limiter);
+        self
+    }
+
+    /// Sync the writes of all users together, see [`GroupSync`]. The stores of
+    /// the users should be opened with `Durability::Buffer`.
+    pub fn with_group_sync(mut self, group_sync: Arc<GroupSync>) -> Self {
+        self.group_sync = Some(group_sync);
+        self
+    }
+
+    /// Enable snapshot-consistent listings on the CasFS instances created by this router
+    pub fn with_list_snapshots(mut self, max_lifetime: Duration, max_count: usize) -> Self {
+        self.list_snapshots = Some((max_lifetime, max_count));
+        self
+    }
+
+    /// Give every CasFS instance created by this router a metadata cache of `entries` objects
+    pub fn with_meta_cache(mut self, entries: usize) -> Self {
+        self.meta_cache_entries = Some(entries);
+        self
+    }
+
+    /// Run the blocking metadata operations of all CasFS instances on one executor,
+    /// instead of one executor per user
+    pub fn with_meta_executor(mut self, executor: Arc<MetaExecutor>) -> Self {
+        self.meta_executor = Some(executor);
+        self
+    }
+
+    /// Hash the uploads of all users on one thread pool
+    pub fn with_hash_pool(mut self, pool: Arc<HashPool>) -> Self {
+        self.hash_pool = Some(pool);
+        self
+    }
+
+    /// Maintain the block reference index in the metadata store of every user
+    pub fn with_block_refs(mut self, enabled: bool) -> Self {
+        self.block_refs = enabled;
+        self
+    }
+
+    /// Override the durability of buckets with these names, in the stores of all users
+    pub fn with_bucket_durability(mut self, overrides: HashMap<String, Durability>) -> Self {
+        self.bucket_durability = overrides;
+        self
+    }
+
+    /// Place the new blocks of all users in storage locations by key prefix, see
+    /// `CasFSBuilder::storage_location` and `CasFSBuilder::prefix_placement`.
+    /// `rules` are (bucket, prefix, location) triples.
+    pub fn with_placement(
+        mut self,
+        locations: Vec<(String, PathBuf)>,
+        rules: Vec<(String, String, String)>,
+    ) -> Self {
+        self.storage_locations = locations;
+        self.placement_rules = rules;
+        self
+    }
+
+    /// Create the new bucket partitions of all users with key-value separation
+    pub fn with_kv_separation(mut self, kv_separation: Option<KvSeparation>) -> Self {
+        self.kv_separation = kv_separation;
+        self
+    }
+
+    /// Keep the files of deleted blocks for `grace` before removing them, see
+    /// `CasFS::with_delete_grace`. The delete queue is in the shared block store.
+    pub fn with_delete_grace(mut self, grace: Duration) -> Self {
+        self.delete_grace = grace;
+        self
+    }
+
+    /// Store the bucket activity of a CasFS instance before closing it, so the
+    /// activity since the last flush isn't lost. Read replicas don't.
+    pub fn with_activity_flush(mut self, enabled: bool) -> Self {
+        self.flush_activity = enabled;
+        self
+    }
+
+    /// Raise the object events of all users to `notifier`
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Keep at most `max` CasFS instances open, closing the least recently used
+    /// idle one when another user needs to be opened
+    pub fn with_max_open_users(mut self, max: usize) -> Self {
+        self.max_open_users = Some(max.max(1));
+        self
+    }
+
+    /// Close the CasFS instances which weren't used for `timeout`, see
+    /// [`UserRouter::evict_idle`]
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// The idle timeout of the CasFS instances, if any
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    fn user_meta_path(&self, user_id: &str) -> PathBuf {
+        self.meta_root.join(format!("user_{}", user_id))
+    }
+
+    /// Directory the buckets of a deleted user are archived in
+    pub fn archive_dir(&self, user_id: &str) -> PathBuf {
+        self.meta_root.join("archive").join(format!("user_{}", user_id))
+    }
+
+    fn now_millis(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
     /// Creates a new CasFS instance for a user (called internally on cache miss)
-    fn create_casfs_for_user(&self, user_id: &str) -> Arc<CasFS> {
+    fn create_casfs_for_user(&self, user_id: &str) -> Result<Arc<CasFS>, RouterError> {
         debug!("Creating new CasFS instance for user: {}", user_id);
 
-        let user_meta_path = self.meta_root.join(format!("user_{}", user_id));
-
-        let casfs = CasFS::new_multi_user(
-            self.fs_root.clone(),
-            user_meta_path,
-            self.shared_block_store.block_tree(),
-            self.shared_block_store.path_tree(),
-            self.shared_block_store.multipart_tree(),
-            self.shared_block_store.meta_store(),
-            self.metrics.to_cas_metrics(),
-            self.storage_engine,
-            self.inlined_metadata_size,
-            self.durability,
-        );
+        let mut builder = CasFSBuilder::new(&self.fs_root, self.user_meta_path(user_id))
+            .shared_block_store(Arc::clone(&self.shared_block_store))
+            .metrics(self.metrics.to_cas_metrics())
+            .storage_engine(self.storage_engine)
+            .write_concurrency(self.write_concurrency)
+            .block_refs(self.block_refs)
+            .delete_grace(self.delete_grace);
+        if let Some(size) = self.inlined_metadata_size {
+            builder = builder.inlined_metadata_size(size);
+        }
+        if let Some(durability) = self.durability {
+            builder = builder.durability(durability);
+        }
+        for (bucket, durability) in &self.bucket_durability {
+            builder = builder.bucket_durability(bucket.clone(), *durability);
+        }
+        for (name, path) in &self.storage_locations {
+            builder = builder.storage_location(name.clone(), path.clone());
+        }
+        for (bucket, prefix, location) in &self.placement_rules {
+            builder = builder.prefix_placement(bucket.clone(), prefix.clone(), location.clone());
+        }
+        if let Some(limiter) = &self.write_limiter {
+            builder = builder.write_limiter(limiter.clone());
+        }
+        if let Some(group_sync) = &self.group_sync {
+            builder = builder.group_sync(group_sync.clone());
+        }
+        if let Some((lifetime, max_count)) = self.list_snapshots {
+            builder = builder.list_snapshots(lifetime, max_count);
+        }
+        if let Some(entries) = self.meta_cache_entries {
+            builder = builder.meta_cache(entries);
+        }
+        if let Some(executor) = &self.meta_executor {
+            builder = builder.meta_executor(executor.clone());
+        }
+        if let Some(pool) = &self.hash_pool {
+            builder = builder.hash_pool(pool.clone());
+        }
+        if let Some(kv_separation) = self.kv_separation {
+            builder = builder.kv_separation(kv_separation);
+        }
+        if let Some(handler) = self.notifier.event_handler(user_id) {
+            builder = builder.event_handler(handler);
+        }
 
-        Arc::new(casfs)
+        let casfs = builder
+            .build()
+            .map_err(|e| RouterError::CreationFailed(e.to_string()))?;
+        Ok(Arc::new(casfs))
     }
 
     /// Get CasFS instance by user_id with lazy initialization
@@ -101,11 +342,29 @@ impl UserRouter {
     /// # Returns
     /// * `Result<Arc<CasFS>, RouterError>` - CasFS instance or error
     pub fn get_casfs_by_user_id(&self, user_id: &str) -> Result<Arc<CasFS>, RouterError> {
+        self.get_casfs(user_id, true)
+    }
+
+    /// Get CasFS instance by user_id for background maintenance, which doesn't
+    /// count as a use: an instance opened by it is the first to be closed again
+    pub fn get_casfs_for_maintenance(&self, user_id: &str) -> Result<Arc<CasFS>, RouterError> {
+        self.get_casfs(user_id, false)
+    }
+
+    fn get_casfs(&self, user_id: &str, touch: bool) -> Result<Arc<CasFS>, RouterError> {
+        let used = || if touch { self.now_millis() } else { 0 };
+        let lookup = |cached: &CachedCasFS| {
+            if touch {
+                cached.last_used.store(self.now_millis(), Ordering::Relaxed);
+            }
+            cached.casfs.clone()
+        };
+
         // First try with read lock (fast path)
         {
             let cache = self.casfs_cache.read().unwrap();
-            if let Some(casfs) = cache.get(user_id) {
-                return Ok(casfs.clone());
+            if let Some(cached) = cache.get(user_id) {
+                return Ok(lookup(cached));
             }
         }
 
@@ -113,19 +372,214 @@ impl UserRouter {
         let mut cache = self.casfs_cache.write().unwrap();
 
         // Double-check after acquiring write lock (another thread might have created it)
-        if let Some(casfs) = cache.get(user_id) {
-            return Ok(casfs.clone());
+        if let Some(cached) = cache.get(user_id) {
+            return Ok(lookup(cached));
+        }
+
+        if let Some(max) = self.max_open_users {
+            self.evict_lru(&mut cache, max - 1);
         }
 
         // Create new CasFS for this user
-        let casfs = self.create_casfs_for_user(user_id);
-        cache.insert(user_id.to_string(), casfs.clone());
+        let casfs = self.create_casfs_for_user(user_id)?;
+        cache.insert(
+            user_id.to_string(),
+            CachedCasFS {
+                casfs: casfs.clone(),
+                last_used: AtomicU64::new(used()),
+            },
+        );
+        self.metrics.set_open_user_stores(cache.len());
 
         Ok(casfs)
     }
 
+    /// Closes idle instances, least recently used first, until at most `keep`
+    /// are open or none of the open ones is idle
+    fn evict_lru(&self, cache: &mut HashMap<String, CachedCasFS>, keep: usize) {
+        if cache.len() <= keep {
+            return;
+        }
+        let mut idle: Vec<(u64, String)> = cache
+            .iter()
+            .filter(|(_, cached)| cached.is_idle())
+            .map(|(user_id, cached)| (cached.last_used.load(Ordering::Relaxed), user_id.clone()))
+            .collect();
+        idle.sort_unstable();
+
+        let excess = cache.len() - keep;
+        if idle.len() < excess {
+            warn!(
+                open = cache.len(),
+                max = keep + 1,
+                "All open user stores are in use, exceeding the maximum of open users"
+            );
+        }
+        for (_, user_id) in idle.into_iter().take(excess) {
+            debug!("Closing least recently used CasFS instance of user: {}", user_id);
+            if let Some(cached) = cache.remove(&user_id) {
+                self.flush_before_close(&user_id, &cached.casfs);
+            }
+        }
+    }
+
+    /// Closes the instances which are idle and weren't used for the idle timeout.
+    /// Returns the amount of closed instances.
+    pub fn evict_idle(&self) -> usize {
+        let Some(timeout) = self.idle_timeout else {
+            return 0;
+        };
+        let deadline = self.now_millis().saturating_sub(timeout.as_millis() as u64);
+
+        let mut cache = self.casfs_cache.write().unwrap();
+        let before = cache.len();
+        cache.retain(|user_id, cached| {
+            let expired = cached.is_idle() && cached.last_used.load(Ordering::Relaxed) < deadline;
+            if expired {
+                debug!("Closing idle CasFS instance of user: {}", user_id);
+                self.flush_before_close(user_id, &cached.casfs);
+            }
+            !expired
+        });
+        self.metrics.set_open_user_stores(cache.len());
+        before - cache.len()
+    }
+
+    fn flush_before_close(&self, user_id: &str, casfs: &CasFS) {
+        if !self.flush_activity {
+            return;
+        }
+        if let Err(e) = casfs.flush_activity() {
+            warn!(user_id, error = %e, "Failed to store the bucket activity of a closed store");
+        }
+    }
+
+    /// Closes the CasFS instance of a deleted user and removes its metadata
+    /// directory. Fails while the instance is in use.
+    pub fn remove_user(&self, user_id: &str) -> Result<(), RouterError> {
+        // holding the lock keeps the instance from being opened again meanwhile
+        let mut cache = self.casfs_cache.write().unwrap();
+        if let Some(cached) = cache.get(user_id) {
+            if !cached.is_idle() {
+                return Err(RouterError::RemovalFailed(format!(
+                    "the storage of user {} is in use",
+                    user_id
+                )));
+            }
+            cache.remove(user_id);
+            self.metrics.set_open_user_stores(cache.len());
+        }
+        match std::fs::remove_dir_all(self.user_meta_path(user_id)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(RouterError::RemovalFailed(e.to_string())),
+        }
+    }
+
+    /// Amount of open CasFS instances
+    pub fn open_users(&self) -> usize {
+        self.casfs_cache.read().unwrap().len()
+    }
+
+    /// The open CasFS instances by user id, without counting as a use
+    pub fn open_stores(&self) -> Vec<(String, Arc<CasFS>)> {
+        self.casfs_cache
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(user_id, cached)| (user_id.clone(), cached.casfs.clone()))
+            .collect()
+    }
+
     /// Get SharedMetrics for metrics collection
     pub fn metrics(&self) -> &SharedMetrics {
         &self.metrics
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(dir: &std::path::Path) -> UserRouter {
+        let shared_block_store = SharedBlockStore::new(
+            dir.join("meta").join("blocks"),
+            StorageEngine::Fjall,
+            None,
+            None,
+        )
+        .unwrap();
+        UserRouter::new(
+            Arc::new(shared_block_store),
+            dir.join("fs"),
+            dir.join("meta"),
+            SharedMetrics::noop(),
+            StorageEngine::Fjall,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_max_open_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path()).with_max_open_users(2);
+
+        let alice = router.get_casfs_by_user_id("alice").unwrap();
+        router.get_casfs_by_user_id("bob").unwrap();
+        // bob is idle, alice is in use
+        router.get_casfs_by_user_id("carol").unwrap();
+        assert_eq!(router.open_users(), 2);
+        assert!(Arc::ptr_eq(&alice, &router.get_casfs_by_user_id("alice").unwrap()));
+
+        // nothing idle, the limit is exceeded rather than failing
+        let carol = router.get_casfs_by_user_id("carol").unwrap();
+        router.get_casfs_by_user_id("bob").unwrap();
+        assert_eq!(router.open_users(), 3);
+        drop(carol);
+    }
+
+    #[test]
+    fn test_evict_idle() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path()).with_idle_timeout(Duration::from_millis(10));
+
+        let alice = router.get_casfs_by_user_id("alice").unwrap();
+        router.get_casfs_by_user_id("bob").unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(router.evict_idle(), 1, "alice is in use");
+        assert_eq!(router.open_users(), 1);
+
+        drop(alice);
+        assert_eq!(router.evict_idle(), 1);
+        assert_eq!(router.open_users(), 0);
+
+        // a closed store can be opened again
+        router.get_casfs_by_user_id("alice").unwrap();
+        assert_eq!(router.open_users(), 1);
+
+        // maintenance doesn't keep a store open
+        router.get_casfs_for_maintenance("alice").unwrap();
+        router.get_casfs_for_maintenance("bob").unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(router.evict_idle(), 2);
+    }
+
+    #[test]
+    fn test_remove_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path());
+
+        let alice = router.get_casfs_by_user_id("alice").unwrap();
+        assert!(matches!(
+            router.remove_user("alice"),
+            Err(RouterError::RemovalFailed(_))
+        ));
+        drop(alice);
+        router.remove_user("alice").unwrap();
+        assert_eq!(router.open_users(), 0);
+        assert!(!dir.path().join("meta").join("user_alice").exists());
+        // never opened
+        router.remove_user("bob").unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+//! Storage of the S3 secret keys of the users.
+//!
+//! The secrets are sealed by a [`SecretCipher`] before a user record is written to
+//! the `_USERS` tree, and opened again when it is read. Without a master key they
+//! are stored as is. With [`EnvelopeCipher`] every secret is encrypted with its
+//! own data key, and the data key with the master key, so the master key only
+//! ever encrypts random keys.
+//!
+//! Secrets stored before a master key was configured are sealed the next time
+//! their record is read.
+
+use std::fmt;
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use cas_storage::MetaError;
+
+/// Prefix of a secret sealed by [`EnvelopeCipher`]
+const SEALED_PREFIX: &str = "enc:v1:";
+
+/// Size of the master key and the data keys, AES-256
+const KEY_LEN: usize = 32;
+
+const NONCE_LEN: usize = 12;
+
+/// Size of a data key encrypted with the master key, including the tag
+const WRAPPED_KEY_LEN: usize = KEY_LEN + 16;
+
+/// Seals the S3 secret keys before they are stored, and opens them when read.
+///
+/// A secret is bound to its access key, a sealed secret copied to another
+/// access key can't be opened.
+pub trait SecretCipher: Send + Sync {
+    /// The stored form of the secret of `access_key`
+    fn seal(&self, access_key: &str, secret: &str) -> Result<String, MetaError>;
+
+    /// The secret of `access_key` from its stored form
+    fn open(&self, access_key: &str, stored: &str) -> Result<String, MetaError>;
+
+    /// Whether a stored secret was not sealed by this cipher, and should be
+    /// stored again
+    fn needs_migration(&self, stored: &str) -> bool;
+}
+
+/// Whether a stored secret is sealed by [`EnvelopeCipher`]
+pub fn is_sealed(stored: &str) -> bool {
+    stored.starts_with(SEALED_PREFIX)
+}
+
+/// Stores the secrets as they are, the default without a master key.
+///
+/// Sealed secrets can't be opened without the master key, they are kept sealed,
+/// so requests signed with them fail. The server refuses to start without the
+/// master key when there are sealed secrets, see [`UserStore::sealed_secrets`].
+///
+/// [`UserStore::sealed_secrets`]: super::UserStore::sealed_secrets
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PlaintextSecrets;
+
+impl SecretCipher for PlaintextSecrets {
+    fn seal(&self, _access_key: &str, secret: &str) -> Result<String, MetaError> {
+        Ok(secret.to_string())
+    }
+
+    fn open(&self, _access_key: &str, stored: &str) -> Result<String, MetaError> {
+        Ok(stored.to_string())
+    }
+
+    fn needs_migration(&self, _stored: &str) -> bool {
+        false
+    }
+}
+
+/// Master key of the [`EnvelopeCipher`]
+#[derive(Clone)]
+pub struct MasterKey(Key<Aes256Gcm>);
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MasterKey(<redacted>)")
+    }
+}
+
+impl MasterKey {
+    /// Parses a master key of 32 bytes, encoded as hex or base64
+    pub fn parse(encoded: &str) -> Result<Self, MetaError> {
+        let encoded = encoded.trim();
+        let bytes = match hex::decode(encoded) {
+            Ok(bytes) => bytes,
+            Err(_) => BASE64.decode(encoded).map_err(|_| {
+                MetaError::OtherDBError("The master key is neither hex nor base64".to_string())
+            })?,
+        };
+        if bytes.len() != KEY_LEN {
+            return Err(MetaError::OtherDBError(format!(
+                "The master key must be {} bytes, not {}",
+                KEY_LEN,
+                bytes.len()
+            )));
+        }
+        Ok(Self(*Key::<Aes256Gcm>::from_slice(&bytes)))
+    }
+
+    /// Reads the master key from a file, e.g. one provided by a KMS
+    pub fn from_file(path: &Path) -> Result<Self, MetaError> {
+        let encoded = std::fs::read_to_string(path).map_err(|e| {
+            MetaError::OtherDBError(format!(
+                "Failed to read master key file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Self::parse(&encoded)
+    }
+}
+
+/// Envelope encryption of the secrets: each secret is encrypted with a random
+/// data key, which is stored encrypted with the master key next to it.
+pub struct EnvelopeCipher {
+    master: Aes256Gcm,
+}
+
+impl EnvelopeCipher {
+    pub fn new(master_key: &MasterKey) -> Self {
+        Self {
+            master: Aes256Gcm::new(&master_key.0),
+        }
+    }
+}
+
+fn crypto_error(access_key: &str) -> MetaError {
+    MetaError::OtherDBError(format!(
+        "Failed to decrypt the secret of access key '{}', wrong master key?",
+        access_key
+    ))
+}
+
+impl SecretCipher for EnvelopeCipher {
+    fn seal(&self, access_key: &str, secret: &str) -> Result<String, MetaError> {
+        let data_key = Aes256Gcm::generate_key(&mut OsRng);
+        let key_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let wrapped_key = self
+            .master
+            .encrypt(&key_nonce, data_key.as_slice())
+            .map_err(|_| MetaError::OtherDBError("Failed to encrypt a data key".to_string()))?;
+
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: secret.as_bytes(),
+            aad: access_key.as_bytes(),
+        };
+        let ciphertext = Aes256Gcm::new(&data_key)
+            .encrypt(&nonce, payload)
+            .map_err(|_| MetaError::OtherDBError("Failed to encrypt a secret".to_string()))?;
+
+        // <key nonce><wrapped data key><nonce><ciphertext>
+        let mut sealed = Vec::with_capacity(2 * NONCE_LEN + WRAPPED_KEY_LEN + ciphertext.len());
+        sealed.extend_from_slice(&key_nonce);
+        sealed.extend_from_slice(&wrapped_key);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", SEALED_PREFIX, BASE64.encode(sealed)))
+    }
+
+    fn open(&self, access_key: &str, stored: &str) -> Result<String, MetaError> {
+        let Some(encoded) = stored.strip_prefix(SEALED_PREFIX) else {
+            // stored before the master key was configured
+            return Ok(stored.to_string());
+        };
+        let sealed = BASE64
+            .decode(encoded)
+            .map_err(|_| crypto_error(access_key))?;
+        if sealed.len() < 2 * NONCE_LEN + WRAPPED_KEY_LEN {
+            return Err(crypto_error(access_key));
+        }
+        let (key_nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (wrapped_key, rest) = rest.split_at(WRAPPED_KEY_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let data_key = self
+            .master
+            .decrypt(Nonce::from_slice(key_nonce), wrapped_key)
+            .map_err(|_| crypto_error(access_key))?;
+        let payload = Payload {
+            msg: ciphertext,
+            aad: access_key.as_bytes(),
+        };
+        let secret = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key))
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| crypto_error(access_key))?;
+        String::from_utf8(secret).map_err(|_| crypto_error(access_key))
+    }
+
+    fn needs_migration(&self, stored: &str) -> bool {
+        !is_sealed(stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_envelope_cipher() {
+        let cipher = EnvelopeCipher::new(&MasterKey::parse(KEY).unwrap());
+        let sealed = cipher.seal("AKIA", "secret").unwrap();
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.contains("secret"));
+        assert!(!cipher.needs_migration(&sealed));
+        assert_eq!(cipher.open("AKIA", &sealed).unwrap(), "secret");
+
+        // every secret gets its own data key and nonce
+        assert_ne!(cipher.seal("AKIA", "secret").unwrap(), sealed);
+
+        // bound to the access key
+        assert!(cipher.open("OTHER", &sealed).is_err());
+
+        // plaintext secrets are read as is, and migrated
+        assert_eq!(cipher.open("AKIA", "plain").unwrap(), "plain");
+        assert!(cipher.needs_migration("plain"));
+
+        let other = [7u8; KEY_LEN];
+        let other = EnvelopeCipher::new(&MasterKey::parse(&BASE64.encode(other)).unwrap());
+        assert!(other.open("AKIA", &sealed).is_err());
+        assert_eq!(PlaintextSecrets.open("AKIA", &sealed).unwrap(), sealed);
+    }
+
+    #[test]
+    fn test_master_key() {
+        assert!(MasterKey::parse(&format!(" {}\n", KEY)).is_ok());
+        assert!(MasterKey::parse("0011").is_err());
+        assert!(MasterKey::parse("not a key").is_err());
+        assert_eq!(
+            format!("{:?}", MasterKey::parse(KEY).unwrap()),
+            "MasterKey(<redacted>)"
+        );
+    }
+}
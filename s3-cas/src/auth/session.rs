@@ -18,6 +18,8 @@ pub struct SessionData {
     pub created_at: Instant,
     /// When the session expires
     pub expires_at: Instant,
+    /// UI preferences of the user, kept for the lifetime of the session
+    pub preferences: HashMap<String, String>,
 }
 
 impl SessionData {
@@ -28,6 +30,7 @@ impl SessionData {
             user_id,
             created_at: now,
             expires_at: now + lifetime,
+            preferences: HashMap::new(),
         }
     }
 
@@ -160,6 +163,27 @@ impl SessionStore {
         false
     }
 
+    /// Gets a preference stored in a session, if the session is valid
+    pub fn preference(&self, session_id: &str, name: &str) -> Option<String> {
+        let sessions = self.sessions.read().unwrap();
+        sessions
+            .get(session_id)
+            .filter(|session_data| !session_data.is_expired())
+            .and_then(|session_data| session_data.preferences.get(name).cloned())
+    }
+
+    /// Stores a preference in a session, returns false if the session is not valid
+    pub fn set_preference(&self, session_id: &str, name: &str, value: String) -> bool {
+        let mut sessions = self.sessions.write().unwrap();
+        match sessions.get_mut(session_id) {
+            Some(session_data) if !session_data.is_expired() => {
+                session_data.preferences.insert(name.to_string(), value);
+                true
+            }
+            _ => false,
+        }
+    }
+
     /// Deletes all sessions for a specific user
     pub fn delete_user_sessions(&self, user_id: &str) -> usize {
         tracing::debug!(user_id = %user_id, "Deleting all sessions for user");
@@ -254,6 +278,26 @@ mod tests {
         assert_eq!(store.get_session(&session_id), Some("testuser".to_string()));
     }
 
+    #[test]
+    fn test_session_preferences() {
+        let store = SessionStore::new();
+        let session1 = store.create_session("user1".to_string());
+        let session2 = store.create_session("user1".to_string());
+
+        assert_eq!(store.preference(&session1, "object_list"), None);
+        assert!(store.set_preference(&session1, "object_list", "limit=25".to_string()));
+        assert_eq!(
+            store.preference(&session1, "object_list"),
+            Some("limit=25".to_string())
+        );
+        // preferences are per session
+        assert_eq!(store.preference(&session2, "object_list"), None);
+
+        store.delete_session(&session1);
+        assert!(!store.set_preference(&session1, "object_list", "limit=50".to_string()));
+        assert_eq!(store.preference(&session1, "object_list"), None);
+    }
+
     #[test]
     fn test_delete_user_sessions() {
         let store = SessionStore::new();
@@ -0,0 +1,460 @@
+//! Self-service signup: people without an account ask for one in the HTTP UI,
+//! and an admin approves or rejects the request.
+//!
+//! A request gets a random token, which is shown once to the person who signed
+//! up. Only its hash is stored. The token is the only way to follow the request,
+//! so no email address is needed. The account is created when the request is
+//! approved, with the password chosen at signup, until then the login doesn't
+//! exist.
+
+use bcrypt::{hash, DEFAULT_COST};
+use rand::Rng;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+use cas_storage::{MetaError, Store};
+
+use super::user_store::{Theme, UserRecord, UserStore};
+
+const SIGNUPS_TREE: &str = "_USER_SIGNUPS";
+
+/// Pending signups accepted by default, later signups are refused until admins
+/// decided on some
+pub const DEFAULT_MAX_PENDING_SIGNUPS: usize = 100;
+
+/// Time approved and rejected requests are kept, so their outcome can be seen
+/// with the token
+pub const DECIDED_SIGNUP_RETENTION_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Shortest password accepted at signup
+pub const MIN_SIGNUP_PASSWORD_LEN: usize = 8;
+
+/// Size of a signup token in bytes, it is shown hex encoded
+const TOKEN_BYTES: usize = 32;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.trim().as_bytes()))
+}
+
+/// State of a signup request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, bincode::Encode, bincode::Decode)]
+#[serde(rename_all = "snake_case")]
+pub enum SignupStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl SignupStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SignupStatus::Pending => "pending",
+            SignupStatus::Approved => "approved",
+            SignupStatus::Rejected => "rejected",
+        }
+    }
+}
+
+/// A request for an account
+#[derive(Clone, Serialize, bincode::Encode, bincode::Decode)]
+pub struct SignupRequest {
+    /// The user id and login asked for
+    pub user_id: String,
+    /// Bcrypt hash of the password chosen at signup
+    #[serde(skip)]
+    pub ui_password_hash: String,
+    /// SHA-256 of the token of the request
+    #[serde(skip)]
+    pub token_hash: String,
+    pub status: SignupStatus,
+    /// Seconds since the UNIX epoch of the signup
+    pub created_at: u64,
+    /// Seconds since the UNIX epoch of the approval or rejection
+    pub decided_at: Option<u64>,
+    /// The admin who approved or rejected the request
+    pub decided_by: Option<String>,
+}
+
+impl fmt::Debug for SignupRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignupRequest")
+            .field("user_id", &self.user_id)
+            .field("status", &self.status)
+            .field("created_at", &self.created_at)
+            .field("decided_at", &self.decided_at)
+            .field("decided_by", &self.decided_by)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SignupRequest {
+    fn to_vec(&self) -> Result<Vec<u8>, MetaError> {
+        bincode::encode_to_vec(self, bincode::config::standard()).map_err(|e| {
+            MetaError::OtherDBError(format!("Failed to serialize SignupRequest: {}", e))
+        })
+    }
+
+    fn from_slice(data: &[u8]) -> Result<Self, MetaError> {
+        bincode::decode_from_slice(data, bincode::config::standard())
+            .map(|(request, _)| request)
+            .map_err(|e| {
+                MetaError::OtherDBError(format!("Failed to deserialize SignupRequest: {}", e))
+            })
+    }
+
+    fn expired(&self, now: u64) -> bool {
+        self.decided_at.map_or(false, |decided_at| {
+            decided_at + DECIDED_SIGNUP_RETENTION_SECS <= now
+        })
+    }
+}
+
+/// Why a signup is refused
+#[derive(Debug)]
+pub enum SignupError {
+    /// The user id is empty, too long or has other characters than letters,
+    /// digits, `-`, `_` and `.`
+    InvalidUserId,
+    /// The password is shorter than [`MIN_SIGNUP_PASSWORD_LEN`]
+    WeakPassword,
+    /// A user or a signup request with the user id exists
+    Taken,
+    /// Too many signups wait for a decision
+    TooManyPending,
+    Store(MetaError),
+}
+
+impl fmt::Display for SignupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignupError::InvalidUserId => write!(
+                f,
+                "The username must have 3 to 32 letters, digits, '-', '_' or '.'"
+            ),
+            SignupError::WeakPassword => write!(
+                f,
+                "The password must have at least {} characters",
+                MIN_SIGNUP_PASSWORD_LEN
+            ),
+            SignupError::Taken => write!(f, "The username is taken"),
+            SignupError::TooManyPending => write!(
+                f,
+                "Too many signups wait for approval, please try again later"
+            ),
+            SignupError::Store(e) => write!(f, "Failed to store the signup: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SignupError {}
+
+impl From<MetaError> for SignupError {
+    fn from(e: MetaError) -> Self {
+        SignupError::Store(e)
+    }
+}
+
+fn valid_user_id(user_id: &str) -> bool {
+    (3..=32).contains(&user_id.len())
+        && user_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Signup requests, stored in the `_USER_SIGNUPS` tree of the user store
+#[derive(Debug, Clone)]
+pub struct SignupStore {
+    store: Arc<dyn Store>,
+    max_pending: usize,
+}
+
+impl SignupStore {
+    pub fn new(store: Arc<dyn Store>) -> Self {
+        Self {
+            store,
+            max_pending: DEFAULT_MAX_PENDING_SIGNUPS,
+        }
+    }
+
+    /// Refuse signups while `max_pending` requests wait for a decision
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending;
+        self
+    }
+
+    /// Asks for an account with `user_id` as user id and login. Returns the token
+    /// to follow the request with.
+    pub fn sign_up(
+        &self,
+        user_store: &UserStore,
+        user_id: &str,
+        password: &str,
+    ) -> Result<String, SignupError> {
+        if !valid_user_id(user_id) {
+            return Err(SignupError::InvalidUserId);
+        }
+        if password.chars().count() < MIN_SIGNUP_PASSWORD_LEN {
+            return Err(SignupError::WeakPassword);
+        }
+        if user_store.get_user_by_id(user_id)?.is_some()
+            || user_store.get_user_by_ui_login(user_id)?.is_some()
+        {
+            return Err(SignupError::Taken);
+        }
+        let requests = self.list()?;
+        if requests.iter().any(|request| request.user_id == user_id) {
+            return Err(SignupError::Taken);
+        }
+        let pending = requests
+            .iter()
+            .filter(|request| request.status == SignupStatus::Pending)
+            .count();
+        if pending >= self.max_pending {
+            return Err(SignupError::TooManyPending);
+        }
+
+        let token = hex::encode(rand::thread_rng().gen::<[u8; TOKEN_BYTES]>());
+        let ui_password_hash = hash(password, DEFAULT_COST)
+            .map_err(|e| MetaError::OtherDBError(format!("Failed to hash password: {}", e)))?;
+        let request = SignupRequest {
+            user_id: user_id.to_string(),
+            ui_password_hash,
+            token_hash: token_hash(&token),
+            status: SignupStatus::Pending,
+            created_at: now_secs(),
+            decided_at: None,
+            decided_by: None,
+        };
+        self.store
+            .tree_open(SIGNUPS_TREE)?
+            .insert(user_id.as_bytes(), request.to_vec()?)?;
+        debug!("Signup of user {} pending", user_id);
+        Ok(token)
+    }
+
+    /// The signup requests, oldest first. Decided requests are removed after
+    /// [`DECIDED_SIGNUP_RETENTION_SECS`].
+    pub fn list(&self) -> Result<Vec<SignupRequest>, MetaError> {
+        let tree = self.store.tree_ext_open(SIGNUPS_TREE)?;
+        let now = now_secs();
+        let mut requests = Vec::new();
+        let mut expired = Vec::new();
+        for item in tree.iter_all() {
+            let (key, value) = item?;
+            let request = SignupRequest::from_slice(&value)?;
+            if request.expired(now) {
+                expired.push(key);
+            } else {
+                requests.push(request);
+            }
+        }
+        if !expired.is_empty() {
+            let tree = self.store.tree_open(SIGNUPS_TREE)?;
+            for key in expired {
+                tree.remove(&key)?;
+            }
+        }
+        requests.sort_by_key(|request| request.created_at);
+        Ok(requests)
+    }
+
+    /// The pending signup requests, oldest first
+    pub fn pending(&self) -> Result<Vec<SignupRequest>, MetaError> {
+        let mut requests = self.list()?;
+        requests.retain(|request| request.status == SignupStatus::Pending);
+        Ok(requests)
+    }
+
+    /// The request of a token, `None` if it is unknown or expired
+    pub fn by_token(&self, token: &str) -> Result<Option<SignupRequest>, MetaError> {
+        let hash = token_hash(token);
+        Ok(self
+            .list()?
+            .into_iter()
+            .find(|request| request.token_hash == hash))
+    }
+
+    fn get_pending(&self, user_id: &str) -> Result<SignupRequest, MetaError> {
+        let tree = self.store.tree_open(SIGNUPS_TREE)?;
+        match tree.get(user_id.as_bytes())? {
+            Some(data) => {
+                let request = SignupRequest::from_slice(&data)?;
+                if request.status != SignupStatus::Pending {
+                    return Err(MetaError::OtherDBError(format!(
+                        "The signup of '{}' is already {}",
+                        user_id,
+                        request.status.as_str()
+                    )));
+                }
+                Ok(request)
+            }
+            None => Err(MetaError::OtherDBError(format!(
+                "No signup of '{}'",
+                user_id
+            ))),
+        }
+    }
+
+    fn decide(
+        &self,
+        mut request: SignupRequest,
+        status: SignupStatus,
+        admin: &str,
+    ) -> Result<(), MetaError> {
+        request.status = status;
+        request.decided_at = Some(now_secs());
+        request.decided_by = Some(admin.to_string());
+        self.store
+            .tree_open(SIGNUPS_TREE)?
+            .insert(request.user_id.as_bytes(), request.to_vec()?)
+    }
+
+    /// Approves the signup of `user_id`, creating the user with the password of
+    /// the signup and the given S3 key pair
+    pub fn approve(
+        &self,
+        user_store: &UserStore,
+        user_id: &str,
+        admin: &str,
+        s3_access_key: String,
+        s3_secret_key: String,
+    ) -> Result<UserRecord, MetaError> {
+        let request = self.get_pending(user_id)?;
+        // the password of the signup is only known by its hash
+        let user = UserRecord {
+            user_id: user_id.to_string(),
+            ui_login: user_id.to_string(),
+            ui_password_hash: request.ui_password_hash.clone(),
+            s3_access_key,
+            s3_secret_key,
+            is_admin: false,
+            created_at: now_secs(),
+            theme: Theme::default(),
+            language: None,
+        };
+        user_store.create_user(user.clone())?;
+        self.decide(request, SignupStatus::Approved, admin)?;
+        Ok(user)
+    }
+
+    /// Rejects the signup of `user_id`
+    pub fn reject(&self, user_id: &str, admin: &str) -> Result<(), MetaError> {
+        let request = self.get_pending(user_id)?;
+        self.decide(request, SignupStatus::Rejected, admin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stores() -> (tempfile::TempDir, UserStore, SignupStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store: Arc<dyn Store> = Arc::new(cas_storage::FjallStore::new(
+            dir.path().to_path_buf(),
+            None,
+            None,
+        ));
+        (dir, UserStore::new(store.clone()), SignupStore::new(store))
+    }
+
+    #[test]
+    fn test_signup_approval() {
+        let (_dir, user_store, signups) = stores();
+        let token = signups
+            .sign_up(&user_store, "alice", "password123")
+            .unwrap();
+
+        let request = signups.by_token(&token).unwrap().unwrap();
+        assert_eq!(request.user_id, "alice");
+        assert_eq!(request.status, SignupStatus::Pending);
+        assert!(signups.by_token("other").unwrap().is_none());
+        // no account until it is approved
+        assert!(user_store
+            .authenticate("alice", "password123")
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            signups.sign_up(&user_store, "alice", "password456"),
+            Err(SignupError::Taken)
+        ));
+
+        let user = signups
+            .approve(
+                &user_store,
+                "alice",
+                "admin",
+                "AKIAALICE".to_string(),
+                "secret".to_string(),
+            )
+            .unwrap();
+        assert!(!user.is_admin);
+        assert!(user_store
+            .authenticate("alice", "password123")
+            .unwrap()
+            .is_some());
+        let request = signups.by_token(&token).unwrap().unwrap();
+        assert_eq!(request.status, SignupStatus::Approved);
+        assert_eq!(request.decided_by.as_deref(), Some("admin"));
+        assert!(signups.pending().unwrap().is_empty());
+        assert!(signups.reject("alice", "admin").is_err());
+        assert!(matches!(
+            signups.sign_up(&user_store, "alice", "password456"),
+            Err(SignupError::Taken)
+        ));
+    }
+
+    #[test]
+    fn test_signup_rejection() {
+        let (_dir, user_store, signups) = stores();
+        let signups = signups.with_max_pending(1);
+        assert!(matches!(
+            signups.sign_up(&user_store, "a", "password123"),
+            Err(SignupError::InvalidUserId)
+        ));
+        assert!(matches!(
+            signups.sign_up(&user_store, "bob/x", "password123"),
+            Err(SignupError::InvalidUserId)
+        ));
+        assert!(matches!(
+            signups.sign_up(&user_store, "bob", "short"),
+            Err(SignupError::WeakPassword)
+        ));
+
+        let token = signups.sign_up(&user_store, "bob", "password123").unwrap();
+        assert!(matches!(
+            signups.sign_up(&user_store, "carol", "password123"),
+            Err(SignupError::TooManyPending)
+        ));
+        signups.reject("bob", "admin").unwrap();
+        assert_eq!(
+            signups.by_token(&token).unwrap().unwrap().status,
+            SignupStatus::Rejected
+        );
+        assert!(user_store.get_user_by_id("bob").unwrap().is_none());
+        assert!(signups
+            .approve(
+                &user_store,
+                "bob",
+                "admin",
+                "AKIABOB".to_string(),
+                "s".to_string()
+            )
+            .is_err());
+        // decided requests don't count as pending
+        signups
+            .sign_up(&user_store, "carol", "password123")
+            .unwrap();
+    }
+}
@@ -0,0 +1,355 @@
+//! Export of everything a user owns, for data takeout requests.
+//!
+//! A takeout job packages the buckets of a user into a tar archive in the
+//! archive directory of the user: per bucket the manifest of its objects, the
+//! object data and the usage history, together with the audit log entries about
+//! the user. The archive is written as `takeout-<job>.tar.part` and renamed once
+//! complete, so a finished archive can be downloaded while others are written.
+//! Optionally the user is deleted once the archive is complete.
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use cas_storage::{CasFS, JobRecord};
+
+use crate::jobs::{Job, JobKind};
+
+use super::user_delete::remove_user;
+use super::{AuditEntry, DeleteError, UserDeleter, UserRouter};
+
+/// The path of the archive written by the takeout job `job_id` of a user
+pub fn takeout_path(user_router: &UserRouter, user_id: &str, job_id: u64) -> PathBuf {
+    user_router
+        .archive_dir(user_id)
+        .join(format!("takeout-{}.tar", job_id))
+}
+
+/// Summary at the start of a takeout archive, as `takeout.json`
+#[derive(Debug, Serialize)]
+struct TakeoutInfo<'a> {
+    user_id: &'a str,
+    /// Seconds since the UNIX epoch
+    created_at: u64,
+    buckets: Vec<String>,
+}
+
+/// An audit log entry about the user, as a line of `audit.jsonl`
+#[derive(Debug, Serialize)]
+struct AuditLine<'a> {
+    seq: u64,
+    time: u64,
+    actor: &'a str,
+    action: &'a str,
+    details: &'a str,
+    hash: String,
+}
+
+impl UserDeleter<'_> {
+    /// Start a job exporting the data of `user_id` with the audit log entries
+    /// about it, `audit`, to a tar archive, see [`takeout_path`]. With
+    /// `delete_after` the buckets and the user are deleted once it is complete.
+    pub fn takeout(
+        &self,
+        user_id: &str,
+        audit: Vec<AuditEntry>,
+        delete_after: bool,
+    ) -> Result<JobRecord, DeleteError> {
+        if self.user_store.get_user_by_id(user_id)?.is_none() {
+            return Err(DeleteError::UnknownUser(user_id.to_string()));
+        }
+        let jobs = self.jobs.ok_or(DeleteError::NoJobs)?;
+        let fs = self.user_router.get_casfs_for_maintenance(user_id)?;
+
+        if delete_after {
+            self.session_store.delete_user_sessions(user_id);
+        }
+        let user_id = user_id.to_string();
+        let user_router = self.user_router.clone();
+        let user_store = self.user_store.clone();
+        let session_store = self.session_store.clone();
+        let record = jobs.start(
+            JobKind::UserTakeout,
+            Some(user_id.clone()),
+            |job| async move {
+                let path = takeout_path(&user_router, &user_id, job.id());
+                let partial = path.with_extension("tar.part");
+                let result = write_archive(&job, &fs, &user_id, audit, &partial).await;
+                if result.is_err() {
+                    let _ = std::fs::remove_file(&partial);
+                }
+                let buckets = result?;
+                std::fs::rename(&partial, &path)
+                    .with_context(|| format!("Can't rename {}", partial.display()))?;
+                tracing::info!(user_id = %user_id, path = %path.display(), "Takeout archive written");
+
+                if delete_after {
+                    for bucket in &buckets {
+                        fs.bucket_delete(bucket).await?;
+                    }
+                    if !fs.list_buckets()?.is_empty() {
+                        anyhow::bail!("Buckets were created while the user was exported");
+                    }
+                    drop(fs);
+                    remove_user(&user_id, &user_router, &user_store, &session_store)?;
+                }
+                Ok(())
+            },
+        )?;
+        Ok(record)
+    }
+}
+
+/// Write the takeout archive of the user owning `fs` to `path`, returning the
+/// exported buckets
+async fn write_archive(
+    job: &Job,
+    fs: &Arc<CasFS>,
+    user_id: &str,
+    audit: Vec<AuditEntry>,
+    path: &Path,
+) -> anyhow::Result<Vec<String>> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("Can't create {}", dir.display()))?;
+    }
+    let file = File::create(path).with_context(|| format!("Can't create {}", path.display()))?;
+    let mut archive = tar::Builder::new(BufWriter::new(file));
+
+    let buckets: Vec<String> = fs
+        .list_buckets()?
+        .iter()
+        .map(|bucket| bucket.name().to_string())
+        .collect();
+    job.set_total(buckets.len() as u64);
+    let info = TakeoutInfo {
+        user_id,
+        created_at: unix_now(),
+        buckets: buckets.clone(),
+    };
+    append_bytes(
+        &mut archive,
+        "takeout.json",
+        &serde_json::to_vec_pretty(&info)?,
+    )?;
+    append_bytes(&mut archive, "audit.jsonl", &audit_lines(&audit)?)?;
+
+    for bucket in &buckets {
+        job.check_cancelled()?;
+        let fs = fs.clone();
+        let name = bucket.clone();
+        archive = tokio::task::spawn_blocking(move || {
+            export_bucket(&fs, &name, &mut archive).map(|()| archive)
+        })
+        .await?
+        .with_context(|| format!("Failed to export bucket {}", bucket))?;
+        job.advance(1);
+    }
+    let out = archive.into_inner()?;
+    let file = out.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    Ok(buckets)
+}
+
+/// Append the manifest, the objects and the usage history of `bucket` to the
+/// archive, below `buckets/`
+fn export_bucket<W: Write>(
+    fs: &CasFS,
+    bucket: &str,
+    archive: &mut tar::Builder<W>,
+) -> anyhow::Result<()> {
+    let mut manifest = Vec::new();
+    let snapshot = fs.get_bucket(bucket)?.snapshot();
+    for (key, _) in snapshot.range_filter(None, None, None) {
+        // the block files stay on disk while they are read
+        let Some((obj, paths, _pin)) = fs.get_object_paths_pinned(bucket, &key)? else {
+            // deleted since the listing
+            continue;
+        };
+        let entry = fs
+            .manifest_entry(&key, &obj)
+            .with_context(|| format!("Can't export {}", key))?;
+        serde_json::to_writer(&mut manifest, &entry)?;
+        manifest.push(b'\n');
+
+        let path = object_path(bucket, &key);
+        let mut header = file_header(obj.size());
+        let mtime = obj.last_modified().duration_since(UNIX_EPOCH);
+        header.set_mtime(mtime.unwrap_or_default().as_secs());
+        match obj.inlined() {
+            Some(data) => archive.append_data(&mut header, &path, data.as_slice())?,
+            None => {
+                let files = BlockFiles::new(paths);
+                archive
+                    .append_data(&mut header, &path, files)
+                    .with_context(|| format!("Can't export {}", key))?
+            }
+        }
+    }
+    append_bytes(archive, &format!("buckets/{}.manifest", bucket), &manifest)?;
+
+    let samples = fs.usage_history().samples(bucket)?;
+    append_bytes(
+        archive,
+        &format!("usage/{}.json", bucket),
+        &serde_json::to_vec_pretty(&samples)?,
+    )?;
+    Ok(())
+}
+
+/// The path in the archive of the data of an object, `buckets/<bucket>/<key>`.
+/// Keys which are no relative path, like `a//b` or `../a`, are percent encoded
+/// with the dots, into a single file name.
+fn object_path(bucket: &str, key: &str) -> String {
+    let plain = !key.contains('\0')
+        && key
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != "..");
+    if plain {
+        format!("buckets/{}/{}", bucket, key)
+    } else {
+        let encoded = urlencoding::encode(key).replace('.', "%2E");
+        format!("buckets/{}/{}", bucket, encoded)
+    }
+}
+
+fn audit_lines(entries: &[AuditEntry]) -> serde_json::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for entry in entries {
+        let line = AuditLine {
+            seq: entry.seq,
+            time: entry.time,
+            actor: &entry.actor,
+            action: &entry.action,
+            details: &entry.details,
+            hash: hex::encode(entry.hash),
+        };
+        serde_json::to_writer(&mut out, &line)?;
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+fn file_header(size: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(unix_now());
+    header
+}
+
+fn append_bytes<W: Write>(
+    archive: &mut tar::Builder<W>,
+    path: &str,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let mut header = file_header(data.len() as u64);
+    archive.append_data(&mut header, path, data)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Reads the block files of an object one after the other
+struct BlockFiles {
+    paths: std::vec::IntoIter<PathBuf>,
+    current: Option<File>,
+}
+
+impl BlockFiles {
+    fn new(paths: Vec<(PathBuf, usize)>) -> Self {
+        let paths: Vec<PathBuf> = paths.into_iter().map(|(path, _)| path).collect();
+        Self {
+            paths: paths.into_iter(),
+            current: None,
+        }
+    }
+}
+
+impl Read for BlockFiles {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(file) = &mut self.current {
+                let read = file.read(buf)?;
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+            }
+            match self.paths.next() {
+                Some(path) => self.current = Some(open_block(&path)?),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+fn open_block(path: &Path) -> std::io::Result<File> {
+    File::open(path).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("Can't open block file {}: {}", path.display(), e),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use cas_storage::JobStatus;
+
+    use super::*;
+    use crate::auth::user_delete::tests::Setup;
+
+    #[tokio::test]
+    async fn test_takeout() {
+        let setup = Setup::new();
+        let deleter = setup.deleter();
+        drop(setup.add_user("alice").await);
+        let record = deleter.takeout("alice", Vec::new(), false).unwrap();
+        let record = setup.wait_finished(record.id).await;
+        assert_eq!(record.status, JobStatus::Completed, "{:?}", record.error);
+
+        let path = takeout_path(&setup.user_router, "alice", record.id);
+        let mut archive = tar::Archive::new(File::open(path).unwrap());
+        let mut files = HashMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().display().to_string();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            files.insert(name, data);
+        }
+        assert_eq!(files["buckets/bucket/key"], b"user data".repeat(100));
+        let manifest = String::from_utf8(files["buckets/bucket.manifest"].clone()).unwrap();
+        assert_eq!(manifest.lines().count(), 1);
+        for name in ["takeout.json", "audit.jsonl", "usage/bucket.json"] {
+            assert!(files.contains_key(name), "{} is missing", name);
+        }
+        assert!(setup.user_store.get_user_by_id("alice").unwrap().is_some());
+
+        // the user is deleted once the archive is complete
+        drop(setup.add_user("bob").await);
+        let record = deleter.takeout("bob", Vec::new(), true).unwrap();
+        let record = setup.wait_finished(record.id).await;
+        assert_eq!(record.status, JobStatus::Completed, "{:?}", record.error);
+        assert!(takeout_path(&setup.user_router, "bob", record.id).exists());
+        assert!(setup.user_store.get_user_by_id("bob").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_object_path() {
+        assert_eq!(object_path("b", "dir/file.txt"), "buckets/b/dir/file.txt");
+        assert_eq!(object_path("b", "../etc"), "buckets/b/%2E%2E%2Fetc");
+        assert_eq!(object_path("b", "dir/"), "buckets/b/dir%2F");
+        assert_eq!(object_path("b", "/abs"), "buckets/b/%2Fabs");
+    }
+}
@@ -0,0 +1,285 @@
+//! Template applied to the users created by an admin or by approving a signup,
+//! so tenants of a standardized product start with the same quota and buckets.
+//!
+//! The template is read from a TOML file:
+//!
+//! ```toml
+//! quota_bytes = 107374182400
+//!
+//! [[bucket]]
+//! name = "backups"
+//! encryption = "AES256"
+//! max_bytes = 53687091200
+//!
+//! [[bucket]]
+//! name = "website"
+//! acl = "public-read"
+//! max_objects = 10000
+//! ```
+
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Deserializer};
+
+use cas_storage::{BucketLimits, CannedAcl};
+
+use super::{UserRouter, UserStore};
+
+/// The only default encryption buckets support
+const AES256: &str = "AES256";
+
+/// A bucket created for every new user
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BucketTemplate {
+    pub name: String,
+    /// Canned ACL of the bucket, private by default
+    #[serde(default, deserialize_with = "deserialize_acl")]
+    pub acl: Option<CannedAcl>,
+    /// Default server-side encryption, only `AES256`
+    #[serde(default)]
+    pub encryption: Option<String>,
+    #[serde(default)]
+    pub max_objects: Option<u64>,
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+}
+
+fn deserialize_acl<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<CannedAcl>, D::Error> {
+    let acl = String::deserialize(deserializer)?;
+    acl.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+impl BucketTemplate {
+    fn limits(&self) -> BucketLimits {
+        BucketLimits {
+            max_objects: self.max_objects,
+            max_bytes: self.max_bytes,
+        }
+    }
+}
+
+/// Quota and buckets of new users
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserTemplate {
+    /// Storage quota of new users in bytes, none by default
+    #[serde(default)]
+    pub quota_bytes: Option<u64>,
+    #[serde(default, rename = "bucket")]
+    pub buckets: Vec<BucketTemplate>,
+}
+
+impl UserTemplate {
+    /// Load a template from a TOML file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read user template {}", path.display()))?;
+        let template: Self = toml::from_str(&data)
+            .with_context(|| format!("Invalid user template {}", path.display()))?;
+        template
+            .validate()
+            .with_context(|| format!("Invalid user template {}", path.display()))?;
+        Ok(template)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            if !is_valid_bucket_name(&bucket.name) {
+                anyhow::bail!("Invalid bucket name '{}'", bucket.name);
+            }
+            if self.buckets[..i].iter().any(|b| b.name == bucket.name) {
+                anyhow::bail!("Bucket '{}' is listed twice", bucket.name);
+            }
+            if let Some(algorithm) = &bucket.encryption {
+                if algorithm != AES256 {
+                    anyhow::bail!("Only {AES256} encryption is supported, not '{algorithm}'");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if a bucket of the template requires server-side encryption
+    pub fn needs_encryption(&self) -> bool {
+        self.buckets
+            .iter()
+            .any(|bucket| bucket.encryption.is_some())
+    }
+
+    /// Set the quota of the new user `user_id` and create its buckets. Buckets
+    /// the user already has are left as they are.
+    ///
+    /// Returns the names of the created buckets.
+    pub fn apply(
+        &self,
+        user_id: &str,
+        user_router: &UserRouter,
+        user_store: &UserStore,
+    ) -> anyhow::Result<Vec<String>> {
+        if self.quota_bytes.is_some() {
+            user_store.set_quota(user_id, self.quota_bytes)?;
+        }
+        if self.buckets.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let casfs = user_router.get_casfs_for_maintenance(user_id)?;
+        let mut created = Vec::new();
+        for bucket in &self.buckets {
+            if casfs.bucket_exists(&bucket.name)? {
+                continue;
+            }
+            casfs.create_bucket(&bucket.name)?;
+            user_router.metrics().inc_bucket_count();
+            if let Some(acl) = bucket.acl {
+                casfs.set_bucket_acl(&bucket.name, acl)?;
+            }
+            if bucket.encryption.is_some() {
+                casfs.set_bucket_encryption(&bucket.name, Some(AES256))?;
+            }
+            let limits = bucket.limits();
+            if !limits.is_empty() {
+                casfs.set_bucket_limits(&bucket.name, limits)?;
+            }
+            created.push(bucket.name.clone());
+        }
+        Ok(created)
+    }
+}
+
+/// Checks the S3 naming rules of buckets: 3 to 63 lowercase letters, digits,
+/// dots and hyphens, starting and ending with a letter or digit
+fn is_valid_bucket_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    let edge = |b: &u8| b.is_ascii_lowercase() || b.is_ascii_digit();
+    (3..=63).contains(&bytes.len())
+        && bytes.iter().all(|b| edge(b) || *b == b'.' || *b == b'-')
+        && bytes.first().is_some_and(edge)
+        && bytes.last().is_some_and(edge)
+        && !name.contains("..")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use cas_storage::{SharedBlockStore, StorageEngine};
+
+    use crate::auth::UserRecord;
+    use crate::metrics::SharedMetrics;
+
+    #[test]
+    fn test_validate() {
+        let template: UserTemplate = toml::from_str(
+            r#"
+            quota_bytes = 1000
+
+            [[bucket]]
+            name = "backups"
+            encryption = "AES256"
+            max_bytes = 500
+
+            [[bucket]]
+            name = "website"
+            acl = "public-read"
+            "#,
+        )
+        .unwrap();
+        template.validate().unwrap();
+        assert!(template.needs_encryption());
+        assert_eq!(template.buckets[1].acl, Some(CannedAcl::PublicRead));
+
+        assert!(
+            toml::from_str::<UserTemplate>("[[bucket]]\nname = \"a\"\nacl = \"public-write\"")
+                .is_err()
+        );
+        assert!(
+            toml::from_str::<UserTemplate>("[[bucket]]\nname = \"a\"\nlifecycle = 30").is_err()
+        );
+        for invalid in [
+            "[[bucket]]\nname = \"Backups\"",
+            "[[bucket]]\nname = \"ab\"",
+            "[[bucket]]\nname = \"-backups\"",
+            "[[bucket]]\nname = \"a..b\"",
+            "[[bucket]]\nname = \"data\"\nencryption = \"aws:kms\"",
+            "[[bucket]]\nname = \"data\"\n[[bucket]]\nname = \"data\"",
+        ] {
+            let template: UserTemplate = toml::from_str(invalid).unwrap();
+            assert!(template.validate().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared_block_store = SharedBlockStore::new(
+            dir.path().join("meta").join("blocks"),
+            StorageEngine::Fjall,
+            None,
+            None,
+        )
+        .unwrap();
+        let user_store = UserStore::new(shared_block_store.meta_store().get_underlying_store());
+        let user_router = UserRouter::new(
+            Arc::new(shared_block_store),
+            dir.path().join("fs"),
+            dir.path().join("meta"),
+            SharedMetrics::noop(),
+            StorageEngine::Fjall,
+            None,
+            None,
+        );
+        let user = UserRecord::new(
+            "alice".to_string(),
+            "alice".to_string(),
+            "password123",
+            "AKIAALICE".to_string(),
+            "secret".to_string(),
+            false,
+        )
+        .unwrap();
+        user_store.create_user(user).unwrap();
+
+        let template: UserTemplate = toml::from_str(
+            r#"
+            quota_bytes = 1000
+
+            [[bucket]]
+            name = "backups"
+            encryption = "AES256"
+            max_objects = 10
+
+            [[bucket]]
+            name = "website"
+            acl = "public-read"
+            "#,
+        )
+        .unwrap();
+        let created = template.apply("alice", &user_router, &user_store).unwrap();
+        assert_eq!(created, ["backups", "website"]);
+        assert_eq!(user_store.get_quota("alice").unwrap(), Some(1000));
+
+        let casfs = user_router.get_casfs_by_user_id("alice").unwrap();
+        assert_eq!(
+            casfs.bucket_encryption("backups").unwrap().as_deref(),
+            Some(AES256)
+        );
+        assert_eq!(
+            casfs.bucket_limits("backups").unwrap().max_objects,
+            Some(10)
+        );
+        assert_eq!(casfs.bucket_acl("website").unwrap(), CannedAcl::PublicRead);
+        assert_eq!(casfs.bucket_encryption("website").unwrap(), None);
+
+        // existing buckets are kept
+        assert!(template
+            .apply("alice", &user_router, &user_store)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,400 @@
+//! Deletion of users together with the data they own.
+//!
+//! A user owns a metadata database with its buckets, whose objects reference
+//! blocks shared with other users. Deleting just the user record would leave the
+//! database and the block references behind, so the owned data is handled by a
+//! [`DeletePolicy`]. Deleting the buckets can take long, it runs as a maintenance
+//! job, after which the user record and database are removed.
+
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use cas_storage::{CasFS, JobRecord, MetaError};
+
+use crate::jobs::{JobError, JobKind, JobManager};
+
+use super::{RouterError, SessionStore, UserRouter, UserStore};
+
+/// What to do with the buckets of a deleted user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletePolicy {
+    /// Refuse to delete a user owning buckets
+    #[default]
+    Deny,
+    /// Delete the buckets with all their objects, releasing their blocks
+    Cascade,
+    /// Export the buckets to the archive directory of the user before deleting them
+    Archive,
+}
+
+impl DeletePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeletePolicy::Deny => "deny",
+            DeletePolicy::Cascade => "cascade",
+            DeletePolicy::Archive => "archive",
+        }
+    }
+}
+
+impl FromStr for DeletePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deny" => Ok(DeletePolicy::Deny),
+            "cascade" => Ok(DeletePolicy::Cascade),
+            "archive" => Ok(DeletePolicy::Archive),
+            _ => Err(format!("Unknown deletion policy: {}", s)),
+        }
+    }
+}
+
+/// Error of a user deletion
+#[derive(Debug)]
+pub enum DeleteError {
+    UnknownUser(String),
+    /// The user owns this amount of buckets, and the policy is to deny
+    HasBuckets(usize),
+    /// The policy needs a job, but this server runs none
+    NoJobs,
+    Router(RouterError),
+    Store(MetaError),
+    Job(JobError),
+}
+
+impl std::fmt::Display for DeleteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeleteError::UnknownUser(user_id) => write!(f, "User '{}' not found", user_id),
+            DeleteError::HasBuckets(count) => write!(
+                f,
+                "The user owns {} buckets, delete them or choose the cascade or archive policy",
+                count
+            ),
+            DeleteError::NoJobs => write!(f, "This server runs no jobs to delete the buckets"),
+            DeleteError::Router(e) => write!(f, "{}", e),
+            DeleteError::Store(e) => write!(f, "Failed to access the user data: {}", e),
+            DeleteError::Job(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DeleteError {}
+
+impl From<RouterError> for DeleteError {
+    fn from(e: RouterError) -> Self {
+        DeleteError::Router(e)
+    }
+}
+
+impl From<MetaError> for DeleteError {
+    fn from(e: MetaError) -> Self {
+        DeleteError::Store(e)
+    }
+}
+
+impl From<JobError> for DeleteError {
+    fn from(e: JobError) -> Self {
+        DeleteError::Job(e)
+    }
+}
+
+/// Outcome of a user deletion
+#[derive(Debug)]
+pub enum Deletion {
+    /// The user owned no buckets and is deleted
+    Done,
+    /// The buckets are deleted by this job, the user once it completes
+    Started(JobRecord),
+}
+
+/// Deletes users and the data they own
+pub struct UserDeleter<'a> {
+    pub user_router: &'a Arc<UserRouter>,
+    pub user_store: &'a Arc<UserStore>,
+    pub session_store: &'a Arc<SessionStore>,
+    pub jobs: Option<&'a Arc<JobManager>>,
+}
+
+impl UserDeleter<'_> {
+    /// Delete the user `user_id`, handling the buckets it owns by `policy`
+    pub fn delete(&self, user_id: &str, policy: DeletePolicy) -> Result<Deletion, DeleteError> {
+        if self.user_store.get_user_by_id(user_id)?.is_none() {
+            return Err(DeleteError::UnknownUser(user_id.to_string()));
+        }
+        let fs = self.user_router.get_casfs_for_maintenance(user_id)?;
+        let buckets = fs.list_buckets()?.len();
+        if buckets == 0 {
+            drop(fs);
+            remove_user(
+                user_id,
+                self.user_router,
+                self.user_store,
+                self.session_store,
+            )?;
+            return Ok(Deletion::Done);
+        }
+        if policy == DeletePolicy::Deny {
+            return Err(DeleteError::HasBuckets(buckets));
+        }
+        let jobs = self.jobs.ok_or(DeleteError::NoJobs)?;
+
+        // the user can't log in to the UI while its data is deleted, S3 access
+        // remains until the user record is removed at the end
+        self.session_store.delete_user_sessions(user_id);
+        let user_id = user_id.to_string();
+        let user_router = self.user_router.clone();
+        let user_store = self.user_store.clone();
+        let session_store = self.session_store.clone();
+        let record = jobs.start(
+            JobKind::UserDelete,
+            Some(user_id.clone()),
+            |job| async move {
+                let archive_dir = user_router.archive_dir(&user_id);
+                let buckets = fs.list_buckets()?;
+                job.set_total(buckets.len() as u64);
+                for bucket in buckets {
+                    job.check_cancelled()?;
+                    let name = bucket.name().to_string();
+                    if policy == DeletePolicy::Archive {
+                        let fs = fs.clone();
+                        let dir = archive_dir.clone();
+                        let name = name.clone();
+                        tokio::task::spawn_blocking(move || archive_bucket(&fs, &name, &dir))
+                            .await?
+                            .with_context(|| format!("Failed to archive bucket {}", name))?;
+                    }
+                    fs.bucket_delete(&name).await?;
+                    job.advance(1);
+                }
+                if !fs.list_buckets()?.is_empty() {
+                    anyhow::bail!("Buckets were created while the user was deleted");
+                }
+                drop(fs);
+                remove_user(&user_id, &user_router, &user_store, &session_store)?;
+                Ok(())
+            },
+        )?;
+        Ok(Deletion::Started(record))
+    }
+}
+
+/// Remove the record, sessions and metadata database of a user without buckets
+pub(super) fn remove_user(
+    user_id: &str,
+    user_router: &UserRouter,
+    user_store: &UserStore,
+    session_store: &SessionStore,
+) -> Result<(), DeleteError> {
+    user_store.delete_user(user_id)?;
+    session_store.delete_user_sessions(user_id);
+    user_router.remove_user(user_id)?;
+    tracing::info!(user_id = %user_id, "User and its data deleted");
+    Ok(())
+}
+
+/// Write the manifest of the objects of `bucket` to `<bucket>.manifest` in `dir`,
+/// with a copy of their block files below `blocks`. The bucket can be restored by
+/// copying the block files to the fs root and importing the manifest.
+fn archive_bucket(fs: &CasFS, bucket: &str, dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Can't create {}", dir.display()))?;
+    let path = dir.join(format!("{}.manifest", bucket));
+    let mut out = BufWriter::new(
+        std::fs::File::create(&path).with_context(|| format!("Can't create {}", path.display()))?,
+    );
+    let blocks_dir = dir.join("blocks");
+
+    let snapshot = fs.get_bucket(bucket)?.snapshot();
+    for (key, obj) in snapshot.range_filter(None, None, None) {
+        let entry = fs
+            .manifest_entry(&key, &obj)
+            .with_context(|| format!("Can't export {}", key))?;
+        for (_, block) in entry.blocks()? {
+            let root = match block.location() {
+                Some(location) => blocks_dir.join(location),
+                None => blocks_dir.clone(),
+            };
+            let copy = block.disk_path(root);
+            // blocks shared by objects are copied once
+            if copy.exists() {
+                continue;
+            }
+            if let Some(parent) = copy.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let source = fs.block_disk_path(&block)?;
+            std::fs::copy(&source, &copy)
+                .with_context(|| format!("Can't copy block file {}", source.display()))?;
+        }
+        serde_json::to_writer(&mut out, &entry)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use cas_storage::{
+        FjallStore, JobStatus, JobStore, MetaStore, SharedBlockStore, StorageEngine,
+    };
+    use rusoto_core::ByteStream;
+
+    use super::*;
+    use crate::auth::UserRecord;
+    use crate::metrics::SharedMetrics;
+
+    pub(crate) struct Setup {
+        pub dir: tempfile::TempDir,
+        pub user_router: Arc<UserRouter>,
+        pub user_store: Arc<UserStore>,
+        pub session_store: Arc<SessionStore>,
+        pub jobs: Arc<JobManager>,
+    }
+
+    impl Setup {
+        pub fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let shared_block_store = SharedBlockStore::new(
+                dir.path().join("meta").join("blocks"),
+                StorageEngine::Fjall,
+                None,
+                None,
+            )
+            .unwrap();
+            let user_router = Arc::new(UserRouter::new(
+                Arc::new(shared_block_store),
+                dir.path().join("fs"),
+                dir.path().join("meta"),
+                SharedMetrics::noop(),
+                StorageEngine::Fjall,
+                None,
+                None,
+            ));
+            let users = FjallStore::new(dir.path().join("users"), None, None);
+            let user_store = Arc::new(UserStore::new(Arc::new(users)));
+            let store = FjallStore::new(dir.path().join("jobs"), Some(1), None);
+            let jobs = JobStore::new(MetaStore::new(store, Some(1)));
+            let jobs = Arc::new(JobManager::new(jobs, || Ok(Vec::new())).unwrap());
+            Self {
+                dir,
+                user_router,
+                user_store,
+                session_store: Arc::new(SessionStore::new()),
+                jobs,
+            }
+        }
+
+        pub fn deleter(&self) -> UserDeleter<'_> {
+            UserDeleter {
+                user_router: &self.user_router,
+                user_store: &self.user_store,
+                session_store: &self.session_store,
+                jobs: Some(&self.jobs),
+            }
+        }
+
+        pub async fn add_user(&self, user_id: &str) -> Arc<CasFS> {
+            let user = UserRecord::new(
+                user_id.to_string(),
+                user_id.to_string(),
+                "password123",
+                format!("KEY{}", user_id.to_uppercase()),
+                "secret".to_string(),
+                false,
+            )
+            .unwrap();
+            self.user_store.create_user(user).unwrap();
+            let fs = self.user_router.get_casfs_by_user_id(user_id).unwrap();
+            fs.create_bucket("bucket").unwrap();
+            let data = b"user data".repeat(100);
+            let len = data.len();
+            let stream =
+                ByteStream::new(futures::stream::once(async move { Ok(Bytes::from(data)) }));
+            fs.store_single_object_and_meta("bucket", "key", stream, len)
+                .await
+                .unwrap();
+            fs
+        }
+
+        pub async fn wait_finished(&self, id: u64) -> JobRecord {
+            loop {
+                let record = self.jobs.get(id).unwrap().unwrap();
+                if record.status.is_finished() {
+                    return record;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        for policy in [
+            DeletePolicy::Deny,
+            DeletePolicy::Cascade,
+            DeletePolicy::Archive,
+        ] {
+            assert_eq!(policy.as_str().parse::<DeletePolicy>(), Ok(policy));
+        }
+        assert!("purge".parse::<DeletePolicy>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delete_policies() {
+        let setup = Setup::new();
+        let deleter = setup.deleter();
+        assert!(matches!(
+            deleter.delete("nobody", DeletePolicy::Cascade),
+            Err(DeleteError::UnknownUser(_))
+        ));
+
+        drop(setup.add_user("alice").await);
+        assert!(matches!(
+            deleter.delete("alice", DeletePolicy::Deny),
+            Err(DeleteError::HasBuckets(1))
+        ));
+        let Deletion::Started(record) = deleter.delete("alice", DeletePolicy::Cascade).unwrap()
+        else {
+            panic!("the buckets are deleted by a job");
+        };
+        let record = setup.wait_finished(record.id).await;
+        assert_eq!(record.status, JobStatus::Completed, "{:?}", record.error);
+        assert!(setup.user_store.get_user_by_id("alice").unwrap().is_none());
+        assert!(!setup.dir.path().join("meta").join("user_alice").exists());
+
+        // the archive holds the manifest and the block files of the objects
+        drop(setup.add_user("bob").await);
+        let Deletion::Started(record) = deleter.delete("bob", DeletePolicy::Archive).unwrap()
+        else {
+            panic!("the buckets are deleted by a job");
+        };
+        let record = setup.wait_finished(record.id).await;
+        assert_eq!(record.status, JobStatus::Completed, "{:?}", record.error);
+        let archive = setup.user_router.archive_dir("bob");
+        let manifest = std::fs::read_to_string(archive.join("bucket.manifest")).unwrap();
+        assert_eq!(manifest.lines().count(), 1);
+        assert!(archive.join("blocks").is_dir());
+        assert!(setup.user_store.get_user_by_id("bob").unwrap().is_none());
+
+        // a user without buckets is deleted right away
+        let fs = setup.add_user("carol").await;
+        fs.bucket_delete("bucket").await.unwrap();
+        drop(fs);
+        assert!(matches!(
+            deleter.delete("carol", DeletePolicy::Deny),
+            Ok(Deletion::Done)
+        ));
+        assert!(setup.user_store.get_user_by_id("carol").unwrap().is_none());
+    }
+}
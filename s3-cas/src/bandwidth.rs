@@ -0,0 +1,532 @@
+//! Bandwidth schedules: limits on the data rate of background work and client
+//! traffic which depend on the time of day, e.g. to cap scrubbing during business
+//! hours and let it run unrestricted at night.
+//!
+//! The schedule is read from a TOML file, times are in the local time zone of the
+//! server. The first window containing the current time applies, outside of all
+//! windows nothing is limited:
+//!
+//! ```toml
+//! [[window]]
+//! days = ["mon", "tue", "wed", "thu", "fri"]
+//! start = "08:00"
+//! end = "18:00"
+//! scrub = "20MiB"
+//! client_read = "500MiB"
+//!
+//! # ends the next morning, starting on the listed days
+//! [[window]]
+//! start = "22:00"
+//! end = "06:00"
+//! client_write = "1GiB"
+//! ```
+//!
+//! The file is checked for changes every few seconds and reloaded, an invalid
+//! file is logged and the previous schedule kept.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Context;
+use chrono::{Datelike, Local, NaiveDateTime, Timelike, Weekday};
+use futures::Stream;
+use serde::{Deserialize, Deserializer};
+use tokio::time::Sleep;
+
+/// How often the schedule file is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Kind of traffic a limit applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficClass {
+    /// Block files read by scrub jobs
+    Scrub,
+    /// Object data sent to S3 clients
+    ClientRead,
+    /// Object data received from S3 clients
+    ClientWrite,
+}
+
+impl TrafficClass {
+    const ALL: [TrafficClass; 3] = [
+        TrafficClass::Scrub,
+        TrafficClass::ClientRead,
+        TrafficClass::ClientWrite,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A data rate in bytes per second, an integer or a string with a unit like
+/// `"20MiB"` or `"500KB"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate(pub u64);
+
+impl Rate {
+    fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| format!("Invalid rate {s:?}, expected e.g. \"20MiB\""))?;
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "kb" => 1000,
+            "kib" | "k" => 1 << 10,
+            "mb" => 1000 * 1000,
+            "mib" | "m" => 1 << 20,
+            "gb" => 1000 * 1000 * 1000,
+            "gib" | "g" => 1 << 30,
+            _ => return Err(format!("Invalid unit of rate {s:?}")),
+        };
+        match number.checked_mul(multiplier) {
+            Some(rate) if rate > 0 => Ok(Rate(rate)),
+            Some(_) => Err("A rate must be above 0".to_string()),
+            None => Err(format!("Rate {s:?} is too large")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(0) => Err(serde::de::Error::custom("A rate must be above 0")),
+            Raw::Bytes(rate) => Ok(Rate(rate)),
+            Raw::Text(s) => Rate::parse(&s).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Minutes since midnight of a `"HH:MM"` time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimeOfDay(u32);
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let invalid = || serde::de::Error::custom(format!("Invalid time {s:?}, expected HH:MM"));
+        let (hours, minutes) = s.split_once(':').ok_or_else(invalid)?;
+        let hours: u32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+        // 24:00 ends a window at midnight
+        if minutes >= 60 || hours > 24 || (hours == 24 && minutes != 0) {
+            return Err(invalid());
+        }
+        Ok(TimeOfDay(hours * 60 + minutes))
+    }
+}
+
+fn deserialize_days<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Weekday>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|day| {
+            day.parse()
+                .map_err(|_| serde::de::Error::custom(format!("Invalid day {day:?}")))
+        })
+        .collect()
+}
+
+/// Limits applying on some days between two times
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Window {
+    /// Days the window starts on, every day if empty
+    #[serde(default, deserialize_with = "deserialize_days")]
+    days: Vec<Weekday>,
+    start: TimeOfDay,
+    /// End of the window, on the next day if it is not after the start
+    end: TimeOfDay,
+    pub scrub: Option<Rate>,
+    pub client_read: Option<Rate>,
+    pub client_write: Option<Rate>,
+}
+
+impl Window {
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn contains(&self, time: NaiveDateTime) -> bool {
+        let minute = time.hour() * 60 + time.minute();
+        let day = time.weekday();
+        let (start, end) = (self.start.0, self.end.0);
+        if start < end {
+            self.starts_on(day) && start <= minute && minute < end
+        } else {
+            // runs past midnight, or all day if the start is the end
+            (minute >= start && self.starts_on(day)) || (minute < end && self.starts_on(day.pred()))
+        }
+    }
+
+    fn limit(&self, class: TrafficClass) -> Option<Rate> {
+        match class {
+            TrafficClass::Scrub => self.scrub,
+            TrafficClass::ClientRead => self.client_read,
+            TrafficClass::ClientWrite => self.client_write,
+        }
+    }
+}
+
+/// Bandwidth schedule, loaded from a TOML file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BandwidthSchedule {
+    #[serde(default, rename = "window")]
+    pub windows: Vec<Window>,
+}
+
+impl BandwidthSchedule {
+    /// Load a schedule from a TOML file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read bandwidth schedule {}", path.display()))?;
+        toml::from_str(&data)
+            .with_context(|| format!("Invalid bandwidth schedule {}", path.display()))
+    }
+
+    /// The limit of `class` at `time`, `None` if it is unlimited
+    pub fn limit(&self, class: TrafficClass, time: NaiveDateTime) -> Option<Rate> {
+        self.windows
+            .iter()
+            .find(|window| window.contains(time))
+            .and_then(|window| window.limit(class))
+    }
+}
+
+/// Throttles traffic to the limits of a [`BandwidthSchedule`]. The limits are
+/// shared by all users of the throttle.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    schedule: RwLock<BandwidthSchedule>,
+    /// Per traffic class, the time the data let through so far is sent at the
+    /// limit
+    next_free: [Mutex<Option<Instant>>; 3],
+}
+
+impl Throttle {
+    pub fn new(schedule: BandwidthSchedule) -> Self {
+        Self {
+            schedule: RwLock::new(schedule),
+            next_free: Default::default(),
+        }
+    }
+
+    /// Replace the schedule, the new limits apply to the following data
+    pub fn set_schedule(&self, schedule: BandwidthSchedule) {
+        *self.schedule.write().unwrap() = schedule;
+    }
+
+    /// The current limit of `class`
+    pub fn limit(&self, class: TrafficClass) -> Option<Rate> {
+        self.schedule
+            .read()
+            .unwrap()
+            .limit(class, Local::now().naive_local())
+    }
+
+    /// Account `bytes` of `class`, returns how long to wait before sending them
+    pub fn reserve(&self, class: TrafficClass, bytes: usize) -> Duration {
+        self.reserve_at(class, bytes, self.limit(class), Instant::now())
+    }
+
+    fn reserve_at(
+        &self,
+        class: TrafficClass,
+        bytes: usize,
+        limit: Option<Rate>,
+        now: Instant,
+    ) -> Duration {
+        let mut next_free = self.next_free[class.index()].lock().unwrap();
+        let Some(Rate(rate)) = limit else {
+            *next_free = None;
+            return Duration::ZERO;
+        };
+        // unused bandwidth of the past is not saved up for bursts
+        let start = next_free.filter(|at| *at > now).unwrap_or(now);
+        *next_free = Some(start + Duration::from_secs_f64(bytes as f64 / rate as f64));
+        start - now
+    }
+
+    /// Wait until `bytes` of `class` may be sent
+    pub async fn consume(&self, class: TrafficClass, bytes: usize) {
+        let wait = self.reserve(class, bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Like [`Throttle::consume`], blocking the thread
+    pub fn consume_blocking(&self, class: TrafficClass, bytes: usize) {
+        let wait = self.reserve(class, bytes);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Load the schedule at `path` and reload it when the file changes
+    pub fn watch(path: PathBuf) -> anyhow::Result<Arc<Self>> {
+        let throttle = Arc::new(Self::new(BandwidthSchedule::load(&path)?));
+        log_limits(&throttle);
+        let watched = throttle.clone();
+        tokio::spawn(async move {
+            let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+            let mut loaded: Option<SystemTime> = modified(&path);
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            loop {
+                interval.tick().await;
+                let current = modified(&path);
+                if current == loaded {
+                    continue;
+                }
+                loaded = current;
+                match BandwidthSchedule::load(&path) {
+                    Ok(schedule) => {
+                        watched.set_schedule(schedule);
+                        tracing::info!(path = %path.display(), "Reloaded bandwidth schedule");
+                        log_limits(&watched);
+                    }
+                    Err(e) => {
+                        tracing::warn!("{e:#}, keeping the previous bandwidth schedule")
+                    }
+                }
+            }
+        });
+        Ok(throttle)
+    }
+}
+
+fn log_limits(throttle: &Throttle) {
+    for class in TrafficClass::ALL {
+        if let Some(Rate(rate)) = throttle.limit(class) {
+            tracing::info!(?class, bytes_per_sec = rate, "Bandwidth currently limited");
+        }
+    }
+}
+
+/// A stream of data chunks which are let through at the limit of `class`
+pub struct Throttled<S: Stream> {
+    inner: Pin<Box<S>>,
+    throttle: Arc<Throttle>,
+    class: TrafficClass,
+    // a chunk waiting for its turn. The mutex is never locked, it only makes the
+    // stream `Sync` like the streams it wraps
+    delayed: Mutex<Option<(Pin<Box<Sleep>>, S::Item)>>,
+}
+
+impl<S: Stream> Throttled<S> {
+    pub fn new(inner: S, throttle: Arc<Throttle>, class: TrafficClass) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            throttle,
+            class,
+            delayed: Mutex::new(None),
+        }
+    }
+}
+
+impl<S, B, E> Stream for Throttled<S>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let delayed = this.delayed.get_mut().unwrap();
+        if let Some((sleep, _)) = delayed {
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            return Poll::Ready(delayed.take().map(|(_, item)| item));
+        }
+
+        let item = match this.inner.as_mut().poll_next(cx) {
+            Poll::Ready(Some(item)) => item,
+            other => return other,
+        };
+        let len = match &item {
+            Ok(chunk) => chunk.as_ref().len(),
+            Err(_) => return Poll::Ready(Some(item)),
+        };
+        let wait = this.throttle.reserve(this.class, len);
+        if wait.is_zero() {
+            return Poll::Ready(Some(item));
+        }
+        let mut sleep = Box::pin(tokio::time::sleep(wait));
+        if sleep.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Some(item));
+        }
+        *delayed = Some((sleep, item));
+        Poll::Pending
+    }
+}
+
+/// Wrap `stream` to be throttled for `class`, if there is a throttle
+pub fn throttled<S, B, E>(
+    stream: S,
+    throttle: Option<&Arc<Throttle>>,
+    class: TrafficClass,
+) -> futures::future::Either<S, Throttled<S>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+{
+    match throttle {
+        Some(throttle) => {
+            futures::future::Either::Right(Throttled::new(stream, throttle.clone(), class))
+        }
+        None => futures::future::Either::Left(stream),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use futures::StreamExt;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // 2024-01-01 is a monday
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn schedule() -> BandwidthSchedule {
+        toml::from_str(
+            r#"
+            [[window]]
+            days = ["mon", "tue", "wed", "thu", "fri"]
+            start = "08:00"
+            end = "18:00"
+            scrub = "20MiB"
+            client_read = 1000
+
+            [[window]]
+            days = ["fri"]
+            start = "22:00"
+            end = "06:00"
+            client_write = "1GB"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_schedule_windows() {
+        let schedule = schedule();
+        let scrub = |time| schedule.limit(TrafficClass::Scrub, time);
+        assert_eq!(scrub(at(1, 8, 0)), Some(Rate(20 << 20)));
+        assert_eq!(scrub(at(5, 17, 59)), Some(Rate(20 << 20)));
+        assert_eq!(scrub(at(1, 18, 0)), None);
+        assert_eq!(scrub(at(1, 7, 59)), None);
+        // saturday
+        assert_eq!(scrub(at(6, 12, 0)), None);
+        assert_eq!(
+            schedule.limit(TrafficClass::ClientRead, at(2, 9, 0)),
+            Some(Rate(1000))
+        );
+        assert_eq!(schedule.limit(TrafficClass::ClientWrite, at(2, 9, 0)), None);
+
+        // the friday night window lasts until saturday morning
+        let write = |time| schedule.limit(TrafficClass::ClientWrite, time);
+        assert_eq!(write(at(5, 23, 0)), Some(Rate(1_000_000_000)));
+        assert_eq!(write(at(6, 5, 59)), Some(Rate(1_000_000_000)));
+        assert_eq!(write(at(6, 6, 0)), None);
+        assert_eq!(write(at(5, 5, 0)), None);
+        assert_eq!(write(at(6, 23, 0)), None);
+    }
+
+    #[test]
+    fn test_invalid_schedule() {
+        let parse = |s: &str| toml::from_str::<BandwidthSchedule>(s);
+        assert!(parse("[[window]]\nstart = \"8:00\"\nend = \"25:00\"").is_err());
+        assert!(
+            parse("[[window]]\nstart = \"08:00\"\nend = \"18:00\"\nscrub = \"10 parsecs\"")
+                .is_err()
+        );
+        assert!(parse("[[window]]\nstart = \"08:00\"\nend = \"18:00\"\nscrub = 0").is_err());
+        assert!(
+            parse("[[window]]\ndays = [\"someday\"]\nstart = \"08:00\"\nend = \"18:00\"").is_err()
+        );
+        assert!(parse("[[window]]\nstart = \"08:00\"\nend = \"18:00\"\nreplication = 1").is_err());
+        assert_eq!(Rate::parse("10 KiB"), Ok(Rate(10 << 10)));
+        assert_eq!(Rate::parse("3m"), Ok(Rate(3 << 20)));
+    }
+
+    #[test]
+    fn test_reserve() {
+        let throttle = Throttle::default();
+        let now = Instant::now();
+        let limit = Some(Rate(1000));
+        assert_eq!(
+            throttle.reserve_at(TrafficClass::Scrub, 500, limit, now),
+            Duration::ZERO
+        );
+        assert_eq!(
+            throttle.reserve_at(TrafficClass::Scrub, 500, limit, now),
+            Duration::from_millis(500)
+        );
+        // other classes have their own budget
+        assert_eq!(
+            throttle.reserve_at(TrafficClass::ClientRead, 500, limit, now),
+            Duration::ZERO
+        );
+        // unused time is not saved up
+        let later = now + Duration::from_secs(10);
+        assert_eq!(
+            throttle.reserve_at(TrafficClass::Scrub, 2000, limit, later),
+            Duration::ZERO
+        );
+        assert_eq!(
+            throttle.reserve_at(TrafficClass::Scrub, 1, limit, later),
+            Duration::from_secs(2)
+        );
+        // lifting the limit lets everything through
+        assert_eq!(
+            throttle.reserve_at(TrafficClass::Scrub, 1, None, later),
+            Duration::ZERO
+        );
+    }
+
+    #[tokio::test]
+    async fn test_throttled_stream() {
+        let chunks: Vec<Result<Vec<u8>, ()>> = vec![Ok(vec![0; 10]); 3];
+        let read = |throttle: &Arc<Throttle>| {
+            Throttled::new(
+                futures::stream::iter(chunks.clone()),
+                throttle.clone(),
+                TrafficClass::ClientRead,
+            )
+            .collect::<Vec<_>>()
+        };
+
+        // without limits the stream is not delayed
+        let throttle = Arc::new(Throttle::default());
+        let started = Instant::now();
+        assert_eq!(read(&throttle).await, chunks);
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        // a window of the whole day, the chunks are sent 100ms apart
+        let schedule = "[[window]]\nstart = \"00:00\"\nend = \"00:00\"\nclient_read = 100";
+        throttle.set_schedule(toml::from_str(schedule).unwrap());
+        let started = Instant::now();
+        assert_eq!(read(&throttle).await, chunks);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,685 @@
+//! Load generator, `s3-cas bench`: writes objects with a distribution of sizes
+//! and a share of duplicate data, reads them back and deletes them, with a given
+//! amount of requests in flight, and reports the throughput and the latency
+//! percentiles of each phase.
+//!
+//! It runs against the S3 API of a server, or directly against a store to
+//! measure the storage without the network and the S3 protocol. The objects of
+//! a run are generated from a seed, so runs with the same seed write the same
+//! data and the results of different hardware can be compared.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use clap::Parser;
+use futures::StreamExt;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use serde::Serialize;
+
+use crate::inspect::{print_json, OutputFormat};
+use crate::manifest::StoreArgs;
+use cas_storage::{BlockStream, CasFS, RangeRequest};
+
+#[derive(Parser, Debug)]
+pub struct BenchConfig {
+    #[command(flatten)]
+    pub store: StoreArgs,
+
+    #[arg(
+        long,
+        help = "S3 endpoint of the server, e.g. http://localhost:8014. The store in --fs-root and --meta-root is used directly if not set"
+    )]
+    pub endpoint: Option<String>,
+
+    #[arg(long, env = "BENCH_ACCESS_KEY", help = "Access key on the server")]
+    pub access_key: Option<String>,
+
+    #[arg(long, env = "BENCH_SECRET_KEY", help = "Secret key on the server")]
+    pub secret_key: Option<String>,
+
+    #[arg(long, default_value = "us-east-1", help = "Region of the server")]
+    pub region: String,
+
+    #[arg(
+        long,
+        default_value = "s3-cas-bench",
+        help = "Bucket the objects are written to, created if it doesn't exist"
+    )]
+    pub bucket: String,
+
+    #[arg(long, default_value_t = 1000, help = "Amount of objects written")]
+    pub objects: usize,
+
+    #[arg(
+        long = "size",
+        value_name = "SIZE[-MAX][:WEIGHT]",
+        default_value = "1M",
+        value_parser = parse_size_range,
+        help = "Object size, or range of sizes picked uniformly, in bytes or with a K, M or G suffix. Repeat with weights for a mix, e.g. --size 4K:70 --size 1M-16M:30"
+    )]
+    pub sizes: Vec<SizeRange>,
+
+    #[arg(
+        long,
+        default_value_t = 0.0,
+        value_parser = parse_ratio,
+        help = "Share of the objects, between 0 and 1, repeating the data of an earlier object"
+    )]
+    pub dedup_ratio: f64,
+
+    #[arg(long, default_value_t = 16, help = "Requests in flight")]
+    pub concurrency: usize,
+
+    #[arg(
+        long,
+        help = "Seed of the generated objects, random if not set. Runs with the same seed write the same objects"
+    )]
+    pub seed: Option<u64>,
+
+    #[arg(long, help = "Don't read the objects back")]
+    pub skip_read: bool,
+
+    #[arg(long, help = "Keep the objects instead of deleting them at the end")]
+    pub keep: bool,
+
+    #[arg(long, default_value = "text", help = "Output format (text, json)")]
+    pub format: OutputFormat,
+}
+
+/// Sizes between `min` and `max`, picked with a `weight` relative to the other
+/// ranges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeRange {
+    pub min: usize,
+    pub max: usize,
+    pub weight: u64,
+}
+
+fn parse_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(at) => value.split_at(at),
+        None => (value, ""),
+    };
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        _ => return Err(format!("invalid size unit in {value}")),
+    };
+    let number: usize = number
+        .parse()
+        .map_err(|_| format!("invalid size {value}"))?;
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size {value} is too large"))
+}
+
+fn parse_size_range(value: &str) -> Result<SizeRange, String> {
+    let (sizes, weight) = match value.split_once(':') {
+        Some((sizes, weight)) => {
+            let weight = weight
+                .parse()
+                .map_err(|_| format!("invalid weight {weight}"))?;
+            (sizes, weight)
+        }
+        None => (value, 1),
+    };
+    if weight == 0 {
+        return Err("the weight must be at least 1".to_string());
+    }
+    let (min, max) = match sizes.split_once('-') {
+        Some((min, max)) => (parse_size(min)?, parse_size(max)?),
+        None => {
+            let size = parse_size(sizes)?;
+            (size, size)
+        }
+    };
+    if min > max {
+        return Err(format!("{min} is larger than {max}"));
+    }
+    Ok(SizeRange { min, max, weight })
+}
+
+fn parse_ratio(value: &str) -> Result<f64, String> {
+    let ratio: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if !(0.0..=1.0).contains(&ratio) {
+        return Err("must be between 0 and 1".to_string());
+    }
+    Ok(ratio)
+}
+
+/// An object written by the benchmark
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectSpec {
+    pub size: usize,
+    /// Seed of the data, a duplicate has the seed of the object it repeats
+    pub data_seed: u64,
+}
+
+impl ObjectSpec {
+    fn data(&self) -> Vec<u8> {
+        let mut data = vec![0; self.size];
+        StdRng::seed_from_u64(self.data_seed).fill_bytes(&mut data);
+        data
+    }
+}
+
+/// The objects of a run: each one is a duplicate of an earlier object with a
+/// probability of `dedup_ratio`, otherwise it has new data and a size from
+/// `sizes`.
+pub fn plan_objects(
+    sizes: &[SizeRange],
+    objects: usize,
+    dedup_ratio: f64,
+    seed: u64,
+) -> Vec<ObjectSpec> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let total_weight: u64 = sizes.iter().map(|range| range.weight).sum();
+    let mut specs: Vec<ObjectSpec> = Vec::with_capacity(objects);
+    for _ in 0..objects {
+        if !specs.is_empty() && rng.gen_bool(dedup_ratio) {
+            let original = specs[rng.gen_range(0..specs.len())];
+            specs.push(original);
+            continue;
+        }
+        let mut pick = rng.gen_range(0..total_weight);
+        let range = sizes
+            .iter()
+            .find(|range| {
+                if pick < range.weight {
+                    return true;
+                }
+                pick -= range.weight;
+                false
+            })
+            .expect("the weights add up to the total");
+        specs.push(ObjectSpec {
+            size: rng.gen_range(range.min..=range.max),
+            data_seed: rng.gen(),
+        });
+    }
+    specs
+}
+
+fn object_key(index: usize) -> String {
+    format!("bench/{index:08}")
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / 1e6
+}
+
+/// Latencies of the requests of a phase, in milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Latencies {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Latencies {
+    /// Nearest-rank percentiles of `samples`
+    pub fn from_samples(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort();
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * samples.len() as f64).ceil() as usize;
+            millis(samples[rank.clamp(1, samples.len()) - 1])
+        };
+        let total: Duration = samples.iter().sum();
+        Self {
+            mean: millis(total) / samples.len() as f64,
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+            max: millis(samples[samples.len() - 1]),
+        }
+    }
+}
+
+/// Results of a phase of the benchmark
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseReport {
+    pub phase: &'static str,
+    /// Successful requests
+    pub ops: usize,
+    pub errors: usize,
+    /// Object data sent or received by the successful requests
+    pub bytes: u64,
+    pub seconds: f64,
+    pub ops_per_second: f64,
+    pub mib_per_second: f64,
+    pub latency_ms: Latencies,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    /// `server` or `store`
+    pub target: &'static str,
+    pub seed: u64,
+    pub objects: usize,
+    /// Objects repeating the data of an earlier one
+    pub duplicates: usize,
+    pub concurrency: usize,
+    pub phases: Vec<PhaseReport>,
+}
+
+/// Where the requests go
+#[derive(Clone)]
+enum Target {
+    Server(aws_sdk_s3::Client),
+    Store(Arc<CasFS>),
+}
+
+impl Target {
+    fn name(&self) -> &'static str {
+        match self {
+            Target::Server(_) => "server",
+            Target::Store(_) => "store",
+        }
+    }
+
+    async fn create_bucket(&self, bucket: &str) -> Result<()> {
+        match self {
+            Target::Server(client) => match client.create_bucket().bucket(bucket).send().await {
+                Ok(_) => Ok(()),
+                Err(e)
+                    if e.as_service_error().is_some_and(|e| {
+                        e.is_bucket_already_owned_by_you() || e.is_bucket_already_exists()
+                    }) =>
+                {
+                    Ok(())
+                }
+                Err(e) => Err(e).with_context(|| format!("Can't create bucket {bucket}")),
+            },
+            Target::Store(casfs) => {
+                if !casfs.bucket_exists(bucket)? {
+                    casfs.create_bucket(bucket)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn put(&self, bucket: &str, key: &str, data: Vec<u8>) -> Result<()> {
+        match self {
+            Target::Server(client) => {
+                client
+                    .put_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .body(aws_sdk_s3::primitives::ByteStream::from(data))
+                    .send()
+                    .await?;
+            }
+            Target::Store(casfs) => {
+                let len = data.len();
+                let data = rusoto_core::ByteStream::from(data);
+                casfs
+                    .store_single_object_and_meta(bucket, key, data, len)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read an object, returning its size
+    async fn get(&self, bucket: &str, key: &str) -> Result<u64> {
+        let mut size = 0;
+        match self {
+            Target::Server(client) => {
+                let mut body = client
+                    .get_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .send()
+                    .await?
+                    .body;
+                while let Some(chunk) = body.try_next().await? {
+                    size += chunk.len() as u64;
+                }
+            }
+            Target::Store(casfs) => {
+                let Some((obj, paths)) = casfs.get_object_paths(bucket, key)? else {
+                    bail!("{key} not found");
+                };
+                if let Some(data) = obj.inlined() {
+                    return Ok(data.len() as u64);
+                }
+                let len = paths.iter().map(|(_, size)| size).sum();
+                let metrics = cas_storage::SharedMetrics::default();
+                let mut blocks = BlockStream::new(paths, len, RangeRequest::All, metrics);
+                while let Some(chunk) = blocks.next().await {
+                    size += chunk?.len() as u64;
+                }
+            }
+        }
+        Ok(size)
+    }
+
+    async fn delete(&self, bucket: &str, key: &str) -> Result<()> {
+        match self {
+            Target::Server(client) => {
+                client
+                    .delete_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .send()
+                    .await?;
+            }
+            Target::Store(casfs) => casfs.delete_object(bucket, key).await?,
+        }
+        Ok(())
+    }
+}
+
+/// Run `op` for every object with `concurrency` requests in flight. `op`
+/// returns the time its request took and the bytes it transferred, so the
+/// preparation of the request isn't measured.
+async fn run_phase<F, Fut>(
+    phase: &'static str,
+    objects: usize,
+    concurrency: usize,
+    op: F,
+) -> Result<PhaseReport>
+where
+    F: Fn(usize) -> Fut,
+    Fut: Future<Output = Result<(Duration, u64)>> + Send + 'static,
+{
+    let started = Instant::now();
+    let mut results = futures::stream::iter(0..objects)
+        .map(|index| tokio::spawn(op(index)))
+        .buffer_unordered(concurrency);
+
+    let (mut latencies, mut bytes, mut errors) = (Vec::with_capacity(objects), 0, 0);
+    while let Some(result) = results.next().await {
+        match result? {
+            Ok((latency, size)) => {
+                latencies.push(latency);
+                bytes += size;
+            }
+            Err(e) => {
+                // the first error tells what's wrong, the others are counted
+                if errors == 0 {
+                    eprintln!("{phase} failed: {e:#}");
+                }
+                errors += 1;
+            }
+        }
+    }
+
+    let seconds = started.elapsed().as_secs_f64();
+    let ops = latencies.len();
+    Ok(PhaseReport {
+        phase,
+        ops,
+        errors,
+        bytes,
+        seconds,
+        ops_per_second: ops as f64 / seconds,
+        mib_per_second: bytes as f64 / (1 << 20) as f64 / seconds,
+        latency_ms: Latencies::from_samples(latencies),
+    })
+}
+
+async fn run_bench(
+    target: Target,
+    bucket: &str,
+    specs: Vec<ObjectSpec>,
+    concurrency: usize,
+    read: bool,
+    delete: bool,
+) -> Result<Vec<PhaseReport>> {
+    target.create_bucket(bucket).await?;
+    let specs = Arc::new(specs);
+    let bucket: Arc<str> = bucket.into();
+    let mut phases = Vec::new();
+
+    let put = run_phase("put", specs.len(), concurrency, |index| {
+        let (target, bucket, spec) = (target.clone(), bucket.clone(), specs[index]);
+        async move {
+            let data = spec.data();
+            let started = Instant::now();
+            target.put(&bucket, &object_key(index), data).await?;
+            Ok((started.elapsed(), spec.size as u64))
+        }
+    });
+    phases.push(put.await?);
+
+    if read {
+        let get = run_phase("get", specs.len(), concurrency, |index| {
+            let (target, bucket, spec) = (target.clone(), bucket.clone(), specs[index]);
+            async move {
+                let started = Instant::now();
+                let size = target.get(&bucket, &object_key(index)).await?;
+                if size != spec.size as u64 {
+                    bail!("{} has {size} bytes, not {}", object_key(index), spec.size);
+                }
+                Ok((started.elapsed(), size))
+            }
+        });
+        phases.push(get.await?);
+    }
+
+    if delete {
+        let delete = run_phase("delete", specs.len(), concurrency, |index| {
+            let (target, bucket) = (target.clone(), bucket.clone());
+            async move {
+                let started = Instant::now();
+                target.delete(&bucket, &object_key(index)).await?;
+                Ok((started.elapsed(), 0))
+            }
+        });
+        phases.push(delete.await?);
+    }
+    Ok(phases)
+}
+
+fn server_client(endpoint: &str, args: &BenchConfig) -> Result<aws_sdk_s3::Client> {
+    let (Some(access_key), Some(secret_key)) = (&args.access_key, &args.secret_key) else {
+        bail!("--access-key and --secret-key are required with --endpoint");
+    };
+    let credentials = Credentials::new(access_key, secret_key, None, None, "bench");
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .credentials_provider(credentials)
+        .region(Region::new(args.region.clone()))
+        .endpoint_url(endpoint)
+        .force_path_style(true)
+        .build();
+    Ok(aws_sdk_s3::Client::from_conf(config))
+}
+
+fn print_report(report: &BenchReport) {
+    println!(
+        "{} objects ({} duplicates) on the {}, {} in flight, seed {}",
+        report.objects, report.duplicates, report.target, report.concurrency, report.seed
+    );
+    println!(
+        "{:<7} {:>8} {:>7} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "phase",
+        "ops",
+        "errors",
+        "ops/s",
+        "MiB/s",
+        "mean ms",
+        "p50 ms",
+        "p90 ms",
+        "p99 ms",
+        "max ms"
+    );
+    for phase in &report.phases {
+        let latency = &phase.latency_ms;
+        println!(
+            "{:<7} {:>8} {:>7} {:>9.1} {:>9.1} {:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>9.2}",
+            phase.phase,
+            phase.ops,
+            phase.errors,
+            phase.ops_per_second,
+            phase.mib_per_second,
+            latency.mean,
+            latency.p50,
+            latency.p90,
+            latency.p99,
+            latency.max
+        );
+    }
+}
+
+/// Run the benchmark against a server or a store and print the results
+#[tokio::main]
+pub async fn bench(args: BenchConfig) -> Result<()> {
+    if args.objects == 0 || args.concurrency == 0 {
+        bail!("--objects and --concurrency must be at least 1");
+    }
+    let target = match &args.endpoint {
+        Some(endpoint) => Target::Server(server_client(endpoint, &args)?),
+        None => Target::Store(Arc::new(args.store.open()?)),
+    };
+
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let specs = plan_objects(&args.sizes, args.objects, args.dedup_ratio, seed);
+    let originals: HashSet<_> = specs.iter().map(|spec| spec.data_seed).collect();
+    let mut report = BenchReport {
+        target: target.name(),
+        seed,
+        objects: specs.len(),
+        duplicates: specs.len() - originals.len(),
+        concurrency: args.concurrency,
+        phases: Vec::new(),
+    };
+
+    report.phases = run_bench(
+        target,
+        &args.bucket,
+        specs,
+        args.concurrency,
+        !args.skip_read,
+        !args.keep,
+    )
+    .await?;
+
+    match args.format {
+        OutputFormat::Text => print_report(&report),
+        OutputFormat::Json => print_json(&report)?,
+    }
+    let errors: usize = report.phases.iter().map(|phase| phase.errors).sum();
+    if errors > 0 {
+        bail!("{errors} requests failed");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cas_storage::{CasFSBuilder, Durability};
+
+    #[test]
+    fn test_parse_size_range() {
+        let range = |min, max, weight| SizeRange { min, max, weight };
+        assert_eq!(parse_size_range("4096"), Ok(range(4096, 4096, 1)));
+        assert_eq!(parse_size_range("4K:70"), Ok(range(4096, 4096, 70)));
+        assert_eq!(
+            parse_size_range("1MiB-16M:30"),
+            Ok(range(1 << 20, 16 << 20, 30))
+        );
+        assert!(parse_size_range("16M-1M").is_err());
+        assert!(parse_size_range("1M:0").is_err());
+        assert!(parse_size_range("1T").is_err());
+        assert!(parse_size_range("M").is_err());
+    }
+
+    #[test]
+    fn test_plan_objects() {
+        let sizes = [
+            SizeRange {
+                min: 10,
+                max: 10,
+                weight: 3,
+            },
+            SizeRange {
+                min: 100,
+                max: 200,
+                weight: 1,
+            },
+        ];
+        let specs = plan_objects(&sizes, 1000, 0.25, 7);
+        assert_eq!(specs, plan_objects(&sizes, 1000, 0.25, 7));
+        assert!(specs
+            .iter()
+            .all(|spec| spec.size == 10 || (100..=200).contains(&spec.size)));
+        let small = specs.iter().filter(|spec| spec.size == 10).count();
+        assert!((650..850).contains(&small), "{small} small objects");
+
+        let unique: HashSet<_> = specs.iter().map(|spec| spec.data_seed).collect();
+        let duplicates = specs.len() - unique.len();
+        assert!((200..300).contains(&duplicates), "{duplicates} duplicates");
+        assert_eq!(specs[0].data(), specs[0].data());
+
+        let unique = plan_objects(&sizes, 100, 0.0, 7);
+        let seeds: HashSet<_> = unique.iter().map(|spec| spec.data_seed).collect();
+        assert_eq!(seeds.len(), 100);
+    }
+
+    #[test]
+    fn test_latencies() {
+        let samples = (1..=100).rev().map(Duration::from_millis).collect();
+        let latencies = Latencies::from_samples(samples);
+        assert_eq!(
+            latencies,
+            Latencies {
+                mean: 50.5,
+                p50: 50.0,
+                p90: 90.0,
+                p99: 99.0,
+                max: 100.0,
+            }
+        );
+        assert_eq!(Latencies::from_samples(Vec::new()), Latencies::default());
+    }
+
+    #[tokio::test]
+    async fn test_bench_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let casfs = CasFSBuilder::new(dir.path(), dir.path().join("meta"))
+            .durability(Durability::Buffer)
+            .build()
+            .unwrap();
+        let casfs = Arc::new(casfs);
+        let sizes = [SizeRange {
+            min: 1,
+            max: 3 << 20,
+            weight: 1,
+        }];
+        let specs = plan_objects(&sizes, 20, 0.5, 1);
+        let bytes: u64 = specs.iter().map(|spec| spec.size as u64).sum();
+
+        let target = Target::Store(casfs.clone());
+        let phases = run_bench(target, "bench", specs, 4, true, true)
+            .await
+            .unwrap();
+        let names: Vec<_> = phases.iter().map(|phase| phase.phase).collect();
+        assert_eq!(names, ["put", "get", "delete"]);
+        for phase in &phases {
+            assert_eq!((phase.ops, phase.errors), (20, 0), "{}", phase.phase);
+        }
+        assert_eq!(phases[0].bytes, bytes);
+        assert_eq!(phases[1].bytes, bytes);
+        assert!(casfs
+            .get_object_meta("bench", &object_key(0))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,556 @@
+//! Server access logging of buckets, configured with `PutBucketLogging`.
+//!
+//! The requests to a bucket with logging enabled are recorded in the format of
+//! S3 server access logs, so the tools reading those can read them unchanged.
+//! Records are buffered per target bucket and prefix, and written to the target
+//! bucket as a new object every flush interval, or as soon as the buffer grows
+//! beyond [`MAX_BUFFERED_BYTES`]. Like S3, delivery is best effort: the records
+//! buffered when the server crashes are lost.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use rusoto_core::ByteStream;
+use s3s::S3Request;
+use tracing::{debug, warn};
+
+use cas_storage::CasFS;
+
+use crate::network::RemoteAddr;
+use crate::s3_wrapper::SigV2;
+
+/// Default seconds between deliveries of the buffered records
+pub const DEFAULT_BUCKET_LOG_INTERVAL_SECS: u64 = 300;
+
+/// Size of the buffered records of a target which are delivered right away
+pub const MAX_BUFFERED_BYTES: usize = 1024 * 1024;
+
+/// Finds the owner and the store of the buckets of a request by the access key
+/// it is signed with, `None` for anonymous requests.
+pub type StoreResolver = dyn Fn(Option<&str>) -> Option<(String, Arc<CasFS>)> + Send + Sync;
+
+/// What is recorded of a request before it is handled
+#[derive(Debug, Clone)]
+pub struct LoggedRequest {
+    pub time: DateTime<Utc>,
+    pub method: String,
+    /// Path and query of the request
+    pub uri: String,
+    pub requester: Option<String>,
+    pub remote_ip: Option<IpAddr>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub host: Option<String>,
+    /// Size of the request body, if any
+    pub content_length: Option<u64>,
+    pub sig_v2: bool,
+    /// The request is signed in the query of a presigned URL
+    pub query_auth: bool,
+}
+
+impl LoggedRequest {
+    pub fn new<T>(req: &S3Request<T>) -> Self {
+        let header = |name: hyper::header::HeaderName| {
+            req.headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        let query_auth = req.uri.query().map_or(false, |query| {
+            query
+                .split('&')
+                .any(|pair| pair.starts_with("X-Amz-Signature=") || pair.starts_with("Signature="))
+        });
+        Self {
+            time: Utc::now(),
+            method: req.method.to_string(),
+            uri: req
+                .uri
+                .path_and_query()
+                .map(|p| p.to_string())
+                .unwrap_or_else(|| req.uri.path().to_string()),
+            requester: req.credentials.as_ref().map(|c| c.access_key.clone()),
+            remote_ip: req.extensions.get::<RemoteAddr>().map(|addr| addr.0.ip()),
+            referer: header(hyper::header::REFERER),
+            user_agent: header(hyper::header::USER_AGENT),
+            host: header(hyper::header::HOST),
+            content_length: header(hyper::header::CONTENT_LENGTH).and_then(|v| v.parse().ok()),
+            sig_v2: req.extensions.get::<SigV2>().is_some(),
+            query_auth,
+        }
+    }
+}
+
+/// A record of the server access log of a bucket
+#[derive(Debug, Clone)]
+pub struct BucketLogRecord {
+    pub owner: String,
+    pub bucket: String,
+    pub key: Option<String>,
+    /// Operation in the S3 notation, e.g. `REST.GET.OBJECT`
+    pub operation: String,
+    pub status: u16,
+    pub error_code: Option<String>,
+    pub bytes_sent: Option<u64>,
+    pub object_size: Option<u64>,
+    pub total_time: Duration,
+    pub request_id: String,
+    pub request: LoggedRequest,
+}
+
+impl BucketLogRecord {
+    /// Formats the record as a line of an S3 server access log, without the
+    /// trailing newline
+    pub fn to_line(&self) -> String {
+        let request = &self.request;
+        let signature_version = match (&request.requester, request.sig_v2) {
+            (None, _) => "-",
+            (Some(_), true) => "SigV2",
+            (Some(_), false) => "SigV4",
+        };
+        let auth_type = match (&request.requester, request.query_auth) {
+            (None, _) => "-",
+            (Some(_), true) => "QueryString",
+            (Some(_), false) => "AuthHeader",
+        };
+        let fields = [
+            self.owner.clone(),
+            self.bucket.clone(),
+            format!("[{}]", request.time.format("%d/%b/%Y:%H:%M:%S %z")),
+            or_dash(request.remote_ip.map(|ip| ip.to_string())),
+            or_dash(request.requester.clone()),
+            self.request_id.clone(),
+            self.operation.clone(),
+            or_dash(self.key.as_deref().map(encode_key)),
+            format!("\"{} {} HTTP/1.1\"", request.method, request.uri),
+            self.status.to_string(),
+            or_dash(self.error_code.clone()),
+            or_dash(self.bytes_sent.filter(|b| *b > 0).map(|b| b.to_string())),
+            or_dash(self.object_size.map(|s| s.to_string())),
+            self.total_time.as_millis().to_string(),
+            // turn-around time
+            "-".to_string(),
+            quoted(request.referer.as_deref()),
+            quoted(request.user_agent.as_deref()),
+            // version id and host id
+            "-".to_string(),
+            "-".to_string(),
+            signature_version.to_string(),
+            // cipher suite, TLS is terminated before the S3 layer
+            "-".to_string(),
+            auth_type.to_string(),
+            or_dash(request.host.clone()),
+            // TLS version
+            "-".to_string(),
+        ];
+        fields.join(" ")
+    }
+}
+
+fn or_dash(value: Option<String>) -> String {
+    value.unwrap_or_else(|| "-".to_string())
+}
+
+fn quoted(value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("\"{}\"", value.replace('"', "\\\"")),
+        None => "\"-\"".to_string(),
+    }
+}
+
+/// URL encodes a key like S3 does in its access logs, keeping the slashes
+fn encode_key(key: &str) -> String {
+    urlencoding::encode(key).replace("%2F", "/")
+}
+
+/// Splits the path of a path style request in its bucket and key
+fn bucket_and_key(path: &str) -> Option<(String, Option<String>)> {
+    let path = urlencoding::decode(path).ok()?;
+    let path = path.strip_prefix('/').unwrap_or(&path);
+    let (bucket, key) = match path.split_once('/') {
+        Some((bucket, key)) => (bucket, Some(key).filter(|k| !k.is_empty())),
+        None => (path, None),
+    };
+    if bucket.is_empty() {
+        return None;
+    }
+    Some((bucket.to_string(), key.map(|k| k.to_string())))
+}
+
+/// Maps an operation name, e.g. `get_object`, to the notation of S3 access logs
+fn rest_operation(operation: &str, method: &str) -> String {
+    let name = match operation {
+        "get_object" => "GET.OBJECT",
+        "head_object" => "HEAD.OBJECT",
+        "put_object" => "PUT.OBJECT",
+        "delete_object" => "DELETE.OBJECT",
+        "copy_object" => "COPY.OBJECT",
+        "delete_objects" => "POST.MULTI_OBJECT_DELETE",
+        "list_objects" | "list_objects_v2" => "GET.BUCKET",
+        "head_bucket" => "HEAD.BUCKET",
+        "create_bucket" => "PUT.BUCKET",
+        "delete_bucket" => "DELETE.BUCKET",
+        "create_multipart_upload" => "POST.UPLOADS",
+        "upload_part" => "PUT.PART",
+        "upload_part_copy" => "COPY.PART",
+        "complete_multipart_upload" => "POST.UPLOAD",
+        "abort_multipart_upload" => "DELETE.UPLOAD",
+        "list_parts" => "GET.UPLOAD",
+        "list_multipart_uploads" => "GET.UPLOADS",
+        _ => {
+            // e.g. get_bucket_logging becomes REST.GET.BUCKET_LOGGING
+            let name = operation
+                .split_once('_')
+                .map_or(operation, |(_, name)| name);
+            return format!("REST.{}.{}", method, name.to_uppercase());
+        }
+    };
+    format!("REST.{name}")
+}
+
+fn random_id() -> String {
+    hex::encode_upper(rand::thread_rng().gen::<[u8; 8]>())
+}
+
+/// Records buffered for a target bucket and prefix
+struct Buffer {
+    casfs: Arc<CasFS>,
+    records: String,
+}
+
+/// Buffers the access log records of the buckets with logging enabled and
+/// delivers them to their target buckets
+pub struct BucketLogs {
+    stores: Box<StoreResolver>,
+    /// Keyed by owner, target bucket and target prefix
+    buffers: Mutex<HashMap<(String, String, String), Buffer>>,
+}
+
+impl BucketLogs {
+    pub fn new<F>(stores: F) -> Self
+    where
+        F: Fn(Option<&str>) -> Option<(String, Arc<CasFS>)> + Send + Sync + 'static,
+    {
+        Self {
+            stores: Box::new(stores),
+            buffers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a handled request if logging is enabled on its bucket
+    pub fn log(
+        &self,
+        request: LoggedRequest,
+        operation: &str,
+        status: u16,
+        error_code: Option<&str>,
+        bytes_sent: Option<u64>,
+        total_time: Duration,
+    ) {
+        let path = request.uri.split('?').next().unwrap_or("");
+        let Some((bucket, key)) = bucket_and_key(path) else {
+            return;
+        };
+        let Some((owner, casfs)) = (self.stores)(request.requester.as_deref()) else {
+            return;
+        };
+        let target = match casfs.bucket_logging(&bucket) {
+            Ok(Some(target)) => target,
+            Ok(None) => return,
+            Err(e) => {
+                warn!(
+                    "Failed to read the logging configuration of bucket {}: {}",
+                    bucket, e
+                );
+                return;
+            }
+        };
+
+        let record = BucketLogRecord {
+            owner: owner.clone(),
+            bucket,
+            key,
+            operation: rest_operation(operation, &request.method),
+            status,
+            error_code: error_code.map(|c| c.to_string()),
+            bytes_sent,
+            object_size: request.content_length.filter(|l| *l > 0).or(bytes_sent),
+            total_time,
+            request_id: random_id(),
+            request,
+        };
+        let mut line = record.to_line();
+        line.push('\n');
+
+        let full = {
+            let mut buffers = self
+                .buffers
+                .lock()
+                .expect("bucket log lock is not poisoned");
+            let target_key = (owner, target.target_bucket, target.target_prefix);
+            let buffer = buffers.entry(target_key.clone()).or_insert_with(|| Buffer {
+                casfs,
+                records: String::new(),
+            });
+            buffer.records.push_str(&line);
+            if buffer.records.len() >= MAX_BUFFERED_BYTES {
+                buffers
+                    .remove(&target_key)
+                    .map(|buffer| (target_key, buffer))
+            } else {
+                None
+            }
+        };
+        if let Some(((_, target_bucket, target_prefix), buffer)) = full {
+            tokio::spawn(async move {
+                deliver(
+                    &buffer.casfs,
+                    &target_bucket,
+                    &target_prefix,
+                    buffer.records,
+                )
+                .await;
+            });
+        }
+    }
+
+    /// Delivers all buffered records, returns the amount of log objects written
+    pub async fn flush(&self) -> usize {
+        let buffers = std::mem::take(
+            &mut *self
+                .buffers
+                .lock()
+                .expect("bucket log lock is not poisoned"),
+        );
+        let mut written = 0;
+        for ((_, target_bucket, target_prefix), buffer) in buffers {
+            if deliver(
+                &buffer.casfs,
+                &target_bucket,
+                &target_prefix,
+                buffer.records,
+            )
+            .await
+            {
+                written += 1;
+            }
+        }
+        written
+    }
+
+    /// Delivers the buffered records every `period`
+    pub async fn run(self: Arc<Self>, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        // the first tick completes immediately
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let written = self.flush().await;
+            if written > 0 {
+                debug!("Delivered {} bucket log objects", written);
+            }
+        }
+    }
+}
+
+/// Writes `records` as a new object in `target_bucket`, returns whether it was written
+async fn deliver(casfs: &CasFS, target_bucket: &str, target_prefix: &str, records: String) -> bool {
+    let key = format!(
+        "{}{}-{}",
+        target_prefix,
+        Utc::now().format("%Y-%m-%d-%H-%M-%S"),
+        random_id()
+    );
+    let len = records.len();
+    match casfs
+        .store_single_object_and_meta(
+            target_bucket,
+            &key,
+            ByteStream::from(records.into_bytes()),
+            len,
+        )
+        .await
+    {
+        Ok(_) => true,
+        Err(e) => {
+            // e.g. the target bucket was deleted after logging was enabled
+            warn!(
+                "Failed to deliver bucket logs to {}/{}: {}",
+                target_bucket, key, e
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cas_storage::{BucketLogging, CasFSBuilder, Durability};
+
+    fn request(uri: &str) -> LoggedRequest {
+        LoggedRequest {
+            time: DateTime::parse_from_rfc3339("2024-02-06T00:00:38Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            method: "GET".to_string(),
+            uri: uri.to_string(),
+            requester: Some("AKIDEXAMPLE".to_string()),
+            remote_ip: Some("192.0.2.3".parse().unwrap()),
+            referer: None,
+            user_agent: Some("curl/8.0".to_string()),
+            host: Some("s3.example.com".to_string()),
+            content_length: None,
+            sig_v2: false,
+            query_auth: false,
+        }
+    }
+
+    #[test]
+    fn test_record_line() {
+        let record = BucketLogRecord {
+            owner: "s3-cas".to_string(),
+            bucket: "photos".to_string(),
+            key: Some("2024/my puppy.jpg".to_string()),
+            operation: rest_operation("get_object", "GET"),
+            status: 200,
+            error_code: None,
+            bytes_sent: Some(2662992),
+            object_size: Some(2662992),
+            total_time: Duration::from_millis(70),
+            request_id: "3E57427F3EXAMPLE".to_string(),
+            request: request("/photos/2024/my%20puppy.jpg"),
+        };
+        assert_eq!(
+            record.to_line(),
+            "s3-cas photos [06/Feb/2024:00:00:38 +0000] 192.0.2.3 AKIDEXAMPLE \
+             3E57427F3EXAMPLE REST.GET.OBJECT 2024/my%20puppy.jpg \
+             \"GET /photos/2024/my%20puppy.jpg HTTP/1.1\" 200 - 2662992 2662992 70 - \
+             \"-\" \"curl/8.0\" - - SigV4 - AuthHeader s3.example.com -"
+        );
+    }
+
+    #[test]
+    fn test_bucket_and_key() {
+        assert_eq!(bucket_and_key("/"), None);
+        assert_eq!(
+            bucket_and_key("/photos"),
+            Some(("photos".to_string(), None))
+        );
+        assert_eq!(
+            bucket_and_key("/photos/"),
+            Some(("photos".to_string(), None))
+        );
+        assert_eq!(
+            bucket_and_key("/photos/a/b%20c"),
+            Some(("photos".to_string(), Some("a/b c".to_string())))
+        );
+    }
+
+    #[test]
+    fn test_rest_operation() {
+        assert_eq!(rest_operation("put_object", "PUT"), "REST.PUT.OBJECT");
+        assert_eq!(rest_operation("list_objects_v2", "GET"), "REST.GET.BUCKET");
+        assert_eq!(
+            rest_operation("get_bucket_logging", "GET"),
+            "REST.GET.BUCKET_LOGGING"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deliver_to_target_bucket() {
+        let dir = tempfile::tempdir().unwrap();
+        let casfs = Arc::new(
+            CasFSBuilder::new(dir.path(), dir.path().join("meta"))
+                .inlined_metadata_size(1024)
+                .durability(Durability::Buffer)
+                .build()
+                .unwrap(),
+        );
+        casfs.create_bucket("photos").unwrap();
+        casfs.create_bucket("logs").unwrap();
+        casfs
+            .set_bucket_logging(
+                "photos",
+                Some(&BucketLogging {
+                    target_bucket: "logs".to_string(),
+                    target_prefix: "access/".to_string(),
+                }),
+            )
+            .unwrap();
+
+        let store = casfs.clone();
+        let logs = BucketLogs::new(move |_| Some(("s3-cas".to_string(), store.clone())));
+        let duration = Duration::from_millis(5);
+        logs.log(
+            request("/photos/a.jpg"),
+            "get_object",
+            200,
+            None,
+            Some(10),
+            duration,
+        );
+        logs.log(
+            request("/photos/b.jpg"),
+            "get_object",
+            404,
+            Some("NoSuchKey"),
+            None,
+            duration,
+        );
+        // logging is not enabled on the target bucket
+        logs.log(
+            request("/logs/c"),
+            "get_object",
+            200,
+            None,
+            Some(10),
+            duration,
+        );
+        assert_eq!(logs.flush().await, 1);
+        assert_eq!(logs.flush().await, 0);
+
+        let objects: Vec<_> = casfs
+            .get_bucket("logs")
+            .unwrap()
+            .iter_prefix(b"access/")
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(objects.len(), 1);
+        let key = String::from_utf8(objects[0].to_vec()).unwrap();
+        let obj = casfs.get_object_meta("logs", &key).unwrap().unwrap();
+        let first = record_len("/photos/a.jpg", "get_object", 200, None, Some(10));
+        let second = record_len("/photos/b.jpg", "get_object", 404, Some("NoSuchKey"), None);
+        assert_eq!(obj.size(), (first + second) as u64);
+    }
+
+    /// Length of the line logged for a request, the request ids are random but
+    /// of a fixed length
+    fn record_len(
+        uri: &str,
+        operation: &str,
+        status: u16,
+        error_code: Option<&str>,
+        bytes_sent: Option<u64>,
+    ) -> usize {
+        let (bucket, key) = bucket_and_key(uri).unwrap();
+        let record = BucketLogRecord {
+            owner: "s3-cas".to_string(),
+            bucket,
+            key,
+            operation: rest_operation(operation, "GET"),
+            status,
+            error_code: error_code.map(|c| c.to_string()),
+            bytes_sent,
+            object_size: bytes_sent,
+            total_time: Duration::from_millis(5),
+            request_id: random_id(),
+            request: request(uri),
+        };
+        record.to_line().len() + 1
+    }
+}
@@ -0,0 +1,322 @@
+//! Estimate of the deduplication content-defined chunking would add, by chunking a
+//! sample of the stored objects with a rolling hash and comparing the distinct
+//! chunks with the distinct fixed-size blocks the store keeps now.
+
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use crate::manifest::StoreArgs;
+use cas_storage::{BlockID, CasFS, ContentHash};
+
+#[derive(Parser, Debug)]
+pub struct CdcEstimateConfig {
+    #[command(flatten)]
+    pub store: StoreArgs,
+
+    #[arg(
+        long,
+        help = "Bucket to sample, all buckets if not set. Can be repeated"
+    )]
+    pub bucket: Vec<String>,
+
+    #[arg(
+        long,
+        default_value_t = 10.0,
+        help = "Percentage of the objects to sample, picked by key so runs sample the same objects"
+    )]
+    pub sample_percent: f64,
+
+    #[arg(
+        long,
+        default_value_t = 64 * 1024,
+        value_parser = parse_avg_chunk_size,
+        help = "Average chunk size to estimate, a power of 2. Chunks are between a quarter and 4 times this size"
+    )]
+    pub avg_chunk_size: usize,
+}
+
+fn parse_avg_chunk_size(value: &str) -> Result<usize, String> {
+    let size: usize = value.parse().map_err(|e| format!("{e}"))?;
+    if !size.is_power_of_two() || size < 256 {
+        return Err("must be a power of 2 of at least 256".to_string());
+    }
+    Ok(size)
+}
+
+/// Gear table of the rolling hash, 256 pseudo random values
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64
+    let mut table = [0; 256];
+    let mut state: u64 = 0x5eed;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Content-defined chunker with a gear rolling hash, fed with the data of an
+/// object in pieces of any size.
+pub struct Chunker {
+    min_size: usize,
+    max_size: usize,
+    mask: u64,
+    hash: u64,
+    chunk: Vec<u8>,
+}
+
+impl Chunker {
+    /// A chunker cutting chunks of `avg_size` bytes on average, a power of 2.
+    pub fn new(avg_size: usize) -> Self {
+        Self {
+            min_size: avg_size / 4,
+            max_size: avg_size * 4,
+            mask: avg_size as u64 - 1,
+            hash: 0,
+            chunk: Vec::with_capacity(avg_size * 4),
+        }
+    }
+
+    /// Add data, calling `on_chunk` for every chunk it completes.
+    pub fn push(&mut self, data: &[u8], mut on_chunk: impl FnMut(&[u8])) {
+        for &byte in data {
+            self.chunk.push(byte);
+            self.hash = (self.hash << 1).wrapping_add(GEAR[byte as usize]);
+            let len = self.chunk.len();
+            if (len >= self.min_size && self.hash & self.mask == 0) || len >= self.max_size {
+                on_chunk(&self.chunk);
+                self.chunk.clear();
+                self.hash = 0;
+            }
+        }
+    }
+
+    /// End the object, calling `on_chunk` for its last chunk.
+    pub fn finish(&mut self, mut on_chunk: impl FnMut(&[u8])) {
+        if !self.chunk.is_empty() {
+            on_chunk(&self.chunk);
+        }
+        self.chunk.clear();
+        self.hash = 0;
+    }
+}
+
+/// Sizes of the sampled objects of a bucket. Unique bytes are the bytes of the
+/// blocks or chunks not seen before in the sample, of this or an earlier bucket.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CdcEstimate {
+    pub objects: usize,
+    pub bytes: u64,
+    pub fixed_unique_bytes: u64,
+    pub cdc_unique_bytes: u64,
+}
+
+impl CdcEstimate {
+    fn add(&mut self, other: &CdcEstimate) {
+        self.objects += other.objects;
+        self.bytes += other.bytes;
+        self.fixed_unique_bytes += other.fixed_unique_bytes;
+        self.cdc_unique_bytes += other.cdc_unique_bytes;
+    }
+
+    /// Ratio of the stored bytes content-defined chunking would save, negative if
+    /// it would store more.
+    pub fn savings(&self) -> f64 {
+        if self.fixed_unique_bytes == 0 {
+            return 0.0;
+        }
+        1.0 - self.cdc_unique_bytes as f64 / self.fixed_unique_bytes as f64
+    }
+}
+
+/// The distinct blocks and chunks seen in the sample so far
+struct Sampler {
+    content_hash: ContentHash,
+    chunker: Chunker,
+    blocks: HashSet<BlockID>,
+    chunks: HashSet<BlockID>,
+}
+
+impl Sampler {
+    fn sample_object(
+        &mut self,
+        casfs: &CasFS,
+        bucket: &str,
+        key: &str,
+        estimate: &mut CdcEstimate,
+    ) -> Result<()> {
+        let Some((obj, paths)) = casfs.get_object_paths(bucket, key)? else {
+            // deleted since the listing
+            return Ok(());
+        };
+        estimate.objects += 1;
+        estimate.bytes += obj.size();
+
+        let (content_hash, chunks) = (self.content_hash, &mut self.chunks);
+        let mut cdc_unique_bytes = 0;
+        let mut on_chunk = |chunk: &[u8]| {
+            if chunks.insert(content_hash.digest(chunk)) {
+                cdc_unique_bytes += chunk.len() as u64;
+            }
+        };
+
+        if let Some(data) = obj.inlined() {
+            // inlined objects are stored once per key, they have no blocks to share
+            estimate.fixed_unique_bytes += data.len() as u64;
+            self.chunker.push(data, &mut on_chunk);
+        }
+        for (id, (path, size)) in obj.blocks().iter().zip(&paths) {
+            let data = std::fs::read(path).with_context(|| {
+                format!("Can't read block {} of {bucket}/{key}", path.display())
+            })?;
+            if self.blocks.insert(*id) {
+                estimate.fixed_unique_bytes += *size as u64;
+            }
+            self.chunker.push(&data, &mut on_chunk);
+        }
+        self.chunker.finish(&mut on_chunk);
+        estimate.cdc_unique_bytes += cdc_unique_bytes;
+        Ok(())
+    }
+}
+
+/// Whether `key` is part of a sample of `percent` % of the objects
+fn sampled(content_hash: ContentHash, key: &str, percent: f64) -> bool {
+    let digest = content_hash.digest(key.as_bytes());
+    let bucket = u16::from_le_bytes([digest[0], digest[1]]) % 10_000;
+    (bucket as f64) < percent * 100.0
+}
+
+/// Chunk a sample of the objects with content-defined chunking, and report per
+/// bucket how many bytes it would store compared to the fixed-size blocks
+pub fn estimate_cdc(args: CdcEstimateConfig) -> Result<()> {
+    let casfs = args.store.open()?;
+    let buckets = if args.bucket.is_empty() {
+        casfs
+            .list_buckets()?
+            .iter()
+            .map(|bucket| bucket.name().to_string())
+            .collect()
+    } else {
+        args.bucket.clone()
+    };
+
+    let content_hash = casfs.content_hash();
+    let mut sampler = Sampler {
+        content_hash,
+        chunker: Chunker::new(args.avg_chunk_size),
+        blocks: HashSet::new(),
+        chunks: HashSet::new(),
+    };
+    let mut total = CdcEstimate::default();
+
+    println!(
+        "{:<32} {:>8} {:>14} {:>14} {:>14} {:>8}",
+        "BUCKET", "OBJECTS", "BYTES", "FIXED", "CDC", "SAVINGS"
+    );
+    let print = |name: &str, estimate: &CdcEstimate| {
+        println!(
+            "{:<32} {:>8} {:>14} {:>14} {:>14} {:>7.1}%",
+            name,
+            estimate.objects,
+            estimate.bytes,
+            estimate.fixed_unique_bytes,
+            estimate.cdc_unique_bytes,
+            estimate.savings() * 100.0
+        );
+    };
+
+    for bucket in &buckets {
+        if !casfs.bucket_exists(bucket)? {
+            anyhow::bail!("Bucket {bucket} not found");
+        }
+        let mut estimate = CdcEstimate::default();
+        let keys: Vec<String> = casfs
+            .get_bucket(bucket)?
+            .range_filter(None, None, None)
+            .map(|(key, _)| key)
+            .filter(|key| sampled(content_hash, key, args.sample_percent))
+            .collect();
+        for key in keys {
+            sampler.sample_object(&casfs, bucket, &key, &mut estimate)?;
+        }
+        print(bucket, &estimate);
+        total.add(&estimate);
+    }
+    print("(total)", &total);
+    eprintln!(
+        "Sampled {:.1}% of the objects, chunks of {} bytes on average. FIXED and CDC are the bytes \
+         stored with the current blocks and with content-defined chunks",
+        args.sample_percent, args.avg_chunk_size
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_sizes(chunker: &mut Chunker, data: &[u8], piece: usize) -> Vec<usize> {
+        let mut sizes = Vec::new();
+        for part in data.chunks(piece) {
+            chunker.push(part, |chunk| sizes.push(chunk.len()));
+        }
+        chunker.finish(|chunk| sizes.push(chunk.len()));
+        sizes
+    }
+
+    fn random_data(len: usize) -> Vec<u8> {
+        let mut state: u64 = 42;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunker() {
+        let data = random_data(1 << 20);
+        let mut chunker = Chunker::new(4096);
+
+        let sizes = chunk_sizes(&mut chunker, &data, 1 << 20);
+        assert_eq!(sizes.iter().sum::<usize>(), data.len());
+        let (last, sizes_but_last) = sizes.split_last().unwrap();
+        assert!(*last <= 4 * 4096);
+        assert!(sizes_but_last
+            .iter()
+            .all(|size| (1024..=4 * 4096).contains(size)));
+        // the boundaries don't depend on how the data is fed
+        assert_eq!(chunk_sizes(&mut chunker, &data, 1000), sizes);
+
+        // inserting a byte only changes the chunk it lands in
+        let mut shifted = vec![7];
+        shifted.extend_from_slice(&data);
+        let shifted_sizes = chunk_sizes(&mut chunker, &shifted, 1 << 20);
+        assert_eq!(shifted_sizes[1..], sizes[1..]);
+    }
+
+    #[test]
+    fn test_savings() {
+        let estimate = CdcEstimate {
+            objects: 2,
+            bytes: 400,
+            fixed_unique_bytes: 400,
+            cdc_unique_bytes: 300,
+        };
+        assert_eq!(estimate.savings(), 0.25);
+        assert_eq!(CdcEstimate::default().savings(), 0.0);
+    }
+}
@@ -1,16 +1,22 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use bytes::Bytes;
 use clap::Parser;
+use faster_hex::hex_string;
 use futures::StreamExt;
-use md5::{Digest, Md5};
+use md5::Md5;
+use sha2::{Digest, Sha256};
 
 use cas_storage::BlockStream;
 use cas_storage::RangeRequest;
-use cas_storage::CasFS;
+use cas_storage::{CasFS, CasFSBuilder};
 use cas_storage::StorageEngine;
+use crate::alerting::{Alert, AlertConfig, AlertKind, Alerter, Severity};
 use crate::metrics::SharedMetrics;
+use crate::placement::parse_storage_location;
 
 #[derive(Parser, Debug)]
 pub struct CheckConfig {
@@ -27,27 +33,92 @@ pub struct CheckConfig {
     )]
     pub metadata_db: StorageEngine,
 
+    #[arg(
+        long = "storage-location",
+        value_name = "NAME=PATH",
+        value_parser = parse_storage_location,
+        help = "Storage location of the server holding placed blocks. Can be repeated"
+    )]
+    pub storage_locations: Vec<(String, PathBuf)>,
+
     #[arg(required = true, help = "Bucket name")]
     pub bucket: String,
 
-    #[arg(required = true, help = "Object key")]
-    pub key: String,
+    #[arg(
+        required_unless_present = "manifest",
+        conflicts_with = "manifest",
+        help = "Object key"
+    )]
+    pub key: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "manifest",
+        help = "Mark the blocks failing the check as corrupt, reads of objects using them fail until they are healed"
+    )]
+    pub mark_corrupt: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Verify the objects listed in a sha256sum or md5sum style manifest against its checksums, instead of one object"
+    )]
+    pub manifest: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "manifest",
+        help = "Prefix of the keys of the manifest paths, e.g. the prefix the files were uploaded below"
+    )]
+    pub key_prefix: Option<String>,
+
+    #[arg(
+        long,
+        requires = "manifest",
+        help = "Also report the objects below --key-prefix which are not in the manifest"
+    )]
+    pub report_unlisted: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Alert config of the server, corruption found by the check is sent to its webhooks"
+    )]
+    pub alert_config: Option<PathBuf>,
 }
 
 #[tokio::main]
 pub async fn check_integrity(args: CheckConfig) -> Result<()> {
     let storage_engine = args.metadata_db;
     let metrics = SharedMetrics::new();
-    let casfs = CasFS::new(
-        args.fs_root.clone(),
-        args.meta_root.clone(),
-        metrics.to_cas_metrics(),
-        storage_engine,
-        None,
-        None,
-    );
-
-    let (obj_meta, _) = match casfs.get_object_paths(&args.bucket, &args.key)? {
+    let mut builder = CasFSBuilder::new(&args.fs_root, &args.meta_root)
+        .metrics(metrics.to_cas_metrics())
+        .storage_engine(storage_engine);
+    for (name, path) in &args.storage_locations {
+        builder = builder.storage_location(name.clone(), path.clone());
+    }
+    let casfs = builder.build()?;
+    let alerter = match &args.alert_config {
+        Some(path) => Alerter::start(AlertConfig::load(path)?)?,
+        None => Alerter::default(),
+    };
+    let result = match (&args.manifest, &args.key) {
+        (Some(manifest), _) => check_manifest(&args, manifest, &casfs, &alerter, metrics).await,
+        (None, Some(key)) => check_object(&args, key, &casfs, &alerter, metrics).await,
+        (None, None) => Err(anyhow!("An object key or --manifest is required")),
+    };
+    alerter.close().await;
+    result
+}
+
+async fn check_object(
+    args: &CheckConfig,
+    key: &str,
+    casfs: &CasFS,
+    alerter: &Alerter,
+    metrics: SharedMetrics,
+) -> Result<()> {
+    let (obj_meta, paths) = match casfs.get_object_paths(&args.bucket, key)? {
         Some((obj, paths)) => (obj, paths),
         None => {
             eprintln!("Object not found");
@@ -55,14 +126,76 @@ pub async fn check_integrity(args: CheckConfig) -> Result<()> {
         }
     };
 
-    let Some(data) = get_object_data(&casfs, &args.bucket, &args.key, metrics).await? else {
+    // the id of a block is the content hash of its data
+    let content_hash = casfs.content_hash();
+    let corrupt_blocks = casfs.corrupt_blocks();
+    let mut bad_blocks = Vec::new();
+    for (block, (path, _)) in obj_meta.blocks().iter().zip(&paths) {
+        let valid = match std::fs::read(path) {
+            Ok(data) => content_hash.digest(&data) == *block,
+            Err(e) => {
+                eprintln!("could not read block {}: {e}", hex_string(block));
+                false
+            }
+        };
+        if !valid && !bad_blocks.contains(block) {
+            bad_blocks.push(*block);
+        }
+    }
+    let mut marked = Vec::new();
+    for block in &bad_blocks {
+        let status = match corrupt_blocks.get(block)? {
+            Some(record) if !record.is_healed() => "already marked as corrupt",
+            _ if args.mark_corrupt => {
+                corrupt_blocks.mark(block)?;
+                "marked as corrupt"
+            }
+            _ => "not marked, use --mark-corrupt",
+        };
+        if corrupt_blocks.is_corrupt(block)? {
+            marked.push(*block);
+        }
+        eprintln!("check failed: block {} is corrupt ({status})", hex_string(block));
+    }
+    if !marked.is_empty() {
+        // deduplicated blocks are shared, other objects than the checked one can
+        // be unreadable too
+        let degraded = casfs.mark_degraded_objects(&marked)?;
+        eprintln!(
+            "{} object(s) using the corrupt blocks marked as degraded, list them with `s3-cas inspect degraded-objects`",
+            degraded.len()
+        );
+    }
+    if !bad_blocks.is_empty() {
+        alerter.send(
+            Alert::new(
+                AlertKind::Corruption,
+                Severity::Critical,
+                format!("Check found {} corrupt block(s)", bad_blocks.len()),
+            )
+            .with_detail("bucket", &args.bucket)
+            .with_detail("key", key)
+            .with_detail("marked", args.mark_corrupt),
+        );
+        return Ok(());
+    }
+
+    let Some(data) = get_object_data(casfs, &args.bucket, key, metrics).await? else {
         eprintln!("Object not found");
         return Ok(());
     };
 
-    let hash: [u8; 16] = Md5::digest(data).into();
-    if hash != *obj_meta.hash() {
+    if content_hash.digest(&data) != *obj_meta.hash() {
         eprintln!("check failed: hash mismatch");
+        alerter.send(
+            Alert::new(
+                AlertKind::Corruption,
+                Severity::Critical,
+                "Check found an object not matching its hash",
+            )
+            .with_detail("bucket", &args.bucket)
+            .with_detail("key", key),
+        );
     } else {
         println!("check passed: hash matched");
     }
@@ -99,3 +232,300 @@ async fn get_object_data(
 
     Ok(Some(data))
 }
+
+/// Hash function of an external manifest, recognized by the length of its hashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ManifestHash {
+    Md5,
+    Sha256,
+}
+
+impl ManifestHash {
+    fn from_hex_len(len: usize) -> Option<Self> {
+        match len {
+            32 => Some(ManifestHash::Md5),
+            64 => Some(ManifestHash::Sha256),
+            _ => None,
+        }
+    }
+
+    fn hasher(&self) -> Hasher {
+        match self {
+            ManifestHash::Md5 => Hasher::Md5(Md5::new()),
+            ManifestHash::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+}
+
+enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        match self {
+            Hasher::Md5(hasher) => hex_string(&hasher.finalize()),
+            Hasher::Sha256(hasher) => hex_string(&hasher.finalize()),
+        }
+    }
+}
+
+/// A file listed in an external manifest with its checksum
+#[derive(Debug, PartialEq, Eq)]
+struct ManifestLine {
+    hash: ManifestHash,
+    /// Lower case hex
+    digest: String,
+    path: String,
+}
+
+/// Parses a line of `sha256sum` or `md5sum` output, `<hex>  <path>` or
+/// `<hex> *<path>`, or of the BSD tools, `SHA256 (<path>) = <hex>`. Returns
+/// `None` for empty lines and comments.
+fn parse_manifest_line(line: &str) -> Result<Option<ManifestLine>, String> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.trim().is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let bsd = ["MD5", "SHA256"]
+        .iter()
+        .find_map(|tag| line.strip_prefix(tag)?.strip_prefix(" ("));
+    let (digest, path) = if let Some(rest) = bsd {
+        let (path, digest) = rest
+            .rsplit_once(") = ")
+            .ok_or_else(|| format!("invalid line: {}", line))?;
+        (digest, path.to_string())
+    } else {
+        // GNU style, a leading backslash means the path is escaped
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let (digest, rest) = line
+            .split_once(' ')
+            .ok_or_else(|| format!("invalid line: {}", line))?;
+        let path = rest
+            .strip_prefix(' ')
+            .or_else(|| rest.strip_prefix('*'))
+            .ok_or_else(|| format!("invalid line: {}", line))?;
+        let path = if escaped {
+            unescape(path)
+        } else {
+            path.to_string()
+        };
+        (digest, path)
+    };
+    let hash = ManifestHash::from_hex_len(digest.len())
+        .filter(|_| digest.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or_else(|| format!("invalid hash {}", digest))?;
+    Ok(Some(ManifestLine {
+        hash,
+        digest: digest.to_ascii_lowercase(),
+        path,
+    }))
+}
+
+/// Undo the escaping of `\\` and `\n` in the paths of the GNU tools
+fn unescape(path: &str) -> String {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// How an object diverges from the manifest
+#[derive(Debug)]
+enum Divergence {
+    Mismatch {
+        expected: String,
+        actual: String,
+    },
+    Missing,
+    Unreadable(String),
+    /// Stored below the key prefix, but not in the manifest
+    Unlisted,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Divergence::Mismatch { expected, actual } => {
+                write!(f, "mismatch, expected {} got {}", expected, actual)
+            }
+            Divergence::Missing => write!(f, "missing"),
+            Divergence::Unreadable(e) => write!(f, "unreadable: {}", e),
+            Divergence::Unlisted => write!(f, "not in the manifest"),
+        }
+    }
+}
+
+/// Verify the objects listed in an external manifest against its checksums,
+/// printing each diverging key. Fails if any object diverges.
+async fn check_manifest(
+    args: &CheckConfig,
+    manifest: &Path,
+    casfs: &CasFS,
+    alerter: &Alerter,
+    metrics: SharedMetrics,
+) -> Result<()> {
+    let file = std::fs::File::open(manifest)
+        .with_context(|| format!("Can't open {}", manifest.display()))?;
+    let prefix = args.key_prefix.as_deref().unwrap_or("");
+
+    let mut listed = HashSet::new();
+    let mut checked = 0;
+    let mut divergences = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let entry = match parse_manifest_line(&line) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => continue,
+            Err(e) => bail!("{}:{}: {}", manifest.display(), i + 1, e),
+        };
+        let path = entry.path.strip_prefix("./").unwrap_or(&entry.path);
+        let key = format!("{}{}", prefix, path);
+        checked += 1;
+        let divergence =
+            match object_digest(casfs, &args.bucket, &key, entry.hash, metrics.clone()).await {
+                Ok(Some(actual)) if actual == entry.digest => None,
+                Ok(Some(actual)) => Some(Divergence::Mismatch {
+                    expected: entry.digest,
+                    actual,
+                }),
+                Ok(None) => Some(Divergence::Missing),
+                Err(e) => Some(Divergence::Unreadable(e.to_string())),
+            };
+        if let Some(divergence) = divergence {
+            println!("{}: {}", key, divergence);
+            divergences.push((key.clone(), divergence));
+        }
+        listed.insert(key);
+    }
+
+    if args.report_unlisted {
+        let snapshot = casfs.get_bucket(&args.bucket)?.snapshot();
+        for (key, _) in snapshot.range_filter(None, Some(prefix.to_string()), None) {
+            if !listed.contains(&key) {
+                println!("{}: {}", key, Divergence::Unlisted);
+                divergences.push((key, Divergence::Unlisted));
+            }
+        }
+    }
+
+    let mismatches = divergences
+        .iter()
+        .filter(|(_, divergence)| matches!(divergence, Divergence::Mismatch { .. }))
+        .count();
+    if mismatches > 0 {
+        alerter.send(
+            Alert::new(
+                AlertKind::Corruption,
+                Severity::Critical,
+                format!(
+                    "Check found {} object(s) not matching the manifest",
+                    mismatches
+                ),
+            )
+            .with_detail("bucket", &args.bucket)
+            .with_detail("manifest", manifest.display()),
+        );
+    }
+    if !divergences.is_empty() {
+        bail!(
+            "{} of {} checked object(s) diverge from the manifest",
+            divergences.len(),
+            checked
+        );
+    }
+    println!("check passed: {} object(s) match the manifest", checked);
+    Ok(())
+}
+
+/// Hex encoded digest of the data of an object, `None` if it doesn't exist
+async fn object_digest(
+    casfs: &CasFS,
+    bucket: &str,
+    key: &str,
+    hash: ManifestHash,
+    metrics: SharedMetrics,
+) -> Result<Option<String>> {
+    let Some((obj_meta, paths)) = casfs.get_object_paths(bucket, key)? else {
+        return Ok(None);
+    };
+    let mut hasher = hash.hasher();
+    if let Some(data) = obj_meta.inlined() {
+        hasher.update(data);
+    } else {
+        let size: usize = paths.iter().map(|(_, size)| size).sum();
+        let mut blocks = BlockStream::new(paths, size, RangeRequest::All, metrics.to_cas_metrics());
+        while let Some(chunk) = blocks.next().await {
+            hasher.update(&chunk?);
+        }
+    }
+    Ok(Some(hasher.finalize_hex()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest_line() {
+        let sha = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        let line = |path: &str| ManifestLine {
+            hash: ManifestHash::Sha256,
+            digest: sha.to_string(),
+            path: path.to_string(),
+        };
+        assert_eq!(
+            parse_manifest_line(&format!("{}  dir/a b.txt", sha)),
+            Ok(Some(line("dir/a b.txt")))
+        );
+        assert_eq!(
+            parse_manifest_line(&format!("{} *data.bin", sha.to_uppercase())),
+            Ok(Some(line("data.bin")))
+        );
+        assert_eq!(
+            parse_manifest_line(&format!("\\{}  a\\nb\\\\n", sha)),
+            Ok(Some(line("a\nb\\n")))
+        );
+        assert_eq!(
+            parse_manifest_line(&format!("{}  x (1).txt", sha)),
+            Ok(Some(line("x (1).txt")))
+        );
+        assert_eq!(
+            parse_manifest_line(&format!("SHA256 (x (1).txt) = {}", sha)),
+            Ok(Some(line("x (1).txt")))
+        );
+        assert_eq!(
+            parse_manifest_line("d41d8cd98f00b204e9800998ecf8427e  empty")
+                .unwrap()
+                .map(|line| line.hash),
+            Some(ManifestHash::Md5)
+        );
+        assert_eq!(parse_manifest_line("# comment"), Ok(None));
+        assert_eq!(parse_manifest_line(""), Ok(None));
+        assert!(parse_manifest_line("abc  file").is_err());
+        assert!(parse_manifest_line(&format!("{}file", sha)).is_err());
+    }
+}
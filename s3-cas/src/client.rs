@@ -0,0 +1,695 @@
+//! Typed client of the extension APIs of the server, which S3 clients don't
+//! cover: concatenating objects, client-side deduplication, share links and the
+//! admin API. Built with the `client` feature.
+//!
+//! The JSON API and share links are part of the HTTP UI and authenticated like
+//! it, with the basic auth credentials of a single-user server or the session of
+//! a logged in user. The admin API needs the admin token, and may be served on
+//! the separate admin listener.
+
+use std::fmt;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{header, Method, Request, StatusCode};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use md5::{Digest, Md5};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+use crate::auth::DeletePolicy;
+
+/// Hash function of the blocks [`CasClient::put_deduplicated`] can split data into
+const BLOCK_HASH: &str = "md5";
+
+/// Error of a request to the server
+#[derive(Debug)]
+pub enum ClientError {
+    /// The request could not be sent, or its response not read
+    Request(String),
+    /// The server refused the request, with the message of its error response
+    Status { status: StatusCode, message: String },
+    /// The response is not what the API returns
+    InvalidResponse(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Request(e) => write!(f, "Request failed: {}", e),
+            ClientError::Status { status, message } => write!(f, "{} ({})", message, status),
+            ClientError::InvalidResponse(e) => write!(f, "Invalid response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+#[derive(Debug, Serialize)]
+pub struct ConcatRequest {
+    /// Key of the new object
+    pub key: String,
+    /// Keys of the objects to concatenate, in order
+    pub sources: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConcatResponse {
+    pub bucket: String,
+    pub key: String,
+    pub size: u64,
+    pub etag: String,
+    pub parts: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockCheckRequest {
+    /// Hex encoded hashes of the blocks
+    pub blocks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockCheckResponse {
+    /// Size of the blocks the store splits objects into
+    pub block_size: usize,
+    /// Hash function of the blocks
+    pub hash: String,
+    /// The blocks to upload before assembling an object from them
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StagedBlock {
+    pub bucket: String,
+    pub block: String,
+    pub size: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssembleRequest {
+    /// Key of the new object
+    pub key: String,
+    /// Hex encoded hashes of the blocks of the object, in order
+    pub blocks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssembleResponse {
+    pub bucket: String,
+    pub key: String,
+    pub size: u64,
+    pub etag: String,
+    pub blocks: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShareLinkInfo {
+    pub bucket: String,
+    pub key: String,
+    /// The link, with the origin the request was sent to
+    pub url: String,
+    /// Seconds since the UNIX epoch the link expires at
+    pub expires: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UserInfo {
+    pub user_id: String,
+    pub ui_login: String,
+    pub s3_access_key: String,
+    pub is_admin: bool,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CreateUserRequest {
+    pub user_id: String,
+    /// Defaults to the user id
+    pub ui_login: Option<String>,
+    /// Generated if not set
+    pub ui_password: Option<String>,
+    /// Generated if not set
+    pub s3_access_key: Option<String>,
+    /// Generated if not set
+    pub s3_secret_key: Option<String>,
+    pub is_admin: bool,
+}
+
+/// A new user, the only time its password and secret key are returned
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreatedUser {
+    #[serde(flatten)]
+    pub user: UserInfo,
+    pub ui_password: String,
+    pub s3_secret_key: String,
+    /// Buckets created by the user template
+    pub buckets: Vec<String>,
+    /// Why applying the user template failed, the user was created anyway
+    pub template_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeletedUser {
+    pub deleted: String,
+    /// The job deleting the buckets of the user, as returned by the jobs API
+    pub job: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BucketInfo {
+    pub name: String,
+    /// Creation time in seconds since the UNIX epoch
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UsageInfo {
+    pub user_id: String,
+    pub buckets: usize,
+    pub objects: usize,
+    pub bytes: u64,
+    pub quota_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct S3KeyInfo {
+    pub s3_access_key: String,
+    /// Expiry timestamp (seconds since UNIX epoch), None for the current key
+    pub expires_at: Option<u64>,
+}
+
+/// A new key pair, the only time its secret key is returned
+#[derive(Debug, Clone, Deserialize)]
+pub struct RotatedKey {
+    pub s3_access_key: String,
+    pub s3_secret_key: String,
+    /// All active keys of the user after the rotation
+    pub keys: Vec<S3KeyInfo>,
+}
+
+/// How requests to the HTTP UI are authenticated
+#[derive(Clone)]
+enum Credentials {
+    None,
+    Basic(String),
+    Session(String),
+}
+
+/// Client of the extension APIs of a server
+pub struct CasClient {
+    endpoint: String,
+    admin_endpoint: Option<String>,
+    credentials: Credentials,
+    admin_token: Option<String>,
+    client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+}
+
+impl CasClient {
+    /// Client of the HTTP UI at `endpoint`, e.g. `http://localhost:8080`, verifying
+    /// an https endpoint with the system roots
+    pub fn new(endpoint: &str) -> anyhow::Result<Self> {
+        let tls = if endpoint.starts_with("https://") {
+            crate::tls::client_config(None, None)?
+        } else {
+            // plain HTTP needs no root certificates, which may not be installed
+            ClientConfig::builder()
+                .with_root_certificates(RootCertStore::empty())
+                .with_no_client_auth()
+        };
+        Ok(Self::with_tls_config(endpoint, tls))
+    }
+
+    /// Client of the HTTP UI at `endpoint` connecting to https endpoints with
+    /// `tls`, see [`crate::tls::client_config`]
+    pub fn with_tls_config(endpoint: &str, tls: ClientConfig) -> Self {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls)
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            admin_endpoint: None,
+            credentials: Credentials::None,
+            admin_token: None,
+            client: Client::builder(TokioExecutor::new()).build(connector),
+        }
+    }
+
+    /// Authenticate with the `--http-ui-username` and `--http-ui-password` of a
+    /// single-user server
+    pub fn with_basic_auth(mut self, username: &str, password: &str) -> Self {
+        let encoded = STANDARD.encode(format!("{}:{}", username, password));
+        self.credentials = Credentials::Basic(encoded);
+        self
+    }
+
+    /// Authenticate with the session id of a user logged in to a multi-user server
+    pub fn with_session(mut self, session_id: &str) -> Self {
+        self.credentials = Credentials::Session(session_id.to_string());
+        self
+    }
+
+    /// The admin token, as configured with `--admin-token` on the server
+    pub fn with_admin_token(mut self, token: &str) -> Self {
+        self.admin_token = Some(token.to_string());
+        self
+    }
+
+    /// Send admin API requests to the admin listener at `endpoint`, instead of
+    /// the HTTP UI. An https listener needs a client made with
+    /// [`CasClient::with_tls_config`] when the HTTP UI is served over plain HTTP.
+    pub fn with_admin_endpoint(mut self, endpoint: &str) -> Self {
+        self.admin_endpoint = Some(endpoint.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Create `key` in `bucket` as the concatenation of the objects `sources`
+    pub async fn concat(
+        &self,
+        bucket: &str,
+        key: &str,
+        sources: &[String],
+    ) -> Result<ConcatResponse, ClientError> {
+        let body = ConcatRequest {
+            key: key.to_string(),
+            sources: sources.to_vec(),
+        };
+        self.ui_json(Method::POST, &bucket_path(bucket, "concat"), &body)
+            .await
+    }
+
+    /// Which of the hex encoded `blocks` the store is missing
+    pub async fn check_blocks(
+        &self,
+        bucket: &str,
+        blocks: &[String],
+    ) -> Result<BlockCheckResponse, ClientError> {
+        let body = BlockCheckRequest {
+            blocks: blocks.to_vec(),
+        };
+        self.ui_json(Method::POST, &bucket_path(bucket, "blocks/check"), &body)
+            .await
+    }
+
+    /// Upload the `data` of a missing block with the hex encoded `hash`
+    pub async fn put_block(
+        &self,
+        bucket: &str,
+        hash: &str,
+        data: Bytes,
+    ) -> Result<StagedBlock, ClientError> {
+        let path = bucket_path(bucket, &format!("blocks/{}", urlencoding::encode(hash)));
+        let req = self
+            .ui_request(Method::PUT, &path)
+            .header(header::CONTENT_TYPE, "application/octet-stream");
+        self.send(req, data).await
+    }
+
+    /// Create `key` in `bucket` from the hex encoded hashes of its blocks, in order
+    pub async fn assemble(
+        &self,
+        bucket: &str,
+        key: &str,
+        blocks: &[String],
+    ) -> Result<AssembleResponse, ClientError> {
+        let body = AssembleRequest {
+            key: key.to_string(),
+            blocks: blocks.to_vec(),
+        };
+        self.ui_json(Method::POST, &bucket_path(bucket, "assemble"), &body)
+            .await
+    }
+
+    /// Store `data` as `key` in `bucket`, uploading only the blocks the store is
+    /// missing. Returns the assembled object, whose ETag is the MD5 of `data`.
+    pub async fn put_deduplicated(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Bytes,
+    ) -> Result<AssembleResponse, ClientError> {
+        // an empty check returns how the store splits and hashes objects
+        let store = self.check_blocks(bucket, &[]).await?;
+        if store.hash != BLOCK_HASH {
+            return Err(ClientError::InvalidResponse(format!(
+                "blocks are hashed with {}, only {} is supported",
+                store.hash, BLOCK_HASH
+            )));
+        }
+        if store.block_size == 0 {
+            return Err(ClientError::InvalidResponse("block size 0".to_string()));
+        }
+
+        let blocks = block_hashes(&data, store.block_size);
+        let missing = self.check_blocks(bucket, &blocks).await?.missing;
+        for (i, hash) in blocks.iter().enumerate() {
+            if missing.contains(hash) {
+                let start = i * store.block_size;
+                let end = (start + store.block_size).min(data.len());
+                self.put_block(bucket, hash, data.slice(start..end)).await?;
+            }
+        }
+        self.assemble(bucket, key, &blocks).await
+    }
+
+    /// A link downloading `key` without authentication, valid for `lifetime` or
+    /// a day
+    pub async fn share_link(
+        &self,
+        bucket: &str,
+        key: &str,
+        lifetime: Option<Duration>,
+    ) -> Result<ShareLinkInfo, ClientError> {
+        let mut form = format!(
+            "bucket={}&key={}",
+            urlencoding::encode(bucket),
+            urlencoding::encode(key)
+        );
+        if let Some(lifetime) = lifetime {
+            form.push_str(&format!("&expires_in={}", lifetime.as_secs()));
+        }
+        let req = self
+            .ui_request(Method::POST, "/share-links?format=json")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+        self.send(req, Bytes::from(form)).await
+    }
+
+    pub async fn list_users(&self) -> Result<Vec<UserInfo>, ClientError> {
+        self.admin(Method::GET, "users", None::<&()>).await
+    }
+
+    /// Create a user, with generated credentials for those not set in `user`
+    pub async fn create_user(&self, user: &CreateUserRequest) -> Result<CreatedUser, ClientError> {
+        self.admin(Method::POST, "users", Some(user)).await
+    }
+
+    /// Delete a user, with `policy` for its buckets
+    pub async fn delete_user(
+        &self,
+        user_id: &str,
+        policy: DeletePolicy,
+    ) -> Result<DeletedUser, ClientError> {
+        let path = format!("{}?policy={}", user_path(user_id), policy.as_str());
+        self.admin(Method::DELETE, &path, None::<&()>).await
+    }
+
+    pub async fn list_buckets(&self, user_id: &str) -> Result<Vec<BucketInfo>, ClientError> {
+        let path = format!("{}/buckets", user_path(user_id));
+        self.admin(Method::GET, &path, None::<&()>).await
+    }
+
+    pub async fn usage(&self, user_id: &str) -> Result<UsageInfo, ClientError> {
+        let path = format!("{}/usage", user_path(user_id));
+        self.admin(Method::GET, &path, None::<&()>).await
+    }
+
+    /// Limit the amount of bytes a user can store, `None` removes the quota
+    pub async fn set_quota(
+        &self,
+        user_id: &str,
+        max_bytes: Option<u64>,
+    ) -> Result<(), ClientError> {
+        let path = format!("{}/quota", user_path(user_id));
+        let body = serde_json::json!({ "max_bytes": max_bytes });
+        self.admin::<serde_json::Value, _>(Method::PUT, &path, Some(&body))
+            .await?;
+        Ok(())
+    }
+
+    /// Allow or deny a user to sign S3 requests with Signature Version 2
+    pub async fn set_sig_v2(&self, user_id: &str, enabled: bool) -> Result<(), ClientError> {
+        let path = format!("{}/sigv2", user_path(user_id));
+        let body = serde_json::json!({ "enabled": enabled });
+        self.admin::<serde_json::Value, _>(Method::PUT, &path, Some(&body))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_keys(&self, user_id: &str) -> Result<Vec<S3KeyInfo>, ClientError> {
+        let path = format!("{}/keys", user_path(user_id));
+        self.admin(Method::GET, &path, None::<&()>).await
+    }
+
+    /// Generate a new key pair for a user, the old key stays valid for
+    /// `grace_secs`, or a day
+    pub async fn rotate_key(
+        &self,
+        user_id: &str,
+        grace_secs: Option<u64>,
+    ) -> Result<RotatedKey, ClientError> {
+        let path = format!("{}/keys", user_path(user_id));
+        let body = serde_json::json!({ "grace_secs": grace_secs });
+        self.admin(Method::POST, &path, Some(&body)).await
+    }
+
+    /// Revoke an old access key of a user before it expires
+    pub async fn revoke_key(&self, user_id: &str, access_key: &str) -> Result<(), ClientError> {
+        let path = format!(
+            "{}/keys/{}",
+            user_path(user_id),
+            urlencoding::encode(access_key)
+        );
+        self.admin::<serde_json::Value, _>(Method::DELETE, &path, None::<&()>)
+            .await?;
+        Ok(())
+    }
+
+    /// Start a job exporting the data of a user to a tar archive, deleting the
+    /// user once it is complete with `delete_after`. Returns the job as returned
+    /// by the jobs API, the archive is downloaded with `s3-cas admin user
+    /// takeout-download`.
+    pub async fn start_takeout(
+        &self,
+        user_id: &str,
+        delete_after: bool,
+    ) -> Result<serde_json::Value, ClientError> {
+        let path = format!("{}/takeout", user_path(user_id));
+        let body = serde_json::json!({ "delete_after": delete_after });
+        self.admin(Method::POST, &path, Some(&body)).await
+    }
+
+    fn ui_request(&self, method: Method, path: &str) -> hyper::http::request::Builder {
+        let req = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.endpoint, path))
+            .header(header::ACCEPT, "application/json");
+        match &self.credentials {
+            Credentials::None => req,
+            Credentials::Basic(encoded) => {
+                req.header(header::AUTHORIZATION, format!("Basic {}", encoded))
+            }
+            Credentials::Session(id) => req.header(header::COOKIE, format!("session_id={}", id)),
+        }
+    }
+
+    async fn ui_json<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: &impl Serialize,
+    ) -> Result<T, ClientError> {
+        let req = self
+            .ui_request(method, path)
+            .header(header::CONTENT_TYPE, "application/json");
+        self.send(req, json_body(body)?).await
+    }
+
+    async fn admin<T: DeserializeOwned, B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<T, ClientError> {
+        let token = self.admin_token.as_deref().ok_or_else(|| {
+            ClientError::Request("the admin API needs an admin token".to_string())
+        })?;
+        let endpoint = self.admin_endpoint.as_deref().unwrap_or(&self.endpoint);
+        let req = Request::builder()
+            .method(method)
+            .uri(format!("{}/api/admin/{}", endpoint, path))
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, "application/json");
+        let body = match body {
+            Some(body) => json_body(body)?,
+            None => Bytes::new(),
+        };
+        self.send(req, body).await
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        req: hyper::http::request::Builder,
+        body: Bytes,
+    ) -> Result<T, ClientError> {
+        let req = req
+            .body(Full::new(body))
+            .map_err(|e| ClientError::Request(e.to_string()))?;
+        let uri = req.uri().clone();
+        let resp = self
+            .client
+            .request(req)
+            .await
+            .map_err(|e| ClientError::Request(format!("{}: {}", uri, e)))?;
+        let status = resp.status();
+        let body = resp
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| ClientError::Request(format!("{}: {}", uri, e)))?
+            .to_bytes();
+
+        if !status.is_success() {
+            let message = serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|value| value["error"].as_str().map(str::to_string))
+                .unwrap_or_else(|| String::from_utf8_lossy(&body).trim().to_string());
+            return Err(ClientError::Status { status, message });
+        }
+        serde_json::from_slice(&body).map_err(|e| ClientError::InvalidResponse(e.to_string()))
+    }
+}
+
+/// Hex encoded hashes of `data` split into blocks of `block_size` bytes, like the
+/// store splits an uploaded object
+pub fn block_hashes(data: &[u8], block_size: usize) -> Vec<String> {
+    data.chunks(block_size)
+        .map(|block| faster_hex::hex_string(&Md5::digest(block)))
+        .collect()
+}
+
+fn json_body(body: &impl Serialize) -> Result<Bytes, ClientError> {
+    serde_json::to_vec(body)
+        .map(Bytes::from)
+        .map_err(|e| ClientError::Request(e.to_string()))
+}
+
+fn bucket_path(bucket: &str, tail: &str) -> String {
+    format!("/api/v1/buckets/{}/{}", urlencoding::encode(bucket), tail)
+}
+
+fn user_path(user_id: &str) -> String {
+    format!("users/{}", urlencoding::encode(user_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers every request with `status` and `body`, returns the endpoint and
+    /// the received requests
+    async fn server(
+        status: &'static str,
+        body: &'static str,
+    ) -> (String, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                // the tests send small requests, with a content length
+                loop {
+                    let n = conn.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((head, data)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("content-length: "))
+                            .map_or(0, |length| length.parse().unwrap());
+                        if data.len() >= length {
+                            break;
+                        }
+                    }
+                }
+                tx.send(String::from_utf8_lossy(&request).to_string())
+                    .unwrap();
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                conn.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (endpoint, rx)
+    }
+
+    #[test]
+    fn test_block_hashes() {
+        let hashes = block_hashes(b"hellohello!", 5);
+        assert_eq!(
+            hashes,
+            vec![
+                "5d41402abc4b2a76b9719d911017c592",
+                "5d41402abc4b2a76b9719d911017c592",
+                "9033e0e305f247c0c3c80d0c7848c8b3",
+            ]
+        );
+        assert!(block_hashes(b"", 5).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_concat() {
+        let (endpoint, mut requests) = server(
+            "200 OK",
+            r#"{"bucket":"logs","key":"all.log","size":10,"etag":"\"abc-2\"","parts":2}"#,
+        )
+        .await;
+        let client = CasClient::new(&endpoint)
+            .unwrap()
+            .with_basic_auth("admin", "secret");
+        let sources = vec!["a.log".to_string(), "b.log".to_string()];
+        let concat = client.concat("logs", "all.log", &sources).await.unwrap();
+        assert_eq!(concat.size, 10);
+        assert_eq!(concat.parts, 2);
+
+        let request = requests.recv().await.unwrap();
+        assert!(request.starts_with("POST /api/v1/buckets/logs/concat HTTP/1.1"));
+        assert!(request.contains("authorization: Basic YWRtaW46c2VjcmV0"));
+        assert!(request.ends_with(r#"{"key":"all.log","sources":["a.log","b.log"]}"#));
+    }
+
+    #[tokio::test]
+    async fn test_admin() {
+        let (endpoint, mut requests) = server(
+            "404 Not Found",
+            r#"{"error":"User not found","status":404}"#,
+        )
+        .await;
+        let client = CasClient::new("http://localhost:1").unwrap();
+        assert!(matches!(
+            client.usage("alice").await,
+            Err(ClientError::Request(_))
+        ));
+
+        let client = client
+            .with_admin_token("token")
+            .with_admin_endpoint(&endpoint);
+        match client.delete_user("bob smith", DeletePolicy::Cascade).await {
+            Err(ClientError::Status { status, message }) => {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(message, "User not found");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        let request = requests.recv().await.unwrap();
+        assert!(request.starts_with("DELETE /api/admin/users/bob%20smith?policy=cascade HTTP/1.1"));
+        assert!(request.contains("authorization: Bearer token"));
+    }
+}
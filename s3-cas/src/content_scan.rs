@@ -0,0 +1,817 @@
+//! Scanning of uploaded objects for viruses, with clamd, an ICAP server or a local
+//! command like `clamscan`.
+//!
+//! The data of an upload is sent to the scanner while it is stored, chunk by chunk,
+//! so the object is neither buffered for the scan nor read back from disk. The
+//! object is only committed once the scanner returned its result. By the
+//! [`ScanPolicy`] an infected object is rejected, its blocks are released and the
+//! object it would have replaced stays, or it is stored and tagged with the result.
+//! A completed multipart upload is scanned from its blocks before it is committed.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::Stdio;
+use std::task::{ready, Context as TaskContext, Poll};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use bytes::Bytes;
+use cas_storage::ObjectTags;
+use futures::channel::mpsc;
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+use crate::write_hooks::{applies_to, message, ObjectWrite};
+
+/// Chunks of an upload queued for the scanner, before the upload waits for it
+const SCAN_QUEUE_LEN: usize = 8;
+
+/// Longest response header read from a scanner
+const MAX_RESPONSE_LEN: usize = 64 * 1024;
+
+/// Port of an ICAP URL without one
+const DEFAULT_ICAP_PORT: u16 = 1344;
+
+/// Key of the tag with the scan result, unless configured otherwise
+pub const DEFAULT_SCAN_TAG: &str = "s3cas-scan";
+
+/// What happens to an infected object
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanPolicy {
+    /// The upload fails with `AccessDenied` and nothing is stored
+    #[default]
+    Reject,
+    /// The object is stored, every scanned object is tagged with the result
+    Tag,
+}
+
+fn default_tag() -> String {
+    DEFAULT_SCAN_TAG.to_string()
+}
+
+fn default_timeout_secs() -> u64 {
+    60
+}
+
+/// The virus scanner of the uploads, the `[scan]` table of the write hook
+/// configuration. Exactly one of `clamd`, `icap` and `program` is set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScanConfig {
+    /// Address of clamd, `host:port` or the path of its unix socket
+    pub clamd: Option<String>,
+    /// URL of an ICAP response modification service, `icap://host[:port]/service`
+    pub icap: Option<String>,
+    /// A program scanning its stdin, which exits with 0 for clean data and with 1
+    /// for infected data, like `clamscan -`
+    pub program: Option<PathBuf>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub policy: ScanPolicy,
+    /// Key of the tag holding the result with the tag policy
+    #[serde(default = "default_tag")]
+    pub tag: String,
+    /// Buckets whose uploads are scanned, all buckets if empty
+    #[serde(default)]
+    pub buckets: Vec<String>,
+    /// Only scan keys with this prefix and suffix
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+    /// Objects larger than this are stored without a scan, e.g. above the
+    /// `StreamMaxLength` of clamd
+    pub max_size: Option<u64>,
+    /// Time the scanner may take to accept more data, and to return its result
+    /// once all data was sent
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Store an object which couldn't be scanned, instead of failing its upload
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+impl ScanConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.engine()?;
+        if self.timeout_secs == 0 {
+            anyhow::bail!("timeout_secs of the scanner must be at least 1");
+        }
+        ObjectTags::new(vec![(self.tag.clone(), "clean".to_string())])
+            .map_err(|e| anyhow::anyhow!("invalid scan tag: {}", e))?;
+        Ok(())
+    }
+
+    fn engine(&self) -> anyhow::Result<Engine> {
+        match (&self.clamd, &self.icap, &self.program) {
+            (Some(address), None, None) => Ok(Engine::Clamd(address.clone())),
+            (None, Some(url), None) => Ok(Engine::Icap(IcapService::parse(url)?)),
+            (None, None, Some(program)) => Ok(Engine::Command {
+                program: program.clone(),
+                args: self.args.clone(),
+            }),
+            _ => anyhow::bail!("the scanner needs exactly one of clamd, icap and program"),
+        }
+    }
+}
+
+/// The result of a scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    /// Infected, with the name of what the scanner found
+    Infected(String),
+    /// The data couldn't be scanned, with why
+    Failed(String),
+}
+
+impl ScanVerdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanVerdict::Clean => "clean",
+            ScanVerdict::Infected(_) => "infected",
+            ScanVerdict::Failed(_) => "failed",
+        }
+    }
+}
+
+/// A finished scan
+#[derive(Debug, Clone)]
+pub struct ScanReport {
+    pub verdict: ScanVerdict,
+    /// Data sent to the scanner
+    pub bytes: u64,
+    /// Time from the start of the scan until its result
+    pub duration: Duration,
+}
+
+/// Why an object isn't stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanRefusal {
+    /// The object is infected with the named virus
+    Infected(String),
+    /// The object couldn't be scanned
+    Failed,
+}
+
+impl fmt::Display for ScanRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanRefusal::Infected(name) => write!(f, "The object is infected with {}", name),
+            ScanRefusal::Failed => f.write_str("The object could not be scanned"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct IcapService {
+    url: String,
+    /// The host and port, as sent in the `Host` header
+    authority: String,
+    address: (String, u16),
+}
+
+impl IcapService {
+    fn parse(url: &str) -> anyhow::Result<Self> {
+        let uri: hyper::Uri = url
+            .parse()
+            .with_context(|| format!("invalid ICAP url {}", url))?;
+        if uri.scheme_str() != Some("icap") {
+            anyhow::bail!("ICAP url {} doesn't start with icap://", url);
+        }
+        let host = uri
+            .host()
+            .with_context(|| format!("ICAP url {} has no host", url))?;
+        let authority = uri.authority().expect("a uri with a host has an authority");
+        Ok(Self {
+            url: url.to_string(),
+            authority: authority.to_string(),
+            address: (
+                host.to_string(),
+                uri.port_u16().unwrap_or(DEFAULT_ICAP_PORT),
+            ),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Engine {
+    Clamd(String),
+    Icap(IcapService),
+    Command { program: PathBuf, args: Vec<String> },
+}
+
+/// Scans the uploads of a [`ScanConfig`]
+#[derive(Debug)]
+pub struct Scanner {
+    config: ScanConfig,
+    engine: Engine,
+}
+
+impl Scanner {
+    pub fn new(config: ScanConfig) -> anyhow::Result<Self> {
+        let engine = config.engine()?;
+        Ok(Self { config, engine })
+    }
+
+    pub fn policy(&self) -> ScanPolicy {
+        self.config.policy
+    }
+
+    /// Whether the data of `write`, of `size` bytes, is scanned
+    pub fn scans(&self, write: &ObjectWrite, size: u64) -> bool {
+        let config = &self.config;
+        applies_to(write, &config.buckets, &config.prefix, &config.suffix)
+            && config.max_size.map_or(true, |max_size| size <= max_size)
+    }
+
+    /// Start scanning the data of `write` as it goes through the returned stream.
+    /// The scan ends with the stream, or on the first error in it.
+    pub fn scan<S, E>(&self, write: &ObjectWrite, data: S) -> (Scanned<S>, PendingScan)
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+    {
+        let (tx, rx) = mpsc::channel(SCAN_QUEUE_LEN);
+        let engine = self.engine.clone();
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let (bucket, key) = (write.bucket.clone(), write.key.clone());
+        let task = tokio::spawn(async move {
+            let mut sent = 0;
+            let mut data = rx.inspect(|chunk: &Bytes| sent += chunk.len() as u64);
+            let verdict = match engine.scan(&mut data, timeout, &bucket, &key).await {
+                Ok(verdict) => verdict,
+                Err(e) => ScanVerdict::Failed(format!("{:#}", e)),
+            };
+            drop(data);
+            (verdict, sent)
+        });
+        let scanned = Scanned {
+            inner: Box::pin(data),
+            tx: Some(tx),
+        };
+        let pending = PendingScan {
+            task,
+            started: Instant::now(),
+        };
+        (scanned, pending)
+    }
+
+    /// Scan all of `data`, the scan fails on an error in it
+    pub async fn scan_all<S, E>(&self, write: &ObjectWrite, data: S) -> ScanReport
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+        E: fmt::Display,
+    {
+        let (mut scanned, pending) = self.scan(write, data);
+        let mut failure = None;
+        while let Some(item) = scanned.next().await {
+            if let Err(e) = item {
+                failure = Some(format!("reading the data: {}", e));
+                break;
+            }
+        }
+        drop(scanned);
+        let mut report = pending.report().await;
+        if let Some(failure) = failure {
+            report.verdict = ScanVerdict::Failed(failure);
+        }
+        report
+    }
+
+    /// Whether an object scanned with `verdict` is stored, with the value of the
+    /// tag to set on it by the policy, or why it is refused
+    pub fn judge(&self, verdict: &ScanVerdict) -> Result<Option<&'static str>, ScanRefusal> {
+        let tag = self.config.policy == ScanPolicy::Tag;
+        match verdict {
+            ScanVerdict::Clean => Ok(tag.then_some("clean")),
+            ScanVerdict::Infected(_) if tag => Ok(Some("infected")),
+            ScanVerdict::Infected(name) => Err(ScanRefusal::Infected(name.clone())),
+            ScanVerdict::Failed(_) if self.config.fail_open => Ok(None),
+            ScanVerdict::Failed(_) => Err(ScanRefusal::Failed),
+        }
+    }
+
+    /// `tags` with the scan result `value`, replacing a tag with the key of the
+    /// result set by the client
+    pub fn result_tags(&self, tags: &ObjectTags, value: &str) -> Result<ObjectTags, String> {
+        let mut result: Vec<(String, String)> = tags
+            .iter()
+            .filter(|(key, _)| *key != self.config.tag)
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        result.push((self.config.tag.clone(), value.to_string()));
+        ObjectTags::new(result)
+    }
+}
+
+/// The data of an upload, sent to the scanner as it is read. The upload waits for
+/// the scanner when it falls behind.
+pub struct Scanned<S> {
+    inner: Pin<Box<S>>,
+    // dropped at the end of the data, so the scanner returns its result
+    tx: Option<mpsc::Sender<Bytes>>,
+}
+
+impl<S, E> Stream for Scanned<S>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(tx) = &mut this.tx {
+            match tx.poll_ready(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(())) => {}
+                // the scanner stopped reading, it has a result without the rest
+                Poll::Ready(Err(_)) => this.tx = None,
+            }
+        }
+        let item = ready!(this.inner.as_mut().poll_next(cx));
+        let sent = match (&item, this.tx.as_mut()) {
+            (Some(Ok(chunk)), Some(tx)) => tx.start_send(chunk.clone()).is_ok(),
+            _ => false,
+        };
+        if !sent {
+            this.tx = None;
+        }
+        Poll::Ready(item)
+    }
+}
+
+/// A scan waiting for the end of its data
+pub struct PendingScan {
+    task: JoinHandle<(ScanVerdict, u64)>,
+    started: Instant,
+}
+
+impl PendingScan {
+    /// Wait for the result, once the data went through the stream of the scan
+    pub async fn report(self) -> ScanReport {
+        let (verdict, bytes) = match self.task.await {
+            Ok(result) => result,
+            Err(e) => (ScanVerdict::Failed(e.to_string()), 0),
+        };
+        ScanReport {
+            verdict,
+            bytes,
+            duration: self.started.elapsed(),
+        }
+    }
+}
+
+impl Engine {
+    async fn scan(
+        &self,
+        data: &mut (impl Stream<Item = Bytes> + Unpin),
+        timeout: Duration,
+        bucket: &str,
+        key: &str,
+    ) -> anyhow::Result<ScanVerdict> {
+        match self {
+            #[cfg(unix)]
+            Engine::Clamd(address) if address.starts_with('/') => {
+                let conn = tokio::time::timeout(timeout, tokio::net::UnixStream::connect(address))
+                    .await
+                    .context("timed out connecting to clamd")?
+                    .with_context(|| format!("connecting to clamd at {}", address))?;
+                clamd(conn, data, timeout).await
+            }
+            Engine::Clamd(address) => {
+                let conn = tokio::time::timeout(timeout, TcpStream::connect(address.as_str()))
+                    .await
+                    .context("timed out connecting to clamd")?
+                    .with_context(|| format!("connecting to clamd at {}", address))?;
+                clamd(conn, data, timeout).await
+            }
+            Engine::Icap(service) => {
+                let (host, port) = &service.address;
+                let conn =
+                    tokio::time::timeout(timeout, TcpStream::connect((host.as_str(), *port)))
+                        .await
+                        .context("timed out connecting to the ICAP server")?
+                        .with_context(|| {
+                            format!("connecting to the ICAP server {}", service.url)
+                        })?;
+                icap(conn, service, data, timeout).await
+            }
+            Engine::Command { program, args } => {
+                command(program, args, bucket, key, data, timeout).await
+            }
+        }
+    }
+}
+
+async fn write_timed(
+    conn: &mut (impl AsyncWrite + Unpin),
+    data: &[u8],
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    tokio::time::timeout(timeout, conn.write_all(data))
+        .await
+        .with_context(|| format!("not accepting data for {} seconds", timeout.as_secs()))??;
+    Ok(())
+}
+
+// reads a response up to `end`, or until the connection is closed
+async fn read_response(
+    conn: &mut (impl AsyncRead + Unpin),
+    end: &[u8],
+) -> std::io::Result<Vec<u8>> {
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+    while !response.windows(end.len()).any(|window| window == end)
+        && response.len() < MAX_RESPONSE_LEN
+    {
+        let n = conn.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+    Ok(response)
+}
+
+// the name in a `<source>: <name> FOUND` line of clamd and clamscan
+fn found(line: &str) -> Option<String> {
+    let found = line.strip_suffix(" FOUND")?;
+    let name = found.rsplit_once(": ").map_or(found, |(_, name)| name);
+    Some(name.to_string())
+}
+
+/// Streams the data to clamd with `INSTREAM`
+async fn clamd<C: AsyncRead + AsyncWrite + Unpin>(
+    mut conn: C,
+    data: &mut (impl Stream<Item = Bytes> + Unpin),
+    timeout: Duration,
+) -> anyhow::Result<ScanVerdict> {
+    let sent = async {
+        write_timed(&mut conn, b"zINSTREAM\0", timeout).await?;
+        while let Some(chunk) = data.next().await {
+            // an empty chunk ends the stream
+            if chunk.is_empty() {
+                continue;
+            }
+            write_timed(&mut conn, &(chunk.len() as u32).to_be_bytes(), timeout).await?;
+            write_timed(&mut conn, &chunk, timeout).await?;
+        }
+        write_timed(&mut conn, &0u32.to_be_bytes(), timeout).await
+    }
+    .await;
+    // clamd replies and closes the connection early, e.g. when the data is too
+    // large, so its reply is read when sending failed too
+    let reply = tokio::time::timeout(timeout, read_response(&mut conn, b"\0")).await;
+    let reply = match (sent, reply) {
+        (_, Ok(Ok(reply))) if !reply.is_empty() => reply,
+        (Err(e), _) => return Err(e),
+        (Ok(()), Ok(Ok(_))) => anyhow::bail!("clamd closed the connection without a result"),
+        (Ok(()), Ok(Err(e))) => return Err(e).context("reading the result of clamd"),
+        (Ok(()), Err(_)) => anyhow::bail!("no result of clamd after {} seconds", timeout.as_secs()),
+    };
+    clamd_verdict(&reply)
+}
+
+fn clamd_verdict(reply: &[u8]) -> anyhow::Result<ScanVerdict> {
+    let reply = String::from_utf8_lossy(reply);
+    let reply = reply.trim_end_matches('\0').trim();
+    if let Some(name) = found(reply) {
+        return Ok(ScanVerdict::Infected(name));
+    }
+    if reply == "OK" || reply.ends_with(": OK") {
+        return Ok(ScanVerdict::Clean);
+    }
+    anyhow::bail!("clamd: {}", reply)
+}
+
+/// Sends the data to an ICAP service as the body of a response to modify. The
+/// service answers `204 No Content` for clean data.
+async fn icap<C: AsyncRead + AsyncWrite + Unpin>(
+    mut conn: C,
+    service: &IcapService,
+    data: &mut (impl Stream<Item = Bytes> + Unpin),
+    timeout: Duration,
+) -> anyhow::Result<ScanVerdict> {
+    let http_header = "HTTP/1.1 200 OK\r\n\
+        Content-Type: application/octet-stream\r\n\
+        Transfer-Encoding: chunked\r\n\r\n";
+    let request = format!(
+        "RESPMOD {} ICAP/1.0\r\n\
+        Host: {}\r\n\
+        Allow: 204\r\n\
+        Connection: close\r\n\
+        Encapsulated: res-hdr=0, res-body={}\r\n\r\n{}",
+        service.url,
+        service.authority,
+        http_header.len(),
+        http_header
+    );
+    write_timed(&mut conn, request.as_bytes(), timeout).await?;
+    while let Some(chunk) = data.next().await {
+        // an empty chunk ends the body
+        if chunk.is_empty() {
+            continue;
+        }
+        write_timed(
+            &mut conn,
+            format!("{:x}\r\n", chunk.len()).as_bytes(),
+            timeout,
+        )
+        .await?;
+        write_timed(&mut conn, &chunk, timeout).await?;
+        write_timed(&mut conn, b"\r\n", timeout).await?;
+    }
+    write_timed(&mut conn, b"0\r\n\r\n", timeout).await?;
+    let response = tokio::time::timeout(timeout, read_response(&mut conn, b"\r\n\r\n"))
+        .await
+        .with_context(|| {
+            format!(
+                "no response of the ICAP server after {} seconds",
+                timeout.as_secs()
+            )
+        })?
+        .context("reading the response of the ICAP server")?;
+    icap_verdict(&response)
+}
+
+fn icap_verdict(response: &[u8]) -> anyhow::Result<ScanVerdict> {
+    let response = String::from_utf8_lossy(response);
+    let mut lines = response.lines();
+    let status_line = lines.next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some("204") => Ok(ScanVerdict::Clean),
+        // the service replaced the content, with what it found in the headers
+        Some("200") => {
+            let mut name = None;
+            for line in lines.take_while(|line| !line.is_empty()) {
+                let Some((header, value)) = line.split_once(':') else {
+                    continue;
+                };
+                match header.trim().to_ascii_lowercase().as_str() {
+                    // e.g. `Type=0; Resolution=2; Threat=Eicar-Test-Signature;`
+                    "x-infection-found" => {
+                        name = value
+                            .split(';')
+                            .find_map(|field| field.trim().strip_prefix("Threat="))
+                            .map(str::to_string)
+                            .or(name);
+                    }
+                    "x-virus-id" => name = name.or_else(|| Some(value.trim().to_string())),
+                    _ => {}
+                }
+            }
+            Ok(ScanVerdict::Infected(
+                name.unwrap_or_else(|| "unknown".to_string()),
+            ))
+        }
+        _ => anyhow::bail!("the ICAP server responded with '{}'", status_line.trim()),
+    }
+}
+
+/// Pipes the data to a local command, which exits with 0 for clean data and with 1
+/// for infected data, with what it found on the first line of its stdout
+async fn command(
+    program: &Path,
+    args: &[String],
+    bucket: &str,
+    key: &str,
+    data: &mut (impl Stream<Item = Bytes> + Unpin),
+    timeout: Duration,
+) -> anyhow::Result<ScanVerdict> {
+    let mut child = Command::new(program)
+        .args(args)
+        .env("S3CAS_BUCKET", bucket)
+        .env("S3CAS_KEY", key)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("failed to start {}", program.display()))?;
+
+    let mut stdin = child.stdin.take().expect("stdin of the scanner is piped");
+    while let Some(chunk) = data.next().await {
+        // a scanner may exit before reading all data, its exit code tells
+        if write_timed(&mut stdin, &chunk, timeout).await.is_err() {
+            break;
+        }
+    }
+    drop(stdin);
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .with_context(|| format!("killed after {} seconds", timeout.as_secs()))??;
+    match output.status.code() {
+        Some(0) => Ok(ScanVerdict::Clean),
+        Some(1) => {
+            let line = message(&output.stdout).unwrap_or_else(|| "unknown".to_string());
+            Ok(ScanVerdict::Infected(found(&line).unwrap_or(line)))
+        }
+        _ => anyhow::bail!("{} exited with {}", program.display(), output.status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use tokio::net::TcpListener;
+
+    // what the fake scanners take for a virus
+    const VIRUS: &[u8] = b"FAKE-VIRUS-SIGNATURE";
+
+    fn write(key: &str) -> ObjectWrite {
+        ObjectWrite {
+            operation: "PutObject",
+            owner: "alice".to_string(),
+            bucket: "uploads".to_string(),
+            key: key.to_string(),
+            content_type: None,
+            size: None,
+        }
+    }
+
+    fn scanner(config: &str) -> Scanner {
+        let config: ScanConfig = toml::from_str(config).unwrap();
+        config.validate().unwrap();
+        Scanner::new(config).unwrap()
+    }
+
+    fn chunks(chunks: &[&'static [u8]]) -> impl Stream<Item = Result<Bytes, std::io::Error>> {
+        let chunks: Vec<_> = chunks.iter().map(|c| Ok(Bytes::from_static(c))).collect();
+        stream::iter(chunks)
+    }
+
+    #[test]
+    fn test_config() {
+        let scanner = scanner("clamd = \"/run/clamav/clamd.ctl\"\nmax_size = 10\nprefix = \"in/\"");
+        assert_eq!(scanner.policy(), ScanPolicy::Reject);
+        assert!(scanner.scans(&write("in/a"), 10));
+        assert!(!scanner.scans(&write("in/a"), 11));
+        assert!(!scanner.scans(&write("out/a"), 1));
+
+        for invalid in [
+            "",
+            "clamd = \"localhost:3310\"\nprogram = \"clamscan\"",
+            "icap = \"http://localhost/avscan\"",
+            "clamd = \"localhost:3310\"\ntimeout_secs = 0",
+            "clamd = \"localhost:3310\"\ntag = \"\"",
+        ] {
+            let config: ScanConfig = toml::from_str(invalid).unwrap();
+            assert!(config.validate().is_err(), "{}", invalid);
+        }
+        let service = IcapService::parse("icap://av.local/avscan").unwrap();
+        assert_eq!(service.address, ("av.local".to_string(), 1344));
+        assert_eq!(service.authority, "av.local");
+    }
+
+    #[test]
+    fn test_judge() {
+        let infected = ScanVerdict::Infected("Eicar-Test-Signature".to_string());
+        let failed = ScanVerdict::Failed("connection refused".to_string());
+
+        let reject = scanner("clamd = \"localhost:3310\"");
+        assert_eq!(reject.judge(&ScanVerdict::Clean), Ok(None));
+        assert_eq!(
+            reject.judge(&infected),
+            Err(ScanRefusal::Infected("Eicar-Test-Signature".to_string()))
+        );
+        assert_eq!(reject.judge(&failed), Err(ScanRefusal::Failed));
+
+        let tag = scanner("clamd = \"localhost:3310\"\npolicy = \"tag\"\nfail_open = true");
+        assert_eq!(tag.judge(&ScanVerdict::Clean), Ok(Some("clean")));
+        assert_eq!(tag.judge(&infected), Ok(Some("infected")));
+        assert_eq!(tag.judge(&failed), Ok(None));
+
+        // a result set by the client is replaced
+        let tags = ObjectTags::new(vec![
+            ("s3cas-scan".to_string(), "clean".to_string()),
+            ("team".to_string(), "a".to_string()),
+        ])
+        .unwrap();
+        let tags = tag.result_tags(&tags, "infected").unwrap();
+        assert_eq!(tags.get("s3cas-scan"), Some("infected"));
+        assert_eq!(tags.get("team"), Some("a"));
+    }
+
+    #[test]
+    fn test_verdicts() {
+        assert_eq!(clamd_verdict(b"stream: OK\0").unwrap(), ScanVerdict::Clean);
+        assert_eq!(
+            clamd_verdict(b"stream: Eicar-Test-Signature FOUND\0").unwrap(),
+            ScanVerdict::Infected("Eicar-Test-Signature".to_string())
+        );
+        assert!(clamd_verdict(b"INSTREAM size limit exceeded. ERROR\0").is_err());
+
+        assert_eq!(
+            icap_verdict(b"ICAP/1.0 204 No Content\r\nISTag: x\r\n\r\n").unwrap(),
+            ScanVerdict::Clean
+        );
+        assert_eq!(
+            icap_verdict(
+                b"ICAP/1.0 200 OK\r\nX-Infection-Found: Type=0; Resolution=2; Threat=Eicar-Test-Signature;\r\n\r\n"
+            )
+            .unwrap(),
+            ScanVerdict::Infected("Eicar-Test-Signature".to_string())
+        );
+        assert_eq!(
+            icap_verdict(b"ICAP/1.0 200 OK\r\n\r\n").unwrap(),
+            ScanVerdict::Infected("unknown".to_string())
+        );
+        assert!(icap_verdict(b"ICAP/1.0 500 Server Error\r\n\r\n").is_err());
+    }
+
+    // a clamd answering INSTREAM requests, finding `VIRUS`
+    async fn fake_clamd() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            loop {
+                let (mut conn, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut command = [0; 10];
+                    conn.read_exact(&mut command).await.unwrap();
+                    assert_eq!(&command, b"zINSTREAM\0");
+                    let mut data = Vec::new();
+                    loop {
+                        let len = conn.read_u32().await.unwrap() as usize;
+                        if len == 0 {
+                            break;
+                        }
+                        let mut chunk = vec![0; len];
+                        conn.read_exact(&mut chunk).await.unwrap();
+                        data.extend_from_slice(&chunk);
+                    }
+                    let reply: &[u8] = if data.windows(VIRUS.len()).any(|w| w == VIRUS) {
+                        b"stream: Eicar-Test-Signature FOUND\0"
+                    } else {
+                        b"stream: OK\0"
+                    };
+                    conn.write_all(reply).await.unwrap();
+                });
+            }
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_clamd() {
+        let address = fake_clamd().await;
+        let scanner = scanner(&format!("clamd = \"{}\"", address));
+
+        // the data goes through the stream unchanged while it is scanned
+        let (scanned, pending) = scanner.scan(&write("a"), chunks(&[b"hello ", b"", b"world"]));
+        let data: Vec<_> = scanned.map(Result::unwrap).collect().await;
+        assert_eq!(data.concat(), b"hello world");
+        let report = pending.report().await;
+        assert_eq!(report.verdict, ScanVerdict::Clean);
+        assert_eq!(report.bytes, 11);
+
+        let (head, tail) = VIRUS.split_at(8);
+        let report = scanner.scan_all(&write("a"), chunks(&[head, tail])).await;
+        assert_eq!(
+            report.verdict,
+            ScanVerdict::Infected("Eicar-Test-Signature".to_string())
+        );
+
+        // the data can't be scanned completely when reading it fails
+        let data = chunks(&[b"hello"]).chain(stream::once(async {
+            Err(std::io::Error::other("disk error"))
+        }));
+        let report = scanner.scan_all(&write("a"), data).await;
+        assert!(matches!(report.verdict, ScanVerdict::Failed(_)));
+
+        let unreachable = self::scanner("clamd = \"127.0.0.1:1\"");
+        let report = unreachable.scan_all(&write("a"), chunks(&[b"hello"])).await;
+        assert!(matches!(report.verdict, ScanVerdict::Failed(_)));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command() {
+        let scanner = scanner(
+            r#"
+            program = "sh"
+            args = ["-c", "if grep -q FAKE-VIRUS-SIGNATURE; then echo \"stdin: Eicar-Signature FOUND\"; exit 1; fi"]
+            "#,
+        );
+        let report = scanner.scan_all(&write("a"), chunks(&[b"hello"])).await;
+        assert_eq!(report.verdict, ScanVerdict::Clean);
+        let report = scanner.scan_all(&write("a"), chunks(&[VIRUS])).await;
+        assert_eq!(
+            report.verdict,
+            ScanVerdict::Infected("Eicar-Signature".to_string())
+        );
+
+        let failing =
+            self::scanner("program = \"sh\"\nargs = [\"-c\", \"cat > /dev/null; exit 2\"]");
+        let report = failing.scan_all(&write("a"), chunks(&[b"hello"])).await;
+        assert!(matches!(report.verdict, ScanVerdict::Failed(_)));
+    }
+}
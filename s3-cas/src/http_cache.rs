@@ -0,0 +1,58 @@
+//! Helpers for HTTP caching headers shared by the S3 API and the HTTP UI.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default `Cache-Control` header: clients may cache objects, but have to
+/// revalidate them with `If-None-Match` before every use, as objects can be
+/// overwritten.
+pub const DEFAULT_CACHE_CONTROL: &str = "no-cache";
+
+fn strip_weak(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+/// Check if the value of an `If-None-Match` header matches an ETag, in which case
+/// the response should be `304 Not Modified`. Uses the weak comparison from
+/// RFC 7232, and `*` matches any ETag.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = strip_weak(etag);
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || strip_weak(candidate) == etag)
+}
+
+/// Format a time as an HTTP date (RFC 7231), e.g. for the `Last-Modified` header.
+pub fn http_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    chrono::DateTime::from_timestamp(secs as i64, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = "\"abc\"";
+        assert!(etag_matches("\"abc\"", etag));
+        assert!(etag_matches("W/\"abc\"", etag));
+        assert!(etag_matches("\"xyz\", \"abc\"", etag));
+        assert!(etag_matches("*", etag));
+        assert!(!etag_matches("\"xyz\"", etag));
+        assert!(!etag_matches("abc", etag));
+    }
+
+    #[test]
+    fn test_http_date() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+}
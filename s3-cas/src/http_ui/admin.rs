@@ -5,13 +5,17 @@ use rand::Rng;
 use std::sync::Arc;
 use tracing;
 
-use crate::auth::{SessionStore, UserRecord, UserStore};
+use crate::auth::{
+    AuditLog, DeletePolicy, Deletion, SessionStore, UserDeleter, UserRecord, UserRouter, UserStore,
+    UserTemplate,
+};
 use crate::metrics::SharedMetrics;
 
+use super::ui::Ui;
 use super::{responses, templates, HttpBody};
 
 /// Generates a random S3 access key (20 characters, alphanumeric uppercase)
-fn generate_access_key() -> String {
+pub(super) fn generate_access_key() -> String {
     const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
     let mut rng = rand::thread_rng();
     (0..20)
@@ -23,7 +27,7 @@ fn generate_access_key() -> String {
 }
 
 /// Generates a random S3 secret key (40 characters, alphanumeric + special chars)
-fn generate_secret_key() -> String {
+pub(super) fn generate_secret_key() -> String {
     const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
     let mut rng = rand::thread_rng();
     (0..40)
@@ -35,7 +39,7 @@ fn generate_secret_key() -> String {
 }
 
 /// Generates a random password (16 characters, alphanumeric)
-fn generate_password() -> String {
+pub(super) fn generate_password() -> String {
     const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
     let mut rng = rand::thread_rng();
     (0..16)
@@ -46,11 +50,45 @@ fn generate_password() -> String {
         .collect()
 }
 
+/// Applies the user template, if any, to the new user `user_id` and returns the
+/// created buckets. The user exists either way, a failure is logged and returned
+/// to tell the admin.
+pub(super) fn apply_template(
+    template: Option<&UserTemplate>,
+    user_id: &str,
+    user_router: &UserRouter,
+    user_store: &UserStore,
+) -> Result<Vec<String>, String> {
+    let Some(template) = template else {
+        return Ok(Vec::new());
+    };
+    match template.apply(user_id, user_router, user_store) {
+        Ok(buckets) => {
+            tracing::info!(user_id = %user_id, buckets = ?buckets, "Applied user template");
+            Ok(buckets)
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, user_id = %user_id, "Failed to apply user template");
+            Err(format!("{e:#}"))
+        }
+    }
+}
+
+/// A note on the buckets created by the user template, for the success message
+/// of the admin
+pub(super) fn template_note(result: &Result<Vec<String>, String>) -> String {
+    match result {
+        Ok(buckets) if buckets.is_empty() => String::new(),
+        Ok(buckets) => format!(" | Buckets: {}", buckets.join(", ")),
+        Err(e) => format!(" | Applying the user template failed: {}", e),
+    }
+}
+
 /// Handles GET /admin/users - lists all users
-pub async fn handle_list_users(user_store: Arc<UserStore>) -> Response<HttpBody> {
+pub async fn handle_list_users(ui: &Ui, user_store: Arc<UserStore>) -> Response<HttpBody> {
     match user_store.list_users() {
         Ok(users) => {
-            responses::html_response(StatusCode::OK, templates::admin_users_page(&users))
+            responses::html_response(StatusCode::OK, templates::admin_users_page(ui, &users))
         }
         Err(e) => {
             tracing::warn!(error = %e, "Failed to list users");
@@ -63,14 +101,43 @@ pub async fn handle_list_users(user_store: Arc<UserStore>) -> Response<HttpBody>
 }
 
 /// Handles GET /admin/users/new - displays user creation form
-pub async fn handle_new_user_form() -> Response<HttpBody> {
-    responses::html_response(StatusCode::OK, templates::new_user_form())
+pub async fn handle_new_user_form(ui: &Ui) -> Response<HttpBody> {
+    responses::html_response(StatusCode::OK, templates::new_user_form(ui))
+}
+
+/// Records the actions of an admin in the audit log
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Auditor<'a> {
+    pub log: Option<&'a AuditLog>,
+    /// The user id of the admin
+    pub actor: &'a str,
+}
+
+impl Auditor<'_> {
+    /// Appends an action to the audit log. The action is done already, so a
+    /// failure is only logged.
+    pub fn record(&self, action: &str, target: &str, details: &str) {
+        let Some(log) = self.log else {
+            return;
+        };
+        if let Err(e) = log.record(self.actor, action, target, details) {
+            tracing::error!(
+                error = %e,
+                action,
+                target,
+                "Failed to record admin action in the audit log"
+            );
+        }
+    }
 }
 
 /// Handles POST /admin/users - creates a new user
 pub async fn handle_create_user(
     req: Request<Incoming>,
     user_store: Arc<UserStore>,
+    user_router: &UserRouter,
+    template: Option<&UserTemplate>,
+    auditor: Auditor<'_>,
     metrics: SharedMetrics,
 ) -> Response<HttpBody> {
     // Parse form data
@@ -158,15 +225,21 @@ pub async fn handle_create_user(
     match user_store.create_user(user) {
         Ok(_) => {
             metrics.record_admin_operation("user_create");
+            auditor.record("user_create", &user_id, &format!("is_admin={}", is_admin));
             tracing::info!(
                 user_id = %user_id,
                 is_admin = is_admin,
                 "User created via admin panel"
             );
+            let provisioned = apply_template(template, &user_id, user_router, &user_store);
             // Redirect to users list with success message showing the credentials
             let message = format!(
-                "User created: {} | Password: {} | S3 Key: {} | S3 Secret: {}",
-                user_id, ui_password, s3_access_key, s3_secret_key
+                "User created: {} | Password: {} | S3 Key: {} | S3 Secret: {}{}",
+                user_id,
+                ui_password,
+                s3_access_key,
+                s3_secret_key,
+                template_note(&provisioned)
             );
             redirect_with_success("/admin/users", &message)
         }
@@ -180,19 +253,50 @@ pub async fn handle_create_user(
 /// Handles DELETE /admin/users/{user_id} - deletes a user
 pub async fn handle_delete_user(
     user_id: &str,
-    user_store: Arc<UserStore>,
-    session_store: Arc<SessionStore>,
+    req: Request<Incoming>,
+    deleter: UserDeleter<'_>,
+    auditor: Auditor<'_>,
     metrics: SharedMetrics,
 ) -> Response<HttpBody> {
-    // Delete all sessions for this user
-    session_store.delete_user_sessions(user_id);
+    // Parse the deletion policy, denying by default
+    let body_bytes = match req.into_body().collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read request body");
+            return redirect_with_error("/admin/users", "Invalid request");
+        }
+    };
+    let body_str = String::from_utf8_lossy(&body_bytes);
+    let policy = match body_str
+        .split('&')
+        .find_map(|param| param.strip_prefix("policy="))
+        .map(str::parse::<DeletePolicy>)
+        .transpose()
+    {
+        Ok(policy) => policy.unwrap_or_default(),
+        Err(e) => return redirect_with_error("/admin/users", &e),
+    };
 
-    // Delete user from database
-    match user_store.delete_user(user_id) {
-        Ok(_) => {
+    match deleter.delete(user_id, policy) {
+        Ok(deletion) => {
             metrics.record_admin_operation("user_delete");
-            tracing::info!(user_id = %user_id, "User deleted via admin panel");
-            redirect_with_success("/admin/users", &format!("User '{}' deleted", user_id))
+            auditor.record(
+                "user_delete",
+                user_id,
+                &format!("policy={}", policy.as_str()),
+            );
+            tracing::info!(
+                user_id = %user_id,
+                policy = policy.as_str(),
+                "User deleted via admin panel"
+            );
+            match deletion {
+                Deletion::Done => {
+                    redirect_with_success("/admin/users", &format!("User '{}' deleted", user_id))
+                }
+                // the user is deleted once the job deleted its buckets
+                Deletion::Started(_) => responses::redirect("/admin/jobs"),
+            }
         }
         Err(e) => {
             tracing::warn!(error = %e, user_id = %user_id, "Failed to delete user");
@@ -203,12 +307,13 @@ pub async fn handle_delete_user(
 
 /// Handles GET /admin/users/{user_id}/reset-password - displays password reset form
 pub async fn handle_reset_password_form(
+    ui: &Ui,
     user_id: &str,
     user_store: Arc<UserStore>,
 ) -> Response<HttpBody> {
     match user_store.get_user_by_id(user_id) {
         Ok(Some(user)) => {
-            responses::html_response(StatusCode::OK, templates::reset_password_form(&user))
+            responses::html_response(StatusCode::OK, templates::reset_password_form(ui, &user))
         }
         Ok(None) => responses::html_response(
             StatusCode::NOT_FOUND,
@@ -230,6 +335,7 @@ pub async fn handle_update_password(
     req: Request<Incoming>,
     user_store: Arc<UserStore>,
     session_store: Arc<SessionStore>,
+    auditor: Auditor<'_>,
     metrics: SharedMetrics,
 ) -> Response<HttpBody> {
     // Parse form data
@@ -266,6 +372,7 @@ pub async fn handle_update_password(
     match user_store.update_password(user_id, &new_password) {
         Ok(_) => {
             metrics.record_admin_operation("password_reset");
+            auditor.record("password_reset", user_id, "");
             tracing::info!(user_id = %user_id, "Password updated via admin panel");
             // Invalidate all sessions for this user
             session_store.delete_user_sessions(user_id);
@@ -293,12 +400,12 @@ fn redirect_with_error(location: &str, error: &str) -> Response<HttpBody> {
 /// Handles POST /admin/users/{user_id}/toggle-admin - toggles admin status
 pub async fn handle_toggle_admin(
     user_id: &str,
-    current_user_id: &str,
     user_store: Arc<UserStore>,
+    auditor: Auditor<'_>,
     metrics: SharedMetrics,
 ) -> Response<HttpBody> {
     // Prevent users from removing their own admin rights
-    if user_id == current_user_id {
+    if user_id == auditor.actor {
         return redirect_with_error("/admin/users", "You cannot modify your own admin rights");
     }
 
@@ -322,6 +429,7 @@ pub async fn handle_toggle_admin(
     match user_store.update_admin_status(user_id, new_status) {
         Ok(_) => {
             metrics.record_admin_operation(metric_operation);
+            auditor.record(metric_operation, user_id, "");
             tracing::info!(
                 user_id = %user_id,
                 is_admin = new_status,
@@ -0,0 +1,843 @@
+//! JSON admin API used by `s3-cas admin`, authenticated with a bearer token.
+//!
+//! The routes are listed in [`ROUTES`], which also describe them in the OpenAPI
+//! spec.
+
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use http_body_util::BodyExt;
+use hyper::{body::Incoming, header, Request, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use cas_storage::JobStatus;
+
+use crate::auth::{
+    compute_usage, takeout_path, AuditLog, DeleteError, DeletePolicy, Deletion, S3KeyInfo,
+    SessionStore, UserDeleter, UserRecord, UserRouter, UserStore, UserTemplate, UserUsage,
+    ADMIN_API_ACTOR, DEFAULT_KEY_GRACE_SECS,
+};
+use crate::jobs::{JobError, JobKind, JobManager};
+use crate::metrics::SharedMetrics;
+
+use super::admin::{
+    apply_template, generate_access_key, generate_password, generate_secret_key, Auditor,
+};
+use super::jobs::JobInfo;
+use super::openapi::{self, Body, Param, Route};
+use super::{responses, HttpBody};
+
+pub const ADMIN_API_PREFIX: &str = "/api/admin/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminOp {
+    ListUsers,
+    CreateUser,
+    DeleteUser,
+    ListBuckets,
+    Usage,
+    SetQuota,
+    SetSigV2,
+    ListKeys,
+    RotateKey,
+    RevokeKey,
+    StartTakeout,
+    DownloadTakeout,
+}
+
+/// Routes of the admin API
+pub const ROUTES: &[Route<AdminOp>] = &[
+    admin_route(
+        AdminOp::ListUsers,
+        "GET",
+        "/api/admin/users",
+        "List the users",
+        None,
+        200,
+        Body::List("UserInfo"),
+    ),
+    admin_route(
+        AdminOp::CreateUser,
+        "POST",
+        "/api/admin/users",
+        "Create a user, returns its generated password and secret key",
+        Some("CreateUserRequest"),
+        201,
+        Body::Object("CreatedUser"),
+    ),
+    Route {
+        op: AdminOp::DeleteUser,
+        method: "DELETE",
+        path: "/api/admin/users/{user_id}",
+        tail: false,
+        summary: "Delete a user and end its sessions. With the cascade or archive policy \
+            the buckets of the user are deleted by a job, responding with 202",
+        query: &[Param {
+            name: "policy",
+            description: "What to do with the buckets of the user: `deny` (default), \
+                `cascade` or `archive`",
+            integer: false,
+        }],
+        request: None,
+        status: 200,
+        response: Body::Object("DeletedUser"),
+        public: false,
+    },
+    admin_route(
+        AdminOp::ListBuckets,
+        "GET",
+        "/api/admin/users/{user_id}/buckets",
+        "List the buckets of a user",
+        None,
+        200,
+        Body::List("AdminBucketInfo"),
+    ),
+    admin_route(
+        AdminOp::Usage,
+        "GET",
+        "/api/admin/users/{user_id}/usage",
+        "Storage usage and quota of a user",
+        None,
+        200,
+        Body::Object("UsageInfo"),
+    ),
+    admin_route(
+        AdminOp::SetQuota,
+        "PUT",
+        "/api/admin/users/{user_id}/quota",
+        "Set or remove the storage quota of a user",
+        Some("QuotaRequest"),
+        200,
+        Body::Object("QuotaRequest"),
+    ),
+    admin_route(
+        AdminOp::SetSigV2,
+        "PUT",
+        "/api/admin/users/{user_id}/sigv2",
+        "Allow or deny Signature Version 2 for a user",
+        Some("SigV2Request"),
+        200,
+        Body::Object("SigV2Request"),
+    ),
+    admin_route(
+        AdminOp::ListKeys,
+        "GET",
+        "/api/admin/users/{user_id}/keys",
+        "List the active S3 keys of a user",
+        None,
+        200,
+        Body::List("S3KeyInfo"),
+    ),
+    admin_route(
+        AdminOp::RotateKey,
+        "POST",
+        "/api/admin/users/{user_id}/keys",
+        "Generate a new S3 key pair, the old one stays valid for a grace period",
+        Some("RotateKeyRequest"),
+        201,
+        Body::Object("RotatedKey"),
+    ),
+    admin_route(
+        AdminOp::RevokeKey,
+        "DELETE",
+        "/api/admin/users/{user_id}/keys/{access_key}",
+        "Revoke an old S3 key before it expires",
+        None,
+        200,
+        Body::Object("RevokedKey"),
+    ),
+    admin_route(
+        AdminOp::StartTakeout,
+        "POST",
+        "/api/admin/users/{user_id}/takeout",
+        "Start a job exporting the buckets, usage history and audit entries of a user \
+            to a tar archive, optionally deleting the user after",
+        Some("TakeoutRequest"),
+        202,
+        Body::Object("JobInfo"),
+    ),
+    admin_route(
+        AdminOp::DownloadTakeout,
+        "GET",
+        "/api/admin/users/{user_id}/takeout/{job_id}",
+        "Download the archive of a completed takeout job",
+        None,
+        200,
+        Body::File("application/x-tar"),
+    ),
+];
+
+const fn admin_route(
+    op: AdminOp,
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    request: Option<&'static str>,
+    status: u16,
+    response: Body,
+) -> Route<AdminOp> {
+    Route {
+        op,
+        method,
+        path,
+        tail: false,
+        summary,
+        query: &[],
+        request,
+        status,
+        response,
+        public: false,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserInfo {
+    pub user_id: String,
+    pub ui_login: String,
+    pub s3_access_key: String,
+    pub is_admin: bool,
+    pub created_at: u64,
+}
+
+impl From<&UserRecord> for UserInfo {
+    fn from(user: &UserRecord) -> Self {
+        Self {
+            user_id: user.user_id.clone(),
+            ui_login: user.ui_login.clone(),
+            s3_access_key: user.s3_access_key.clone(),
+            is_admin: user.is_admin,
+            created_at: user.created_at,
+        }
+    }
+}
+
+/// Response to a user creation, the only time the password and secret key are returned
+#[derive(Debug, Serialize)]
+pub struct CreatedUser {
+    #[serde(flatten)]
+    pub user: UserInfo,
+    pub ui_password: String,
+    pub s3_secret_key: String,
+    /// Buckets created by the user template
+    pub buckets: Vec<String>,
+    /// Why applying the user template failed, the user was created anyway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUserRequest {
+    pub user_id: String,
+    /// Defaults to the user id
+    pub ui_login: Option<String>,
+    /// Generated if not set
+    pub ui_password: Option<String>,
+    /// Generated if not set
+    pub s3_access_key: Option<String>,
+    /// Generated if not set
+    pub s3_secret_key: Option<String>,
+    #[serde(default)]
+    pub is_admin: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BucketInfo {
+    pub name: String,
+    /// Creation time in seconds since the UNIX epoch
+    pub created_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageInfo {
+    pub user_id: String,
+    #[serde(flatten)]
+    pub usage: UserUsage,
+    pub quota_bytes: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuotaRequest {
+    /// Maximum amount of stored bytes, null removes the quota
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SigV2Request {
+    /// Whether the user may sign S3 requests with Signature Version 2, only
+    /// effective when the server runs with `--allow-sig-v2`
+    pub enabled: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RotateKeyRequest {
+    /// Seconds the old key stays valid, defaults to a day
+    pub grace_secs: Option<u64>,
+}
+
+/// Response to a user deletion
+#[derive(Debug, Serialize)]
+pub struct DeletedUser {
+    pub deleted: String,
+    /// The job deleting the buckets of the user, which deletes the user once it
+    /// completes
+    pub job: Option<JobInfo>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TakeoutRequest {
+    /// Delete the buckets and the user once the archive is complete
+    #[serde(default)]
+    pub delete_after: bool,
+}
+
+/// Response to a key rotation, the only time the new secret key is returned
+#[derive(Debug, Serialize)]
+pub struct RotatedKey {
+    pub s3_access_key: String,
+    pub s3_secret_key: String,
+    /// All active keys of the user after the rotation
+    pub keys: Vec<S3KeyInfo>,
+}
+
+/// Admin API handler, only enabled when an admin token is configured
+pub struct AdminApi {
+    token: String,
+    user_router: Arc<UserRouter>,
+    user_store: Arc<UserStore>,
+    session_store: Arc<SessionStore>,
+    metrics: SharedMetrics,
+}
+
+impl AdminApi {
+    pub fn new(
+        token: String,
+        user_router: Arc<UserRouter>,
+        user_store: Arc<UserStore>,
+        session_store: Arc<SessionStore>,
+        metrics: SharedMetrics,
+    ) -> Self {
+        Self {
+            token,
+            user_router,
+            user_store,
+            session_store,
+            metrics,
+        }
+    }
+
+    fn check_token(&self, req: &Request<Incoming>) -> bool {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) => token.as_bytes().ct_eq(self.token.as_bytes()).into(),
+            None => false,
+        }
+    }
+
+    /// Serves a request of the admin API, user deletions with a policy deleting
+    /// buckets and takeouts run as one of `jobs`, created users get the quota and
+    /// buckets of `template`, and changes to users are recorded in `audit`
+    pub async fn handle_request(
+        &self,
+        req: Request<Incoming>,
+        jobs: Option<&Arc<JobManager>>,
+        template: Option<&UserTemplate>,
+        audit: Option<&AuditLog>,
+    ) -> Response<HttpBody> {
+        if !self.check_token(&req) {
+            tracing::warn!(path = %req.uri().path(), "Admin API request with invalid token");
+            return responses::error_response(
+                StatusCode::UNAUTHORIZED,
+                "Invalid admin token",
+                false,
+            );
+        }
+
+        let Some((op, params)) = openapi::match_route(ROUTES, req.method(), req.uri().path()) else {
+            return responses::not_found(false);
+        };
+        let params: Vec<&str> = params.iter().map(String::as_str).collect();
+        let auditor = Auditor {
+            log: audit,
+            actor: ADMIN_API_ACTOR,
+        };
+
+        match (op, params.as_slice()) {
+            (AdminOp::ListUsers, []) => self.list_users(),
+            (AdminOp::CreateUser, []) => self.create_user(req, template, auditor).await,
+            (AdminOp::DeleteUser, [user_id]) => self.delete_user(user_id, &req, jobs, auditor),
+            (AdminOp::ListBuckets, [user_id]) => self.list_buckets(user_id),
+            (AdminOp::Usage, [user_id]) => self.usage(user_id),
+            (AdminOp::SetQuota, [user_id]) => self.set_quota(user_id, req, auditor).await,
+            (AdminOp::SetSigV2, [user_id]) => self.set_sig_v2(user_id, req, auditor).await,
+            (AdminOp::ListKeys, [user_id]) => self.list_keys(user_id),
+            (AdminOp::RotateKey, [user_id]) => self.rotate_key(user_id, req, auditor).await,
+            (AdminOp::RevokeKey, [user_id, access_key]) => {
+                self.revoke_key(user_id, access_key, auditor)
+            }
+            (AdminOp::StartTakeout, [user_id]) => {
+                self.start_takeout(user_id, req, jobs, audit, auditor).await
+            }
+            (AdminOp::DownloadTakeout, [user_id, job_id]) => {
+                self.download_takeout(user_id, job_id, jobs)
+            }
+            _ => responses::not_found(false),
+        }
+    }
+
+    fn list_users(&self) -> Response<HttpBody> {
+        match self.user_store.list_users() {
+            Ok(users) => {
+                let users: Vec<UserInfo> = users.iter().map(UserInfo::from).collect();
+                responses::json_response(StatusCode::OK, &users)
+            }
+            Err(e) => internal_error("Failed to list users", e),
+        }
+    }
+
+    async fn create_user(
+        &self,
+        req: Request<Incoming>,
+        template: Option<&UserTemplate>,
+        auditor: Auditor<'_>,
+    ) -> Response<HttpBody> {
+        let request: CreateUserRequest = match read_json(req).await {
+            Ok(request) => request,
+            Err(resp) => return resp,
+        };
+        if request.user_id.is_empty() {
+            return bad_request("user_id is required");
+        }
+
+        let ui_login = request.ui_login.unwrap_or_else(|| request.user_id.clone());
+        let ui_password = request.ui_password.unwrap_or_else(generate_password);
+        let s3_access_key = request.s3_access_key.unwrap_or_else(generate_access_key);
+        let s3_secret_key = request.s3_secret_key.unwrap_or_else(generate_secret_key);
+
+        let user = match UserRecord::new(
+            request.user_id.clone(),
+            ui_login,
+            &ui_password,
+            s3_access_key,
+            s3_secret_key.clone(),
+            request.is_admin,
+        ) {
+            Ok(user) => user,
+            Err(e) => return internal_error("Failed to create user", e),
+        };
+        let info = UserInfo::from(&user);
+
+        if let Err(e) = self.user_store.create_user(user) {
+            tracing::warn!(error = %e, user_id = %info.user_id, "Failed to store user");
+            return responses::error_response(
+                StatusCode::CONFLICT,
+                &format!("Failed to create user: {}", e),
+                false,
+            );
+        }
+
+        self.metrics.record_admin_operation("user_create");
+        auditor.record(
+            "user_create",
+            &info.user_id,
+            &format!("is_admin={}", info.is_admin),
+        );
+        tracing::info!(user_id = %info.user_id, is_admin = info.is_admin, "User created via admin API");
+        let (buckets, template_error) =
+            match apply_template(template, &info.user_id, &self.user_router, &self.user_store) {
+                Ok(buckets) => (buckets, None),
+                Err(e) => (Vec::new(), Some(e)),
+            };
+        responses::json_response(
+            StatusCode::CREATED,
+            &CreatedUser {
+                user: info,
+                ui_password,
+                s3_secret_key,
+                buckets,
+                template_error,
+            },
+        )
+    }
+
+    fn delete_user(
+        &self,
+        user_id: &str,
+        req: &Request<Incoming>,
+        jobs: Option<&Arc<JobManager>>,
+        auditor: Auditor<'_>,
+    ) -> Response<HttpBody> {
+        let query = req.uri().query().unwrap_or("");
+        let policy = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("policy="));
+        let policy = match policy.map(str::parse::<DeletePolicy>).transpose() {
+            Ok(policy) => policy.unwrap_or_default(),
+            Err(message) => return bad_request(&message),
+        };
+
+        let deleter = UserDeleter {
+            user_router: &self.user_router,
+            user_store: &self.user_store,
+            session_store: &self.session_store,
+            jobs,
+        };
+        let (status, job) = match deleter.delete(user_id, policy) {
+            Ok(Deletion::Done) => (StatusCode::OK, None),
+            Ok(Deletion::Started(record)) => (StatusCode::ACCEPTED, Some(JobInfo::from(record))),
+            Err(e) => return delete_error(e),
+        };
+        self.metrics.record_admin_operation("user_delete");
+        auditor.record(
+            "user_delete",
+            user_id,
+            &format!("policy={}", policy.as_str()),
+        );
+        tracing::info!(user_id = %user_id, policy = policy.as_str(), "User deleted via admin API");
+        responses::json_response(
+            status,
+            &DeletedUser {
+                deleted: user_id.to_string(),
+                job,
+            },
+        )
+    }
+
+    fn list_buckets(&self, user_id: &str) -> Response<HttpBody> {
+        if let Some(resp) = self.require_user(user_id) {
+            return resp;
+        }
+
+        let casfs = match self.user_router.get_casfs_by_user_id(user_id) {
+            Ok(casfs) => casfs,
+            Err(e) => return internal_error("Failed to open user storage", e),
+        };
+        match casfs.list_buckets() {
+            Ok(buckets) => {
+                let buckets: Vec<BucketInfo> = buckets
+                    .iter()
+                    .map(|bucket| BucketInfo {
+                        name: bucket.name().to_string(),
+                        created_at: bucket
+                            .ctime()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs(),
+                    })
+                    .collect();
+                responses::json_response(StatusCode::OK, &buckets)
+            }
+            Err(e) => internal_error("Failed to list buckets", e),
+        }
+    }
+
+    fn usage(&self, user_id: &str) -> Response<HttpBody> {
+        if let Some(resp) = self.require_user(user_id) {
+            return resp;
+        }
+
+        let casfs = match self.user_router.get_casfs_by_user_id(user_id) {
+            Ok(casfs) => casfs,
+            Err(e) => return internal_error("Failed to open user storage", e),
+        };
+        let usage = match compute_usage(&casfs) {
+            Ok(usage) => usage,
+            Err(e) => return internal_error("Failed to compute usage", e),
+        };
+        let quota_bytes = match self.user_store.get_quota(user_id) {
+            Ok(quota) => quota,
+            Err(e) => return internal_error("Failed to get quota", e),
+        };
+
+        responses::json_response(
+            StatusCode::OK,
+            &UsageInfo {
+                user_id: user_id.to_string(),
+                usage,
+                quota_bytes,
+            },
+        )
+    }
+
+    async fn set_quota(
+        &self,
+        user_id: &str,
+        req: Request<Incoming>,
+        auditor: Auditor<'_>,
+    ) -> Response<HttpBody> {
+        let request: QuotaRequest = match read_json(req).await {
+            Ok(request) => request,
+            Err(resp) => return resp,
+        };
+        if let Some(resp) = self.require_user(user_id) {
+            return resp;
+        }
+
+        match self.user_store.set_quota(user_id, request.max_bytes) {
+            Ok(()) => {
+                self.metrics.record_admin_operation("quota_set");
+                let max_bytes = request
+                    .max_bytes
+                    .map_or("none".to_string(), |m| m.to_string());
+                auditor.record("quota_set", user_id, &format!("max_bytes={}", max_bytes));
+                tracing::info!(user_id = %user_id, max_bytes = ?request.max_bytes, "Quota set via admin API");
+                responses::json_response(StatusCode::OK, &request)
+            }
+            Err(e) => internal_error("Failed to set quota", e),
+        }
+    }
+
+    async fn set_sig_v2(
+        &self,
+        user_id: &str,
+        req: Request<Incoming>,
+        auditor: Auditor<'_>,
+    ) -> Response<HttpBody> {
+        let request: SigV2Request = match read_json(req).await {
+            Ok(request) => request,
+            Err(resp) => return resp,
+        };
+        if let Some(resp) = self.require_user(user_id) {
+            return resp;
+        }
+
+        match self.user_store.set_sig_v2(user_id, request.enabled) {
+            Ok(()) => {
+                self.metrics.record_admin_operation("sigv2_set");
+                auditor.record(
+                    "sigv2_set",
+                    user_id,
+                    &format!("enabled={}", request.enabled),
+                );
+                tracing::info!(user_id = %user_id, enabled = request.enabled, "SigV2 set via admin API");
+                responses::json_response(StatusCode::OK, &request)
+            }
+            Err(e) => internal_error("Failed to set SigV2", e),
+        }
+    }
+
+    fn list_keys(&self, user_id: &str) -> Response<HttpBody> {
+        if let Some(resp) = self.require_user(user_id) {
+            return resp;
+        }
+
+        match self.user_store.list_s3_keys(user_id) {
+            Ok(keys) => responses::json_response(StatusCode::OK, &keys),
+            Err(e) => internal_error("Failed to list keys", e),
+        }
+    }
+
+    async fn rotate_key(
+        &self,
+        user_id: &str,
+        req: Request<Incoming>,
+        auditor: Auditor<'_>,
+    ) -> Response<HttpBody> {
+        let request: RotateKeyRequest = match read_json(req).await {
+            Ok(request) => request,
+            Err(resp) => return resp,
+        };
+        if let Some(resp) = self.require_user(user_id) {
+            return resp;
+        }
+
+        let grace_secs = request.grace_secs.unwrap_or(DEFAULT_KEY_GRACE_SECS);
+        let user = match self.user_store.rotate_s3_key(
+            user_id,
+            generate_access_key(),
+            generate_secret_key(),
+            grace_secs,
+        ) {
+            Ok(user) => user,
+            Err(e) => return internal_error("Failed to rotate key", e),
+        };
+        let keys = match self.user_store.list_s3_keys(user_id) {
+            Ok(keys) => keys,
+            Err(e) => return internal_error("Failed to list keys", e),
+        };
+
+        self.metrics.record_admin_operation("key_rotate");
+        auditor.record(
+            "key_rotate",
+            user_id,
+            &format!(
+                "access_key={} grace_secs={}",
+                user.s3_access_key, grace_secs
+            ),
+        );
+        tracing::info!(user_id = %user_id, grace_secs, "S3 key rotated via admin API");
+        responses::json_response(
+            StatusCode::CREATED,
+            &RotatedKey {
+                s3_access_key: user.s3_access_key,
+                s3_secret_key: user.s3_secret_key,
+                keys,
+            },
+        )
+    }
+
+    fn revoke_key(
+        &self,
+        user_id: &str,
+        access_key: &str,
+        auditor: Auditor<'_>,
+    ) -> Response<HttpBody> {
+        if let Some(resp) = self.require_user(user_id) {
+            return resp;
+        }
+
+        match self.user_store.revoke_s3_key(user_id, access_key) {
+            Ok(()) => {
+                self.metrics.record_admin_operation("key_revoke");
+                auditor.record("key_revoke", user_id, &format!("access_key={}", access_key));
+                tracing::info!(user_id = %user_id, access_key = %access_key, "S3 key revoked via admin API");
+                responses::json_response(StatusCode::OK, &serde_json::json!({ "revoked": access_key }))
+            }
+            Err(e) => bad_request(&format!("Failed to revoke key: {}", e)),
+        }
+    }
+
+    async fn start_takeout(
+        &self,
+        user_id: &str,
+        req: Request<Incoming>,
+        jobs: Option<&Arc<JobManager>>,
+        audit: Option<&AuditLog>,
+        auditor: Auditor<'_>,
+    ) -> Response<HttpBody> {
+        let request: TakeoutRequest = match read_json(req).await {
+            Ok(request) => request,
+            Err(resp) => return resp,
+        };
+        // the entries are taken now, the archive holds the log up to the takeout
+        let entries = match audit.map(AuditLog::entries).transpose() {
+            Ok(entries) => entries.unwrap_or_default(),
+            Err(e) => return internal_error("Failed to read the audit log", e),
+        };
+        let entries = entries
+            .into_iter()
+            .filter(|entry| entry.target == user_id)
+            .collect();
+
+        let deleter = UserDeleter {
+            user_router: &self.user_router,
+            user_store: &self.user_store,
+            session_store: &self.session_store,
+            jobs,
+        };
+        let record = match deleter.takeout(user_id, entries, request.delete_after) {
+            Ok(record) => record,
+            Err(e) => return delete_error(e),
+        };
+        self.metrics.record_admin_operation("user_takeout");
+        auditor.record(
+            "user_takeout",
+            user_id,
+            &format!("job={} delete_after={}", record.id, request.delete_after),
+        );
+        tracing::info!(user_id = %user_id, job = record.id, delete_after = request.delete_after, "Takeout started via admin API");
+        responses::json_response(StatusCode::ACCEPTED, &JobInfo::from(record))
+    }
+
+    /// Serves the archive of a completed takeout job of the user, which might
+    /// be deleted by now
+    fn download_takeout(
+        &self,
+        user_id: &str,
+        job_id: &str,
+        jobs: Option<&Arc<JobManager>>,
+    ) -> Response<HttpBody> {
+        let Some(jobs) = jobs else {
+            return responses::error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "This server runs no jobs",
+                false,
+            );
+        };
+        let not_found = || {
+            responses::error_response(
+                StatusCode::NOT_FOUND,
+                &format!("No takeout job {} of user '{}'", job_id, user_id),
+                false,
+            )
+        };
+        let Ok(id) = job_id.parse::<u64>() else {
+            return not_found();
+        };
+        let record = match jobs.get(id) {
+            Ok(Some(record)) => record,
+            Ok(None) => return not_found(),
+            Err(e) => return internal_error("Failed to get job", e),
+        };
+        if record.kind != JobKind::UserTakeout.as_str() || record.target.as_deref() != Some(user_id)
+        {
+            return not_found();
+        }
+        if record.status != JobStatus::Completed {
+            return responses::error_response(
+                StatusCode::CONFLICT,
+                &format!("Takeout job {} is not completed: {:?}", id, record.status),
+                false,
+            );
+        }
+
+        let path = takeout_path(&self.user_router, user_id, id);
+        let size = match std::fs::metadata(&path) {
+            Ok(metadata) => metadata.len() as usize,
+            // removed by an operator
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return not_found(),
+            Err(e) => return internal_error("Failed to open takeout archive", e),
+        };
+        let filename = format!("takeout-{}-{}.tar", user_id, id);
+        responses::file_response(path, size, "application/x-tar", &filename)
+    }
+
+    /// Returns a 404 response if the user does not exist
+    fn require_user(&self, user_id: &str) -> Option<Response<HttpBody>> {
+        match self.user_store.get_user_by_id(user_id) {
+            Ok(Some(_)) => None,
+            Ok(None) => Some(responses::error_response(
+                StatusCode::NOT_FOUND,
+                &format!("User '{}' not found", user_id),
+                false,
+            )),
+            Err(e) => Some(internal_error("Failed to get user", e)),
+        }
+    }
+}
+
+async fn read_json<T: DeserializeOwned>(req: Request<Incoming>) -> Result<T, Response<HttpBody>> {
+    let body = match req.into_body().collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read request body");
+            return Err(bad_request("Invalid request"));
+        }
+    };
+    serde_json::from_slice(&body).map_err(|e| bad_request(&format!("Invalid JSON: {}", e)))
+}
+
+fn delete_error(e: DeleteError) -> Response<HttpBody> {
+    let status = match &e {
+        DeleteError::UnknownUser(_) => StatusCode::NOT_FOUND,
+        DeleteError::HasBuckets(_) | DeleteError::Job(JobError::AlreadyRunning(_)) => {
+            StatusCode::CONFLICT
+        }
+        DeleteError::NoJobs => StatusCode::SERVICE_UNAVAILABLE,
+        _ => return internal_error("Failed to delete user", e),
+    };
+    responses::error_response(status, &e.to_string(), false)
+}
+
+fn bad_request(message: &str) -> Response<HttpBody> {
+    responses::error_response(StatusCode::BAD_REQUEST, message, false)
+}
+
+fn internal_error(message: &str, error: impl std::fmt::Display) -> Response<HttpBody> {
+    tracing::warn!(error = %error, "{}", message);
+    responses::error_response(StatusCode::INTERNAL_SERVER_ERROR, message, false)
+}
@@ -0,0 +1,32 @@
+//! Static files of the HTTP UI, compiled into the binary.
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{Response, StatusCode};
+
+use super::responses::map_response;
+use super::HttpBody;
+
+/// Rules of the light theme, which is also the base of the dark one
+const STYLE_CSS: &str = include_str!("assets/style.css");
+/// Overrides of the dark theme
+const DARK_CSS: &str = include_str!("assets/dark.css");
+
+/// Serves a file under `/assets/`, `None` if there is no such file.
+///
+/// Pages link the files with the version as query parameter, so they can be
+/// cached for a day without an upgrade showing stale styles.
+pub fn serve(path: &str) -> Option<Response<HttpBody>> {
+    let css = match path.strip_prefix("/assets/")? {
+        "style.css" => STYLE_CSS,
+        "dark.css" => DARK_CSS,
+        _ => return None,
+    };
+    let resp = Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/css; charset=utf-8")
+        .header("cache-control", "public, max-age=86400")
+        .body(Full::new(Bytes::from_static(css.as_bytes())))
+        .unwrap();
+    Some(map_response(resp))
+}
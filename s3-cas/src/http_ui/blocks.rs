@@ -0,0 +1,283 @@
+//! Client-side deduplication: a client splits a file into blocks like the store
+//! does, asks which blocks the store is missing, uploads only those and then
+//! assembles the object from the hashes of its blocks.
+
+use std::collections::HashSet;
+
+use faster_hex::hex_string;
+use http_body_util::{BodyExt, Limited};
+use hyper::{
+    body::{Body, Incoming},
+    Request, Response, StatusCode,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use cas_storage::{BlockID, CasFS, MetaError};
+
+use super::by_hash::parse_hash;
+use super::{responses, HttpBody};
+
+/// Most blocks a single check or assembled object can list
+const MAX_BLOCKS: usize = 100_000;
+
+/// Largest accepted check or assemble request body, enough for [`MAX_BLOCKS`]
+const MAX_BLOCKS_REQUEST_SIZE: usize = 4 * 1024 * 1024;
+
+/// Request body of `POST /api/v1/buckets/{bucket}/blocks/check`
+#[derive(Deserialize)]
+pub struct BlockCheckRequest {
+    /// Hex encoded hashes of the blocks
+    pub blocks: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct BlockCheckResponse {
+    /// Size of the blocks the store splits objects into, all but the last block
+    /// of an object must have this size to be deduplicated with uploads
+    pub block_size: usize,
+    /// Hash function of the blocks
+    pub hash: String,
+    /// The blocks to upload before assembling an object from them
+    pub missing: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct StagedBlock {
+    pub bucket: String,
+    pub block: String,
+    pub size: usize,
+}
+
+/// Request body of `POST /api/v1/buckets/{bucket}/assemble`
+#[derive(Deserialize)]
+pub struct AssembleRequest {
+    /// Key of the new object
+    pub key: String,
+    /// Hex encoded hashes of the blocks of the object, in order
+    pub blocks: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct AssembleResponse {
+    pub bucket: String,
+    pub key: String,
+    pub size: u64,
+    pub etag: String,
+    pub blocks: usize,
+}
+
+fn parse_blocks(hashes: &[String]) -> Result<Vec<BlockID>, Response<HttpBody>> {
+    if hashes.len() > MAX_BLOCKS {
+        return Err(responses::error_response(
+            StatusCode::BAD_REQUEST,
+            &format!("At most {MAX_BLOCKS} blocks can be listed"),
+            false,
+        ));
+    }
+    hashes
+        .iter()
+        .map(|hash| {
+            parse_hash(hash).ok_or_else(|| {
+                responses::error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!("Invalid block hash {hash}"),
+                    false,
+                )
+            })
+        })
+        .collect()
+}
+
+/// Reads a JSON request body. Only JSON bodies are accepted, browsers can't send
+/// those cross-site without a preflight request.
+pub(super) async fn read_json<T, B>(req: Request<B>) -> Result<T, Response<HttpBody>>
+where
+    T: DeserializeOwned,
+    B: Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let is_json = req
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !is_json {
+        return Err(responses::error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected an application/json body",
+            false,
+        ));
+    }
+    let body = match Limited::new(req.into_body(), MAX_BLOCKS_REQUEST_SIZE)
+        .collect()
+        .await
+    {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read block request body");
+            return Err(responses::error_response(
+                StatusCode::BAD_REQUEST,
+                "Invalid request",
+                false,
+            ));
+        }
+    };
+    serde_json::from_slice(&body).map_err(|e| {
+        responses::error_response(
+            StatusCode::BAD_REQUEST,
+            &format!("Invalid JSON: {e}"),
+            false,
+        )
+    })
+}
+
+pub(super) fn error_response(e: MetaError, action: &str) -> Response<HttpBody> {
+    match e {
+        MetaError::BucketNotFound => {
+            responses::error_response(StatusCode::NOT_FOUND, "Bucket not found", false)
+        }
+        MetaError::BlockNotFound => responses::error_response(
+            StatusCode::CONFLICT,
+            "A block is missing, check the blocks and upload the missing ones",
+            false,
+        ),
+        MetaError::InvalidArgument(message) => {
+            responses::error_response(StatusCode::BAD_REQUEST, &message, false)
+        }
+        e => responses::error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error {action}: {e}"),
+            false,
+        ),
+    }
+}
+
+/// Handles POST /api/v1/buckets/{bucket}/blocks/check
+pub async fn check_blocks(
+    casfs: &CasFS,
+    bucket: &str,
+    req: Request<Incoming>,
+) -> Response<HttpBody> {
+    let request: BlockCheckRequest = match read_json(req).await {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    let blocks = match parse_blocks(&request.blocks) {
+        Ok(blocks) => blocks,
+        Err(response) => return response,
+    };
+    if !casfs.bucket_exists(bucket).unwrap_or(false) {
+        return responses::error_response(StatusCode::NOT_FOUND, "Bucket not found", false);
+    }
+    match casfs.has_blocks(bucket, &blocks) {
+        Ok(present) => {
+            let mut listed = HashSet::new();
+            let missing = blocks
+                .iter()
+                .zip(present)
+                .filter(|(id, present)| !present && listed.insert(*id))
+                .map(|(id, _)| hex_string(id))
+                .collect();
+            let response = BlockCheckResponse {
+                block_size: casfs.block_size(),
+                hash: casfs.content_hash().to_string(),
+                missing,
+            };
+            responses::json_response(StatusCode::OK, &response)
+        }
+        Err(e) => error_response(e, "checking blocks"),
+    }
+}
+
+/// Handles PUT /api/v1/buckets/{bucket}/blocks/{hash}, the body is the data of
+/// the block
+pub async fn put_block(
+    casfs: &CasFS,
+    bucket: &str,
+    hash: &str,
+    req: Request<Incoming>,
+) -> Response<HttpBody> {
+    let Some(id) = parse_hash(hash) else {
+        return responses::error_response(StatusCode::BAD_REQUEST, "Invalid block hash", false);
+    };
+    let data = match Limited::new(req.into_body(), casfs.block_size())
+        .collect()
+        .await
+    {
+        Ok(collected) => collected.to_bytes(),
+        Err(_) => {
+            return responses::error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                &format!("A block holds at most {} bytes", casfs.block_size()),
+                false,
+            )
+        }
+    };
+    let size = data.len();
+    match casfs.stage_block(bucket, &id, data).await {
+        Ok(()) => {
+            tracing::debug!(bucket, block = %hex_string(&id), size, "Staged block");
+            let response = StagedBlock {
+                bucket: bucket.to_string(),
+                block: hex_string(&id),
+                size,
+            };
+            responses::json_response(StatusCode::OK, &response)
+        }
+        Err(e) => error_response(e, "storing block"),
+    }
+}
+
+/// Handles POST /api/v1/buckets/{bucket}/assemble
+pub async fn assemble_object<B>(casfs: &CasFS, bucket: &str, req: Request<B>) -> Response<HttpBody>
+where
+    B: Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let request: AssembleRequest = match read_json(req).await {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    if request.key.is_empty() {
+        return responses::error_response(StatusCode::BAD_REQUEST, "Missing object key", false);
+    }
+    let blocks = match parse_blocks(&request.blocks) {
+        Ok(blocks) => blocks,
+        Err(response) => return response,
+    };
+    match casfs.assemble_object(bucket, &request.key, &blocks).await {
+        Ok(obj) => {
+            tracing::info!(bucket, key = %request.key, blocks = blocks.len(), size = obj.size(), "Assembled object");
+            let response = AssembleResponse {
+                bucket: bucket.to_string(),
+                key: request.key,
+                size: obj.size(),
+                etag: obj.format_e_tag(),
+                blocks: blocks.len(),
+            };
+            responses::json_response(StatusCode::OK, &response)
+        }
+        Err(e) => error_response(e, "assembling object"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_blocks() {
+        let hash = "00112233445566778899aabbccddeeff".to_string();
+        let blocks = parse_blocks(&[hash.clone(), hash.to_uppercase()])
+            .ok()
+            .unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0], blocks[1]);
+        assert_eq!(blocks[0][..2], [0x00, 0x11]);
+
+        assert!(parse_blocks(&[hash[2..].to_string()]).is_err());
+        assert!(parse_blocks(&["zz".repeat(16)]).is_err());
+        assert!(parse_blocks(&vec![hash; MAX_BLOCKS + 1]).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! Content addressed downloads: `/_cas/blocks/{hash}` serves a single block and
+//! `/_cas/objects/{hash}` an object by the hash of its content, the hex encoded
+//! MD5 for objects uploaded in a single part.
+//!
+//! Only content the caller references is served, a block must be used by one of
+//! their objects and an object must be theirs. Both are looked up in the block
+//! reference index, so the store must run with `--block-refs-index`.
+
+use bytes::Bytes;
+use faster_hex::hex_decode;
+use futures::StreamExt;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::Frame;
+use hyper::header::{CACHE_CONTROL, ETAG};
+use hyper::{Request, Response, StatusCode};
+
+use cas_storage::{BlockID, BlockStream, CasFS, RangeRequest, SharedMetrics};
+
+use crate::http_cache::etag_matches;
+
+use super::{handlers, responses, HttpBody};
+
+/// Prefix of the block downloads
+pub const BLOCKS_PREFIX: &str = "/_cas/blocks/";
+
+/// Prefix of the object downloads
+pub const OBJECTS_PREFIX: &str = "/_cas/objects/";
+
+/// The content of a block never changes, only the caller may cache it
+const BLOCK_CACHE_CONTROL: &str = "private, max-age=31536000, immutable";
+
+/// The block id of a hex encoded hash
+pub(super) fn parse_hash(hash: &str) -> Option<BlockID> {
+    let mut id = BlockID::default();
+    if hash.len() != 2 * id.len() || hex_decode(hash.as_bytes(), &mut id).is_err() {
+        return None;
+    }
+    Some(id)
+}
+
+/// The hash of the path, or the response to return if it can't be served
+fn lookup(casfs: &CasFS, hash: &str) -> Result<BlockID, Response<HttpBody>> {
+    let Some(id) = parse_hash(hash) else {
+        return Err(responses::error_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid hash, expected 32 hex characters",
+            false,
+        ));
+    };
+    match casfs.has_block_refs() {
+        Ok(true) => Ok(id),
+        Ok(false) => Err(responses::error_response(
+            StatusCode::NOT_IMPLEMENTED,
+            "Lookups by hash need the block reference index (--block-refs-index)",
+            false,
+        )),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to check the block reference index");
+            Err(responses::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error looking up hash",
+                false,
+            ))
+        }
+    }
+}
+
+/// Handles GET /_cas/blocks/{hash}
+pub async fn download_block<B>(casfs: &CasFS, hash: &str, req: &Request<B>) -> Response<HttpBody> {
+    let id = match lookup(casfs, hash) {
+        Ok(id) => id,
+        Err(response) => return response,
+    };
+    // the block is pinned so a concurrent delete can't remove it while streaming
+    let (path, size, pin) = match casfs.referenced_block_pinned(&id) {
+        Ok(Some(block)) => block,
+        Ok(None) => {
+            return responses::error_response(StatusCode::NOT_FOUND, "Block not found", false)
+        }
+        Err(e) => {
+            tracing::warn!(hash, error = %e, "Failed to get block");
+            return responses::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error getting block",
+                false,
+            );
+        }
+    };
+
+    let etag = format!("\"{}\"", hash.to_ascii_lowercase());
+    let builder = Response::builder()
+        .header(ETAG, &etag)
+        .header(CACHE_CONTROL, BLOCK_CACHE_CONTROL);
+    if matches!(handlers::if_none_match(req), Some(condition) if etag_matches(condition, &etag)) {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Full::new(Bytes::new()))
+            .map(responses::map_response)
+            .unwrap();
+    }
+
+    let blocks = BlockStream::new(
+        vec![(path, size)],
+        size,
+        RangeRequest::All,
+        SharedMetrics::default(),
+    )
+    .with_pin(pin)
+    .map(|res| {
+        res.map(Frame::data)
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    });
+    builder
+        .status(StatusCode::OK)
+        .header("content-type", "application/octet-stream")
+        .header("content-length", size)
+        .body(BodyExt::boxed(StreamBody::new(blocks)))
+        .unwrap()
+}
+
+/// Handles GET /_cas/objects/{hash}, serving the first object of the caller with
+/// this content
+pub async fn download_object<B>(casfs: &CasFS, hash: &str, req: &Request<B>) -> Response<HttpBody> {
+    let id = match lookup(casfs, hash) {
+        Ok(id) => id,
+        Err(response) => return response,
+    };
+    match casfs.objects_by_hash(&id) {
+        Ok(objects) => match objects.first() {
+            Some(object) => {
+                handlers::download_object(
+                    casfs,
+                    &object.bucket,
+                    &object.key,
+                    handlers::if_none_match(req),
+                )
+                .await
+            }
+            None => responses::error_response(StatusCode::NOT_FOUND, "Object not found", false),
+        },
+        Err(e) => {
+            tracing::warn!(hash, error = %e, "Failed to look up object by hash");
+            responses::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error getting object",
+                false,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hash() {
+        let hash = "00112233445566778899aabbccddeeff";
+        assert_eq!(parse_hash(hash).unwrap()[..2], [0x00, 0x11]);
+        assert_eq!(parse_hash(&hash.to_uppercase()), parse_hash(hash));
+        assert_eq!(parse_hash(&hash[2..]), None);
+        assert_eq!(parse_hash(&format!("{hash}00")), None);
+        assert_eq!(parse_hash("zz112233445566778899aabbccddeeff"), None);
+    }
+}
@@ -1,13 +1,26 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 use bytes::Bytes;
-use http_body_util::{Full, BodyExt, StreamBody};
-use hyper::{Request, Response, StatusCode, body::Frame};
-use serde::Serialize;
+use http_body_util::{Full, BodyExt, Limited, StreamBody};
+use hyper::{Request, Response, StatusCode, body::{Frame, Incoming}};
+use serde::{Deserialize, Serialize};
 
 use cas_storage::{CasFS, BlockStream, RangeRequest};
-use cas_storage::BucketMeta;
+use cas_storage::{BucketLifecycle, ObjectTags};
+use cas_storage::{BucketLimits, BucketMeta, BucketUsage, MetaError, MetaTreeExt, Object, TagFilter, UsageSample};
+use cas_storage::ACTIVITY_WINDOW_MINUTES;
 
+use crate::http_cache::etag_matches;
+use crate::listing::{self, group_by_top_level};
+
+use super::blocks;
+use super::idempotency;
+use super::index_page::{find_index_page, IndexPage};
+use super::list_preferences::{Column, ListPreferences, SortKey};
+use super::openapi::{self, Body, Param, Route};
+use super::preview;
+use super::resumable;
+use super::ui::Ui;
 use super::{responses, templates, HttpBody};
 
 #[derive(Serialize)]
@@ -25,10 +38,33 @@ impl From<&BucketMeta> for BucketInfo {
     }
 }
 
+#[derive(Serialize)]
+pub struct BucketUsageReport {
+    pub bucket: String,
+    /// The tags of the bucket, to group usage by project or team
+    pub tags: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub current: BucketUsage,
+    /// Daily samples, oldest first, the last one is the current usage
+    pub history: Vec<UsageSample>,
+}
+
+/// Output format of the usage report
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Html,
+    Json,
+    Csv,
+}
+
 #[derive(Serialize, Hash, Eq, PartialEq, Clone)]
 pub struct DirectoryInfo {
     pub name: String,
     pub prefix: String,
+    /// Objects under the directory, known for the directories at the top level
+    /// of the bucket from its prefix counts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub objects: Option<u64>,
 }
 
 #[derive(Serialize)]
@@ -37,8 +73,24 @@ pub struct ObjectInfo {
     pub size: u64,
     pub hash: String,
     pub last_modified: String,
+    /// Modification time in seconds since the UNIX epoch, to sort by
+    #[serde(skip)]
+    pub modified_secs: u64,
     pub is_inlined: bool,
     pub block_count: usize,
+    /// Size of the distinct blocks, when the physical size column is shown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub physical_size: Option<u64>,
+    /// Expiry by the lifecycle rules of the bucket, if a rule applies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<ExpirationInfo>,
+}
+
+#[derive(Serialize)]
+pub struct ExpirationInfo {
+    /// The rule expiring the object first
+    pub rule_id: String,
+    pub expiry_date: String,
 }
 
 #[derive(Serialize)]
@@ -49,7 +101,21 @@ pub struct ObjectListResponse {
     pub objects: Vec<ObjectInfo>,
     pub total_count: usize,
     pub has_more: bool,
+    /// Token of the next page, passed as `token`
     pub next_token: Option<String>,
+    pub has_prev: bool,
+    /// Token of the previous page, passed as `before`
+    pub prev_token: Option<String>,
+    /// The `key=value` tag the objects were filtered by
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// `README.md` or `index.html` at the listed prefix, on the first page only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_page: Option<IndexPage>,
+    /// Keys under the prefix of the objects using corrupt blocks, which may be
+    /// unreadable
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub degraded_objects: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -58,30 +124,393 @@ pub struct ObjectMetadata {
     pub bucket: String,
     pub size: u64,
     pub hash: String,
+    pub etag: String,
     pub last_modified: String,
     pub is_inlined: bool,
     pub blocks: Vec<BlockInfo>,
+    /// Hashes of the blocks of the object marked as corrupt and not healed, the
+    /// object may be unreadable
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub corrupt_blocks: Vec<String>,
+}
+
+/// Request body of `POST /api/v1/buckets/{bucket}/concat`
+#[derive(Deserialize)]
+pub struct ConcatRequest {
+    /// Key of the new object
+    pub key: String,
+    /// Keys of the objects to concatenate, in order
+    pub sources: Vec<String>,
 }
 
+#[derive(Serialize)]
+pub struct ConcatResponse {
+    pub bucket: String,
+    pub key: String,
+    pub size: u64,
+    pub etag: String,
+    pub parts: usize,
+}
+
+/// Most objects a single concatenation can reference, like the parts of a
+/// multipart upload
+const MAX_CONCAT_SOURCES: usize = 10_000;
+
+/// Largest accepted concatenation request body
+const MAX_CONCAT_REQUEST_SIZE: usize = 4 * 1024 * 1024;
+
+/// The limits of a bucket and the contents they apply to
+#[derive(Serialize)]
+pub struct BucketLimitsInfo {
+    pub bucket: String,
+    pub max_objects: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub objects: u64,
+    pub logical_bytes: u64,
+}
+
+/// Largest accepted bucket limits request body
+const MAX_LIMITS_REQUEST_SIZE: usize = 4096;
+
 #[derive(Serialize)]
 pub struct BlockInfo {
     pub hash: String,
     pub size: usize,
-    pub refcount: usize,
+    pub refcount: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiOp {
+    ListBuckets,
+    ListObjects,
+    ObjectMetadata,
+    ConcatObjects,
+    CheckBlocks,
+    PutBlock,
+    AssembleObject,
+    CreateUpload,
+    UploadStatus,
+    PutUpload,
+    AbortUpload,
+    GetBucketLimits,
+    PutBucketLimits,
+    Usage,
+    Activity,
+    OpenApi,
+}
+
+const LIST_OBJECTS_QUERY: &[Param] = &[
+    Param {
+        name: "prefix",
+        description: "List the level below this prefix, ending with `/`",
+        integer: false,
+    },
+    Param {
+        name: "limit",
+        description: "Entries per page, at most 1000",
+        integer: true,
+    },
+    Param {
+        name: "token",
+        description: "`next_token` of the previous page",
+        integer: false,
+    },
+    Param {
+        name: "before",
+        description: "`prev_token` of the next page",
+        integer: false,
+    },
+    Param {
+        name: "sort",
+        description: "Sort the page by `name`, `size`, `modified` or `blocks`",
+        integer: false,
+    },
+    Param {
+        name: "order",
+        description: "`asc` or `desc`",
+        integer: false,
+    },
+    Param {
+        name: "columns",
+        description: "Comma separated columns, `physical` adds `physical_size` to the objects",
+        integer: false,
+    },
+    Param {
+        name: "tag",
+        description: "Only objects with this `key=value` tag, across the whole bucket",
+        integer: false,
+    },
+];
+
+/// Routes of the JSON API, served in both single-user and multi-user mode
+pub const API_ROUTES: &[Route<ApiOp>] = &[
+    Route {
+        op: ApiOp::ListBuckets,
+        method: "GET",
+        path: "/api/v1/buckets",
+        tail: false,
+        summary: "List the buckets",
+        query: &[],
+        request: None,
+        status: 200,
+        response: Body::List("BucketInfo"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::ListObjects,
+        method: "GET",
+        path: "/api/v1/buckets/{bucket}",
+        tail: false,
+        summary: "List a page of the objects and directories of a bucket",
+        query: LIST_OBJECTS_QUERY,
+        request: None,
+        status: 200,
+        response: Body::Object("ObjectListResponse"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::ObjectMetadata,
+        method: "GET",
+        path: "/api/v1/buckets/{bucket}/objects/{key}",
+        tail: true,
+        summary: "Metadata and blocks of an object",
+        query: &[],
+        request: None,
+        status: 200,
+        response: Body::Object("ObjectMetadata"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::ConcatObjects,
+        method: "POST",
+        path: "/api/v1/buckets/{bucket}/concat",
+        tail: false,
+        summary: "Create an object as the concatenation of existing objects, without copying data",
+        query: &[],
+        request: Some("ConcatRequest"),
+        status: 200,
+        response: Body::Object("ConcatResponse"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::CheckBlocks,
+        method: "POST",
+        path: "/api/v1/buckets/{bucket}/blocks/check",
+        tail: false,
+        summary: "Which of the listed blocks must be uploaded to assemble an object from them",
+        query: &[],
+        request: Some("BlockCheckRequest"),
+        status: 200,
+        response: Body::Object("BlockCheckResponse"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::PutBlock,
+        method: "PUT",
+        path: "/api/v1/buckets/{bucket}/blocks/{hash}",
+        tail: false,
+        summary: "Upload the data of a missing block, which must match its hash",
+        query: &[],
+        request: None,
+        status: 200,
+        response: Body::Object("StagedBlock"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::AssembleObject,
+        method: "POST",
+        path: "/api/v1/buckets/{bucket}/assemble",
+        tail: false,
+        summary: "Create an object from the hashes of its blocks, without uploading them again",
+        query: &[],
+        request: Some("AssembleRequest"),
+        status: 200,
+        response: Body::Object("AssembleResponse"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::CreateUpload,
+        method: "POST",
+        path: "/api/v1/buckets/{bucket}/uploads",
+        tail: false,
+        summary: "Start a resumable upload of an object",
+        query: &[],
+        request: Some("CreateUploadRequest"),
+        status: 201,
+        response: Body::Object("ResumableUploadInfo"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::UploadStatus,
+        method: "GET",
+        path: "/api/v1/buckets/{bucket}/uploads/{token}",
+        tail: false,
+        summary: "How much of a resumable upload is stored",
+        query: &[],
+        request: None,
+        status: 200,
+        response: Body::Object("ResumableUploadInfo"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::PutUpload,
+        method: "PUT",
+        path: "/api/v1/buckets/{bucket}/uploads/{token}",
+        tail: false,
+        summary: "Send the data of the Content-Range of a resumable upload, \
+            the upload is finished when the range ends at the total size",
+        query: &[],
+        request: None,
+        status: 200,
+        response: Body::Object("ResumableUploadInfo"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::AbortUpload,
+        method: "DELETE",
+        path: "/api/v1/buckets/{bucket}/uploads/{token}",
+        tail: false,
+        summary: "Cancel a resumable upload and release the data it stored",
+        query: &[],
+        request: None,
+        status: 200,
+        response: Body::Object("ResumableUploadInfo"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::GetBucketLimits,
+        method: "GET",
+        path: "/api/v1/buckets/{bucket}/limits",
+        tail: false,
+        summary: "Object count and size limits of a bucket, with its current contents",
+        query: &[],
+        request: None,
+        status: 200,
+        response: Body::Object("BucketLimitsInfo"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::PutBucketLimits,
+        method: "PUT",
+        path: "/api/v1/buckets/{bucket}/limits",
+        tail: false,
+        summary: "Replace the limits of a bucket, a missing or null limit removes it",
+        query: &[],
+        request: Some("BucketLimits"),
+        status: 200,
+        response: Body::Object("BucketLimitsInfo"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::Usage,
+        method: "GET",
+        path: "/api/v1/usage",
+        tail: false,
+        summary: "Usage of every bucket, with its daily history",
+        query: &[],
+        request: None,
+        status: 200,
+        response: Body::List("BucketUsageReport"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::Activity,
+        method: "GET",
+        path: "/api/v1/activity",
+        tail: false,
+        summary: "Requests and transferred object data of the buckets, busiest first",
+        query: &[Param {
+            name: "minutes",
+            description: "Window of the activity in minutes, at most 60 (the default)",
+            integer: true,
+        }],
+        request: None,
+        status: 200,
+        response: Body::List("BucketActivity"),
+        public: false,
+    },
+    Route {
+        op: ApiOp::OpenApi,
+        method: "GET",
+        path: openapi::SPEC_PATH,
+        tail: false,
+        summary: "This OpenAPI description",
+        query: &[],
+        request: None,
+        status: 200,
+        response: Body::Any,
+        public: true,
+    },
+];
+
+/// Serves a request of the JSON API, routed by [`API_ROUTES`]
+pub async fn api_request(casfs: &CasFS, req: Request<Incoming>) -> Response<HttpBody> {
+    let Some((op, params)) = openapi::match_route(API_ROUTES, req.method(), req.uri().path()) else {
+        return responses::not_found(false);
+    };
+    // JSON responses don't depend on the theme and language
+    let ui = Ui::default();
+
+    match (op, params.as_slice()) {
+        (ApiOp::ListBuckets, []) => list_buckets(casfs, false, None, &ui).await,
+        (ApiOp::ListObjects, [bucket]) => {
+            let prefs = ListPreferences::from_query(req.uri().query().unwrap_or(""));
+            list_objects(casfs, bucket, &req, false, &prefs, &ui).await
+        }
+        (ApiOp::ObjectMetadata, [bucket, key]) => {
+            object_metadata(casfs, bucket, key, false, if_none_match(&req), &ui, false).await
+        }
+        (ApiOp::ConcatObjects, [bucket]) => {
+            idempotency::handle(casfs, req, |req| concat_objects(casfs, bucket, req)).await
+        }
+        (ApiOp::CheckBlocks, [bucket]) => blocks::check_blocks(casfs, bucket, req).await,
+        (ApiOp::PutBlock, [bucket, hash]) => blocks::put_block(casfs, bucket, hash, req).await,
+        (ApiOp::AssembleObject, [bucket]) => {
+            idempotency::handle(casfs, req, |req| {
+                blocks::assemble_object(casfs, bucket, req)
+            })
+            .await
+        }
+        (ApiOp::CreateUpload, [bucket]) => {
+            idempotency::handle(casfs, req, |req| {
+                resumable::create_upload(casfs, bucket, req)
+            })
+            .await
+        }
+        (ApiOp::UploadStatus, [bucket, token]) => {
+            resumable::upload_status(casfs, bucket, token).await
+        }
+        (ApiOp::PutUpload, [bucket, token]) => {
+            resumable::put_upload(casfs, bucket, token, req).await
+        }
+        (ApiOp::AbortUpload, [bucket, token]) => {
+            resumable::abort_upload(casfs, bucket, token).await
+        }
+        (ApiOp::GetBucketLimits, [bucket]) => bucket_limits(casfs, bucket, false, None, &ui).await,
+        (ApiOp::PutBucketLimits, [bucket]) => put_bucket_limits(casfs, bucket, req).await,
+        (ApiOp::Usage, []) => usage_report(casfs, ReportFormat::Json, None, &ui).await,
+        (ApiOp::Activity, []) => match activity_minutes(req.uri().query()) {
+            Ok(minutes) => responses::json_response(StatusCode::OK, &casfs.bucket_activity(minutes)),
+            Err(message) => responses::error_response(StatusCode::BAD_REQUEST, &message, false),
+        },
+        (ApiOp::OpenApi, []) => responses::json_response(StatusCode::OK, &openapi::spec()),
+        _ => responses::not_found(false),
+    }
 }
 
 pub async fn list_buckets(
     casfs: &CasFS,
     wants_html: bool,
     is_admin: Option<bool>,
+    ui: &Ui,
 ) -> Response<HttpBody> {
     match casfs.list_buckets() {
         Ok(buckets) => {
             let bucket_infos: Vec<BucketInfo> = buckets.iter().map(BucketInfo::from).collect();
             if wants_html {
                 let page = match is_admin {
-                    Some(admin) => templates::buckets_page_with_user(&bucket_infos, admin),
-                    None => templates::buckets_page(&bucket_infos),
+                    Some(admin) => templates::buckets_page_with_user(ui, &bucket_infos, admin),
+                    None => templates::buckets_page(ui, &bucket_infos),
                 };
                 responses::html_response(StatusCode::OK, page)
             } else {
@@ -96,11 +525,141 @@ pub async fn list_buckets(
     }
 }
 
+/// Usage of every bucket of `casfs`, with the sampled history. The sample of
+/// today is replaced by the current usage, so the report is never behind.
+pub fn usage_reports(casfs: &CasFS) -> Result<Vec<BucketUsageReport>, MetaError> {
+    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    let history = casfs.usage_history();
+
+    let mut reports = Vec::new();
+    for bucket in casfs.list_buckets()? {
+        let current = casfs.bucket_usage(bucket.name())?;
+        let mut samples = history.samples(bucket.name())?;
+        samples.retain(|sample| sample.day != today);
+        samples.push(UsageSample {
+            day: today.clone(),
+            usage: current,
+        });
+        reports.push(BucketUsageReport {
+            bucket: bucket.name().to_string(),
+            tags: bucket
+                .tags()
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            current,
+            history: samples,
+        });
+    }
+    Ok(reports)
+}
+
+pub async fn usage_report(
+    casfs: &CasFS,
+    format: ReportFormat,
+    is_admin: Option<bool>,
+    ui: &Ui,
+) -> Response<HttpBody> {
+    match usage_reports(casfs) {
+        Ok(reports) => match format {
+            ReportFormat::Html => {
+                let hot = casfs.bucket_activity(ACTIVITY_WINDOW_MINUTES);
+                responses::html_response(StatusCode::OK, templates::usage_page(ui, &reports, &hot, is_admin))
+            }
+            ReportFormat::Json => responses::json_response(StatusCode::OK, &reports),
+            ReportFormat::Csv => responses::csv_response("s3-cas-usage.csv", usage_csv(&reports)),
+        },
+        Err(e) => responses::error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error computing bucket usage: {e}"),
+            format == ReportFormat::Html,
+        ),
+    }
+}
+
+/// The `minutes` of an activity query, the whole window without it
+pub fn activity_minutes(query: Option<&str>) -> Result<u64, String> {
+    let minutes = query
+        .unwrap_or("")
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("minutes="));
+    match minutes {
+        None => Ok(ACTIVITY_WINDOW_MINUTES),
+        Some(minutes) => match minutes.parse() {
+            Ok(minutes) if (1..=ACTIVITY_WINDOW_MINUTES).contains(&minutes) => Ok(minutes),
+            _ => Err(format!(
+                "Invalid minutes '{}', expected 1 to {}",
+                minutes, ACTIVITY_WINDOW_MINUTES
+            )),
+        },
+    }
+}
+
+/// One row per bucket and day. Bucket names can't contain commas or quotes, so
+/// no field needs quoting.
+/// The usage report as CSV, with a `tag:<key>` column for every tag key of the
+/// buckets, so spreadsheets can group the usage by tag.
+fn usage_csv(reports: &[BucketUsageReport]) -> String {
+    let tag_keys: BTreeSet<&str> = reports
+        .iter()
+        .flat_map(|report| report.tags.keys().map(String::as_str))
+        .collect();
+    let mut csv = String::from("bucket,day,objects,logical_bytes,physical_bytes");
+    for key in &tag_keys {
+        csv.push(',');
+        csv.push_str(&csv_field(&format!("tag:{key}")));
+    }
+    csv.push('\n');
+    for report in reports {
+        let tags: String = tag_keys
+            .iter()
+            .map(|key| {
+                let value = report.tags.get(*key).map_or("", String::as_str);
+                format!(",{}", csv_field(value))
+            })
+            .collect();
+        for sample in &report.history {
+            csv.push_str(&format!(
+                "{},{},{},{},{}{}\n",
+                report.bucket,
+                sample.day,
+                sample.usage.objects,
+                sample.usage.logical_bytes,
+                sample.usage.physical_bytes,
+                tags
+            ));
+        }
+    }
+    csv
+}
+
+/// Quotes a CSV field holding a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Keys under `prefix` of the degraded objects of `bucket`, see
+/// [`CasFS::list_degraded_objects`].
+fn degraded_keys(casfs: &CasFS, bucket: &str, prefix: &str) -> Result<Vec<String>, MetaError> {
+    Ok(casfs
+        .list_degraded_objects(Some(bucket))?
+        .into_iter()
+        .map(|object| object.key)
+        .filter(|key| key.starts_with(prefix))
+        .collect())
+}
+
 pub async fn list_objects(
     casfs: &CasFS,
     bucket: &str,
     req: &Request<hyper::body::Incoming>,
     wants_html: bool,
+    prefs: &ListPreferences,
+    ui: &Ui,
 ) -> Response<HttpBody> {
     // Check if bucket exists
     match casfs.bucket_exists(bucket) {
@@ -116,124 +675,553 @@ pub async fn list_objects(
         }
         Ok(true) => {}
     }
+    let lifecycle = match casfs.bucket_lifecycle(bucket) {
+        Ok(lifecycle) => lifecycle,
+        Err(e) => {
+            return responses::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error reading lifecycle rules: {e}"),
+                wants_html,
+            )
+        }
+    };
+    let lister = ObjectLister {
+        casfs,
+        bucket,
+        lifecycle: lifecycle.as_ref(),
+        prefs,
+    };
 
     // Parse query parameters
     let query_params = req.uri().query().unwrap_or("");
+    let param = |name: &str| {
+        query_params
+            .split('&')
+            .find_map(|p| p.strip_prefix(name)?.strip_prefix('='))
+            .map(|p| urlencoding::decode(p).unwrap_or_default().to_string())
+    };
 
-    let prefix = query_params
-        .split('&')
-        .find(|p| p.starts_with("prefix="))
-        .and_then(|p| p.strip_prefix("prefix="))
-        .map(|p| urlencoding::decode(p).unwrap_or_default().to_string())
-        .unwrap_or_default();
-
-    let limit: usize = query_params
-        .split('&')
-        .find(|p| p.starts_with("limit="))
-        .and_then(|p| p.strip_prefix("limit="))
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(100); // Default limit of 100 items
+    let prefix = param("prefix").unwrap_or_default();
+    let page = match (param("token"), param("before")) {
+        (Some(token), _) => PageRequest::After(token),
+        (None, Some(before)) => PageRequest::Before(before),
+        (None, None) => PageRequest::First,
+    };
 
-    let start_after = query_params
-        .split('&')
-        .find(|p| p.starts_with("token="))
-        .and_then(|p| p.strip_prefix("token="))
-        .map(|p| urlencoding::decode(p).unwrap_or_default().to_string());
+    if let Some(tag) = param("tag") {
+        let filter = match tag.parse::<TagFilter>() {
+            Ok(filter) => filter,
+            Err(e) => return responses::error_response(StatusCode::BAD_REQUEST, &e, wants_html),
+        };
+        return list_tagged_objects(&lister, &filter, prefix, page, wants_html, ui);
+    }
 
     // Get bucket tree and list objects
-    match casfs.get_bucket(bucket) {
-        Ok(tree) => {
-            let mut directories = HashSet::new();
-            let mut objects = Vec::new();
-            let mut last_key: Option<String> = None;
-            let mut item_count = 0;
-            let mut has_more = false;
-
-            // Use range_filter to get objects with the given prefix
-            for (key, obj) in tree.range_filter(start_after.clone(), Some(prefix.clone()), None) {
-                // Check if we've hit the limit
-                if item_count >= limit {
-                    has_more = true;
-                    break;
-                }
+    let tree = match casfs.get_bucket(bucket) {
+        Ok(tree) => tree,
+        Err(e) => {
+            return responses::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error listing objects: {e}"),
+                wants_html,
+            )
+        }
+    };
 
-                // Check if this key has subdirectories after the prefix
-                let relative_key = if prefix.is_empty() {
-                    key.as_str()
-                } else {
-                    key.strip_prefix(&prefix).unwrap_or(&key)
-                };
+    // the directories at the top level and their object counts, deeper
+    // directories are found by scanning
+    let counts: Option<BTreeMap<String, u64>> = if prefix.contains('/') {
+        None
+    } else {
+        match casfs.prefix_counts(bucket) {
+            Ok(counts) => Some(
+                counts
+                    .into_iter()
+                    .map(|count| (count.prefix, count.objects))
+                    .collect(),
+            ),
+            Err(e) => {
+                return responses::error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Error listing objects: {e}"),
+                    wants_html,
+                )
+            }
+        }
+    };
+    let after = |token: Option<String>| match &counts {
+        Some(counts) => top_level_after(
+            tree.as_ref(),
+            &prefix,
+            token,
+            prefs.limit,
+            counts.keys().cloned().collect(),
+        ),
+        None => level_after(tree.as_ref(), &prefix, token, prefs.limit),
+    };
 
-                if let Some(slash_pos) = relative_key.find('/') {
-                    // This is a subdirectory
-                    let dir_name = &relative_key[..slash_pos + 1];
-                    let full_prefix = format!("{}{}", prefix, dir_name);
-                    let dir_info = DirectoryInfo {
-                        name: dir_name.to_string(),
-                        prefix: full_prefix,
-                    };
-
-                    // Only count unique directories toward the limit
-                    if directories.insert(dir_info) {
-                        item_count += 1;
-                        last_key = Some(key.clone());
-                    }
-                } else {
-                    // This is a file at the current level
-                    objects.push(ObjectInfo {
-                        key: key.clone(),
-                        size: obj.size(),
-                        hash: faster_hex::hex_string(obj.hash()),
-                        last_modified: format_timestamp(obj.last_modified()),
-                        is_inlined: obj.is_inlined(),
-                        block_count: obj.blocks().len(),
-                    });
-                    item_count += 1;
-                    last_key = Some(key.clone());
-                }
+    let (entries, has_more, has_prev) = match &page {
+        PageRequest::First => {
+            let (entries, has_more) = after(None);
+            (entries, has_more, false)
+        }
+        PageRequest::After(token) => {
+            let (entries, has_more) = after(Some(token.clone()));
+            (entries, has_more, true)
+        }
+        PageRequest::Before(before) => {
+            let (entries, has_prev) = level_before(tree.as_ref(), &prefix, before, prefs.limit);
+            (entries, true, has_prev)
+        }
+    };
+
+    let next_token = entries
+        .last()
+        .filter(|_| has_more)
+        .map(|entry| entry.bound().to_string());
+    let prev_token = entries
+        .first()
+        .filter(|_| has_prev)
+        .map(|entry| entry.bound().to_string());
+
+    let mut directories = Vec::new();
+    let mut objects = Vec::new();
+    for entry in entries {
+        match entry {
+            ListEntry::Directory(mut dir) => {
+                dir.objects = counts
+                    .as_ref()
+                    .and_then(|counts| counts.get(&dir.prefix).copied());
+                directories.push(dir);
             }
+            ListEntry::Object(key, obj) => objects.push(lister.object_info(key, &obj)),
+        }
+    }
+    sort_page(&mut directories, &mut objects, prefs);
 
-            let mut directories: Vec<DirectoryInfo> = directories.into_iter().collect();
-            directories.sort_by(|a, b| a.name.cmp(&b.name));
+    let total_count = directories.len() + objects.len();
 
-            objects.sort_by(|a, b| a.key.cmp(&b.key));
+    let index_page = match page {
+        PageRequest::First => find_index_page(casfs, bucket, &prefix).await,
+        _ => None,
+    };
+    let degraded_objects = match degraded_keys(casfs, bucket, &prefix) {
+        Ok(keys) => keys,
+        Err(e) => {
+            return responses::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error listing objects: {e}"),
+                wants_html,
+            )
+        }
+    };
 
-            let total_count = directories.len() + objects.len();
+    let response = ObjectListResponse {
+        bucket: bucket.to_string(),
+        prefix,
+        directories,
+        objects,
+        total_count,
+        has_more,
+        next_token,
+        has_prev,
+        prev_token,
+        tag: None,
+        index_page,
+        degraded_objects,
+    };
 
-            let next_token = if has_more { last_key } else { None };
+    if wants_html {
+        responses::html_response(StatusCode::OK, templates::objects_page(ui, &response, prefs))
+    } else {
+        responses::json_response(StatusCode::OK, &response)
+    }
+}
 
-            let response = ObjectListResponse {
-                bucket: bucket.to_string(),
-                prefix,
-                directories,
-                objects,
-                total_count,
-                has_more,
-                next_token,
-            };
+/// The page of a listing a request asks for
+enum PageRequest {
+    First,
+    /// The page after the entry with this bound
+    After(String),
+    /// The page before the entry with this bound
+    Before(String),
+}
 
-            if wants_html {
-                responses::html_response(StatusCode::OK, templates::objects_page(&response))
+/// An entry of one level of a listing
+enum ListEntry {
+    Directory(DirectoryInfo),
+    Object(String, Object),
+}
+
+impl ListEntry {
+    /// The key a page starting or ending with this entry is bounded by: the prefix
+    /// of a directory, or the key of an object.
+    fn bound(&self) -> &str {
+        match self {
+            ListEntry::Directory(dir) => &dir.prefix,
+            ListEntry::Object(key, _) => key,
+        }
+    }
+}
+
+/// The entry `key` belongs to at the level of `prefix`, a key with the prefix.
+fn level_entry(prefix: &str, key: String, obj: Object) -> ListEntry {
+    match key[prefix.len()..].find('/') {
+        Some(slash) => {
+            let dir = &key[..prefix.len() + slash + 1];
+            ListEntry::Directory(DirectoryInfo {
+                name: dir[prefix.len()..].to_string(),
+                prefix: dir.to_string(),
+                objects: None,
+            })
+        }
+        None => ListEntry::Object(key, obj),
+    }
+}
+
+/// Like [`level_after`] for a `prefix` at the top level of the bucket, with the
+/// `directories` of its prefix counts, so only the objects between them are read.
+fn top_level_after(
+    tree: &(dyn MetaTreeExt + Send + Sync),
+    prefix: &str,
+    token: Option<String>,
+    limit: usize,
+    directories: Vec<String>,
+) -> (Vec<ListEntry>, bool) {
+    let mut entries: Vec<ListEntry> = group_by_top_level(
+        |start| tree.range_filter(start, Some(prefix.to_string()), None),
+        prefix,
+        directories,
+        token.as_deref(),
+    )
+    .map(|entry| match entry {
+        listing::ListEntry::Object(key, obj) => ListEntry::Object(key, obj),
+        listing::ListEntry::CommonPrefix(dir) => ListEntry::Directory(DirectoryInfo {
+            name: dir[prefix.len()..].to_string(),
+            prefix: dir,
+            objects: None,
+        }),
+    })
+    .take(limit + 1)
+    .collect();
+    let more = entries.len() > limit;
+    entries.truncate(limit);
+    (entries, more)
+}
+
+/// Up to `limit` entries at the level of `prefix` after the bound `token`, and
+/// whether there are more.
+///
+/// The scan restarts after every directory, so the keys below a directory are
+/// not all read to find the entries following it.
+fn level_after(
+    tree: &(dyn MetaTreeExt + Send + Sync),
+    prefix: &str,
+    token: Option<String>,
+    limit: usize,
+) -> (Vec<ListEntry>, bool) {
+    let mut entries = Vec::new();
+    let mut after = token;
+    'scan: loop {
+        let dir = after
+            .clone()
+            .filter(|after| after.len() > prefix.len() && after.ends_with('/'));
+        // no key below the directory sorts after its prefix followed by the
+        // highest character, but for keys starting with that
+        let start_after = match &dir {
+            Some(dir) => Some(format!("{dir}{}", char::MAX)),
+            None => after.clone(),
+        };
+        let keys = tree
+            .range_filter(start_after, Some(prefix.to_string()), None)
+            .skip_while(|(key, _)| dir.as_ref().map_or(false, |dir| key.starts_with(dir)));
+        for (key, obj) in keys {
+            if entries.len() == limit {
+                return (entries, true);
+            }
+            let entry = level_entry(prefix, key, obj);
+            if let ListEntry::Directory(dir) = &entry {
+                after = Some(dir.prefix.clone());
+                entries.push(entry);
+                continue 'scan;
+            }
+            entries.push(entry);
+        }
+        return (entries, false);
+    }
+}
+
+/// Up to `limit` entries at the level of `prefix` before the bound `before`, in
+/// key order, and whether there are more before them.
+fn level_before(
+    tree: &(dyn MetaTreeExt + Send + Sync),
+    prefix: &str,
+    before: &str,
+    limit: usize,
+) -> (Vec<ListEntry>, bool) {
+    let mut entries = Vec::new();
+    let mut before = before.to_string();
+    'scan: loop {
+        for (key, obj) in tree.range_before(before.clone(), Some(prefix.to_string())) {
+            if entries.len() == limit {
+                entries.reverse();
+                return (entries, true);
+            }
+            let entry = level_entry(prefix, key, obj);
+            if let ListEntry::Directory(dir) = &entry {
+                // the keys below the directory all sort after its prefix
+                before = dir.prefix.clone();
+                entries.push(entry);
+                continue 'scan;
+            }
+            entries.push(entry);
+        }
+        entries.reverse();
+        return (entries, false);
+    }
+}
+
+/// Builds the entries of the objects of a listing
+struct ObjectLister<'a> {
+    casfs: &'a CasFS,
+    bucket: &'a str,
+    /// The lifecycle rules of the bucket, loaded once per listing
+    lifecycle: Option<&'a BucketLifecycle>,
+    prefs: &'a ListPreferences,
+}
+
+impl ObjectLister<'_> {
+    fn object_info(&self, key: String, obj: &Object) -> ObjectInfo {
+        let physical_size = if self.prefs.shows(Column::Physical) {
+            Some(physical_size(self.casfs, obj))
+        } else {
+            None
+        };
+        let modified_secs = obj
+            .last_modified()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let expiration = self.lifecycle.and_then(|lifecycle| {
+            // an object without readable tags matches no tag filter
+            let tags = if lifecycle.has_tag_filters() {
+                self.casfs
+                    .object_tags(self.bucket, &key)
+                    .unwrap_or_default()
             } else {
-                responses::json_response(StatusCode::OK, &response)
+                ObjectTags::default()
+            };
+            let expiration = lifecycle.expiration(&key, &tags, modified_secs as i64)?;
+            let expiry_date = std::time::UNIX_EPOCH
+                + std::time::Duration::from_secs(expiration.expiry_date.max(0) as u64);
+            Some(ExpirationInfo {
+                rule_id: expiration.rule_id,
+                expiry_date: format_timestamp(expiry_date),
+            })
+        });
+        ObjectInfo {
+            key,
+            size: obj.size(),
+            hash: faster_hex::hex_string(obj.hash()),
+            last_modified: format_timestamp(obj.last_modified()),
+            modified_secs,
+            is_inlined: obj.is_inlined(),
+            block_count: obj.blocks().len(),
+            physical_size,
+            expiration,
+        }
+    }
+}
+
+/// The size of the distinct blocks of an object, or of its data if it's inlined.
+/// Blocks shared with other objects are counted in full.
+fn physical_size(casfs: &CasFS, obj: &Object) -> u64 {
+    if obj.is_inlined() {
+        return obj.size();
+    }
+    let Ok(block_tree) = casfs.block_tree() else {
+        return 0;
+    };
+    let blocks: HashSet<_> = obj.blocks().iter().collect();
+    blocks
+        .into_iter()
+        .filter_map(|id| block_tree.get_block(id).ok().flatten())
+        .map(|block| block.size() as u64)
+        .sum()
+}
+
+/// Sort the entries of a page, the directories stay before the objects and are
+/// only sorted by name.
+fn sort_page(directories: &mut [DirectoryInfo], objects: &mut [ObjectInfo], prefs: &ListPreferences) {
+    directories.sort_by(|a, b| a.name.cmp(&b.name));
+    match prefs.sort {
+        SortKey::Name => objects.sort_by(|a, b| a.key.cmp(&b.key)),
+        SortKey::Size => objects.sort_by_key(|obj| obj.size),
+        SortKey::Modified => objects.sort_by_key(|obj| obj.modified_secs),
+        SortKey::Blocks => objects.sort_by_key(|obj| obj.block_count),
+    }
+    if prefs.descending {
+        directories.reverse();
+        objects.reverse();
+    }
+}
+
+/// Flat listing of the objects with a tag, from the tag index. Directories are not
+/// grouped, a tag selects objects across the whole bucket.
+fn list_tagged_objects(
+    lister: &ObjectLister<'_>,
+    filter: &TagFilter,
+    prefix: String,
+    page: PageRequest,
+    wants_html: bool,
+    ui: &Ui,
+) -> Response<HttpBody> {
+    let ObjectLister {
+        casfs,
+        bucket,
+        prefs,
+        ..
+    } = *lister;
+    let mut keys = match casfs.tagged_keys(bucket, filter) {
+        Ok(keys) => keys,
+        Err(e) => {
+            return responses::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error listing tagged objects: {e}"),
+                wants_html,
+            )
+        }
+    };
+    keys.retain(|key| key.starts_with(&prefix));
+    keys.sort();
+
+    let (window, has_prev) = match &page {
+        PageRequest::First => (&keys[..], false),
+        PageRequest::After(token) => (&keys[keys.partition_point(|key| key <= token)..], true),
+        PageRequest::Before(before) => {
+            let end = keys.partition_point(|key| key < before);
+            let start = end.saturating_sub(prefs.limit);
+            (&keys[start..end], start > 0)
+        }
+    };
+
+    let mut objects = Vec::new();
+    let mut has_more = matches!(page, PageRequest::Before(_));
+    for key in window {
+        if objects.len() >= prefs.limit {
+            has_more = true;
+            break;
+        }
+        // tags are set before the data of a new object is written
+        let obj = match casfs.get_object_meta(bucket, key) {
+            Ok(Some(obj)) => obj,
+            Ok(None) => continue,
+            Err(e) => {
+                return responses::error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Error listing tagged objects: {e}"),
+                    wants_html,
+                )
             }
+        };
+        objects.push(lister.object_info(key.clone(), &obj));
+    }
+
+    let next_token = if has_more {
+        objects.last().map(|obj| obj.key.clone())
+    } else {
+        None
+    };
+    let prev_token = if has_prev {
+        objects.first().map(|obj| obj.key.clone())
+    } else {
+        None
+    };
+    sort_page(&mut [], &mut objects, prefs);
+    let degraded_objects = match degraded_keys(casfs, bucket, &prefix) {
+        Ok(keys) => keys,
+        Err(e) => {
+            return responses::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error listing tagged objects: {e}"),
+                wants_html,
+            )
         }
-        Err(e) => responses::error_response(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            &format!("Error listing objects: {e}"),
-            wants_html,
-        ),
+    };
+
+    let response = ObjectListResponse {
+        bucket: bucket.to_string(),
+        prefix,
+        directories: Vec::new(),
+        total_count: objects.len(),
+        has_more,
+        next_token,
+        has_prev,
+        prev_token,
+        objects,
+        tag: Some(filter.to_string()),
+        index_page: None,
+        degraded_objects,
+    };
+
+    if wants_html {
+        responses::html_response(StatusCode::OK, templates::objects_page(ui, &response, prefs))
+    } else {
+        responses::json_response(StatusCode::OK, &response)
     }
 }
 
+/// The value of the `If-None-Match` header of a request, if any.
+pub fn if_none_match<B>(req: &Request<B>) -> Option<&str> {
+    req.headers()
+        .get(hyper::header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+}
+
 pub async fn object_metadata(
     casfs: &CasFS,
     bucket: &str,
     key: &str,
     wants_html: bool,
+    if_none_match: Option<&str>,
+    ui: &Ui,
+    shareable: bool,
 ) -> Response<HttpBody> {
     match casfs.get_object_meta(bucket, key) {
         Ok(Some(obj)) => {
+            let corrupt_blocks = match casfs.unhealed_blocks(&obj) {
+                Ok(blocks) => blocks,
+                Err(e) => {
+                    return responses::error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        &format!("Error getting object: {e}"),
+                        wants_html,
+                    )
+                }
+            };
+            // The page is derived from the object, but isn't the object itself.
+            // The HTML also depends on the theme and language it's rendered in,
+            // and both on whether blocks of the object are corrupt.
+            let degraded = if corrupt_blocks.is_empty() {
+                ""
+            } else {
+                "-degraded"
+            };
+            let etag = if wants_html {
+                format!(
+                    "W/\"{}-{}-{}{}\"",
+                    obj.format_e_tag().trim_matches('"'),
+                    ui.language.code(),
+                    ui.theme.as_str(),
+                    degraded
+                )
+            } else {
+                format!("W/\"{}{}\"", obj.format_e_tag().trim_matches('"'), degraded)
+            };
+            if matches!(if_none_match, Some(condition) if etag_matches(condition, &etag)) {
+                return responses::not_modified(&etag, obj.last_modified());
+            }
+
             // Get block details
             let block_tree = match casfs.block_tree() {
                 Ok(tree) => tree,
@@ -267,16 +1255,26 @@ pub async fn object_metadata(
                 bucket: bucket.to_string(),
                 size: obj.size(),
                 hash: faster_hex::hex_string(obj.hash()),
+                etag: obj.format_e_tag(),
                 last_modified: format_timestamp(obj.last_modified()),
                 is_inlined: obj.is_inlined(),
                 blocks,
+                corrupt_blocks: corrupt_blocks
+                    .iter()
+                    .map(|block| faster_hex::hex_string(block))
+                    .collect(),
             };
 
-            if wants_html {
-                responses::html_response(StatusCode::OK, templates::object_detail_page(&metadata))
+            let response = if wants_html {
+                let preview = preview::preview(casfs, bucket, key, &obj).await;
+                responses::html_response(
+                    StatusCode::OK,
+                    templates::object_detail_page(ui, &metadata, preview.as_ref(), shareable),
+                )
             } else {
                 responses::json_response(StatusCode::OK, &metadata)
-            }
+            };
+            responses::with_cache_headers(response, &etag, obj.last_modified())
         }
         Ok(None) => responses::error_response(StatusCode::NOT_FOUND, "Object not found", wants_html),
         Err(e) => responses::error_response(
@@ -301,9 +1299,36 @@ pub async fn download_object(
     casfs: &CasFS,
     bucket: &str,
     key: &str,
+    if_none_match: Option<&str>,
 ) -> Response<HttpBody> {
     match casfs.get_object_paths(bucket, key) {
         Ok(Some((obj_meta, paths))) => {
+            match casfs.unhealed_blocks(&obj_meta) {
+                Ok(corrupt) if !corrupt.is_empty() => {
+                    let description = casfs.describe_corrupt_blocks(&corrupt);
+                    tracing::warn!(bucket, key, "Download of object with {description}");
+                    return responses::error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        &format!("Object has {description}"),
+                        false,
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    return responses::error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        &format!("Error getting object: {e}"),
+                        false,
+                    )
+                }
+            }
+
+            let etag = obj_meta.format_e_tag();
+            let last_modified = obj_meta.last_modified();
+            if matches!(if_none_match, Some(condition) if etag_matches(condition, &etag)) {
+                return responses::not_modified(&etag, last_modified);
+            }
+
             let filename = key.rsplit('/').next().unwrap_or(key);
             let content_disposition = format!("attachment; filename=\"{}\"", filename);
 
@@ -313,13 +1338,14 @@ pub async fn download_object(
                     .map_err(|_| -> Box<dyn std::error::Error + Send + Sync> { unreachable!() })
                     .boxed();
 
-                return Response::builder()
+                let response = Response::builder()
                     .status(StatusCode::OK)
                     .header("content-type", "application/octet-stream")
                     .header("content-disposition", content_disposition)
                     .header("content-length", data.len())
                     .body(body)
                     .unwrap();
+                return responses::with_cache_headers(response, &etag, last_modified);
             }
 
             // Handle block stream
@@ -338,13 +1364,14 @@ pub async fn download_object(
 
             let body = BodyExt::boxed(StreamBody::new(stream));
 
-            Response::builder()
+            let response = Response::builder()
                 .status(StatusCode::OK)
                 .header("content-type", "application/octet-stream")
                 .header("content-disposition", content_disposition)
                 .header("content-length", block_size)
                 .body(body)
-                .unwrap()
+                .unwrap();
+            responses::with_cache_headers(response, &etag, last_modified)
         }
         Ok(None) => responses::error_response(StatusCode::NOT_FOUND, "Object not found", false),
         Err(e) => responses::error_response(
@@ -354,3 +1381,303 @@ pub async fn download_object(
         ),
     }
 }
+
+/// Create an object as the concatenation of existing objects of the bucket,
+/// without copying data.
+///
+/// Only JSON bodies are accepted, browsers can't send those cross-site without
+/// a preflight request.
+pub async fn concat_objects<B>(casfs: &CasFS, bucket: &str, req: Request<B>) -> Response<HttpBody>
+where
+    B: hyper::body::Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let is_json = req
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !is_json {
+        return responses::error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected an application/json body",
+            false,
+        );
+    }
+
+    let body = match Limited::new(req.into_body(), MAX_CONCAT_REQUEST_SIZE).collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read concat request body");
+            return responses::error_response(StatusCode::BAD_REQUEST, "Invalid request", false);
+        }
+    };
+    let request: ConcatRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return responses::error_response(StatusCode::BAD_REQUEST, &format!("Invalid JSON: {e}"), false)
+        }
+    };
+    if request.key.is_empty() {
+        return responses::error_response(StatusCode::BAD_REQUEST, "Missing object key", false);
+    }
+    if request.sources.len() > MAX_CONCAT_SOURCES {
+        return responses::error_response(
+            StatusCode::BAD_REQUEST,
+            &format!("At most {MAX_CONCAT_SOURCES} source objects can be concatenated"),
+            false,
+        );
+    }
+
+    match casfs.concat_objects(bucket, &request.key, &request.sources).await {
+        Ok(obj) => {
+            tracing::info!(bucket, key = %request.key, sources = request.sources.len(), size = obj.size(), "Concatenated objects");
+            let response = ConcatResponse {
+                bucket: bucket.to_string(),
+                key: request.key,
+                size: obj.size(),
+                etag: obj.format_e_tag(),
+                parts: request.sources.len(),
+            };
+            responses::json_response(StatusCode::OK, &response)
+        }
+        Err(MetaError::BucketNotFound) => responses::error_response(StatusCode::NOT_FOUND, "Bucket not found", false),
+        Err(MetaError::KeyNotFound) => {
+            responses::error_response(StatusCode::NOT_FOUND, "Source object not found", false)
+        }
+        // a source was deleted while its blocks were referenced
+        Err(MetaError::BlockNotFound) => responses::error_response(
+            StatusCode::CONFLICT,
+            "A source object was removed during the concatenation",
+            false,
+        ),
+        Err(MetaError::InvalidArgument(message)) => {
+            responses::error_response(StatusCode::BAD_REQUEST, &message, false)
+        }
+        Err(e) => responses::error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error concatenating objects: {e}"),
+            false,
+        ),
+    }
+}
+
+fn bucket_limits_info(casfs: &CasFS, bucket: &str) -> Result<BucketLimitsInfo, MetaError> {
+    let limits = casfs.bucket_limits(bucket)?;
+    let counters = casfs.bucket_counters(bucket)?;
+    Ok(BucketLimitsInfo {
+        bucket: bucket.to_string(),
+        max_objects: limits.max_objects,
+        max_bytes: limits.max_bytes,
+        objects: counters.objects,
+        logical_bytes: counters.logical_bytes,
+    })
+}
+
+fn limits_error_response(e: MetaError, wants_html: bool) -> Response<HttpBody> {
+    match e {
+        MetaError::BucketNotFound => responses::error_response(StatusCode::NOT_FOUND, "Bucket not found", wants_html),
+        e => responses::error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error accessing bucket limits: {e}"),
+            wants_html,
+        ),
+    }
+}
+
+/// Shows the limits of a bucket, as HTML with a form to change them
+pub async fn bucket_limits(
+    casfs: &CasFS,
+    bucket: &str,
+    wants_html: bool,
+    error_message: Option<&str>,
+    ui: &Ui,
+) -> Response<HttpBody> {
+    match bucket_limits_info(casfs, bucket) {
+        Ok(info) if wants_html => {
+            responses::html_response(StatusCode::OK, templates::bucket_limits_page(ui, &info, error_message))
+        }
+        Ok(info) => responses::json_response(StatusCode::OK, &info),
+        Err(e) => limits_error_response(e, wants_html),
+    }
+}
+
+/// Replaces the limits of a bucket with the ones in the JSON body of the request
+pub async fn put_bucket_limits(casfs: &CasFS, bucket: &str, req: Request<Incoming>) -> Response<HttpBody> {
+    let body = match Limited::new(req.into_body(), MAX_LIMITS_REQUEST_SIZE).collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read bucket limits request body");
+            return responses::error_response(StatusCode::BAD_REQUEST, "Invalid request", false);
+        }
+    };
+    let limits: BucketLimits = match serde_json::from_slice(&body) {
+        Ok(limits) => limits,
+        Err(e) => {
+            return responses::error_response(StatusCode::BAD_REQUEST, &format!("Invalid JSON: {e}"), false)
+        }
+    };
+    if let Err(e) = casfs.set_bucket_limits(bucket, limits) {
+        return limits_error_response(e, false);
+    }
+    tracing::info!(bucket, ?limits, "Changed bucket limits");
+    bucket_limits(casfs, bucket, false, None, &Ui::default()).await
+}
+
+/// Serves the limits page of a bucket under `/limits/{bucket}`, and its form submissions
+pub async fn limits_request(
+    casfs: &CasFS,
+    req: Request<Incoming>,
+    wants_html: bool,
+    ui: &Ui,
+) -> Response<HttpBody> {
+    let bucket = req.uri().path().trim_start_matches("/limits/");
+    let bucket = urlencoding::decode(bucket).unwrap_or_default().into_owned();
+    if bucket.is_empty() || bucket.contains('/') {
+        return responses::not_found(wants_html);
+    }
+
+    match *req.method() {
+        hyper::Method::GET => {
+            let error = req.uri().query().and_then(|query| {
+                query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("error="))
+                    .map(|e| urlencoding::decode(e).unwrap_or_default().into_owned())
+            });
+            bucket_limits(casfs, &bucket, wants_html, error.as_deref(), ui).await
+        }
+        hyper::Method::POST => post_bucket_limits_form(casfs, &bucket, req).await,
+        _ => responses::not_found(wants_html),
+    }
+}
+
+/// Replaces the limits of a bucket with the ones of the form on its limits page,
+/// an empty field removes a limit
+pub async fn post_bucket_limits_form(casfs: &CasFS, bucket: &str, req: Request<Incoming>) -> Response<HttpBody> {
+    let page = format!("/limits/{}", urlencoding::encode(bucket));
+    let with_error = |message: &str| responses::redirect(&format!("{page}?error={}", urlencoding::encode(message)));
+
+    let body = match Limited::new(req.into_body(), MAX_LIMITS_REQUEST_SIZE).collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read bucket limits form");
+            return with_error("Invalid request");
+        }
+    };
+
+    let mut limits = BucketLimits::default();
+    for pair in String::from_utf8_lossy(&body).split('&') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let limit = match name {
+            "max_objects" => &mut limits.max_objects,
+            "max_bytes" => &mut limits.max_bytes,
+            _ => continue,
+        };
+        let value = urlencoding::decode(&value.replace('+', " ")).unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        match value.parse() {
+            Ok(value) => *limit = Some(value),
+            Err(_) => return with_error(&format!("Invalid limit: {value}")),
+        }
+    }
+
+    match casfs.set_bucket_limits(bucket, limits) {
+        Ok(()) => {
+            tracing::info!(bucket, ?limits, "Changed bucket limits");
+            responses::redirect(&page)
+        }
+        Err(e) => limits_error_response(e, true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cas_storage::{FjallStore, ObjectData, Store};
+
+    fn bounds(entries: &[ListEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.bound()).collect()
+    }
+
+    #[test]
+    fn test_level_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FjallStore::new(dir.path().to_path_buf(), None, None);
+        let tree = store.tree_ext_open("bucket").unwrap();
+        for key in ["a", "d/1", "d/2", "d/3", "e", "f/x/1", "g"] {
+            let obj = Object::new(1, [1; 16], ObjectData::Inline { data: vec![1] });
+            tree.insert(key.as_bytes(), obj.to_vec()).unwrap();
+        }
+        let tree = tree.as_ref();
+
+        // directories are one entry, and the next page starts after their keys
+        let (page, more) = level_after(tree, "", None, 2);
+        assert_eq!((bounds(&page), more), (vec!["a", "d/"], true));
+        let (page, more) = level_after(tree, "", Some("d/".to_string()), 2);
+        assert_eq!((bounds(&page), more), (vec!["e", "f/"], true));
+        let (page, more) = level_after(tree, "", Some("f/".to_string()), 2);
+        assert_eq!((bounds(&page), more), (vec!["g"], false));
+
+        // pages before a bound are in key order too
+        let (page, prev) = level_before(tree, "", "g", 2);
+        assert_eq!((bounds(&page), prev), (vec!["e", "f/"], true));
+        let (page, prev) = level_before(tree, "", "e", 2);
+        assert_eq!((bounds(&page), prev), (vec!["a", "d/"], false));
+
+        let (page, more) = level_after(tree, "d/", Some("d/1".to_string()), 10);
+        assert_eq!((bounds(&page), more), (vec!["d/2", "d/3"], false));
+        let (page, prev) = level_before(tree, "d/", "d/3", 1);
+        assert_eq!((bounds(&page), prev), (vec!["d/2"], true));
+
+        // the top level pages are the same with the directories of the prefix counts
+        let dirs = || vec!["d/".to_string(), "f/".to_string()];
+        let (page, more) = top_level_after(tree, "", None, 2, dirs());
+        assert_eq!((bounds(&page), more), (vec!["a", "d/"], true));
+        let (page, more) = top_level_after(tree, "", Some("d/".to_string()), 2, dirs());
+        assert_eq!((bounds(&page), more), (vec!["e", "f/"], true));
+        let (page, more) = top_level_after(tree, "", Some("f/".to_string()), 2, dirs());
+        assert_eq!((bounds(&page), more), (vec!["g"], false));
+    }
+
+    #[test]
+    fn test_activity_minutes() {
+        assert_eq!(activity_minutes(None), Ok(ACTIVITY_WINDOW_MINUTES));
+        assert_eq!(activity_minutes(Some("x=1&minutes=5")), Ok(5));
+        assert!(activity_minutes(Some("minutes=0")).is_err());
+        assert!(activity_minutes(Some("minutes=61")).is_err());
+        assert!(activity_minutes(Some("minutes=five")).is_err());
+    }
+
+    #[test]
+    fn test_usage_csv_tags() {
+        let usage = BucketUsage::default();
+        let report = |bucket: &str, tags: &[(&str, &str)]| BucketUsageReport {
+            bucket: bucket.to_string(),
+            tags: tags
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            current: usage,
+            history: vec![UsageSample {
+                day: "2024-01-01".to_string(),
+                usage,
+            }],
+        };
+        let csv = usage_csv(&[
+            report("a", &[("team", "ops"), ("project", "x, \"y\"")]),
+            report("b", &[("team", "web")]),
+        ]);
+        assert_eq!(
+            csv,
+            "bucket,day,objects,logical_bytes,physical_bytes,tag:project,tag:team\n\
+             a,2024-01-01,0,0,0,\"x, \"\"y\"\"\",ops\n\
+             b,2024-01-01,0,0,0,,web\n"
+        );
+    }
+}
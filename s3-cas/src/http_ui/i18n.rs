@@ -0,0 +1,271 @@
+//! Translations of the texts of the HTTP UI.
+//!
+//! Texts are looked up by their English version, which is also what is shown
+//! when a language has no translation for a text. Adding a language means adding
+//! a variant to [`Language`] and a table of translations.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Language of the HTTP UI
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::German];
+
+    /// The language code, as in `Accept-Language` and the `lang` of a page
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::German => "de",
+        }
+    }
+
+    /// The name of the language, in the language itself
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::German => "Deutsch",
+        }
+    }
+
+    /// The language of a language tag, regional variants like `de-CH` included.
+    pub fn parse(tag: &str) -> Option<Self> {
+        let primary = tag.split(['-', '_']).next().unwrap_or_default();
+        Language::ALL
+            .iter()
+            .copied()
+            .find(|language| language.code().eq_ignore_ascii_case(primary.trim()))
+    }
+
+    /// The supported language the browser prefers, from an `Accept-Language`
+    /// header such as `fr-CH, de;q=0.8, en;q=0.5`.
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        let mut ranges: Vec<(&str, f32)> = header
+            .split(',')
+            .map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next().unwrap_or_default().trim();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                (tag, quality)
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        // stable, so ranges of the same quality keep their order
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges.into_iter().find_map(|(tag, _)| Self::parse(tag))
+    }
+
+    /// The translation of an English text, the text itself if there is none.
+    pub fn translate(self, text: &'static str) -> &'static str {
+        let translations = match self {
+            Language::English => return text,
+            Language::German => {
+                static GERMAN_TEXTS: OnceLock<HashMap<&str, &str>> = OnceLock::new();
+                GERMAN_TEXTS.get_or_init(|| GERMAN.iter().copied().collect())
+            }
+        };
+        translations.get(text).copied().unwrap_or(text)
+    }
+}
+
+const GERMAN: &[(&str, &str)] = &[
+    // layout
+    ("Buckets", "Buckets"),
+    ("Usage", "Belegung"),
+    ("Health", "Status"),
+    ("Profile", "Profil"),
+    ("Admin", "Verwaltung"),
+    ("Logout", "Abmelden"),
+    ("Dark theme", "Dunkles Design"),
+    ("Light theme", "Helles Design"),
+    // bucket and object lists
+    ("Name", "Name"),
+    ("Created", "Erstellt"),
+    ("bucket(s)", "Bucket(s)"),
+    ("No buckets found", "Keine Buckets gefunden"),
+    ("Objects in", "Objekte in"),
+    ("tagged", "markiert mit"),
+    ("item(s) on this page", "Einträge auf dieser Seite"),
+    ("No objects in this location", "Keine Objekte an diesem Ort"),
+    ("Size", "Größe"),
+    ("Type", "Typ"),
+    ("Last Modified", "Zuletzt geändert"),
+    ("Blocks", "Blöcke"),
+    ("Physical Size", "Physische Größe"),
+    ("Expires", "Läuft ab"),
+    ("folder", "Ordner"),
+    ("objects", "Objekte"),
+    ("inline", "inline"),
+    ("blocks", "Blöcke"),
+    ("Page size", "Seitengröße"),
+    ("Columns", "Spalten"),
+    ("Apply", "Anwenden"),
+    ("« First", "« Anfang"),
+    ("‹ Previous", "‹ Zurück"),
+    ("Next ›", "Weiter ›"),
+    // object details
+    ("Object Metadata", "Objektmetadaten"),
+    ("Key", "Schlüssel"),
+    ("Bucket", "Bucket"),
+    ("bytes", "Bytes"),
+    ("Content Hash", "Inhalts-Hash"),
+    ("Storage Type", "Speicherart"),
+    ("Inline", "Inline"),
+    ("(stored in metadata)", "(in den Metadaten gespeichert)"),
+    ("(content-addressable storage)", "(inhaltsadressierter Speicher)"),
+    ("Block Count", "Anzahl Blöcke"),
+    ("Hash", "Hash"),
+    ("Refcount", "Referenzen"),
+    ("shared", "geteilt"),
+    (
+        "This object uses corrupt blocks and may be unreadable until they are healed by uploading their content again:",
+        "Dieses Objekt verwendet beschädigte Blöcke und ist möglicherweise nicht lesbar, bis sie durch erneutes Hochladen ihres Inhalts repariert werden:",
+    ),
+    (
+        "object(s) here use corrupt blocks and may be unreadable:",
+        "Objekt(e) hier verwenden beschädigte Blöcke und sind möglicherweise nicht lesbar:",
+    ),
+    ("Preview", "Vorschau"),
+    ("Only the first 64 KiB are shown.", "Nur die ersten 64 KiB werden angezeigt."),
+    ("The image is too large to preview.", "Das Bild ist zu groß für eine Vorschau."),
+    ("Share link", "Freigabelink"),
+    ("Valid for", "Gültig für"),
+    ("1 hour", "1 Stunde"),
+    ("1 day", "1 Tag"),
+    ("7 days", "7 Tage"),
+    ("Create share link", "Freigabelink erstellen"),
+    (
+        "Anyone with the link can download the object until",
+        "Jeder mit dem Link kann das Objekt herunterladen bis",
+    ),
+    // usage report
+    ("Download CSV", "CSV herunterladen"),
+    ("Objects", "Objekte"),
+    ("Logical size", "Logische Größe"),
+    ("Physical size", "Physische Größe"),
+    ("Growth", "Wachstum"),
+    ("Physical size trend", "Verlauf der physischen Größe"),
+    (
+        "Physical size counts every distinct block of a bucket once. Blocks shared between buckets are counted in each of them. History is sampled once a day.",
+        "Die physische Größe zählt jeden Block eines Buckets einmal. Blöcke, die mehrere Buckets teilen, werden in jedem von ihnen gezählt. Der Verlauf wird einmal täglich erfasst.",
+    ),
+    // hot buckets
+    ("Hot buckets", "Meistgenutzte Buckets"),
+    ("No requests in the last hour", "Keine Anfragen in der letzten Stunde"),
+    ("Requests/min", "Anfragen/min"),
+    ("Reads", "Lesezugriffe"),
+    ("Writes", "Schreibzugriffe"),
+    ("Read", "Gelesen"),
+    ("Written", "Geschrieben"),
+    ("Paid by requesters", "Von Anfragenden bezahlt"),
+    (
+        "Requests and transferred object data of the last hour, busiest first.",
+        "Anfragen und übertragene Objektdaten der letzten Stunde, die meistgenutzten zuerst.",
+    ),
+    // bucket limits
+    ("Limits", "Limits"),
+    ("Limits of", "Limits von"),
+    ("Maximum objects", "Maximale Anzahl Objekte"),
+    ("Maximum size in bytes", "Maximale Größe in Bytes"),
+    ("Leave a field empty for no limit.", "Ein leeres Feld bedeutet kein Limit."),
+    (
+        "Uploads exceeding a limit are rejected, objects already in the bucket are kept.",
+        "Uploads, die ein Limit überschreiten, werden abgelehnt, vorhandene Objekte bleiben erhalten.",
+    ),
+    ("Current contents", "Aktueller Inhalt"),
+    ("unlimited", "unbegrenzt"),
+    // errors and login
+    ("Error", "Fehler"),
+    ("← Back to buckets", "← Zurück zu den Buckets"),
+    ("Login", "Anmelden"),
+    ("Username", "Benutzername"),
+    ("Password", "Passwort"),
+    // profile
+    ("Display", "Anzeige"),
+    ("Theme", "Design"),
+    ("Language", "Sprache"),
+    ("System", "System"),
+    ("Light", "Hell"),
+    ("Dark", "Dunkel"),
+    ("Browser language", "Sprache des Browsers"),
+    ("Save", "Speichern"),
+    ("No account yet?", "Noch kein Konto?"),
+    ("Sign up", "Registrieren"),
+    (
+        "An administrator has to approve the account before you can log in.",
+        "Ein Administrator muss das Konto freigeben, bevor Sie sich anmelden können.",
+    ),
+    ("Minimum 8 characters", "Mindestens 8 Zeichen"),
+    ("Confirm Password", "Passwort bestätigen"),
+    ("Check the status of a signup", "Status einer Registrierung prüfen"),
+    ("Signup received", "Registrierung erhalten"),
+    (
+        "The account is waiting for the approval of an administrator:",
+        "Das Konto wartet auf die Freigabe durch einen Administrator:",
+    ),
+    (
+        "Keep this token to check the status of your signup, it can't be shown again:",
+        "Bewahren Sie dieses Token auf, um den Status Ihrer Registrierung zu prüfen, es kann nicht erneut angezeigt werden:",
+    ),
+    ("Signup status", "Status der Registrierung"),
+    (
+        "The signup is waiting for the approval of an administrator:",
+        "Die Registrierung wartet auf die Freigabe durch einen Administrator:",
+    ),
+    (
+        "The signup was approved, you can log in now:",
+        "Die Registrierung wurde freigegeben, Sie können sich jetzt anmelden:",
+    ),
+    ("The signup was rejected:", "Die Registrierung wurde abgelehnt:"),
+    ("Token", "Token"),
+    ("Check", "Prüfen"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_accept_language() {
+        assert_eq!(
+            Language::from_accept_language("de-CH,de;q=0.9,en;q=0.8"),
+            Some(Language::German)
+        );
+        assert_eq!(
+            Language::from_accept_language("fr-CH, en;q=0.5, de;q=0.8"),
+            Some(Language::German)
+        );
+        assert_eq!(
+            Language::from_accept_language("en-US,de;q=0.9"),
+            Some(Language::English)
+        );
+        assert_eq!(Language::from_accept_language("de;q=0, fr"), None);
+        assert_eq!(Language::from_accept_language(""), None);
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(Language::German.translate("Logout"), "Abmelden");
+        assert_eq!(Language::English.translate("Logout"), "Logout");
+        // texts without a translation are shown in English
+        assert_eq!(Language::German.translate("Untranslated"), "Untranslated");
+
+        let mut texts: Vec<&str> = GERMAN.iter().map(|(text, _)| *text).collect();
+        texts.sort_unstable();
+        texts.dedup();
+        assert_eq!(
+            texts.len(),
+            GERMAN.len(),
+            "duplicate text in the translations"
+        );
+    }
+}
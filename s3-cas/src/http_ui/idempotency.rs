@@ -0,0 +1,178 @@
+//! `Idempotency-Key` header of the JSON API requests which create objects or
+//! uploads. The successful response of a request with a key is stored, and a
+//! retry with the same key gets it back instead of repeating the request, so a
+//! client which lost the response after a network failure can safely send the
+//! request again.
+
+use std::future::Future;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{body::Incoming, Request, Response, StatusCode};
+use sha2::{Digest, Sha256};
+
+use cas_storage::{CasFS, IdempotentResult, MAX_IDEMPOTENCY_KEY_LEN};
+
+use super::{responses, HttpBody};
+
+/// Header with the idempotency key of a request
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// Header set on a stored response sent again for a retried request
+pub const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+
+/// Largest accepted request body, the requests with a key have small JSON bodies
+const MAX_REQUEST_SIZE: usize = 4 * 1024 * 1024;
+
+/// The idempotency key of a request, `Err` if it's not a valid key.
+fn idempotency_key<B>(req: &Request<B>) -> Result<Option<String>, &'static str> {
+    let Some(value) = req.headers().get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    let key = value
+        .to_str()
+        .map_err(|_| "The Idempotency-Key must be ASCII")?;
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        return Err("The Idempotency-Key must have 1 to 255 characters");
+    }
+    Ok(Some(key.to_string()))
+}
+
+/// Identifies a request, so a key sent again with another request is noticed
+fn fingerprint(req: &Request<Full<Bytes>>, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str());
+    hasher.update(b" ");
+    hasher.update(req.uri().path());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+fn replay(result: IdempotentResult) -> Response<HttpBody> {
+    let status = StatusCode::from_u16(result.status).unwrap_or(StatusCode::OK);
+    let resp = Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .header(IDEMPOTENT_REPLAYED, "true")
+        .body(Full::new(Bytes::from(result.body)))
+        .unwrap();
+    responses::map_response(resp)
+}
+
+/// Serves `req` with `handler`, or with the response stored for its idempotency
+/// key if it has one which was used before.
+///
+/// Only successful responses are stored: a failed request made no change, so
+/// its retry is served again. Requests with the same key are served one at a
+/// time, a retry sent while the first request still runs waits for its result.
+pub async fn handle<F, Fut>(casfs: &CasFS, req: Request<Incoming>, handler: F) -> Response<HttpBody>
+where
+    F: FnOnce(Request<Full<Bytes>>) -> Fut,
+    Fut: Future<Output = Response<HttpBody>>,
+{
+    let key = match idempotency_key(&req) {
+        Ok(key) => key,
+        Err(message) => return responses::error_response(StatusCode::BAD_REQUEST, message, false),
+    };
+    let (parts, body) = req.into_parts();
+    let body = match Limited::new(body, MAX_REQUEST_SIZE).collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read request body");
+            return responses::error_response(StatusCode::BAD_REQUEST, "Invalid request", false);
+        }
+    };
+    let req = Request::from_parts(parts, Full::new(body.clone()));
+    let Some(key) = key else {
+        return handler(req).await;
+    };
+
+    let _guard = casfs.lock_idempotency_key(&key).await;
+    let fingerprint = fingerprint(&req, &body);
+    let keys = casfs.idempotency_keys();
+    match keys.get(&key) {
+        Ok(Some(result)) if result.fingerprint == fingerprint => {
+            tracing::debug!(key, "Replaying the response of an idempotent request");
+            return replay(result);
+        }
+        Ok(Some(_)) => {
+            return responses::error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The Idempotency-Key was used for another request",
+                false,
+            )
+        }
+        Ok(None) => {}
+        Err(e) => {
+            return responses::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error reading idempotency key: {e}"),
+                false,
+            )
+        }
+    }
+
+    let response = handler(req).await;
+    if !response.status().is_success() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            return responses::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error reading response: {e}"),
+                false,
+            )
+        }
+    };
+    let result = IdempotentResult {
+        fingerprint,
+        status: parts.status.as_u16(),
+        body: String::from_utf8_lossy(&body).into_owned(),
+        created_at: chrono::Utc::now().timestamp().max(0) as u64,
+    };
+    // the request is done, a retry repeats it if its result couldn't be stored
+    if let Err(e) = keys.insert(&key, &result) {
+        tracing::warn!(key, error = %e, "Could not store the result of an idempotent request");
+    }
+    responses::map_response(Response::from_parts(parts, Full::new(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idempotency_key() {
+        let request = |key: Option<&str>| {
+            let mut builder = Request::builder()
+                .method("POST")
+                .uri("/api/v1/buckets/b/concat");
+            if let Some(key) = key {
+                builder = builder.header(IDEMPOTENCY_KEY, key);
+            }
+            builder.body(Full::new(Bytes::new())).unwrap()
+        };
+        assert_eq!(idempotency_key(&request(None)), Ok(None));
+        assert_eq!(
+            idempotency_key(&request(Some("job-42"))),
+            Ok(Some("job-42".to_string()))
+        );
+        assert!(idempotency_key(&request(Some(""))).is_err());
+        assert!(idempotency_key(&request(Some(&"k".repeat(256)))).is_err());
+
+        let req = request(None);
+        let first = fingerprint(&req, b"{\"key\": \"a\"}");
+        assert_eq!(first, fingerprint(&req, b"{\"key\": \"a\"}"));
+        assert_ne!(first, fingerprint(&req, b"{\"key\": \"b\"}"));
+        let other = Request::builder()
+            .method("POST")
+            .uri("/api/v1/buckets/b/assemble")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        assert_ne!(first, fingerprint(&other, b"{\"key\": \"a\"}"));
+    }
+}
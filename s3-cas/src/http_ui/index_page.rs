@@ -0,0 +1,187 @@
+//! Index pages shown above the object listing of a bucket or prefix.
+//!
+//! A `README.md` is rendered to HTML on the server, raw HTML in the markdown is
+//! escaped and links with unsafe schemes are dropped. An `index.html` is shown
+//! as is, in a sandboxed frame without scripts.
+
+use futures::StreamExt;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+use serde::Serialize;
+
+use cas_storage::{BlockStream, CasFS, RangeRequest};
+
+/// Names of the index pages, in order of preference
+pub const INDEX_PAGE_NAMES: [&str; 2] = ["README.md", "index.html"];
+
+/// Index pages larger than this are not shown
+pub const MAX_INDEX_PAGE_SIZE: u64 = 1024 * 1024;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexFormat {
+    /// Markdown rendered to safe HTML
+    Markdown,
+    /// An untrusted HTML document
+    Html,
+}
+
+#[derive(Serialize)]
+pub struct IndexPage {
+    pub key: String,
+    pub format: IndexFormat,
+    /// The rendered markdown, or the HTML document
+    pub content: String,
+}
+
+/// Find and load the index page of `prefix` in `bucket`.
+///
+/// The index page is a convenience, errors are logged and the listing is shown
+/// without it.
+pub async fn find_index_page(casfs: &CasFS, bucket: &str, prefix: &str) -> Option<IndexPage> {
+    // only index pages directly at the listed level
+    if !prefix.is_empty() && !prefix.ends_with('/') {
+        return None;
+    }
+
+    for name in INDEX_PAGE_NAMES {
+        let key = format!("{prefix}{name}");
+        match read_small_object(casfs, bucket, &key).await {
+            Ok(Some(data)) => {
+                let (format, content) = if name.ends_with(".md") {
+                    (IndexFormat::Markdown, render_markdown(&data))
+                } else {
+                    (IndexFormat::Html, data)
+                };
+                return Some(IndexPage {
+                    key,
+                    format,
+                    content,
+                });
+            }
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!(bucket, key = %key, "Could not load index page: {e}");
+                return None;
+            }
+        }
+    }
+    None
+}
+
+/// Read an object as text, if it exists and is at most `MAX_INDEX_PAGE_SIZE`.
+async fn read_small_object(
+    casfs: &CasFS,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some((obj, paths)) = casfs.get_object_paths(bucket, key)? else {
+        return Ok(None);
+    };
+    if obj.size() > MAX_INDEX_PAGE_SIZE {
+        tracing::debug!(bucket, key, size = obj.size(), "Index page too large");
+        return Ok(None);
+    }
+
+    let data = match obj.inlined() {
+        Some(data) => data.clone(),
+        None => {
+            let size = paths.iter().map(|(_, size)| size).sum();
+            let metrics = cas_storage::SharedMetrics::default();
+            let mut stream = BlockStream::new(paths, size, RangeRequest::All, metrics);
+            let mut data = Vec::with_capacity(size);
+            while let Some(chunk) = stream.next().await {
+                data.extend_from_slice(&chunk?);
+            }
+            data
+        }
+    };
+    Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+}
+
+/// Render markdown to HTML which is safe to embed in a page.
+pub fn render_markdown(markdown: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        // raw HTML is shown as text
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+
+    let mut output = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut output, events);
+    output
+}
+
+/// Keep relative URLs and the http(s) and mailto schemes, drop everything else
+/// like `javascript:` or `data:`.
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+    let allowed = match scheme {
+        None => true,
+        Some(scheme) => ["http", "https", "mailto"]
+            .iter()
+            .any(|allowed| scheme.trim().eq_ignore_ascii_case(allowed)),
+    };
+    if allowed {
+        url
+    } else {
+        CowStr::Borrowed("#")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        let html = render_markdown("# Artifacts\n\nSee [docs](docs/index.html).");
+        assert!(html.contains("<h1>Artifacts</h1>"));
+        assert!(html.contains(r#"<a href="docs/index.html">docs</a>"#));
+    }
+
+    #[test]
+    fn test_render_markdown_is_safe() {
+        let html = render_markdown("<script>alert(1)</script>\n\nhi <img src=x onerror=alert(1)>");
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<img"));
+        assert!(html.contains("&lt;script&gt;"));
+
+        let html = render_markdown("[a](javascript:alert(1)) [b](JavaScript:x) ![c](data:x)");
+        assert!(!html.to_lowercase().contains("javascript:"));
+        assert!(!html.contains("data:"));
+
+        let html = render_markdown("[a](https://example.com/x:y) [b](mailto:ops@example.com)");
+        assert!(html.contains(r#"href="https://example.com/x:y""#));
+        assert!(html.contains(r#"href="mailto:ops@example.com""#));
+    }
+}
@@ -0,0 +1,394 @@
+//! Maintenance jobs of the HTTP UI: the jobs page of the admins and the JSON API
+//! below `/api/v1/admin/jobs`, both authenticated with the session of an admin.
+
+use std::sync::Arc;
+
+use http_body_util::{BodyExt, Limited};
+use hyper::{body::Incoming, Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use cas_storage::{CasFS, JobRecord};
+
+use crate::auth::{UserRouter, UserStore};
+use crate::jobs::{JobError, JobKind, JobManager};
+
+use super::openapi::{self, Body, Route};
+use super::ui::Ui;
+use super::{responses, templates, HttpBody};
+
+pub const JOBS_API_PREFIX: &str = "/api/v1/admin/jobs";
+
+/// Maximum size of a job request body
+const MAX_JOB_REQUEST_SIZE: usize = 4 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobOp {
+    ListJobs,
+    StartJob,
+    GetJob,
+    CancelJob,
+}
+
+/// Routes of the jobs API
+pub const ROUTES: &[Route<JobOp>] = &[
+    job_route(
+        JobOp::ListJobs,
+        "GET",
+        "/api/v1/admin/jobs",
+        "List the maintenance jobs, newest first",
+        None,
+        200,
+        Body::List("JobInfo"),
+    ),
+    job_route(
+        JobOp::StartJob,
+        "POST",
+        "/api/v1/admin/jobs",
+        "Start a maintenance job, unless the same job is running",
+        Some("StartJobRequest"),
+        202,
+        Body::Object("JobInfo"),
+    ),
+    job_route(
+        JobOp::GetJob,
+        "GET",
+        "/api/v1/admin/jobs/{id}",
+        "Status and progress of a job",
+        None,
+        200,
+        Body::Object("JobInfo"),
+    ),
+    job_route(
+        JobOp::CancelJob,
+        "POST",
+        "/api/v1/admin/jobs/{id}/cancel",
+        "Ask a running job to stop, it stops at its next unit of work",
+        None,
+        202,
+        Body::Object("JobInfo"),
+    ),
+];
+
+const fn job_route(
+    op: JobOp,
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    request: Option<&'static str>,
+    status: u16,
+    response: Body,
+) -> Route<JobOp> {
+    Route {
+        op,
+        method,
+        path,
+        tail: false,
+        summary,
+        query: &[],
+        request,
+        status,
+        response,
+        public: false,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobInfo {
+    #[serde(flatten)]
+    pub record: JobRecord,
+    /// Progress in percent, `null` while the amount of work is not known
+    pub percent: Option<u8>,
+}
+
+impl From<JobRecord> for JobInfo {
+    fn from(record: JobRecord) -> Self {
+        Self {
+            percent: record.percent(),
+            record,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartJobRequest {
+    pub kind: JobKind,
+    /// Owner of the bucket of a `bucket_delete` or `prefix_rename` job
+    pub user: Option<String>,
+    /// Bucket of a `bucket_delete` or `prefix_rename` job
+    pub bucket: Option<String>,
+    /// Key prefix moved by a `prefix_rename` job
+    pub prefix: Option<String>,
+    /// Key prefix a `prefix_rename` job moves the objects to
+    pub new_prefix: Option<String>,
+}
+
+/// Services to start jobs with
+pub struct JobContext<'a> {
+    pub jobs: &'a Arc<JobManager>,
+    pub user_router: &'a UserRouter,
+    pub user_store: &'a UserStore,
+}
+
+/// Serves the jobs page below `/admin/jobs` and the jobs API
+pub async fn handle_request(
+    req: Request<Incoming>,
+    ui: &Ui,
+    ctx: JobContext<'_>,
+) -> Response<HttpBody> {
+    let path = req.uri().path().to_string();
+    if path.starts_with(JOBS_API_PREFIX) {
+        return api_request(req, ctx).await;
+    }
+
+    match (req.method(), path.as_str()) {
+        (&Method::GET, "/admin/jobs") => jobs_page(ui, ctx.jobs, StatusCode::OK, None),
+        (&Method::POST, "/admin/jobs") => {
+            let request = match read_form(req).await {
+                Ok(request) => request,
+                Err(message) => {
+                    return jobs_page(ui, ctx.jobs, StatusCode::BAD_REQUEST, Some(&message))
+                }
+            };
+            match start_job(&ctx, request) {
+                Ok(_) => responses::redirect("/admin/jobs"),
+                Err((status, message)) => jobs_page(ui, ctx.jobs, status, Some(&message)),
+            }
+        }
+        (&Method::POST, path) if path.starts_with("/admin/jobs/") && path.ends_with("/cancel") => {
+            let id = path
+                .trim_start_matches("/admin/jobs/")
+                .trim_end_matches("/cancel");
+            match cancel_job(ctx.jobs, id) {
+                Ok(_) => responses::redirect("/admin/jobs"),
+                Err((status, message)) => jobs_page(ui, ctx.jobs, status, Some(&message)),
+            }
+        }
+        _ => responses::not_found(true),
+    }
+}
+
+async fn api_request(req: Request<Incoming>, ctx: JobContext<'_>) -> Response<HttpBody> {
+    let Some((op, params)) = openapi::match_route(ROUTES, req.method(), req.uri().path()) else {
+        return responses::not_found(false);
+    };
+
+    let result = match (op, params.as_slice()) {
+        (JobOp::ListJobs, []) => {
+            return match ctx.jobs.list() {
+                Ok(jobs) => {
+                    let jobs: Vec<JobInfo> = jobs.into_iter().map(JobInfo::from).collect();
+                    responses::json_response(StatusCode::OK, &jobs)
+                }
+                Err(e) => error_response(job_error(e)),
+            };
+        }
+        (JobOp::StartJob, []) => match read_json(req).await {
+            Ok(request) => start_job(&ctx, request).map(|record| (StatusCode::ACCEPTED, record)),
+            Err(message) => Err((StatusCode::BAD_REQUEST, message)),
+        },
+        (JobOp::GetJob, [id]) => get_job(ctx.jobs, id).map(|record| (StatusCode::OK, record)),
+        (JobOp::CancelJob, [id]) => {
+            cancel_job(ctx.jobs, id).map(|record| (StatusCode::ACCEPTED, record))
+        }
+        _ => return responses::not_found(false),
+    };
+    match result {
+        Ok((status, record)) => responses::json_response(status, &JobInfo::from(record)),
+        Err(error) => error_response(error),
+    }
+}
+
+type JobResult = Result<JobRecord, (StatusCode, String)>;
+
+fn error_response((status, message): (StatusCode, String)) -> Response<HttpBody> {
+    responses::error_response(status, &message, false)
+}
+
+fn job_error(e: JobError) -> (StatusCode, String) {
+    let status = match &e {
+        JobError::AlreadyRunning(_) | JobError::NotRunning(_) => StatusCode::CONFLICT,
+        JobError::Store(_) => {
+            tracing::warn!(error = %e, "Failed to access the job records");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    (status, e.to_string())
+}
+
+fn start_job(ctx: &JobContext<'_>, request: StartJobRequest) -> JobResult {
+    let started = match request.kind {
+        JobKind::BlobGc => ctx.jobs.start_blob_gc(),
+        JobKind::Scrub => ctx.jobs.start_scrub(),
+        JobKind::BucketDelete => {
+            let (Some(user), Some(bucket)) = (request.user, request.bucket) else {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "A bucket_delete job needs a user and a bucket".to_string(),
+                ));
+            };
+            let fs = user_bucket(ctx, &user, &bucket)?;
+            let target = format!("{}/{}", user, bucket);
+            ctx.jobs.start_bucket_delete(fs, bucket, target)
+        }
+        JobKind::UserDelete => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "A user_delete job is started by deleting the user with a deletion policy"
+                    .to_string(),
+            ))
+        }
+        JobKind::UserTakeout => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "A user_takeout job is started by the takeout route of the admin API".to_string(),
+            ))
+        }
+        JobKind::PrefixRename => {
+            let (Some(user), Some(bucket), Some(prefix), Some(new_prefix)) = (
+                request.user,
+                request.bucket,
+                request.prefix,
+                request.new_prefix,
+            ) else {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "A prefix_rename job needs a user, a bucket, a prefix and a new prefix"
+                        .to_string(),
+                ));
+            };
+            if prefix.starts_with(&new_prefix) || new_prefix.starts_with(&prefix) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Can't rename '{}' to '{}', the prefixes overlap",
+                        prefix, new_prefix
+                    ),
+                ));
+            }
+            let fs = user_bucket(ctx, &user, &bucket)?;
+            let target = format!("{}/{}", user, bucket);
+            ctx.jobs
+                .start_prefix_rename(fs, bucket, prefix, new_prefix, target)
+        }
+    };
+    started.map_err(job_error)
+}
+
+/// The store of `user`, if it exists and has `bucket`
+fn user_bucket(
+    ctx: &JobContext<'_>,
+    user: &str,
+    bucket: &str,
+) -> Result<Arc<CasFS>, (StatusCode, String)> {
+    match ctx.user_store.get_user_by_id(user) {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err((StatusCode::NOT_FOUND, format!("User '{}' not found", user))),
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to get user: {}", e),
+            ))
+        }
+    }
+    let fs = ctx
+        .user_router
+        .get_casfs_by_user_id(user)
+        .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+    match fs.bucket_exists(bucket) {
+        Ok(true) => Ok(fs),
+        Ok(false) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Bucket '{}' not found", bucket),
+        )),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+fn parse_id(id: &str) -> Result<u64, (StatusCode, String)> {
+    id.parse()
+        .map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid job id '{}'", id)))
+}
+
+fn get_job(jobs: &JobManager, id: &str) -> JobResult {
+    let id = parse_id(id)?;
+    match jobs.get(id).map_err(job_error)? {
+        Some(record) => Ok(record),
+        None => Err((StatusCode::NOT_FOUND, format!("Job {} not found", id))),
+    }
+}
+
+fn cancel_job(jobs: &JobManager, id: &str) -> JobResult {
+    let id = parse_id(id)?;
+    jobs.cancel(id).map_err(job_error)
+}
+
+fn jobs_page(
+    ui: &Ui,
+    jobs: &JobManager,
+    status: StatusCode,
+    error_message: Option<&str>,
+) -> Response<HttpBody> {
+    match jobs.list() {
+        Ok(jobs) => {
+            let jobs: Vec<JobInfo> = jobs.into_iter().map(JobInfo::from).collect();
+            responses::html_response(status, templates::admin_jobs_page(ui, &jobs, error_message))
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to list jobs");
+            responses::html_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                templates::error_page("Failed to list jobs"),
+            )
+        }
+    }
+}
+
+async fn read_body(req: Request<Incoming>) -> Result<bytes::Bytes, String> {
+    match Limited::new(req.into_body(), MAX_JOB_REQUEST_SIZE)
+        .collect()
+        .await
+    {
+        Ok(collected) => Ok(collected.to_bytes()),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read request body");
+            Err("Invalid request".to_string())
+        }
+    }
+}
+
+async fn read_json(req: Request<Incoming>) -> Result<StartJobRequest, String> {
+    let body = read_body(req).await?;
+    serde_json::from_slice(&body).map_err(|e| format!("Invalid JSON: {}", e))
+}
+
+/// The job request of the form of the jobs page, empty fields are not set
+async fn read_form(req: Request<Incoming>) -> Result<StartJobRequest, String> {
+    let body = read_body(req).await?;
+    let mut kind = None;
+    let mut user = None;
+    let mut bucket = None;
+    let mut prefix = None;
+    let mut new_prefix = None;
+    for pair in String::from_utf8_lossy(&body).split('&') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = urlencoding::decode(&value.replace('+', " ")).unwrap_or_default();
+        let value = Some(value.trim().to_string()).filter(|value| !value.is_empty());
+        match name {
+            "kind" => kind = value,
+            "user" => user = value,
+            "bucket" => bucket = value,
+            "prefix" => prefix = value,
+            "new_prefix" => new_prefix = value,
+            _ => {}
+        }
+    }
+    Ok(StartJobRequest {
+        kind: kind.unwrap_or_default().parse()?,
+        user,
+        bucket,
+        prefix,
+        new_prefix,
+    })
+}
@@ -0,0 +1,245 @@
+//! Display preferences of the object listing: the page size, the sort order and
+//! the shown columns. They are set with query parameters, and kept in the session
+//! in multi-user mode so they apply to every listing of the user.
+
+/// Page sizes offered in the UI
+pub const PAGE_SIZES: [usize; 5] = [25, 50, 100, 250, 1000];
+
+/// Largest page a listing returns
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Session preference holding the listing preferences, as a query string
+pub const SESSION_PREFERENCE: &str = "object_list";
+
+/// Column objects can be sorted by. Only the entries of the current page are
+/// sorted, pages always follow the key order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+    Blocks,
+}
+
+impl SortKey {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+            SortKey::Blocks => "blocks",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "name" => Some(SortKey::Name),
+            "size" => Some(SortKey::Size),
+            "modified" => Some(SortKey::Modified),
+            "blocks" => Some(SortKey::Blocks),
+            _ => None,
+        }
+    }
+}
+
+/// Optional column of the listing, the name is always shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Size,
+    Type,
+    Modified,
+    Blocks,
+    /// Size of the distinct blocks of the object, computed from the block metadata
+    Physical,
+    /// Expiry date by the lifecycle rules of the bucket
+    Expires,
+}
+
+impl Column {
+    pub const ALL: [Column; 6] = [
+        Column::Size,
+        Column::Type,
+        Column::Modified,
+        Column::Blocks,
+        Column::Physical,
+        Column::Expires,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Column::Size => "size",
+            Column::Type => "type",
+            Column::Modified => "modified",
+            Column::Blocks => "blocks",
+            Column::Physical => "physical",
+            Column::Expires => "expires",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Column::Size => "Size",
+            Column::Type => "Type",
+            Column::Modified => "Last Modified",
+            Column::Blocks => "Blocks",
+            Column::Physical => "Physical Size",
+            Column::Expires => "Expires",
+        }
+    }
+
+    /// The sort key of the column, if it can be sorted by
+    pub fn sort_key(self) -> Option<SortKey> {
+        match self {
+            Column::Size => Some(SortKey::Size),
+            Column::Modified => Some(SortKey::Modified),
+            Column::Blocks => Some(SortKey::Blocks),
+            Column::Type | Column::Physical | Column::Expires => None,
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        Column::ALL.iter().copied().find(|c| c.as_str() == value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListPreferences {
+    /// Entries per page
+    pub limit: usize,
+    pub sort: SortKey,
+    pub descending: bool,
+    /// Shown columns, in display order
+    pub columns: Vec<Column>,
+}
+
+impl Default for ListPreferences {
+    fn default() -> Self {
+        Self {
+            limit: DEFAULT_PAGE_SIZE,
+            sort: SortKey::Name,
+            descending: false,
+            columns: vec![Column::Size, Column::Type, Column::Modified],
+        }
+    }
+}
+
+impl ListPreferences {
+    /// The preferences set in a query string, the defaults for the others.
+    pub fn from_query(query: &str) -> Self {
+        let mut prefs = Self::default();
+        prefs.apply(query);
+        prefs
+    }
+
+    /// Override the preferences set in a query string, returns whether it set any.
+    ///
+    /// `columns` can be repeated, as a form with a checkbox per column sends it,
+    /// and holds comma separated columns. An empty `columns` hides all optional
+    /// columns.
+    pub fn apply(&mut self, query: &str) -> bool {
+        let mut changed = false;
+        let mut columns: Option<Vec<Column>> = None;
+        for (name, value) in query.split('&').filter_map(|p| p.split_once('=')) {
+            let value = urlencoding::decode(value).unwrap_or_default();
+            match name {
+                "limit" => {
+                    if let Ok(limit) = value.parse::<usize>() {
+                        self.limit = limit.clamp(1, MAX_PAGE_SIZE);
+                        changed = true;
+                    }
+                }
+                "sort" => {
+                    if let Some(sort) = SortKey::parse(&value) {
+                        self.sort = sort;
+                        changed = true;
+                    }
+                }
+                "order" => {
+                    self.descending = value == "desc";
+                    changed = true;
+                }
+                "columns" => {
+                    let columns = columns.get_or_insert_with(Vec::new);
+                    for column in value.split(',').filter_map(Column::parse) {
+                        if !columns.contains(&column) {
+                            columns.push(column);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(mut columns) = columns {
+            // the display order doesn't depend on the order of the checkboxes
+            columns.sort_by_key(|c| Column::ALL.iter().position(|all| all == c));
+            self.columns = columns;
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn shows(&self, column: Column) -> bool {
+        self.columns.contains(&column)
+    }
+
+    /// The preferences as query parameters, only the ones which differ from the
+    /// defaults. Empty if all are the defaults.
+    pub fn to_query(&self) -> String {
+        let defaults = Self::default();
+        let mut params = Vec::new();
+        if self.limit != defaults.limit {
+            params.push(format!("limit={}", self.limit));
+        }
+        if self.sort != defaults.sort {
+            params.push(format!("sort={}", self.sort.as_str()));
+        }
+        if self.descending {
+            params.push("order=desc".to_string());
+        }
+        if self.columns != defaults.columns {
+            let columns: Vec<&str> = self.columns.iter().map(|c| c.as_str()).collect();
+            params.push(format!("columns={}", columns.join(",")));
+        }
+        params.join("&")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_preferences_query() {
+        assert_eq!(ListPreferences::from_query(""), ListPreferences::default());
+        assert_eq!(ListPreferences::default().to_query(), "");
+
+        let prefs = ListPreferences::from_query(
+            "prefix=a%2F&limit=250&sort=size&order=desc&columns=&columns=physical&columns=size",
+        );
+        assert_eq!(prefs.limit, 250);
+        assert_eq!(prefs.sort, SortKey::Size);
+        assert!(prefs.descending);
+        assert_eq!(prefs.columns, vec![Column::Size, Column::Physical]);
+        assert_eq!(
+            prefs.to_query(),
+            "limit=250&sort=size&order=desc&columns=size,physical"
+        );
+        assert_eq!(ListPreferences::from_query(&prefs.to_query()), prefs);
+
+        // an empty column list hides the optional columns
+        assert!(ListPreferences::from_query("columns=").columns.is_empty());
+        assert_eq!(
+            ListPreferences::from_query("limit=100000").limit,
+            MAX_PAGE_SIZE
+        );
+        assert_eq!(ListPreferences::from_query("limit=0").limit, 1);
+
+        // other parameters don't change the preferences
+        let mut prefs = ListPreferences::default();
+        assert!(!prefs.apply("prefix=a&token=b&sort=unknown"));
+        assert!(prefs.apply("order=asc"));
+        assert_eq!(prefs, ListPreferences::default());
+    }
+}
@@ -4,17 +4,21 @@ use hyper::{body::Incoming, header, Request, Response, StatusCode};
 use std::sync::Arc;
 use tracing;
 
+use crate::alerting::Alerter;
 use crate::metrics::SharedMetrics;
 
 use crate::auth::{SessionStore, UserStore};
 
+use super::ui::Ui;
 use super::{middleware::SessionAuth, responses, templates, HttpBody};
 
-/// Handles GET /login - displays login form or first-time setup
+/// Handles GET /login - displays login form or first-time setup, with a link to
+/// the signup form if `signup` is enabled
 pub async fn handle_login_page(
     req: Request<Incoming>,
     user_store: Arc<UserStore>,
     session_auth: Arc<SessionAuth>,
+    signup: bool,
 ) -> Response<HttpBody> {
     // Check if already authenticated
     if session_auth.authenticate(&req).is_some() {
@@ -54,7 +58,7 @@ pub async fn handle_login_page(
 
         return responses::html_response(
             StatusCode::OK,
-            templates::setup_admin_page(error_message.as_deref()),
+            templates::setup_admin_page(&Ui::for_request(&req), error_message.as_deref()),
         );
     }
 
@@ -85,7 +89,12 @@ pub async fn handle_login_page(
 
     responses::html_response(
         StatusCode::OK,
-        templates::login_page(&redirect_to, error_message.as_deref()),
+        templates::login_page(
+            &Ui::for_request(&req),
+            &redirect_to,
+            error_message.as_deref(),
+            signup,
+        ),
     )
 }
 
@@ -97,6 +106,7 @@ pub async fn handle_login_submit(
     session_store: Arc<SessionStore>,
     session_auth: Arc<SessionAuth>,
     metrics: SharedMetrics,
+    alerter: &Alerter,
 ) -> Response<HttpBody> {
     // Parse form data from request body
     let body_bytes = match req.into_body().collect().await {
@@ -169,6 +179,7 @@ pub async fn handle_login_submit(
             // Authentication failed
             tracing::Span::current().record("success", false);
             metrics.record_login_attempt(false);
+            alerter.login_failed();
             tracing::warn!(username = %username, "Login failed: invalid credentials");
             redirect_with_error("/login", "Invalid username or password")
         }
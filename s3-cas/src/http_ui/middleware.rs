@@ -5,6 +5,9 @@ use hyper::{header, Request, Response, StatusCode};
 use std::sync::Arc;
 
 use crate::auth::{SessionStore, UserStore};
+use super::admin_api::ADMIN_API_PREFIX;
+use super::openapi;
+use super::ui::Ui;
 use super::{responses, HttpBody};
 
 /// Session cookie name
@@ -20,6 +23,10 @@ pub struct AuthContext {
     pub user_id: String,
     /// Whether user is admin
     pub is_admin: bool,
+    /// ID of the session, to store UI preferences in
+    pub session_id: String,
+    /// Theme and language chosen by the user
+    pub ui: Ui,
 }
 
 /// Session-based authentication middleware
@@ -74,6 +81,8 @@ impl SessionAuth {
                 Some(AuthContext {
                     user_id,
                     is_admin: user.is_admin,
+                    session_id,
+                    ui: Ui::for_user(req, &user),
                 })
             }
             Ok(None) => {
@@ -156,12 +165,46 @@ impl SessionAuth {
 
 /// Helper to check if a path is public (doesn't require authentication)
 pub fn is_public_path(path: &str) -> bool {
-    matches!(path, "/login" | "/setup-admin" | "/health")
+    matches!(
+        path,
+        "/login" | "/setup-admin" | "/signup" | "/signup/status" | "/health" | openapi::SPEC_PATH
+    ) || path.starts_with("/assets/")
 }
 
 /// Helper to check if a path requires admin privileges
 pub fn is_admin_path(path: &str) -> bool {
-    path.starts_with("/admin")
+    path.starts_with("/admin") || path.starts_with("/api/v1/admin")
+}
+
+/// Routes served by a listener of the multi-user HTTP UI
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UiListener {
+    /// All routes
+    #[default]
+    All,
+    /// All but the admin pages and APIs, which are served on the admin listener
+    Tenant,
+    /// The admin pages and APIs, and the login they need
+    Admin,
+}
+
+impl UiListener {
+    /// Returns `true` if the listener serves `path`
+    pub fn serves(self, path: &str) -> bool {
+        let admin = is_admin_path(path) || path.starts_with(ADMIN_API_PREFIX);
+        match self {
+            UiListener::All => true,
+            UiListener::Tenant => !admin,
+            UiListener::Admin => {
+                admin
+                    || matches!(
+                        path,
+                        "/login" | "/logout" | "/setup-admin" | "/health" | openapi::SPEC_PATH
+                    )
+                    || path.starts_with("/assets/")
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -172,6 +215,11 @@ mod tests {
     fn test_is_public_path() {
         assert!(is_public_path("/login"));
         assert!(is_public_path("/health"));
+        assert!(is_public_path("/assets/style.css"));
+        assert!(is_public_path("/api/v1/openapi.json"));
+        assert!(is_public_path("/signup"));
+        assert!(is_public_path("/signup/status"));
+        assert!(!is_public_path("/api/v1/buckets"));
         assert!(!is_public_path("/buckets"));
         assert!(!is_public_path("/admin"));
     }
@@ -181,10 +229,30 @@ mod tests {
         assert!(is_admin_path("/admin"));
         assert!(is_admin_path("/admin/users"));
         assert!(is_admin_path("/admin/users/new"));
+        assert!(is_admin_path("/api/v1/admin/jobs"));
+        assert!(!is_admin_path("/api/v1/buckets"));
         assert!(!is_admin_path("/buckets"));
         assert!(!is_admin_path("/login"));
     }
 
+    #[test]
+    fn test_ui_listener_serves() {
+        for path in ["/admin/users", "/api/v1/admin/jobs", "/api/admin/users"] {
+            assert!(UiListener::All.serves(path));
+            assert!(!UiListener::Tenant.serves(path));
+            assert!(UiListener::Admin.serves(path));
+        }
+        for path in ["/buckets", "/api/v1/buckets", "/signup", "/profile"] {
+            assert!(UiListener::All.serves(path));
+            assert!(UiListener::Tenant.serves(path));
+            assert!(!UiListener::Admin.serves(path));
+        }
+        for path in ["/login", "/logout", "/health", "/assets/style.css"] {
+            assert!(UiListener::Tenant.serves(path));
+            assert!(UiListener::Admin.serves(path));
+        }
+    }
+
     #[test]
     fn test_session_cookie_creation() {
         use crate::auth::SessionStore;
@@ -1,14 +1,31 @@
 mod admin;
+mod admin_api;
+mod assets;
 mod auth;
+mod blocks;
+mod by_hash;
 mod handlers;
+mod i18n;
+mod idempotency;
+mod index_page;
+mod jobs;
+mod list_preferences;
 mod login;
 mod middleware;
+mod openapi;
+mod preview;
 mod profile;
 mod responses;
+mod resumable;
+mod share;
+mod signup;
 mod templates;
+mod ui;
 
+pub use admin_api::AdminApi;
 pub use auth::BasicAuth;
-pub use middleware::SessionAuth;
+pub use middleware::{SessionAuth, UiListener};
+pub use share::{ShareLinks, DEFAULT_MAX_SHARE_LINK_LIFETIME};
 
 // Re-export the main service types
 pub use HttpUiServiceEnum as HttpUiServiceWrapper;
@@ -21,9 +38,17 @@ use hyper::{Method, Request, Response, StatusCode};
 
 use cas_storage::CasFS;
 use crate::metrics::SharedMetrics;
+use list_preferences::ListPreferences;
+use ui::Ui;
 
 use http_body_util::combinators::BoxBody;
 
+/// Prefix of the JSON API, routed by [`handlers::API_ROUTES`]
+const API_PREFIX: &str = "/api/v1/";
+
+/// Buckets listed on the hot buckets page of the admins
+const HOT_BUCKETS_SHOWN: usize = 50;
+
 pub type HttpBody = BoxBody<Bytes, Box<dyn std::error::Error + Send + Sync>>;
 
 /// HTTP UI service for browsing CAS storage
@@ -33,6 +58,7 @@ pub struct HttpUiService {
     #[allow(dead_code)]
     metrics: Arc<SharedMetrics>,
     auth: Option<BasicAuth>,
+    share_links: Option<Arc<ShareLinks>>,
 }
 
 impl HttpUiService {
@@ -41,14 +67,28 @@ impl HttpUiService {
             casfs: Arc::new(casfs),
             metrics: Arc::new(metrics),
             auth,
+            share_links: None,
         }
     }
 
+    /// Let users make share links, which download an object without logging in
+    pub fn with_share_links(mut self, share_links: Option<ShareLinks>) -> Self {
+        self.share_links = share_links.map(Arc::new);
+        self
+    }
+
     /// Main request handler
     pub async fn handle_request(
         &self,
         req: Request<hyper::body::Incoming>,
     ) -> Result<Response<HttpBody>, std::convert::Infallible> {
+        // Share links are signed, and need no authentication
+        if let Some(share_links) = &self.share_links {
+            if req.uri().path().starts_with(share::SHARE_PREFIX) {
+                return Ok(serve_share_link(share_links, &req, |_| Some(self.casfs.clone())).await);
+            }
+        }
+
         // Check authentication if enabled
         if let Some(ref auth) = self.auth {
             if !auth.check_auth(&req) {
@@ -61,24 +101,51 @@ impl HttpUiService {
     }
 
     async fn route_request(&self, req: Request<hyper::body::Incoming>) -> Response<HttpBody> {
+        if req.uri().path().starts_with(API_PREFIX) {
+            return handlers::api_request(&self.casfs, req).await;
+        }
+
         let path = req.uri().path();
         let method = req.method();
         let wants_html = self.wants_html(&req);
+        let ui = Ui::for_request(&req);
 
         match (method, path) {
             (&Method::GET, "/") => self.handle_root(wants_html).await,
             (&Method::GET, "/health") => self.handle_health().await,
-            (&Method::GET, "/api/v1/buckets") => handlers::list_buckets(&self.casfs, false, None).await,
-            (&Method::GET, "/buckets") => handlers::list_buckets(&self.casfs, wants_html, None).await,
+            (&Method::GET, path) if path.starts_with("/assets/") => {
+                assets::serve(path).unwrap_or_else(|| responses::not_found(false))
+            }
+            (&Method::GET, "/buckets") => handlers::list_buckets(&self.casfs, wants_html, None, &ui).await,
+            (&Method::GET, "/usage") => {
+                let format = if wants_html { handlers::ReportFormat::Html } else { handlers::ReportFormat::Json };
+                handlers::usage_report(&self.casfs, format, None, &ui).await
+            }
+            (&Method::GET, "/usage.csv") => {
+                handlers::usage_report(&self.casfs, handlers::ReportFormat::Csv, None, &ui).await
+            }
             (&Method::GET, path) if path.starts_with("/buckets/") => {
-                self.handle_bucket_path(path, wants_html, &req).await
+                self.handle_bucket_path(path, wants_html, &req, &ui).await
             }
             (&Method::GET, path) if path.starts_with("/download/") => {
-                self.handle_download_path(path).await
+                self.handle_download_path(path, &req).await
+            }
+            (&Method::GET | &Method::HEAD, path) if path.starts_with(preview::PREVIEW_PREFIX) => {
+                preview::serve(&self.casfs, &path[preview::PREVIEW_PREFIX.len()..], &req).await
+            }
+            (_, path) if path.starts_with("/limits/") => {
+                handlers::limits_request(&self.casfs, req, wants_html, &ui).await
             }
-            (&Method::GET, path) if path.starts_with("/api/v1/buckets/") => {
-                self.handle_api_path(path, &req).await
+            (&Method::GET, path) if path.starts_with(by_hash::BLOCKS_PREFIX) => {
+                by_hash::download_block(&self.casfs, &path[by_hash::BLOCKS_PREFIX.len()..], &req).await
             }
+            (&Method::GET, path) if path.starts_with(by_hash::OBJECTS_PREFIX) => {
+                by_hash::download_object(&self.casfs, &path[by_hash::OBJECTS_PREFIX.len()..], &req).await
+            }
+            (&Method::POST, share::CREATE_SHARE_LINK_PATH) => match &self.share_links {
+                Some(share_links) => share::create(share_links, None, &self.casfs, req, wants_html, &ui).await,
+                None => responses::not_found(wants_html),
+            },
             _ => responses::not_found(wants_html),
         }
     }
@@ -127,9 +194,21 @@ impl HttpUiService {
                     "/buckets/{bucket}": "List objects in bucket",
                     "/buckets/{bucket}/{key}": "Get object metadata",
                     "/download/{bucket}/{key}": "Download object",
+                    "/preview/{bucket}/{key}": "Image, video or audio object shown inline",
+                    "/share-links": "Create a share link (POST, if enabled)",
+                    "/share/{bucket}/{key}": "Download object with a share link",
+                    "/_cas/blocks/{hash}": "Download a block referenced by your objects",
+                    "/_cas/objects/{hash}": "Download an object by its content hash",
                     "/api/v1/buckets": "List buckets (JSON)",
                     "/api/v1/buckets/{bucket}": "List objects (JSON)",
                     "/api/v1/buckets/{bucket}/objects/{key}": "Object metadata (JSON)",
+                    "/limits/{bucket}": "Bucket object count and size limits",
+                    "/api/v1/buckets/{bucket}/limits": "Bucket limits (JSON, GET and PUT)",
+                    "/usage": "Bucket usage report",
+                    "/usage.csv": "Bucket usage history (CSV)",
+                    "/api/v1/usage": "Bucket usage report with history (JSON)",
+                    "/api/v1/activity": "Hot buckets of the last hour (JSON)",
+                    "/api/v1/openapi.json": "OpenAPI description of the JSON API",
                     "/health": "Health check"
                 }
             });
@@ -150,6 +229,7 @@ impl HttpUiService {
         path: &str,
         wants_html: bool,
         req: &Request<hyper::body::Incoming>,
+        ui: &Ui,
     ) -> Response<HttpBody> {
         let path_parts: Vec<&str> = path
             .trim_start_matches("/buckets/")
@@ -160,13 +240,15 @@ impl HttpUiService {
         match path_parts.as_slice() {
             [bucket] => {
                 let bucket = urlencoding::decode(bucket).unwrap_or(std::borrow::Cow::Borrowed(bucket));
-                handlers::list_objects(&self.casfs, &bucket, req, wants_html).await
+                let prefs = ListPreferences::from_query(req.uri().query().unwrap_or(""));
+                handlers::list_objects(&self.casfs, &bucket, req, wants_html, &prefs, ui).await
             },
             [bucket, key @ ..] => {
                 let bucket = urlencoding::decode(bucket).unwrap_or(std::borrow::Cow::Borrowed(bucket));
                 let object_key = key.join("/");
                 let object_key = urlencoding::decode(&object_key).unwrap_or(std::borrow::Cow::Borrowed(&object_key));
-                handlers::object_metadata(&self.casfs, &bucket, &object_key, wants_html).await
+                let shareable = self.share_links.is_some();
+                handlers::object_metadata(&self.casfs, &bucket, &object_key, wants_html, handlers::if_none_match(req), ui, shareable).await
             }
             _ => responses::error_response(StatusCode::BAD_REQUEST, "Invalid path", wants_html),
         }
@@ -175,6 +257,7 @@ impl HttpUiService {
     async fn handle_download_path(
         &self,
         path: &str,
+        req: &Request<hyper::body::Incoming>,
     ) -> Response<HttpBody> {
         let path_parts: Vec<&str> = path
             .trim_start_matches("/download/")
@@ -187,40 +270,18 @@ impl HttpUiService {
                 let bucket = urlencoding::decode(bucket).unwrap_or(std::borrow::Cow::Borrowed(bucket));
                 let object_key = key.join("/");
                 let object_key = urlencoding::decode(&object_key).unwrap_or(std::borrow::Cow::Borrowed(&object_key));
-                handlers::download_object(&self.casfs, &bucket, &object_key).await
+                handlers::download_object(&self.casfs, &bucket, &object_key, handlers::if_none_match(req)).await
             }
             _ => responses::error_response(StatusCode::BAD_REQUEST, "Invalid download path", false),
         }
     }
-
-    async fn handle_api_path(
-        &self,
-        path: &str,
-        req: &Request<hyper::body::Incoming>,
-    ) -> Response<HttpBody> {
-        let path_parts: Vec<&str> = path
-            .trim_start_matches("/api/v1/buckets/")
-            .split('/')
-            .filter(|s| !s.is_empty())
-            .collect();
-
-        match path_parts.as_slice() {
-            [bucket] => {
-                let bucket = urlencoding::decode(bucket).unwrap_or(std::borrow::Cow::Borrowed(bucket));
-                handlers::list_objects(&self.casfs, &bucket, req, false).await
-            },
-            [bucket, "objects", key @ ..] => {
-                let bucket = urlencoding::decode(bucket).unwrap_or(std::borrow::Cow::Borrowed(bucket));
-                let object_key = key.join("/");
-                let object_key = urlencoding::decode(&object_key).unwrap_or(std::borrow::Cow::Borrowed(&object_key));
-                handlers::object_metadata(&self.casfs, &bucket, &object_key, false).await
-            }
-            _ => responses::error_response(StatusCode::BAD_REQUEST, "Invalid API path", false),
-        }
-    }
 }
 
-use crate::auth::{SessionStore, UserRouter, UserStore};
+use crate::alerting::Alerter;
+use crate::auth::{
+    AuditLog, SessionStore, SignupStore, UserDeleter, UserRouter, UserStore, UserTemplate,
+};
+use crate::jobs::JobManager;
 
 /// HTTP UI service for multi-user mode with session-based authentication
 #[derive(Clone)]
@@ -229,6 +290,15 @@ pub struct HttpUiServiceMultiUser {
     user_store: Arc<UserStore>,
     session_store: Arc<SessionStore>,
     session_auth: Arc<SessionAuth>,
+    admin_api: Option<Arc<AdminApi>>,
+    jobs: Option<Arc<JobManager>>,
+    share_links: Option<Arc<ShareLinks>>,
+    signups: Option<Arc<SignupStore>>,
+    user_template: Option<Arc<UserTemplate>>,
+    audit: Option<Arc<AuditLog>>,
+    listener: UiListener,
+    read_only: bool,
+    alerter: Alerter,
     #[allow(dead_code)]
     metrics: SharedMetrics,
 }
@@ -250,10 +320,88 @@ impl HttpUiServiceMultiUser {
             user_store,
             session_store,
             session_auth,
+            admin_api: None,
+            jobs: None,
+            share_links: None,
+            signups: None,
+            user_template: None,
+            audit: None,
+            listener: UiListener::All,
+            read_only: false,
+            alerter: Alerter::default(),
             metrics,
         }
     }
 
+    /// Enable the JSON admin API below `/api/admin/`, authenticated with `token`
+    pub fn with_admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_api = token.map(|token| {
+            Arc::new(AdminApi::new(
+                token,
+                self.user_router.clone(),
+                self.user_store.clone(),
+                self.session_store.clone(),
+                self.metrics.clone(),
+            ))
+        });
+        self
+    }
+
+    /// Reject requests which modify users or sessions, except logging in and out.
+    /// Used on read replicas.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Alert about storms of failed logins with `alerter`
+    pub fn with_alerter(mut self, alerter: Alerter) -> Self {
+        self.alerter = alerter;
+        self
+    }
+
+    /// Let admins follow, start and cancel maintenance jobs, on the jobs page and
+    /// through the jobs API
+    pub fn with_jobs(mut self, jobs: Option<Arc<JobManager>>) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Let users make share links, which download an object of theirs without
+    /// logging in
+    pub fn with_share_links(mut self, share_links: Option<ShareLinks>) -> Self {
+        self.share_links = share_links.map(Arc::new);
+        self
+    }
+
+    /// Let visitors sign up on `/signup`, their accounts are created when an
+    /// admin approves them on `/admin/signups`
+    pub fn with_signups(mut self, signups: Option<SignupStore>) -> Self {
+        self.signups = signups.map(Arc::new);
+        self
+    }
+
+    /// Give the users created by admins and approved signups the quota and
+    /// buckets of `template`
+    pub fn with_user_template(mut self, template: Option<UserTemplate>) -> Self {
+        self.user_template = template.map(Arc::new);
+        self
+    }
+
+    /// Record the changes admins make to users in `audit`, from the admin pages
+    /// and the admin API
+    pub fn with_audit_log(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Only serve the routes of `listener`, to serve the admin routes on a
+    /// separate listener
+    pub fn with_listener(mut self, listener: UiListener) -> Self {
+        self.listener = listener;
+        self
+    }
+
     /// Main request handler
     pub async fn handle_request(
         &self,
@@ -267,11 +415,59 @@ impl HttpUiServiceMultiUser {
         let path = req.uri().path().to_string();
         let method = req.method().clone();
 
+        if !self.listener.serves(&path) {
+            // after logging in, users are sent to the buckets, which aren't served here
+            if self.listener == UiListener::Admin && method == Method::GET {
+                return responses::redirect("/admin/users");
+            }
+            return responses::not_found(false);
+        }
+
+        let is_read = matches!(method, Method::GET | Method::HEAD)
+            || (method == Method::POST && matches!(path.as_str(), "/login" | "/logout"));
+        if self.read_only && !is_read {
+            return responses::error_response(
+                StatusCode::FORBIDDEN,
+                "This server is a read-only replica",
+                false,
+            );
+        }
+
+        // Admin API, authenticated with a token instead of a session
+        if path.starts_with(admin_api::ADMIN_API_PREFIX) {
+            return match &self.admin_api {
+                Some(admin_api) => {
+                    admin_api
+                        .handle_request(
+                            req,
+                            self.jobs.as_ref(),
+                            self.user_template.as_deref(),
+                            self.audit.as_deref(),
+                        )
+                        .await
+                }
+                None => responses::not_found(false),
+            };
+        }
+
+        // Share links are signed for the owner of the object, and need no session
+        if let Some(share_links) = &self.share_links {
+            if path.starts_with(share::SHARE_PREFIX) {
+                return serve_share_link(share_links, &req, |owner| self.owner_casfs(owner)).await;
+            }
+        }
+
         // Public routes (no auth required)
         if middleware::is_public_path(&path) {
             return match (&method, path.as_str()) {
                 (&Method::GET, "/login") => {
-                    login::handle_login_page(req, self.user_store.clone(), self.session_auth.clone()).await
+                    login::handle_login_page(
+                        req,
+                        self.user_store.clone(),
+                        self.session_auth.clone(),
+                        self.signups.is_some(),
+                    )
+                    .await
                 }
                 (&Method::POST, "/login") => {
                     login::handle_login_submit(
@@ -280,6 +476,7 @@ impl HttpUiServiceMultiUser {
                         self.session_store.clone(),
                         self.session_auth.clone(),
                         self.metrics.clone(),
+                        &self.alerter,
                     )
                     .await
                 }
@@ -296,7 +493,17 @@ impl HttpUiServiceMultiUser {
                 (&Method::POST, "/logout") => {
                     login::handle_logout(req, self.session_store.clone(), self.session_auth.clone()).await
                 }
+                (_, "/signup" | "/signup/status") => match &self.signups {
+                    Some(signups) => self.handle_signup_request(req, signups, &method, &path).await,
+                    None => responses::not_found(true),
+                },
                 (&Method::GET, "/health") => self.handle_health().await,
+                (&Method::GET, openapi::SPEC_PATH) => {
+                    responses::json_response(StatusCode::OK, &openapi::spec())
+                }
+                (&Method::GET, path) if path.starts_with("/assets/") => {
+                    assets::serve(path).unwrap_or_else(|| responses::not_found(false))
+                }
                 _ => responses::not_found(true),
             };
         }
@@ -316,63 +523,176 @@ impl HttpUiServiceMultiUser {
                 return self.session_auth.forbidden_response();
             }
 
-            return self.handle_admin_request(req, &auth_context.user_id, &path, &method).await;
+            return self.handle_admin_request(req, &auth_context, &path, &method).await;
         }
 
         // Regular authenticated routes
-        self.handle_authenticated_request(req, &auth_context.user_id, auth_context.is_admin, &path, &method)
+        self.handle_authenticated_request(req, &auth_context, &path, &method)
             .await
     }
 
     async fn handle_admin_request(
         &self,
         req: Request<hyper::body::Incoming>,
-        current_user_id: &str,
+        auth_context: &middleware::AuthContext,
         path: &str,
         method: &Method,
     ) -> Response<HttpBody> {
+        let (current_user_id, ui) = (auth_context.user_id.as_str(), &auth_context.ui);
+        let auditor = admin::Auditor {
+            log: self.audit.as_deref(),
+            actor: current_user_id,
+        };
+        if path == "/admin/jobs" || path.starts_with("/admin/jobs/") || path.starts_with(jobs::JOBS_API_PREFIX) {
+            let wants_html = !path.starts_with(jobs::JOBS_API_PREFIX);
+            return match &self.jobs {
+                Some(jobs) => {
+                    let ctx = jobs::JobContext {
+                        jobs,
+                        user_router: &self.user_router,
+                        user_store: &self.user_store,
+                    };
+                    jobs::handle_request(req, ui, ctx).await
+                }
+                // read replicas run no jobs
+                None => responses::not_found(wants_html),
+            };
+        }
         match (method, path) {
-            (&Method::GET, "/admin/users") => admin::handle_list_users(self.user_store.clone()).await,
-            (&Method::GET, "/admin/users/new") => admin::handle_new_user_form().await,
+            (&Method::GET, "/admin/users") => admin::handle_list_users(ui, self.user_store.clone()).await,
+            (&Method::GET, "/admin/users/new") => admin::handle_new_user_form(ui).await,
+            (&Method::GET, "/admin/hot-buckets") => self.hot_buckets(ui, &req),
             (&Method::POST, "/admin/users") => {
-                admin::handle_create_user(req, self.user_store.clone(), self.metrics.clone()).await
+                admin::handle_create_user(
+                    req,
+                    self.user_store.clone(),
+                    &self.user_router,
+                    self.user_template.as_deref(),
+                    auditor,
+                    self.metrics.clone(),
+                )
+                .await
             }
             (&Method::POST, path) if path.starts_with("/admin/users/") && path.ends_with("/delete") => {
                 let user_id = path
                     .trim_start_matches("/admin/users/")
                     .trim_end_matches("/delete");
-                admin::handle_delete_user(user_id, self.user_store.clone(), self.session_store.clone(), self.metrics.clone()).await
+                let deleter = UserDeleter {
+                    user_router: &self.user_router,
+                    user_store: &self.user_store,
+                    session_store: &self.session_store,
+                    jobs: self.jobs.as_ref(),
+                };
+                admin::handle_delete_user(user_id, req, deleter, auditor, self.metrics.clone()).await
             }
             (&Method::POST, path) if path.starts_with("/admin/users/") && path.ends_with("/toggle-admin") => {
                 let user_id = path
                     .trim_start_matches("/admin/users/")
                     .trim_end_matches("/toggle-admin");
-                admin::handle_toggle_admin(user_id, current_user_id, self.user_store.clone(), self.metrics.clone()).await
+                admin::handle_toggle_admin(user_id, self.user_store.clone(), auditor, self.metrics.clone()).await
             }
             (&Method::GET, path) if path.starts_with("/admin/users/") && path.ends_with("/reset-password") => {
                 let user_id = path
                     .trim_start_matches("/admin/users/")
                     .trim_end_matches("/reset-password");
-                admin::handle_reset_password_form(user_id, self.user_store.clone()).await
+                admin::handle_reset_password_form(ui, user_id, self.user_store.clone()).await
             }
             (&Method::POST, path) if path.starts_with("/admin/users/") && path.ends_with("/password") => {
                 let user_id = path
                     .trim_start_matches("/admin/users/")
                     .trim_end_matches("/password");
-                admin::handle_update_password(user_id, req, self.user_store.clone(), self.session_store.clone(), self.metrics.clone()).await
+                admin::handle_update_password(user_id, req, self.user_store.clone(), self.session_store.clone(), auditor, self.metrics.clone()).await
+            }
+            (_, path) if path == "/admin/signups" || path.starts_with("/admin/signups/") => {
+                match &self.signups {
+                    Some(signups) => {
+                        self.handle_admin_signup_request(req, ui, auditor, signups, method, path).await
+                    }
+                    None => responses::not_found(true),
+                }
             }
             _ => return responses::not_found(true),
         }
     }
 
+    async fn handle_signup_request(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        signups: &SignupStore,
+        method: &Method,
+        path: &str,
+    ) -> Response<HttpBody> {
+        match (method, path) {
+            (&Method::GET, "/signup") => signup::handle_signup_page(&Ui::for_request(&req)).await,
+            (&Method::POST, "/signup") => signup::handle_signup_submit(req, &self.user_store, signups).await,
+            (&Method::GET, "/signup/status") => signup::handle_signup_status(req, signups).await,
+            _ => responses::not_found(true),
+        }
+    }
+
+    async fn handle_admin_signup_request(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        ui: &Ui,
+        auditor: admin::Auditor<'_>,
+        signups: &SignupStore,
+        method: &Method,
+        path: &str,
+    ) -> Response<HttpBody> {
+        let decision = path
+            .strip_prefix("/admin/signups/")
+            .and_then(|rest| rest.rsplit_once('/'));
+        match (method, decision) {
+            (&Method::GET, None) if path == "/admin/signups" => signup::handle_list_signups(ui, &req, signups).await,
+            (&Method::POST, Some((user_id, action))) if action == "approve" || action == "reject" => {
+                signup::handle_decide_signup(
+                    user_id,
+                    action == "approve",
+                    auditor,
+                    &self.user_store,
+                    &self.user_router,
+                    self.user_template.as_deref(),
+                    signups,
+                    &self.metrics,
+                )
+                .await
+            }
+            _ => responses::not_found(true),
+        }
+    }
+
+    /// The busiest buckets of the open user stores, for the window of the
+    /// `minutes` query parameter
+    fn hot_buckets(&self, ui: &Ui, req: &Request<hyper::body::Incoming>) -> Response<HttpBody> {
+        let minutes = match handlers::activity_minutes(req.uri().query()) {
+            Ok(minutes) => minutes,
+            Err(message) => return responses::error_response(StatusCode::BAD_REQUEST, &message, true),
+        };
+        let mut hot: Vec<(String, cas_storage::BucketActivity)> = self
+            .user_router
+            .open_stores()
+            .into_iter()
+            .flat_map(|(user_id, casfs)| {
+                casfs
+                    .bucket_activity(minutes)
+                    .into_iter()
+                    .map(move |activity| (user_id.clone(), activity))
+            })
+            .collect();
+        hot.sort_by(|(_, a), (_, b)| b.counts.requests().cmp(&a.counts.requests()));
+        hot.truncate(HOT_BUCKETS_SHOWN);
+        responses::html_response(StatusCode::OK, templates::admin_hot_buckets_page(ui, &hot, minutes))
+    }
+
     async fn handle_authenticated_request(
         &self,
         req: Request<hyper::body::Incoming>,
-        user_id: &str,
-        is_admin: bool,
+        auth_context: &middleware::AuthContext,
         path: &str,
         method: &Method,
     ) -> Response<HttpBody> {
+        let (user_id, is_admin) = (auth_context.user_id.as_str(), auth_context.is_admin);
+        let ui = &auth_context.ui;
         // Get CasFS for this user
         let casfs = match self.user_router.get_casfs_by_user_id(user_id) {
             Ok(cf) => cf,
@@ -385,12 +705,28 @@ impl HttpUiServiceMultiUser {
             }
         };
 
+        if path.starts_with(API_PREFIX) {
+            return handlers::api_request(&casfs, req).await;
+        }
+
         let wants_html = self.wants_html(&req);
 
         match (method, path) {
             (&Method::GET, "/") => self.handle_root(wants_html).await,
             (&Method::GET, "/profile") => {
-                profile::handle_profile_page(user_id.to_string(), self.user_store.clone(), req).await
+                profile::handle_profile_page(ui, user_id.to_string(), self.user_store.clone(), req).await
+            }
+            (&Method::POST, "/profile/preferences") => {
+                profile::handle_update_preferences(user_id.to_string(), req, self.user_store.clone()).await
+            }
+            (&Method::POST, "/profile/keys/rotate") => {
+                profile::handle_rotate_keys(user_id.to_string(), req, self.user_store.clone()).await
+            }
+            (&Method::POST, "/profile/keys/temporary") => {
+                profile::handle_temporary_key(ui, user_id.to_string(), req, self.user_store.clone()).await
+            }
+            (&Method::POST, "/profile/keys/revoke") => {
+                profile::handle_revoke_key(user_id.to_string(), req, self.user_store.clone()).await
             }
             (&Method::POST, "/profile/password") => {
                 profile::handle_change_password(
@@ -402,17 +738,38 @@ impl HttpUiServiceMultiUser {
                 )
                 .await
             }
-            (&Method::GET, "/api/v1/buckets") => handlers::list_buckets(&casfs, false, Some(is_admin)).await,
-            (&Method::GET, "/buckets") => handlers::list_buckets(&casfs, wants_html, Some(is_admin)).await,
+            (&Method::GET, "/buckets") => handlers::list_buckets(&casfs, wants_html, Some(is_admin), ui).await,
+            (&Method::GET, "/usage") => {
+                let format = if wants_html { handlers::ReportFormat::Html } else { handlers::ReportFormat::Json };
+                handlers::usage_report(&casfs, format, Some(is_admin), ui).await
+            }
+            (&Method::GET, "/usage.csv") => {
+                handlers::usage_report(&casfs, handlers::ReportFormat::Csv, Some(is_admin), ui).await
+            }
             (&Method::GET, path) if path.starts_with("/buckets/") => {
-                self.handle_bucket_path(&casfs, path, wants_html, &req).await
+                self.handle_bucket_path(&casfs, path, wants_html, &req, auth_context).await
             }
             (&Method::GET, path) if path.starts_with("/download/") => {
-                self.handle_download_path(&casfs, path).await
+                self.handle_download_path(&casfs, path, &req).await
             }
-            (&Method::GET, path) if path.starts_with("/api/v1/buckets/") => {
-                self.handle_api_path(&casfs, path, &req).await
+            (&Method::GET | &Method::HEAD, path) if path.starts_with(preview::PREVIEW_PREFIX) => {
+                preview::serve(&casfs, &path[preview::PREVIEW_PREFIX.len()..], &req).await
             }
+            (_, path) if path.starts_with("/limits/") => {
+                handlers::limits_request(&casfs, req, wants_html, ui).await
+            }
+            (&Method::GET, path) if path.starts_with(by_hash::BLOCKS_PREFIX) => {
+                by_hash::download_block(&casfs, &path[by_hash::BLOCKS_PREFIX.len()..], &req).await
+            }
+            (&Method::GET, path) if path.starts_with(by_hash::OBJECTS_PREFIX) => {
+                by_hash::download_object(&casfs, &path[by_hash::OBJECTS_PREFIX.len()..], &req).await
+            }
+            (&Method::POST, share::CREATE_SHARE_LINK_PATH) => match &self.share_links {
+                Some(share_links) => {
+                    share::create(share_links, Some(user_id), &casfs, req, wants_html, ui).await
+                }
+                None => responses::not_found(wants_html),
+            },
             _ => responses::not_found(wants_html),
         }
     }
@@ -432,11 +789,25 @@ impl HttpUiServiceMultiUser {
                 "endpoints": {
                     "/login": "Login page",
                     "/logout": "Logout",
+                    "/signup": "Sign up for an account (if enabled)",
                     "/buckets": "List all buckets",
                     "/buckets/{bucket}": "List objects in bucket",
                     "/buckets/{bucket}/{key}": "Get object metadata",
                     "/download/{bucket}/{key}": "Download object",
+                    "/preview/{bucket}/{key}": "Image, video or audio object shown inline",
+                    "/share-links": "Create a share link (POST, if enabled)",
+                    "/share/{bucket}/{key}": "Download object with a share link",
+                    "/_cas/blocks/{hash}": "Download a block referenced by your objects",
+                    "/_cas/objects/{hash}": "Download an object by its content hash",
+                    "/limits/{bucket}": "Bucket object count and size limits",
+                    "/usage": "Bucket usage report",
+                    "/usage.csv": "Bucket usage history (CSV)",
                     "/admin/users": "User management (admin only)",
+                    "/admin/jobs": "Maintenance jobs (admin only)",
+                    "/admin/hot-buckets": "Busiest buckets of all users (admin only)",
+                    "/admin/signups": "Pending signups (admin only, if enabled)",
+                    "/api/v1/admin/jobs": "Maintenance jobs API (admin only)",
+                    "/api/v1/openapi.json": "OpenAPI description of the JSON API and the admin API",
                     "/health": "Health check"
                 }
             });
@@ -453,12 +824,47 @@ impl HttpUiServiceMultiUser {
         responses::json_response(StatusCode::OK, &health)
     }
 
+    /// The storage of the owner of a share link, `None` if the user was deleted
+    fn owner_casfs(&self, owner: Option<&str>) -> Option<Arc<CasFS>> {
+        let owner = owner?;
+        match self.user_store.get_user_by_id(owner) {
+            Ok(Some(_)) => {}
+            Ok(None) => return None,
+            Err(e) => {
+                tracing::warn!(user_id = owner, error = %e, "Failed to get owner of share link");
+                return None;
+            }
+        }
+        match self.user_router.get_casfs_by_user_id(owner) {
+            Ok(casfs) => Some(casfs),
+            Err(e) => {
+                tracing::warn!(user_id = owner, error = %e, "Failed to open storage of share link");
+                None
+            }
+        }
+    }
+
+    /// The listing preferences of the session, updated with the ones set in the query
+    fn list_preferences(
+        &self,
+        session_id: &str,
+        req: &Request<hyper::body::Incoming>,
+    ) -> ListPreferences {
+        let saved = self.session_store.preference(session_id, list_preferences::SESSION_PREFERENCE);
+        let mut prefs = ListPreferences::from_query(saved.as_deref().unwrap_or(""));
+        if prefs.apply(req.uri().query().unwrap_or("")) {
+            self.session_store.set_preference(session_id, list_preferences::SESSION_PREFERENCE, prefs.to_query());
+        }
+        prefs
+    }
+
     async fn handle_bucket_path(
         &self,
         casfs: &Arc<CasFS>,
         path: &str,
         wants_html: bool,
         req: &Request<hyper::body::Incoming>,
+        auth_context: &middleware::AuthContext,
     ) -> Response<HttpBody> {
         let path_parts: Vec<&str> = path
             .trim_start_matches("/buckets/")
@@ -469,13 +875,15 @@ impl HttpUiServiceMultiUser {
         match path_parts.as_slice() {
             [bucket] => {
                 let bucket = urlencoding::decode(bucket).unwrap_or(std::borrow::Cow::Borrowed(bucket));
-                handlers::list_objects(casfs, &bucket, req, wants_html).await
+                let prefs = self.list_preferences(&auth_context.session_id, req);
+                handlers::list_objects(casfs, &bucket, req, wants_html, &prefs, &auth_context.ui).await
             },
             [bucket, key @ ..] => {
                 let bucket = urlencoding::decode(bucket).unwrap_or(std::borrow::Cow::Borrowed(bucket));
                 let object_key = key.join("/");
                 let object_key = urlencoding::decode(&object_key).unwrap_or(std::borrow::Cow::Borrowed(&object_key));
-                handlers::object_metadata(casfs, &bucket, &object_key, wants_html).await
+                let shareable = self.share_links.is_some();
+                handlers::object_metadata(casfs, &bucket, &object_key, wants_html, handlers::if_none_match(req), &auth_context.ui, shareable).await
             }
             _ => responses::error_response(StatusCode::BAD_REQUEST, "Invalid path", wants_html),
         }
@@ -485,6 +893,7 @@ impl HttpUiServiceMultiUser {
         &self,
         casfs: &Arc<CasFS>,
         path: &str,
+        req: &Request<hyper::body::Incoming>,
     ) -> Response<HttpBody> {
         let path_parts: Vec<&str> = path
             .trim_start_matches("/download/")
@@ -497,39 +906,12 @@ impl HttpUiServiceMultiUser {
                 let bucket = urlencoding::decode(bucket).unwrap_or(std::borrow::Cow::Borrowed(bucket));
                 let object_key = key.join("/");
                 let object_key = urlencoding::decode(&object_key).unwrap_or(std::borrow::Cow::Borrowed(&object_key));
-                handlers::download_object(casfs, &bucket, &object_key).await
+                handlers::download_object(casfs, &bucket, &object_key, handlers::if_none_match(req)).await
             }
             _ => responses::error_response(StatusCode::BAD_REQUEST, "Invalid download path", false),
         }
     }
 
-    async fn handle_api_path(
-        &self,
-        casfs: &Arc<CasFS>,
-        path: &str,
-        req: &Request<hyper::body::Incoming>,
-    ) -> Response<HttpBody> {
-        let path_parts: Vec<&str> = path
-            .trim_start_matches("/api/v1/buckets/")
-            .split('/')
-            .filter(|s| !s.is_empty())
-            .collect();
-
-        match path_parts.as_slice() {
-            [bucket] => {
-                let bucket = urlencoding::decode(bucket).unwrap_or(std::borrow::Cow::Borrowed(bucket));
-                handlers::list_objects(casfs, &bucket, req, false).await
-            },
-            [bucket, "objects", key @ ..] => {
-                let bucket = urlencoding::decode(bucket).unwrap_or(std::borrow::Cow::Borrowed(bucket));
-                let object_key = key.join("/");
-                let object_key = urlencoding::decode(&object_key).unwrap_or(std::borrow::Cow::Borrowed(&object_key));
-                handlers::object_metadata(casfs, &bucket, &object_key, false).await
-            }
-            _ => responses::error_response(StatusCode::BAD_REQUEST, "Invalid API path", false),
-        }
-    }
-
     fn wants_html(&self, req: &Request<hyper::body::Incoming>) -> bool {
         // Check query parameter first
         if let Some(query) = req.uri().query() {
@@ -558,6 +940,29 @@ impl HttpUiServiceMultiUser {
     }
 }
 
+/// Serves a share link with the storage `casfs` returns for its owner, once its
+/// signature and expiry are checked
+async fn serve_share_link(
+    share_links: &ShareLinks,
+    req: &Request<hyper::body::Incoming>,
+    casfs: impl FnOnce(Option<&str>) -> Option<Arc<CasFS>>,
+) -> Response<HttpBody> {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return responses::error_response(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed", true);
+    }
+    let Some(request) = share::ShareRequest::parse(req.uri().path(), req.uri().query()) else {
+        return responses::not_found(true);
+    };
+    if let Err(e) = share_links.verify(&request) {
+        tracing::debug!(bucket = %request.bucket, key = %request.key, "Share link denied: {e}");
+        return responses::error_response(StatusCode::FORBIDDEN, &e.to_string(), true);
+    }
+    match casfs(request.owner.as_deref()) {
+        Some(casfs) => share::download(&casfs, &request, req).await,
+        None => responses::error_response(StatusCode::NOT_FOUND, "Object not found", true),
+    }
+}
+
 /// Enum wrapper to support both single-user and multi-user HTTP UI services
 #[derive(Clone)]
 pub enum HttpUiServiceEnum {
@@ -0,0 +1,900 @@
+//! Routes of the JSON APIs and their OpenAPI description.
+//!
+//! The routers of the JSON API below `/api/v1` and of the admin API match
+//! requests against their [`Route`] tables, so every served route is described in
+//! the spec served at [`SPEC_PATH`]. The schemas of the bodies are written by
+//! hand, the tests check them against serialized values of the types.
+
+use std::borrow::Cow;
+
+use hyper::Method;
+use serde_json::{json, Map, Value};
+
+use super::{admin_api, handlers, jobs};
+
+/// Path of the OpenAPI spec
+pub const SPEC_PATH: &str = "/api/v1/openapi.json";
+
+/// A route of a JSON API
+pub struct Route<Op> {
+    /// What the router does for the route
+    pub op: Op,
+    pub method: &'static str,
+    /// Path with `{name}` parameters, each matching a single segment
+    pub path: &'static str,
+    /// Whether the last parameter takes the rest of the path, slashes included,
+    /// like an object key
+    pub tail: bool,
+    pub summary: &'static str,
+    pub query: &'static [Param],
+    /// Schema of the JSON request body
+    pub request: Option<&'static str>,
+    /// Status of a successful response
+    pub status: u16,
+    pub response: Body,
+    /// Whether the route is served without authentication
+    pub public: bool,
+}
+
+/// A query parameter of a route
+pub struct Param {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub integer: bool,
+}
+
+/// Body of a successful response
+pub enum Body {
+    /// The schema of that name
+    Object(&'static str),
+    /// An array of the schema of that name
+    List(&'static str),
+    /// A JSON document without a described schema
+    Any,
+    /// A file download of this media type
+    File(&'static str),
+}
+
+impl<Op> Route<Op> {
+    /// The decoded path parameters if the route matches the segments of a path
+    fn params(&self, segments: &[&str]) -> Option<Vec<String>> {
+        let template: Vec<&str> = self.path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut params = Vec::new();
+        for (i, part) in template.iter().enumerate() {
+            let is_param = part.starts_with('{') && part.ends_with('}');
+            if is_param && self.tail && i == template.len() - 1 {
+                let rest = segments.get(i..).filter(|rest| !rest.is_empty())?;
+                params.push(decode(&rest.join("/")));
+                return Some(params);
+            }
+            let segment = segments.get(i)?;
+            if is_param {
+                params.push(decode(segment));
+            } else if part != segment {
+                return None;
+            }
+        }
+        (segments.len() == template.len()).then_some(params)
+    }
+
+    fn path_params(&self) -> impl Iterator<Item = &'static str> {
+        self.path
+            .split('/')
+            .filter_map(|part| part.strip_prefix('{')?.strip_suffix('}'))
+    }
+}
+
+fn decode(segment: &str) -> String {
+    urlencoding::decode(segment)
+        .map(Cow::into_owned)
+        .unwrap_or_else(|_| segment.to_string())
+}
+
+/// The operation of the route matching a request, with the decoded path parameters
+pub fn match_route<Op: Copy>(
+    routes: &[Route<Op>],
+    method: &Method,
+    path: &str,
+) -> Option<(Op, Vec<String>)> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    routes
+        .iter()
+        .filter(|route| route.method == method.as_str())
+        .find_map(|route| Some((route.op, route.params(&segments)?)))
+}
+
+/// OpenAPI 3.0 description of the JSON API and the admin API
+pub fn spec() -> Value {
+    let mut paths = Map::new();
+    add_routes(
+        &mut paths,
+        handlers::API_ROUTES,
+        &["basicAuth", "sessionCookie"],
+    );
+    add_routes(&mut paths, jobs::ROUTES, &["sessionCookie"]);
+    add_routes(&mut paths, admin_api::ROUTES, &["adminToken"]);
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "s3-cas HTTP API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "JSON API of the HTTP UI, below /api/v1, and admin API, below /api/admin. \
+                The admin API is only served in multi-user mode with an admin token. The jobs API, \
+                below /api/v1/admin/jobs, is served in multi-user mode to the sessions of admins. \
+                The requests creating objects or uploads accept an Idempotency-Key header, a retry \
+                with the same key gets the response of the first request.",
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "basicAuth": {
+                    "type": "http",
+                    "scheme": "basic",
+                    "description": "Single-user mode, when the HTTP UI has credentials",
+                },
+                "sessionCookie": {
+                    "type": "apiKey",
+                    "in": "cookie",
+                    "name": super::middleware::SESSION_COOKIE_NAME,
+                    "description": "Multi-user mode, the session of a login at /login",
+                },
+                "adminToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "The token set with --admin-token",
+                },
+            },
+        },
+    })
+}
+
+fn add_routes<Op>(paths: &mut Map<String, Value>, routes: &[Route<Op>], security: &[&str]) {
+    for route in routes {
+        let mut parameters: Vec<Value> = route
+            .path_params()
+            .map(|name| {
+                json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                })
+            })
+            .collect();
+        if route.tail {
+            if let Some(last) = parameters.last_mut() {
+                last["description"] = json!("May contain slashes");
+            }
+        }
+        parameters.extend(route.query.iter().map(|param| {
+            let kind = if param.integer { "integer" } else { "string" };
+            json!({
+                "name": param.name,
+                "in": "query",
+                "description": param.description,
+                "schema": { "type": kind },
+            })
+        }));
+
+        let (media_type, content) = match route.response {
+            Body::Object(name) => ("application/json", json!({ "$ref": schema_ref(name) })),
+            Body::List(name) => (
+                "application/json",
+                json!({ "type": "array", "items": { "$ref": schema_ref(name) } }),
+            ),
+            Body::Any => ("application/json", json!({ "type": "object" })),
+            Body::File(media_type) => (media_type, json!({ "type": "string", "format": "binary" })),
+        };
+        let mut responses = Map::new();
+        responses.insert(
+            route.status.to_string(),
+            json!({
+                "description": "Success",
+                "content": { media_type: { "schema": content } },
+            }),
+        );
+        responses.insert(
+            "default".to_string(),
+            json!({
+                "description": "Error",
+                "content": { "application/json": { "schema": { "$ref": schema_ref("Error") } } },
+            }),
+        );
+        let mut operation = json!({
+            "operationId": operation_id(route),
+            "summary": route.summary,
+            "parameters": parameters,
+            "responses": responses,
+        });
+        if let Some(request) = route.request {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": { "$ref": schema_ref(request) } } },
+            });
+        }
+        if route.public {
+            operation["security"] = json!([]);
+        } else {
+            let alternatives = security.iter().map(|name| {
+                let mut requirement = Map::new();
+                requirement.insert(name.to_string(), json!([]));
+                Value::Object(requirement)
+            });
+            operation["security"] = Value::Array(alternatives.collect());
+        }
+
+        let item = paths
+            .entry(route.path.to_string())
+            .or_insert_with(|| json!({}));
+        item[route.method.to_ascii_lowercase()] = operation;
+    }
+}
+
+fn schema_ref(name: &str) -> String {
+    format!("#/components/schemas/{name}")
+}
+
+/// `GET /api/admin/users/{user_id}/keys` becomes `getApiAdminUsersUserIdKeys`
+fn operation_id<Op>(route: &Route<Op>) -> String {
+    let mut id = route.method.to_ascii_lowercase();
+    for word in route
+        .path
+        .split(['/', '_', '{', '}', '.'])
+        .filter(|w| !w.is_empty())
+    {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            id.extend(first.to_uppercase());
+            id.push_str(chars.as_str());
+        }
+    }
+    id
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer", "format": "int64", "minimum": 0 })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn nullable(mut schema: Value) -> Value {
+    schema["nullable"] = json!(true);
+    schema
+}
+
+fn array_of(name: &str) -> Value {
+    json!({ "type": "array", "items": { "$ref": schema_ref(name) } })
+}
+
+/// An object schema, all properties but the `optional` ones are required
+fn object(properties: Value, optional: &[&str]) -> Value {
+    let required: Vec<String> = properties
+        .as_object()
+        .into_iter()
+        .flat_map(|properties| properties.keys())
+        .filter(|key| !optional.contains(&key.as_str()))
+        .cloned()
+        .collect();
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+fn schemas() -> Value {
+    let usage = || {
+        json!({
+            "objects": integer(),
+            "logical_bytes": integer(),
+            "physical_bytes": integer(),
+        })
+    };
+    let with_usage = |mut properties: Value| {
+        if let (Some(properties), Value::Object(usage)) = (properties.as_object_mut(), usage()) {
+            properties.extend(usage);
+        }
+        properties
+    };
+
+    json!({
+        "Error": object(json!({ "error": string(), "status": integer() }), &[]),
+        "BucketInfo": object(json!({ "name": string(), "creation_date": string() }), &[]),
+        "DirectoryInfo": object(
+            json!({ "name": string(), "prefix": string(), "objects": integer() }),
+            &["objects"],
+        ),
+        "ObjectInfo": object(
+            json!({
+                "key": string(),
+                "size": integer(),
+                "hash": string(),
+                "last_modified": string(),
+                "is_inlined": boolean(),
+                "block_count": integer(),
+                "physical_size": integer(),
+                "expiration": { "$ref": schema_ref("ExpirationInfo") },
+            }),
+            &["physical_size", "expiration"],
+        ),
+        "ExpirationInfo": object(
+            json!({ "rule_id": string(), "expiry_date": string() }),
+            &[],
+        ),
+        "IndexPage": object(
+            json!({
+                "key": string(),
+                "format": { "type": "string", "enum": ["markdown", "html"] },
+                "content": string(),
+            }),
+            &[],
+        ),
+        "ObjectListResponse": object(
+            json!({
+                "bucket": string(),
+                "prefix": string(),
+                "directories": array_of("DirectoryInfo"),
+                "objects": array_of("ObjectInfo"),
+                "total_count": integer(),
+                "has_more": boolean(),
+                "next_token": nullable(string()),
+                "has_prev": boolean(),
+                "prev_token": nullable(string()),
+                "tag": string(),
+                "index_page": { "$ref": schema_ref("IndexPage") },
+                "degraded_objects": { "type": "array", "items": string() },
+            }),
+            &["tag", "index_page", "degraded_objects"],
+        ),
+        "BlockInfo": object(
+            json!({ "hash": string(), "size": integer(), "refcount": integer() }),
+            &[],
+        ),
+        "ObjectMetadata": object(
+            json!({
+                "key": string(),
+                "bucket": string(),
+                "size": integer(),
+                "hash": string(),
+                "etag": string(),
+                "last_modified": string(),
+                "is_inlined": boolean(),
+                "blocks": array_of("BlockInfo"),
+                "corrupt_blocks": { "type": "array", "items": string() },
+            }),
+            &["corrupt_blocks"],
+        ),
+        "ConcatRequest": object(
+            json!({
+                "key": string(),
+                "sources": { "type": "array", "items": string() },
+            }),
+            &[],
+        ),
+        "ConcatResponse": object(
+            json!({
+                "bucket": string(),
+                "key": string(),
+                "size": integer(),
+                "etag": string(),
+                "parts": integer(),
+            }),
+            &[],
+        ),
+        "BlockCheckRequest": object(
+            json!({ "blocks": { "type": "array", "items": string() } }),
+            &[],
+        ),
+        "BlockCheckResponse": object(
+            json!({
+                "block_size": integer(),
+                "hash": string(),
+                "missing": { "type": "array", "items": string() },
+            }),
+            &[],
+        ),
+        "StagedBlock": object(
+            json!({ "bucket": string(), "block": string(), "size": integer() }),
+            &[],
+        ),
+        "AssembleRequest": object(
+            json!({
+                "key": string(),
+                "blocks": { "type": "array", "items": string() },
+            }),
+            &[],
+        ),
+        "AssembleResponse": object(
+            json!({
+                "bucket": string(),
+                "key": string(),
+                "size": integer(),
+                "etag": string(),
+                "blocks": integer(),
+            }),
+            &[],
+        ),
+        "CreateUploadRequest": object(
+            json!({ "key": string(), "size": nullable(integer()) }),
+            &["size"],
+        ),
+        "ResumableUploadInfo": object(
+            json!({
+                "token": string(),
+                "bucket": string(),
+                "key": string(),
+                "size": nullable(integer()),
+                "offset": integer(),
+                "block_size": integer(),
+                "etag": string(),
+            }),
+            &["etag"],
+        ),
+        "BucketLimits": object(
+            json!({
+                "max_objects": nullable(integer()),
+                "max_bytes": nullable(integer()),
+            }),
+            &["max_objects", "max_bytes"],
+        ),
+        "BucketLimitsInfo": object(
+            json!({
+                "bucket": string(),
+                "max_objects": nullable(integer()),
+                "max_bytes": nullable(integer()),
+                "objects": integer(),
+                "logical_bytes": integer(),
+            }),
+            &[],
+        ),
+        "UsageSample": object(with_usage(json!({ "day": string() })), &[]),
+        "BucketActivity": object(
+            json!({
+                "bucket": string(),
+                "reads": integer(),
+                "writes": integer(),
+                "bytes_read": integer(),
+                "bytes_written": integer(),
+                "bytes_read_by_requesters": integer(),
+            }),
+            &[],
+        ),
+        "BucketUsageReport": object(
+            with_usage(json!({
+                "bucket": string(),
+                "tags": { "type": "object", "additionalProperties": string() },
+                "history": array_of("UsageSample"),
+            })),
+            &[],
+        ),
+        "UserInfo": object(
+            json!({
+                "user_id": string(),
+                "ui_login": string(),
+                "s3_access_key": string(),
+                "is_admin": boolean(),
+                "created_at": integer(),
+            }),
+            &[],
+        ),
+        "CreatedUser": object(
+            json!({
+                "user_id": string(),
+                "ui_login": string(),
+                "s3_access_key": string(),
+                "is_admin": boolean(),
+                "created_at": integer(),
+                "ui_password": string(),
+                "s3_secret_key": string(),
+                "buckets": { "type": "array", "items": string() },
+                "template_error": string(),
+            }),
+            &["template_error"],
+        ),
+        "CreateUserRequest": object(
+            json!({
+                "user_id": string(),
+                "ui_login": nullable(string()),
+                "ui_password": nullable(string()),
+                "s3_access_key": nullable(string()),
+                "s3_secret_key": nullable(string()),
+                "is_admin": boolean(),
+            }),
+            &["ui_login", "ui_password", "s3_access_key", "s3_secret_key", "is_admin"],
+        ),
+        "DeletedUser": object(
+            json!({
+                "deleted": string(),
+                "job": nullable(json!({ "$ref": schema_ref("JobInfo") })),
+            }),
+            &[],
+        ),
+        "AdminBucketInfo": object(json!({ "name": string(), "created_at": integer() }), &[]),
+        "UsageInfo": object(
+            json!({
+                "user_id": string(),
+                "buckets": integer(),
+                "objects": integer(),
+                "bytes": integer(),
+                "quota_bytes": nullable(integer()),
+            }),
+            &[],
+        ),
+        "QuotaRequest": object(json!({ "max_bytes": nullable(integer()) }), &[]),
+        "SigV2Request": object(json!({ "enabled": boolean() }), &[]),
+        "S3KeyInfo": object(
+            json!({ "s3_access_key": string(), "expires_at": nullable(integer()) }),
+            &[],
+        ),
+        "RotateKeyRequest": object(json!({ "grace_secs": nullable(integer()) }), &["grace_secs"]),
+        "TakeoutRequest": object(json!({ "delete_after": boolean() }), &["delete_after"]),
+        "RotatedKey": object(
+            json!({
+                "s3_access_key": string(),
+                "s3_secret_key": string(),
+                "keys": array_of("S3KeyInfo"),
+            }),
+            &[],
+        ),
+        "RevokedKey": object(json!({ "revoked": string() }), &[]),
+        "JobInfo": object(
+            json!({
+                "id": integer(),
+                "kind": {
+                    "type": "string",
+                    "enum": [
+                        "blob_gc",
+                        "scrub",
+                        "bucket_delete",
+                        "user_delete",
+                        "prefix_rename",
+                        "user_takeout"
+                    ],
+                },
+                "target": nullable(string()),
+                "status": {
+                    "type": "string",
+                    "enum": ["running", "completed", "failed", "cancelled", "interrupted"],
+                },
+                "done": integer(),
+                "total": integer(),
+                "percent": nullable(integer()),
+                "started_at": integer(),
+                "finished_at": nullable(integer()),
+                "error": nullable(string()),
+            }),
+            &[],
+        ),
+        "StartJobRequest": object(
+            json!({
+                "kind": {
+                    "type": "string",
+                    "enum": ["blob_gc", "scrub", "bucket_delete", "prefix_rename"],
+                },
+                "user": nullable(string()),
+                "bucket": nullable(string()),
+                "prefix": nullable(string()),
+                "new_prefix": nullable(string()),
+            }),
+            &["user", "bucket", "prefix", "new_prefix"],
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use cas_storage::{BucketUsage, UsageSample};
+    use serde::Serialize;
+
+    use super::*;
+    use crate::auth::{S3KeyInfo, UserUsage};
+    use crate::http_ui::{blocks, resumable};
+    use handlers::ApiOp;
+
+    /// Checks that the fields of a serialized value are the properties of a schema
+    fn assert_schema<T: Serialize>(name: &str, value: &T) {
+        let schemas = schemas();
+        let schema = &schemas[name];
+        let value = serde_json::to_value(value).unwrap();
+        let fields: BTreeSet<&str> = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        let properties: BTreeSet<&str> = schema["properties"]
+            .as_object()
+            .unwrap_or_else(|| panic!("no schema {name}"))
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert!(
+            fields.is_subset(&properties),
+            "{name}: {fields:?} not in {properties:?}"
+        );
+        for required in schema["required"].as_array().unwrap() {
+            assert!(
+                fields.contains(required.as_str().unwrap()),
+                "{name}: {required} missing"
+            );
+        }
+    }
+
+    #[test]
+    fn test_schemas_match_types() {
+        let usage = BucketUsage {
+            objects: 1,
+            logical_bytes: 2,
+            physical_bytes: 3,
+        };
+        assert_schema(
+            "ObjectListResponse",
+            &handlers::ObjectListResponse {
+                bucket: "b".to_string(),
+                prefix: String::new(),
+                directories: Vec::new(),
+                objects: Vec::new(),
+                total_count: 0,
+                has_more: false,
+                next_token: None,
+                has_prev: false,
+                prev_token: None,
+                tag: None,
+                index_page: None,
+                degraded_objects: vec!["k".to_string()],
+            },
+        );
+        assert_schema(
+            "ObjectInfo",
+            &handlers::ObjectInfo {
+                key: "k".to_string(),
+                size: 1,
+                hash: "h".to_string(),
+                last_modified: String::new(),
+                modified_secs: 0,
+                is_inlined: true,
+                block_count: 0,
+                physical_size: Some(1),
+                expiration: Some(handlers::ExpirationInfo {
+                    rule_id: "r".to_string(),
+                    expiry_date: String::new(),
+                }),
+            },
+        );
+        assert_schema(
+            "ObjectMetadata",
+            &handlers::ObjectMetadata {
+                key: "k".to_string(),
+                bucket: "b".to_string(),
+                size: 1,
+                hash: "h".to_string(),
+                etag: "e".to_string(),
+                last_modified: String::new(),
+                is_inlined: false,
+                blocks: Vec::new(),
+                corrupt_blocks: vec!["h".to_string()],
+            },
+        );
+        assert_schema(
+            "BlockInfo",
+            &handlers::BlockInfo {
+                hash: "h".to_string(),
+                size: 1,
+                refcount: 1,
+            },
+        );
+        assert_schema(
+            "ConcatResponse",
+            &handlers::ConcatResponse {
+                bucket: "b".to_string(),
+                key: "k".to_string(),
+                size: 1,
+                etag: "e".to_string(),
+                parts: 2,
+            },
+        );
+        assert_schema(
+            "BlockCheckResponse",
+            &blocks::BlockCheckResponse {
+                block_size: 1,
+                hash: "md5".to_string(),
+                missing: vec!["h".to_string()],
+            },
+        );
+        assert_schema(
+            "StagedBlock",
+            &blocks::StagedBlock {
+                bucket: "b".to_string(),
+                block: "h".to_string(),
+                size: 1,
+            },
+        );
+        assert_schema(
+            "AssembleResponse",
+            &blocks::AssembleResponse {
+                bucket: "b".to_string(),
+                key: "k".to_string(),
+                size: 1,
+                etag: "e".to_string(),
+                blocks: 2,
+            },
+        );
+        assert_schema(
+            "ResumableUploadInfo",
+            &resumable::ResumableUploadInfo {
+                token: "t".to_string(),
+                bucket: "b".to_string(),
+                key: "k".to_string(),
+                size: None,
+                offset: 0,
+                block_size: 1,
+                etag: Some("e".to_string()),
+            },
+        );
+        assert_schema(
+            "BucketLimits",
+            &cas_storage::BucketLimits {
+                max_objects: Some(1),
+                max_bytes: None,
+            },
+        );
+        assert_schema(
+            "BucketLimitsInfo",
+            &handlers::BucketLimitsInfo {
+                bucket: "b".to_string(),
+                max_objects: None,
+                max_bytes: Some(1),
+                objects: 0,
+                logical_bytes: 0,
+            },
+        );
+        assert_schema(
+            "BucketUsageReport",
+            &handlers::BucketUsageReport {
+                bucket: "b".to_string(),
+                tags: [("team".to_string(), "ops".to_string())].into(),
+                current: usage,
+                history: Vec::new(),
+            },
+        );
+        assert_schema(
+            "BucketActivity",
+            &cas_storage::BucketActivity {
+                bucket: "b".to_string(),
+                counts: cas_storage::ActivityCounts::default(),
+            },
+        );
+        assert_schema(
+            "UsageSample",
+            &UsageSample {
+                day: "2024-01-01".to_string(),
+                usage,
+            },
+        );
+
+        let user = admin_api::UserInfo {
+            user_id: "u".to_string(),
+            ui_login: "u".to_string(),
+            s3_access_key: "a".to_string(),
+            is_admin: false,
+            created_at: 0,
+        };
+        assert_schema("UserInfo", &user);
+        assert_schema(
+            "CreatedUser",
+            &admin_api::CreatedUser {
+                user,
+                ui_password: "p".to_string(),
+                s3_secret_key: "s".to_string(),
+                buckets: vec!["b".to_string()],
+                template_error: Some("e".to_string()),
+            },
+        );
+        assert_schema(
+            "AdminBucketInfo",
+            &admin_api::BucketInfo {
+                name: "b".to_string(),
+                created_at: 0,
+            },
+        );
+        assert_schema(
+            "UsageInfo",
+            &admin_api::UsageInfo {
+                user_id: "u".to_string(),
+                usage: UserUsage::default(),
+                quota_bytes: None,
+            },
+        );
+        assert_schema(
+            "S3KeyInfo",
+            &S3KeyInfo {
+                s3_access_key: "a".to_string(),
+                expires_at: None,
+            },
+        );
+        assert_schema("QuotaRequest", &admin_api::QuotaRequest { max_bytes: None });
+        assert_schema("SigV2Request", &admin_api::SigV2Request { enabled: true });
+        assert_schema(
+            "DeletedUser",
+            &admin_api::DeletedUser {
+                deleted: "alice".to_string(),
+                job: None,
+            },
+        );
+        assert_schema(
+            "JobInfo",
+            &jobs::JobInfo::from(cas_storage::JobRecord {
+                id: 1,
+                kind: "scrub".to_string(),
+                target: None,
+                status: cas_storage::JobStatus::Running,
+                done: 1,
+                total: 2,
+                started_at: 0,
+                finished_at: None,
+                error: None,
+            }),
+        );
+    }
+
+    #[test]
+    fn test_match_route() {
+        let routes = handlers::API_ROUTES;
+        let get = |path| match_route(routes, &Method::GET, path);
+        assert_eq!(get("/api/v1/buckets"), Some((ApiOp::ListBuckets, vec![])));
+        assert_eq!(
+            get("/api/v1/buckets/my%20bucket/"),
+            Some((ApiOp::ListObjects, vec!["my bucket".to_string()]))
+        );
+        assert_eq!(
+            get("/api/v1/buckets/b/objects/dir/file%2B1.txt"),
+            Some((
+                ApiOp::ObjectMetadata,
+                vec!["b".to_string(), "dir/file+1.txt".to_string()]
+            ))
+        );
+        assert_eq!(get("/api/v1/buckets/b/objects"), None);
+        assert_eq!(get("/api/v1/buckets/b/concat"), None);
+        assert_eq!(
+            match_route(routes, &Method::POST, "/api/v1/buckets/b/concat"),
+            Some((ApiOp::ConcatObjects, vec!["b".to_string()]))
+        );
+        assert_eq!(get(SPEC_PATH), Some((ApiOp::OpenApi, vec![])));
+        assert_eq!(get("/api/v1/unknown"), None);
+    }
+
+    #[test]
+    fn test_spec() {
+        let spec = spec();
+        let schemas = schemas();
+        let paths = spec["paths"].as_object().unwrap();
+        let routes = handlers::API_ROUTES.len() + jobs::ROUTES.len() + admin_api::ROUTES.len();
+        let operations: usize = paths
+            .values()
+            .map(|item| item.as_object().unwrap().len())
+            .sum();
+        assert_eq!(operations, routes, "two routes share a method and path");
+
+        // every referenced schema exists
+        let text = spec.to_string();
+        for reference in text.split("\"#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(schemas.get(name).is_some(), "missing schema {name}");
+        }
+
+        let keys = &paths["/api/admin/users/{user_id}/keys/{access_key}"]["delete"];
+        assert_eq!(
+            keys["operationId"],
+            "deleteApiAdminUsersUserIdKeysAccessKey"
+        );
+        assert_eq!(keys["parameters"].as_array().unwrap().len(), 2);
+        assert_eq!(keys["security"], json!([{ "adminToken": [] }]));
+        assert_eq!(paths[SPEC_PATH]["get"]["security"], json!([]));
+    }
+}
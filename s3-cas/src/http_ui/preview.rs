@@ -0,0 +1,390 @@
+//! Previews on the object page of the HTTP UI.
+//!
+//! Objects have no stored content type, so the type is guessed from the extension
+//! of the key, and an object with an unknown extension is shown as text if its
+//! first bytes are UTF-8. Text is rendered in the page, at most the first
+//! `MAX_TEXT_PREVIEW` bytes of it, JSON pretty printed and highlighted. Images,
+//! video and audio are loaded by the browser from `/preview/{bucket}/{key}`,
+//! which serves them inline and with ranges, so media can be seeked without
+//! downloading it first.
+//!
+//! Only types browsers show as passive content are served inline. SVG and HTML
+//! could run scripts in the origin of the UI, they are shown as text.
+
+use futures::StreamExt;
+use hyper::header::{HeaderValue, CONTENT_SECURITY_POLICY, X_CONTENT_TYPE_OPTIONS};
+use hyper::{Request, Response, StatusCode};
+
+use cas_storage::{BlockStream, CasFS, Object, RangeRequest};
+
+use super::{responses, share, HttpBody};
+
+/// Prefix of the URLs media previews are loaded from
+pub const PREVIEW_PREFIX: &str = "/preview/";
+
+/// Bytes of text shown in the preview, the page tells 64 KiB are shown
+pub const MAX_TEXT_PREVIEW: usize = 64 * 1024;
+
+/// Images larger than this are not previewed, they are loaded in full
+pub const MAX_IMAGE_PREVIEW: u64 = 20 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    Video,
+    Audio,
+}
+
+/// Extensions of the media browsers show inline, with their content type
+const MEDIA_TYPES: &[(&str, MediaKind, &str)] = &[
+    ("apng", MediaKind::Image, "image/apng"),
+    ("avif", MediaKind::Image, "image/avif"),
+    ("bmp", MediaKind::Image, "image/bmp"),
+    ("gif", MediaKind::Image, "image/gif"),
+    ("ico", MediaKind::Image, "image/x-icon"),
+    ("jpeg", MediaKind::Image, "image/jpeg"),
+    ("jpg", MediaKind::Image, "image/jpeg"),
+    ("png", MediaKind::Image, "image/png"),
+    ("webp", MediaKind::Image, "image/webp"),
+    ("m4v", MediaKind::Video, "video/mp4"),
+    ("mp4", MediaKind::Video, "video/mp4"),
+    ("ogv", MediaKind::Video, "video/ogg"),
+    ("webm", MediaKind::Video, "video/webm"),
+    ("flac", MediaKind::Audio, "audio/flac"),
+    ("m4a", MediaKind::Audio, "audio/mp4"),
+    ("mp3", MediaKind::Audio, "audio/mpeg"),
+    ("oga", MediaKind::Audio, "audio/ogg"),
+    ("ogg", MediaKind::Audio, "audio/ogg"),
+    ("opus", MediaKind::Audio, "audio/ogg"),
+    ("wav", MediaKind::Audio, "audio/wav"),
+];
+
+/// Extensions of binary files, which are not sniffed for text
+const BINARY_EXTENSIONS: &[&str] = &[
+    "7z", "bin", "bz2", "deb", "dll", "doc", "docx", "exe", "gz", "iso", "jar", "mkv", "mov",
+    "pdf", "rar", "rpm", "so", "tar", "tgz", "xls", "xlsx", "xz", "zip", "zst",
+];
+
+/// The preview of an object
+#[derive(Debug, PartialEq, Eq)]
+pub enum Preview {
+    /// Media loaded from `url`
+    Media {
+        kind: MediaKind,
+        url: String,
+        content_type: &'static str,
+    },
+    /// The start of a text object
+    Text {
+        text: String,
+        /// The object is longer than the text
+        truncated: bool,
+        /// The text is pretty printed JSON
+        json: bool,
+    },
+    /// An image above `MAX_IMAGE_PREVIEW`
+    TooLarge,
+}
+
+fn extension(key: &str) -> Option<String> {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    let (_, extension) = name.rsplit_once('.')?;
+    Some(extension.to_ascii_lowercase())
+}
+
+/// The kind and content type of the media `key` is, by its extension
+pub fn media_type(key: &str) -> Option<(MediaKind, &'static str)> {
+    let extension = extension(key)?;
+    MEDIA_TYPES
+        .iter()
+        .find(|(ext, _, _)| *ext == extension)
+        .map(|(_, kind, content_type)| (*kind, *content_type))
+}
+
+/// The preview of `obj`, stored at `key` in `bucket`, if it has one.
+///
+/// Previews are a convenience, errors are logged and the page is shown without.
+pub async fn preview(casfs: &CasFS, bucket: &str, key: &str, obj: &Object) -> Option<Preview> {
+    if let Some((kind, content_type)) = media_type(key) {
+        if kind == MediaKind::Image && obj.size() > MAX_IMAGE_PREVIEW {
+            return Some(Preview::TooLarge);
+        }
+        let encoded_key: Vec<_> = key.split('/').map(urlencoding::encode).collect();
+        let url = format!(
+            "{PREVIEW_PREFIX}{}/{}",
+            urlencoding::encode(bucket),
+            encoded_key.join("/")
+        );
+        return Some(Preview::Media {
+            kind,
+            url,
+            content_type,
+        });
+    }
+    if obj.size() == 0
+        || extension(key).is_some_and(|ext| BINARY_EXTENSIONS.contains(&ext.as_str()))
+    {
+        return None;
+    }
+
+    let data = match read_head(casfs, bucket, key, MAX_TEXT_PREVIEW).await {
+        Ok(data) => data?,
+        Err(e) => {
+            tracing::warn!(bucket, key, "Could not load preview: {e}");
+            return None;
+        }
+    };
+    let truncated = obj.size() > data.len() as u64;
+    text_preview(key, &data, truncated)
+}
+
+/// The text preview of `data`, the start of the object at `key`, if it is text
+fn text_preview(key: &str, data: &[u8], truncated: bool) -> Option<Preview> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        // the cut may split a character
+        Err(e) if truncated && e.error_len().is_none() => {
+            std::str::from_utf8(&data[..e.valid_up_to()]).ok()?
+        }
+        Err(_) => return None,
+    };
+    if text.contains('\0') {
+        return None;
+    }
+
+    let pretty = (!truncated && extension(key).as_deref() == Some("json"))
+        .then(|| serde_json::from_str::<serde_json::Value>(text).ok())
+        .flatten()
+        .and_then(|value| serde_json::to_string_pretty(&value).ok());
+    Some(match pretty {
+        Some(text) => Preview::Text {
+            text,
+            truncated,
+            json: true,
+        },
+        None => Preview::Text {
+            text: text.to_string(),
+            truncated,
+            json: false,
+        },
+    })
+}
+
+/// Read the first `limit` bytes of an object, None if it doesn't exist.
+async fn read_head(
+    casfs: &CasFS,
+    bucket: &str,
+    key: &str,
+    limit: usize,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some((obj, paths, pin)) = casfs.get_object_paths_pinned(bucket, key)? else {
+        return Ok(None);
+    };
+    if let Some(data) = obj.inlined() {
+        return Ok(Some(data[..data.len().min(limit)].to_vec()));
+    }
+
+    let size = paths.iter().map(|(_, size)| size).sum();
+    let metrics = cas_storage::SharedMetrics::default();
+    let mut stream = BlockStream::new(paths, size, RangeRequest::All, metrics).with_pin(pin);
+    let mut data = Vec::with_capacity(limit.min(size));
+    while data.len() < limit {
+        let Some(chunk) = stream.next().await else {
+            break;
+        };
+        let chunk = chunk?;
+        data.extend_from_slice(&chunk[..chunk.len().min(limit - data.len())]);
+    }
+    Ok(Some(data))
+}
+
+/// Handles GET and HEAD /preview/{bucket}/{key}, `path` is the part after the
+/// prefix
+pub async fn serve<B>(casfs: &CasFS, path: &str, req: &Request<B>) -> Response<HttpBody> {
+    let Some((bucket, key)) = path.split_once('/') else {
+        return responses::error_response(StatusCode::BAD_REQUEST, "Invalid preview path", false);
+    };
+    let bucket = urlencoding::decode(bucket).unwrap_or(std::borrow::Cow::Borrowed(bucket));
+    let key = urlencoding::decode(key).unwrap_or(std::borrow::Cow::Borrowed(key));
+
+    let Some((kind, content_type)) = media_type(&key) else {
+        return responses::error_response(
+            StatusCode::NOT_FOUND,
+            "No preview for this object",
+            false,
+        );
+    };
+    if kind == MediaKind::Image {
+        match casfs.get_object_meta(&bucket, &key) {
+            Ok(Some(obj)) if obj.size() > MAX_IMAGE_PREVIEW => {
+                return responses::error_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "Image too large to preview",
+                    false,
+                )
+            }
+            // a missing object is reported when streaming it
+            Ok(_) => {}
+            Err(e) => {
+                return responses::error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Error getting object: {e}"),
+                    false,
+                )
+            }
+        }
+    }
+
+    let mut response =
+        share::stream_object(casfs, &bucket, &key, req, content_type, "inline").await;
+    // a browser sniffing the content anyway must not run it
+    let headers = response.headers_mut();
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox"));
+    response
+}
+
+/// Class of a token of pretty printed JSON, for the highlighting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonToken {
+    Key,
+    String,
+    Number,
+    Literal,
+    /// Punctuation and white space
+    Plain,
+}
+
+impl JsonToken {
+    pub fn class(self) -> Option<&'static str> {
+        match self {
+            JsonToken::Key => Some("json-key"),
+            JsonToken::String => Some("json-string"),
+            JsonToken::Number => Some("json-number"),
+            JsonToken::Literal => Some("json-literal"),
+            JsonToken::Plain => None,
+        }
+    }
+}
+
+/// Split valid JSON into tokens to highlight
+pub fn json_tokens(json: &str) -> Vec<(JsonToken, &str)> {
+    let bytes = json.as_bytes();
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    while start < bytes.len() {
+        let (token, end) = match bytes[start] {
+            b'"' => {
+                let mut end = start + 1;
+                while end < bytes.len() && bytes[end] != b'"' {
+                    end += if bytes[end] == b'\\' { 2 } else { 1 };
+                }
+                let end = (end + 1).min(bytes.len());
+                let is_key = bytes[end..]
+                    .iter()
+                    .find(|b| !b.is_ascii_whitespace())
+                    .is_some_and(|b| *b == b':');
+                let token = if is_key {
+                    JsonToken::Key
+                } else {
+                    JsonToken::String
+                };
+                (token, end)
+            }
+            b'-' | b'0'..=b'9' => (
+                JsonToken::Number,
+                scan(bytes, start, |b| {
+                    b.is_ascii_digit() || matches!(b, b'-' | b'+' | b'.' | b'e' | b'E')
+                }),
+            ),
+            b't' | b'f' | b'n' => (
+                JsonToken::Literal,
+                scan(bytes, start, |b| b.is_ascii_lowercase()),
+            ),
+            _ => (
+                JsonToken::Plain,
+                scan(bytes, start, |b| {
+                    !matches!(b, b'"' | b'-' | b'0'..=b'9' | b't' | b'f' | b'n')
+                }),
+            ),
+        };
+        tokens.push((token, &json[start..end]));
+        start = end;
+    }
+    tokens
+}
+
+/// The end of the run of bytes from `start` matching `f`, at least one byte
+fn scan(bytes: &[u8], start: usize, f: impl Fn(u8) -> bool) -> usize {
+    let run = bytes[start + 1..].iter().take_while(|b| f(**b)).count();
+    start + 1 + run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media_type() {
+        assert_eq!(
+            media_type("photos/Cat.JPG"),
+            Some((MediaKind::Image, "image/jpeg"))
+        );
+        assert_eq!(media_type("a.mp4"), Some((MediaKind::Video, "video/mp4")));
+        assert_eq!(media_type("a.mp3"), Some((MediaKind::Audio, "audio/mpeg")));
+        // scripts could run in the origin of the UI
+        assert_eq!(media_type("logo.svg"), None);
+        assert_eq!(media_type("index.html"), None);
+        assert_eq!(media_type("dir.png/README"), None);
+    }
+
+    #[test]
+    fn test_text_preview() {
+        let preview = text_preview("data.json", br#"{"a":[1,true]}"#, false).unwrap();
+        assert_eq!(
+            preview,
+            Preview::Text {
+                text: "{\n  \"a\": [\n    1,\n    true\n  ]\n}".to_string(),
+                truncated: false,
+                json: true,
+            }
+        );
+        // a truncated document is shown as it is
+        let preview = text_preview("data.json", br#"{"a":[1,"#, true).unwrap();
+        assert!(matches!(
+            preview,
+            Preview::Text {
+                json: false,
+                truncated: true,
+                ..
+            }
+        ));
+
+        // a character cut by the limit is dropped
+        let preview = text_preview("notes", &"grüße".as_bytes()[..3], true).unwrap();
+        assert!(matches!(preview, Preview::Text { text, .. } if text == "gr"));
+        assert_eq!(text_preview("notes", b"\x89PNG\r\n\x1a\n", false), None);
+        assert_eq!(text_preview("notes", b"a\0b", false), None);
+    }
+
+    #[test]
+    fn test_json_tokens() {
+        let tokens = json_tokens("{\n  \"a\\\"b\": [-1.5e3, \"x\", null]\n}");
+        let highlighted: Vec<_> = tokens
+            .iter()
+            .filter(|(token, _)| *token != JsonToken::Plain)
+            .copied()
+            .collect();
+        assert_eq!(
+            highlighted,
+            [
+                (JsonToken::Key, "\"a\\\"b\""),
+                (JsonToken::Number, "-1.5e3"),
+                (JsonToken::String, "\"x\""),
+                (JsonToken::Literal, "null"),
+            ]
+        );
+        let text: String = tokens.iter().map(|(_, text)| *text).collect();
+        assert_eq!(text, "{\n  \"a\\\"b\": [-1.5e3, \"x\", null]\n}");
+    }
+}
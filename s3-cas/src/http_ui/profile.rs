@@ -4,12 +4,16 @@ use hyper::{body::Incoming, header, Request, Response, StatusCode};
 use std::sync::Arc;
 use tracing::{debug, warn};
 
-use crate::auth::{SessionStore, UserStore};
+use crate::auth::{SessionStore, Theme, UserStore, DEFAULT_KEY_GRACE_SECS, DEFAULT_TEMPORARY_KEY_SECS};
 
+use super::admin::{generate_access_key, generate_secret_key};
+use super::i18n::Language;
+use super::ui::Ui;
 use super::{responses, templates, SessionAuth, HttpBody};
 
 /// Handles GET /profile - displays user profile with S3 credentials
 pub async fn handle_profile_page(
+    ui: &Ui,
     user_id: String,
     user_store: Arc<UserStore>,
     req: Request<Incoming>,
@@ -28,12 +32,23 @@ pub async fn handle_profile_page(
     let is_setup = query
         .and_then(|q| q.split('&').find(|p| *p == "setup=1"))
         .is_some();
+    let is_rotated = query
+        .and_then(|q| q.split('&').find(|p| *p == "rotated=1"))
+        .is_some();
+
+    let keys = match user_store.list_s3_keys(&user_id) {
+        Ok(keys) => keys,
+        Err(e) => {
+            warn!("Failed to list S3 keys: {}", e);
+            Vec::new()
+        }
+    };
 
     match user_store.get_user_by_id(&user_id) {
         Ok(Some(user)) => {
             responses::html_response(
                 StatusCode::OK,
-                templates::profile_page(&user, error_message.as_deref(), is_setup),
+                templates::profile_page(ui, &user, &keys, error_message.as_deref(), is_setup, is_rotated),
             )
         }
         Ok(None) => {
@@ -154,6 +169,219 @@ pub async fn handle_change_password(
     }
 }
 
+/// Handles POST /profile/keys/rotate - replaces the S3 key pair, the old pair
+/// stays valid for the chosen grace period
+pub async fn handle_rotate_keys(
+    user_id: String,
+    req: Request<Incoming>,
+    user_store: Arc<UserStore>,
+) -> Response<HttpBody> {
+    let form = match read_form(req).await {
+        Ok(form) => form,
+        Err(resp) => return resp,
+    };
+
+    let grace_secs = match form.iter().find(|(key, _)| key == "grace_hours") {
+        Some((_, hours)) => match hours.parse::<u64>() {
+            Ok(hours) => hours.saturating_mul(60 * 60),
+            Err(_) => return redirect_with_error("/profile", "Invalid grace period"),
+        },
+        None => DEFAULT_KEY_GRACE_SECS,
+    };
+
+    match user_store.rotate_s3_key(&user_id, generate_access_key(), generate_secret_key(), grace_secs) {
+        Ok(_) => {
+            debug!("S3 key rotated for user: {}", user_id);
+            redirect("/profile?rotated=1")
+        }
+        Err(e) => {
+            warn!("Failed to rotate S3 key: {}", e);
+            redirect_with_error("/profile", "Failed to rotate S3 keys")
+        }
+    }
+}
+
+/// Handles POST /profile/keys/temporary - creates a short-lived S3 key pair,
+/// shown once in the response
+pub async fn handle_temporary_key(
+    ui: &Ui,
+    user_id: String,
+    req: Request<Incoming>,
+    user_store: Arc<UserStore>,
+) -> Response<HttpBody> {
+    let form = match read_form(req).await {
+        Ok(form) => form,
+        Err(resp) => return resp,
+    };
+
+    let ttl_secs = match form.iter().find(|(key, _)| key == "ttl_minutes") {
+        Some((_, minutes)) => match minutes.parse::<u64>() {
+            Ok(minutes) if minutes > 0 => minutes.saturating_mul(60),
+            _ => return redirect_with_error("/profile", "Invalid lifetime"),
+        },
+        None => DEFAULT_TEMPORARY_KEY_SECS,
+    };
+
+    let access_key = generate_access_key();
+    let secret_key = generate_secret_key();
+    match user_store.create_temporary_s3_key(&user_id, access_key.clone(), secret_key.clone(), ttl_secs) {
+        Ok(expires_at) => {
+            debug!("Temporary S3 key {} created for user: {}", access_key, user_id);
+            let resp = responses::html_response(
+                StatusCode::OK,
+                templates::temporary_key_page(ui, &access_key, &secret_key, expires_at),
+            );
+            no_store(resp)
+        }
+        Err(e) => {
+            warn!("Failed to create temporary S3 key: {}", e);
+            redirect_with_error("/profile", "Failed to create temporary credentials")
+        }
+    }
+}
+
+/// Handles POST /profile/keys/revoke - revokes an old S3 key before it expires
+pub async fn handle_revoke_key(
+    user_id: String,
+    req: Request<Incoming>,
+    user_store: Arc<UserStore>,
+) -> Response<HttpBody> {
+    let form = match read_form(req).await {
+        Ok(form) => form,
+        Err(resp) => return resp,
+    };
+
+    let access_key = match form.into_iter().find(|(key, _)| key == "access_key") {
+        Some((_, access_key)) if !access_key.is_empty() => access_key,
+        _ => return redirect_with_error("/profile", "Access key is required"),
+    };
+
+    match user_store.revoke_s3_key(&user_id, &access_key) {
+        Ok(()) => {
+            debug!("S3 key {} revoked for user: {}", access_key, user_id);
+            redirect("/profile")
+        }
+        Err(e) => {
+            warn!("Failed to revoke S3 key: {}", e);
+            redirect_with_error("/profile", "Failed to revoke S3 key")
+        }
+    }
+}
+
+/// Handles POST /profile/preferences - sets the theme and language of the UI.
+/// Fields missing from the form keep their value, an empty language follows the
+/// browser. Returns to the page the form was sent from.
+pub async fn handle_update_preferences(
+    user_id: String,
+    req: Request<Incoming>,
+    user_store: Arc<UserStore>,
+) -> Response<HttpBody> {
+    let back = req
+        .headers()
+        .get(header::REFERER)
+        .and_then(|referer| referer.to_str().ok())
+        .and_then(local_path)
+        .unwrap_or("/profile")
+        .to_string();
+
+    let form = match read_form(req).await {
+        Ok(form) => form,
+        Err(resp) => return resp,
+    };
+
+    let user = match user_store.get_user_by_id(&user_id) {
+        Ok(Some(user)) => user,
+        Ok(None) => return redirect_with_error("/profile", "User not found"),
+        Err(e) => {
+            warn!("Failed to get user: {}", e);
+            return redirect_with_error("/profile", "Failed to load profile");
+        }
+    };
+
+    let mut theme = user.theme;
+    let mut language = user.language;
+    for (key, value) in form {
+        match key.as_str() {
+            "theme" => match Theme::parse(&value) {
+                Some(value) => theme = value,
+                None => return redirect_with_error("/profile", "Invalid theme"),
+            },
+            "language" if value.is_empty() => language = None,
+            "language" => match Language::parse(&value) {
+                Some(value) => language = Some(value.code().to_string()),
+                None => return redirect_with_error("/profile", "Invalid language"),
+            },
+            _ => {}
+        }
+    }
+
+    match user_store.update_ui_preferences(&user_id, theme, language) {
+        Ok(()) => redirect(&back),
+        Err(e) => {
+            warn!("Failed to update UI preferences: {}", e);
+            redirect_with_error("/profile", "Failed to save the preferences")
+        }
+    }
+}
+
+/// The path and query of a URL on this server, to redirect to it without
+/// becoming an open redirect
+fn local_path(url: &str) -> Option<&str> {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/')?..],
+        None => url,
+    };
+    // browsers read `//` and `/\` as the start of another host
+    let local = path.starts_with('/') && !path.starts_with("//") && !path.starts_with("/\\");
+    local.then_some(path)
+}
+
+/// Reads the fields of a url-encoded form
+async fn read_form(req: Request<Incoming>) -> Result<Vec<(String, String)>, Response<HttpBody>> {
+    let body_bytes = match req.into_body().collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            warn!("Failed to read request body: {}", e);
+            return Err(redirect_with_error("/profile", "Invalid request"));
+        }
+    };
+
+    let body_str = match std::str::from_utf8(&body_bytes) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Invalid UTF-8 in request body: {}", e);
+            return Err(redirect_with_error("/profile", "Invalid request"));
+        }
+    };
+
+    Ok(body_str
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| {
+            let value = urlencoding::decode(&value.replace('+', " "))
+                .unwrap_or_default()
+                .into_owned();
+            (key.to_string(), value)
+        })
+        .collect())
+}
+
+// keeps a page with a secret out of the browser cache
+fn no_store(mut resp: Response<HttpBody>) -> Response<HttpBody> {
+    resp.headers_mut()
+        .insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-store"));
+    resp
+}
+
+fn redirect(location: &str) -> Response<HttpBody> {
+    let resp = Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(header::LOCATION, location)
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    responses::map_response(resp)
+}
+
 fn redirect_with_error(path: &str, message: &str) -> Response<HttpBody> {
     let url = format!("{}?error={}", path, urlencoding::encode(message));
     let resp = Response::builder()
@@ -163,3 +391,18 @@ fn redirect_with_error(path: &str, message: &str) -> Response<HttpBody> {
         .unwrap();
     responses::map_response(resp)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_path() {
+        assert_eq!(local_path("https://s3.example.com/buckets?prefix=a"), Some("/buckets?prefix=a"));
+        assert_eq!(local_path("/profile"), Some("/profile"));
+        assert_eq!(local_path("https://s3.example.com"), None);
+        assert_eq!(local_path("//evil.example.com/"), None);
+        assert_eq!(local_path("/\\evil.example.com/"), None);
+        assert_eq!(local_path("profile"), None);
+    }
+}
@@ -1,8 +1,18 @@
+use std::path::PathBuf;
+use std::time::SystemTime;
+
 use bytes::Bytes;
-use http_body_util::{Full, BodyExt};
+use futures::StreamExt;
+use http_body_util::{Full, BodyExt, StreamBody};
+use hyper::body::Frame;
+use hyper::header::{HeaderValue, CACHE_CONTROL, ETAG, LAST_MODIFIED, VARY};
 use hyper::{Response, StatusCode};
 use serde::Serialize;
 
+use cas_storage::{BlockStream, RangeRequest, SharedMetrics};
+
+use crate::http_cache::http_date;
+
 use super::templates;
 use super::HttpBody;
 
@@ -31,6 +41,46 @@ pub fn html_response(status: StatusCode, html: String) -> Response<HttpBody> {
     map_response(resp)
 }
 
+/// CSV download, saved as `filename` by browsers.
+pub fn csv_response(filename: &str, csv: String) -> Response<HttpBody> {
+    let resp = Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/csv; charset=utf-8")
+        .header("content-disposition", format!("attachment; filename=\"{filename}\""))
+        .body(Full::new(Bytes::from(csv)))
+        .unwrap();
+    map_response(resp)
+}
+
+/// Download of the file at `path` of `size` bytes, saved as `filename` by browsers.
+pub fn file_response(
+    path: PathBuf,
+    size: usize,
+    content_type: &str,
+    filename: &str,
+) -> Response<HttpBody> {
+    let frames = BlockStream::new(
+        vec![(path, size)],
+        size,
+        RangeRequest::All,
+        SharedMetrics::default(),
+    )
+    .map(|res| {
+        res.map(Frame::data)
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    });
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", content_type)
+        .header("content-length", size)
+        .header(
+            "content-disposition",
+            format!("attachment; filename=\"{filename}\""),
+        )
+        .body(BodyExt::boxed(StreamBody::new(frames)))
+        .unwrap()
+}
+
 pub fn error_response(status: StatusCode, message: &str, wants_html: bool) -> Response<HttpBody> {
     if wants_html {
         html_response(status, templates::error_page(message))
@@ -43,6 +93,48 @@ pub fn error_response(status: StatusCode, message: &str, wants_html: bool) -> Re
     }
 }
 
+/// 303 redirect to `location`, used after handling a form submission.
+pub fn redirect(location: &str) -> Response<HttpBody> {
+    let resp = Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header("location", location)
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    map_response(resp)
+}
+
 pub fn not_found(wants_html: bool) -> Response<HttpBody> {
     error_response(StatusCode::NOT_FOUND, "Not Found", wants_html)
 }
+
+/// Cache-Control of object responses in the UI: they are only available to the
+/// logged in user, and must be revalidated as objects can be overwritten.
+pub const UI_CACHE_CONTROL: &str = "private, no-cache";
+
+/// Add the ETag, Last-Modified and Cache-Control headers to an object response.
+pub fn with_cache_headers(
+    mut response: Response<HttpBody>,
+    etag: &str,
+    last_modified: SystemTime,
+) -> Response<HttpBody> {
+    let headers = response.headers_mut();
+    if let Ok(etag) = HeaderValue::from_str(etag) {
+        headers.insert(ETAG, etag);
+    }
+    if let Ok(date) = HeaderValue::from_str(&http_date(last_modified)) {
+        headers.insert(LAST_MODIFIED, date);
+    }
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(UI_CACHE_CONTROL));
+    // the same path serves HTML and JSON depending on the Accept header
+    headers.insert(VARY, HeaderValue::from_static("Accept"));
+    response
+}
+
+/// Empty `304 Not Modified` response for a conditional request.
+pub fn not_modified(etag: &str, last_modified: SystemTime) -> Response<HttpBody> {
+    let resp = Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    with_cache_headers(map_response(resp), etag, last_modified)
+}
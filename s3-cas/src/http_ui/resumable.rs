@@ -0,0 +1,302 @@
+//! Resumable uploads: a client creates an upload, sends the data of the object
+//! in one or more PUT requests with a `Content-Range` header and, when a
+//! connection is lost, asks how much of it is stored and continues from there,
+//! instead of sending the whole object again.
+
+use bytes::BytesMut;
+use http_body_util::BodyExt;
+use hyper::{
+    body::{Body, Incoming},
+    Request, Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+use cas_storage::{CasFS, MetaError, ResumableUpload};
+
+use super::blocks::{error_response, read_json};
+use super::{responses, HttpBody};
+
+/// Request body of `POST /api/v1/buckets/{bucket}/uploads`
+#[derive(Deserialize)]
+pub struct CreateUploadRequest {
+    /// Key of the object created by the upload
+    pub key: String,
+    /// Size of the object, if known
+    #[serde(default)]
+    pub size: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ResumableUploadInfo {
+    pub token: String,
+    pub bucket: String,
+    pub key: String,
+    pub size: Option<u64>,
+    /// Amount of bytes stored, the next request sends the data from here
+    pub offset: u64,
+    /// Data is stored a block at a time, the rest of a request which doesn't end
+    /// the upload is sent again
+    pub block_size: usize,
+    /// ETag of the object, once the upload is finished
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+}
+
+impl ResumableUploadInfo {
+    fn new(upload: ResumableUpload, block_size: usize) -> Self {
+        Self {
+            token: upload.token,
+            bucket: upload.bucket,
+            key: upload.key,
+            size: upload.size,
+            offset: upload.offset,
+            block_size,
+            etag: None,
+        }
+    }
+}
+
+/// A parsed `Content-Range: bytes <first>-<last>/<total>` header, the range is
+/// `*` in a request without data and the total is `*` while it's unknown.
+#[derive(Debug, PartialEq, Eq)]
+struct ContentRange {
+    range: Option<(u64, u64)>,
+    total: Option<u64>,
+}
+
+fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let (range, total) = value.strip_prefix("bytes ")?.trim().split_once('/')?;
+    let range = match range {
+        "*" => None,
+        range => {
+            let (first, last) = range.split_once('-')?;
+            let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+            if first > last {
+                return None;
+            }
+            Some((first, last))
+        }
+    };
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    if range.is_none() && total.is_none() {
+        return None;
+    }
+    if let (Some((_, last)), Some(total)) = (range, total) {
+        if last >= total {
+            return None;
+        }
+    }
+    Some(ContentRange { range, total })
+}
+
+fn upload_error(e: MetaError, action: &str) -> Response<HttpBody> {
+    match e {
+        MetaError::KeyNotFound => upload_not_found(),
+        e => error_response(e, action),
+    }
+}
+
+fn upload_not_found() -> Response<HttpBody> {
+    responses::error_response(StatusCode::NOT_FOUND, "Upload not found", false)
+}
+
+/// Handles POST /api/v1/buckets/{bucket}/uploads
+pub async fn create_upload<B>(casfs: &CasFS, bucket: &str, req: Request<B>) -> Response<HttpBody>
+where
+    B: Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let request: CreateUploadRequest = match read_json(req).await {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    match casfs.create_resumable_upload(bucket, &request.key, request.size) {
+        Ok(upload) => {
+            tracing::debug!(bucket, key = %upload.key, token = %upload.token, "Created resumable upload");
+            let info = ResumableUploadInfo::new(upload, casfs.block_size());
+            responses::json_response(StatusCode::CREATED, &info)
+        }
+        Err(e) => upload_error(e, "creating upload"),
+    }
+}
+
+/// Handles GET /api/v1/buckets/{bucket}/uploads/{token}
+pub async fn upload_status(casfs: &CasFS, bucket: &str, token: &str) -> Response<HttpBody> {
+    match casfs.resumable_upload(bucket, token) {
+        Ok(Some(upload)) => {
+            let info = ResumableUploadInfo::new(upload, casfs.block_size());
+            responses::json_response(StatusCode::OK, &info)
+        }
+        Ok(None) => upload_not_found(),
+        Err(e) => upload_error(e, "reading upload"),
+    }
+}
+
+/// Handles PUT /api/v1/buckets/{bucket}/uploads/{token}, the body is the data of
+/// the `Content-Range` of the request.
+///
+/// Data the upload already has is skipped, so a client which doesn't know how
+/// far the last request got can send it again. The upload is finished when the
+/// request ends at the total size, otherwise the whole blocks received are
+/// stored and the offset in the response tells where to continue.
+pub async fn put_upload(
+    casfs: &CasFS,
+    bucket: &str,
+    token: &str,
+    req: Request<Incoming>,
+) -> Response<HttpBody> {
+    let content_range = req
+        .headers()
+        .get(hyper::header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_content_range);
+    let Some(content_range) = content_range else {
+        return responses::error_response(
+            StatusCode::BAD_REQUEST,
+            "Expected a Content-Range: bytes <first>-<last>/<total> header",
+            false,
+        );
+    };
+    let mut upload = match casfs.resumable_upload(bucket, token) {
+        Ok(Some(upload)) => upload,
+        Ok(None) => return upload_not_found(),
+        Err(e) => return upload_error(e, "reading upload"),
+    };
+    if let (Some(total), Some(size)) = (content_range.total, upload.size) {
+        if total != size {
+            return responses::error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("The upload has {size} bytes, not {total}"),
+                false,
+            );
+        }
+    }
+    let (first, end) = match content_range.range {
+        Some((first, last)) => (first, last + 1),
+        None => (upload.offset, upload.offset),
+    };
+    if first > upload.offset {
+        return responses::error_response(
+            StatusCode::CONFLICT,
+            &format!("The upload continues at byte {}", upload.offset),
+            false,
+        );
+    }
+
+    let block_size = casfs.block_size();
+    let mut body = req.into_body();
+    let mut skip = upload.offset - first;
+    let mut position = first;
+    let mut buffer = BytesMut::new();
+    while let Some(frame) = body.frame().await {
+        let data = match frame {
+            Ok(frame) => match frame.into_data() {
+                Ok(data) => data,
+                Err(_) => continue,
+            },
+            Err(e) => {
+                // the blocks received so far are kept for the next request
+                tracing::debug!(bucket, token, error = %e, "Resumable upload interrupted");
+                break;
+            }
+        };
+        position += data.len() as u64;
+        if position > end {
+            return responses::error_response(
+                StatusCode::BAD_REQUEST,
+                "The body is longer than the Content-Range",
+                false,
+            );
+        }
+        let skipped = skip.min(data.len() as u64);
+        skip -= skipped;
+        buffer.extend_from_slice(&data[skipped as usize..]);
+        while buffer.len() >= block_size {
+            let block = buffer.split_to(block_size).freeze();
+            upload = match casfs
+                .append_resumable_block(bucket, token, upload.offset, block)
+                .await
+            {
+                Ok(upload) => upload,
+                Err(e) => return upload_error(e, "storing upload data"),
+            };
+        }
+    }
+
+    let finished = position == end && content_range.total == Some(end);
+    if !finished {
+        // the rest of a block is sent again by the next request
+        let info = ResumableUploadInfo::new(upload, block_size);
+        return responses::json_response(StatusCode::OK, &info);
+    }
+    let tail = buffer.freeze();
+    match casfs
+        .finish_resumable_upload(bucket, token, upload.offset, tail)
+        .await
+    {
+        Ok(obj) => {
+            tracing::info!(bucket, key = %upload.key, size = obj.size(), "Finished resumable upload");
+            let mut info = ResumableUploadInfo::new(upload, block_size);
+            info.offset = obj.size();
+            info.etag = Some(obj.format_e_tag());
+            responses::json_response(StatusCode::OK, &info)
+        }
+        Err(e) => upload_error(e, "finishing upload"),
+    }
+}
+
+/// Handles DELETE /api/v1/buckets/{bucket}/uploads/{token}
+pub async fn abort_upload(casfs: &CasFS, bucket: &str, token: &str) -> Response<HttpBody> {
+    let upload = match casfs.resumable_upload(bucket, token) {
+        Ok(Some(upload)) => upload,
+        Ok(None) => return upload_not_found(),
+        Err(e) => return upload_error(e, "reading upload"),
+    };
+    match casfs.abort_resumable_upload(bucket, token).await {
+        Ok(true) => {
+            let info = ResumableUploadInfo::new(upload, casfs.block_size());
+            responses::json_response(StatusCode::OK, &info)
+        }
+        Ok(false) => upload_not_found(),
+        Err(e) => upload_error(e, "aborting upload"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_content_range() {
+        let parse = parse_content_range;
+        assert_eq!(
+            parse("bytes 0-99/1000"),
+            Some(ContentRange {
+                range: Some((0, 99)),
+                total: Some(1000),
+            })
+        );
+        assert_eq!(
+            parse("bytes 100-199/*"),
+            Some(ContentRange {
+                range: Some((100, 199)),
+                total: None,
+            })
+        );
+        assert_eq!(
+            parse("bytes */1000"),
+            Some(ContentRange {
+                range: None,
+                total: Some(1000),
+            })
+        );
+        assert_eq!(parse("bytes */*"), None);
+        assert_eq!(parse("bytes 10-9/*"), None);
+        assert_eq!(parse("bytes 0-1000/1000"), None);
+        assert_eq!(parse("items 0-9/10"), None);
+        assert_eq!(parse("bytes 0-x/10"), None);
+    }
+}
@@ -0,0 +1,690 @@
+//! Share links: signed URLs below `/share/` which let anyone download an object
+//! until the link expires, without an account.
+//!
+//! A link is `/share/{bucket}/{key}?expires=..&signature=..`, in multi-user mode
+//! with the `owner` of the bucket in the query as well. The signature is an
+//! HMAC-SHA256 over the owner, bucket, key and expiry with the secret of the
+//! server, so a link can't be changed to another object or a later expiry.
+//! Links can't be revoked one by one, changing the secret invalidates all of them.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use faster_hex::{hex_decode, hex_string};
+use futures::{future, StreamExt};
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full, Limited, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{HeaderValue, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_RANGE};
+use hyper::{Method, Request, Response, StatusCode};
+use serde::Serialize;
+use sha2::Sha256;
+
+use cas_storage::{parse_multi_range_request, BlockStream, ByteRanges, CasFS, RangeRequest};
+
+use crate::http_cache::etag_matches;
+
+use super::ui::Ui;
+use super::{responses, templates, HttpBody};
+
+/// Prefix of the share links
+pub const SHARE_PREFIX: &str = "/share/";
+
+/// Path the share links are created at, from the form of the object page or as JSON
+pub const CREATE_SHARE_LINK_PATH: &str = "/share-links";
+
+/// Longest lifetime of a share link by default
+pub const DEFAULT_MAX_SHARE_LINK_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Lifetime of a share link when none is asked for
+const DEFAULT_SHARE_LINK_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Maximum size of the form asking for a share link
+const MAX_SHARE_LINK_REQUEST_SIZE: usize = 4096;
+
+/// Share links are downloaded by others, they must not be kept in shared caches
+const SHARE_CACHE_CONTROL: &str = "private, no-cache";
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Why a share link is not accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareError {
+    /// The link has no valid expiry or signature
+    Malformed,
+    /// The signature doesn't match the link, or another secret signed it
+    InvalidSignature,
+    /// The link expired
+    Expired,
+}
+
+impl fmt::Display for ShareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShareError::Malformed => write!(f, "The share link is incomplete"),
+            ShareError::InvalidSignature => write!(f, "The share link is invalid"),
+            ShareError::Expired => write!(f, "The share link has expired"),
+        }
+    }
+}
+
+/// A share link made by [`ShareLinks::link`]
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareLink {
+    /// Path and query of the link
+    pub path: String,
+    /// Seconds since the UNIX epoch the link expires at
+    pub expires: u64,
+}
+
+/// A request for a share link, before its signature is verified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShareRequest {
+    pub owner: Option<String>,
+    pub bucket: String,
+    pub key: String,
+    pub expires: Option<u64>,
+    pub signature: Option<String>,
+}
+
+impl ShareRequest {
+    /// The share request of a path below [`SHARE_PREFIX`], `None` if it names no object
+    pub fn parse(path: &str, query: Option<&str>) -> Option<Self> {
+        let mut parts = path.strip_prefix(SHARE_PREFIX)?.splitn(2, '/');
+        let bucket = urlencoding::decode(parts.next()?).ok()?.into_owned();
+        let key = urlencoding::decode(parts.next()?).ok()?.into_owned();
+        if bucket.is_empty() || key.is_empty() {
+            return None;
+        }
+
+        let mut request = ShareRequest {
+            owner: None,
+            bucket,
+            key,
+            expires: None,
+            signature: None,
+        };
+        for pair in query.unwrap_or_default().split('&') {
+            let Some((name, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let value = urlencoding::decode(value).unwrap_or_default().into_owned();
+            match name {
+                "owner" => request.owner = Some(value),
+                "expires" => request.expires = value.parse().ok(),
+                "signature" => request.signature = Some(value),
+                _ => {}
+            }
+        }
+        Some(request)
+    }
+}
+
+/// Signs and verifies share links with the secret of the server
+#[derive(Clone)]
+pub struct ShareLinks {
+    secret: Vec<u8>,
+    max_lifetime: Duration,
+}
+
+impl fmt::Debug for ShareLinks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShareLinks")
+            .field("secret", &"<redacted>")
+            .field("max_lifetime", &self.max_lifetime)
+            .finish()
+    }
+}
+
+impl ShareLinks {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+            max_lifetime: DEFAULT_MAX_SHARE_LINK_LIFETIME,
+        }
+    }
+
+    /// Links asking for a longer lifetime expire after `max_lifetime`
+    pub fn with_max_lifetime(mut self, max_lifetime: Duration) -> Self {
+        self.max_lifetime = max_lifetime;
+        self
+    }
+
+    pub fn max_lifetime(&self) -> Duration {
+        self.max_lifetime
+    }
+
+    fn signature(
+        &self,
+        owner: Option<&str>,
+        bucket: &str,
+        key: &str,
+        expires: u64,
+    ) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        let expires = expires.to_string();
+        for part in [owner.unwrap_or_default(), bucket, key, expires.as_str()] {
+            mac.update(&(part.len() as u64).to_le_bytes());
+            mac.update(part.as_bytes());
+        }
+        mac
+    }
+
+    /// A link to `key` in `bucket` of `owner`, valid for `lifetime`, at most the
+    /// maximum lifetime, or a day if `None`
+    pub fn link(
+        &self,
+        owner: Option<&str>,
+        bucket: &str,
+        key: &str,
+        lifetime: Option<Duration>,
+    ) -> ShareLink {
+        self.link_at(owner, bucket, key, lifetime, now_secs())
+    }
+
+    fn link_at(
+        &self,
+        owner: Option<&str>,
+        bucket: &str,
+        key: &str,
+        lifetime: Option<Duration>,
+        now: u64,
+    ) -> ShareLink {
+        let lifetime = lifetime
+            .unwrap_or(DEFAULT_SHARE_LINK_LIFETIME)
+            .min(self.max_lifetime);
+        let expires = now + lifetime.as_secs();
+        let signature = self.signature(owner, bucket, key, expires).finalize();
+
+        let mut path = format!(
+            "{}{}/{}?",
+            SHARE_PREFIX,
+            urlencoding::encode(bucket),
+            urlencoding::encode(key)
+        );
+        if let Some(owner) = owner {
+            path.push_str(&format!("owner={}&", urlencoding::encode(owner)));
+        }
+        path.push_str(&format!(
+            "expires={}&signature={}",
+            expires,
+            hex_string(&signature.into_bytes())
+        ));
+        ShareLink { path, expires }
+    }
+
+    /// Checks the signature and expiry of a share request
+    pub fn verify(&self, request: &ShareRequest) -> Result<(), ShareError> {
+        self.verify_at(request, now_secs())
+    }
+
+    fn verify_at(&self, request: &ShareRequest, now: u64) -> Result<(), ShareError> {
+        let (Some(expires), Some(signature)) = (request.expires, &request.signature) else {
+            return Err(ShareError::Malformed);
+        };
+        let mut signature_bytes = vec![0; signature.len() / 2];
+        if signature.len() % 2 != 0
+            || hex_decode(signature.as_bytes(), &mut signature_bytes).is_err()
+        {
+            return Err(ShareError::Malformed);
+        }
+        self.signature(
+            request.owner.as_deref(),
+            &request.bucket,
+            &request.key,
+            expires,
+        )
+        .verify_slice(&signature_bytes)
+        .map_err(|_| ShareError::InvalidSignature)?;
+        if expires <= now {
+            return Err(ShareError::Expired);
+        }
+        Ok(())
+    }
+}
+
+/// The scheme and host the request was sent to, to make absolute links. Empty
+/// if the request has no `Host` header, the links are relative then.
+fn origin<B>(req: &Request<B>) -> String {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    match header("host") {
+        Some(host) => {
+            let scheme = header("x-forwarded-proto").unwrap_or("http");
+            format!("{}://{}", scheme, host)
+        }
+        None => String::new(),
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<HttpBody> {
+    responses::error_response(status, message, true)
+}
+
+fn range_not_satisfiable(size: u64) -> Response<HttpBody> {
+    let mut response = responses::error_response(
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        "The range is not satisfiable",
+        false,
+    );
+    if let Ok(value) = HeaderValue::from_str(&format!("bytes */{}", size)) {
+        response.headers_mut().insert(CONTENT_RANGE, value);
+    }
+    response
+}
+
+fn boxed<S>(stream: S) -> HttpBody
+where
+    S: futures::Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+{
+    let frames = stream.map(|res| {
+        res.map(Frame::data)
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+    });
+    BodyExt::boxed(StreamBody::new(frames))
+}
+
+fn full(data: Bytes) -> HttpBody {
+    Full::new(data)
+        .map_err(|_| -> Box<dyn std::error::Error + Send + Sync> { unreachable!() })
+        .boxed()
+}
+
+/// Serves the object of a verified share request, streaming it from its blocks.
+/// `GET` and `HEAD` requests are served, with a single or several ranges.
+pub async fn download<B>(
+    casfs: &CasFS,
+    request: &ShareRequest,
+    req: &Request<B>,
+) -> Response<HttpBody> {
+    let key = request.key.as_str();
+    let filename = key.rsplit('/').next().unwrap_or(key).replace('"', "");
+    let disposition = format!("attachment; filename=\"{}\"", filename);
+    let mut response =
+        stream_object(casfs, &request.bucket, key, req, OCTET_STREAM, &disposition).await;
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static(SHARE_CACHE_CONTROL));
+    response
+}
+
+/// Streams an object from its blocks with `content_type` and the
+/// `Content-Disposition` header `disposition`. `GET` and `HEAD` requests are
+/// served, with a single or several ranges.
+pub(super) async fn stream_object<B>(
+    casfs: &CasFS,
+    bucket: &str,
+    key: &str,
+    req: &Request<B>,
+    content_type: &str,
+    disposition: &str,
+) -> Response<HttpBody> {
+    // the blocks are pinned so a concurrent delete can't remove them while streaming
+    let (obj_meta, paths, pin) = match casfs.get_object_paths_pinned(bucket, key) {
+        Ok(Some(object)) => object,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "Object not found"),
+        Err(e) => {
+            tracing::warn!(bucket, key, error = %e, "Failed to get object to stream");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Error getting object");
+        }
+    };
+    match casfs.unhealed_blocks(&obj_meta) {
+        Ok(corrupt) if !corrupt.is_empty() => {
+            let description = casfs.describe_corrupt_blocks(&corrupt);
+            tracing::warn!(bucket, key, "Streaming object with {description}");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Object is damaged");
+        }
+        Ok(_) => {}
+        Err(e) => {
+            tracing::warn!(bucket, key, error = %e, "Failed to check object to stream");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Error getting object");
+        }
+    }
+
+    let etag = obj_meta.format_e_tag();
+    let last_modified = obj_meta.last_modified();
+    let if_none_match = super::handlers::if_none_match(req);
+    if matches!(if_none_match, Some(condition) if etag_matches(condition, &etag)) {
+        return responses::not_modified(&etag, last_modified);
+    }
+
+    let size = obj_meta.size();
+    let ranges = req
+        .headers()
+        .get(hyper::header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        // a malformed header is ignored, and the whole object returned
+        .and_then(parse_multi_range_request);
+    let head = req.method() == Method::HEAD;
+
+    let mut builder = Response::builder();
+    let (status, length, content_type, body) = match ranges.as_deref() {
+        None => {
+            let body = if head {
+                full(Bytes::new())
+            } else if let Some(data) = obj_meta.inlined() {
+                full(Bytes::from(data.clone()))
+            } else {
+                let block_size: usize = paths.iter().map(|(_, size)| size).sum();
+                let metrics = cas_storage::SharedMetrics::default();
+                boxed(BlockStream::new(paths, block_size, RangeRequest::All, metrics).with_pin(pin))
+            };
+            (StatusCode::OK, size, content_type.to_string(), body)
+        }
+        Some([range]) => {
+            let Some((start, end)) = range.bounds(size) else {
+                return range_not_satisfiable(size);
+            };
+            let length = end - start + 1;
+            let body = if head {
+                full(Bytes::new())
+            } else if let Some(data) = obj_meta.inlined() {
+                full(Bytes::copy_from_slice(&data[start as usize..=end as usize]))
+            } else {
+                let metrics = cas_storage::SharedMetrics::default();
+                let blocks = BlockStream::new(
+                    paths,
+                    size as usize,
+                    RangeRequest::FromBytes(start),
+                    metrics,
+                )
+                .with_pin(pin);
+                // the block stream reads on from the start of the range, the range
+                // ends where the last chunk is cut off
+                boxed(blocks.scan(length, |remaining, chunk| {
+                    if *remaining == 0 {
+                        return future::ready(None);
+                    }
+                    let chunk = chunk.map(|mut chunk| {
+                        chunk.truncate(*remaining as usize);
+                        *remaining -= chunk.len() as u64;
+                        chunk
+                    });
+                    future::ready(Some(chunk))
+                }))
+            };
+            builder = builder.header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size));
+            (
+                StatusCode::PARTIAL_CONTENT,
+                length,
+                content_type.to_string(),
+                body,
+            )
+        }
+        Some(ranges) => {
+            let Some(byte_ranges) = ByteRanges::new(ranges, size, content_type) else {
+                return range_not_satisfiable(size);
+            };
+            let body = if head {
+                full(Bytes::new())
+            } else if let Some(data) = obj_meta.inlined() {
+                full(byte_ranges.body(data))
+            } else {
+                let metrics = cas_storage::SharedMetrics::default();
+                boxed(byte_ranges.stream(paths, metrics).with_pin(pin))
+            };
+            let content_type = byte_ranges.content_type();
+            (
+                StatusCode::PARTIAL_CONTENT,
+                byte_ranges.content_length(),
+                content_type,
+                body,
+            )
+        }
+    };
+
+    let response = builder
+        .status(status)
+        .header("content-type", content_type)
+        .header("content-disposition", disposition)
+        .header("content-length", length)
+        .header(ACCEPT_RANGES, "bytes")
+        .body(body)
+        .unwrap();
+    responses::with_cache_headers(response, &etag, last_modified)
+}
+
+/// A share link asked for with the form of the object page, or as JSON
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShareLinkRequest {
+    pub bucket: String,
+    pub key: String,
+    pub lifetime: Option<Duration>,
+}
+
+impl ShareLinkRequest {
+    /// Parses the urlencoded form with the `bucket`, `key` and the lifetime in
+    /// seconds as `expires_in`
+    pub fn from_form(body: &[u8]) -> Result<Self, String> {
+        let mut bucket = None;
+        let mut key = None;
+        let mut lifetime = None;
+        for pair in String::from_utf8_lossy(body).split('&') {
+            let Some((name, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let value = urlencoding::decode(&value.replace('+', " "))
+                .unwrap_or_default()
+                .into_owned();
+            match name {
+                "bucket" => bucket = Some(value),
+                "key" => key = Some(value),
+                "expires_in" if !value.trim().is_empty() => {
+                    let secs = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("Invalid lifetime: {}", value))?;
+                    lifetime = Some(Duration::from_secs(secs));
+                }
+                _ => {}
+            }
+        }
+        match (bucket, key) {
+            (Some(bucket), Some(key)) if !bucket.is_empty() && !key.is_empty() => Ok(Self {
+                bucket,
+                key,
+                lifetime,
+            }),
+            _ => Err("A share link needs a bucket and a key".to_string()),
+        }
+    }
+}
+
+/// Response to a new share link, see [`create`]
+#[derive(Debug, Serialize)]
+pub struct ShareLinkInfo {
+    pub bucket: String,
+    pub key: String,
+    pub url: String,
+    pub expires: u64,
+}
+
+/// Makes a share link for an object of `owner`, asked for with the urlencoded
+/// form of [`ShareLinkRequest::from_form`]. Shows the link on a page, or returns
+/// it as JSON.
+pub async fn create(
+    links: &ShareLinks,
+    owner: Option<&str>,
+    casfs: &CasFS,
+    req: Request<Incoming>,
+    wants_html: bool,
+    ui: &Ui,
+) -> Response<HttpBody> {
+    let origin = origin(&req);
+    let body = match Limited::new(req.into_body(), MAX_SHARE_LINK_REQUEST_SIZE)
+        .collect()
+        .await
+    {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read share link request");
+            return responses::error_response(
+                StatusCode::BAD_REQUEST,
+                "Invalid request",
+                wants_html,
+            );
+        }
+    };
+    let request = match ShareLinkRequest::from_form(&body) {
+        Ok(request) => request,
+        Err(message) => {
+            return responses::error_response(StatusCode::BAD_REQUEST, &message, wants_html)
+        }
+    };
+    match casfs.key_exists(&request.bucket, &request.key) {
+        Ok(true) => {}
+        Ok(false) => {
+            return responses::error_response(StatusCode::NOT_FOUND, "Object not found", wants_html)
+        }
+        Err(e) => {
+            return responses::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error getting object: {}", e),
+                wants_html,
+            )
+        }
+    }
+
+    let link = links.link(owner, &request.bucket, &request.key, request.lifetime);
+    tracing::info!(
+        owner,
+        bucket = %request.bucket,
+        key = %request.key,
+        expires = link.expires,
+        "Created share link"
+    );
+    let info = ShareLinkInfo {
+        url: format!("{}{}", origin, link.path),
+        expires: link.expires,
+        bucket: request.bucket,
+        key: request.key,
+    };
+    if wants_html {
+        responses::html_response(StatusCode::OK, templates::share_link_page(ui, &info))
+    } else {
+        responses::json_response(StatusCode::OK, &info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(link: &ShareLink) -> ShareRequest {
+        let (path, query) = link.path.split_once('?').unwrap();
+        ShareRequest::parse(path, Some(query)).unwrap()
+    }
+
+    #[test]
+    fn test_share_links() {
+        let links = ShareLinks::new(b"secret");
+        let now = 1_000_000;
+        let link = links.link_at(
+            Some("alice"),
+            "bucket",
+            "dir/file name.txt",
+            Some(Duration::from_secs(60)),
+            now,
+        );
+        assert_eq!(link.expires, now + 60);
+        assert!(link
+            .path
+            .starts_with("/share/bucket/dir%2Ffile%20name.txt?owner=alice&"));
+
+        let shared = request(&link);
+        assert_eq!(shared.owner.as_deref(), Some("alice"));
+        assert_eq!(shared.key, "dir/file name.txt");
+        assert_eq!(links.verify_at(&shared, now + 59), Ok(()));
+        assert_eq!(links.verify_at(&shared, now + 60), Err(ShareError::Expired));
+
+        // a link can't be changed to another object, owner or expiry
+        for tamper in [
+            ShareRequest {
+                key: "other".to_string(),
+                ..shared.clone()
+            },
+            ShareRequest {
+                bucket: "other".to_string(),
+                ..shared.clone()
+            },
+            ShareRequest {
+                owner: Some("bob".to_string()),
+                ..shared.clone()
+            },
+            ShareRequest {
+                owner: None,
+                ..shared.clone()
+            },
+            ShareRequest {
+                expires: Some(now + 3600),
+                ..shared.clone()
+            },
+        ] {
+            assert_eq!(
+                links.verify_at(&tamper, now),
+                Err(ShareError::InvalidSignature)
+            );
+        }
+        let other = ShareLinks::new(b"other secret");
+        assert_eq!(
+            other.verify_at(&shared, now),
+            Err(ShareError::InvalidSignature)
+        );
+
+        let unsigned = ShareRequest {
+            signature: None,
+            ..shared.clone()
+        };
+        assert_eq!(links.verify_at(&unsigned, now), Err(ShareError::Malformed));
+        let garbled = ShareRequest {
+            signature: Some("xyz".to_string()),
+            ..shared
+        };
+        assert_eq!(links.verify_at(&garbled, now), Err(ShareError::Malformed));
+    }
+
+    #[test]
+    fn test_share_link_lifetime() {
+        let links = ShareLinks::new(b"secret").with_max_lifetime(Duration::from_secs(3600));
+        let link = links.link_at(None, "b", "k", Some(Duration::from_secs(86400)), 0);
+        assert_eq!(link.expires, 3600);
+        let link = links.link_at(None, "b", "k", None, 0);
+        assert_eq!(link.expires, 3600);
+        assert!(!link.path.contains("owner="));
+        assert_eq!(request(&link).owner, None);
+    }
+
+    #[test]
+    fn test_share_request_parse() {
+        assert_eq!(ShareRequest::parse("/share/bucket", None), None);
+        assert_eq!(ShareRequest::parse("/share/bucket/", None), None);
+        let shared = ShareRequest::parse("/share/bucket/a/b", Some("expires=10")).unwrap();
+        assert_eq!(shared.key, "a/b");
+        assert_eq!(shared.expires, Some(10));
+        assert_eq!(shared.signature, None);
+    }
+
+    #[test]
+    fn test_share_link_request_from_form() {
+        let request =
+            ShareLinkRequest::from_form(b"bucket=b&key=dir%2Fa+b&expires_in=3600").unwrap();
+        assert_eq!(request.key, "dir/a b");
+        assert_eq!(request.lifetime, Some(Duration::from_secs(3600)));
+        let request = ShareLinkRequest::from_form(b"bucket=b&key=k&expires_in=").unwrap();
+        assert_eq!(request.lifetime, None);
+        assert!(ShareLinkRequest::from_form(b"bucket=b").is_err());
+        assert!(ShareLinkRequest::from_form(b"bucket=b&key=k&expires_in=soon").is_err());
+    }
+}
@@ -0,0 +1,222 @@
+//! Self-service signup in the HTTP UI: the public signup form and status page,
+//! and the approval queue of the admins below `/admin/signups`.
+
+use http_body_util::{BodyExt, Limited};
+use hyper::{body::Incoming, Request, Response, StatusCode};
+
+use crate::auth::{SignupError, SignupStore, UserRouter, UserStore, UserTemplate};
+use crate::metrics::SharedMetrics;
+
+use super::admin::{
+    apply_template, generate_access_key, generate_secret_key, template_note, Auditor,
+};
+use super::ui::Ui;
+use super::{responses, templates, HttpBody};
+
+/// Maximum size of a signup or decision form
+const MAX_SIGNUP_FORM_SIZE: usize = 4 * 1024;
+
+/// The decoded fields of an urlencoded form
+async fn read_form(req: Request<Incoming>) -> Result<Vec<(String, String)>, String> {
+    let body = match Limited::new(req.into_body(), MAX_SIGNUP_FORM_SIZE)
+        .collect()
+        .await
+    {
+        Ok(collected) => collected.to_bytes(),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read signup form");
+            return Err("Invalid request".to_string());
+        }
+    };
+    Ok(String::from_utf8_lossy(&body)
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| {
+            let value = urlencoding::decode(&value.replace('+', " "))
+                .unwrap_or_default()
+                .into_owned();
+            (name.to_string(), value)
+        })
+        .collect())
+}
+
+fn field<'a>(form: &'a [(String, String)], name: &str) -> &'a str {
+    form.iter()
+        .find(|(field, _)| field == name)
+        .map(|(_, value)| value.as_str())
+        .unwrap_or_default()
+}
+
+fn query_param(req: &Request<Incoming>, name: &str) -> Option<String> {
+    req.uri().query().and_then(|query| {
+        query.split('&').find_map(|pair| {
+            let (field, value) = pair.split_once('=')?;
+            (field == name).then(|| urlencoding::decode(value).unwrap_or_default().into_owned())
+        })
+    })
+}
+
+/// Handles GET /signup - displays the signup form
+pub async fn handle_signup_page(ui: &Ui) -> Response<HttpBody> {
+    responses::html_response(StatusCode::OK, templates::signup_page(ui, None))
+}
+
+/// Handles POST /signup - stores a pending signup and shows its token
+pub async fn handle_signup_submit(
+    req: Request<Incoming>,
+    user_store: &UserStore,
+    signups: &SignupStore,
+) -> Response<HttpBody> {
+    let ui = Ui::for_request(&req);
+    let form = match read_form(req).await {
+        Ok(form) => form,
+        Err(message) => {
+            return responses::html_response(
+                StatusCode::BAD_REQUEST,
+                templates::signup_page(&ui, Some(&message)),
+            )
+        }
+    };
+    let username = field(&form, "username").trim();
+    let password = field(&form, "password");
+    if password != field(&form, "confirm_password") {
+        return responses::html_response(
+            StatusCode::BAD_REQUEST,
+            templates::signup_page(&ui, Some("The passwords don't match")),
+        );
+    }
+
+    match signups.sign_up(user_store, username, password) {
+        Ok(token) => {
+            tracing::info!(user_id = %username, "Signup pending approval");
+            responses::html_response(
+                StatusCode::OK,
+                templates::signup_submitted_page(&ui, username, &token),
+            )
+        }
+        Err(SignupError::Store(e)) => {
+            tracing::warn!(error = %e, "Failed to store signup");
+            responses::html_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                templates::signup_page(&ui, Some("Failed to store the signup")),
+            )
+        }
+        Err(e) => {
+            let status = match e {
+                SignupError::TooManyPending => StatusCode::SERVICE_UNAVAILABLE,
+                SignupError::Taken => StatusCode::CONFLICT,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            responses::html_response(status, templates::signup_page(&ui, Some(&e.to_string())))
+        }
+    }
+}
+
+/// Handles GET /signup/status?token= - shows the state of a signup
+pub async fn handle_signup_status(
+    req: Request<Incoming>,
+    signups: &SignupStore,
+) -> Response<HttpBody> {
+    let ui = Ui::for_request(&req);
+    let Some(token) = query_param(&req, "token").filter(|token| !token.is_empty()) else {
+        return responses::html_response(StatusCode::OK, templates::signup_status_page(&ui, None));
+    };
+    match signups.by_token(&token) {
+        Ok(Some(request)) => responses::html_response(
+            StatusCode::OK,
+            templates::signup_status_page(&ui, Some(&request)),
+        ),
+        Ok(None) => responses::html_response(
+            StatusCode::NOT_FOUND,
+            templates::error_page("No signup with this token, it may have expired"),
+        ),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to look up signup");
+            responses::html_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                templates::error_page("Failed to look up the signup"),
+            )
+        }
+    }
+}
+
+/// Handles GET /admin/signups - lists the pending signups
+pub async fn handle_list_signups(
+    ui: &Ui,
+    req: &Request<Incoming>,
+    signups: &SignupStore,
+) -> Response<HttpBody> {
+    let message = query_param(req, "error").map(|error| (false, error));
+    let message = message.or_else(|| query_param(req, "success").map(|success| (true, success)));
+    match signups.pending() {
+        Ok(pending) => responses::html_response(
+            StatusCode::OK,
+            templates::admin_signups_page(ui, &pending, message),
+        ),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to list signups");
+            responses::html_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                templates::error_page("Failed to list signups"),
+            )
+        }
+    }
+}
+
+/// Handles POST /admin/signups/{user_id}/approve and
+/// POST /admin/signups/{user_id}/reject
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_decide_signup(
+    user_id: &str,
+    approve: bool,
+    auditor: Auditor<'_>,
+    user_store: &UserStore,
+    user_router: &UserRouter,
+    template: Option<&UserTemplate>,
+    signups: &SignupStore,
+    metrics: &SharedMetrics,
+) -> Response<HttpBody> {
+    let admin_id = auditor.actor;
+    let result = if approve {
+        signups
+            .approve(
+                user_store,
+                user_id,
+                admin_id,
+                generate_access_key(),
+                generate_secret_key(),
+            )
+            .map(|_| ())
+    } else {
+        signups.reject(user_id, admin_id)
+    };
+    let action = if approve { "approved" } else { "rejected" };
+    match result {
+        Ok(()) => {
+            let operation = if approve {
+                "signup_approve"
+            } else {
+                "signup_reject"
+            };
+            metrics.record_admin_operation(operation);
+            auditor.record(operation, user_id, "");
+            tracing::info!(user_id = %user_id, admin = %admin_id, "Signup {}", action);
+            let mut message = format!("Signup of '{}' {}", user_id, action);
+            if approve {
+                let result = apply_template(template, user_id, user_router, user_store);
+                message.push_str(&template_note(&result));
+            }
+            responses::redirect(&format!(
+                "/admin/signups?success={}",
+                urlencoding::encode(&message)
+            ))
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, user_id = %user_id, "Failed to decide on signup");
+            responses::redirect(&format!(
+                "/admin/signups?error={}",
+                urlencoding::encode(&e.to_string())
+            ))
+        }
+    }
+}
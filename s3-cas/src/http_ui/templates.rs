@@ -1,42 +1,69 @@
 use maud::{html, Markup, PreEscaped, DOCTYPE};
 
-use super::handlers::{BucketInfo, ObjectListResponse, ObjectMetadata};
+use cas_storage::{BucketActivity, ACTIVITY_WINDOW_MINUTES};
+
+use super::handlers::{
+    BucketInfo, BucketLimitsInfo, BucketUsageReport, ObjectListResponse, ObjectMetadata,
+};
+use super::index_page::{IndexFormat, IndexPage};
+use super::jobs::JobInfo;
+use super::i18n::Language;
+use super::list_preferences::{Column, ListPreferences, SortKey, PAGE_SIZES};
+use super::preview::{json_tokens, MediaKind, Preview};
+use super::share::{ShareLinkInfo, CREATE_SHARE_LINK_PATH};
+use super::ui::Ui;
+use crate::auth::Theme;
 
 /// Base HTML layout
-fn layout(title: &str, content: Markup) -> Markup {
-    layout_with_user(title, content, None)
+fn layout(ui: &Ui, title: &str, content: Markup) -> Markup {
+    layout_with_user(ui, title, content, None)
 }
 
 /// Base HTML layout with user context (for multi-user mode)
-fn layout_with_user(title: &str, content: Markup, is_admin: Option<bool>) -> Markup {
+fn layout_with_user(ui: &Ui, title: &str, content: Markup, is_admin: Option<bool>) -> Markup {
+    let version = env!("CARGO_PKG_VERSION");
+    // the dark rules apply on top of the base ones, always or when the browser prefers them
+    let (color_scheme, dark_media) = match ui.theme {
+        Theme::System => ("light dark", Some("(prefers-color-scheme: dark)")),
+        Theme::Light => ("light", None),
+        Theme::Dark => ("dark", Some("all")),
+    };
     html! {
         (DOCTYPE)
-        html lang="en" {
+        html lang=(ui.language.code()) {
             head {
                 meta charset="utf-8";
                 meta name="viewport" content="width=device-width, initial-scale=1";
+                meta name="color-scheme" content=(color_scheme);
                 title { (title) }
-                style { (PreEscaped(STYLES)) }
+                link rel="stylesheet" href={ "/assets/style.css?v=" (version) };
+                @if let Some(media) = dark_media {
+                    link rel="stylesheet" href={ "/assets/dark.css?v=" (version) } media=(media);
+                }
             }
             body {
                 header {
                     h1 { "S3-CAS Browser" }
                     nav {
-                        a href="/buckets" { "Buckets" }
+                        a href="/buckets" { (ui.t("Buckets")) }
                         " | "
-                        a href="/health" { "Health" }
+                        a href="/usage" { (ui.t("Usage")) }
+                        " | "
+                        a href="/health" { (ui.t("Health")) }
                         @if is_admin.is_some() {
                             " | "
-                            a href="/profile" { "👤 Profile" }
+                            a href="/profile" { "👤 " (ui.t("Profile")) }
                         }
                         @if let Some(true) = is_admin {
                             " | "
-                            a href="/admin/users" class="admin-link" { "⚙️ Admin" }
+                            a href="/admin/users" class="admin-link" { "⚙️ " (ui.t("Admin")) }
                         }
                         @if is_admin.is_some() {
+                            " | "
+                            (theme_toggle(ui))
                             " | "
                             form method="post" action="/logout" style="display: inline;" {
-                                button type="submit" class="logout-button" { "Logout" }
+                                button type="submit" class="logout-button" { (ui.t("Logout")) }
                             }
                         }
                     }
@@ -45,29 +72,43 @@ fn layout_with_user(title: &str, content: Markup, is_admin: Option<bool>) -> Mar
                     (content)
                 }
                 footer {
-                    p { "s3-cas v" (env!("CARGO_PKG_VERSION")) }
+                    p { "s3-cas v" (version) }
                 }
             }
         }
     }
 }
 
+/// Button switching between the light and the dark theme of the logged in user
+fn theme_toggle(ui: &Ui) -> Markup {
+    let (next, label) = match ui.theme {
+        Theme::Dark => (Theme::Light, "Light theme"),
+        Theme::Light | Theme::System => (Theme::Dark, "Dark theme"),
+    };
+    html! {
+        form method="post" action="/profile/preferences" style="display: inline;" {
+            input type="hidden" name="theme" value=(next.as_str());
+            button type="submit" class="theme-toggle" { (ui.t(*label)) }
+        }
+    }
+}
+
 /// Bucket list page (multi-user mode)
-pub fn buckets_page_with_user(buckets: &[BucketInfo], is_admin: bool) -> String {
+pub fn buckets_page_with_user(ui: &Ui, buckets: &[BucketInfo], is_admin: bool) -> String {
     let content = html! {
         div class="page-header" {
-            h2 { "Buckets" }
-            span class="count" { (buckets.len()) " bucket(s)" }
+            h2 { (ui.t("Buckets")) }
+            span class="count" { (buckets.len()) " " (ui.t("bucket(s)")) }
         }
 
         @if buckets.is_empty() {
-            p class="empty-state" { "No buckets found" }
+            p class="empty-state" { (ui.t("No buckets found")) }
         } @else {
             table {
                 thead {
                     tr {
-                        th { "Name" }
-                        th { "Created" }
+                        th { (ui.t("Name")) }
+                        th { (ui.t("Created")) }
                     }
                 }
                 tbody {
@@ -86,25 +127,25 @@ pub fn buckets_page_with_user(buckets: &[BucketInfo], is_admin: bool) -> String
         }
     };
 
-    layout_with_user("Buckets - S3-CAS", content, Some(is_admin)).into_string()
+    layout_with_user(ui, "Buckets - S3-CAS", content, Some(is_admin)).into_string()
 }
 
 /// Bucket list page (single-user mode)
-pub fn buckets_page(buckets: &[BucketInfo]) -> String {
+pub fn buckets_page(ui: &Ui, buckets: &[BucketInfo]) -> String {
     let content = html! {
         div class="page-header" {
-            h2 { "Buckets" }
-            span class="count" { (buckets.len()) " bucket(s)" }
+            h2 { (ui.t("Buckets")) }
+            span class="count" { (buckets.len()) " " (ui.t("bucket(s)")) }
         }
 
         @if buckets.is_empty() {
-            p class="empty-state" { "No buckets found" }
+            p class="empty-state" { (ui.t("No buckets found")) }
         } @else {
             table {
                 thead {
                     tr {
-                        th { "Name" }
-                        th { "Created" }
+                        th { (ui.t("Name")) }
+                        th { (ui.t("Created")) }
                     }
                 }
                 tbody {
@@ -123,23 +164,139 @@ pub fn buckets_page(buckets: &[BucketInfo]) -> String {
         }
     };
 
-    layout("Buckets - S3-CAS", content).into_string()
+    layout(ui, "Buckets - S3-CAS", content).into_string()
+}
+
+/// Bucket usage report page, `is_admin` is set in multi-user mode
+/// Buckets shown in the hot buckets panel of the usage page
+const HOT_BUCKETS_SHOWN: usize = 10;
+
+pub fn usage_page(
+    ui: &Ui,
+    reports: &[BucketUsageReport],
+    hot: &[BucketActivity],
+    is_admin: Option<bool>,
+) -> String {
+    let content = html! {
+        div class="page-header" {
+            h2 { (ui.t("Usage")) }
+            span class="actions" {
+                a href="/usage.csv" class="btn" { (ui.t("Download CSV")) }
+                " "
+                a href="/api/v1/usage" class="btn" { "JSON" }
+            }
+        }
+
+        @if reports.is_empty() {
+            p class="empty-state" { (ui.t("No buckets found")) }
+        } @else {
+            table {
+                thead {
+                    tr {
+                        th { (ui.t("Bucket")) }
+                        th class="number" { (ui.t("Objects")) }
+                        th class="number" { (ui.t("Logical size")) }
+                        th class="number" { (ui.t("Physical size")) }
+                        th class="number" { (ui.t("Growth")) }
+                        th { (ui.t("Physical size trend")) }
+                    }
+                }
+                tbody {
+                    @for report in reports {
+                        @let physical: Vec<u64> = report
+                            .history
+                            .iter()
+                            .map(|sample| sample.usage.physical_bytes)
+                            .collect();
+                        tr {
+                            td {
+                                a href={ "/buckets/" (urlencoding::encode(&report.bucket)) } {
+                                    (&report.bucket)
+                                }
+                                @if !report.tags.is_empty() {
+                                    br;
+                                    small {
+                                        @for (key, value) in &report.tags {
+                                            code { (key) "=" (value) } " "
+                                        }
+                                    }
+                                }
+                            }
+                            td class="number" { (report.current.objects) }
+                            td class="number" { (format_size(report.current.logical_bytes)) }
+                            td class="number" { (format_size(report.current.physical_bytes)) }
+                            td class="number" {
+                                (format_growth(&physical))
+                                " / " (report.history.len()) "d"
+                            }
+                            td { (sparkline(&physical)) }
+                        }
+                    }
+                }
+            }
+            p class="help-text" {
+                (ui.t("Physical size counts every distinct block of a bucket once. Blocks shared between buckets are counted in each of them. History is sampled once a day."))
+            }
+        }
+
+        div class="page-header" {
+            h3 { (ui.t("Hot buckets")) }
+            span class="actions" {
+                a href="/api/v1/activity" class="btn" { "JSON" }
+            }
+        }
+        @if hot.is_empty() {
+            p class="empty-state" { (ui.t("No requests in the last hour")) }
+        } @else {
+            table {
+                thead {
+                    tr {
+                        th { (ui.t("Bucket")) }
+                        th class="number" { (ui.t("Requests/min")) }
+                        th class="number" { (ui.t("Reads")) }
+                        th class="number" { (ui.t("Writes")) }
+                        th class="number" { (ui.t("Read")) }
+                        th class="number" { (ui.t("Written")) }
+                        th class="number" { (ui.t("Paid by requesters")) }
+                    }
+                }
+                tbody {
+                    @for activity in hot.iter().take(HOT_BUCKETS_SHOWN) {
+                        tr {
+                            td {
+                                a href={ "/buckets/" (urlencoding::encode(&activity.bucket)) } {
+                                    (&activity.bucket)
+                                }
+                            }
+                            (activity_cells(activity, ACTIVITY_WINDOW_MINUTES))
+                        }
+                    }
+                }
+            }
+            p class="help-text" {
+                (ui.t("Requests and transferred object data of the last hour, busiest first."))
+            }
+        }
+    };
+
+    layout_with_user(ui, "Usage - S3-CAS", content, is_admin).into_string()
 }
 
 /// Object list page
-pub fn objects_page(response: &ObjectListResponse) -> String {
+pub fn objects_page(ui: &Ui, response: &ObjectListResponse, prefs: &ListPreferences) -> String {
     // Build breadcrumb navigation from prefix
     let breadcrumb_parts = if response.prefix.is_empty() {
         vec![]
     } else {
         response.prefix.trim_end_matches('/').split('/').collect()
     };
+    let tag = response.tag.as_deref();
 
     let content = html! {
         div class="breadcrumb" {
-            a href="/buckets" { "Buckets" }
+            a href="/buckets" { (ui.t("Buckets")) }
             " / "
-            a href={ "/buckets/" (urlencoding::encode(&response.bucket)) } { (response.bucket) }
+            a href=(listing_url(&response.bucket, "", None, prefs, None)) { (response.bucket) }
             @if !breadcrumb_parts.is_empty() {
                 @for (i, part) in breadcrumb_parts.iter().enumerate() {
                     " / "
@@ -147,7 +304,7 @@ pub fn objects_page(response: &ObjectListResponse) -> String {
                         strong { (part) }
                     } @else {
                         @let prefix = breadcrumb_parts[..=i].join("/") + "/";
-                        a href={ "/buckets/" (urlencoding::encode(&response.bucket)) "?prefix=" (urlencoding::encode(&prefix)) } {
+                        a href=(listing_url(&response.bucket, &prefix, None, prefs, None)) {
                             (part)
                         }
                     }
@@ -158,24 +315,60 @@ pub fn objects_page(response: &ObjectListResponse) -> String {
         div class="page-header" {
             h2 {
                 @if response.prefix.is_empty() {
-                    "Objects in \"" (response.bucket) "\""
+                    (ui.t("Objects in")) " \"" (response.bucket) "\""
                 } @else {
                     "\"" (response.prefix.trim_end_matches('/')) "\""
                 }
             }
-            span class="count" { (response.total_count) " item(s)" }
+            span class="count" {
+                @if let Some(tag) = &response.tag {
+                    (ui.t("tagged")) " " code { (tag) } ", "
+                }
+                (response.total_count) " " (ui.t("item(s) on this page"))
+            }
+            a class="btn" href={ "/limits/" (urlencoding::encode(&response.bucket)) } { (ui.t("Limits")) }
+        }
+
+        @if !response.degraded_objects.is_empty() {
+            div class="alert alert-error" {
+                (response.degraded_objects.len()) " "
+                (ui.t("object(s) here use corrupt blocks and may be unreadable:"))
+                ul {
+                    @for key in &response.degraded_objects {
+                        @let encoded_key = key.split('/').map(|s| urlencoding::encode(s)).collect::<Vec<_>>().join("/");
+                        li {
+                            a href={ "/buckets/" (urlencoding::encode(&response.bucket)) "/" (encoded_key) } {
+                                code { (key) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        @if let Some(index_page) = &response.index_page {
+            (index_page_section(index_page))
         }
 
+        (list_controls(ui, response, prefs))
+
         @if response.directories.is_empty() && response.objects.is_empty() {
-            p class="empty-state" { "No objects in this location" }
+            p class="empty-state" { (ui.t("No objects in this location")) }
         } @else {
             table {
                 thead {
                     tr {
-                        th { "Name" }
-                        th class="number" { "Size" }
-                        th { "Type" }
-                        th { "Last Modified" }
+                        th { (sort_link(response, prefs, SortKey::Name, ui.t("Name"))) }
+                        @for column in &prefs.columns {
+                            @let class = if column_is_number(*column) { "number" } else { "" };
+                            th class=(class) {
+                                @if let Some(sort) = column.sort_key() {
+                                    (sort_link(response, prefs, sort, ui.t(column.label())))
+                                } @else {
+                                    (ui.t(column.label()))
+                                }
+                            }
+                        }
                     }
                 }
                 tbody {
@@ -183,233 +376,248 @@ pub fn objects_page(response: &ObjectListResponse) -> String {
                     @for dir in &response.directories {
                         tr class="directory-row" {
                             td {
-                                a href={ "/buckets/" (urlencoding::encode(&response.bucket)) "?prefix=" (urlencoding::encode(&dir.prefix)) } {
+                                a href=(listing_url(&response.bucket, &dir.prefix, None, prefs, None)) {
                                     "📁 " (dir.name)
                                 }
+                                @if let Some(objects) = dir.objects {
+                                    " " span class="help-text" { (objects) " " (ui.t("objects")) }
+                                }
+                            }
+                            @for column in &prefs.columns {
+                                @match column {
+                                    Column::Type => td { span class="badge directory" { (ui.t("folder")) } },
+                                    column if column_is_number(*column) => td class="number" { "—" },
+                                    _ => td { "—" },
+                                }
                             }
-                            td class="number" { "—" }
-                            td { span class="badge directory" { "folder" } }
-                            td { "—" }
                         }
                     }
                     // Show files
                     @for obj in &response.objects {
                         tr {
+                            @let encoded_key = obj.key.split('/').map(|s| urlencoding::encode(s)).collect::<Vec<_>>().join("/");
                             td {
-                                @let encoded_key = obj.key.split('/').map(|s| urlencoding::encode(s)).collect::<Vec<_>>().join("/");
                                 a href={ "/download/" (urlencoding::encode(&response.bucket)) "/" (encoded_key) } {
                                     "📄 " (obj.key.rsplit('/').next().unwrap_or(&obj.key))
                                 }
                             }
-                            td class="number" { (format_size(obj.size)) }
-                            td {
-                                a href={ "/buckets/" (urlencoding::encode(&response.bucket)) "/" (encoded_key) } {
-                                    @if obj.is_inlined {
-                                        span class="badge inline" { "inline" }
-                                    } @else {
-                                        span class="badge blocks" { "blocks" }
-                                    }
+                            @for column in &prefs.columns {
+                                @match column {
+                                    Column::Size => td class="number" { (format_size(obj.size)) },
+                                    Column::Type => td {
+                                        a href={ "/buckets/" (urlencoding::encode(&response.bucket)) "/" (encoded_key) } {
+                                            @if obj.is_inlined {
+                                                span class="badge inline" { (ui.t("inline")) }
+                                            } @else {
+                                                span class="badge blocks" { (ui.t("blocks")) }
+                                            }
+                                        }
+                                    },
+                                    Column::Modified => td { (obj.last_modified) },
+                                    Column::Blocks => td class="number" { (obj.block_count) },
+                                    Column::Physical => td class="number" {
+                                        @match obj.physical_size {
+                                            Some(size) => { (format_size(size)) },
+                                            None => { "—" },
+                                        }
+                                    },
+                                    Column::Expires => td {
+                                        @match &obj.expiration {
+                                            Some(expiration) => span title=(expiration.rule_id) { (expiration.expiry_date) },
+                                            None => { "—" },
+                                        }
+                                    },
                                 }
                             }
-                            td { (obj.last_modified) }
                         }
                     }
                 }
             }
+        }
 
-            // Infinite scroll loading indicator
-            @if response.has_more {
-                div id="loading-indicator" class="loading-indicator" style="display: none;" {
-                    "Loading more items..."
+        @if response.has_prev || response.has_more {
+            div class="pagination" {
+                @if response.has_prev {
+                    a href=(listing_url(&response.bucket, &response.prefix, tag, prefs, None)) { (ui.t("« First")) }
+                }
+                @if let Some(token) = &response.prev_token {
+                    a href=(listing_url(&response.bucket, &response.prefix, tag, prefs, Some(("before", token)))) { (ui.t("‹ Previous")) }
+                }
+                @if let Some(token) = &response.next_token {
+                    a href=(listing_url(&response.bucket, &response.prefix, tag, prefs, Some(("token", token)))) { (ui.t("Next ›")) }
                 }
-                div id="scroll-sentinel" style="height: 1px;";
             }
+        }
+    };
 
-            script {
-                (PreEscaped(format!(r#"
-                    (function() {{
-                        let isLoading = false;
-                        let hasMore = {};
-                        let nextToken = {};
-                        const bucket = {};
-                        const prefix = {};
-
-                        const loadingIndicator = document.getElementById('loading-indicator');
-                        const sentinel = document.getElementById('scroll-sentinel');
-                        const tbody = document.querySelector('table tbody');
-
-                        function formatSize(bytes) {{
-                            const units = ['B', 'KB', 'MB', 'GB', 'TB'];
-                            let size = bytes;
-                            let unitIdx = 0;
-                            while (size >= 1024 && unitIdx < units.length - 1) {{
-                                size /= 1024;
-                                unitIdx++;
-                            }}
-                            if (unitIdx === 0) {{
-                                return bytes + ' ' + units[0];
-                            }}
-                            return size.toFixed(2) + ' ' + units[unitIdx];
-                        }}
-
-                        async function loadMore() {{
-                            if (isLoading || !hasMore) return;
-
-                            isLoading = true;
-                            loadingIndicator.style.display = 'block';
-
-                            try {{
-                                let url = '/api/v1/buckets/' + encodeURIComponent(bucket);
-                                const params = new URLSearchParams();
-                                if (prefix) params.append('prefix', prefix);
-                                if (nextToken) params.append('token', nextToken);
-                                if (params.toString()) url += '?' + params.toString();
-
-                                const response = await fetch(url);
-                                if (!response.ok) throw new Error('Failed to load more items');
-
-                                const data = await response.json();
-
-                                // Append directories
-                                data.directories.forEach(dir => {{
-                                    const row = document.createElement('tr');
-                                    row.className = 'directory-row';
-                                    row.innerHTML = `
-                                        <td>
-                                            <a href="/buckets/${{encodeURIComponent(bucket)}}?prefix=${{encodeURIComponent(dir.prefix)}}">
-                                                📁 ${{escapeHtml(dir.name)}}
-                                            </a>
-                                        </td>
-                                        <td class="number">—</td>
-                                        <td><span class="badge directory">folder</span></td>
-                                        <td>—</td>
-                                    `;
-                                    tbody.appendChild(row);
-                                }});
-
-                                // Append objects
-                                data.objects.forEach(obj => {{
-                                    const row = document.createElement('tr');
-                                    const fileName = obj.key.split('/').pop() || obj.key;
-                                    const typeClass = obj.is_inlined ? 'inline' : 'blocks';
-                                    const typeLabel = obj.is_inlined ? 'inline' : 'blocks';
-                                    const encodedKey = obj.key.split('/').map(encodeURIComponent).join('/');
-                                    row.innerHTML = `
-                                        <td>
-                                            <a href="/download/${{encodeURIComponent(bucket)}}/${{encodedKey}}">
-                                                📄 ${{escapeHtml(fileName)}}
-                                            </a>
-                                        </td>
-                                        <td class="number">${{formatSize(obj.size)}}</td>
-                                        <td>
-                                            <a href="/buckets/${{encodeURIComponent(bucket)}}/${{encodedKey}}">
-                                                <span class="badge ${{typeClass}}">${{typeLabel}}</span>
-                                            </a>
-                                        </td>
-                                        <td>${{escapeHtml(obj.last_modified)}}</td>
-                                    `;
-                                    tbody.appendChild(row);
-                                }});
-
-                                hasMore = data.has_more;
-                                nextToken = data.next_token;
-
-                                if (!hasMore) {{
-                                    sentinel.remove();
-                                }}
-                            }} catch (error) {{
-                                console.error('Error loading more items:', error);
-                            }} finally {{
-                                isLoading = false;
-                                loadingIndicator.style.display = 'none';
-                            }}
-                        }}
-
-                        function escapeHtml(text) {{
-                            const div = document.createElement('div');
-                            div.textContent = text;
-                            return div.innerHTML;
-                        }}
-
-                        // Intersection Observer for infinite scroll
-                        if (sentinel && hasMore) {{
-                            const observer = new IntersectionObserver(
-                                (entries) => {{
-                                    if (entries[0].isIntersecting) {{
-                                        loadMore();
-                                    }}
-                                }},
-                                {{ rootMargin: '100px' }}
-                            );
-                            observer.observe(sentinel);
-                        }}
-                    }})();
-                "#,
-                    response.has_more,
-                    response.next_token.as_ref().map(|t| format!("\"{}\"", t.replace("\"", "\\\""))).unwrap_or_else(|| "null".to_string()),
-                    serde_json::to_string(&response.bucket).unwrap(),
-                    serde_json::to_string(&response.prefix).unwrap()
-                )))
+    layout(ui, &format!("{} - S3-CAS", response.bucket), content).into_string()
+}
+
+/// URL of a listing of `bucket` at `prefix`, with the preferences which differ
+/// from the defaults and an extra query parameter
+fn listing_url(
+    bucket: &str,
+    prefix: &str,
+    tag: Option<&str>,
+    prefs: &ListPreferences,
+    extra: Option<(&str, &str)>,
+) -> String {
+    let mut params = Vec::new();
+    if !prefix.is_empty() {
+        params.push(format!("prefix={}", urlencoding::encode(prefix)));
+    }
+    if let Some(tag) = tag {
+        params.push(format!("tag={}", urlencoding::encode(tag)));
+    }
+    let prefs = prefs.to_query();
+    if !prefs.is_empty() {
+        params.push(prefs);
+    }
+    if let Some((name, value)) = extra {
+        params.push(format!("{name}={}", urlencoding::encode(value)));
+    }
+
+    let mut url = format!("/buckets/{}", urlencoding::encode(bucket));
+    if !params.is_empty() {
+        url.push('?');
+        url.push_str(&params.join("&"));
+    }
+    url
+}
+
+fn column_is_number(column: Column) -> bool {
+    matches!(column, Column::Size | Column::Blocks | Column::Physical)
+}
+
+/// Header link sorting the page by `sort`, reversing the order if it's sorted by it
+fn sort_link(response: &ObjectListResponse, prefs: &ListPreferences, sort: SortKey, label: &str) -> Markup {
+    let current = prefs.sort == sort;
+    let mut sorted = prefs.clone();
+    sorted.sort = sort;
+    sorted.descending = current && !prefs.descending;
+    html! {
+        a class="sort-link" href=(listing_url(&response.bucket, &response.prefix, response.tag.as_deref(), &sorted, None)) {
+            (label)
+            @if current {
+                @if prefs.descending { " ▼" } @else { " ▲" }
             }
         }
-    };
+    }
+}
 
-    layout(&format!("{} - S3-CAS", response.bucket), content).into_string()
+/// Form choosing the page size and the shown columns
+fn list_controls(ui: &Ui, response: &ObjectListResponse, prefs: &ListPreferences) -> Markup {
+    html! {
+        form class="list-controls" method="get" action={ "/buckets/" (urlencoding::encode(&response.bucket)) } {
+            @if !response.prefix.is_empty() {
+                input type="hidden" name="prefix" value=(response.prefix);
+            }
+            @if let Some(tag) = &response.tag {
+                input type="hidden" name="tag" value=(tag);
+            }
+            input type="hidden" name="sort" value=(prefs.sort.as_str());
+            input type="hidden" name="order" value=(if prefs.descending { "desc" } else { "asc" });
+            label {
+                (ui.t("Page size")) " "
+                select name="limit" {
+                    @for size in PAGE_SIZES {
+                        option value=(size) selected[size == prefs.limit] { (size) }
+                    }
+                }
+            }
+            // sent without any checked column, so all columns can be hidden
+            input type="hidden" name="columns" value="";
+            span class="columns" {
+                (ui.t("Columns")) " "
+                @for column in Column::ALL {
+                    label {
+                        input type="checkbox" name="columns" value=(column.as_str()) checked[prefs.shows(column)];
+                        " " (ui.t(column.label()))
+                    }
+                }
+            }
+            button type="submit" class="btn-small" { (ui.t("Apply")) }
+        }
+    }
 }
 
 /// Object detail page
-pub fn object_detail_page(metadata: &ObjectMetadata) -> String {
+/// Lifetimes offered for share links, in seconds
+const SHARE_LINK_LIFETIMES: &[(u64, &str)] =
+    &[(3600, "1 hour"), (86400, "1 day"), (604800, "7 days")];
+
+/// The metadata of an object, with a form to make a share link for it if `shareable`
+pub fn object_detail_page(
+    ui: &Ui,
+    metadata: &ObjectMetadata,
+    preview: Option<&Preview>,
+    shareable: bool,
+) -> String {
     let content = html! {
         div class="breadcrumb" {
-            a href="/buckets" { "← Buckets" }
+            a href="/buckets" { "← " (ui.t("Buckets")) }
             " / "
             a href={ "/buckets/" (urlencoding::encode(&metadata.bucket)) } { (metadata.bucket) }
             " / "
             strong { (metadata.key) }
         }
 
-        h2 { "Object Metadata" }
+        h2 { (ui.t("Object Metadata")) }
+
+        @if !metadata.corrupt_blocks.is_empty() {
+            div class="alert alert-error" {
+                (ui.t("This object uses corrupt blocks and may be unreadable until they are healed by uploading their content again:"))
+                ul {
+                    @for block in &metadata.corrupt_blocks {
+                        li { code class="hash-full" { (block) } }
+                    }
+                }
+            }
+        }
 
         dl class="metadata" {
-            dt { "Key" }
+            dt { (ui.t("Key")) }
             dd { code { (metadata.key) } }
 
-            dt { "Bucket" }
+            dt { (ui.t("Bucket")) }
             dd { code { (metadata.bucket) } }
 
-            dt { "Size" }
-            dd { (format_size(metadata.size)) " (" (metadata.size) " bytes)" }
+            dt { (ui.t("Size")) }
+            dd { (format_size(metadata.size)) " (" (metadata.size) " " (ui.t("bytes")) ")" }
 
-            dt { "Content Hash (MD5)" }
+            dt { (ui.t("Content Hash")) }
             dd { code class="hash-full" { (metadata.hash) } }
 
-            dt { "Last Modified" }
+            dt { "ETag" }
+            dd { code class="hash-full" { (metadata.etag) } }
+
+            dt { (ui.t("Last Modified")) }
             dd { (metadata.last_modified) }
 
-            dt { "Storage Type" }
+            dt { (ui.t("Storage Type")) }
             dd {
                 @if metadata.is_inlined {
-                    span class="badge inline" { "Inline" }
-                    " (stored in metadata)"
+                    span class="badge inline" { (ui.t("Inline")) }
+                    " " (ui.t("(stored in metadata)"))
                 } @else {
-                    span class="badge blocks" { "Blocks" }
-                    " (content-addressable storage)"
+                    span class="badge blocks" { (ui.t("Blocks")) }
+                    " " (ui.t("(content-addressable storage)"))
                 }
             }
 
-            dt { "Block Count" }
+            dt { (ui.t("Block Count")) }
             dd { (metadata.blocks.len()) }
 
             @if !metadata.blocks.is_empty() {
-                dt { "Blocks" }
+                dt { (ui.t("Blocks")) }
                 dd {
                     table class="blocks-table" {
                         thead {
                             tr {
                                 th { "#" }
-                                th { "Hash" }
-                                th class="number" { "Size" }
-                                th class="number" { "Refcount" }
+                                th { (ui.t("Hash")) }
+                                th class="number" { (ui.t("Size")) }
+                                th class="number" { (ui.t("Refcount")) }
                             }
                         }
                         tbody {
@@ -422,7 +630,7 @@ pub fn object_detail_page(metadata: &ObjectMetadata) -> String {
                                         (block.refcount)
                                         @if block.refcount > 1 {
                                             " "
-                                            span class="dedup-badge" { "shared" }
+                                            span class="dedup-badge" { (ui.t("shared")) }
                                         }
                                     }
                                 }
@@ -432,32 +640,140 @@ pub fn object_detail_page(metadata: &ObjectMetadata) -> String {
                 }
             }
         }
+
+        @if let Some(preview) = preview {
+            h3 { (ui.t("Preview")) }
+            (preview_section(ui, preview))
+        }
+
+        @if shareable {
+            h3 { (ui.t("Share link")) }
+            form method="POST" action=(CREATE_SHARE_LINK_PATH) {
+                input type="hidden" name="bucket" value=(metadata.bucket);
+                input type="hidden" name="key" value=(metadata.key);
+                div class="form-group" {
+                    label for="expires_in" { (ui.t("Valid for")) }
+                    select id="expires_in" name="expires_in" {
+                        @for (secs, label) in SHARE_LINK_LIFETIMES {
+                            option value=(secs) selected[*secs == 86400] { (ui.t(*label)) }
+                        }
+                    }
+                }
+                button type="submit" class="btn btn-primary" { (ui.t("Create share link")) }
+            }
+        }
+    };
+
+    layout(ui, &format!("{} - S3-CAS", metadata.key), content).into_string()
+}
+
+fn preview_section(ui: &Ui, preview: &Preview) -> Markup {
+    html! {
+        div class="preview" {
+            @match preview {
+                Preview::Media { kind: MediaKind::Image, url, .. } => {
+                    img src=(url) alt=(ui.t("Preview"));
+                }
+                Preview::Media { kind: MediaKind::Video, url, content_type } => {
+                    video controls preload="metadata" {
+                        source src=(url) type=(content_type);
+                    }
+                }
+                Preview::Media { kind: MediaKind::Audio, url, content_type } => {
+                    audio controls preload="metadata" {
+                        source src=(url) type=(content_type);
+                    }
+                }
+                Preview::Text { text, truncated, json } => {
+                    pre class="preview-text" {
+                        @if *json {
+                            code {
+                                @for (token, text) in json_tokens(text) {
+                                    @if let Some(class) = token.class() {
+                                        span class=(class) { (text) }
+                                    } @else {
+                                        (text)
+                                    }
+                                }
+                            }
+                        } @else {
+                            code { (text) }
+                        }
+                    }
+                    @if *truncated {
+                        p class="preview-note" { (ui.t("Only the first 64 KiB are shown.")) }
+                    }
+                }
+                Preview::TooLarge => {
+                    p class="preview-note" { (ui.t("The image is too large to preview.")) }
+                }
+            }
+        }
+    }
+}
+
+/// A new share link, to copy and hand to someone without an account
+pub fn share_link_page(ui: &Ui, info: &ShareLinkInfo) -> String {
+    let object = format!(
+        "/buckets/{}/{}",
+        urlencoding::encode(&info.bucket),
+        urlencoding::encode(&info.key)
+    );
+    let content = html! {
+        div class="breadcrumb" {
+            a href="/buckets" { "← " (ui.t("Buckets")) }
+            " / "
+            a href={ "/buckets/" (urlencoding::encode(&info.bucket)) } { (info.bucket) }
+            " / "
+            a href=(object) { (info.key) }
+        }
+
+        div class="form-container" {
+            h2 { (ui.t("Share link")) }
+            div class="form-group" {
+                input type="text" readonly value=(info.url) onclick="this.select()";
+            }
+            div class="alert alert-info" {
+                (ui.t("Anyone with the link can download the object until")) " "
+                (format_epoch_secs(info.expires)) "."
+            }
+        }
     };
 
-    layout(&format!("{} - S3-CAS", metadata.key), content).into_string()
+    layout(ui, &format!("{} - {}", ui.t("Share link"), info.key), content).into_string()
+}
+
+fn theme_label(theme: Theme) -> &'static str {
+    match theme {
+        Theme::System => "System",
+        Theme::Light => "Light",
+        Theme::Dark => "Dark",
+    }
 }
 
 /// Error page (simple version for admin/login)
 pub fn error_page(message: &str) -> String {
+    // rendered without the request, so in the default theme and language
+    let ui = &Ui::default();
     let content = html! {
         div class="error-page" {
-            h2 { "Error" }
+            h2 { (ui.t("Error")) }
             p { (message) }
             p {
-                a href="/buckets" { "← Back to buckets" }
+                a href="/buckets" { (ui.t("← Back to buckets")) }
             }
         }
     };
 
-    layout("Error - S3-CAS", content).into_string()
+    layout(ui, "Error - S3-CAS", content).into_string()
 }
 
 /// Login page
-pub fn login_page(redirect_to: &str, error: Option<&str>) -> String {
+pub fn login_page(ui: &Ui, redirect_to: &str, error: Option<&str>, signup: bool) -> String {
     let content = html! {
         div class="login-container" {
             div class="login-box" {
-                h2 { "Login" }
+                h2 { (ui.t("Login")) }
 
                 @if let Some(err) = error {
                     div class="alert alert-error" {
@@ -469,26 +785,148 @@ pub fn login_page(redirect_to: &str, error: Option<&str>) -> String {
                     input type="hidden" name="redirect" value=(redirect_to);
 
                     div class="form-group" {
-                        label for="username" { "Username" }
+                        label for="username" { (ui.t("Username")) }
                         input type="text" id="username" name="username" required autofocus;
                     }
 
                     div class="form-group" {
-                        label for="password" { "Password" }
+                        label for="password" { (ui.t("Password")) }
+                        input type="password" id="password" name="password" required;
+                    }
+
+                    button type="submit" class="btn btn-primary" { (ui.t("Login")) }
+                }
+
+                @if signup {
+                    p class="setup-note" {
+                        (ui.t("No account yet?")) " "
+                        a href="/signup" { (ui.t("Sign up")) }
+                    }
+                }
+            }
+        }
+    };
+
+    layout(ui, "Login - S3-CAS", content).into_string()
+}
+
+/// Self-service signup form
+pub fn signup_page(ui: &Ui, error: Option<&str>) -> String {
+    let content = html! {
+        div class="login-container" {
+            div class="login-box" {
+                h2 { (ui.t("Sign up")) }
+                p class="setup-message" {
+                    (ui.t("An administrator has to approve the account before you can log in."))
+                }
+
+                @if let Some(err) = error {
+                    div class="alert alert-error" {
+                        (err)
+                    }
+                }
+
+                form method="POST" action="/signup" {
+                    div class="form-group" {
+                        label for="username" { (ui.t("Username")) }
+                        input type="text" id="username" name="username" required autofocus;
+                    }
+
+                    div class="form-group" {
+                        label for="password" { (ui.t("Password")) }
                         input type="password" id="password" name="password" required;
+                        small { (ui.t("Minimum 8 characters")) }
+                    }
+
+                    div class="form-group" {
+                        label for="confirm_password" { (ui.t("Confirm Password")) }
+                        input type="password" id="confirm_password" name="confirm_password" required;
+                    }
+
+                    button type="submit" class="btn btn-primary" { (ui.t("Sign up")) }
+                }
+
+                p class="setup-note" {
+                    a href="/signup/status" { (ui.t("Check the status of a signup")) }
+                }
+            }
+        }
+    };
+
+    layout(ui, "Sign up - S3-CAS", content).into_string()
+}
+
+/// Page shown after a signup, the only time its status token is shown
+pub fn signup_submitted_page(ui: &Ui, user_id: &str, token: &str) -> String {
+    let status_url = format!("/signup/status?token={}", token);
+    let content = html! {
+        div class="login-container" {
+            div class="login-box" {
+                h2 { (ui.t("Signup received")) }
+
+                div class="alert alert-success" {
+                    (ui.t("The account is waiting for the approval of an administrator:")) " "
+                    strong { (user_id) }
+                }
+
+                p {
+                    (ui.t("Keep this token to check the status of your signup, it can't be shown again:"))
+                }
+                p { code class="credential" { (token) } }
+                p {
+                    a href=(status_url) { (ui.t("Check the status of a signup")) }
+                }
+            }
+        }
+    };
+
+    layout(ui, "Sign up - S3-CAS", content).into_string()
+}
+
+/// Status of a signup, or the form to look one up by its token
+pub fn signup_status_page(ui: &Ui, request: Option<&crate::auth::SignupRequest>) -> String {
+    use crate::auth::SignupStatus;
+
+    let content = html! {
+        div class="login-container" {
+            div class="login-box" {
+                h2 { (ui.t("Signup status")) }
+
+                @if let Some(request) = request {
+                    @match request.status {
+                        SignupStatus::Pending => div class="alert alert-info" {
+                            (ui.t("The signup is waiting for the approval of an administrator:")) " "
+                            strong { (&request.user_id) }
+                        },
+                        SignupStatus::Approved => div class="alert alert-success" {
+                            (ui.t("The signup was approved, you can log in now:")) " "
+                            strong { (&request.user_id) }
+                        },
+                        SignupStatus::Rejected => div class="alert alert-error" {
+                            (ui.t("The signup was rejected:")) " "
+                            strong { (&request.user_id) }
+                        },
                     }
+                    p { a href="/login" { (ui.t("Login")) } }
+                } @else {
+                    form method="GET" action="/signup/status" {
+                        div class="form-group" {
+                            label for="token" { (ui.t("Token")) }
+                            input type="text" id="token" name="token" required autofocus;
+                        }
 
-                    button type="submit" class="btn btn-primary" { "Login" }
+                        button type="submit" class="btn btn-primary" { (ui.t("Check")) }
+                    }
                 }
             }
         }
     };
 
-    layout("Login - S3-CAS", content).into_string()
+    layout(ui, "Signup status - S3-CAS", content).into_string()
 }
 
 /// First-time setup page for creating admin account
-pub fn setup_admin_page(error: Option<&str>) -> String {
+pub fn setup_admin_page(ui: &Ui, error: Option<&str>) -> String {
     let content = html! {
         div class="login-container" {
             div class="login-box" {
@@ -533,19 +971,95 @@ pub fn setup_admin_page(error: Option<&str>) -> String {
         }
     };
 
-    layout("Setup Admin - S3-CAS", content).into_string()
+    layout(ui, "Setup Admin - S3-CAS", content).into_string()
 }
 
-/// Admin users list page
-pub fn admin_users_page(users: &[crate::auth::UserRecord]) -> String {
+/// The request rate and counts of a bucket, over `minutes`
+fn activity_cells(activity: &BucketActivity, minutes: u64) -> Markup {
+    let counts = &activity.counts;
+    html! {
+        td class="number" { (format!("{:.1}", counts.requests() as f64 / minutes as f64)) }
+        td class="number" { (counts.reads) }
+        td class="number" { (counts.writes) }
+        td class="number" { (format_size(counts.bytes_read)) }
+        td class="number" { (format_size(counts.bytes_written)) }
+        td class="number" { (format_size(counts.bytes_read_by_requesters)) }
+    }
+}
+
+/// Buckets of all users with open stores with the most requests in the last
+/// `minutes`, by user id
+pub fn admin_hot_buckets_page(ui: &Ui, hot: &[(String, BucketActivity)], minutes: u64) -> String {
     let content = html! {
         div class="page-header" {
-            h2 { "User Management" }
-            a href="/admin/users/new" class="btn btn-primary" { "+ Create User" }
+            h2 { "Hot Buckets" }
+            div {
+                @for window in [5, 15, 60] {
+                    a href={ "/admin/hot-buckets?minutes=" (window) }
+                        class=(if window == minutes { "btn btn-primary" } else { "btn" }) {
+                        (window) " min"
+                    }
+                    " "
+                }
+            }
         }
 
-        @if users.is_empty() {
-            p class="empty-state" { "No users found" }
+        @if hot.is_empty() {
+            p class="empty-state" { "No requests in the last " (minutes) " minutes" }
+        } @else {
+            table {
+                thead {
+                    tr {
+                        th { "User" }
+                        th { "Bucket" }
+                        th class="number" { "Requests/min" }
+                        th class="number" { "Reads" }
+                        th class="number" { "Writes" }
+                        th class="number" { "Read" }
+                        th class="number" { "Written" }
+                        th class="number" { "Paid by requesters" }
+                    }
+                }
+                tbody {
+                    @for (user_id, activity) in hot {
+                        tr {
+                            td { (user_id) }
+                            td { (&activity.bucket) }
+                            (activity_cells(activity, minutes))
+                        }
+                    }
+                }
+            }
+        }
+        p class="help-text" {
+            "Only users whose storage is open are listed, the storage of idle users is closed."
+        }
+        p {
+            a href="/admin/users" { "← Back to users" }
+        }
+    };
+
+    layout(ui, "Hot Buckets - S3-CAS", content).into_string()
+}
+
+/// Admin users list page
+pub fn admin_users_page(ui: &Ui, users: &[crate::auth::UserRecord]) -> String {
+    let content = html! {
+        div class="page-header" {
+            h2 { "User Management" }
+            div {
+                a href="/admin/jobs" class="btn" { "Jobs" }
+                " "
+                a href="/admin/hot-buckets" class="btn" { "Hot Buckets" }
+                " "
+                a href="/admin/signups" class="btn" { "Signups" }
+                " "
+                a href="/admin/users/new" class="btn btn-primary" { "+ Create User" }
+            }
+        }
+
+        @if users.is_empty() {
+            p class="empty-state" { "No users found" }
         } @else {
             table {
                 thead {
@@ -589,6 +1103,12 @@ pub fn admin_users_page(users: &[crate::auth::UserRecord]) -> String {
                                 }
                                 " "
                                 form method="POST" action={"/admin/users/" (&user.user_id) "/delete"} style="display: inline;" {
+                                    select name="policy" title="What to do with the buckets of the user" {
+                                        option value="deny" { "Only without buckets" }
+                                        option value="cascade" { "Delete buckets" }
+                                        option value="archive" { "Archive buckets" }
+                                    }
+                                    " "
                                     button type="submit" class="btn btn-small btn-danger"
                                             onclick={"return confirm('Delete user " (&user.user_id) "?');"} {
                                         "Delete"
@@ -606,11 +1126,196 @@ pub fn admin_users_page(users: &[crate::auth::UserRecord]) -> String {
         }
     };
 
-    layout("User Management - S3-CAS", content).into_string()
+    layout(ui, "User Management - S3-CAS", content).into_string()
+}
+
+/// Pending self-service signups with their approve and reject actions,
+/// `message` is a success (`true`) or error message of the last decision
+pub fn admin_signups_page(
+    ui: &Ui,
+    pending: &[crate::auth::SignupRequest],
+    message: Option<(bool, String)>,
+) -> String {
+    let content = html! {
+        div class="page-header" {
+            h2 { "Pending Signups" }
+        }
+
+        @if let Some((success, message)) = message {
+            div class=(if success { "alert alert-success" } else { "alert alert-error" }) {
+                (message)
+            }
+        }
+
+        @if pending.is_empty() {
+            p class="empty-state" { "No pending signups" }
+        } @else {
+            table {
+                thead {
+                    tr {
+                        th { "User ID" }
+                        th { "Requested" }
+                        th { "Actions" }
+                    }
+                }
+                tbody {
+                    @for request in pending {
+                        tr {
+                            td { code { (&request.user_id) } }
+                            td { (format_epoch_secs(request.created_at)) }
+                            td class="actions" {
+                                form method="POST" action={"/admin/signups/" (&request.user_id) "/approve"} style="display: inline;" {
+                                    button type="submit" class="btn btn-small btn-primary" { "Approve" }
+                                }
+                                " "
+                                form method="POST" action={"/admin/signups/" (&request.user_id) "/reject"} style="display: inline;" {
+                                    button type="submit" class="btn btn-small btn-danger"
+                                            onclick={"return confirm('Reject the signup of " (&request.user_id) "?');"} {
+                                        "Reject"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        p class="help-text" {
+            "Approved users get S3 credentials generated, they can see them on their profile page."
+        }
+        p {
+            a href="/admin/users" { "← Back to users" }
+        }
+    };
+
+    layout(ui, "Signups - S3-CAS", content).into_string()
+}
+
+/// Maintenance jobs page, with forms to start and cancel jobs
+pub fn admin_jobs_page(ui: &Ui, jobs: &[JobInfo], error_message: Option<&str>) -> String {
+    let content = html! {
+        div class="page-header" {
+            h2 { "Maintenance Jobs" }
+            a href="/admin/jobs" class="btn" { "Refresh" }
+        }
+
+        @if let Some(error) = error_message {
+            div class="alert alert-error" { (error) }
+        }
+
+        div class="form-container" {
+            form method="POST" action="/admin/jobs" style="display: inline;" {
+                input type="hidden" name="kind" value="blob_gc";
+                button type="submit" class="btn btn-small" { "Collect Blob Files" }
+            }
+            " "
+            form method="POST" action="/admin/jobs" style="display: inline;" {
+                input type="hidden" name="kind" value="scrub";
+                button type="submit" class="btn btn-small" { "Scrub Blocks" }
+            }
+            form method="POST" action="/admin/jobs" {
+                input type="hidden" name="kind" value="bucket_delete";
+                div class="form-group" {
+                    label for="user" { "User ID" }
+                    input type="text" id="user" name="user" required;
+                }
+                div class="form-group" {
+                    label for="bucket" { "Bucket" }
+                    input type="text" id="bucket" name="bucket" required;
+                }
+                button type="submit" class="btn btn-small btn-danger"
+                        onclick="return confirm('Delete the bucket and all its objects?');" {
+                    "Delete Bucket"
+                }
+            }
+            form method="POST" action="/admin/jobs" {
+                input type="hidden" name="kind" value="prefix_rename";
+                div class="form-group" {
+                    label for="rename-user" { "User ID" }
+                    input type="text" id="rename-user" name="user" required;
+                }
+                div class="form-group" {
+                    label for="rename-bucket" { "Bucket" }
+                    input type="text" id="rename-bucket" name="bucket" required;
+                }
+                div class="form-group" {
+                    label for="prefix" { "Prefix" }
+                    input type="text" id="prefix" name="prefix" placeholder="logs/2023/" required;
+                }
+                div class="form-group" {
+                    label for="new_prefix" { "New Prefix" }
+                    input type="text" id="new_prefix" name="new_prefix" placeholder="archive/2023/" required;
+                }
+                button type="submit" class="btn btn-small" { "Rename Prefix" }
+            }
+        }
+
+        @if jobs.is_empty() {
+            p class="empty-state" { "No jobs found" }
+        } @else {
+            table {
+                thead {
+                    tr {
+                        th { "ID" }
+                        th { "Kind" }
+                        th { "Target" }
+                        th { "Status" }
+                        th { "Progress" }
+                        th { "Started" }
+                        th { "Finished" }
+                        th { "Actions" }
+                    }
+                }
+                tbody {
+                    @for job in jobs {
+                        @let record = &job.record;
+                        tr {
+                            td { (record.id) }
+                            td { code { (&record.kind) } }
+                            td { (record.target.as_deref().unwrap_or("-")) }
+                            td {
+                                (format!("{:?}", record.status))
+                                @if let Some(error) = &record.error {
+                                    br;
+                                    small { (error) }
+                                }
+                            }
+                            td {
+                                @match job.percent {
+                                    Some(percent) => {
+                                        progress max="100" value=(percent) {}
+                                        " " (percent) "% (" (record.done) "/" (record.total) ")"
+                                    }
+                                    None => { "-" }
+                                }
+                            }
+                            td { (format_unix_timestamp(record.started_at)) }
+                            td {
+                                (record.finished_at.map(format_unix_timestamp).unwrap_or_default())
+                            }
+                            td class="actions" {
+                                @if !record.status.is_finished() {
+                                    form method="POST" action={"/admin/jobs/" (record.id) "/cancel"} style="display: inline;" {
+                                        button type="submit" class="btn btn-small btn-danger" { "Cancel" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        p class="help-text" {
+            a href="/admin/users" { "← Back to users" }
+        }
+    };
+
+    layout(ui, "Maintenance Jobs - S3-CAS", content).into_string()
 }
 
 /// New user creation form
-pub fn new_user_form() -> String {
+pub fn new_user_form(ui: &Ui) -> String {
     let content = html! {
         div class="form-container" {
             h2 { "Create New User" }
@@ -662,11 +1367,11 @@ pub fn new_user_form() -> String {
         }
     };
 
-    layout("Create User - S3-CAS", content).into_string()
+    layout(ui, "Create User - S3-CAS", content).into_string()
 }
 
 /// Password reset form
-pub fn reset_password_form(user: &crate::auth::UserRecord) -> String {
+pub fn reset_password_form(ui: &Ui, user: &crate::auth::UserRecord) -> String {
     let content = html! {
         div class="form-container" {
             h2 { "Reset Password for " (&user.ui_login) }
@@ -690,14 +1395,28 @@ pub fn reset_password_form(user: &crate::auth::UserRecord) -> String {
         }
     };
 
-    layout(&format!("Reset Password - {}", user.ui_login), content).into_string()
+    layout(ui, &format!("Reset Password - {}", user.ui_login), content).into_string()
 }
 
 /// Profile page showing S3 credentials and password change form
-pub fn profile_page(user: &crate::auth::UserRecord, error_message: Option<&str>, is_setup: bool) -> String {
+pub fn profile_page(
+    ui: &Ui,
+    user: &crate::auth::UserRecord,
+    keys: &[crate::auth::S3KeyInfo],
+    error_message: Option<&str>,
+    is_setup: bool,
+    is_rotated: bool,
+) -> String {
     let content = html! {
         h2 { "My Profile" }
 
+        @if is_rotated {
+            div class="alert alert-success" style="margin-bottom: 2rem;" {
+                "New S3 credentials were generated, they are shown below. "
+                "Move your clients to them before the old access key expires."
+            }
+        }
+
         @if is_setup {
             div class="alert alert-success" style="margin-bottom: 2rem;" {
                 h3 style="margin-top: 0;" { "Setup Complete!" }
@@ -803,6 +1522,102 @@ pub fn profile_page(user: &crate::auth::UserRecord, error_message: Option<&str>,
             }
         }
 
+        div class="profile-section" {
+            h3 { "Access Keys" }
+            p class="help-text" {
+                "Rotating generates a new key pair. The old pair keeps working during the grace period, "
+                "so clients can be moved to the new one, and can be revoked earlier once they are."
+            }
+
+            table class="info-table" {
+                thead {
+                    tr {
+                        th { "Access Key" }
+                        th { "Expires" }
+                        th {}
+                    }
+                }
+                tbody {
+                    @for key in keys {
+                        tr {
+                            td { code class="credential" { (&key.s3_access_key) } }
+                            @if let Some(expires_at) = key.expires_at {
+                                td { (format_epoch_secs(expires_at)) }
+                                td {
+                                    form method="POST" action="/profile/keys/revoke" style="margin: 0;" {
+                                        input type="hidden" name="access_key" value=(&key.s3_access_key);
+                                        button type="submit" class="btn-small" { "Revoke" }
+                                    }
+                                }
+                            } @else {
+                                td { span class="badge" { "current" } }
+                                td {}
+                            }
+                        }
+                    }
+                }
+            }
+
+            form method="POST" action="/profile/keys/rotate"
+                onsubmit="return confirm('Generate a new S3 key pair?');" {
+                div class="form-group" {
+                    label for="grace_hours" { "Keep the old key valid for (hours)" }
+                    input type="number" id="grace_hours" name="grace_hours" min="0" value="24";
+                }
+                button type="submit" class="btn btn-primary" { "Rotate Keys" }
+            }
+        }
+
+        div class="profile-section" {
+            h3 { "Temporary Credentials" }
+            p class="help-text" {
+                "Generates a short-lived key pair for ad-hoc use, e.g. in a shell. "
+                "It is shown once, expires on its own, and is listed above until then."
+            }
+
+            form method="POST" action="/profile/keys/temporary" {
+                div class="form-group" {
+                    label for="ttl_minutes" { "Valid for (minutes)" }
+                    input type="number" id="ttl_minutes" name="ttl_minutes" min="1"
+                        max=(crate::auth::MAX_TEMPORARY_KEY_SECS / 60)
+                        value=(crate::auth::DEFAULT_TEMPORARY_KEY_SECS / 60);
+                }
+                button type="submit" class="btn btn-primary" { "Create Temporary Credentials" }
+            }
+        }
+
+        div class="profile-section" {
+            h3 { (ui.t("Display")) }
+
+            form method="POST" action="/profile/preferences" {
+                div class="form-group" {
+                    label for="theme" { (ui.t("Theme")) }
+                    select id="theme" name="theme" {
+                        @for theme in Theme::ALL {
+                            option value=(theme.as_str()) selected[theme == user.theme] {
+                                (ui.t(theme_label(theme)))
+                            }
+                        }
+                    }
+                }
+
+                div class="form-group" {
+                    label for="language" { (ui.t("Language")) }
+                    select id="language" name="language" {
+                        option value="" selected[user.language.is_none()] { (ui.t("Browser language")) }
+                        @for language in Language::ALL {
+                            option value=(language.code())
+                                selected[user.language.as_deref() == Some(language.code())] {
+                                (language.name())
+                            }
+                        }
+                    }
+                }
+
+                button type="submit" class="btn btn-primary" { (ui.t("Save")) }
+            }
+        }
+
         div class="profile-section" {
             h3 { "Change Password" }
 
@@ -840,11 +1655,129 @@ pub fn profile_page(user: &crate::auth::UserRecord, error_message: Option<&str>,
         }
     };
 
-    layout_with_user("My Profile - S3-CAS", content, Some(user.is_admin)).into_string()
+    layout_with_user(ui, "My Profile - S3-CAS", content, Some(user.is_admin)).into_string()
+}
+
+/// A `README.md` or `index.html` shown above the object listing
+fn index_page_section(index_page: &IndexPage) -> Markup {
+    let file_name = index_page.key.rsplit('/').next().unwrap_or(&index_page.key);
+    html! {
+        section class="index-page" {
+            div class="index-page-header" { "📖 " (file_name) }
+            @if index_page.format == IndexFormat::Markdown {
+                // sanitized when rendered
+                div class="markdown" { (PreEscaped(&index_page.content)) }
+            } @else {
+                // untrusted HTML, runs without scripts, forms and same origin access
+                iframe class="index-html" sandbox="" srcdoc=(index_page.content) {}
+            }
+        }
+    }
 }
 
 // Helper functions
 
+fn format_epoch_secs(secs: u64) -> String {
+    chrono::DateTime::from_timestamp(secs as i64, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%d %H:%M:%S UTC")
+        .to_string()
+}
+
+/// Page showing a temporary S3 key pair, the only time its secret is shown
+pub fn temporary_key_page(ui: &Ui, access_key: &str, secret_key: &str, expires_at: u64) -> String {
+    let content = html! {
+        h2 { "Temporary Credentials" }
+
+        div class="alert alert-success" style="margin-bottom: 2rem;" {
+            "These credentials are valid until " (format_epoch_secs(expires_at)) ". "
+            strong { "Copy them now - the secret key can't be shown again." }
+        }
+
+        div class="profile-section" {
+            table class="info-table credentials-table" {
+                tr {
+                    th { "Access Key" }
+                    td { code class="credential" { (access_key) } }
+                }
+                tr {
+                    th { "Secret Key" }
+                    td { code class="credential" { (secret_key) } }
+                }
+            }
+
+            details class="example-config" open {
+                summary { "Example: Environment Variables" }
+                pre {
+                    code class="config-code" {
+                        "export AWS_ACCESS_KEY_ID=" (access_key) "\n"
+                        "export AWS_SECRET_ACCESS_KEY=" (secret_key)
+                    }
+                }
+            }
+
+            a href="/profile" class="btn btn-primary" { "Back to Profile" }
+        }
+    };
+
+    layout(ui, "Temporary Credentials", content).into_string()
+}
+
+/// Limits of a bucket, with a form to change them
+pub fn bucket_limits_page(ui: &Ui, info: &BucketLimitsInfo, error_message: Option<&str>) -> String {
+    let limit = |limit: Option<u64>| limit.map(|limit| limit.to_string()).unwrap_or_default();
+    let content = html! {
+        div class="breadcrumb" {
+            a href="/buckets" { (ui.t("Buckets")) }
+            " / "
+            a href={ "/buckets/" (urlencoding::encode(&info.bucket)) } { (info.bucket) }
+            " / "
+            strong { (ui.t("Limits")) }
+        }
+
+        div class="form-container" {
+            h2 { (ui.t("Limits of")) " \"" (info.bucket) "\"" }
+
+            @if let Some(error) = error_message {
+                div class="alert alert-error" { (error) }
+            }
+
+            table class="info-table" {
+                tr {
+                    th { (ui.t("Current contents")) }
+                    td {
+                        (info.objects) " " (ui.t("Objects")) ", " (format_size(info.logical_bytes))
+                    }
+                }
+            }
+
+            form method="POST" action={ "/limits/" (urlencoding::encode(&info.bucket)) } {
+                div class="form-group" {
+                    label for="max_objects" { (ui.t("Maximum objects")) }
+                    input type="number" id="max_objects" name="max_objects" min="0"
+                        placeholder=(ui.t("unlimited")) value=(limit(info.max_objects));
+                }
+                div class="form-group" {
+                    label for="max_bytes" { (ui.t("Maximum size in bytes")) }
+                    input type="number" id="max_bytes" name="max_bytes" min="0"
+                        placeholder=(ui.t("unlimited")) value=(limit(info.max_bytes));
+                }
+
+                div class="alert alert-info" {
+                    (ui.t("Leave a field empty for no limit.")) " "
+                    (ui.t("Uploads exceeding a limit are rejected, objects already in the bucket are kept."))
+                }
+
+                div class="form-actions" {
+                    button type="submit" class="btn btn-primary" { (ui.t("Save")) }
+                }
+            }
+        }
+    };
+
+    layout(ui, &format!("{} - {}", ui.t("Limits"), info.bucket), content).into_string()
+}
+
 #[allow(dead_code)]
 fn format_timestamp(time: std::time::SystemTime) -> String {
     use std::time::SystemTime;
@@ -873,669 +1806,45 @@ fn format_size(bytes: u64) -> String {
     }
 }
 
-fn format_unix_timestamp(unix_seconds: u64) -> String {
-    let datetime = chrono::DateTime::from_timestamp(unix_seconds as i64, 0)
-        .unwrap_or_default();
-    datetime.format("%Y-%m-%d %H:%M:%S UTC").to_string()
-}
-
-// CSS Styles
-const STYLES: &str = r#"
-* {
-    margin: 0;
-    padding: 0;
-    box-sizing: border-box;
-}
-
-body {
-    font-family: system-ui, -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;
-    line-height: 1.6;
-    color: #333;
-    background: #f5f5f5;
-    padding-bottom: 3rem;
-}
-
-header {
-    background: #2c3e50;
-    color: white;
-    padding: 1rem 2rem;
-    display: flex;
-    justify-content: space-between;
-    align-items: center;
-}
-
-header h1 {
-    font-size: 1.5rem;
-    font-weight: 600;
-}
-
-nav a {
-    color: #ecf0f1;
-    text-decoration: none;
-    font-size: 0.9rem;
-}
-
-nav a:hover {
-    text-decoration: underline;
-}
-
-main {
-    max-width: 1400px;
-    margin: 2rem auto;
-    padding: 0 2rem;
-    background: white;
-    border-radius: 8px;
-    box-shadow: 0 1px 3px rgba(0,0,0,0.1);
-    padding: 2rem;
-}
-
-footer {
-    text-align: center;
-    color: #666;
-    font-size: 0.85rem;
-    margin-top: 2rem;
-}
-
-.breadcrumb {
-    color: #666;
-    margin-bottom: 1.5rem;
-    font-size: 0.9rem;
-}
-
-.breadcrumb a {
-    color: #3498db;
-    text-decoration: none;
-}
-
-.breadcrumb a:hover {
-    text-decoration: underline;
-}
-
-.page-header {
-    display: flex;
-    justify-content: space-between;
-    align-items: center;
-    margin-bottom: 1.5rem;
-    padding-bottom: 1rem;
-    border-bottom: 2px solid #ecf0f1;
-}
-
-.page-header h2 {
-    font-size: 1.75rem;
-    color: #2c3e50;
-}
-
-.count {
-    color: #7f8c8d;
-    font-size: 0.9rem;
-}
-
-table {
-    width: 100%;
-    border-collapse: collapse;
-    margin-top: 1rem;
-}
-
-th, td {
-    text-align: left;
-    padding: 0.4rem 0.6rem;
-    border-bottom: 1px solid #ecf0f1;
-}
-
-th {
-    background: #f8f9fa;
-    font-weight: 600;
-    color: #555;
-    font-size: 0.9rem;
-}
-
-th.number, td.number {
-    text-align: right;
-}
-
-tbody tr:hover {
-    background: #f8f9fa;
-}
-
-tbody a {
-    color: #3498db;
-    text-decoration: none;
-}
-
-tbody a:hover {
-    text-decoration: underline;
-}
-
-code {
-    background: #f8f9fa;
-    padding: 0.2rem 0.5rem;
-    border-radius: 3px;
-    font-size: 0.85rem;
-    font-family: 'Courier New', monospace;
-}
-
-.hash-short {
-    color: #7f8c8d;
-}
-
-.hash-full {
-    word-break: break-all;
-    font-size: 0.8rem;
-}
-
-.badge {
-    display: inline-block;
-    padding: 0.2rem 0.5rem;
-    border-radius: 3px;
-    font-size: 0.75rem;
-    font-weight: 600;
-    text-transform: uppercase;
-}
-
-.badge.inline {
-    background: #e8f5e9;
-    color: #2e7d32;
-}
-
-.badge.blocks {
-    background: #e3f2fd;
-    color: #1565c0;
-}
-
-.badge.directory {
-    background: #fff3e0;
-    color: #e65100;
-}
-
-.directory-row {
-    font-weight: 500;
-}
-
-.directory-row:hover {
-    background: #fffbf5;
-}
-
-.dedup-badge {
-    background: #fff3e0;
-    color: #e65100;
-    padding: 0.1rem 0.3rem;
-    border-radius: 3px;
-    font-size: 0.7rem;
-    font-weight: 600;
-}
-
-.metadata {
-    background: #f8f9fa;
-    padding: 1.5rem;
-    border-radius: 6px;
-    margin: 1.5rem 0;
-}
-
-.metadata dt {
-    font-weight: 600;
-    color: #555;
-    margin-top: 1rem;
-}
-
-.metadata dt:first-child {
-    margin-top: 0;
-}
-
-.metadata dd {
-    margin: 0.5rem 0 0 0;
-}
-
-.blocks-table {
-    margin-top: 0.5rem;
-    background: white;
-}
-
-.blocks-table th {
-    background: #ecf0f1;
-}
-
-.empty-state {
-    text-align: center;
-    color: #95a5a6;
-    padding: 3rem 0;
-    font-size: 1.1rem;
-}
-
-.error-page {
-    text-align: center;
-    padding: 3rem 0;
-}
-
-.error-page h2 {
-    color: #e74c3c;
-    margin-bottom: 1rem;
-}
-
-.error-page p {
-    margin: 1rem 0;
-}
-
-.error-page a {
-    color: #3498db;
-    text-decoration: none;
-}
-
-.error-page a:hover {
-    text-decoration: underline;
-}
-
-@media (max-width: 768px) {
-    main {
-        margin: 1rem;
-        padding: 1rem;
-    }
-
-    header {
-        flex-direction: column;
-        text-align: center;
-    }
-
-    .page-header {
-        flex-direction: column;
-        align-items: flex-start;
+/// Difference between the last and the first value, e.g. `+1.50 MB`
+fn format_growth(values: &[u64]) -> String {
+    match (values.first(), values.last()) {
+        (Some(&first), Some(&last)) if last >= first => format!("+{}", format_size(last - first)),
+        (Some(&first), Some(&last)) => format!("-{}", format_size(first - last)),
+        _ => format_size(0),
     }
-
-    table {
-        font-size: 0.85rem;
-    }
-
-    th, td {
-        padding: 0.5rem;
-    }
-}
-
-/* Login page */
-.login-container {
-    display: flex;
-    justify-content: center;
-    align-items: center;
-    min-height: 60vh;
-}
-
-.login-box {
-    width: 100%;
-    max-width: 400px;
-    padding: 2rem;
-    border: 1px solid #ddd;
-    border-radius: 8px;
-    background: white;
-}
-
-.login-box h2 {
-    margin-bottom: 1.5rem;
-    text-align: center;
-}
-
-/* Forms */
-.form-container {
-    max-width: 600px;
-    margin: 0 auto;
-}
-
-.form-group {
-    margin-bottom: 1.5rem;
 }
 
-.form-group label {
-    display: block;
-    margin-bottom: 0.5rem;
-    font-weight: 500;
-}
-
-.form-group input[type="text"],
-.form-group input[type="password"] {
-    width: 100%;
-    padding: 0.5rem;
-    border: 1px solid #ddd;
-    border-radius: 4px;
-    font-size: 1rem;
-}
-
-.form-group small {
-    display: block;
-    margin-top: 0.25rem;
-    color: #666;
-    font-size: 0.875rem;
-}
-
-.required {
-    color: #d9534f;
-}
-
-.form-actions {
-    margin-top: 2rem;
-    padding-top: 1rem;
-    border-top: 1px solid #ddd;
-}
-
-/* Buttons */
-.btn {
-    display: inline-block;
-    padding: 0.5rem 1rem;
-    border: 1px solid #ddd;
-    border-radius: 4px;
-    background: white;
-    color: #333;
-    text-decoration: none;
-    cursor: pointer;
-    font-size: 1rem;
-    vertical-align: middle;
-    line-height: 1.5;
-    box-sizing: border-box;
-}
-
-.btn:hover {
-    background: #f0f0f0;
-}
-
-.btn-primary {
-    background: #007bff;
-    color: white;
-    border-color: #007bff;
-}
-
-.btn-primary:hover {
-    background: #0056b3;
-    border-color: #0056b3;
-}
-
-.btn-danger {
-    background: #d9534f;
-    color: white;
-    border-color: #d9534f;
-}
-
-.btn-danger:hover {
-    background: #c9302c;
-    border-color: #c9302c;
-}
-
-.btn-small {
-    padding: 0.25rem 0.5rem;
-    font-size: 0.875rem;
-}
-
-/* Alerts */
-.alert {
-    padding: 1rem;
-    margin-bottom: 1rem;
-    border-radius: 4px;
-}
-
-.alert-error {
-    background: #f8d7da;
-    border: 1px solid #f5c6cb;
-    color: #721c24;
-}
-
-.alert-info {
-    background: #d1ecf1;
-    border: 1px solid #bee5eb;
-    color: #0c5460;
-}
-
-.alert-success {
-    background: #d4edda;
-    border: 1px solid #c3e6cb;
-    color: #155724;
-}
-
-/* Profile Page */
-.profile-section {
-    background: white;
-    padding: 1.5rem;
-    margin-bottom: 2rem;
-    border-radius: 8px;
-    box-shadow: 0 1px 3px rgba(0,0,0,0.1);
-}
-
-.profile-section h3 {
-    margin-bottom: 1rem;
-    padding-bottom: 0.5rem;
-    border-bottom: 2px solid #f0f0f0;
-    color: #444;
-}
-
-.info-table {
-    width: 100%;
-    border-collapse: collapse;
-}
-
-.info-table th,
-.info-table td {
-    padding: 0.75rem;
-    text-align: left;
-    border-bottom: 1px solid #eee;
-}
-
-.info-table th {
-    width: 200px;
-    font-weight: 600;
-    color: #666;
-}
-
-.credential {
-    background: #f5f5f5;
-    padding: 0.5rem 1rem;
-    border-radius: 4px;
-    font-family: 'Courier New', monospace;
-    font-size: 0.9em;
-    display: inline-block;
-}
-
-.btn-small {
-    padding: 0.25rem 0.75rem;
-    font-size: 0.85em;
-    background: #007bff;
-    color: white;
-    border: none;
-    border-radius: 4px;
-    cursor: pointer;
-    vertical-align: middle;
-    line-height: 1.5;
-    box-sizing: border-box;
-}
+/// Small inline SVG line chart of `values`
+fn sparkline(values: &[u64]) -> Markup {
+    const WIDTH: f64 = 120.0;
+    const HEIGHT: f64 = 24.0;
 
-.btn-small:hover {
-    background: #0056b3;
-}
-
-.example-config {
-    margin-top: 1.5rem;
-    padding: 1rem;
-    background: #f8f9fa;
-    border-radius: 4px;
-}
-
-.example-config summary {
-    cursor: pointer;
-    font-weight: 600;
-    color: #007bff;
-}
-
-.example-config pre {
-    margin-top: 1rem;
-    background: #2d2d2d;
-    padding: 1rem;
-    border-radius: 4px;
-    overflow-x: auto;
-}
-
-.example-config pre code {
-    font-family: 'Courier New', monospace;
-    font-size: 0.9em;
-    color: #f8f8f2 !important;
-    background: transparent !important;
-    padding: 0 !important;
-    display: block;
-    line-height: 1.5;
-}
-
-.badge-admin {
-    background: #007bff;
-    color: white;
-    padding: 0.25rem 0.75rem;
-    border-radius: 4px;
-    font-size: 0.85em;
-}
-
-/* Admin UI */
-.page-header {
-    display: flex;
-    justify-content: space-between;
-    align-items: center;
-    margin-bottom: 1.5rem;
-}
-
-.actions {
-    white-space: nowrap;
-}
-
-.badge.admin {
-    background: #007bff;
-    color: white;
-}
-
-.help-text {
-    margin-top: 2rem;
-    padding-top: 1rem;
-    border-top: 1px solid #ddd;
-    color: #666;
-}
-
-/* Loading indicator for infinite scroll */
-.loading-indicator {
-    margin-top: 2rem;
-    padding: 1rem;
-    text-align: center;
-    color: #666;
-    font-style: italic;
-}
-
-.loading-indicator::after {
-    content: '';
-    display: inline-block;
-    width: 12px;
-    height: 12px;
-    margin-left: 8px;
-    border: 2px solid #ddd;
-    border-top-color: #3498db;
-    border-radius: 50%;
-    animation: spinner 0.8s linear infinite;
-}
-
-@keyframes spinner {
-    to { transform: rotate(360deg); }
-}
-
-@media (prefers-color-scheme: dark) {
-    body {
-        background: #1a1a1a;
-        color: #e0e0e0;
-    }
-
-    main {
-        background: #2d2d2d;
-    }
-
-    header {
-        background: #1a1a1a;
-    }
-
-    th {
-        background: #3a3a3a;
-        color: #e0e0e0;
-    }
-
-    tbody tr:hover {
-        background: #3a3a3a;
-    }
-
-    .directory-row:hover {
-        background: #3a3a3a;
-    }
-
-    code, .metadata {
-        background: #3a3a3a;
-    }
-
-    .breadcrumb {
-        color: #a0a0a0;
-    }
-
-    .page-header h2 {
-        color: #e0e0e0;
-    }
-
-    .count {
-        color: #a0a0a0;
-    }
-
-    .login-box {
-        background: #2d2d2d;
-        border-color: #444;
-    }
-
-    .form-group input[type="text"],
-    .form-group input[type="password"] {
-        background: #3a3a3a;
-        border-color: #444;
-        color: #e0e0e0;
-    }
-
-    .form-group small {
-        color: #a0a0a0;
-    }
-
-    .form-actions {
-        border-top-color: #444;
-    }
-
-    .btn {
-        background: #3a3a3a;
-        border-color: #444;
-        color: #e0e0e0;
-    }
-
-    .btn:hover {
-        background: #4a4a4a;
-    }
-
-    .alert-error {
-        background: #3a1a1a;
-        border-color: #6a2a2a;
-        color: #f8d7da;
-    }
-
-    .alert-info {
-        background: #1a2a3a;
-        border-color: #2a4a6a;
-        color: #d1ecf1;
-    }
-
-    .alert-success {
-        background: #1a3a1a;
-        border-color: #2a6a2a;
-        color: #d4edda;
-    }
-
-    .help-text {
-        border-top-color: #444;
-        color: #a0a0a0;
+    let max = values.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let step = if values.len() > 1 {
+        WIDTH / (values.len() - 1) as f64
+    } else {
+        0.0
+    };
+    let mut points: Vec<String> = values
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| format!("{:.1},{:.1}", i as f64 * step, HEIGHT - v as f64 / max * HEIGHT))
+        .collect();
+    // a single sample is drawn as a flat line
+    if points.len() == 1 {
+        points.push(format!("{:.1},{:.1}", WIDTH, HEIGHT - values[0] as f64 / max * HEIGHT));
     }
 
-    .loading-indicator {
-        color: #a0a0a0;
+    html! {
+        svg class="sparkline" width=(WIDTH) height=(HEIGHT) viewBox={ "0 0 " (WIDTH) " " (HEIGHT) } {
+            polyline points=(points.join(" ")) fill="none" stroke="#3498db" stroke-width="1.5";
+        }
     }
+}
 
-    .loading-indicator::after {
-        border-color: #444;
-        border-top-color: #3498db;
-    }
+fn format_unix_timestamp(unix_seconds: u64) -> String {
+    let datetime = chrono::DateTime::from_timestamp(unix_seconds as i64, 0)
+        .unwrap_or_default();
+    datetime.format("%Y-%m-%d %H:%M:%S UTC").to_string()
 }
-"#;
@@ -0,0 +1,50 @@
+//! The theme and language pages of the HTTP UI are rendered in.
+
+use hyper::{header, Request};
+
+use super::i18n::Language;
+use crate::auth::{Theme, UserRecord};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Ui {
+    pub theme: Theme,
+    pub language: Language,
+}
+
+impl Ui {
+    /// For a visitor without a user: the default theme, in the language of the browser.
+    pub fn for_request<B>(req: &Request<B>) -> Self {
+        Self {
+            theme: Theme::default(),
+            language: browser_language(req).unwrap_or_default(),
+        }
+    }
+
+    /// The theme of a user, in their language or the one of the browser if they
+    /// didn't choose one.
+    pub fn for_user<B>(req: &Request<B>, user: &UserRecord) -> Self {
+        let language = user
+            .language
+            .as_deref()
+            .and_then(Language::parse)
+            .or_else(|| browser_language(req))
+            .unwrap_or_default();
+        Self {
+            theme: user.theme,
+            language,
+        }
+    }
+
+    /// The translation of a text of the UI
+    pub fn t(&self, text: &'static str) -> &'static str {
+        self.language.translate(text)
+    }
+}
+
+fn browser_language<B>(req: &Request<B>) -> Option<Language> {
+    req.headers()
+        .get(header::ACCEPT_LANGUAGE)?
+        .to_str()
+        .ok()
+        .and_then(Language::from_accept_language)
+}
@@ -1,14 +1,52 @@
 use anyhow::{Result, bail};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::UNIX_EPOCH;
 
-use cas_storage::StorageEngine;
-use cas_storage::{FjallStore, FjallStoreNotx, MetaStore, ObjectType, ObjectData};
-use crate::auth::UserStore;
+use cas_storage::{
+    BlobStats, CorruptBlocks, DegradedObjects, MetadataSize, StorageEngine, TreeSize,
+};
+use cas_storage::{FjallStore, FjallStoreNotx, MetaStore, MultiPart, ObjectType, ObjectData};
+use cas_storage::metastore::{BlockID, BlockRef, BLOCKID_SIZE};
+use cas_storage::cas::multipart::MULTIPART_TREE;
+use crate::auth::{AuditLog, UserStore};
+
+/// Output of the inspect commands: aligned text for people, or JSON for scripts.
+///
+/// The JSON output of a command is a single object. Sizes are in bytes, times in
+/// seconds since the UNIX epoch and hashes hex encoded. Fields are only added,
+/// never renamed or removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Invalid output format: {}, valid values are: text, json", s)),
+        }
+    }
+}
+
+/// Print a report as pretty JSON
+pub fn print_json<T: Serialize>(report: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(report)?);
+    Ok(())
+}
 
 /// Detects if multi-user mode is enabled and returns list of user IDs
-fn detect_user_databases(meta_root: &PathBuf) -> Result<Option<Vec<String>>> {
+fn detect_user_databases(meta_root: &Path) -> Result<Option<Vec<String>>> {
     let mut user_ids = Vec::new();
 
     // Read directory entries
@@ -112,11 +150,26 @@ pub fn disk_space(
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct UserEntry {
+    pub user_id: String,
+    pub ui_login: String,
+    pub s3_access_key: String,
+    pub is_admin: bool,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserList {
+    pub users: Vec<UserEntry>,
+}
+
 /// List all users (multi-user mode only)
 pub fn list_users(
     meta_root: PathBuf,
     storage_engine: StorageEngine,
     users_config: Option<PathBuf>,
+    format: OutputFormat,
 ) -> Result<()> {
     if users_config.is_none() {
         bail!("list-users command requires multi-user mode (use --users-config)");
@@ -126,9 +179,24 @@ pub fn list_users(
     let shared_store = create_meta_store(meta_root, storage_engine);
     let user_store = UserStore::new(shared_store.get_underlying_store());
 
-    let users = user_store.list_users()?;
+    let report = UserList {
+        users: user_store
+            .list_users()?
+            .into_iter()
+            .map(|user| UserEntry {
+                user_id: user.user_id,
+                ui_login: user.ui_login,
+                s3_access_key: user.s3_access_key,
+                is_admin: user.is_admin,
+                created_at: user.created_at,
+            })
+            .collect(),
+    };
+    if format == OutputFormat::Json {
+        return print_json(&report);
+    }
 
-    if users.is_empty() {
+    if report.users.is_empty() {
         println!("No users found");
         return Ok(());
     }
@@ -139,28 +207,41 @@ pub fn list_users(
     println!("{:-<100}", "");
 
     // Print each user
-    for user in users {
-        let created_at = UNIX_EPOCH + std::time::Duration::from_secs(user.created_at);
-        let datetime = chrono::DateTime::<chrono::Utc>::from(created_at);
-
+    for user in report.users {
         println!("{:<20} {:<20} {:<30} {:<10} {:<20}",
             user.user_id,
             user.ui_login,
             user.s3_access_key,
             if user.is_admin { "Yes" } else { "No" },
-            datetime.format("%Y-%m-%d %H:%M:%S"),
+            format_timestamp(user.created_at),
         );
     }
 
     Ok(())
 }
 
+#[derive(Debug, Serialize)]
+pub struct UserStatsEntry {
+    pub user_id: String,
+    /// Whether the database of the user exists, the counts are 0 if not
+    pub found: bool,
+    pub buckets: usize,
+    pub objects: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserStatsReport {
+    pub users: Vec<UserStatsEntry>,
+}
+
 /// Show per-user storage statistics
 pub fn user_stats(
     meta_root: PathBuf,
     storage_engine: StorageEngine,
     users_config: Option<PathBuf>,
     user_id_filter: Option<String>,
+    format: OutputFormat,
 ) -> Result<()> {
     if users_config.is_none() {
         bail!("user-stats command requires multi-user mode (use --users-config)");
@@ -172,7 +253,44 @@ pub fn user_stats(
         detect_user_databases(&meta_root)?.unwrap_or_default()
     };
 
-    if user_ids.is_empty() {
+    let mut report = UserStatsReport { users: Vec::new() };
+    for user_id in user_ids {
+        let user_meta_path = meta_root.join(format!("user_{}", user_id));
+
+        let mut entry = UserStatsEntry {
+            user_id,
+            found: user_meta_path.exists(),
+            buckets: 0,
+            objects: 0,
+            bytes: 0,
+        };
+        if entry.found {
+            let meta_store = create_meta_store(user_meta_path, storage_engine);
+
+            // Get bucket count from _BUCKETS tree
+            let buckets = meta_store.list_buckets().unwrap_or_default();
+            entry.buckets = buckets.len();
+
+            // Count objects across all buckets and sum sizes
+            for bucket in buckets {
+                let bucket_tree = match meta_store.get_bucket_ext(&bucket.name()) {
+                    Ok(tree) => tree,
+                    Err(_) => continue,
+                };
+
+                for (_key, obj) in bucket_tree.range_filter(None, None, None) {
+                    entry.objects += 1;
+                    entry.bytes += obj.size();
+                }
+            }
+        }
+        report.users.push(entry);
+    }
+    if format == OutputFormat::Json {
+        return print_json(&report);
+    }
+
+    if report.users.is_empty() {
         println!("No users found");
         return Ok(());
     }
@@ -182,56 +300,73 @@ pub fn user_stats(
         "User ID", "Bucket Count", "Object Count", "Total Size");
     println!("{:-<70}", "");
 
-    for user_id in user_ids {
-        let user_meta_path = meta_root.join(format!("user_{}", user_id));
-
-        if !user_meta_path.exists() {
-            println!("{:<20} (database not found)", user_id);
+    for user in report.users {
+        if !user.found {
+            println!("{:<20} (database not found)", user.user_id);
             continue;
         }
-
-        let meta_store = create_meta_store(user_meta_path, storage_engine);
-
-        // Get bucket count from _BUCKETS tree
-        let buckets = meta_store.list_buckets().unwrap_or_default();
-        let bucket_count = buckets.len();
-
-        // Count objects across all buckets and sum sizes
-        let mut total_objects = 0usize;
-        let mut total_size = 0u64;
-
-        for bucket in buckets {
-            let bucket_tree = match meta_store.get_bucket_ext(&bucket.name()) {
-                Ok(tree) => tree,
-                Err(_) => continue,
-            };
-
-            for (_key, obj) in bucket_tree.range_filter(None, None, None) {
-                total_objects += 1;
-                total_size += obj.size();
-            }
-        }
-
         println!("{:<20} {:<15} {:<15} {:<20}",
-            user_id,
-            bucket_count,
-            total_objects,
-            format_bytes(total_size),
+            user.user_id,
+            user.buckets,
+            user.objects,
+            format_bytes(user.bytes),
         );
     }
 
     Ok(())
 }
 
+#[derive(Debug, Serialize)]
+pub struct BucketEntry {
+    /// User owning the bucket, in multi-user mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    pub name: String,
+    pub objects: usize,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BucketList {
+    pub buckets: Vec<BucketEntry>,
+}
+
+/// The buckets of a store with their amount of objects
+fn bucket_entries(meta_store: &MetaStore, owner: Option<&str>) -> Result<Vec<BucketEntry>> {
+    Ok(meta_store
+        .list_buckets()?
+        .into_iter()
+        .map(|bucket| {
+            // Count objects in bucket
+            let objects = meta_store
+                .get_bucket_ext(bucket.name())
+                .map(|tree| tree.range_filter(None, None, None).count())
+                .unwrap_or(0);
+            BucketEntry {
+                owner: owner.map(str::to_string),
+                name: bucket.name().to_string(),
+                objects,
+                created_at: bucket
+                    .ctime()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            }
+        })
+        .collect())
+}
+
 /// List all buckets
 pub fn list_buckets(
     meta_root: PathBuf,
     storage_engine: StorageEngine,
     users_config: Option<PathBuf>,
     user_filter: Option<String>,
+    format: OutputFormat,
 ) -> Result<()> {
     let is_multi_user = users_config.is_some();
 
+    let mut report = BucketList { buckets: Vec::new() };
     if is_multi_user {
         // Multi-user mode
         let user_ids = if let Some(user_id) = user_filter {
@@ -240,11 +375,6 @@ pub fn list_buckets(
             detect_user_databases(&meta_root)?.unwrap_or_default()
         };
 
-        // Print header
-        println!("{:<20} {:<30} {:<15} {:<20}",
-            "Owner", "Bucket Name", "Object Count", "Created At");
-        println!("{:-<85}", "");
-
         for user_id in user_ids {
             let user_meta_path = meta_root.join(format!("user_{}", user_id));
 
@@ -253,33 +383,34 @@ pub fn list_buckets(
             }
 
             let meta_store = create_meta_store(user_meta_path, storage_engine);
-            let buckets = meta_store.list_buckets().unwrap_or_default();
-
-            for bucket in buckets {
-                // Count objects in bucket
-                let bucket_tree = meta_store.get_bucket_ext(&bucket.name()).ok();
-                let object_count = if let Some(tree) = bucket_tree {
-                    tree.range_filter(None, None, None).count()
-                } else {
-                    0
-                };
-
-                let datetime = chrono::DateTime::<chrono::Utc>::from(bucket.ctime());
-
-                println!("{:<20} {:<30} {:<15} {:<20}",
-                    user_id,
-                    bucket.name(),
-                    object_count,
-                    datetime.format("%Y-%m-%d %H:%M:%S"),
-                );
-            }
+            let buckets = bucket_entries(&meta_store, Some(&user_id)).unwrap_or_default();
+            report.buckets.extend(buckets);
         }
     } else {
         // Single-user mode
         let meta_store = create_meta_store(meta_root, storage_engine);
-        let buckets = meta_store.list_buckets()?;
+        report.buckets = bucket_entries(&meta_store, None)?;
+    }
+    if format == OutputFormat::Json {
+        return print_json(&report);
+    }
+
+    if is_multi_user {
+        // Print header
+        println!("{:<20} {:<30} {:<15} {:<20}",
+            "Owner", "Bucket Name", "Object Count", "Created At");
+        println!("{:-<85}", "");
 
-        if buckets.is_empty() {
+        for bucket in report.buckets {
+            println!("{:<20} {:<30} {:<15} {:<20}",
+                bucket.owner.unwrap_or_default(),
+                bucket.name,
+                bucket.objects,
+                format_timestamp(bucket.created_at),
+            );
+        }
+    } else {
+        if report.buckets.is_empty() {
             println!("No buckets found");
             return Ok(());
         }
@@ -289,21 +420,11 @@ pub fn list_buckets(
             "Bucket Name", "Object Count", "Created At");
         println!("{:-<65}", "");
 
-        for bucket in buckets {
-            // Count objects in bucket
-            let bucket_tree = meta_store.get_bucket_ext(&bucket.name()).ok();
-            let object_count = if let Some(tree) = bucket_tree {
-                tree.range_filter(None, None, None).count()
-            } else {
-                0
-            };
-
-            let datetime = chrono::DateTime::<chrono::Utc>::from(bucket.ctime());
-
+        for bucket in report.buckets {
             println!("{:<30} {:<15} {:<20}",
-                bucket.name(),
-                object_count,
-                datetime.format("%Y-%m-%d %H:%M:%S"),
+                bucket.name,
+                bucket.objects,
+                format_timestamp(bucket.created_at),
             );
         }
     }
@@ -311,6 +432,18 @@ pub fn list_buckets(
     Ok(())
 }
 
+#[derive(Debug, Serialize)]
+pub struct BucketStatsReport {
+    pub bucket: String,
+    pub objects: usize,
+    pub bytes: u64,
+    pub unique_blocks: usize,
+    pub multipart_objects: usize,
+    pub inline_objects: usize,
+    /// Average object size, `null` for an empty bucket
+    pub average_object_size: Option<u64>,
+}
+
 /// Show statistics for a specific bucket
 pub fn bucket_stats(
     meta_root: PathBuf,
@@ -318,6 +451,7 @@ pub fn bucket_stats(
     users_config: Option<PathBuf>,
     bucket: String,
     user_filter: Option<String>,
+    format: OutputFormat,
 ) -> Result<()> {
     let is_multi_user = users_config.is_some();
 
@@ -361,26 +495,59 @@ pub fn bucket_stats(
         }
     }
 
-    println!("Bucket: {}", bucket);
-    println!("Object count: {}", object_count);
-    println!("Total size: {} ({} bytes)", format_bytes(total_size), total_size);
-    println!("Unique blocks: {}", unique_blocks.len());
-    println!("Multipart objects: {}", multipart_count);
-    println!("Inline objects: {}", inline_count);
+    let report = BucketStatsReport {
+        bucket,
+        objects: object_count,
+        bytes: total_size,
+        unique_blocks: unique_blocks.len(),
+        multipart_objects: multipart_count,
+        inline_objects: inline_count,
+        average_object_size: (object_count > 0).then(|| total_size / object_count as u64),
+    };
+    if format == OutputFormat::Json {
+        return print_json(&report);
+    }
+
+    println!("Bucket: {}", report.bucket);
+    println!("Object count: {}", report.objects);
+    println!("Total size: {} ({} bytes)", format_bytes(report.bytes), report.bytes);
+    println!("Unique blocks: {}", report.unique_blocks);
+    println!("Multipart objects: {}", report.multipart_objects);
+    println!("Inline objects: {}", report.inline_objects);
 
-    if object_count > 0 {
-        let avg_size = total_size / object_count as u64;
+    if let Some(avg_size) = report.average_object_size {
         println!("Average object size: {}", format_bytes(avg_size));
     }
 
     Ok(())
 }
 
+#[derive(Debug, Serialize)]
+pub struct RefCountBucket {
+    pub refcount: u64,
+    pub blocks: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockStatsReport {
+    pub blocks: usize,
+    pub bytes: u64,
+    pub references: u64,
+    /// References per block, which is also the deduplication ratio. `null`
+    /// without blocks
+    pub average_references: Option<f64>,
+    /// Share of the referenced data which deduplication saves, in percent
+    pub savings_percent: Option<f64>,
+    /// Amount of blocks per reference count, by increasing reference count
+    pub refcount_distribution: Vec<RefCountBucket>,
+}
+
 /// Show block storage statistics and deduplication ratio
 pub fn block_stats(
     meta_root: PathBuf,
     storage_engine: StorageEngine,
     _users_config: Option<PathBuf>,
+    format: OutputFormat,
 ) -> Result<()> {
     // Block storage is always in the shared database
     let shared_store = create_meta_store(meta_root, storage_engine);
@@ -388,8 +555,8 @@ pub fn block_stats(
 
     let mut total_blocks = 0usize;
     let mut total_block_size = 0u64;
-    let mut total_ref_count = 0usize;
-    let mut ref_count_distribution: std::collections::HashMap<usize, usize> = std::collections::HashMap::new();
+    let mut total_ref_count = 0u64;
+    let mut ref_count_distribution: HashMap<u64, usize> = HashMap::new();
 
     for item in block_tree.iter_all() {
         let (_block_id, block) = match item {
@@ -403,38 +570,213 @@ pub fn block_stats(
         *ref_count_distribution.entry(rc).or_insert(0) += 1;
     }
 
-    println!("Block Statistics:");
-    println!("  Total blocks: {}", total_blocks);
-    println!("  Total block storage: {} ({} bytes)", format_bytes(total_block_size), total_block_size);
-    println!("  Total references: {}", total_ref_count);
-
-    if total_blocks > 0 {
-        let avg_refs = total_ref_count as f64 / total_blocks as f64;
-        println!("  Average references per block: {:.2}", avg_refs);
+    // Deduplication ratio: how much storage is saved
+    let dedupe_ratio = (total_blocks > 0).then(|| total_ref_count as f64 / total_blocks as f64);
+    let mut refcount_distribution: Vec<_> = ref_count_distribution
+        .into_iter()
+        .map(|(refcount, blocks)| RefCountBucket { refcount, blocks })
+        .collect();
+    refcount_distribution.sort_by_key(|bucket| bucket.refcount);
+    let report = BlockStatsReport {
+        blocks: total_blocks,
+        bytes: total_block_size,
+        references: total_ref_count,
+        average_references: dedupe_ratio,
+        savings_percent: dedupe_ratio.map(|ratio| ((ratio - 1.0) / ratio) * 100.0),
+        refcount_distribution,
+    };
+    if format == OutputFormat::Json {
+        return print_json(&report);
+    }
 
-        // Deduplication ratio: how much storage is saved
-        let dedupe_ratio = total_ref_count as f64 / total_blocks as f64;
-        println!("  Deduplication ratio: {:.2}x", dedupe_ratio);
+    println!("Block Statistics:");
+    println!("  Total blocks: {}", report.blocks);
+    println!("  Total block storage: {} ({} bytes)", format_bytes(report.bytes), report.bytes);
+    println!("  Total references: {}", report.references);
 
-        let savings_pct = ((dedupe_ratio - 1.0) / dedupe_ratio) * 100.0;
+    if let (Some(ratio), Some(savings_pct)) = (report.average_references, report.savings_percent) {
+        println!("  Average references per block: {:.2}", ratio);
+        println!("  Deduplication ratio: {:.2}x", ratio);
         println!("  Storage savings: {:.1}%", savings_pct);
     }
 
     println!("\nReference count distribution:");
-    let mut counts: Vec<_> = ref_count_distribution.iter().collect();
-    counts.sort_by_key(|(rc, _)| *rc);
+    for bucket in report.refcount_distribution.iter().take(10) {
+        println!("  RC={}: {} blocks", bucket.refcount, bucket.blocks);
+    }
 
-    for (rc, count) in counts.iter().take(10) {
-        println!("  RC={}: {} blocks", rc, count);
+    if report.refcount_distribution.len() > 10 {
+        println!("  ... ({} more)", report.refcount_distribution.len() - 10);
     }
 
-    if counts.len() > 10 {
-        println!("  ... ({} more)", counts.len() - 10);
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetadataSizeReport {
+    /// The trees, largest first
+    pub trees: Vec<TreeSize>,
+    pub metadata_bytes: u64,
+    pub data_bytes: u64,
+    pub ratio: f64,
+}
+
+/// Show the disk space of every metadata tree and the metadata to data ratio
+pub fn metadata_size(
+    meta_root: PathBuf,
+    storage_engine: StorageEngine,
+    users_config: Option<PathBuf>,
+    format: OutputFormat,
+) -> Result<()> {
+    let tree_sizes = |meta_store: &MetaStore, user_id: Option<&str>, shared| -> Result<_> {
+        let counters = meta_store.counters()?;
+        let size = MetadataSize {
+            trees: meta_store
+                .tree_sizes()?
+                .into_iter()
+                .map(|(name, bytes)| TreeSize {
+                    name: match user_id {
+                        Some(user_id) => format!("{}/{}", user_id, name),
+                        None => name,
+                    },
+                    shared,
+                    bytes,
+                })
+                .collect(),
+            block_bytes: counters.block_bytes,
+            inline_bytes: counters.inline_bytes,
+        };
+        Ok(size)
+    };
+
+    // In multi-user mode the blocks are in the shared database, the buckets in
+    // the per-user databases
+    let mut sizes = vec![tree_sizes(
+        &create_meta_store(meta_root.clone(), storage_engine),
+        None,
+        users_config.is_some(),
+    )?];
+    if users_config.is_some() {
+        for user_id in detect_user_databases(&meta_root)?.unwrap_or_default() {
+            let path = meta_root.join(format!("user_{}", user_id));
+            let mut size = tree_sizes(&create_meta_store(path, storage_engine), Some(&user_id), false)?;
+            // only the shared database counts the blocks
+            size.block_bytes = 0;
+            sizes.push(size);
+        }
+    }
+    let size = MetadataSize::combine(&sizes);
+    let report = MetadataSizeReport {
+        trees: size.largest_first().into_iter().cloned().collect(),
+        metadata_bytes: size.metadata_bytes(),
+        data_bytes: size.data_bytes(),
+        ratio: size.ratio(),
+    };
+    if format == OutputFormat::Json {
+        return print_json(&report);
     }
 
+    println!("Metadata Trees:");
+    for tree in &report.trees {
+        println!("  {}: {} ({} bytes)", tree.name, format_bytes(tree.bytes), tree.bytes);
+    }
+    println!("\nTotal metadata: {}", format_bytes(report.metadata_bytes));
+    println!("Total data: {}", format_bytes(report.data_bytes));
+    println!("Metadata to data ratio: {:.4}", report.ratio);
     Ok(())
 }
 
+#[derive(Debug, Serialize)]
+pub struct BlobPartition {
+    #[serde(flatten)]
+    pub stats: BlobStats,
+    pub stale_ratio: f32,
+    pub space_amp: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlobStatsReport {
+    pub partitions: Vec<BlobPartition>,
+}
+
+/// Show the blob file utilization of the key-value separated bucket partitions
+pub fn blob_stats(
+    meta_root: PathBuf,
+    storage_engine: StorageEngine,
+    users_config: Option<PathBuf>,
+    format: OutputFormat,
+) -> Result<()> {
+    // Bucket partitions live in the per-user databases in multi-user mode
+    let stores = if users_config.is_some() {
+        detect_user_databases(&meta_root)?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|user_id| {
+                let path = meta_root.join(format!("user_{}", user_id));
+                (Some(user_id), create_meta_store(path, storage_engine))
+            })
+            .collect()
+    } else {
+        vec![(None, create_meta_store(meta_root, storage_engine))]
+    };
+
+    let mut report = BlobStatsReport { partitions: Vec::new() };
+    for (user_id, meta_store) in stores {
+        for mut stats in meta_store.blob_stats()? {
+            if let Some(user_id) = &user_id {
+                stats.tree = format!("{}/{}", user_id, stats.tree);
+            }
+            report.partitions.push(BlobPartition {
+                stale_ratio: stats.stale_ratio(),
+                space_amp: stats.space_amp(),
+                stats,
+            });
+        }
+    }
+    if format == OutputFormat::Json {
+        return print_json(&report);
+    }
+
+    for partition in &report.partitions {
+        let stats = &partition.stats;
+        println!("{}:", stats.tree);
+        println!("  Blob files: {} ({} stale)", stats.blob_files, stats.stale_blob_files);
+        println!(
+            "  Blob bytes: {} ({} stale, {:.1}%)",
+            format_bytes(stats.total_bytes),
+            format_bytes(stats.stale_bytes),
+            partition.stale_ratio * 100.0
+        );
+        println!("  Space amplification: {:.2}x", partition.space_amp);
+    }
+
+    if report.partitions.is_empty() {
+        println!("No key-value separated bucket partitions");
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObjectInfoReport {
+    pub bucket: String,
+    pub key: String,
+    pub size: u64,
+    /// `single`, `multipart` or `inline`
+    #[serde(rename = "type")]
+    pub object_type: &'static str,
+    pub hash: String,
+    pub etag: String,
+    pub created_at: u64,
+    /// Size of the data stored in the metadata, for inline objects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inline_bytes: Option<usize>,
+    /// All blocks of the object, in order
+    pub blocks: Vec<String>,
+    /// Amount of parts, for multipart objects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parts: Option<usize>,
+}
+
 /// Show detailed information about a specific object
 pub fn object_info(
     meta_root: PathBuf,
@@ -443,6 +785,7 @@ pub fn object_info(
     bucket: String,
     key: String,
     user_filter: Option<String>,
+    format: OutputFormat,
 ) -> Result<()> {
     let is_multi_user = users_config.is_some();
 
@@ -463,47 +806,684 @@ pub fn object_info(
         None => bail!("Object '{}' not found in bucket '{}'", key, bucket),
     };
 
-    println!("Object: {}/{}", bucket, key);
-    println!("Size: {} ({} bytes)", format_bytes(obj.size()), obj.size());
-    println!("Type: {:?}", obj.object_type());
-    println!("Hash: {}", hex::encode(obj.hash()));
+    let report = ObjectInfoReport {
+        bucket,
+        key,
+        size: obj.size(),
+        object_type: match obj.object_type() {
+            ObjectType::Single => "single",
+            ObjectType::Multipart => "multipart",
+            ObjectType::Inline => "inline",
+        },
+        hash: hex::encode(obj.hash()),
+        etag: obj.format_e_tag(),
+        created_at: obj
+            .last_modified()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        inline_bytes: obj.inlined().map(|data| data.len()),
+        blocks: obj.blocks().iter().map(hex::encode).collect(),
+        parts: match obj.data() {
+            ObjectData::MultiPart { parts, .. } => Some(*parts),
+            _ => None,
+        },
+    };
+    if format == OutputFormat::Json {
+        return print_json(&report);
+    }
 
-    let created_at = obj.last_modified();
-    let datetime = chrono::DateTime::<chrono::Utc>::from(created_at);
-    println!("Created: {}", datetime.format("%Y-%m-%d %H:%M:%S"));
+    println!("Object: {}/{}", report.bucket, report.key);
+    println!("Size: {} ({} bytes)", format_bytes(report.size), report.size);
+    println!("Type: {:?}", obj.object_type());
+    println!("Hash: {}", report.hash);
+    println!("ETag: {}", report.etag);
+    println!("Created: {}", format_timestamp(report.created_at));
 
     if obj.is_inlined() {
-        if let Some(data) = obj.inlined() {
-            println!("Inline data: {} bytes", data.len());
+        if let Some(inline_bytes) = report.inline_bytes {
+            println!("Inline data: {} bytes", inline_bytes);
         }
     } else {
-        let blocks = obj.blocks();
+        let blocks = &report.blocks;
         println!("Blocks: {}", blocks.len());
 
         if blocks.len() <= 10 {
             println!("\nBlock IDs:");
             for (i, block_id) in blocks.iter().enumerate() {
-                println!("  {}: {}", i + 1, hex::encode(block_id));
+                println!("  {}: {}", i + 1, block_id);
             }
         } else {
             println!("\nFirst 10 block IDs:");
             for (i, block_id) in blocks.iter().take(10).enumerate() {
-                println!("  {}: {}", i + 1, hex::encode(block_id));
+                println!("  {}: {}", i + 1, block_id);
             }
             println!("  ... ({} more blocks)", blocks.len() - 10);
         }
 
-        if let ObjectType::Multipart = obj.object_type() {
-            // Extract part count from ObjectData
-            if let ObjectData::MultiPart { parts, .. } = obj.data() {
-                println!("\nMultipart upload: {} parts", parts);
+        if let Some(parts) = report.parts {
+            println!("\nMultipart upload: {} parts", parts);
+        }
+    }
+
+    Ok(())
+}
+
+/// An object using a block
+#[derive(Debug, Serialize)]
+pub struct ObjectRef {
+    /// User owning the object, in multi-user mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(flatten)]
+    pub object: BlockRef,
+}
+
+impl ObjectRef {
+    fn print(&self, indent: &str) {
+        match &self.user {
+            Some(user_id) => println!("{}{}: {}/{}", indent, user_id, self.object.bucket, self.object.key),
+            None => println!("{}{}/{}", indent, self.object.bucket, self.object.key),
+        }
+    }
+}
+
+/// The objects of the stores using a block
+fn object_refs(stores: &[(Option<String>, MetaStore)], block_id: &BlockID) -> Result<Vec<ObjectRef>> {
+    let mut refs = Vec::new();
+    for (user_id, meta_store) in stores {
+        for object in find_block_refs(user_id.as_deref(), meta_store, block_id)? {
+            refs.push(ObjectRef {
+                user: user_id.clone(),
+                object,
+            });
+        }
+    }
+    Ok(refs)
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockRefsReport {
+    pub block: String,
+    /// Whether the block is in the block tree, size and refcount are `null` if not
+    pub found: bool,
+    pub size: Option<usize>,
+    pub refcount: Option<u64>,
+    pub objects: Vec<ObjectRef>,
+}
+
+/// List the objects using a block, with the block reference index if the store
+/// has a complete one, or else by scanning all objects
+pub fn block_refs(
+    meta_root: PathBuf,
+    storage_engine: StorageEngine,
+    users_config: Option<PathBuf>,
+    hash: String,
+    user_filter: Option<String>,
+    format: OutputFormat,
+) -> Result<()> {
+    let block_id: BlockID = match hex::decode(hash.trim()).map(BlockID::try_from) {
+        Ok(Ok(block_id)) => block_id,
+        _ => bail!("Invalid block hash '{}', expected {} hex encoded bytes", hash, BLOCKID_SIZE),
+    };
+
+    // Block storage is always in the shared database
+    let shared_store = create_meta_store(meta_root.clone(), storage_engine);
+    let block = shared_store.get_block_tree()?.get_block(&block_id)?;
+
+    let stores = object_stores(&meta_root, storage_engine, users_config, user_filter, shared_store)?;
+    let report = BlockRefsReport {
+        block: hex::encode(block_id),
+        found: block.is_some(),
+        size: block.as_ref().map(|block| block.size()),
+        refcount: block.as_ref().map(|block| block.rc()),
+        objects: object_refs(&stores, &block_id)?,
+    };
+    if format == OutputFormat::Json {
+        return print_json(&report);
+    }
+
+    match (report.size, report.refcount) {
+        (Some(size), Some(refcount)) => {
+            println!("Block: {}", report.block);
+            println!("Size: {} ({} bytes)", format_bytes(size as u64), size);
+            println!("Reference count: {}", refcount);
+        }
+        _ => println!("Block {} not found in the block tree", report.block),
+    }
+
+    println!("\nObjects:");
+    for object in &report.objects {
+        object.print("  ");
+    }
+    println!("Total objects: {}", report.objects.len());
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct CorruptBlockEntry {
+    pub block: String,
+    pub marked_at: u64,
+    /// When the block was healed, `null` while it is corrupt
+    pub healed_at: Option<u64>,
+    pub objects: Vec<ObjectRef>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CorruptBlocksReport {
+    pub blocks: Vec<CorruptBlockEntry>,
+    pub corrupt: usize,
+    pub healed: usize,
+}
+
+/// Inspect the blocks marked as corrupt, their healing status and the objects using them
+pub fn corrupt_blocks(
+    meta_root: PathBuf,
+    storage_engine: StorageEngine,
+    users_config: Option<PathBuf>,
+    user_filter: Option<String>,
+    format: OutputFormat,
+) -> Result<()> {
+    // Block metadata, and so the corrupt blocks, are always in the shared database
+    let shared_store = create_meta_store(meta_root.clone(), storage_engine);
+    let blocks = CorruptBlocks::new(shared_store.clone()).list()?;
+    let stores = if blocks.is_empty() {
+        Vec::new()
+    } else {
+        object_stores(&meta_root, storage_engine, users_config, user_filter, shared_store)?
+    };
+
+    let mut report = CorruptBlocksReport {
+        blocks: Vec::with_capacity(blocks.len()),
+        corrupt: 0,
+        healed: 0,
+    };
+    for (block_id, record) in &blocks {
+        if record.healed_at.is_some() {
+            report.healed += 1;
+        } else {
+            report.corrupt += 1;
+        }
+        report.blocks.push(CorruptBlockEntry {
+            block: hex::encode(block_id),
+            marked_at: record.marked_at,
+            healed_at: record.healed_at,
+            objects: object_refs(&stores, block_id)?,
+        });
+    }
+    if format == OutputFormat::Json {
+        return print_json(&report);
+    }
+
+    if report.blocks.is_empty() {
+        println!("No corrupt blocks");
+        return Ok(());
+    }
+
+    for block in &report.blocks {
+        println!("Block: {}", block.block);
+        println!("  Marked corrupt: {}", format_timestamp(block.marked_at));
+        match block.healed_at {
+            Some(healed_at) => println!("  Status: healed at {}", format_timestamp(healed_at)),
+            None => println!("  Status: corrupt"),
+        }
+        println!("  Objects:");
+        for object in &block.objects {
+            object.print("    ");
+        }
+    }
+    println!(
+        "\nTotal: {} block(s), {} corrupt, {} healed",
+        report.blocks.len(),
+        report.corrupt,
+        report.healed
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct DegradedObjectEntry {
+    /// User owning the object, in multi-user mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    pub bucket: String,
+    pub key: String,
+    pub marked_at: u64,
+    /// The corrupt blocks of the object which are not healed
+    pub blocks: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DegradedObjectsReport {
+    pub objects: Vec<DegradedObjectEntry>,
+}
+
+/// Inspect the objects marked as degraded by `check` or a scrub, which use corrupt
+/// blocks that are not healed. Objects healed, replaced or deleted since are
+/// dropped from the list.
+pub fn degraded_objects(
+    meta_root: PathBuf,
+    storage_engine: StorageEngine,
+    users_config: Option<PathBuf>,
+    user_filter: Option<String>,
+    bucket: Option<String>,
+    format: OutputFormat,
+) -> Result<()> {
+    // Block metadata, and so the corrupt blocks, are always in the shared database
+    let shared_store = create_meta_store(meta_root.clone(), storage_engine);
+    let corrupt_blocks = CorruptBlocks::new(shared_store.clone());
+    let stores = object_stores(
+        &meta_root,
+        storage_engine,
+        users_config,
+        user_filter,
+        shared_store,
+    )?;
+
+    let mut report = DegradedObjectsReport {
+        objects: Vec::new(),
+    };
+    for (user_id, meta_store) in &stores {
+        for object in DegradedObjects::new(meta_store).list(bucket.as_deref(), &corrupt_blocks)? {
+            report.objects.push(DegradedObjectEntry {
+                user: user_id.clone(),
+                bucket: object.bucket,
+                key: object.key,
+                marked_at: object.marked_at,
+                blocks: object.blocks.iter().map(hex::encode).collect(),
+            });
+        }
+    }
+    if format == OutputFormat::Json {
+        return print_json(&report);
+    }
+
+    if report.objects.is_empty() {
+        println!("No degraded objects");
+        return Ok(());
+    }
+
+    for object in &report.objects {
+        match &object.user {
+            Some(user_id) => println!("{}: {}/{}", user_id, object.bucket, object.key),
+            None => println!("{}/{}", object.bucket, object.key),
+        }
+        println!("  Marked degraded: {}", format_timestamp(object.marked_at));
+        for block in &object.blocks {
+            println!("  Corrupt block: {}", block);
+        }
+    }
+    println!("\nTotal: {} degraded object(s)", report.objects.len());
+
+    Ok(())
+}
+
+/// A block whose reference count doesn't match the references to it
+#[derive(Debug, Serialize)]
+pub struct RefcountMismatch {
+    pub block: String,
+    /// Reference count in the block tree, `null` if the block is missing
+    pub refcount: Option<u64>,
+    /// References found in the objects and multipart upload parts
+    pub references: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CrossCheckReport {
+    /// Metadata stores scanned for objects, one per user in multi-user mode
+    pub stores: usize,
+    pub objects: usize,
+    pub parts: usize,
+    /// Blocks in the block tree
+    pub blocks: usize,
+    /// References found to the blocks, every occurrence of a block in an object
+    /// or part counting once
+    pub references: u64,
+    /// Blocks used by objects or parts but missing from the block tree
+    pub missing: Vec<RefcountMismatch>,
+    /// Blocks with fewer references counted than found, deleting one of the
+    /// objects using them removes data still in use
+    pub undercounted: Vec<RefcountMismatch>,
+    /// Blocks with more references counted than found, their files are never
+    /// removed (leak candidates)
+    pub leaked: Vec<RefcountMismatch>,
+}
+
+impl CrossCheckReport {
+    /// Returns `true` if objects or parts use missing or undercounted blocks
+    pub fn is_broken(&self) -> bool {
+        !self.missing.is_empty() || !self.undercounted.is_empty()
+    }
+}
+
+/// Compare the references of the objects of `stores` and of the multipart upload
+/// parts with the reference counts in the block tree of `shared_store`.
+///
+/// The references found are kept in memory, one counter per used block.
+fn cross_check_stores(
+    shared_store: &MetaStore,
+    stores: &[(Option<String>, MetaStore)],
+) -> Result<CrossCheckReport> {
+    let mut references: HashMap<BlockID, u64> = HashMap::new();
+    let mut report = CrossCheckReport {
+        stores: stores.len(),
+        objects: 0,
+        parts: 0,
+        blocks: 0,
+        references: 0,
+        missing: Vec::new(),
+        undercounted: Vec::new(),
+        leaked: Vec::new(),
+    };
+
+    for (_, meta_store) in stores {
+        for bucket in meta_store.list_buckets()? {
+            let bucket_tree = meta_store.get_bucket_ext(bucket.name())?;
+            for (_, obj) in bucket_tree.range_filter(None, None, None) {
+                report.objects += 1;
+                for block_id in obj.blocks() {
+                    *references.entry(*block_id).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+    // the parts of unfinished multipart uploads hold references too, they are in
+    // the store of the blocks
+    for item in shared_store.get_bucket_ext(MULTIPART_TREE)?.iter_all() {
+        let (_, value) = item?;
+        let part = MultiPart::try_from(&value[..])
+            .map_err(|e| anyhow::anyhow!("Invalid multipart upload part: {}", e))?;
+        report.parts += 1;
+        for block_id in part.blocks() {
+            *references.entry(*block_id).or_insert(0) += 1;
+        }
+    }
+    report.references = references.values().sum();
+
+    for item in shared_store.get_block_tree()?.iter_all() {
+        let (block_id, block) = item?;
+        report.blocks += 1;
+        let found = references.remove(&block_id).unwrap_or(0);
+        let mismatch = RefcountMismatch {
+            block: hex::encode(block_id),
+            refcount: Some(block.rc()),
+            references: found,
+        };
+        match block.rc().cmp(&found) {
+            Ordering::Less => report.undercounted.push(mismatch),
+            Ordering::Greater => report.leaked.push(mismatch),
+            Ordering::Equal => {}
+        }
+    }
+    // the blocks left are not in the block tree
+    report.missing = references
+        .into_iter()
+        .map(|(block_id, found)| RefcountMismatch {
+            block: hex::encode(block_id),
+            refcount: None,
+            references: found,
+        })
+        .collect();
+    for list in [
+        &mut report.missing,
+        &mut report.undercounted,
+        &mut report.leaked,
+    ] {
+        list.sort_by(|a, b| a.block.cmp(&b.block));
+    }
+    Ok(report)
+}
+
+/// Verify that every block used by an object of any user, or by a multipart
+/// upload part, is in the shared block tree with a reference count covering its
+/// uses, and report the blocks counting more references than found. Fails if
+/// blocks are missing or undercounted.
+pub fn cross_check(
+    meta_root: PathBuf,
+    storage_engine: StorageEngine,
+    users_config: Option<PathBuf>,
+    format: OutputFormat,
+) -> Result<()> {
+    // Block storage is always in the shared database
+    let shared_store = create_meta_store(meta_root.clone(), storage_engine);
+    let stores = object_stores(
+        &meta_root,
+        storage_engine,
+        users_config,
+        None,
+        shared_store.clone(),
+    )?;
+    let report = cross_check_stores(&shared_store, &stores)?;
+
+    if format == OutputFormat::Json {
+        print_json(&report)?;
+    } else {
+        println!("Cross-check:");
+        println!("  Stores: {}", report.stores);
+        println!("  Objects: {}", report.objects);
+        println!("  Multipart upload parts: {}", report.parts);
+        println!("  Blocks: {}", report.blocks);
+        println!("  References found: {}", report.references);
+
+        let sections = [
+            ("Missing blocks", &report.missing),
+            ("Undercounted blocks", &report.undercounted),
+            ("Leak candidates", &report.leaked),
+        ];
+        for (title, list) in sections {
+            println!("\n{}: {}", title, list.len());
+            for mismatch in list.iter().take(20) {
+                match mismatch.refcount {
+                    Some(refcount) => println!(
+                        "  {} refcount={} references={}",
+                        mismatch.block, refcount, mismatch.references
+                    ),
+                    None => println!("  {} references={}", mismatch.block, mismatch.references),
+                }
+            }
+            if list.len() > 20 {
+                println!("  ... ({} more)", list.len() - 20);
             }
         }
+        if report.is_broken() {
+            println!("\nThe objects using a block are listed by `inspect block-refs <hash>`");
+        }
     }
 
+    if report.is_broken() {
+        bail!(
+            "{} missing and {} undercounted block(s)",
+            report.missing.len(),
+            report.undercounted.len()
+        );
+    }
     Ok(())
 }
 
+#[derive(Debug, Serialize)]
+pub struct AuditEntryInfo {
+    pub seq: u64,
+    pub time: u64,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub details: String,
+    pub hash: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditProblemInfo {
+    pub seq: u64,
+    pub problem: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditLogReport {
+    pub entries: u64,
+    /// Sequence number and hash of the last entry, to compare with a head
+    /// recorded earlier
+    pub head_seq: Option<u64>,
+    pub head_hash: Option<String>,
+    pub problems: Vec<AuditProblemInfo>,
+    /// The last entries, oldest first
+    pub last_entries: Vec<AuditEntryInfo>,
+}
+
+/// Verify the hash chain of the admin audit log and show its `last` entries.
+/// Fails if entries were modified, removed or inserted.
+pub fn audit_log(
+    meta_root: PathBuf,
+    storage_engine: StorageEngine,
+    users_config: Option<PathBuf>,
+    last: usize,
+    format: OutputFormat,
+) -> Result<()> {
+    if users_config.is_none() {
+        bail!("audit-log command requires multi-user mode (use --users-config)");
+    }
+
+    let shared_store = create_meta_store(meta_root, storage_engine);
+    let log = AuditLog::new(shared_store.get_underlying_store());
+    let verification = log.verify()?;
+    let entries = match log.entries() {
+        Ok(entries) => entries,
+        // an unreadable entry is reported as a problem
+        Err(_) if !verification.is_intact() => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let report = AuditLogReport {
+        entries: verification.entries,
+        head_seq: verification.head.map(|(seq, _)| seq),
+        head_hash: verification.head.map(|(_, hash)| hex::encode(hash)),
+        problems: verification
+            .problems
+            .iter()
+            .map(|(seq, problem)| AuditProblemInfo {
+                seq: *seq,
+                problem: problem.to_string(),
+            })
+            .collect(),
+        last_entries: entries[entries.len().saturating_sub(last)..]
+            .iter()
+            .map(|entry| AuditEntryInfo {
+                seq: entry.seq,
+                time: entry.time,
+                actor: entry.actor.clone(),
+                action: entry.action.clone(),
+                target: entry.target.clone(),
+                details: entry.details.clone(),
+                hash: hex::encode(entry.hash),
+            })
+            .collect(),
+    };
+
+    if format == OutputFormat::Json {
+        print_json(&report)?;
+    } else {
+        println!("Admin audit log:");
+        println!("  Entries: {}", report.entries);
+        if let (Some(seq), Some(hash)) = (report.head_seq, &report.head_hash) {
+            println!("  Head: {} {}", seq, hash);
+        }
+        if !report.last_entries.is_empty() {
+            println!(
+                "\n{:<8} {:<20} {:<16} {:<16} {:<16} {}",
+                "Seq", "Time", "Actor", "Action", "Target", "Details"
+            );
+            println!("{:-<100}", "");
+            for entry in &report.last_entries {
+                println!(
+                    "{:<8} {:<20} {:<16} {:<16} {:<16} {}",
+                    entry.seq,
+                    format_timestamp(entry.time),
+                    entry.actor,
+                    entry.action,
+                    entry.target,
+                    entry.details
+                );
+            }
+        }
+        if !report.problems.is_empty() {
+            println!("\nTampered entries: {}", report.problems.len());
+            for problem in &report.problems {
+                println!("  {}: {}", problem.seq, problem.problem);
+            }
+        }
+    }
+
+    if !report.problems.is_empty() {
+        bail!("{} tampered audit log entries", report.problems.len());
+    }
+    Ok(())
+}
+
+/// The stores holding the objects: the store of each user, or of `user_filter`, in
+/// multi-user mode, else the shared store.
+fn object_stores(
+    meta_root: &Path,
+    storage_engine: StorageEngine,
+    users_config: Option<PathBuf>,
+    user_filter: Option<String>,
+    shared_store: MetaStore,
+) -> Result<Vec<(Option<String>, MetaStore)>> {
+    if users_config.is_none() {
+        return Ok(vec![(None, shared_store)]);
+    }
+    let user_ids = match user_filter {
+        Some(user_id) => vec![user_id],
+        None => detect_user_databases(meta_root)?.unwrap_or_default(),
+    };
+    Ok(user_ids
+        .into_iter()
+        .map(|user_id| {
+            let user_meta_path = meta_root.join(format!("user_{}", user_id));
+            (Some(user_id), create_meta_store(user_meta_path, storage_engine))
+        })
+        .collect())
+}
+
+/// The objects of a store using a block, from the block reference index if it is
+/// complete, else by scanning all objects
+fn find_block_refs(
+    user_id: Option<&str>,
+    meta_store: &MetaStore,
+    block_id: &BlockID,
+) -> Result<Vec<BlockRef>> {
+    if meta_store.has_block_refs()? {
+        return Ok(meta_store.block_refs(block_id)?);
+    }
+    eprintln!(
+        "No block reference index{}, scanning all objects",
+        user_id.map(|id| format!(" for user {}", id)).unwrap_or_default()
+    );
+    scan_block_refs(meta_store, block_id)
+}
+
+/// The objects of a store using a block, found without the block reference index
+fn scan_block_refs(meta_store: &MetaStore, block_id: &BlockID) -> Result<Vec<BlockRef>> {
+    let mut refs = Vec::new();
+    for bucket in meta_store.list_buckets()? {
+        let bucket_tree = meta_store.get_bucket_ext(bucket.name())?;
+        for (key, obj) in bucket_tree.range_filter(None, None, None) {
+            if obj.has_block(block_id) {
+                refs.push(BlockRef {
+                    bucket: bucket.name().to_string(),
+                    key,
+                });
+            }
+        }
+    }
+    Ok(refs)
+}
+
+/// Format seconds since the UNIX epoch as a UTC date
+fn format_timestamp(secs: u64) -> String {
+    let datetime = chrono::DateTime::<chrono::Utc>::from(UNIX_EPOCH + std::time::Duration::from_secs(secs));
+    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
 /// Format bytes in human-readable format
 fn format_bytes(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
@@ -522,3 +1502,100 @@ fn format_bytes(bytes: u64) -> String {
 
     format!("{:.2} {}", size, UNITS[unit_index])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cas_storage::cas::multipart::part_key;
+    use cas_storage::{BucketMeta, MultiPartTree, Object};
+
+    fn store_object(meta_store: &MetaStore, key: &str, blocks: Vec<BlockID>) {
+        if !meta_store.bucket_exists("bucket").unwrap() {
+            let bucket = BucketMeta::new("bucket".to_string());
+            meta_store.insert_bucket("bucket", bucket.to_vec()).unwrap();
+        }
+        let obj = Object::new(0, [0; BLOCKID_SIZE], ObjectData::SinglePart { blocks });
+        meta_store.insert_meta("bucket", key, obj.to_vec()).unwrap();
+    }
+
+    fn store_block(shared_store: &MetaStore, block: BlockID, refcount: usize) {
+        let mut tx = shared_store.begin_transaction();
+        tx.write_block(block, 10, false, None).unwrap();
+        if refcount > 1 {
+            tx.add_block_references(&block, refcount - 1).unwrap();
+        }
+        tx.commit().unwrap();
+    }
+
+    #[test]
+    fn test_cross_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let meta_root = dir.path().join("meta");
+        let shared_store = create_meta_store(meta_root.clone(), StorageEngine::Fjall);
+        let alice = create_meta_store(meta_root.join("user_alice"), StorageEngine::Fjall);
+        let bob = create_meta_store(meta_root.join("user_bob"), StorageEngine::Fjall);
+
+        let (a, b, c, d) = (
+            [1; BLOCKID_SIZE],
+            [2; BLOCKID_SIZE],
+            [3; BLOCKID_SIZE],
+            [4; BLOCKID_SIZE],
+        );
+        // a is shared by the users and used twice by an object of alice
+        store_object(&alice, "x", vec![a, a, b]);
+        store_object(&bob, "y", vec![a]);
+        store_block(&shared_store, a, 3);
+        store_block(&shared_store, b, 1);
+        // c is used by a part of an unfinished multipart upload
+        MultiPartTree::new(shared_store.get_tree(MULTIPART_TREE).unwrap())
+            .insert(
+                &part_key("upload", 1),
+                MultiPart::new(
+                    10,
+                    1,
+                    "bucket".into(),
+                    "z".into(),
+                    "upload".into(),
+                    Default::default(),
+                    vec![c],
+                ),
+            )
+            .unwrap();
+        store_block(&shared_store, c, 1);
+
+        let stores = vec![
+            (Some("alice".to_string()), alice),
+            (Some("bob".to_string()), bob),
+        ];
+        let report = cross_check_stores(&shared_store, &stores).unwrap();
+        assert_eq!((report.stores, report.objects, report.parts), (2, 2, 1));
+        assert_eq!((report.blocks, report.references), (3, 5));
+        assert!(!report.is_broken());
+        assert!(report.leaked.is_empty());
+
+        // d is used by no object, b counts a reference too many
+        store_block(&shared_store, d, 1);
+        store_block(&shared_store, b, 1);
+        // a counts a reference too few and c is missing
+        shared_store.get_block_tree().unwrap().remove(&a).unwrap();
+        store_block(&shared_store, a, 2);
+        shared_store.get_block_tree().unwrap().remove(&c).unwrap();
+
+        let report = cross_check_stores(&shared_store, &stores).unwrap();
+        assert!(report.is_broken());
+        let summary = |list: &[RefcountMismatch]| -> Vec<(String, Option<u64>, u64)> {
+            list.iter()
+                .map(|m| (m.block.clone(), m.refcount, m.references))
+                .collect()
+        };
+        assert_eq!(summary(&report.missing), [(hex::encode(c), None, 1)]);
+        assert_eq!(
+            summary(&report.undercounted),
+            [(hex::encode(a), Some(2), 3)]
+        );
+        assert_eq!(
+            summary(&report.leaked),
+            [(hex::encode(b), Some(2), 1), (hex::encode(d), Some(1), 0)]
+        );
+    }
+}
@@ -0,0 +1,538 @@
+//! Long-running maintenance jobs, like blob garbage collection or scrubbing.
+//!
+//! Jobs run in the background with a record in the shared metadata store, which
+//! keeps their progress while they run and their outcome once they finished. A
+//! running job can be cancelled, it stops at the next unit of work. Jobs still
+//! recorded as running on startup were cut short by a restart and are marked as
+//! interrupted.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use cas_storage::{BlockID, CasFS, JobRecord, JobStatus, JobStore, MetaError};
+
+use crate::bandwidth::{Throttle, TrafficClass};
+
+/// How often the progress of a running job is stored
+const PERSIST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Finished jobs kept in the store, older ones are removed
+pub const MAX_FINISHED_JOBS: usize = 100;
+
+/// Kind of maintenance job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    /// Garbage collect the blob files of the key-value separated bucket partitions
+    BlobGc,
+    /// Verify every block file against its hash, marking mismatches as corrupt
+    Scrub,
+    /// Delete a bucket with all its objects
+    BucketDelete,
+    /// Delete the buckets of a user, then the user
+    UserDelete,
+    /// Move the objects below a key prefix of a bucket to another prefix
+    PrefixRename,
+    /// Export the data of a user to an archive, optionally deleting the user after
+    UserTakeout,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::BlobGc => "blob_gc",
+            JobKind::Scrub => "scrub",
+            JobKind::BucketDelete => "bucket_delete",
+            JobKind::UserDelete => "user_delete",
+            JobKind::PrefixRename => "prefix_rename",
+            JobKind::UserTakeout => "user_takeout",
+        }
+    }
+}
+
+impl FromStr for JobKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blob_gc" => Ok(JobKind::BlobGc),
+            "scrub" => Ok(JobKind::Scrub),
+            "bucket_delete" => Ok(JobKind::BucketDelete),
+            "user_delete" => Ok(JobKind::UserDelete),
+            "prefix_rename" => Ok(JobKind::PrefixRename),
+            "user_takeout" => Ok(JobKind::UserTakeout),
+            _ => Err(format!("Unknown job kind: {}", s)),
+        }
+    }
+}
+
+/// Error of the job manager
+#[derive(Debug)]
+pub enum JobError {
+    /// A job of the same kind and target is running, with this id
+    AlreadyRunning(u64),
+    /// The job is not running, it can't be cancelled
+    NotRunning(u64),
+    Store(MetaError),
+}
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobError::AlreadyRunning(id) => write!(f, "Job {} is already doing this", id),
+            JobError::NotRunning(id) => write!(f, "Job {} is not running", id),
+            JobError::Store(e) => write!(f, "Failed to access the job records: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+impl From<MetaError> for JobError {
+    fn from(e: MetaError) -> Self {
+        JobError::Store(e)
+    }
+}
+
+/// Returned by a job which stopped because it was cancelled
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Job cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Handle of a running job, to report its progress and see whether it is cancelled
+pub struct Job {
+    record: Mutex<JobRecord>,
+    saved_at: Mutex<Instant>,
+    cancelled: AtomicBool,
+    store: Arc<JobStore>,
+}
+
+impl Job {
+    pub fn id(&self) -> u64 {
+        self.record.lock().unwrap().id
+    }
+
+    /// Set the amount of units of work of the job, once it is known
+    pub fn set_total(&self, total: u64) {
+        self.record.lock().unwrap().total = total;
+        self.save(true);
+    }
+
+    /// Report `done` more units of work as done
+    pub fn advance(&self, done: u64) {
+        self.record.lock().unwrap().done += done;
+        self.save(false);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fails with [`Cancelled`] if the job was cancelled, called by jobs between
+    /// units of work
+    pub fn check_cancelled(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            return Err(Cancelled);
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> JobRecord {
+        self.record.lock().unwrap().clone()
+    }
+
+    // progress is stored at most every PERSIST_INTERVAL, unless `force`d
+    fn save(&self, force: bool) {
+        {
+            let mut saved_at = self.saved_at.lock().unwrap();
+            if !force && saved_at.elapsed() < PERSIST_INTERVAL {
+                return;
+            }
+            *saved_at = Instant::now();
+        }
+        let record = self.snapshot();
+        if let Err(e) = self.store.update(&record) {
+            tracing::warn!(job = record.id, error = %e, "Failed to store the job progress");
+        }
+    }
+
+    fn finish(&self, result: anyhow::Result<()>) -> JobRecord {
+        {
+            let mut record = self.record.lock().unwrap();
+            record.finished_at = Some(now_secs());
+            match result {
+                Ok(()) => {
+                    record.status = JobStatus::Completed;
+                    record.done = record.done.max(record.total);
+                }
+                Err(e) if e.is::<Cancelled>() => record.status = JobStatus::Cancelled,
+                Err(e) => {
+                    record.status = JobStatus::Failed;
+                    record.error = Some(format!("{:#}", e));
+                }
+            }
+        }
+        self.save(true);
+        self.snapshot()
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+type StoresFn = dyn Fn() -> anyhow::Result<Vec<Arc<CasFS>>> + Send + Sync;
+
+/// Starts the maintenance jobs and keeps track of the running ones
+pub struct JobManager {
+    store: Arc<JobStore>,
+    running: Mutex<HashMap<u64, Arc<Job>>>,
+    stores: Box<StoresFn>,
+    throttle: Option<Arc<Throttle>>,
+}
+
+impl JobManager {
+    /// A manager keeping its records in `store`, running store wide jobs on the
+    /// CasFS instances returned by `stores`: the one of the server in single-user
+    /// mode, those of all users in multi-user mode.
+    pub fn new<F>(store: JobStore, stores: F) -> Result<Self, JobError>
+    where
+        F: Fn() -> anyhow::Result<Vec<Arc<CasFS>>> + Send + Sync + 'static,
+    {
+        let interrupted = store.mark_interrupted()?;
+        if interrupted > 0 {
+            tracing::warn!(interrupted, "Jobs were interrupted by a restart");
+        }
+        Ok(Self {
+            store: Arc::new(store),
+            running: Mutex::new(HashMap::new()),
+            stores: Box::new(stores),
+            throttle: None,
+        })
+    }
+
+    /// Throttle the block reads of scrubs
+    pub fn with_throttle(mut self, throttle: Option<Arc<Throttle>>) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// All recorded jobs, newest first
+    pub fn list(&self) -> Result<Vec<JobRecord>, JobError> {
+        let running = self.running.lock().unwrap();
+        let mut jobs: Vec<JobRecord> = self
+            .store
+            .list()?
+            .into_iter()
+            .map(|record| match running.get(&record.id) {
+                // the stored progress of running jobs lags behind
+                Some(job) => job.snapshot(),
+                None => record,
+            })
+            .collect();
+        jobs.reverse();
+        Ok(jobs)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<JobRecord>, JobError> {
+        if let Some(job) = self.running.lock().unwrap().get(&id) {
+            return Ok(Some(job.snapshot()));
+        }
+        Ok(self.store.get(id)?)
+    }
+
+    /// Ask a running job to stop. It stops at its next unit of work, the returned
+    /// record is still running.
+    pub fn cancel(&self, id: u64) -> Result<JobRecord, JobError> {
+        match self.running.lock().unwrap().get(&id) {
+            Some(job) => {
+                job.cancelled.store(true, Ordering::Relaxed);
+                tracing::info!(job = id, "Job cancellation requested");
+                Ok(job.snapshot())
+            }
+            None => Err(JobError::NotRunning(id)),
+        }
+    }
+
+    /// Start a job running `run` in the background, unless a job of the same kind
+    /// and target is running
+    pub fn start<F, Fut>(
+        self: &Arc<Self>,
+        kind: JobKind,
+        target: Option<String>,
+        run: F,
+    ) -> Result<JobRecord, JobError>
+    where
+        F: FnOnce(Arc<Job>) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let job = {
+            // also serializes the creation of records, which picks the next id
+            let mut running = self.running.lock().unwrap();
+            if let Some(job) = running.values().find(|job| {
+                let record = job.record.lock().unwrap();
+                record.kind == kind.as_str() && record.target == target
+            }) {
+                return Err(JobError::AlreadyRunning(job.id()));
+            }
+            let record = self.store.create(kind.as_str(), target)?;
+            let job = Arc::new(Job {
+                record: Mutex::new(record),
+                saved_at: Mutex::new(Instant::now()),
+                cancelled: AtomicBool::new(false),
+                store: self.store.clone(),
+            });
+            running.insert(job.id(), job.clone());
+            job
+        };
+        let record = job.snapshot();
+        tracing::info!(job = record.id, kind = kind.as_str(), target = ?record.target, "Job started");
+
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let result = run(job.clone()).await;
+            let record = job.finish(result);
+            manager.running.lock().unwrap().remove(&record.id);
+            match record.status {
+                JobStatus::Failed => tracing::warn!(
+                    job = record.id,
+                    kind = %record.kind,
+                    error = record.error.as_deref().unwrap_or_default(),
+                    "Job failed"
+                ),
+                status => {
+                    tracing::info!(job = record.id, kind = %record.kind, ?status, "Job finished")
+                }
+            }
+            if let Err(e) = manager.store.prune(MAX_FINISHED_JOBS) {
+                tracing::warn!(error = %e, "Failed to remove old job records");
+            }
+        });
+        Ok(record)
+    }
+
+    /// Garbage collect the blob files of all stores
+    pub fn start_blob_gc(self: &Arc<Self>) -> Result<JobRecord, JobError> {
+        let manager = self.clone();
+        self.start(JobKind::BlobGc, None, |job| async move {
+            let stores = (manager.stores)()?;
+            job.set_total(stores.len() as u64);
+            let mut freed = 0;
+            for fs in stores {
+                job.check_cancelled()?;
+                // garbage collection rewrites blob files, keep it off the runtime threads
+                freed += tokio::task::spawn_blocking(move || fs.blob_gc()).await??;
+                job.advance(1);
+            }
+            tracing::debug!(freed, "Collected blob files");
+            Ok(())
+        })
+    }
+
+    /// Verify the files of all blocks against their hash, and mark the objects
+    /// using the corrupt blocks found as degraded
+    pub fn start_scrub(self: &Arc<Self>) -> Result<JobRecord, JobError> {
+        let manager = self.clone();
+        self.start(JobKind::Scrub, None, |job| async move {
+            let stores = (manager.stores)()?;
+            // the blocks are shared by all stores, any of them can check them
+            let Some(fs) = stores.first().cloned() else {
+                return Ok(());
+            };
+            let throttle = manager.throttle.clone();
+            let corrupt =
+                tokio::task::spawn_blocking(move || scrub(&fs, &job, throttle.as_deref()))
+                    .await??;
+            if corrupt.is_empty() {
+                return Ok(());
+            }
+            tokio::task::spawn_blocking(move || {
+                for fs in stores {
+                    let degraded = fs.mark_degraded_objects(&corrupt)?;
+                    if !degraded.is_empty() {
+                        tracing::warn!(
+                            objects = degraded.len(),
+                            "Scrub marked objects as degraded"
+                        );
+                    }
+                }
+                Ok::<_, anyhow::Error>(())
+            })
+            .await?
+        })
+    }
+
+    /// Delete the bucket `bucket` of `fs`, `target` names it in the job record
+    pub fn start_bucket_delete(
+        self: &Arc<Self>,
+        fs: Arc<CasFS>,
+        bucket: String,
+        target: String,
+    ) -> Result<JobRecord, JobError> {
+        self.start(JobKind::BucketDelete, Some(target), |job| async move {
+            job.set_total(1);
+            fs.bucket_delete(&bucket).await?;
+            job.advance(1);
+            Ok(())
+        })
+    }
+
+    /// Move the objects of `bucket` in `fs` whose key starts with `from` to the
+    /// prefix `to`, one transaction per batch. `target` names the bucket in the
+    /// job record. A cancelled or failed job leaves the objects moved so far
+    /// at their new key, the same job started again moves the others.
+    pub fn start_prefix_rename(
+        self: &Arc<Self>,
+        fs: Arc<CasFS>,
+        bucket: String,
+        from: String,
+        to: String,
+        target: String,
+    ) -> Result<JobRecord, JobError> {
+        let target = format!("{}/{} -> {}", target, from, to);
+        self.start(JobKind::PrefixRename, Some(target), |job| async move {
+            let total = {
+                let (fs, bucket, from) = (fs.clone(), bucket.clone(), from.clone());
+                tokio::task::spawn_blocking(move || fs.count_prefix(&bucket, &from)).await??
+            };
+            job.set_total(total);
+            loop {
+                job.check_cancelled()?;
+                let renamed = fs.rename_prefix_batch(&bucket, &from, &to).await?;
+                if renamed == 0 {
+                    return Ok(());
+                }
+                job.advance(renamed as u64);
+            }
+        })
+    }
+}
+
+/// Check every block file against the block id, which is the hash of its data, and
+/// mark the blocks failing the check as corrupt. Returns the blocks marked.
+fn scrub(fs: &CasFS, job: &Job, throttle: Option<&Throttle>) -> anyhow::Result<Vec<BlockID>> {
+    let blocks = fs.block_tree()?;
+    job.set_total(blocks.len()? as u64);
+    let content_hash = fs.content_hash();
+    let corrupt_blocks = fs.corrupt_blocks();
+    let mut corrupt = Vec::new();
+    for item in blocks.iter_all() {
+        job.check_cancelled()?;
+        let (id, block) = item?;
+        let valid = match std::fs::read(fs.block_disk_path(&block)?) {
+            Ok(data) => {
+                if let Some(throttle) = throttle {
+                    throttle.consume_blocking(TrafficClass::Scrub, data.len());
+                }
+                content_hash.digest(&data) == id
+            }
+            // the block was removed since the scan started
+            Err(e)
+                if e.kind() == std::io::ErrorKind::NotFound && blocks.get_block(&id)?.is_none() =>
+            {
+                true
+            }
+            Err(_) => false,
+        };
+        if !valid && !corrupt_blocks.is_corrupt(&id)? {
+            tracing::warn!(block = %hex::encode(id), "Scrub found a corrupt block");
+            corrupt_blocks.mark(&id)?;
+            corrupt.push(id);
+        }
+        job.advance(1);
+    }
+    if !corrupt.is_empty() {
+        tracing::warn!(corrupt = corrupt.len(), "Scrub marked blocks as corrupt");
+    }
+    Ok(corrupt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cas_storage::{FjallStore, MetaStore};
+
+    fn manager(dir: &std::path::Path) -> Arc<JobManager> {
+        let store = FjallStore::new(dir.to_path_buf(), Some(1), None);
+        let jobs = JobStore::new(MetaStore::new(store, Some(1)));
+        Arc::new(JobManager::new(jobs, || Ok(Vec::new())).unwrap())
+    }
+
+    async fn wait_finished(manager: &JobManager, id: u64) -> JobRecord {
+        loop {
+            let record = manager.get(id).unwrap().unwrap();
+            if record.status.is_finished() {
+                return record;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_job() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = manager(dir.path());
+
+        let record = manager
+            .start(JobKind::Scrub, None, |job| async move {
+                job.set_total(10);
+                job.advance(3);
+                while !job.is_cancelled() {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                job.check_cancelled()?;
+                Ok(())
+            })
+            .unwrap();
+        assert!(matches!(
+            manager.start(JobKind::Scrub, None, |_| async { Ok(()) }),
+            Err(JobError::AlreadyRunning(id)) if id == record.id
+        ));
+
+        manager.cancel(record.id).unwrap();
+        let record = wait_finished(&manager, record.id).await;
+        assert_eq!(record.status, JobStatus::Cancelled);
+        assert_eq!(record.percent(), Some(30));
+        assert!(matches!(
+            manager.cancel(record.id),
+            Err(JobError::NotRunning(_))
+        ));
+
+        let failed = manager
+            .start(JobKind::Scrub, None, |_| async {
+                anyhow::bail!("disk gone")
+            })
+            .unwrap();
+        let failed = wait_finished(&manager, failed.id).await;
+        assert_eq!(failed.status, JobStatus::Failed);
+        assert_eq!(failed.error.as_deref(), Some("disk gone"));
+        let ids: Vec<u64> = manager.list().unwrap().iter().map(|job| job.id).collect();
+        assert_eq!(ids, vec![failed.id, record.id]);
+    }
+
+    #[tokio::test]
+    async fn test_blob_gc_without_stores() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = manager(dir.path());
+        let record = manager.start_blob_gc().unwrap();
+        let record = wait_finished(&manager, record.id).await;
+        assert_eq!(record.status, JobStatus::Completed);
+        assert_eq!(record.percent(), Some(100));
+    }
+}
@@ -1,11 +1,42 @@
 #[macro_use]
 mod internal_macros;
 
+pub mod access_log;
+pub mod acl;
+pub mod admin_cli;
+pub mod alerting;
 pub mod auth;
+pub mod bandwidth;
+pub mod bench;
+pub mod bucket_logging;
+pub mod cdc_estimate;
 pub mod check;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod content_scan;
+pub mod http_cache;
 pub mod http_ui;
 pub mod inspect;
+pub mod jobs;
+pub mod lifecycle;
+pub mod listing;
+pub mod manifest;
 pub mod metrics;
+pub mod metrics_auth;
+pub mod migrate_metadata;
+pub mod network;
+pub mod notifications;
+pub mod placement;
+pub mod rebalance;
+pub mod replay;
+pub mod replica;
 pub mod retrieve;
 pub mod s3fs;
 pub mod s3_wrapper;
+pub mod seed;
+pub mod signing_scope;
+pub mod slow_log;
+pub mod tagging;
+pub mod tls;
+pub mod verify_replica;
+pub mod write_hooks;
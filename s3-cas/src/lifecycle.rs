@@ -0,0 +1,324 @@
+//! Bucket lifecycle support: conversion from and to the S3 lifecycle
+//! configuration, and the `x-amz-expiration` header of the objects a rule
+//! applies to.
+//!
+//! Only expiration rules are supported, transitions and the rules for versions
+//! and multipart uploads are rejected as not implemented.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use s3s::dto::{
+    BucketLifecycleConfiguration, ExpirationStatus, LifecycleExpiration,
+    LifecycleRule as S3LifecycleRule, LifecycleRuleAndOperator, LifecycleRuleFilter, Tag,
+    Timestamp, TimestampFormat,
+};
+use s3s::{s3_error, S3Result};
+
+use cas_storage::{BucketLifecycle, Expiration, LifecycleRule, ObjectExpiration, TagFilter};
+
+use crate::http_cache::http_date;
+
+/// Convert the configuration of a PutBucketLifecycleConfiguration request.
+pub fn lifecycle_from_configuration(
+    configuration: BucketLifecycleConfiguration,
+) -> S3Result<BucketLifecycle> {
+    let rules = configuration
+        .rules
+        .into_iter()
+        .map(rule_from_s3)
+        .collect::<S3Result<Vec<_>>>()?;
+    BucketLifecycle::new(rules).map_err(|e| s3_error!(InvalidArgument, "{}", e))
+}
+
+fn rule_from_s3(rule: S3LifecycleRule) -> S3Result<LifecycleRule> {
+    if rule.transitions.as_ref().is_some_and(|t| !t.is_empty())
+        || rule
+            .noncurrent_version_transitions
+            .as_ref()
+            .is_some_and(|t| !t.is_empty())
+    {
+        return Err(s3_error!(
+            NotImplemented,
+            "Lifecycle transitions are not supported"
+        ));
+    }
+    if rule.noncurrent_version_expiration.is_some()
+        || rule.abort_incomplete_multipart_upload.is_some()
+    {
+        return Err(s3_error!(
+            NotImplemented,
+            "Only the expiration of current objects is supported"
+        ));
+    }
+    let id = rule.id.unwrap_or_default();
+    let enabled = match rule.status.as_str() {
+        ExpirationStatus::ENABLED => true,
+        ExpirationStatus::DISABLED => false,
+        status => return Err(s3_error!(MalformedXML, "Invalid rule status '{}'", status)),
+    };
+
+    let (prefix, tags) = match rule.filter {
+        Some(filter) => filter_from_s3(filter)?,
+        // the deprecated prefix of the rule, outside of a filter
+        None => (rule.prefix.unwrap_or_default(), Vec::new()),
+    };
+
+    let Some(expiration) = rule.expiration else {
+        return Err(s3_error!(NotImplemented, "Rule '{}' has no expiration", id));
+    };
+    if expiration.expired_object_delete_marker.is_some() {
+        return Err(s3_error!(NotImplemented, "Buckets are not versioned"));
+    }
+    let expiration = match (expiration.days, expiration.date) {
+        (Some(days), None) => Expiration::Days(
+            u32::try_from(days)
+                .map_err(|_| s3_error!(InvalidArgument, "Days must be a positive integer"))?,
+        ),
+        (None, Some(date)) => Expiration::Date(epoch_seconds(&date)?),
+        _ => {
+            return Err(s3_error!(
+                MalformedXML,
+                "Rule '{}' needs either Days or Date in its expiration",
+                id
+            ))
+        }
+    };
+
+    Ok(LifecycleRule {
+        id,
+        enabled,
+        prefix,
+        tags,
+        expiration,
+    })
+}
+
+fn filter_from_s3(filter: LifecycleRuleFilter) -> S3Result<(String, Vec<TagFilter>)> {
+    let size_filter = filter.object_size_greater_than.is_some()
+        || filter.object_size_less_than.is_some()
+        || filter.and.as_ref().is_some_and(|and| {
+            and.object_size_greater_than.is_some() || and.object_size_less_than.is_some()
+        });
+    if size_filter {
+        return Err(s3_error!(
+            NotImplemented,
+            "Object size filters are not supported"
+        ));
+    }
+
+    let tag_filter =
+        |tag: Tag| TagFilter::new(tag.key.unwrap_or_default(), tag.value.unwrap_or_default());
+    match (filter.prefix, filter.tag, filter.and) {
+        (prefix, None, None) => Ok((prefix.unwrap_or_default(), Vec::new())),
+        (None, Some(tag), None) => Ok((String::new(), vec![tag_filter(tag)])),
+        (None, None, Some(and)) => Ok((
+            and.prefix.unwrap_or_default(),
+            and.tags.into_iter().flatten().map(tag_filter).collect(),
+        )),
+        _ => Err(s3_error!(
+            MalformedXML,
+            "A filter holds one of Prefix, Tag or And"
+        )),
+    }
+}
+
+/// The seconds since the UNIX epoch of a date of a lifecycle rule.
+fn epoch_seconds(date: &Timestamp) -> S3Result<i64> {
+    let mut buf = Vec::new();
+    date.format(TimestampFormat::EpochSeconds, &mut buf)
+        .map_err(|_| s3_error!(InvalidArgument, "Invalid expiration date"))?;
+    let formatted = String::from_utf8_lossy(&buf);
+    // whole seconds, the fraction is never set at midnight
+    let secs = formatted.split('.').next().unwrap_or_default();
+    secs.parse()
+        .map_err(|_| s3_error!(InvalidArgument, "Invalid expiration date"))
+}
+
+fn timestamp(secs: i64) -> Timestamp {
+    Timestamp::from(UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64))
+}
+
+/// The rules returned by GetBucketLifecycleConfiguration.
+pub fn lifecycle_rules(lifecycle: &BucketLifecycle) -> Vec<S3LifecycleRule> {
+    lifecycle
+        .rules()
+        .iter()
+        .map(|rule| {
+            let tag = |filter: &TagFilter| Tag {
+                key: Some(filter.key.clone()),
+                value: Some(filter.value.clone()),
+            };
+            let filter = match rule.tags.as_slice() {
+                [] => LifecycleRuleFilter {
+                    prefix: Some(rule.prefix.clone()),
+                    ..Default::default()
+                },
+                [filter] if rule.prefix.is_empty() => LifecycleRuleFilter {
+                    tag: Some(tag(filter)),
+                    ..Default::default()
+                },
+                tags => LifecycleRuleFilter {
+                    and: Some(LifecycleRuleAndOperator {
+                        prefix: (!rule.prefix.is_empty()).then(|| rule.prefix.clone()),
+                        tags: Some(tags.iter().map(tag).collect()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            };
+            let expiration = match rule.expiration {
+                Expiration::Days(days) => LifecycleExpiration {
+                    days: Some(days as i32),
+                    ..Default::default()
+                },
+                Expiration::Date(date) => LifecycleExpiration {
+                    date: Some(timestamp(date)),
+                    ..Default::default()
+                },
+            };
+            let status = if rule.enabled {
+                ExpirationStatus::ENABLED
+            } else {
+                ExpirationStatus::DISABLED
+            };
+            S3LifecycleRule {
+                id: Some(rule.id.clone()),
+                status: ExpirationStatus::from_static(status),
+                filter: Some(filter),
+                expiration: Some(expiration),
+                prefix: None,
+                transitions: None,
+                noncurrent_version_expiration: None,
+                noncurrent_version_transitions: None,
+                abort_incomplete_multipart_upload: None,
+            }
+        })
+        .collect()
+}
+
+/// The `x-amz-expiration` header of an object, e.g.
+/// `expiry-date="Fri, 23 Dec 2012 00:00:00 GMT", rule-id="logs"`.
+pub fn expiration_header(expiration: &ObjectExpiration) -> String {
+    let date = UNIX_EPOCH + Duration::from_secs(expiration.expiry_date.max(0) as u64);
+    format!(
+        "expiry-date=\"{}\", rule-id=\"{}\"",
+        http_date(date),
+        expiration.rule_id.replace('"', "\\\"")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s3_rule(id: &str, filter: LifecycleRuleFilter, days: i32) -> S3LifecycleRule {
+        S3LifecycleRule {
+            id: Some(id.to_string()),
+            status: ExpirationStatus::from_static(ExpirationStatus::ENABLED),
+            filter: Some(filter),
+            expiration: Some(LifecycleExpiration {
+                days: Some(days),
+                ..Default::default()
+            }),
+            prefix: None,
+            transitions: None,
+            noncurrent_version_expiration: None,
+            noncurrent_version_transitions: None,
+            abort_incomplete_multipart_upload: None,
+        }
+    }
+
+    #[test]
+    fn test_configuration_roundtrip() {
+        let tag = |key: &str, value: &str| Tag {
+            key: Some(key.to_string()),
+            value: Some(value.to_string()),
+        };
+        let rules = vec![
+            s3_rule(
+                "logs",
+                LifecycleRuleFilter {
+                    prefix: Some("logs/".to_string()),
+                    ..Default::default()
+                },
+                30,
+            ),
+            s3_rule(
+                "tmp",
+                LifecycleRuleFilter {
+                    tag: Some(tag("tmp", "true")),
+                    ..Default::default()
+                },
+                1,
+            ),
+            s3_rule(
+                "scratch",
+                LifecycleRuleFilter {
+                    and: Some(LifecycleRuleAndOperator {
+                        prefix: Some("scratch/".to_string()),
+                        tags: Some(vec![tag("tmp", "true"), tag("team", "ops")]),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                7,
+            ),
+        ];
+        let lifecycle =
+            lifecycle_from_configuration(BucketLifecycleConfiguration { rules }).unwrap();
+        assert_eq!(lifecycle.rules()[0].prefix, "logs/");
+        assert_eq!(lifecycle.rules()[1].tags, [TagFilter::new("tmp", "true")]);
+        assert_eq!(lifecycle.rules()[2].tags.len(), 2);
+
+        let converted = lifecycle_rules(&lifecycle);
+        assert_eq!(
+            lifecycle_from_configuration(BucketLifecycleConfiguration { rules: converted })
+                .unwrap(),
+            lifecycle
+        );
+    }
+
+    #[test]
+    fn test_expiration_date() {
+        // 2014-01-19 00:00:00 UTC
+        let mut rule = s3_rule("r", LifecycleRuleFilter::default(), 1);
+        rule.expiration = Some(LifecycleExpiration {
+            date: Some(timestamp(1_390_089_600)),
+            ..Default::default()
+        });
+        let lifecycle =
+            lifecycle_from_configuration(BucketLifecycleConfiguration { rules: vec![rule] })
+                .unwrap();
+        assert_eq!(
+            lifecycle.rules()[0].expiration,
+            Expiration::Date(1_390_089_600)
+        );
+    }
+
+    #[test]
+    fn test_unsupported_rules() {
+        let mut rule = s3_rule("r", LifecycleRuleFilter::default(), 1);
+        rule.abort_incomplete_multipart_upload = Some(Default::default());
+        assert!(
+            lifecycle_from_configuration(BucketLifecycleConfiguration { rules: vec![rule] })
+                .is_err()
+        );
+        let rule = s3_rule("r", LifecycleRuleFilter::default(), 0);
+        assert!(
+            lifecycle_from_configuration(BucketLifecycleConfiguration { rules: vec![rule] })
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_expiration_header() {
+        let expiration = ObjectExpiration {
+            rule_id: "logs".to_string(),
+            expiry_date: 1_390_089_600,
+        };
+        assert_eq!(
+            expiration_header(&expiration),
+            "expiry-date=\"Sun, 19 Jan 2014 00:00:00 GMT\", rule-id=\"logs\""
+        );
+    }
+}
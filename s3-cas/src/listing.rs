@@ -0,0 +1,682 @@
+//! Encoding of keys in ListObjects and ListObjectsV2 responses, the grouping of
+//! keys into common prefixes, with the directories of the prefix counts at the
+//! top level of a bucket, and the limits of listings.
+
+use std::sync::Arc;
+
+use faster_hex::{hex_decode, hex_string};
+use hmac::{Hmac, Mac};
+use s3s::dto::EncodingType;
+use s3s::{s3_error, S3Result};
+use sha2::Sha256;
+use tracing::warn;
+
+/// Most keys of a listing page by default, the limit of S3
+pub const DEFAULT_MAX_KEYS: i32 = 1000;
+
+/// Keys after which a listing is deep, and logged, by default
+pub const DEFAULT_DEEP_LISTING_KEYS: u64 = 1_000_000;
+
+/// Size of the signature of a continuation token, HMAC-SHA256
+const TOKEN_SIGNATURE_LEN: usize = 32;
+
+/// How keys, prefixes and markers are written in a listing response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEncoding {
+    /// As is, XML escaped by the serializer
+    Plain,
+    /// URL encoded, as requested with `encoding-type=url`
+    Url,
+}
+
+impl KeyEncoding {
+    /// The encoding requested with `encoding-type`, `url` is the only valid value.
+    pub fn from_request(encoding_type: Option<&EncodingType>) -> S3Result<Self> {
+        match encoding_type.map(|e| e.as_str()) {
+            None => Ok(KeyEncoding::Plain),
+            Some(e) if e.eq_ignore_ascii_case(EncodingType::URL) => Ok(KeyEncoding::Url),
+            Some(_) => Err(s3_error!(
+                InvalidArgument,
+                "Invalid Encoding Method specified in Request"
+            )),
+        }
+    }
+
+    /// Switch to URL encoding if one of `values` can't be represented in XML 1.0.
+    ///
+    /// Control characters can't be escaped in XML 1.0 and break the XML parsers
+    /// of clients. The response then reports `EncodingType` url, so clients
+    /// decode the keys again.
+    pub fn for_values<'a>(self, values: impl IntoIterator<Item = &'a str>) -> Self {
+        match self {
+            KeyEncoding::Url => KeyEncoding::Url,
+            KeyEncoding::Plain if values.into_iter().all(is_xml_safe) => KeyEncoding::Plain,
+            KeyEncoding::Plain => KeyEncoding::Url,
+        }
+    }
+
+    pub fn encode(self, value: String) -> String {
+        match self {
+            KeyEncoding::Plain => value,
+            // like S3, the path separator is kept readable
+            KeyEncoding::Url => urlencoding::encode(&value).replace("%2F", "/"),
+        }
+    }
+
+    pub fn encode_opt(self, value: Option<String>) -> Option<String> {
+        value.map(|value| self.encode(value))
+    }
+
+    /// The `EncodingType` of the response.
+    pub fn encoding_type(self) -> Option<EncodingType> {
+        match self {
+            KeyEncoding::Plain => None,
+            KeyEncoding::Url => Some(EncodingType::from_static(EncodingType::URL)),
+        }
+    }
+}
+
+/// Returns `true` if `value` can be written in an XML 1.0 document and is read
+/// back unchanged. Carriage returns are valid, but parsers normalize them to
+/// line feeds.
+pub fn is_xml_safe(value: &str) -> bool {
+    value.chars().all(|c| {
+        matches!(c,
+            '\t' | '\n'
+            | '\u{20}'..='\u{D7FF}'
+            | '\u{E000}'..='\u{FFFD}'
+            | '\u{10000}'..='\u{10FFFF}')
+    })
+}
+
+/// An entry of a listing page, both count towards `max-keys`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListEntry<T> {
+    /// An object and its key
+    Object(String, T),
+    /// The keys which contain the delimiter after the prefix, rolled up to the
+    /// prefix up to and including the first delimiter
+    CommonPrefix(String),
+}
+
+impl<T> ListEntry<T> {
+    /// The key of an object or the common prefix, which is where the next page of
+    /// the listing starts after.
+    pub fn key(&self) -> &str {
+        match self {
+            ListEntry::Object(key, _) => key,
+            ListEntry::CommonPrefix(prefix) => prefix,
+        }
+    }
+}
+
+/// Group the keys of `entries`, which must be sorted and all start with `prefix`,
+/// by `delimiter` like S3 does.
+///
+/// A key containing the delimiter after the prefix is rolled up into a single
+/// common prefix with all other keys sharing it. An empty delimiter is ignored.
+/// Common prefixes which don't sort after `after`, the marker or continuation
+/// token the listing continues from, were returned by an earlier page and are
+/// skipped, together with their keys.
+pub fn group_by_delimiter<'a, T: 'a>(
+    entries: impl Iterator<Item = (String, T)> + 'a,
+    prefix: &'a str,
+    delimiter: Option<&'a str>,
+    after: Option<&'a str>,
+) -> impl Iterator<Item = ListEntry<T>> + 'a {
+    let delimiter = delimiter.filter(|delimiter| !delimiter.is_empty());
+    let mut last_prefix: Option<String> = None;
+    entries.filter_map(move |(key, value)| {
+        let common_prefix = delimiter.and_then(|delimiter| {
+            let rest = key.get(prefix.len()..)?;
+            let end = prefix.len() + rest.find(delimiter)? + delimiter.len();
+            Some(&key[..end])
+        });
+        match common_prefix {
+            None => Some(ListEntry::Object(key, value)),
+            Some(common_prefix) => {
+                if last_prefix.as_deref() == Some(common_prefix)
+                    || after.map_or(false, |after| common_prefix <= after)
+                {
+                    return None;
+                }
+                last_prefix = Some(common_prefix.to_string());
+                Some(ListEntry::CommonPrefix(common_prefix.to_string()))
+            }
+        }
+    })
+}
+
+/// Returns `true` if the entries of a listing of `prefix` grouped by `delimiter`
+/// can be found with [`group_by_top_level`]: the common prefixes are then the
+/// directories at the top level of the bucket, which have prefix counts.
+pub fn is_top_level(prefix: &str, delimiter: Option<&str>) -> bool {
+    delimiter == Some("/") && !prefix.contains('/')
+}
+
+/// Like [`group_by_delimiter`] with the `/` delimiter, for a `prefix` at the top
+/// level of a bucket, with the `directories` of the bucket known from its prefix
+/// counts, in key order.
+///
+/// The keys under a directory are skipped with a new `scan`, which returns the
+/// keys starting with `prefix` after the key it is given, so a directory costs
+/// a seek rather than a scan of its keys. Only the objects between the
+/// directories are read. Keys under a directory missing from `directories`,
+/// written after the counts were read, are still grouped by scanning them.
+pub fn group_by_top_level<'a, T: 'a>(
+    mut scan: impl FnMut(Option<String>) -> Box<dyn Iterator<Item = (String, T)> + 'a> + 'a,
+    prefix: &'a str,
+    directories: Vec<String>,
+    after: Option<&'a str>,
+) -> impl Iterator<Item = ListEntry<T>> + 'a {
+    let mut directories: Vec<String> = directories
+        .into_iter()
+        .filter(|dir| dir.starts_with(prefix) && after.map_or(true, |after| dir.as_str() > after))
+        .collect();
+    directories.dedup();
+
+    // a listing continuing after a key under a directory continues after the
+    // directory
+    let mut skipped = after.and_then(|after| {
+        let slash = after.get(prefix.len()..)?.find('/')?;
+        Some(after[..prefix.len() + slash + 1].to_string())
+    });
+    let mut start = match &skipped {
+        Some(dir) => Some(format!("{dir}{}", char::MAX)),
+        None => after.map(str::to_string),
+    };
+
+    directories
+        .into_iter()
+        .map(Some)
+        .chain(std::iter::once(None))
+        .flat_map(move |dir| {
+            let skip = skipped.take();
+            let keys = scan(start.take()).skip_while(move |(key, _)| {
+                skip.as_ref().map_or(false, |dir| key.starts_with(dir))
+            });
+            let objects: Box<dyn Iterator<Item = (String, T)> + 'a> = match &dir {
+                Some(dir) => {
+                    let end = dir.clone();
+                    Box::new(keys.take_while(move |(key, _)| *key < end))
+                }
+                None => Box::new(keys),
+            };
+            if let Some(dir) = &dir {
+                // no key below the directory sorts after its prefix followed by
+                // the highest character, but for keys starting with that
+                start = Some(format!("{dir}{}", char::MAX));
+                skipped = Some(dir.clone());
+            }
+            group_by_delimiter(objects, prefix, Some("/"), after)
+                .chain(dir.map(ListEntry::CommonPrefix))
+        })
+}
+
+/// Where a ListObjectsV2 listing continues, carried in its continuation token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContinuationToken {
+    /// The snapshot the listing is served from, with snapshot listings
+    pub snapshot_id: Option<u64>,
+    /// The keys and common prefixes returned by the earlier pages
+    pub listed: u64,
+    /// The last key or common prefix of the previous page
+    pub last_key: String,
+}
+
+impl ContinuationToken {
+    // `\x01<listed>\0<snapshot id>\0<last key>`, the snapshot id is empty without one.
+    // Tokens of older versions are `\0<snapshot id>\0<last key>` or the last key.
+    fn to_bytes(&self) -> Vec<u8> {
+        let snapshot_id = self.snapshot_id.map(|id| id.to_string()).unwrap_or_default();
+        format!("\x01{}\0{}\0{}", self.listed, snapshot_id, self.last_key).into_bytes()
+    }
+
+    fn from_bytes(data: Vec<u8>) -> Option<Self> {
+        let token = String::from_utf8(data).ok()?;
+        if let Some(rest) = token.strip_prefix('\x01') {
+            let mut fields = rest.splitn(3, '\0');
+            let listed = fields.next()?.parse().ok()?;
+            let snapshot_id = match fields.next()? {
+                "" => None,
+                id => Some(id.parse().ok()?),
+            };
+            return Some(Self {
+                snapshot_id,
+                listed,
+                last_key: fields.next()?.to_string(),
+            });
+        }
+        if let Some(rest) = token.strip_prefix('\0') {
+            let (id, key) = rest.split_once('\0')?;
+            return Some(Self {
+                snapshot_id: Some(id.parse().ok()?),
+                listed: 0,
+                last_key: key.to_string(),
+            });
+        }
+        Some(Self {
+            snapshot_id: None,
+            listed: 0,
+            last_key: token,
+        })
+    }
+}
+
+/// Limits of the listings of a server: the size of a page, and how deep a
+/// listing may page.
+///
+/// Clients which page through millions of keys keep the metadata store busy for a
+/// long time. Listings deeper than [`ListLimits::deep_keys`] are logged, and
+/// refused past [`ListLimits::max_depth`]. The depth is counted in the
+/// continuation tokens of ListObjectsV2, which are signed with a secret to keep
+/// clients from resetting it, or using a token of another bucket. ListObjects (v1)
+/// continues from a marker key, so its depth isn't known.
+#[derive(Debug, Clone)]
+pub struct ListLimits {
+    /// Most keys and common prefixes of a page, whatever `max-keys` asks for
+    pub max_keys: i32,
+    /// Listings which returned this many keys are logged
+    pub deep_keys: u64,
+    /// Listings which returned this many keys are refused, 0 for no limit
+    pub max_depth: u64,
+    /// Key of the signatures of the continuation tokens, unsigned without one
+    token_secret: Option<Arc<Vec<u8>>>,
+}
+
+impl Default for ListLimits {
+    fn default() -> Self {
+        Self {
+            max_keys: DEFAULT_MAX_KEYS,
+            deep_keys: DEFAULT_DEEP_LISTING_KEYS,
+            max_depth: 0,
+            token_secret: None,
+        }
+    }
+}
+
+impl ListLimits {
+    /// Sign the continuation tokens with `secret`. Instances behind a load
+    /// balancer need the same secret. Unsigned tokens are refused then.
+    pub fn with_token_secret(mut self, secret: &[u8]) -> Self {
+        self.token_secret = Some(Arc::new(secret.to_vec()));
+        self
+    }
+
+    /// The size of a page for the `max-keys` of a request
+    pub fn page_size(&self, max_keys: Option<i32>) -> i32 {
+        max_keys.unwrap_or(self.max_keys).clamp(0, self.max_keys)
+    }
+
+    /// Checks the depth of a listing of `bucket` before a page is returned,
+    /// `listed` keys were returned by its earlier pages.
+    pub fn check_depth(&self, bucket: &str, listed: u64, page_size: i32) -> S3Result<()> {
+        if self.max_depth > 0 && listed >= self.max_depth {
+            warn!(
+                bucket,
+                listed, "Refused to continue a listing past the maximum depth"
+            );
+            return Err(s3_error!(
+                InvalidArgument,
+                "The listing is too deep, list a narrower prefix"
+            ));
+        }
+        // logged once, on the page crossing the threshold
+        if self.deep_keys > 0
+            && listed >= self.deep_keys
+            && listed - self.deep_keys < page_size.max(1) as u64
+        {
+            warn!(bucket, listed, "Deep listing, the client pages through many keys");
+        }
+        Ok(())
+    }
+
+    fn signature(&self, secret: &[u8], owner: &str, bucket: &str, token: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+        for part in [owner.as_bytes(), bucket.as_bytes(), token] {
+            mac.update(&(part.len() as u64).to_le_bytes());
+            mac.update(part);
+        }
+        mac
+    }
+
+    /// The continuation token of a listing of `bucket` of `owner`
+    pub fn encode_token(&self, owner: &str, bucket: &str, token: &ContinuationToken) -> String {
+        let mut data = token.to_bytes();
+        if let Some(secret) = &self.token_secret {
+            let signature = self.signature(secret, owner, bucket, &data).finalize();
+            data.extend_from_slice(&signature.into_bytes());
+        }
+        hex_string(&data)
+    }
+
+    /// Decodes a continuation token made by [`ListLimits::encode_token`] for the
+    /// same bucket and owner
+    pub fn decode_token(&self, owner: &str, bucket: &str, token: &str) -> S3Result<ContinuationToken> {
+        let mut data = vec![0; token.len() / 2];
+        if token.len() % 2 != 0 || hex_decode(token.as_bytes(), &mut data).is_err() {
+            return Err(s3_error!(
+                InvalidToken,
+                "continuation token has an invalid format"
+            ));
+        }
+        if let Some(secret) = &self.token_secret {
+            if data.len() < TOKEN_SIGNATURE_LEN {
+                return Err(s3_error!(InvalidToken, "continuation token is invalid"));
+            }
+            let signature = data.split_off(data.len() - TOKEN_SIGNATURE_LEN);
+            if self
+                .signature(secret, owner, bucket, &data)
+                .verify_slice(&signature)
+                .is_err()
+            {
+                return Err(s3_error!(InvalidToken, "continuation token is invalid"));
+            }
+        }
+        ContinuationToken::from_bytes(data)
+            .ok_or_else(|| s3_error!(InvalidToken, "continuation token is invalid"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(
+        keys: &[&str],
+        prefix: &str,
+        delimiter: Option<&str>,
+        after: Option<&str>,
+    ) -> Vec<String> {
+        let mut keys: Vec<String> = keys
+            .iter()
+            .filter(|key| key.starts_with(prefix) && after.map_or(true, |after| **key > after))
+            .map(|key| key.to_string())
+            .collect();
+        keys.sort();
+        group_by_delimiter(
+            keys.into_iter().map(|key| (key, ())),
+            prefix,
+            delimiter,
+            after,
+        )
+        .map(|entry| match entry {
+            ListEntry::Object(key, _) => key,
+            ListEntry::CommonPrefix(prefix) => format!("{prefix}*"),
+        })
+        .collect()
+    }
+
+    // Lists all keys one entry per page, continuing after the previous entry
+    fn list_paged(keys: &[&str], prefix: &str, delimiter: Option<&str>) -> Vec<String> {
+        let mut entries = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let page = list(keys, prefix, delimiter, after.as_deref());
+            let Some(first) = page.into_iter().next() else {
+                return entries;
+            };
+            after = Some(first.trim_end_matches('*').to_string());
+            entries.push(first);
+        }
+    }
+
+    const TRICKY_KEYS: &[&str] = &[
+        "a",
+        "a/",
+        "a/b",
+        "a/b/",
+        "a/b/c",
+        "a//b",
+        "a-b",
+        "a0",
+        "b/ü",
+        "b/ünïcode/x",
+        "b/z",
+        "é/x",
+        "\u{1F600}/x",
+        "z/",
+    ];
+
+    #[test]
+    fn test_group_by_delimiter() {
+        assert_eq!(
+            list(TRICKY_KEYS, "", Some("/"), None),
+            vec!["a", "a-b", "a/*", "a0", "b/*", "z/*", "é/*", "\u{1F600}/*"]
+        );
+        // a key equal to the prefix is an object
+        assert_eq!(
+            list(TRICKY_KEYS, "a/", Some("/"), None),
+            vec!["a/", "a//*", "a/b", "a/b/*"]
+        );
+        assert_eq!(
+            list(TRICKY_KEYS, "a", Some("/"), None),
+            vec!["a", "a-b", "a/*", "a0"]
+        );
+        assert_eq!(
+            list(TRICKY_KEYS, "b/", Some("/"), None),
+            vec!["b/z", "b/ü", "b/ünïcode/*"]
+        );
+        // multi character delimiter
+        assert_eq!(
+            list(TRICKY_KEYS, "", Some("/b"), None)[..5],
+            ["a", "a-b", "a/", "a//b*", "a/b*"]
+        );
+        // without or with an empty delimiter all keys are listed
+        assert_eq!(list(TRICKY_KEYS, "", None, None).len(), TRICKY_KEYS.len());
+        assert_eq!(
+            list(TRICKY_KEYS, "", Some(""), None).len(),
+            TRICKY_KEYS.len()
+        );
+        // a page starting after a common prefix skips its keys
+        assert_eq!(
+            list(TRICKY_KEYS, "", Some("/"), Some("a/"))[..2],
+            ["a0", "b/*"]
+        );
+    }
+
+    #[test]
+    fn test_listing_order_and_pages() {
+        for prefix in ["", "a", "a/", "a/b", "b/", "é", "z/"] {
+            for delimiter in [None, Some("/"), Some("b"), Some("//")] {
+                let entries = list(TRICKY_KEYS, prefix, delimiter, None);
+                // entries are sorted by their key, or common prefix, in byte order
+                let keys: Vec<&str> = entries.iter().map(|e| e.trim_end_matches('*')).collect();
+                let mut sorted = keys.clone();
+                sorted.sort();
+                sorted.dedup();
+                assert_eq!(keys, sorted, "prefix {prefix:?} delimiter {delimiter:?}");
+                // listing page by page returns the same entries
+                assert_eq!(
+                    list_paged(TRICKY_KEYS, prefix, delimiter),
+                    entries,
+                    "prefix {prefix:?} delimiter {delimiter:?}"
+                );
+                // every key is either listed or rolled up into a listed prefix
+                for key in TRICKY_KEYS.iter().filter(|key| key.starts_with(prefix)) {
+                    assert!(
+                        entries.iter().any(|e| match e.strip_suffix('*') {
+                            Some(common_prefix) => key.starts_with(common_prefix),
+                            None => e == key,
+                        }),
+                        "{key} is missing with prefix {prefix:?} delimiter {delimiter:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    // The keys of `keys` starting with `prefix` after `start`, like a range scan
+    fn scan<'a>(
+        keys: &'a [String],
+        prefix: &'a str,
+        start: Option<String>,
+    ) -> Box<dyn Iterator<Item = (String, ())> + 'a> {
+        Box::new(
+            keys.iter()
+                .filter(move |key| {
+                    key.starts_with(prefix) && start.as_ref().map_or(true, |start| *key > start)
+                })
+                .map(|key| (key.clone(), ())),
+        )
+    }
+
+    #[test]
+    fn test_group_by_top_level() {
+        let mut keys: Vec<String> = TRICKY_KEYS.iter().map(|key| key.to_string()).collect();
+        keys.push(format!("a/{}x", char::MAX));
+        keys.sort();
+        let mut directories: Vec<String> = keys
+            .iter()
+            .filter_map(|key| Some(key[..=key.find('/')?].to_string()))
+            .collect();
+        directories.dedup();
+
+        for prefix in ["", "a", "b", "é", "z"] {
+            let expected: Vec<ListEntry<()>> =
+                group_by_delimiter(scan(&keys, prefix, None), prefix, Some("/"), None).collect();
+            // every page, continuing after every entry
+            for (i, entry) in expected.iter().enumerate() {
+                for (after, from) in [(None, 0), (Some(entry.key()), i + 1)] {
+                    let entries: Vec<_> = group_by_top_level(
+                        |start| scan(&keys, prefix, start),
+                        prefix,
+                        directories.clone(),
+                        after,
+                    )
+                    .collect();
+                    assert_eq!(
+                        entries,
+                        expected[from..],
+                        "prefix {prefix:?} after {after:?}"
+                    );
+                }
+            }
+        }
+
+        // a directory missing from the counts is found by scanning its keys
+        let keys: Vec<String> = ["a", "b/1", "b/2", "c"].map(String::from).to_vec();
+        let entries: Vec<_> =
+            group_by_top_level(|start| scan(&keys, "", start), "", Vec::new(), None).collect();
+        assert_eq!(
+            entries,
+            vec![
+                ListEntry::Object("a".to_string(), ()),
+                ListEntry::CommonPrefix("b/".to_string()),
+                ListEntry::Object("c".to_string(), ()),
+            ]
+        );
+
+        assert!(is_top_level("", Some("/")));
+        assert!(is_top_level("logs", Some("/")));
+        assert!(!is_top_level("logs/", Some("/")));
+        assert!(!is_top_level("", None));
+    }
+
+    #[test]
+    fn test_from_request() {
+        let url = EncodingType::from_static(EncodingType::URL);
+        assert_eq!(KeyEncoding::from_request(None).unwrap(), KeyEncoding::Plain);
+        assert_eq!(KeyEncoding::from_request(Some(&url)).unwrap(), KeyEncoding::Url);
+        let invalid = EncodingType::from("base64".to_string());
+        assert!(KeyEncoding::from_request(Some(&invalid)).is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            KeyEncoding::Url.encode("dir/a b+c&d\u{1}.txt".to_string()),
+            "dir/a%20b%2Bc%26d%01.txt"
+        );
+        assert_eq!(KeyEncoding::Plain.encode("a b".to_string()), "a b");
+    }
+
+    #[test]
+    fn test_control_characters_switch_to_url() {
+        assert!(is_xml_safe("dir/ünïcode\ttab\nnewline"));
+        assert!(!is_xml_safe("bell\u{7}"));
+        assert!(!is_xml_safe("cr\r"));
+
+        let plain = KeyEncoding::Plain;
+        assert_eq!(plain.for_values(["a", "b/c"]), KeyEncoding::Plain);
+        assert_eq!(plain.for_values(["a", "b\u{1}"]), KeyEncoding::Url);
+        assert_eq!(KeyEncoding::Url.for_values(["a"]), KeyEncoding::Url);
+        assert_eq!(KeyEncoding::Url.encoding_type().unwrap().as_str(), "url");
+        assert!(plain.encoding_type().is_none());
+    }
+
+    #[test]
+    fn test_page_size() {
+        let limits = ListLimits {
+            max_keys: 100,
+            ..Default::default()
+        };
+        assert_eq!(limits.page_size(None), 100);
+        assert_eq!(limits.page_size(Some(10)), 10);
+        assert_eq!(limits.page_size(Some(5000)), 100);
+        assert_eq!(limits.page_size(Some(-1)), 0);
+    }
+
+    #[test]
+    fn test_check_depth() {
+        let limits = ListLimits {
+            deep_keys: 10,
+            max_depth: 100,
+            ..Default::default()
+        };
+        assert!(limits.check_depth("bucket", 0, 10).is_ok());
+        assert!(limits.check_depth("bucket", 50, 10).is_ok());
+        assert!(limits.check_depth("bucket", 100, 10).is_err());
+        assert!(ListLimits::default().check_depth("bucket", u64::MAX / 2, 1000).is_ok());
+    }
+
+    #[test]
+    fn test_continuation_token() {
+        let token = ContinuationToken {
+            snapshot_id: Some(7),
+            listed: 2000,
+            last_key: "dir/key".to_string(),
+        };
+        let limits = ListLimits::default();
+        let encoded = limits.encode_token("owner", "bucket", &token);
+        assert_eq!(limits.decode_token("owner", "bucket", &encoded).unwrap(), token);
+
+        // tokens of older versions
+        let legacy = hex_string(b"\x007\x00dir/key");
+        let decoded = limits.decode_token("owner", "bucket", &legacy).unwrap();
+        assert_eq!(decoded.snapshot_id, Some(7));
+        assert_eq!(decoded.listed, 0);
+        assert_eq!(decoded.last_key, "dir/key");
+        let legacy = hex_string(b"dir/key");
+        let decoded = limits.decode_token("owner", "bucket", &legacy).unwrap();
+        assert_eq!(decoded.snapshot_id, None);
+        assert_eq!(decoded.last_key, "dir/key");
+        assert!(limits.decode_token("owner", "bucket", "abc").is_err());
+        assert!(limits.decode_token("owner", "bucket", "zz").is_err());
+    }
+
+    #[test]
+    fn test_signed_continuation_token() {
+        let token = ContinuationToken {
+            snapshot_id: None,
+            listed: 1000,
+            last_key: "key".to_string(),
+        };
+        let limits = ListLimits::default().with_token_secret(b"secret");
+        let encoded = limits.encode_token("owner", "bucket", &token);
+        assert_eq!(limits.decode_token("owner", "bucket", &encoded).unwrap(), token);
+
+        // bound to the bucket and its owner
+        assert!(limits.decode_token("owner", "other", &encoded).is_err());
+        assert!(limits.decode_token("other", "bucket", &encoded).is_err());
+        // unsigned and forged tokens are refused
+        let unsigned = ListLimits::default().encode_token("owner", "bucket", &token);
+        assert!(limits.decode_token("owner", "bucket", &unsigned).is_err());
+        let forged = ContinuationToken { listed: 0, ..token };
+        let forged = ListLimits::default()
+            .with_token_secret(b"other")
+            .encode_token("owner", "bucket", &forged);
+        assert!(limits.decode_token("owner", "bucket", &forged).is_err());
+    }
+}
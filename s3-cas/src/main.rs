@@ -9,10 +9,29 @@ use prometheus::Encoder;
 use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-use cas_storage::{CasFS, StorageEngine};
+use cas_storage::{CasFSBuilder, StorageEngine};
+use s3_cas::cdc_estimate::{estimate_cdc, CdcEstimateConfig};
+use s3_cas::jobs::{JobError, JobManager};
 use s3_cas::check::{check_integrity, CheckConfig};
+use s3_cas::rebalance::{rebalance, RebalanceConfig};
+use s3_cas::migrate_metadata::{migrate_metadata, MigrateMetadataConfig};
 use cas_storage::Durability;
+use s3_cas::access_log::{AccessLogConfig, AccessLogFormat, AccessLogger};
+use s3_cas::bucket_logging::BucketLogs;
+use s3_cas::slow_log::{RequestStart, SlowLogConfig, SlowLogger};
+use s3_cas::alerting::{Alert, AlertConfig, AlertKind, Alerter, Severity};
+use s3_cas::bandwidth::Throttle;
+use s3_cas::bench::{bench, BenchConfig};
+use s3_cas::notifications::{NotificationConfig, Notifier};
+use s3_cas::admin_cli::{admin, AdminConfig};
+use s3_cas::manifest::{export_bucket, import_bucket, ExportConfig, ImportConfig};
+use s3_cas::metrics::{MetricsBackend, SharedMetrics, DEFAULT_MAX_BUCKET_LABELS};
+use s3_cas::metrics_auth::MetricsAuth;
+use s3_cas::placement::{parse_prefix_placement, parse_storage_location};
 use s3_cas::retrieve::{retrieve, RetrieveConfig};
+use s3_cas::seed::{seed_blocks, SeedConfig};
+use s3_cas::verify_replica::{verify_replica, VerifyReplicaConfig};
+use s3_cas::write_hooks::{WriteHookConfig, WriteHooks};
 
 #[derive(Parser)]
 #[command(version)]
@@ -41,6 +60,78 @@ pub struct ServerConfig {
     #[arg(long, default_value = "9100")]
     metric_port: u16,
 
+    #[arg(
+        long,
+        default_value = "prometheus",
+        help = "Metrics backend (prometheus, statsd, none)"
+    )]
+    metrics_backend: MetricsBackend,
+
+    #[arg(
+        long,
+        default_value = "127.0.0.1:8125",
+        help = "StatsD daemon address, used with --metrics-backend statsd"
+    )]
+    statsd_addr: String,
+
+    #[arg(long, default_value = "s3cas", help = "Prefix for StatsD metric names")]
+    statsd_prefix: String,
+
+    #[arg(
+        long,
+        default_value_t = DEFAULT_MAX_BUCKET_LABELS,
+        help = "Maximum amount of distinct bucket labels in latency metrics, other buckets are reported as _other"
+    )]
+    metrics_max_bucket_labels: usize,
+
+    #[arg(
+        long,
+        env = "S3CAS_METRICS_TOKEN",
+        hide_env_values = true,
+        help = "Bearer token Prometheus must send to scrape the metrics"
+    )]
+    metrics_token: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "metrics_token",
+        help = "File holding the bearer token Prometheus must send to scrape the metrics"
+    )]
+    metrics_token_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "CIDR",
+        value_delimiter = ',',
+        help = "Addresses or networks allowed to scrape the metrics, all by default. Can be repeated"
+    )]
+    metrics_allow: Vec<s3_cas::network::IpRange>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "metrics_tls_key",
+        help = "PEM certificate chain to serve the metrics over HTTPS"
+    )]
+    metrics_tls_cert: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "metrics_tls_cert",
+        help = "PEM private key of --metrics-tls-cert"
+    )]
+    metrics_tls_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "metrics_tls_cert",
+        help = "PEM CA certificates, scrapers must present a client certificate signed by one of them"
+    )]
+    metrics_tls_client_ca: Option<PathBuf>,
+
     #[arg(long, help = "Enable HTTP browser interface")]
     enable_http_ui: bool,
 
@@ -62,9 +153,332 @@ pub struct ServerConfig {
     )]
     http_ui_password: Option<String>,
 
+    #[arg(
+        long,
+        requires = "enable_http_ui",
+        help = "Serve the admin pages of the HTTP UI and the admin APIs on this port only, instead of the HTTP UI port (multi-user mode)"
+    )]
+    admin_port: Option<u16>,
+
+    #[arg(long, default_value = "localhost")]
+    admin_host: String,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires_all = ["admin_port", "admin_tls_key"],
+        help = "PEM certificate chain to serve the admin listener over HTTPS"
+    )]
+    admin_tls_cert: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "admin_tls_cert",
+        help = "PEM private key of --admin-tls-cert"
+    )]
+    admin_tls_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "admin_tls_cert",
+        help = "PEM CA certificates, admin clients must present a client certificate signed by one of them"
+    )]
+    admin_tls_client_ca: Option<PathBuf>,
+
+    #[arg(
+        long,
+        env = "S3CAS_SHARE_LINK_SECRET",
+        hide_env_values = true,
+        help = "Secret signing share links of the HTTP UI, which download an object without logging in. Share links are disabled without it"
+    )]
+    share_link_secret: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = s3_cas::http_ui::DEFAULT_MAX_SHARE_LINK_LIFETIME.as_secs(),
+        help = "Longest lifetime of a share link in seconds"
+    )]
+    share_link_max_lifetime_secs: u64,
+
+    #[arg(
+        long,
+        help = "Let visitors sign up in the HTTP UI, their accounts are created when an admin approves them (multi-user mode only)"
+    )]
+    allow_signup: bool,
+
+    #[arg(
+        long,
+        default_value_t = s3_cas::auth::signup::DEFAULT_MAX_PENDING_SIGNUPS,
+        help = "Most signups waiting for approval, further signups are refused"
+    )]
+    max_pending_signups: usize,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "TOML file with the quota and buckets given to users created by admins or approved signups (multi-user mode only)"
+    )]
+    user_template: Option<PathBuf>,
+
     #[arg(long, help = "leave empty to disable it")]
     inline_metadata_size: Option<usize>,
 
+    #[arg(
+        long,
+        default_value_t = cas_storage::DEFAULT_WRITE_CONCURRENCY,
+        help = "Amount of blocks of a single upload which are written concurrently"
+    )]
+    write_concurrency: usize,
+
+    #[arg(
+        long,
+        help = "Adapt the amount of concurrent block writes to the observed storage latency"
+    )]
+    adaptive_write_concurrency: bool,
+
+    #[arg(
+        long,
+        default_value = "32",
+        help = "Upper bound for the adaptive block write concurrency"
+    )]
+    write_concurrency_max: usize,
+
+    #[arg(
+        long,
+        default_value = "50",
+        help = "Target block write latency in milliseconds for adaptive write concurrency"
+    )]
+    write_latency_target_ms: u64,
+
+    #[arg(
+        long,
+        help = "Serve all pages of a ListObjectsV2 listing from a snapshot taken at the first page"
+    )]
+    list_snapshots: bool,
+
+    #[arg(
+        long,
+        default_value_t = cas_storage::DEFAULT_LIST_SNAPSHOT_LIFETIME.as_secs(),
+        help = "Maximum lifetime in seconds of a listing snapshot"
+    )]
+    list_snapshot_lifetime_secs: u64,
+
+    #[arg(
+        long,
+        default_value_t = cas_storage::DEFAULT_MAX_LIST_SNAPSHOTS,
+        help = "Maximum amount of live listing snapshots, the oldest is released first"
+    )]
+    max_list_snapshots: usize,
+
+    #[arg(
+        long,
+        default_value_t = s3_cas::listing::DEFAULT_MAX_KEYS,
+        help = "Most keys of a listing page, requests for more get this many"
+    )]
+    list_max_keys: i32,
+
+    #[arg(
+        long,
+        default_value_t = s3_cas::listing::DEFAULT_DEEP_LISTING_KEYS,
+        help = "Log ListObjectsV2 listings which page past this many keys, 0 disables it"
+    )]
+    list_deep_keys: u64,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Refuse to continue ListObjectsV2 listings past this many keys, 0 for no limit"
+    )]
+    list_max_depth: u64,
+
+    #[arg(
+        long,
+        env = "S3CAS_LIST_TOKEN_SECRET",
+        hide_env_values = true,
+        help = "Secret signing the ListObjectsV2 continuation tokens, so they can't be forged or used for another bucket"
+    )]
+    list_token_secret: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Amount of object metadata entries to cache in memory (0 disables the cache)"
+    )]
+    meta_cache_entries: usize,
+
+    #[arg(
+        long,
+        default_value_t = cas_storage::DEFAULT_META_THREADS,
+        help = "Maximum amount of metadata operations (commits, fsyncs) running concurrently on blocking threads"
+    )]
+    meta_threads: usize,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Threads hashing uploaded data, so hashing doesn't take CPU time from the request tasks (0 hashes on the request tasks)"
+    )]
+    hash_threads: usize,
+
+    #[arg(
+        long,
+        help = "Maintain an index from every block to the objects using it, for `inspect block-refs`. Built on startup if missing"
+    )]
+    block_refs_index: bool,
+
+    #[arg(
+        long,
+        default_value = "86400",
+        help = "Seconds between bucket usage samples for the HTTP UI usage history, 0 disables sampling"
+    )]
+    usage_sample_interval_secs: u64,
+
+    #[arg(
+        long,
+        default_value = "60",
+        help = "Seconds between stores of the in-memory bucket activity of the hot buckets panel, 0 keeps it in memory only"
+    )]
+    bucket_activity_flush_secs: u64,
+
+    #[arg(
+        long,
+        default_value = "300",
+        help = "Seconds between measurements of the metadata tree sizes, exported as metrics and sampled daily, 0 disables it"
+    )]
+    metadata_size_interval_secs: u64,
+
+    #[arg(
+        long,
+        value_name = "RATIO",
+        help = "Log a warning when the metadata size divided by the data size exceeds this ratio, e.g. 0.05"
+    )]
+    metadata_ratio_alert: Option<f64>,
+
+    #[arg(
+        long,
+        default_value = s3_cas::http_cache::DEFAULT_CACHE_CONTROL,
+        help = "Cache-Control header returned with objects on S3 GET and HEAD (empty to omit it)"
+    )]
+    cache_control: String,
+
+    #[arg(
+        long,
+        help = "Return the block count, physical size, inline flag and storage locations of objects in x-cas-* headers on S3 HEAD"
+    )]
+    cas_headers: bool,
+
+    #[arg(
+        long,
+        help = "fs_root and meta_root are on encrypted storage, which allows buckets to require AES256 server-side encryption"
+    )]
+    encrypted_at_rest: bool,
+
+    #[arg(
+        long,
+        env = "S3CAS_ADMIN_TOKEN",
+        hide_env_values = true,
+        help = "Token for the JSON admin API on the HTTP UI (multi-user mode), leave empty to disable it"
+    )]
+    admin_token: Option<String>,
+
+    #[arg(
+        long,
+        env = "S3CAS_SECRETS_MASTER_KEY",
+        hide_env_values = true,
+        help = "Master key encrypting the S3 secret keys of the users at rest (multi-user mode), 32 bytes as hex or base64"
+    )]
+    secrets_master_key: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "secrets_master_key",
+        help = "File holding the master key encrypting the S3 secret keys, e.g. provided by a KMS"
+    )]
+    secrets_master_key_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Only serve requests which don't modify the store, e.g. behind a load balancer with a synced copy of meta_root"
+    )]
+    read_replica: bool,
+
+    #[arg(
+        long,
+        help = "TOML file with global and per-bucket IP allow and deny lists for S3 requests"
+    )]
+    network_policy: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "TOML file with webhooks to send alerts about operational events to, e.g. disk watermark breaches"
+    )]
+    alert_config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "TOML file with local commands to run on object created and removed events"
+    )]
+    notification_config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "TOML file with rules, commands and webhooks validating object writes before they are stored"
+    )]
+    write_hook_config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "TOML file with time windows limiting the bandwidth of scrubs and client reads and writes, reloaded on change"
+    )]
+    bandwidth_schedule: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Reject all S3 requests without credentials, including reads of public objects"
+    )]
+    reject_anonymous: bool,
+
+    #[arg(
+        long,
+        help = "Accept S3 requests signed with Signature Version 2 from users which opted in (multi-user mode)"
+    )]
+    allow_sig_v2: bool,
+
+    #[arg(
+        long,
+        help = "Reject S3 requests signed with SigV4 outside the clock skew window, and requests whose signature was already received"
+    )]
+    strict_sigv4: bool,
+
+    #[arg(
+        long,
+        default_value = "900",
+        help = "Maximum difference in seconds between the signing time of a request and the server time, with --strict-sigv4"
+    )]
+    sigv4_max_skew_secs: u64,
+
+    #[arg(
+        long,
+        value_name = "REGION",
+        value_delimiter = ',',
+        help = "Region S3 requests must be signed for, any by default. Can be repeated to accept several regions"
+    )]
+    signing_region: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        value_delimiter = ',',
+        help = "Service name S3 requests must be signed for, e.g. s3, any by default. Can be repeated"
+    )]
+    signing_service: Vec<String>,
+
     #[arg(long, display_order = 1000, help = "S3 access key (required in single-user mode)")]
     access_key: Option<String>,
 
@@ -85,6 +499,173 @@ pub struct ServerConfig {
     )]
     durability: Durability,
 
+    #[arg(
+        long = "bucket-durability",
+        value_name = "BUCKET=LEVEL",
+        value_parser = parse_bucket_durability,
+        help = "Durability level of the writes to a bucket, instead of --durability, e.g. scratch=buffer. Can be repeated"
+    )]
+    bucket_durability: Vec<(String, Durability)>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        value_name = "MS",
+        help = "Sync the block files and metadata of all writes together every MS milliseconds with --durability, instead of the metadata of every write. Writes are acknowledged before they are synced, a crash loses at most those of the last MS milliseconds. 0 disables it"
+    )]
+    group_sync_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "8388608",
+        value_name = "BYTES",
+        help = "Sync without waiting for --group-sync-ms once this size of block files was written"
+    )]
+    group_sync_bytes: u64,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        help = "Store metadata values of at least this size, objects with inlined data, in blob files. Applies to bucket partitions created afterwards"
+    )]
+    kv_separation_threshold: Option<u32>,
+
+    #[arg(
+        long,
+        default_value = "67108864",
+        help = "Target size of the blob files of key-value separated bucket partitions"
+    )]
+    blob_file_target_size: u64,
+
+    #[arg(
+        long,
+        default_value = "2.0",
+        help = "Rewrite blob files until their space amplification is below this factor"
+    )]
+    blob_gc_space_amp: f32,
+
+    #[arg(
+        long,
+        default_value = "0.5",
+        help = "Rewrite blob files with at least this ratio of stale data"
+    )]
+    blob_gc_staleness: f32,
+
+    #[arg(
+        long,
+        default_value = "3600",
+        help = "Seconds between blob file garbage collections, 0 disables it"
+    )]
+    blob_gc_interval_secs: u64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Seconds the files of deleted blocks are kept before they are removed, 0 removes them right away"
+    )]
+    delete_grace_secs: u64,
+
+    #[arg(
+        long,
+        default_value = "1000",
+        help = "Maximum amount of block files of the delete queue removed per second"
+    )]
+    delete_purge_rate: usize,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Multi-user mode: maximum amount of users with an open metadata store, the least recently used idle ones are closed (0 for no limit)"
+    )]
+    max_open_users: usize,
+
+    #[arg(
+        long,
+        default_value = "900",
+        help = "Multi-user mode: seconds after which the metadata store of an idle user is closed, 0 keeps them open"
+    )]
+    user_idle_timeout_secs: u64,
+
+    #[arg(
+        long = "storage-location",
+        value_name = "NAME=PATH",
+        value_parser = parse_storage_location,
+        help = "Storage location for blocks placed with --prefix-placement, stored in PATH/blocks, e.g. ssd=/mnt/ssd/s3-cas. Can be repeated"
+    )]
+    storage_locations: Vec<(String, PathBuf)>,
+
+    #[arg(
+        long = "prefix-placement",
+        value_name = "BUCKET/PREFIX=NAME",
+        value_parser = parse_prefix_placement,
+        help = "Store new blocks of the objects in BUCKET whose key starts with PREFIX in the storage location NAME, e.g. media/thumbs/=ssd. Can be repeated"
+    )]
+    prefix_placements: Vec<(String, String, String)>,
+
+    #[arg(
+        long,
+        help = "Write an S3 access log to this file, use - for stdout. Leave empty to disable it"
+    )]
+    access_log: Option<String>,
+
+    #[arg(long, default_value = "common", help = "Access log format (common, json)")]
+    access_log_format: AccessLogFormat,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Rotate the access log file once it exceeds this many bytes, 0 disables rotation"
+    )]
+    access_log_max_size: u64,
+
+    #[arg(long, default_value = "5", help = "Amount of rotated access log files to keep")]
+    access_log_max_files: usize,
+
+    #[arg(
+        long,
+        default_value = "1.0",
+        help = "Fraction of successful requests written to the access log, errors are always logged"
+    )]
+    access_log_sample_rate: f64,
+
+    #[arg(
+        long,
+        help = "Write the S3 requests exceeding the latency threshold of their operation to this file as JSON lines, use - for stdout. Leave empty to disable it"
+    )]
+    slow_log: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "1000",
+        help = "Latency in milliseconds from which a request is written to the slow log"
+    )]
+    slow_log_threshold_ms: u64,
+
+    #[arg(
+        long = "slow-log-operation-threshold",
+        value_name = "OPERATION=MS",
+        value_parser = parse_operation_threshold,
+        help = "Slow log threshold of an S3 operation, instead of --slow-log-threshold-ms, e.g. list_objects_v2=5000. Can be repeated"
+    )]
+    slow_log_operation_thresholds: Vec<(String, u64)>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Rotate the slow log file once it exceeds this many bytes, 0 disables rotation"
+    )]
+    slow_log_max_size: u64,
+
+    #[arg(long, default_value = "5", help = "Amount of rotated slow log files to keep")]
+    slow_log_max_files: usize,
+
+    #[arg(
+        long,
+        default_value_t = s3_cas::bucket_logging::DEFAULT_BUCKET_LOG_INTERVAL_SECS,
+        help = "Seconds between deliveries of the access logs of buckets with logging enabled to their target buckets, 0 disables bucket logging"
+    )]
+    bucket_log_interval_secs: u64,
+
     #[arg(
         long,
         default_value = "info",
@@ -110,6 +691,14 @@ pub enum Command {
         #[arg(long, help = "Path to users config file for multi-user mode")]
         users_config: Option<PathBuf>,
 
+        #[arg(
+            long,
+            global = true,
+            default_value = "text",
+            help = "Output format (text, json)"
+        )]
+        format: s3_cas::inspect::OutputFormat,
+
         #[command(subcommand)]
         command: InspectCommand,
     },
@@ -117,11 +706,38 @@ pub enum Command {
     /// retrieve an object
     Retrieve(RetrieveConfig),
 
-    /// Check object integrity
+    /// Check object integrity, or the objects of a bucket against an external checksum manifest
     Check(CheckConfig),
 
+    /// Write the object metadata of a bucket as a manifest, without data
+    ExportBucket(ExportConfig),
+
+    /// Attach the objects of a manifest to a bucket, the block files must be copied already
+    ImportBucket(ImportConfig),
+
+    /// Store the files of a local directory so later uploads of them deduplicate
+    SeedBlocks(SeedConfig),
+
+    /// Compare the objects with a disaster recovery copy on another S3 endpoint
+    VerifyReplica(VerifyReplicaConfig),
+
+    /// Estimate the deduplication content-defined chunking would add, from a sample of the objects
+    EstimateCdc(CdcEstimateConfig),
+
+    /// Move a share of the blocks to another storage location, e.g. a new disk
+    Rebalance(RebalanceConfig),
+
+    /// Copy the metadata stores to a new metadata root using the other metadata DB, and verify them
+    MigrateMetadata(MigrateMetadataConfig),
+
+    /// Measure the throughput and latency of a server or a store with generated objects
+    Bench(BenchConfig),
+
     /// Start S3-cas server
     Server(ServerConfig),
+
+    /// Manage a remote multi-user server through its admin API
+    Admin(AdminConfig),
 }
 
 #[derive(Debug, Subcommand)]
@@ -153,6 +769,10 @@ pub enum InspectCommand {
     },
     /// Show block storage statistics and deduplication ratio
     BlockStats,
+    /// Show the blob file utilization of the key-value separated bucket partitions
+    BlobStats,
+    /// Show the disk space of the metadata trees and the metadata to data ratio
+    MetadataSize,
     /// Show detailed information about a specific object
     ObjectInfo {
         /// Bucket name
@@ -163,6 +783,40 @@ pub enum InspectCommand {
         #[arg(long)]
         user: Option<String>,
     },
+    /// List the objects using a block
+    BlockRefs {
+        /// Block hash, hex encoded
+        hash: String,
+        /// Only search the objects of this user (multi-user mode)
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// List the blocks marked as corrupt, their healing status and the objects using them
+    CorruptBlocks {
+        /// Only list the objects of this user (multi-user mode)
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// List the objects using corrupt blocks which are not healed, as marked by check and
+    /// scrubs
+    DegradedObjects {
+        /// Only list the objects of this bucket
+        #[arg(long)]
+        bucket: Option<String>,
+        /// Only list the objects of this user (multi-user mode)
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// Check the reference counts of the shared block tree against the blocks used by the
+    /// objects of all users and by multipart upload parts
+    CrossCheck,
+    /// Verify the hash chain of the admin audit log and show its last entries (multi-user
+    /// mode only)
+    AuditLog {
+        /// Number of entries to show
+        #[arg(long, default_value_t = 20)]
+        last: usize,
+    },
 }
 
 fn setup_tracing(log_level: &str) {
@@ -200,87 +854,818 @@ fn main() -> Result<()> {
             meta_root,
             metadata_db,
             users_config,
+            format,
         } => {
             use s3_cas::inspect::*;
             match command {
                 InspectCommand::NumKeys => {
                     let num_keys = num_keys(meta_root, metadata_db, users_config)?;
-                    println!("Number of keys: {num_keys}");
+                    match format {
+                        OutputFormat::Text => println!("Number of keys: {num_keys}"),
+                        OutputFormat::Json => print_json(&serde_json::json!({ "num_keys": num_keys }))?,
+                    }
                 }
                 InspectCommand::DiskSpace => {
                     let disk_space = disk_space(meta_root, metadata_db, users_config);
-                    println!("Disk space: {disk_space}");
+                    match format {
+                        OutputFormat::Text => println!("Disk space: {disk_space}"),
+                        OutputFormat::Json => {
+                            print_json(&serde_json::json!({ "disk_space_bytes": disk_space }))?
+                        }
+                    }
                 }
                 InspectCommand::ListUsers => {
-                    list_users(meta_root, metadata_db, users_config)?;
+                    list_users(meta_root, metadata_db, users_config, format)?;
+                }
+                InspectCommand::UserStats { user_id } => {
+                    user_stats(meta_root, metadata_db, users_config, user_id, format)?;
+                }
+                InspectCommand::ListBuckets { user } => {
+                    list_buckets(meta_root, metadata_db, users_config, user, format)?;
+                }
+                InspectCommand::BucketStats { bucket, user } => {
+                    bucket_stats(meta_root, metadata_db, users_config, bucket, user, format)?;
+                }
+                InspectCommand::BlockStats => {
+                    block_stats(meta_root, metadata_db, users_config, format)?;
+                }
+                InspectCommand::BlobStats => {
+                    blob_stats(meta_root, metadata_db, users_config, format)?;
+                }
+                InspectCommand::MetadataSize => {
+                    metadata_size(meta_root, metadata_db, users_config, format)?;
+                }
+                InspectCommand::ObjectInfo { bucket, key, user } => {
+                    object_info(meta_root, metadata_db, users_config, bucket, key, user, format)?;
+                }
+                InspectCommand::BlockRefs { hash, user } => {
+                    block_refs(meta_root, metadata_db, users_config, hash, user, format)?;
+                }
+                InspectCommand::CorruptBlocks { user } => {
+                    corrupt_blocks(meta_root, metadata_db, users_config, user, format)?;
+                }
+                InspectCommand::DegradedObjects { bucket, user } => {
+                    degraded_objects(meta_root, metadata_db, users_config, user, bucket, format)?;
+                }
+                InspectCommand::CrossCheck => {
+                    cross_check(meta_root, metadata_db, users_config, format)?;
+                }
+                InspectCommand::AuditLog { last } => {
+                    audit_log(meta_root, metadata_db, users_config, last, format)?;
+                }
+            }
+        }
+        Command::Retrieve(config) => retrieve(config)?,
+        Command::Check(config) => check_integrity(config)?,
+        Command::ExportBucket(config) => export_bucket(config)?,
+        Command::ImportBucket(config) => import_bucket(config)?,
+        Command::SeedBlocks(config) => seed_blocks(config)?,
+        Command::VerifyReplica(config) => verify_replica(config)?,
+        Command::EstimateCdc(config) => estimate_cdc(config)?,
+        Command::Rebalance(config) => rebalance(config)?,
+        Command::MigrateMetadata(config) => migrate_metadata(config)?,
+        Command::Bench(config) => bench(config)?,
+        Command::Admin(config) => admin(config)?,
+        Command::Server(config) => {
+            run(config)?;
+        }
+    }
+    Ok(())
+}
+
+use hyper::service::Service as _;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder as ConnBuilder;
+use s3_cas::auth::UserTemplate;
+use s3_cas::http_ui::UiListener;
+use s3_cas::network::{NetworkAccess, NetworkPolicy, RemoteAddr};
+use s3_cas::replay::{mark_sig_v4, ReplayGuard, StrictSigV4Access};
+use s3_cas::replica::ReadOnlyAccess;
+use s3_cas::s3fs::take_multi_range;
+use s3_cas::s3_wrapper::mark_sig_v2;
+use s3_cas::signing_scope::{mark_credential_scope, SigningScope, SigningScopeAccess};
+use s3s::service::S3ServiceBuilder;
+
+#[tokio::main]
+async fn run(mut args: ServerConfig) -> anyhow::Result<()> {
+    // Canonicalize paths to avoid repeated getcwd() syscalls in async operations
+    // This is critical for performance when using relative paths
+    args.fs_root = args.fs_root.canonicalize()
+        .unwrap_or_else(|_| {
+            std::fs::create_dir_all(&args.fs_root).ok();
+            args.fs_root.canonicalize()
+                .unwrap_or_else(|_| std::env::current_dir().unwrap().join(&args.fs_root))
+        });
+
+    args.meta_root = args.meta_root.canonicalize()
+        .unwrap_or_else(|_| {
+            std::fs::create_dir_all(&args.meta_root).ok();
+            args.meta_root.canonicalize()
+                .unwrap_or_else(|_| std::env::current_dir().unwrap().join(&args.meta_root))
+        });
+
+    info!("Using fs_root: {}", args.fs_root.display());
+    info!("Using meta_root: {}", args.meta_root.display());
+
+    // held until the server stops, the OS releases it if the process dies
+    let mode = if args.read_replica { "read replica" } else { "server" };
+    let _store_lock = cas_storage::StoreLock::acquire(&args.meta_root, mode)?;
+    if args.read_replica {
+        info!("Read replica mode, requests which modify the store are rejected");
+    }
+
+    let storage_engine = args.metadata_db;
+    let metrics = match args.metrics_backend {
+        MetricsBackend::Prometheus => SharedMetrics::from_collector(
+            s3_cas::metrics::PrometheusMetrics::new(),
+            args.metrics_max_bucket_labels,
+        ),
+        MetricsBackend::Statsd => {
+            info!("Sending metrics to StatsD at {}", args.statsd_addr);
+            SharedMetrics::from_collector(
+                s3_cas::metrics::StatsdMetrics::new(&args.statsd_addr, &args.statsd_prefix)?,
+                args.metrics_max_bucket_labels,
+            )
+        }
+        MetricsBackend::None => SharedMetrics::from_collector(
+            s3_cas::metrics::NoOpMetrics,
+            args.metrics_max_bucket_labels,
+        ),
+    };
+
+    // Check if single-user mode is explicitly requested
+    if args.access_key.is_some() && args.secret_key.is_some() {
+        info!("Single-user mode (explicit credentials provided)");
+        run_single_user(args, storage_engine, metrics).await
+    } else if args.access_key.is_some() || args.secret_key.is_some() {
+        anyhow::bail!(
+            "Single-user mode requires both --access-key and --secret-key.\n\
+             Omit both for multi-user mode with database-backed authentication."
+        );
+    } else {
+        info!("Multi-user mode (database-backed authentication)");
+        run_multi_user(args, storage_engine, metrics).await
+    }
+}
+
+fn write_limiter(args: &ServerConfig) -> Option<Arc<cas_storage::AdaptiveWriteLimiter>> {
+    if !args.adaptive_write_concurrency {
+        return None;
+    }
+    let config = cas_storage::WriteLimiterConfig {
+        initial_concurrency: args.write_concurrency,
+        max_concurrency: args.write_concurrency_max,
+        target_latency: std::time::Duration::from_millis(args.write_latency_target_ms),
+        ..Default::default()
+    };
+    info!(
+        "Adaptive write concurrency enabled (max: {}, target latency: {}ms)",
+        config.max_concurrency, args.write_latency_target_ms
+    );
+    Some(Arc::new(cas_storage::AdaptiveWriteLimiter::new(config)))
+}
+
+fn cache_control(args: &ServerConfig) -> Option<String> {
+    Some(args.cache_control.clone()).filter(|c| !c.is_empty())
+}
+
+fn admin_token(args: &ServerConfig) -> Option<String> {
+    match args.admin_token.as_deref() {
+        None | Some("") => None,
+        Some(token) => {
+            info!("Admin API enabled");
+            Some(token.to_string())
+        }
+    }
+}
+
+/// Signer of the share links of the HTTP UI, None if they are disabled
+fn share_links(args: &ServerConfig) -> Option<s3_cas::http_ui::ShareLinks> {
+    match args.share_link_secret.as_deref() {
+        None | Some("") => None,
+        Some(secret) => {
+            info!(
+                "Share links enabled, valid for at most {} seconds",
+                args.share_link_max_lifetime_secs
+            );
+            let max_lifetime = std::time::Duration::from_secs(args.share_link_max_lifetime_secs);
+            Some(s3_cas::http_ui::ShareLinks::new(secret.as_bytes()).with_max_lifetime(max_lifetime))
+        }
+    }
+}
+
+/// Cipher of the S3 secret keys in the user store, None without a master key
+fn secret_cipher(
+    args: &ServerConfig,
+) -> anyhow::Result<Option<Arc<dyn s3_cas::auth::SecretCipher>>> {
+    use s3_cas::auth::{EnvelopeCipher, MasterKey};
+
+    let master_key = match (&args.secrets_master_key, &args.secrets_master_key_file) {
+        (Some(key), _) if !key.is_empty() => MasterKey::parse(key)?,
+        (_, Some(path)) => MasterKey::from_file(path)?,
+        _ => return Ok(None),
+    };
+    info!("S3 secret keys are encrypted at rest");
+    Ok(Some(Arc::new(EnvelopeCipher::new(&master_key))))
+}
+
+fn list_limits(args: &ServerConfig) -> anyhow::Result<s3_cas::listing::ListLimits> {
+    if args.list_max_keys < 1 {
+        anyhow::bail!("--list-max-keys must be at least 1");
+    }
+    let limits = s3_cas::listing::ListLimits {
+        max_keys: args.list_max_keys,
+        deep_keys: args.list_deep_keys,
+        max_depth: args.list_max_depth,
+        ..Default::default()
+    };
+    Ok(match args.list_token_secret.as_deref() {
+        None | Some("") => limits,
+        Some(secret) => {
+            info!("Continuation tokens are signed");
+            limits.with_token_secret(secret.as_bytes())
+        }
+    })
+}
+
+/// Maximum lifetime and amount of the listing snapshots, if enabled
+fn list_snapshots(args: &ServerConfig) -> Option<(std::time::Duration, usize)> {
+    if !args.list_snapshots {
+        return None;
+    }
+    Some((
+        std::time::Duration::from_secs(args.list_snapshot_lifetime_secs),
+        args.max_list_snapshots,
+    ))
+}
+
+/// Executor for blocking metadata operations, shared by all CasFS instances
+fn meta_executor(
+    args: &ServerConfig,
+    metrics: &s3_cas::metrics::SharedMetrics,
+) -> Arc<cas_storage::MetaExecutor> {
+    Arc::new(cas_storage::MetaExecutor::new(
+        args.meta_threads,
+        metrics.to_cas_metrics(),
+    ))
+}
+
+fn hash_pool(args: &ServerConfig) -> anyhow::Result<Option<Arc<cas_storage::HashPool>>> {
+    use anyhow::Context;
+
+    if args.hash_threads == 0 {
+        return Ok(None);
+    }
+    let pool = cas_storage::HashPool::new(args.hash_threads)
+        .context("Failed to start the hash threads")?;
+    info!("Hashing uploads on {} threads", pool.threads());
+    Ok(Some(Arc::new(pool)))
+}
+
+fn parse_bucket_durability(s: &str) -> Result<(String, Durability), String> {
+    let (bucket, durability) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected BUCKET=LEVEL, got '{s}'"))?;
+    if bucket.is_empty() {
+        return Err(format!("Missing bucket name in '{s}'"));
+    }
+    Ok((bucket.to_string(), durability.parse()?))
+}
+
+fn parse_operation_threshold(s: &str) -> Result<(String, u64), String> {
+    let (operation, ms) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected OPERATION=MS, got '{s}'"))?;
+    if operation.is_empty() {
+        return Err(format!("Missing operation name in '{s}'"));
+    }
+    let ms = ms
+        .parse()
+        .map_err(|_| format!("Invalid threshold in milliseconds in '{s}'"))?;
+    Ok((operation.to_string(), ms))
+}
+
+/// A CasFS builder with the storage options of the server
+fn casfs_builder(
+    args: &ServerConfig,
+    storage_engine: StorageEngine,
+    metrics: &s3_cas::metrics::SharedMetrics,
+) -> CasFSBuilder {
+    let builder = CasFSBuilder::new(&args.fs_root, &args.meta_root)
+        .metrics(metrics.to_cas_metrics())
+        .storage_engine(storage_engine)
+        .durability(store_durability(args))
+        .block_refs(args.block_refs_index)
+        .delete_grace(std::time::Duration::from_secs(args.delete_grace_secs));
+    let builder = args
+        .bucket_durability
+        .iter()
+        .fold(builder, |builder, (bucket, durability)| {
+            builder.bucket_durability(bucket.clone(), *durability)
+        });
+    let builder = args
+        .storage_locations
+        .iter()
+        .fold(builder, |builder, (name, path)| {
+            builder.storage_location(name.clone(), path.clone())
+        });
+    let builder = args
+        .prefix_placements
+        .iter()
+        .fold(builder, |builder, (bucket, prefix, location)| {
+            builder.prefix_placement(bucket.clone(), prefix.clone(), location.clone())
+        });
+    let builder = match kv_separation(args) {
+        Some(kv_separation) => builder.kv_separation(kv_separation),
+        None => builder,
+    };
+    match args.inline_metadata_size {
+        Some(size) => builder.inlined_metadata_size(size),
+        None => builder,
+    }
+}
+
+/// Syncs the writes of all requests together every `--group-sync-ms`, with
+/// `--durability`. A read replica doesn't write.
+fn group_sync(
+    args: &ServerConfig,
+    metrics: &s3_cas::metrics::SharedMetrics,
+) -> anyhow::Result<Option<Arc<cas_storage::GroupSync>>> {
+    if args.group_sync_ms == 0 || args.read_replica {
+        return Ok(None);
+    }
+    if !args.durability.is_synced() {
+        anyhow::bail!("--group-sync-ms requires --durability fsync or fdatasync");
+    }
+    let config = cas_storage::GroupSyncConfig {
+        max_bytes: args.group_sync_bytes,
+        max_delay: std::time::Duration::from_millis(args.group_sync_ms),
+        durability: args.durability,
+    };
+    info!(
+        "Group sync enabled (every {}ms or {} bytes)",
+        args.group_sync_ms, config.max_bytes
+    );
+    let group_sync = Arc::new(cas_storage::GroupSync::new(
+        config,
+        metrics.to_cas_metrics(),
+    ));
+    tokio::spawn(group_sync.clone().run());
+    Ok(Some(group_sync))
+}
+
+/// The durability the metadata stores are opened with, `buffer` if the group
+/// sync persists them
+fn store_durability(args: &ServerConfig) -> Durability {
+    if args.group_sync_ms == 0 || args.read_replica {
+        args.durability
+    } else {
+        Durability::Buffer
+    }
+}
+
+/// Sync the writes since the last group sync before the server exits
+async fn final_group_sync(group_sync: Option<Arc<cas_storage::GroupSync>>) {
+    let Some(group_sync) = group_sync else {
+        return;
+    };
+    match tokio::task::spawn_blocking(move || group_sync.sync()).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::error!("Final group sync failed: {e}"),
+        Err(e) => tracing::error!("Final group sync task failed: {e}"),
+    }
+}
+
+/// The key-value separation of new bucket partitions, if a threshold is set
+fn kv_separation(args: &ServerConfig) -> Option<cas_storage::KvSeparation> {
+    Some(cas_storage::KvSeparation {
+        threshold: args.kv_separation_threshold?,
+        file_target_size: args.blob_file_target_size,
+        gc_space_amp_target: args.blob_gc_space_amp,
+        gc_staleness_threshold: args.blob_gc_staleness,
+    })
+}
+
+/// The manager of the maintenance jobs, run on the CasFS instances returned by
+/// `casfs`. Read replicas run no jobs, they can't write to the store.
+fn job_manager<F>(
+    args: &ServerConfig,
+    store: cas_storage::JobStore,
+    throttle: Option<Arc<Throttle>>,
+    casfs: F,
+) -> anyhow::Result<Option<Arc<JobManager>>>
+where
+    F: Fn() -> anyhow::Result<Vec<Arc<cas_storage::CasFS>>> + Send + Sync + 'static,
+{
+    if args.read_replica {
+        return Ok(None);
+    }
+    Ok(Some(Arc::new(JobManager::new(store, casfs)?.with_throttle(throttle))))
+}
+
+/// Periodically start a job garbage collecting the blob files of the key-value
+/// separated bucket partitions. Partitions created while key-value separation was
+/// enabled are collected even if it's disabled since.
+fn spawn_blob_gc(args: &ServerConfig, jobs: Option<Arc<JobManager>>) {
+    let Some(jobs) = jobs.filter(|_| args.blob_gc_interval_secs != 0) else {
+        return;
+    };
+    let period = std::time::Duration::from_secs(args.blob_gc_interval_secs);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match jobs.start_blob_gc() {
+                // an admin may have started one
+                Ok(_) | Err(JobError::AlreadyRunning(_)) => {}
+                Err(e) => tracing::warn!("Could not start blob file garbage collection: {}", e),
+            }
+        }
+    });
+}
+
+/// Remove the block files of the delete queue once their grace has passed, at most
+/// `--delete-purge-rate` per second so mass deletes don't cause IO spikes, and
+/// export the size of the queue as metrics. The queue is in the block metadata
+/// store, so purging it through one CasFS is enough. It is also purged without a
+/// grace, blocks queued before it was disabled are removed.
+fn spawn_delete_purger<F>(args: &ServerConfig, metrics: SharedMetrics, casfs: F)
+where
+    F: Fn() -> anyhow::Result<Option<Arc<cas_storage::CasFS>>> + Send + Sync + 'static,
+{
+    // how often the size of the queue is exported, it takes a scan of the queue
+    const STATS_EVERY_TICKS: u64 = 60;
+
+    if args.read_replica {
+        return;
+    }
+    let rate = args.delete_purge_rate.max(1);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+        for tick in 0u64.. {
+            interval.tick().await;
+            let fs = match casfs() {
+                Ok(Some(fs)) => fs,
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::warn!("Could not open the store to purge the delete queue: {}", e);
+                    continue;
                 }
-                InspectCommand::UserStats { user_id } => {
-                    user_stats(meta_root, metadata_db, users_config, user_id)?;
+            };
+            match fs.purge_delete_queue(rate).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::debug!(purged, "Removed block files of the delete queue"),
+                Err(e) => tracing::warn!("Could not purge the delete queue: {}", e),
+            }
+            if tick % STATS_EVERY_TICKS == 0 {
+                match fs.run_blocking(|fs| fs.delete_queue_stats()).await {
+                    Ok(stats) => metrics
+                        .to_cas_metrics()
+                        .delete_queue(stats.blocks, stats.bytes),
+                    Err(e) => tracing::warn!("Could not measure the delete queue: {}", e),
                 }
-                InspectCommand::ListBuckets { user } => {
-                    list_buckets(meta_root, metadata_db, users_config, user)?;
+            }
+        }
+    });
+}
+
+/// Periodically store the usage of every bucket of the CasFS instances returned by
+/// `casfs`, the HTTP UI shows it as the usage history. A sample replaces an earlier
+/// one of the same day. Read replicas don't sample, they can't write to the store.
+fn spawn_usage_sampler<F>(args: &ServerConfig, casfs: F)
+where
+    F: Fn() -> anyhow::Result<Vec<Arc<cas_storage::CasFS>>> + Send + Sync + 'static,
+{
+    if args.usage_sample_interval_secs == 0 || args.read_replica {
+        return;
+    }
+    let period = std::time::Duration::from_secs(args.usage_sample_interval_secs);
+    let casfs = Arc::new(casfs);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let casfs = casfs.clone();
+            // sampling scans all objects, keep it off the runtime threads
+            let result = tokio::task::spawn_blocking(move || -> anyhow::Result<usize> {
+                let day = chrono::Utc::now().format("%Y-%m-%d").to_string();
+                let mut sampled = 0;
+                for fs in casfs()? {
+                    sampled += fs.record_usage(&day)?;
                 }
-                InspectCommand::BucketStats { bucket, user } => {
-                    bucket_stats(meta_root, metadata_db, users_config, bucket, user)?;
+                Ok(sampled)
+            })
+            .await;
+            match result {
+                Ok(Ok(buckets)) => tracing::debug!(buckets, "Sampled bucket usage"),
+                Ok(Err(e)) => tracing::warn!("Could not sample bucket usage: {}", e),
+                Err(e) => tracing::warn!("Bucket usage sampling failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Periodically store the bucket activity of the CasFS instances returned by
+/// `casfs`, so a restart keeps the hot buckets of the last hour. Read replicas
+/// keep it in memory only.
+fn spawn_activity_flusher<F>(args: &ServerConfig, casfs: F)
+where
+    F: Fn() -> Vec<Arc<cas_storage::CasFS>> + Send + Sync + 'static,
+{
+    if args.bucket_activity_flush_secs == 0 || args.read_replica {
+        return;
+    }
+    let period = std::time::Duration::from_secs(args.bucket_activity_flush_secs);
+    let casfs = Arc::new(casfs);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let casfs = casfs.clone();
+            let result = tokio::task::spawn_blocking(move || -> anyhow::Result<usize> {
+                let mut flushed = 0;
+                for fs in casfs() {
+                    flushed += fs.flush_activity()?;
                 }
-                InspectCommand::BlockStats => {
-                    block_stats(meta_root, metadata_db, users_config)?;
+                Ok(flushed)
+            })
+            .await;
+            match result {
+                Ok(Ok(0)) => {}
+                Ok(Ok(buckets)) => tracing::debug!(buckets, "Stored bucket activity"),
+                Ok(Err(e)) => tracing::warn!("Could not store bucket activity: {}", e),
+                Err(e) => tracing::warn!("Storing bucket activity failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Periodically measure the disk space of the metadata trees of the CasFS instances
+/// returned by `casfs`, export it as metrics and warn when the metadata to data
+/// ratio crosses `--metadata-ratio-alert`. Many tiny objects with long keys make
+/// the metadata outgrow the data. Read replicas measure, but don't store samples.
+fn spawn_metadata_monitor<F>(
+    args: &ServerConfig,
+    metrics: SharedMetrics,
+    alerter: Alerter,
+    casfs: F,
+) where
+    F: Fn() -> anyhow::Result<Vec<Arc<cas_storage::CasFS>>> + Send + Sync + 'static,
+{
+    if args.metadata_size_interval_secs == 0 {
+        return;
+    }
+    let period = std::time::Duration::from_secs(args.metadata_size_interval_secs);
+    let (alert_ratio, record) = (args.metadata_ratio_alert, !args.read_replica);
+    let casfs = Arc::new(casfs);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        let mut alerting = false;
+        loop {
+            interval.tick().await;
+            let casfs = casfs.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let day = chrono::Utc::now().format("%Y-%m-%d").to_string();
+                let sizes = casfs()?
+                    .iter()
+                    .map(|fs| match record {
+                        true => fs.record_metadata_size(&day),
+                        false => fs.metadata_size(),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                anyhow::Ok(cas_storage::MetadataSize::combine(&sizes))
+            })
+            .await;
+            let size = match result {
+                Ok(Ok(size)) => size,
+                Ok(Err(e)) => {
+                    tracing::warn!("Could not measure the metadata size: {}", e);
+                    continue;
                 }
-                InspectCommand::ObjectInfo { bucket, key, user } => {
-                    object_info(meta_root, metadata_db, users_config, bucket, key, user)?;
+                Err(e) => {
+                    tracing::warn!("Metadata size measurement failed: {}", e);
+                    continue;
                 }
+            };
+            metrics.observe_metadata_size(&size);
+
+            let ratio = size.ratio();
+            let over = alert_ratio.map_or(false, |alert| ratio > alert);
+            if over && !alerting {
+                let largest = size.largest_first();
+                let largest = largest.first().map_or("", |tree| tree.name.as_str());
+                tracing::warn!(
+                    ratio,
+                    metadata_bytes = size.metadata_bytes(),
+                    data_bytes = size.data_bytes(),
+                    largest,
+                    "Metadata to data ratio exceeds the alert threshold"
+                );
+                alerter.send(
+                    Alert::new(
+                        AlertKind::MetadataRatio,
+                        Severity::Warning,
+                        format!("Metadata to data ratio {ratio:.3} exceeds the alert threshold"),
+                    )
+                    .with_detail("metadata_bytes", size.metadata_bytes())
+                    .with_detail("data_bytes", size.data_bytes())
+                    .with_detail("largest_tree", largest),
+                );
+            } else if !over && alerting {
+                tracing::info!(ratio, "Metadata to data ratio is below the alert threshold again");
+                alerter.send(Alert::new(
+                    AlertKind::MetadataRatio,
+                    Severity::Resolved,
+                    format!("Metadata to data ratio {ratio:.3} is below the alert threshold again"),
+                ));
             }
+            alerting = over;
         }
-        Command::Retrieve(config) => retrieve(config)?,
-        Command::Check(config) => check_integrity(config)?,
-        Command::Server(config) => {
-            run(config)?;
-        }
-    }
-    Ok(())
+    });
 }
 
-use hyper_util::rt::{TokioExecutor, TokioIo};
-use hyper_util::server::conn::auto::Builder as ConnBuilder;
-use s3s::service::S3ServiceBuilder;
+/// Periodically check the used space of the filesystems of the data, metadata and
+/// storage location directories, and alert when one crosses the disk watermark of
+/// the alert config.
+fn spawn_disk_monitor(args: &ServerConfig, alerter: Alerter) {
+    let Some(config) = alerter.config() else {
+        return;
+    };
+    let Some(watermark) = config.disk_watermark else {
+        return;
+    };
+    let period = std::time::Duration::from_secs(config.disk_check_interval_secs.max(1));
+    let mut paths = vec![args.fs_root.clone(), args.meta_root.clone()];
+    paths.extend(args.storage_locations.iter().map(|(_, path)| path.clone()));
+    paths.dedup();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        let mut alerting = vec![false; paths.len()];
+        loop {
+            interval.tick().await;
+            for (path, alerting) in paths.iter().zip(&mut alerting) {
+                let usage = match s3_cas::alerting::disk_usage(path) {
+                    Ok(usage) => usage,
+                    Err(e) => {
+                        tracing::warn!(path = %path.display(), "Could not measure the disk usage: {}", e);
+                        continue;
+                    }
+                };
+                let over = usage >= watermark;
+                if over && !*alerting {
+                    tracing::warn!(path = %path.display(), usage, "Disk usage exceeds the watermark");
+                    alerter.send(
+                        Alert::new(
+                            AlertKind::DiskWatermark,
+                            Severity::Critical,
+                            format!("Disk of {} is {:.1}% full", path.display(), usage * 100.0),
+                        )
+                        .with_detail("path", path.display())
+                        .with_detail("watermark", watermark),
+                    );
+                } else if !over && *alerting {
+                    tracing::info!(
+                        path = %path.display(),
+                        usage,
+                        "Disk usage is below the watermark again"
+                    );
+                    alerter.send(Alert::new(
+                        AlertKind::DiskWatermark,
+                        Severity::Resolved,
+                        format!("Disk of {} is {:.1}% full again", path.display(), usage * 100.0),
+                    ));
+                }
+                *alerting = over;
+            }
+        }
+    });
+}
 
-#[tokio::main]
-async fn run(mut args: ServerConfig) -> anyhow::Result<()> {
-    // Canonicalize paths to avoid repeated getcwd() syscalls in async operations
-    // This is critical for performance when using relative paths
-    args.fs_root = args.fs_root.canonicalize()
-        .unwrap_or_else(|_| {
-            std::fs::create_dir_all(&args.fs_root).ok();
-            args.fs_root.canonicalize()
-                .unwrap_or_else(|_| std::env::current_dir().unwrap().join(&args.fs_root))
-        });
+fn alerter(args: &ServerConfig) -> anyhow::Result<Alerter> {
+    let Some(path) = &args.alert_config else {
+        return Ok(Alerter::default());
+    };
+    let config = AlertConfig::load(path)?;
+    info!("Alerting enabled, {} webhook(s)", config.webhooks.len());
+    Alerter::start(config)
+}
 
-    args.meta_root = args.meta_root.canonicalize()
-        .unwrap_or_else(|_| {
-            std::fs::create_dir_all(&args.meta_root).ok();
-            args.meta_root.canonicalize()
-                .unwrap_or_else(|_| std::env::current_dir().unwrap().join(&args.meta_root))
-        });
+fn notifier(args: &ServerConfig) -> anyhow::Result<Notifier> {
+    let Some(path) = &args.notification_config else {
+        return Ok(Notifier::default());
+    };
+    let config = NotificationConfig::load(path)?;
+    info!("Bucket notifications enabled, {} command(s)", config.commands.len());
+    Ok(Notifier::start(config))
+}
 
-    info!("Using fs_root: {}", args.fs_root.display());
-    info!("Using meta_root: {}", args.meta_root.display());
+fn write_hooks(args: &ServerConfig) -> anyhow::Result<WriteHooks> {
+    let Some(path) = &args.write_hook_config else {
+        return Ok(WriteHooks::default());
+    };
+    let config = WriteHookConfig::load(path)?;
+    info!("Write hooks enabled, {} hook(s)", config.len());
+    WriteHooks::from_config(config)
+}
 
-    let storage_engine = args.metadata_db;
-    let metrics = s3_cas::metrics::SharedMetrics::new();
+fn bandwidth_throttle(args: &ServerConfig) -> anyhow::Result<Option<Arc<Throttle>>> {
+    let Some(path) = &args.bandwidth_schedule else {
+        return Ok(None);
+    };
+    info!("Bandwidth schedule enabled from {}", path.display());
+    Ok(Some(Throttle::watch(path.clone())?))
+}
 
-    // Check if single-user mode is explicitly requested
-    if args.access_key.is_some() && args.secret_key.is_some() {
-        info!("Single-user mode (explicit credentials provided)");
-        run_single_user(args, storage_engine, metrics).await
-    } else if args.access_key.is_some() || args.secret_key.is_some() {
+/// Quota and buckets of the new users, None without `--user-template`
+fn user_template(args: &ServerConfig) -> anyhow::Result<Option<UserTemplate>> {
+    let Some(path) = &args.user_template else {
+        return Ok(None);
+    };
+    let template = UserTemplate::load(path)?;
+    if template.needs_encryption() && !args.encrypted_at_rest {
         anyhow::bail!(
-            "Single-user mode requires both --access-key and --secret-key.\n\
-             Omit both for multi-user mode with database-backed authentication."
+            "User template {} requires bucket encryption, which needs --encrypted-at-rest",
+            path.display()
         );
-    } else {
-        info!("Multi-user mode (database-backed authentication)");
-        run_multi_user(args, storage_engine, metrics).await
     }
+    info!(
+        "User template enabled from {}, {} bucket(s)",
+        path.display(),
+        template.buckets.len()
+    );
+    Ok(Some(template))
+}
+
+fn network_policy(args: &ServerConfig) -> anyhow::Result<Arc<NetworkPolicy>> {
+    let mut policy = match &args.network_policy {
+        Some(path) => NetworkPolicy::load(path)?,
+        None => NetworkPolicy::default(),
+    };
+    policy.reject_anonymous |= args.reject_anonymous;
+    if policy.has_ip_rules() {
+        info!(
+            "Network policy enabled, {} bucket specific rule set(s)",
+            policy.buckets.len()
+        );
+    }
+    if policy.reject_anonymous {
+        info!("Anonymous S3 requests are rejected");
+    }
+    Ok(Arc::new(policy))
+}
+
+fn access_logger(args: &ServerConfig) -> anyhow::Result<Option<Arc<AccessLogger>>> {
+    let path = match &args.access_log {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let config = AccessLogConfig {
+        path: if path == "-" { None } else { Some(PathBuf::from(path)) },
+        format: args.access_log_format,
+        max_size: args.access_log_max_size,
+        max_files: args.access_log_max_files,
+        sample_rate: args.access_log_sample_rate,
+    };
+    info!("Access log enabled ({:?}) at {}", config.format, path);
+    Ok(Some(Arc::new(AccessLogger::new(config)?)))
+}
+
+fn slow_logger(
+    args: &ServerConfig,
+    metrics: &s3_cas::metrics::SharedMetrics,
+) -> anyhow::Result<Option<Arc<SlowLogger>>> {
+    let path = match &args.slow_log {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let config = SlowLogConfig {
+        path: if path == "-" { None } else { Some(PathBuf::from(path)) },
+        threshold: std::time::Duration::from_millis(args.slow_log_threshold_ms),
+        operation_thresholds: args
+            .slow_log_operation_thresholds
+            .iter()
+            .map(|(operation, ms)| (operation.clone(), std::time::Duration::from_millis(*ms)))
+            .collect(),
+        max_size: args.slow_log_max_size,
+        max_files: args.slow_log_max_files,
+    };
+    info!("Slow log enabled at {}, threshold {:?}", path, config.threshold);
+    Ok(Some(Arc::new(SlowLogger::new(config, metrics.clone())?)))
+}
+
+/// Buffers the access logs of buckets with logging enabled, delivered every
+/// `--bucket-log-interval-secs`. A read replica doesn't write to the buckets.
+fn bucket_logs<F>(args: &ServerConfig, stores: F) -> Option<Arc<BucketLogs>>
+where
+    F: Fn(Option<&str>) -> Option<(String, Arc<cas_storage::CasFS>)> + Send + Sync + 'static,
+{
+    if args.bucket_log_interval_secs == 0 || args.read_replica {
+        return None;
+    }
+    let logs = Arc::new(BucketLogs::new(stores));
+    let period = std::time::Duration::from_secs(args.bucket_log_interval_secs);
+    tokio::spawn(logs.clone().run(period));
+    Some(logs)
 }
 
 async fn run_single_user(
@@ -289,27 +1674,98 @@ async fn run_single_user(
     metrics: s3_cas::metrics::SharedMetrics,
 ) -> anyhow::Result<()> {
     // Original single-user implementation
-    let casfs = CasFS::new(
-        args.fs_root.clone(),
-        args.meta_root.clone(),
-        metrics.to_cas_metrics(),
-        storage_engine,
-        args.inline_metadata_size,
-        Some(args.durability),
-    );
-    let s3fs = s3_cas::s3fs::S3FS::new(Arc::new(casfs), metrics.clone());
+    if args.admin_port.is_some() {
+        anyhow::bail!("--admin-port is only supported in multi-user mode");
+    }
+    if args.user_template.is_some() {
+        anyhow::bail!("--user-template is only supported in multi-user mode");
+    }
+    let alerter = alerter(&args)?;
+    let throttle = bandwidth_throttle(&args)?;
+    spawn_disk_monitor(&args, alerter.clone());
+    let meta_executor = meta_executor(&args, &metrics);
+    let hash_pool = hash_pool(&args)?;
+    let group_sync = group_sync(&args, &metrics)?;
+    let mut builder = casfs_builder(&args, storage_engine, &metrics)
+        .meta_executor(meta_executor.clone())
+        .write_concurrency(args.write_concurrency)
+        .meta_cache(args.meta_cache_entries);
+    if let Some(pool) = &hash_pool {
+        builder = builder.hash_pool(pool.clone());
+    }
+    if let Some(limiter) = write_limiter(&args) {
+        builder = builder.write_limiter(limiter);
+    }
+    if let Some(group_sync) = &group_sync {
+        builder = builder.group_sync(group_sync.clone());
+    }
+    if let Some((lifetime, max_count)) = list_snapshots(&args) {
+        builder = builder.list_snapshots(lifetime, max_count);
+    }
+    let notifier = notifier(&args)?;
+    let events = notifier.event_handler(s3_cas::acl::DEFAULT_OWNER_ID);
+    if let Some(handler) = &events {
+        builder = builder.event_handler(handler.clone());
+    }
+    let casfs = Arc::new(builder.build()?);
+    {
+        let casfs = casfs.clone();
+        spawn_usage_sampler(&args, move || Ok(vec![casfs.clone()]));
+    }
+    {
+        let casfs = casfs.clone();
+        spawn_activity_flusher(&args, move || vec![casfs.clone()]);
+    }
+    let jobs = {
+        let casfs = casfs.clone();
+        job_manager(&args, casfs.jobs(), throttle.clone(), move || {
+            Ok(vec![casfs.clone()])
+        })?
+    };
+    spawn_blob_gc(&args, jobs);
+    {
+        let casfs = casfs.clone();
+        spawn_metadata_monitor(&args, metrics.clone(), alerter, move || {
+            Ok(vec![casfs.clone()])
+        });
+    }
+    {
+        let casfs = casfs.clone();
+        spawn_delete_purger(&args, metrics.clone(), move || Ok(Some(casfs.clone())));
+    }
+    let s3fs = s3_cas::s3fs::S3FS::new(casfs.clone(), metrics.clone())
+        .with_cache_control(cache_control(&args))
+        .with_cas_headers(args.cas_headers)
+        .with_encrypted_at_rest(args.encrypted_at_rest)
+        .with_list_limits(list_limits(&args)?)
+        .with_throttle(throttle)
+        .with_write_hooks(write_hooks(&args)?);
     let s3fs = s3_cas::metrics::MetricFs::new(s3fs, metrics.clone());
+    let bucket_logs = {
+        let casfs = casfs.clone();
+        bucket_logs(&args, move |_| {
+            Some((s3_cas::acl::DEFAULT_OWNER_ID.to_string(), casfs.clone()))
+        })
+    };
+    let s3fs = s3_cas::s3_wrapper::AccessLogS3::new(s3fs, access_logger(&args)?)
+        .with_slow_log(slow_logger(&args, &metrics)?)
+        .with_bucket_logs(bucket_logs.clone());
 
     // HTTP UI service (if enabled)
     let http_ui_service = if args.enable_http_ui {
-        let http_casfs = CasFS::new(
-            args.fs_root.clone(),
-            args.meta_root.clone(),
-            metrics.to_cas_metrics(),
-            storage_engine,
-            args.inline_metadata_size,
-            Some(args.durability),
-        );
+        let mut http_casfs = casfs_builder(&args, storage_engine, &metrics)
+            .meta_executor(meta_executor);
+        if let Some(pool) = hash_pool {
+            http_casfs = http_casfs.hash_pool(pool);
+        }
+        if let Some(group_sync) = &group_sync {
+            http_casfs = http_casfs.group_sync(group_sync.clone());
+        }
+        // objects written through the UI raise events too
+        if let Some(handler) = events {
+            http_casfs = http_casfs.event_handler(handler);
+        }
+        let http_casfs = http_casfs.build()?;
 
         let http_ui_username = args.http_ui_username.clone();
         let http_ui_password = args.http_ui_password.clone();
@@ -327,6 +1783,7 @@ async fn run_single_user(
                 metrics.clone(),
                 auth,
             )
+            .with_share_links(share_links(&args))
         ))
     } else {
         None
@@ -341,13 +1798,27 @@ async fn run_single_user(
         let secret_key = args.secret_key.clone();
         if let (Some(ak), Some(sk)) = (access_key, secret_key) {
             b.set_auth(s3s::auth::SimpleAuth::from_single(ak, sk));
+            // anonymous users can read objects with a public-read ACL
+            let acl_access = s3_cas::acl::AclAccess::new(casfs);
+            let access: Box<dyn s3s::access::S3Access> = if args.read_replica {
+                Box::new(ReadOnlyAccess::new(Some(Box::new(acl_access))))
+            } else {
+                Box::new(acl_access)
+            };
+            let access = signing_scope(&args, strict_sigv4(&args, access));
+            b.set_access(NetworkAccess::new(network_policy(&args)?, Some(access)));
             info!("authentication is enabled");
         }
 
         b.build()
     };
 
-    run_server(args, service, http_ui_service, metrics).await
+    let result = run_server(args, service, http_ui_service, None, metrics).await;
+    if let Some(bucket_logs) = bucket_logs {
+        bucket_logs.flush().await;
+    }
+    final_group_sync(group_sync).await;
+    result
 }
 
 async fn run_multi_user(
@@ -356,6 +1827,10 @@ async fn run_multi_user(
     metrics: s3_cas::metrics::SharedMetrics,
 ) -> anyhow::Result<()> {
     use s3_cas::auth::UserRouter;
+
+    let alerter = alerter(&args)?;
+    let throttle = bandwidth_throttle(&args)?;
+    spawn_disk_monitor(&args, alerter.clone());
     use cas_storage::SharedBlockStore;
     use s3_cas::s3_wrapper::DynamicS3Auth;
 
@@ -366,27 +1841,83 @@ async fn run_multi_user(
         args.meta_root.join("blocks"),
         storage_engine,
         args.inline_metadata_size,
-        Some(args.durability),
-    )?);
+        Some(store_durability(&args)),
+    )?
+    .with_metrics(metrics.to_cas_metrics()));
 
     // Create UserStore using the same storage backend as SharedBlockStore
-    let user_store = Arc::new(s3_cas::auth::UserStore::new(
+    let user_store = s3_cas::auth::UserStore::new(
         shared_block_store.meta_store().get_underlying_store()
-    ));
+    );
+    let user_store = Arc::new(match secret_cipher(&args)? {
+        Some(cipher) => user_store.with_secrets(cipher),
+        None => {
+            let sealed = user_store.sealed_secrets()?;
+            if sealed > 0 {
+                anyhow::bail!(
+                    "{} users have encrypted S3 secret keys, start with --secrets-master-key or --secrets-master-key-file",
+                    sealed
+                );
+            }
+            user_store
+        }
+    });
 
     // Create SessionStore for HTTP UI authentication
     let session_store = Arc::new(s3_cas::auth::SessionStore::new());
 
     // Create user router with lazy CasFS initialization
-    let user_router = Arc::new(UserRouter::new(
+    let user_router = UserRouter::new(
         shared_block_store.clone(),
         args.fs_root.clone(),
         args.meta_root.clone(),
         metrics.clone(),
         storage_engine,
         args.inline_metadata_size,
-        Some(args.durability),
-    ));
+        Some(store_durability(&args)),
+    )
+    .with_write_concurrency(args.write_concurrency)
+    .with_meta_executor(meta_executor(&args, &metrics))
+    .with_block_refs(args.block_refs_index)
+    .with_bucket_durability(args.bucket_durability.iter().cloned().collect())
+    .with_placement(args.storage_locations.clone(), args.prefix_placements.clone())
+    .with_kv_separation(kv_separation(&args))
+    .with_delete_grace(std::time::Duration::from_secs(args.delete_grace_secs))
+    .with_activity_flush(args.bucket_activity_flush_secs > 0 && !args.read_replica)
+    .with_notifier(notifier(&args)?);
+    let user_router = match write_limiter(&args) {
+        Some(limiter) => user_router.with_write_limiter(limiter),
+        None => user_router,
+    };
+    let group_sync = group_sync(&args, &metrics)?;
+    if let Some(group_sync) = &group_sync {
+        // the users and jobs are in the shared store, not only the blocks
+        group_sync.register_store(&shared_block_store.meta_store().get_underlying_store());
+    }
+    let user_router = match &group_sync {
+        Some(group_sync) => user_router.with_group_sync(group_sync.clone()),
+        None => user_router,
+    };
+    let user_router = match list_snapshots(&args) {
+        Some((lifetime, max_count)) => user_router.with_list_snapshots(lifetime, max_count),
+        None => user_router,
+    };
+    let user_router = match hash_pool(&args)? {
+        Some(pool) => user_router.with_hash_pool(pool),
+        None => user_router,
+    };
+    let user_router = match args.meta_cache_entries {
+        0 => user_router,
+        entries => user_router.with_meta_cache(entries),
+    };
+    let user_router = match args.max_open_users {
+        0 => user_router,
+        max => user_router.with_max_open_users(max),
+    };
+    let user_router = Arc::new(match args.user_idle_timeout_secs {
+        0 => user_router,
+        secs => user_router.with_idle_timeout(std::time::Duration::from_secs(secs)),
+    });
 
     let user_count = user_store.count_users()?;
     if user_count == 0 {
@@ -400,29 +1931,94 @@ async fn run_multi_user(
     let s3_user_router = s3_cas::s3_wrapper::S3UserRouter::new(
         user_router.clone(),
         user_store.clone(),
-    );
+    )
+    .with_cache_control(cache_control(&args))
+    .with_cas_headers(args.cas_headers)
+    .with_encrypted_at_rest(args.encrypted_at_rest)
+    .with_list_limits(list_limits(&args)?)
+    .with_throttle(throttle.clone())
+    .with_write_hooks(write_hooks(&args)?);
     let s3_service = s3_cas::metrics::MetricFs::new(s3_user_router, metrics.clone());
+    let bucket_logs = {
+        let user_router = user_router.clone();
+        let user_store = user_store.clone();
+        bucket_logs(&args, move |access_key| {
+            let user = user_store.get_user_by_s3_key(access_key?).ok()??;
+            let casfs = user_router.get_casfs_by_user_id(&user.user_id).ok()?;
+            Some((user.user_id, casfs))
+        })
+    };
+    let s3_service = s3_cas::s3_wrapper::AccessLogS3::new(s3_service, access_logger(&args)?)
+        .with_slow_log(slow_logger(&args, &metrics)?)
+        .with_bucket_logs(bucket_logs.clone());
+
+    let jobs = {
+        let user_router = user_router.clone();
+        let user_store = user_store.clone();
+        let store = cas_storage::JobStore::new(shared_block_store.meta_store().as_ref().clone());
+        job_manager(&args, store, throttle, move || {
+            user_store
+                .list_users()?
+                .iter()
+                .map(|user| Ok(user_router.get_casfs_for_maintenance(&user.user_id)?))
+                .collect()
+        })?
+    };
 
     // HTTP UI service (if enabled) - multi-user with session-based auth
-    let http_ui_service = if args.enable_http_ui {
+    let http_ui = if args.enable_http_ui {
         info!("HTTP UI enabled with session-based authentication");
-        Some(s3_cas::http_ui::HttpUiServiceWrapper::MultiUser(
+        Some(
             s3_cas::http_ui::HttpUiServiceMultiUser::new(
                 user_router.clone(),
                 user_store.clone(),
                 session_store.clone(),
                 metrics.clone(),
             )
-        ))
+            .with_admin_token(admin_token(&args))
+            .with_read_only(args.read_replica)
+            .with_alerter(alerter.clone())
+            .with_jobs(jobs.clone())
+            .with_share_links(share_links(&args))
+            .with_signups(args.allow_signup.then(|| {
+                s3_cas::auth::SignupStore::new(shared_block_store.meta_store().get_underlying_store())
+                    .with_max_pending(args.max_pending_signups)
+            }))
+            .with_user_template(user_template(&args)?)
+            .with_audit_log(Arc::new(s3_cas::auth::AuditLog::new(
+                shared_block_store.meta_store().get_underlying_store(),
+            ))),
+        )
     } else {
         None
     };
+    // with an admin listener, the HTTP UI port serves no admin routes
+    let (http_ui_service, admin_ui_service) = match (http_ui, args.admin_port) {
+        (Some(ui), Some(_)) => (
+            Some(ui.clone().with_listener(UiListener::Tenant)),
+            Some(ui.with_listener(UiListener::Admin)),
+        ),
+        (ui, _) => (ui, None),
+    };
+    let http_ui_service = http_ui_service.map(s3_cas::http_ui::HttpUiServiceWrapper::MultiUser);
+    let admin_ui_service = admin_ui_service.map(s3_cas::http_ui::HttpUiServiceWrapper::MultiUser);
 
     // Setup S3 service with dynamic authentication
     let service = {
-        let auth = DynamicS3Auth::new(user_store.clone());
+        let auth = DynamicS3Auth::new(user_store.clone()).with_sig_v2(args.allow_sig_v2);
         let mut b = s3s::service::S3ServiceBuilder::new(s3_service);
+        let access: Option<Box<dyn s3s::access::S3Access>> = if args.read_replica {
+            Some(Box::new(ReadOnlyAccess::new(None)))
+        } else {
+            None
+        };
+        let access = strict_sigv4(&args, Box::new(auth.sig_v2_access(access)));
+        let access = signing_scope(&args, access);
         b.set_auth(auth);
+        b.set_access(NetworkAccess::new(network_policy(&args)?, Some(access)));
+        if args.allow_sig_v2 {
+            info!("SigV2 authentication enabled for users which opted in");
+        }
         info!("Multi-user S3 service enabled with dynamic authentication");
         b.build()
     };
@@ -451,13 +2047,208 @@ async fn run_multi_user(
         info!("Started background session cleanup and metrics task");
     }
 
-    run_server(args, service, http_ui_service, metrics).await
+    // Close the metadata stores of idle users
+    if let Some(timeout) = user_router.idle_timeout() {
+        let user_router = user_router.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval((timeout / 4).clamp(
+                std::time::Duration::from_secs(1),
+                std::time::Duration::from_secs(60),
+            ));
+            loop {
+                interval.tick().await;
+                let user_router = user_router.clone();
+                match tokio::task::spawn_blocking(move || user_router.evict_idle()).await {
+                    Ok(0) => {}
+                    Ok(closed) => tracing::debug!(closed, "Closed the stores of idle users"),
+                    Err(e) => tracing::error!(error = %e, "Closing the stores of idle users panicked"),
+                }
+            }
+        });
+    }
+
+    {
+        let user_router = user_router.clone();
+        let user_store = user_store.clone();
+        spawn_usage_sampler(&args, move || {
+            user_store
+                .list_users()?
+                .iter()
+                .map(|user| Ok(user_router.get_casfs_for_maintenance(&user.user_id)?))
+                .collect()
+        });
+    }
+    {
+        // only open stores can have activity which isn't stored yet
+        let user_router = user_router.clone();
+        spawn_activity_flusher(&args, move || {
+            user_router
+                .open_stores()
+                .into_iter()
+                .map(|(_, casfs)| casfs)
+                .collect()
+        });
+    }
+    spawn_blob_gc(&args, jobs);
+    {
+        let user_router = user_router.clone();
+        let user_store = user_store.clone();
+        spawn_metadata_monitor(&args, metrics.clone(), alerter.clone(), move || {
+            user_store
+                .list_users()?
+                .iter()
+                .map(|user| Ok(user_router.get_casfs_for_maintenance(&user.user_id)?))
+                .collect()
+        });
+    }
+    {
+        let user_router = user_router.clone();
+        let user_store = user_store.clone();
+        // the delete queue is in the shared block store, any user's CasFS purges it
+        spawn_delete_purger(&args, metrics.clone(), move || {
+            user_store
+                .list_users()?
+                .first()
+                .map(|user| Ok(user_router.get_casfs_for_maintenance(&user.user_id)?))
+                .transpose()
+        });
+    }
+
+    let result = run_server(args, service, http_ui_service, admin_ui_service, metrics).await;
+    if let Some(bucket_logs) = bucket_logs {
+        bucket_logs.flush().await;
+    }
+    final_group_sync(group_sync).await;
+    result
+}
+
+/// Wraps `access` in the clock skew and replay checks of `--strict-sigv4`
+fn strict_sigv4(
+    args: &ServerConfig,
+    access: Box<dyn s3s::access::S3Access>,
+) -> Box<dyn s3s::access::S3Access> {
+    if !args.strict_sigv4 {
+        return access;
+    }
+    let max_skew = std::time::Duration::from_secs(args.sigv4_max_skew_secs);
+    info!(
+        max_skew_secs = args.sigv4_max_skew_secs,
+        "Strict SigV4 clock skew and replay checks enabled"
+    );
+    let guard = Arc::new(ReplayGuard::new(max_skew));
+    Box::new(StrictSigV4Access::new(guard, Some(access)))
+}
+
+/// Wraps `access` in the region and service checks of `--signing-region` and
+/// `--signing-service`
+fn signing_scope(
+    args: &ServerConfig,
+    access: Box<dyn s3s::access::S3Access>,
+) -> Box<dyn s3s::access::S3Access> {
+    let scope = SigningScope::new(args.signing_region.clone(), args.signing_service.clone());
+    if scope.is_open() {
+        return access;
+    }
+    info!("Only S3 requests signed for {scope} are accepted");
+    Box::new(SigningScopeAccess::new(Arc::new(scope), Some(access)))
+}
+
+fn metrics_auth(args: &ServerConfig) -> anyhow::Result<Arc<MetricsAuth>> {
+    let mut auth = MetricsAuth::default()
+        .with_token(args.metrics_token.clone())
+        .with_allow(args.metrics_allow.clone());
+    if let Some(path) = &args.metrics_token_file {
+        auth = auth.with_token_file(path)?;
+    }
+    if auth.is_restricted() {
+        info!("Metrics endpoint requires a token or an allowed address");
+    }
+    Ok(Arc::new(auth))
+}
+
+/// Acceptor of the TLS connections of a listener, None to serve plain HTTP
+fn tls_acceptor(
+    cert: &Option<PathBuf>,
+    key: &Option<PathBuf>,
+    client_ca: &Option<PathBuf>,
+) -> anyhow::Result<Option<tokio_rustls::TlsAcceptor>> {
+    let (Some(cert), Some(key)) = (cert, key) else {
+        return Ok(None);
+    };
+    let files = s3_cas::tls::TlsFiles {
+        cert,
+        key,
+        client_ca: client_ca.as_deref(),
+    };
+    Ok(Some(s3_cas::tls::acceptor(&files)?))
+}
+
+fn metrics_tls(args: &ServerConfig) -> anyhow::Result<Option<tokio_rustls::TlsAcceptor>> {
+    if args.metrics_tls_client_ca.is_some() {
+        info!("Metrics scrapers must present a client certificate");
+    }
+    tls_acceptor(
+        &args.metrics_tls_cert,
+        &args.metrics_tls_key,
+        &args.metrics_tls_client_ca,
+    )
+}
+
+fn admin_tls(args: &ServerConfig) -> anyhow::Result<Option<tokio_rustls::TlsAcceptor>> {
+    if args.admin_tls_client_ca.is_some() {
+        info!("Admin clients must present a client certificate");
+    }
+    tls_acceptor(
+        &args.admin_tls_cert,
+        &args.admin_tls_key,
+        &args.admin_tls_client_ca,
+    )
+}
+
+/// Response to a scrape of the metrics server
+fn metrics_response(
+    auth: &MetricsAuth,
+    peer: std::net::SocketAddr,
+    req: &hyper::Request<hyper::body::Incoming>,
+) -> hyper::Response<Full<Bytes>> {
+    match (req.method(), req.uri().path()) {
+        (&hyper::Method::GET, "/metrics") => {
+            if let Err(status) = auth.check(peer.ip(), req.headers()) {
+                tracing::debug!(%peer, %status, "Rejected metrics scrape");
+                let mut response = hyper::Response::builder().status(status);
+                if status == hyper::StatusCode::UNAUTHORIZED {
+                    response = response.header(hyper::header::WWW_AUTHENTICATE, "Bearer");
+                }
+                return response
+                    .body(Full::new(Bytes::from(
+                        status.canonical_reason().unwrap_or_default(),
+                    )))
+                    .unwrap();
+            }
+
+            let mut buffer = Vec::new();
+            let encoder = prometheus::TextEncoder::new();
+            let metric_families = prometheus::gather();
+            encoder.encode(&metric_families, &mut buffer).unwrap();
+
+            hyper::Response::builder()
+                .status(200)
+                .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(Full::new(Bytes::from(buffer)))
+                .unwrap()
+        }
+        _ => hyper::Response::builder()
+            .status(404)
+            .body(Full::new(Bytes::from("Not Found")))
+            .unwrap(),
+    }
 }
 
 async fn run_server(
     args: ServerConfig,
     service: s3s::service::S3Service,
     http_ui_service: Option<s3_cas::http_ui::HttpUiServiceWrapper>,
+    admin_ui_service: Option<s3_cas::http_ui::HttpUiServiceWrapper>,
     _metrics: s3_cas::metrics::SharedMetrics,
 ) -> anyhow::Result<()> {
 
@@ -468,13 +2259,19 @@ async fn run_server(
 
     let hyper_service = service.into_shared();
 
-    // metrics server
-    // Add after the main listener setup
-    let metrics_listener =
-        tokio::net::TcpListener::bind((args.metric_host.as_str(), args.metric_port)).await?;
-    let metrics_addr = metrics_listener.local_addr()?;
-
-    info!("metrics server is running at http://{metrics_addr}");
+    // metrics server, only needed when prometheus scrapes us
+    let (metrics_listener, metrics_tls) = if args.metrics_backend == MetricsBackend::Prometheus {
+        let listener =
+            tokio::net::TcpListener::bind((args.metric_host.as_str(), args.metric_port)).await?;
+        let metrics_addr = listener.local_addr()?;
+        let tls = metrics_tls(&args)?;
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        info!("metrics server is running at {scheme}://{metrics_addr}");
+        (Some(listener), tls)
+    } else {
+        (None, None)
+    };
+    let metrics_auth = metrics_auth(&args)?;
 
     // HTTP UI server (optional)
     let http_ui_listener = if args.enable_http_ui {
@@ -487,32 +2284,18 @@ async fn run_server(
         None
     };
 
-    let metrics_service = hyper::service::service_fn(
-        move |req: hyper::Request<hyper::body::Incoming>| async move {
-            match (req.method(), req.uri().path()) {
-                (&hyper::Method::GET, "/metrics") => {
-                    let mut buffer = Vec::new();
-                    let encoder = prometheus::TextEncoder::new();
-                    let metric_families = prometheus::gather();
-                    encoder.encode(&metric_families, &mut buffer).unwrap();
-
-                    Ok::<_, std::convert::Infallible>(
-                        hyper::Response::builder()
-                            .status(200)
-                            .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
-                            .body(Full::new(Bytes::from(buffer)))
-                            .unwrap(),
-                    )
-                }
-                _ => Ok::<_, std::convert::Infallible>(
-                    hyper::Response::builder()
-                        .status(404)
-                        .body(Full::new(Bytes::from("Not Found")))
-                        .unwrap(),
-                ),
-            }
-        },
-    );
+    // admin listener, serving the admin routes of the HTTP UI
+    let (admin_listener, admin_tls) = match (&admin_ui_service, args.admin_port) {
+        (Some(_), Some(port)) => {
+            let listener = tokio::net::TcpListener::bind((args.admin_host.as_str(), port)).await?;
+            let addr = listener.local_addr()?;
+            let tls = admin_tls(&args)?;
+            let scheme = if tls.is_some() { "https" } else { "http" };
+            info!("admin server is running at {scheme}://{addr}");
+            (Some(listener), tls)
+        }
+        _ => (None, None),
+    };
 
     let http_server = ConnBuilder::new(TokioExecutor::new());
     let graceful = hyper_util::server::graceful::GracefulShutdown::new();
@@ -525,8 +2308,22 @@ async fn run_server(
         tokio::select! {
             res = listener.accept() => {
                 match res {
-                    Ok((socket,_)) => {
-                        let conn = http_server.serve_connection(TokioIo::new(socket), hyper_service.clone());
+                    Ok((socket, peer)) => {
+                        // the network policy checks the address of the client
+                        let service = hyper_service.clone();
+                        let s3_handler = hyper::service::service_fn(
+                            move |mut req: hyper::Request<hyper::body::Incoming>| {
+                                req.extensions_mut().insert(RequestStart(std::time::Instant::now()));
+                                req.extensions_mut().insert(RemoteAddr(peer));
+                                take_multi_range(&mut req);
+                                mark_sig_v2(&mut req);
+                                mark_sig_v4(&mut req);
+                                mark_credential_scope(&mut req);
+                                let service = service.clone();
+                                async move { service.call(req).await }
+                            },
+                        );
+                        let conn = http_server.serve_connection(TokioIo::new(socket), s3_handler);
                         let conn = graceful.watch(conn.into_owned());
                         tokio::spawn(async move {
                             let _ = conn.await;
@@ -539,17 +2336,44 @@ async fn run_server(
                     }
                 }
             }
-            res = metrics_listener.accept() => {
+            res = async {
+                match &metrics_listener {
+                    Some(listener) => listener.accept().await,
+                    None => std::future::pending().await,
+                }
+            } => {
                 match res {
-                    Ok((socket, _)) =>{
-                        let conn = http_server.serve_connection(TokioIo::new(socket), metrics_service);
-                        let conn = graceful.watch(conn.into_owned());
+                    Ok((socket, peer)) => {
+                        let auth = metrics_auth.clone();
+                        let metrics_service = hyper::service::service_fn(
+                            move |req: hyper::Request<hyper::body::Incoming>| {
+                                let response = metrics_response(&auth, peer, &req);
+                                async move { Ok::<_, std::convert::Infallible>(response) }
+                            },
+                        );
+                        let Some(tls) = metrics_tls.clone() else {
+                            let conn = http_server.serve_connection(TokioIo::new(socket), metrics_service);
+                            let conn = graceful.watch(conn.into_owned());
+                            tokio::spawn(async move {
+                                let _ = conn.await;
+                            });
+                            continue;
+                        };
+                        // the handshake runs in the task so it can't hold up the accept loop,
+                        // scrapes are short and not waited for on shutdown
+                        let http_server = http_server.clone();
                         tokio::spawn(async move {
-                            let _ = conn.await;
+                            match tls.accept(socket).await {
+                                Ok(stream) => {
+                                    let _ = http_server
+                                        .serve_connection(TokioIo::new(stream), metrics_service)
+                                        .await;
+                                }
+                                Err(err) => tracing::debug!(%peer, "Metrics TLS handshake failed: {err}"),
+                            }
                         });
                         continue;
-
-                    }// (socket, metrics_service.clone()),
+                    }
                     Err(err) => {
                         tracing::error!("error accepting metrics connection: {err}");
                         continue;
@@ -584,6 +2408,49 @@ async fn run_server(
                     }
                 }
             }
+            res = async {
+                match &admin_listener {
+                    Some(listener) => listener.accept().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                if let Some(ref service) = admin_ui_service {
+                    match res {
+                        Ok((socket, peer)) => {
+                            let service_clone = service.clone();
+                            let admin_handler = hyper::service::service_fn(move |req| {
+                                let service = service_clone.clone();
+                                async move { service.handle_request(req).await }
+                            });
+                            let Some(tls) = admin_tls.clone() else {
+                                let conn = http_server.serve_connection(TokioIo::new(socket), admin_handler);
+                                let conn = graceful.watch(conn.into_owned());
+                                tokio::spawn(async move {
+                                    let _ = conn.await;
+                                });
+                                continue;
+                            };
+                            // the handshake runs in the task so it can't hold up the accept loop
+                            let http_server = http_server.clone();
+                            tokio::spawn(async move {
+                                match tls.accept(socket).await {
+                                    Ok(stream) => {
+                                        let _ = http_server
+                                            .serve_connection(TokioIo::new(stream), admin_handler)
+                                            .await;
+                                    }
+                                    Err(err) => tracing::warn!(%peer, "Admin TLS handshake failed: {err}"),
+                                }
+                            });
+                            continue;
+                        }
+                        Err(err) => {
+                            tracing::error!("error accepting admin connection: {err}");
+                            continue;
+                        }
+                    }
+                }
+            }
             _ = ctrl_c.as_mut() => {
                 break;
             }
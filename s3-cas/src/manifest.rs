@@ -0,0 +1,152 @@
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser};
+
+use cas_storage::{CasFS, CasFSBuilder, ManifestEntry, StorageEngine};
+use crate::metrics::SharedMetrics;
+use crate::placement::parse_storage_location;
+
+/// The store to export from or import into
+#[derive(Args, Debug)]
+pub struct StoreArgs {
+    #[arg(long, default_value = ".")]
+    pub meta_root: PathBuf,
+
+    #[arg(long, default_value = ".")]
+    pub fs_root: PathBuf,
+
+    #[arg(
+        long,
+        default_value = "fjall",
+        help = "Metadata DB  (fjall, fjall_notx)"
+    )]
+    pub metadata_db: StorageEngine,
+
+    #[arg(
+        long = "storage-location",
+        value_name = "NAME=PATH",
+        value_parser = parse_storage_location,
+        help = "Storage location of the server holding placed blocks. Can be repeated"
+    )]
+    pub storage_locations: Vec<(String, PathBuf)>,
+}
+
+impl StoreArgs {
+    pub(crate) fn open(&self) -> Result<CasFS> {
+        let metrics = SharedMetrics::new();
+        let mut builder = CasFSBuilder::new(&self.fs_root, &self.meta_root)
+            .metrics(metrics.to_cas_metrics())
+            .storage_engine(self.metadata_db);
+        for (name, path) in &self.storage_locations {
+            builder = builder.storage_location(name.clone(), path.clone());
+        }
+        Ok(builder.build()?)
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportConfig {
+    #[command(flatten)]
+    pub store: StoreArgs,
+
+    #[arg(required = true, help = "Bucket name")]
+    pub bucket: String,
+
+    #[arg(long, short, help = "Manifest file to write, stdout if not set")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ImportConfig {
+    #[command(flatten)]
+    pub store: StoreArgs,
+
+    #[arg(required = true, help = "Bucket name, created if it doesn't exist")]
+    pub bucket: String,
+
+    #[arg(long, short, help = "Manifest file to read, stdin if not set")]
+    pub input: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Read the block files new to this store and check they match their id"
+    )]
+    pub verify: bool,
+}
+
+/// Write the manifest of the objects of a bucket, one JSON entry per line
+pub fn export_bucket(args: ExportConfig) -> Result<()> {
+    let casfs = args.store.open()?;
+    if !casfs.bucket_exists(&args.bucket)? {
+        bail!("Bucket {} not found", args.bucket);
+    }
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(
+            std::fs::File::create(path)
+                .with_context(|| format!("Can't create {}", path.display()))?,
+        )),
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+
+    // a snapshot, so objects written meanwhile don't mix into the manifest
+    let snapshot = casfs.get_bucket(&args.bucket)?.snapshot();
+    let mut count = 0;
+    for (key, obj) in snapshot.range_filter(None, None, None) {
+        let entry = casfs
+            .manifest_entry(&key, &obj)
+            .with_context(|| format!("Can't export {key}"))?;
+        serde_json::to_writer(&mut out, &entry)?;
+        out.write_all(b"\n")?;
+        count += 1;
+    }
+    out.flush()?;
+
+    eprintln!("Exported {count} objects of bucket {}", args.bucket);
+    Ok(())
+}
+
+/// Attach the objects of a manifest to a bucket, the block files of the exported
+/// store must have been copied to this one
+#[tokio::main]
+pub async fn import_bucket(args: ImportConfig) -> Result<()> {
+    let casfs = args.store.open()?;
+    if !casfs.bucket_exists(&args.bucket)? {
+        casfs.create_bucket(&args.bucket)?;
+    }
+
+    let input: Box<dyn BufRead> = match &args.input {
+        Some(path) => Box::new(BufReader::new(
+            std::fs::File::open(path).with_context(|| format!("Can't open {}", path.display()))?,
+        )),
+        None => Box::new(BufReader::new(std::io::stdin())),
+    };
+
+    let (mut imported, mut failed) = (0, 0);
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: ManifestEntry = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid manifest entry on line {}", number + 1))?;
+        match casfs
+            .import_manifest_entry(&args.bucket, &entry, args.verify)
+            .await
+        {
+            Ok(_) => imported += 1,
+            Err(e) => {
+                eprintln!("Can't import {}: {e}", entry.key);
+                failed += 1;
+            }
+        }
+    }
+
+    eprintln!("Imported {imported} objects into bucket {}", args.bucket);
+    if failed > 0 {
+        bail!("{failed} objects could not be imported");
+    }
+    Ok(())
+}
@@ -0,0 +1,616 @@
+mod prometheus_backend;
+mod statsd_backend;
+
+pub use prometheus_backend::PrometheusMetrics;
+pub use statsd_backend::StatsdMetrics;
+
+use async_trait::async_trait;
+use cas_storage::{Access, MetricsCollector};
+use s3s::dto::*;
+use s3s::S3;
+use s3s::{S3Request, S3Response, S3Result};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{ops::Deref, sync::Arc};
+
+const S3_API_METHODS: &[&str] = &[
+    "complete_multipart_upload",
+    "copy_object",
+    "create_multipart_upload",
+    "create_bucket",
+    "delete_bucket",
+    "delete_object",
+    "delete_objects",
+    "get_bucket_location",
+    "get_object",
+    "head_bucket",
+    "head_object",
+    "list_buckets",
+    "list_objects",
+    "list_objects_v2",
+    "put_object",
+    "upload_part",
+];
+
+/// Label used for buckets once the bucket label cardinality limit is reached.
+pub const OVERFLOW_BUCKET_LABEL: &str = "_other";
+
+/// Default amount of distinct bucket labels tracked by latency metrics.
+pub const DEFAULT_MAX_BUCKET_LABELS: usize = 100;
+
+/// Metrics collector for the S3 layer.
+///
+/// Extends the block level [`MetricsCollector`] from cas-storage with the S3 API
+/// and authentication metrics, so the whole server can be pointed at a single
+/// backend (Prometheus, StatsD, or nothing at all).
+pub trait S3MetricsCollector: MetricsCollector {
+    fn add_method_call(&self, call_name: &str);
+    fn set_bucket_count(&self, count: usize);
+    fn inc_bucket_count(&self);
+    fn dec_bucket_count(&self);
+    /// Record how long an S3 operation took. `bucket` is already passed through
+    /// the cardinality guard, so implementations can use it as a label as is.
+    fn record_latency(&self, operation: &str, bucket: &str, duration: Duration);
+    fn record_login_attempt(&self, success: bool);
+    fn set_active_sessions(&self, count: usize);
+    fn record_admin_operation(&self, operation: &str);
+    /// Set the disk space of a metadata tree. `tree` is already passed through the
+    /// bucket label cardinality guard.
+    fn set_metadata_tree_size(&self, tree: &str, bytes: u64);
+    fn set_metadata_ratio(&self, ratio: f64);
+    /// Set the amount of users with an open metadata store (multi-user mode).
+    fn set_open_user_stores(&self, count: usize);
+    /// Count a request to a bucket and the object data it transferred. `bucket`
+    /// is already passed through the bucket label cardinality guard.
+    fn record_bucket_access(&self, bucket: &str, access: Access, bytes: u64);
+    /// Count a request written to the slow log.
+    fn record_slow_request(&self, operation: &str);
+    /// Record the scan of an uploaded object, by `result` (clean, infected or
+    /// failed), with the data scanned and the time the scan took.
+    fn record_scan(&self, result: &str, bytes: u64, duration: Duration);
+}
+
+/// Collector which discards all metrics.
+#[derive(Debug, Clone, Default)]
+pub struct NoOpMetrics;
+
+impl MetricsCollector for NoOpMetrics {
+    fn block_pending(&self) {}
+    fn block_written(&self) {}
+    fn block_write_error(&self) {}
+    fn block_ignored(&self) {}
+    fn blocks_dropped(&self, _amount: u64) {}
+    fn bytes_sent(&self, _amount: usize) {}
+    fn bytes_received(&self, _amount: usize) {}
+}
+
+impl S3MetricsCollector for NoOpMetrics {
+    fn add_method_call(&self, _call_name: &str) {}
+    fn set_bucket_count(&self, _count: usize) {}
+    fn inc_bucket_count(&self) {}
+    fn dec_bucket_count(&self) {}
+    fn record_latency(&self, _operation: &str, _bucket: &str, _duration: Duration) {}
+    fn record_login_attempt(&self, _success: bool) {}
+    fn set_active_sessions(&self, _count: usize) {}
+    fn record_admin_operation(&self, _operation: &str) {}
+    fn set_metadata_tree_size(&self, _tree: &str, _bytes: u64) {}
+    fn set_metadata_ratio(&self, _ratio: f64) {}
+    fn set_open_user_stores(&self, _count: usize) {}
+    fn record_bucket_access(&self, _bucket: &str, _access: Access, _bytes: u64) {}
+    fn record_slow_request(&self, _operation: &str) {}
+    fn record_scan(&self, _result: &str, _bytes: u64, _duration: Duration) {}
+}
+
+/// Metrics backend selectable on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsBackend {
+    Prometheus,
+    Statsd,
+    None,
+}
+
+impl FromStr for MetricsBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "prometheus" => Ok(MetricsBackend::Prometheus),
+            "statsd" => Ok(MetricsBackend::Statsd),
+            "none" => Ok(MetricsBackend::None),
+            _ => Err(format!(
+                "Invalid metrics backend: {}, valid values are: prometheus, statsd, none",
+                s
+            )),
+        }
+    }
+}
+
+/// Limits the amount of distinct bucket names used as metric labels.
+///
+/// The first `max` buckets seen keep their own label, everything after that is
+/// folded into [`OVERFLOW_BUCKET_LABEL`].
+#[derive(Debug)]
+pub struct BucketLabelGuard {
+    max: usize,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl BucketLabelGuard {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            seen: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn label(&self, bucket: &str) -> String {
+        let mut seen = self.seen.lock().expect("bucket label lock is not poisoned");
+        if seen.contains(bucket) {
+            return bucket.to_string();
+        }
+        if seen.len() < self.max {
+            seen.insert(bucket.to_string());
+            return bucket.to_string();
+        }
+        OVERFLOW_BUCKET_LABEL.to_string()
+    }
+}
+
+#[derive(Clone)]
+pub struct SharedMetrics {
+    collector: Arc<dyn S3MetricsCollector>,
+    cas_metrics: cas_storage::SharedMetrics,
+    bucket_labels: Arc<BucketLabelGuard>,
+}
+
+impl SharedMetrics {
+    /// Prometheus backed metrics, registered in the default registry.
+    pub fn new() -> Self {
+        Self::from_collector(PrometheusMetrics::new(), DEFAULT_MAX_BUCKET_LABELS)
+    }
+
+    /// Metrics which are discarded.
+    pub fn noop() -> Self {
+        Self::from_collector(NoOpMetrics, DEFAULT_MAX_BUCKET_LABELS)
+    }
+
+    pub fn from_collector<C>(collector: C, max_bucket_labels: usize) -> Self
+    where
+        C: S3MetricsCollector + 'static,
+    {
+        let collector = Arc::new(collector);
+        Self {
+            cas_metrics: cas_storage::SharedMetrics::new(collector.clone()),
+            collector,
+            bucket_labels: Arc::new(BucketLabelGuard::new(max_bucket_labels)),
+        }
+    }
+
+    /// Convert to cas_storage::SharedMetrics
+    pub fn to_cas_metrics(&self) -> cas_storage::SharedMetrics {
+        self.cas_metrics.clone()
+    }
+
+    /// Record the latency of an operation on a bucket, applying the bucket label
+    /// cardinality guard.
+    pub fn observe_operation(&self, operation: &str, bucket: &str, duration: Duration) {
+        let bucket = self.bucket_labels.label(bucket);
+        self.collector.record_latency(operation, &bucket, duration);
+    }
+
+    /// Count a request to a bucket, applying the bucket label cardinality guard.
+    pub fn observe_bucket_access(&self, bucket: &str, access: Access, bytes: u64) {
+        let bucket = self.bucket_labels.label(bucket);
+        self.collector.record_bucket_access(&bucket, access, bytes);
+    }
+
+    /// Set the metadata tree sizes and the metadata to data ratio. Bucket trees go
+    /// through the bucket label cardinality guard, the ones sharing a label are summed.
+    pub fn observe_metadata_size(&self, size: &cas_storage::MetadataSize) {
+        let mut trees: HashMap<String, u64> = HashMap::new();
+        for tree in &size.trees {
+            let label = if tree.name.starts_with('_') {
+                tree.name.clone()
+            } else {
+                self.bucket_labels.label(&tree.name)
+            };
+            *trees.entry(label).or_default() += tree.bytes;
+        }
+        for (tree, bytes) in trees {
+            self.collector.set_metadata_tree_size(&tree, bytes);
+        }
+        self.collector.set_metadata_ratio(size.ratio());
+    }
+}
+
+impl Default for SharedMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for SharedMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedMetrics")
+            .field("bucket_labels", &self.bucket_labels)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Deref for SharedMetrics {
+    type Target = dyn S3MetricsCollector;
+
+    fn deref(&self) -> &Self::Target {
+        self.collector.as_ref()
+    }
+}
+
+pub struct MetricFs<T> {
+    storage: T,
+    metrics: SharedMetrics,
+}
+
+impl<T> MetricFs<T> {
+    pub fn new(storage: T, metrics: SharedMetrics) -> Self {
+        Self { storage, metrics }
+    }
+}
+
+#[async_trait]
+impl<T> S3 for MetricFs<T>
+where
+    T: S3 + Sync + Send,
+{
+    async fn complete_multipart_upload(
+        &self,
+        req: S3Request<CompleteMultipartUploadInput>,
+    ) -> S3Result<S3Response<CompleteMultipartUploadOutput>> {
+        self.metrics.add_method_call("complete_multipart_upload");
+        self.storage.complete_multipart_upload(req).await
+    }
+
+    async fn copy_object(
+        &self,
+        req: S3Request<CopyObjectInput>,
+    ) -> S3Result<S3Response<CopyObjectOutput>> {
+        self.metrics.add_method_call("copy_object");
+        self.storage.copy_object(req).await
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        req: S3Request<CreateMultipartUploadInput>,
+    ) -> S3Result<S3Response<CreateMultipartUploadOutput>> {
+        self.metrics.add_method_call("create_multipart_upload");
+        self.storage.create_multipart_upload(req).await
+    }
+
+    async fn create_bucket(
+        &self,
+        req: S3Request<CreateBucketInput>,
+    ) -> S3Result<S3Response<CreateBucketOutput>> {
+        self.metrics.add_method_call("create_bucket");
+        self.storage.create_bucket(req).await
+    }
+
+    async fn delete_bucket(
+        &self,
+        req: S3Request<DeleteBucketInput>,
+    ) -> S3Result<S3Response<DeleteBucketOutput>> {
+        self.metrics.add_method_call("delete_bucket");
+        self.storage.delete_bucket(req).await
+    }
+
+    async fn delete_bucket_encryption(
+        &self,
+        req: S3Request<DeleteBucketEncryptionInput>,
+    ) -> S3Result<S3Response<DeleteBucketEncryptionOutput>> {
+        self.metrics.add_method_call("delete_bucket_encryption");
+        self.storage.delete_bucket_encryption(req).await
+    }
+
+    async fn delete_bucket_lifecycle(
+        &self,
+        req: S3Request<DeleteBucketLifecycleInput>,
+    ) -> S3Result<S3Response<DeleteBucketLifecycleOutput>> {
+        self.metrics.add_method_call("delete_bucket_lifecycle");
+        self.storage.delete_bucket_lifecycle(req).await
+    }
+
+    async fn delete_bucket_tagging(
+        &self,
+        req: S3Request<DeleteBucketTaggingInput>,
+    ) -> S3Result<S3Response<DeleteBucketTaggingOutput>> {
+        self.metrics.add_method_call("delete_bucket_tagging");
+        self.storage.delete_bucket_tagging(req).await
+    }
+
+    async fn delete_object(
+        &self,
+        req: S3Request<DeleteObjectInput>,
+    ) -> S3Result<S3Response<DeleteObjectOutput>> {
+        self.metrics.add_method_call("delete_object");
+        let bucket = req.input.bucket.clone();
+        let start = Instant::now();
+        let res = self.storage.delete_object(req).await;
+        self.metrics
+            .observe_operation("delete_object", &bucket, start.elapsed());
+        res
+    }
+
+    async fn delete_object_tagging(
+        &self,
+        req: S3Request<DeleteObjectTaggingInput>,
+    ) -> S3Result<S3Response<DeleteObjectTaggingOutput>> {
+        self.metrics.add_method_call("delete_object_tagging");
+        self.storage.delete_object_tagging(req).await
+    }
+
+    async fn delete_objects(
+        &self,
+        req: S3Request<DeleteObjectsInput>,
+    ) -> S3Result<S3Response<DeleteObjectsOutput>> {
+        self.metrics.add_method_call("delete_objects");
+        let bucket = req.input.bucket.clone();
+        let start = Instant::now();
+        let res = self.storage.delete_objects(req).await;
+        self.metrics
+            .observe_operation("delete_objects", &bucket, start.elapsed());
+        res
+    }
+
+    async fn get_bucket_acl(
+        &self,
+        req: S3Request<GetBucketAclInput>,
+    ) -> S3Result<S3Response<GetBucketAclOutput>> {
+        self.metrics.add_method_call("get_bucket_acl");
+        self.storage.get_bucket_acl(req).await
+    }
+
+    async fn get_bucket_encryption(
+        &self,
+        req: S3Request<GetBucketEncryptionInput>,
+    ) -> S3Result<S3Response<GetBucketEncryptionOutput>> {
+        self.metrics.add_method_call("get_bucket_encryption");
+        self.storage.get_bucket_encryption(req).await
+    }
+
+    async fn get_bucket_lifecycle_configuration(
+        &self,
+        req: S3Request<GetBucketLifecycleConfigurationInput>,
+    ) -> S3Result<S3Response<GetBucketLifecycleConfigurationOutput>> {
+        self.metrics
+            .add_method_call("get_bucket_lifecycle_configuration");
+        self.storage.get_bucket_lifecycle_configuration(req).await
+    }
+
+    async fn get_bucket_location(
+        &self,
+        req: S3Request<GetBucketLocationInput>,
+    ) -> S3Result<S3Response<GetBucketLocationOutput>> {
+        self.metrics.add_method_call("get_bucket_location");
+        self.storage.get_bucket_location(req).await
+    }
+
+    async fn get_bucket_logging(
+        &self,
+        req: S3Request<GetBucketLoggingInput>,
+    ) -> S3Result<S3Response<GetBucketLoggingOutput>> {
+        self.metrics.add_method_call("get_bucket_logging");
+        self.storage.get_bucket_logging(req).await
+    }
+
+    async fn get_bucket_request_payment(
+        &self,
+        req: S3Request<GetBucketRequestPaymentInput>,
+    ) -> S3Result<S3Response<GetBucketRequestPaymentOutput>> {
+        self.metrics.add_method_call("get_bucket_request_payment");
+        self.storage.get_bucket_request_payment(req).await
+    }
+
+    async fn get_bucket_tagging(
+        &self,
+        req: S3Request<GetBucketTaggingInput>,
+    ) -> S3Result<S3Response<GetBucketTaggingOutput>> {
+        self.metrics.add_method_call("get_bucket_tagging");
+        self.storage.get_bucket_tagging(req).await
+    }
+
+    async fn get_object(
+        &self,
+        req: S3Request<GetObjectInput>,
+    ) -> S3Result<S3Response<GetObjectOutput>> {
+        self.metrics.add_method_call("get_object");
+        let bucket = req.input.bucket.clone();
+        let start = Instant::now();
+        let res = self.storage.get_object(req).await;
+        // Note: this measures time to first byte, the body is streamed afterwards
+        self.metrics
+            .observe_operation("get_object", &bucket, start.elapsed());
+        res
+    }
+
+    async fn get_object_acl(
+        &self,
+        req: S3Request<GetObjectAclInput>,
+    ) -> S3Result<S3Response<GetObjectAclOutput>> {
+        self.metrics.add_method_call("get_object_acl");
+        self.storage.get_object_acl(req).await
+    }
+
+    async fn get_object_tagging(
+        &self,
+        req: S3Request<GetObjectTaggingInput>,
+    ) -> S3Result<S3Response<GetObjectTaggingOutput>> {
+        self.metrics.add_method_call("get_object_tagging");
+        self.storage.get_object_tagging(req).await
+    }
+
+    async fn head_bucket(
+        &self,
+        req: S3Request<HeadBucketInput>,
+    ) -> S3Result<S3Response<HeadBucketOutput>> {
+        self.metrics.add_method_call("head_bucket");
+        self.storage.head_bucket(req).await
+    }
+
+    async fn head_object(
+        &self,
+        req: S3Request<HeadObjectInput>,
+    ) -> S3Result<S3Response<HeadObjectOutput>> {
+        self.metrics.add_method_call("head_object");
+        self.storage.head_object(req).await
+    }
+
+    async fn list_buckets(
+        &self,
+        req: S3Request<ListBucketsInput>,
+    ) -> S3Result<S3Response<ListBucketsOutput>> {
+        self.metrics.add_method_call("list_buckets");
+        self.storage.list_buckets(req).await
+    }
+
+    async fn list_objects(
+        &self,
+        req: S3Request<ListObjectsInput>,
+    ) -> S3Result<S3Response<ListObjectsOutput>> {
+        self.metrics.add_method_call("list_objects");
+        let bucket = req.input.bucket.clone();
+        let start = Instant::now();
+        let res = self.storage.list_objects(req).await;
+        self.metrics
+            .observe_operation("list_objects", &bucket, start.elapsed());
+        res
+    }
+
+    async fn list_objects_v2(
+        &self,
+        req: S3Request<ListObjectsV2Input>,
+    ) -> S3Result<S3Response<ListObjectsV2Output>> {
+        self.metrics.add_method_call("list_objects_v2");
+        let bucket = req.input.bucket.clone();
+        let start = Instant::now();
+        let res = self.storage.list_objects_v2(req).await;
+        self.metrics
+            .observe_operation("list_objects_v2", &bucket, start.elapsed());
+        res
+    }
+
+    async fn put_bucket_acl(
+        &self,
+        req: S3Request<PutBucketAclInput>,
+    ) -> S3Result<S3Response<PutBucketAclOutput>> {
+        self.metrics.add_method_call("put_bucket_acl");
+        self.storage.put_bucket_acl(req).await
+    }
+
+    async fn put_bucket_encryption(
+        &self,
+        req: S3Request<PutBucketEncryptionInput>,
+    ) -> S3Result<S3Response<PutBucketEncryptionOutput>> {
+        self.metrics.add_method_call("put_bucket_encryption");
+        self.storage.put_bucket_encryption(req).await
+    }
+
+    async fn put_bucket_lifecycle_configuration(
+        &self,
+        req: S3Request<PutBucketLifecycleConfigurationInput>,
+    ) -> S3Result<S3Response<PutBucketLifecycleConfigurationOutput>> {
+        self.metrics
+            .add_method_call("put_bucket_lifecycle_configuration");
+        self.storage.put_bucket_lifecycle_configuration(req).await
+    }
+
+    async fn put_bucket_logging(
+        &self,
+        req: S3Request<PutBucketLoggingInput>,
+    ) -> S3Result<S3Response<PutBucketLoggingOutput>> {
+        self.metrics.add_method_call("put_bucket_logging");
+        self.storage.put_bucket_logging(req).await
+    }
+
+    async fn put_bucket_request_payment(
+        &self,
+        req: S3Request<PutBucketRequestPaymentInput>,
+    ) -> S3Result<S3Response<PutBucketRequestPaymentOutput>> {
+        self.metrics.add_method_call("put_bucket_request_payment");
+        self.storage.put_bucket_request_payment(req).await
+    }
+
+    async fn put_bucket_tagging(
+        &self,
+        req: S3Request<PutBucketTaggingInput>,
+    ) -> S3Result<S3Response<PutBucketTaggingOutput>> {
+        self.metrics.add_method_call("put_bucket_tagging");
+        self.storage.put_bucket_tagging(req).await
+    }
+
+    async fn put_object_acl(
+        &self,
+        req: S3Request<PutObjectAclInput>,
+    ) -> S3Result<S3Response<PutObjectAclOutput>> {
+        self.metrics.add_method_call("put_object_acl");
+        self.storage.put_object_acl(req).await
+    }
+
+    async fn put_object_tagging(
+        &self,
+        req: S3Request<PutObjectTaggingInput>,
+    ) -> S3Result<S3Response<PutObjectTaggingOutput>> {
+        self.metrics.add_method_call("put_object_tagging");
+        self.storage.put_object_tagging(req).await
+    }
+
+    async fn put_object(
+        &self,
+        req: S3Request<PutObjectInput>,
+    ) -> S3Result<S3Response<PutObjectOutput>> {
+        self.metrics.add_method_call("put_object");
+        let bucket = req.input.bucket.clone();
+        let start = Instant::now();
+        let res = self.storage.put_object(req).await;
+        self.metrics
+            .observe_operation("put_object", &bucket, start.elapsed());
+        res
+    }
+
+    async fn upload_part(
+        &self,
+        req: S3Request<UploadPartInput>,
+    ) -> S3Result<S3Response<UploadPartOutput>> {
+        self.metrics.add_method_call("upload_part");
+        self.storage.upload_part(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_label_guard_limits_cardinality() {
+        let guard = BucketLabelGuard::new(2);
+        assert_eq!(guard.label("a"), "a");
+        assert_eq!(guard.label("b"), "b");
+        assert_eq!(guard.label("c"), OVERFLOW_BUCKET_LABEL);
+        // Already known buckets keep their label
+        assert_eq!(guard.label("a"), "a");
+    }
+
+    #[test]
+    fn test_metrics_backend_from_str() {
+        assert_eq!(
+            "prometheus".parse::<MetricsBackend>().unwrap(),
+            MetricsBackend::Prometheus
+        );
+        assert_eq!(
+            "StatsD".parse::<MetricsBackend>().unwrap(),
+            MetricsBackend::Statsd
+        );
+        assert_eq!("none".parse::<MetricsBackend>().unwrap(), MetricsBackend::None);
+        assert!("graphite".parse::<MetricsBackend>().is_err());
+    }
+}
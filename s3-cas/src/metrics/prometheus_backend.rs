@@ -0,0 +1,505 @@
+use cas_storage::{Access, MetricsCollector};
+use prometheus::{
+    register_gauge, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, Gauge, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
+use std::time::Duration;
+
+use super::{S3MetricsCollector, S3_API_METHODS};
+
+impl MetricsCollector for PrometheusMetrics {
+    fn block_pending(&self) {
+        self.data_blocks_pending_write.inc();
+    }
+
+    fn block_written(&self) {
+        self.data_blocks_pending_write.dec();
+        self.data_blocks_written.inc();
+    }
+
+    fn block_write_error(&self) {
+        self.data_blocks_pending_write.dec();
+        self.data_blocks_write_errors.inc();
+    }
+
+    fn block_ignored(&self) {
+        self.data_blocks_pending_write.dec();
+        self.data_blocks_ignored.inc();
+    }
+
+    fn blocks_dropped(&self, amount: u64) {
+        self.data_blocks_pending_write.sub(amount as i64);
+        self.data_blocks_dropped.inc_by(amount);
+    }
+
+    fn bytes_sent(&self, amount: usize) {
+        self.data_bytes_sent.inc_by(amount as u64);
+    }
+
+    fn bytes_received(&self, amount: usize) {
+        self.data_bytes_received.inc_by(amount as u64);
+    }
+
+    fn block_write_started(&self) {
+        self.data_blocks_in_flight.inc();
+    }
+
+    fn block_write_finished(&self) {
+        self.data_blocks_in_flight.dec();
+    }
+
+    fn block_queue_wait(&self, wait: Duration) {
+        self.data_block_queue_wait.observe(wait.as_secs_f64());
+    }
+
+    fn block_write_latency(&self, duration: Duration) {
+        self.data_block_write_duration.observe(duration.as_secs_f64());
+    }
+
+    fn write_concurrency_limit(&self, limit: usize) {
+        self.data_write_concurrency_limit.set(limit as i64);
+    }
+
+    fn meta_cache_hit(&self) {
+        self.meta_cache_requests.with_label_values(&["hit"]).inc();
+    }
+
+    fn meta_cache_miss(&self) {
+        self.meta_cache_requests.with_label_values(&["miss"]).inc();
+    }
+
+    fn meta_pool_queued(&self, queued: usize) {
+        self.meta_pool_queued.set(queued as i64);
+    }
+
+    fn meta_pool_active(&self, active: usize) {
+        self.meta_pool_active.set(active as i64);
+    }
+
+    fn meta_pool_wait(&self, wait: Duration) {
+        self.meta_pool_wait.observe(wait.as_secs_f64());
+    }
+
+    fn delete_queue(&self, blocks: u64, bytes: u64) {
+        self.delete_queue_blocks.set(blocks as i64);
+        self.delete_queue_bytes.set(bytes as i64);
+    }
+
+    fn refcount_anomaly(&self, kind: &str) {
+        self.block_refcount_anomalies
+            .with_label_values(&[kind])
+            .inc();
+    }
+
+    fn group_sync(&self, duration: Duration, bytes: u64) {
+        self.group_sync_duration.observe(duration.as_secs_f64());
+        self.group_sync_bytes.inc_by(bytes);
+    }
+}
+
+#[derive(Debug)]
+pub struct PrometheusMetrics {
+    method_calls: IntCounterVec,
+    bucket_count: IntGauge,
+    data_bytes_received: IntCounter,
+    data_bytes_sent: IntCounter,
+    data_bytes_written: IntCounter,
+    data_blocks_written: IntCounter,
+    data_blocks_ignored: IntCounter,
+    data_blocks_pending_write: IntGauge,
+    data_blocks_write_errors: IntCounter,
+    data_blocks_dropped: IntCounter,
+    data_blocks_in_flight: IntGauge,
+    data_block_queue_wait: Histogram,
+    data_block_write_duration: Histogram,
+    data_write_concurrency_limit: IntGauge,
+    meta_cache_requests: IntCounterVec,
+    meta_pool_queued: IntGauge,
+    meta_pool_active: IntGauge,
+    meta_pool_wait: Histogram,
+    delete_queue_blocks: IntGauge,
+    delete_queue_bytes: IntGauge,
+    block_refcount_anomalies: IntCounterVec,
+    group_sync_duration: Histogram,
+    group_sync_bytes: IntCounter,
+    operation_duration: HistogramVec,
+    metadata_tree_bytes: IntGaugeVec,
+    metadata_data_ratio: Gauge,
+    open_user_stores: IntGauge,
+    bucket_requests: IntCounterVec,
+    bucket_bytes: IntCounterVec,
+    slow_requests: IntCounterVec,
+    scans: IntCounterVec,
+    scan_bytes: IntCounter,
+    scan_duration: Histogram,
+    // Authentication metrics
+    auth_login_attempts: IntCounterVec,
+    auth_active_sessions: IntGauge,
+    auth_admin_operations: IntCounterVec,
+}
+
+// TODO: this can be improved, make sure this does not crash on multiple instances;
+impl PrometheusMetrics {
+    pub fn new() -> Self {
+        let method_calls = register_int_counter_vec!(
+            "s3_api_method_invocations",
+            "Amount of times a particular S3 API method has been called in the lifetime of the process",
+            &["api_method"],
+        ).expect("can register an int counter vec in the default registry");
+
+        // instantiate the correct counters for api calls
+        for api in S3_API_METHODS {
+            method_calls.with_label_values(&[api]);
+        }
+
+        let bucket_count = register_int_gauge!(
+            "s3_bucket_count",
+            "Amount of active buckets in the S3 instance"
+        )
+        .expect("can register an int gauge in the default registry");
+
+        let data_bytes_received = register_int_counter!(
+            "s3_data_bytes_received",
+            "Amount of bytes of actual data received"
+        )
+        .expect("can register an int counter in the default registry");
+
+        let data_bytes_sent =
+            register_int_counter!("s3_data_bytes_sent", "Amount of bytes of actual data sent")
+                .expect("can register an int counter in the default registry");
+
+        let data_bytes_written = register_int_counter!(
+            "s3_data_bytes_written",
+            "Amount of bytes of actual data written to block storage"
+        )
+        .expect("can register an int counter in the default registry");
+
+        let data_blocks_written = register_int_counter!(
+            "s3_data_blocks_written",
+            "Amount of data blocks written to block storage"
+        )
+        .expect("can register an int counter in the default registry");
+
+        let data_blocks_ignored = register_int_counter!(
+            "s3_data_blocks_ignored",
+            "Amount of data blocks not written to block storage, because a block with the same hash is already present"
+        )
+        .expect("can register an int counter in the default registry");
+
+        let data_blocks_pending_write = register_int_gauge!(
+            "s3_data_blocks_pending_write",
+            "Amount of data blocks in memory, waiting to be written to block storage"
+        )
+        .expect("can register an int gauge in the default registry");
+
+        let data_blocks_write_errors = register_int_counter!(
+            "s3_data_blocks_write_errors",
+            "Amount of data blocks which could not be written to block storage"
+        )
+        .expect("can register an int counter in the default registry");
+
+        let data_blocks_dropped = register_int_counter!(
+            "s3_data_blocks_dropped",
+            "Amount of data blocks dropped due to client disconnects before the block was (fully) written to storage",
+        ).expect("can register an int gauge in the default registry");
+
+        let data_blocks_in_flight = register_int_gauge!(
+            "s3_data_blocks_in_flight",
+            "Amount of data blocks currently being processed by the write pipeline"
+        )
+        .expect("can register an int gauge in the default registry");
+
+        let data_block_queue_wait = register_histogram!(
+            "s3_data_block_queue_wait_seconds",
+            "Time a data block waited between being received and the start of its disk write"
+        )
+        .expect("can register a histogram in the default registry");
+
+        let data_block_write_duration = register_histogram!(
+            "s3_data_block_write_duration_seconds",
+            "Time spent writing a data block to block storage"
+        )
+        .expect("can register a histogram in the default registry");
+
+        let data_write_concurrency_limit = register_int_gauge!(
+            "s3_data_write_concurrency_limit",
+            "Current limit of concurrent block writes when adaptive write concurrency is enabled"
+        )
+        .expect("can register an int gauge in the default registry");
+
+        let meta_cache_requests = register_int_counter_vec!(
+            "s3_meta_cache_requests",
+            "Object metadata lookups served by the metadata cache, by result (hit or miss)",
+            &["result"],
+        )
+        .expect("can register an int counter vec in the default registry");
+        meta_cache_requests.with_label_values(&["hit"]);
+        meta_cache_requests.with_label_values(&["miss"]);
+
+        let meta_pool_queued = register_int_gauge!(
+            "s3_meta_pool_queued",
+            "Amount of metadata operations waiting for a blocking thread"
+        )
+        .expect("can register an int gauge in the default registry");
+
+        let meta_pool_active = register_int_gauge!(
+            "s3_meta_pool_active",
+            "Amount of metadata operations running on blocking threads"
+        )
+        .expect("can register an int gauge in the default registry");
+
+        let meta_pool_wait = register_histogram!(
+            "s3_meta_pool_wait_seconds",
+            "Time a metadata operation waited for a blocking thread"
+        )
+        .expect("can register a histogram in the default registry");
+
+        let open_user_stores = register_int_gauge!(
+            "s3_open_user_stores",
+            "Amount of users with an open metadata store"
+        )
+        .expect("can register an int gauge in the default registry");
+
+        let bucket_requests = register_int_counter_vec!(
+            "s3_bucket_requests",
+            "Requests to a bucket, by access (read or write)",
+            &["bucket", "access"],
+        )
+        .expect("can register an int counter vec in the default registry");
+
+        let bucket_bytes = register_int_counter_vec!(
+            "s3_bucket_bytes",
+            "Object data transferred from or to a bucket, by access (read or write)",
+            &["bucket", "access"],
+        )
+        .expect("can register an int counter vec in the default registry");
+
+        let slow_requests = register_int_counter_vec!(
+            "s3_slow_requests",
+            "Requests which took longer than the slow log threshold of their operation",
+            &["operation"],
+        )
+        .expect("can register an int counter vec in the default registry");
+
+        let scans = register_int_counter_vec!(
+            "s3_scans",
+            "Uploaded objects scanned for viruses, by result (clean, infected or failed)",
+            &["result"],
+        )
+        .expect("can register an int counter vec in the default registry");
+        for result in ["clean", "infected", "failed"] {
+            scans.with_label_values(&[result]);
+        }
+
+        let scan_bytes = register_int_counter!(
+            "s3_scan_bytes",
+            "Object data sent to the virus scanner, in bytes"
+        )
+        .expect("can register an int counter in the default registry");
+
+        let scan_duration = register_histogram!(
+            "s3_scan_duration_seconds",
+            "Time from the start of the scan of an uploaded object until its result"
+        )
+        .expect("can register a histogram in the default registry");
+
+        let delete_queue_blocks = register_int_gauge!(
+            "s3_delete_queue_blocks",
+            "Amount of blocks of deleted objects waiting for the removal of their files"
+        )
+        .expect("can register an int gauge in the default registry");
+
+        let delete_queue_bytes = register_int_gauge!(
+            "s3_delete_queue_bytes",
+            "Size of the blocks of deleted objects waiting for the removal of their files"
+        )
+        .expect("can register an int gauge in the default registry");
+
+        let block_refcount_anomalies = register_int_counter_vec!(
+            "s3_block_refcount_anomalies",
+            "Block reference counts which would have wrapped around, by kind (overflow or underflow)",
+            &["kind"],
+        )
+        .expect("can register an int counter vec in the default registry");
+        block_refcount_anomalies.with_label_values(&["overflow"]);
+        block_refcount_anomalies.with_label_values(&["underflow"]);
+
+        let group_sync_duration = register_histogram!(
+            "s3_group_sync_duration_seconds",
+            "Time spent syncing the block files and metadata written since the previous group sync"
+        )
+        .expect("can register a histogram in the default registry");
+
+        let group_sync_bytes = register_int_counter!(
+            "s3_group_sync_bytes",
+            "Size of the block files synced by group syncs"
+        )
+        .expect("can register an int counter in the default registry");
+
+        let operation_duration = register_histogram_vec!(
+            "s3_operation_duration_seconds",
+            "Time spent handling an S3 operation, per bucket",
+            &["operation", "bucket"],
+        )
+        .expect("can register a histogram vec in the default registry");
+
+        let metadata_tree_bytes = register_int_gauge_vec!(
+            "s3_metadata_tree_bytes",
+            "Disk space used by a metadata tree, buckets past the label limit are summed",
+            &["tree"],
+        )
+        .expect("can register an int gauge vec in the default registry");
+
+        let metadata_data_ratio = register_gauge!(
+            "s3_metadata_data_ratio",
+            "Disk space of the metadata divided by the size of the stored data"
+        )
+        .expect("can register a gauge in the default registry");
+
+        let auth_login_attempts = register_int_counter_vec!(
+            "auth_login_attempts_total",
+            "Total number of login attempts (HTTP UI)",
+            &["result"],
+        ).expect("can register auth_login_attempts counter vec");
+
+        // Initialize labels for login attempts
+        auth_login_attempts.with_label_values(&["success"]);
+        auth_login_attempts.with_label_values(&["failure"]);
+
+        let auth_active_sessions = register_int_gauge!(
+            "auth_active_sessions",
+            "Current number of active HTTP UI sessions"
+        ).expect("can register auth_active_sessions gauge");
+
+        let auth_admin_operations = register_int_counter_vec!(
+            "auth_admin_operations_total",
+            "Total number of admin operations performed",
+            &["operation"],
+        ).expect("can register auth_admin_operations counter vec");
+
+        // Initialize labels for admin operations
+        auth_admin_operations.with_label_values(&["user_create"]);
+        auth_admin_operations.with_label_values(&["user_delete"]);
+        auth_admin_operations.with_label_values(&["password_reset"]);
+        auth_admin_operations.with_label_values(&["admin_grant"]);
+        auth_admin_operations.with_label_values(&["admin_revoke"]);
+
+        Self {
+            method_calls,
+            bucket_count,
+            data_bytes_received,
+            data_bytes_sent,
+            data_bytes_written,
+            data_blocks_written,
+            data_blocks_ignored,
+            data_blocks_pending_write,
+            data_blocks_write_errors,
+            data_blocks_dropped,
+            data_blocks_in_flight,
+            data_block_queue_wait,
+            data_block_write_duration,
+            data_write_concurrency_limit,
+            meta_cache_requests,
+            meta_pool_queued,
+            meta_pool_active,
+            meta_pool_wait,
+            delete_queue_blocks,
+            delete_queue_bytes,
+            block_refcount_anomalies,
+            group_sync_duration,
+            group_sync_bytes,
+            operation_duration,
+            metadata_tree_bytes,
+            metadata_data_ratio,
+            open_user_stores,
+            bucket_requests,
+            bucket_bytes,
+            slow_requests,
+            scans,
+            scan_bytes,
+            scan_duration,
+            auth_login_attempts,
+            auth_active_sessions,
+            auth_admin_operations,
+        }
+    }
+
+    pub fn bytes_written(&self, amount: usize) {
+        self.data_bytes_written.inc_by(amount as u64)
+    }
+}
+
+impl S3MetricsCollector for PrometheusMetrics {
+    fn add_method_call(&self, call_name: &str) {
+        self.method_calls.with_label_values(&[call_name]).inc();
+    }
+
+    fn set_bucket_count(&self, count: usize) {
+        self.bucket_count.set(count as i64)
+    }
+
+    fn inc_bucket_count(&self) {
+        self.bucket_count.inc()
+    }
+
+    fn dec_bucket_count(&self) {
+        self.bucket_count.dec()
+    }
+
+    fn record_latency(&self, operation: &str, bucket: &str, duration: Duration) {
+        self.operation_duration
+            .with_label_values(&[operation, bucket])
+            .observe(duration.as_secs_f64());
+    }
+
+    // Authentication metrics methods
+    fn record_login_attempt(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.auth_login_attempts.with_label_values(&[result]).inc();
+    }
+
+    fn set_active_sessions(&self, count: usize) {
+        self.auth_active_sessions.set(count as i64);
+    }
+
+    fn record_admin_operation(&self, operation: &str) {
+        self.auth_admin_operations.with_label_values(&[operation]).inc();
+    }
+
+    fn set_metadata_tree_size(&self, tree: &str, bytes: u64) {
+        self.metadata_tree_bytes
+            .with_label_values(&[tree])
+            .set(bytes as i64);
+    }
+
+    fn set_metadata_ratio(&self, ratio: f64) {
+        self.metadata_data_ratio.set(ratio);
+    }
+
+    fn set_open_user_stores(&self, count: usize) {
+        self.open_user_stores.set(count as i64);
+    }
+
+    fn record_bucket_access(&self, bucket: &str, access: Access, bytes: u64) {
+        let labels = [bucket, access.as_str()];
+        self.bucket_requests.with_label_values(&labels).inc();
+        self.bucket_bytes.with_label_values(&labels).inc_by(bytes);
+    }
+
+    fn record_slow_request(&self, operation: &str) {
+        self.slow_requests.with_label_values(&[operation]).inc();
+    }
+
+    fn record_scan(&self, result: &str, bytes: u64, duration: Duration) {
+        self.scans.with_label_values(&[result]).inc();
+        self.scan_bytes.inc_by(bytes);
+        self.scan_duration.observe(duration.as_secs_f64());
+    }
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,309 @@
+use cas_storage::{Access, MetricsCollector};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use super::S3MetricsCollector;
+
+/// Metrics collector which pushes metrics to a StatsD daemon over UDP.
+///
+/// Tags are emitted in the DogStatsD format (`|#key:value`), which is understood
+/// by Datadog agents and most modern StatsD implementations. Sending is best
+/// effort: failures are logged at trace level and otherwise ignored.
+#[derive(Debug)]
+pub struct StatsdMetrics {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdMetrics {
+    /// Create a new StatsD collector sending to `addr` (host:port). Every metric
+    /// name is prefixed with `prefix` followed by a dot, unless the prefix is empty.
+    ///
+    /// The host is resolved once, and the socket bound to the unspecified address
+    /// of the family of its first address, so IPv6 daemons are reachable too.
+    pub fn new(addr: &str, prefix: &str) -> io::Result<Self> {
+        let target = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("statsd address {addr} doesn't resolve"),
+            )
+        })?;
+        let local: SocketAddr = match target {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(target)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    fn send(&self, line: String) {
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            tracing::trace!(error = %e, "failed to send statsd metric");
+        }
+    }
+
+    fn count(&self, name: &str, value: u64, tags: &[(&str, &str)]) {
+        self.send(format_line(&self.prefix, name, &value.to_string(), "c", tags));
+    }
+
+    fn gauge(&self, name: &str, value: &str) {
+        self.send(format_line(&self.prefix, name, value, "g", &[]));
+    }
+
+    fn timing(&self, name: &str, duration: Duration, tags: &[(&str, &str)]) {
+        let ms = format!("{:.3}", duration.as_secs_f64() * 1000.0);
+        self.send(format_line(&self.prefix, name, &ms, "ms", tags));
+    }
+}
+
+fn format_line(prefix: &str, name: &str, value: &str, kind: &str, tags: &[(&str, &str)]) -> String {
+    let mut line = if prefix.is_empty() {
+        format!("{}:{}|{}", name, value, kind)
+    } else {
+        format!("{}.{}:{}|{}", prefix, name, value, kind)
+    };
+    if !tags.is_empty() {
+        let tags = tags
+            .iter()
+            .map(|(k, v)| format!("{}:{}", k, sanitize_tag(v)))
+            .collect::<Vec<_>>()
+            .join(",");
+        line.push_str("|#");
+        line.push_str(&tags);
+    }
+    line
+}
+
+/// Strip characters which have a meaning in the StatsD line protocol.
+fn sanitize_tag(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '|' | ',' | '#' | ':' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+impl MetricsCollector for StatsdMetrics {
+    fn block_pending(&self) {
+        self.gauge("data_blocks_pending_write", "+1");
+    }
+
+    fn block_written(&self) {
+        self.gauge("data_blocks_pending_write", "-1");
+        self.count("data_blocks_written", 1, &[]);
+    }
+
+    fn block_write_error(&self) {
+        self.gauge("data_blocks_pending_write", "-1");
+        self.count("data_blocks_write_errors", 1, &[]);
+    }
+
+    fn block_ignored(&self) {
+        self.gauge("data_blocks_pending_write", "-1");
+        self.count("data_blocks_ignored", 1, &[]);
+    }
+
+    fn blocks_dropped(&self, amount: u64) {
+        self.gauge("data_blocks_pending_write", &format!("-{}", amount));
+        self.count("data_blocks_dropped", amount, &[]);
+    }
+
+    fn bytes_sent(&self, amount: usize) {
+        self.count("data_bytes_sent", amount as u64, &[]);
+    }
+
+    fn bytes_received(&self, amount: usize) {
+        self.count("data_bytes_received", amount as u64, &[]);
+    }
+
+    fn block_write_started(&self) {
+        self.gauge("data_blocks_in_flight", "+1");
+    }
+
+    fn block_write_finished(&self) {
+        self.gauge("data_blocks_in_flight", "-1");
+    }
+
+    fn block_queue_wait(&self, wait: Duration) {
+        self.timing("data_block_queue_wait", wait, &[]);
+    }
+
+    fn block_write_latency(&self, duration: Duration) {
+        self.timing("data_block_write_duration", duration, &[]);
+    }
+
+    fn write_concurrency_limit(&self, limit: usize) {
+        self.gauge("data_write_concurrency_limit", &limit.to_string());
+    }
+
+    fn meta_cache_hit(&self) {
+        self.count("meta_cache_requests", 1, &[("result", "hit")]);
+    }
+
+    fn meta_cache_miss(&self) {
+        self.count("meta_cache_requests", 1, &[("result", "miss")]);
+    }
+
+    fn meta_pool_queued(&self, queued: usize) {
+        self.gauge("meta_pool_queued", &queued.to_string());
+    }
+
+    fn meta_pool_active(&self, active: usize) {
+        self.gauge("meta_pool_active", &active.to_string());
+    }
+
+    fn meta_pool_wait(&self, wait: Duration) {
+        self.timing("meta_pool_wait", wait, &[]);
+    }
+
+    fn delete_queue(&self, blocks: u64, bytes: u64) {
+        self.gauge("delete_queue_blocks", &blocks.to_string());
+        self.gauge("delete_queue_bytes", &bytes.to_string());
+    }
+
+    fn refcount_anomaly(&self, kind: &str) {
+        self.count("block_refcount_anomalies", 1, &[("kind", kind)]);
+    }
+
+    fn group_sync(&self, duration: Duration, bytes: u64) {
+        self.timing("group_sync_duration", duration, &[]);
+        self.count("group_sync_bytes", bytes, &[]);
+    }
+}
+
+impl S3MetricsCollector for StatsdMetrics {
+    fn add_method_call(&self, call_name: &str) {
+        self.count("api_method_invocations", 1, &[("api_method", call_name)]);
+    }
+
+    fn set_bucket_count(&self, count: usize) {
+        self.gauge("bucket_count", &count.to_string());
+    }
+
+    fn inc_bucket_count(&self) {
+        self.gauge("bucket_count", "+1");
+    }
+
+    fn dec_bucket_count(&self) {
+        self.gauge("bucket_count", "-1");
+    }
+
+    fn record_latency(&self, operation: &str, bucket: &str, duration: Duration) {
+        self.timing(
+            "operation_duration",
+            duration,
+            &[("operation", operation), ("bucket", bucket)],
+        );
+    }
+
+    fn record_login_attempt(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.count("auth_login_attempts", 1, &[("result", result)]);
+    }
+
+    fn set_active_sessions(&self, count: usize) {
+        self.gauge("auth_active_sessions", &count.to_string());
+    }
+
+    fn record_admin_operation(&self, operation: &str) {
+        self.count("auth_admin_operations", 1, &[("operation", operation)]);
+    }
+
+    fn set_metadata_tree_size(&self, tree: &str, bytes: u64) {
+        self.send(format_line(
+            &self.prefix,
+            "metadata_tree_bytes",
+            &bytes.to_string(),
+            "g",
+            &[("tree", tree)],
+        ));
+    }
+
+    fn set_metadata_ratio(&self, ratio: f64) {
+        self.gauge("metadata_data_ratio", &format!("{:.6}", ratio));
+    }
+
+    fn set_open_user_stores(&self, count: usize) {
+        self.gauge("open_user_stores", &count.to_string());
+    }
+
+    fn record_bucket_access(&self, bucket: &str, access: Access, bytes: u64) {
+        let tags = [("bucket", bucket), ("access", access.as_str())];
+        self.count("bucket_requests", 1, &tags);
+        self.count("bucket_bytes", bytes, &tags);
+    }
+
+    fn record_slow_request(&self, operation: &str) {
+        self.count("slow_requests", 1, &[("operation", operation)]);
+    }
+
+    fn record_scan(&self, result: &str, bytes: u64, duration: Duration) {
+        let tags = [("result", result)];
+        self.count("scans", 1, &tags);
+        self.count("scan_bytes", bytes, &tags);
+        self.timing("scan_duration", duration, &tags);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_line() {
+        assert_eq!(format_line("", "hits", "1", "c", &[]), "hits:1|c");
+        assert_eq!(
+            format_line("s3cas", "operation_duration", "1.500", "ms", &[("bucket", "a|b")]),
+            "s3cas.operation_duration:1.500|ms|#bucket:a_b"
+        );
+    }
+
+    #[test]
+    fn test_send_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        let metrics = StatsdMetrics::new(&addr, "s3cas").unwrap();
+
+        metrics.record_login_attempt(true);
+
+        let mut buf = [0u8; 256];
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..n]).unwrap(),
+            "s3cas.auth_login_attempts:1|c|#result:success"
+        );
+    }
+
+    #[test]
+    fn test_send_over_udp_ipv6() {
+        // hosts without IPv6 can't run this test
+        let Ok(server) = UdpSocket::bind("[::1]:0") else {
+            return;
+        };
+        let addr = server.local_addr().unwrap().to_string();
+        let metrics = StatsdMetrics::new(&addr, "").unwrap();
+
+        metrics.record_login_attempt(false);
+
+        let mut buf = [0u8; 256];
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..n]).unwrap(),
+            "auth_login_attempts:1|c|#result:failure"
+        );
+    }
+}
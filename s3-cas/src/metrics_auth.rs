@@ -0,0 +1,130 @@
+//! Access control of the Prometheus endpoint: the metric labels name buckets and
+//! users, which tenants of a shared server shouldn't see.
+
+use std::net::IpAddr;
+use std::path::Path;
+
+use anyhow::Context;
+use hyper::{header, HeaderMap, StatusCode};
+use subtle::ConstantTimeEq;
+
+use crate::network::IpRange;
+
+/// Who may scrape the metrics, everyone by default
+#[derive(Debug, Clone, Default)]
+pub struct MetricsAuth {
+    /// Bearer token scrapers must send
+    token: Option<String>,
+    /// Addresses allowed to scrape, all if empty
+    allow: Vec<IpRange>,
+}
+
+impl MetricsAuth {
+    /// Require scrapers to send `token` as bearer token
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token.filter(|token| !token.is_empty());
+        self
+    }
+
+    /// Require scrapers to send the token in the file at `path` as bearer token
+    pub fn with_token_file(self, path: &Path) -> anyhow::Result<Self> {
+        let token = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read metrics token {}", path.display()))?;
+        let token = token.trim();
+        if token.is_empty() {
+            anyhow::bail!("Empty metrics token {}", path.display());
+        }
+        Ok(self.with_token(Some(token.to_string())))
+    }
+
+    /// Only accept scrapes from the addresses in `allow`, all if it is empty
+    pub fn with_allow(mut self, allow: Vec<IpRange>) -> Self {
+        self.allow = allow;
+        self
+    }
+
+    /// Returns `true` if scrapes need a token or come from some addresses only
+    pub fn is_restricted(&self) -> bool {
+        self.token.is_some() || !self.allow.is_empty()
+    }
+
+    /// Checks a scrape from `peer`, the error is the status to reject it with
+    pub fn check(&self, peer: IpAddr, headers: &HeaderMap) -> Result<(), StatusCode> {
+        if !self.allow.is_empty() && !self.allow.iter().any(|range| range.contains(peer)) {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        let Some(expected) = &self.token else {
+            return Ok(());
+        };
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) if bool::from(token.as_bytes().ct_eq(expected.as_bytes())) => Ok(()),
+            _ => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_unrestricted() {
+        let auth = MetricsAuth::default().with_token(Some(String::new()));
+        assert!(!auth.is_restricted());
+        assert_eq!(auth.check(ip("203.0.113.7"), &HeaderMap::new()), Ok(()));
+    }
+
+    #[test]
+    fn test_token_and_allow() {
+        let auth = MetricsAuth::default()
+            .with_token(Some("secret".to_string()))
+            .with_allow(vec!["10.0.0.0/8".parse().unwrap()]);
+        assert!(auth.is_restricted());
+
+        assert_eq!(auth.check(ip("10.1.2.3"), &bearer("secret")), Ok(()));
+        assert_eq!(auth.check(ip("::ffff:10.1.2.3"), &bearer("secret")), Ok(()));
+        assert_eq!(
+            auth.check(ip("10.1.2.3"), &bearer("secre")),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            auth.check(ip("10.1.2.3"), &HeaderMap::new()),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        // the address is checked first, without revealing if the token is valid
+        assert_eq!(
+            auth.check(ip("192.168.1.1"), &bearer("secret")),
+            Err(StatusCode::FORBIDDEN)
+        );
+    }
+
+    #[test]
+    fn test_token_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        std::fs::write(&path, "secret\n").unwrap();
+        let auth = MetricsAuth::default().with_token_file(&path).unwrap();
+        assert_eq!(auth.check(ip("127.0.0.1"), &bearer("secret")), Ok(()));
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(MetricsAuth::default().with_token_file(&path).is_err());
+    }
+}
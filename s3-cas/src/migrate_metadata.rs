@@ -0,0 +1,145 @@
+//! Migration of the metadata stores to the other metadata engine (`fjall` or
+//! `fjall_notx`), into a new metadata root.
+
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+
+use cas_storage::{migrate_store, StorageEngine, StoreLock};
+
+#[derive(Parser, Debug)]
+pub struct MigrateMetadataConfig {
+    #[arg(long, default_value = ".")]
+    pub meta_root: PathBuf,
+
+    #[arg(
+        long,
+        default_value = "fjall",
+        help = "Metadata DB the stores use now (fjall, fjall_notx)"
+    )]
+    pub metadata_db: StorageEngine,
+
+    #[arg(
+        long,
+        help = "Metadata DB to migrate the stores to (fjall, fjall_notx)"
+    )]
+    pub to: StorageEngine,
+
+    #[arg(
+        long,
+        help = "New metadata root the stores are written to, it must not exist or be empty"
+    )]
+    pub target: PathBuf,
+}
+
+/// The `db` directories of the stores in a metadata root, relative to it: the
+/// store of a single-user server, the shared store and the stores of the users
+/// of a multi-user server, and the stores of the deleted users kept in the
+/// archive.
+fn find_stores(meta_root: &Path) -> Result<Vec<PathBuf>> {
+    let mut stores = Vec::new();
+    for store in ["db", "blocks/db"] {
+        if meta_root.join(store).is_dir() {
+            stores.push(PathBuf::from(store));
+        }
+    }
+    for dir in [PathBuf::new(), PathBuf::from("archive")] {
+        let Ok(entries) = std::fs::read_dir(meta_root.join(&dir)) else {
+            continue;
+        };
+        let mut users = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            let is_user = name.to_str().is_some_and(|name| name.starts_with("user_"));
+            if is_user && entry.path().join("db").is_dir() {
+                users.push(dir.join(name).join("db"));
+            }
+        }
+        users.sort();
+        stores.extend(users);
+    }
+    Ok(stores)
+}
+
+/// Copy every metadata store of `meta_root` to the same place in `target`,
+/// converted to another engine and verified. The stores in `meta_root` are not
+/// modified, the server is switched over by replacing its metadata root with
+/// `target`.
+pub fn migrate_metadata(args: MigrateMetadataConfig) -> Result<()> {
+    if args.metadata_db == args.to {
+        bail!("The stores already use {}", args.to.as_str());
+    }
+    if std::fs::read_dir(&args.target).is_ok_and(|mut entries| entries.next().is_some()) {
+        bail!("{} is not empty", args.target.display());
+    }
+    // the stores must not change while they are copied
+    let _lock = StoreLock::acquire(&args.meta_root, "metadata migration")?;
+    let stores = find_stores(&args.meta_root)?;
+    if stores.is_empty() {
+        bail!("No metadata store found in {}", args.meta_root.display());
+    }
+
+    let started = Instant::now();
+    for store in &stores {
+        let to = args.target.join(store);
+        std::fs::create_dir_all(&to).with_context(|| format!("Can't create {}", to.display()))?;
+        let stats = migrate_store(&args.meta_root.join(store), args.metadata_db, &to, args.to)
+            .with_context(|| format!("Can't migrate the store in {}", store.display()))?;
+        eprintln!(
+            "{}: {} trees, {} keys, {} bytes copied and verified",
+            store.display(),
+            stats.trees,
+            stats.keys,
+            stats.bytes
+        );
+    }
+
+    eprintln!(
+        "Migrated {} stores to {} in {:.1}s. Start the server with --meta-root {} --metadata-db {}",
+        stores.len(),
+        args.to.as_str(),
+        started.elapsed().as_secs_f64(),
+        args.target.display(),
+        args.to.as_str()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_stores() {
+        let dir = tempfile::tempdir().unwrap();
+        for store in [
+            "blocks/db",
+            "user_bob/db",
+            "user_alice/db",
+            "archive/user_carol/db",
+        ] {
+            std::fs::create_dir_all(dir.path().join(store)).unwrap();
+        }
+        // not a store
+        std::fs::create_dir_all(dir.path().join("user_dave")).unwrap();
+        std::fs::create_dir_all(dir.path().join("other/db")).unwrap();
+
+        let stores: Vec<_> = find_stores(dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|store| store.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            stores,
+            [
+                "blocks/db",
+                "user_alice/db",
+                "user_bob/db",
+                "archive/user_carol/db"
+            ]
+        );
+    }
+}
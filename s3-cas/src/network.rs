@@ -0,0 +1,324 @@
+//! Network level access control: IP allow and deny lists, globally and per bucket,
+//! and rejecting anonymous requests.
+//!
+//! The policy is read from a TOML file:
+//!
+//! ```toml
+//! reject_anonymous = true
+//! # X-Forwarded-For is only used for requests from these addresses
+//! trusted_proxies = ["10.0.0.1"]
+//! allow = ["192.168.0.0/16", "2001:db8::/32"]
+//! deny = ["192.168.66.0/24"]
+//!
+//! [buckets.backups]
+//! allow = ["192.168.10.0/24"]
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::Context;
+use hyper::HeaderMap;
+use ipnet::IpNet;
+use s3s::access::{S3Access, S3AccessContext};
+use s3s::path::S3Path;
+use s3s::{s3_error, S3Result};
+use serde::{Deserialize, Deserializer};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Address of the peer of a connection, stored in the request extensions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// A single address or a CIDR network, e.g. `10.0.0.1` or `10.0.0.0/8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange(IpNet);
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.contains(&ip.to_canonical())
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        s.parse::<IpNet>()
+            .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+            .map(|net| IpRange(net.trunc()))
+            .map_err(|_| format!("Invalid IP address or network: {s}"))
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// An allow and a deny list. Denied addresses are rejected even if they are
+/// allowed, an empty allow list allows all addresses.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IpRules {
+    #[serde(default)]
+    pub allow: Vec<IpRange>,
+    #[serde(default)]
+    pub deny: Vec<IpRange>,
+}
+
+impl IpRules {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        is_allowed(&self.allow, &self.deny, ip)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+fn is_allowed(allow: &[IpRange], deny: &[IpRange], ip: IpAddr) -> bool {
+    if deny.iter().any(|range| range.contains(ip)) {
+        return false;
+    }
+    allow.is_empty() || allow.iter().any(|range| range.contains(ip))
+}
+
+/// Network policy applied to every S3 request before it is authorized
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkPolicy {
+    /// Reject requests without credentials, even for public objects
+    #[serde(default)]
+    pub reject_anonymous: bool,
+    /// Proxies whose `X-Forwarded-For` header is trusted
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpRange>,
+    /// Addresses allowed to access the server, all if empty
+    #[serde(default)]
+    pub allow: Vec<IpRange>,
+    /// Addresses denied access to the server
+    #[serde(default)]
+    pub deny: Vec<IpRange>,
+    /// Additional rules for requests to a bucket
+    #[serde(default)]
+    pub buckets: HashMap<String, IpRules>,
+}
+
+impl NetworkPolicy {
+    /// Load a policy from a TOML file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read network policy {}", path.display()))?;
+        toml::from_str(&data).with_context(|| format!("Invalid network policy {}", path.display()))
+    }
+
+    /// Returns `true` if the policy has IP rules, so the client address is needed
+    pub fn has_ip_rules(&self) -> bool {
+        !self.allow.is_empty()
+            || !self.deny.is_empty()
+            || self.buckets.values().any(|rules| !rules.is_empty())
+    }
+
+    /// Returns `true` if the policy doesn't restrict any request
+    pub fn is_unrestricted(&self) -> bool {
+        !self.reject_anonymous && !self.has_ip_rules()
+    }
+
+    /// The address of the client of a request from `peer`.
+    ///
+    /// If the peer is a trusted proxy, the `X-Forwarded-For` header is followed
+    /// from the right, skipping trusted proxies, to the first untrusted address.
+    /// Addresses further left were added by the client and can't be trusted.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer.to_canonical();
+        if !self.is_trusted_proxy(client) {
+            return client;
+        }
+
+        let forwarded = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>();
+        for hop in forwarded.into_iter().rev() {
+            match hop.trim().parse::<IpAddr>() {
+                Ok(ip) => {
+                    client = ip.to_canonical();
+                    if !self.is_trusted_proxy(client) {
+                        break;
+                    }
+                }
+                // a malformed entry ends the trusted part of the chain
+                Err(_) => break,
+            }
+        }
+        client
+    }
+
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|range| range.contains(ip))
+    }
+
+    /// Returns `true` if `ip` may access `bucket`, or the service if there is
+    /// no bucket. Both the global and the bucket rules must allow the address.
+    pub fn allows(&self, ip: IpAddr, bucket: Option<&str>) -> bool {
+        if !is_allowed(&self.allow, &self.deny, ip) {
+            return false;
+        }
+        match bucket.and_then(|bucket| self.buckets.get(bucket)) {
+            Some(rules) => rules.allows(ip),
+            None => true,
+        }
+    }
+}
+
+/// Access check enforcing a [`NetworkPolicy`] before the `inner` check. Without
+/// an inner check, requests need credentials.
+pub struct NetworkAccess {
+    policy: Arc<NetworkPolicy>,
+    inner: Option<Box<dyn S3Access>>,
+}
+
+impl NetworkAccess {
+    pub fn new(policy: Arc<NetworkPolicy>, inner: Option<Box<dyn S3Access>>) -> Self {
+        Self { policy, inner }
+    }
+}
+
+#[async_trait::async_trait]
+impl S3Access for NetworkAccess {
+    async fn check(&self, cx: &mut S3AccessContext<'_>) -> S3Result<()> {
+        if self.policy.has_ip_rules() {
+            let peer = cx.extensions_mut().get::<RemoteAddr>().copied();
+            let bucket = match cx.s3_path() {
+                S3Path::Root => None,
+                S3Path::Bucket { bucket } | S3Path::Object { bucket, .. } => Some(&**bucket),
+            };
+            let allowed = match peer {
+                Some(RemoteAddr(peer)) => {
+                    let ip = self.policy.client_ip(peer.ip(), cx.headers());
+                    let allowed = self.policy.allows(ip, bucket);
+                    if !allowed {
+                        tracing::debug!(ip = %ip, bucket = ?bucket, "Request denied by network policy");
+                    }
+                    allowed
+                }
+                None => {
+                    tracing::warn!("Remote address of request unknown, denied by network policy");
+                    false
+                }
+            };
+            if !allowed {
+                return Err(s3_error!(AccessDenied, "Access denied from this network"));
+            }
+        }
+
+        if self.policy.reject_anonymous && cx.credentials().is_none() {
+            return Err(s3_error!(
+                AccessDenied,
+                "Anonymous requests are not allowed"
+            ));
+        }
+
+        match &self.inner {
+            Some(inner) => inner.check(cx).await,
+            None if cx.credentials().is_some() => Ok(()),
+            None => Err(s3_error!(AccessDenied, "Signature is required")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ip_range() {
+        let range: IpRange = "10.1.0.0/16".parse().unwrap();
+        assert!(range.contains(ip("10.1.2.3")));
+        assert!(!range.contains(ip("10.2.0.1")));
+        // IPv4 clients on a dual stack socket
+        assert!(range.contains(ip("::ffff:10.1.2.3")));
+
+        let single: IpRange = "192.168.1.1".parse().unwrap();
+        assert!(single.contains(ip("192.168.1.1")));
+        assert!(!single.contains(ip("192.168.1.2")));
+
+        assert!("10.1.2.3/8"
+            .parse::<IpRange>()
+            .unwrap()
+            .contains(ip("10.9.9.9")));
+        assert!("not-an-ip".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy: NetworkPolicy = toml::from_str(
+            r#"
+            allow = ["192.168.0.0/16"]
+            deny = ["192.168.66.0/24"]
+
+            [buckets.backups]
+            allow = ["192.168.10.0/24"]
+            "#,
+        )
+        .unwrap();
+        assert!(policy.has_ip_rules());
+        assert!(!policy.reject_anonymous);
+
+        assert!(policy.allows(ip("192.168.1.1"), None));
+        assert!(policy.allows(ip("192.168.1.1"), Some("other")));
+        assert!(!policy.allows(ip("192.168.66.1"), None));
+        assert!(!policy.allows(ip("10.0.0.1"), None));
+        assert!(policy.allows(ip("192.168.10.5"), Some("backups")));
+        assert!(!policy.allows(ip("192.168.1.1"), Some("backups")));
+
+        assert!(toml::from_str::<NetworkPolicy>("alow = []").is_err());
+        assert!(NetworkPolicy::default().is_unrestricted());
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let policy: NetworkPolicy = toml::from_str(r#"trusted_proxies = ["10.0.0.0/8"]"#).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            X_FORWARDED_FOR,
+            HeaderValue::from_static("1.1.1.1, 203.0.113.7, 10.0.0.2"),
+        );
+
+        // only trusted proxies can set the client address
+        assert_eq!(
+            policy.client_ip(ip("198.51.100.1"), &headers),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            policy.client_ip(ip("10.0.0.1"), &headers),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            policy.client_ip(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+    }
+}
@@ -0,0 +1,527 @@
+//! Bucket notifications, delivered to local commands.
+//!
+//! Every command of the configuration subscribes to object created and removed
+//! events, optionally of some buckets or keys only. For each event it is run
+//! with the event as an S3 style JSON document on stdin, so deployments without
+//! an HTTP receiver can react to uploads with a script. Commands run in the
+//! background with a bounded concurrency and are killed when they exceed their
+//! timeout; raising an event never blocks a request.
+//!
+//! Events are raised by the [`ObjectEventHandler`] of a [`Notifier`] on the CasFS
+//! of a user, so objects written through the HTTP UI raise them too.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use cas_storage::{Object, ObjectEventHandler};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+
+/// Most stderr of a failed command which is logged
+const MAX_LOGGED_STDERR: usize = 1024;
+
+/// Kind of bucket event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// An object was written by a PUT or a completed multipart upload
+    ObjectCreated,
+    /// An object was deleted
+    ObjectRemoved,
+}
+
+/// An event of an object
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    /// The S3 event name, e.g. `ObjectCreated:Put`
+    pub name: &'static str,
+    /// The user owning the bucket
+    pub owner: String,
+    pub bucket: String,
+    pub key: String,
+    /// Size and ETag of a created object
+    pub size: Option<u64>,
+    pub e_tag: Option<String>,
+    pub time: SystemTime,
+}
+
+impl Event {
+    /// `key` was written by the operation of the S3 event name `name`, e.g.
+    /// `ObjectCreated:Put`
+    pub fn created(
+        name: &'static str,
+        owner: &str,
+        bucket: &str,
+        key: &str,
+        size: u64,
+        e_tag: String,
+    ) -> Self {
+        Self {
+            kind: EventKind::ObjectCreated,
+            name,
+            owner: owner.to_string(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            size: Some(size),
+            e_tag: Some(e_tag),
+            time: SystemTime::now(),
+        }
+    }
+
+    /// `key` was deleted
+    pub fn removed(owner: &str, bucket: &str, key: &str) -> Self {
+        Self {
+            kind: EventKind::ObjectRemoved,
+            name: "ObjectRemoved:Delete",
+            owner: owner.to_string(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            size: None,
+            e_tag: None,
+            time: SystemTime::now(),
+        }
+    }
+
+    /// The event as an S3 event notification document
+    pub fn to_json(&self) -> Value {
+        let mut object = json!({ "key": self.key });
+        if let Some(size) = self.size {
+            object["size"] = json!(size);
+        }
+        if let Some(e_tag) = &self.e_tag {
+            object["eTag"] = json!(e_tag.trim_matches('"'));
+        }
+        json!({
+            "Records": [{
+                "eventVersion": "2.1",
+                "eventSource": "s3-cas:s3",
+                "eventTime": chrono::DateTime::<chrono::Utc>::from(self.time).to_rfc3339(),
+                "eventName": self.name,
+                "userIdentity": { "principalId": self.owner },
+                "s3": {
+                    "s3SchemaVersion": "1.0",
+                    "bucket": {
+                        "name": self.bucket,
+                        "ownerIdentity": { "principalId": self.owner },
+                    },
+                    "object": object,
+                },
+            }]
+        })
+    }
+}
+
+/// A command run for the events it subscribed to
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandConfig {
+    /// The program to run, looked up in `PATH` unless it is a path
+    pub program: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Kinds of events the command runs for, all kinds if empty
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    /// Buckets the command runs for, all buckets if empty
+    #[serde(default)]
+    pub buckets: Vec<String>,
+    /// Only run for keys with this prefix and suffix
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+    /// Time after which a run is killed
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Most runs of the command at the same time
+    #[serde(default = "default_max_concurrency")]
+    pub max_concurrency: usize,
+    /// Events waiting for a run, further events are dropped
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_concurrency() -> usize {
+    4
+}
+
+fn default_queue_size() -> usize {
+    1024
+}
+
+impl CommandConfig {
+    fn accepts(&self, event: &Event) -> bool {
+        (self.events.is_empty() || self.events.contains(&event.kind))
+            && (self.buckets.is_empty() || self.buckets.contains(&event.bucket))
+            && event.key.starts_with(&self.prefix)
+            && event.key.ends_with(&self.suffix)
+    }
+}
+
+/// Notification configuration, loaded from a TOML file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationConfig {
+    #[serde(default, rename = "command")]
+    pub commands: Vec<CommandConfig>,
+}
+
+impl NotificationConfig {
+    /// Load and validate a configuration from a TOML file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read notification config {}", path.display()))?;
+        let config: Self = toml::from_str(&data)
+            .with_context(|| format!("Invalid notification config {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid notification config {}", path.display()))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for command in &self.commands {
+            let program = command.program.display();
+            if command.program.as_os_str().is_empty() {
+                anyhow::bail!("command without a program");
+            }
+            if command.timeout_secs == 0 {
+                anyhow::bail!("timeout_secs of {program} must be at least 1");
+            }
+            if command.max_concurrency == 0 {
+                anyhow::bail!("max_concurrency of {program} must be at least 1");
+            }
+            if command.queue_size == 0 {
+                anyhow::bail!("queue_size of {program} must be at least 1");
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Subscriber {
+    config: CommandConfig,
+    sender: mpsc::Sender<Event>,
+}
+
+struct Inner {
+    subscribers: Mutex<Vec<Subscriber>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// Raises bucket events. The default notifier is disabled and drops all
+/// events.
+#[derive(Clone, Default)]
+pub struct Notifier {
+    inner: Option<Arc<Inner>>,
+}
+
+impl Notifier {
+    /// Start running the commands of `config` for their events. Must be called
+    /// from within a tokio runtime.
+    pub fn start(config: NotificationConfig) -> Self {
+        let mut subscribers = Vec::with_capacity(config.commands.len());
+        let mut tasks = Vec::with_capacity(config.commands.len());
+        for command in config.commands {
+            let (sender, receiver) = mpsc::channel(command.queue_size);
+            tasks.push(tokio::spawn(run_command(receiver, command.clone())));
+            subscribers.push(Subscriber {
+                config: command,
+                sender,
+            });
+        }
+        Self {
+            inner: Some(Arc::new(Inner {
+                subscribers: Mutex::new(subscribers),
+                tasks: Mutex::new(tasks),
+            })),
+        }
+    }
+
+    /// Queue `event` for the commands subscribed to it
+    pub fn send(&self, event: Event) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        for subscriber in inner.subscribers.lock().unwrap().iter() {
+            if !subscriber.config.accepts(&event) {
+                continue;
+            }
+            if let Err(e) = subscriber.sender.try_send(event.clone()) {
+                tracing::warn!(
+                    program = %subscriber.config.program.display(),
+                    bucket = %event.bucket,
+                    key = %event.key,
+                    error = %e,
+                    "Dropped bucket event, the command queue is full"
+                );
+            }
+        }
+    }
+
+    /// The handler raising the object events of the CasFS of `owner`, `None` if
+    /// notifications are disabled
+    pub fn event_handler(&self, owner: &str) -> Option<Arc<dyn ObjectEventHandler>> {
+        self.inner.as_ref()?;
+        Some(Arc::new(OwnerEvents {
+            notifier: self.clone(),
+            owner: owner.to_string(),
+        }))
+    }
+
+    /// Stop accepting events and wait until the queued ones are handled
+    pub async fn close(&self) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        inner.subscribers.lock().unwrap().clear();
+        let tasks = std::mem::take(&mut *inner.tasks.lock().unwrap());
+        for task in tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+/// Raises the object events of the CasFS of a user. Deleting a bucket raises
+/// no events for its objects.
+struct OwnerEvents {
+    notifier: Notifier,
+    owner: String,
+}
+
+impl ObjectEventHandler for OwnerEvents {
+    fn on_put(&self, bucket: &str, key: &str, object: &Object) {
+        self.notifier.send(Event::created(
+            "ObjectCreated:Put",
+            &self.owner,
+            bucket,
+            key,
+            object.size(),
+            object.format_e_tag(),
+        ));
+    }
+
+    fn on_delete(&self, bucket: &str, key: &str) {
+        self.notifier.send(Event::removed(&self.owner, bucket, key));
+    }
+
+    fn on_multipart_complete(&self, bucket: &str, key: &str, object: &Object) {
+        self.notifier.send(Event::created(
+            "ObjectCreated:CompleteMultipartUpload",
+            &self.owner,
+            bucket,
+            key,
+            object.size(),
+            object.format_e_tag(),
+        ));
+    }
+}
+
+/// Runs `command` for the events of `receiver`, at most `max_concurrency` at
+/// a time
+async fn run_command(mut receiver: mpsc::Receiver<Event>, command: CommandConfig) {
+    let command = Arc::new(command);
+    let permits = Arc::new(Semaphore::new(command.max_concurrency));
+    let mut runs = JoinSet::new();
+    while let Some(event) = receiver.recv().await {
+        // only fails when the semaphore is closed, which it never is
+        let Ok(permit) = permits.clone().acquire_owned().await else {
+            break;
+        };
+        // reap the finished runs, so they don't pile up
+        while runs.try_join_next().is_some() {}
+        let command = command.clone();
+        runs.spawn(async move {
+            if let Err(e) = run(&command, &event).await {
+                tracing::warn!(
+                    program = %command.program.display(),
+                    event = event.name,
+                    bucket = %event.bucket,
+                    key = %event.key,
+                    error = %e,
+                    "Bucket notification command failed"
+                );
+            }
+            drop(permit);
+        });
+    }
+    while runs.join_next().await.is_some() {}
+}
+
+/// Runs `command` once with `event` on stdin
+async fn run(command: &CommandConfig, event: &Event) -> anyhow::Result<()> {
+    let mut child = Command::new(&command.program)
+        .args(&command.args)
+        .env("S3CAS_EVENT", event.name)
+        .env("S3CAS_BUCKET", &event.bucket)
+        .env("S3CAS_KEY", &event.key)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to start")?;
+
+    let input = event.to_json().to_string();
+    let timeout = Duration::from_secs(command.timeout_secs);
+    let output = tokio::time::timeout(timeout, async move {
+        if let Some(mut stdin) = child.stdin.take() {
+            // a command may exit without reading the event
+            let _ = stdin.write_all(input.as_bytes()).await;
+        }
+        child.wait_with_output().await
+    })
+    .await
+    .with_context(|| format!("killed after {} seconds", command.timeout_secs))??;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr: String = stderr.chars().take(MAX_LOGGED_STDERR).collect();
+        anyhow::bail!("{}: {}", output.status, stderr.trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config: NotificationConfig = toml::from_str(
+            r#"
+            [[command]]
+            program = "/usr/local/bin/thumbnail"
+            args = ["--size", "256"]
+            events = ["object_created"]
+            buckets = ["photos"]
+            suffix = ".jpg"
+
+            [[command]]
+            program = "logger"
+            timeout_secs = 5
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let thumbnail = &config.commands[0];
+        assert_eq!(thumbnail.max_concurrency, 4);
+        let created = Event::created(
+            "ObjectCreated:Put",
+            "alice",
+            "photos",
+            "a.jpg",
+            3,
+            "\"e\"".into(),
+        );
+        assert!(thumbnail.accepts(&created));
+        assert!(!thumbnail.accepts(&Event::removed("alice", "photos", "a.jpg")));
+        assert!(!thumbnail.accepts(&Event::created(
+            "ObjectCreated:Put",
+            "alice",
+            "photos",
+            "a.png",
+            3,
+            "\"e\"".into()
+        )));
+        assert!(!thumbnail.accepts(&Event::created(
+            "ObjectCreated:Put",
+            "alice",
+            "docs",
+            "a.jpg",
+            3,
+            "\"e\"".into()
+        )));
+        assert!(config.commands[1].accepts(&Event::removed("bob", "docs", "b.txt")));
+
+        let config: NotificationConfig =
+            toml::from_str("[[command]]\nprogram = \"x\"\nmax_concurrency = 0").unwrap();
+        assert!(config.validate().is_err());
+        assert!(
+            toml::from_str::<NotificationConfig>("[[command]]\nprogram = \"x\"\nurl = \"y\"")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_event_json() {
+        let event = Event::created(
+            "ObjectCreated:CompleteMultipartUpload",
+            "alice",
+            "photos",
+            "2024/a b.jpg",
+            1024,
+            "\"abc-2\"".into(),
+        );
+        let json = event.to_json();
+        let record = &json["Records"][0];
+        assert_eq!(record["eventName"], "ObjectCreated:CompleteMultipartUpload");
+        assert_eq!(record["userIdentity"]["principalId"], "alice");
+        assert_eq!(record["s3"]["bucket"]["name"], "photos");
+        assert_eq!(record["s3"]["object"]["key"], "2024/a b.jpg");
+        assert_eq!(record["s3"]["object"]["size"], 1024);
+        assert_eq!(record["s3"]["object"]["eTag"], "abc-2");
+
+        let json = Event::removed("alice", "photos", "a.jpg").to_json();
+        let object = &json["Records"][0]["s3"]["object"];
+        assert_eq!(object["key"], "a.jpg");
+        assert!(object.get("size").is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("event.json");
+        let command = CommandConfig {
+            program: "sh".into(),
+            args: vec!["-c".into(), format!("cat > {}", out.display())],
+            events: vec![],
+            buckets: vec![],
+            prefix: String::new(),
+            suffix: String::new(),
+            timeout_secs: 10,
+            max_concurrency: 1,
+            queue_size: 8,
+        };
+        let notifier = Notifier::start(NotificationConfig {
+            commands: vec![command.clone()],
+        });
+        notifier.send(Event::removed("alice", "photos", "a.jpg"));
+        notifier.close().await;
+        let json: Value = serde_json::from_slice(&std::fs::read(&out).unwrap()).unwrap();
+        assert_eq!(json["Records"][0]["eventName"], "ObjectRemoved:Delete");
+
+        let failing = CommandConfig {
+            args: vec!["-c".into(), "echo broken >&2; exit 3".into()],
+            ..command.clone()
+        };
+        let err = run(&failing, &Event::removed("alice", "photos", "a.jpg"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("broken"));
+
+        let slow = CommandConfig {
+            args: vec!["-c".into(), "sleep 10".into()],
+            timeout_secs: 1,
+            ..command
+        };
+        let err = run(&slow, &Event::removed("alice", "photos", "a.jpg"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("killed"));
+    }
+}
@@ -0,0 +1,54 @@
+//! Command line arguments placing blocks in storage locations by key prefix, see
+//! `cas_storage::Placement`.
+
+use std::path::PathBuf;
+
+/// Parse a `NAME=PATH` storage location argument.
+pub fn parse_storage_location(s: &str) -> Result<(String, PathBuf), String> {
+    let (name, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected NAME=PATH, got '{s}'"))?;
+    if name.is_empty() || path.is_empty() {
+        return Err(format!("Missing location name or path in '{s}'"));
+    }
+    Ok((name.to_string(), PathBuf::from(path)))
+}
+
+/// Parse a `BUCKET/PREFIX=NAME` placement rule argument into (bucket, prefix, name).
+// bucket names can't contain a '/', the location name follows the last '='
+pub fn parse_prefix_placement(s: &str) -> Result<(String, String, String), String> {
+    let (rule, location) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("Expected BUCKET/PREFIX=NAME, got '{s}'"))?;
+    let (bucket, prefix) = rule
+        .split_once('/')
+        .ok_or_else(|| format!("Expected BUCKET/PREFIX=NAME, got '{s}'"))?;
+    if bucket.is_empty() || location.is_empty() {
+        return Err(format!("Missing bucket or location name in '{s}'"));
+    }
+    Ok((bucket.to_string(), prefix.to_string(), location.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_placement() {
+        assert_eq!(
+            parse_storage_location("ssd=/mnt/ssd"),
+            Ok(("ssd".to_string(), PathBuf::from("/mnt/ssd")))
+        );
+        assert!(parse_storage_location("ssd").is_err());
+        assert_eq!(
+            parse_prefix_placement("media/thumbs/=ssd"),
+            Ok(("media".to_string(), "thumbs/".to_string(), "ssd".to_string()))
+        );
+        assert_eq!(
+            parse_prefix_placement("media/a=b=ssd"),
+            Ok(("media".to_string(), "a=b".to_string(), "ssd".to_string()))
+        );
+        assert!(parse_prefix_placement("media=ssd").is_err());
+        assert!(parse_prefix_placement("media/thumbs/=").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! Rebalancing of the stored blocks onto another storage location, typically a
+//! disk added to a store whose existing disks fill up.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+
+use crate::manifest::StoreArgs;
+use cas_storage::BlockID;
+
+/// How often progress is reported
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Parser, Debug)]
+pub struct RebalanceConfig {
+    #[command(flatten)]
+    pub store: StoreArgs,
+
+    #[arg(long, help = "Storage location to move the blocks to")]
+    pub to: String,
+
+    #[arg(
+        long,
+        help = "Storage location to move the blocks from, all other locations if not set. \
+                `default` is the default location"
+    )]
+    pub from: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 50.0,
+        help = "Percentage of the blocks to move, picked by block id so runs pick the same blocks"
+    )]
+    pub share: f64,
+
+    #[arg(long, help = "Maximum bytes moved per second, unlimited if not set")]
+    pub max_bytes_per_sec: Option<u64>,
+
+    #[arg(long, help = "Only report what would be moved")]
+    pub dry_run: bool,
+}
+
+/// Whether the block `id` is part of a share of `percent` % of the blocks
+fn selected(id: &BlockID, percent: f64) -> bool {
+    let bucket = u16::from_le_bytes([id[0], id[1]]) % 10_000;
+    (bucket as f64) < percent * 100.0
+}
+
+/// Sleep as long as the bytes moved since `started` are ahead of `rate`
+async fn throttle(started: Instant, moved: u64, rate: Option<u64>) {
+    let Some(rate) = rate.filter(|rate| *rate > 0) else {
+        return;
+    };
+    let due = Duration::from_secs_f64(moved as f64 / rate as f64);
+    if let Some(ahead) = due.checked_sub(started.elapsed()) {
+        tokio::time::sleep(ahead).await;
+    }
+}
+
+/// Move a share of the blocks to another storage location. Every block is copied,
+/// verified against its id and recorded in the block metadata before the original
+/// file is removed, so the command can be interrupted and run again.
+#[tokio::main]
+pub async fn rebalance(args: RebalanceConfig) -> Result<()> {
+    if !(0.0..=100.0).contains(&args.share) {
+        bail!("--share must be a percentage between 0 and 100");
+    }
+    let casfs = args.store.open()?;
+    if casfs.placement().location_dir(&args.to).is_none() {
+        bail!(
+            "Unknown storage location {}, add it with --storage-location",
+            args.to
+        );
+    }
+    let from = match args.from.as_deref() {
+        None => None,
+        Some("default") => Some(None),
+        Some(name) if casfs.placement().location_dir(name).is_some() => Some(Some(name)),
+        Some(name) => bail!("Unknown storage location {name}"),
+    };
+
+    // collect the blocks first, moving them rewrites the block tree
+    let mut candidates = Vec::new();
+    let (mut candidate_bytes, mut total) = (0u64, 0usize);
+    for item in casfs.block_tree()?.iter_all() {
+        let (id, block) = item?;
+        total += 1;
+        let location = block.location();
+        if location == Some(args.to.as_str()) || from.is_some_and(|from| location != from) {
+            continue;
+        }
+        if selected(&id, args.share) {
+            candidate_bytes += block.size() as u64;
+            candidates.push(id);
+        }
+    }
+    eprintln!(
+        "{} of {total} blocks to move to {}, {candidate_bytes} bytes",
+        candidates.len(),
+        args.to
+    );
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let started = Instant::now();
+    let mut last_report = started;
+    let (mut moved, mut moved_bytes) = (0usize, 0u64);
+    for (i, id) in candidates.iter().enumerate() {
+        match casfs.relocate_block(id, Some(&args.to)).await {
+            Ok(0) => {}
+            Ok(size) => {
+                moved += 1;
+                moved_bytes += size as u64;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!(
+                        "Can't move block {}, {moved} blocks were moved",
+                        hex::encode(id)
+                    )
+                })
+            }
+        }
+        throttle(started, moved_bytes, args.max_bytes_per_sec).await;
+
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            let secs = started.elapsed().as_secs_f64();
+            eprintln!(
+                "{}/{} blocks, {moved_bytes}/{candidate_bytes} bytes moved, {:.1} MiB/s",
+                i + 1,
+                candidates.len(),
+                moved_bytes as f64 / secs / (1024.0 * 1024.0)
+            );
+        }
+    }
+
+    eprintln!(
+        "Moved {moved} blocks, {moved_bytes} bytes to {} in {:.1}s",
+        args.to,
+        started.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selected() {
+        let ids: Vec<BlockID> = (0..=255u8)
+            .flat_map(|a| {
+                (0..=255u8).map(move |b| [a, b, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+            })
+            .collect();
+        let share = |percent| ids.iter().filter(|id| selected(id, percent)).count();
+
+        assert_eq!(share(0.0), 0);
+        assert_eq!(share(100.0), ids.len());
+        let half = share(50.0) as f64 / ids.len() as f64;
+        assert!((0.45..0.55).contains(&half));
+        // a larger share keeps the blocks of a smaller one
+        assert!(ids
+            .iter()
+            .filter(|id| selected(id, 20.0))
+            .all(|id| selected(id, 40.0)));
+    }
+}
@@ -0,0 +1,67 @@
+//! Read replica mode: only operations which don't modify the store are served.
+
+use s3s::access::{S3Access, S3AccessContext};
+use s3s::{s3_error, S3Result};
+
+/// S3 operations a read replica serves
+const READ_OPERATIONS: &[&str] = &[
+    "GetBucketAcl",
+    "GetBucketEncryption",
+    "GetBucketLocation",
+    "GetObject",
+    "GetObjectAcl",
+    "GetObjectTagging",
+    "HeadBucket",
+    "HeadObject",
+    "ListBuckets",
+    "ListObjects",
+    "ListObjectsV2",
+];
+
+pub fn is_read_operation(operation: &str) -> bool {
+    READ_OPERATIONS.contains(&operation)
+}
+
+/// Access check of a read replica: write operations are denied, read operations
+/// are checked by `inner`, or require credentials if there is no inner check.
+pub struct ReadOnlyAccess {
+    inner: Option<Box<dyn S3Access>>,
+}
+
+impl ReadOnlyAccess {
+    pub fn new(inner: Option<Box<dyn S3Access>>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait::async_trait]
+impl S3Access for ReadOnlyAccess {
+    async fn check(&self, cx: &mut S3AccessContext<'_>) -> S3Result<()> {
+        if !is_read_operation(cx.s3_op().name()) {
+            return Err(s3_error!(
+                AccessDenied,
+                "This server is a read-only replica"
+            ));
+        }
+
+        match &self.inner {
+            Some(inner) => inner.check(cx).await,
+            None if cx.credentials().is_some() => Ok(()),
+            None => Err(s3_error!(AccessDenied, "Signature is required")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_read_operation() {
+        assert!(is_read_operation("GetObject"));
+        assert!(is_read_operation("ListObjectsV2"));
+        assert!(!is_read_operation("PutObject"));
+        assert!(!is_read_operation("DeleteObjects"));
+        assert!(!is_read_operation("PutObjectAcl"));
+    }
+}
@@ -8,9 +8,10 @@ use tokio::io::AsyncWriteExt;
 
 use cas_storage::BlockStream;
 use cas_storage::RangeRequest;
-use cas_storage::CasFS;
+use cas_storage::CasFSBuilder;
 use cas_storage::StorageEngine;
 use crate::metrics::SharedMetrics;
+use crate::placement::parse_storage_location;
 
 #[derive(Parser, Debug)]
 pub struct RetrieveConfig {
@@ -27,6 +28,14 @@ pub struct RetrieveConfig {
     )]
     pub metadata_db: StorageEngine,
 
+    #[arg(
+        long = "storage-location",
+        value_name = "NAME=PATH",
+        value_parser = parse_storage_location,
+        help = "Storage location of the server holding placed blocks. Can be repeated"
+    )]
+    pub storage_locations: Vec<(String, PathBuf)>,
+
     #[arg(required = true, help = "Bucket name")]
     pub bucket: String,
 
@@ -41,14 +50,13 @@ pub struct RetrieveConfig {
 pub async fn retrieve(args: RetrieveConfig) -> Result<()> {
     let storage_engine = args.metadata_db;
     let metrics = SharedMetrics::new();
-    let casfs = CasFS::new(
-        args.fs_root.clone(),
-        args.meta_root.clone(),
-        metrics.to_cas_metrics(),
-        storage_engine,
-        None,
-        None,
-    );
+    let mut builder = CasFSBuilder::new(&args.fs_root, &args.meta_root)
+        .metrics(metrics.to_cas_metrics())
+        .storage_engine(storage_engine);
+    for (name, path) in &args.storage_locations {
+        builder = builder.storage_location(name.clone(), path.clone());
+    }
+    let casfs = builder.build()?;
 
     let (obj_meta, paths) = match casfs.get_object_paths(&args.bucket, &args.key)? {
         Some((obj, paths)) => (obj, paths),
@@ -1,11 +1,12 @@
+use std::collections::{BTreeSet, HashSet};
 use std::io::{self, ErrorKind};
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use bytes::Bytes;
-use faster_hex::{hex_decode, hex_string};
-use futures::Stream;
+use faster_hex::hex_string;
+use futures::{stream, Stream};
 use futures::StreamExt;
-use md5::{Digest, Md5};
 use tracing;
 use uuid::Uuid;
 
@@ -13,14 +14,25 @@ use rusoto_core::ByteStream;
 use s3s::dto::StreamingBlob;
 use s3s::dto::Timestamp;
 use s3s::dto::{
-    Bucket, CompleteMultipartUploadInput, CompleteMultipartUploadOutput, CopyObjectInput,
-    CopyObjectOutput, CreateBucketInput, CreateBucketOutput, CreateMultipartUploadInput,
-    CreateMultipartUploadOutput, DeleteBucketInput, DeleteBucketOutput, DeleteObjectInput,
-    DeleteObjectOutput, DeleteObjectsInput, DeleteObjectsOutput, DeletedObject,
-    GetBucketLocationInput, GetBucketLocationOutput, GetObjectInput, GetObjectOutput,
-    HeadBucketInput, HeadBucketOutput, HeadObjectInput, HeadObjectOutput, ListBucketsInput,
-    ListBucketsOutput, ListObjectsInput, ListObjectsOutput, ListObjectsV2Input,
-    ListObjectsV2Output, PutObjectInput, PutObjectOutput, UploadPartInput, UploadPartOutput,
+    Bucket, CommonPrefix, CompleteMultipartUploadInput, CompleteMultipartUploadOutput,
+    CopyObjectInput, CopyObjectOutput, CreateBucketInput, CreateBucketOutput,
+    CreateMultipartUploadInput, CreateMultipartUploadOutput, DeleteBucketEncryptionInput,
+    DeleteBucketEncryptionOutput, DeleteBucketInput, DeleteBucketLifecycleInput,
+    DeleteBucketLifecycleOutput, DeleteBucketOutput, DeleteBucketTaggingInput,
+    DeleteBucketTaggingOutput, DeleteObjectInput, DeleteObjectOutput, DeleteObjectTaggingInput,
+    DeleteObjectTaggingOutput, DeleteObjectsInput, DeleteObjectsOutput, DeletedObject,
+    GetBucketAclInput, GetBucketAclOutput, GetBucketEncryptionInput, GetBucketEncryptionOutput,
+    GetBucketLifecycleConfigurationInput, GetBucketLifecycleConfigurationOutput,
+    GetBucketLocationInput, GetBucketLocationOutput, GetBucketTaggingInput, GetBucketTaggingOutput,
+    GetObjectAclInput, GetObjectAclOutput, GetObjectInput, GetObjectOutput, GetObjectTaggingInput,
+    GetObjectTaggingOutput, HeadBucketInput, HeadBucketOutput, HeadObjectInput, HeadObjectOutput,
+    ListBucketsInput, ListBucketsOutput, ListObjectsInput, ListObjectsOutput, ListObjectsV2Input,
+    ListObjectsV2Output, ObjectStorageClass, Owner, PutBucketAclInput, PutBucketAclOutput,
+    PutBucketEncryptionInput, PutBucketEncryptionOutput, PutBucketLifecycleConfigurationInput,
+    PutBucketLifecycleConfigurationOutput, PutBucketTaggingInput, PutBucketTaggingOutput,
+    PutObjectAclInput, PutObjectAclOutput, PutObjectInput, PutObjectOutput, PutObjectTaggingInput,
+    PutObjectTaggingOutput, ServerSideEncryption, ServerSideEncryptionByDefault,
+    ServerSideEncryptionConfiguration, ServerSideEncryptionRule, UploadPartInput, UploadPartOutput,
 };
 use s3s::s3_error;
 use s3s::S3Result;
@@ -28,13 +40,32 @@ use s3s::S3;
 use s3s::{S3Request, S3Response};
 
 use cas_storage::{BlockStream, parse_range_request, RangeRequest, CasFS, BlockID, ObjectData};
+use cas_storage::{parse_multi_range_request, Access, ByteRanges, Durability};
+use cas_storage::{BucketLogging, CannedAcl, ObjectAttributes, ObjectTags};
+use cas_storage::cas::content_hash::multipart_e_tag;
+use crate::acl::{acl_grants, acl_owner, parse_canned_acl, DEFAULT_OWNER_ID};
+use crate::bandwidth::{throttled, Throttle, TrafficClass};
+use crate::http_cache::etag_matches;
+use crate::lifecycle::{expiration_header, lifecycle_from_configuration, lifecycle_rules};
+use crate::listing::{
+    group_by_delimiter, group_by_top_level, is_top_level, ContinuationToken, KeyEncoding,
+    ListEntry, ListLimits,
+};
 use crate::metrics::SharedMetrics;
-
-const MAX_KEYS: i32 = 1000;
+use crate::tagging::{bucket_tags_from_tag_set, parse_tagging_header, tag_set, tags_from_tag_set};
+use crate::content_scan::{ScanRefusal, ScanReport, ScanVerdict, Scanner};
+use crate::write_hooks::{ObjectWrite, WriteHooks, DEFAULT_CONTENT_TYPE};
 
 pub struct S3FS {
     casfs: Arc<CasFS>,
     metrics: SharedMetrics,
+    cache_control: Option<String>,
+    cas_headers: bool,
+    owner_id: String,
+    encrypted_at_rest: bool,
+    list_limits: ListLimits,
+    throttle: Option<Arc<Throttle>>,
+    write_hooks: WriteHooks,
 }
 impl S3FS {
     pub fn new(casfs: Arc<CasFS>, metrics: SharedMetrics) -> Self {
@@ -42,13 +73,328 @@ impl S3FS {
         // FIXME: This is a bit of a hack, we should have a better way to get the amount of buckets
         metrics.set_bucket_count(1); //db.open_tree(BUCKET_META_TREE).unwrap().len());
 
-        Self { casfs, metrics }
+        Self {
+            casfs,
+            metrics,
+            cache_control: None,
+            cas_headers: false,
+            owner_id: DEFAULT_OWNER_ID.to_string(),
+            encrypted_at_rest: false,
+            list_limits: ListLimits::default(),
+            throttle: None,
+            write_hooks: WriteHooks::default(),
+        }
+    }
+
+    /// Set the `Cache-Control` header returned with objects on GET and HEAD
+    pub fn with_cache_control(mut self, cache_control: Option<String>) -> Self {
+        self.cache_control = cache_control;
+        self
+    }
+
+    /// Return how an object is stored in `x-cas-*` headers on HEAD, see
+    /// [`cas_headers`]. They aren't S3 headers, so they are off by default.
+    pub fn with_cas_headers(mut self, enabled: bool) -> Self {
+        self.cas_headers = enabled;
+        self
+    }
+
+    /// Report `owner_id` as the owner of the buckets and objects, the user of the
+    /// CasFS in multi-user mode.
+    pub fn with_owner(mut self, owner_id: impl Into<String>) -> Self {
+        self.owner_id = owner_id.into();
+        self
+    }
+
+    /// Accept bucket default encryption, when the operator put the storage of the
+    /// store on encrypted disks. The store itself doesn't encrypt anything.
+    pub fn with_encrypted_at_rest(mut self, encrypted_at_rest: bool) -> Self {
+        self.encrypted_at_rest = encrypted_at_rest;
+        self
+    }
+
+    /// Limit the page size and depth of listings
+    pub fn with_list_limits(mut self, list_limits: ListLimits) -> Self {
+        self.list_limits = list_limits;
+        self
+    }
+
+    /// Throttle the object data sent to and received from clients
+    pub fn with_throttle(mut self, throttle: Option<Arc<Throttle>>) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Run `write_hooks` before objects are written, and after they are committed
+    pub fn with_write_hooks(mut self, write_hooks: WriteHooks) -> Self {
+        self.write_hooks = write_hooks;
+        self
+    }
+
+    fn owner(&self) -> Owner {
+        acl_owner(&self.owner_id)
+    }
+
+    /// Reject a request with an `x-amz-expected-bucket-owner` header which isn't
+    /// the owner of the buckets, like S3 does with `403 Access Denied`
+    fn check_bucket_owner(&self, expected: Option<&str>) -> S3Result<()> {
+        match expected {
+            Some(expected) if expected != self.owner_id => {
+                tracing::debug!(expected, owner = %self.owner_id, "Expected bucket owner mismatch");
+                Err(s3_error!(AccessDenied, "Access Denied"))
+            }
+            _ => Ok(()),
+        }
     }
 
-    // Compute the e_tag of the multpart upload. Per the S3 standard (according to minio), the
-    // e_tag of a multipart uploaded object is the Md5 of the Md5 of the parts.
-    fn calculate_multipart_hash(&self, blocks: &[BlockID]) -> io::Result<([u8; 16], usize)> {
-        let mut hasher = Md5::new();
+    /// The write of `operation` to `key` in `bucket`, as the write hooks see it
+    fn object_write(
+        &self,
+        operation: &'static str,
+        bucket: &str,
+        key: &str,
+        content_type: Option<String>,
+        size: Option<u64>,
+    ) -> ObjectWrite {
+        ObjectWrite {
+            operation,
+            owner: self.owner_id.clone(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            content_type,
+            size,
+        }
+    }
+
+    /// Run the write hooks before `write`, a rejection denies the request
+    async fn check_write(&self, write: &ObjectWrite) -> S3Result<()> {
+        self.write_hooks
+            .before_write(write)
+            .await
+            .map_err(|rejection| s3_error!(AccessDenied, "{}", rejection))
+    }
+
+    /// Decide by the policy of `scanner` whether the object of `write` is stored,
+    /// with the scan `report` of its data. Returns the value of the tag with the
+    /// result to set on the object.
+    fn check_scan(
+        &self,
+        scanner: &Scanner,
+        write: &ObjectWrite,
+        report: ScanReport,
+    ) -> S3Result<Option<&'static str>> {
+        self.metrics
+            .record_scan(report.verdict.as_str(), report.bytes, report.duration);
+        match &report.verdict {
+            ScanVerdict::Clean => {}
+            ScanVerdict::Infected(name) => tracing::warn!(
+                operation = write.operation,
+                bucket = %write.bucket,
+                key = %write.key,
+                virus = %name,
+                "Infected object uploaded"
+            ),
+            ScanVerdict::Failed(error) => tracing::warn!(
+                operation = write.operation,
+                bucket = %write.bucket,
+                key = %write.key,
+                error = %error,
+                "Could not scan object"
+            ),
+        }
+        scanner
+            .judge(&report.verdict)
+            .map_err(|refusal| match refusal {
+                ScanRefusal::Infected(_) => s3_error!(AccessDenied, "{}", refusal),
+                ScanRefusal::Failed => s3_error!(ServiceUnavailable, "{}", refusal),
+            })
+    }
+
+    /// The ACL and tags of a new object, to commit with its metadata so it is never
+    /// visible with those of the object it replaces. Without an ACL the object
+    /// follows the bucket. With a `scan` result the tags get the tag of the scanner.
+    fn object_attributes(
+        acl: Option<CannedAcl>,
+        tags: &ObjectTags,
+        scan: Option<(&Scanner, &str)>,
+    ) -> S3Result<ObjectAttributes> {
+        let tags = match scan {
+            Some((scanner, result)) => scanner
+                .result_tags(tags, result)
+                .map_err(|e| s3_error!(InvalidTag, "{}", e))?,
+            None => tags.clone(),
+        };
+        Ok(ObjectAttributes::default().with_acl(acl).with_tags(tags))
+    }
+
+    /// Persist the metadata of a write with the durability requested by the
+    /// client, before the write is acknowledged. `buffer` can't weaken the
+    /// durability of the bucket, its commits are already persisted.
+    async fn persist_requested(&self, durability: Option<Durability>) -> S3Result<()> {
+        if let Some(durability) = durability.filter(Durability::is_synced) {
+            try_!(
+                self.casfs
+                    .run_blocking(move |fs| fs.persist_metadata(durability))
+                    .await
+            );
+        }
+        Ok(())
+    }
+
+    // The `x-cas-*` headers of `obj`, from the size and location of its blocks
+    fn object_cas_headers(&self, obj: &cas_storage::Object) -> S3Result<hyper::HeaderMap> {
+        let mut blocks = Vec::new();
+        if !obj.is_inlined() {
+            let block_tree = try_!(self.casfs.block_tree());
+            let distinct: HashSet<_> = obj.blocks().iter().collect();
+            for id in distinct {
+                if let Some(block) = try_!(block_tree.get_block(id)) {
+                    blocks.push(block);
+                }
+            }
+        }
+        let blocks: Vec<_> = blocks
+            .iter()
+            .map(|block| (block.size(), block.location()))
+            .collect();
+        Ok(cas_headers(obj, &blocks))
+    }
+
+    /// Count a successful request in the activity of the bucket and its metrics
+    fn record_access(&self, bucket: &str, access: Access, bytes: u64) {
+        self.casfs.record_access(bucket, access, bytes);
+        self.metrics.observe_bucket_access(bucket, access, bytes);
+    }
+
+    /// Count a successful read of `bytes` of object data, charged to the requester
+    /// instead of the owner of the bucket if `requester_charged`
+    fn record_read(&self, bucket: &str, bytes: u64, requester_charged: bool) {
+        if requester_charged {
+            self.casfs.record_requester_paid_read(bucket, bytes);
+            self.metrics
+                .observe_bucket_access(bucket, Access::Read, bytes);
+        } else {
+            self.record_access(bucket, Access::Read, bytes);
+        }
+    }
+
+    /// Reject a read of a requester pays bucket which doesn't accept the charges
+    /// with `x-amz-request-payer: requester`, like S3 does with `403 Access
+    /// Denied`. Anonymous requests are rejected, there is no one to charge.
+    /// Returns whether the requester is charged for the read.
+    fn check_request_payer(
+        &self,
+        bucket: &str,
+        request_payer: Option<&RequestPayer>,
+        anonymous: bool,
+    ) -> S3Result<bool> {
+        if !try_!(self.casfs.requester_pays(bucket)) {
+            return Ok(false);
+        }
+        if anonymous {
+            return Err(s3_error!(
+                AccessDenied,
+                "Anonymous requests to a requester pays bucket are not allowed"
+            ));
+        }
+        match request_payer {
+            Some(payer) if payer.as_str() == RequestPayer::REQUESTER => Ok(true),
+            _ => Err(s3_error!(
+                AccessDenied,
+                "The bucket is requester pays, set the x-amz-request-payer: requester header"
+            )),
+        }
+    }
+
+    /// The default server-side encryption of a bucket, reported for its objects
+    fn bucket_encryption(&self, bucket: &str) -> S3Result<Option<ServerSideEncryption>> {
+        Ok(try_!(self.casfs.bucket_encryption(bucket)).map(ServerSideEncryption::from))
+    }
+
+    /// The `x-amz-expiration` header of an object last written at `mtime`, if a
+    /// lifecycle rule of its bucket applies to it
+    fn expiration(&self, bucket: &str, key: &str, mtime: SystemTime) -> S3Result<Option<String>> {
+        let expiration = try_!(self.casfs.object_expiration(bucket, key, mtime));
+        Ok(expiration.as_ref().map(expiration_header))
+    }
+
+    /// The server-side encryption of an object written to `bucket`: the default of
+    /// the bucket applies whatever the request asks for, so requests asking for
+    /// another algorithm or a customer provided key are rejected.
+    fn write_encryption(
+        &self,
+        bucket: &str,
+        requested: Option<&ServerSideEncryption>,
+        sse_customer_algorithm: Option<&str>,
+    ) -> S3Result<Option<ServerSideEncryption>> {
+        let Some(default) = self.bucket_encryption(bucket)? else {
+            return Ok(None);
+        };
+        let other = requested.map_or(false, |requested| requested.as_str() != default.as_str());
+        if other || sse_customer_algorithm.is_some() {
+            return Err(s3_error!(
+                InvalidArgument,
+                "Bucket {} requires {} server-side encryption",
+                bucket,
+                default.as_str()
+            ));
+        }
+        Ok(Some(default))
+    }
+
+    /// Rejects storing an object of `size` bytes under `key` if it exceeds the
+    /// object or size limit of the bucket
+    fn check_bucket_limits(&self, bucket: &str, key: &str, size: u64) -> S3Result<()> {
+        match try_!(self.casfs.check_bucket_limits(bucket, key, size)) {
+            Some(exceeded) => {
+                tracing::debug!(bucket, key, size, "{}", exceeded);
+                Err(s3_error!(AccessDenied, "{}", exceeded))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The directories at the top level of `bucket`, from its prefix counts
+    fn top_level_directories(&self, bucket: &str) -> S3Result<Vec<String>> {
+        let counts = try_!(self.casfs.prefix_counts(bucket));
+        Ok(counts.into_iter().map(|count| count.prefix).collect())
+    }
+
+    // Split the entries of a listing page into the objects, with their owner if
+    // `fetch_owner`, and the common prefixes
+    fn list_page(
+        &self,
+        entries: Vec<ListEntry<cas_storage::Object>>,
+        fetch_owner: bool,
+    ) -> (Vec<s3s::dto::Object>, Vec<CommonPrefix>) {
+        let mut objects = Vec::new();
+        let mut common_prefixes = Vec::new();
+        for entry in entries {
+            match entry {
+                ListEntry::Object(key, obj) => objects.push(s3s::dto::Object {
+                    key: Some(key),
+                    e_tag: Some(obj.format_e_tag()),
+                    last_modified: Some(obj.last_modified().into()),
+                    owner: fetch_owner.then(|| self.owner()),
+                    size: Some(obj.size() as i64),
+                    // all objects are stored alike
+                    storage_class: Some(ObjectStorageClass::from_static(
+                        ObjectStorageClass::STANDARD,
+                    )),
+                    ..Default::default()
+                }),
+                ListEntry::CommonPrefix(prefix) => common_prefixes.push(CommonPrefix {
+                    prefix: Some(prefix),
+                }),
+            }
+        }
+        (objects, common_prefixes)
+    }
+
+    // Compute the content hash of the multipart upload, the hash of its block ids. The e_tag
+    // is computed from the e_tags of the parts instead, see `multipart_e_tag`.
+    fn calculate_multipart_hash(&self, blocks: &[BlockID]) -> io::Result<(BlockID, usize)> {
+        let mut hasher = self.casfs.content_hash().hasher();
         let mut size = 0;
         let block_map = self.casfs.block_tree()?;
 
@@ -63,7 +409,7 @@ impl S3FS {
             hasher.update(block);
         }
 
-        Ok((hasher.finalize().into(), size))
+        Ok((hasher.finalize(), size))
     }
 }
 
@@ -71,6 +417,129 @@ fn fmt_content_range(start: u64, end_inclusive: u64, size: u64) -> String {
     format!("bytes {start}-{end_inclusive}/{size}")
 }
 
+/// The `x-amz-request-charged` header of a response charged to the requester
+fn request_charged(requester_charged: bool) -> Option<RequestCharged> {
+    requester_charged.then(|| RequestCharged::from_static(RequestCharged::REQUESTER))
+}
+
+/// Content type of the parts of a `multipart/byteranges` response, the content
+/// type of objects isn't stored.
+const BYTERANGES_PART_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Header with which a client asks for the metadata of a write to be persisted
+/// with a stronger durability than the one of its bucket, e.g. `fsync`
+pub const DURABILITY_HEADER: &str = "x-cas-durability";
+
+/// The durability requested by the `x-cas-durability` header of a write, if any.
+pub fn requested_durability(headers: &hyper::HeaderMap) -> S3Result<Option<Durability>> {
+    let Some(value) = headers.get(DURABILITY_HEADER) else {
+        return Ok(None);
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| s3_error!(InvalidArgument, "Invalid {} header", DURABILITY_HEADER))?;
+    value.parse().map(Some).map_err(|e: String| {
+        s3_error!(
+            InvalidArgument,
+            "Invalid {} header: {}",
+            DURABILITY_HEADER,
+            e
+        )
+    })
+}
+
+/// Headers describing how an object is stored, returned on HEAD with
+/// [`S3FS::with_cas_headers`]:
+///
+/// - `x-cas-block-count`: the blocks of the object, 0 if it's inlined
+/// - `x-cas-physical-size`: the size of its distinct blocks, or of its data if
+///   it's inlined. Blocks shared with other objects count fully
+/// - `x-cas-inline`: `true` if the data is inlined in the metadata
+/// - `x-cas-tier`: the storage locations of its blocks, comma separated,
+///   `default` for the one in the fs root. Not set for inlined objects
+///
+/// `blocks` are the size and storage location of the distinct blocks of `obj`.
+pub fn cas_headers(
+    obj: &cas_storage::Object,
+    blocks: &[(usize, Option<&str>)],
+) -> hyper::HeaderMap {
+    let mut headers = hyper::HeaderMap::new();
+    let physical_size = if obj.is_inlined() {
+        obj.size()
+    } else {
+        blocks.iter().map(|(size, _)| *size as u64).sum()
+    };
+    headers.insert("x-cas-block-count", obj.blocks().len().into());
+    headers.insert("x-cas-physical-size", physical_size.into());
+    headers.insert(
+        "x-cas-inline",
+        hyper::header::HeaderValue::from_static(if obj.is_inlined() { "true" } else { "false" }),
+    );
+    let tiers: BTreeSet<_> = blocks
+        .iter()
+        .map(|(_, location)| location.unwrap_or("default"))
+        .collect();
+    if !tiers.is_empty() {
+        let tiers = tiers.into_iter().collect::<Vec<_>>().join(",");
+        // location names come from the configuration, but aren't checked to be
+        // valid in a header
+        if let Ok(value) = tiers.parse() {
+            headers.insert("x-cas-tier", value);
+        }
+    }
+    headers
+}
+
+/// A `Range` header with multiple ranges, stored in the request extensions by
+/// [`take_multi_range`].
+#[derive(Debug, Clone)]
+pub struct MultiRange(pub String);
+
+/// Move a `Range` header with multiple ranges from the headers of `req` to its
+/// extensions. s3s only parses single ranges and rejects the request otherwise,
+/// `get_object` answers these with a `multipart/byteranges` response.
+///
+/// A header covered by the signature of the request is left alone, as removing it
+/// would fail the signature check. Browsers and viewers sending multiple ranges use
+/// presigned URLs or anonymous access, which don't sign it.
+pub fn take_multi_range<B>(req: &mut hyper::Request<B>) {
+    let range = match req.headers().get(hyper::header::RANGE) {
+        Some(value) => match value.to_str() {
+            Ok(value) if value.contains(',') => MultiRange(value.to_string()),
+            _ => return,
+        },
+        None => return,
+    };
+    if signs_header(req, "range") {
+        return;
+    }
+    req.headers_mut().remove(hyper::header::RANGE);
+    req.extensions_mut().insert(range);
+}
+
+// whether the SigV4 signature of `req`, in the Authorization header or the query of
+// a presigned URL, covers the header `name`
+fn signs_header<B>(req: &hyper::Request<B>, name: &str) -> bool {
+    let from_header = req
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|auth| auth.split_once("SignedHeaders="))
+        .map(|(_, rest)| rest.split(',').next().unwrap_or_default().to_string());
+    let from_query = || {
+        req.uri().query().and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("X-Amz-SignedHeaders="))
+                .map(|value| value.replace("%3B", ";").replace("%3b", ";"))
+        })
+    };
+    match from_header.or_else(from_query) {
+        Some(signed) => signed.split(';').any(|header| header.trim() == name),
+        None => false,
+    }
+}
+
 #[async_trait::async_trait]
 impl S3 for S3FS {
     #[tracing::instrument(skip(self, req), fields(bucket, key, upload_id))]
@@ -78,6 +547,8 @@ impl S3 for S3FS {
         &self,
         req: S3Request<CompleteMultipartUploadInput>,
     ) -> S3Result<S3Response<CompleteMultipartUploadOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let durability = requested_durability(&req.headers)?;
         let CompleteMultipartUploadInput {
             multipart_upload,
             bucket,
@@ -105,6 +576,7 @@ impl S3 for S3FS {
         };
 
         let mut blocks = vec![];
+        let mut part_e_tags = vec![];
         let mut cnt: i32 = 0;
         for part in multipart_upload.parts.iter().flatten() {
             // validate part number
@@ -148,6 +620,7 @@ impl S3 for S3FS {
                 }
             };
             blocks.extend_from_slice(mp.blocks());
+            part_e_tags.push(*mp.e_tag());
         }
 
         tracing::debug!(
@@ -157,17 +630,71 @@ impl S3 for S3FS {
         );
 
         let (content_hash, size) = try_!(self.calculate_multipart_hash(&blocks));
+        // Per the S3 standard (according to minio), the e_tag of a multipart uploaded object
+        // is the Md5 of the Md5 of the parts.
+        let e_tag = multipart_e_tag(&part_e_tags);
 
-        let object_meta = try_!(self.casfs.create_object_meta(
+        let write = self.object_write(
+            "CompleteMultipartUpload",
             &bucket,
             &key,
-            size as u64,
-            content_hash,
-            ObjectData::MultiPart {
-                blocks: blocks.clone(),
-                parts: cnt as usize
-            },
-        ));
+            None,
+            Some(size as u64),
+        );
+        self.check_write(&write).await?;
+        let scan = match self.write_hooks.scanner(&write, size as u64) {
+            Some(scanner) => {
+                // the parts are only scanned as a whole. Reading them back is not
+                // counted as data sent to clients.
+                let paths = try_!(self.casfs.block_paths(&blocks));
+                let data = BlockStream::new(
+                    paths,
+                    size,
+                    RangeRequest::All,
+                    cas_storage::SharedMetrics::default(),
+                );
+                let report = scanner.scan_all(&write, data).await;
+                self.check_scan(scanner, &write, report)?
+                    .map(|result| (scanner, result))
+            }
+            None => None,
+        };
+
+        let guard = self.casfs.lock_object(&bucket, &key).await;
+        self.check_bucket_limits(&bucket, &key, size as u64)?;
+        // the new object keeps the tags set on the key when the upload was
+        // created, the tag of the scanner is committed with its metadata
+        let attributes = match scan {
+            Some((scanner, result)) => {
+                let tags = try_!(self.casfs.object_tags(&bucket, &key));
+                let tags = scanner
+                    .result_tags(&tags, result)
+                    .map_err(|e| s3_error!(InvalidTag, "{}", e))?;
+                ObjectAttributes::default().with_tags(tags)
+            }
+            None => ObjectAttributes::default(),
+        };
+        let object_data = ObjectData::MultiPart {
+            blocks: blocks.clone(),
+            parts: cnt as usize,
+        };
+        let (meta_bucket, meta_key) = (bucket.clone(), key.clone());
+        let object_meta = try_!(
+            self.casfs
+                .run_blocking(move |fs| {
+                    fs.create_object_meta(
+                        &meta_bucket,
+                        &meta_key,
+                        size as u64,
+                        content_hash,
+                        e_tag,
+                        object_data,
+                        &attributes,
+                    )
+                })
+                .await
+        );
+        drop(guard);
 
         tracing::debug!(
             bucket = %bucket,
@@ -199,8 +726,13 @@ impl S3 for S3FS {
             cleaned_parts = cleaned_parts,
             "Completed multipart upload successfully"
         );
+        self.persist_requested(durability).await?;
+        self.record_access(&bucket, Access::Write, 0);
+        self.write_hooks.after_write(write, object_meta.clone());
 
         let output = CompleteMultipartUploadOutput {
+            server_side_encryption: self.bucket_encryption(&bucket)?,
+            expiration: self.expiration(&bucket, &key, object_meta.last_modified())?,
             bucket: Some(bucket),
             key: Some(key),
             e_tag: Some(object_meta.format_e_tag()),
@@ -225,6 +757,7 @@ impl S3 for S3FS {
         let input = req.input;
 
         tracing::debug!(bucket = %input.bucket, "Create bucket");
+        let acl = parse_canned_acl(input.acl.as_ref().map(|acl| acl.as_str()))?;
         if try_!(self.casfs.bucket_exists(&input.bucket)) {
             return Err(s3_error!(
                 BucketAlreadyExists,
@@ -233,6 +766,9 @@ impl S3 for S3FS {
         }
 
         try_!(self.casfs.create_bucket(&input.bucket));
+        if let Some(acl) = acl {
+            try_!(self.casfs.set_bucket_acl(&input.bucket, acl));
+        }
 
         self.metrics.inc_bucket_count();
 
@@ -246,11 +782,45 @@ impl S3 for S3FS {
         &self,
         req: S3Request<CreateMultipartUploadInput>,
     ) -> S3Result<S3Response<CreateMultipartUploadOutput>> {
-        let CreateMultipartUploadInput { bucket, key, .. } = req.input;
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let CreateMultipartUploadInput {
+            bucket,
+            key,
+            content_type,
+            acl,
+            tagging,
+            server_side_encryption,
+            sse_customer_algorithm,
+            ..
+        } = req.input;
 
+        let acl = parse_canned_acl(acl.as_ref().map(|acl| acl.as_str()))?;
+        let tags = match tagging {
+            Some(header) => parse_tagging_header(&header)?,
+            None => Default::default(),
+        };
         if !try_!(self.casfs.bucket_exists(&bucket)) {
             return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
         }
+        let server_side_encryption = self.write_encryption(
+            &bucket,
+            server_side_encryption.as_ref(),
+            sse_customer_algorithm.as_deref(),
+        )?;
+        // reject uploads to a full bucket early, the size is checked on completion
+        self.check_bucket_limits(&bucket, &key, 0)?;
+        let write = self.object_write(
+            "CreateMultipartUpload",
+            &bucket,
+            &key,
+            Some(content_type.unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_string())),
+            None,
+        );
+        self.check_write(&write).await?;
+        // there is no bookkeeping for uploads, so the ACL and tags are applied to the
+        // key right away, like put_object does before writing the data
+        try_!(self.casfs.set_object_acl(&bucket, &key, acl));
+        try_!(self.casfs.set_object_tags(&bucket, &key, &tags));
 
         let upload_id = Uuid::new_v4().to_string();
 
@@ -265,6 +835,7 @@ impl S3 for S3FS {
             bucket: Some(bucket),
             key: Some(key),
             upload_id: Some(upload_id.to_string()),
+            server_side_encryption,
             ..Default::default()
         };
 
@@ -275,6 +846,7 @@ impl S3 for S3FS {
         &self,
         req: S3Request<DeleteBucketInput>,
     ) -> S3Result<S3Response<DeleteBucketOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
         let DeleteBucketInput { bucket, .. } = req.input;
 
         try_!(self.casfs.bucket_delete(&bucket).await);
@@ -284,11 +856,57 @@ impl S3 for S3FS {
         Ok(S3Response::new(DeleteBucketOutput {}))
     }
 
+    async fn delete_bucket_encryption(
+        &self,
+        req: S3Request<DeleteBucketEncryptionInput>,
+    ) -> S3Result<S3Response<DeleteBucketEncryptionOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let DeleteBucketEncryptionInput { bucket, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        try_!(self.casfs.set_bucket_encryption(&bucket, None));
+        Ok(S3Response::new(DeleteBucketEncryptionOutput::default()))
+    }
+
+    async fn delete_bucket_lifecycle(
+        &self,
+        req: S3Request<DeleteBucketLifecycleInput>,
+    ) -> S3Result<S3Response<DeleteBucketLifecycleOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let DeleteBucketLifecycleInput { bucket, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        try_!(self.casfs.set_bucket_lifecycle(&bucket, None));
+        Ok(S3Response::new(DeleteBucketLifecycleOutput::default()))
+    }
+
+    async fn delete_bucket_tagging(
+        &self,
+        req: S3Request<DeleteBucketTaggingInput>,
+    ) -> S3Result<S3Response<DeleteBucketTaggingOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let DeleteBucketTaggingInput { bucket, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        try_!(self.casfs.set_bucket_tags(&bucket, Default::default()));
+        Ok(S3Response::new(DeleteBucketTaggingOutput::default()))
+    }
+
     #[tracing::instrument(skip(self, req), fields(bucket, key))]
     async fn delete_object(
         &self,
         req: S3Request<DeleteObjectInput>,
     ) -> S3Result<S3Response<DeleteObjectOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
         let DeleteObjectInput { bucket, key, .. } = req.input;
 
         tracing::Span::current().record("bucket", &tracing::field::display(&bucket));
@@ -303,14 +921,34 @@ impl S3 for S3FS {
         // TODO: check for the key existence?
         try_!(self.casfs.delete_object(&bucket, &key).await);
 
+        self.record_access(&bucket, Access::Write, 0);
         let output = DeleteObjectOutput::default(); // TODO: handle other fields
         Ok(S3Response::new(output))
     }
 
+    async fn delete_object_tagging(
+        &self,
+        req: S3Request<DeleteObjectTaggingInput>,
+    ) -> S3Result<S3Response<DeleteObjectTaggingOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let DeleteObjectTaggingInput { bucket, key, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+        if !try_!(self.casfs.key_exists(&bucket, &key)) {
+            return Err(s3_error!(NoSuchKey, "Object does not exist"));
+        }
+
+        try_!(self.casfs.set_object_tags(&bucket, &key, &Default::default()));
+        Ok(S3Response::new(DeleteObjectTaggingOutput::default()))
+    }
+
     async fn delete_objects(
         &self,
         req: S3Request<DeleteObjectsInput>,
     ) -> S3Result<S3Response<DeleteObjectsOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
         let DeleteObjectsInput { bucket, delete, .. } = req.input;
 
         tracing::debug!(bucket = %bucket, object_count = delete.objects.len(), "Delete objects");
@@ -343,6 +981,7 @@ impl S3 for S3FS {
             };
         }
 
+        self.record_access(&bucket, Access::Write, 0);
         let output = DeleteObjectsOutput {
             deleted: Some(deleted_objects),
             errors: if errors.is_empty() {
@@ -355,10 +994,88 @@ impl S3 for S3FS {
         Ok(S3Response::new(output))
     }
 
+    async fn get_bucket_acl(
+        &self,
+        req: S3Request<GetBucketAclInput>,
+    ) -> S3Result<S3Response<GetBucketAclOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let GetBucketAclInput { bucket, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        let acl = try_!(self.casfs.bucket_acl(&bucket));
+        let output = GetBucketAclOutput {
+            grants: Some(acl_grants(acl, &self.owner_id)),
+            owner: Some(self.owner()),
+        };
+        Ok(S3Response::new(output))
+    }
+
+    async fn get_bucket_encryption(
+        &self,
+        req: S3Request<GetBucketEncryptionInput>,
+    ) -> S3Result<S3Response<GetBucketEncryptionOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let GetBucketEncryptionInput { bucket, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        let Some(sse_algorithm) = self.bucket_encryption(&bucket)? else {
+            let mut err = s3s::S3Error::with_message(
+                s3s::S3ErrorCode::Custom("ServerSideEncryptionConfigurationNotFoundError".into()),
+                "The server side encryption configuration was not found",
+            );
+            err.set_status_code(hyper::StatusCode::NOT_FOUND);
+            return Err(err);
+        };
+        let rule = ServerSideEncryptionRule {
+            apply_server_side_encryption_by_default: Some(ServerSideEncryptionByDefault {
+                kms_master_key_id: None,
+                sse_algorithm,
+            }),
+            bucket_key_enabled: None,
+        };
+        Ok(S3Response::new(GetBucketEncryptionOutput {
+            server_side_encryption_configuration: Some(ServerSideEncryptionConfiguration {
+                rules: vec![rule],
+            }),
+        }))
+    }
+
+    async fn get_bucket_lifecycle_configuration(
+        &self,
+        req: S3Request<GetBucketLifecycleConfigurationInput>,
+    ) -> S3Result<S3Response<GetBucketLifecycleConfigurationOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let GetBucketLifecycleConfigurationInput { bucket, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        let Some(lifecycle) = try_!(self.casfs.bucket_lifecycle(&bucket)) else {
+            let mut err = s3s::S3Error::with_message(
+                s3s::S3ErrorCode::Custom("NoSuchLifecycleConfiguration".into()),
+                "The lifecycle configuration does not exist",
+            );
+            err.set_status_code(hyper::StatusCode::NOT_FOUND);
+            return Err(err);
+        };
+        Ok(S3Response::new(GetBucketLifecycleConfigurationOutput {
+            rules: Some(lifecycle_rules(&lifecycle)),
+            ..Default::default()
+        }))
+    }
+
     async fn get_bucket_location(
         &self,
         req: S3Request<GetBucketLocationInput>,
     ) -> S3Result<S3Response<GetBucketLocationOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
         let input = req.input;
         let exists = try_!(self.casfs.bucket_exists(&input.bucket));
 
@@ -370,13 +1087,90 @@ impl S3 for S3FS {
         Ok(S3Response::new(output))
     }
 
+    async fn get_bucket_logging(
+        &self,
+        req: S3Request<GetBucketLoggingInput>,
+    ) -> S3Result<S3Response<GetBucketLoggingOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let GetBucketLoggingInput { bucket, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        let logging_enabled =
+            try_!(self.casfs.bucket_logging(&bucket)).map(|logging| LoggingEnabled {
+                target_bucket: logging.target_bucket,
+                target_prefix: logging.target_prefix,
+                ..Default::default()
+            });
+        Ok(S3Response::new(GetBucketLoggingOutput { logging_enabled }))
+    }
+
+    async fn get_bucket_request_payment(
+        &self,
+        req: S3Request<GetBucketRequestPaymentInput>,
+    ) -> S3Result<S3Response<GetBucketRequestPaymentOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let GetBucketRequestPaymentInput { bucket, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        let payer = if try_!(self.casfs.requester_pays(&bucket)) {
+            Payer::REQUESTER
+        } else {
+            Payer::BUCKET_OWNER
+        };
+        Ok(S3Response::new(GetBucketRequestPaymentOutput {
+            payer: Some(Payer::from_static(payer)),
+        }))
+    }
+
+    async fn get_bucket_tagging(
+        &self,
+        req: S3Request<GetBucketTaggingInput>,
+    ) -> S3Result<S3Response<GetBucketTaggingOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let GetBucketTaggingInput { bucket, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        let tags = try_!(self.casfs.bucket_tags(&bucket));
+        if tags.is_empty() {
+            let mut err = s3s::S3Error::with_message(
+                s3s::S3ErrorCode::Custom("NoSuchTagSet".into()),
+                "The TagSet does not exist",
+            );
+            err.set_status_code(hyper::StatusCode::NOT_FOUND);
+            return Err(err);
+        }
+        Ok(S3Response::new(GetBucketTaggingOutput {
+            tag_set: tag_set(&tags),
+        }))
+    }
+
     #[tracing::instrument(skip(self, req), fields(bucket, key, size))]
     async fn get_object(
         &self,
         req: S3Request<GetObjectInput>,
     ) -> S3Result<S3Response<GetObjectOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let requester_charged = self.check_request_payer(
+            &req.input.bucket,
+            req.input.request_payer.as_ref(),
+            req.credentials.is_none(),
+        )?;
+        let multi_range = req.extensions.get::<MultiRange>().cloned();
         let GetObjectInput {
-            bucket, key, range, ..
+            bucket,
+            key,
+            range,
+            if_none_match,
+            ..
         } = req.input;
 
         tracing::Span::current().record("bucket", &tracing::field::display(&bucket));
@@ -387,11 +1181,13 @@ impl S3 for S3FS {
         if !try_!(self.casfs.bucket_exists(&bucket)) {
             return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
         }
+        let server_side_encryption = self.bucket_encryption(&bucket)?;
 
         // load metadata
 
-        let (obj_meta, paths) = match self.casfs.get_object_paths(&bucket, &key) {
-            Ok(Some((obj_meta, paths))) => (obj_meta, paths),
+        // the blocks are pinned so a concurrent delete can't remove them while streaming
+        let (obj_meta, paths, pin) = match self.casfs.get_object_paths_pinned(&bucket, &key) {
+            Ok(Some((obj_meta, paths, pin))) => (obj_meta, paths, pin),
             Ok(None) => {
                 return Err(s3_error!(NoSuchKey, "Object does not exist"));
             }
@@ -401,6 +1197,59 @@ impl S3 for S3FS {
             }
         };
 
+        let corrupt = try_!(self.casfs.unhealed_blocks(&obj_meta));
+        if !corrupt.is_empty() {
+            let description = self.casfs.describe_corrupt_blocks(&corrupt);
+            tracing::warn!(bucket = %bucket, key = %key, "Read of object with {description}");
+            return Err(s3_error!(InternalError, "Object has {description}"));
+        }
+
+        let e_tag = obj_meta.format_e_tag();
+        if matches!(&if_none_match, Some(condition) if etag_matches(condition, &e_tag)) {
+            return Err(s3_error!(NotModified));
+        }
+        let expiration = self.expiration(&bucket, &key, obj_meta.last_modified())?;
+
+        if let Some(MultiRange(header)) = multi_range {
+            // a malformed header is ignored, and the whole object returned
+            match parse_multi_range_request(&header) {
+                Some(ranges) => {
+                    let Some(byte_ranges) =
+                        ByteRanges::new(&ranges, obj_meta.size(), BYTERANGES_PART_CONTENT_TYPE)
+                    else {
+                        return Err(s3_error!(InvalidRange, "No range is satisfiable"));
+                    };
+                    let body = match obj_meta.inlined() {
+                        Some(data) => StreamingBlob::from(s3s::Body::from(byte_ranges.body(data))),
+                        None => StreamingBlob::wrap(throttled(
+                            byte_ranges
+                                .stream(paths, self.metrics.to_cas_metrics())
+                                .with_pin(pin),
+                            self.throttle.as_ref(),
+                            TrafficClass::ClientRead,
+                        )),
+                    };
+                    let output = GetObjectOutput {
+                        body: Some(body),
+                        content_length: Some(byte_ranges.content_length() as i64),
+                        content_type: Some(byte_ranges.content_type()),
+                        last_modified: Some(Timestamp::from(obj_meta.last_modified())),
+                        e_tag: Some(e_tag),
+                        cache_control: self.cache_control.clone(),
+                        server_side_encryption,
+                        expiration,
+                        request_charged: request_charged(requester_charged),
+                        ..Default::default()
+                    };
+                    self.record_read(&bucket, byte_ranges.content_length(), requester_charged);
+                    let mut response = S3Response::new(output);
+                    response.status = Some(hyper::StatusCode::PARTIAL_CONTENT);
+                    return Ok(response);
+                }
+                None => tracing::debug!(range = %header, "Ignoring malformed Range header"),
+            }
+        }
+
         // if the object is inlined, we return it directly
         if let Some(data) = obj_meta.inlined() {
             let bytes = bytes::Bytes::from(data.clone());
@@ -414,9 +1263,14 @@ impl S3 for S3FS {
                 content_length: Some(stream_size as i64),
                 content_range: Some(fmt_content_range(0, stream_size - 1, stream_size)),
                 last_modified: Some(Timestamp::from(obj_meta.last_modified())),
-                e_tag: Some(obj_meta.format_e_tag()),
+                e_tag: Some(e_tag),
+                cache_control: self.cache_control.clone(),
+                server_side_encryption,
+                expiration,
+                request_charged: request_charged(requester_charged),
                 ..Default::default()
             };
+            self.record_read(&bucket, stream_size, requester_charged);
             return Ok(S3Response::new(output));
         }
 
@@ -432,8 +1286,13 @@ impl S3 for S3FS {
         let block_size: usize = paths.iter().map(|(_, size)| size).sum();
 
         debug_assert!(obj_meta.size() as usize == block_size);
-        let block_stream = BlockStream::new(paths, block_size, range, self.metrics.to_cas_metrics());
-        let stream = StreamingBlob::wrap(block_stream);
+        let block_stream = BlockStream::new(paths, block_size, range, self.metrics.to_cas_metrics())
+            .with_pin(pin);
+        let stream = StreamingBlob::wrap(throttled(
+            block_stream,
+            self.throttle.as_ref(),
+            TrafficClass::ClientRead,
+        ));
 
         let output = GetObjectOutput {
             body: Some(stream),
@@ -441,7 +1300,57 @@ impl S3 for S3FS {
             content_range: Some(fmt_content_range(0, stream_size - 1, stream_size)),
             last_modified: Some(Timestamp::from(obj_meta.last_modified())),
             //metadata: object_metadata,
-            e_tag: Some(obj_meta.format_e_tag()),
+            e_tag: Some(e_tag),
+            cache_control: self.cache_control.clone(),
+            server_side_encryption,
+            expiration,
+            request_charged: request_charged(requester_charged),
+            ..Default::default()
+        };
+        self.record_read(&bucket, stream_size, requester_charged);
+        Ok(S3Response::new(output))
+    }
+
+    async fn get_object_acl(
+        &self,
+        req: S3Request<GetObjectAclInput>,
+    ) -> S3Result<S3Response<GetObjectAclOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let GetObjectAclInput { bucket, key, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+        if !try_!(self.casfs.key_exists(&bucket, &key)) {
+            return Err(s3_error!(NoSuchKey, "Object does not exist"));
+        }
+
+        let acl = try_!(self.casfs.object_acl(&bucket, &key));
+        let output = GetObjectAclOutput {
+            grants: Some(acl_grants(acl, &self.owner_id)),
+            owner: Some(self.owner()),
+            ..Default::default()
+        };
+        Ok(S3Response::new(output))
+    }
+
+    async fn get_object_tagging(
+        &self,
+        req: S3Request<GetObjectTaggingInput>,
+    ) -> S3Result<S3Response<GetObjectTaggingOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let GetObjectTaggingInput { bucket, key, .. } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+        if !try_!(self.casfs.key_exists(&bucket, &key)) {
+            return Err(s3_error!(NoSuchKey, "Object does not exist"));
+        }
+
+        let tags = try_!(self.casfs.object_tags(&bucket, &key));
+        let output = GetObjectTaggingOutput {
+            tag_set: tag_set(&tags),
             ..Default::default()
         };
         Ok(S3Response::new(output))
@@ -451,6 +1360,7 @@ impl S3 for S3FS {
         &self,
         req: S3Request<HeadBucketInput>,
     ) -> S3Result<S3Response<HeadBucketOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
         let HeadBucketInput { bucket, .. } = req.input;
 
         if !try_!(self.casfs.bucket_exists(&bucket)) {
@@ -467,7 +1377,18 @@ impl S3 for S3FS {
         &self,
         req: S3Request<HeadObjectInput>,
     ) -> S3Result<S3Response<HeadObjectOutput>> {
-        let HeadObjectInput { bucket, key, .. } = req.input;
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let requester_charged = self.check_request_payer(
+            &req.input.bucket,
+            req.input.request_payer.as_ref(),
+            req.credentials.is_none(),
+        )?;
+        let HeadObjectInput {
+            bucket,
+            key,
+            if_none_match,
+            ..
+        } = req.input;
 
         if !try_!(self.casfs.bucket_exists(&bucket)) {
             return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
@@ -484,14 +1405,29 @@ impl S3 for S3FS {
             }
         };
 
+        let e_tag = obj_meta.format_e_tag();
+        if matches!(&if_none_match, Some(condition) if etag_matches(condition, &e_tag)) {
+            return Err(s3_error!(NotModified));
+        }
+
         let output = HeadObjectOutput {
             content_length: Some(obj_meta.size() as i64),
             //content_type: Some(content_type),
             last_modified: Some(obj_meta.last_modified().into()),
             //metadata: object_metadata,
+            e_tag: Some(e_tag),
+            cache_control: self.cache_control.clone(),
+            server_side_encryption: self.bucket_encryption(&bucket)?,
+            expiration: self.expiration(&bucket, &key, obj_meta.last_modified())?,
+            request_charged: request_charged(requester_charged),
             ..Default::default()
         };
-        Ok(S3Response::new(output))
+        let mut response = S3Response::new(output);
+        if self.cas_headers {
+            response.headers = self.object_cas_headers(&obj_meta)?;
+        }
+        self.record_read(&bucket, 0, requester_charged);
+        Ok(response)
     }
 
     async fn list_buckets(
@@ -510,7 +1446,7 @@ impl S3 for S3FS {
         }
         let output = ListBucketsOutput {
             buckets: Some(buckets),
-            owner: None,
+            owner: Some(self.owner()),
             ..Default::default()
         };
         Ok(S3Response::new(output))
@@ -520,6 +1456,12 @@ impl S3 for S3FS {
         &self,
         req: S3Request<ListObjectsInput>,
     ) -> S3Result<S3Response<ListObjectsOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let requester_charged = self.check_request_payer(
+            &req.input.bucket,
+            req.input.request_payer.as_ref(),
+            req.credentials.is_none(),
+        )?;
         let ListObjectsInput {
             bucket,
             delimiter,
@@ -530,43 +1472,71 @@ impl S3 for S3FS {
             ..
         } = req.input;
 
-        let key_count = max_keys
-            .map(|mk| if mk > MAX_KEYS { MAX_KEYS } else { mk })
-            .unwrap_or(MAX_KEYS);
+        let encoding = KeyEncoding::from_request(encoding_type.as_ref())?;
+        let key_count = self.list_limits.page_size(max_keys);
 
         let b = try_!(self.casfs.get_bucket(&bucket));
 
-        let mut objects = b
-            .range_filter(marker.clone(), prefix.clone(), None)
-            .map(|(key, obj)| s3s::dto::Object {
-                key: Some(key),
-                e_tag: Some(obj.format_e_tag()),
-                last_modified: Some(obj.last_modified().into()),
-                owner: None,
-                size: Some(obj.size() as i64),
-                storage_class: None,
-                ..Default::default()
-            })
-            .take((key_count + 1) as usize)
-            .collect::<Vec<_>>();
+        let list_prefix = prefix.clone().unwrap_or_default();
+        // the common prefixes at the top level are the directories of the prefix
+        // counts, their keys aren't scanned
+        let grouped: Box<dyn Iterator<Item = _> + '_> =
+            if is_top_level(&list_prefix, delimiter.as_deref()) {
+                Box::new(group_by_top_level(
+                    |start| b.range_filter(start, prefix.clone(), None),
+                    &list_prefix,
+                    self.top_level_directories(&bucket)?,
+                    marker.as_deref(),
+                ))
+            } else {
+                Box::new(group_by_delimiter(
+                    b.range_filter(marker.clone(), prefix.clone(), None),
+                    &list_prefix,
+                    delimiter.as_deref(),
+                    marker.as_deref(),
+                ))
+            };
+        let mut entries: Vec<_> = grouped.take((key_count + 1) as usize).collect();
 
-        let mut next_marker = None;
-        let truncated = objects.len() == key_count as usize + 1;
+        let truncated = entries.len() == key_count as usize + 1;
         if truncated {
-            next_marker = Some(objects.pop().unwrap().key.unwrap())
+            entries.pop();
         }
+        // like S3 the next marker is only returned with a delimiter, without one
+        // clients continue after the last key
+        let next_marker = match (truncated, &delimiter) {
+            (true, Some(_)) => entries.last().map(|entry| entry.key().to_string()),
+            _ => None,
+        };
+
+        self.record_read(&bucket, 0, requester_charged);
+
+        // ListObjects (v1) always reports the owner
+        let (mut objects, mut common_prefixes) = self.list_page(entries, true);
+        let encoding = encoding.for_values(
+            objects
+                .iter()
+                .filter_map(|obj| obj.key.as_deref())
+                .chain(common_prefixes.iter().filter_map(|cp| cp.prefix.as_deref()))
+                .chain(prefix.as_deref())
+                .chain(delimiter.as_deref())
+                .chain(marker.as_deref())
+                .chain(next_marker.as_deref()),
+        );
+        encode_page(encoding, &mut objects, &mut common_prefixes);
 
         let output = ListObjectsOutput {
             contents: Some(objects),
-            delimiter,
-            encoding_type,
+            common_prefixes: Some(common_prefixes),
+            delimiter: encoding.encode_opt(delimiter),
+            encoding_type: encoding.encoding_type(),
             name: Some(bucket),
-            //common_prefixes: None,
             is_truncated: Some(truncated),
-            next_marker: if marker.is_some() { next_marker } else { None },
-            marker,
+            next_marker: encoding.encode_opt(next_marker),
+            marker: encoding.encode_opt(marker),
             max_keys: Some(key_count),
-            prefix,
+            prefix: encoding.encode_opt(prefix),
+            request_charged: request_charged(requester_charged),
             ..Default::default()
         };
         Ok(S3Response::new(output))
@@ -582,6 +1552,12 @@ impl S3 for S3FS {
         &self,
         req: S3Request<ListObjectsV2Input>,
     ) -> S3Result<S3Response<ListObjectsV2Output>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let requester_charged = self.check_request_payer(
+            &req.input.bucket,
+            req.input.request_payer.as_ref(),
+            req.credentials.is_none(),
+        )?;
         let ListObjectsV2Input {
             bucket,
             delimiter,
@@ -590,71 +1566,356 @@ impl S3 for S3FS {
             start_after,
             max_keys,
             continuation_token,
+            fetch_owner,
             ..
         } = req.input;
 
         tracing::debug!(bucket = %bucket, "List objects v2");
+        let encoding = KeyEncoding::from_request(encoding_type.as_ref())?;
 
         let b = try_!(self.casfs.get_bucket(&bucket));
 
-        // max number of keys to return, default is MAX_KEYS(1000)
-        let requested_keys = max_keys.unwrap_or(MAX_KEYS);
-        let key_count = std::cmp::min(requested_keys, MAX_KEYS);
+        // max number of keys to return, at most the page size of the server
+        let key_count = self.list_limits.page_size(max_keys);
 
         // continuation token
-        let decoded_continuation_token = decode_continuation_token(continuation_token.as_deref())?;
+        let (snapshot_id, listed, decoded_continuation_token) =
+            match continuation_token.as_deref() {
+                Some(token) => {
+                    let token = self.list_limits.decode_token(&self.owner_id, &bucket, token)?;
+                    (token.snapshot_id, token.listed, Some(token.last_key))
+                }
+                None => (None, 0, None),
+            };
+        self.list_limits.check_depth(&bucket, listed, key_count)?;
 
-        let objects: Vec<_> = b
-            .range_filter(
-                start_after.clone(),
-                prefix.clone(),
-                decoded_continuation_token,
-            )
-            .map(|(key, obj)| s3s::dto::Object {
-                key: Some(key),
-                e_tag: Some(obj.format_e_tag()),
-                last_modified: Some(obj.last_modified().into()),
-                owner: None,
-                size: Some(obj.size() as i64),
-                storage_class: None,
-                ..Default::default()
-            })
-            .take(key_count as usize)
-            .collect();
+        // With snapshot listings enabled, every page of a listing is served from the
+        // snapshot taken for its first page.
+        let snapshot = match (self.casfs.list_snapshots(), snapshot_id) {
+            (Some(snapshots), Some(id)) => match snapshots.get(&bucket, id) {
+                Some(snapshot) => Some((snapshots, id, snapshot)),
+                None => {
+                    return Err(s3_error!(
+                        InvalidToken,
+                        "continuation token has expired, restart the listing"
+                    ))
+                }
+            },
+            (Some(snapshots), None) => {
+                let snapshot = b.snapshot();
+                let id = snapshots.insert(&bucket, Arc::clone(&snapshot));
+                Some((snapshots, id, snapshot))
+            }
+            (None, _) => None,
+        };
+
+        // the listing continues after the highest of both, like `range_filter` does
+        let after = std::cmp::max(decoded_continuation_token.clone(), start_after.clone());
+        let list_prefix = prefix.clone().unwrap_or_default();
+        // the common prefixes at the top level are the directories of the prefix
+        // counts, their keys aren't scanned. The counts are current, so snapshot
+        // listings scan.
+        let grouped: Box<dyn Iterator<Item = _> + '_> = match &snapshot {
+            Some((_, _, snapshot)) => Box::new(group_by_delimiter(
+                snapshot.range_filter(
+                    start_after.clone(),
+                    prefix.clone(),
+                    decoded_continuation_token,
+                ),
+                &list_prefix,
+                delimiter.as_deref(),
+                after.as_deref(),
+            )),
+            None if is_top_level(&list_prefix, delimiter.as_deref()) => {
+                Box::new(group_by_top_level(
+                    |start| b.range_filter(start, prefix.clone(), None),
+                    &list_prefix,
+                    self.top_level_directories(&bucket)?,
+                    after.as_deref(),
+                ))
+            }
+            None => Box::new(group_by_delimiter(
+                b.range_filter(
+                    start_after.clone(),
+                    prefix.clone(),
+                    decoded_continuation_token,
+                ),
+                &list_prefix,
+                delimiter.as_deref(),
+                after.as_deref(),
+            )),
+        };
+        let entries: Vec<_> = grouped.take(key_count as usize).collect();
 
         let mut next_token = None;
-        let has_next = objects.len() == key_count as usize;
-        if has_next {
-            next_token = Some(hex_string(
-                objects[(key_count - 1) as usize]
-                    .key
-                    .as_ref()
-                    .unwrap()
-                    .as_bytes(),
-            ))
+        let has_next = entries.len() == key_count as usize;
+        if let (true, Some(last)) = (has_next, entries.last()) {
+            let token = ContinuationToken {
+                snapshot_id: snapshot.as_ref().map(|(_, id, _)| *id),
+                listed: listed.saturating_add(entries.len() as u64),
+                last_key: last.key().to_string(),
+            };
+            next_token = Some(self.list_limits.encode_token(&self.owner_id, &bucket, &token));
+        } else if let Some((snapshots, id, _)) = &snapshot {
+            // last page, the snapshot is no longer needed
+            snapshots.remove(*id);
         }
 
+        self.record_read(&bucket, 0, requester_charged);
+
+        let (mut objects, mut common_prefixes) =
+            self.list_page(entries, fetch_owner.unwrap_or(false));
+        // the continuation tokens are opaque and never encoded
+        let encoding = encoding.for_values(
+            objects
+                .iter()
+                .filter_map(|obj| obj.key.as_deref())
+                .chain(common_prefixes.iter().filter_map(|cp| cp.prefix.as_deref()))
+                .chain(prefix.as_deref())
+                .chain(delimiter.as_deref())
+                .chain(start_after.as_deref()),
+        );
+        encode_page(encoding, &mut objects, &mut common_prefixes);
+
         let output = ListObjectsV2Output {
-            key_count: Some(key_count),
+            key_count: Some((objects.len() + common_prefixes.len()) as i32),
             max_keys: Some(key_count),
             contents: Some(objects),
+            common_prefixes: Some(common_prefixes),
             continuation_token,
-            delimiter,
-            encoding_type,
+            delimiter: encoding.encode_opt(delimiter),
+            encoding_type: encoding.encoding_type(),
             name: Some(bucket),
-            prefix,
-            start_after,
+            prefix: encoding.encode_opt(prefix),
+            start_after: encoding.encode_opt(start_after),
             next_continuation_token: next_token,
+            request_charged: request_charged(requester_charged),
             ..Default::default()
         };
         Ok(S3Response::new(output))
     }
 
+    async fn put_bucket_acl(
+        &self,
+        req: S3Request<PutBucketAclInput>,
+    ) -> S3Result<S3Response<PutBucketAclOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let PutBucketAclInput { bucket, acl, .. } = req.input;
+
+        let Some(acl) = parse_canned_acl(acl.as_ref().map(|acl| acl.as_str()))? else {
+            return Err(s3_error!(NotImplemented, "Only canned ACLs are supported"));
+        };
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        try_!(self.casfs.set_bucket_acl(&bucket, acl));
+        Ok(S3Response::new(PutBucketAclOutput::default()))
+    }
+
+    async fn put_bucket_encryption(
+        &self,
+        req: S3Request<PutBucketEncryptionInput>,
+    ) -> S3Result<S3Response<PutBucketEncryptionOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let PutBucketEncryptionInput {
+            bucket,
+            server_side_encryption_configuration,
+            ..
+        } = req.input;
+
+        if !self.encrypted_at_rest {
+            return Err(s3_error!(
+                NotImplemented,
+                "Server-side encryption is not available, the store doesn't encrypt data at rest"
+            ));
+        }
+        let [rule] = server_side_encryption_configuration.rules.as_slice() else {
+            return Err(s3_error!(MalformedXML, "Exactly one encryption rule is required"));
+        };
+        let Some(default) = &rule.apply_server_side_encryption_by_default else {
+            return Err(s3_error!(MalformedXML, "The rule has no default encryption"));
+        };
+        if default.sse_algorithm.as_str() != ServerSideEncryption::AES256 {
+            return Err(s3_error!(
+                NotImplemented,
+                "Only AES256 server-side encryption is supported"
+            ));
+        }
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        try_!(self
+            .casfs
+            .set_bucket_encryption(&bucket, Some(ServerSideEncryption::AES256)));
+        Ok(S3Response::new(PutBucketEncryptionOutput::default()))
+    }
+
+    async fn put_bucket_lifecycle_configuration(
+        &self,
+        req: S3Request<PutBucketLifecycleConfigurationInput>,
+    ) -> S3Result<S3Response<PutBucketLifecycleConfigurationOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let PutBucketLifecycleConfigurationInput {
+            bucket,
+            lifecycle_configuration,
+            ..
+        } = req.input;
+
+        let Some(configuration) = lifecycle_configuration else {
+            return Err(s3_error!(
+                MalformedXML,
+                "The lifecycle configuration is missing"
+            ));
+        };
+        let lifecycle = lifecycle_from_configuration(configuration)?;
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        try_!(self.casfs.set_bucket_lifecycle(&bucket, Some(&lifecycle)));
+        Ok(S3Response::new(
+            PutBucketLifecycleConfigurationOutput::default(),
+        ))
+    }
+
+    async fn put_bucket_logging(
+        &self,
+        req: S3Request<PutBucketLoggingInput>,
+    ) -> S3Result<S3Response<PutBucketLoggingOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let PutBucketLoggingInput {
+            bucket,
+            bucket_logging_status,
+            ..
+        } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        // an empty status disables logging
+        let logging = match bucket_logging_status.logging_enabled {
+            Some(enabled) => {
+                // the records are written by the server, to a bucket of the same owner
+                if !try_!(self.casfs.bucket_exists(&enabled.target_bucket)) {
+                    let mut err = s3s::S3Error::with_message(
+                        s3s::S3ErrorCode::Custom("InvalidTargetBucketForLogging".into()),
+                        "The target bucket for logging does not exist",
+                    );
+                    err.set_status_code(hyper::StatusCode::BAD_REQUEST);
+                    return Err(err);
+                }
+                Some(BucketLogging {
+                    target_bucket: enabled.target_bucket,
+                    target_prefix: enabled.target_prefix,
+                })
+            }
+            None => None,
+        };
+        try_!(self.casfs.set_bucket_logging(&bucket, logging.as_ref()));
+        Ok(S3Response::new(PutBucketLoggingOutput::default()))
+    }
+
+    async fn put_bucket_request_payment(
+        &self,
+        req: S3Request<PutBucketRequestPaymentInput>,
+    ) -> S3Result<S3Response<PutBucketRequestPaymentOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let PutBucketRequestPaymentInput {
+            bucket,
+            request_payment_configuration,
+            ..
+        } = req.input;
+
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        let requester_pays = match request_payment_configuration.payer.as_str() {
+            Payer::REQUESTER => true,
+            Payer::BUCKET_OWNER => false,
+            payer => {
+                return Err(s3_error!(MalformedXML, "Unknown payer {}", payer));
+            }
+        };
+        try_!(self.casfs.set_requester_pays(&bucket, requester_pays));
+        Ok(S3Response::new(PutBucketRequestPaymentOutput::default()))
+    }
+
+    async fn put_bucket_tagging(
+        &self,
+        req: S3Request<PutBucketTaggingInput>,
+    ) -> S3Result<S3Response<PutBucketTaggingOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let PutBucketTaggingInput {
+            bucket, tagging, ..
+        } = req.input;
+
+        let tags = bucket_tags_from_tag_set(tagging.tag_set)?;
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+
+        try_!(self.casfs.set_bucket_tags(&bucket, tags));
+        Ok(S3Response::new(PutBucketTaggingOutput::default()))
+    }
+
+    async fn put_object_acl(
+        &self,
+        req: S3Request<PutObjectAclInput>,
+    ) -> S3Result<S3Response<PutObjectAclOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let PutObjectAclInput {
+            bucket, key, acl, ..
+        } = req.input;
+
+        let Some(acl) = parse_canned_acl(acl.as_ref().map(|acl| acl.as_str()))? else {
+            return Err(s3_error!(NotImplemented, "Only canned ACLs are supported"));
+        };
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+        if !try_!(self.casfs.key_exists(&bucket, &key)) {
+            return Err(s3_error!(NoSuchKey, "Object does not exist"));
+        }
+
+        try_!(self.casfs.set_object_acl(&bucket, &key, Some(acl)));
+        Ok(S3Response::new(PutObjectAclOutput::default()))
+    }
+
+    async fn put_object_tagging(
+        &self,
+        req: S3Request<PutObjectTaggingInput>,
+    ) -> S3Result<S3Response<PutObjectTaggingOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let PutObjectTaggingInput {
+            bucket,
+            key,
+            tagging,
+            ..
+        } = req.input;
+
+        let tags = tags_from_tag_set(tagging.tag_set)?;
+        if !try_!(self.casfs.bucket_exists(&bucket)) {
+            return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
+        }
+        if !try_!(self.casfs.key_exists(&bucket, &key)) {
+            return Err(s3_error!(NoSuchKey, "Object does not exist"));
+        }
+
+        try_!(self.casfs.set_object_tags(&bucket, &key, &tags));
+        Ok(S3Response::new(PutObjectTaggingOutput::default()))
+    }
+
     #[tracing::instrument(skip(self, req), fields(bucket, key, size))]
     async fn put_object(
         &self,
         req: S3Request<PutObjectInput>,
     ) -> S3Result<S3Response<PutObjectOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
+        let durability = requested_durability(&req.headers)?;
         let input = req.input;
 
         tracing::Span::current().record("bucket", &tracing::field::display(&input.bucket));
@@ -673,16 +1934,41 @@ impl S3 for S3FS {
             bucket,
             key,
             content_length,
+            content_type,
+            acl,
+            tagging,
+            server_side_encryption,
+            sse_customer_algorithm,
             ..
         } = input;
 
         let Some(body) = body else {
             return Err(s3_error!(IncompleteBody));
         };
+        let acl = parse_canned_acl(acl.as_ref().map(|acl| acl.as_str()))?;
+        let tags = match tagging {
+            Some(header) => parse_tagging_header(&header)?,
+            None => Default::default(),
+        };
 
         if !try_!(self.casfs.bucket_exists(&bucket)) {
             return Err(s3_error!(NoSuchBucket, "Bucket does not exist"));
         }
+        let server_side_encryption = self.write_encryption(
+            &bucket,
+            server_side_encryption.as_ref(),
+            sse_customer_algorithm.as_deref(),
+        )?;
+        // without a content length only a full bucket is rejected up front
+        self.check_bucket_limits(&bucket, &key, content_length.unwrap_or_default().max(0) as u64)?;
+        let write = self.object_write(
+            "PutObject",
+            &bucket,
+            &key,
+            Some(content_type.unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_string())),
+            content_length.map(|length| length.max(0) as u64),
+        );
+        self.check_write(&write).await?;
 
         // if the content length is less than the max inlined data length, we store the object in the
         // metadata store, otherwise we store it in the cas layer.
@@ -699,26 +1985,98 @@ impl S3 for S3FS {
                 .into_iter()
                 .flatten()
                 .collect();
-            let obj_meta = try_!(self.casfs.store_inlined_object(&bucket, &key, data));
-
+            let scan = match self.write_hooks.scanner(&write, data.len() as u64) {
+                Some(scanner) => {
+                    let chunk = Bytes::from(data.clone());
+                    let report = scanner
+                        .scan_all(&write, stream::once(async { Ok::<_, io::Error>(chunk) }))
+                        .await;
+                    self.check_scan(scanner, &write, report)?
+                        .map(|result| (scanner, result))
+                }
+                None => None,
+            };
+            let attributes = Self::object_attributes(acl, &tags, scan)?;
+            let _guard = self.casfs.lock_object(&bucket, &key).await;
+            let (meta_bucket, meta_key) = (bucket.clone(), key.clone());
+            let obj_meta = try_!(
+                self.casfs
+                    .run_blocking(move |fs| {
+                        fs.store_inlined_object_with_attributes(
+                            &meta_bucket,
+                            &meta_key,
+                            data,
+                            &attributes,
+                        )
+                    })
+                    .await
+            );
+            self.persist_requested(durability).await?;
+
+            self.record_access(&bucket, Access::Write, obj_meta.size());
+            self.write_hooks.after_write(write, obj_meta.clone());
             let output = PutObjectOutput {
                 e_tag: Some(obj_meta.format_e_tag()),
+                server_side_encryption,
+                expiration: self.expiration(&bucket, &key, obj_meta.last_modified())?,
                 ..Default::default()
             };
             return Ok(S3Response::new(output));
         }
 
         // save the datadata
-        let converted_stream = convert_stream_error(body);
-        let byte_stream = ByteStream::new_with_size(converted_stream, content_length);
-        let obj_meta = try_!(
-            self.casfs
-                .store_single_object_and_meta(&bucket, &key, byte_stream, content_length)
-                .await
+        let converted_stream = throttled(
+            convert_stream_error(body),
+            self.throttle.as_ref(),
+            TrafficClass::ClientWrite,
         );
+        let obj_meta = match self.write_hooks.scanner(&write, content_length as u64) {
+            Some(scanner) => {
+                // the data is scanned as it is stored, the object is only committed
+                // once the scan passed
+                let (scanned, scan) = scanner.scan(&write, converted_stream);
+                let byte_stream = ByteStream::new_with_size(scanned, content_length);
+                let check = async {
+                    let result = self.check_scan(scanner, &write, scan.report().await)?;
+                    let scan = result.map(|result| (scanner, result));
+                    Self::object_attributes(acl, &tags, scan)
+                };
+                try_!(
+                    self.casfs
+                        .store_single_object_and_meta_checked(
+                            &bucket,
+                            &key,
+                            byte_stream,
+                            content_length,
+                            check,
+                        )
+                        .await
+                )?
+            }
+            None => {
+                let attributes = Self::object_attributes(acl, &tags, None)?;
+                let byte_stream = ByteStream::new_with_size(converted_stream, content_length);
+                try_!(
+                    self.casfs
+                        .store_single_object_and_meta_with_attributes(
+                            &bucket,
+                            &key,
+                            byte_stream,
+                            content_length,
+                            attributes,
+                        )
+                        .await
+                )
+            }
+        };
+        self.persist_requested(durability).await?;
+        self.record_access(&bucket, Access::Write, obj_meta.size());
+        self.write_hooks.after_write(write, obj_meta.clone());
 
         let output = PutObjectOutput {
             e_tag: Some(obj_meta.format_e_tag()),
+            server_side_encryption,
+            expiration: self.expiration(&bucket, &key, obj_meta.last_modified())?,
             ..Default::default()
         };
         Ok(S3Response::new(output))
@@ -729,6 +2087,7 @@ impl S3 for S3FS {
         &self,
         req: S3Request<UploadPartInput>,
     ) -> S3Result<S3Response<UploadPartOutput>> {
+        self.check_bucket_owner(req.input.expected_bucket_owner.as_deref())?;
         let UploadPartInput {
             body,
             bucket,
@@ -764,14 +2123,21 @@ impl S3 for S3FS {
             )
         })?;
 
-        let converted_stream = convert_stream_error(body);
+        let converted_stream = throttled(
+            convert_stream_error(body),
+            self.throttle.as_ref(),
+            TrafficClass::ClientWrite,
+        );
         let byte_stream = ByteStream::new_with_size(converted_stream, content_length as usize);
 
         // we only store the object here, metadata is not stored in the meta store.
-        // it is stored in the multipart metadata, in the `cas` layer.
+        // it is stored in the multipart metadata, in the `cas` layer, one record per
+        // part, so parts can be uploaded concurrently. A part uploaded again replaces
+        // the previous one.
         // the multipart metadata will be deleted when the multipart upload is completed
         // and replaced with the object metadata in metastore in the `complete_multipart_upload` function.
-        let (blocks, hash, size) = try_!(self.casfs.store_object(&bucket, &key, byte_stream).await);
+        let (blocks, _, e_tag, size) =
+            try_!(self.casfs.store_part(&bucket, &key, byte_stream).await);
 
         if size != content_length as u64 {
             return Err(s3_error!(
@@ -786,9 +2152,10 @@ impl S3 for S3FS {
             size as usize,
             part_number as i64,
             upload_id.clone(),
-            hash,
+            e_tag,
             blocks.clone()
-        ));
+        )
+        .await);
 
         tracing::debug!(
             bucket = %bucket,
@@ -799,8 +2166,9 @@ impl S3 for S3FS {
             blocks = blocks.len(),
             "Upload part completed"
         );
+        self.record_access(&bucket, Access::Write, size);
 
-        let e_tag = format!("\"{}\"", hex_string(&hash));
+        let e_tag = format!("\"{}\"", hex_string(&e_tag));
 
         let output = UploadPartOutput {
             e_tag: Some(e_tag),
@@ -815,20 +2183,101 @@ fn convert_stream_error(body: StreamingBlob) -> impl Stream<Item = Result<Bytes,
     body.map(|r| r.map_err(|e| io::Error::new(ErrorKind::Other, e.to_string())))
 }
 
-fn decode_continuation_token(rt: Option<&str>) -> Result<Option<String>, s3s::S3Error> {
-    if let Some(rt) = rt {
-        let mut out = vec![0; rt.len() / 2];
-        if hex_decode(rt.as_bytes(), &mut out).is_err() {
-            return Err(s3_error!(
-                InvalidToken,
-                "continuation token has an invalid format"
-            ));
+// Encode the keys and common prefixes of a listing page
+fn encode_page(
+    encoding: KeyEncoding,
+    objects: &mut [s3s::dto::Object],
+    common_prefixes: &mut [CommonPrefix],
+) {
+    for obj in objects {
+        obj.key = encoding.encode_opt(obj.key.take());
+    }
+    for cp in common_prefixes {
+        cp.prefix = encoding.encode_opt(cp.prefix.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(range: &str, authorization: Option<&str>, uri: &str) -> hyper::Request<()> {
+        let mut builder = hyper::Request::get(uri).header(hyper::header::RANGE, range);
+        if let Some(authorization) = authorization {
+            builder = builder.header(hyper::header::AUTHORIZATION, authorization);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_cas_headers() {
+        let header = |headers: &hyper::HeaderMap, name| {
+            headers.get(name).map(|v| v.to_str().unwrap().to_string())
         };
+        let blocks = vec![[1; 16], [2; 16], [1; 16]];
+        let obj = cas_storage::Object::new(30, [0; 16], ObjectData::SinglePart { blocks });
+        let headers = cas_headers(&obj, &[(10, None), (10, Some("ssd"))]);
+        assert_eq!(header(&headers, "x-cas-block-count").unwrap(), "3");
+        assert_eq!(header(&headers, "x-cas-physical-size").unwrap(), "20");
+        assert_eq!(header(&headers, "x-cas-inline").unwrap(), "false");
+        assert_eq!(header(&headers, "x-cas-tier").unwrap(), "default,ssd");
+
+        let obj = cas_storage::Object::new(
+            5,
+            [0; 16],
+            ObjectData::Inline {
+                data: b"hello".to_vec(),
+            },
+        );
+        let headers = cas_headers(&obj, &[]);
+        assert_eq!(header(&headers, "x-cas-block-count").unwrap(), "0");
+        assert_eq!(header(&headers, "x-cas-physical-size").unwrap(), "5");
+        assert_eq!(header(&headers, "x-cas-inline").unwrap(), "true");
+        assert_eq!(header(&headers, "x-cas-tier"), None);
+    }
 
-        String::from_utf8(out)
-            .map(Some)
-            .map_err(|_| s3_error!(InvalidToken, "continuation token is invalid"))
-    } else {
-        Ok(None)
+    #[test]
+    fn test_requested_durability() {
+        let mut headers = hyper::HeaderMap::new();
+        assert_eq!(requested_durability(&headers).unwrap(), None);
+        headers.insert(DURABILITY_HEADER, "fsync".parse().unwrap());
+        assert_eq!(
+            requested_durability(&headers).unwrap(),
+            Some(Durability::Fsync)
+        );
+        headers.insert(DURABILITY_HEADER, "always".parse().unwrap());
+        assert!(requested_durability(&headers).is_err());
+    }
+
+    #[test]
+    fn test_take_multi_range() {
+        let mut req = request("bytes=0-1,5-6", None, "/bucket/key");
+        take_multi_range(&mut req);
+        assert!(req.headers().get(hyper::header::RANGE).is_none());
+        let range = req.extensions().get::<MultiRange>().unwrap();
+        assert_eq!(range.0, "bytes=0-1,5-6");
+
+        // single ranges are parsed by s3s
+        let mut req = request("bytes=0-1", None, "/bucket/key");
+        take_multi_range(&mut req);
+        assert!(req.headers().get(hyper::header::RANGE).is_some());
+        assert!(req.extensions().get::<MultiRange>().is_none());
+
+        // signed headers can't be removed
+        let auth = "AWS4-HMAC-SHA256 Credential=a/20250101/us-east-1/s3/aws4_request, \
+                    SignedHeaders=host;range;x-amz-date, Signature=abc";
+        let mut req = request("bytes=0-1,5-6", Some(auth), "/bucket/key");
+        take_multi_range(&mut req);
+        assert!(req.extensions().get::<MultiRange>().is_none());
+
+        let uri = "/bucket/key?X-Amz-SignedHeaders=host%3Brange&X-Amz-Signature=abc";
+        let mut req = request("bytes=0-1,5-6", None, uri);
+        take_multi_range(&mut req);
+        assert!(req.extensions().get::<MultiRange>().is_none());
+
+        let uri = "/bucket/key?X-Amz-SignedHeaders=host&X-Amz-Signature=abc";
+        let mut req = request("bytes=0-1,5-6", None, uri);
+        take_multi_range(&mut req);
+        assert!(req.extensions().get::<MultiRange>().is_some());
     }
 }
@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use cas_storage::{CasFS, FileId, Object};
+use clap::Parser;
+use rusoto_core::ByteStream;
+use tokio::io::AsyncReadExt;
+
+use crate::manifest::StoreArgs;
+
+/// Size of the reads from seeded files, the store splits them into blocks
+const READ_SIZE: usize = 1 << 20;
+
+#[derive(Parser, Debug)]
+pub struct SeedConfig {
+    #[command(flatten)]
+    pub store: StoreArgs,
+
+    #[arg(long, help = "Directory whose files are seeded, recursively")]
+    pub from_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "seed",
+        help = "Bucket holding the seeded files, which keeps their blocks referenced"
+    )]
+    pub bucket: String,
+
+    #[arg(
+        long,
+        conflicts_with = "from_dir",
+        help = "Delete the seed bucket, blocks no object uploaded since references are removed"
+    )]
+    pub release: bool,
+
+    #[arg(
+        long,
+        help = "Read and hash every file, also the ones unchanged since they were last seeded"
+    )]
+    pub rehash: bool,
+}
+
+/// Seed the block store with the files of a directory, or release the seeded blocks
+#[tokio::main]
+pub async fn seed_blocks(args: SeedConfig) -> Result<()> {
+    let casfs = args.store.open()?;
+
+    if args.release {
+        if !casfs.bucket_exists(&args.bucket)? {
+            bail!("Bucket {} not found", args.bucket);
+        }
+        casfs.bucket_delete(&args.bucket).await?;
+        casfs.file_id_cache().clear()?;
+        eprintln!("Released the seeded blocks of bucket {}", args.bucket);
+        return Ok(());
+    }
+
+    let Some(dir) = &args.from_dir else {
+        bail!("--from-dir or --release is required");
+    };
+    if !casfs.bucket_exists(&args.bucket)? {
+        casfs.create_bucket(&args.bucket)?;
+    }
+
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+    files.sort();
+
+    let (mut seeded, mut unchanged, mut bytes) = (0, 0, 0);
+    for path in files {
+        // files are stored under their path in the directory
+        let key = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace(std::path::MAIN_SEPARATOR, "/");
+        let file = tokio::fs::File::open(&path)
+            .await
+            .with_context(|| format!("Can't open {}", path.display()))?;
+        let metadata = file.metadata().await?;
+        let len = metadata.len() as usize;
+        if len == 0 {
+            continue;
+        }
+
+        // unchanged files and hard links of seeded files only need their metadata
+        let file_id = FileId::from_metadata(&metadata);
+        if let (Some(id), false) = (file_id, args.rehash) {
+            if let Some(obj) = store_unchanged(&casfs, &args.bucket, &key, id).await? {
+                eprintln!("{} ({} blocks, unchanged)", key, obj.blocks().len());
+                seeded += 1;
+                unchanged += 1;
+                bytes += obj.size();
+                continue;
+            }
+        }
+
+        let obj = casfs
+            .store_single_object_and_meta(&args.bucket, &key, file_stream(file), len)
+            .await
+            .with_context(|| format!("Can't seed {}", path.display()))?;
+        if let Some(id) = file_id {
+            casfs
+                .file_id_cache()
+                .insert(id, &casfs.manifest_entry(&key, &obj)?)?;
+        }
+        eprintln!("{} ({} blocks)", key, obj.blocks().len());
+        seeded += 1;
+        bytes += obj.size();
+    }
+
+    eprintln!(
+        "Seeded {seeded} files ({unchanged} unchanged, {bytes} bytes) into bucket {}, release them with --release",
+        args.bucket
+    );
+    Ok(())
+}
+
+/// Store the object last seeded from the file with identity `id` at `key`, adding
+/// references to its blocks without reading the file. Returns `None` when the file
+/// wasn't seeded before, or its blocks were removed since.
+async fn store_unchanged(
+    casfs: &CasFS,
+    bucket: &str,
+    key: &str,
+    id: FileId,
+) -> Result<Option<Object>> {
+    let Some(mut entry) = casfs.file_id_cache().get(id)? else {
+        return Ok(None);
+    };
+    entry.key = key.to_string();
+    match casfs.import_manifest_entry(bucket, &entry, false).await {
+        Ok(obj) => Ok(Some(obj)),
+        Err(e) => {
+            tracing::debug!("Seeded object of {key} can't be reused: {e}");
+            Ok(None)
+        }
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Can't read {}", dir.display()))? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+fn file_stream(file: tokio::fs::File) -> ByteStream {
+    let chunks = futures::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buf = vec![0; READ_SIZE];
+        match file.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), Some(file)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+    ByteStream::new(chunks)
+}
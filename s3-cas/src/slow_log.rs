@@ -0,0 +1,277 @@
+//! Slow log for S3 requests.
+//!
+//! Requests which take longer than the threshold of their operation are written
+//! as one JSON object per line, with the time spent authenticating the request,
+//! in the metadata store and on disk, and counted in the `s3_slow_requests`
+//! metric. It is meant for finding the cause of tail latencies on a server in
+//! production, without enabling debug logging for all requests.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use cas_storage::PhaseTimings;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::access_log::RotatingFile;
+use crate::metrics::SharedMetrics;
+
+/// Default threshold of the operations without their own
+pub const DEFAULT_SLOW_LOG_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Time a request was received, stored in the request extensions before it is
+/// authenticated
+#[derive(Debug, Clone, Copy)]
+pub struct RequestStart(pub Instant);
+
+#[derive(Debug, Clone)]
+pub struct SlowLogConfig {
+    /// File to write to, `None` writes to stdout
+    pub path: Option<PathBuf>,
+    pub threshold: Duration,
+    /// Thresholds of single operations, e.g. `put_object`, instead of `threshold`
+    pub operation_thresholds: HashMap<String, Duration>,
+    /// Rotate the file once it grows beyond this size in bytes, 0 disables rotation
+    pub max_size: u64,
+    /// Amount of rotated files to keep
+    pub max_files: usize,
+}
+
+impl Default for SlowLogConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            threshold: DEFAULT_SLOW_LOG_THRESHOLD,
+            operation_thresholds: HashMap::new(),
+            max_size: 0,
+            max_files: 5,
+        }
+    }
+}
+
+/// A single slow log record
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowLogEntry {
+    #[serde(serialize_with = "serialize_time")]
+    pub time: DateTime<Utc>,
+    /// S3 operation name, e.g. `put_object`
+    pub operation: &'static str,
+    pub method: String,
+    pub uri: String,
+    /// Access key of the requester, if the request was signed
+    pub user: Option<String>,
+    pub bucket: Option<String>,
+    pub key: Option<String>,
+    pub status: u16,
+    /// Size of the request body, from its `Content-Length`
+    pub request_bytes: Option<u64>,
+    /// Size of the response body, if known
+    pub response_bytes: Option<u64>,
+    /// From the time the request was received until the response
+    pub duration_ms: f64,
+    /// Signature and access checks, before the request reached the storage
+    pub auth_ms: Option<f64>,
+    /// Metadata store operations
+    pub meta_ms: f64,
+    /// Block writes
+    pub disk_ms: f64,
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn serialize_time<S: serde::Serializer>(time: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&time.to_rfc3339())
+}
+
+/// Bucket and key of a path-style request URI.
+pub fn request_target(path: &str) -> (Option<String>, Option<String>) {
+    let decode = |s: &str| {
+        urlencoding::decode(s)
+            .map(|s| s.into_owned())
+            .unwrap_or_else(|_| s.to_string())
+    };
+    let path = path.trim_start_matches('/');
+    match path.split_once('/') {
+        Some((bucket, "")) => (Some(decode(bucket)), None),
+        Some((bucket, key)) => (Some(decode(bucket)), Some(decode(key))),
+        None if path.is_empty() => (None, None),
+        None => (Some(decode(path)), None),
+    }
+}
+
+/// A request which may be written to the slow log
+pub struct SlowRequest<'a> {
+    pub operation: &'static str,
+    pub method: &'a str,
+    pub uri: &'a hyper::Uri,
+    pub user: Option<&'a str>,
+    pub status: u16,
+    pub request_bytes: Option<u64>,
+    pub response_bytes: Option<u64>,
+    /// Time spent before the request reached the storage, if known
+    pub auth: Option<Duration>,
+    /// Time spent by the storage
+    pub duration: Duration,
+    pub timings: PhaseTimings,
+}
+
+impl SlowRequest<'_> {
+    fn total(&self) -> Duration {
+        self.auth.unwrap_or_default() + self.duration
+    }
+
+    fn to_entry(&self) -> SlowLogEntry {
+        let (bucket, key) = request_target(self.uri.path());
+        SlowLogEntry {
+            time: Utc::now(),
+            operation: self.operation,
+            method: self.method.to_string(),
+            uri: self.uri.to_string(),
+            user: self.user.map(|u| u.to_string()),
+            bucket,
+            key,
+            status: self.status,
+            request_bytes: self.request_bytes,
+            response_bytes: self.response_bytes,
+            duration_ms: millis(self.total()),
+            auth_ms: self.auth.map(millis),
+            meta_ms: millis(self.timings.meta),
+            disk_ms: millis(self.timings.disk),
+        }
+    }
+}
+
+/// Writes the requests exceeding the thresholds of a [`SlowLogConfig`]
+pub struct SlowLogger {
+    config: SlowLogConfig,
+    file: Option<RotatingFile>,
+    metrics: SharedMetrics,
+}
+
+impl SlowLogger {
+    pub fn new(config: SlowLogConfig, metrics: SharedMetrics) -> io::Result<Self> {
+        let file = match &config.path {
+            Some(path) => Some(RotatingFile::open(
+                path.clone(),
+                config.max_size,
+                config.max_files,
+            )?),
+            None => None,
+        };
+        Ok(Self {
+            config,
+            file,
+            metrics,
+        })
+    }
+
+    pub fn config(&self) -> &SlowLogConfig {
+        &self.config
+    }
+
+    /// The threshold of `operation`.
+    pub fn threshold(&self, operation: &str) -> Duration {
+        self.config
+            .operation_thresholds
+            .get(operation)
+            .copied()
+            .unwrap_or(self.config.threshold)
+    }
+
+    /// Log `request` if it took longer than the threshold of its operation.
+    /// Returns whether it was logged. Errors writing the log are reported
+    /// through tracing and otherwise ignored.
+    pub fn observe(&self, request: &SlowRequest) -> bool {
+        if request.total() < self.threshold(request.operation) {
+            return false;
+        }
+        self.metrics.record_slow_request(request.operation);
+
+        let entry = request.to_entry();
+        let mut line = serde_json::to_string(&entry).unwrap_or_default();
+        line.push('\n');
+        let written = match &self.file {
+            Some(file) => file.write_line(&line),
+            None => io::stdout().lock().write_all(line.as_bytes()),
+        };
+        if let Err(e) = written {
+            tracing::warn!(error = %e, "failed to write slow log");
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn request(operation: &'static str, uri: &hyper::Uri, duration: Duration) -> SlowRequest<'_> {
+        SlowRequest {
+            operation,
+            method: "PUT",
+            uri,
+            user: Some("AKIA"),
+            status: 200,
+            request_bytes: Some(1024),
+            response_bytes: None,
+            auth: Some(Duration::from_millis(2)),
+            duration,
+            timings: PhaseTimings {
+                meta: Duration::from_millis(30),
+                disk: Duration::from_millis(80),
+            },
+        }
+    }
+
+    #[test]
+    fn test_request_target() {
+        assert_eq!(request_target("/"), (None, None));
+        assert_eq!(request_target("/bucket"), (Some("bucket".into()), None));
+        assert_eq!(request_target("/bucket/"), (Some("bucket".into()), None));
+        assert_eq!(
+            request_target("/bucket/dir/my%20file"),
+            (Some("bucket".into()), Some("dir/my file".into()))
+        );
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("slow.log");
+        let logger = SlowLogger::new(
+            SlowLogConfig {
+                path: Some(path.clone()),
+                threshold: Duration::from_millis(100),
+                operation_thresholds: [("list_objects_v2".to_string(), Duration::from_secs(1))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+            SharedMetrics::noop(),
+        )
+        .unwrap();
+        let uri: hyper::Uri = "/bucket/key".parse().unwrap();
+
+        let observe =
+            |operation, ms| logger.observe(&request(operation, &uri, Duration::from_millis(ms)));
+        assert!(!observe("put_object", 50));
+        assert!(observe("put_object", 120));
+        assert!(!observe("list_objects_v2", 500));
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        let value: serde_json::Value = serde_json::from_str(contents.trim()).unwrap();
+        assert_eq!(value["operation"], "put_object");
+        assert_eq!(value["bucket"], "bucket");
+        assert_eq!(value["key"], "key");
+        assert_eq!(value["request_bytes"], 1024);
+        assert_eq!(value["duration_ms"], 122.0);
+        assert_eq!(value["disk_ms"], 80.0);
+    }
+}
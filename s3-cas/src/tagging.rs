@@ -0,0 +1,96 @@
+//! Object and bucket tagging support: conversion from and to the S3 tag sets, and
+//! parsing of the `x-amz-tagging` header of PutObject.
+
+use s3s::dto::{Tag, TagSet};
+use s3s::{s3_error, S3Result};
+
+use cas_storage::ObjectTags;
+
+/// Convert the tag set of a PutObjectTagging request, checking the S3 limits.
+pub fn tags_from_tag_set(tag_set: TagSet) -> S3Result<ObjectTags> {
+    let tags = tag_set
+        .into_iter()
+        .map(|tag| (tag.key.unwrap_or_default(), tag.value.unwrap_or_default()))
+        .collect();
+    ObjectTags::new(tags).map_err(|e| s3_error!(InvalidTag, "{}", e))
+}
+
+/// Convert the tag set of a PutBucketTagging request, checking the S3 limits.
+pub fn bucket_tags_from_tag_set(tag_set: TagSet) -> S3Result<ObjectTags> {
+    let tags = tag_set
+        .into_iter()
+        .map(|tag| (tag.key.unwrap_or_default(), tag.value.unwrap_or_default()))
+        .collect();
+    ObjectTags::for_bucket(tags).map_err(|e| s3_error!(InvalidTag, "{}", e))
+}
+
+/// The tag set returned by GetObjectTagging and GetBucketTagging.
+pub fn tag_set(tags: &ObjectTags) -> TagSet {
+    tags.iter()
+        .map(|(key, value)| Tag {
+            key: Some(key.to_string()),
+            value: Some(value.to_string()),
+        })
+        .collect()
+}
+
+/// Parse the URL query encoded `x-amz-tagging` header, e.g. `tmp=true&team=ops`.
+pub fn parse_tagging_header(header: &str) -> S3Result<ObjectTags> {
+    let decode = |s: &str| {
+        // `+` is a space in query encoding, urlencoding only decodes %XX
+        urlencoding::decode(&s.replace('+', " "))
+            .map(|s| s.into_owned())
+            .map_err(|_| s3_error!(InvalidArgument, "Invalid x-amz-tagging header"))
+    };
+
+    let mut tags = Vec::new();
+    for pair in header.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        tags.push((decode(key)?, decode(value)?));
+    }
+    ObjectTags::new(tags).map_err(|e| s3_error!(InvalidTag, "{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tagging_header() {
+        let tags = parse_tagging_header("tmp=true&team=data+ops&path=a%2Fb&empty").unwrap();
+        let tags: Vec<_> = tags.iter().collect();
+        assert_eq!(
+            tags,
+            vec![
+                ("tmp", "true"),
+                ("team", "data ops"),
+                ("path", "a/b"),
+                ("empty", "")
+            ]
+        );
+
+        assert!(parse_tagging_header("").unwrap().is_empty());
+        assert!(parse_tagging_header("a=1&a=2").is_err());
+    }
+
+    #[test]
+    fn test_tag_set_roundtrip() {
+        let tags = parse_tagging_header("tmp=true&team=ops").unwrap();
+        assert_eq!(tags_from_tag_set(tag_set(&tags)).unwrap(), tags);
+    }
+
+    #[test]
+    fn test_bucket_tag_limit() {
+        let tag_set: TagSet = (0..20)
+            .map(|i| Tag {
+                key: Some(format!("key{i}")),
+                value: Some(String::new()),
+            })
+            .collect();
+        assert!(tags_from_tag_set(tag_set.clone()).is_err());
+        assert_eq!(
+            bucket_tags_from_tag_set(tag_set).unwrap().iter().count(),
+            20
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! TLS for the listeners which are not behind a proxy, optionally requiring
+//! clients to present a certificate signed by a given CA (mutual TLS), and for
+//! the clients of those listeners.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+/// Certificate and key of a listener, and the CA of its clients for mutual TLS
+#[derive(Debug, Clone)]
+pub struct TlsFiles<'a> {
+    /// PEM file with the certificate chain, the server certificate first
+    pub cert: &'a Path,
+    /// PEM file with the private key of the certificate
+    pub key: &'a Path,
+    /// PEM file with the CA certificates of the clients, clients without a
+    /// certificate signed by one of them are rejected
+    pub client_ca: Option<&'a Path>,
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates in {}", path.display());
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .with_context(|| format!("Failed to read private key {}", path.display()))
+}
+
+fn load_roots(path: &Path) -> anyhow::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+    }
+    Ok(roots)
+}
+
+/// Acceptor doing the TLS handshake of the connections of a listener
+pub fn acceptor(files: &TlsFiles<'_>) -> anyhow::Result<TlsAcceptor> {
+    let certs = load_certs(files.cert)?;
+    let key = load_key(files.key)?;
+
+    let builder = match files.client_ca {
+        Some(path) => {
+            let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(path)?))
+                .build()
+                .with_context(|| format!("Invalid client CA {}", path.display()))?;
+            ServerConfig::builder().with_client_cert_verifier(verifier)
+        }
+        None => ServerConfig::builder().with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .with_context(|| format!("Invalid certificate {}", files.cert.display()))?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Config of a client trusting the CAs in `ca`, or the system roots without it,
+/// and presenting the certificate and key of `identity` to the server
+pub fn client_config(
+    ca: Option<&Path>,
+    identity: Option<(&Path, &Path)>,
+) -> anyhow::Result<ClientConfig> {
+    let builder = match ca {
+        Some(path) => ClientConfig::builder().with_root_certificates(load_roots(path)?),
+        None => hyper_rustls::ConfigBuilderExt::with_native_roots(ClientConfig::builder())
+            .context("Failed to load the root certificates")?,
+    };
+    match identity {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .with_context(|| format!("Invalid client certificate {}", cert.display())),
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = dir.path().join("cert.pem");
+        let key = dir.path().join("key.pem");
+        let files = TlsFiles {
+            cert: &cert,
+            key: &key,
+            client_ca: None,
+        };
+        let err = acceptor(&files).unwrap_err();
+        assert!(format!("{err:#}").contains("Failed to read certificates"));
+
+        std::fs::write(&cert, "not a certificate").unwrap();
+        let err = acceptor(&files).unwrap_err();
+        assert!(format!("{err:#}").contains("No certificates in"));
+    }
+}
@@ -0,0 +1,350 @@
+//! Verification of a disaster recovery copy of the store on another S3 endpoint,
+//! comparing the keys, sizes and ETags of the objects on both sides.
+
+use std::cmp::Ordering;
+
+use anyhow::{bail, Context, Result};
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::primitives::ByteStream;
+use bytes::BytesMut;
+use clap::Parser;
+use futures::StreamExt;
+
+use crate::manifest::StoreArgs;
+use crate::metrics::SharedMetrics;
+use cas_storage::{BlockStream, CasFS, RangeRequest};
+
+#[derive(Parser, Debug)]
+pub struct VerifyReplicaConfig {
+    #[command(flatten)]
+    pub store: StoreArgs,
+
+    #[arg(
+        long,
+        help = "Endpoint of the replica, e.g. https://dr.example.com:8014"
+    )]
+    pub remote: String,
+
+    #[arg(long, env = "REMOTE_ACCESS_KEY", help = "Access key on the replica")]
+    pub remote_access_key: String,
+
+    #[arg(long, env = "REMOTE_SECRET_KEY", help = "Secret key on the replica")]
+    pub remote_secret_key: String,
+
+    #[arg(long, default_value = "us-east-1", help = "Region of the replica")]
+    pub remote_region: String,
+
+    #[arg(
+        long,
+        help = "Bucket to verify, all buckets if not set. Can be repeated"
+    )]
+    pub bucket: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Copy the objects missing or different on the replica from this store"
+    )]
+    pub repair: bool,
+}
+
+/// What the comparison looks at of an object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectSummary {
+    pub key: String,
+    pub size: u64,
+    /// ETag without its quotes
+    pub e_tag: String,
+}
+
+/// An object which differs between this store and the replica
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// The replica doesn't have the object
+    Missing(ObjectSummary),
+    /// Only the replica has the object
+    Extra(ObjectSummary),
+    /// Both have the object, with another size or ETag
+    Changed {
+        local: ObjectSummary,
+        remote: ObjectSummary,
+    },
+}
+
+impl Divergence {
+    pub fn key(&self) -> &str {
+        match self {
+            Divergence::Missing(obj) | Divergence::Extra(obj) => &obj.key,
+            Divergence::Changed { local, .. } => &local.key,
+        }
+    }
+
+    /// The object is on this store and can be copied to the replica
+    fn repairable(&self) -> bool {
+        !matches!(self, Divergence::Extra(_))
+    }
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Divergence::Missing(obj) => write!(f, "missing  {} ({} bytes)", obj.key, obj.size),
+            Divergence::Extra(obj) => write!(f, "extra    {} ({} bytes)", obj.key, obj.size),
+            Divergence::Changed { local, remote } => write!(
+                f,
+                "changed  {} ({} bytes, {} here, {} bytes, {} on the replica)",
+                local.key, local.size, local.e_tag, remote.size, remote.e_tag
+            ),
+        }
+    }
+}
+
+/// Compare two listings ordered by key, as S3 lists them.
+pub fn compare(local: &[ObjectSummary], remote: &[ObjectSummary]) -> Vec<Divergence> {
+    let mut divergences = Vec::new();
+    let (mut local, mut remote) = (local.iter().peekable(), remote.iter().peekable());
+    loop {
+        let order = match (local.peek(), remote.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(l), Some(r)) => l.key.as_bytes().cmp(r.key.as_bytes()),
+        };
+        match order {
+            Ordering::Less => divergences.push(Divergence::Missing(local.next().unwrap().clone())),
+            Ordering::Greater => {
+                divergences.push(Divergence::Extra(remote.next().unwrap().clone()))
+            }
+            Ordering::Equal => {
+                let (l, r) = (local.next().unwrap(), remote.next().unwrap());
+                if l != r {
+                    divergences.push(Divergence::Changed {
+                        local: l.clone(),
+                        remote: r.clone(),
+                    });
+                }
+            }
+        }
+    }
+    divergences
+}
+
+/// Compare the objects of this store with the ones on a replica, and copy the
+/// missing or different objects to it with `--repair`
+#[tokio::main]
+pub async fn verify_replica(args: VerifyReplicaConfig) -> Result<()> {
+    let casfs = args.store.open()?;
+    let client = remote_client(&args);
+
+    let buckets = if args.bucket.is_empty() {
+        casfs
+            .list_buckets()?
+            .iter()
+            .map(|bucket| bucket.name().to_string())
+            .collect()
+    } else {
+        args.bucket.clone()
+    };
+
+    let (mut diverged, mut repaired) = (0, 0);
+    for bucket in &buckets {
+        if !casfs.bucket_exists(bucket)? {
+            bail!("Bucket {bucket} not found");
+        }
+        let local = local_objects(&casfs, bucket)?;
+        let remote = match remote_objects(&client, bucket).await? {
+            Some(remote) => remote,
+            None if args.repair => {
+                client
+                    .create_bucket()
+                    .bucket(bucket)
+                    .send()
+                    .await
+                    .with_context(|| format!("Can't create bucket {bucket} on the replica"))?;
+                Vec::new()
+            }
+            None => {
+                eprintln!("{bucket}: bucket missing on the replica");
+                diverged += local.len();
+                continue;
+            }
+        };
+
+        let divergences = compare(&local, &remote);
+        eprintln!(
+            "{bucket}: {} objects, {} on the replica, {} diverge",
+            local.len(),
+            remote.len(),
+            divergences.len()
+        );
+        for divergence in &divergences {
+            println!("{bucket}/{divergence}");
+            if args.repair && divergence.repairable() {
+                copy_object(&casfs, &client, bucket, divergence.key())
+                    .await
+                    .with_context(|| {
+                        format!("Can't copy {bucket}/{} to the replica", divergence.key())
+                    })?;
+                repaired += 1;
+            } else {
+                diverged += 1;
+            }
+        }
+    }
+
+    if repaired > 0 {
+        eprintln!("Copied {repaired} objects to the replica");
+    }
+    if diverged > 0 {
+        bail!("{diverged} objects diverge from the replica");
+    }
+    eprintln!("The replica matches the store");
+    Ok(())
+}
+
+fn remote_client(args: &VerifyReplicaConfig) -> aws_sdk_s3::Client {
+    let credentials = Credentials::new(
+        &args.remote_access_key,
+        &args.remote_secret_key,
+        None,
+        None,
+        "verify-replica",
+    );
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .credentials_provider(credentials)
+        .region(Region::new(args.remote_region.clone()))
+        .endpoint_url(&args.remote)
+        .force_path_style(true)
+        .build();
+    aws_sdk_s3::Client::from_conf(config)
+}
+
+fn local_objects(casfs: &CasFS, bucket: &str) -> Result<Vec<ObjectSummary>> {
+    // a snapshot, so objects written meanwhile don't mix into the listing
+    let snapshot = casfs.get_bucket(bucket)?.snapshot();
+    Ok(snapshot
+        .range_filter(None, None, None)
+        .map(|(key, obj)| ObjectSummary {
+            key,
+            size: obj.size(),
+            e_tag: obj.format_e_tag().trim_matches('"').to_string(),
+        })
+        .collect())
+}
+
+/// The objects of `bucket` on the replica, `None` if it doesn't have the bucket
+async fn remote_objects(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+) -> Result<Option<Vec<ObjectSummary>>> {
+    let mut objects = Vec::new();
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        let page = match page {
+            Ok(page) => page,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_bucket()) => {
+                return Ok(None)
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Can't list bucket {bucket} on the replica"))
+            }
+        };
+        objects.extend(page.contents().iter().map(|obj| {
+            ObjectSummary {
+                key: obj.key().unwrap_or_default().to_string(),
+                size: obj.size().unwrap_or_default() as u64,
+                e_tag: obj
+                    .e_tag()
+                    .unwrap_or_default()
+                    .trim_matches('"')
+                    .to_string(),
+            }
+        }));
+    }
+    Ok(Some(objects))
+}
+
+/// Upload an object of this store to the replica. The object is read into memory
+/// first, the SDK needs the length and checksum of the body.
+async fn copy_object(
+    casfs: &CasFS,
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+) -> Result<()> {
+    let Some((obj, paths)) = casfs.get_object_paths(bucket, key)? else {
+        // deleted since the listing
+        return Ok(());
+    };
+
+    let data = match obj.inlined() {
+        Some(data) => data.to_vec(),
+        None => {
+            let size = obj.size() as usize;
+            let metrics = SharedMetrics::new().to_cas_metrics();
+            let mut blocks = BlockStream::new(paths, size, RangeRequest::All, metrics);
+            let mut data = BytesMut::with_capacity(size);
+            while let Some(chunk) = blocks.next().await {
+                data.extend_from_slice(&chunk?);
+            }
+            data.to_vec()
+        }
+    };
+
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .body(ByteStream::from(data))
+        .send()
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obj(key: &str, size: u64, e_tag: &str) -> ObjectSummary {
+        ObjectSummary {
+            key: key.to_string(),
+            size,
+            e_tag: e_tag.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let local = [
+            obj("a", 1, "x"),
+            obj("b", 2, "y"),
+            obj("c", 3, "z"),
+            obj("e", 5, "v"),
+        ];
+        let remote = [
+            obj("a", 1, "x"),
+            obj("b", 2, "w"),
+            obj("d", 4, "u"),
+            obj("e", 5, "v"),
+        ];
+
+        assert_eq!(
+            compare(&local, &remote),
+            vec![
+                Divergence::Changed {
+                    local: obj("b", 2, "y"),
+                    remote: obj("b", 2, "w"),
+                },
+                Divergence::Missing(obj("c", 3, "z")),
+                Divergence::Extra(obj("d", 4, "u")),
+            ]
+        );
+        assert!(compare(&local, &local).is_empty());
+        assert_eq!(compare(&local, &[]).len(), 4);
+        assert_eq!(compare(&[], &remote).len(), 4);
+    }
+}
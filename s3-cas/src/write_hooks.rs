@@ -0,0 +1,796 @@
+//! Hooks run around object writes, to validate uploads and react to them.
+//!
+//! Before an object is stored every hook can reject it, e.g. for its content type,
+//! size or key, or for what an external scanner says about it. The request then
+//! fails with `AccessDenied` and nothing is stored. Once the object is committed
+//! the hooks are told about it in the background, without delaying the response.
+//!
+//! Hooks are implemented in process with the [`WriteHook`] trait, or configured in
+//! a TOML file as rules, local commands and webhooks. Commands and webhooks
+//! receive the write as a JSON document, on stdin or as the request body. The
+//! data of the writes can be scanned for viruses too, see [`crate::content_scan`].
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use bytes::Bytes;
+use cas_storage::Object;
+use http_body_util::{BodyExt, Full};
+use hyper::{header, Method, Request};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::content_scan::{ScanConfig, Scanner};
+
+/// Longest rejection message taken from the output of a command or webhook
+const MAX_MESSAGE_LEN: usize = 512;
+
+/// Content type of a write which doesn't set one, like S3 assumes
+pub const DEFAULT_CONTENT_TYPE: &str = "binary/octet-stream";
+
+/// A write of an object, as the hooks see it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ObjectWrite {
+    /// The S3 operation, e.g. `PutObject`
+    pub operation: &'static str,
+    /// The user owning the bucket
+    pub owner: String,
+    pub bucket: String,
+    pub key: String,
+    /// Content type of the object, `None` if the operation doesn't carry it.
+    /// `CompleteMultipartUpload` doesn't, its upload was checked with it when it
+    /// was created.
+    pub content_type: Option<String>,
+    /// Size of the object, `None` if it isn't known before the data is received
+    pub size: Option<u64>,
+}
+
+/// Why a hook refused a write, returned to the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection(pub String);
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+/// Validates and follows the object writes of the S3 API.
+///
+/// Both methods do nothing by default.
+#[async_trait]
+pub trait WriteHook: Send + Sync {
+    /// Check `write` before its object is stored, an error rejects it. Called on
+    /// the request, so it delays the write by the time it takes.
+    async fn before_write(&self, _write: &ObjectWrite) -> Result<(), Rejection> {
+        Ok(())
+    }
+
+    /// `object` was committed for `write`. Called in the background, after the
+    /// write was acknowledged.
+    async fn after_write(&self, _write: &ObjectWrite, _object: &Object) {}
+}
+
+/// The write hooks of a server, run in the order they were added, and the
+/// scanner of the data written
+#[derive(Clone, Default)]
+pub struct WriteHooks {
+    hooks: Vec<Arc<dyn WriteHook>>,
+    scanner: Option<Arc<Scanner>>,
+}
+
+impl WriteHooks {
+    /// The hooks of `config`: the rules, then the commands, then the webhooks
+    pub fn from_config(config: WriteHookConfig) -> anyhow::Result<Self> {
+        let mut hooks = Self::default();
+        if let Some(scan) = config.scan {
+            hooks = hooks.with_scanner(Arc::new(Scanner::new(scan)?));
+        }
+        for rule in config.rules {
+            hooks = hooks.with_hook(Arc::new(rule));
+        }
+        for command in config.commands {
+            hooks = hooks.with_hook(Arc::new(command));
+        }
+        if !config.webhooks.is_empty() {
+            let connector = hyper_rustls::HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .context("Failed to load the root certificates for write hooks")?
+                .https_or_http()
+                .enable_http1()
+                .build();
+            let client = Client::builder(TokioExecutor::new()).build(connector);
+            for config in config.webhooks {
+                hooks = hooks.with_hook(Arc::new(WebhookHook {
+                    config,
+                    client: client.clone(),
+                }));
+            }
+        }
+        Ok(hooks)
+    }
+
+    /// Add `hook` after the hooks added before
+    pub fn with_hook(mut self, hook: Arc<dyn WriteHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Scan the data of the writes `scanner` applies to before they are committed
+    pub fn with_scanner(mut self, scanner: Arc<Scanner>) -> Self {
+        self.scanner = Some(scanner);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// The scanner of the data of `write`, of `size` bytes, if it is scanned
+    pub fn scanner(&self, write: &ObjectWrite, size: u64) -> Option<&Scanner> {
+        self.scanner
+            .as_deref()
+            .filter(|scanner| scanner.scans(write, size))
+    }
+
+    /// Run the hooks before `write`, the first rejection stops it
+    pub async fn before_write(&self, write: &ObjectWrite) -> Result<(), Rejection> {
+        for hook in &self.hooks {
+            if let Err(rejection) = hook.before_write(write).await {
+                tracing::info!(
+                    operation = write.operation,
+                    bucket = %write.bucket,
+                    key = %write.key,
+                    reason = %rejection,
+                    "Write rejected by hook"
+                );
+                return Err(rejection);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the hooks after `write` committed `object`, in a background task
+    pub fn after_write(&self, write: ObjectWrite, object: Object) {
+        if self.is_empty() {
+            return;
+        }
+        let hooks = self.hooks.clone();
+        tokio::spawn(async move {
+            for hook in hooks {
+                hook.after_write(&write, &object).await;
+            }
+        });
+    }
+}
+
+/// When a command or webhook runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WritePhase {
+    /// Before the object is stored, it can reject the write
+    #[default]
+    Before,
+    /// After the object is committed, in the background
+    After,
+}
+
+impl WritePhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WritePhase::Before => "before",
+            WritePhase::After => "after",
+        }
+    }
+}
+
+// whether a write is to one of `buckets`, all if empty, with a key starting with
+// `prefix` and ending with `suffix`
+pub(crate) fn applies_to(
+    write: &ObjectWrite,
+    buckets: &[String],
+    prefix: &str,
+    suffix: &str,
+) -> bool {
+    (buckets.is_empty() || buckets.contains(&write.bucket))
+        && write.key.starts_with(prefix)
+        && write.key.ends_with(suffix)
+}
+
+// the media type of a content type, without its parameters
+fn media_type(content_type: &str) -> String {
+    let media_type = content_type.split(';').next().unwrap_or_default();
+    media_type.trim().to_ascii_lowercase()
+}
+
+// a pattern is a media type, or a type with any subtype like `video/*`
+fn content_type_matches(pattern: &str, media_type: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    match pattern.strip_suffix("/*") {
+        Some(top_level) => media_type
+            .split_once('/')
+            .is_some_and(|(ty, _)| ty == top_level),
+        None => pattern == media_type,
+    }
+}
+
+/// Rejects the writes it applies to by their content type or size, or all of
+/// them
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    /// Buckets the rule applies to, all buckets if empty
+    #[serde(default)]
+    pub buckets: Vec<String>,
+    /// Only apply to keys with this prefix and suffix
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+    /// Reject every write the rule applies to
+    #[serde(default)]
+    pub reject: bool,
+    /// Content types which are rejected, e.g. `application/x-msdownload` or `video/*`
+    #[serde(default)]
+    pub deny_content_types: Vec<String>,
+    /// Content types which are accepted, any other is rejected, all if empty
+    #[serde(default)]
+    pub allow_content_types: Vec<String>,
+    /// Largest accepted object size in bytes
+    pub max_size: Option<u64>,
+    /// Returned to the client instead of the reason of the rejection
+    pub message: Option<String>,
+}
+
+impl RuleConfig {
+    fn check(&self, write: &ObjectWrite) -> Result<(), String> {
+        if !applies_to(write, &self.buckets, &self.prefix, &self.suffix) {
+            return Ok(());
+        }
+        if self.reject {
+            return Err(format!("writes to '{}' are not allowed", write.key));
+        }
+        if let Some(content_type) = &write.content_type {
+            let media_type = media_type(content_type);
+            let denied = self
+                .deny_content_types
+                .iter()
+                .any(|pattern| content_type_matches(pattern, &media_type));
+            let allowed = self.allow_content_types.is_empty()
+                || self
+                    .allow_content_types
+                    .iter()
+                    .any(|pattern| content_type_matches(pattern, &media_type));
+            if denied || !allowed {
+                return Err(format!("content type '{}' is not allowed", media_type));
+            }
+        }
+        if let (Some(size), Some(max_size)) = (write.size, self.max_size) {
+            if size > max_size {
+                return Err(format!(
+                    "objects larger than {} bytes are not allowed",
+                    max_size
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WriteHook for RuleConfig {
+    async fn before_write(&self, write: &ObjectWrite) -> Result<(), Rejection> {
+        self.check(write)
+            .map_err(|reason| Rejection(self.message.clone().unwrap_or(reason)))
+    }
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+/// A local command run for the writes it applies to, with the write as JSON on
+/// stdin. Before a write the command accepts it by exiting with 0, anything else
+/// rejects it, with the first line of its stdout as the reason.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandHookConfig {
+    /// The program to run, looked up in `PATH` unless it is a path
+    pub program: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub phase: WritePhase,
+    /// Buckets the command runs for, all buckets if empty
+    #[serde(default)]
+    pub buckets: Vec<String>,
+    /// Only run for keys with this prefix and suffix
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+    /// Time after which a run is killed
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Accept the write when the command can't be run or is killed, instead of
+    /// rejecting it
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+/// A URL the writes it applies to are posted to as JSON. Before a write a 2xx
+/// response accepts it, a 4xx response rejects it with the response body as the
+/// reason.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookHookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub phase: WritePhase,
+    /// Buckets the webhook is called for, all buckets if empty
+    #[serde(default)]
+    pub buckets: Vec<String>,
+    /// Only call it for keys with this prefix and suffix
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+    /// Time after which the request is abandoned
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Accept the write when the webhook can't be reached or fails with another
+    /// status, instead of rejecting it
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+/// Write hook configuration, loaded from a TOML file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WriteHookConfig {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<RuleConfig>,
+    #[serde(default, rename = "command")]
+    pub commands: Vec<CommandHookConfig>,
+    #[serde(default, rename = "webhook")]
+    pub webhooks: Vec<WebhookHookConfig>,
+    pub scan: Option<ScanConfig>,
+}
+
+impl WriteHookConfig {
+    /// Load and validate a configuration from a TOML file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read write hook config {}", path.display()))?;
+        let config: Self = toml::from_str(&data)
+            .with_context(|| format!("Invalid write hook config {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid write hook config {}", path.display()))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for command in &self.commands {
+            if command.program.as_os_str().is_empty() {
+                anyhow::bail!("command without a program");
+            }
+            if command.timeout_secs == 0 {
+                anyhow::bail!(
+                    "timeout_secs of {} must be at least 1",
+                    command.program.display()
+                );
+            }
+        }
+        for webhook in &self.webhooks {
+            webhook
+                .url
+                .parse::<hyper::Uri>()
+                .with_context(|| format!("invalid webhook url {}", webhook.url))?;
+            if webhook.timeout_secs == 0 {
+                anyhow::bail!("timeout_secs of {} must be at least 1", webhook.url);
+            }
+        }
+        if let Some(scan) = &self.scan {
+            scan.validate()?;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+            + self.commands.len()
+            + self.webhooks.len()
+            + usize::from(self.scan.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The JSON document of `write` in `phase`, with the committed `object` after it
+pub fn write_document(phase: WritePhase, write: &ObjectWrite, object: Option<&Object>) -> Value {
+    let mut document = json!({
+        "phase": phase.as_str(),
+        "operation": write.operation,
+        "owner": write.owner,
+        "bucket": write.bucket,
+        "key": write.key,
+        "content_type": write.content_type,
+        "size": write.size,
+    });
+    if let Some(object) = object {
+        document["size"] = json!(object.size());
+        document["e_tag"] = json!(object.format_e_tag().trim_matches('"'));
+    }
+    document
+}
+
+// the first line of the output of a hook, shortened
+pub(crate) fn message(output: &[u8]) -> Option<String> {
+    let output = String::from_utf8_lossy(output);
+    let line = output
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())?;
+    Some(line.chars().take(MAX_MESSAGE_LEN).collect())
+}
+
+impl CommandHookConfig {
+    /// Runs the command once with `document` on stdin. Returns whether it
+    /// succeeded, with the first line of its stdout, or why it couldn't run.
+    async fn run(
+        &self,
+        write: &ObjectWrite,
+        document: &Value,
+    ) -> anyhow::Result<(bool, Option<String>)> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .env("S3CAS_PHASE", self.phase.as_str())
+            .env("S3CAS_BUCKET", &write.bucket)
+            .env("S3CAS_KEY", &write.key)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("failed to start")?;
+
+        let input = document.to_string();
+        let timeout = Duration::from_secs(self.timeout_secs);
+        let output = tokio::time::timeout(timeout, async move {
+            if let Some(mut stdin) = child.stdin.take() {
+                // a command may exit without reading the write
+                let _ = stdin.write_all(input.as_bytes()).await;
+            }
+            child.wait_with_output().await
+        })
+        .await
+        .with_context(|| format!("killed after {} seconds", self.timeout_secs))??;
+        Ok((output.status.success(), message(&output.stdout)))
+    }
+}
+
+#[async_trait]
+impl WriteHook for CommandHookConfig {
+    async fn before_write(&self, write: &ObjectWrite) -> Result<(), Rejection> {
+        if self.phase != WritePhase::Before
+            || !applies_to(write, &self.buckets, &self.prefix, &self.suffix)
+        {
+            return Ok(());
+        }
+        let document = write_document(WritePhase::Before, write, None);
+        match self.run(write, &document).await {
+            Ok((true, _)) => Ok(()),
+            Ok((false, message)) => Err(Rejection(
+                message.unwrap_or_else(|| "rejected by a write hook".to_string()),
+            )),
+            Err(e) => {
+                tracing::warn!(program = %self.program.display(), error = %e, "Write hook command failed");
+                if self.fail_open {
+                    Ok(())
+                } else {
+                    Err(Rejection("the write could not be validated".to_string()))
+                }
+            }
+        }
+    }
+
+    async fn after_write(&self, write: &ObjectWrite, object: &Object) {
+        if self.phase != WritePhase::After
+            || !applies_to(write, &self.buckets, &self.prefix, &self.suffix)
+        {
+            return;
+        }
+        let document = write_document(WritePhase::After, write, Some(object));
+        match self.run(write, &document).await {
+            Ok((true, _)) => {}
+            Ok((false, message)) => {
+                tracing::warn!(program = %self.program.display(), output = ?message, "Write hook command failed")
+            }
+            Err(e) => {
+                tracing::warn!(program = %self.program.display(), error = %e, "Write hook command failed")
+            }
+        }
+    }
+}
+
+type WebhookClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+/// Posts writes to a [`WebhookHookConfig`]
+struct WebhookHook {
+    config: WebhookHookConfig,
+    client: WebhookClient,
+}
+
+impl WebhookHook {
+    /// Posts `document`, returns the response status and the first line of its body
+    async fn post(&self, document: &Value) -> anyhow::Result<(hyper::StatusCode, Option<String>)> {
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(&self.config.url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(document.to_string())))?;
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        tokio::time::timeout(timeout, async {
+            let resp = self.client.request(req).await?;
+            let status = resp.status();
+            let body = resp.into_body().collect().await?.to_bytes();
+            Ok::<_, anyhow::Error>((status, message(&body)))
+        })
+        .await
+        .context("timed out")?
+    }
+}
+
+#[async_trait]
+impl WriteHook for WebhookHook {
+    async fn before_write(&self, write: &ObjectWrite) -> Result<(), Rejection> {
+        let config = &self.config;
+        if config.phase != WritePhase::Before
+            || !applies_to(write, &config.buckets, &config.prefix, &config.suffix)
+        {
+            return Ok(());
+        }
+        let document = write_document(WritePhase::Before, write, None);
+        let failure = match self.post(&document).await {
+            Ok((status, _)) if status.is_success() => return Ok(()),
+            Ok((status, message)) if status.is_client_error() => {
+                return Err(Rejection(
+                    message.unwrap_or_else(|| "rejected by a write hook".to_string()),
+                ))
+            }
+            Ok((status, _)) => anyhow::anyhow!("webhook responded with {}", status),
+            Err(e) => e,
+        };
+        tracing::warn!(url = %config.url, error = %failure, "Write hook webhook failed");
+        if config.fail_open {
+            Ok(())
+        } else {
+            Err(Rejection("the write could not be validated".to_string()))
+        }
+    }
+
+    async fn after_write(&self, write: &ObjectWrite, object: &Object) {
+        let config = &self.config;
+        if config.phase != WritePhase::After
+            || !applies_to(write, &config.buckets, &config.prefix, &config.suffix)
+        {
+            return;
+        }
+        let document = write_document(WritePhase::After, write, Some(object));
+        match self.post(&document).await {
+            Ok((status, _)) if status.is_success() => {}
+            Ok((status, _)) => {
+                tracing::warn!(url = %config.url, %status, "Write hook webhook failed")
+            }
+            Err(e) => tracing::warn!(url = %config.url, error = %e, "Write hook webhook failed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(key: &str, content_type: Option<&str>, size: Option<u64>) -> ObjectWrite {
+        ObjectWrite {
+            operation: "PutObject",
+            owner: "alice".to_string(),
+            bucket: "uploads".to_string(),
+            key: key.to_string(),
+            content_type: content_type.map(str::to_string),
+            size,
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        let config: WriteHookConfig = toml::from_str(
+            r#"
+            [[rule]]
+            deny_content_types = ["application/x-msdownload", "video/*"]
+            max_size = 1024
+
+            [[rule]]
+            suffix = ".exe"
+            reject = true
+            message = "no executables"
+
+            [[command]]
+            program = "/usr/local/bin/scan"
+            phase = "before"
+
+            [[webhook]]
+            url = "https://example.com/hooks/write"
+            phase = "after"
+            buckets = ["uploads"]
+
+            [scan]
+            clamd = "/run/clamav/clamd.ctl"
+            policy = "tag"
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.len(), 5);
+        assert_eq!(config.commands[0].timeout_secs, 10);
+        assert!(!config.commands[0].fail_open);
+        assert_eq!(config.webhooks[0].phase, WritePhase::After);
+
+        let config: WriteHookConfig =
+            toml::from_str("[[command]]\nprogram = \"x\"\ntimeout_secs = 0").unwrap();
+        assert!(config.validate().is_err());
+        assert!(toml::from_str::<WriteHookConfig>("[[rule]]\nmax = 1").is_err());
+        let config: WriteHookConfig = toml::from_str("[scan]\npolicy = \"tag\"").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rules() {
+        let hooks = WriteHooks::default()
+            .with_hook(Arc::new(RuleConfig {
+                deny_content_types: vec!["application/x-msdownload".into(), "video/*".into()],
+                max_size: Some(1024),
+                ..Default::default()
+            }))
+            .with_hook(Arc::new(RuleConfig {
+                buckets: vec!["uploads".into()],
+                prefix: "images/".into(),
+                allow_content_types: vec!["image/png".into(), "image/jpeg".into()],
+                ..Default::default()
+            }))
+            .with_hook(Arc::new(RuleConfig {
+                suffix: ".exe".into(),
+                reject: true,
+                message: Some("no executables".into()),
+                ..Default::default()
+            }));
+        let check = |key: &'static str, content_type: Option<&'static str>, size| {
+            let hooks = hooks.clone();
+            async move {
+                hooks
+                    .before_write(&write(key, content_type, size))
+                    .await
+                    .map_err(|rejection| rejection.0)
+            }
+        };
+
+        assert_eq!(check("a.txt", Some("text/plain"), Some(10)).await, Ok(()));
+        assert_eq!(
+            check("a.bin", Some("Application/X-MSDownload; x=1"), Some(10)).await,
+            Err("content type 'application/x-msdownload' is not allowed".to_string())
+        );
+        assert!(check("a.mp4", Some("video/mp4"), Some(10)).await.is_err());
+        assert_eq!(
+            check("a.txt", Some("text/plain"), Some(2048)).await,
+            Err("objects larger than 1024 bytes are not allowed".to_string())
+        );
+        // the size of a multipart upload is only checked on completion
+        assert_eq!(check("a.txt", Some("text/plain"), None).await, Ok(()));
+
+        assert_eq!(check("images/a.png", Some("image/png"), None).await, Ok(()));
+        assert!(check("images/a.gif", Some("image/gif"), None)
+            .await
+            .is_err());
+        assert_eq!(check("other/a.gif", Some("image/gif"), None).await, Ok(()));
+        // a completed upload was checked for its content type when it was created
+        assert_eq!(check("images/a.gif", None, Some(10)).await, Ok(()));
+
+        assert_eq!(
+            check("setup.exe", Some("binary/octet-stream"), Some(10)).await,
+            Err("no executables".to_string())
+        );
+    }
+
+    #[test]
+    fn test_write_document() {
+        let write = write("a b.txt", Some("text/plain"), Some(3));
+        let document = write_document(WritePhase::Before, &write, None);
+        assert_eq!(document["phase"], "before");
+        assert_eq!(document["operation"], "PutObject");
+        assert_eq!(document["owner"], "alice");
+        assert_eq!(document["key"], "a b.txt");
+        assert_eq!(document["content_type"], "text/plain");
+        assert_eq!(document["size"], 3);
+        assert!(document.get("e_tag").is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("write.json");
+        let command = CommandHookConfig {
+            program: "sh".into(),
+            args: vec![
+                "-c".into(),
+                format!(
+                    "cat > {}; case \"$S3CAS_KEY\" in *.bad) echo infected; exit 1;; esac",
+                    out.display()
+                ),
+            ],
+            phase: WritePhase::Before,
+            buckets: vec![],
+            prefix: String::new(),
+            suffix: String::new(),
+            timeout_secs: 10,
+            fail_open: false,
+        };
+
+        command
+            .before_write(&write("a.txt", Some("text/plain"), Some(3)))
+            .await
+            .unwrap();
+        let json: Value = serde_json::from_slice(&std::fs::read(&out).unwrap()).unwrap();
+        assert_eq!(json["key"], "a.txt");
+        assert_eq!(json["phase"], "before");
+
+        let rejection = command
+            .before_write(&write("a.bad", None, None))
+            .await
+            .unwrap_err();
+        assert_eq!(rejection.0, "infected");
+
+        // a command which can't be run rejects the write, unless it fails open
+        let missing = CommandHookConfig {
+            program: dir.path().join("missing"),
+            ..command.clone()
+        };
+        assert!(missing
+            .before_write(&write("a.txt", None, None))
+            .await
+            .is_err());
+        let missing = CommandHookConfig {
+            fail_open: true,
+            ..missing
+        };
+        assert!(missing
+            .before_write(&write("a.txt", None, None))
+            .await
+            .is_ok());
+
+        // a command after the write doesn't run before it
+        let after = CommandHookConfig {
+            phase: WritePhase::After,
+            ..command
+        };
+        after
+            .before_write(&write("a.bad", None, None))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,353 @@
+//! Multi-user S3 routing: every request is routed by its access key to the
+//! store of its user, while the blocks are shared by all users.
+
+#![forbid(unsafe_code)]
+
+use std::sync::Arc;
+
+use aws_config::SdkConfig;
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_sdk_s3::config::Credentials;
+use aws_sdk_s3::config::Region;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::BucketLocationConstraint;
+use aws_sdk_s3::types::CreateBucketConfiguration;
+use aws_sdk_s3::Client;
+
+use anyhow::Result;
+use s3s::host::SingleDomain;
+use s3s::service::S3ServiceBuilder;
+use tempfile::TempDir;
+
+use cas_storage::{BlockID, SharedBlockStore, StorageEngine};
+use s3_cas::auth::{UserRecord, UserRouter, UserStore};
+use s3_cas::metrics::SharedMetrics;
+use s3_cas::s3_wrapper::{DynamicS3Auth, S3UserRouter};
+
+const DOMAIN_NAME: &str = "localhost:8014";
+const REGION: &str = "us-west-2";
+
+/// A multi-user server with the users alice and bob, wired like `main` does
+struct Server {
+    _dir: TempDir,
+    shared_block_store: Arc<SharedBlockStore>,
+    user_router: Arc<UserRouter>,
+    user_store: Arc<UserStore>,
+}
+
+impl Server {
+    fn new() -> Server {
+        let dir = tempfile::tempdir().unwrap();
+        let shared_block_store = Arc::new(
+            SharedBlockStore::new(
+                dir.path().join("meta").join("blocks"),
+                StorageEngine::Fjall,
+                None,
+                None,
+            )
+            .unwrap(),
+        );
+        let user_store = Arc::new(UserStore::new(
+            shared_block_store.meta_store().get_underlying_store(),
+        ));
+        let user_router = Arc::new(UserRouter::new(
+            shared_block_store.clone(),
+            dir.path().join("fs"),
+            dir.path().join("meta"),
+            SharedMetrics::new(),
+            StorageEngine::Fjall,
+            // objects are stored in blocks, never inlined
+            Some(1),
+            None,
+        ));
+        for user in ["alice", "bob"] {
+            let record = UserRecord::new(
+                user.to_string(),
+                user.to_string(),
+                "password123",
+                access_key(user),
+                secret_key(user),
+                false,
+            )
+            .unwrap();
+            user_store.create_user(record).unwrap();
+        }
+        Server {
+            _dir: dir,
+            shared_block_store,
+            user_router,
+            user_store,
+        }
+    }
+
+    /// A client sending the requests through the S3 service of the server
+    fn client(&self, access_key: &str, secret_key: &str) -> Client {
+        let s3 = S3UserRouter::new(self.user_router.clone(), self.user_store.clone());
+        let auth = DynamicS3Auth::new(self.user_store.clone());
+        let mut b = S3ServiceBuilder::new(s3);
+        b.set_access(auth.sig_v2_access(None));
+        b.set_auth(auth);
+        b.set_host(SingleDomain::new(DOMAIN_NAME).unwrap());
+        let service = b.build();
+
+        let config = SdkConfig::builder()
+            .credentials_provider(SharedCredentialsProvider::new(Credentials::new(
+                access_key, secret_key, None, None, "test",
+            )))
+            .http_client(s3s_aws::Client::from(service.into_shared()))
+            .region(Region::new(REGION))
+            .endpoint_url(format!("http://{DOMAIN_NAME}"))
+            .build();
+        Client::new(&config)
+    }
+
+    fn user(&self, user: &str) -> Client {
+        self.client(&access_key(user), &secret_key(user))
+    }
+
+    /// The refcount of `block` in the shared block store, None if it is gone
+    fn refcount(&self, block: &BlockID) -> Option<u64> {
+        self.shared_block_store
+            .block_tree()
+            .get_block(block)
+            .unwrap()
+            .map(|block| block.rc())
+    }
+
+    fn blocks(&self, user: &str, bucket: &str, key: &str) -> Vec<BlockID> {
+        let casfs = self.user_router.get_casfs_by_user_id(user).unwrap();
+        let obj = casfs.get_object_meta(bucket, key).unwrap().unwrap();
+        obj.blocks().to_vec()
+    }
+}
+
+fn access_key(user: &str) -> String {
+    format!("AKIA{}", user.to_uppercase())
+}
+
+fn secret_key(user: &str) -> String {
+    format!("secret-of-{user}")
+}
+
+async fn create_bucket(c: &Client, bucket: &str) -> Result<()> {
+    let cfg = CreateBucketConfiguration::builder()
+        .location_constraint(BucketLocationConstraint::from(REGION))
+        .build();
+    c.create_bucket()
+        .create_bucket_configuration(cfg)
+        .bucket(bucket)
+        .send()
+        .await?;
+    Ok(())
+}
+
+async fn put_object(c: &Client, bucket: &str, key: &str, data: Vec<u8>) -> Result<()> {
+    c.put_object()
+        .bucket(bucket)
+        .key(key)
+        .body(ByteStream::from(data))
+        .send()
+        .await?;
+    Ok(())
+}
+
+async fn get_object(c: &Client, bucket: &str, key: &str) -> Result<Vec<u8>> {
+    let ans = c.get_object().bucket(bucket).key(key).send().await?;
+    Ok(ans.body.collect().await?.into_bytes().to_vec())
+}
+
+async fn bucket_names(c: &Client) -> Result<Vec<String>> {
+    let ans = c.list_buckets().send().await?;
+    Ok(ans
+        .buckets()
+        .iter()
+        .filter_map(|bucket| bucket.name().map(str::to_string))
+        .collect())
+}
+
+#[tokio::test]
+async fn test_namespace_isolation() -> Result<()> {
+    let server = Server::new();
+    let (alice, bob) = (server.user("alice"), server.user("bob"));
+
+    create_bucket(&alice, "photos").await?;
+    put_object(&alice, "photos", "cat.jpg", b"alice's cat".to_vec()).await?;
+
+    // bob sees nothing of alice's buckets
+    assert!(bucket_names(&bob).await?.is_empty());
+    let err = bob
+        .get_object()
+        .bucket("photos")
+        .key("cat.jpg")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("NoSuchBucket"));
+    let err = bob
+        .list_objects_v2()
+        .bucket("photos")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("NoSuchBucket"));
+    assert!(bob.delete_bucket().bucket("photos").send().await.is_err());
+
+    // bucket names are per user, bob's bucket of the same name is his own
+    create_bucket(&bob, "photos").await?;
+    put_object(&bob, "photos", "dog.jpg", b"bob's dog".to_vec()).await?;
+    bob.delete_object()
+        .bucket("photos")
+        .key("cat.jpg")
+        .send()
+        .await?;
+
+    assert_eq!(bucket_names(&alice).await?, ["photos"]);
+    assert_eq!(
+        get_object(&alice, "photos", "cat.jpg").await?,
+        b"alice's cat"
+    );
+    let listed = alice.list_objects_v2().bucket("photos").send().await?;
+    let keys: Vec<_> = listed.contents().iter().filter_map(|o| o.key()).collect();
+    assert_eq!(keys, ["cat.jpg"]);
+    let listed = bob.list_objects_v2().bucket("photos").send().await?;
+    let keys: Vec<_> = listed.contents().iter().filter_map(|o| o.key()).collect();
+    assert_eq!(keys, ["dog.jpg"]);
+
+    // deleting bob's bucket leaves alice's alone
+    bob.delete_object()
+        .bucket("photos")
+        .key("dog.jpg")
+        .send()
+        .await?;
+    bob.delete_bucket().bucket("photos").send().await?;
+    assert_eq!(
+        get_object(&alice, "photos", "cat.jpg").await?,
+        b"alice's cat"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shared_block_refcounts() -> Result<()> {
+    let server = Server::new();
+    let (alice, bob) = (server.user("alice"), server.user("bob"));
+    create_bucket(&alice, "data").await?;
+    create_bucket(&bob, "data").await?;
+
+    // two blocks, the second one partial
+    let data: Vec<u8> = (0..(3 << 19)).map(|i| (i % 251) as u8).collect();
+    put_object(&alice, "data", "a", data.clone()).await?;
+    let blocks = server.blocks("alice", "data", "a");
+    assert_eq!(blocks.len(), 2);
+    for block in &blocks {
+        assert_eq!(server.refcount(block), Some(1));
+    }
+
+    // the same data uploaded by another user references the same blocks
+    put_object(&bob, "data", "b", data.clone()).await?;
+    assert_eq!(server.blocks("bob", "data", "b"), blocks);
+    for block in &blocks {
+        assert_eq!(server.refcount(block), Some(2));
+    }
+
+    // a delete of one user releases only its own references
+    bob.delete_object().bucket("data").key("b").send().await?;
+    for block in &blocks {
+        assert_eq!(server.refcount(block), Some(1));
+    }
+    assert_eq!(get_object(&alice, "data", "a").await?, data);
+
+    alice.delete_object().bucket("data").key("a").send().await?;
+    for block in &blocks {
+        assert_eq!(server.refcount(block), None);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_auth_failures() -> Result<()> {
+    let server = Server::new();
+    create_bucket(&server.user("alice"), "private").await?;
+
+    let unknown = server.client("AKIAUNKNOWN", "secret");
+    let err = unknown.list_buckets().send().await.unwrap_err();
+    assert_eq!(err.code(), Some("InvalidAccessKeyId"));
+
+    // the key of alice with the secret of bob
+    let forged = server.client(&access_key("alice"), &secret_key("bob"));
+    let err = forged
+        .list_objects_v2()
+        .bucket("private")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("SignatureDoesNotMatch"));
+
+    // the keys of a deleted user stop working
+    let alice = server.user("alice");
+    server.user_store.delete_user("alice")?;
+    let err = alice.list_buckets().send().await.unwrap_err();
+    assert_eq!(err.code(), Some("InvalidAccessKeyId"));
+
+    // other users are unaffected
+    assert!(bucket_names(&server.user("bob")).await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_expected_bucket_owner() -> Result<()> {
+    let server = Server::new();
+    let alice = server.user("alice");
+    create_bucket(&alice, "photos").await?;
+
+    alice
+        .put_object()
+        .bucket("photos")
+        .key("cat.jpg")
+        .body(ByteStream::from_static(b"alice's cat"))
+        .expected_bucket_owner("alice")
+        .send()
+        .await?;
+    alice
+        .get_object()
+        .bucket("photos")
+        .key("cat.jpg")
+        .expected_bucket_owner("alice")
+        .send()
+        .await?;
+
+    // the bucket doesn't belong to the account the client expects
+    let err = alice
+        .get_object()
+        .bucket("photos")
+        .key("cat.jpg")
+        .expected_bucket_owner("bob")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("AccessDenied"));
+    let err = alice
+        .delete_object()
+        .bucket("photos")
+        .key("cat.jpg")
+        .expected_bucket_owner("bob")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("AccessDenied"));
+    let err = alice
+        .list_objects_v2()
+        .bucket("photos")
+        .expected_bucket_owner("bob")
+        .send()
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Some("AccessDenied"));
+
+    assert_eq!(
+        get_object(&alice, "photos", "cat.jpg").await?,
+        b"alice's cat"
+    );
+    Ok(())
+}